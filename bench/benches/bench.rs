@@ -1,5 +1,6 @@
 extern crate lightning;
 extern crate lightning_persister;
+extern crate lightning_net_tokio;
 
 extern crate criterion;
 
@@ -16,6 +17,7 @@ criterion_group!(benches,
 	lightning::sign::benches::bench_get_secure_random_bytes,
 	lightning::ln::channelmanager::bench::bench_sends,
 	lightning_persister::bench::bench_sends,
+	lightning_net_tokio::bench::bench_socket_writes,
 	lightning_rapid_gossip_sync::bench::bench_reading_full_graph_from_file,
 	lightning::routing::gossip::benches::read_network_graph,
 	lightning::routing::gossip::benches::write_network_graph);
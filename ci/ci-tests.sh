@@ -67,6 +67,10 @@ done
 # Note that outbound_commitment_test only runs in this mode because of hardcoded signature values
 pushd lightning
 cargo test --verbose --color always --no-default-features --features=std,_test_vectors
+# HTLC timeline events are only generated with the htlc_timeline_events feature, which adds events
+# most functional tests do not expect, so only run its dedicated test.
+cargo test --verbose --color always --features htlc_timeline_events test_htlc_timeline_events
+cargo test --verbose --color always --features conformance_vectors conformance_vectors
 popd
 # This one only works for lightning-invoice
 pushd lightning-invoice
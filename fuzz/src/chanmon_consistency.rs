@@ -216,6 +216,10 @@ impl NodeSigner for KeyProvider {
 		let secp_ctx = Secp256k1::signing_only();
 		Ok(secp_ctx.sign_ecdsa(&msg_hash, &self.node_secret))
 	}
+
+	fn sign_onion_message_receipt(&self, _nonce: &[u8; 32]) -> Result<Signature, ()> {
+		Err(())
+	}
 }
 
 impl SignerProvider for KeyProvider {
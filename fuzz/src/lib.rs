@@ -27,6 +27,7 @@ pub mod peer_crypt;
 pub mod process_network_graph;
 pub mod refund_deser;
 pub mod router;
+pub mod ser_limits;
 pub mod zbase32;
 
 pub mod msg_targets;
@@ -11,7 +11,7 @@ use lightning::ln::script::ShutdownScript;
 use lightning::util::enforcing_trait_impls::EnforcingSigner;
 use lightning::util::logger::Logger;
 use lightning::util::ser::{Readable, Writeable, Writer};
-use lightning::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, Destination, MessageRouter, OffersMessage, OffersMessageHandler, OnionMessagePath, OnionMessenger};
+use lightning::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, Destination, DlcMessage, DlcMessageHandler, MessageRouter, OffersMessage, OffersMessageHandler, OnionMessagePath, OnionMessageReceivedVia, OnionMessenger, Responder};
 
 use crate::utils::test_logger;
 
@@ -31,10 +31,11 @@ pub fn do_test<L: Logger>(data: &[u8], logger: &L) {
 		};
 		let message_router = TestMessageRouter {};
 		let offers_msg_handler = TestOffersMessageHandler {};
+		let dlc_msg_handler = TestDlcMessageHandler {};
 		let custom_msg_handler = TestCustomMessageHandler {};
 		let onion_messenger = OnionMessenger::new(
 			&keys_manager, &keys_manager, logger, &message_router, &offers_msg_handler,
-			&custom_msg_handler
+			&dlc_msg_handler, &custom_msg_handler
 		);
 		let mut pk = [2; 33]; pk[1] = 0xff;
 		let peer_node_id_not_used = PublicKey::from_slice(&pk).unwrap();
@@ -64,6 +65,7 @@ impl MessageRouter for TestMessageRouter {
 		Ok(OnionMessagePath {
 			intermediate_nodes: vec![],
 			destination,
+			first_node_addresses: None,
 		})
 	}
 }
@@ -76,6 +78,17 @@ impl OffersMessageHandler for TestOffersMessageHandler {
 	}
 }
 
+struct TestDlcMessageHandler {}
+
+impl DlcMessageHandler for TestDlcMessageHandler {
+	fn handle_message(
+		&self, _message: DlcMessage, _received_via: OnionMessageReceivedVia,
+		_responder: Option<Responder>
+	) -> Option<DlcMessage> {
+		None
+	}
+}
+
 struct TestCustomMessage {}
 
 const CUSTOM_MESSAGE_TYPE: u64 = 4242;
@@ -156,6 +169,10 @@ impl NodeSigner for KeyProvider {
 	fn sign_gossip_message(&self, _msg: lightning::ln::msgs::UnsignedGossipMessage) -> Result<bitcoin::secp256k1::ecdsa::Signature, ()> {
 		unreachable!()
 	}
+
+	fn sign_onion_message_receipt(&self, _nonce: &[u8; 32]) -> Result<bitcoin::secp256k1::ecdsa::Signature, ()> {
+		Err(())
+	}
 }
 
 impl SignerProvider for KeyProvider {
@@ -0,0 +1,46 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+use bitcoin::secp256k1::ecdsa::Signature;
+
+use lightning::routing::router::Route;
+use lightning::util::ser::{self, Readable};
+
+use crate::utils::test_logger;
+
+#[inline]
+pub fn do_test(data: &[u8]) {
+	// Anything we manage to read must respect the limits, no matter what length prefixes the
+	// input claims.
+	let defaults = ser::DeserializationLimits::default();
+	if let Ok(bytes) = <Vec<u8>>::read(&mut &data[..]) {
+		assert!(bytes.len() as u64 <= defaults.max_bytes_len);
+	}
+	if let Ok(sigs) = <Vec<Signature>>::read(&mut &data[..]) {
+		assert!(sigs.len() as u64 <= defaults.max_collection_len);
+	}
+	if let Ok(route) = Route::read(&mut &data[..]) {
+		assert!(route.paths.len() as u64 <= defaults.max_collection_len);
+	}
+
+	// Reads bounded in the total bytes they consume never read more than their share of the input.
+	let limits = ser::DeserializationLimits { max_total_bytes: data.len() as u64 / 2, ..defaults };
+	if let Ok(bytes) = ser::read_with_limits::<Vec<u8>, _>(&mut &data[..], &limits) {
+		assert!(bytes.len() as u64 <= limits.max_total_bytes);
+	}
+}
+
+pub fn ser_limits_test<Out: test_logger::Output>(data: &[u8], _out: Out) {
+	do_test(data);
+}
+
+#[no_mangle]
+pub extern "C" fn ser_limits_run(data: *const u8, datalen: usize) {
+	do_test(unsafe { std::slice::from_raw_parts(data, datalen) });
+}
@@ -30,6 +30,7 @@ use lightning::events::{Event, PathFailure};
 #[cfg(feature = "std")]
 use lightning::events::{EventHandler, EventsProvider};
 use lightning::ln::channelmanager::ChannelManager;
+use lightning::ln::contractmanager::AContractManager;
 use lightning::ln::peer_handler::APeerManager;
 use lightning::routing::gossip::{NetworkGraph, P2PGossipSync};
 use lightning::routing::utxo::UtxoLookup;
@@ -63,10 +64,18 @@ use alloc::vec::Vec;
 /// * Monitoring whether the [`ChannelManager`] needs to be re-persisted to disk, and if so,
 ///   writing it to disk/backups by invoking the callback given to it at startup.
 ///   [`ChannelManager`] persistence should be done in the background.
-/// * Calling [`ChannelManager::timer_tick_occurred`], [`ChainMonitor::rebroadcast_pending_claims`]
-///   and [`PeerManager::timer_tick_occurred`] at the appropriate intervals.
+/// * Calling [`ChannelManager::timer_tick_occurred`], [`ChainMonitor::rebroadcast_pending_claims`],
+///   [`ChainMonitor::rebroadcast_pending_transactions`] and [`PeerManager::timer_tick_occurred`]
+///   at the appropriate intervals. The latter lets a [`RebroadcastScheduler`] used as broadcaster
+///   rebroadcast transactions which didn't confirm.
+/// * Calling [`ChainMonitor::check_persistence_health`] along with the rebroadcasts above, so that
+///   an [`Event::PersistenceHealth`] is generated once persistence starts degrading.
 /// * Calling [`NetworkGraph::remove_stale_channels_and_tracking`] (if a [`GossipSync`] with a
 ///   [`NetworkGraph`] is provided to [`BackgroundProcessor::start`]).
+/// * Calling [`ContractManager::timer_tick_occurred`] (if a [`ContractManager`] is provided to
+///   [`BackgroundProcessor::start`]), which exercises matured contracts and times out pending
+///   contract negotiations. Note that the [`ContractManager`]'s events must still be processed
+///   separately via its [`EventsProvider`] implementation.
 ///
 /// It will also call [`PeerManager::process_events`] periodically though this shouldn't be relied
 /// upon as doing so may result in high latency.
@@ -79,9 +88,14 @@ use alloc::vec::Vec;
 /// unilateral chain closure fees are at risk.
 ///
 /// [`ChannelMonitor`]: lightning::chain::channelmonitor::ChannelMonitor
+/// [`ContractManager`]: lightning::ln::contractmanager::ContractManager
+/// [`ContractManager::timer_tick_occurred`]: lightning::ln::contractmanager::ContractManager::timer_tick_occurred
 /// [`Event`]: lightning::events::Event
+/// [`EventsProvider`]: lightning::events::EventsProvider
+/// [`Event::PersistenceHealth`]: lightning::events::Event::PersistenceHealth
 /// [`PeerManager::timer_tick_occurred`]: lightning::ln::peer_handler::PeerManager::timer_tick_occurred
 /// [`PeerManager::process_events`]: lightning::ln::peer_handler::PeerManager::process_events
+/// [`RebroadcastScheduler`]: lightning::chain::rebroadcast::RebroadcastScheduler
 #[cfg(feature = "std")]
 #[must_use = "BackgroundProcessor will immediately stop on drop. It should be stored until shutdown."]
 pub struct BackgroundProcessor {
@@ -122,11 +136,16 @@ const REBROADCAST_TIMER: u64 = 30;
 #[cfg(test)]
 const REBROADCAST_TIMER: u64 = 1;
 
+#[cfg(not(test))]
+const CONTRACT_TIMER: u64 = 10;
+#[cfg(test)]
+const CONTRACT_TIMER: u64 = 1;
+
 #[cfg(feature = "futures")]
 /// core::cmp::min is not currently const, so we define a trivial (and equivalent) replacement
 const fn min_u64(a: u64, b: u64) -> u64 { if a < b { a } else { b } }
 #[cfg(feature = "futures")]
-const FASTEST_TIMER: u64 = min_u64(min_u64(FRESHNESS_TIMER, PING_TIMER),
+const FASTEST_TIMER: u64 = min_u64(min_u64(FRESHNESS_TIMER, min_u64(PING_TIMER, CONTRACT_TIMER)),
 	min_u64(SCORER_PERSIST_TIMER, min_u64(FIRST_NETWORK_PRUNE_TIMER, REBROADCAST_TIMER)));
 
 /// Either [`P2PGossipSync`] or [`RapidGossipSync`].
@@ -269,7 +288,7 @@ macro_rules! define_run_body {
 	($persister: ident, $chain_monitor: ident, $process_chain_monitor_events: expr,
 	 $channel_manager: ident, $process_channel_manager_events: expr,
 	 $gossip_sync: ident, $peer_manager: ident, $logger: ident, $scorer: ident,
-	 $loop_exit_check: expr, $await: expr, $get_timer: expr, $timer_elapsed: expr,
+	 $contract_manager: ident, $loop_exit_check: expr, $await: expr, $get_timer: expr, $timer_elapsed: expr,
 	 $check_slow_await: expr)
 	=> { {
 		log_trace!($logger, "Calling ChannelManager's timer_tick_occurred on startup");
@@ -282,6 +301,7 @@ macro_rules! define_run_body {
 		let mut last_prune_call = $get_timer(FIRST_NETWORK_PRUNE_TIMER);
 		let mut last_scorer_persist_call = $get_timer(SCORER_PERSIST_TIMER);
 		let mut last_rebroadcast_call = $get_timer(REBROADCAST_TIMER);
+		let mut last_contract_call = $get_timer(CONTRACT_TIMER);
 		let mut have_pruned = false;
 
 		loop {
@@ -398,8 +418,20 @@ macro_rules! define_run_body {
 			if $timer_elapsed(&mut last_rebroadcast_call, REBROADCAST_TIMER) {
 				log_trace!($logger, "Rebroadcasting monitor's pending claims");
 				$chain_monitor.rebroadcast_pending_claims();
+				log_trace!($logger, "Rebroadcasting pending transactions");
+				$chain_monitor.rebroadcast_pending_transactions();
+				log_trace!($logger, "Checking persistence health");
+				$chain_monitor.check_persistence_health();
 				last_rebroadcast_call = $get_timer(REBROADCAST_TIMER);
 			}
+
+			if $timer_elapsed(&mut last_contract_call, CONTRACT_TIMER) {
+				if let Some(ref contract_manager) = $contract_manager {
+					log_trace!($logger, "Calling ContractManager's timer_tick_occurred");
+					contract_manager.as_ref().timer_tick_occurred();
+				}
+				last_contract_call = $get_timer(CONTRACT_TIMER);
+			}
 		}
 
 		// After we exit, ensure we persist the ChannelManager one final time - this avoids
@@ -525,8 +557,9 @@ use core::task;
 /// # type MyGossipSync = lightning::routing::gossip::P2PGossipSync<Arc<MyNetworkGraph>, Arc<MyUtxoLookup>, Arc<MyLogger>>;
 /// # type MyChannelManager = lightning::ln::channelmanager::SimpleArcChannelManager<MyChainMonitor, MyBroadcaster, MyFeeEstimator, MyLogger>;
 /// # type MyScorer = Mutex<lightning::routing::scoring::ProbabilisticScorer<Arc<MyNetworkGraph>, Arc<MyLogger>>>;
+/// # type MyContractManager = lightning::ln::contractmanager::ContractManager<Arc<lightning::sign::KeysManager>, Arc<MyChannelManager>, Arc<MyChannelManager>, Arc<MyBroadcaster>, Arc<MyFeeEstimator>, Arc<MyLogger>>;
 ///
-/// # async fn setup_background_processing(my_persister: Arc<MyPersister>, my_event_handler: Arc<MyEventHandler>, my_chain_monitor: Arc<MyChainMonitor>, my_channel_manager: Arc<MyChannelManager>, my_gossip_sync: Arc<MyGossipSync>, my_logger: Arc<MyLogger>, my_scorer: Arc<MyScorer>, my_peer_manager: Arc<MyPeerManager>) {
+/// # async fn setup_background_processing(my_persister: Arc<MyPersister>, my_event_handler: Arc<MyEventHandler>, my_chain_monitor: Arc<MyChainMonitor>, my_channel_manager: Arc<MyChannelManager>, my_gossip_sync: Arc<MyGossipSync>, my_logger: Arc<MyLogger>, my_scorer: Arc<MyScorer>, my_peer_manager: Arc<MyPeerManager>, my_contract_manager: Arc<MyContractManager>) {
 ///	let background_persister = Arc::clone(&my_persister);
 ///	let background_event_handler = Arc::clone(&my_event_handler);
 ///	let background_chain_mon = Arc::clone(&my_chain_monitor);
@@ -535,6 +568,7 @@ use core::task;
 ///	let background_peer_man = Arc::clone(&my_peer_manager);
 ///	let background_logger = Arc::clone(&my_logger);
 ///	let background_scorer = Arc::clone(&my_scorer);
+///	let background_contract_man = Arc::clone(&my_contract_manager);
 ///
 ///	// Setup the sleeper.
 ///	let (stop_sender, stop_receiver) = tokio::sync::watch::channel(());
@@ -561,6 +595,7 @@ use core::task;
 ///			background_peer_man,
 ///			background_logger,
 ///			Some(background_scorer),
+///			Some(background_contract_man),
 ///			sleeper,
 ///			mobile_interruptable_platform,
 ///			)
@@ -599,12 +634,14 @@ pub async fn process_events_async<
 	PM: 'static + Deref<Target = APM> + Send + Sync,
 	S: 'static + Deref<Target = SC> + Send + Sync,
 	SC: for<'b> WriteableScore<'b>,
+	ACTM: AContractManager + Send + Sync,
+	CTM: 'static + Deref<Target = ACTM> + Send + Sync,
 	SleepFuture: core::future::Future<Output = bool> + core::marker::Unpin,
 	Sleeper: Fn(Duration) -> SleepFuture
 >(
 	persister: PS, event_handler: EventHandler, chain_monitor: M, channel_manager: CM,
 	gossip_sync: GossipSync<PGS, RGS, G, UL, L>, peer_manager: PM, logger: L, scorer: Option<S>,
-	sleeper: Sleeper, mobile_interruptable_platform: bool,
+	contract_manager: Option<CTM>, sleeper: Sleeper, mobile_interruptable_platform: bool,
 ) -> Result<(), lightning::io::Error>
 where
 	UL::Target: 'static + UtxoLookup,
@@ -645,7 +682,7 @@ where
 	define_run_body!(persister,
 		chain_monitor, chain_monitor.process_pending_events_async(async_event_handler).await,
 		channel_manager, channel_manager.process_pending_events_async(async_event_handler).await,
-		gossip_sync, peer_manager, logger, scorer, should_break, {
+		gossip_sync, peer_manager, logger, scorer, contract_manager, should_break, {
 			let fut = Selector {
 				a: channel_manager.get_persistable_update_future(),
 				b: chain_monitor.get_update_future(),
@@ -740,9 +777,12 @@ impl BackgroundProcessor {
 		PM: 'static + Deref<Target = APM> + Send + Sync,
 		S: 'static + Deref<Target = SC> + Send + Sync,
 		SC: for <'b> WriteableScore<'b>,
+		ACTM: AContractManager + Send + Sync,
+		CTM: 'static + Deref<Target = ACTM> + Send + Sync,
 	>(
 		persister: PS, event_handler: EH, chain_monitor: M, channel_manager: CM,
 		gossip_sync: GossipSync<PGS, RGS, G, UL, L>, peer_manager: PM, logger: L, scorer: Option<S>,
+		contract_manager: Option<CTM>,
 	) -> Self
 	where
 		UL::Target: 'static + UtxoLookup,
@@ -778,7 +818,7 @@ impl BackgroundProcessor {
 			};
 			define_run_body!(persister, chain_monitor, chain_monitor.process_pending_events(&event_handler),
 				channel_manager, channel_manager.process_pending_events(&event_handler),
-				gossip_sync, peer_manager, logger, scorer, stop_thread.load(Ordering::Acquire),
+				gossip_sync, peer_manager, logger, scorer, contract_manager, stop_thread.load(Ordering::Acquire),
 				Sleeper::from_two_futures(
 					channel_manager.get_persistable_update_future(),
 					chain_monitor.get_update_future()
@@ -843,15 +883,19 @@ mod tests {
 	use bitcoin::blockdata::transaction::{Transaction, TxOut};
 	use bitcoin::network::constants::Network;
 	use bitcoin::secp256k1::{SecretKey, PublicKey, Secp256k1};
-	use lightning::chain::{BestBlock, Confirm, chainmonitor};
+	use bitcoin::hashes::Hash;
+	use bitcoin::{Script, Txid, WPubkeyHash};
+	use lightning::chain::{BestBlock, Confirm, Listen, chainmonitor};
 	use lightning::chain::channelmonitor::ANTI_REORG_DELAY;
 	use lightning::sign::{InMemorySigner, KeysManager};
 	use lightning::chain::transaction::OutPoint;
-	use lightning::events::{Event, PathFailure, MessageSendEventsProvider, MessageSendEvent};
+	use lightning::events::{Event, EventsProvider, PathFailure, MessageSendEventsProvider, MessageSendEvent};
 	use lightning::{get_event_msg, get_event};
 	use lightning::ln::PaymentHash;
 	use lightning::ln::channelmanager;
 	use lightning::ln::channelmanager::{BREAKDOWN_TIMEOUT, ChainParameters, MIN_CLTV_EXPIRY_DELTA, PaymentId};
+	use lightning::ln::contractmanager::{self, ContractExerciseStatus, ContractId};
+	use lightning::ln::contracts::{CollateralOutput, SettlementBranch, SettlementBundle};
 	use lightning::ln::features::{ChannelFeatures, NodeFeatures};
 	use lightning::ln::functional_test_utils::*;
 	use lightning::ln::msgs::{ChannelMessageHandler, Init};
@@ -902,6 +946,8 @@ mod tests {
 			>,
 			Arc<test_utils::TestLogger>>;
 
+	type ContractManager = contractmanager::ContractManager<Arc<KeysManager>, Arc<ChannelManager>, Arc<ChannelManager>, Arc<test_utils::TestBroadcaster>, Arc<test_utils::TestFeeEstimator>, Arc<test_utils::TestLogger>>;
+
 	type ChainMonitor = chainmonitor::ChainMonitor<InMemorySigner, Arc<test_utils::TestChainSource>, Arc<test_utils::TestBroadcaster>, Arc<test_utils::TestFeeEstimator>, Arc<test_utils::TestLogger>, Arc<FilesystemPersister>>;
 
 	type PGS = Arc<P2PGossipSync<Arc<NetworkGraph<Arc<test_utils::TestLogger>>>, Arc<test_utils::TestChainSource>, Arc<test_utils::TestLogger>>>;
@@ -919,6 +965,7 @@ mod tests {
 		logger: Arc<test_utils::TestLogger>,
 		best_block: BestBlock,
 		scorer: Arc<Mutex<TestScorer>>,
+		contract_manager: Arc<ContractManager>,
 	}
 
 	impl Node {
@@ -1166,7 +1213,8 @@ mod tests {
 				onion_message_handler: IgnoringMessageHandler{}, custom_message_handler: IgnoringMessageHandler{}
 			};
 			let peer_manager = Arc::new(PeerManager::new(msg_handler, 0, &seed, logger.clone(), keys_manager.clone()));
-			let node = Node { node: manager, p2p_gossip_sync, rapid_gossip_sync, peer_manager, chain_monitor, persister, tx_broadcaster, network_graph, logger, best_block, scorer };
+			let contract_manager = Arc::new(ContractManager::new(keys_manager.clone(), manager.clone(), manager.clone(), tx_broadcaster.clone(), fee_estimator.clone(), logger.clone(), manager.get_our_node_id()));
+			let node = Node { node: manager, p2p_gossip_sync, rapid_gossip_sync, peer_manager, chain_monitor, persister, tx_broadcaster, network_graph, logger, best_block, scorer, contract_manager };
 			nodes.push(node);
 		}
 
@@ -1264,7 +1312,7 @@ mod tests {
 		let data_dir = nodes[0].persister.get_data_dir();
 		let persister = Arc::new(Persister::new(data_dir));
 		let event_handler = |_: _| {};
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].p2p_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].p2p_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		macro_rules! check_persisted_data {
 			($node: expr, $filepath: expr) => {
@@ -1329,7 +1377,7 @@ mod tests {
 		let data_dir = nodes[0].persister.get_data_dir();
 		let persister = Arc::new(Persister::new(data_dir));
 		let event_handler = |_: _| {};
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 		loop {
 			let log_entries = nodes[0].logger.lines.lock().unwrap();
 			let desired_log_1 = "Calling ChannelManager's timer_tick_occurred".to_string();
@@ -1347,6 +1395,72 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn test_contract_manager_timer_tick_called() {
+		// Test that a matured contract is exercised on the `ContractManager::timer_tick_occurred`
+		// calls made every `CONTRACT_TIMER`, without the `ContractManager` being provided to any
+		// `PeerManager`.
+		let (_, nodes) = create_nodes(1, "test_contract_manager_timer_tick_called");
+		let secp_ctx = Secp256k1::new();
+		let holder_key = SecretKey::from_slice(&[1; 32]).unwrap();
+		let counterparty_key = SecretKey::from_slice(&[2; 32]).unwrap();
+		let counterparty_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[3; 32]).unwrap());
+		let holder_script = Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[1]));
+		let counterparty_script = Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[2]));
+		let outpoint = OutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), index: 0 };
+		let branches = vec![SettlementBranch {
+			outcome: vec![0], holder_payout_satoshis: 50_000, counterparty_payout_satoshis: 50_000,
+		}];
+
+		// Build the contract's settlement bundle as seen by either party, completing ours with our
+		// counterparty's signature.
+		let collateral = CollateralOutput {
+			outpoint, value_satoshis: 100_000,
+			holder_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, &holder_key),
+			counterparty_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, &counterparty_key),
+		};
+		let counterparty_collateral = CollateralOutput {
+			outpoint, value_satoshis: 100_000,
+			holder_funding_pubkey: collateral.counterparty_funding_pubkey,
+			counterparty_funding_pubkey: collateral.holder_funding_pubkey,
+		};
+		let mut bundle = SettlementBundle::new(collateral, holder_script.clone(),
+			counterparty_script.clone(), 100, 546, branches.clone()).unwrap();
+		let counterparty_bundle = SettlementBundle::new(counterparty_collateral, counterparty_script,
+			holder_script, 100, 546, branches).unwrap();
+		let signature = counterparty_bundle.sign_branch(0, &counterparty_key, &secp_ctx).unwrap();
+		bundle.set_counterparty_signatures(vec![signature], &secp_ctx).unwrap();
+
+		let contract_id = ContractId([42; 32]);
+		nodes[0].contract_manager.register_contract(contract_id, counterparty_node_id, [0; 32], bundle, 50_000).unwrap();
+		nodes[0].contract_manager.filtered_block_connected(&genesis_block(Network::Bitcoin).header, &[], 100);
+
+		let data_dir = nodes[0].persister.get_data_dir();
+		let persister = Arc::new(Persister::new(data_dir));
+		let event_handler = |_: _| {};
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
+
+		let events = Mutex::new(Vec::new());
+		let start_time = std::time::Instant::now();
+		loop {
+			nodes[0].contract_manager.process_pending_events(&|event| events.lock().unwrap().push(event));
+			if !events.lock().unwrap().is_empty() { break }
+			assert!(start_time.elapsed() < Duration::from_secs(EVENT_DEADLINE), "Contract was not exercised");
+		}
+		match &events.into_inner().unwrap()[..] {
+			[Event::ContractExerciseProgress { contract_id: id, counterparty_node_id: node_id, status }] => {
+				assert_eq!(*id, contract_id);
+				assert_eq!(*node_id, counterparty_node_id);
+				assert_eq!(*status, ContractExerciseStatus::AttestationRequested { maturity: 100 });
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		if !std::thread::panicking() {
+			bg_processor.stop().unwrap();
+		}
+	}
+
 	#[test]
 	fn test_channel_manager_persist_error() {
 		// Test that if we encounter an error during manager persistence, the thread panics.
@@ -1356,7 +1470,7 @@ mod tests {
 		let data_dir = nodes[0].persister.get_data_dir();
 		let persister = Arc::new(Persister::new(data_dir).with_manager_error(std::io::ErrorKind::Other, "test"));
 		let event_handler = |_: _| {};
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 		match bg_processor.join() {
 			Ok(_) => panic!("Expected error persisting manager"),
 			Err(e) => {
@@ -1379,7 +1493,7 @@ mod tests {
 		let bp_future = super::process_events_async(
 			persister, |_: _| {async {}}, nodes[0].chain_monitor.clone(), nodes[0].node.clone(),
 			nodes[0].rapid_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(),
-			Some(nodes[0].scorer.clone()), move |dur: Duration| {
+			Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()), move |dur: Duration| {
 				Box::pin(async move {
 					tokio::time::sleep(dur).await;
 					false // Never exit
@@ -1402,7 +1516,7 @@ mod tests {
 		let data_dir = nodes[0].persister.get_data_dir();
 		let persister = Arc::new(Persister::new(data_dir).with_graph_error(std::io::ErrorKind::Other, "test"));
 		let event_handler = |_: _| {};
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].p2p_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].p2p_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		match bg_processor.stop() {
 			Ok(_) => panic!("Expected error persisting network graph"),
@@ -1420,7 +1534,7 @@ mod tests {
 		let data_dir = nodes[0].persister.get_data_dir();
 		let persister = Arc::new(Persister::new(data_dir).with_scorer_error(std::io::ErrorKind::Other, "test"));
 		let event_handler = |_: _| {};
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(),  nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(),  nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		match bg_processor.stop() {
 			Ok(_) => panic!("Expected error persisting scorer"),
@@ -1448,7 +1562,7 @@ mod tests {
 			_ => panic!("Unexpected event: {:?}", event),
 		};
 
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		// Open a channel and check that the FundingGenerationReady event was handled.
 		begin_open_channel!(nodes[0], nodes[1], channel_value);
@@ -1485,7 +1599,7 @@ mod tests {
 			_ => panic!("Unexpected event: {:?}", event),
 		};
 		let persister = Arc::new(Persister::new(data_dir));
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		// Force close the channel and check that the SpendableOutputs event was handled.
 		nodes[0].node.force_close_broadcasting_latest_txn(&nodes[0].node.list_channels()[0].channel_id, &nodes[1].node.get_our_node_id()).unwrap();
@@ -1511,7 +1625,7 @@ mod tests {
 		let data_dir = nodes[0].persister.get_data_dir();
 		let persister = Arc::new(Persister::new(data_dir));
 		let event_handler = |_: _| {};
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		loop {
 			let log_entries = nodes[0].logger.lines.lock().unwrap();
@@ -1584,7 +1698,7 @@ mod tests {
 		let persister = Arc::new(Persister::new(data_dir).with_graph_persistence_notifier(sender));
 
 		let event_handler = |_: _| {};
-		let background_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].rapid_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let background_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].rapid_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		do_test_not_pruning_network_graph_until_graph_sync_completion!(nodes,
 			receiver.recv_timeout(Duration::from_secs(super::FIRST_NETWORK_PRUNE_TIMER * 5)),
@@ -1606,7 +1720,7 @@ mod tests {
 		let bp_future = super::process_events_async(
 			persister, |_: _| {async {}}, nodes[0].chain_monitor.clone(), nodes[0].node.clone(),
 			nodes[0].rapid_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(),
-			Some(nodes[0].scorer.clone()), move |dur: Duration| {
+			Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()), move |dur: Duration| {
 				let mut exit_receiver = exit_receiver.clone();
 				Box::pin(async move {
 					tokio::select! {
@@ -1741,7 +1855,7 @@ mod tests {
 		let (_, nodes) = create_nodes(1, "test_payment_path_scoring");
 		let data_dir = nodes[0].persister.get_data_dir();
 		let persister = Arc::new(Persister::new(data_dir));
-		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()));
+		let bg_processor = BackgroundProcessor::start(persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(), nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(), Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()));
 
 		do_test_payment_path_scoring!(nodes, receiver.recv_timeout(Duration::from_secs(EVENT_DEADLINE)));
 
@@ -1780,7 +1894,7 @@ mod tests {
 		let bp_future = super::process_events_async(
 			persister, event_handler, nodes[0].chain_monitor.clone(), nodes[0].node.clone(),
 			nodes[0].no_gossip_sync(), nodes[0].peer_manager.clone(), nodes[0].logger.clone(),
-			Some(nodes[0].scorer.clone()), move |dur: Duration| {
+			Some(nodes[0].scorer.clone()), Some(nodes[0].contract_manager.clone()), move |dur: Duration| {
 				let mut exit_receiver = exit_receiver.clone();
 				Box::pin(async move {
 					tokio::select! {
@@ -28,10 +28,10 @@
 compile_error!("at least one of the `std` or `no-std` features must be enabled");
 
 pub mod payment;
+pub mod receipt;
+pub mod uri;
 pub mod utils;
 
-pub(crate) mod time_utils;
-
 extern crate bech32;
 extern crate bitcoin_hashes;
 #[macro_use] extern crate lightning;
@@ -56,6 +56,7 @@ use lightning::ln::features::Bolt11InvoiceFeatures;
 use lightning::routing::gossip::RoutingFees;
 use lightning::routing::router::RouteHint;
 use lightning::util::invoice::construct_invoice_preimage;
+use lightning::util::time::Time;
 
 use secp256k1::PublicKey;
 use secp256k1::{Message, Secp256k1};
@@ -1364,6 +1365,15 @@ impl Bolt11Invoice {
 		Self::is_expired_from_epoch(&self.timestamp(), self.expiry_time())
 	}
 
+	/// Returns whether the invoice has expired, using the given [`Time`] as the source of the
+	/// current time.
+	///
+	/// This is available without the `std` feature, allowing `no-std` users to provide their own
+	/// clock, and allows tests to fast-forward time.
+	pub fn is_expired_using<T: Time>(&self) -> bool {
+		self.would_expire(T::duration_since_epoch())
+	}
+
 	/// Returns whether the expiry time from the given epoch has passed.
 	#[cfg(feature = "std")]
 	pub(crate) fn is_expired_from_epoch(epoch: &SystemTime, expiry_time: Duration) -> bool {
@@ -1381,6 +1391,12 @@ impl Bolt11Invoice {
 			.unwrap_or(Duration::from_nanos(0))
 	}
 
+	/// Returns the Duration remaining until the invoice expires, using the given [`Time`] as the
+	/// source of the current time.
+	pub fn duration_until_expiry_using<T: Time>(&self) -> Duration {
+		self.expiration_remaining_from_epoch(T::duration_since_epoch())
+	}
+
 	/// Returns the Duration remaining until the invoice expires given the current time.
 	/// `time` is the timestamp as a duration since the Unix epoch.
 	pub fn expiration_remaining_from_epoch(&self, time: Duration) -> Duration {
@@ -1632,6 +1648,10 @@ pub enum CreationError {
 	///
 	/// [`MIN_FINAL_CLTV_EXPIRY_DELTA`]: lightning::ln::channelmanager::MIN_FINAL_CLTV_EXPIRY_DELTA
 	MinFinalCltvExpiryDeltaTooShort,
+
+	/// An on-chain fallback script could not be fetched, or could not be encoded as a
+	/// [`Fallback`].
+	InvalidFallback,
 }
 
 impl Display for CreationError {
@@ -1644,6 +1664,7 @@ impl Display for CreationError {
 			CreationError::MissingRouteHints => f.write_str("The invoice required route hints and they weren't provided"),
 			CreationError::MinFinalCltvExpiryDeltaTooShort => f.write_str(
 				"The supplied final CLTV expiry delta was less than LDK's `MIN_FINAL_CLTV_EXPIRY_DELTA`"),
+			CreationError::InvalidFallback => f.write_str("The on-chain fallback script was unavailable or not a standard address"),
 		}
 	}
 }
@@ -2205,6 +2226,144 @@ mod test {
 		let invoice = Bolt11Invoice::from_signed(signed_invoice).unwrap();
 
 		assert!(invoice.would_expire(Duration::from_secs(1234567 + DEFAULT_EXPIRY_TIME + 1)));
+
+		#[cfg(feature = "std")] {
+			use lightning::util::time::tests::SinceEpoch;
+			SinceEpoch::advance(Duration::from_secs(1234567));
+			assert!(!invoice.is_expired_using::<SinceEpoch>());
+			assert_eq!(invoice.duration_until_expiry_using::<SinceEpoch>(), Duration::from_secs(DEFAULT_EXPIRY_TIME));
+			SinceEpoch::advance(Duration::from_secs(DEFAULT_EXPIRY_TIME + 1));
+			assert!(invoice.is_expired_using::<SinceEpoch>());
+			assert_eq!(invoice.duration_until_expiry_using::<SinceEpoch>(), Duration::from_secs(0));
+		}
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn test_time_dependent_logic_follows_injected_time() {
+		// Check that invoice expiry, the decay of the scorer's knowledge and the onion messenger's
+		// rate limits all follow a single injected `Time`, only observing time passing when it is
+		// fast-forwarded.
+		use crate::*;
+		use bitcoin::blockdata::constants::genesis_block;
+		use bitcoin::network::constants::Network;
+		use lightning::ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
+		use lightning::ln::msgs::{Init, OnionMessageHandler, UnsignedChannelAnnouncement, UnsignedChannelUpdate};
+		use lightning::ln::peer_handler::IgnoringMessageHandler;
+		use lightning::onion_message::{CustomOnionMessageContents, DefaultMessageRouter, Destination, OnionMessageContents, OnionMessagePath, OnionMessageRateLimit, OnionMessageRateLimits, OnionMessenger, SendError};
+		use lightning::routing::gossip::{NetworkGraph, NodeId};
+		use lightning::routing::router::{Path, RouteHop};
+		use lightning::routing::scoring::{ProbabilisticScorerUsingTime, ProbabilisticScoringDecayParameters, Score};
+		use lightning::sign::KeysManager;
+		use lightning::util::ser::{Writeable, Writer};
+		use lightning::util::test_utils::{TestChainSource, TestLogger};
+		use lightning::util::time::{Time, TimeSource};
+		use lightning::util::time::tests::SinceEpoch;
+		use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+		struct InjectedTimeSource;
+		impl TimeSource for InjectedTimeSource {
+			fn now(&self) -> Duration { SinceEpoch::duration_since_epoch() }
+		}
+
+		struct TestMessage;
+		impl CustomOnionMessageContents for TestMessage {
+			fn tlv_type(&self) -> u64 { 4242 }
+		}
+		impl Writeable for TestMessage {
+			fn write<W: Writer>(&self, _w: &mut W) -> Result<(), lightning::io::Error> { Ok(()) }
+		}
+
+		let secp_ctx = Secp256k1::new();
+		let logger = TestLogger::new();
+		SinceEpoch::advance(Duration::from_secs(1234567));
+
+		// An invoice created now, expiring in a minute.
+		let signed_invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("Test".into())
+			.payment_hash(sha256::Hash::from_slice(&[0;32][..]).unwrap())
+			.payment_secret(PaymentSecret([0; 32]))
+			.duration_since_epoch(SinceEpoch::duration_since_epoch())
+			.expiry_time(Duration::from_secs(60))
+			.build_raw()
+			.unwrap()
+			.sign::<_, ()>(|hash| {
+				let privkey = SecretKey::from_slice(&[41; 32]).unwrap();
+				Ok(secp_ctx.sign_ecdsa_recoverable(hash, &privkey))
+			})
+			.unwrap();
+		let invoice = Bolt11Invoice::from_signed(signed_invoice).unwrap();
+
+		// A scorer which learned an upper bound on a channel's liquidity, decaying every 60 seconds.
+		let node_ids: Vec<NodeId> = [42, 43].iter()
+			.map(|i| NodeId::from_pubkey(&PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[*i; 32]).unwrap())))
+			.collect();
+		let (node_id_1, node_id_2) = if node_ids[0] < node_ids[1] { (node_ids[0], node_ids[1]) } else { (node_ids[1], node_ids[0]) };
+		let network_graph = NetworkGraph::new(Network::Testnet, &logger);
+		let chain_hash = genesis_block(Network::Testnet).header.block_hash();
+		network_graph.update_channel_from_unsigned_announcement(&UnsignedChannelAnnouncement {
+			features: ChannelFeatures::empty(), chain_hash, short_channel_id: 42, node_id_1, node_id_2,
+			bitcoin_key_1: node_id_1, bitcoin_key_2: node_id_2, excess_data: Vec::new(),
+		}, &None::<&TestChainSource>).unwrap();
+		for flags in 0..2 {
+			network_graph.update_channel_unsigned(&UnsignedChannelUpdate {
+				chain_hash, short_channel_id: 42, timestamp: 100, flags, cltv_expiry_delta: 18,
+				htlc_minimum_msat: 0, htlc_maximum_msat: 1_000, fee_base_msat: 0,
+				fee_proportional_millionths: 0, excess_data: Vec::new(),
+			}).unwrap();
+		}
+		let decay_params = ProbabilisticScoringDecayParameters {
+			liquidity_offset_half_life: Duration::from_secs(60), ..Default::default()
+		};
+		let mut scorer = ProbabilisticScorerUsingTime::<_, _, SinceEpoch>::new(decay_params, &network_graph, &logger);
+		let path = Path {
+			hops: vec![RouteHop {
+				pubkey: node_id_2.as_pubkey().unwrap(), node_features: NodeFeatures::empty(),
+				short_channel_id: 42, channel_features: ChannelFeatures::empty(), fee_msat: 500,
+				cltv_expiry_delta: 18,
+			}],
+			blinded_tail: None,
+		};
+		scorer.payment_path_failed(&path, 42);
+		let learned_range = scorer.estimated_channel_liquidity_range(42, &node_id_2).unwrap();
+
+		// An onion messenger allowing a single message per second to each peer.
+		let keys_manager = KeysManager::new(&[42; 32], 42, 42);
+		let message_router = DefaultMessageRouter::new(&network_graph);
+		let messenger = OnionMessenger::new(
+			&keys_manager, &keys_manager, &logger, &message_router, &IgnoringMessageHandler {},
+			&IgnoringMessageHandler {}, &IgnoringMessageHandler {}
+		);
+		messenger.set_time_source(InjectedTimeSource);
+		messenger.set_rate_limits(OnionMessageRateLimits {
+			inbound: None, outbound: Some(OnionMessageRateLimit { messages_per_second: 1, burst: 1 }),
+		});
+		let peer = node_id_1.as_pubkey().unwrap();
+		let mut features = InitFeatures::empty();
+		features.set_onion_messages_optional();
+		let init = Init { features, networks: None, remote_network_address: None };
+		messenger.peer_connected(&peer, &init, true).unwrap();
+		let path = OnionMessagePath {
+			intermediate_nodes: vec![], destination: Destination::Node(peer), first_node_addresses: None,
+		};
+		let send = || messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestMessage), None);
+		send().unwrap();
+		assert_eq!(send(), Err(SendError::RateLimited));
+
+		// Just short of the invoice's expiry and the scorer's half-life, neither has changed, while
+		// the rate limit has refilled.
+		SinceEpoch::advance(Duration::from_millis(59_999));
+		assert!(!invoice.is_expired_using::<SinceEpoch>());
+		assert_eq!(scorer.estimated_channel_liquidity_range(42, &node_id_2), Some(learned_range));
+		send().unwrap();
+		assert_eq!(send(), Err(SendError::RateLimited));
+
+		// Once the clock passes the invoice's expiry and the scorer's half-life, both see it.
+		SinceEpoch::advance(Duration::from_millis(2));
+		assert!(invoice.is_expired_using::<SinceEpoch>());
+		let (min, max) = scorer.estimated_channel_liquidity_range(42, &node_id_2).unwrap();
+		assert_eq!(min, learned_range.0);
+		assert!(max > learned_range.1);
 	}
 
 	#[cfg(feature = "serde")]
@@ -0,0 +1,585 @@
+//! Proof-of-payment receipts assembled by the payer once an invoice has been paid.
+//!
+//! A [`PaymentReceipt`] bundles the paid BOLT 11 or BOLT 12 invoice with the payment preimage
+//! received in [`Event::PaymentSent`], a summary of the routing fees paid and a signature by the
+//! payer into a single serializable artifact. Third parties can check it via
+//! [`PaymentReceipt::verify`], e.g. for bookkeeping or to settle a dispute over whether an invoice
+//! was paid.
+//!
+//! As every node along the payment's paths learns the preimage, a receipt only proves who paid if
+//! its signer is tied to the invoice. BOLT 12 invoices commit to the payer's key, which receipts
+//! must be signed with, while for BOLT 11 invoices the verifier must know the payer's key.
+//!
+//! [`Event::PaymentSent`]: lightning::events::Event::PaymentSent
+
+use bitcoin_hashes::{Hash, HashEngine, sha256};
+use lightning::io;
+use lightning::ln::{PaymentHash, PaymentPreimage};
+use lightning::ln::msgs::DecodeError;
+use lightning::offers::invoice::Bolt12Invoice;
+use lightning::routing::router::Path;
+use lightning::util::ser::{Readable, Writeable, Writer};
+use secp256k1::{Message, PublicKey, Secp256k1, Verification};
+use secp256k1::ecdsa::Signature;
+
+use crate::Bolt11Invoice;
+use crate::prelude::*;
+
+use core::convert::TryFrom;
+use core::fmt::{self, Display, Formatter};
+use core::str::FromStr;
+
+/// The tag prefixed to the contents of a [`PaymentReceipt`] before hashing them for signing.
+const RECEIPT_SIGNATURE_TAG: &[u8] = b"lightning-invoice payment receipt";
+
+/// The invoice paid in a [`PaymentReceipt`].
+#[derive(Clone, Debug)]
+pub enum PaidInvoice {
+	/// A BOLT 11 invoice.
+	Bolt11(Bolt11Invoice),
+	/// A BOLT 12 invoice, as received in response to an invoice request for an offer or for a
+	/// refund.
+	Bolt12(Bolt12Invoice),
+}
+
+impl PaidInvoice {
+	/// Returns the hash of the preimage the invoice was paid for.
+	pub fn payment_hash(&self) -> PaymentHash {
+		match self {
+			PaidInvoice::Bolt11(invoice) => PaymentHash(invoice.payment_hash().into_inner()),
+			PaidInvoice::Bolt12(invoice) => invoice.payment_hash(),
+		}
+	}
+
+	/// Returns the amount the invoice requested, in msat, if any.
+	pub fn amount_msat(&self) -> Option<u64> {
+		match self {
+			PaidInvoice::Bolt11(invoice) => invoice.amount_milli_satoshis(),
+			PaidInvoice::Bolt12(invoice) => Some(invoice.amount_msats()),
+		}
+	}
+
+	/// Returns the public key of the payee which signed the invoice.
+	pub fn payee_pubkey(&self) -> PublicKey {
+		match self {
+			PaidInvoice::Bolt11(invoice) =>
+				invoice.payee_pub_key().cloned().unwrap_or_else(|| invoice.recover_payee_pub_key()),
+			PaidInvoice::Bolt12(invoice) => invoice.signing_pubkey(),
+		}
+	}
+
+	/// Returns the public key of the payer the invoice was issued to, if the invoice commits to
+	/// one, which is only the case for BOLT 12 invoices.
+	pub fn payer_id(&self) -> Option<PublicKey> {
+		match self {
+			PaidInvoice::Bolt11(_) => None,
+			PaidInvoice::Bolt12(invoice) => Some(invoice.payer_id()),
+		}
+	}
+}
+
+impl Writeable for PaidInvoice {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		match self {
+			PaidInvoice::Bolt11(invoice) => {
+				0u8.write(w)?;
+				invoice.to_string().into_bytes().write(w)
+			},
+			PaidInvoice::Bolt12(invoice) => {
+				1u8.write(w)?;
+				invoice.encode().write(w)
+			},
+		}
+	}
+}
+
+impl Readable for PaidInvoice {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let invoice_type: u8 = Readable::read(r)?;
+		let bytes: Vec<u8> = Readable::read(r)?;
+		match invoice_type {
+			0 => {
+				let invoice_str = String::from_utf8(bytes).map_err(|_| DecodeError::InvalidValue)?;
+				Bolt11Invoice::from_str(&invoice_str)
+					.map(PaidInvoice::Bolt11)
+					.map_err(|_| DecodeError::InvalidValue)
+			},
+			1 => Bolt12Invoice::try_from(bytes)
+				.map(PaidInvoice::Bolt12)
+				.map_err(|_| DecodeError::InvalidValue),
+			_ => Err(DecodeError::UnknownRequiredFeature),
+		}
+	}
+}
+
+/// A summary of one of the paths a payment was sent over, as included in a [`PaymentReceipt`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PathFeeSummary {
+	/// The amount delivered to the recipient over the path, in msat.
+	pub amount_msat: u64,
+	/// The fees paid to the intermediate nodes along the path, in msat.
+	pub fee_msat: u64,
+	/// The number of hops in the path, excluding any within a blinded path.
+	pub hop_count: u8,
+}
+
+impl From<&Path> for PathFeeSummary {
+	fn from(path: &Path) -> Self {
+		PathFeeSummary {
+			amount_msat: path.final_value_msat(),
+			fee_msat: path.fee_msat(),
+			hop_count: core::cmp::min(path.hops.len(), u8::max_value() as usize) as u8,
+		}
+	}
+}
+
+lightning::impl_writeable_tlv_based!(PathFeeSummary, {
+	(0, amount_msat, required),
+	(2, fee_msat, required),
+	(4, hop_count, required),
+});
+
+/// An error encountered when building or verifying a [`PaymentReceipt`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PaymentReceiptError {
+	/// The payment preimage doesn't hash to the invoice's payment hash.
+	InvalidPreimage,
+	/// The fees of the summarized paths don't add up to the total fees paid.
+	InconsistentFees,
+	/// The summarized paths delivered less than the amount requested by the invoice.
+	InsufficientAmount,
+	/// The amounts or fees of the summarized paths overflow when added up.
+	AmountOverflow,
+	/// The receipt was signed by a key other than the payer's, either as given by a BOLT 12
+	/// invoice or as expected by the verifier.
+	PayerMismatch,
+	/// The receipt is for a BOLT 11 invoice, which doesn't commit to its payer, and the verifier
+	/// didn't provide the expected payer's key.
+	UnknownPayer,
+	/// The payer's signature over the receipt is invalid.
+	InvalidSignature,
+	/// The signing function failed to sign the receipt.
+	SigningFailed,
+}
+
+impl Display for PaymentReceiptError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			PaymentReceiptError::InvalidPreimage =>
+				f.write_str("The payment preimage does not match the invoice's payment hash"),
+			PaymentReceiptError::InconsistentFees =>
+				f.write_str("The path fees do not add up to the total fees paid"),
+			PaymentReceiptError::InsufficientAmount =>
+				f.write_str("The paths delivered less than the invoice's amount"),
+			PaymentReceiptError::AmountOverflow =>
+				f.write_str("The path amounts or fees overflow"),
+			PaymentReceiptError::PayerMismatch =>
+				f.write_str("The receipt was not signed by the invoice's payer"),
+			PaymentReceiptError::UnknownPayer =>
+				f.write_str("The payer's key is required to verify a BOLT 11 receipt"),
+			PaymentReceiptError::InvalidSignature => f.write_str("Invalid payer signature"),
+			PaymentReceiptError::SigningFailed => f.write_str("Failed signing the receipt"),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PaymentReceiptError { }
+
+/// A signed proof that the payer paid an invoice, built via a [`PaymentReceiptBuilder`].
+///
+/// The payment preimage proves that the payee released it, which it should only do once paid,
+/// while the payer's signature binds the fee summary to the payer. Use [`Writeable::encode`] and
+/// [`Readable::read`] to pass the receipt to third parties, who should call [`Self::verify`].
+#[derive(Clone, Debug)]
+pub struct PaymentReceipt {
+	invoice: PaidInvoice,
+	payment_preimage: PaymentPreimage,
+	fee_paid_msat: Option<u64>,
+	paths: Vec<PathFeeSummary>,
+	payer_id: PublicKey,
+	signature: Signature,
+}
+
+impl PaymentReceipt {
+	/// Returns the invoice which was paid.
+	pub fn invoice(&self) -> &PaidInvoice {
+		&self.invoice
+	}
+
+	/// Returns the preimage the payee released in exchange for the payment.
+	pub fn payment_preimage(&self) -> PaymentPreimage {
+		self.payment_preimage
+	}
+
+	/// Returns the total fees paid to intermediate nodes, in msat, if known.
+	pub fn fee_paid_msat(&self) -> Option<u64> {
+		self.fee_paid_msat
+	}
+
+	/// Returns a summary of each of the paths the payment was sent over, if they were provided.
+	pub fn paths(&self) -> &[PathFeeSummary] {
+		&self.paths
+	}
+
+	/// Returns the public key of the payer which signed the receipt.
+	pub fn payer_id(&self) -> PublicKey {
+		self.payer_id
+	}
+
+	/// Returns the payer's signature over the receipt.
+	pub fn signature(&self) -> Signature {
+		self.signature
+	}
+
+	/// Checks that the payment preimage matches the invoice, the fee summary is consistent with
+	/// the invoice and that the receipt was signed by the invoice's payer.
+	///
+	/// For BOLT 12 invoices, [`Self::payer_id`] must be the payer id the invoice was issued to and,
+	/// if given, `expected_payer_id`. As BOLT 11 invoices don't commit to their payer, the
+	/// verifier must know the payer's key and pass it as `expected_payer_id`.
+	///
+	/// The invoice's own signature is checked whenever a receipt is read.
+	pub fn verify<T: Verification>(
+		&self, expected_payer_id: Option<PublicKey>, secp_ctx: &Secp256k1<T>
+	) -> Result<(), PaymentReceiptError> {
+		check_preimage(&self.invoice, &self.payment_preimage)?;
+		check_payer_id(&self.invoice, &self.payer_id)?;
+		match expected_payer_id {
+			Some(expected_payer_id) if expected_payer_id != self.payer_id =>
+				return Err(PaymentReceiptError::PayerMismatch),
+			None if self.invoice.payer_id().is_none() => return Err(PaymentReceiptError::UnknownPayer),
+			_ => {},
+		}
+		if !self.paths.is_empty() {
+			let paths_fee_msat = self.paths.iter()
+				.try_fold(0u64, |total, path| total.checked_add(path.fee_msat))
+				.ok_or(PaymentReceiptError::AmountOverflow)?;
+			if self.fee_paid_msat.map_or(false, |fee_paid_msat| fee_paid_msat != paths_fee_msat) {
+				return Err(PaymentReceiptError::InconsistentFees);
+			}
+			let paths_amount_msat = self.paths.iter()
+				.try_fold(0u64, |total, path| total.checked_add(path.amount_msat))
+				.ok_or(PaymentReceiptError::AmountOverflow)?;
+			if self.invoice.amount_msat().map_or(false, |amount_msat| paths_amount_msat < amount_msat) {
+				return Err(PaymentReceiptError::InsufficientAmount);
+			}
+		}
+		let message = signature_message(
+			&self.invoice, &self.payment_preimage, self.fee_paid_msat, &self.paths, &self.payer_id
+		);
+		secp_ctx.verify_ecdsa(&message, &self.signature, &self.payer_id)
+			.map_err(|_| PaymentReceiptError::InvalidSignature)
+	}
+}
+
+lightning::impl_writeable_tlv_based!(PaymentReceipt, {
+	(0, invoice, required),
+	(2, payment_preimage, required),
+	(4, fee_paid_msat, option),
+	(6, paths, optional_vec),
+	(8, payer_id, required),
+	(10, signature, required),
+});
+
+/// Builds a [`PaymentReceipt`] from the details of a successful payment.
+///
+/// The details are provided in [`Event::PaymentSent`] and, for the optional per-path fee
+/// summary, [`Event::PaymentPathSuccessful`].
+///
+/// [`Event::PaymentSent`]: lightning::events::Event::PaymentSent
+/// [`Event::PaymentPathSuccessful`]: lightning::events::Event::PaymentPathSuccessful
+pub struct PaymentReceiptBuilder {
+	invoice: PaidInvoice,
+	payment_preimage: PaymentPreimage,
+	fee_paid_msat: Option<u64>,
+	paths: Vec<PathFeeSummary>,
+}
+
+impl PaymentReceiptBuilder {
+	/// Starts building a receipt for `invoice`, which was paid in exchange for `payment_preimage`.
+	///
+	/// Errors if the preimage doesn't match the invoice's payment hash.
+	pub fn new(invoice: PaidInvoice, payment_preimage: PaymentPreimage) -> Result<Self, PaymentReceiptError> {
+		check_preimage(&invoice, &payment_preimage)?;
+		Ok(Self { invoice, payment_preimage, fee_paid_msat: None, paths: Vec::new() })
+	}
+
+	/// Sets the total fees paid to intermediate nodes, as given by
+	/// [`Event::PaymentSent::fee_paid_msat`].
+	///
+	/// [`Event::PaymentSent::fee_paid_msat`]: lightning::events::Event::PaymentSent::fee_paid_msat
+	pub fn fee_paid_msat(mut self, fee_paid_msat: u64) -> Self {
+		self.fee_paid_msat = Some(fee_paid_msat);
+		self
+	}
+
+	/// Adds a summary of a path the payment was sent over, as given by
+	/// [`Event::PaymentPathSuccessful::path`].
+	///
+	/// If any paths are added, all paths of the payment must be, or the receipt will fail to
+	/// verify.
+	///
+	/// [`Event::PaymentPathSuccessful::path`]: lightning::events::Event::PaymentPathSuccessful::path
+	pub fn path(mut self, path: &Path) -> Self {
+		self.paths.push(PathFeeSummary::from(path));
+		self
+	}
+
+	/// Signs the receipt using the given function, which must produce a signature for `payer_id`.
+	/// The key is included as [`PaymentReceipt::payer_id`].
+	///
+	/// For BOLT 12 invoices, `payer_id` must be the payer id the invoice was issued to, i.e. the
+	/// one used to sign the invoice request or refund.
+	///
+	/// Errors if `payer_id` doesn't match the invoice, or if the signing function fails or doesn't
+	/// produce a valid signature.
+	///
+	/// This is not exported to bindings users as functions aren't currently mapped.
+	pub fn build_signed<F, E, T: Verification>(
+		self, payer_id: PublicKey, sign: F, secp_ctx: &Secp256k1<T>
+	) -> Result<PaymentReceipt, PaymentReceiptError>
+	where
+		F: FnOnce(&Message) -> Result<Signature, E>
+	{
+		check_payer_id(&self.invoice, &payer_id)?;
+		let message = signature_message(
+			&self.invoice, &self.payment_preimage, self.fee_paid_msat, &self.paths, &payer_id
+		);
+		let signature = sign(&message).map_err(|_| PaymentReceiptError::SigningFailed)?;
+		secp_ctx.verify_ecdsa(&message, &signature, &payer_id)
+			.map_err(|_| PaymentReceiptError::InvalidSignature)?;
+		Ok(PaymentReceipt {
+			invoice: self.invoice,
+			payment_preimage: self.payment_preimage,
+			fee_paid_msat: self.fee_paid_msat,
+			paths: self.paths,
+			payer_id,
+			signature,
+		})
+	}
+}
+
+fn check_preimage(invoice: &PaidInvoice, payment_preimage: &PaymentPreimage) -> Result<(), PaymentReceiptError> {
+	let payment_hash = PaymentHash(sha256::Hash::hash(&payment_preimage.0).into_inner());
+	if payment_hash != invoice.payment_hash() {
+		return Err(PaymentReceiptError::InvalidPreimage);
+	}
+	Ok(())
+}
+
+fn check_payer_id(invoice: &PaidInvoice, payer_id: &PublicKey) -> Result<(), PaymentReceiptError> {
+	if invoice.payer_id().map_or(false, |invoice_payer_id| invoice_payer_id != *payer_id) {
+		return Err(PaymentReceiptError::PayerMismatch);
+	}
+	Ok(())
+}
+
+/// Computes the message the payer signs, committing to everything in the receipt but the
+/// signature itself.
+fn signature_message(
+	invoice: &PaidInvoice, payment_preimage: &PaymentPreimage, fee_paid_msat: Option<u64>,
+	paths: &[PathFeeSummary], payer_id: &PublicKey
+) -> Message {
+	let mut engine = sha256::Hash::engine();
+	engine.input(RECEIPT_SIGNATURE_TAG);
+	engine.input(&invoice.encode());
+	engine.input(&payment_preimage.encode());
+	engine.input(&fee_paid_msat.encode());
+	engine.input(&(paths.len() as u64).encode());
+	for path in paths {
+		engine.input(&path.encode());
+	}
+	engine.input(&payer_id.encode());
+	Message::from_slice(&sha256::Hash::from_engine(engine).into_inner()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PaidInvoice, PathFeeSummary, PaymentReceipt, PaymentReceiptBuilder, PaymentReceiptError};
+	use crate::{Currency, InvoiceBuilder};
+
+	use bitcoin_hashes::{Hash, sha256};
+	use lightning::blinded_path::BlindedPath;
+	use lightning::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
+	use lightning::ln::features::{BlindedHopFeatures, ChannelFeatures, NodeFeatures};
+	use lightning::offers::invoice::BlindedPayInfo;
+	use lightning::offers::refund::RefundBuilder;
+	use lightning::routing::router::{Path, RouteHop};
+	use lightning::sign::EntropySource;
+	use lightning::util::ser::{Readable, Writeable};
+	use secp256k1::{KeyPair, Message, PublicKey, Secp256k1, SecretKey};
+	use secp256k1::ecdsa::Signature;
+
+	use core::convert::Infallible;
+	use core::time::Duration;
+
+	fn payer_key() -> SecretKey {
+		SecretKey::from_slice(&[43; 32]).unwrap()
+	}
+
+	fn payer_id() -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &payer_key())
+	}
+
+	fn payer_sign(message: &Message) -> Result<Signature, Infallible> {
+		Ok(Secp256k1::new().sign_ecdsa(message, &payer_key()))
+	}
+
+	fn invoice(payment_preimage: &PaymentPreimage) -> PaidInvoice {
+		let secp_ctx = Secp256k1::new();
+		let payee_key = SecretKey::from_slice(&[41; 32]).unwrap();
+		let invoice = InvoiceBuilder::new(Currency::Bitcoin)
+			.description("Receipt test".into())
+			.payment_hash(sha256::Hash::hash(&payment_preimage.0))
+			.payment_secret(PaymentSecret([42; 32]))
+			.duration_since_epoch(Duration::from_secs(1_700_000_000))
+			.min_final_cltv_expiry_delta(144)
+			.amount_milli_satoshis(100_000)
+			.build_signed(|hash| secp_ctx.sign_ecdsa_recoverable(hash, &payee_key))
+			.unwrap();
+		PaidInvoice::Bolt11(invoice)
+	}
+
+	struct Randomness;
+
+	impl EntropySource for Randomness {
+		fn get_secure_random_bytes(&self) -> [u8; 32] { [42; 32] }
+	}
+
+	fn bolt12_invoice(payment_preimage: &PaymentPreimage) -> PaidInvoice {
+		let secp_ctx = Secp256k1::new();
+		let payee_keys = KeyPair::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[41; 32]).unwrap());
+		let payee_id = PublicKey::from_secret_key(&secp_ctx, &payee_keys.secret_key());
+		let payment_path = BlindedPath::new_for_message(&[payer_id(), payee_id], &Randomness, &secp_ctx).unwrap();
+		let payinfo = BlindedPayInfo {
+			fee_base_msat: 1,
+			fee_proportional_millionths: 1_000,
+			cltv_expiry_delta: 42,
+			htlc_minimum_msat: 100,
+			htlc_maximum_msat: 1_000_000_000_000,
+			features: BlindedHopFeatures::empty(),
+		};
+		let payment_hash = PaymentHash(sha256::Hash::hash(&payment_preimage.0).into_inner());
+		let invoice = RefundBuilder::new("Receipt test".into(), vec![1; 32], payer_id(), 100_000).unwrap()
+			.build().unwrap()
+			.respond_with_no_std(vec![(payinfo, payment_path)], payment_hash, payee_id, Duration::from_secs(1_700_000_000))
+			.unwrap()
+			.build().unwrap()
+			.sign(|message| Ok::<_, Infallible>(secp_ctx.sign_schnorr_no_aux_rand(message, &payee_keys)))
+			.unwrap();
+		PaidInvoice::Bolt12(invoice)
+	}
+
+	fn path(amount_msat: u64, fee_msat: u64) -> Path {
+		let hop = |byte: u8, fee_msat: u64| RouteHop {
+			pubkey: PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap()),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: byte as u64,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat,
+			cltv_expiry_delta: 40,
+		};
+		Path { hops: vec![hop(1, fee_msat), hop(41, amount_msat)], blinded_tail: None }
+	}
+
+	#[test]
+	fn builds_and_verifies_receipt() {
+		let secp_ctx = Secp256k1::new();
+		let payment_preimage = PaymentPreimage([1; 32]);
+
+		let receipt = PaymentReceiptBuilder::new(invoice(&payment_preimage), payment_preimage).unwrap()
+			.fee_paid_msat(30)
+			.path(&path(60_000, 10))
+			.path(&path(40_000, 20))
+			.build_signed(payer_id(), payer_sign, &secp_ctx).unwrap();
+		assert_eq!(receipt.payer_id(), payer_id());
+		assert_eq!(receipt.paths()[0], PathFeeSummary { amount_msat: 60_000, fee_msat: 10, hop_count: 2 });
+		assert_eq!(receipt.verify(Some(payer_id()), &secp_ctx), Ok(()));
+
+		// BOLT 11 invoices don't commit to their payer, so the verifier has to know who it is.
+		assert_eq!(receipt.verify(None, &secp_ctx), Err(PaymentReceiptError::UnknownPayer));
+		let other_payer_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[44; 32]).unwrap());
+		assert_eq!(receipt.verify(Some(other_payer_id), &secp_ctx), Err(PaymentReceiptError::PayerMismatch));
+
+		// The receipt survives a round trip through its serialization.
+		let encoded = receipt.encode();
+		let decoded: PaymentReceipt = Readable::read(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded.encode(), encoded);
+		assert_eq!(decoded.invoice().payment_hash(), receipt.invoice().payment_hash());
+		assert_eq!(decoded.verify(Some(payer_id()), &secp_ctx), Ok(()));
+
+		// Tampering with the signed contents invalidates the signature.
+		let mut tampered = receipt.clone();
+		tampered.fee_paid_msat = None;
+		assert_eq!(tampered.verify(Some(payer_id()), &secp_ctx), Err(PaymentReceiptError::InvalidSignature));
+	}
+
+	#[test]
+	fn binds_bolt12_receipts_to_payer() {
+		let secp_ctx = Secp256k1::new();
+		let payment_preimage = PaymentPreimage([1; 32]);
+		let invoice = bolt12_invoice(&payment_preimage);
+		assert_eq!(invoice.payer_id(), Some(payer_id()));
+
+		let receipt = PaymentReceiptBuilder::new(invoice.clone(), payment_preimage).unwrap()
+			.build_signed(payer_id(), payer_sign, &secp_ctx).unwrap();
+		assert_eq!(receipt.verify(None, &secp_ctx), Ok(()));
+		assert_eq!(receipt.verify(Some(payer_id()), &secp_ctx), Ok(()));
+
+		// A routing node which learned the preimage can't sign a receipt for the invoice.
+		let hop_key = SecretKey::from_slice(&[1; 32]).unwrap();
+		let hop_id = PublicKey::from_secret_key(&secp_ctx, &hop_key);
+		assert!(matches!(
+			PaymentReceiptBuilder::new(invoice, payment_preimage).unwrap()
+				.build_signed(hop_id, |message| Ok::<_, Infallible>(secp_ctx.sign_ecdsa(message, &hop_key)), &secp_ctx),
+			Err(PaymentReceiptError::PayerMismatch)
+		));
+		let mut forged = receipt.clone();
+		forged.payer_id = hop_id;
+		assert_eq!(forged.verify(Some(hop_id), &secp_ctx), Err(PaymentReceiptError::PayerMismatch));
+	}
+
+	#[test]
+	fn rejects_invalid_receipts() {
+		let secp_ctx = Secp256k1::new();
+		let payment_preimage = PaymentPreimage([1; 32]);
+
+		assert!(matches!(
+			PaymentReceiptBuilder::new(invoice(&payment_preimage), PaymentPreimage([2; 32])),
+			Err(PaymentReceiptError::InvalidPreimage)
+		));
+
+		// The signing function must sign for the given payer id.
+		let other_key = SecretKey::from_slice(&[44; 32]).unwrap();
+		assert!(matches!(
+			PaymentReceiptBuilder::new(invoice(&payment_preimage), payment_preimage).unwrap()
+				.build_signed(payer_id(), |message| Ok::<_, Infallible>(secp_ctx.sign_ecdsa(message, &other_key)), &secp_ctx),
+			Err(PaymentReceiptError::InvalidSignature)
+		));
+		assert!(matches!(
+			PaymentReceiptBuilder::new(invoice(&payment_preimage), payment_preimage).unwrap()
+				.build_signed(payer_id(), |_| Err(()), &secp_ctx),
+			Err(PaymentReceiptError::SigningFailed)
+		));
+
+		let receipt = PaymentReceiptBuilder::new(invoice(&payment_preimage), payment_preimage).unwrap()
+			.fee_paid_msat(25)
+			.path(&path(100_000, 20))
+			.build_signed(payer_id(), payer_sign, &secp_ctx).unwrap();
+		assert_eq!(receipt.verify(Some(payer_id()), &secp_ctx), Err(PaymentReceiptError::InconsistentFees));
+
+		let receipt = PaymentReceiptBuilder::new(invoice(&payment_preimage), payment_preimage).unwrap()
+			.path(&path(60_000, 10))
+			.build_signed(payer_id(), payer_sign, &secp_ctx).unwrap();
+		assert_eq!(receipt.verify(Some(payer_id()), &secp_ctx), Err(PaymentReceiptError::InsufficientAmount));
+
+		// Untrusted path summaries which overflow when added up are rejected.
+		let mut overflowing = receipt.clone();
+		overflowing.paths = vec![
+			PathFeeSummary { amount_msat: u64::max_value(), fee_msat: 0, hop_count: 2 },
+			PathFeeSummary { amount_msat: 1, fee_msat: 0, hop_count: 2 },
+		];
+		assert_eq!(overflowing.verify(Some(payer_id()), &secp_ctx), Err(PaymentReceiptError::AmountOverflow));
+	}
+}
@@ -1 +0,0 @@
-../../lightning/src/util/time.rs
\ No newline at end of file
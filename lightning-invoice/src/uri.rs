@@ -0,0 +1,502 @@
+//! Parsing and serialization of [BIP 21] payment URIs which may carry a BOLT 11 invoice and/or a
+//! BOLT 12 offer alongside an on-chain fallback address.
+//!
+//! This allows applications to accept a single payment string — whether a plain invoice, a plain
+//! offer, or a unified `bitcoin:` URI as rendered in QR codes — and extract every supported way of
+//! paying it.
+//!
+//! [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+
+use bitcoin::Address;
+use lightning::offers::offer::Offer;
+use lightning::offers::parse::Bolt12ParseError;
+
+use crate::{Bolt11Invoice, ParseOrSemanticError};
+use crate::prelude::*;
+
+use core::fmt::{self, Display, Formatter, Write};
+use core::str::FromStr;
+
+/// The URI scheme used by [BIP 21] payment URIs.
+///
+/// [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+pub const BITCOIN_URI_SCHEME: &str = "bitcoin";
+
+/// The URI scheme commonly used to wrap a bare BOLT 11 invoice or BOLT 12 offer.
+pub const LIGHTNING_URI_SCHEME: &str = "lightning";
+
+/// The query parameter carrying a BOLT 11 invoice in a unified payment URI.
+const LIGHTNING_PARAM: &str = "lightning";
+
+/// The query parameter carrying a BOLT 12 offer in a unified payment URI.
+const OFFER_PARAM: &str = "lno";
+
+const SATS_PER_BTC: u64 = 100_000_000;
+
+/// A payment request which may be paid over lightning, via a BOLT 11 invoice or a BOLT 12 offer,
+/// or on-chain.
+///
+/// Use [`PaymentUri::from_str`] to parse a [BIP 21] URI, or a bare invoice or offer, and the
+/// [`Display`] implementation to serialize it back into a `bitcoin:` URI.
+///
+/// [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+/// [`PaymentUri::from_str`]: crate::uri::PaymentUri#impl-FromStr
+#[derive(Clone, Debug)]
+pub struct PaymentUri {
+	/// The on-chain address which may be paid, if any.
+	pub address: Option<Address>,
+	/// The amount requested, in satoshis, as given by the `amount` parameter.
+	pub amount_sats: Option<u64>,
+	/// A label for the recipient, as given by the `label` parameter.
+	pub label: Option<String>,
+	/// A message describing the payment, as given by the `message` parameter.
+	pub message: Option<String>,
+	/// The BOLT 11 invoice which may be paid instead of the on-chain address, if any.
+	pub invoice: Option<Bolt11Invoice>,
+	/// The BOLT 12 offer which may be paid instead of the on-chain address, if any.
+	pub offer: Option<Offer>,
+}
+
+impl PaymentUri {
+	/// Creates a [`PaymentUri`] paying only the given on-chain address.
+	pub fn from_address(address: Address) -> Self {
+		Self {
+			address: Some(address), amount_sats: None, label: None, message: None, invoice: None,
+			offer: None,
+		}
+	}
+
+	/// Creates a [`PaymentUri`] paying only the given BOLT 11 invoice.
+	pub fn from_invoice(invoice: Bolt11Invoice) -> Self {
+		Self {
+			address: None, amount_sats: None, label: None, message: None, invoice: Some(invoice),
+			offer: None,
+		}
+	}
+
+	/// Creates a [`PaymentUri`] paying only the given BOLT 12 offer.
+	pub fn from_offer(offer: Offer) -> Self {
+		Self {
+			address: None, amount_sats: None, label: None, message: None, invoice: None,
+			offer: Some(offer),
+		}
+	}
+
+	/// Returns the requested amount in millisatoshis, preferring the amount from the `amount`
+	/// parameter and falling back to the amount in the BOLT 11 invoice.
+	///
+	/// Returns `None` if the `amount` parameter is too large to be expressed in millisatoshis.
+	pub fn amount_msats(&self) -> Option<u64> {
+		match self.amount_sats {
+			Some(sats) => sats.checked_mul(1000),
+			None => self.invoice.as_ref().and_then(|invoice| invoice.amount_milli_satoshis()),
+		}
+	}
+
+	/// Returns whether the payment may be made over lightning.
+	pub fn supports_lightning(&self) -> bool {
+		self.invoice.is_some() || self.offer.is_some()
+	}
+
+	fn parse_bip21(uri: &str) -> Result<Self, UriParseError> {
+		let (address, query) = match uri.find('?') {
+			Some(idx) => (&uri[..idx], Some(&uri[idx + 1..])),
+			None => (uri, None),
+		};
+
+		let mut payment_uri = PaymentUri {
+			address: None, amount_sats: None, label: None, message: None, invoice: None,
+			offer: None,
+		};
+		if !address.is_empty() {
+			payment_uri.address = Some(Address::from_str(address).map_err(UriParseError::InvalidAddress)?);
+		}
+
+		for param in query.into_iter().flat_map(|query| query.split('&')) {
+			if param.is_empty() { continue; }
+			let (key, value) = match param.find('=') {
+				Some(idx) => (&param[..idx], &param[idx + 1..]),
+				None => (param, ""),
+			};
+			let key = key.to_ascii_lowercase();
+			match key.as_str() {
+				"amount" => {
+					if payment_uri.amount_sats.is_some() {
+						return Err(UriParseError::DuplicateParameter(key));
+					}
+					payment_uri.amount_sats = Some(parse_btc_amount(value)?);
+				},
+				"label" => {
+					if payment_uri.label.is_some() {
+						return Err(UriParseError::DuplicateParameter(key));
+					}
+					payment_uri.label = Some(percent_decode(value)?);
+				},
+				"message" => {
+					if payment_uri.message.is_some() {
+						return Err(UriParseError::DuplicateParameter(key));
+					}
+					payment_uri.message = Some(percent_decode(value)?);
+				},
+				LIGHTNING_PARAM => {
+					if payment_uri.invoice.is_some() {
+						return Err(UriParseError::DuplicateParameter(key));
+					}
+					payment_uri.invoice = Some(parse_invoice(&percent_decode(value)?)?);
+				},
+				OFFER_PARAM => {
+					if payment_uri.offer.is_some() {
+						return Err(UriParseError::DuplicateParameter(key));
+					}
+					payment_uri.offer = Some(parse_offer(&percent_decode(value)?)?);
+				},
+				_ if key.starts_with("req-") => return Err(UriParseError::UnknownRequiredParameter(key)),
+				_ => {},
+			}
+		}
+
+		if payment_uri.address.is_none() && !payment_uri.supports_lightning() {
+			return Err(UriParseError::NoPaymentMethod);
+		}
+
+		Ok(payment_uri)
+	}
+}
+
+impl FromStr for PaymentUri {
+	type Err = UriParseError;
+
+	/// Parses a [BIP 21] URI, a `lightning:` URI, or a bare BOLT 11 invoice or BOLT 12 offer.
+	///
+	/// [BIP 21]: https://github.com/bitcoin/bips/blob/master/bip-0021.mediawiki
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim();
+		if let Some(uri) = strip_scheme(s, BITCOIN_URI_SCHEME) {
+			return Self::parse_bip21(uri);
+		}
+
+		let payment_string = strip_scheme(s, LIGHTNING_URI_SCHEME).unwrap_or(s);
+		if payment_string.get(..3).map_or(false, |hrp| hrp.eq_ignore_ascii_case(OFFER_PARAM)) {
+			parse_offer(payment_string).map(PaymentUri::from_offer)
+		} else {
+			parse_invoice(payment_string).map(PaymentUri::from_invoice)
+		}
+	}
+}
+
+impl Display for PaymentUri {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		write!(f, "{}:", BITCOIN_URI_SCHEME)?;
+		if let Some(address) = &self.address {
+			write!(f, "{}", address)?;
+		}
+
+		let mut separator = '?';
+		if let Some(amount_sats) = self.amount_sats {
+			write_param(f, &mut separator, "amount")?;
+			write_btc_amount(f, amount_sats)?;
+		}
+		if let Some(label) = &self.label {
+			write_param(f, &mut separator, "label")?;
+			percent_encode(f, label)?;
+		}
+		if let Some(message) = &self.message {
+			write_param(f, &mut separator, "message")?;
+			percent_encode(f, message)?;
+		}
+		if let Some(invoice) = &self.invoice {
+			write_param(f, &mut separator, LIGHTNING_PARAM)?;
+			write!(f, "{}", invoice)?;
+		}
+		if let Some(offer) = &self.offer {
+			write_param(f, &mut separator, OFFER_PARAM)?;
+			write!(f, "{}", offer)?;
+		}
+		Ok(())
+	}
+}
+
+fn write_param(f: &mut Formatter, separator: &mut char, key: &str) -> fmt::Result {
+	write!(f, "{}{}=", separator, key)?;
+	*separator = '&';
+	Ok(())
+}
+
+/// Errors that may occur when parsing a [`PaymentUri`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum UriParseError {
+	/// The string contained neither an on-chain address, a BOLT 11 invoice, nor a BOLT 12 offer.
+	NoPaymentMethod,
+	/// The on-chain address could not be parsed.
+	InvalidAddress(bitcoin::util::address::Error),
+	/// The `amount` parameter was not a valid BTC amount with at most eight decimal places.
+	InvalidAmount,
+	/// A parameter value contained an invalid percent-encoded sequence or was not valid UTF-8.
+	InvalidPercentEncoding,
+	/// The given parameter appeared more than once.
+	DuplicateParameter(String),
+	/// A `req-` parameter which is not understood was present, so the URI must be rejected.
+	UnknownRequiredParameter(String),
+	/// The BOLT 11 invoice could not be parsed.
+	InvalidInvoice(ParseOrSemanticError),
+	/// The BOLT 12 offer could not be parsed.
+	InvalidOffer(Bolt12ParseError),
+}
+
+impl Display for UriParseError {
+	fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+		match self {
+			UriParseError::NoPaymentMethod => f.write_str("No supported payment method was found"),
+			UriParseError::InvalidAddress(e) => write!(f, "Invalid on-chain address: {}", e),
+			UriParseError::InvalidAmount => f.write_str("Invalid amount"),
+			UriParseError::InvalidPercentEncoding => f.write_str("Invalid percent-encoding"),
+			UriParseError::DuplicateParameter(key) => write!(f, "Duplicate parameter: {}", key),
+			UriParseError::UnknownRequiredParameter(key) =>
+				write!(f, "Unknown required parameter: {}", key),
+			UriParseError::InvalidInvoice(e) => write!(f, "Invalid BOLT 11 invoice: {}", e),
+			UriParseError::InvalidOffer(e) => write!(f, "Invalid BOLT 12 offer: {:?}", e),
+		}
+	}
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UriParseError { }
+
+/// Returns the remainder of `s` after a case-insensitive `scheme:` prefix, if present.
+fn strip_scheme<'a>(s: &'a str, scheme: &str) -> Option<&'a str> {
+	let prefix_len = scheme.len() + 1;
+	if s.len() < prefix_len || !s.is_char_boundary(prefix_len) { return None; }
+	let (prefix, rest) = s.split_at(prefix_len);
+	if prefix[..scheme.len()].eq_ignore_ascii_case(scheme) && prefix.ends_with(':') {
+		Some(rest)
+	} else {
+		None
+	}
+}
+
+fn parse_invoice(s: &str) -> Result<Bolt11Invoice, UriParseError> {
+	Bolt11Invoice::from_str(s).map_err(UriParseError::InvalidInvoice)
+}
+
+fn parse_offer(s: &str) -> Result<Offer, UriParseError> {
+	// Offers are bech32-style strings and thus case-insensitive, but are often upper-cased in QR
+	// codes to make use of the alphanumeric encoding mode.
+	Offer::from_str(&s.to_ascii_lowercase()).map_err(UriParseError::InvalidOffer)
+}
+
+/// Parses a decimal BTC amount with at most eight decimal places into satoshis.
+fn parse_btc_amount(s: &str) -> Result<u64, UriParseError> {
+	let (whole, fraction) = match s.find('.') {
+		Some(idx) => (&s[..idx], &s[idx + 1..]),
+		None => (s, ""),
+	};
+	if whole.is_empty() && fraction.is_empty() { return Err(UriParseError::InvalidAmount); }
+	if fraction.len() > 8 { return Err(UriParseError::InvalidAmount); }
+	if !whole.bytes().chain(fraction.bytes()).all(|b| b.is_ascii_digit()) {
+		return Err(UriParseError::InvalidAmount);
+	}
+
+	let whole_sats = if whole.is_empty() { 0 } else {
+		whole.parse::<u64>().ok()
+			.and_then(|btc| btc.checked_mul(SATS_PER_BTC))
+			.ok_or(UriParseError::InvalidAmount)?
+	};
+	let fraction_sats = if fraction.is_empty() { 0 } else {
+		let digits = fraction.parse::<u64>().map_err(|_| UriParseError::InvalidAmount)?;
+		digits * 10u64.pow(8 - fraction.len() as u32)
+	};
+	let amount_sats = whole_sats.checked_add(fraction_sats).ok_or(UriParseError::InvalidAmount)?;
+	// Make sure the amount can also be expressed in millisatoshis, see `amount_msats`.
+	amount_sats.checked_mul(1000).ok_or(UriParseError::InvalidAmount)?;
+	Ok(amount_sats)
+}
+
+/// Writes an amount in satoshis as a decimal BTC amount without trailing zeros.
+fn write_btc_amount(f: &mut Formatter, amount_sats: u64) -> fmt::Result {
+	let whole = amount_sats / SATS_PER_BTC;
+	let fraction = amount_sats % SATS_PER_BTC;
+	write!(f, "{}", whole)?;
+	if fraction != 0 {
+		let mut digits = fraction;
+		let mut width = 8;
+		while digits % 10 == 0 {
+			digits /= 10;
+			width -= 1;
+		}
+		write!(f, ".{:0width$}", digits, width = width)?;
+	}
+	Ok(())
+}
+
+fn percent_decode(s: &str) -> Result<String, UriParseError> {
+	let bytes = s.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut i = 0;
+	while i < bytes.len() {
+		if bytes[i] == b'%' {
+			let hex = bytes.get(i + 1..i + 3).ok_or(UriParseError::InvalidPercentEncoding)?;
+			let hex = core::str::from_utf8(hex).map_err(|_| UriParseError::InvalidPercentEncoding)?;
+			let byte = u8::from_str_radix(hex, 16).map_err(|_| UriParseError::InvalidPercentEncoding)?;
+			decoded.push(byte);
+			i += 3;
+		} else {
+			decoded.push(bytes[i]);
+			i += 1;
+		}
+	}
+	String::from_utf8(decoded).map_err(|_| UriParseError::InvalidPercentEncoding)
+}
+
+fn percent_encode(f: &mut Formatter, s: &str) -> fmt::Result {
+	for byte in s.bytes() {
+		match byte {
+			b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => f.write_char(byte as char)?,
+			_ => write!(f, "%{:02X}", byte)?,
+		}
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PaymentUri, UriParseError};
+	use crate::Bolt11Invoice;
+
+	use bitcoin::Address;
+	use bitcoin::network::constants::Network;
+	use bitcoin::secp256k1::{KeyPair, Secp256k1, SecretKey};
+	use lightning::offers::offer::{Offer, OfferBuilder};
+
+	use core::str::FromStr;
+
+	const INVOICE: &str = "lnbc1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdpl2pkx2ctnv5sxxmmwwd5kgetjypeh2ursdae8g6twvus8g6rfwvs8qun0dfjkxaq9qrsgq357wnc5r2ueh7ck6q93dj32dlqnls087fxdwk8qakdyafkq3yap9us6v52vjjsrvywa6rt52cm9r9zqt8r2t7mlcwspyetp5h2tztugp9lfyql";
+	const ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+
+	fn offer() -> Offer {
+		let secp_ctx = Secp256k1::new();
+		let keys = KeyPair::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		OfferBuilder::new("foo".into(), keys.public_key()).build().unwrap()
+	}
+
+	#[test]
+	fn parses_bare_invoice_and_offer() {
+		let uri = PaymentUri::from_str(INVOICE).unwrap();
+		assert_eq!(uri.invoice, Some(Bolt11Invoice::from_str(INVOICE).unwrap()));
+		assert!(uri.address.is_none());
+		assert!(uri.offer.is_none());
+
+		let uri = PaymentUri::from_str(&format!("LIGHTNING:{}", INVOICE.to_uppercase())).unwrap();
+		assert!(uri.invoice.is_some());
+
+		let offer = offer().to_string();
+		let uri = PaymentUri::from_str(&offer).unwrap();
+		assert_eq!(uri.offer.unwrap().to_string(), offer);
+		let uri = PaymentUri::from_str(&format!("lightning:{}", offer.to_uppercase())).unwrap();
+		assert_eq!(uri.offer.unwrap().to_string(), offer);
+	}
+
+	#[test]
+	fn parses_unified_uri() {
+		let offer = offer().to_string();
+		let uri_str = format!(
+			"BITCOIN:{}?amount=0.0025&label=Luke%20Jr&message=Donation%20for%20project%20xyz&lightning={}&lno={}",
+			ADDRESS, INVOICE, offer
+		);
+		let uri = PaymentUri::from_str(&uri_str).unwrap();
+		assert_eq!(uri.address, Some(Address::from_str(ADDRESS).unwrap()));
+		assert_eq!(uri.address.as_ref().unwrap().network, Network::Bitcoin);
+		assert_eq!(uri.amount_sats, Some(250_000));
+		assert_eq!(uri.amount_msats(), Some(250_000_000));
+		assert_eq!(uri.label.as_deref(), Some("Luke Jr"));
+		assert_eq!(uri.message.as_deref(), Some("Donation for project xyz"));
+		assert_eq!(uri.invoice, Some(Bolt11Invoice::from_str(INVOICE).unwrap()));
+		assert_eq!(uri.offer.as_ref().unwrap().to_string(), offer);
+		assert!(uri.supports_lightning());
+
+		// Round-trips through the canonical serialization.
+		let serialized = uri.to_string();
+		assert_eq!(
+			serialized,
+			format!(
+				"bitcoin:{}?amount=0.0025&label=Luke%20Jr&message=Donation%20for%20project%20xyz&lightning={}&lno={}",
+				ADDRESS, INVOICE, offer
+			)
+		);
+		let reparsed = PaymentUri::from_str(&serialized).unwrap();
+		assert_eq!(reparsed.to_string(), serialized);
+	}
+
+	#[test]
+	fn parses_uri_without_address() {
+		let uri = PaymentUri::from_str(&format!("bitcoin:?lightning={}", INVOICE)).unwrap();
+		assert!(uri.address.is_none());
+		assert_eq!(uri.amount_sats, None);
+		assert_eq!(uri.amount_msats(), Bolt11Invoice::from_str(INVOICE).unwrap().amount_milli_satoshis());
+
+		let uri = PaymentUri::from_str(&format!("bitcoin:{}", ADDRESS)).unwrap();
+		assert!(!uri.supports_lightning());
+		assert_eq!(uri.to_string(), format!("bitcoin:{}", ADDRESS));
+	}
+
+	#[test]
+	fn handles_amounts() {
+		for (amount, sats) in [
+			("1", 100_000_000), ("20.3", 2_030_000_000), (".5", 50_000_000), ("0.00000001", 1),
+			("21000000", 2_100_000_000_000_000),
+		].iter() {
+			let uri = PaymentUri::from_str(&format!("bitcoin:{}?amount={}", ADDRESS, amount)).unwrap();
+			assert_eq!(uri.amount_sats, Some(*sats));
+		}
+
+		let mut uri = PaymentUri::from_address(Address::from_str(ADDRESS).unwrap());
+		uri.amount_sats = Some(2_030_000_000);
+		assert_eq!(uri.to_string(), format!("bitcoin:{}?amount=20.3", ADDRESS));
+		uri.amount_sats = Some(1);
+		assert_eq!(uri.to_string(), format!("bitcoin:{}?amount=0.00000001", ADDRESS));
+		uri.amount_sats = Some(u64::max_value());
+		assert_eq!(uri.amount_msats(), None);
+
+		for amount in ["", ".", "1.000000001", "-1", "1,5", "1e3", "99999999999", "99999999999999999999"].iter() {
+			assert_eq!(
+				PaymentUri::from_str(&format!("bitcoin:{}?amount={}", ADDRESS, amount)).unwrap_err(),
+				UriParseError::InvalidAmount
+			);
+		}
+	}
+
+	#[test]
+	fn rejects_invalid_uris() {
+		assert_eq!(PaymentUri::from_str("bitcoin:").unwrap_err(), UriParseError::NoPaymentMethod);
+		assert_eq!(
+			PaymentUri::from_str("bitcoin:?label=foo").unwrap_err(),
+			UriParseError::NoPaymentMethod
+		);
+		assert!(matches!(
+			PaymentUri::from_str("bitcoin:notanaddress").unwrap_err(),
+			UriParseError::InvalidAddress(_)
+		));
+		assert_eq!(
+			PaymentUri::from_str(&format!("bitcoin:{}?req-somethingnew=1", ADDRESS)).unwrap_err(),
+			UriParseError::UnknownRequiredParameter("req-somethingnew".to_string())
+		);
+		assert_eq!(
+			PaymentUri::from_str(&format!("bitcoin:{}?label=a&label=b", ADDRESS)).unwrap_err(),
+			UriParseError::DuplicateParameter("label".to_string())
+		);
+		assert_eq!(
+			PaymentUri::from_str(&format!("bitcoin:{}?label=%ZZ", ADDRESS)).unwrap_err(),
+			UriParseError::InvalidPercentEncoding
+		);
+		assert!(matches!(
+			PaymentUri::from_str(&format!("bitcoin:{}?lightning=lnbc1", ADDRESS)).unwrap_err(),
+			UriParseError::InvalidInvoice(_)
+		));
+		assert!(matches!(
+			PaymentUri::from_str(&format!("bitcoin:{}?lno=lno1", ADDRESS)).unwrap_err(),
+			UriParseError::InvalidOffer(_)
+		));
+
+		// Unknown optional parameters are ignored.
+		assert!(PaymentUri::from_str(&format!("bitcoin:{}?somethingnew=1", ADDRESS)).is_ok());
+	}
+}
@@ -1,13 +1,15 @@
 //! Convenient utilities to create an invoice.
 
-use crate::{Bolt11Invoice, CreationError, Currency, InvoiceBuilder, SignOrCreationError};
+use crate::{Bolt11Invoice, CreationError, Currency, Fallback, InvoiceBuilder, SignOrCreationError};
 
 use crate::{prelude::*, Description, Bolt11InvoiceDescription, Sha256};
 use bech32::ToBase32;
+use bitcoin::blockdata::script::Script;
+use bitcoin::util::address::Payload;
 use bitcoin_hashes::Hash;
 use lightning::chain;
 use lightning::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
-use lightning::sign::{Recipient, NodeSigner, SignerProvider, EntropySource};
+use lightning::sign::{ChangeDestinationSource, Recipient, NodeSigner, SignerProvider, EntropySource};
 use lightning::ln::{PaymentHash, PaymentSecret};
 use lightning::ln::channelmanager::{ChannelDetails, ChannelManager, MIN_FINAL_CLTV_EXPIRY_DELTA};
 use lightning::ln::channelmanager::{PhantomRouteHints, MIN_CLTV_EXPIRY_DELTA};
@@ -418,7 +420,7 @@ pub fn create_invoice_from_channelmanager_with_description_hash_and_duration_sin
 	_create_invoice_from_channelmanager_and_duration_since_epoch(
 		channelmanager, node_signer, logger, network, amt_msat,
 		Bolt11InvoiceDescription::Hash(&description_hash),
-		duration_since_epoch, invoice_expiry_delta_secs, min_final_cltv_expiry_delta,
+		duration_since_epoch, invoice_expiry_delta_secs, min_final_cltv_expiry_delta, false, None,
 	)
 }
 
@@ -445,7 +447,44 @@ pub fn create_invoice_from_channelmanager_and_duration_since_epoch<M: Deref, T:
 		Bolt11InvoiceDescription::Direct(
 			&Description::new(description).map_err(SignOrCreationError::CreationError)?,
 		),
-		duration_since_epoch, invoice_expiry_delta_secs, min_final_cltv_expiry_delta,
+		duration_since_epoch, invoice_expiry_delta_secs, min_final_cltv_expiry_delta, false, None,
+	)
+}
+
+/// Utility to construct an invoice for a node which receives payments via channels with multiple
+/// Lightning Service Providers (LSPs), i.e., whose channels are all unannounced. Otherwise
+/// identical to [`create_invoice_from_channelmanager_and_duration_since_epoch`].
+///
+/// Rather than including route hints for up to three of our counterparties, only as many LSPs are
+/// included as are needed to receive the payment, avoiding revealing all our LSP relationships in
+/// every invoice. Which LSPs are included is rotated across invoices. The expected inbound amount
+/// is split across the included hints proportionally to the inbound capacity we have with each
+/// LSP by limiting each hint's `htlc_maximum_msat` to its share, nudging payers into splitting
+/// the payment accordingly.
+///
+/// If we have any public channels, route hints are selected as in
+/// [`create_invoice_from_channelmanager_and_duration_since_epoch`].
+pub fn create_multi_lsp_invoice_from_channelmanager_and_duration_since_epoch<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref>(
+	channelmanager: &ChannelManager<M, T, ES, NS, SP, F, R, L>, node_signer: NS, logger: L,
+	network: Currency, amt_msat: Option<u64>, description: String, duration_since_epoch: Duration,
+	invoice_expiry_delta_secs: u32, min_final_cltv_expiry_delta: Option<u16>,
+) -> Result<Bolt11Invoice, SignOrCreationError<()>>
+		where
+			M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
+			T::Target: BroadcasterInterface,
+			ES::Target: EntropySource,
+			NS::Target: NodeSigner,
+			SP::Target: SignerProvider,
+			F::Target: FeeEstimator,
+			R::Target: Router,
+			L::Target: Logger,
+{
+	_create_invoice_from_channelmanager_and_duration_since_epoch(
+		channelmanager, node_signer, logger, network, amt_msat,
+		Bolt11InvoiceDescription::Direct(
+			&Description::new(description).map_err(SignOrCreationError::CreationError)?,
+		),
+		duration_since_epoch, invoice_expiry_delta_secs, min_final_cltv_expiry_delta, true, None,
 	)
 }
 
@@ -453,6 +492,7 @@ fn _create_invoice_from_channelmanager_and_duration_since_epoch<M: Deref, T: Der
 	channelmanager: &ChannelManager<M, T, ES, NS, SP, F, R, L>, node_signer: NS, logger: L,
 	network: Currency, amt_msat: Option<u64>, description: Bolt11InvoiceDescription,
 	duration_since_epoch: Duration, invoice_expiry_delta_secs: u32, min_final_cltv_expiry_delta: Option<u16>,
+	multi_lsp_hints: bool, fallback: Option<Fallback>,
 ) -> Result<Bolt11Invoice, SignOrCreationError<()>>
 		where
 			M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
@@ -475,7 +515,90 @@ fn _create_invoice_from_channelmanager_and_duration_since_epoch<M: Deref, T: Der
 		.map_err(|()| SignOrCreationError::CreationError(CreationError::InvalidAmount))?;
 	_create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_hash(
 		channelmanager, node_signer, logger, network, amt_msat, description, duration_since_epoch,
-		invoice_expiry_delta_secs, payment_hash, payment_secret, min_final_cltv_expiry_delta)
+		invoice_expiry_delta_secs, payment_hash, payment_secret, min_final_cltv_expiry_delta,
+		multi_lsp_hints, fallback)
+}
+
+#[cfg(feature = "std")]
+/// Utility to construct an invoice which may also be paid on-chain, using a fresh fallback script
+/// from `change_destination_source`. Otherwise identical to [`create_invoice_from_channelmanager`].
+///
+/// The fallback script is registered with the `ChannelManager` via
+/// [`ChannelManager::watch_onchain_fallback`], generating an
+/// [`Event::OnchainFallbackPaymentReceived`] once the invoice is paid on-chain. If the invoice is
+/// instead paid over lightning, the script should be unwatched again via
+/// [`ChannelManager::unwatch_onchain_fallback`].
+///
+/// [`Event::OnchainFallbackPaymentReceived`]: lightning::events::Event::OnchainFallbackPaymentReceived
+pub fn create_invoice_from_channelmanager_with_onchain_fallback<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref, D: Deref>(
+	channelmanager: &ChannelManager<M, T, ES, NS, SP, F, R, L>, node_signer: NS, logger: L,
+	network: Currency, amt_msat: Option<u64>, description: String, invoice_expiry_delta_secs: u32,
+	min_final_cltv_expiry_delta: Option<u16>, change_destination_source: D,
+) -> Result<Bolt11Invoice, SignOrCreationError<()>>
+where
+	M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
+	T::Target: BroadcasterInterface,
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+	SP::Target: SignerProvider,
+	F::Target: FeeEstimator,
+	R::Target: Router,
+	L::Target: Logger,
+	D::Target: ChangeDestinationSource,
+{
+	use std::time::SystemTime;
+	let duration = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+		.expect("for the foreseeable future this shouldn't happen");
+	create_invoice_from_channelmanager_with_onchain_fallback_and_duration_since_epoch(
+		channelmanager, node_signer, logger, network, amt_msat, description, duration,
+		invoice_expiry_delta_secs, min_final_cltv_expiry_delta, change_destination_source,
+	)
+}
+
+/// See [`create_invoice_from_channelmanager_with_onchain_fallback`]
+/// This version can be used in a `no_std` environment, where [`std::time::SystemTime`] is not
+/// available and the current time is supplied by the caller.
+pub fn create_invoice_from_channelmanager_with_onchain_fallback_and_duration_since_epoch<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref, D: Deref>(
+	channelmanager: &ChannelManager<M, T, ES, NS, SP, F, R, L>, node_signer: NS, logger: L,
+	network: Currency, amt_msat: Option<u64>, description: String, duration_since_epoch: Duration,
+	invoice_expiry_delta_secs: u32, min_final_cltv_expiry_delta: Option<u16>,
+	change_destination_source: D,
+) -> Result<Bolt11Invoice, SignOrCreationError<()>>
+		where
+			M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
+			T::Target: BroadcasterInterface,
+			ES::Target: EntropySource,
+			NS::Target: NodeSigner,
+			SP::Target: SignerProvider,
+			F::Target: FeeEstimator,
+			R::Target: Router,
+			L::Target: Logger,
+			D::Target: ChangeDestinationSource,
+{
+	let fallback_script = change_destination_source.get_change_destination_script()
+		.map_err(|()| SignOrCreationError::CreationError(CreationError::InvalidFallback))?;
+	let fallback = script_to_fallback(&fallback_script)
+		.ok_or(SignOrCreationError::CreationError(CreationError::InvalidFallback))?;
+	let invoice = _create_invoice_from_channelmanager_and_duration_since_epoch(
+		channelmanager, node_signer, logger, network, amt_msat,
+		Bolt11InvoiceDescription::Direct(
+			&Description::new(description).map_err(SignOrCreationError::CreationError)?,
+		),
+		duration_since_epoch, invoice_expiry_delta_secs, min_final_cltv_expiry_delta, false,
+		Some(fallback),
+	)?;
+	let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+	channelmanager.watch_onchain_fallback(fallback_script, payment_hash, amt_msat);
+	Ok(invoice)
+}
+
+/// Converts a script to the [`Fallback`] paying to it, if it is a standard output script.
+fn script_to_fallback(script: &Script) -> Option<Fallback> {
+	match Payload::from_script(script)? {
+		Payload::PubkeyHash(pubkey_hash) => Some(Fallback::PubKeyHash(pubkey_hash)),
+		Payload::ScriptHash(script_hash) => Some(Fallback::ScriptHash(script_hash)),
+		Payload::WitnessProgram { version, program } => Some(Fallback::SegWitProgram { version, program }),
+	}
 }
 
 /// See [`create_invoice_from_channelmanager_and_duration_since_epoch`]
@@ -507,7 +630,7 @@ pub fn create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_
 			&Description::new(description).map_err(SignOrCreationError::CreationError)?,
 		),
 		duration_since_epoch, invoice_expiry_delta_secs, payment_hash, payment_secret,
-		min_final_cltv_expiry_delta,
+		min_final_cltv_expiry_delta, false, None,
 	)
 }
 
@@ -515,7 +638,8 @@ fn _create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_has
 	channelmanager: &ChannelManager<M, T, ES, NS, SP, F, R, L>, node_signer: NS, logger: L,
 	network: Currency, amt_msat: Option<u64>, description: Bolt11InvoiceDescription,
 	duration_since_epoch: Duration, invoice_expiry_delta_secs: u32, payment_hash: PaymentHash,
-	payment_secret: PaymentSecret, min_final_cltv_expiry_delta: Option<u16>,
+	payment_secret: PaymentSecret, min_final_cltv_expiry_delta: Option<u16>, multi_lsp_hints: bool,
+	fallback: Option<Fallback>,
 ) -> Result<Bolt11Invoice, SignOrCreationError<()>>
 	where
 		M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
@@ -556,8 +680,15 @@ fn _create_invoice_from_channelmanager_and_duration_since_epoch_with_payment_has
 	if let Some(amt) = amt_msat {
 		invoice = invoice.amount_milli_satoshis(amt);
 	}
+	if let Some(fallback) = fallback {
+		invoice = invoice.fallback(fallback);
+	}
 
-	let route_hints = sort_and_filter_channels(channels, amt_msat, &logger);
+	let route_hints = if multi_lsp_hints && !channels.iter().any(|channel| channel.is_public) {
+		select_multi_lsp_hints(channels, amt_msat, &payment_hash, &logger)
+	} else {
+		sort_and_filter_channels(channels, amt_msat, &logger).collect()
+	};
 	for hint in route_hints {
 		invoice = invoice.private_route(hint);
 	}
@@ -755,6 +886,100 @@ where
 		eligible_channels.into_iter().take(MAX_CHANNEL_HINTS).map(route_hint_from_channel)
 }
 
+/// Selects the route hints for an invoice paid via one or more of our LSPs, see
+/// [`create_multi_lsp_invoice_from_channelmanager_and_duration_since_epoch`].
+///
+/// All our channels with each LSP are considered together, as the LSP may forward over any of
+/// them, with the hint for an LSP pointing to the channel with the most inbound capacity. Starting
+/// at an offset derived from the `payment_hash`, LSPs are included (up to a total of 3) until their
+/// combined inbound capacity covers `min_inbound_capacity_msat` plus a 10% margin. If no amount is
+/// given, 3 LSPs are included.
+fn select_multi_lsp_hints<L: Deref>(
+	channels: Vec<ChannelDetails>, min_inbound_capacity_msat: Option<u64>, payment_hash: &PaymentHash,
+	logger: &L,
+) -> Vec<RouteHint>
+where
+	L::Target: Logger,
+{
+	// For each LSP, its channel with the most inbound capacity and our total inbound capacity
+	// with it.
+	let mut lsps: HashMap<PublicKey, (ChannelDetails, u64)> = HashMap::new();
+	let online_channel_exists = channels.iter().any(|channel| channel.is_usable);
+	for channel in channels.into_iter().filter(|chan| chan.is_channel_ready) {
+		if channel.get_inbound_payment_scid().is_none() || channel.counterparty.forwarding_info.is_none() {
+			log_trace!(logger, "Ignoring channel {} for invoice route hints", log_bytes!(channel.channel_id));
+			continue;
+		}
+		if online_channel_exists && !channel.is_usable {
+			log_trace!(logger, "Ignoring channel {} with disconnected peer", log_bytes!(channel.channel_id));
+			continue;
+		}
+		match lsps.entry(channel.counterparty.node_id) {
+			hash_map::Entry::Occupied(mut entry) => {
+				let (best_channel, total_inbound_msat) = entry.get_mut();
+				*total_inbound_msat += channel.inbound_capacity_msat;
+				if channel.inbound_capacity_msat > best_channel.inbound_capacity_msat {
+					*best_channel = channel;
+				}
+			},
+			hash_map::Entry::Vacant(entry) => {
+				let inbound_capacity_msat = channel.inbound_capacity_msat;
+				entry.insert((channel, inbound_capacity_msat));
+			},
+		}
+	}
+
+	let mut lsps = lsps.into_iter().map(|(_, lsp)| lsp)
+		.filter(|(_, total_inbound_msat)| *total_inbound_msat > 0)
+		.collect::<Vec<_>>();
+	if lsps.is_empty() {
+		return Vec::new();
+	}
+	lsps.sort_unstable_by_key(|(channel, _)| channel.counterparty.node_id);
+	let mut offset_bytes = [0; 8];
+	offset_bytes.copy_from_slice(&payment_hash.0[..8]);
+	let offset = (u64::from_be_bytes(offset_bytes) % lsps.len() as u64) as usize;
+	lsps.rotate_left(offset);
+
+	let target_inbound_msat = min_inbound_capacity_msat.map(|amt_msat| amt_msat.saturating_mul(110) / 100);
+	let mut selected_inbound_msat = 0u64;
+	let mut selected = Vec::new();
+	for (channel, total_inbound_msat) in lsps.into_iter().take(MAX_CHANNEL_HINTS) {
+		if let Some(target_inbound_msat) = target_inbound_msat {
+			if !selected.is_empty() && selected_inbound_msat >= target_inbound_msat { break; }
+		}
+		log_trace!(logger, "Including LSP {} with {} msat inbound capacity in invoice route hints",
+			log_pubkey!(channel.counterparty.node_id), total_inbound_msat);
+		selected_inbound_msat = selected_inbound_msat.saturating_add(total_inbound_msat);
+		selected.push((channel, total_inbound_msat));
+	}
+
+	selected.into_iter().map(|(channel, total_inbound_msat)| {
+		let forwarding_info = channel.counterparty.forwarding_info.as_ref().unwrap();
+		// Each LSP is expected to receive its proportional share of the payment, which we enforce
+		// (rounding up) via the hint's maximum HTLC amount if there are several LSPs to split over.
+		let share_msat = target_inbound_msat.map(|target_inbound_msat| {
+			((target_inbound_msat as u128 * total_inbound_msat as u128 + selected_inbound_msat as u128 - 1)
+				/ selected_inbound_msat as u128) as u64
+		}).filter(|share_msat| *share_msat < target_inbound_msat.unwrap());
+		let htlc_maximum_msat = match (channel.inbound_htlc_maximum_msat, share_msat) {
+			(Some(max_msat), Some(share_msat)) => Some(core::cmp::min(max_msat, share_msat)),
+			(max_msat, share_msat) => max_msat.or(share_msat),
+		};
+		RouteHint(vec![RouteHintHop {
+			src_node_id: channel.counterparty.node_id,
+			short_channel_id: channel.get_inbound_payment_scid().unwrap(),
+			fees: RoutingFees {
+				base_msat: forwarding_info.fee_base_msat,
+				proportional_millionths: forwarding_info.fee_proportional_millionths,
+			},
+			cltv_expiry_delta: forwarding_info.cltv_expiry_delta,
+			htlc_minimum_msat: channel.inbound_htlc_minimum_msat,
+			htlc_maximum_msat,
+		}])
+	}).collect()
+}
+
 /// prefer_current_channel chooses a channel to use for route hints between a currently selected and candidate
 /// channel based on the inbound capacity of each channel and the minimum inbound capacity requested for the hints,
 /// returning true if the current channel should be preferred over the candidate channel.
@@ -797,7 +1022,9 @@ mod test {
 	use crate::{Currency, Description, Bolt11InvoiceDescription, SignOrCreationError, CreationError};
 	use bitcoin_hashes::{Hash, sha256};
 	use bitcoin_hashes::sha256::Hash as Sha256;
-	use lightning::sign::PhantomKeysManager;
+	use bitcoin::{PackedLockTime, Script, Transaction, TxOut, WPubkeyHash};
+	use lightning::chain::channelmonitor::ANTI_REORG_DELAY;
+	use lightning::sign::{ChangeDestinationSource, PhantomKeysManager};
 	use lightning::events::{MessageSendEvent, MessageSendEventsProvider, Event, EventsProvider};
 	use lightning::ln::{PaymentPreimage, PaymentHash};
 	use lightning::ln::channelmanager::{PhantomRouteHints, MIN_FINAL_CLTV_EXPIRY_DELTA, PaymentId, RecipientOnionFields, Retry};
@@ -806,7 +1033,7 @@ mod test {
 	use lightning::routing::router::{PaymentParameters, RouteParameters};
 	use lightning::util::test_utils;
 	use lightning::util::config::UserConfig;
-	use crate::utils::{create_invoice_from_channelmanager_and_duration_since_epoch, rotate_through_iterators};
+	use crate::utils::{create_invoice_from_channelmanager_and_duration_since_epoch, create_multi_lsp_invoice_from_channelmanager_and_duration_since_epoch, rotate_through_iterators};
 	use std::collections::HashSet;
 
 	#[test]
@@ -969,6 +1196,61 @@ mod test {
 		assert_eq!(invoice.payment_hash(), &sha256::Hash::from_slice(&payment_hash.0[..]).unwrap());
 	}
 
+	struct TestDestinationSource(Script);
+	impl ChangeDestinationSource for TestDestinationSource {
+		fn get_change_destination_script(&self) -> Result<Script, ()> { Ok(self.0.clone()) }
+	}
+
+	#[test]
+	fn test_create_invoice_with_onchain_fallback() {
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+		let fallback_script = Script::new_v0_p2wpkh(&WPubkeyHash::from_slice(&[42; 20]).unwrap());
+		let invoice = crate::utils::create_invoice_from_channelmanager_with_onchain_fallback_and_duration_since_epoch(
+			nodes[1].node, nodes[1].keys_manager, nodes[1].logger, Currency::BitcoinTestnet,
+			Some(10_000_000), "test".to_string(), Duration::from_secs(1234567), 3600, None,
+			&TestDestinationSource(fallback_script.clone()),
+		).unwrap();
+		assert_eq!(invoice.fallback_addresses().len(), 1);
+		assert_eq!(invoice.fallback_addresses()[0].script_pubkey(), fallback_script);
+
+		// Scripts which can't be encoded as a fallback are rejected.
+		assert_eq!(
+			crate::utils::create_invoice_from_channelmanager_with_onchain_fallback_and_duration_since_epoch(
+				nodes[1].node, nodes[1].keys_manager, nodes[1].logger, Currency::BitcoinTestnet,
+				Some(10_000_000), "test".to_string(), Duration::from_secs(1234567), 3600, None,
+				&TestDestinationSource(Script::new()),
+			),
+			Err(SignOrCreationError::CreationError(CreationError::InvalidFallback))
+		);
+
+		let tx = Transaction {
+			version: 2, lock_time: PackedLockTime::ZERO, input: Vec::new(),
+			output: vec![TxOut { value: 10_000, script_pubkey: fallback_script.clone() }],
+		};
+		// Paying the fallback on-chain results in an event once the payment is reorg-safe.
+		mine_transaction(&nodes[1], &tx);
+		connect_blocks(&nodes[1], ANTI_REORG_DELAY - 2);
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+		connect_blocks(&nodes[1], 1);
+		let payment_hash = PaymentHash(invoice.payment_hash().into_inner());
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events, vec![Event::OnchainFallbackPaymentReceived {
+			payment_hash,
+			outpoint: bitcoin::OutPoint { txid: tx.txid(), vout: 0 },
+			amount_satoshis: 10_000,
+			expected_amount_msat: Some(10_000_000),
+		}]);
+
+		// The script is no longer watched once paid.
+		mine_transaction(&nodes[1], &tx);
+		connect_blocks(&nodes[1], ANTI_REORG_DELAY);
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	}
+
 	#[test]
 	fn test_hints_has_only_public_confd_channels() {
 		let chanmon_cfgs = create_chanmon_cfgs(2);
@@ -1245,6 +1527,50 @@ mod test {
 		match_invoice_routes(None, &nodes[0], scid_aliases_no_specified_amount);
 	}
 
+	#[test]
+	fn test_multi_lsp_hints_split_and_rotate() {
+		let chanmon_cfgs = create_chanmon_cfgs(5);
+		let node_cfgs = create_node_cfgs(5, &chanmon_cfgs);
+		// Allow the full channel to be used by a single HTLC so that hints are limited by our
+		// split only.
+		let mut config = test_default_channel_config();
+		config.channel_handshake_config.max_inbound_htlc_value_in_flight_percent_of_channel = 100;
+		let node_chanmgrs = create_node_chanmgrs(5, &node_cfgs, &[Some(config), None, None, None, None]);
+		let nodes = create_network(5, &node_cfgs, &node_chanmgrs);
+		for lsp in 1..5 {
+			create_unannounced_chan_between_nodes_with_value(&nodes, lsp, 0, 100_000, 0);
+		}
+		let create_invoice = |amt_msat| create_multi_lsp_invoice_from_channelmanager_and_duration_since_epoch(
+			nodes[0].node, nodes[0].keys_manager, nodes[0].logger, Currency::BitcoinTestnet, amt_msat,
+			"test".to_string(), Duration::from_secs(1234567), 3600, None).unwrap();
+
+		// No single LSP has enough inbound capacity for the payment, so we expect it to be split
+		// evenly across two of them.
+		let mut included_lsps = HashSet::new();
+		for _ in 0..8 {
+			let invoice = create_invoice(Some(150_000_000));
+			let hints = invoice.private_routes();
+			assert_eq!(hints.len(), 2);
+			for hint in hints {
+				let hop = &(hint.0).0[0];
+				let htlc_maximum_msat = hop.htlc_maximum_msat.unwrap();
+				assert!(htlc_maximum_msat < 150_000_000);
+				assert!(htlc_maximum_msat * 2 >= 165_000_000);
+				included_lsps.insert(hop.src_node_id);
+			}
+		}
+		// Across invoices, hints should be rotated through our LSPs.
+		assert!(included_lsps.len() > 2);
+
+		// A payment which fits within any one LSP's inbound capacity only reveals one LSP.
+		let invoice = create_invoice(Some(10_000_000));
+		assert_eq!(invoice.private_routes().len(), 1);
+
+		// Without an amount, we include as many hints as we would otherwise.
+		let invoice = create_invoice(None);
+		assert_eq!(invoice.private_routes().len(), 3);
+	}
+
 	fn match_invoice_routes<'a, 'b: 'a, 'c: 'b>(
 		invoice_amt: Option<u64>,
 		invoice_node: &Node<'a, 'b, 'c>,
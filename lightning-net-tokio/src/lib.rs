@@ -29,12 +29,15 @@
 #![deny(missing_docs)]
 #![cfg_attr(docsrs, feature(doc_auto_cfg))]
 
+#[cfg(ldk_bench)] extern crate criterion;
+
 use bitcoin::secp256k1::PublicKey;
 
 use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::{io, time};
 use tokio::sync::mpsc;
-use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 
 use lightning::ln::peer_handler;
 use lightning::ln::peer_handler::SocketDescriptor as LnSocketTrait;
@@ -44,6 +47,7 @@ use lightning::ln::msgs::NetAddress;
 use std::ops::Deref;
 use std::task::{self, Poll};
 use std::future::Future;
+use std::io::IoSlice;
 use std::net::SocketAddr;
 use std::net::TcpStream as StdTcpStream;
 use std::sync::{Arc, Mutex};
@@ -54,62 +58,40 @@ use std::hash::Hash;
 
 static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
-// We only need to select over multiple futures in one place, and taking on the full `tokio/macros`
-// dependency tree in order to do so (which has broken our MSRV before) is excessive. Instead, we
-// define a trivial two- and three- select macro with the specific types we need and just use that.
-
-pub(crate) enum SelectorOutput {
-	A(Option<()>), B(Option<()>), C(tokio::io::Result<usize>),
-}
+/// The maximum number of slices we pass to a single vectored write.
+const MAX_VECTORED_WRITE_SLICES: usize = 16;
 
-pub(crate) struct TwoSelector<
-	A: Future<Output=Option<()>> + Unpin, B: Future<Output=Option<()>> + Unpin
-> {
-	pub a: A,
-	pub b: B,
-}
-
-impl<
-	A: Future<Output=Option<()>> + Unpin, B: Future<Output=Option<()>> + Unpin
-> Future for TwoSelector<A, B> {
-	type Output = SelectorOutput;
-	fn poll(mut self: Pin<&mut Self>, ctx: &mut task::Context<'_>) -> Poll<SelectorOutput> {
-		match Pin::new(&mut self.a).poll(ctx) {
-			Poll::Ready(res) => { return Poll::Ready(SelectorOutput::A(res)); },
-			Poll::Pending => {},
-		}
-		match Pin::new(&mut self.b).poll(ctx) {
-			Poll::Ready(res) => { return Poll::Ready(SelectorOutput::B(res)); },
-			Poll::Pending => {},
-		}
-		Poll::Pending
-	}
+/// The events the read loop of a connection waits for.
+enum ReadLoopEvent {
+	WriteAvail(Option<()>), ReadWake(Option<()>), Read(io::Result<usize>),
 }
 
-pub(crate) struct ThreeSelector<
-	A: Future<Output=Option<()>> + Unpin, B: Future<Output=Option<()>> + Unpin, C: Future<Output=tokio::io::Result<usize>> + Unpin
-> {
-	pub a: A,
-	pub b: B,
-	pub c: C,
+/// Resolves to the first [`ReadLoopEvent`] to occur.
+///
+/// Rather than selecting over the futures returned by `recv()` and `read()`, which we'd have to
+/// box on every iteration of the read loop to be able to poll them, this polls the channels and
+/// the socket directly, and thus doesn't allocate.
+struct NextReadLoopEvent<'a> {
+	write_avail_receiver: &'a mut mpsc::Receiver<()>,
+	read_wake_receiver: &'a mut mpsc::Receiver<()>,
+	// Only set if we should read from the socket, i.e. if reading isn't paused.
+	reader: Option<(&'a mut OwnedReadHalf, &'a mut [u8])>,
 }
 
-impl<
-	A: Future<Output=Option<()>> + Unpin, B: Future<Output=Option<()>> + Unpin, C: Future<Output=tokio::io::Result<usize>> + Unpin
-> Future for ThreeSelector<A, B, C> {
-	type Output = SelectorOutput;
-	fn poll(mut self: Pin<&mut Self>, ctx: &mut task::Context<'_>) -> Poll<SelectorOutput> {
-		match Pin::new(&mut self.a).poll(ctx) {
-			Poll::Ready(res) => { return Poll::Ready(SelectorOutput::A(res)); },
-			Poll::Pending => {},
+impl<'a> Future for NextReadLoopEvent<'a> {
+	type Output = ReadLoopEvent;
+	fn poll(mut self: Pin<&mut Self>, ctx: &mut task::Context<'_>) -> Poll<ReadLoopEvent> {
+		if let Poll::Ready(res) = self.write_avail_receiver.poll_recv(ctx) {
+			return Poll::Ready(ReadLoopEvent::WriteAvail(res));
 		}
-		match Pin::new(&mut self.b).poll(ctx) {
-			Poll::Ready(res) => { return Poll::Ready(SelectorOutput::B(res)); },
-			Poll::Pending => {},
+		if let Poll::Ready(res) = self.read_wake_receiver.poll_recv(ctx) {
+			return Poll::Ready(ReadLoopEvent::ReadWake(res));
 		}
-		match Pin::new(&mut self.c).poll(ctx) {
-			Poll::Ready(res) => { return Poll::Ready(SelectorOutput::C(res)); },
-			Poll::Pending => {},
+		if let Some((reader, buf)) = self.reader.as_mut() {
+			let mut read_buf = io::ReadBuf::new(&mut **buf);
+			if let Poll::Ready(res) = Pin::new(&mut **reader).poll_read(ctx, &mut read_buf) {
+				return Poll::Ready(ReadLoopEvent::Read(res.map(|()| read_buf.filled().len())));
+			}
 		}
 		Poll::Pending
 	}
@@ -119,7 +101,7 @@ impl<
 /// Connection object (in an Arc<Mutex<>>) in each SocketDescriptor we create as well as in the
 /// read future (which is returned by schedule_read).
 struct Connection {
-	writer: Option<io::WriteHalf<TcpStream>>,
+	writer: Option<OwnedWriteHalf>,
 	// Because our PeerManager is templated by user-provided types, and we can't (as far as I can
 	// tell) have a const RawWakerVTable built out of templated functions, we need some indirection
 	// between being woken up with write-ready and calling PeerManager::write_buffer_space_avail.
@@ -156,7 +138,7 @@ impl Connection {
 	async fn schedule_read<PM: Deref + 'static + Send + Sync + Clone>(
 		peer_manager: PM,
 		us: Arc<Mutex<Self>>,
-		mut reader: io::ReadHalf<TcpStream>,
+		mut reader: OwnedReadHalf,
 		mut read_wake_receiver: mpsc::Receiver<()>,
 		mut write_avail_receiver: mpsc::Receiver<()>,
 	) where PM::Target: APeerManager<Descriptor = SocketDescriptor> {
@@ -165,8 +147,12 @@ impl Connection {
 		tokio::spawn(Self::poll_event_process(peer_manager.clone(), event_receiver));
 
 		// 4KiB is nice and big without handling too many messages all at once, giving other peers
-		// a chance to do some work.
+		// a chance to do some work. We read straight into it and hand the PeerManager slices of it,
+		// reusing it for every read.
 		let mut buf = [0; 4096];
+		// The number of bytes at the start of `buf` we read from the socket but didn't hand to the
+		// PeerManager yet, as it paused reading in the meantime.
+		let mut pending_read_len = 0;
 
 		let mut our_descriptor = SocketDescriptor::new(us.clone());
 		// An enum describing why we did/are disconnecting:
@@ -190,45 +176,41 @@ impl Connection {
 				}
 				us_lock.read_paused
 			};
-			// TODO: Drop the Box'ing of the futures once Rust has pin-on-stack support.
-			let select_result = if read_paused {
-				TwoSelector {
-					a: Box::pin(write_avail_receiver.recv()),
-					b: Box::pin(read_wake_receiver.recv()),
-				}.await
+			if pending_read_len != 0 && !read_paused {
+				let read_res = peer_manager.as_ref().read_event(&mut our_descriptor, &buf[0..pending_read_len]);
+				pending_read_len = 0;
+				match read_res {
+					Ok(pause_read) => {
+						if pause_read {
+							us.lock().unwrap().read_paused = true;
+						}
+					},
+					Err(_) => break Disconnect::CloseConnection,
+				}
 			} else {
-				ThreeSelector {
-					a: Box::pin(write_avail_receiver.recv()),
-					b: Box::pin(read_wake_receiver.recv()),
-					c: Box::pin(reader.read(&mut buf)),
-				}.await
-			};
-			match select_result {
-				SelectorOutput::A(v) => {
-					assert!(v.is_some()); // We can't have dropped the sending end, its in the us Arc!
-					if peer_manager.as_ref().write_buffer_space_avail(&mut our_descriptor).is_err() {
-						break Disconnect::CloseConnection;
-					}
-				},
-				SelectorOutput::B(_) => {},
-				SelectorOutput::C(read) => {
-					match read {
-						Ok(0) => break Disconnect::PeerDisconnected,
-						Ok(len) => {
-							let read_res = peer_manager.as_ref().read_event(&mut our_descriptor, &buf[0..len]);
-							let mut us_lock = us.lock().unwrap();
-							match read_res {
-								Ok(pause_read) => {
-									if pause_read {
-										us_lock.read_paused = true;
-									}
-								},
-								Err(_) => break Disconnect::CloseConnection,
-							}
-						},
-						Err(_) => break Disconnect::PeerDisconnected,
-					}
-				},
+				let next_event = NextReadLoopEvent {
+					write_avail_receiver: &mut write_avail_receiver,
+					read_wake_receiver: &mut read_wake_receiver,
+					reader: if read_paused || pending_read_len != 0 { None } else { Some((&mut reader, &mut buf)) },
+				}.await;
+				match next_event {
+					ReadLoopEvent::WriteAvail(v) => {
+						assert!(v.is_some()); // We can't have dropped the sending end, its in the us Arc!
+						if peer_manager.as_ref().write_buffer_space_avail(&mut our_descriptor).is_err() {
+							break Disconnect::CloseConnection;
+						}
+					},
+					ReadLoopEvent::ReadWake(_) => {},
+					ReadLoopEvent::Read(Ok(0)) => break Disconnect::PeerDisconnected,
+					ReadLoopEvent::Read(Ok(len)) => {
+						// Reading may have been paused while we were waiting on the socket, in which
+						// case we must hold on to the bytes until it is resumed. Either way, we
+						// re-check right away rather than yielding first.
+						pending_read_len = len;
+						continue;
+					},
+					ReadLoopEvent::Read(Err(_)) => break Disconnect::PeerDisconnected,
+				}
 			}
 			let _ = event_waker.try_send(());
 
@@ -250,7 +232,7 @@ impl Connection {
 		}
 	}
 
-	fn new(stream: StdTcpStream) -> (io::ReadHalf<TcpStream>, mpsc::Receiver<()>, mpsc::Receiver<()>, Arc<Mutex<Self>>) {
+	fn new(stream: StdTcpStream) -> (OwnedReadHalf, mpsc::Receiver<()>, mpsc::Receiver<()>, Arc<Mutex<Self>>) {
 		// We only ever need a channel of depth 1 here: if we returned a non-full write to the
 		// PeerManager, we will eventually get notified that there is room in the socket to write
 		// new bytes, which will generate an event. That event will be popped off the queue before
@@ -262,7 +244,8 @@ impl Connection {
 		// false.
 		let (read_waker, read_receiver) = mpsc::channel(1);
 		stream.set_nonblocking(true).unwrap();
-		let (reader, writer) = io::split(TcpStream::from_std(stream).unwrap());
+		// Unlike `io::split`, `into_split` doesn't need to lock the stream on every read and write.
+		let (reader, writer) = TcpStream::from_std(stream).unwrap().into_split();
 
 		(reader, write_receiver, read_receiver,
 		Arc::new(Mutex::new(Self {
@@ -462,6 +445,10 @@ impl SocketDescriptor {
 }
 impl peer_handler::SocketDescriptor for SocketDescriptor {
 	fn send_data(&mut self, data: &[u8], resume_read: bool) -> usize {
+		self.send_data_vectored(&[data], resume_read)
+	}
+
+	fn send_data_vectored(&mut self, data: &[&[u8]], resume_read: bool) -> usize {
 		// To send data, we take a lock on our Connection to access the WriteHalf of the TcpStream,
 		// writing to it if there's room in the kernel buffer, or otherwise create a new Waker with
 		// a SocketDescriptor in it which can wake up the write_avail Sender, waking up the
@@ -479,19 +466,34 @@ impl peer_handler::SocketDescriptor for SocketDescriptor {
 			us.read_paused = false;
 			let _ = us.read_waker.try_send(());
 		}
-		if data.is_empty() { return 0; }
+		let data_len: usize = data.iter().map(|slice| slice.len()).sum();
+		if data_len == 0 { return 0; }
 		let waker = unsafe { task::Waker::from_raw(write_avail_to_waker(&us.write_avail)) };
 		let mut ctx = task::Context::from_waker(&waker);
 		let mut written_len = 0;
 		loop {
-			match std::pin::Pin::new(us.writer.as_mut().unwrap()).poll_write(&mut ctx, &data[written_len..]) {
+			// Skip whatever we already wrote, without copying any of the remaining data.
+			let mut slices = [IoSlice::new(&[]); MAX_VECTORED_WRITE_SLICES];
+			let mut slices_count = 0;
+			let mut skip_len = written_len;
+			for slice in data.iter() {
+				if skip_len >= slice.len() {
+					skip_len -= slice.len();
+					continue;
+				}
+				if slices_count == MAX_VECTORED_WRITE_SLICES { break; }
+				slices[slices_count] = IoSlice::new(&slice[skip_len..]);
+				slices_count += 1;
+				skip_len = 0;
+			}
+			match std::pin::Pin::new(us.writer.as_mut().unwrap()).poll_write_vectored(&mut ctx, &slices[..slices_count]) {
 				task::Poll::Ready(Ok(res)) => {
 					// The tokio docs *seem* to indicate this can't happen, and I certainly don't
 					// know how to handle it if it does (cause it should be a Poll::Pending
 					// instead):
 					assert_ne!(res, 0);
 					written_len += res;
-					if written_len == data.len() { return written_len; }
+					if written_len == data_len { return written_len; }
 				},
 				task::Poll::Ready(Err(e)) => {
 					// The tokio docs *seem* to indicate this can't happen, and I certainly don't
@@ -543,11 +545,84 @@ impl Hash for SocketDescriptor {
 	}
 }
 
+#[cfg(ldk_bench)]
+/// Benches
+pub mod bench {
+	use super::{Connection, SocketDescriptor};
+	use lightning::ln::peer_handler::SocketDescriptor as _;
+
+	use criterion::Criterion;
+
+	use std::io::Read;
+	use std::net::{TcpListener, TcpStream};
+
+	const MSG_COUNT: usize = 32;
+	const MSG_LEN: usize = 300;
+
+	fn write_all(rt: &tokio::runtime::Runtime, descriptor: &mut SocketDescriptor, msgs: &[&[u8]], vectored: bool) {
+		let mut msg_idx = 0;
+		let mut msg_offset = 0;
+		while msg_idx < msgs.len() {
+			let sent = if vectored {
+				let mut pending: Vec<&[u8]> = msgs[msg_idx..].to_vec();
+				pending[0] = &pending[0][msg_offset..];
+				descriptor.send_data_vectored(&pending, true)
+			} else {
+				descriptor.send_data(&msgs[msg_idx][msg_offset..], true)
+			};
+			if sent == 0 {
+				// Let the reactor notice the socket became writable again.
+				rt.block_on(tokio::task::yield_now());
+			}
+			msg_offset += sent;
+			while msg_idx < msgs.len() && msg_offset >= msgs[msg_idx].len() {
+				msg_offset -= msgs[msg_idx].len();
+				msg_idx += 1;
+			}
+		}
+	}
+
+	fn bench_writes(bench: &mut Criterion, name: &str, vectored: bool) {
+		let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+		let _guard = rt.enter();
+
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let addr = listener.local_addr().unwrap();
+		let drain_thread = std::thread::spawn(move || {
+			let (mut conn, _) = listener.accept().unwrap();
+			let mut buf = [0; 65536];
+			while conn.read(&mut buf).unwrap_or(0) != 0 {}
+		});
+
+		let stream = TcpStream::connect(addr).unwrap();
+		stream.set_nonblocking(true).unwrap();
+		let (_reader, _write_receiver, _read_receiver, us) = Connection::new(stream);
+		let mut descriptor = SocketDescriptor::new(us);
+
+		let msgs_data = vec![[42; MSG_LEN]; MSG_COUNT];
+		let msgs: Vec<&[u8]> = msgs_data.iter().map(|msg| &msg[..]).collect();
+		bench.bench_function(name, |b| b.iter(|| write_all(&rt, &mut descriptor, &msgs, vectored)));
+
+		// Dropping the write half shuts the connection down, letting the drain thread exit.
+		descriptor.conn.lock().unwrap().writer.take();
+		drain_thread.join().unwrap();
+	}
+
+	/// Bench!
+	pub fn bench_socket_writes(bench: &mut Criterion) {
+		bench_writes(bench, "bench_socket_writes_sequential", false);
+		bench_writes(bench, "bench_socket_writes_vectored", true);
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use lightning::ln::features::*;
 	use lightning::ln::msgs::*;
-	use lightning::ln::peer_handler::{MessageHandler, PeerManager};
+	use lightning::ln::peer_handler::{CustomMessageHandler, IgnoringMessageHandler, MessageHandler, PeerManager};
+	use lightning::ln::peer_handler::SocketDescriptor as _;
+	use lightning::ln::wire::CustomMessageReader;
+	use lightning::util::ser::{Readable, Writeable, Writer};
 	use lightning::ln::features::NodeFeatures;
 	use lightning::routing::gossip::NodeId;
 	use lightning::events::*;
@@ -607,6 +682,7 @@ mod tests {
 		fn handle_commitment_signed(&self, _their_node_id: &PublicKey, _msg: &CommitmentSigned) {}
 		fn handle_revoke_and_ack(&self, _their_node_id: &PublicKey, _msg: &RevokeAndACK) {}
 		fn handle_update_fee(&self, _their_node_id: &PublicKey, _msg: &UpdateFee) {}
+		fn handle_yield(&self, _their_node_id: &PublicKey, _msg: &Yield) {}
 		fn handle_announcement_signatures(&self, _their_node_id: &PublicKey, _msg: &AnnouncementSignatures) {}
 		fn handle_channel_update(&self, _their_node_id: &PublicKey, _msg: &ChannelUpdate) {}
 		fn handle_open_channel_v2(&self, _their_node_id: &PublicKey, _msg: &OpenChannelV2) {}
@@ -786,4 +862,225 @@ mod tests {
 	async fn unthreaded_race_disconnect_accept() {
 		race_disconnect_accept().await;
 	}
+
+	const SEQ_MESSAGE_TYPE: u16 = 32_769;
+
+	/// A custom message carrying a sequence number, letting us check that messages are neither
+	/// lost nor duplicated.
+	#[derive(Debug)]
+	struct SeqMessage(u64);
+	impl lightning::ln::wire::Type for SeqMessage {
+		fn type_id(&self) -> u16 { SEQ_MESSAGE_TYPE }
+	}
+	impl Writeable for SeqMessage {
+		fn write<W: Writer>(&self, w: &mut W) -> Result<(), lightning::io::Error> { self.0.write(w) }
+	}
+
+	struct SeqMessageHandler {
+		pending_msgs: Mutex<Vec<(PublicKey, SeqMessage)>>,
+		received: Mutex<Vec<u64>>,
+	}
+	impl SeqMessageHandler {
+		fn new() -> Self {
+			Self { pending_msgs: Mutex::new(Vec::new()), received: Mutex::new(Vec::new()) }
+		}
+
+		async fn wait_for_received(&self, count: usize) {
+			tokio::time::timeout(Duration::from_secs(10), async {
+				while self.received.lock().unwrap().len() < count {
+					tokio::time::sleep(Duration::from_millis(10)).await;
+				}
+			}).await.unwrap();
+		}
+	}
+	impl CustomMessageReader for SeqMessageHandler {
+		type CustomMessage = SeqMessage;
+		fn read<R: lightning::io::Read>(&self, message_type: u16, buffer: &mut R) -> Result<Option<SeqMessage>, DecodeError> {
+			if message_type != SEQ_MESSAGE_TYPE { return Ok(None); }
+			Ok(Some(SeqMessage(Readable::read(buffer)?)))
+		}
+	}
+	impl CustomMessageHandler for SeqMessageHandler {
+		fn handle_custom_message(&self, msg: SeqMessage, _sender_node_id: &PublicKey) -> Result<(), LightningError> {
+			self.received.lock().unwrap().push(msg.0);
+			Ok(())
+		}
+		fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, SeqMessage)> {
+			mem::take(&mut *self.pending_msgs.lock().unwrap())
+		}
+		fn provided_node_features(&self) -> NodeFeatures { NodeFeatures::empty() }
+		fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures { InitFeatures::empty() }
+	}
+
+	type SeqPeerManager = PeerManager<super::SocketDescriptor, Arc<MsgHandler>, Arc<MsgHandler>,
+		Arc<IgnoringMessageHandler>, Arc<TestLogger>, Arc<SeqMessageHandler>, Arc<TestNodeSigner>>;
+
+	/// Two connected peers, where we drive the inbound peer's read loop ourselves so that we can
+	/// pause and resume its reads.
+	struct PausablePeers {
+		a_manager: Arc<SeqPeerManager>,
+		a_seq_handler: Arc<SeqMessageHandler>,
+		b_pub: PublicKey,
+		b_seq_handler: Arc<SeqMessageHandler>,
+		b_conn: Arc<Mutex<super::Connection>>,
+		// Keep the channels the handlers notify on alive.
+		_channels: Vec<mpsc::Receiver<()>>,
+	}
+
+	impl PausablePeers {
+		async fn connect() -> Self {
+			let secp_ctx = Secp256k1::new();
+			let a_key = SecretKey::from_slice(&[1; 32]).unwrap();
+			let b_key = SecretKey::from_slice(&[2; 32]).unwrap();
+			let a_pub = PublicKey::from_secret_key(&secp_ctx, &a_key);
+			let b_pub = PublicKey::from_secret_key(&secp_ctx, &b_key);
+
+			let mut channels = Vec::new();
+			let mut new_peer = |key: SecretKey, expected_pubkey: PublicKey, seed: u8| {
+				let (connected_sender, connected) = mpsc::channel(1);
+				let (disconnected_sender, disconnected) = mpsc::channel(1);
+				channels.push(disconnected);
+				let handler = Arc::new(MsgHandler {
+					expected_pubkey,
+					pubkey_connected: connected_sender,
+					pubkey_disconnected: disconnected_sender,
+					disconnected_flag: AtomicBool::new(false),
+					msg_events: Mutex::new(Vec::new()),
+				});
+				let seq_handler = Arc::new(SeqMessageHandler::new());
+				let manager = Arc::new(PeerManager::new(MessageHandler {
+					chan_handler: Arc::clone(&handler),
+					route_handler: handler,
+					onion_message_handler: Arc::new(IgnoringMessageHandler{}),
+					custom_message_handler: Arc::clone(&seq_handler),
+				}, 0, &[seed; 32], Arc::new(TestLogger()), Arc::new(TestNodeSigner::new(key))));
+				(manager, seq_handler, connected)
+			};
+			let (a_manager, a_seq_handler, mut a_connected) = new_peer(a_key, b_pub, 1);
+			let (b_manager, b_seq_handler, mut b_connected) = new_peer(b_key, a_pub, 2);
+
+			let (conn_a, conn_b) = make_tcp_connection();
+			tokio::spawn(super::setup_outbound(Arc::clone(&a_manager), b_pub, conn_a));
+
+			// Set up the inbound connection as `setup_inbound` does, but hold on to its `Connection`.
+			let (reader, write_receiver, read_receiver, b_conn) = super::Connection::new(conn_b);
+			b_manager.new_inbound_connection(super::SocketDescriptor::new(Arc::clone(&b_conn)), None).unwrap();
+			tokio::spawn(super::Connection::schedule_read(
+				b_manager, Arc::clone(&b_conn), reader, read_receiver, write_receiver));
+
+			tokio::time::timeout(Duration::from_secs(10), a_connected.recv()).await.unwrap();
+			tokio::time::timeout(Duration::from_secs(1), b_connected.recv()).await.unwrap();
+			channels.push(a_connected);
+			channels.push(b_connected);
+
+			Self { a_manager, a_seq_handler, b_pub, b_seq_handler, b_conn, _channels: channels }
+		}
+
+		fn send_from_a(&self, seqs: std::ops::Range<u64>) {
+			self.a_seq_handler.pending_msgs.lock().unwrap().extend(seqs.map(|seq| (self.b_pub, SeqMessage(seq))));
+			self.a_manager.process_events();
+		}
+
+		/// Pauses reads on B's connection, as we do when its `PeerManager` asks us to.
+		fn pause_b_reads(&self) {
+			self.b_conn.lock().unwrap().read_paused = true;
+		}
+	}
+
+	#[tokio::test]
+	async fn paused_reads_resume_only_when_requested() {
+		// Check that once reads are paused, calls to `send_data` with `resume_read` unset don't
+		// resume them, and that the read loop picks bytes which arrived in the meantime up as soon
+		// as `send_data` is called with `resume_read` set.
+		let peers = PausablePeers::connect().await;
+		peers.pause_b_reads();
+		peers.send_from_a(0..1);
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert!(peers.b_seq_handler.received.lock().unwrap().is_empty());
+
+		let mut b_descriptor = super::SocketDescriptor::new(Arc::clone(&peers.b_conn));
+		assert_eq!(b_descriptor.send_data(&[], false), 0);
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert!(peers.b_conn.lock().unwrap().read_paused);
+		assert!(peers.b_seq_handler.received.lock().unwrap().is_empty());
+
+		assert_eq!(b_descriptor.send_data(&[], true), 0);
+		assert!(!peers.b_conn.lock().unwrap().read_paused);
+		peers.b_seq_handler.wait_for_received(1).await;
+		assert_eq!(*peers.b_seq_handler.received.lock().unwrap(), vec![0]);
+	}
+
+	async fn do_no_messages_lost_across_pause() {
+		// Check that bytes read from the socket just before or while reads are paused are handed to
+		// the `PeerManager` exactly once, in order, once reads are resumed.
+		let peers = PausablePeers::connect().await;
+		peers.send_from_a(0..100);
+		peers.b_seq_handler.wait_for_received(100).await;
+
+		peers.pause_b_reads();
+		// Send more than fits in a single read.
+		peers.send_from_a(100..500);
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(peers.b_seq_handler.received.lock().unwrap().len(), 100);
+
+		super::SocketDescriptor::new(Arc::clone(&peers.b_conn)).send_data(&[], true);
+		peers.send_from_a(500..600);
+		peers.b_seq_handler.wait_for_received(600).await;
+		tokio::time::sleep(Duration::from_millis(100)).await;
+		assert_eq!(*peers.b_seq_handler.received.lock().unwrap(), (0..600).collect::<Vec<_>>());
+	}
+
+	#[tokio::test(flavor = "multi_thread")]
+	async fn threaded_no_messages_lost_across_pause() {
+		do_no_messages_lost_across_pause().await;
+	}
+
+	#[tokio::test]
+	async fn unthreaded_no_messages_lost_across_pause() {
+		do_no_messages_lost_across_pause().await;
+	}
+
+	#[tokio::test]
+	async fn partial_vectored_write_completes_in_order() {
+		// Check that a vectored write of more slices than we pass to a single write, which the
+		// socket only partially accepts, completes in order once we're told we can write again.
+		let (conn_a, mut conn_b) = make_tcp_connection();
+		let (_reader, mut write_receiver, _read_receiver, conn) = super::Connection::new(conn_a);
+		let mut descriptor = super::SocketDescriptor::new(Arc::clone(&conn));
+
+		const SLICE_LEN: usize = 256 * 1024;
+		let slices_data: Vec<Vec<u8>> = (0..super::MAX_VECTORED_WRITE_SLICES + 8)
+			.map(|i| (0..SLICE_LEN).map(|j| ((i * SLICE_LEN + j) % 251) as u8).collect())
+			.collect();
+		let expected: Vec<u8> = slices_data.iter().flatten().copied().collect();
+		let unsent = |mut written: usize| -> Vec<&[u8]> {
+			slices_data.iter().filter_map(|slice| {
+				if written >= slice.len() { written -= slice.len(); return None; }
+				let unsent = &slice[written..];
+				written = 0;
+				Some(unsent)
+			}).collect()
+		};
+
+		// Nothing is read from the other end yet, so the socket can't take all of it.
+		let mut written = descriptor.send_data_vectored(&unsent(0), false);
+		assert!(written < expected.len());
+		// A write which would block pauses reads, as `send_data` documents.
+		assert!(conn.lock().unwrap().read_paused);
+
+		let read_thread = std::thread::spawn(move || {
+			let mut received = Vec::new();
+			std::io::Read::read_to_end(&mut conn_b, &mut received).unwrap();
+			received
+		});
+		while written < expected.len() {
+			tokio::time::timeout(Duration::from_secs(10), write_receiver.recv()).await.unwrap();
+			written += descriptor.send_data_vectored(&unsent(written), false);
+		}
+		assert_eq!(written, expected.len());
+
+		// Dropping the write half shuts the connection down, letting the read thread finish.
+		conn.lock().unwrap().writer.take();
+		assert!(read_thread.join().unwrap() == expected);
+	}
 }
@@ -21,11 +21,13 @@ use bitcoin::hashes::hex::FromHex;
 use lightning::chain::channelmonitor::ChannelMonitor;
 use lightning::sign::{EntropySource, SignerProvider};
 use lightning::util::ser::{ReadableArgs, Writeable};
-use lightning::util::persist::KVStorePersister;
+use lightning::util::persist::{KVStorePersister, PersistenceHealthStatus, WriteLatencyTracker};
 use std::fs;
 use std::io::Cursor;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Instant;
 
 /// FilesystemPersister persists channel data on disk, where each channel's
 /// data is stored in a file named after its funding outpoint.
@@ -41,6 +43,8 @@ use std::path::{Path, PathBuf};
 /// FilesystemPersister.
 pub struct FilesystemPersister {
 	path_to_channel_data: String,
+	write_latencies: Mutex<WriteLatencyTracker>,
+	last_error: Mutex<Option<String>>,
 }
 
 impl FilesystemPersister {
@@ -49,9 +53,17 @@ impl FilesystemPersister {
 	pub fn new(path_to_channel_data: String) -> Self {
 		Self {
 			path_to_channel_data,
+			write_latencies: Mutex::new(WriteLatencyTracker::new()),
+			last_error: Mutex::new(None),
 		}
 	}
 
+	/// Clears the error of the most recent failed write, which is otherwise reported in the
+	/// [`PersistenceHealthStatus`] returned by [`KVStorePersister::health`] indefinitely.
+	pub fn clear_last_error(&self) {
+		*self.last_error.lock().unwrap() = None;
+	}
+
 	/// Get the directory which was provided when this persister was initialized.
 	pub fn get_data_dir(&self) -> String {
 		self.path_to_channel_data.clone()
@@ -126,7 +138,23 @@ impl KVStorePersister for FilesystemPersister {
 	fn persist<W: Writeable>(&self, key: &str, object: &W) -> std::io::Result<()> {
 		let mut dest_file = PathBuf::from(self.path_to_channel_data.clone());
 		dest_file.push(key);
-		util::write_to_file(dest_file, object)
+		let start = Instant::now();
+		let res = util::write_to_file(dest_file, object);
+		self.write_latencies.lock().unwrap().record(start.elapsed());
+		if let Err(e) = &res {
+			*self.last_error.lock().unwrap() = Some(e.to_string());
+		}
+		res
+	}
+
+	fn health(&self) -> Option<PersistenceHealthStatus> {
+		let write_latencies = self.write_latencies.lock().unwrap();
+		Some(PersistenceHealthStatus {
+			free_space_bytes: util::free_space_bytes(Path::new(&self.path_to_channel_data)),
+			write_latency_p50: write_latencies.percentile(50),
+			write_latency_p99: write_latencies.percentile(99),
+			last_error: self.last_error.lock().unwrap().clone(),
+		})
 	}
 }
 
@@ -144,6 +172,7 @@ mod tests {
 	use lightning::{check_closed_broadcast, check_closed_event, check_added_monitors};
 	use lightning::events::{ClosureReason, MessageSendEventsProvider};
 	use lightning::ln::functional_test_utils::*;
+	use lightning::util::persist::KVStorePersister;
 	use lightning::util::test_utils;
 	use std::fs;
 	#[cfg(target_os = "windows")]
@@ -296,6 +325,35 @@ mod tests {
 		added_monitors.clear();
 	}
 
+	#[test]
+	fn test_persistence_health() {
+		let persister = FilesystemPersister::new("test_persistence_health".to_string());
+		let health = persister.health().unwrap();
+		assert_eq!(health.write_latency_p50, None);
+		assert_eq!(health.write_latency_p99, None);
+		assert_eq!(health.last_error, None);
+
+		persister.persist("manager", &42u64).unwrap();
+		let health = persister.health().unwrap();
+		assert!(health.write_latency_p50.is_some());
+		assert!(health.write_latency_p99 >= health.write_latency_p50);
+		assert_eq!(health.last_error, None);
+		#[cfg(not(target_os = "windows"))]
+		assert!(health.free_space_bytes.unwrap() > 0);
+
+		// A failed write is reported even after subsequent successful ones, until cleared.
+		let mut path = std::path::PathBuf::from(&persister.path_to_channel_data);
+		path.push("monitors");
+		fs::File::create(path).unwrap();
+		assert!(persister.persist("monitors/monitor", &42u64).is_err());
+		assert!(persister.health().unwrap().last_error.is_some());
+
+		persister.persist("manager", &42u64).unwrap();
+		assert!(persister.health().unwrap().last_error.is_some());
+		persister.clear_last_error();
+		assert_eq!(persister.health().unwrap().last_error, None);
+	}
+
 	// Test that if a persister's directory name is invalid, monitor persistence
 	// will fail.
 	#[cfg(target_os = "windows")]
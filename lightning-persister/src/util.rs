@@ -2,7 +2,7 @@
 extern crate winapi;
 
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::io::BufWriter;
 
 #[cfg(not(target_os = "windows"))]
@@ -77,6 +77,26 @@ pub(crate) fn write_to_file<W: Writeable>(dest_file: PathBuf, data: &W) -> std::
 	Ok(())
 }
 
+/// Returns the number of bytes available to unprivileged users on the filesystem containing
+/// `path`, or `None` if it cannot be determined (e.g. as `path` doesn't exist yet).
+#[cfg(not(target_os = "windows"))]
+pub(crate) fn free_space_bytes(path: &Path) -> Option<u64> {
+	use std::os::unix::ffi::OsStrExt;
+	let path = std::ffi::CString::new(path.as_os_str().as_bytes()).ok()?;
+	let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+	if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+		return None;
+	}
+	#[allow(clippy::unnecessary_cast)] // The statvfs field types differ across platforms
+	Some((stat.f_bavail as u64).saturating_mul(stat.f_frsize as u64))
+}
+
+/// Free space is not yet measured on Windows.
+#[cfg(target_os = "windows")]
+pub(crate) fn free_space_bytes(_path: &Path) -> Option<u64> {
+	None
+}
+
 #[cfg(test)]
 mod tests {
 	use lightning::util::ser::{Writer, Writeable};
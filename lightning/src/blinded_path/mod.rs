@@ -64,8 +64,17 @@ impl BlindedPath {
 	///
 	/// Errors if less than two hops are provided or if `node_pk`(s) are invalid.
 	//  TODO: make all payloads the same size with padding + add dummy hops
-	pub fn new_for_message<ES: EntropySource, T: secp256k1::Signing + secp256k1::Verification>
+	pub fn new_for_message<ES: EntropySource + ?Sized, T: secp256k1::Signing + secp256k1::Verification>
 		(node_pks: &[PublicKey], entropy_source: &ES, secp_ctx: &Secp256k1<T>) -> Result<Self, ()>
+	{
+		Self::new_for_message_with_path_id(node_pks, None, entropy_source, secp_ctx)
+	}
+
+	/// Create a blinded path for an onion message as in [`Self::new_for_message`], which
+	/// additionally includes the given `path_id` in the data encrypted to the destination node,
+	/// allowing it to recognize messages received along the path.
+	pub(crate) fn new_for_message_with_path_id<ES: EntropySource + ?Sized, T: secp256k1::Signing + secp256k1::Verification>
+		(node_pks: &[PublicKey], path_id: Option<[u8; 32]>, entropy_source: &ES, secp_ctx: &Secp256k1<T>) -> Result<Self, ()>
 	{
 		if node_pks.len() < 2 { return Err(()) }
 		let blinding_secret_bytes = entropy_source.get_secure_random_bytes();
@@ -75,7 +84,7 @@ impl BlindedPath {
 		Ok(BlindedPath {
 			introduction_node_id,
 			blinding_point: PublicKey::from_secret_key(secp_ctx, &blinding_secret),
-			blinded_hops: blinded_message_hops(secp_ctx, node_pks, &blinding_secret).map_err(|_| ())?,
+			blinded_hops: blinded_message_hops(secp_ctx, node_pks, path_id, &blinding_secret).map_err(|_| ())?,
 		})
 	}
 
@@ -118,7 +127,8 @@ impl BlindedPath {
 
 /// Construct blinded onion message hops for the given `unblinded_path`.
 fn blinded_message_hops<T: secp256k1::Signing + secp256k1::Verification>(
-	secp_ctx: &Secp256k1<T>, unblinded_path: &[PublicKey], session_priv: &SecretKey
+	secp_ctx: &Secp256k1<T>, unblinded_path: &[PublicKey], path_id: Option<[u8; 32]>,
+	session_priv: &SecretKey
 ) -> Result<Vec<BlindedHop>, secp256k1::Error> {
 	let mut blinded_hops = Vec::with_capacity(unblinded_path.len());
 
@@ -140,7 +150,7 @@ fn blinded_message_hops<T: secp256k1::Signing + secp256k1::Verification>(
 	})?;
 
 	if let Some((final_ss, final_blinded_node_id)) = prev_ss_and_blinded_node_id {
-		let final_payload = ReceiveTlvs { path_id: None };
+		let final_payload = ReceiveTlvs { path_id };
 		blinded_hops.push(BlindedHop {
 			blinded_node_id: final_blinded_node_id,
 			encrypted_payload: encrypt_payload(final_payload, final_ss),
@@ -17,6 +17,9 @@ use core::{cmp, ops::Deref};
 use core::convert::TryInto;
 
 use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::secp256k1::PublicKey;
+
+use crate::prelude::*;
 
 // TODO: Define typed abstraction over feerates to handle their conversions.
 pub(crate) fn compute_feerate_sat_per_1000_weight(fee_sat: u64, weight: u64) -> u32 {
@@ -42,8 +45,79 @@ pub trait BroadcasterInterface {
 	/// Bitcoin transaction packages are defined in BIP 331 and here:
 	/// https://github.com/bitcoin/bitcoin/blob/master/doc/policy/packages.md
 	fn broadcast_transactions(&self, txs: &[&Transaction]);
+
+	/// Sends a list of transactions out to (hopefully) be mined, along with [`TransactionMetadata`]
+	/// describing why each transaction is being broadcast, e.g., allowing wallets to label their
+	/// on-chain history.
+	///
+	/// LDK always broadcasts via this method. The same considerations as for
+	/// [`Self::broadcast_transactions`] apply.
+	///
+	/// The default implementation drops the metadata and calls [`Self::broadcast_transactions`].
+	fn broadcast_transactions_with_meta(&self, txs: &[(&Transaction, TransactionMetadata)]) {
+		let txs = txs.iter().map(|(tx, _)| *tx).collect::<Vec<_>>();
+		self.broadcast_transactions(&txs);
+	}
+
+	/// Called periodically to allow the broadcaster to rebroadcast transactions which haven't
+	/// confirmed yet, see [`RebroadcastScheduler`].
+	///
+	/// The `BackgroundProcessor` calls this every 30 seconds via
+	/// [`ChainMonitor::rebroadcast_pending_transactions`]. The default implementation does nothing.
+	///
+	/// [`RebroadcastScheduler`]: crate::chain::rebroadcast::RebroadcastScheduler
+	/// [`ChainMonitor::rebroadcast_pending_transactions`]: crate::chain::chainmonitor::ChainMonitor::rebroadcast_pending_transactions
+	fn rebroadcast_pending_transactions(&self) {}
+}
+
+/// The reason a transaction is being broadcast, see [`TransactionMetadata`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum TransactionType {
+	/// A channel's funding transaction.
+	Funding,
+	/// A channel's closing transaction, mutually agreed upon with our counterparty.
+	CooperativeClose,
+	/// Our commitment transaction, unilaterally closing a channel, or a child transaction spending
+	/// its anchor output to bump its fee.
+	UnilateralClose,
+	/// A transaction claiming funds from a commitment transaction, e.g., an HTLC transaction or a
+	/// claim of a revoked output.
+	Claim,
+	/// A transaction settling a contract's collateral according to its outcome.
+	ContractSettlement,
 }
 
+impl_writeable_tlv_based_enum!(TransactionType,
+	(0, Funding) => {},
+	(2, CooperativeClose) => {},
+	(4, UnilateralClose) => {},
+	(6, Claim) => {},
+	(8, ContractSettlement) => {};
+);
+
+/// Metadata describing a transaction passed to
+/// [`BroadcasterInterface::broadcast_transactions_with_meta`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct TransactionMetadata {
+	/// The id of the channel the transaction belongs to, if any.
+	pub channel_id: Option<[u8; 32]>,
+	/// The node id of the channel's counterparty, if known.
+	///
+	/// This will be `None` for claims of channels opened prior to LDK 0.0.110, as well as for
+	/// transactions broadcast when handling [`BumpTransactionEvent`]s.
+	///
+	/// [`BumpTransactionEvent`]: crate::events::bump_transaction::BumpTransactionEvent
+	pub counterparty_node_id: Option<PublicKey>,
+	/// The reason the transaction is being broadcast.
+	pub transaction_type: TransactionType,
+}
+
+impl_writeable_tlv_based!(TransactionMetadata, {
+	(0, channel_id, option),
+	(2, counterparty_node_id, option),
+	(4, transaction_type, required),
+});
+
 /// An enum that represents the priority at which we want a transaction to confirm used for feerate
 /// estimation.
 #[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
@@ -24,12 +24,13 @@
 //! servicing [`ChannelMonitor`] updates from the client.
 
 use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::script::Script;
 use bitcoin::hash_types::{Txid, BlockHash};
 
 use crate::chain;
 use crate::chain::{ChannelMonitorUpdateStatus, Filter, WatchedOutput};
 use crate::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
-use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, Balance, MonitorEvent, TransactionOutputs, LATENCY_GRACE_PERIOD_BLOCKS};
+use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, Balance, BalanceStatement, MonitorEvent, TransactionOutputs, LATENCY_GRACE_PERIOD_BLOCKS};
 use crate::chain::transaction::{OutPoint, TransactionData};
 use crate::sign::WriteableEcdsaChannelSigner;
 use crate::events;
@@ -37,6 +38,7 @@ use crate::events::{Event, EventHandler};
 use crate::util::atomic_counter::AtomicCounter;
 use crate::util::logger::Logger;
 use crate::util::errors::APIError;
+use crate::util::persist::{PersistenceHealthStatus, PersistenceHealthThresholds};
 use crate::util::wakers::{Future, Notifier};
 use crate::ln::channelmanager::ChannelDetails;
 
@@ -72,6 +74,10 @@ impl MonitorUpdateId {
 	pub(crate) fn from_new_monitor<ChannelSigner: WriteableEcdsaChannelSigner>(monitor: &ChannelMonitor<ChannelSigner>) -> Self {
 		Self { contents: UpdateOrigin::OffChain(monitor.get_latest_update_id()) }
 	}
+	#[cfg(test)]
+	pub(crate) fn from_update_id(update_id: u64) -> Self {
+		Self { contents: UpdateOrigin::OffChain(update_id) }
+	}
 }
 
 /// `Persist` defines behavior for persisting channel monitors: this could mean
@@ -146,6 +152,13 @@ pub trait Persist<ChannelSigner: WriteableEcdsaChannelSigner> {
 	///
 	/// [`Writeable::write`]: crate::util::ser::Writeable::write
 	fn update_persisted_channel(&self, channel_id: OutPoint, update: Option<&ChannelMonitorUpdate>, data: &ChannelMonitor<ChannelSigner>, update_id: MonitorUpdateId) -> ChannelMonitorUpdateStatus;
+
+	/// Returns a snapshot of the health of the underlying storage, if the implementation is able
+	/// to measure it.
+	///
+	/// This is polled by [`ChainMonitor::check_persistence_health`] in order to warn the user via
+	/// an [`Event::PersistenceHealth`] before persistence actually starts failing.
+	fn persistence_health(&self) -> Option<PersistenceHealthStatus> { None }
 }
 
 struct MonitorHolder<ChannelSigner: WriteableEcdsaChannelSigner> {
@@ -248,6 +261,13 @@ pub struct ChainMonitor<ChannelSigner: WriteableEcdsaChannelSigner, C: Deref, T:
 	pending_monitor_events: Mutex<Vec<(OutPoint, Vec<MonitorEvent>, Option<PublicKey>)>>,
 	/// The best block height seen, used as a proxy for the passage of time.
 	highest_chain_height: AtomicUsize,
+	/// The thresholds set via [`ChainMonitor::set_persistence_health_thresholds`], if any.
+	persistence_health_thresholds: Mutex<Option<PersistenceHealthThresholds>>,
+	/// Whether persistence was healthy the last time we checked, used to only generate an
+	/// [`Event::PersistenceHealth`] when a threshold is crossed.
+	persistence_healthy: AtomicBool,
+	/// Events generated by the [`ChainMonitor`] itself rather than one of its [`ChannelMonitor`]s.
+	pending_events: Mutex<Vec<Event>>,
 
 	event_notifier: Notifier,
 }
@@ -356,6 +376,9 @@ where C::Target: chain::Filter,
 			persister,
 			pending_monitor_events: Mutex::new(Vec::new()),
 			highest_chain_height: AtomicUsize::new(0),
+			persistence_health_thresholds: Mutex::new(None),
+			persistence_healthy: AtomicBool::new(true),
+			pending_events: Mutex::new(Vec::new()),
 			event_notifier: Notifier::new(),
 		}
 	}
@@ -385,6 +408,18 @@ where C::Target: chain::Filter,
 		ret
 	}
 
+	/// Gets a [`BalanceStatement`] for each of the contained [`ChannelMonitor`]s at the given
+	/// block height, suitable for signing and handing to an auditor.
+	///
+	/// Fails if any [`ChannelMonitor`] has not yet been synced to exactly `block_height`, see
+	/// [`ChannelMonitor::get_balance_statement`].
+	pub fn get_balance_statements(&self, block_height: u32) -> Result<Vec<BalanceStatement>, ()> {
+		let monitor_states = self.monitors.read().unwrap();
+		monitor_states.values()
+			.map(|monitor_state| monitor_state.monitor.get_balance_statement(block_height))
+			.collect()
+	}
+
 	/// Gets the [`LockedChannelMonitor`] for a given funding outpoint, returning an `Err` if no
 	/// such [`ChannelMonitor`] is currently being monitored for.
 	///
@@ -522,6 +557,10 @@ where C::Target: chain::Filter,
 	) {
 		// Sadly we can't hold the monitors read lock through an async call. Thus we have to do a
 		// crazy dance to process a monitor's events then only remove them once we've done so.
+		let pending_events = core::mem::take(&mut *self.pending_events.lock().unwrap());
+		for event in pending_events {
+			handler(event).await;
+		}
 		let mons_to_process = self.monitors.read().unwrap().keys().cloned().collect::<Vec<_>>();
 		for funding_txo in mons_to_process {
 			let mut ev;
@@ -555,6 +594,47 @@ where C::Target: chain::Filter,
 			)
 		}
 	}
+
+	/// Lets our broadcaster rebroadcast any of its transactions which haven't confirmed yet, via
+	/// [`BroadcasterInterface::rebroadcast_pending_transactions`]. We recommend invoking this every
+	/// 30 seconds, along with [`Self::rebroadcast_pending_claims`].
+	pub fn rebroadcast_pending_transactions(&self) {
+		self.broadcaster.rebroadcast_pending_transactions();
+	}
+
+	/// Sets the thresholds past which the [`Persist::persistence_health`] reported by our
+	/// persister is considered unhealthy, enabling [`Self::check_persistence_health`]. Passing
+	/// `None` disables the check again.
+	pub fn set_persistence_health_thresholds(&self, thresholds: Option<PersistenceHealthThresholds>) {
+		*self.persistence_health_thresholds.lock().unwrap() = thresholds;
+	}
+
+	/// Queries [`Persist::persistence_health`] and generates an [`Event::PersistenceHealth`] if
+	/// persistence became unhealthy according to the thresholds set via
+	/// [`Self::set_persistence_health_thresholds`], or recovered after having been unhealthy.
+	///
+	/// This gives the user a chance to stop accepting new HTLCs before persistence actually fails
+	/// and channels have to be force-closed. We recommend invoking this every 30 seconds, along
+	/// with [`Self::rebroadcast_pending_claims`].
+	pub fn check_persistence_health(&self) {
+		let thresholds = match *self.persistence_health_thresholds.lock().unwrap() {
+			Some(thresholds) => thresholds,
+			None => return,
+		};
+		let health = match self.persister.persistence_health() {
+			Some(health) => health,
+			None => return,
+		};
+		let is_healthy = health.is_healthy(&thresholds);
+		if self.persistence_healthy.swap(is_healthy, Ordering::AcqRel) == is_healthy { return; }
+		if is_healthy {
+			log_info!(self.logger, "Persistence recovered: {:?}", health);
+		} else {
+			log_error!(self.logger, "Persistence crossed a health threshold: {:?}", health);
+		}
+		self.pending_events.lock().unwrap().push(Event::PersistenceHealth { health, is_healthy });
+		self.event_notifier.notify();
+	}
 }
 
 impl<ChannelSigner: WriteableEcdsaChannelSigner, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref>
@@ -774,6 +854,12 @@ where C::Target: chain::Filter,
 		}
 		pending_monitor_events
 	}
+
+	fn watch_onchain_fallback(&self, script_pubkey: &Script) {
+		if let Some(ref chain_source) = self.chain_source {
+			chain_source.register_script(script_pubkey);
+		}
+	}
 }
 
 impl<ChannelSigner: WriteableEcdsaChannelSigner, C: Deref, T: Deref, F: Deref, L: Deref, P: Deref> events::EventsProvider for ChainMonitor<ChannelSigner, C, T, F, L, P>
@@ -783,7 +869,9 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, C: Deref, T: Deref, F: Deref, L
 	      L::Target: Logger,
 	      P::Target: Persist<ChannelSigner>,
 {
-	/// Processes [`SpendableOutputs`] events produced from each [`ChannelMonitor`] upon maturity.
+	/// Processes [`SpendableOutputs`] events produced from each [`ChannelMonitor`] upon maturity,
+	/// as well as any [`PersistenceHealth`] events generated by
+	/// [`ChainMonitor::check_persistence_health`].
 	///
 	/// For channels featuring anchor outputs, this method will also process [`BumpTransaction`]
 	/// events produced from each [`ChannelMonitor`] while there is a balance to claim onchain
@@ -796,7 +884,12 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, C: Deref, T: Deref, F: Deref, L
 	///
 	/// [`SpendableOutputs`]: events::Event::SpendableOutputs
 	/// [`BumpTransaction`]: events::Event::BumpTransaction
+	/// [`PersistenceHealth`]: events::Event::PersistenceHealth
 	fn process_pending_events<H: Deref>(&self, handler: H) where H::Target: EventHandler {
+		let pending_events = core::mem::take(&mut *self.pending_events.lock().unwrap());
+		for event in pending_events {
+			handler.handle_event(event);
+		}
 		for monitor_state in self.monitors.read().unwrap().values() {
 			monitor_state.monitor.process_pending_events(&handler);
 		}
@@ -815,6 +908,8 @@ mod tests {
 	use crate::ln::functional_test_utils::*;
 	use crate::ln::msgs::ChannelMessageHandler;
 	use crate::util::errors::APIError;
+	use crate::util::persist::{PersistenceHealthStatus, PersistenceHealthThresholds};
+	use core::time::Duration;
 
 	#[test]
 	fn test_async_ooo_offchain_updates() {
@@ -1016,4 +1111,44 @@ mod tests {
 		check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Failed to persist ChannelMonitor update during chain sync".to_string() });
 		check_added_monitors!(nodes[0], 1);
 	}
-}
+
+	#[test]
+	fn test_persistence_health_events() {
+		// Test that we generate an `Event::PersistenceHealth` whenever the health reported by the
+		// persister crosses one of the configured thresholds, but not on every check.
+		let chanmon_cfgs = create_chanmon_cfgs(1);
+		let node_cfgs = create_node_cfgs(1, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(1, &node_cfgs, &[None]);
+		let nodes = create_network(1, &node_cfgs, &node_chanmgrs);
+		let chain_monitor = &nodes[0].chain_monitor.chain_monitor;
+
+		let thresholds = PersistenceHealthThresholds::default();
+		let healthy = PersistenceHealthStatus {
+			free_space_bytes: Some(thresholds.min_free_space_bytes * 2),
+			write_latency_p50: Some(Duration::from_millis(1)),
+			write_latency_p99: Some(Duration::from_millis(10)),
+			last_error: None,
+		};
+		let mut low_space = healthy.clone();
+		low_space.free_space_bytes = Some(thresholds.min_free_space_bytes / 2);
+		*chanmon_cfgs[0].persister.health.lock().unwrap() = Some(low_space.clone());
+
+		// Without thresholds, nothing is checked.
+		chain_monitor.check_persistence_health();
+		assert!(chain_monitor.get_and_clear_pending_events().is_empty());
+
+		chain_monitor.set_persistence_health_thresholds(Some(thresholds));
+		chain_monitor.check_persistence_health();
+		assert_eq!(chain_monitor.get_and_clear_pending_events(),
+			vec![Event::PersistenceHealth { health: low_space, is_healthy: false }]);
+		chain_monitor.check_persistence_health();
+		assert!(chain_monitor.get_and_clear_pending_events().is_empty());
+
+		*chanmon_cfgs[0].persister.health.lock().unwrap() = Some(healthy.clone());
+		chain_monitor.check_persistence_health();
+		assert_eq!(chain_monitor.get_and_clear_pending_events(),
+			vec![Event::PersistenceHealth { health: healthy, is_healthy: true }]);
+		chain_monitor.check_persistence_health();
+		assert!(chain_monitor.get_and_clear_pending_events().is_empty());
+	}
+}
\ No newline at end of file
@@ -35,19 +35,21 @@ use bitcoin::secp256k1;
 
 use crate::ln::{PaymentHash, PaymentPreimage};
 use crate::ln::msgs::DecodeError;
+use crate::ln::features::ChannelTypeFeatures;
 use crate::ln::chan_utils;
-use crate::ln::chan_utils::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, HTLCClaim, ChannelTransactionParameters, HolderCommitmentTransaction};
+use crate::ln::chan_utils::{CounterpartyCommitmentSecrets, HTLCOutputInCommitment, HTLCClaim, ChannelTransactionParameters, HolderCommitmentTransaction, MAX_HTLCS};
 use crate::ln::channelmanager::{HTLCSource, SentHTLCId};
 use crate::chain;
 use crate::chain::{BestBlock, WatchedOutput};
-use crate::chain::chaininterface::{BroadcasterInterface, FeeEstimator, LowerBoundedFeeEstimator};
+use crate::chain::chaininterface::{BroadcasterInterface, FeeEstimator, LowerBoundedFeeEstimator, TransactionMetadata, TransactionType, fee_for_weight};
 use crate::chain::transaction::{OutPoint, TransactionData};
-use crate::sign::{SpendableOutputDescriptor, StaticPaymentOutputDescriptor, DelayedPaymentOutputDescriptor, WriteableEcdsaChannelSigner, SignerProvider, EntropySource};
+use crate::chain::watchtower::{JusticeBlob, JusticeData, PresignedJustice, to_local_justice_tx_weight};
+use crate::sign::{SpendableOutputDescriptor, StaticPaymentOutputDescriptor, DelayedPaymentOutputDescriptor, WriteableEcdsaChannelSigner, SignerProvider, EntropySource, NodeSigner};
 use crate::chain::onchaintx::{ClaimEvent, OnchainTxHandler};
 use crate::chain::package::{CounterpartyOfferedHTLCOutput, CounterpartyReceivedHTLCOutput, HolderFundingOutput, HolderHTLCOutput, PackageSolvingData, PackageTemplate, RevokedOutput, RevokedHTLCOutput};
 use crate::chain::Filter;
 use crate::util::logger::Logger;
-use crate::util::ser::{Readable, ReadableArgs, RequiredWrapper, MaybeReadable, UpgradableRequired, Writer, Writeable, U48};
+use crate::util::ser::{Readable, ReadableArgs, RequiredWrapper, MaybeReadable, UpgradableRequired, Writer, Writeable, U48, check_collection_len, check_collection_len_within};
 use crate::util::byte_utils;
 use crate::events::{Event, EventHandler};
 use crate::events::bump_transaction::{ChannelDerivationParameters, AnchorDescriptor, HTLCDescriptor, BumpTransactionEvent};
@@ -304,9 +306,11 @@ impl Readable for CounterpartyCommitmentParameters {
 			// Versions prior to 0.0.100 had some per-HTLC state stored here, which is no longer
 			// used. Read it for compatibility.
 			let per_htlc_len: u64 = Readable::read(r)?;
+			check_collection_len(per_htlc_len)?;
 			for _  in 0..per_htlc_len {
 				let _txid: Txid = Readable::read(r)?;
 				let htlcs_count: u64 = Readable::read(r)?;
+				check_collection_len_within(htlcs_count, 2 * MAX_HTLCS as u64)?;
 				for _ in 0..htlcs_count {
 					let _htlc: HTLCOutputInCommitment = Readable::read(r)?;
 				}
@@ -375,6 +379,28 @@ impl OnchainEventEntry {
 /// This was added as an `Option` in 0.0.110.
 type CommitmentTxCounterpartyOutputInfo = Option<(u32, u64)>;
 
+/// A cooperatively signed transaction releasing a contract's collateral output, which we keep
+/// rebroadcasting until some spend of the collateral output reaches [`ANTI_REORG_DELAY`]
+/// confirmations.
+#[derive(Clone, PartialEq, Eq)]
+struct CollateralRelease {
+	transaction: Transaction,
+	/// The txid and height of the confirmed transaction spending the collateral output, if any.
+	spend_confirmation: Option<(Txid, u32)>,
+}
+
+impl CollateralRelease {
+	fn is_spent_by(&self, tx: &Transaction) -> bool {
+		tx.input.iter().any(|input| self.transaction.input.iter()
+			.any(|release_input| release_input.previous_output == input.previous_output))
+	}
+}
+
+impl_writeable_tlv_based!(CollateralRelease, {
+	(0, transaction, required),
+	(2, spend_confirmation, option),
+});
+
 /// Upon discovering of some classes of onchain tx by ChannelMonitor, we may have to take actions on it
 /// once they mature to enough confirmations (ANTI_REORG_DELAY)
 #[derive(PartialEq, Eq)]
@@ -502,6 +528,7 @@ pub(crate) enum ChannelMonitorUpdateStep {
 		htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>,
 		commitment_number: u64,
 		their_per_commitment_point: PublicKey,
+		revokeable_output: Option<(u32, u64)>,
 	},
 	PaymentPreimage {
 		payment_preimage: PaymentPreimage,
@@ -520,6 +547,20 @@ pub(crate) enum ChannelMonitorUpdateStep {
 	ShutdownScript {
 		scriptpubkey: Script,
 	},
+	/// The channel type was renegotiated. The new type applies starting with the next holder
+	/// commitment transaction provided via [`Self::LatestHolderCommitmentTXInfo`].
+	ChannelTypeUpgraded {
+		channel_type: ChannelTypeFeatures,
+	},
+	/// A transaction releasing a contract's collateral output which should be rebroadcast until
+	/// the collateral output is spent.
+	CollateralRelease {
+		transaction: Transaction,
+	},
+	/// Our counterparty rotated its node identity key and is now known by the given node id.
+	CounterpartyNodeIdUpdated {
+		counterparty_node_id: PublicKey,
+	},
 }
 
 impl ChannelMonitorUpdateStep {
@@ -531,6 +572,9 @@ impl ChannelMonitorUpdateStep {
 			ChannelMonitorUpdateStep::CommitmentSecret { .. } => "CommitmentSecret",
 			ChannelMonitorUpdateStep::ChannelForceClosed { .. } => "ChannelForceClosed",
 			ChannelMonitorUpdateStep::ShutdownScript { .. } => "ShutdownScript",
+			ChannelMonitorUpdateStep::ChannelTypeUpgraded { .. } => "ChannelTypeUpgraded",
+			ChannelMonitorUpdateStep::CollateralRelease { .. } => "CollateralRelease",
+			ChannelMonitorUpdateStep::CounterpartyNodeIdUpdated { .. } => "CounterpartyNodeIdUpdated",
 		}
 	}
 }
@@ -544,6 +588,7 @@ impl_writeable_tlv_based_enum_upgradable!(ChannelMonitorUpdateStep,
 	},
 	(1, LatestCounterpartyCommitmentTXInfo) => {
 		(0, commitment_txid, required),
+		(1, revokeable_output, option),
 		(2, commitment_number, required),
 		(4, their_per_commitment_point, required),
 		(6, htlc_outputs, required_vec),
@@ -561,6 +606,15 @@ impl_writeable_tlv_based_enum_upgradable!(ChannelMonitorUpdateStep,
 	(5, ShutdownScript) => {
 		(0, scriptpubkey, required),
 	},
+	(6, ChannelTypeUpgraded) => {
+		(0, channel_type, required),
+	},
+	(7, CollateralRelease) => {
+		(0, transaction, required),
+	},
+	(9, CounterpartyNodeIdUpdated) => {
+		(0, counterparty_node_id, required),
+	},
 );
 
 /// Details about the balance(s) available for spending once the channel appears on chain.
@@ -681,6 +735,84 @@ impl Balance {
 	}
 }
 
+/// A summary of our claimable balance in a single channel at a given block height, built from
+/// [`ChannelMonitor`] data via [`ChannelMonitor::get_balance_statement`].
+///
+/// Once signed by our [`NodeSigner`] via [`BalanceStatement::sign`], this allows a third
+/// party, such as an auditor, to verify that we attest to holding the given balance without
+/// granting them access to any private keys. The included transaction ids allow the auditor to
+/// cross-check the statement against the chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BalanceStatement {
+	/// The funding transaction output of the channel.
+	pub funding_txo: OutPoint,
+	/// The node id of our counterparty in the channel, if known.
+	pub counterparty_node_id: Option<PublicKey>,
+	/// The height of the block the statement was generated at.
+	pub block_height: u32,
+	/// The hash of the block the statement was generated at.
+	pub block_hash: BlockHash,
+	/// The sum of [`Balance::claimable_amount_satoshis`] across all [`Balance`]s in the channel.
+	pub claimable_balance_satoshis: u64,
+	/// The txid of our current commitment transaction.
+	pub holder_commitment_txid: Txid,
+	/// The commitment number of our current commitment transaction. Note that, as in the BOLTs,
+	/// commitment numbers count down from 2^48 - 1.
+	pub holder_commitment_number: u64,
+	/// The txid of our counterparty's current commitment transaction, if one has been provided.
+	pub counterparty_commitment_txid: Option<Txid>,
+	/// The id of the latest [`ChannelMonitorUpdate`] applied to the monitor.
+	pub latest_update_id: u64,
+}
+
+impl BalanceStatement {
+	/// Gets the canonical message which is signed by [`Self::sign`].
+	pub fn to_message(&self) -> String {
+		format!("LDK balance statement v1\n\
+			funding_txo: {}:{}\n\
+			counterparty_node_id: {}\n\
+			block_height: {}\n\
+			block_hash: {}\n\
+			claimable_balance_satoshis: {}\n\
+			holder_commitment_txid: {}\n\
+			holder_commitment_number: {}\n\
+			counterparty_commitment_txid: {}\n\
+			latest_update_id: {}",
+			self.funding_txo.txid, self.funding_txo.index,
+			self.counterparty_node_id.map_or("unknown".to_owned(), |pk| pk.to_string()),
+			self.block_height, self.block_hash, self.claimable_balance_satoshis,
+			self.holder_commitment_txid, self.holder_commitment_number,
+			self.counterparty_commitment_txid.map_or("none".to_owned(), |txid| txid.to_string()),
+			self.latest_update_id)
+	}
+
+	/// Signs this statement via [`NodeSigner::sign_message`], allowing it to be verified by any
+	/// party which knows our node id.
+	///
+	/// Fails if the [`NodeSigner`] does not support signing arbitrary messages.
+	pub fn sign<NS: Deref>(self, node_signer: NS) -> Result<SignedBalanceStatement, ()>
+	where NS::Target: NodeSigner {
+		let signature = node_signer.sign_message(self.to_message().as_bytes())?;
+		Ok(SignedBalanceStatement { statement: self, signature })
+	}
+}
+
+/// A [`BalanceStatement`] along with a signature over its [`BalanceStatement::to_message`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignedBalanceStatement {
+	/// The statement which was signed.
+	pub statement: BalanceStatement,
+	/// The zbase32-encoded recoverable signature over the statement's message.
+	pub signature: String,
+}
+
+impl SignedBalanceStatement {
+	/// Checks that the statement was signed by the node with the given node id.
+	pub fn verify(&self, node_id: &PublicKey) -> bool {
+		crate::util::message_signing::verify(self.statement.to_message().as_bytes(), &self.signature, node_id)
+	}
+}
+
 /// An HTLC which has been irrevocably resolved on-chain, and has reached ANTI_REORG_DELAY.
 #[derive(PartialEq, Eq)]
 struct IrrevocablyResolvedHTLC {
@@ -872,6 +1004,29 @@ pub(crate) struct ChannelMonitorImpl<Signer: WriteableEcdsaChannelSigner> {
 	/// spending CSV for revocable outputs).
 	htlcs_resolved_on_chain: Vec<IrrevocablyResolvedHTLC>,
 
+	/// A channel type we've been told about via [`ChannelMonitorUpdateStep::ChannelTypeUpgraded`]
+	/// which we'll switch to once we're given the first holder commitment transaction using it.
+	/// Until then, our latest holder commitment transaction still uses the previous type.
+	pending_channel_type_upgrade: Option<ChannelTypeFeatures>,
+
+	/// For each upgrade of the channel type, the first counterparty commitment number built using
+	/// the new type along with the type used by all prior counterparty commitment transactions,
+	/// oldest upgrade first. Used to claim outputs of commitment transactions which predate an
+	/// upgrade using the scripts they were actually built with.
+	counterparty_commitment_channel_types: Vec<(u64, ChannelTypeFeatures)>,
+
+	/// Collateral releases given to us via [`ChannelMonitorUpdateStep::CollateralRelease`] which
+	/// we rebroadcast until the collateral output they spend is irrevocably spent.
+	collateral_releases: Vec<CollateralRelease>,
+
+	/// The txid of the counterparty commitment transaction which was most recently revoked, used
+	/// to build [`JusticeBlob`]s for it.
+	last_revoked_counterparty_commitment_txid: Option<Txid>,
+	/// The index and value of the revokeable `to_local` output of the counterparty commitment
+	/// transactions we may still build a [`JusticeBlob`] for, needed to pre-sign the justice
+	/// transaction spending it.
+	counterparty_revokeable_outputs: HashMap<Txid, (u32, u64)>,
+
 	/// The set of `SpendableOutput` events which we have already passed upstream to be claimed.
 	/// These are tracked explicitly to ensure that we don't generate the same events redundantly
 	/// if users duplicatively confirm old transactions. Specifically for transactions claiming a
@@ -1078,6 +1233,11 @@ impl<Signer: WriteableEcdsaChannelSigner> Writeable for ChannelMonitorImpl<Signe
 			(11, self.confirmed_commitment_tx_counterparty_output, option),
 			(13, self.spendable_txids_confirmed, required_vec),
 			(15, self.counterparty_fulfilled_htlcs, required),
+			(16, self.pending_channel_type_upgrade, option),
+			(17, self.last_revoked_counterparty_commitment_txid, option),
+			(18, self.counterparty_commitment_channel_types, optional_vec),
+			(19, self.collateral_releases, optional_vec),
+			(21, self.counterparty_revokeable_outputs, required),
 		});
 
 		Ok(())
@@ -1170,7 +1330,7 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitor<Signer> {
 
 		let onchain_tx_handler =
 			OnchainTxHandler::new(destination_script.clone(), keys,
-			channel_parameters.clone(), initial_holder_commitment_tx, secp_ctx);
+			channel_parameters.clone(), initial_holder_commitment_tx, counterparty_node_id, secp_ctx);
 
 		let mut outputs_to_watch = HashMap::new();
 		outputs_to_watch.insert(funding_info.0.txid, vec![(funding_info.0.index as u32, funding_info.1.clone())]);
@@ -1224,6 +1384,11 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitor<Signer> {
 			funding_spend_confirmed: None,
 			confirmed_commitment_tx_counterparty_output: None,
 			htlcs_resolved_on_chain: Vec::new(),
+			pending_channel_type_upgrade: None,
+			counterparty_commitment_channel_types: Vec::new(),
+			collateral_releases: Vec::new(),
+			last_revoked_counterparty_commitment_txid: None,
+			counterparty_revokeable_outputs: HashMap::new(),
 			spendable_txids_confirmed: Vec::new(),
 
 			best_block,
@@ -1246,10 +1411,11 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitor<Signer> {
 		htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>,
 		commitment_number: u64,
 		their_per_commitment_point: PublicKey,
+		revokeable_output: Option<(u32, u64)>,
 		logger: &L,
 	) where L::Target: Logger {
 		self.inner.lock().unwrap().provide_latest_counterparty_commitment_tx(
-			txid, htlc_outputs, commitment_number, their_per_commitment_point, logger)
+			txid, htlc_outputs, commitment_number, their_per_commitment_point, revokeable_output, logger)
 	}
 
 	#[cfg(test)]
@@ -1396,6 +1562,23 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitor<Signer> {
 		self.inner.lock().unwrap().counterparty_node_id
 	}
 
+	/// Returns a [`JusticeBlob`] for the counterparty commitment transaction revoked by the given
+	/// update, if any, which may be handed to a watchtower.
+	///
+	/// If the revoked commitment transaction has a `to_local` output, the blob includes a justice
+	/// transaction spending it to our destination script at `feerate_per_kw`, pre-signed with the
+	/// revocation key so the tower can broadcast it as-is.
+	///
+	/// This is intended to be called from [`Persist::update_persisted_channel`] with the update
+	/// being persisted, once it has been applied to this monitor. Returns an empty `Vec` for updates
+	/// which don't revoke a counterparty commitment transaction, as well as for updates which have
+	/// since been superseded by a later revocation.
+	///
+	/// [`Persist::update_persisted_channel`]: crate::chain::chainmonitor::Persist::update_persisted_channel
+	pub fn justice_blobs_from_update(&self, update: &ChannelMonitorUpdate, feerate_per_kw: u32) -> Vec<JusticeBlob> {
+		self.inner.lock().unwrap().justice_blobs_from_update(update, feerate_per_kw)
+	}
+
 	/// Used by ChannelManager deserialization to broadcast the latest holder state if its copy of
 	/// the Channel was out-of-date.
 	///
@@ -1552,6 +1735,8 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitor<Signer> {
 			.iter()
 			.map(|entry| (entry.txid, entry.block_hash))
 			.chain(inner.onchain_tx_handler.get_relevant_txids().into_iter())
+			.chain(inner.collateral_releases.iter()
+				.filter_map(|release| release.spend_confirmation.map(|(txid, _)| (txid, None))))
 			.collect();
 		txids.sort_unstable();
 		txids.dedup();
@@ -1564,6 +1749,31 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitor<Signer> {
 		self.inner.lock().unwrap().best_block.clone()
 	}
 
+	/// Gets a [`BalanceStatement`] summarizing our claimable balance in this channel.
+	///
+	/// Because balances are only tracked as of the monitor's current best block, this fails if
+	/// `block_height` does not match [`Self::current_best_block`]. This ensures that statements
+	/// for several channels generated at the same height describe a consistent view of the chain.
+	pub fn get_balance_statement(&self, block_height: u32) -> Result<BalanceStatement, ()> {
+		let claimable_balance_satoshis = self.get_claimable_balances().iter()
+			.map(|balance| balance.claimable_amount_satoshis()).sum();
+		let us = self.inner.lock().unwrap();
+		if us.best_block.height() != block_height {
+			return Err(());
+		}
+		Ok(BalanceStatement {
+			funding_txo: us.funding_info.0,
+			counterparty_node_id: us.counterparty_node_id,
+			block_height,
+			block_hash: us.best_block.block_hash(),
+			claimable_balance_satoshis,
+			holder_commitment_txid: us.current_holder_commitment_tx.txid,
+			holder_commitment_number: us.current_holder_commitment_number,
+			counterparty_commitment_txid: us.current_counterparty_commitment_txid,
+			latest_update_id: us.latest_update_id,
+		})
+	}
+
 	/// Triggers rebroadcasts/fee-bumps of pending claims from a force-closed channel. This is
 	/// crucial in preventing certain classes of pinning attacks, detecting substantial mempool
 	/// feerate changes between blocks, and ensuring reliability if broadcasting fails. We recommend
@@ -1583,6 +1793,7 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitor<Signer> {
 		inner.onchain_tx_handler.rebroadcast_pending_claims(
 			current_height, &broadcaster, &fee_estimator, &logger,
 		);
+		inner.broadcast_collateral_releases(&broadcaster, &logger);
 	}
 }
 
@@ -2177,6 +2388,7 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		// Prune HTLCs from the previous counterparty commitment tx so we don't generate failure/fulfill
 		// events for now-revoked/fulfilled HTLCs.
 		if let Some(txid) = self.prev_counterparty_commitment_txid.take() {
+			self.last_revoked_counterparty_commitment_txid = Some(txid);
 			if self.current_counterparty_commitment_txid.unwrap() != txid {
 				let cur_claimables = self.counterparty_claimable_outpoints.get(
 					&self.current_counterparty_commitment_txid.unwrap()).unwrap();
@@ -2232,7 +2444,7 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		Ok(())
 	}
 
-	pub(crate) fn provide_latest_counterparty_commitment_tx<L: Deref>(&mut self, txid: Txid, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>, commitment_number: u64, their_per_commitment_point: PublicKey, logger: &L) where L::Target: Logger {
+	pub(crate) fn provide_latest_counterparty_commitment_tx<L: Deref>(&mut self, txid: Txid, htlc_outputs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)>, commitment_number: u64, their_per_commitment_point: PublicKey, revokeable_output: Option<(u32, u64)>, logger: &L) where L::Target: Logger {
 		// TODO: Encrypt the htlc_outputs data with the single-hash of the commitment transaction
 		// so that a remote monitor doesn't learn anything unless there is a malicious close.
 		// (only maybe, sadly we cant do the same for local info, as we need to be aware of
@@ -2245,6 +2457,12 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		self.prev_counterparty_commitment_txid = self.current_counterparty_commitment_txid.take();
 		self.current_counterparty_commitment_txid = Some(txid);
 		self.counterparty_claimable_outpoints.insert(txid, htlc_outputs.clone());
+		if let Some(output) = revokeable_output {
+			self.counterparty_revokeable_outputs.insert(txid, output);
+		}
+		let tracked_txids = [self.current_counterparty_commitment_txid, self.prev_counterparty_commitment_txid,
+			self.last_revoked_counterparty_commitment_txid];
+		self.counterparty_revokeable_outputs.retain(|txid, _| tracked_txids.contains(&Some(*txid)));
 		self.current_counterparty_commitment_number = commitment_number;
 		//TODO: Merge this into the other per-counterparty-transaction output storage stuff
 		match self.their_cur_per_commitment_points {
@@ -2340,6 +2558,14 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 			to_self_value_sat: holder_commitment_tx.to_broadcaster_value_sat(),
 			feerate_per_kw: trusted_tx.feerate_per_kw(),
 		};
+		if !self.holder_tx_signed {
+			if let Some(channel_type) = self.pending_channel_type_upgrade.take() {
+				// This is the first holder commitment transaction using the new channel type, so
+				// switch over before handing it to the `OnchainTxHandler`.
+				self.onchain_tx_handler.channel_transaction_parameters.channel_type_features = channel_type;
+				self.onchain_tx_handler.signer.provide_channel_parameters(&self.onchain_tx_handler.channel_transaction_parameters);
+			}
+		}
 		self.onchain_tx_handler.provide_latest_holder_tx(holder_commitment_tx);
 		mem::swap(&mut new_holder_commitment_tx, &mut self.current_holder_commitment_tx);
 		self.prev_holder_signed_commitment_tx = Some(new_holder_commitment_tx);
@@ -2411,17 +2637,43 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		}
 	}
 
+	fn broadcast_collateral_releases<B: Deref, L: Deref>(&self, broadcaster: &B, logger: &L)
+		where B::Target: BroadcasterInterface,
+					L::Target: Logger,
+	{
+		let txs = self.collateral_releases.iter()
+			.filter(|release| release.spend_confirmation.is_none())
+			.map(|release| {
+				log_info!(logger, "Broadcasting collateral release {}", log_tx!(release.transaction));
+				(&release.transaction, TransactionMetadata {
+					channel_id: Some(self.funding_info.0.to_channel_id()),
+					counterparty_node_id: self.counterparty_node_id,
+					transaction_type: TransactionType::ContractSettlement,
+				})
+			})
+			.collect::<Vec<_>>();
+		if !txs.is_empty() {
+			broadcaster.broadcast_transactions_with_meta(&txs);
+		}
+	}
+
 	pub(crate) fn broadcast_latest_holder_commitment_txn<B: Deref, L: Deref>(&mut self, broadcaster: &B, logger: &L)
 		where B::Target: BroadcasterInterface,
 					L::Target: Logger,
 	{
 		let commit_txs = self.get_latest_holder_commitment_txn(logger);
 		let mut txs = vec![];
-		for tx in commit_txs.iter() {
+		for (idx, tx) in commit_txs.iter().enumerate() {
 			log_info!(logger, "Broadcasting local {}", log_tx!(tx));
-			txs.push(tx);
+			// The commitment transaction comes first, followed by any HTLC transactions spending it.
+			let transaction_type = if idx == 0 { TransactionType::UnilateralClose } else { TransactionType::Claim };
+			txs.push((tx, TransactionMetadata {
+				channel_id: Some(self.funding_info.0.to_channel_id()),
+				counterparty_node_id: self.counterparty_node_id,
+				transaction_type,
+			}));
 		}
-		broadcaster.broadcast_transactions(&txs);
+		broadcaster.broadcast_transactions_with_meta(&txs);
 		self.pending_monitor_events.push(MonitorEvent::CommitmentTxConfirmed(self.funding_info.0));
 	}
 
@@ -2477,9 +2729,9 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 						ret = Err(());
 					}
 				}
-				ChannelMonitorUpdateStep::LatestCounterpartyCommitmentTXInfo { commitment_txid, htlc_outputs, commitment_number, their_per_commitment_point } => {
+				ChannelMonitorUpdateStep::LatestCounterpartyCommitmentTXInfo { commitment_txid, htlc_outputs, commitment_number, their_per_commitment_point, revokeable_output } => {
 					log_trace!(logger, "Updating ChannelMonitor with latest counterparty commitment transaction info");
-					self.provide_latest_counterparty_commitment_tx(*commitment_txid, htlc_outputs.clone(), *commitment_number, *their_per_commitment_point, logger)
+					self.provide_latest_counterparty_commitment_tx(*commitment_txid, htlc_outputs.clone(), *commitment_number, *their_per_commitment_point, *revokeable_output, logger)
 				},
 				ChannelMonitorUpdateStep::PaymentPreimage { payment_preimage } => {
 					log_trace!(logger, "Updating ChannelMonitor with payment preimage");
@@ -2546,6 +2798,31 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 						panic!("Attempted to replace shutdown script {} with {}", shutdown_script, scriptpubkey);
 					}
 				},
+				ChannelMonitorUpdateStep::ChannelTypeUpgraded { channel_type } => {
+					log_trace!(logger, "Updating ChannelMonitor with upgraded channel type {}", channel_type);
+					if self.lockdown_from_offchain { panic!(); }
+					// The counterparty commitment transaction provided along with this update is the
+					// first one using the new type.
+					let previous_type = self.pending_channel_type_upgrade.take()
+						.unwrap_or_else(|| self.onchain_tx_handler.channel_type_features().clone());
+					self.counterparty_commitment_channel_types.push(
+						(self.current_counterparty_commitment_number - 1, previous_type));
+					self.pending_channel_type_upgrade = Some(channel_type.clone());
+				},
+				ChannelMonitorUpdateStep::CollateralRelease { transaction } => {
+					log_trace!(logger, "Updating ChannelMonitor with collateral release {}", transaction.txid());
+					if !self.collateral_releases.iter().any(|release| release.transaction == *transaction) {
+						self.collateral_releases.push(CollateralRelease {
+							transaction: transaction.clone(), spend_confirmation: None,
+						});
+					}
+					self.broadcast_collateral_releases(broadcaster, logger);
+				},
+				ChannelMonitorUpdateStep::CounterpartyNodeIdUpdated { counterparty_node_id } => {
+					log_trace!(logger, "Updating ChannelMonitor with counterparty node id {}", counterparty_node_id);
+					self.counterparty_node_id = Some(*counterparty_node_id);
+					self.onchain_tx_handler.counterparty_node_id = Some(*counterparty_node_id);
+				},
 			}
 		}
 
@@ -2666,6 +2943,56 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		self.commitment_secrets.get_min_seen_secret()
 	}
 
+	fn justice_blobs_from_update(&self, update: &ChannelMonitorUpdate, feerate_per_kw: u32) -> Vec<JusticeBlob> {
+		let mut blobs = Vec::new();
+		let commitment_txid = match self.last_revoked_counterparty_commitment_txid {
+			Some(txid) => txid,
+			None => return blobs,
+		};
+		for step in update.updates.iter() {
+			if let ChannelMonitorUpdateStep::CommitmentSecret { idx, secret } = step {
+				// Only the latest revocation is tracked, so skip any older ones.
+				if *idx != self.get_min_seen_secret() { continue; }
+				let per_commitment_key = match SecretKey::from_slice(secret) {
+					Ok(key) => key,
+					Err(_) => continue,
+				};
+				let secp_ctx = &self.onchain_tx_handler.secp_ctx;
+				let per_commitment_point = PublicKey::from_secret_key(secp_ctx, &per_commitment_key);
+				let revoked_htlcs = self.counterparty_claimable_outpoints.get(&commitment_txid)
+					.map(|htlcs| htlcs.iter().map(|(htlc, _)| htlc.clone()).collect())
+					.unwrap_or_else(Vec::new);
+				let mut data = JusticeData {
+					commitment_number: *idx,
+					per_commitment_secret: *secret,
+					revocation_pubkey: chan_utils::derive_public_revocation_key(
+						secp_ctx, &per_commitment_point, &self.holder_revocation_basepoint),
+					broadcaster_delayed_payment_key: chan_utils::derive_public_key(
+						secp_ctx, &per_commitment_point,
+						&self.counterparty_commitment_params.counterparty_delayed_payment_base_key),
+					to_self_delay: self.counterparty_commitment_params.on_counterparty_tx_csv,
+					sweep_script: self.destination_script.clone(),
+					revoked_htlcs,
+					to_local_justice: None,
+				};
+				if let Some(&(output_index, value)) = self.counterparty_revokeable_outputs.get(&commitment_txid) {
+					let fee = fee_for_weight(feerate_per_kw, to_local_justice_tx_weight(&data.sweep_script));
+					let sweep_value_sat = value.saturating_sub(fee);
+					if sweep_value_sat >= data.sweep_script.dust_value().to_sat() {
+						let justice_tx = data.unsigned_to_local_justice_tx(&commitment_txid, output_index, sweep_value_sat);
+						if let Ok(signature) = self.onchain_tx_handler.signer.sign_justice_revoked_output(
+							&justice_tx, 0, value, &per_commitment_key, secp_ctx
+						) {
+							data.to_local_justice = Some(PresignedJustice { output_index, sweep_value_sat, signature });
+						}
+					}
+				}
+				blobs.push(JusticeBlob::encrypt(&commitment_txid, &data));
+			}
+		}
+		blobs
+	}
+
 	pub(crate) fn get_cur_counterparty_commitment_number(&self) -> u64 {
 		self.current_counterparty_commitment_number
 	}
@@ -2682,6 +3009,20 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 	/// Returns packages to claim the revoked output(s), as well as additional outputs to watch and
 	/// general information about the output that is to the counterparty in the commitment
 	/// transaction.
+	/// Returns the channel type the counterparty commitment transaction with the given commitment
+	/// number was built with, accounting for channel type upgrades since.
+	fn counterparty_commitment_channel_type(&self, commitment_number: u64) -> &ChannelTypeFeatures {
+		// Commitment numbers count down, so a commitment transaction predates an upgrade if its
+		// number is higher than the first one built after it.
+		self.counterparty_commitment_channel_types.iter()
+			.find(|(first_upgraded_commitment_number, _)| commitment_number > *first_upgraded_commitment_number)
+			.map(|(_, channel_type)| channel_type)
+			.unwrap_or_else(|| match &self.pending_channel_type_upgrade {
+				Some(channel_type) => channel_type,
+				None => self.onchain_tx_handler.channel_type_features(),
+			})
+	}
+
 	fn check_spend_counterparty_transaction<L: Deref>(&mut self, tx: &Transaction, height: u32, block_hash: &BlockHash, logger: &L)
 		-> (Vec<PackageTemplate>, TransactionOutputs, CommitmentTxCounterpartyOutputInfo)
 	where L::Target: Logger {
@@ -2713,11 +3054,12 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 
 			let revokeable_redeemscript = chan_utils::get_revokeable_redeemscript(&revocation_pubkey, self.counterparty_commitment_params.on_counterparty_tx_csv, &delayed_key);
 			let revokeable_p2wsh = revokeable_redeemscript.to_v0_p2wsh();
+			let channel_type_features = self.counterparty_commitment_channel_type(commitment_number).clone();
 
 			// First, process non-htlc outputs (to_holder & to_counterparty)
 			for (idx, outp) in tx.output.iter().enumerate() {
 				if outp.script_pubkey == revokeable_p2wsh {
-					let revk_outp = RevokedOutput::build(per_commitment_point, self.counterparty_commitment_params.counterparty_delayed_payment_base_key, self.counterparty_commitment_params.counterparty_htlc_base_key, per_commitment_key, outp.value, self.counterparty_commitment_params.on_counterparty_tx_csv, self.onchain_tx_handler.channel_type_features().supports_anchors_zero_fee_htlc_tx());
+					let revk_outp = RevokedOutput::build(per_commitment_point, self.counterparty_commitment_params.counterparty_delayed_payment_base_key, self.counterparty_commitment_params.counterparty_htlc_base_key, per_commitment_key, outp.value, self.counterparty_commitment_params.on_counterparty_tx_csv, channel_type_features.supports_anchors_zero_fee_htlc_tx());
 					let justice_package = PackageTemplate::build_package(commitment_txid, idx as u32, PackageSolvingData::RevokedOutput(revk_outp), height + self.counterparty_commitment_params.on_counterparty_tx_csv as u32, height);
 					claimable_outpoints.push(justice_package);
 					to_counterparty_output_info =
@@ -2735,7 +3077,7 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 							return (claimable_outpoints, (commitment_txid, watch_outputs),
 								to_counterparty_output_info);
 						}
-						let revk_htlc_outp = RevokedHTLCOutput::build(per_commitment_point, self.counterparty_commitment_params.counterparty_delayed_payment_base_key, self.counterparty_commitment_params.counterparty_htlc_base_key, per_commitment_key, htlc.amount_msat / 1000, htlc.clone(), &self.onchain_tx_handler.channel_transaction_parameters.channel_type_features);
+						let revk_htlc_outp = RevokedHTLCOutput::build(per_commitment_point, self.counterparty_commitment_params.counterparty_delayed_payment_base_key, self.counterparty_commitment_params.counterparty_htlc_base_key, per_commitment_key, htlc.amount_msat / 1000, htlc.clone(), &channel_type_features);
 						let justice_package = PackageTemplate::build_package(commitment_txid, transaction_output_index, PackageSolvingData::RevokedHTLCOutput(revk_htlc_outp), htlc.cltv_expiry, height);
 						claimable_outpoints.push(justice_package);
 					}
@@ -2837,6 +3179,7 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 			}
 		}
 
+		let channel_type_features = self.counterparty_commitment_channel_type(commitment_number).clone();
 		for (_, &(ref htlc, _)) in htlc_outputs.iter().enumerate() {
 			if let Some(transaction_output_index) = htlc.transaction_output_index {
 				if let Some(transaction) = tx {
@@ -2853,13 +3196,13 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 							CounterpartyOfferedHTLCOutput::build(*per_commitment_point,
 								self.counterparty_commitment_params.counterparty_delayed_payment_base_key,
 								self.counterparty_commitment_params.counterparty_htlc_base_key,
-								preimage.unwrap(), htlc.clone(), self.onchain_tx_handler.channel_type_features().clone()))
+								preimage.unwrap(), htlc.clone(), channel_type_features.clone()))
 					} else {
 						PackageSolvingData::CounterpartyReceivedHTLCOutput(
 							CounterpartyReceivedHTLCOutput::build(*per_commitment_point,
 								self.counterparty_commitment_params.counterparty_delayed_payment_base_key,
 								self.counterparty_commitment_params.counterparty_htlc_base_key,
-								htlc.clone(), self.onchain_tx_handler.channel_type_features().clone()))
+								htlc.clone(), channel_type_features.clone()))
 					};
 					let counterparty_package = PackageTemplate::build_package(commitment_txid, transaction_output_index, counterparty_htlc_outp, htlc.cltv_expiry, 0);
 					claimable_outpoints.push(counterparty_package);
@@ -3116,6 +3459,11 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		} else if block_hash != self.best_block.block_hash() {
 			self.best_block = BestBlock::new(block_hash, height);
 			self.onchain_events_awaiting_threshold_conf.retain(|ref entry| entry.height <= height);
+			for release in self.collateral_releases.iter_mut() {
+				if release.spend_confirmation.map_or(false, |(_, spend_height)| spend_height > height) {
+					release.spend_confirmation = None;
+				}
+			}
 			self.onchain_tx_handler.block_disconnected(height + 1, broadcaster, fee_estimator, logger);
 			Vec::new()
 		} else { Vec::new() }
@@ -3135,6 +3483,15 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		F::Target: FeeEstimator,
 		L::Target: Logger,
 	{
+		for release in self.collateral_releases.iter_mut() {
+			if release.spend_confirmation.is_some() { continue; }
+			if let Some((_, tx)) = txdata.iter().find(|(_, tx)| release.is_spent_by(tx)) {
+				log_info!(logger, "Collateral released by {} spending {} confirmed at height {}",
+					tx.txid(), release.transaction.txid(), height);
+				release.spend_confirmation = Some((tx.txid(), height));
+			}
+		}
+
 		let txn_matched = self.filter_block(txdata);
 		for tx in &txn_matched {
 			let mut output_val = 0;
@@ -3285,6 +3642,12 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		log_trace!(logger, "Processing {} matched transactions for block at height {}.", txn_matched.len(), conf_height);
 		debug_assert!(self.best_block.height() >= conf_height);
 
+		let best_height = self.best_block.height();
+		self.collateral_releases.retain(|release| match release.spend_confirmation {
+			Some((_, spend_height)) => spend_height + ANTI_REORG_DELAY - 1 > best_height,
+			None => true,
+		});
+
 		let should_broadcast = self.should_broadcast_holder_commitment_txn(logger);
 		if should_broadcast {
 			let funding_outp = HolderFundingOutput::build(self.funding_redeemscript.clone(), self.channel_value_satoshis, self.onchain_tx_handler.channel_type_features().clone());
@@ -3427,6 +3790,11 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 		//- htlc update there as failure-trigger tx (revoked commitment tx, non-revoked commitment tx, HTLC-timeout tx) has been disconnected
 		//- maturing spendable output has transaction paying us has been disconnected
 		self.onchain_events_awaiting_threshold_conf.retain(|ref entry| entry.height < height);
+		for release in self.collateral_releases.iter_mut() {
+			if release.spend_confirmation.map_or(false, |(_, spend_height)| spend_height >= height) {
+				release.spend_confirmation = None;
+			}
+		}
 
 		let bounded_fee_estimator = LowerBoundedFeeEstimator::new(fee_estimator);
 		self.onchain_tx_handler.block_disconnected(height, broadcaster, &bounded_fee_estimator, logger);
@@ -3463,6 +3831,12 @@ impl<Signer: WriteableEcdsaChannelSigner> ChannelMonitorImpl<Signer> {
 
 		debug_assert!(!self.onchain_events_awaiting_threshold_conf.iter().any(|ref entry| entry.txid == *txid));
 
+		for release in self.collateral_releases.iter_mut() {
+			if release.spend_confirmation.map_or(false, |(spend_txid, _)| spend_txid == *txid) {
+				release.spend_confirmation = None;
+			}
+		}
+
 		self.onchain_tx_handler.transaction_unconfirmed(txid, broadcaster, fee_estimator, logger);
 	}
 
@@ -3992,6 +4366,7 @@ impl<'a, 'b, ES: EntropySource, SP: SignerProvider> ReadableArgs<(&'a ES, &'b SP
 		for _ in 0..counterparty_claimable_outpoints_len {
 			let txid: Txid = Readable::read(reader)?;
 			let htlcs_count: u64 = Readable::read(reader)?;
+			check_collection_len_within(htlcs_count, 2 * MAX_HTLCS as u64)?;
 			let mut htlcs = Vec::with_capacity(cmp::min(htlcs_count as usize, MAX_ALLOC_SIZE / 32));
 			for _ in 0..htlcs_count {
 				htlcs.push((read_htlc_in_commitment!(), <Option<HTLCSource> as Readable>::read(reader)?.map(|o: HTLCSource| Box::new(o))));
@@ -4087,7 +4462,7 @@ impl<'a, 'b, ES: EntropySource, SP: SignerProvider> ReadableArgs<(&'a ES, &'b SP
 				return Err(DecodeError::InvalidValue);
 			}
 		}
-		let onchain_tx_handler: OnchainTxHandler<SP::Signer> = ReadableArgs::read(
+		let mut onchain_tx_handler: OnchainTxHandler<SP::Signer> = ReadableArgs::read(
 			reader, (entropy_source, signer_provider, channel_value_satoshis, channel_keys_id)
 		)?;
 
@@ -4118,6 +4493,11 @@ impl<'a, 'b, ES: EntropySource, SP: SignerProvider> ReadableArgs<(&'a ES, &'b SP
 		let mut confirmed_commitment_tx_counterparty_output = None;
 		let mut spendable_txids_confirmed = Some(Vec::new());
 		let mut counterparty_fulfilled_htlcs = Some(HashMap::new());
+		let mut pending_channel_type_upgrade = None;
+		let mut counterparty_commitment_channel_types = Some(Vec::new());
+		let mut last_revoked_counterparty_commitment_txid = None;
+		let mut collateral_releases = Some(Vec::new());
+		let mut counterparty_revokeable_outputs = Some(HashMap::new());
 		read_tlv_fields!(reader, {
 			(1, funding_spend_confirmed, option),
 			(3, htlcs_resolved_on_chain, optional_vec),
@@ -4127,7 +4507,13 @@ impl<'a, 'b, ES: EntropySource, SP: SignerProvider> ReadableArgs<(&'a ES, &'b SP
 			(11, confirmed_commitment_tx_counterparty_output, option),
 			(13, spendable_txids_confirmed, optional_vec),
 			(15, counterparty_fulfilled_htlcs, option),
+			(16, pending_channel_type_upgrade, option),
+			(17, last_revoked_counterparty_commitment_txid, option),
+			(18, counterparty_commitment_channel_types, optional_vec),
+			(19, collateral_releases, optional_vec),
+			(21, counterparty_revokeable_outputs, option),
 		});
+		onchain_tx_handler.counterparty_node_id = counterparty_node_id;
 
 		Ok((best_block.block_hash(), ChannelMonitor::from_impl(ChannelMonitorImpl {
 			latest_update_id,
@@ -4178,6 +4564,11 @@ impl<'a, 'b, ES: EntropySource, SP: SignerProvider> ReadableArgs<(&'a ES, &'b SP
 			funding_spend_confirmed,
 			confirmed_commitment_tx_counterparty_output,
 			htlcs_resolved_on_chain: htlcs_resolved_on_chain.unwrap(),
+			pending_channel_type_upgrade,
+			counterparty_commitment_channel_types: counterparty_commitment_channel_types.unwrap(),
+			collateral_releases: collateral_releases.unwrap(),
+			last_revoked_counterparty_commitment_txid,
+			counterparty_revokeable_outputs: counterparty_revokeable_outputs.unwrap(),
 			spendable_txids_confirmed: spendable_txids_confirmed.unwrap(),
 
 			best_block,
@@ -4416,9 +4807,9 @@ mod tests {
 		monitor.provide_latest_holder_commitment_tx(dummy_commitment_tx.clone(),
 			htlcs.into_iter().map(|(htlc, _)| (htlc, Some(dummy_sig), None)).collect()).unwrap();
 		monitor.provide_latest_counterparty_commitment_tx(Txid::from_inner(Sha256::hash(b"1").into_inner()),
-			preimages_slice_to_htlc_outputs!(preimages[5..15]), 281474976710655, dummy_key, &logger);
+			preimages_slice_to_htlc_outputs!(preimages[5..15]), 281474976710655, dummy_key, None, &logger);
 		monitor.provide_latest_counterparty_commitment_tx(Txid::from_inner(Sha256::hash(b"2").into_inner()),
-			preimages_slice_to_htlc_outputs!(preimages[15..20]), 281474976710654, dummy_key, &logger);
+			preimages_slice_to_htlc_outputs!(preimages[15..20]), 281474976710654, dummy_key, None, &logger);
 		for &(ref preimage, ref hash) in preimages.iter() {
 			let bounded_fee_estimator = LowerBoundedFeeEstimator::new(&fee_estimator);
 			monitor.provide_payment_preimage(hash, preimage, &broadcaster, &bounded_fee_estimator, &logger);
@@ -4433,7 +4824,7 @@ mod tests {
 		test_preimages_exist!(&preimages[15..20], monitor);
 
 		monitor.provide_latest_counterparty_commitment_tx(Txid::from_inner(Sha256::hash(b"3").into_inner()),
-			preimages_slice_to_htlc_outputs!(preimages[17..20]), 281474976710653, dummy_key, &logger);
+			preimages_slice_to_htlc_outputs!(preimages[17..20]), 281474976710653, dummy_key, None, &logger);
 
 		// Now provide a further secret, pruning preimages 15-17
 		secret[0..32].clone_from_slice(&hex::decode("c7518c8ae4660ed02894df8976fa1a3659c1a8b4b5bec0c4b872abeba4cb8964").unwrap());
@@ -4443,7 +4834,7 @@ mod tests {
 		test_preimages_exist!(&preimages[17..20], monitor);
 
 		monitor.provide_latest_counterparty_commitment_tx(Txid::from_inner(Sha256::hash(b"4").into_inner()),
-			preimages_slice_to_htlc_outputs!(preimages[18..20]), 281474976710652, dummy_key, &logger);
+			preimages_slice_to_htlc_outputs!(preimages[18..20]), 281474976710652, dummy_key, None, &logger);
 
 		// Now update holder commitment tx info, pruning only element 18 as we still care about the
 		// previous commitment tx's preimages too
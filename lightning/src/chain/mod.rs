@@ -25,7 +25,9 @@ use crate::prelude::*;
 pub mod chaininterface;
 pub mod chainmonitor;
 pub mod channelmonitor;
+pub mod rebroadcast;
 pub mod transaction;
+pub mod watchtower;
 pub(crate) mod onchaintx;
 pub(crate) mod package;
 
@@ -305,6 +307,17 @@ pub trait Watch<ChannelSigner: WriteableEcdsaChannelSigner> {
 	/// For details on asynchronous [`ChannelMonitor`] updating and returning
 	/// [`MonitorEvent::Completed`] here, see [`ChannelMonitorUpdateStatus::InProgress`].
 	fn release_pending_monitor_events(&self) -> Vec<(OutPoint, Vec<MonitorEvent>, Option<PublicKey>)>;
+
+	/// Registers interest in transactions paying to `script_pubkey`, the on-chain fallback of one
+	/// of our invoices, e.g. with a [`Filter`], so that they are provided to the [`ChannelManager`]
+	/// which then watches for payments to it (see [`ChannelManager::watch_onchain_fallback`]).
+	///
+	/// This is called again for each fallback still being watched when the [`ChannelManager`] is
+	/// deserialized. The default implementation does nothing.
+	///
+	/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+	/// [`ChannelManager::watch_onchain_fallback`]: crate::ln::channelmanager::ChannelManager::watch_onchain_fallback
+	fn watch_onchain_fallback(&self, _script_pubkey: &Script) {}
 }
 
 /// The `Filter` trait defines behavior for indicating chain activity of interest pertaining to
@@ -339,6 +352,12 @@ pub trait Filter {
 	/// handled, e.g., by re-scanning the block in question whenever new outputs have been
 	/// registered mid-processing.
 	fn register_output(&self, output: WatchedOutput);
+
+	/// Registers interest in any transaction with an output paying to `script_pubkey`.
+	///
+	/// This is used to detect payments to on-chain invoice fallbacks. The default implementation
+	/// does nothing, which is sufficient for clients which always provide full blocks.
+	fn register_script(&self, _script_pubkey: &Script) {}
 }
 
 /// A transaction output watched by a [`ChannelMonitor`] for spends on-chain.
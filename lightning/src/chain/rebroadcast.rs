@@ -0,0 +1,504 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A [`BroadcasterInterface`] wrapper which keeps rebroadcasting our transactions until they
+//! confirm.
+//!
+//! Transactions may be dropped from mempools for many reasons, e.g. because they were evicted as
+//! the mempool filled up or because the node we handed them to restarted. While LDK rebroadcasts
+//! the claims of force-closed channels itself, it doesn't do so for e.g. funding or cooperative
+//! closing transactions. The [`RebroadcastScheduler`] tracks every transaction broadcast through
+//! it and periodically rebroadcasts those which haven't confirmed yet, backing off exponentially
+//! to avoid spamming the network. It should be persisted, such that transactions broadcast before
+//! a restart keep being rebroadcast after it.
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::transaction::{OutPoint, Transaction};
+use bitcoin::hash_types::{BlockHash, Txid};
+
+use crate::chain;
+use crate::chain::chaininterface::{BroadcasterInterface, TransactionMetadata};
+use crate::chain::channelmonitor::ANTI_REORG_DELAY;
+use crate::chain::transaction::TransactionData;
+use crate::util::logger::Logger;
+use crate::util::ser::{Readable, ReadableArgs, Writeable, Writer};
+use crate::io;
+use crate::ln::msgs::DecodeError;
+use crate::sync::Mutex;
+
+use core::ops::Deref;
+
+use crate::prelude::*;
+
+/// The maximum number of calls to [`RebroadcastScheduler::rebroadcast_pending_transactions`]
+/// between two rebroadcasts of the same transaction.
+///
+/// We start out rebroadcasting a transaction on every call, doubling the interval after each
+/// rebroadcast until it reaches this value. When called every 30 seconds, as is done by the
+/// `BackgroundProcessor`, this is about half an hour.
+pub const MAX_REBROADCAST_INTERVAL_TICKS: u16 = 64;
+
+/// The maximum number of transactions a [`RebroadcastScheduler`] tracks at once.
+///
+/// Once reached, the transactions which were broadcast the longest time ago are no longer
+/// rebroadcast, making room for new ones.
+pub const MAX_TRACKED_TRANSACTIONS: usize = 1_000;
+
+const SERIALIZATION_VERSION: u8 = 1;
+const MIN_SERIALIZATION_VERSION: u8 = 1;
+
+struct TrackedTransaction {
+	tx: Transaction,
+	metadata: Option<TransactionMetadata>,
+	confirmed_in: Option<(BlockHash, u32)>,
+}
+
+impl_writeable_tlv_based!(TrackedTransaction, {
+	(0, tx, required),
+	(1, metadata, option),
+	(3, confirmed_in, option),
+});
+
+impl TrackedTransaction {
+	fn spends_any(&self, outpoints: &[OutPoint]) -> bool {
+		self.tx.input.iter().any(|input| outpoints.contains(&input.previous_output))
+	}
+}
+
+/// Transactions which were broadcast together, and are thus rebroadcast together, as they may
+/// depend on each other.
+struct TrackedPackage {
+	txs: Vec<TrackedTransaction>,
+	interval_ticks: u16,
+	ticks: u16,
+}
+
+impl_writeable_tlv_based!(TrackedPackage, {
+	(0, txs, required_vec),
+	(2, interval_ticks, required),
+	(4, ticks, required),
+});
+
+impl TrackedPackage {
+	fn unconfirmed_txs(&self) -> Vec<(Transaction, Option<TransactionMetadata>)> {
+		self.txs.iter().filter(|tracked| tracked.confirmed_in.is_none())
+			.map(|tracked| (tracked.tx.clone(), tracked.metadata)).collect()
+	}
+
+	fn reset_backoff(&mut self) {
+		self.interval_ticks = 1;
+		self.ticks = 0;
+	}
+}
+
+/// Wraps a [`BroadcasterInterface`], handing it all transactions immediately, but also tracking
+/// them to rebroadcast them with exponential backoff until they have
+/// [`ANTI_REORG_DELAY`] confirmations.
+///
+/// For this to work, the same instance must be used as the broadcaster of the [`ChannelManager`]
+/// and [`ChainMonitor`] (and any other component broadcasting transactions, such as the
+/// [`BumpTransactionEventHandler`] or [`ContractManager`]), and it must be notified of blocks via
+/// [`chain::Listen`] or [`chain::Confirm`]. Rebroadcasts happen when
+/// [`Self::rebroadcast_pending_transactions`] is called, which the `BackgroundProcessor` does
+/// every 30 seconds via [`ChainMonitor::rebroadcast_pending_transactions`].
+///
+/// Transactions spending the same inputs as a transaction broadcast later, such as previous
+/// versions of a fee-bumped claim, or as a transaction which confirmed are no longer rebroadcast.
+///
+/// If you're able to detect transactions being evicted from your mempool, you may call
+/// [`Self::transaction_evicted`] to rebroadcast them right away. [`Self::pending_txids`] returns
+/// the transactions which should currently be in the mempool.
+///
+/// At most [`MAX_TRACKED_TRANSACTIONS`] transactions are tracked at once. The scheduler should be
+/// persisted whenever the [`ChannelManager`] is, and read back via [`ReadableArgs`] on startup.
+///
+/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+/// [`ChainMonitor`]: crate::chain::chainmonitor::ChainMonitor
+/// [`ChainMonitor::rebroadcast_pending_transactions`]: crate::chain::chainmonitor::ChainMonitor::rebroadcast_pending_transactions
+/// [`BumpTransactionEventHandler`]: crate::events::bump_transaction::BumpTransactionEventHandler
+/// [`ContractManager`]: crate::ln::contractmanager::ContractManager
+pub struct RebroadcastScheduler<B: Deref, L: Deref>
+where
+	B::Target: BroadcasterInterface,
+	L::Target: Logger,
+{
+	broadcaster: B,
+	logger: L,
+	packages: Mutex<Vec<TrackedPackage>>,
+}
+
+impl<B: Deref, L: Deref> RebroadcastScheduler<B, L>
+where
+	B::Target: BroadcasterInterface,
+	L::Target: Logger,
+{
+	/// Creates a new scheduler broadcasting transactions via the given `broadcaster`.
+	pub fn new(broadcaster: B, logger: L) -> Self {
+		Self { broadcaster, logger, packages: Mutex::new(Vec::new()) }
+	}
+
+	/// Returns the ids of the tracked transactions which haven't confirmed yet.
+	pub fn pending_txids(&self) -> Vec<Txid> {
+		self.packages.lock().unwrap().iter()
+			.flat_map(|package| package.txs.iter())
+			.filter(|tracked| tracked.confirmed_in.is_none())
+			.map(|tracked| tracked.tx.txid())
+			.collect()
+	}
+
+	/// Notifies the scheduler that the given transaction was evicted from the mempool, rebroadcasting
+	/// it (along with the rest of its package) immediately and resetting its backoff.
+	///
+	/// Does nothing if the transaction isn't tracked or already confirmed.
+	pub fn transaction_evicted(&self, txid: &Txid) {
+		let mut to_broadcast = Vec::new();
+		{
+			let mut packages = self.packages.lock().unwrap();
+			for package in packages.iter_mut() {
+				if package.txs.iter().any(|tracked| tracked.confirmed_in.is_none() && tracked.tx.txid() == *txid) {
+					package.reset_backoff();
+					to_broadcast = package.unconfirmed_txs();
+					break;
+				}
+			}
+		}
+		if !to_broadcast.is_empty() {
+			log_info!(self.logger, "Rebroadcasting evicted transaction {}", txid);
+			self.forward(&to_broadcast);
+		}
+	}
+
+	/// Rebroadcasts all tracked transactions which didn't confirm yet and are due according to
+	/// their backoff.
+	///
+	/// Should be called roughly every 30 seconds, which the `BackgroundProcessor` does for you.
+	pub fn rebroadcast_pending_transactions(&self) {
+		let mut to_broadcast = Vec::new();
+		{
+			let mut packages = self.packages.lock().unwrap();
+			for package in packages.iter_mut() {
+				package.ticks = package.ticks.saturating_add(1);
+				if package.ticks < package.interval_ticks { continue; }
+				package.ticks = 0;
+				package.interval_ticks = core::cmp::min(package.interval_ticks * 2, MAX_REBROADCAST_INTERVAL_TICKS);
+				let txs = package.unconfirmed_txs();
+				if !txs.is_empty() { to_broadcast.push(txs); }
+			}
+		}
+		for txs in to_broadcast {
+			log_debug!(self.logger, "Rebroadcasting unconfirmed transaction(s) {}",
+				log_iter!(txs.iter().map(|(tx, _)| tx.txid())));
+			self.forward(&txs);
+		}
+	}
+
+	fn forward(&self, txs: &[(Transaction, Option<TransactionMetadata>)]) {
+		if txs.iter().all(|(_, metadata)| metadata.is_some()) {
+			let txs = txs.iter().map(|(tx, metadata)| (tx, metadata.unwrap())).collect::<Vec<_>>();
+			self.broadcaster.broadcast_transactions_with_meta(&txs);
+		} else {
+			let txs = txs.iter().map(|(tx, _)| tx).collect::<Vec<_>>();
+			self.broadcaster.broadcast_transactions(&txs);
+		}
+	}
+
+	fn track(&self, txs: Vec<(Transaction, Option<TransactionMetadata>)>) {
+		let txids = txs.iter().map(|(tx, _)| tx.txid()).collect::<Vec<_>>();
+		let spent_outpoints = txs.iter().flat_map(|(tx, _)| tx.input.iter().map(|input| input.previous_output))
+			.collect::<Vec<_>>();
+		let mut packages = self.packages.lock().unwrap();
+		// Anything spending the same inputs has been replaced, and any re-broadcast of a
+		// transaction we already track now belongs to the new package.
+		for package in packages.iter_mut() {
+			package.txs.retain(|tracked| tracked.confirmed_in.is_some() ||
+				(!txids.contains(&tracked.tx.txid()) && !tracked.spends_any(&spent_outpoints)));
+		}
+		packages.retain(|package| !package.txs.is_empty());
+		// Don't track transactions which already confirmed again.
+		let new_txs = txs.into_iter().filter(|(tx, _)|
+			!packages.iter().any(|package| package.txs.iter().any(|tracked| tracked.tx.txid() == tx.txid()))
+		).map(|(tx, metadata)| TrackedTransaction { tx, metadata, confirmed_in: None }).collect::<Vec<_>>();
+		if !new_txs.is_empty() {
+			packages.push(TrackedPackage { txs: new_txs, interval_ticks: 1, ticks: 0 });
+		}
+		let mut tracked_count: usize = packages.iter().map(|package| package.txs.len()).sum();
+		while tracked_count > MAX_TRACKED_TRANSACTIONS && packages.len() > 1 {
+			let package = packages.remove(0);
+			log_warn!(self.logger, "Tracking too many transactions, no longer rebroadcasting {}",
+				log_iter!(package.txs.iter().map(|tracked| tracked.tx.txid())));
+			tracked_count -= package.txs.len();
+		}
+	}
+
+	fn transactions_confirmed_internal(&self, header: &BlockHeader, txdata: &TransactionData, height: u32) {
+		let block_hash = header.block_hash();
+		let mut packages = self.packages.lock().unwrap();
+		for (_, tx) in txdata.iter() {
+			let txid = tx.txid();
+			let spent_outpoints = tx.input.iter().map(|input| input.previous_output).collect::<Vec<_>>();
+			for package in packages.iter_mut() {
+				package.txs.retain(|tracked| tracked.confirmed_in.is_some() ||
+					tracked.tx.txid() == txid || !tracked.spends_any(&spent_outpoints));
+				for tracked in package.txs.iter_mut() {
+					if tracked.tx.txid() == txid {
+						log_debug!(self.logger, "Transaction {} confirmed, no longer rebroadcasting it", txid);
+						tracked.confirmed_in = Some((block_hash, height));
+					}
+				}
+			}
+		}
+		packages.retain(|package| !package.txs.is_empty());
+	}
+
+	fn best_block_updated_internal(&self, height: u32) {
+		let mut packages = self.packages.lock().unwrap();
+		for package in packages.iter_mut() {
+			package.txs.retain(|tracked| match tracked.confirmed_in {
+				Some((_, conf_height)) => conf_height + ANTI_REORG_DELAY - 1 > height,
+				None => true,
+			});
+		}
+		packages.retain(|package| !package.txs.is_empty());
+	}
+
+	fn unconfirm(&self, filter: &dyn Fn(&TrackedTransaction) -> bool) {
+		let mut packages = self.packages.lock().unwrap();
+		for package in packages.iter_mut() {
+			let mut unconfirmed_any = false;
+			for tracked in package.txs.iter_mut() {
+				if tracked.confirmed_in.is_some() && filter(tracked) {
+					log_debug!(self.logger, "Transaction {} was reorged out, rebroadcasting it again", tracked.tx.txid());
+					tracked.confirmed_in = None;
+					unconfirmed_any = true;
+				}
+			}
+			if unconfirmed_any { package.reset_backoff(); }
+		}
+	}
+}
+
+impl<B: Deref, L: Deref> Writeable for RebroadcastScheduler<B, L>
+where
+	B::Target: BroadcasterInterface,
+	L::Target: Logger,
+{
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+		write_ver_prefix!(writer, SERIALIZATION_VERSION, MIN_SERIALIZATION_VERSION);
+		let packages = self.packages.lock().unwrap();
+		write_tlv_fields!(writer, {
+			(0, *packages, optional_vec),
+		});
+		Ok(())
+	}
+}
+
+impl<B: Deref, L: Deref> ReadableArgs<(B, L)> for RebroadcastScheduler<B, L>
+where
+	B::Target: BroadcasterInterface,
+	L::Target: Logger,
+{
+	fn read<R: io::Read>(reader: &mut R, args: (B, L)) -> Result<Self, DecodeError> {
+		let (broadcaster, logger) = args;
+		let _ver = read_ver_prefix!(reader, SERIALIZATION_VERSION);
+		let mut packages: Option<Vec<TrackedPackage>> = Some(Vec::new());
+		read_tlv_fields!(reader, {
+			(0, packages, optional_vec),
+		});
+		Ok(Self { broadcaster, logger, packages: Mutex::new(packages.unwrap()) })
+	}
+}
+
+impl<B: Deref, L: Deref> BroadcasterInterface for RebroadcastScheduler<B, L>
+where
+	B::Target: BroadcasterInterface,
+	L::Target: Logger,
+{
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		self.track(txs.iter().map(|tx| ((*tx).clone(), None)).collect());
+		self.broadcaster.broadcast_transactions(txs);
+	}
+
+	fn broadcast_transactions_with_meta(&self, txs: &[(&Transaction, TransactionMetadata)]) {
+		self.track(txs.iter().map(|(tx, metadata)| ((*tx).clone(), Some(*metadata))).collect());
+		self.broadcaster.broadcast_transactions_with_meta(txs);
+	}
+
+	fn rebroadcast_pending_transactions(&self) {
+		RebroadcastScheduler::rebroadcast_pending_transactions(self);
+	}
+}
+
+impl<B: Deref, L: Deref> chain::Listen for RebroadcastScheduler<B, L>
+where
+	B::Target: BroadcasterInterface,
+	L::Target: Logger,
+{
+	fn filtered_block_connected(&self, header: &BlockHeader, txdata: &TransactionData, height: u32) {
+		self.transactions_confirmed_internal(header, txdata, height);
+		self.best_block_updated_internal(height);
+	}
+
+	fn block_disconnected(&self, _header: &BlockHeader, height: u32) {
+		self.unconfirm(&|tracked| tracked.confirmed_in.map(|(_, conf_height)| conf_height >= height).unwrap_or(false));
+	}
+}
+
+impl<B: Deref, L: Deref> chain::Confirm for RebroadcastScheduler<B, L>
+where
+	B::Target: BroadcasterInterface,
+	L::Target: Logger,
+{
+	fn transactions_confirmed(&self, header: &BlockHeader, txdata: &TransactionData, height: u32) {
+		self.transactions_confirmed_internal(header, txdata, height);
+	}
+
+	fn transaction_unconfirmed(&self, txid: &Txid) {
+		self.unconfirm(&|tracked| tracked.tx.txid() == *txid);
+	}
+
+	fn best_block_updated(&self, _header: &BlockHeader, height: u32) {
+		self.best_block_updated_internal(height);
+	}
+
+	fn get_relevant_txids(&self) -> Vec<(Txid, Option<BlockHash>)> {
+		self.packages.lock().unwrap().iter()
+			.flat_map(|package| package.txs.iter())
+			.filter_map(|tracked| tracked.confirmed_in.map(|(block_hash, _)| (tracked.tx.txid(), Some(block_hash))))
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::chain::chaininterface::TransactionType;
+	use crate::chain::Listen;
+	use crate::util::test_utils::{TestBroadcaster, TestLogger};
+
+	use bitcoin::{PackedLockTime, Sequence, Script, TxIn, TxOut, Witness};
+	use bitcoin::blockdata::constants::genesis_block;
+	use bitcoin::hashes::Hash;
+	use bitcoin::network::constants::Network;
+
+	fn spend(vout: u32, value: u64) -> Transaction {
+		Transaction {
+			version: 2,
+			lock_time: PackedLockTime::ZERO,
+			input: vec![TxIn {
+				previous_output: OutPoint { txid: Txid::all_zeros(), vout },
+				script_sig: Script::new(),
+				sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+				witness: Witness::new(),
+			}],
+			output: vec![TxOut { value, script_pubkey: Script::new() }],
+		}
+	}
+
+	fn take_broadcast(broadcaster: &TestBroadcaster) -> Vec<Txid> {
+		broadcaster.txn_broadcast().iter().map(|tx| tx.txid()).collect()
+	}
+
+	#[test]
+	fn rebroadcasts_with_backoff_until_confirmed() {
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let logger = TestLogger::new();
+		let scheduler = RebroadcastScheduler::new(&broadcaster, &logger);
+		let metadata = TransactionMetadata {
+			channel_id: Some([42; 32]), counterparty_node_id: None,
+			transaction_type: TransactionType::Funding,
+		};
+		let tx_a = spend(0, 10_000);
+		let tx_b = spend(1, 10_000);
+		scheduler.broadcast_transactions_with_meta(&[(&tx_a, metadata)]);
+		scheduler.broadcast_transactions(&[&tx_b]);
+		assert_eq!(take_broadcast(&broadcaster), vec![tx_a.txid(), tx_b.txid()]);
+
+		// Rebroadcasts happen after 1, 2, 4, ... ticks.
+		let mut rebroadcast_ticks = Vec::new();
+		for tick in 1..=16 {
+			scheduler.rebroadcast_pending_transactions();
+			let broadcast = take_broadcast(&broadcaster);
+			if !broadcast.is_empty() {
+				assert_eq!(broadcast, vec![tx_a.txid(), tx_b.txid()]);
+				rebroadcast_ticks.push(tick);
+			}
+		}
+		assert_eq!(rebroadcast_ticks, vec![1, 3, 7, 15]);
+
+		// An eviction leads to an immediate rebroadcast and resets the backoff.
+		scheduler.transaction_evicted(&tx_a.txid());
+		assert_eq!(take_broadcast(&broadcaster), vec![tx_a.txid()]);
+		scheduler.rebroadcast_pending_transactions();
+		assert_eq!(take_broadcast(&broadcaster), vec![tx_a.txid()]);
+
+		// A replacement of tx_b supersedes it.
+		let tx_b_bumped = spend(1, 9_000);
+		scheduler.broadcast_transactions(&[&tx_b_bumped]);
+		assert_eq!(take_broadcast(&broadcaster), vec![tx_b_bumped.txid()]);
+		let mut pending_txids = scheduler.pending_txids();
+		pending_txids.sort();
+		let mut expected_txids = vec![tx_a.txid(), tx_b_bumped.txid()];
+		expected_txids.sort();
+		assert_eq!(pending_txids, expected_txids);
+
+		// Once confirmed, transactions are no longer rebroadcast, and forgotten about once they
+		// can no longer be reorged out.
+		let header = genesis_block(Network::Testnet).header;
+		scheduler.filtered_block_connected(&header, &[(0, &tx_a), (1, &tx_b_bumped)], 1);
+		assert!(scheduler.pending_txids().is_empty());
+		for _ in 0..MAX_REBROADCAST_INTERVAL_TICKS {
+			scheduler.rebroadcast_pending_transactions();
+		}
+		assert!(take_broadcast(&broadcaster).is_empty());
+
+		// Unless a reorg happens first.
+		scheduler.block_disconnected(&header, 1);
+		assert_eq!(scheduler.pending_txids().len(), 2);
+		scheduler.rebroadcast_pending_transactions();
+		assert_eq!(take_broadcast(&broadcaster).len(), 2);
+
+		scheduler.filtered_block_connected(&header, &[(0, &tx_a), (1, &tx_b_bumped)], 1);
+		scheduler.filtered_block_connected(&header, &[], ANTI_REORG_DELAY);
+		assert!(chain::Confirm::get_relevant_txids(&scheduler).is_empty());
+	}
+
+	#[test]
+	fn persists_and_bounds_tracked_transactions() {
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let logger = TestLogger::new();
+		let scheduler = RebroadcastScheduler::new(&broadcaster, &logger);
+		let metadata = TransactionMetadata {
+			channel_id: None, counterparty_node_id: None, transaction_type: TransactionType::CooperativeClose,
+		};
+		let txs = (0..=MAX_TRACKED_TRANSACTIONS as u32).map(|vout| spend(vout, 10_000)).collect::<Vec<_>>();
+		for tx in txs[..MAX_TRACKED_TRANSACTIONS].iter() {
+			scheduler.broadcast_transactions(&[tx]);
+		}
+		scheduler.broadcast_transactions_with_meta(&[(&txs[MAX_TRACKED_TRANSACTIONS], metadata)]);
+		take_broadcast(&broadcaster);
+
+		// Only the most recently broadcast transactions are tracked.
+		let pending_txids = scheduler.pending_txids();
+		assert_eq!(pending_txids.len(), MAX_TRACKED_TRANSACTIONS);
+		assert!(!pending_txids.contains(&txs[0].txid()));
+		assert_eq!(pending_txids[0], txs[1].txid());
+
+		// Tracked transactions, along with their backoff, survive a restart.
+		scheduler.rebroadcast_pending_transactions();
+		assert_eq!(take_broadcast(&broadcaster).len(), MAX_TRACKED_TRANSACTIONS);
+		let scheduler = <RebroadcastScheduler<&TestBroadcaster, &TestLogger>>::read(
+			&mut &scheduler.encode()[..], (&broadcaster, &logger)).unwrap();
+		assert_eq!(scheduler.pending_txids(), pending_txids);
+		scheduler.rebroadcast_pending_transactions();
+		assert!(take_broadcast(&broadcaster).is_empty());
+		scheduler.rebroadcast_pending_transactions();
+		assert_eq!(take_broadcast(&broadcaster).len(), MAX_TRACKED_TRANSACTIONS);
+	}
+}
@@ -0,0 +1,286 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! An encrypted blob format for handing revocation data to a watchtower.
+//!
+//! Each time our counterparty revokes a commitment transaction, [`ChannelMonitor`] can produce a
+//! [`JusticeBlob`] for it via [`ChannelMonitor::justice_blobs_from_update`]. A blob may be handed
+//! to an untrusted (e.g. altruistic) tower, which can only decrypt it once the revoked commitment
+//! transaction appears on chain.
+//!
+//! The envelope follows the conventions common to altruistic towers: blobs are located by a
+//! [`BreachHint`] made of the first 16 bytes of the revoked commitment transaction's txid, and are
+//! encrypted with ChaCha20-Poly1305 using the SHA256 of the full txid as the key and an all-zero
+//! nonce, with the 16-byte authentication tag appended to the ciphertext. As the txid is unique
+//! per commitment transaction, the key is never reused.
+//!
+//! The plaintext, [`JusticeData`], is LDK-specific. Like altruistic tower blobs, it carries a
+//! signature for a justice transaction sweeping the revoked commitment transaction's `to_local`
+//! output, which [`JusticeData::build_to_local_justice_tx`] turns into a broadcastable
+//! transaction. It additionally carries the keys and HTLCs needed to claim the HTLC outputs.
+//!
+//! [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+//! [`ChannelMonitor::justice_blobs_from_update`]: crate::chain::channelmonitor::ChannelMonitor::justice_blobs_from_update
+
+use bitcoin::{PackedLockTime, Sequence, Witness};
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{EcdsaSighashType, OutPoint, Transaction, TxIn, TxOut};
+use bitcoin::hash_types::Txid;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::ecdsa::Signature;
+
+use crate::chain::package::WEIGHT_REVOKED_OUTPUT;
+use crate::io::{self, Read};
+use crate::ln::chan_utils::{self, HTLCOutputInCommitment};
+use crate::ln::msgs::DecodeError;
+use crate::util::chacha20poly1305rfc::ChaCha20Poly1305RFC;
+use crate::util::ser::{Readable, Writeable, Writer};
+
+use crate::prelude::*;
+
+/// The length of the authentication tag appended to the ciphertext of a [`JusticeBlob`].
+const TAG_LEN: usize = 16;
+
+/// Returns the weight of the justice transaction built by
+/// [`JusticeData::build_to_local_justice_tx`] sweeping to `sweep_script`.
+pub(crate) fn to_local_justice_tx_weight(sweep_script: &Script) -> u64 {
+	// version: 4 bytes ; count_tx_in: 1 byte ; count_tx_out: 1 byte ; lock_time: 4 bytes
+	let transaction_weight = 10 * 4;
+	// segwit flags: 2 ; previous_out_point: 36 bytes ; var_int: 1 byte ; sequence: 4 bytes
+	let input_weight = 2 + 41 * 4 + WEIGHT_REVOKED_OUTPUT;
+	// value: 8 bytes ; var_int: 1 byte ; pk_script: `sweep_script.len()`
+	let output_weight = (8 + 1 + sweep_script.len() as u64) * 4;
+	transaction_weight + input_weight + output_weight
+}
+
+/// The first 16 bytes of a revoked commitment transaction's txid, used by a tower to match a
+/// [`JusticeBlob`] against the transactions it sees confirm.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct BreachHint(pub [u8; 16]);
+
+impl BreachHint {
+	/// Returns the hint for the commitment transaction with the given txid.
+	pub fn from_txid(txid: &Txid) -> Self {
+		let mut hint = [0; 16];
+		hint.copy_from_slice(&txid[..16]);
+		Self(hint)
+	}
+}
+
+impl Writeable for BreachHint {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		self.0.write(w)
+	}
+}
+
+impl Readable for BreachHint {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		Ok(Self(Readable::read(r)?))
+	}
+}
+
+/// A signature for a justice transaction sweeping the `to_local` output of a revoked commitment
+/// transaction, see [`JusticeData::build_to_local_justice_tx`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PresignedJustice {
+	/// The index of the `to_local` output in the revoked commitment transaction.
+	pub output_index: u32,
+	/// The value of the justice transaction's single output, i.e. the value of the `to_local`
+	/// output less the fee.
+	pub sweep_value_sat: u64,
+	/// The signature for the justice transaction's single input.
+	pub signature: Signature,
+}
+
+impl_writeable_tlv_based!(PresignedJustice, {
+	(0, output_index, required),
+	(2, sweep_value_sat, required),
+	(4, signature, required),
+});
+
+/// The data needed to punish our counterparty for broadcasting a revoked commitment transaction.
+///
+/// All keys are those of the revoked commitment transaction, i.e. already derived with its
+/// per-commitment point.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JusticeData {
+	/// The commitment number of the revoked commitment transaction.
+	pub commitment_number: u64,
+	/// The per-commitment secret our counterparty revealed when revoking the commitment
+	/// transaction.
+	pub per_commitment_secret: [u8; 32],
+	/// The revocation public key which may spend the counterparty's `to_local` output and all HTLC
+	/// outputs of the revoked commitment transaction.
+	pub revocation_pubkey: PublicKey,
+	/// The counterparty's delayed payment key for the revoked commitment transaction, needed to
+	/// rebuild the `to_local` output's script.
+	pub broadcaster_delayed_payment_key: PublicKey,
+	/// The CSV delay on the counterparty's `to_local` output.
+	pub to_self_delay: u16,
+	/// The script the punished funds should be swept to.
+	pub sweep_script: Script,
+	/// The HTLC outputs of the revoked commitment transaction.
+	pub revoked_htlcs: Vec<HTLCOutputInCommitment>,
+	/// The signed justice transaction for the `to_local` output of the revoked commitment
+	/// transaction, unless it has none or its value wouldn't cover the fee.
+	pub to_local_justice: Option<PresignedJustice>,
+}
+
+impl_writeable_tlv_based!(JusticeData, {
+	(0, commitment_number, required),
+	(2, per_commitment_secret, required),
+	(4, revocation_pubkey, required),
+	(6, broadcaster_delayed_payment_key, required),
+	(8, to_self_delay, required),
+	(10, sweep_script, required),
+	(12, revoked_htlcs, required_vec),
+	(13, to_local_justice, option),
+});
+
+impl JusticeData {
+	/// Builds the justice transaction for [`PresignedJustice`], without its witness.
+	pub(crate) fn unsigned_to_local_justice_tx(&self, commitment_txid: &Txid, output_index: u32, sweep_value_sat: u64) -> Transaction {
+		Transaction {
+			version: 2,
+			lock_time: PackedLockTime::ZERO,
+			input: vec![TxIn {
+				previous_output: OutPoint { txid: *commitment_txid, vout: output_index },
+				script_sig: Script::new(),
+				sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+				witness: Witness::new(),
+			}],
+			output: vec![TxOut { script_pubkey: self.sweep_script.clone(), value: sweep_value_sat }],
+		}
+	}
+
+	/// Builds the signed justice transaction sweeping the `to_local` output of the revoked
+	/// commitment transaction with the given txid, if [`Self::to_local_justice`] is set.
+	pub fn build_to_local_justice_tx(&self, commitment_txid: &Txid) -> Option<Transaction> {
+		let justice = self.to_local_justice.as_ref()?;
+		let mut justice_tx = self.unsigned_to_local_justice_tx(commitment_txid, justice.output_index, justice.sweep_value_sat);
+		let witness_script = chan_utils::get_revokeable_redeemscript(
+			&self.revocation_pubkey, self.to_self_delay, &self.broadcaster_delayed_payment_key);
+		let mut ser_sig = justice.signature.serialize_der().to_vec();
+		ser_sig.push(EcdsaSighashType::All as u8);
+		justice_tx.input[0].witness.push(ser_sig);
+		justice_tx.input[0].witness.push(vec!(1));
+		justice_tx.input[0].witness.push(witness_script.into_bytes());
+		Some(justice_tx)
+	}
+}
+
+/// [`JusticeData`] for a revoked commitment transaction, encrypted such that it can only be
+/// decrypted with the transaction's txid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JusticeBlob {
+	/// The hint a tower should index this blob by.
+	pub hint: BreachHint,
+	/// The encrypted [`JusticeData`], followed by its 16-byte authentication tag.
+	pub encrypted_data: Vec<u8>,
+}
+
+impl_writeable_tlv_based!(JusticeBlob, {
+	(0, hint, required),
+	(2, encrypted_data, required),
+});
+
+impl JusticeBlob {
+	/// Encrypts `data` for the revoked commitment transaction with the given txid.
+	pub fn encrypt(commitment_txid: &Txid, data: &JusticeData) -> Self {
+		let plaintext = data.encode();
+		let mut encrypted_data = vec![0; plaintext.len() + TAG_LEN];
+		let (ciphertext, tag) = encrypted_data.split_at_mut(plaintext.len());
+		let key = Sha256::hash(&commitment_txid[..]);
+		ChaCha20Poly1305RFC::new(&key[..], &[0; 12], &[]).encrypt(&plaintext, ciphertext, tag);
+		Self { hint: BreachHint::from_txid(commitment_txid), encrypted_data }
+	}
+
+	/// Decrypts the blob using the txid of a transaction matching its [`BreachHint`].
+	///
+	/// Fails if the txid isn't that of the revoked commitment transaction the blob was created for
+	/// or if the blob was tampered with.
+	pub fn decrypt(&self, commitment_txid: &Txid) -> Result<JusticeData, DecodeError> {
+		if self.hint != BreachHint::from_txid(commitment_txid) || self.encrypted_data.len() < TAG_LEN {
+			return Err(DecodeError::InvalidValue);
+		}
+		let (ciphertext, tag) = self.encrypted_data.split_at(self.encrypted_data.len() - TAG_LEN);
+		let mut plaintext = vec![0; ciphertext.len()];
+		let key = Sha256::hash(&commitment_txid[..]);
+		if !ChaCha20Poly1305RFC::new(&key[..], &[0; 12], &[]).decrypt(ciphertext, &mut plaintext, tag) {
+			return Err(DecodeError::InvalidValue);
+		}
+		JusticeData::read(&mut io::Cursor::new(&plaintext))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::ln::PaymentHash;
+
+	use bitcoin::secp256k1::{Message, Secp256k1, SecretKey};
+
+	fn justice_data() -> JusticeData {
+		let secp_ctx = Secp256k1::new();
+		let key = |byte| PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[byte; 32]).unwrap());
+		JusticeData {
+			commitment_number: 281474976710654,
+			per_commitment_secret: [42; 32],
+			revocation_pubkey: key(1),
+			broadcaster_delayed_payment_key: key(2),
+			to_self_delay: 144,
+			sweep_script: Script::new(),
+			revoked_htlcs: vec![HTLCOutputInCommitment {
+				offered: true,
+				amount_msat: 100_000,
+				cltv_expiry: 500_000,
+				payment_hash: PaymentHash([3; 32]),
+				transaction_output_index: Some(2),
+			}],
+			to_local_justice: Some(PresignedJustice {
+				output_index: 1,
+				sweep_value_sat: 9_000,
+				signature: secp_ctx.sign_ecdsa(&Message::from_slice(&[4; 32]).unwrap(), &SecretKey::from_slice(&[5; 32]).unwrap()),
+			}),
+		}
+	}
+
+	#[test]
+	fn justice_blob_roundtrip() {
+		let txid = Txid::from_slice(&[7; 32]).unwrap();
+		let data = justice_data();
+		let blob = JusticeBlob::encrypt(&txid, &data);
+		assert_eq!(blob.hint, BreachHint([7; 16]));
+		assert_eq!(blob.encrypted_data.len(), data.encode().len() + TAG_LEN);
+
+		let decoded_blob: JusticeBlob = Readable::read(&mut io::Cursor::new(&blob.encode())).unwrap();
+		assert_eq!(decoded_blob, blob);
+		assert_eq!(decoded_blob.decrypt(&txid).unwrap(), data);
+	}
+
+	#[test]
+	fn justice_blob_requires_matching_txid() {
+		let txid = Txid::from_slice(&[7; 32]).unwrap();
+		let blob = JusticeBlob::encrypt(&txid, &justice_data());
+
+		// A txid with a matching hint but a different key fails authentication.
+		let mut other_txid = [7; 32];
+		other_txid[31] = 8;
+		let other_txid = Txid::from_slice(&other_txid).unwrap();
+		assert_eq!(blob.hint, BreachHint::from_txid(&other_txid));
+		assert_eq!(blob.decrypt(&other_txid), Err(DecodeError::InvalidValue));
+
+		let mut tampered_blob = blob.clone();
+		tampered_blob.encrypted_data[0] ^= 1;
+		assert_eq!(tampered_blob.decrypt(&txid), Err(DecodeError::InvalidValue));
+	}
+}
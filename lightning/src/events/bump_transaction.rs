@@ -14,7 +14,7 @@
 use alloc::collections::BTreeMap;
 use core::ops::Deref;
 
-use crate::chain::chaininterface::{BroadcasterInterface, compute_feerate_sat_per_1000_weight, fee_for_weight, FEERATE_FLOOR_SATS_PER_KW};
+use crate::chain::chaininterface::{BroadcasterInterface, compute_feerate_sat_per_1000_weight, fee_for_weight, FEERATE_FLOOR_SATS_PER_KW, TransactionMetadata, TransactionType};
 use crate::chain::ClaimId;
 use crate::io_extras::sink;
 use crate::ln::channel::ANCHOR_OUTPUT_VALUE_SATOSHI;
@@ -336,6 +336,41 @@ pub enum BumpTransactionEvent {
 		/// The locktime required for the resulting HTLC transaction.
 		tx_lock_time: PackedLockTime,
 	},
+	/// Indicates that the settlement transaction of a contract tracked by a [`ContractManager`] was
+	/// broadcast, but its presigned fee doesn't meet the target feerate. Much like for
+	/// [`BumpTransactionEvent::ChannelClose`], additional fees must be attached through a child
+	/// transaction spending our payout output of the settlement transaction, described by
+	/// `payout_input`, along with additional inputs to meet the target feerate. Once the
+	/// transaction is constructed, it must be fully signed for and broadcast by the consumer of the
+	/// event along with the `settlement_tx` enclosed.
+	///
+	/// The consumer should be able to sign for any of the additional inputs as well as for the
+	/// `payout_input`, which pays to the holder payout script the contract was set up with. It is
+	/// only generated for P2WPKH payout scripts.
+	///
+	/// As with the other variants, it is possible to receive more than one instance of this event,
+	/// with the same care required to adhere to the Replace-By-Fee rules.
+	///
+	/// [`ContractManager`]: crate::ln::contractmanager::ContractManager
+	ContractSettlement {
+		/// The unique identifier for the claim of our payout output in the settlement transaction.
+		///
+		/// The identifier must map to the set of external UTXOs assigned to the claim, such that
+		/// they can be reused when a new claim with the same identifier needs to be made, resulting
+		/// in a fee-bumping attempt.
+		claim_id: ClaimId,
+		/// The target feerate that the transaction package, which consists of the settlement
+		/// transaction and the to-be-crafted child transaction, must meet.
+		package_target_feerate_sat_per_1000_weight: u32,
+		/// The settlement transaction to bump the fee of. This transaction should be broadcast
+		/// along with the child transaction constructed as a result of consuming this event.
+		settlement_tx: Transaction,
+		/// The absolute fee in satoshis of the settlement transaction. This can be used along with
+		/// the weight of the settlement transaction to determine its feerate.
+		settlement_tx_fee_satoshis: u64,
+		/// Our payout output of the settlement transaction the child transaction must spend.
+		payout_input: Input,
+	},
 }
 
 /// An input that must be included in a transaction when performing coin selection through
@@ -488,9 +523,118 @@ pub trait WalletSource {
 	fn sign_tx(&self, tx: Transaction) -> Result<Transaction, ()>;
 }
 
+/// The number of calls to [`UtxoReservations::timer_tick_occurred`] after which a reservation that
+/// wasn't renewed expires, releasing its UTXO for other uses.
+///
+/// When ticked once a minute, as is done for [`ChannelManager::timer_tick_occurred`], this is about
+/// an hour.
+///
+/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+pub const UTXO_RESERVATION_EXPIRY_TICKS: u16 = 60;
+
+/// What a UTXO was reserved for in a [`UtxoReservations`] registry.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum UtxoReservationPurpose {
+	/// The UTXO is spent by the funding transaction of the channel with the given (temporary)
+	/// channel id.
+	ChannelFunding([u8; 32]),
+	/// The UTXO is spent by a splice of the channel with the given channel id.
+	Splice([u8; 32]),
+	/// The UTXO is spent by a transaction bumping the fee of the claim with the given id, e.g. an
+	/// anchor or HTLC transaction.
+	FeeBump(ClaimId),
+}
+
+struct UtxoReservation {
+	purpose: UtxoReservationPurpose,
+	ticks: u16,
+}
+
+/// A registry of the UTXOs our wallet currently has in-flight uses for, ensuring the same UTXO is
+/// not concurrently picked for channel funding, splicing and fee bumping, which would lead to us
+/// broadcasting transactions conflicting with each other.
+///
+/// Every [`Wallet`] tracks its reservations in such a registry, which can be accessed via
+/// [`Wallet::reservations`] to reserve UTXOs selected through other means as well.
+///
+/// Reservations expire after [`UTXO_RESERVATION_EXPIRY_TICKS`] calls to
+/// [`Self::timer_tick_occurred`] unless renewed, so that UTXOs of abandoned transactions become
+/// available again.
+pub struct UtxoReservations {
+	reservations: Mutex<HashMap<OutPoint, UtxoReservation>>,
+}
+
+impl Default for UtxoReservations {
+	fn default() -> Self { Self::new() }
+}
+
+impl UtxoReservations {
+	/// Creates a new, empty registry.
+	pub fn new() -> Self {
+		Self { reservations: Mutex::new(HashMap::new()) }
+	}
+
+	/// Reserves the given UTXOs for the given purpose, renewing any existing reservations for the
+	/// same purpose.
+	///
+	/// Fails without reserving any of the UTXOs if one of them is already reserved for a different
+	/// purpose, returning that UTXO.
+	pub fn reserve(&self, outpoints: &[OutPoint], purpose: UtxoReservationPurpose) -> Result<(), OutPoint> {
+		let mut reservations = self.reservations.lock().unwrap();
+		for outpoint in outpoints {
+			if let Some(reservation) = reservations.get(outpoint) {
+				if reservation.purpose != purpose {
+					return Err(*outpoint);
+				}
+			}
+		}
+		for outpoint in outpoints {
+			reservations.insert(*outpoint, UtxoReservation { purpose, ticks: 0 });
+		}
+		Ok(())
+	}
+
+	/// Returns what the given UTXO is currently reserved for, if anything.
+	pub fn reserved_for(&self, outpoint: &OutPoint) -> Option<UtxoReservationPurpose> {
+		self.reservations.lock().unwrap().get(outpoint).map(|reservation| reservation.purpose)
+	}
+
+	/// Releases all UTXOs reserved for the given purpose, e.g. once a channel open was aborted.
+	pub fn release(&self, purpose: UtxoReservationPurpose) {
+		self.reservations.lock().unwrap().retain(|_, reservation| reservation.purpose != purpose);
+	}
+
+	/// Releases the reservations of the given UTXOs, whatever they were reserved for.
+	pub fn release_utxos(&self, outpoints: &[OutPoint]) {
+		let mut reservations = self.reservations.lock().unwrap();
+		for outpoint in outpoints {
+			reservations.remove(outpoint);
+		}
+	}
+
+	/// Releases the reservations of all UTXOs which aren't among the given ones, e.g. as they were
+	/// spent by a confirmed transaction and are thus no longer returned by
+	/// [`WalletSource::list_confirmed_utxos`].
+	pub fn release_unavailable(&self, available_utxos: &[Utxo]) {
+		self.reservations.lock().unwrap().retain(|outpoint, _|
+			available_utxos.iter().any(|utxo| utxo.outpoint == *outpoint));
+	}
+
+	/// Expires reservations which weren't renewed for [`UTXO_RESERVATION_EXPIRY_TICKS`] calls.
+	///
+	/// Should be called roughly once a minute.
+	pub fn timer_tick_occurred(&self) {
+		self.reservations.lock().unwrap().retain(|_, reservation| {
+			reservation.ticks += 1;
+			reservation.ticks < UTXO_RESERVATION_EXPIRY_TICKS
+		});
+	}
+}
+
 /// A wrapper over [`WalletSource`] that implements [`CoinSelection`] by preferring UTXOs that would
 /// avoid conflicting double spends. If not enough UTXOs are available to do so, conflicting double
-/// spends may happen.
+/// spends between different fee bumps may happen, but UTXOs reserved for channel funding or
+/// splicing in the wallet's [`UtxoReservations`] are never spent by fee bumps.
 pub struct Wallet<W: Deref, L: Deref>
 where
 	W::Target: WalletSource,
@@ -498,10 +642,7 @@ where
 {
 	source: W,
 	logger: L,
-	// TODO: Do we care about cleaning this up once the UTXOs have a confirmed spend? We can do so
-	// by checking whether any UTXOs that exist in the map are no longer returned in
-	// `list_confirmed_utxos`.
-	locked_utxos: Mutex<HashMap<OutPoint, ClaimId>>,
+	reservations: UtxoReservations,
 }
 
 impl<W: Deref, L: Deref> Wallet<W, L>
@@ -512,26 +653,102 @@ where
 	/// Returns a new instance backed by the given [`WalletSource`] that serves as an implementation
 	/// of [`CoinSelectionSource`].
 	pub fn new(source: W, logger: L) -> Self {
-		Self { source, logger, locked_utxos: Mutex::new(HashMap::new()) }
+		Self { source, logger, reservations: UtxoReservations::new() }
+	}
+
+	/// Returns the registry of UTXOs reserved by this wallet.
+	///
+	/// UTXOs spent by transactions built without this wallet's coin selection should be reserved
+	/// here, to make sure they aren't spent by fee bumps in the meantime.
+	pub fn reservations(&self) -> &UtxoReservations {
+		&self.reservations
+	}
+
+	/// Expires stale UTXO reservations. See [`UtxoReservations::timer_tick_occurred`].
+	pub fn timer_tick_occurred(&self) {
+		self.reservations.timer_tick_occurred();
+	}
+
+	/// Performs coin selection for a transaction funding the channel with the given (temporary)
+	/// channel id, or splicing funds into it, reserving the selected UTXOs for it.
+	///
+	/// Unlike fee bumps, the selected UTXOs are never reserved for anything else, so that the
+	/// resulting transaction can't conflict with any other transaction built using this wallet.
+	/// Once the transaction confirms, or is abandoned, the reservations can be dropped via
+	/// [`UtxoReservations::release`], otherwise they'll expire after
+	/// [`UTXO_RESERVATION_EXPIRY_TICKS`] unless the coin selection is repeated.
+	///
+	/// `purpose` must not be [`UtxoReservationPurpose::FeeBump`].
+	pub fn select_utxos_for_funding(
+		&self, purpose: UtxoReservationPurpose, must_pay_to: &[TxOut],
+		target_feerate_sat_per_1000_weight: u32,
+	) -> Result<CoinSelection, ()> {
+		if let UtxoReservationPurpose::FeeBump(_) = purpose {
+			debug_assert!(false, "Fee bumps must go through CoinSelectionSource::select_confirmed_utxos");
+			return Err(());
+		}
+		self.select_utxos(purpose, Vec::new(), must_pay_to, target_feerate_sat_per_1000_weight)
+	}
+
+	fn select_utxos(
+		&self, purpose: UtxoReservationPurpose, must_spend: Vec<Input>, must_pay_to: &[TxOut],
+		target_feerate_sat_per_1000_weight: u32,
+	) -> Result<CoinSelection, ()> {
+		let utxos = self.source.list_confirmed_utxos()?;
+		// UTXOs which are no longer listed have been spent, so we can forget about them.
+		self.reservations.release_unavailable(&utxos);
+		// TODO: Use fee estimation utils when we upgrade to bitcoin v0.30.0.
+		const BASE_TX_SIZE: u64 = 4 /* version */ + 1 /* input count */ + 1 /* output count */ + 4 /* locktime */;
+		let total_output_size: u64 = must_pay_to.iter().map(|output|
+			8 /* value */ + 1 /* script len */ + output.script_pubkey.len() as u64
+		).sum();
+		let total_satisfaction_weight: u64 = must_spend.iter().map(|input| input.satisfaction_weight).sum();
+		let total_input_weight = (BASE_INPUT_WEIGHT * must_spend.len() as u64) + total_satisfaction_weight;
+
+		let preexisting_tx_weight = 2 /* segwit marker & flag */ + total_input_weight +
+			((BASE_TX_SIZE + total_output_size) * WITNESS_SCALE_FACTOR as u64);
+		let target_amount_sat = must_pay_to.iter().map(|output| output.value).sum();
+		let do_coin_selection = |force_conflicting_utxo_spend: bool, tolerate_high_network_feerates: bool| {
+			log_debug!(self.logger, "Attempting coin selection targeting {} sat/kW (force_conflicting_utxo_spend = {}, tolerate_high_network_feerates = {})",
+				target_feerate_sat_per_1000_weight, force_conflicting_utxo_spend, tolerate_high_network_feerates);
+			self.select_confirmed_utxos_internal(
+				&utxos, purpose, force_conflicting_utxo_spend, tolerate_high_network_feerates,
+				target_feerate_sat_per_1000_weight, preexisting_tx_weight, target_amount_sat,
+			)
+		};
+		let res = do_coin_selection(false, false)
+			.or_else(|_| do_coin_selection(false, true));
+		if let UtxoReservationPurpose::FeeBump(_) = purpose {
+			res.or_else(|_| do_coin_selection(true, false))
+				.or_else(|_| do_coin_selection(true, true))
+		} else {
+			res
+		}
 	}
 
 	/// Performs coin selection on the set of UTXOs obtained from
 	/// [`WalletSource::list_confirmed_utxos`]. Its algorithm can be described as "smallest
 	/// above-dust-after-spend first", with a slight twist: we may skip UTXOs that are above dust at
 	/// the target feerate after having spent them in a separate claim transaction if
-	/// `force_conflicting_utxo_spend` is unset to avoid producing conflicting transactions. If
+	/// `force_conflicting_utxo_spend` is unset to avoid producing conflicting transactions. UTXOs
+	/// reserved for channel funding or splicing are always skipped, unless reserved for `purpose`
+	/// itself. If
 	/// `tolerate_high_network_feerates` is set, we'll attempt to spend UTXOs that contribute at
 	/// least 1 satoshi at the current feerate, otherwise, we'll only attempt to spend those which
 	/// contribute at least twice their fee.
 	fn select_confirmed_utxos_internal(
-		&self, utxos: &[Utxo], claim_id: ClaimId, force_conflicting_utxo_spend: bool,
+		&self, utxos: &[Utxo], purpose: UtxoReservationPurpose, force_conflicting_utxo_spend: bool,
 		tolerate_high_network_feerates: bool, target_feerate_sat_per_1000_weight: u32,
 		preexisting_tx_weight: u64, target_amount_sat: u64,
 	) -> Result<CoinSelection, ()> {
-		let mut locked_utxos = self.locked_utxos.lock().unwrap();
+		let mut reservations = self.reservations.reservations.lock().unwrap();
 		let mut eligible_utxos = utxos.iter().filter_map(|utxo| {
-			if let Some(utxo_claim_id) = locked_utxos.get(&utxo.outpoint) {
-				if *utxo_claim_id != claim_id && !force_conflicting_utxo_spend {
+			if let Some(reservation) = reservations.get(&utxo.outpoint) {
+				let is_fee_bump = match reservation.purpose {
+					UtxoReservationPurpose::FeeBump(_) => true,
+					_ => false,
+				};
+				if reservation.purpose != purpose && (!force_conflicting_utxo_spend || !is_fee_bump) {
 					log_trace!(self.logger, "Skipping UTXO {} to prevent conflicting spend", utxo.outpoint);
 					return None;
 				}
@@ -570,9 +787,9 @@ where
 			return Err(());
 		}
 		for utxo in &selected_utxos {
-			locked_utxos.insert(utxo.outpoint, claim_id);
+			reservations.insert(utxo.outpoint, UtxoReservation { purpose, ticks: 0 });
 		}
-		core::mem::drop(locked_utxos);
+		core::mem::drop(reservations);
 
 		let remaining_amount = selected_amount - target_amount_sat - total_fees;
 		let change_script = self.source.get_change_script()?;
@@ -605,30 +822,10 @@ where
 		&self, claim_id: ClaimId, must_spend: Vec<Input>, must_pay_to: &[TxOut],
 		target_feerate_sat_per_1000_weight: u32,
 	) -> Result<CoinSelection, ()> {
-		let utxos = self.source.list_confirmed_utxos()?;
-		// TODO: Use fee estimation utils when we upgrade to bitcoin v0.30.0.
-		const BASE_TX_SIZE: u64 = 4 /* version */ + 1 /* input count */ + 1 /* output count */ + 4 /* locktime */;
-		let total_output_size: u64 = must_pay_to.iter().map(|output|
-			8 /* value */ + 1 /* script len */ + output.script_pubkey.len() as u64
-		).sum();
-		let total_satisfaction_weight: u64 = must_spend.iter().map(|input| input.satisfaction_weight).sum();
-		let total_input_weight = (BASE_INPUT_WEIGHT * must_spend.len() as u64) + total_satisfaction_weight;
-
-		let preexisting_tx_weight = 2 /* segwit marker & flag */ + total_input_weight +
-			((BASE_TX_SIZE + total_output_size) * WITNESS_SCALE_FACTOR as u64);
-		let target_amount_sat = must_pay_to.iter().map(|output| output.value).sum();
-		let do_coin_selection = |force_conflicting_utxo_spend: bool, tolerate_high_network_feerates: bool| {
-			log_debug!(self.logger, "Attempting coin selection targeting {} sat/kW (force_conflicting_utxo_spend = {}, tolerate_high_network_feerates = {})",
-				target_feerate_sat_per_1000_weight, force_conflicting_utxo_spend, tolerate_high_network_feerates);
-			self.select_confirmed_utxos_internal(
-				&utxos, claim_id, force_conflicting_utxo_spend, tolerate_high_network_feerates,
-				target_feerate_sat_per_1000_weight, preexisting_tx_weight, target_amount_sat,
-			)
-		};
-		do_coin_selection(false, false)
-			.or_else(|_| do_coin_selection(false, true))
-			.or_else(|_| do_coin_selection(true, false))
-			.or_else(|_| do_coin_selection(true, true))
+		self.select_utxos(
+			UtxoReservationPurpose::FeeBump(claim_id), must_spend, must_pay_to,
+			target_feerate_sat_per_1000_weight,
+		)
 	}
 
 	fn sign_tx(&self, tx: Transaction) -> Result<Transaction, ()> {
@@ -767,7 +964,74 @@ where
 
 		log_info!(self.logger, "Broadcasting anchor transaction {} to bump channel close with txid {}",
 			anchor_txid, commitment_tx.txid());
-		self.broadcaster.broadcast_transactions(&[&commitment_tx, &anchor_tx]);
+		let metadata = TransactionMetadata {
+			channel_id: anchor_descriptor.channel_derivation_parameters.transaction_parameters
+				.funding_outpoint.map(|outpoint| outpoint.to_channel_id()),
+			counterparty_node_id: None,
+			transaction_type: TransactionType::UnilateralClose,
+		};
+		self.broadcaster.broadcast_transactions_with_meta(&[(&commitment_tx, metadata), (&anchor_tx, metadata)]);
+		Ok(())
+	}
+
+	/// Handles a [`BumpTransactionEvent::ContractSettlement`] event variant by producing a
+	/// fully-signed transaction spending our payout output of the settlement transaction to bump
+	/// its fee and broadcasts them to the network as a package.
+	fn handle_contract_settlement(
+		&self, claim_id: ClaimId, package_target_feerate_sat_per_1000_weight: u32,
+		settlement_tx: &Transaction, settlement_tx_fee_sat: u64, payout_input: &Input,
+	) -> Result<(), ()> {
+		// As for anchor transactions, we subtract the settlement transaction's feerate from the
+		// package target, slightly overpaying as we don't know the child's size yet.
+		let settlement_tx_sat_per_1000_weight: u32 = compute_feerate_sat_per_1000_weight(
+			settlement_tx_fee_sat, settlement_tx.weight() as u64,
+		);
+		let child_target_feerate_sat_per_1000_weight = core::cmp::max(
+			package_target_feerate_sat_per_1000_weight.saturating_sub(settlement_tx_sat_per_1000_weight),
+			FEERATE_FLOOR_SATS_PER_KW,
+		);
+
+		// The payout is sent back to its own script, such that the fee is paid by the coin-selected
+		// inputs only. As the coin selection doesn't account for the payout's input and output, we
+		// include their weight in the satisfaction weight, along with the settlement transaction's.
+		let payout_output = payout_input.previous_utxo.clone();
+		let payout_output_weight = (8 /* value */ + 1 /* script len */ + payout_output.script_pubkey.len() as u64) *
+			WITNESS_SCALE_FACTOR as u64;
+		log_debug!(self.logger, "Peforming coin selection for settlement child transaction targeting {} sat/kW",
+			child_target_feerate_sat_per_1000_weight);
+		let must_spend = vec![Input {
+			outpoint: payout_input.outpoint,
+			previous_utxo: payout_input.previous_utxo.clone(),
+			satisfaction_weight: settlement_tx.weight() as u64 + payout_input.satisfaction_weight + payout_output_weight,
+		}];
+		let coin_selection = self.utxo_source.select_confirmed_utxos(
+			claim_id, must_spend, &[], child_target_feerate_sat_per_1000_weight,
+		)?;
+
+		let mut child_tx = Transaction {
+			version: 2,
+			lock_time: PackedLockTime::ZERO,
+			input: vec![TxIn {
+				previous_output: payout_input.outpoint,
+				script_sig: Script::new(),
+				sequence: Sequence::ZERO,
+				witness: Witness::new(),
+			}],
+			output: vec![payout_output],
+		};
+		self.process_coin_selection(&mut child_tx, coin_selection);
+
+		log_debug!(self.logger, "Signing settlement child transaction {}", child_tx.txid());
+		let child_tx = self.utxo_source.sign_tx(child_tx)?;
+
+		log_info!(self.logger, "Broadcasting child transaction {} to bump settlement transaction {}",
+			child_tx.txid(), settlement_tx.txid());
+		let metadata = TransactionMetadata {
+			channel_id: None,
+			counterparty_node_id: None,
+			transaction_type: TransactionType::ContractSettlement,
+		};
+		self.broadcaster.broadcast_transactions_with_meta(&[(settlement_tx, metadata), (&child_tx, metadata)]);
 		Ok(())
 	}
 
@@ -839,7 +1103,13 @@ where
 		}
 
 		log_info!(self.logger, "Broadcasting {}", log_tx!(htlc_tx));
-		self.broadcaster.broadcast_transactions(&[&htlc_tx]);
+		self.broadcaster.broadcast_transactions_with_meta(&[(&htlc_tx, TransactionMetadata {
+			channel_id: htlc_descriptors.first().and_then(|htlc_descriptor|
+				htlc_descriptor.channel_derivation_parameters.transaction_parameters.funding_outpoint)
+				.map(|outpoint| outpoint.to_channel_id()),
+			counterparty_node_id: None,
+			transaction_type: TransactionType::Claim,
+		})]);
 		Ok(())
 	}
 
@@ -872,6 +1142,143 @@ where
 						htlc_descriptors[0].commitment_txid);
 				}
 			}
+			BumpTransactionEvent::ContractSettlement {
+				claim_id, package_target_feerate_sat_per_1000_weight, settlement_tx,
+				settlement_tx_fee_satoshis, payout_input,
+			} => {
+				log_info!(self.logger, "Handling contract settlement bump (claim_id = {}, settlement_txid = {})",
+					log_bytes!(claim_id.0), settlement_tx.txid());
+				if let Err(_) = self.handle_contract_settlement(
+					*claim_id, *package_target_feerate_sat_per_1000_weight, settlement_tx,
+					*settlement_tx_fee_satoshis, payout_input,
+				) {
+					log_error!(self.logger, "Failed bumping settlement transaction fee for {}",
+						settlement_tx.txid());
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::sign::KeysManager;
+	use crate::util::test_utils::{TestBroadcaster, TestLogger, TestWalletSource};
+
+	use bitcoin::hashes::Hash;
+	use bitcoin::secp256k1::SecretKey;
+
+	#[test]
+	fn funding_utxos_are_not_used_for_fee_bumps() {
+		let logger = TestLogger::new();
+		let source = TestWalletSource::new(SecretKey::from_slice(&[42; 32]).unwrap());
+		let funding_outpoint = OutPoint { txid: Txid::all_zeros(), vout: 0 };
+		let other_outpoint = OutPoint { txid: Txid::all_zeros(), vout: 1 };
+		source.add_utxo(funding_outpoint, 1_000_000);
+		source.add_utxo(other_outpoint, 100_000);
+		let wallet = Wallet::new(&source, &logger);
+
+		// A fee bump picks the smallest UTXO and reserves it.
+		let claim_a = UtxoReservationPurpose::FeeBump(ClaimId([2; 32]));
+		let selection = wallet.select_confirmed_utxos(ClaimId([2; 32]), Vec::new(), &[], 253).unwrap();
+		assert_eq!(selection.confirmed_utxos.len(), 1);
+		assert_eq!(selection.confirmed_utxos[0].outpoint, other_outpoint);
+		assert_eq!(wallet.reservations().reserved_for(&other_outpoint), Some(claim_a));
+
+		// Funding won't touch UTXOs reserved for a fee bump.
+		let funding_purpose = UtxoReservationPurpose::ChannelFunding([1; 32]);
+		let funding_output = TxOut { value: 900_000, script_pubkey: Script::new() };
+		let selection = wallet.select_utxos_for_funding(funding_purpose, &[funding_output.clone()], 253).unwrap();
+		assert_eq!(selection.confirmed_utxos.len(), 1);
+		assert_eq!(selection.confirmed_utxos[0].outpoint, funding_outpoint);
+		assert_eq!(wallet.reservations().reserved_for(&funding_outpoint), Some(funding_purpose));
+
+		// Another fee bump may double spend the first one's UTXO, but never the funding UTXO.
+		let claim_b = UtxoReservationPurpose::FeeBump(ClaimId([3; 32]));
+		let selection = wallet.select_confirmed_utxos(ClaimId([3; 32]), Vec::new(), &[], 253).unwrap();
+		assert_eq!(selection.confirmed_utxos.len(), 1);
+		assert_eq!(selection.confirmed_utxos[0].outpoint, other_outpoint);
+		assert_eq!(wallet.reservations().reserved_for(&other_outpoint), Some(claim_b));
+		assert!(wallet.select_confirmed_utxos(
+			ClaimId([3; 32]), Vec::new(), &[TxOut { value: 200_000, script_pubkey: Script::new() }], 253
+		).is_err());
+
+		// Neither can a splice of another channel pick any of the reserved UTXOs.
+		let splice_purpose = UtxoReservationPurpose::Splice([4; 32]);
+		assert!(wallet.select_utxos_for_funding(splice_purpose, &[funding_output.clone()], 253).is_err());
+		assert_eq!(wallet.reservations().reserve(&[funding_outpoint], claim_a), Err(funding_outpoint));
+
+		// Once the reservations expire, the UTXOs become available again.
+		for _ in 0..UTXO_RESERVATION_EXPIRY_TICKS {
+			assert!(wallet.reservations().reserved_for(&funding_outpoint).is_some());
+			wallet.timer_tick_occurred();
 		}
+		assert!(wallet.reservations().reserved_for(&funding_outpoint).is_none());
+		assert!(wallet.reservations().reserved_for(&other_outpoint).is_none());
+		wallet.select_utxos_for_funding(splice_purpose, &[funding_output], 253).unwrap();
+		assert_eq!(wallet.reservations().reserved_for(&funding_outpoint), Some(splice_purpose));
+		assert_eq!(wallet.reservations().reserved_for(&other_outpoint), Some(splice_purpose));
+
+		// Spent UTXOs are forgotten about on the next coin selection, which fails here as the
+		// remaining UTXO is reserved for the splice.
+		source.remove_utxo(funding_outpoint);
+		assert!(wallet.select_confirmed_utxos(ClaimId([2; 32]), Vec::new(), &[], 253).is_err());
+		assert!(wallet.reservations().reserved_for(&funding_outpoint).is_none());
+
+		wallet.reservations().release(splice_purpose);
+		assert!(wallet.reservations().reserved_for(&other_outpoint).is_none());
+	}
+
+	#[test]
+	fn bumps_contract_settlements() {
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(bitcoin::Network::Testnet);
+		let keys = KeysManager::new(&[42; 32], 42, 42);
+		let source = TestWalletSource::new(SecretKey::from_slice(&[42; 32]).unwrap());
+		let wallet_outpoint = OutPoint { txid: Txid::all_zeros(), vout: 0 };
+		source.add_utxo(wallet_outpoint, 100_000);
+		let wallet = Wallet::new(&source, &logger);
+		let handler = BumpTransactionEventHandler::new(&broadcaster, &wallet, &keys, &logger);
+
+		let payout_script = Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[1]));
+		let settlement_tx = Transaction {
+			version: 2,
+			lock_time: PackedLockTime(500_000),
+			input: vec![TxIn {
+				previous_output: OutPoint { txid: Txid::all_zeros(), vout: 1 },
+				script_sig: Script::new(),
+				sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+				witness: Witness::new(),
+			}],
+			output: vec![TxOut { value: 25_000, script_pubkey: payout_script.clone() }],
+		};
+		let payout_utxo = Utxo::new_v0_p2wpkh(OutPoint { txid: settlement_tx.txid(), vout: 0 }, 25_000,
+			&WPubkeyHash::hash(&[1]));
+		handler.handle_event(&BumpTransactionEvent::ContractSettlement {
+			claim_id: ClaimId(settlement_tx.txid().into_inner()),
+			package_target_feerate_sat_per_1000_weight: 10_000,
+			settlement_tx: settlement_tx.clone(),
+			settlement_tx_fee_satoshis: 0,
+			payout_input: Input {
+				outpoint: payout_utxo.outpoint,
+				previous_utxo: payout_utxo.output.clone(),
+				satisfaction_weight: payout_utxo.satisfaction_weight,
+			},
+		});
+
+		// The child spends the payout and a wallet UTXO, returning the payout to its script and
+		// paying the fee for the whole package out of the wallet UTXO.
+		let txn = broadcaster.txn_broadcast();
+		assert_eq!(txn.len(), 2);
+		assert_eq!(txn[0], settlement_tx);
+		let child_tx = &txn[1];
+		assert_eq!(child_tx.input[0].previous_output, payout_utxo.outpoint);
+		assert_eq!(child_tx.input[1].previous_output, wallet_outpoint);
+		assert_eq!(child_tx.output[0], payout_utxo.output);
+		let fee = 25_000 + 100_000 - child_tx.output.iter().map(|txout| txout.value).sum::<u64>();
+		let package_weight = settlement_tx.weight() as u64 + child_tx.weight() as u64;
+		assert!(fee >= fee_for_weight(10_000, package_weight));
 	}
 }
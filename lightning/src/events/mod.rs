@@ -18,17 +18,23 @@ pub mod bump_transaction;
 
 pub use bump_transaction::BumpTransactionEvent;
 
+use crate::blinded_path::BlindedPath;
 use crate::sign::SpendableOutputDescriptor;
 use crate::ln::channelmanager::{InterceptId, PaymentId, RecipientOnionFields};
+use crate::ln::contractmanager::{ContractExerciseStatus, ContractId, DisputePackage};
+use crate::ln::contracts::SettlementBundle;
+use crate::ln::oracle::OracleAnnouncement;
+use crate::onion_message::{OnionMessageDeliveryId, OnionMessageReceivedVia, OnionMessageRequestId};
 use crate::ln::channel::FUNDING_CONF_DEADLINE_BLOCKS;
 use crate::ln::features::ChannelTypeFeatures;
 use crate::ln::msgs;
 use crate::ln::{PaymentPreimage, PaymentHash, PaymentSecret};
 use crate::routing::gossip::NetworkUpdate;
 use crate::util::errors::APIError;
+use crate::util::persist::PersistenceHealthStatus;
 use crate::util::ser::{BigSize, FixedLengthReader, Writeable, Writer, MaybeReadable, Readable, RequiredWrapper, UpgradableRequired, WithoutLength};
 use crate::util::string::UntrustedString;
-use crate::routing::router::{BlindedTail, Path, RouteHop, RouteParameters};
+use crate::routing::router::{BlindedTail, Path, Route, RouteHop, RouteParameters};
 
 use bitcoin::{PackedLockTime, Transaction, OutPoint};
 use bitcoin::blockdata::script::Script;
@@ -276,6 +282,19 @@ impl_writeable_tlv_based_enum!(InterceptNextHop,
 	};
 );
 
+/// The lifecycle stage an HTLC reached. Used in [`Event::HTLCTimeline`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HTLCTimelineStage {
+	/// The HTLC was offered, i.e. an `update_add_htlc` was sent or received.
+	Added,
+	/// The addition of the HTLC was irrevocably committed to by both parties.
+	Committed,
+	/// The removal of the HTLC after it was fulfilled was irrevocably committed to by both parties.
+	Fulfilled,
+	/// The removal of the HTLC after it was failed was irrevocably committed to by both parties.
+	Failed,
+}
+
 /// The reason the payment failed. Used in [`Event::PaymentFailed`].
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum PaymentFailureReason {
@@ -454,6 +473,14 @@ pub enum Event {
 		/// The purpose of the claimed payment, i.e. whether the payment was for an invoice or a
 		/// spontaneous payment.
 		purpose: PaymentPurpose,
+		/// The amount, in thousandths of a satoshi, by which the sender-intended value of the
+		/// claimed HTLCs fell short of the payment's total amount.
+		///
+		/// This is only set if the payment was claimed for less than its total amount, which can
+		/// only happen if it was allowed via [`ChannelManager::allow_partial_claim`].
+		///
+		/// [`ChannelManager::allow_partial_claim`]: crate::ln::channelmanager::ChannelManager::allow_partial_claim
+		shortfall_msat: Option<u64>,
 	},
 	/// Indicates an outbound payment we made succeeded (i.e. it made it all the way to its target
 	/// and we got back the payment preimage for it).
@@ -573,6 +600,89 @@ pub enum Event {
 #[cfg(test)]
 		error_data: Option<Vec<u8>>,
 	},
+	/// Indicates that we sent (part of) an outbound payment over the given route, reporting the
+	/// exact paths used by each attempt.
+	///
+	/// This event is only generated for payments sent via
+	/// [`ChannelManager::send_payment_with_pinned_route`], once for the initial attempt and once
+	/// for each retry.
+	///
+	/// [`ChannelManager::send_payment_with_pinned_route`]: crate::ln::channelmanager::ChannelManager::send_payment_with_pinned_route
+	PaymentAttemptSent {
+		/// The `payment_id` passed to [`ChannelManager::send_payment_with_pinned_route`].
+		///
+		/// [`ChannelManager::send_payment_with_pinned_route`]: crate::ln::channelmanager::ChannelManager::send_payment_with_pinned_route
+		payment_id: PaymentId,
+		/// The hash that was given to [`ChannelManager::send_payment_with_pinned_route`].
+		///
+		/// [`ChannelManager::send_payment_with_pinned_route`]: crate::ln::channelmanager::ChannelManager::send_payment_with_pinned_route
+		payment_hash: PaymentHash,
+		/// The number of retries made prior to this attempt, i.e. 0 for the initial attempt.
+		attempt: u32,
+		/// The route this attempt was sent over, after any changes made by
+		/// [`Router::update_pinned_route`].
+		///
+		/// Note that some paths in this route may have failed to send immediately, which will be
+		/// reported via [`Event::PaymentPathFailed`].
+		///
+		/// [`Router::update_pinned_route`]: crate::routing::router::Router::update_pinned_route
+		route: Route,
+	},
+	/// Indicates that our best known block height reached an outbound payment's
+	/// [`PaymentParameters::deadline_block_height`], or the current time its
+	/// [`PaymentParameters::deadline_time`], and thus that it will no longer be retried.
+	///
+	/// Any HTLCs for the payment which are still in-flight are left to resolve on their own. Unless
+	/// one of them succeeds (resulting in an [`Event::PaymentSent`]), an [`Event::PaymentFailed`]
+	/// with [`PaymentFailureReason::PaymentExpired`] will follow once all have failed.
+	///
+	/// [`PaymentParameters::deadline_block_height`]: crate::routing::router::PaymentParameters::deadline_block_height
+	/// [`PaymentParameters::deadline_time`]: crate::routing::router::PaymentParameters::deadline_time
+	PaymentDeadlineExceeded {
+		/// The `payment_id` passed to [`ChannelManager::send_payment`].
+		///
+		/// [`ChannelManager::send_payment`]: crate::ln::channelmanager::ChannelManager::send_payment
+		payment_id: PaymentId,
+		/// The hash that was given to [`ChannelManager::send_payment`].
+		///
+		/// [`ChannelManager::send_payment`]: crate::ln::channelmanager::ChannelManager::send_payment
+		payment_hash: PaymentHash,
+		/// The [`PaymentParameters::deadline_block_height`] of the payment, if it was reached.
+		///
+		/// [`PaymentParameters::deadline_block_height`]: crate::routing::router::PaymentParameters::deadline_block_height
+		deadline_block_height: Option<u32>,
+		/// The [`PaymentParameters::deadline_time`] of the payment, if it was reached.
+		///
+		/// [`PaymentParameters::deadline_time`]: crate::routing::router::PaymentParameters::deadline_time
+		deadline_time: Option<u64>,
+	},
+	/// Indicates that an HTLC in one of our channels reached a new [`HTLCTimelineStage`].
+	///
+	/// This is only generated if the `htlc_timeline_events` feature is enabled. The `timestamp`
+	/// is taken when the HTLC reaches the stage rather than when the event is surfaced, making
+	/// these events suitable for measuring per-hop latency of our own node.
+	///
+	/// Note that this event is never persisted, and thus may be lost if the node restarts before
+	/// it is handled.
+	#[cfg(feature = "htlc_timeline_events")]
+	HTLCTimeline {
+		/// The channel the HTLC is in.
+		channel_id: [u8; 32],
+		/// The id of the HTLC, unique per channel and direction.
+		htlc_id: u64,
+		/// Whether the HTLC was offered to us or by us.
+		direction: crate::ln::channelmanager::HTLCDirection,
+		/// The payment hash of the HTLC.
+		payment_hash: PaymentHash,
+		/// The stage the HTLC reached.
+		stage: HTLCTimelineStage,
+		/// The time at which the HTLC reached the stage, as returned by the [`TimeSource`] set via
+		/// [`ChannelManager::set_htlc_timeline_time_source`].
+		///
+		/// [`TimeSource`]: crate::util::time::TimeSource
+		/// [`ChannelManager::set_htlc_timeline_time_source`]: crate::ln::channelmanager::ChannelManager::set_htlc_timeline_time_source
+		timestamp: Duration,
+	},
 	/// Indicates that a probe payment we sent returned successful, i.e., only failed at the destination.
 	ProbeSuccessful {
 		/// The id returned by [`ChannelManager::send_probe`].
@@ -649,6 +759,38 @@ pub enum Event {
 		/// LDK will not stop you from forwarding more than you received.
 		expected_outbound_amount_msat: u64,
 	},
+	/// Indicates that an HTLC paying us was held back for validation, as
+	/// [`InboundHTLCValidationConfig::hold_inbound_htlcs`] is set. Nothing about the HTLC has been
+	/// committed to yet, and it will not be part of an [`Event::PaymentClaimable`] until accepted.
+	///
+	/// [`ChannelManager::accept_held_htlc`] or [`ChannelManager::fail_held_htlc`] should be called
+	/// in response to this event. If neither is called within
+	/// [`InboundHTLCValidationConfig::decision_timeout_ticks`], or before the HTLC gets close to
+	/// expiring, the HTLC is failed back.
+	///
+	/// [`InboundHTLCValidationConfig::hold_inbound_htlcs`]: crate::util::config::InboundHTLCValidationConfig::hold_inbound_htlcs
+	/// [`InboundHTLCValidationConfig::decision_timeout_ticks`]: crate::util::config::InboundHTLCValidationConfig::decision_timeout_ticks
+	/// [`ChannelManager::accept_held_htlc`]: crate::ln::channelmanager::ChannelManager::accept_held_htlc
+	/// [`ChannelManager::fail_held_htlc`]: crate::ln::channelmanager::ChannelManager::fail_held_htlc
+	HTLCHeldForValidation {
+		/// An id to pass to [`ChannelManager::accept_held_htlc`] or
+		/// [`ChannelManager::fail_held_htlc`].
+		///
+		/// [`ChannelManager::accept_held_htlc`]: crate::ln::channelmanager::ChannelManager::accept_held_htlc
+		/// [`ChannelManager::fail_held_htlc`]: crate::ln::channelmanager::ChannelManager::fail_held_htlc
+		hold_id: InterceptId,
+		/// The payment hash used for this HTLC.
+		payment_hash: PaymentHash,
+		/// The value, in thousandths of a satoshi, of this HTLC.
+		amount_msat: u64,
+		/// The fields in the onion which were received with the HTLC, including any custom TLVs
+		/// the sender included.
+		onion_fields: RecipientOnionFields,
+		/// The block height at which this HTLC expires.
+		cltv_expiry: u32,
+		/// The channel over which the HTLC was received.
+		via_channel_id: [u8; 32],
+	},
 	/// Used to indicate that an output which you should know how to spend was confirmed on chain
 	/// and is now spendable.
 	/// Such an output will *not* ever be spent by rust-lightning, and are not at risk of your
@@ -715,6 +857,13 @@ pub enum Event {
 		counterparty_node_id: PublicKey,
 		/// The outpoint of the channel's funding transaction.
 		funding_txo: OutPoint,
+		/// The custom TLVs our counterparty attached to their `open_channel` or `accept_channel`
+		/// message, sorted by type.
+		///
+		/// See [`ChannelManager::create_channel_with_custom_tlvs`] for attaching our own.
+		///
+		/// [`ChannelManager::create_channel_with_custom_tlvs`]: crate::ln::channelmanager::ChannelManager::create_channel_with_custom_tlvs
+		counterparty_custom_tlvs: Vec<(u64, Vec<u8>)>,
 	},
 	/// Used to indicate that a channel with the given `channel_id` is ready to
 	/// be used. This event is emitted either when the funding transaction has been confirmed
@@ -765,6 +914,505 @@ pub enum Event {
 		/// The full transaction received from the user
 		transaction: Transaction
 	},
+	/// Indicates that a channel has seen no activity for
+	/// [`IdleChannelConfig::idle_timer_ticks_threshold`] consecutive calls to
+	/// [`ChannelManager::timer_tick_occurred`].
+	///
+	/// If [`IdleChannelConfig::action`] is [`IdleChannelAction::CooperativeClose`], the
+	/// `ChannelManager` will initiate a cooperative close of the channel once feerates are low
+	/// enough, unless the counterparty has been allow-listed via
+	/// [`ChannelManager::set_idle_close_allowlisted`]. Otherwise, the user may decide to close the
+	/// channel via [`ChannelManager::close_channel`].
+	///
+	/// This event is generated once per idle period, i.e. it will be generated again if the channel
+	/// sees activity and then becomes idle again.
+	///
+	/// [`IdleChannelConfig::idle_timer_ticks_threshold`]: crate::util::config::IdleChannelConfig::idle_timer_ticks_threshold
+	/// [`IdleChannelConfig::action`]: crate::util::config::IdleChannelConfig::action
+	/// [`IdleChannelAction::CooperativeClose`]: crate::util::config::IdleChannelAction::CooperativeClose
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	/// [`ChannelManager::set_idle_close_allowlisted`]: crate::ln::channelmanager::ChannelManager::set_idle_close_allowlisted
+	/// [`ChannelManager::close_channel`]: crate::ln::channelmanager::ChannelManager::close_channel
+	ChannelIdle {
+		/// The `channel_id` of the idle channel.
+		channel_id: [u8; 32],
+		/// The `user_channel_id` value passed in to [`ChannelManager::create_channel`] for outbound
+		/// channels, or to [`ChannelManager::accept_inbound_channel`] for inbound channels if
+		/// [`UserConfig::manually_accept_inbound_channels`] config flag is set to true. Otherwise
+		/// `user_channel_id` will be randomized for an inbound channel.
+		///
+		/// [`ChannelManager::create_channel`]: crate::ln::channelmanager::ChannelManager::create_channel
+		/// [`ChannelManager::accept_inbound_channel`]: crate::ln::channelmanager::ChannelManager::accept_inbound_channel
+		/// [`UserConfig::manually_accept_inbound_channels`]: crate::util::config::UserConfig::manually_accept_inbound_channels
+		user_channel_id: u128,
+		/// The `node_id` of the channel counterparty.
+		counterparty_node_id: PublicKey,
+		/// The number of consecutive timer ticks the channel has been idle for.
+		idle_timer_ticks: u64,
+	},
+	/// Indicates that our counterparty proposed renewing a contract with new terms.
+	///
+	/// To accept the new terms, call [`ContractManager::accept_contract_renewal`]. To reject them,
+	/// call [`ContractManager::abort_contract_renewal`]. Until either is done, the contract is
+	/// quiesced.
+	///
+	/// [`ContractManager::accept_contract_renewal`]: crate::ln::contractmanager::ContractManager::accept_contract_renewal
+	/// [`ContractManager::abort_contract_renewal`]: crate::ln::contractmanager::ContractManager::abort_contract_renewal
+	ContractRenewalRequest {
+		/// The id of the contract to be renewed.
+		contract_id: ContractId,
+		/// The id the contract will have once renewed.
+		new_contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// The proposed settlement bundle, not yet signed by our counterparty.
+		settlement_bundle: SettlementBundle,
+		/// The announcement of the oracle the new terms depend on, whose attestation points are
+		/// the adaptor points of the new settlement bundle. This is only `None` for events
+		/// serialized by versions which accepted proposals without an announcement, and complies
+		/// with our policy if we set an [`OraclePolicy`].
+		///
+		/// [`OraclePolicy`]: crate::ln::oracle::OraclePolicy
+		oracle_announcement: Option<OracleAnnouncement>,
+	},
+	/// Indicates that a contract has been renewed, with the previous contract having been
+	/// atomically replaced by the new one.
+	ContractRenewed {
+		/// The id of the contract before the renewal, which is no longer tracked.
+		previous_contract_id: ContractId,
+		/// The id of the renewed contract.
+		contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+	},
+	/// Indicates that a renewal of a contract was aborted, either by us or our counterparty. The
+	/// contract remains unchanged.
+	ContractRenewalFailed {
+		/// The id of the contract which was to be renewed.
+		contract_id: ContractId,
+		/// The id the contract would have had once renewed.
+		new_contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// A human-readable reason for the failure.
+		reason: String,
+	},
+	/// Indicates that our counterparty proposed settling a contract off-chain for an outcome the
+	/// oracle attested to.
+	///
+	/// The attestation was already checked against the outcome's adaptor point. To settle the
+	/// contract, call [`ContractManager::accept_mutual_settlement`], otherwise call
+	/// [`ContractManager::reject_mutual_settlement`].
+	///
+	/// [`ContractManager::accept_mutual_settlement`]: crate::ln::contractmanager::ContractManager::accept_mutual_settlement
+	/// [`ContractManager::reject_mutual_settlement`]: crate::ln::contractmanager::ContractManager::reject_mutual_settlement
+	ContractSettlementRequest {
+		/// The id of the contract to settle.
+		contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// The outcome to settle the contract for.
+		outcome: Vec<u8>,
+		/// Our payout for `outcome`, in satoshis.
+		holder_payout_satoshis: u64,
+		/// Our counterparty's payout for `outcome`, in satoshis.
+		counterparty_payout_satoshis: u64,
+	},
+	/// Indicates that a contract has been settled off-chain for an outcome the oracle attested to
+	/// and is no longer tracked.
+	///
+	/// This is generated once the transaction releasing the contract's collateral both parties
+	/// signed when agreeing to settle it reached [`ANTI_REORG_DELAY`] confirmations, such that
+	/// none of the contract's settlement transactions can confirm anymore. It is also generated
+	/// once one of the contract's settlement transactions confirmed on-chain, in which case no
+	/// payment is sent in either direction.
+	///
+	/// When settled off-chain, the release transaction returns each party's contribution to the
+	/// collateral, less its share of the fee. If our payout is lower than our contribution, the
+	/// difference was sent to our counterparty as a payment over the contract's channel.
+	/// Conversely, if our counterparty owes us, we'll receive an [`Event::PaymentClaimable`] for a
+	/// spontaneous payment whose [`RecipientOnionFields::payment_metadata`] is the `contract_id`,
+	/// which should be claimed.
+	///
+	/// [`ANTI_REORG_DELAY`]: crate::chain::channelmonitor::ANTI_REORG_DELAY
+	ContractSettled {
+		/// The id of the settled contract.
+		contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// The outcome the contract was settled for.
+		outcome: Vec<u8>,
+		/// Our payout for `outcome`, in satoshis.
+		holder_payout_satoshis: u64,
+		/// Our counterparty's payout for `outcome`, in satoshis.
+		counterparty_payout_satoshis: u64,
+		/// The id of the payment we sent to our counterparty for the amount we owed them.
+		///
+		/// This is `None` if we didn't owe our counterparty anything or if the payment could not
+		/// be sent.
+		payment_id: Option<PaymentId>,
+	},
+	/// Indicates that our counterparty rejected our proposal to settle a contract off-chain, or
+	/// accepted it without a valid signature for the transaction releasing the contract's
+	/// collateral. The contract remains unchanged.
+	ContractSettlementFailed {
+		/// The id of the contract which was to be settled.
+		contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// A human-readable reason for the failure.
+		reason: String,
+	},
+	/// Indicates that our counterparty requested that we top up the margin of a contract by paying
+	/// them.
+	///
+	/// To pay the requested amount over the contract's channel, call
+	/// [`ContractManager::pay_margin_call`]. To reject the request, call
+	/// [`ContractManager::reject_margin_call`].
+	///
+	/// [`ContractManager::pay_margin_call`]: crate::ln::contractmanager::ContractManager::pay_margin_call
+	/// [`ContractManager::reject_margin_call`]: crate::ln::contractmanager::ContractManager::reject_margin_call
+	MarginCallReceived {
+		/// The id of the contract whose margin is to be topped up.
+		contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// The amount requested, in satoshis.
+		top_up_satoshis: u64,
+		/// The time by which the amount is to be paid, as a UNIX timestamp.
+		deadline: u64,
+	},
+	/// Indicates that our counterparty rejected a margin call we sent via
+	/// [`ContractManager::send_margin_call`].
+	///
+	/// [`ContractManager::send_margin_call`]: crate::ln::contractmanager::ContractManager::send_margin_call
+	MarginCallRejected {
+		/// The id of the contract whose margin was to be topped up.
+		contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// The amount requested, in satoshis.
+		top_up_satoshis: u64,
+		/// The deadline of the rejected margin call.
+		deadline: u64,
+		/// A human-readable reason for the rejection.
+		reason: String,
+	},
+	/// Indicates that a settlement transaction of a contract which has since been renewed
+	/// confirmed on-chain, i.e. that the contract was settled according to outdated terms.
+	///
+	/// The included [`DisputePackage`] should be handed off to whoever contests the settlement,
+	/// e.g. an operator or an arbitration service, as soon as possible.
+	ContractDisputeDetected {
+		/// The evidence of the outdated settlement.
+		package: DisputePackage,
+	},
+	/// Indicates that a share of a collateral pool was allocated to a contract, either as our
+	/// counterparty accepted our [`ContractManager::propose_pool_allocation`] or as we accepted
+	/// theirs.
+	///
+	/// [`ContractManager::propose_pool_allocation`]: crate::ln::contractmanager::ContractManager::propose_pool_allocation
+	PoolAllocationAdded {
+		/// The collateral output of the pool.
+		pool_outpoint: crate::chain::transaction::OutPoint,
+		/// The contract collateral was allocated to.
+		contract_id: ContractId,
+		/// The `node_id` of the pool counterparty.
+		counterparty_node_id: PublicKey,
+		/// The share of our contribution to the pool allocated to the contract, in satoshis.
+		holder_collateral_satoshis: u64,
+		/// The share of our counterparty's contribution to the pool allocated to the contract, in
+		/// satoshis.
+		counterparty_collateral_satoshis: u64,
+	},
+	/// Indicates that our counterparty rejected our proposal to allocate a share of a collateral
+	/// pool to a contract.
+	PoolAllocationFailed {
+		/// The collateral output of the pool.
+		pool_outpoint: crate::chain::transaction::OutPoint,
+		/// The contract collateral was to be allocated to.
+		contract_id: ContractId,
+		/// The `node_id` of the pool counterparty.
+		counterparty_node_id: PublicKey,
+		/// A human-readable reason for the failure.
+		reason: String,
+	},
+	/// Indicates progress in automatically exercising a contract which has matured, see the
+	/// [`ContractManager`] documentation for details.
+	///
+	/// [`ContractManager`]: crate::ln::contractmanager::ContractManager
+	ContractExerciseProgress {
+		/// The id of the contract being exercised.
+		contract_id: ContractId,
+		/// The `node_id` of the contract counterparty.
+		counterparty_node_id: PublicKey,
+		/// What has been done, and what may be required of the user.
+		status: ContractExerciseStatus,
+	},
+	/// Indicates that the health of the storage backing a [`ChainMonitor`] crossed one of the
+	/// thresholds set via [`ChainMonitor::set_persistence_health_thresholds`], or recovered after
+	/// having done so.
+	///
+	/// While persistence is unhealthy you should consider no longer accepting new HTLCs or
+	/// channels, as failing to persist a [`ChannelMonitorUpdate`] will force-close the affected
+	/// channel.
+	///
+	/// [`ChainMonitor`]: crate::chain::chainmonitor::ChainMonitor
+	/// [`ChainMonitor::set_persistence_health_thresholds`]: crate::chain::chainmonitor::ChainMonitor::set_persistence_health_thresholds
+	/// [`ChannelMonitorUpdate`]: crate::chain::channelmonitor::ChannelMonitorUpdate
+	PersistenceHealth {
+		/// The health snapshot which caused this event to be generated.
+		health: PersistenceHealthStatus,
+		/// Whether `health` is within the configured thresholds.
+		is_healthy: bool,
+	},
+	/// Indicates that a transaction paying to an on-chain fallback script registered via
+	/// [`ChannelManager::watch_onchain_fallback`] has reached [`ANTI_REORG_DELAY`] confirmations.
+	///
+	/// This is the on-chain equivalent of [`Event::PaymentClaimable`], except that the funds are
+	/// already ours and thus nothing needs to be claimed. Note that the amount is not checked
+	/// against `expected_amount_msat`; it is up to you to decide whether to consider the invoice
+	/// paid. The funds are controlled by the wallet which provided the script, not by LDK.
+	///
+	/// Only one such event is generated per registered script, after which the script is no
+	/// longer watched.
+	///
+	/// [`ChannelManager::watch_onchain_fallback`]: crate::ln::channelmanager::ChannelManager::watch_onchain_fallback
+	/// [`ANTI_REORG_DELAY`]: crate::chain::channelmonitor::ANTI_REORG_DELAY
+	OnchainFallbackPaymentReceived {
+		/// The hash of the invoice whose fallback script was paid.
+		payment_hash: PaymentHash,
+		/// The output which paid to the fallback script.
+		outpoint: OutPoint,
+		/// The value of the output paying to the fallback script.
+		amount_satoshis: u64,
+		/// The amount the invoice requested, if any.
+		expected_amount_msat: Option<u64>,
+	},
+	/// Indicates that a request sent via [`OnionMessenger::send_onion_message_request`] was not
+	/// responded to within its timeout.
+	///
+	/// Any response which arrives later is handled as an uncorrelated message by
+	/// [`CustomOnionMessageHandler::handle_custom_message_with_responder`].
+	///
+	/// [`OnionMessenger::send_onion_message_request`]: crate::onion_message::OnionMessenger::send_onion_message_request
+	/// [`CustomOnionMessageHandler::handle_custom_message_with_responder`]: crate::onion_message::CustomOnionMessageHandler::handle_custom_message_with_responder
+	OnionMessageTimedOut {
+		/// The id the request was sent with.
+		request_id: OnionMessageRequestId,
+	},
+	/// Indicates that an onion message we were asked to forward to a disconnected peer was stored
+	/// in our mailbox, to be delivered once the peer reconnects.
+	///
+	/// Only generated if the mailbox was enabled via [`OnionMessenger::set_mailbox_config`].
+	///
+	/// [`OnionMessenger::set_mailbox_config`]: crate::onion_message::OnionMessenger::set_mailbox_config
+	OnionMessageStored {
+		/// The node id of the offline peer the message is destined to.
+		peer_node_id: PublicKey,
+	},
+	/// Indicates that onion messages stored in our mailbox for an offline peer expired before the
+	/// peer reconnected, and were dropped.
+	///
+	/// Only generated if the mailbox was enabled via [`OnionMessenger::set_mailbox_config`].
+	///
+	/// [`OnionMessenger::set_mailbox_config`]: crate::onion_message::OnionMessenger::set_mailbox_config
+	OnionMessagesExpired {
+		/// The node id of the offline peer the messages were destined to.
+		peer_node_id: PublicKey,
+		/// The number of messages which were dropped.
+		count: u64,
+	},
+	/// Indicates that an onion message was queued for a node we are not connected to, and that we
+	/// should connect to it for the message to be sent.
+	///
+	/// Generated when the [`OnionMessagePath`] a message is sent along includes
+	/// [`OnionMessagePath::first_node_addresses`], such as those returned by a
+	/// [`DefaultMessageRouter`] configured with [`DefaultMessageRouterParams::connect_directly`]
+	/// when no path through our peers is known. The message is sent once the node connects, or
+	/// dropped with an [`Event::ConnectionNeededTimedOut`] if it doesn't within a number of timer
+	/// ticks.
+	///
+	/// [`OnionMessagePath`]: crate::onion_message::OnionMessagePath
+	/// [`OnionMessagePath::first_node_addresses`]: crate::onion_message::OnionMessagePath::first_node_addresses
+	/// [`DefaultMessageRouter`]: crate::onion_message::DefaultMessageRouter
+	/// [`DefaultMessageRouterParams::connect_directly`]: crate::onion_message::DefaultMessageRouterParams::connect_directly
+	ConnectionNeeded {
+		/// The node id of the node to connect to.
+		node_id: PublicKey,
+		/// The addresses the node has announced, which may be used to connect to it.
+		addresses: Vec<msgs::NetAddress>,
+	},
+	/// Indicates that onion messages queued for a node we generated an [`Event::ConnectionNeeded`]
+	/// for were dropped, as the node didn't connect in time.
+	ConnectionNeededTimedOut {
+		/// The node id of the node we needed to connect to.
+		node_id: PublicKey,
+		/// The number of messages which were dropped.
+		count: u64,
+	},
+	/// Indicates that onion messages queued for a peer were dropped to make room for newer ones, as
+	/// our outbound buffers were full.
+	///
+	/// Only generated if [`OnionMessageEvictionPolicy::DropOldest`] was configured via
+	/// [`OnionMessenger::set_config`].
+	///
+	/// [`OnionMessageEvictionPolicy::DropOldest`]: crate::onion_message::OnionMessageEvictionPolicy::DropOldest
+	/// [`OnionMessenger::set_config`]: crate::onion_message::OnionMessenger::set_config
+	OnionMessagesEvicted {
+		/// The node id of the peer the messages were destined to.
+		peer_node_id: PublicKey,
+		/// The number of messages which were dropped.
+		count: u64,
+	},
+	/// Indicates that our counterparty claimed an HTLC we forwarded to them over a channel with a
+	/// liquidity fee set via [`ChannelManager::set_channel_liquidity_fee`], paying the part of the
+	/// fee we skimmed from it.
+	///
+	/// [`ChannelManager::set_channel_liquidity_fee`]: crate::ln::channelmanager::ChannelManager::set_channel_liquidity_fee
+	LiquidityFeeCollected {
+		/// The channel the HTLC was forwarded over.
+		channel_id: [u8; 32],
+		/// The node id of the counterparty paying the fee.
+		counterparty_node_id: PublicKey,
+		/// The amount of the fee which was skimmed from the claimed HTLC.
+		amount_msat: u64,
+		/// The amount of the fee which our counterparty still owes us.
+		remaining_msat: u64,
+	},
+	/// Indicates that a valid receipt was received for an onion message sent via
+	/// [`OnionMessenger::send_onion_message_with_receipt`], i.e. that its recipient received it.
+	///
+	/// [`OnionMessenger::send_onion_message_with_receipt`]: crate::onion_message::OnionMessenger::send_onion_message_with_receipt
+	OnionMessageDelivered {
+		/// The id the message was sent with.
+		id: OnionMessageDeliveryId,
+	},
+	/// Indicates that no valid receipt was received for an onion message sent via
+	/// [`OnionMessenger::send_onion_message_with_receipt`] before it timed out, i.e. that it may
+	/// not have been delivered, or its recipient doesn't support receipts. The message may be
+	/// resent.
+	///
+	/// [`OnionMessenger::send_onion_message_with_receipt`]: crate::onion_message::OnionMessenger::send_onion_message_with_receipt
+	OnionMessageReceiptTimedOut {
+		/// The id the message was sent with.
+		id: OnionMessageDeliveryId,
+	},
+	/// Indicates that we received a custom onion message while delivering them as events, as
+	/// configured via [`OnionMessenger::set_custom_message_delivery`].
+	///
+	/// A response may be sent along the `reply_path` via [`OnionMessenger::send_onion_message`]
+	/// using any [`CustomOnionMessageContents`].
+	///
+	/// [`OnionMessenger::set_custom_message_delivery`]: crate::onion_message::OnionMessenger::set_custom_message_delivery
+	/// [`OnionMessenger::send_onion_message`]: crate::onion_message::OnionMessenger::send_onion_message
+	/// [`CustomOnionMessageContents`]: crate::onion_message::CustomOnionMessageContents
+	CustomOnionMessageReceived {
+		/// The TLV type of the message.
+		tlv_type: u64,
+		/// The value of the message's TLV record, i.e. its serialized contents.
+		data: Vec<u8>,
+		/// How the message reached us.
+		received_via: OnionMessageReceivedVia,
+		/// The id of the request sent via [`OnionMessenger::send_onion_message_request`] this
+		/// message responds to, if it was received before the request timed out.
+		///
+		/// [`OnionMessenger::send_onion_message_request`]: crate::onion_message::OnionMessenger::send_onion_message_request
+		request_id: Option<OnionMessageRequestId>,
+		/// The path the sender asked us to reply along, if any.
+		reply_path: Option<BlindedPath>,
+	},
+	/// Indicates that a channel's counterparty has been unresponsive while HTLCs are pending in
+	/// the channel, and that the channel will be force-closed at `force_close_height` unless the
+	/// counterparty becomes responsive again.
+	///
+	/// This event is generated once per unresponsive period, see
+	/// [`UserConfig::unresponsive_peer_config`].
+	///
+	/// [`UserConfig::unresponsive_peer_config`]: crate::util::config::UserConfig::unresponsive_peer_config
+	ChannelUnresponsive {
+		/// The `channel_id` of the channel.
+		channel_id: [u8; 32],
+		/// The `user_channel_id` value passed in to [`ChannelManager::create_channel`] for outbound
+		/// channels, or to [`ChannelManager::accept_inbound_channel`] for inbound channels if
+		/// [`UserConfig::manually_accept_inbound_channels`] config flag is set to true. Otherwise
+		/// `user_channel_id` will be randomized for an inbound channel.
+		///
+		/// [`ChannelManager::create_channel`]: crate::ln::channelmanager::ChannelManager::create_channel
+		/// [`ChannelManager::accept_inbound_channel`]: crate::ln::channelmanager::ChannelManager::accept_inbound_channel
+		/// [`UserConfig::manually_accept_inbound_channels`]: crate::util::config::UserConfig::manually_accept_inbound_channels
+		user_channel_id: u128,
+		/// The `node_id` of the channel counterparty.
+		counterparty_node_id: PublicKey,
+		/// The earliest `cltv_expiry` of the HTLCs pending in the channel.
+		earliest_htlc_expiry: u32,
+		/// The block height at which the channel will be force-closed.
+		force_close_height: u32,
+	},
+	/// Indicates that a probe sent via [`OnionMessenger::probe_path`] reached its destination,
+	/// which acknowledged it with a valid receipt.
+	///
+	/// [`OnionMessenger::probe_path`]: crate::onion_message::OnionMessenger::probe_path
+	OnionMessageProbeSuccessful {
+		/// The id returned by [`OnionMessenger::probe_path`].
+		///
+		/// [`OnionMessenger::probe_path`]: crate::onion_message::OnionMessenger::probe_path
+		id: OnionMessageDeliveryId,
+	},
+	/// Indicates that no valid receipt was received for a probe sent via
+	/// [`OnionMessenger::probe_path`] before it timed out, i.e. that the probed path is likely
+	/// unusable or its destination doesn't support receipts.
+	///
+	/// [`OnionMessenger::probe_path`]: crate::onion_message::OnionMessenger::probe_path
+	OnionMessageProbeFailed {
+		/// The id returned by [`OnionMessenger::probe_path`].
+		///
+		/// [`OnionMessenger::probe_path`]: crate::onion_message::OnionMessenger::probe_path
+		id: OnionMessageDeliveryId,
+	},
+	/// Indicates that a liveness probe sent via [`OnionMessenger::send_liveness_probe`] was
+	/// answered by its destination or timed out.
+	///
+	/// [`OnionMessenger::send_liveness_probe`]: crate::onion_message::OnionMessenger::send_liveness_probe
+	LivenessProbeCompleted {
+		/// The id returned by [`OnionMessenger::send_liveness_probe`].
+		///
+		/// [`OnionMessenger::send_liveness_probe`]: crate::onion_message::OnionMessenger::send_liveness_probe
+		id: OnionMessageDeliveryId,
+		/// The time between sending the probe and receiving the answer, in milliseconds, or `None`
+		/// if the probe timed out.
+		///
+		/// Without the `std` feature, this is always `Some(0)` for answered probes unless a
+		/// [`TimeSource`] backed by a real clock was set via [`OnionMessenger::set_time_source`].
+		///
+		/// [`TimeSource`]: crate::util::time::TimeSource
+		/// [`OnionMessenger::set_time_source`]: crate::onion_message::OnionMessenger::set_time_source
+		round_trip_time_ms: Option<u64>,
+	},
+	/// Indicates that the onion messages queued for a peer reached
+	/// [`OnionMessengerConfig::buffer_high_water_mark_bytes`], and that sends to the peer may soon
+	/// fail with [`SendError::BufferFull`]. Applications may wish to pause generating traffic for
+	/// the peer until an [`Event::OnionMessageBufferDrained`] is generated for it.
+	///
+	/// [`OnionMessengerConfig::buffer_high_water_mark_bytes`]: crate::onion_message::OnionMessengerConfig::buffer_high_water_mark_bytes
+	/// [`SendError::BufferFull`]: crate::onion_message::SendError::BufferFull
+	OnionMessageBufferHighWater {
+		/// The node id of the peer the messages are queued for.
+		peer_node_id: PublicKey,
+		/// The total size, in bytes, of the queued messages.
+		buffered_bytes: u64,
+	},
+	/// Indicates that the onion messages queued for a peer, for which an
+	/// [`Event::OnionMessageBufferHighWater`] was previously generated, drained to
+	/// [`OnionMessengerConfig::buffer_low_water_mark_bytes`], or that the peer disconnected.
+	///
+	/// [`OnionMessengerConfig::buffer_low_water_mark_bytes`]: crate::onion_message::OnionMessengerConfig::buffer_low_water_mark_bytes
+	OnionMessageBufferDrained {
+		/// The node id of the peer the messages were queued for.
+		peer_node_id: PublicKey,
+		/// The total size, in bytes, of the messages still queued.
+		buffered_bytes: u64,
+	},
 	/// Indicates a request to open a new channel by a peer.
 	///
 	/// To accept the request, call [`ChannelManager::accept_inbound_channel`]. To reject the
@@ -816,6 +1464,14 @@ pub enum Event {
 		///
 		/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
 		channel_type: ChannelTypeFeatures,
+		/// The custom TLVs the counterparty attached to their `open_channel` message, sorted by
+		/// type, e.g. referencing the terms of a contract the channel is opened for.
+		///
+		/// Custom TLVs may be attached to our `accept_channel` in turn via
+		/// [`ChannelManager::accept_inbound_channel_with_custom_tlvs`].
+		///
+		/// [`ChannelManager::accept_inbound_channel_with_custom_tlvs`]: crate::ln::channelmanager::ChannelManager::accept_inbound_channel_with_custom_tlvs
+		custom_tlvs: Vec<(u64, Vec<u8>)>,
 	},
 	/// Indicates that the HTLC was accepted, but could not be processed when or after attempting to
 	/// forward it.
@@ -839,11 +1495,31 @@ pub enum Event {
 	/// requires confirmed external funds to be readily available to spend.
 	///
 	/// LDK does not currently generate this event unless the
-	/// [`ChannelHandshakeConfig::negotiate_anchors_zero_fee_htlc_tx`] config flag is set to true.
-	/// It is limited to the scope of channels with anchor outputs.
+	/// [`ChannelHandshakeConfig::negotiate_anchors_zero_fee_htlc_tx`] config flag is set to true,
+	/// in which case it is limited to the scope of channels with anchor outputs, or a
+	/// [`ContractManager`] settles a contract on-chain.
 	///
+	/// [`ContractManager`]: crate::ln::contractmanager::ContractManager
 	/// [`ChannelHandshakeConfig::negotiate_anchors_zero_fee_htlc_tx`]: crate::util::config::ChannelHandshakeConfig::negotiate_anchors_zero_fee_htlc_tx
 	BumpTransaction(BumpTransactionEvent),
+	/// Indicates that our fee spike circuit breaker was engaged as on-chain feerates rose above
+	/// [`FeeSpikeCircuitBreakerConfig::engage_feerate_sat_per_1000_weight`]. Until an
+	/// [`Event::FeeSpikeCircuitBreakerReleased`] is generated, applications should defer
+	/// non-urgent on-chain operations, such as sweeping [`SpendableOutputDescriptor`]s.
+	///
+	/// [`FeeSpikeCircuitBreakerConfig::engage_feerate_sat_per_1000_weight`]: crate::util::config::FeeSpikeCircuitBreakerConfig::engage_feerate_sat_per_1000_weight
+	FeeSpikeCircuitBreakerEngaged {
+		/// The feerate estimate, in satoshis per 1000 weight units, which engaged the breaker.
+		feerate_sat_per_1000_weight: u32,
+	},
+	/// Indicates that our fee spike circuit breaker was released as on-chain feerates dropped
+	/// back down, see [`FeeSpikeCircuitBreakerConfig::release_feerate_sat_per_1000_weight`].
+	///
+	/// [`FeeSpikeCircuitBreakerConfig::release_feerate_sat_per_1000_weight`]: crate::util::config::FeeSpikeCircuitBreakerConfig::release_feerate_sat_per_1000_weight
+	FeeSpikeCircuitBreakerReleased {
+		/// The feerate estimate, in satoshis per 1000 weight units, which released the breaker.
+		feerate_sat_per_1000_weight: u32,
+	},
 }
 
 impl Writeable for Event {
@@ -999,12 +1675,13 @@ impl Writeable for Event {
 				// We never write the OpenChannelRequest events as, upon disconnection, peers
 				// drop any channels which have not yet exchanged funding_signed.
 			},
-			&Event::PaymentClaimed { ref payment_hash, ref amount_msat, ref purpose, ref receiver_node_id } => {
+			&Event::PaymentClaimed { ref payment_hash, ref amount_msat, ref purpose, ref receiver_node_id, ref shortfall_msat } => {
 				19u8.write(writer)?;
 				write_tlv_fields!(writer, {
 					(0, payment_hash, required),
 					(1, receiver_node_id, option),
 					(2, purpose, required),
+					(3, shortfall_msat, option),
 					(4, amount_msat, required),
 				});
 			},
@@ -1037,10 +1714,11 @@ impl Writeable for Event {
 			&Event::BumpTransaction(ref event)=> {
 				27u8.write(writer)?;
 				match event {
-					// We never write the ChannelClose|HTLCResolution events as they'll be replayed
-					// upon restarting anyway if they remain unresolved.
+					// We never write the ChannelClose|HTLCResolution|ContractSettlement events as
+					// they'll be replayed upon restarting anyway if they remain unresolved.
 					BumpTransactionEvent::ChannelClose { .. } => {}
 					BumpTransactionEvent::HTLCResolution { .. } => {}
+					BumpTransactionEvent::ContractSettlement { .. } => {}
 				}
 				write_tlv_fields!(writer, {}); // Write a length field for forwards compat
 			}
@@ -1053,7 +1731,7 @@ impl Writeable for Event {
 					(6, channel_type, required),
 				});
 			},
-			&Event::ChannelPending { ref channel_id, ref user_channel_id, ref former_temporary_channel_id, ref counterparty_node_id, ref funding_txo } => {
+			&Event::ChannelPending { ref channel_id, ref user_channel_id, ref former_temporary_channel_id, ref counterparty_node_id, ref funding_txo, ref counterparty_custom_tlvs } => {
 				31u8.write(writer)?;
 				write_tlv_fields!(writer, {
 					(0, channel_id, required),
@@ -1061,99 +1739,409 @@ impl Writeable for Event {
 					(4, former_temporary_channel_id, required),
 					(6, counterparty_node_id, required),
 					(8, funding_txo, required),
+					(10, *counterparty_custom_tlvs, optional_vec),
 				});
 			},
-			// Note that, going forward, all new events must only write data inside of
-			// `write_tlv_fields`. Versions 0.0.101+ will ignore odd-numbered events that write
-			// data via `write_tlv_fields`.
-		}
-		Ok(())
-	}
-}
-impl MaybeReadable for Event {
-	fn read<R: io::Read>(reader: &mut R) -> Result<Option<Self>, msgs::DecodeError> {
-		match Readable::read(reader)? {
-			// Note that we do not write a length-prefixed TLV for FundingGenerationReady events,
-			// unlike all other events, thus we return immediately here.
-			0u8 => Ok(None),
-			1u8 => {
-				let f = || {
-					let mut payment_hash = PaymentHash([0; 32]);
-					let mut payment_preimage = None;
-					let mut payment_secret = None;
-					let mut amount_msat = 0;
-					let mut counterparty_skimmed_fee_msat_opt = None;
-					let mut receiver_node_id = None;
-					let mut _user_payment_id = None::<u64>; // Used in 0.0.103 and earlier, no longer written in 0.0.116+.
-					let mut via_channel_id = None;
-					let mut claim_deadline = None;
-					let mut via_user_channel_id = None;
-					let mut onion_fields = None;
-					read_tlv_fields!(reader, {
-						(0, payment_hash, required),
-						(1, receiver_node_id, option),
-						(2, payment_secret, option),
-						(3, via_channel_id, option),
-						(4, amount_msat, required),
-						(5, via_user_channel_id, option),
-						(6, _user_payment_id, option),
-						(7, claim_deadline, option),
-						(8, payment_preimage, option),
-						(9, onion_fields, option),
-						(10, counterparty_skimmed_fee_msat_opt, option),
-					});
-					let purpose = match payment_secret {
-						Some(secret) => PaymentPurpose::InvoicePayment {
-							payment_preimage,
-							payment_secret: secret
-						},
-						None if payment_preimage.is_some() => PaymentPurpose::SpontaneousPayment(payment_preimage.unwrap()),
-						None => return Err(msgs::DecodeError::InvalidValue),
-					};
-					Ok(Some(Event::PaymentClaimable {
-						receiver_node_id,
-						payment_hash,
-						amount_msat,
-						counterparty_skimmed_fee_msat: counterparty_skimmed_fee_msat_opt.unwrap_or(0),
-						purpose,
-						via_channel_id,
-						via_user_channel_id,
-						claim_deadline,
-						onion_fields,
-					}))
-				};
-				f()
+			&Event::PaymentAttemptSent { ref payment_id, ref payment_hash, ref attempt, ref route } => {
+				33u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, payment_id, required),
+					(2, payment_hash, required),
+					(4, attempt, required),
+					(6, route, required),
+				});
 			},
-			2u8 => {
-				let f = || {
-					let mut payment_preimage = PaymentPreimage([0; 32]);
-					let mut payment_hash = None;
-					let mut payment_id = None;
-					let mut fee_paid_msat = None;
-					read_tlv_fields!(reader, {
-						(0, payment_preimage, required),
-						(1, payment_hash, option),
-						(3, payment_id, option),
-						(5, fee_paid_msat, option),
-					});
-					if payment_hash.is_none() {
-						payment_hash = Some(PaymentHash(Sha256::hash(&payment_preimage.0[..]).into_inner()));
-					}
-					Ok(Some(Event::PaymentSent {
-						payment_id,
-						payment_preimage,
-						payment_hash: payment_hash.unwrap(),
-						fee_paid_msat,
-					}))
-				};
-				f()
+			&Event::PaymentDeadlineExceeded { ref payment_id, ref payment_hash, ref deadline_block_height, ref deadline_time } => {
+				35u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, payment_id, required),
+					(2, payment_hash, required),
+					(4, deadline_block_height, option),
+					(6, deadline_time, option),
+				});
 			},
-			3u8 => {
-				let f = || {
-					#[cfg(test)]
-					let error_code = Readable::read(reader)?;
-					#[cfg(test)]
-					let error_data = Readable::read(reader)?;
+			#[cfg(feature = "htlc_timeline_events")]
+			&Event::HTLCTimeline { .. } => {
+				37u8.write(writer)?;
+				// We never write out HTLCTimeline events as they are only meaningful at the time
+				// they are generated.
+				write_tlv_fields!(writer, {}); // Write a length field for forwards compat
+			},
+			&Event::ChannelIdle { ref channel_id, ref user_channel_id, ref counterparty_node_id, ref idle_timer_ticks } => {
+				39u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, channel_id, required),
+					(2, user_channel_id, required),
+					(4, counterparty_node_id, required),
+					(6, idle_timer_ticks, required),
+				});
+			},
+			&Event::ContractRenewalRequest {
+				ref contract_id, ref new_contract_id, ref counterparty_node_id, ref settlement_bundle,
+				ref oracle_announcement,
+			} => {
+				41u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, new_contract_id, required),
+					(4, counterparty_node_id, required),
+					(6, settlement_bundle, required),
+					(8, oracle_announcement, option),
+				});
+			},
+			&Event::ContractRenewed { ref previous_contract_id, ref contract_id, ref counterparty_node_id } => {
+				43u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, previous_contract_id, required),
+					(2, contract_id, required),
+					(4, counterparty_node_id, required),
+				});
+			},
+			&Event::ContractRenewalFailed { ref contract_id, ref new_contract_id, ref counterparty_node_id, ref reason } => {
+				45u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, new_contract_id, required),
+					(4, counterparty_node_id, required),
+					(6, reason, required),
+				});
+			},
+			&Event::ContractSettled {
+				ref contract_id, ref counterparty_node_id, ref outcome, ref holder_payout_satoshis,
+				ref counterparty_payout_satoshis, ref payment_id,
+			} => {
+				47u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, counterparty_node_id, required),
+					(4, outcome, required),
+					(6, holder_payout_satoshis, required),
+					(8, counterparty_payout_satoshis, required),
+					(10, payment_id, option),
+				});
+			},
+			&Event::ContractSettlementRequest {
+				ref contract_id, ref counterparty_node_id, ref outcome, ref holder_payout_satoshis,
+				ref counterparty_payout_satoshis,
+			} => {
+				101u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, counterparty_node_id, required),
+					(4, outcome, required),
+					(6, holder_payout_satoshis, required),
+					(8, counterparty_payout_satoshis, required),
+				});
+			},
+			&Event::ContractSettlementFailed { ref contract_id, ref counterparty_node_id, ref reason } => {
+				49u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, counterparty_node_id, required),
+					(4, reason, required),
+				});
+			},
+			&Event::MarginCallReceived { ref contract_id, ref counterparty_node_id, ref top_up_satoshis, ref deadline } => {
+				51u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, counterparty_node_id, required),
+					(4, top_up_satoshis, required),
+					(6, deadline, required),
+				});
+			},
+			&Event::MarginCallRejected {
+				ref contract_id, ref counterparty_node_id, ref top_up_satoshis, ref deadline, ref reason,
+			} => {
+				53u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, counterparty_node_id, required),
+					(4, top_up_satoshis, required),
+					(6, deadline, required),
+					(8, reason, required),
+				});
+			},
+			&Event::ContractDisputeDetected { ref package } => {
+				55u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, package, required),
+				});
+			},
+			&Event::PoolAllocationAdded {
+				ref pool_outpoint, ref contract_id, ref counterparty_node_id, ref holder_collateral_satoshis,
+				ref counterparty_collateral_satoshis,
+			} => {
+				57u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, pool_outpoint, required),
+					(2, contract_id, required),
+					(4, counterparty_node_id, required),
+					(6, holder_collateral_satoshis, required),
+					(8, counterparty_collateral_satoshis, required),
+				});
+			},
+			&Event::PoolAllocationFailed { ref pool_outpoint, ref contract_id, ref counterparty_node_id, ref reason } => {
+				59u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, pool_outpoint, required),
+					(2, contract_id, required),
+					(4, counterparty_node_id, required),
+					(6, reason, required),
+				});
+			},
+			&Event::ContractExerciseProgress { ref contract_id, ref counterparty_node_id, ref status } => {
+				61u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, contract_id, required),
+					(2, counterparty_node_id, required),
+					(4, status, required),
+				});
+			},
+			&Event::LivenessProbeCompleted { ref id, ref round_trip_time_ms } => {
+				63u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, id, required),
+					(2, round_trip_time_ms, option),
+				});
+			},
+			&Event::PersistenceHealth { ref health, ref is_healthy } => {
+				65u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, health, required),
+					(2, is_healthy, required),
+				});
+			},
+			&Event::OnchainFallbackPaymentReceived { ref payment_hash, ref outpoint, ref amount_satoshis, ref expected_amount_msat } => {
+				67u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, payment_hash, required),
+					(2, outpoint, required),
+					(4, amount_satoshis, required),
+					(6, expected_amount_msat, option),
+				});
+			},
+			&Event::OnionMessageTimedOut { ref request_id } => {
+				69u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, request_id, required),
+				});
+			},
+			&Event::OnionMessageStored { ref peer_node_id } => {
+				71u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, peer_node_id, required),
+				});
+			},
+			&Event::OnionMessagesExpired { ref peer_node_id, ref count } => {
+				73u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, peer_node_id, required),
+					(2, count, required),
+				});
+			},
+			&Event::ConnectionNeeded { ref node_id, ref addresses } => {
+				75u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, node_id, required),
+					(2, *addresses, required_vec),
+				});
+			},
+			&Event::ConnectionNeededTimedOut { ref node_id, ref count } => {
+				103u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, node_id, required),
+					(2, count, required),
+				});
+			},
+			&Event::OnionMessagesEvicted { ref peer_node_id, ref count } => {
+				77u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, peer_node_id, required),
+					(2, count, required),
+				});
+			},
+			&Event::LiquidityFeeCollected { ref channel_id, ref counterparty_node_id, ref amount_msat, ref remaining_msat } => {
+				79u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, channel_id, required),
+					(2, counterparty_node_id, required),
+					(4, amount_msat, required),
+					(6, remaining_msat, required),
+				});
+			},
+			&Event::OnionMessageDelivered { ref id } => {
+				81u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, id, required),
+				});
+			},
+			&Event::OnionMessageReceiptTimedOut { ref id } => {
+				105u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, id, required),
+				});
+			},
+			&Event::CustomOnionMessageReceived { ref tlv_type, ref data, ref received_via, ref request_id, ref reply_path } => {
+				83u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, tlv_type, required),
+					(2, data, required),
+					(4, received_via, required),
+					(6, request_id, option),
+					(8, reply_path, option),
+				});
+			},
+			&Event::ChannelUnresponsive { ref channel_id, ref user_channel_id, ref counterparty_node_id, ref earliest_htlc_expiry, ref force_close_height } => {
+				85u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, channel_id, required),
+					(2, user_channel_id, required),
+					(4, counterparty_node_id, required),
+					(6, earliest_htlc_expiry, required),
+					(8, force_close_height, required),
+				});
+			},
+			&Event::OnionMessageProbeSuccessful { ref id } => {
+				87u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, id, required),
+				});
+			},
+			&Event::OnionMessageProbeFailed { ref id } => {
+				89u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, id, required),
+				});
+			},
+			&Event::OnionMessageBufferHighWater { ref peer_node_id, ref buffered_bytes } => {
+				91u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, peer_node_id, required),
+					(2, buffered_bytes, required),
+				});
+			},
+			&Event::OnionMessageBufferDrained { ref peer_node_id, ref buffered_bytes } => {
+				93u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, peer_node_id, required),
+					(2, buffered_bytes, required),
+				});
+			},
+			&Event::HTLCHeldForValidation { ref hold_id, ref payment_hash, ref amount_msat, ref onion_fields, ref cltv_expiry, ref via_channel_id } => {
+				95u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, hold_id, required),
+					(2, payment_hash, required),
+					(4, amount_msat, required),
+					(6, onion_fields, required),
+					(8, cltv_expiry, required),
+					(10, via_channel_id, required),
+				});
+			},
+			&Event::FeeSpikeCircuitBreakerEngaged { ref feerate_sat_per_1000_weight } => {
+				97u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, feerate_sat_per_1000_weight, required),
+				});
+			},
+			&Event::FeeSpikeCircuitBreakerReleased { ref feerate_sat_per_1000_weight } => {
+				99u8.write(writer)?;
+				write_tlv_fields!(writer, {
+					(0, feerate_sat_per_1000_weight, required),
+				});
+			},
+			// Note that, going forward, all new events must only write data inside of
+			// `write_tlv_fields`. Versions 0.0.101+ will ignore odd-numbered events that write
+			// data via `write_tlv_fields`.
+		}
+		Ok(())
+	}
+}
+impl MaybeReadable for Event {
+	fn read<R: io::Read>(reader: &mut R) -> Result<Option<Self>, msgs::DecodeError> {
+		match Readable::read(reader)? {
+			// Note that we do not write a length-prefixed TLV for FundingGenerationReady events,
+			// unlike all other events, thus we return immediately here.
+			0u8 => Ok(None),
+			1u8 => {
+				let f = || {
+					let mut payment_hash = PaymentHash([0; 32]);
+					let mut payment_preimage = None;
+					let mut payment_secret = None;
+					let mut amount_msat = 0;
+					let mut counterparty_skimmed_fee_msat_opt = None;
+					let mut receiver_node_id = None;
+					let mut _user_payment_id = None::<u64>; // Used in 0.0.103 and earlier, no longer written in 0.0.116+.
+					let mut via_channel_id = None;
+					let mut claim_deadline = None;
+					let mut via_user_channel_id = None;
+					let mut onion_fields = None;
+					read_tlv_fields!(reader, {
+						(0, payment_hash, required),
+						(1, receiver_node_id, option),
+						(2, payment_secret, option),
+						(3, via_channel_id, option),
+						(4, amount_msat, required),
+						(5, via_user_channel_id, option),
+						(6, _user_payment_id, option),
+						(7, claim_deadline, option),
+						(8, payment_preimage, option),
+						(9, onion_fields, option),
+						(10, counterparty_skimmed_fee_msat_opt, option),
+					});
+					let purpose = match payment_secret {
+						Some(secret) => PaymentPurpose::InvoicePayment {
+							payment_preimage,
+							payment_secret: secret
+						},
+						None if payment_preimage.is_some() => PaymentPurpose::SpontaneousPayment(payment_preimage.unwrap()),
+						None => return Err(msgs::DecodeError::InvalidValue),
+					};
+					Ok(Some(Event::PaymentClaimable {
+						receiver_node_id,
+						payment_hash,
+						amount_msat,
+						counterparty_skimmed_fee_msat: counterparty_skimmed_fee_msat_opt.unwrap_or(0),
+						purpose,
+						via_channel_id,
+						via_user_channel_id,
+						claim_deadline,
+						onion_fields,
+					}))
+				};
+				f()
+			},
+			2u8 => {
+				let f = || {
+					let mut payment_preimage = PaymentPreimage([0; 32]);
+					let mut payment_hash = None;
+					let mut payment_id = None;
+					let mut fee_paid_msat = None;
+					read_tlv_fields!(reader, {
+						(0, payment_preimage, required),
+						(1, payment_hash, option),
+						(3, payment_id, option),
+						(5, fee_paid_msat, option),
+					});
+					if payment_hash.is_none() {
+						payment_hash = Some(PaymentHash(Sha256::hash(&payment_preimage.0[..]).into_inner()));
+					}
+					Ok(Some(Event::PaymentSent {
+						payment_id,
+						payment_preimage,
+						payment_hash: payment_hash.unwrap(),
+						fee_paid_msat,
+					}))
+				};
+				f()
+			},
+			3u8 => {
+				let f = || {
+					#[cfg(test)]
+					let error_code = Readable::read(reader)?;
+					#[cfg(test)]
+					let error_data = Readable::read(reader)?;
 					let mut payment_hash = PaymentHash([0; 32]);
 					let mut payment_failed_permanently = false;
 					let mut network_update = None;
@@ -1174,265 +2162,828 @@ impl MaybeReadable for Event {
 						(11, payment_id, option),
 						(13, failure_opt, upgradable_option),
 					});
-					let failure = failure_opt.unwrap_or_else(|| PathFailure::OnPath { network_update });
-					Ok(Some(Event::PaymentPathFailed {
-						payment_id,
-						payment_hash,
-						payment_failed_permanently,
-						failure,
-						path: Path { hops: path.unwrap(), blinded_tail },
-						short_channel_id,
-						#[cfg(test)]
-						error_code,
-						#[cfg(test)]
-						error_data,
+					let failure = failure_opt.unwrap_or_else(|| PathFailure::OnPath { network_update });
+					Ok(Some(Event::PaymentPathFailed {
+						payment_id,
+						payment_hash,
+						payment_failed_permanently,
+						failure,
+						path: Path { hops: path.unwrap(), blinded_tail },
+						short_channel_id,
+						#[cfg(test)]
+						error_code,
+						#[cfg(test)]
+						error_data,
+					}))
+				};
+				f()
+			},
+			4u8 => Ok(None),
+			5u8 => {
+				let f = || {
+					let mut outputs = WithoutLength(Vec::new());
+					read_tlv_fields!(reader, {
+						(0, outputs, required),
+					});
+					Ok(Some(Event::SpendableOutputs { outputs: outputs.0 }))
+				};
+				f()
+			},
+			6u8 => {
+				let mut payment_hash = PaymentHash([0; 32]);
+				let mut intercept_id = InterceptId([0; 32]);
+				let mut requested_next_hop_scid = InterceptNextHop::FakeScid { requested_next_hop_scid: 0 };
+				let mut inbound_amount_msat = 0;
+				let mut expected_outbound_amount_msat = 0;
+				read_tlv_fields!(reader, {
+					(0, intercept_id, required),
+					(2, requested_next_hop_scid, required),
+					(4, payment_hash, required),
+					(6, inbound_amount_msat, required),
+					(8, expected_outbound_amount_msat, required),
+				});
+				let next_scid = match requested_next_hop_scid {
+					InterceptNextHop::FakeScid { requested_next_hop_scid: scid } => scid
+				};
+				Ok(Some(Event::HTLCIntercepted {
+					payment_hash,
+					requested_next_hop_scid: next_scid,
+					inbound_amount_msat,
+					expected_outbound_amount_msat,
+					intercept_id,
+				}))
+			},
+			7u8 => {
+				let f = || {
+					let mut fee_earned_msat = None;
+					let mut prev_channel_id = None;
+					let mut claim_from_onchain_tx = false;
+					let mut next_channel_id = None;
+					let mut outbound_amount_forwarded_msat = None;
+					read_tlv_fields!(reader, {
+						(0, fee_earned_msat, option),
+						(1, prev_channel_id, option),
+						(2, claim_from_onchain_tx, required),
+						(3, next_channel_id, option),
+						(5, outbound_amount_forwarded_msat, option),
+					});
+					Ok(Some(Event::PaymentForwarded {
+						fee_earned_msat, prev_channel_id, claim_from_onchain_tx, next_channel_id,
+						outbound_amount_forwarded_msat
+					}))
+				};
+				f()
+			},
+			9u8 => {
+				let f = || {
+					let mut channel_id = [0; 32];
+					let mut reason = UpgradableRequired(None);
+					let mut user_channel_id_low_opt: Option<u64> = None;
+					let mut user_channel_id_high_opt: Option<u64> = None;
+					read_tlv_fields!(reader, {
+						(0, channel_id, required),
+						(1, user_channel_id_low_opt, option),
+						(2, reason, upgradable_required),
+						(3, user_channel_id_high_opt, option),
+					});
+
+					// `user_channel_id` used to be a single u64 value. In order to remain
+					// backwards compatible with versions prior to 0.0.113, the u128 is serialized
+					// as two separate u64 values.
+					let user_channel_id = (user_channel_id_low_opt.unwrap_or(0) as u128) +
+						((user_channel_id_high_opt.unwrap_or(0) as u128) << 64);
+
+					Ok(Some(Event::ChannelClosed { channel_id, user_channel_id, reason: _init_tlv_based_struct_field!(reason, upgradable_required) }))
+				};
+				f()
+			},
+			11u8 => {
+				let f = || {
+					let mut channel_id = [0; 32];
+					let mut transaction = Transaction{ version: 2, lock_time: PackedLockTime::ZERO, input: Vec::new(), output: Vec::new() };
+					read_tlv_fields!(reader, {
+						(0, channel_id, required),
+						(2, transaction, required),
+					});
+					Ok(Some(Event::DiscardFunding { channel_id, transaction } ))
+				};
+				f()
+			},
+			13u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, payment_id, required),
+						(2, payment_hash, option),
+						(4, path, required_vec),
+						(6, blinded_tail, option),
+					});
+					Ok(Some(Event::PaymentPathSuccessful {
+						payment_id: payment_id.0.unwrap(),
+						payment_hash,
+						path: Path { hops: path, blinded_tail },
+					}))
+				};
+				f()
+			},
+			15u8 => {
+				let f = || {
+					let mut payment_hash = PaymentHash([0; 32]);
+					let mut payment_id = PaymentId([0; 32]);
+					let mut reason = None;
+					read_tlv_fields!(reader, {
+						(0, payment_id, required),
+						(1, reason, upgradable_option),
+						(2, payment_hash, required),
+					});
+					Ok(Some(Event::PaymentFailed {
+						payment_id,
+						payment_hash,
+						reason,
+					}))
+				};
+				f()
+			},
+			17u8 => {
+				// Value 17 is used for `Event::OpenChannelRequest`.
+				Ok(None)
+			},
+			19u8 => {
+				let f = || {
+					let mut payment_hash = PaymentHash([0; 32]);
+					let mut purpose = UpgradableRequired(None);
+					let mut amount_msat = 0;
+					let mut receiver_node_id = None;
+					let mut shortfall_msat = None;
+					read_tlv_fields!(reader, {
+						(0, payment_hash, required),
+						(1, receiver_node_id, option),
+						(2, purpose, upgradable_required),
+						(3, shortfall_msat, option),
+						(4, amount_msat, required),
+					});
+					Ok(Some(Event::PaymentClaimed {
+						receiver_node_id,
+						payment_hash,
+						purpose: _init_tlv_based_struct_field!(purpose, upgradable_required),
+						amount_msat,
+						shortfall_msat,
+					}))
+				};
+				f()
+			},
+			21u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, payment_id, required),
+						(2, payment_hash, required),
+						(4, path, required_vec),
+						(6, blinded_tail, option),
+					});
+					Ok(Some(Event::ProbeSuccessful {
+						payment_id: payment_id.0.unwrap(),
+						payment_hash: payment_hash.0.unwrap(),
+						path: Path { hops: path, blinded_tail },
+					}))
+				};
+				f()
+			},
+			23u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, payment_id, required),
+						(2, payment_hash, required),
+						(4, path, required_vec),
+						(6, short_channel_id, option),
+						(8, blinded_tail, option),
+					});
+					Ok(Some(Event::ProbeFailed {
+						payment_id: payment_id.0.unwrap(),
+						payment_hash: payment_hash.0.unwrap(),
+						path: Path { hops: path, blinded_tail },
+						short_channel_id,
+					}))
+				};
+				f()
+			},
+			25u8 => {
+				let f = || {
+					let mut prev_channel_id = [0; 32];
+					let mut failed_next_destination_opt = UpgradableRequired(None);
+					read_tlv_fields!(reader, {
+						(0, prev_channel_id, required),
+						(2, failed_next_destination_opt, upgradable_required),
+					});
+					Ok(Some(Event::HTLCHandlingFailed {
+						prev_channel_id,
+						failed_next_destination: _init_tlv_based_struct_field!(failed_next_destination_opt, upgradable_required),
+					}))
+				};
+				f()
+			},
+			27u8 => Ok(None),
+			29u8 => {
+				let f = || {
+					let mut channel_id = [0; 32];
+					let mut user_channel_id: u128 = 0;
+					let mut counterparty_node_id = RequiredWrapper(None);
+					let mut channel_type = RequiredWrapper(None);
+					read_tlv_fields!(reader, {
+						(0, channel_id, required),
+						(2, user_channel_id, required),
+						(4, counterparty_node_id, required),
+						(6, channel_type, required),
+					});
+
+					Ok(Some(Event::ChannelReady {
+						channel_id,
+						user_channel_id,
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						channel_type: channel_type.0.unwrap()
+					}))
+				};
+				f()
+			},
+			31u8 => {
+				let f = || {
+					let mut channel_id = [0; 32];
+					let mut user_channel_id: u128 = 0;
+					let mut former_temporary_channel_id = None;
+					let mut counterparty_node_id = RequiredWrapper(None);
+					let mut funding_txo = RequiredWrapper(None);
+					let mut counterparty_custom_tlvs = Some(Vec::new());
+					read_tlv_fields!(reader, {
+						(0, channel_id, required),
+						(2, user_channel_id, required),
+						(4, former_temporary_channel_id, required),
+						(6, counterparty_node_id, required),
+						(8, funding_txo, required),
+						(10, counterparty_custom_tlvs, optional_vec),
+					});
+
+					Ok(Some(Event::ChannelPending {
+						channel_id,
+						user_channel_id,
+						former_temporary_channel_id,
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						funding_txo: funding_txo.0.unwrap(),
+						counterparty_custom_tlvs: counterparty_custom_tlvs.unwrap(),
+					}))
+				};
+				f()
+			},
+			33u8 => {
+				let f = || {
+					let mut payment_id = PaymentId([0; 32]);
+					let mut payment_hash = PaymentHash([0; 32]);
+					let mut attempt = 0;
+					let mut route = RequiredWrapper(None);
+					read_tlv_fields!(reader, {
+						(0, payment_id, required),
+						(2, payment_hash, required),
+						(4, attempt, required),
+						(6, route, required),
+					});
+					Ok(Some(Event::PaymentAttemptSent {
+						payment_id,
+						payment_hash,
+						attempt,
+						route: route.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			35u8 => {
+				let f = || {
+					let mut payment_id = PaymentId([0; 32]);
+					let mut payment_hash = PaymentHash([0; 32]);
+					let mut deadline_block_height = None;
+					let mut deadline_time = None;
+					read_tlv_fields!(reader, {
+						(0, payment_id, required),
+						(2, payment_hash, required),
+						(4, deadline_block_height, option),
+						(6, deadline_time, option),
+					});
+					Ok(Some(Event::PaymentDeadlineExceeded {
+						payment_id,
+						payment_hash,
+						deadline_block_height,
+						deadline_time,
+					}))
+				};
+				f()
+			},
+			37u8 => {
+				// Value 37 is used for `Event::HTLCTimeline`, which is never persisted.
+				read_tlv_fields!(reader, {});
+				Ok(None)
+			},
+			39u8 => {
+				let f = || {
+					let mut channel_id = [0; 32];
+					let mut user_channel_id: u128 = 0;
+					let mut counterparty_node_id = RequiredWrapper(None);
+					let mut idle_timer_ticks = 0;
+					read_tlv_fields!(reader, {
+						(0, channel_id, required),
+						(2, user_channel_id, required),
+						(4, counterparty_node_id, required),
+						(6, idle_timer_ticks, required),
+					});
+					Ok(Some(Event::ChannelIdle {
+						channel_id,
+						user_channel_id,
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						idle_timer_ticks,
+					}))
+				};
+				f()
+			},
+			41u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, new_contract_id, required),
+						(4, counterparty_node_id, required),
+						(6, settlement_bundle, required),
+						(8, oracle_announcement, option),
+					});
+					Ok(Some(Event::ContractRenewalRequest {
+						contract_id: contract_id.0.unwrap(),
+						new_contract_id: new_contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						settlement_bundle: settlement_bundle.0.unwrap(),
+						oracle_announcement,
+					}))
+				};
+				f()
+			},
+			43u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, previous_contract_id, required),
+						(2, contract_id, required),
+						(4, counterparty_node_id, required),
+					});
+					Ok(Some(Event::ContractRenewed {
+						previous_contract_id: previous_contract_id.0.unwrap(),
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			45u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, new_contract_id, required),
+						(4, counterparty_node_id, required),
+						(6, reason, required),
+					});
+					Ok(Some(Event::ContractRenewalFailed {
+						contract_id: contract_id.0.unwrap(),
+						new_contract_id: new_contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						reason: reason.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			47u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, counterparty_node_id, required),
+						(4, outcome, required),
+						(6, holder_payout_satoshis, required),
+						(8, counterparty_payout_satoshis, required),
+						(10, payment_id, option),
+					});
+					Ok(Some(Event::ContractSettled {
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						outcome: outcome.0.unwrap(),
+						holder_payout_satoshis: holder_payout_satoshis.0.unwrap(),
+						counterparty_payout_satoshis: counterparty_payout_satoshis.0.unwrap(),
+						payment_id,
+					}))
+				};
+				f()
+			},
+			49u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, counterparty_node_id, required),
+						(4, reason, required),
+					});
+					Ok(Some(Event::ContractSettlementFailed {
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						reason: reason.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			101u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, counterparty_node_id, required),
+						(4, outcome, required),
+						(6, holder_payout_satoshis, required),
+						(8, counterparty_payout_satoshis, required),
+					});
+					Ok(Some(Event::ContractSettlementRequest {
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						outcome: outcome.0.unwrap(),
+						holder_payout_satoshis: holder_payout_satoshis.0.unwrap(),
+						counterparty_payout_satoshis: counterparty_payout_satoshis.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			51u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, counterparty_node_id, required),
+						(4, top_up_satoshis, required),
+						(6, deadline, required),
+					});
+					Ok(Some(Event::MarginCallReceived {
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						top_up_satoshis: top_up_satoshis.0.unwrap(),
+						deadline: deadline.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			53u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, counterparty_node_id, required),
+						(4, top_up_satoshis, required),
+						(6, deadline, required),
+						(8, reason, required),
+					});
+					Ok(Some(Event::MarginCallRejected {
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						top_up_satoshis: top_up_satoshis.0.unwrap(),
+						deadline: deadline.0.unwrap(),
+						reason: reason.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			55u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, package, required),
+					});
+					Ok(Some(Event::ContractDisputeDetected {
+						package: package.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			57u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, pool_outpoint, required),
+						(2, contract_id, required),
+						(4, counterparty_node_id, required),
+						(6, holder_collateral_satoshis, required),
+						(8, counterparty_collateral_satoshis, required),
+					});
+					Ok(Some(Event::PoolAllocationAdded {
+						pool_outpoint: pool_outpoint.0.unwrap(),
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						holder_collateral_satoshis: holder_collateral_satoshis.0.unwrap(),
+						counterparty_collateral_satoshis: counterparty_collateral_satoshis.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			59u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, pool_outpoint, required),
+						(2, contract_id, required),
+						(4, counterparty_node_id, required),
+						(6, reason, required),
+					});
+					Ok(Some(Event::PoolAllocationFailed {
+						pool_outpoint: pool_outpoint.0.unwrap(),
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						reason: reason.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			61u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, contract_id, required),
+						(2, counterparty_node_id, required),
+						(4, status, required),
+					});
+					Ok(Some(Event::ContractExerciseProgress {
+						contract_id: contract_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						status: status.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			63u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, id, required),
+						(2, round_trip_time_ms, option),
+					});
+					Ok(Some(Event::LivenessProbeCompleted {
+						id: id.0.unwrap(),
+						round_trip_time_ms,
+					}))
+				};
+				f()
+			},
+			65u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, health, required),
+						(2, is_healthy, required),
+					});
+					Ok(Some(Event::PersistenceHealth {
+						health: health.0.unwrap(),
+						is_healthy: is_healthy.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			67u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, payment_hash, required),
+						(2, outpoint, required),
+						(4, amount_satoshis, required),
+						(6, expected_amount_msat, option),
+					});
+					Ok(Some(Event::OnchainFallbackPaymentReceived {
+						payment_hash: payment_hash.0.unwrap(),
+						outpoint: outpoint.0.unwrap(),
+						amount_satoshis: amount_satoshis.0.unwrap(),
+						expected_amount_msat,
+					}))
+				};
+				f()
+			},
+			69u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, request_id, required),
+					});
+					Ok(Some(Event::OnionMessageTimedOut {
+						request_id: request_id.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			71u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, peer_node_id, required),
+					});
+					Ok(Some(Event::OnionMessageStored {
+						peer_node_id: peer_node_id.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			73u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, peer_node_id, required),
+						(2, count, required),
+					});
+					Ok(Some(Event::OnionMessagesExpired {
+						peer_node_id: peer_node_id.0.unwrap(),
+						count: count.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			4u8 => Ok(None),
-			5u8 => {
+			75u8 => {
 				let f = || {
-					let mut outputs = WithoutLength(Vec::new());
-					read_tlv_fields!(reader, {
-						(0, outputs, required),
+					_init_and_read_tlv_fields!(reader, {
+						(0, node_id, required),
+						(2, addresses, required_vec),
 					});
-					Ok(Some(Event::SpendableOutputs { outputs: outputs.0 }))
+					Ok(Some(Event::ConnectionNeeded {
+						node_id: node_id.0.unwrap(),
+						addresses,
+					}))
 				};
 				f()
 			},
-			6u8 => {
-				let mut payment_hash = PaymentHash([0; 32]);
-				let mut intercept_id = InterceptId([0; 32]);
-				let mut requested_next_hop_scid = InterceptNextHop::FakeScid { requested_next_hop_scid: 0 };
-				let mut inbound_amount_msat = 0;
-				let mut expected_outbound_amount_msat = 0;
-				read_tlv_fields!(reader, {
-					(0, intercept_id, required),
-					(2, requested_next_hop_scid, required),
-					(4, payment_hash, required),
-					(6, inbound_amount_msat, required),
-					(8, expected_outbound_amount_msat, required),
-				});
-				let next_scid = match requested_next_hop_scid {
-					InterceptNextHop::FakeScid { requested_next_hop_scid: scid } => scid
+			103u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, node_id, required),
+						(2, count, required),
+					});
+					Ok(Some(Event::ConnectionNeededTimedOut {
+						node_id: node_id.0.unwrap(),
+						count: count.0.unwrap(),
+					}))
 				};
-				Ok(Some(Event::HTLCIntercepted {
-					payment_hash,
-					requested_next_hop_scid: next_scid,
-					inbound_amount_msat,
-					expected_outbound_amount_msat,
-					intercept_id,
-				}))
+				f()
 			},
-			7u8 => {
+			77u8 => {
 				let f = || {
-					let mut fee_earned_msat = None;
-					let mut prev_channel_id = None;
-					let mut claim_from_onchain_tx = false;
-					let mut next_channel_id = None;
-					let mut outbound_amount_forwarded_msat = None;
-					read_tlv_fields!(reader, {
-						(0, fee_earned_msat, option),
-						(1, prev_channel_id, option),
-						(2, claim_from_onchain_tx, required),
-						(3, next_channel_id, option),
-						(5, outbound_amount_forwarded_msat, option),
+					_init_and_read_tlv_fields!(reader, {
+						(0, peer_node_id, required),
+						(2, count, required),
 					});
-					Ok(Some(Event::PaymentForwarded {
-						fee_earned_msat, prev_channel_id, claim_from_onchain_tx, next_channel_id,
-						outbound_amount_forwarded_msat
+					Ok(Some(Event::OnionMessagesEvicted {
+						peer_node_id: peer_node_id.0.unwrap(),
+						count: count.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			9u8 => {
+			79u8 => {
 				let f = || {
-					let mut channel_id = [0; 32];
-					let mut reason = UpgradableRequired(None);
-					let mut user_channel_id_low_opt: Option<u64> = None;
-					let mut user_channel_id_high_opt: Option<u64> = None;
-					read_tlv_fields!(reader, {
+					_init_and_read_tlv_fields!(reader, {
 						(0, channel_id, required),
-						(1, user_channel_id_low_opt, option),
-						(2, reason, upgradable_required),
-						(3, user_channel_id_high_opt, option),
+						(2, counterparty_node_id, required),
+						(4, amount_msat, required),
+						(6, remaining_msat, required),
 					});
-
-					// `user_channel_id` used to be a single u64 value. In order to remain
-					// backwards compatible with versions prior to 0.0.113, the u128 is serialized
-					// as two separate u64 values.
-					let user_channel_id = (user_channel_id_low_opt.unwrap_or(0) as u128) +
-						((user_channel_id_high_opt.unwrap_or(0) as u128) << 64);
-
-					Ok(Some(Event::ChannelClosed { channel_id, user_channel_id, reason: _init_tlv_based_struct_field!(reason, upgradable_required) }))
+					Ok(Some(Event::LiquidityFeeCollected {
+						channel_id: channel_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						amount_msat: amount_msat.0.unwrap(),
+						remaining_msat: remaining_msat.0.unwrap(),
+					}))
 				};
 				f()
 			},
-			11u8 => {
+			81u8 => {
 				let f = || {
-					let mut channel_id = [0; 32];
-					let mut transaction = Transaction{ version: 2, lock_time: PackedLockTime::ZERO, input: Vec::new(), output: Vec::new() };
-					read_tlv_fields!(reader, {
-						(0, channel_id, required),
-						(2, transaction, required),
+					_init_and_read_tlv_fields!(reader, {
+						(0, id, required),
 					});
-					Ok(Some(Event::DiscardFunding { channel_id, transaction } ))
+					Ok(Some(Event::OnionMessageDelivered {
+						id: id.0.unwrap(),
+					}))
 				};
 				f()
 			},
-			13u8 => {
+			105u8 => {
 				let f = || {
 					_init_and_read_tlv_fields!(reader, {
-						(0, payment_id, required),
-						(2, payment_hash, option),
-						(4, path, required_vec),
-						(6, blinded_tail, option),
+						(0, id, required),
 					});
-					Ok(Some(Event::PaymentPathSuccessful {
-						payment_id: payment_id.0.unwrap(),
-						payment_hash,
-						path: Path { hops: path, blinded_tail },
+					Ok(Some(Event::OnionMessageReceiptTimedOut {
+						id: id.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			15u8 => {
+			83u8 => {
 				let f = || {
-					let mut payment_hash = PaymentHash([0; 32]);
-					let mut payment_id = PaymentId([0; 32]);
-					let mut reason = None;
-					read_tlv_fields!(reader, {
-						(0, payment_id, required),
-						(1, reason, upgradable_option),
-						(2, payment_hash, required),
+					_init_and_read_tlv_fields!(reader, {
+						(0, tlv_type, required),
+						(2, data, required),
+						(4, received_via, required),
+						(6, request_id, option),
+						(8, reply_path, option),
 					});
-					Ok(Some(Event::PaymentFailed {
-						payment_id,
-						payment_hash,
-						reason,
+					Ok(Some(Event::CustomOnionMessageReceived {
+						tlv_type: tlv_type.0.unwrap(),
+						data: data.0.unwrap(),
+						received_via: received_via.0.unwrap(),
+						request_id,
+						reply_path,
 					}))
 				};
 				f()
 			},
-			17u8 => {
-				// Value 17 is used for `Event::OpenChannelRequest`.
-				Ok(None)
+			85u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, channel_id, required),
+						(2, user_channel_id, required),
+						(4, counterparty_node_id, required),
+						(6, earliest_htlc_expiry, required),
+						(8, force_close_height, required),
+					});
+					Ok(Some(Event::ChannelUnresponsive {
+						channel_id: channel_id.0.unwrap(),
+						user_channel_id: user_channel_id.0.unwrap(),
+						counterparty_node_id: counterparty_node_id.0.unwrap(),
+						earliest_htlc_expiry: earliest_htlc_expiry.0.unwrap(),
+						force_close_height: force_close_height.0.unwrap(),
+					}))
+				};
+				f()
 			},
-			19u8 => {
+			87u8 => {
 				let f = || {
-					let mut payment_hash = PaymentHash([0; 32]);
-					let mut purpose = UpgradableRequired(None);
-					let mut amount_msat = 0;
-					let mut receiver_node_id = None;
-					read_tlv_fields!(reader, {
-						(0, payment_hash, required),
-						(1, receiver_node_id, option),
-						(2, purpose, upgradable_required),
-						(4, amount_msat, required),
+					_init_and_read_tlv_fields!(reader, {
+						(0, id, required),
 					});
-					Ok(Some(Event::PaymentClaimed {
-						receiver_node_id,
-						payment_hash,
-						purpose: _init_tlv_based_struct_field!(purpose, upgradable_required),
-						amount_msat,
+					Ok(Some(Event::OnionMessageProbeSuccessful {
+						id: id.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			21u8 => {
+			89u8 => {
 				let f = || {
 					_init_and_read_tlv_fields!(reader, {
-						(0, payment_id, required),
-						(2, payment_hash, required),
-						(4, path, required_vec),
-						(6, blinded_tail, option),
+						(0, id, required),
 					});
-					Ok(Some(Event::ProbeSuccessful {
-						payment_id: payment_id.0.unwrap(),
-						payment_hash: payment_hash.0.unwrap(),
-						path: Path { hops: path, blinded_tail },
+					Ok(Some(Event::OnionMessageProbeFailed {
+						id: id.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			23u8 => {
+			91u8 => {
 				let f = || {
 					_init_and_read_tlv_fields!(reader, {
-						(0, payment_id, required),
-						(2, payment_hash, required),
-						(4, path, required_vec),
-						(6, short_channel_id, option),
-						(8, blinded_tail, option),
+						(0, peer_node_id, required),
+						(2, buffered_bytes, required),
 					});
-					Ok(Some(Event::ProbeFailed {
-						payment_id: payment_id.0.unwrap(),
-						payment_hash: payment_hash.0.unwrap(),
-						path: Path { hops: path, blinded_tail },
-						short_channel_id,
+					Ok(Some(Event::OnionMessageBufferHighWater {
+						peer_node_id: peer_node_id.0.unwrap(),
+						buffered_bytes: buffered_bytes.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			25u8 => {
+			93u8 => {
 				let f = || {
-					let mut prev_channel_id = [0; 32];
-					let mut failed_next_destination_opt = UpgradableRequired(None);
-					read_tlv_fields!(reader, {
-						(0, prev_channel_id, required),
-						(2, failed_next_destination_opt, upgradable_required),
+					_init_and_read_tlv_fields!(reader, {
+						(0, peer_node_id, required),
+						(2, buffered_bytes, required),
 					});
-					Ok(Some(Event::HTLCHandlingFailed {
-						prev_channel_id,
-						failed_next_destination: _init_tlv_based_struct_field!(failed_next_destination_opt, upgradable_required),
+					Ok(Some(Event::OnionMessageBufferDrained {
+						peer_node_id: peer_node_id.0.unwrap(),
+						buffered_bytes: buffered_bytes.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			27u8 => Ok(None),
-			29u8 => {
+			95u8 => {
 				let f = || {
-					let mut channel_id = [0; 32];
-					let mut user_channel_id: u128 = 0;
-					let mut counterparty_node_id = RequiredWrapper(None);
-					let mut channel_type = RequiredWrapper(None);
-					read_tlv_fields!(reader, {
-						(0, channel_id, required),
-						(2, user_channel_id, required),
-						(4, counterparty_node_id, required),
-						(6, channel_type, required),
+					_init_and_read_tlv_fields!(reader, {
+						(0, hold_id, required),
+						(2, payment_hash, required),
+						(4, amount_msat, required),
+						(6, onion_fields, required),
+						(8, cltv_expiry, required),
+						(10, via_channel_id, required),
 					});
-
-					Ok(Some(Event::ChannelReady {
-						channel_id,
-						user_channel_id,
-						counterparty_node_id: counterparty_node_id.0.unwrap(),
-						channel_type: channel_type.0.unwrap()
+					Ok(Some(Event::HTLCHeldForValidation {
+						hold_id: hold_id.0.unwrap(),
+						payment_hash: payment_hash.0.unwrap(),
+						amount_msat: amount_msat.0.unwrap(),
+						onion_fields: onion_fields.0.unwrap(),
+						cltv_expiry: cltv_expiry.0.unwrap(),
+						via_channel_id: via_channel_id.0.unwrap(),
 					}))
 				};
 				f()
 			},
-			31u8 => {
+			97u8 => {
 				let f = || {
-					let mut channel_id = [0; 32];
-					let mut user_channel_id: u128 = 0;
-					let mut former_temporary_channel_id = None;
-					let mut counterparty_node_id = RequiredWrapper(None);
-					let mut funding_txo = RequiredWrapper(None);
-					read_tlv_fields!(reader, {
-						(0, channel_id, required),
-						(2, user_channel_id, required),
-						(4, former_temporary_channel_id, required),
-						(6, counterparty_node_id, required),
-						(8, funding_txo, required),
+					_init_and_read_tlv_fields!(reader, {
+						(0, feerate_sat_per_1000_weight, required),
 					});
-
-					Ok(Some(Event::ChannelPending {
-						channel_id,
-						user_channel_id,
-						former_temporary_channel_id,
-						counterparty_node_id: counterparty_node_id.0.unwrap(),
-						funding_txo: funding_txo.0.unwrap()
+					Ok(Some(Event::FeeSpikeCircuitBreakerEngaged {
+						feerate_sat_per_1000_weight: feerate_sat_per_1000_weight.0.unwrap(),
+					}))
+				};
+				f()
+			},
+			99u8 => {
+				let f = || {
+					_init_and_read_tlv_fields!(reader, {
+						(0, feerate_sat_per_1000_weight, required),
+					});
+					Ok(Some(Event::FeeSpikeCircuitBreakerReleased {
+						feerate_sat_per_1000_weight: feerate_sat_per_1000_weight.0.unwrap(),
 					}))
 				};
 				f()
@@ -1599,6 +3150,13 @@ pub enum MessageSendEvent {
 		/// The message which should be sent.
 		msg: msgs::RevokeAndACK,
 	},
+	/// Used to indicate that a yield message should be sent to the peer with the given node_id.
+	SendYield {
+		/// The node_id of the node which should receive this message
+		node_id: PublicKey,
+		/// The message which should be sent.
+		msg: msgs::Yield,
+	},
 	/// Used to indicate that a closing_signed message should be sent to the peer with the given node_id.
 	SendClosingSigned {
 		/// The node_id of the node which should receive this message
@@ -1784,3 +3342,183 @@ impl<T: EventHandler> EventHandler for Arc<T> {
 		self.deref().handle_event(event)
 	}
 }
+
+/// The broad category an [`Event`] falls into, used to route events to separate handlers via
+/// [`CategorizedEventHandler`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EventCategory {
+	/// Events relating to inbound, outbound, forwarded or probe payments and the HTLCs which make
+	/// them up.
+	Payment,
+	/// Events relating to the opening, operation or closing of channels.
+	Channel,
+	/// Events relating to on-chain outputs, transactions and feerates, e.g.
+	/// [`Event::SpendableOutputs`] and [`Event::BumpTransaction`].
+	Onchain,
+	/// Events relating to the lifecycle of contracts embedded in our channels, including margin
+	/// calls, disputes and collateral pools.
+	Contract,
+	/// Events relating to onion messages we sent, received, stored or buffered, including probes
+	/// and liveness pings sent over onion messages.
+	OnionMessage,
+	/// Events relating to the health of the node itself, e.g. of its storage.
+	Node,
+}
+
+impl Event {
+	/// Returns the [`EventCategory`] this event belongs to.
+	pub fn category(&self) -> EventCategory {
+		match self {
+			Event::PaymentClaimable { .. } |
+			Event::PaymentClaimed { .. } |
+			Event::PaymentSent { .. } |
+			Event::PaymentFailed { .. } |
+			Event::PaymentPathSuccessful { .. } |
+			Event::PaymentPathFailed { .. } |
+			Event::PaymentAttemptSent { .. } |
+			Event::PaymentDeadlineExceeded { .. } |
+			Event::ProbeSuccessful { .. } |
+			Event::ProbeFailed { .. } |
+			Event::PendingHTLCsForwardable { .. } |
+			Event::HTLCIntercepted { .. } |
+			Event::HTLCHeldForValidation { .. } |
+			Event::PaymentForwarded { .. } |
+			Event::LiquidityFeeCollected { .. } |
+			Event::HTLCHandlingFailed { .. } |
+			Event::OnchainFallbackPaymentReceived { .. } => EventCategory::Payment,
+			Event::FundingGenerationReady { .. } |
+			Event::ChannelPending { .. } |
+			Event::ChannelReady { .. } |
+			Event::ChannelClosed { .. } |
+			Event::DiscardFunding { .. } |
+			Event::ChannelIdle { .. } |
+			Event::ChannelUnresponsive { .. } |
+			Event::OpenChannelRequest { .. } => EventCategory::Channel,
+			#[cfg(feature = "htlc_timeline_events")]
+			Event::HTLCTimeline { .. } => EventCategory::Channel,
+			Event::ContractRenewalRequest { .. } |
+			Event::ContractRenewed { .. } |
+			Event::ContractRenewalFailed { .. } |
+			Event::ContractSettlementRequest { .. } |
+			Event::ContractSettled { .. } |
+			Event::ContractSettlementFailed { .. } |
+			Event::MarginCallReceived { .. } |
+			Event::MarginCallRejected { .. } |
+			Event::ContractDisputeDetected { .. } |
+			Event::PoolAllocationAdded { .. } |
+			Event::PoolAllocationFailed { .. } |
+			Event::ContractExerciseProgress { .. } => EventCategory::Contract,
+			Event::LivenessProbeCompleted { .. } |
+			Event::OnionMessageTimedOut { .. } |
+			Event::OnionMessageDelivered { .. } |
+			Event::OnionMessageReceiptTimedOut { .. } |
+			Event::OnionMessageProbeSuccessful { .. } |
+			Event::OnionMessageProbeFailed { .. } |
+			Event::CustomOnionMessageReceived { .. } |
+			Event::OnionMessageStored { .. } |
+			Event::OnionMessagesExpired { .. } |
+			Event::ConnectionNeeded { .. } |
+			Event::ConnectionNeededTimedOut { .. } |
+			Event::OnionMessagesEvicted { .. } |
+			Event::OnionMessageBufferHighWater { .. } |
+			Event::OnionMessageBufferDrained { .. } => EventCategory::OnionMessage,
+			Event::PersistenceHealth { .. } => EventCategory::Node,
+			Event::SpendableOutputs { .. } |
+			Event::FeeSpikeCircuitBreakerEngaged { .. } |
+			Event::FeeSpikeCircuitBreakerReleased { .. } |
+			Event::BumpTransaction(_) => EventCategory::Onchain,
+		}
+	}
+}
+
+/// An [`EventHandler`] which dispatches each [`Event`] to a separate handler based on its
+/// [`EventCategory`].
+///
+/// This allows large applications to split event processing across independent modules while
+/// still passing a single handler to [`EventsProvider::process_pending_events`]. Events are
+/// dispatched in the order they are provided, thus ordering is preserved within each category
+/// (and across categories).
+///
+/// As with any [`EventHandler`], an event is only considered handled once the relevant
+/// sub-handler returns, so all sub-handlers must satisfy the requirements documented on
+/// [`EventsProvider`].
+pub struct CategorizedEventHandler<P: EventHandler, C: EventHandler, O: EventHandler, CT: EventHandler, OM: EventHandler, N: EventHandler> {
+	payment_handler: P,
+	channel_handler: C,
+	onchain_handler: O,
+	contract_handler: CT,
+	onion_message_handler: OM,
+	node_handler: N,
+}
+
+impl<P: EventHandler, C: EventHandler, O: EventHandler, CT: EventHandler, OM: EventHandler, N: EventHandler>
+CategorizedEventHandler<P, C, O, CT, OM, N> {
+	/// Constructs a new [`CategorizedEventHandler`] which passes [`EventCategory::Payment`] events
+	/// to `payment_handler`, [`EventCategory::Channel`] events to `channel_handler`,
+	/// [`EventCategory::Onchain`] events to `onchain_handler`, [`EventCategory::Contract`] events
+	/// to `contract_handler`, [`EventCategory::OnionMessage`] events to `onion_message_handler` and
+	/// [`EventCategory::Node`] events to `node_handler`.
+	pub fn new(
+		payment_handler: P, channel_handler: C, onchain_handler: O, contract_handler: CT,
+		onion_message_handler: OM, node_handler: N,
+	) -> Self {
+		Self { payment_handler, channel_handler, onchain_handler, contract_handler, onion_message_handler, node_handler }
+	}
+}
+
+impl<P: EventHandler, C: EventHandler, O: EventHandler, CT: EventHandler, OM: EventHandler, N: EventHandler>
+EventHandler for CategorizedEventHandler<P, C, O, CT, OM, N> {
+	fn handle_event(&self, event: Event) {
+		match event.category() {
+			EventCategory::Payment => self.payment_handler.handle_event(event),
+			EventCategory::Channel => self.channel_handler.handle_event(event),
+			EventCategory::Onchain => self.onchain_handler.handle_event(event),
+			EventCategory::Contract => self.contract_handler.handle_event(event),
+			EventCategory::OnionMessage => self.onion_message_handler.handle_event(event),
+			EventCategory::Node => self.node_handler.handle_event(event),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CategorizedEventHandler, Event, EventCategory, EventHandler};
+	use crate::ln::PaymentHash;
+	use crate::sync::Mutex;
+	use crate::prelude::*;
+	use core::time::Duration;
+
+	#[test]
+	fn test_categorized_event_handler_preserves_order() {
+		let payment_events = Mutex::new(Vec::new());
+		let onchain_events = Mutex::new(Vec::new());
+		let handler = CategorizedEventHandler::new(
+			|event: Event| payment_events.lock().unwrap().push(event),
+			|_: Event| panic!("No channel events were provided"),
+			|event: Event| onchain_events.lock().unwrap().push(event),
+			|_: Event| panic!("No contract events were provided"),
+			|_: Event| panic!("No onion message events were provided"),
+			|_: Event| panic!("No node events were provided"),
+		);
+
+		let forwardable = Event::PendingHTLCsForwardable { time_forwardable: Duration::from_secs(1) };
+		let spendable = Event::SpendableOutputs { outputs: Vec::new() };
+		let failed = Event::PaymentFailed {
+			payment_id: crate::ln::channelmanager::PaymentId([42; 32]),
+			payment_hash: PaymentHash([42; 32]),
+			reason: None,
+		};
+		assert_eq!(forwardable.category(), EventCategory::Payment);
+		assert_eq!(spendable.category(), EventCategory::Onchain);
+		assert_eq!(Event::OnionMessageBufferDrained {
+			peer_node_id: crate::util::test_utils::pubkey(42), buffered_bytes: 0,
+		}.category(), EventCategory::OnionMessage);
+
+		handler.handle_event(forwardable.clone());
+		handler.handle_event(spendable.clone());
+		handler.handle_event(failed.clone());
+
+		assert_eq!(*payment_events.lock().unwrap(), vec![forwardable, failed]);
+		assert_eq!(*onchain_events.lock().unwrap(), vec![spendable]);
+	}
+}
@@ -1675,6 +1675,16 @@ impl<'a> TrustedCommitmentTransaction<'a> {
 		&self.inner.channel_type_features
 	}
 
+	/// The index of the revokeable `to_local` output of the built Bitcoin transaction, if it has
+	/// one (i.e. it wasn't trimmed as dust).
+	pub fn revokeable_output_index(&self, channel_parameters: &DirectedChannelTransactionParameters) -> Option<usize> {
+		let keys = &self.inner.keys;
+		let revokeable_p2wsh = get_revokeable_redeemscript(&keys.revocation_key,
+			channel_parameters.contest_delay(), &keys.broadcaster_delayed_payment_key).to_v0_p2wsh();
+		self.inner.built.transaction.output.iter()
+			.position(|output| output.script_pubkey == revokeable_p2wsh)
+	}
+
 	/// Get a signature for each HTLC which was included in the commitment transaction (ie for
 	/// which HTLCOutputInCommitment::transaction_output_index.is_some()).
 	///
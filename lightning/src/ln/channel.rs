@@ -27,18 +27,24 @@ use crate::ln::features::{ChannelTypeFeatures, InitFeatures};
 use crate::ln::msgs;
 use crate::ln::msgs::DecodeError;
 use crate::ln::script::{self, ShutdownScript};
-use crate::ln::channelmanager::{self, CounterpartyForwardingInfo, PendingHTLCStatus, HTLCSource, SentHTLCId, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA, MAX_LOCAL_BREAKDOWN_TIMEOUT, ChannelShutdownState};
+use crate::ln::channelmanager::{self, CounterpartyForwardingInfo, PendingHTLCStatus, HTLCSource, HTLCPreviousHopData, InFlightHTLCDetails, OutboundPaymentPartDetails, PaymentId, ChannelDebugState, HTLCDirection, HTLCStage, SentHTLCId, HTLCFailureMsg, PendingHTLCInfo, RAACommitmentOrder, BREAKDOWN_TIMEOUT, MIN_CLTV_EXPIRY_DELTA, MAX_LOCAL_BREAKDOWN_TIMEOUT, ChannelShutdownState};
 use crate::ln::chan_utils::{CounterpartyCommitmentSecrets, TxCreationKeys, HTLCOutputInCommitment, htlc_success_tx_weight, htlc_timeout_tx_weight, make_funding_redeemscript, ChannelPublicKeys, CommitmentTransaction, HolderCommitmentTransaction, ChannelTransactionParameters, CounterpartyChannelTransactionParameters, MAX_HTLCS, get_commitment_transaction_number_obscure_factor, ClosingTransaction};
 use crate::ln::chan_utils;
+use crate::ln::contracts::SettlementBundle;
 use crate::ln::onion_utils::HTLCFailReason;
 use crate::chain::BestBlock;
 use crate::chain::chaininterface::{FeeEstimator, ConfirmationTarget, LowerBoundedFeeEstimator};
 use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateStep, LATENCY_GRACE_PERIOD_BLOCKS, CLOSED_CHANNEL_UPDATE_ID};
 use crate::chain::transaction::{OutPoint, TransactionData};
 use crate::sign::{WriteableEcdsaChannelSigner, EntropySource, ChannelSigner, SignerProvider, NodeSigner, Recipient};
-use crate::events::ClosureReason;
+use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
+use crate::events::{ClosureReason, HTLCTimelineStage};
+#[cfg(feature = "htlc_timeline_events")]
+use crate::events::Event;
+#[cfg(feature = "htlc_timeline_events")]
+use crate::util::time::TimeSource;
 use crate::routing::gossip::NodeId;
-use crate::util::ser::{Readable, ReadableArgs, Writeable, Writer, VecWriter};
+use crate::util::ser::{Readable, ReadableArgs, Writeable, Writer, VecWriter, check_collection_len_within};
 use crate::util::logger::Logger;
 use crate::util::errors::APIError;
 use crate::util::config::{UserConfig, ChannelConfig, LegacyChannelConfig, ChannelHandshakeConfig, ChannelHandshakeLimits, MaxDustHTLCExposure};
@@ -49,7 +55,8 @@ use crate::prelude::*;
 use core::{cmp,mem,fmt};
 use core::ops::Deref;
 #[cfg(any(test, fuzzing, debug_assertions))]
-use crate::sync::Mutex;
+use crate::sync::{Arc, Mutex};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use bitcoin::hashes::hex::ToHex;
 
 #[cfg(test)]
@@ -158,6 +165,8 @@ struct InboundHTLCOutput {
 	cltv_expiry: u32,
 	payment_hash: PaymentHash,
 	state: InboundHTLCState,
+	/// The number of timer ticks since we first saw this HTLC (or since we were last deserialized).
+	timer_ticks: u32,
 }
 
 enum OutboundHTLCState {
@@ -225,6 +234,39 @@ struct OutboundHTLCOutput {
 	state: OutboundHTLCState,
 	source: HTLCSource,
 	skimmed_fee_msat: Option<u64>,
+	/// The number of timer ticks since we first sent this HTLC (or since we were last deserialized).
+	timer_ticks: u32,
+}
+
+/// Records HTLC lifecycle transitions, along with the time at which they happened, for later
+/// surfacing as [`Event::HTLCTimeline`]s. Does nothing unless the `htlc_timeline_events` feature
+/// is enabled.
+///
+/// [`Event::HTLCTimeline`]: crate::events::Event::HTLCTimeline
+#[derive(Default)]
+struct HTLCTimelineRecorder {
+	#[cfg(feature = "htlc_timeline_events")]
+	pending_events: Vec<Event>,
+	/// The source of the timestamps of recorded transitions, shared with the `ChannelManager` so
+	/// that timestamps are comparable across channels. Nothing is recorded until it is set.
+	#[cfg(feature = "htlc_timeline_events")]
+	time_source: Option<Arc<dyn TimeSource + Send + Sync>>,
+}
+
+impl HTLCTimelineRecorder {
+	#[inline]
+	fn record(&mut self, channel_id: [u8; 32], htlc_id: u64, direction: HTLCDirection, payment_hash: PaymentHash, stage: HTLCTimelineStage) {
+		#[cfg(feature = "htlc_timeline_events")] {
+			if let Some(time_source) = &self.time_source {
+				let timestamp = time_source.now();
+				self.pending_events.push(Event::HTLCTimeline {
+					channel_id, htlc_id, direction, payment_hash, stage, timestamp,
+				});
+			}
+		}
+		#[cfg(not(feature = "htlc_timeline_events"))]
+		let _ = (channel_id, htlc_id, direction, payment_hash, stage);
+	}
 }
 
 /// See AwaitingRemoteRevoke ChannelState for more info
@@ -524,6 +566,9 @@ pub(super) struct ReestablishResponses {
 	pub order: RAACommitmentOrder,
 	pub announcement_sigs: Option<msgs::AnnouncementSignatures>,
 	pub shutdown_msg: Option<msgs::Shutdown>,
+	/// Set if the channel type was upgraded as a part of reestablishing the channel, in which case
+	/// the new commitment_signed will go out once the update has been persisted.
+	pub monitor_update: Option<ChannelMonitorUpdate>,
 }
 
 /// The return type of `force_shutdown`
@@ -603,6 +648,53 @@ impl_writeable_tlv_based!(PendingChannelMonitorUpdate, {
 	(0, update, required),
 });
 
+/// A fee our counterparty owes us for the liquidity in a channel we opened to them, which we
+/// collect by skimming it from the HTLCs we forward to them.
+///
+/// See [`ChannelManager::set_channel_liquidity_fee`].
+///
+/// [`ChannelManager::set_channel_liquidity_fee`]: crate::ln::channelmanager::ChannelManager::set_channel_liquidity_fee
+struct LiquidityFeeCredit {
+	/// The total fee owed.
+	fee_msat: u64,
+	/// The portion of `fee_msat` our counterparty has paid by claiming HTLCs we skimmed from.
+	collected_msat: u64,
+	/// The most we'll skim from a single HTLC, in millionths of its amount.
+	max_skim_proportional_millionths: u32,
+	/// The source and skimmed amount of each outbound HTLC we've skimmed from which has not yet
+	/// been claimed or failed. Keyed by source rather than payment hash as several HTLCs may share
+	/// a payment hash.
+	pending_skims: Vec<(HTLCSource, u64)>,
+}
+
+impl LiquidityFeeCredit {
+	/// The portion of the fee which is neither collected nor skimmed from a pending HTLC.
+	fn unskimmed_msat(&self) -> u64 {
+		let pending_msat: u64 = self.pending_skims.iter().map(|(_, skim_msat)| skim_msat).sum();
+		self.fee_msat.saturating_sub(self.collected_msat).saturating_sub(pending_msat)
+	}
+}
+
+impl_writeable_tlv_based!(LiquidityFeeCredit, {
+	(0, fee_msat, required),
+	(2, collected_msat, required),
+	(4, max_skim_proportional_millionths, required),
+	(6, pending_skims, optional_vec),
+});
+
+/// A channel's contribution to a node-wide count of pending inbound HTLCs, which is removed from
+/// the count when dropped along with the channel.
+struct PendingInboundHTLCCounter {
+	counter: Arc<AtomicUsize>,
+	count: usize,
+}
+
+impl Drop for PendingInboundHTLCCounter {
+	fn drop(&mut self) {
+		self.counter.fetch_sub(self.count, Ordering::AcqRel);
+	}
+}
+
 /// Contains all state common to unfunded inbound/outbound channels.
 pub(super) struct UnfundedChannelContext {
 	/// A counter tracking how many ticks have elapsed since this unfunded channel was
@@ -671,6 +763,10 @@ pub(super) struct ChannelContext<Signer: ChannelSigner> {
 	cur_counterparty_commitment_transaction_number: u64,
 	value_to_self_msat: u64, // Excluding all pending_htlcs, excluding fees
 	pending_inbound_htlcs: Vec<InboundHTLCOutput>,
+	/// HTLC lifecycle transitions not yet surfaced as [`Event::HTLCTimeline`]s.
+	///
+	/// [`Event::HTLCTimeline`]: crate::events::Event::HTLCTimeline
+	htlc_timeline: HTLCTimelineRecorder,
 	pending_outbound_htlcs: Vec<OutboundHTLCOutput>,
 	holding_cell_htlc_updates: Vec<HTLCUpdateAwaitingACK>,
 
@@ -889,6 +985,78 @@ pub(super) struct ChannelContext<Signer: ChannelSigner> {
 	// We track whether we already emitted a `ChannelReady` event.
 	channel_ready_event_emitted: bool,
 
+	/// Overrides for the `htlc_minimum_msat` and `htlc_maximum_msat` we advertise in our
+	/// `channel_update`s and enforce when forwarding over this channel. See
+	/// [`ChannelManager::update_channel_htlc_limits`].
+	///
+	/// [`ChannelManager::update_channel_htlc_limits`]: crate::ln::channelmanager::ChannelManager::update_channel_htlc_limits
+	announced_htlc_minimum_msat: Option<u64>,
+	announced_htlc_maximum_msat: Option<u64>,
+
+	/// The number of consecutive timer ticks during which no HTLCs were added to or pending in
+	/// this channel. See [`UserConfig::idle_channel_config`].
+	///
+	/// [`UserConfig::idle_channel_config`]: crate::util::config::UserConfig::idle_channel_config
+	idle_timer_ticks: u64,
+	/// The sum of `next_holder_htlc_id` and `next_counterparty_htlc_id` as of the last timer tick,
+	/// used to detect HTLCs which were added and removed between two timer ticks.
+	idle_htlc_id_checkpoint: u64,
+
+	/// The number of consecutive timer ticks during which this channel had HTLCs pending while our
+	/// counterparty was unresponsive (or since we were last deserialized). See
+	/// [`UserConfig::unresponsive_peer_config`].
+	///
+	/// [`UserConfig::unresponsive_peer_config`]: crate::util::config::UserConfig::unresponsive_peer_config
+	unresponsive_timer_ticks: u64,
+	/// Whether we've generated an [`Event::ChannelUnresponsive`] since our counterparty was last
+	/// responsive.
+	///
+	/// [`Event::ChannelUnresponsive`]: crate::events::Event::ChannelUnresponsive
+	unresponsive_warning_issued: bool,
+
+	/// If the channel uses `option_simplified_update`, whether we currently hold the turn, i.e. are
+	/// the only side allowed to propose updates. `None` if the channel uses the regular, concurrent
+	/// update protocol.
+	simplified_update_turn: Option<bool>,
+	/// Whether we've sent our counterparty a `yield` asking for the turn since we last connected.
+	turn_requested: bool,
+	/// Whether our counterparty has asked us for the turn, which we'll hand over once all of our
+	/// updates have been irrevocably committed.
+	counterparty_requested_turn: bool,
+
+	/// The fee our counterparty owes us for the liquidity in this channel, if any.
+	liquidity_fee: Option<LiquidityFeeCredit>,
+
+	/// The percentage of our maximum dust exposure new HTLCs may use while the fee spike circuit
+	/// breaker is engaged, or `None` if it isn't. Not persisted, as it's set on every timer tick.
+	/// See [`UserConfig::fee_spike_circuit_breaker_config`].
+	///
+	/// [`UserConfig::fee_spike_circuit_breaker_config`]: crate::util::config::UserConfig::fee_spike_circuit_breaker_config
+	fee_spike_dust_exposure_percent: Option<u8>,
+
+	/// The custom TLVs we attach to our `open_channel` or `accept_channel` message. Not persisted,
+	/// as they're only needed until the channel is funded.
+	holder_custom_tlvs: Vec<(u64, Vec<u8>)>,
+	/// The custom TLVs our counterparty attached to their `open_channel` or `accept_channel`
+	/// message.
+	counterparty_custom_tlvs: Vec<(u64, Vec<u8>)>,
+	/// Our contribution to the `ChannelManager`'s count of inbound HTLCs we have not yet begun
+	/// removing across all channels, if it is tracking us. Not persisted.
+	pending_inbound_htlc_counter: Option<PendingInboundHTLCCounter>,
+
+	/// The channel type we asked to move to in our last `channel_reestablish`, if any. Only
+	/// meaningful until the counterparty's `channel_reestablish` has been handled.
+	sent_desired_channel_type: Option<ChannelTypeFeatures>,
+	/// The [`ChannelHandshakeConfig::negotiate_channel_type_upgrade`] setting of the config this
+	/// channel was opened with, or `None` if it was written before we tracked it.
+	negotiate_channel_type_upgrade: Option<bool>,
+	/// Whether the user asked us to move this channel off anchor outputs via
+	/// [`ChannelManager::request_channel_type_downgrade`]. Otherwise we only ever add features to
+	/// the current channel type when renegotiating it.
+	///
+	/// [`ChannelManager::request_channel_type_downgrade`]: crate::ln::channelmanager::ChannelManager::request_channel_type_downgrade
+	channel_type_downgrade_requested: bool,
+
 	/// The unique identifier used to re-derive the private key material for the channel through
 	/// [`SignerProvider::derive_channel_signer`].
 	channel_keys_id: [u8; 32],
@@ -1064,6 +1232,16 @@ impl<Signer: ChannelSigner> ChannelContext<Signer> {
 		self.counterparty_node_id
 	}
 
+	/// Updates the counterparty's node id after it rotated its node identity key.
+	///
+	/// Any `announcement_signatures` we exchanged covered the old node id, so they're dropped and
+	/// will be exchanged again once the channel is reestablished.
+	pub(crate) fn migrate_counterparty_node_id(&mut self, counterparty_node_id: PublicKey) {
+		self.counterparty_node_id = counterparty_node_id;
+		self.announcement_sigs = None;
+		self.announcement_sigs_state = AnnouncementSigsState::NotSent;
+	}
+
 	/// Allowed in any state (including after shutdown)
 	pub fn get_holder_htlc_minimum_msat(&self) -> u64 {
 		self.holder_htlc_minimum_msat
@@ -1076,14 +1254,232 @@ impl<Signer: ChannelSigner> ChannelContext<Signer> {
 
 	/// Allowed in any state (including after shutdown)
 	pub fn get_announced_htlc_max_msat(&self) -> u64 {
-		return cmp::min(
+		self.announced_htlc_limits(self.announced_htlc_minimum_msat, self.announced_htlc_maximum_msat).1
+	}
+
+	/// Allowed in any state (including after shutdown)
+	pub fn get_announced_htlc_min_msat(&self) -> u64 {
+		self.announced_htlc_limits(self.announced_htlc_minimum_msat, self.announced_htlc_maximum_msat).0
+	}
+
+	/// Returns the maximum we advertise as set via [`Self::update_announced_htlc_limits`], if any.
+	pub fn get_announced_htlc_max_msat_override(&self) -> Option<u64> {
+		self.announced_htlc_maximum_msat
+	}
+
+	/// Gets the `htlc_minimum_msat` and `htlc_maximum_msat` we'd advertise given the overrides.
+	fn announced_htlc_limits(&self, min_override: Option<u64>, max_override: Option<u64>) -> (u64, u64) {
+		// We can never advertise less than our counterparty is willing to accept from us.
+		let min = cmp::max(self.counterparty_htlc_minimum_msat, min_override.unwrap_or(0));
+		let max = cmp::min(cmp::min(
 			// Upper bound by capacity. We make it a bit less than full capacity to prevent attempts
 			// to use full capacity. This is an effort to reduce routing failures, because in many cases
 			// channel might have been used to route very small values (either by honest users or as DoS).
 			self.channel_value_satoshis * 1000 * 9 / 10,
 
 			self.counterparty_max_htlc_value_in_flight_msat
-		);
+		), max_override.unwrap_or(u64::max_value()));
+		(min, max)
+	}
+
+	/// Checks that the given overrides for the advertised `htlc_minimum_msat` and
+	/// `htlc_maximum_msat` would result in a valid `channel_update`.
+	pub fn check_announced_htlc_limits(&self, min_override: Option<u64>, max_override: Option<u64>) -> Result<(), APIError> {
+		let (min, max) = self.announced_htlc_limits(min_override, max_override);
+		if min > max {
+			return Err(APIError::APIMisuseError {
+				err: format!("The resulting htlc_minimum_msat ({}) would exceed the resulting htlc_maximum_msat ({}) for channel {}",
+					min, max, log_bytes!(self.channel_id())),
+			});
+		}
+		Ok(())
+	}
+
+	/// Overrides the `htlc_minimum_msat` and `htlc_maximum_msat` we advertise, with `None`
+	/// reverting to the value derived from the channel parameters. A bool is returned indicating
+	/// whether the update resulted in a new ChannelUpdate message.
+	///
+	/// The overrides should have been checked with [`Self::check_announced_htlc_limits`].
+	pub fn update_announced_htlc_limits(&mut self, min_override: Option<u64>, max_override: Option<u64>) -> bool {
+		let did_channel_update = self.announced_htlc_limits(min_override, max_override) !=
+			self.announced_htlc_limits(self.announced_htlc_minimum_msat, self.announced_htlc_maximum_msat);
+		if did_channel_update {
+			// Update the counter, which backs the ChannelUpdate timestamp, to allow the new limits
+			// to propagate throughout the network.
+			self.update_time_counter += 1;
+		}
+		self.announced_htlc_minimum_msat = min_override;
+		self.announced_htlc_maximum_msat = max_override;
+		did_channel_update
+	}
+
+	/// Sets the fee our counterparty owes us for the liquidity in this channel, to be skimmed
+	/// from the HTLCs we forward to them, taking at most `max_skim_proportional_millionths` of
+	/// each. Fails if we didn't open the channel, if the fee exceeds the channel's value or if we
+	/// already started collecting a previously set fee.
+	pub fn set_liquidity_fee(&mut self, fee_msat: u64, max_skim_proportional_millionths: u32) -> Result<(), APIError> {
+		if !self.is_outbound() {
+			return Err(APIError::APIMisuseError {
+				err: format!("Cannot charge a liquidity fee on channel {} as we didn't open it", log_bytes!(self.channel_id())),
+			});
+		}
+		if fee_msat > self.channel_value_satoshis * 1000 || max_skim_proportional_millionths > 1_000_000 {
+			return Err(APIError::APIMisuseError {
+				err: format!("Liquidity fee of {} msat, skimming at most {} millionths per HTLC, is invalid for channel {}",
+					fee_msat, max_skim_proportional_millionths, log_bytes!(self.channel_id())),
+			});
+		}
+		if let Some(liquidity_fee) = &self.liquidity_fee {
+			if liquidity_fee.collected_msat != 0 || !liquidity_fee.pending_skims.is_empty() {
+				return Err(APIError::APIMisuseError {
+					err: format!("Already collecting a liquidity fee on channel {}", log_bytes!(self.channel_id())),
+				});
+			}
+		}
+		self.liquidity_fee = Some(LiquidityFeeCredit {
+			fee_msat, collected_msat: 0, max_skim_proportional_millionths, pending_skims: Vec::new(),
+		});
+		Ok(())
+	}
+
+	/// Gets the portion of the liquidity fee set via [`Self::set_liquidity_fee`] which our
+	/// counterparty has yet to pay, including any skimmed from HTLCs which are still pending.
+	pub fn get_liquidity_fee_owed_msat(&self) -> Option<u64> {
+		self.liquidity_fee.as_ref()
+			.map(|liquidity_fee| liquidity_fee.fee_msat.saturating_sub(liquidity_fee.collected_msat))
+	}
+
+	/// Gets the liquidity fee we should skim from an HTLC of `amount_msat` we're about to forward,
+	/// leaving it at or above our counterparty's `htlc_minimum_msat`.
+	pub fn get_liquidity_skim_msat(&self, amount_msat: u64) -> u64 {
+		let liquidity_fee = match &self.liquidity_fee {
+			Some(liquidity_fee) => liquidity_fee,
+			None => return 0,
+		};
+		let max_skim_msat = (amount_msat as u128 *
+			liquidity_fee.max_skim_proportional_millionths as u128 / 1_000_000) as u64;
+		cmp::min(cmp::min(liquidity_fee.unskimmed_msat(), max_skim_msat),
+			amount_msat.saturating_sub(self.counterparty_htlc_minimum_msat))
+	}
+
+	/// Records that we skimmed `skim_msat` of liquidity fee from an outbound HTLC we've queued,
+	/// as returned by [`Self::get_liquidity_skim_msat`].
+	pub fn record_liquidity_skim(&mut self, source: HTLCSource, skim_msat: u64) {
+		if let Some(liquidity_fee) = &mut self.liquidity_fee {
+			if skim_msat != 0 {
+				liquidity_fee.pending_skims.push((source, skim_msat));
+			}
+		}
+	}
+
+	/// Marks the liquidity fee skimmed from the outbound HTLC with the given source as paid, now
+	/// that our counterparty claimed it. Returns the skimmed amount and the portion of the fee
+	/// which is still owed, if we skimmed from the HTLC at all.
+	pub fn collect_liquidity_skim(&mut self, source: &HTLCSource) -> Option<(u64, u64)> {
+		let liquidity_fee = self.liquidity_fee.as_mut()?;
+		let idx = liquidity_fee.pending_skims.iter().position(|(skim_source, _)| skim_source == source)?;
+		let (_, skim_msat) = liquidity_fee.pending_skims.remove(idx);
+		liquidity_fee.collected_msat += skim_msat;
+		Some((skim_msat, liquidity_fee.fee_msat.saturating_sub(liquidity_fee.collected_msat)))
+	}
+
+	/// Releases the liquidity fee skimmed from the failed outbound HTLC with the given source, so
+	/// that it is skimmed from a later HTLC instead.
+	fn release_liquidity_skim(&mut self, source: &HTLCSource) {
+		if let Some(liquidity_fee) = &mut self.liquidity_fee {
+			if let Some(idx) = liquidity_fee.pending_skims.iter().position(|(skim_source, _)| skim_source == source) {
+				liquidity_fee.pending_skims.remove(idx);
+			}
+		}
+	}
+
+	/// Returns whether an outbound HTLC of `amount_msat` would be trimmed from either party's
+	/// commitment transaction at the current dust buffer feerate, leaving its value to be lost to
+	/// fees rather than claimable on-chain.
+	pub fn is_outbound_htlc_dust(&self, amount_msat: u64) -> bool {
+		let (htlc_timeout_dust_limit, htlc_success_dust_limit) = if self.get_channel_type().supports_anchors_zero_fee_htlc_tx() {
+			(0, 0)
+		} else {
+			let dust_buffer_feerate = self.get_dust_buffer_feerate(None) as u64;
+			(dust_buffer_feerate * htlc_timeout_tx_weight(self.get_channel_type()) / 1000,
+				dust_buffer_feerate * htlc_success_tx_weight(self.get_channel_type()) / 1000)
+		};
+		amount_msat / 1000 < htlc_timeout_dust_limit + self.holder_dust_limit_satoshis ||
+			amount_msat / 1000 < htlc_success_dust_limit + self.counterparty_dust_limit_satoshis
+	}
+
+	/// Gets the total value of the dust HTLCs, as determined by [`Self::is_outbound_htlc_dust`],
+	/// we've forwarded over this channel which haven't yet been removed by our counterparty. This
+	/// is the value we'd lose to fees were the channel force-closed now.
+	///
+	/// HTLCs still in the holding cell are failed back rather than lost on closure, so are only
+	/// included if `include_holding_cell` is set.
+	pub fn get_pending_dust_forwards_msat(&self, include_holding_cell: bool) -> u64 {
+		let mut pending_dust_msat = 0u64;
+		for htlc in self.pending_outbound_htlcs.iter() {
+			match (&htlc.state, &htlc.source) {
+				(OutboundHTLCState::LocalAnnounced(_), HTLCSource::PreviousHopData(_)) |
+				(OutboundHTLCState::Committed, HTLCSource::PreviousHopData(_)) => {
+					if self.is_outbound_htlc_dust(htlc.amount_msat) {
+						pending_dust_msat = pending_dust_msat.saturating_add(htlc.amount_msat);
+					}
+				},
+				_ => {},
+			}
+		}
+		if include_holding_cell {
+			for update in self.holding_cell_htlc_updates.iter() {
+				if let HTLCUpdateAwaitingACK::AddHTLC { amount_msat, source: HTLCSource::PreviousHopData(_), .. } = update {
+					if self.is_outbound_htlc_dust(*amount_msat) {
+						pending_dust_msat = pending_dust_msat.saturating_add(*amount_msat);
+					}
+				}
+			}
+		}
+		pending_dust_msat
+	}
+
+	/// Sets the custom TLVs to attach to our `open_channel` or `accept_channel` message, which must
+	/// be odd-typed, at least [`msgs::MIN_CUSTOM_CHANNEL_TLV_TYPE`] and sorted by type.
+	pub fn set_holder_custom_tlvs(&mut self, custom_tlvs: Vec<(u64, Vec<u8>)>) {
+		self.holder_custom_tlvs = custom_tlvs;
+	}
+
+	/// Gets the custom TLVs our counterparty attached to their `open_channel` or `accept_channel`
+	/// message.
+	pub fn get_counterparty_custom_tlvs(&self) -> &Vec<(u64, Vec<u8>)> {
+		&self.counterparty_custom_tlvs
+	}
+
+	/// Gets the number of inbound HTLCs on this channel which we have not yet begun removing.
+	fn get_pending_inbound_htlc_count(&self) -> usize {
+		self.pending_inbound_htlcs.iter()
+			.filter(|htlc| match htlc.state { InboundHTLCState::LocalRemoved(_) => false, _ => true })
+			.count()
+	}
+
+	/// Starts including this channel's pending inbound HTLCs in the given node-wide `counter`,
+	/// until the channel is dropped.
+	pub fn track_pending_inbound_htlcs(&mut self, counter: Arc<AtomicUsize>) {
+		debug_assert!(self.pending_inbound_htlc_counter.is_none());
+		let count = self.get_pending_inbound_htlc_count();
+		counter.fetch_add(count, Ordering::AcqRel);
+		self.pending_inbound_htlc_counter = Some(PendingInboundHTLCCounter { counter, count });
+	}
+
+	fn pending_inbound_htlcs_added(&mut self, count: usize) {
+		if let Some(counter) = &mut self.pending_inbound_htlc_counter {
+			counter.count += count;
+			counter.counter.fetch_add(count, Ordering::AcqRel);
+		}
+	}
+
+	fn pending_inbound_htlcs_removed(&mut self, count: usize) {
+		if let Some(counter) = &mut self.pending_inbound_htlc_counter {
+			debug_assert!(counter.count >= count);
+			counter.count -= count;
+			counter.counter.fetch_sub(count, Ordering::AcqRel);
+		}
 	}
 
 	/// Allowed in any state (including after shutdown)
@@ -1132,6 +1528,26 @@ impl<Signer: ChannelSigner> ChannelContext<Signer> {
 		}
 	}
 
+	/// Gets the maximum dust exposure which new HTLCs may bring us to, which is tightened while the
+	/// fee spike circuit breaker is engaged. Existing HTLCs and feerate updates should be checked
+	/// against [`Self::get_max_dust_htlc_exposure_msat`] instead.
+	pub fn get_max_new_dust_htlc_exposure_msat<F: Deref>(&self,
+		fee_estimator: &LowerBoundedFeeEstimator<F>) -> u64
+	where F::Target: FeeEstimator
+	{
+		let max_dust_htlc_exposure_msat = self.get_max_dust_htlc_exposure_msat(fee_estimator);
+		match self.fee_spike_dust_exposure_percent {
+			Some(percent) => max_dust_htlc_exposure_msat.saturating_mul(cmp::min(percent, 100) as u64) / 100,
+			None => max_dust_htlc_exposure_msat,
+		}
+	}
+
+	/// Sets the percentage of our maximum dust exposure new HTLCs may use while the fee spike
+	/// circuit breaker is engaged, or `None` once it is released.
+	pub fn set_fee_spike_dust_exposure_percent(&mut self, percent: Option<u8>) {
+		self.fee_spike_dust_exposure_percent = percent;
+	}
+
 	/// Returns the previous [`ChannelConfig`] applied to this channel, if any.
 	pub fn prev_config(&self) -> Option<ChannelConfig> {
 		self.prev_config.map(|prev_config| prev_config.0)
@@ -1176,6 +1592,212 @@ impl<Signer: ChannelSigner> ChannelContext<Signer> {
 		}
 	}
 
+	/// Ages all pending HTLCs by one timer tick. Used to report how long an HTLC has been in flight
+	/// in [`InFlightHTLCDetails::age_timer_ticks`].
+	pub fn htlc_timer_tick_occurred(&mut self) {
+		for htlc in self.pending_inbound_htlcs.iter_mut() {
+			htlc.timer_ticks = htlc.timer_ticks.saturating_add(1);
+		}
+		for htlc in self.pending_outbound_htlcs.iter_mut() {
+			htlc.timer_ticks = htlc.timer_ticks.saturating_add(1);
+		}
+	}
+
+	/// Gets the number of consecutive timer ticks this channel has been idle for.
+	pub fn get_idle_timer_ticks(&self) -> u64 {
+		self.idle_timer_ticks
+	}
+
+	/// Advances the channel's idle counter by one timer tick, resetting it if any HTLCs were added
+	/// since the previous tick or are still pending. Returns the number of consecutive timer ticks
+	/// the channel has now been idle for.
+	pub fn idle_timer_tick_occurred(&mut self) -> u64 {
+		let htlc_id_checkpoint = self.next_holder_htlc_id + self.next_counterparty_htlc_id;
+		if htlc_id_checkpoint != self.idle_htlc_id_checkpoint ||
+			!self.pending_inbound_htlcs.is_empty() || !self.pending_outbound_htlcs.is_empty()
+		{
+			self.idle_timer_ticks = 0;
+		} else {
+			self.idle_timer_ticks = self.idle_timer_ticks.saturating_add(1);
+		}
+		self.idle_htlc_id_checkpoint = htlc_id_checkpoint;
+		self.idle_timer_ticks
+	}
+
+	/// Gets the earliest `cltv_expiry` of the HTLCs in this channel which we'd have to resolve
+	/// on-chain if our counterparty stopped responding, i.e. outbound HTLCs it hasn't begun removing
+	/// and inbound HTLCs we know the preimage for.
+	pub fn get_earliest_pending_htlc_expiry(&self) -> Option<u32> {
+		// Inbound HTLCs only put our funds at risk once we know their preimage, whether claimed
+		// already or with the claim still in our holding cell.
+		let claiming_inbound = self.pending_inbound_htlcs.iter().filter(|htlc| match htlc.state {
+			InboundHTLCState::LocalRemoved(InboundHTLCRemovalReason::Fulfill(_)) => true,
+			InboundHTLCState::Committed => self.holding_cell_htlc_updates.iter().any(|update| match update {
+				HTLCUpdateAwaitingACK::ClaimHTLC { htlc_id, .. } => *htlc_id == htlc.htlc_id,
+				_ => false,
+			}),
+			_ => false,
+		});
+		// Outbound HTLCs our counterparty already began removing don't need to be timed out.
+		let unresolved_outbound = self.pending_outbound_htlcs.iter().filter(|htlc| match htlc.state {
+			OutboundHTLCState::LocalAnnounced(_) | OutboundHTLCState::Committed => true,
+			_ => false,
+		});
+		claiming_inbound.map(|htlc| htlc.cltv_expiry)
+			.chain(unresolved_outbound.map(|htlc| htlc.cltv_expiry))
+			.min()
+	}
+
+	/// Advances the channel's unresponsive counter by one timer tick if it has HTLCs pending while
+	/// our counterparty is disconnected or hasn't responded to a message we're awaiting a response
+	/// to, resetting it otherwise. Returns the number of consecutive timer ticks our counterparty
+	/// has now been unresponsive for.
+	pub fn unresponsive_timer_tick_occurred(&mut self) -> u64 {
+		let htlcs_pending = !self.pending_inbound_htlcs.is_empty() || !self.pending_outbound_htlcs.is_empty();
+		let unresponsive = self.channel_state & (ChannelState::PeerDisconnected as u32) != 0 ||
+			self.sent_message_awaiting_response.is_some();
+		if htlcs_pending && unresponsive {
+			self.unresponsive_timer_ticks = self.unresponsive_timer_ticks.saturating_add(1);
+		} else {
+			self.unresponsive_timer_ticks = 0;
+			self.unresponsive_warning_issued = false;
+		}
+		self.unresponsive_timer_ticks
+	}
+
+	/// Records that we've warned the user about our counterparty being unresponsive, returning
+	/// whether we hadn't already since it was last responsive.
+	pub fn mark_unresponsive_warning_issued(&mut self) -> bool {
+		!core::mem::replace(&mut self.unresponsive_warning_issued, true)
+	}
+
+	/// Returns true if the channel uses `option_simplified_update` and our counterparty currently
+	/// holds the turn, in which case any updates we wish to make must wait in the holding cell.
+	fn awaiting_turn(&self) -> bool {
+		self.simplified_update_turn == Some(false)
+	}
+
+	/// Returns true if any update proposed by either side has yet to be irrevocably committed.
+	fn has_uncommitted_updates(&self) -> bool {
+		self.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::MonitorUpdateInProgress as u32) != 0 ||
+			self.pending_update_fee.is_some() ||
+			self.pending_inbound_htlcs.iter().any(|htlc| !matches!(htlc.state, InboundHTLCState::Committed)) ||
+			self.pending_outbound_htlcs.iter().any(|htlc| !matches!(htlc.state, OutboundHTLCState::Committed))
+	}
+
+	/// Gets the details of all HTLCs pending in this channel. HTLCs which are still in the holding
+	/// cell are not included.
+	pub fn get_in_flight_htlc_details(&self) -> Vec<InFlightHTLCDetails> {
+		let mut res = Vec::with_capacity(self.pending_inbound_htlcs.len() + self.pending_outbound_htlcs.len());
+		for htlc in self.pending_inbound_htlcs.iter() {
+			let stage = match htlc.state {
+				InboundHTLCState::RemoteAnnounced(_) |
+				InboundHTLCState::AwaitingRemoteRevokeToAnnounce(_) |
+				InboundHTLCState::AwaitingAnnouncedRemoteRevoke(_) => HTLCStage::AwaitingCommitment,
+				InboundHTLCState::Committed => HTLCStage::Committed,
+				InboundHTLCState::LocalRemoved(_) => HTLCStage::AwaitingRemoval,
+			};
+			res.push(InFlightHTLCDetails {
+				channel_id: self.channel_id,
+				counterparty_node_id: self.counterparty_node_id,
+				htlc_id: htlc.htlc_id,
+				direction: HTLCDirection::Inbound,
+				stage,
+				amount_msat: htlc.amount_msat,
+				payment_hash: htlc.payment_hash,
+				cltv_expiry: htlc.cltv_expiry,
+				age_timer_ticks: htlc.timer_ticks,
+				is_forward: false,
+			});
+		}
+		for htlc in self.pending_outbound_htlcs.iter() {
+			let stage = match htlc.state {
+				OutboundHTLCState::LocalAnnounced(_) => HTLCStage::AwaitingCommitment,
+				OutboundHTLCState::Committed => HTLCStage::Committed,
+				OutboundHTLCState::RemoteRemoved(_) |
+				OutboundHTLCState::AwaitingRemoteRevokeToRemove(_) |
+				OutboundHTLCState::AwaitingRemovedRemoteRevoke(_) => HTLCStage::AwaitingRemoval,
+			};
+			res.push(InFlightHTLCDetails {
+				channel_id: self.channel_id,
+				counterparty_node_id: self.counterparty_node_id,
+				htlc_id: htlc.htlc_id,
+				direction: HTLCDirection::Outbound,
+				stage,
+				amount_msat: htlc.amount_msat,
+				payment_hash: htlc.payment_hash,
+				cltv_expiry: htlc.cltv_expiry,
+				age_timer_ticks: htlc.timer_ticks,
+				is_forward: matches!(htlc.source, HTLCSource::PreviousHopData(_)),
+			});
+		}
+		res
+	}
+
+	/// Gets the details of all parts of outbound payments we sent which are pending in this channel,
+	/// including those still in the holding cell, along with the id of the payment each belongs to.
+	pub fn get_outbound_payment_parts(&self) -> Vec<(PaymentId, OutboundPaymentPartDetails)> {
+		let part_details = |source: &HTLCSource, cltv_expiry: u32, stage: Option<HTLCStage>| {
+			match source {
+				HTLCSource::OutboundRoute { path, payment_id, .. } => {
+					Some((*payment_id, OutboundPaymentPartDetails {
+						channel_id: self.channel_id,
+						counterparty_node_id: self.counterparty_node_id,
+						first_hop_short_channel_id: path.hops.first().map(|hop| hop.short_channel_id).unwrap_or(0),
+						amount_msat: path.final_value_msat(),
+						fee_msat: path.fee_msat(),
+						cltv_expiry,
+						stage,
+					}))
+				},
+				HTLCSource::PreviousHopData(_) => None,
+			}
+		};
+		let mut res = Vec::new();
+		for htlc in self.pending_outbound_htlcs.iter() {
+			let stage = match htlc.state {
+				OutboundHTLCState::LocalAnnounced(_) => HTLCStage::AwaitingCommitment,
+				OutboundHTLCState::Committed => HTLCStage::Committed,
+				OutboundHTLCState::RemoteRemoved(_) |
+				OutboundHTLCState::AwaitingRemoteRevokeToRemove(_) |
+				OutboundHTLCState::AwaitingRemovedRemoteRevoke(_) => HTLCStage::AwaitingRemoval,
+			};
+			res.extend(part_details(&htlc.source, htlc.cltv_expiry, Some(stage)));
+		}
+		for update in self.holding_cell_htlc_updates.iter() {
+			if let HTLCUpdateAwaitingACK::AddHTLC { source, cltv_expiry, .. } = update {
+				res.extend(part_details(source, *cltv_expiry, None));
+			}
+		}
+		res
+	}
+
+	/// Gets the previous hop of an outbound HTLC we forwarded which has not yet been removed by our
+	/// counterparty, along with its payment hash.
+	pub(super) fn get_forwarded_htlc_prev_hop(&self, htlc_id: u64) -> Option<(HTLCPreviousHopData, PaymentHash)> {
+		self.pending_outbound_htlcs.iter()
+			.find(|htlc| htlc.htlc_id == htlc_id)
+			.and_then(|htlc| match (&htlc.state, &htlc.source) {
+				(OutboundHTLCState::LocalAnnounced(_), HTLCSource::PreviousHopData(prev_hop)) |
+				(OutboundHTLCState::Committed, HTLCSource::PreviousHopData(prev_hop)) =>
+					Some((prev_hop.clone(), htlc.payment_hash)),
+				_ => None,
+			})
+	}
+
+	/// Gets and clears the [`Event::HTLCTimeline`]s generated for this channel since the last call.
+	#[cfg(feature = "htlc_timeline_events")]
+	pub fn take_htlc_timeline_events(&mut self) -> Vec<Event> {
+		core::mem::take(&mut self.htlc_timeline.pending_events)
+	}
+
+	/// Sets the [`TimeSource`] the [`Event::HTLCTimeline`]s of this channel are timestamped with.
+	/// No such events are generated until this is called.
+	#[cfg(feature = "htlc_timeline_events")]
+	pub fn set_htlc_timeline_time_source(&mut self, time_source: Arc<dyn TimeSource + Send + Sync>) {
+		self.htlc_timeline.time_source = Some(time_source);
+	}
+
 	/// Returns the current [`ChannelConfig`] applied to the channel.
 	pub fn config(&self) -> ChannelConfig {
 		self.config.options
@@ -1478,6 +2100,16 @@ impl<Signer: ChannelSigner> ChannelContext<Signer> {
 		TxCreationKeys::derive_new(&self.secp_ctx, &self.counterparty_cur_commitment_point.unwrap(), &counterparty_pubkeys.delayed_payment_basepoint, &counterparty_pubkeys.htlc_basepoint, revocation_basepoint, htlc_basepoint)
 	}
 
+	/// Gets the index and value of the revokeable `to_local` output of a counterparty commitment
+	/// transaction built by build_commitment_transaction, if it has one.
+	fn counterparty_revokeable_output(&self, counterparty_commitment_tx: &CommitmentTransaction) -> Option<(u32, u64)> {
+		let trusted_tx = counterparty_commitment_tx.trust();
+		let channel_parameters = self.channel_transaction_parameters.as_counterparty_broadcastable();
+		trusted_tx.revokeable_output_index(&channel_parameters).map(|idx| {
+			(idx as u32, trusted_tx.built_transaction().transaction.output[idx].value)
+		})
+	}
+
 	/// Gets the redeemscript for the funding transaction output (ie the funding transaction output
 	/// pays to get_funding_redeemscript().to_v0_p2wsh()).
 	/// Panics if called before accept_channel/InboundV1Channel::new
@@ -1687,7 +2319,7 @@ impl<Signer: ChannelSigner> ChannelContext<Signer> {
 		// send above the dust limit (as the router can always overpay to meet the dust limit).
 		let mut remaining_msat_below_dust_exposure_limit = None;
 		let mut dust_exposure_dust_limit_msat = 0;
-		let max_dust_htlc_exposure_msat = context.get_max_dust_htlc_exposure_msat(fee_estimator);
+		let max_dust_htlc_exposure_msat = context.get_max_new_dust_htlc_exposure_msat(fee_estimator);
 
 		let (htlc_success_dust_limit, htlc_timeout_dust_limit) = if context.get_channel_type().supports_anchors_zero_fee_htlc_tx() {
 			(context.counterparty_dust_limit_satoshis, context.holder_dust_limit_satoshis)
@@ -1971,6 +2603,12 @@ impl<Signer: ChannelSigner> ChannelContext<Signer> {
 				_ => {}
 			}
 		}
+		// Once closed, we can no longer collect any liquidity fee in-band, so release the fee
+		// skimmed from HTLCs which are still pending, whether we're failing them back above or
+		// they're resolved on-chain.
+		if let Some(liquidity_fee) = &mut self.liquidity_fee {
+			liquidity_fee.pending_skims.clear();
+		}
 		let monitor_update = if let Some(funding_txo) = self.get_funding_txo() {
 			// If we haven't yet exchanged funding signatures (ie channel_state < FundingSent),
 			// returning a channel monitor update here would imply a channel monitor update before
@@ -2039,7 +2677,7 @@ pub(crate) fn get_legacy_default_holder_selected_channel_reserve_satoshis(channe
 // Get the fee cost in SATS of a commitment tx with a given number of HTLC outputs.
 // Note that num_htlcs should not include dust HTLCs.
 #[inline]
-fn commit_tx_fee_sat(feerate_per_kw: u32, num_htlcs: usize, channel_type_features: &ChannelTypeFeatures) -> u64 {
+pub(crate) fn commit_tx_fee_sat(feerate_per_kw: u32, num_htlcs: usize, channel_type_features: &ChannelTypeFeatures) -> u64 {
 	feerate_per_kw as u64 * (commitment_tx_base_weight(channel_type_features) + num_htlcs as u64 * COMMITMENT_TX_WEIGHT_PER_HTLC) / 1000
 }
 
@@ -2276,9 +2914,11 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			}],
 		};
 
-		if (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateInProgress as u32)) != 0 {
-			// Note that this condition is the same as the assertion in
-			// `claim_htlc_while_disconnected_dropping_mon_update` and must match exactly -
+		if (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateInProgress as u32)) != 0 ||
+			self.context.awaiting_turn()
+		{
+			// Note that this condition must hold whenever the assertion in
+			// `claim_htlc_while_disconnected_dropping_mon_update` does -
 			// `claim_htlc_while_disconnected_dropping_mon_update` would not work correctly if we
 			// do not not get into this branch.
 			for pending_update in self.context.holding_cell_htlc_updates.iter() {
@@ -2325,6 +2965,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			log_trace!(logger, "Upgrading HTLC {} to LocalRemoved with a Fulfill in channel {}!", log_bytes!(htlc.payment_hash.0), log_bytes!(self.context.channel_id));
 			htlc.state = InboundHTLCState::LocalRemoved(InboundHTLCRemovalReason::Fulfill(payment_preimage_arg.clone()));
 		}
+		self.context.pending_inbound_htlcs_removed(1);
 
 		UpdateFulfillFetch::NewClaim {
 			monitor_update,
@@ -2436,7 +3077,9 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			return Ok(None);
 		}
 
-		if (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateInProgress as u32)) != 0 {
+		if (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateInProgress as u32)) != 0 ||
+			self.context.awaiting_turn()
+		{
 			debug_assert!(force_holding_cell, "!force_holding_cell is only called when emptying the holding cell, so we shouldn't end up back in it!");
 			force_holding_cell = true;
 		}
@@ -2474,6 +3117,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			let htlc = &mut self.context.pending_inbound_htlcs[pending_idx];
 			htlc.state = InboundHTLCState::LocalRemoved(InboundHTLCRemovalReason::FailRelay(err_packet.clone()));
 		}
+		self.context.pending_inbound_htlcs_removed(1);
 
 		Ok(Some(msgs::UpdateFailHTLC {
 			channel_id: self.context.channel_id(),
@@ -2554,7 +3198,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		                                          obscure_factor,
 		                                          holder_commitment_tx, best_block, self.context.counterparty_node_id);
 
-		channel_monitor.provide_latest_counterparty_commitment_tx(counterparty_initial_bitcoin_tx.txid, Vec::new(), self.context.cur_counterparty_commitment_transaction_number, self.context.counterparty_cur_commitment_point.unwrap(), logger);
+		channel_monitor.provide_latest_counterparty_commitment_tx(counterparty_initial_bitcoin_tx.txid, Vec::new(), self.context.cur_counterparty_commitment_transaction_number, self.context.counterparty_cur_commitment_point.unwrap(), self.context.counterparty_revokeable_output(&counterparty_initial_commitment_tx), logger);
 
 		assert_eq!(self.context.channel_state & (ChannelState::MonitorUpdateInProgress as u32), 0); // We have no had any monitor(s) yet to fail update!
 		self.context.channel_state = ChannelState::FundingSent as u32;
@@ -2660,6 +3304,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		if self.context.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_add_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		self.check_counterparty_turn("update_add_htlc")?;
 		if msg.amount_msat > self.context.channel_value_satoshis * 1000 {
 			return Err(ChannelError::Close("Remote side tried to send more than the total value of the channel".to_owned()));
 		}
@@ -2699,7 +3344,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			}
 		}
 
-		let max_dust_htlc_exposure_msat = self.context.get_max_dust_htlc_exposure_msat(fee_estimator);
+		let max_dust_htlc_exposure_msat = self.context.get_max_new_dust_htlc_exposure_msat(fee_estimator);
 		let (htlc_timeout_dust_limit, htlc_success_dust_limit) = if self.context.get_channel_type().supports_anchors_zero_fee_htlc_tx() {
 			(0, 0)
 		} else {
@@ -2795,7 +3440,11 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			payment_hash: msg.payment_hash,
 			cltv_expiry: msg.cltv_expiry,
 			state: InboundHTLCState::RemoteAnnounced(pending_forward_status),
+			timer_ticks: 0,
 		});
+		self.context.htlc_timeline.record(self.context.channel_id, msg.htlc_id, HTLCDirection::Inbound,
+			msg.payment_hash, HTLCTimelineStage::Added);
+		self.context.pending_inbound_htlcs_added(1);
 		Ok(())
 	}
 
@@ -2837,6 +3486,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		if self.context.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fulfill_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		self.check_counterparty_turn("update_fulfill_htlc")?;
 
 		self.mark_outbound_htlc_removed(msg.htlc_id, Some(msg.payment_preimage), None).map(|htlc| (htlc.source.clone(), htlc.amount_msat))
 	}
@@ -2848,8 +3498,10 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		if self.context.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fail_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		self.check_counterparty_turn("update_fail_htlc")?;
 
-		self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?;
+		let source = self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?.source.clone();
+		self.context.release_liquidity_skim(&source);
 		Ok(())
 	}
 
@@ -2860,11 +3512,78 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		if self.context.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fail_malformed_htlc when we needed a channel_reestablish".to_owned()));
 		}
+		self.check_counterparty_turn("update_fail_malformed_htlc")?;
 
-		self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?;
+		let source = self.mark_outbound_htlc_removed(msg.htlc_id, None, Some(fail_reason))?.source.clone();
+		self.context.release_liquidity_skim(&source);
 		Ok(())
 	}
 
+	/// Checks that our counterparty may propose an update under `option_simplified_update`, i.e.
+	/// that it holds the turn.
+	///
+	/// The turn only ever passes via an explicit `yield`, or deterministically upon reconnection
+	/// (see [`Self::channel_reestablish`]), so both sides always agree on who holds it and an
+	/// update proposed while we hold it is a protocol violation.
+	fn check_counterparty_turn(&self, msg_name: &str) -> Result<(), ChannelError> {
+		if self.context.simplified_update_turn == Some(true) {
+			return Err(ChannelError::Close(format!("Peer sent {} when it wasn't its turn", msg_name)));
+		}
+		Ok(())
+	}
+
+	/// Handles a `yield` message from our counterparty. If our counterparty holds the turn, it is
+	/// handed over to us, otherwise our counterparty is asking for it and we'll yield once our
+	/// pending updates have been committed (see [`Self::maybe_get_yield`]).
+	pub fn yield_received(&mut self, _msg: &msgs::Yield) -> Result<(), ChannelError> {
+		if self.context.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
+			return Err(ChannelError::Close("Peer sent yield when we needed a channel_reestablish".to_owned()));
+		}
+		match self.context.simplified_update_turn {
+			None => Err(ChannelError::Close("Peer sent yield for a channel which doesn't use simplified updates".to_owned())),
+			Some(true) => {
+				self.context.counterparty_requested_turn = true;
+				Ok(())
+			},
+			Some(false) => {
+				self.context.simplified_update_turn = Some(true);
+				self.context.turn_requested = false;
+				Ok(())
+			},
+		}
+	}
+
+	/// Gets a `yield` message to send to our counterparty, if any. If we hold the turn and our
+	/// counterparty asked for it, we hand it over once all of our updates have been irrevocably
+	/// committed. If our counterparty holds the turn and we have updates waiting in the holding
+	/// cell, we ask for it.
+	///
+	/// Should be called after [`Self::maybe_free_holding_cell_htlcs`] so that any updates we're
+	/// able to send go out before we hand over the turn.
+	pub fn maybe_get_yield(&mut self) -> Option<msgs::Yield> {
+		let our_turn = self.context.simplified_update_turn?;
+		if self.context.channel_state & (ChannelState::ChannelReady as u32) == 0 ||
+			self.context.channel_state & (ChannelState::PeerDisconnected as u32 | ChannelState::ShutdownComplete as u32) != 0
+		{
+			return None;
+		}
+		if our_turn {
+			if !self.context.counterparty_requested_turn || self.context.has_uncommitted_updates() {
+				return None;
+			}
+			self.context.simplified_update_turn = Some(false);
+			self.context.counterparty_requested_turn = false;
+		} else {
+			if self.context.turn_requested ||
+				(self.context.holding_cell_htlc_updates.is_empty() && self.context.holding_cell_update_fee.is_none())
+			{
+				return None;
+			}
+			self.context.turn_requested = true;
+		}
+		Some(msgs::Yield { channel_id: self.context.channel_id })
+	}
+
 	pub fn commitment_signed<L: Deref>(&mut self, msg: &msgs::CommitmentSigned, logger: &L) -> Result<Option<ChannelMonitorUpdate>, ChannelError>
 		where L::Target: Logger
 	{
@@ -3095,7 +3814,8 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 	where F::Target: FeeEstimator, L::Target: Logger
 	{
 		if self.context.channel_state >= ChannelState::ChannelReady as u32 &&
-		   (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateInProgress as u32)) == 0 {
+		   (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::PeerDisconnected as u32 | ChannelState::MonitorUpdateInProgress as u32)) == 0 &&
+		   !self.context.awaiting_turn() {
 			self.free_holding_cell_htlcs(fee_estimator, logger)
 		} else { (None, Vec::new()) }
 	}
@@ -3150,6 +3870,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 										// successfully forwarded/failed/fulfilled, causing
 										// our counterparty to eventually close on us.
 										htlcs_to_fail.push((source.clone(), *payment_hash));
+										self.context.release_liquidity_skim(source);
 									},
 									_ => {
 										panic!("Got a non-IgnoreError action trying to send holding cell HTLC");
@@ -3299,33 +4020,41 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		let mut update_fail_htlcs = Vec::new();
 		let mut update_fail_malformed_htlcs = Vec::new();
 		let mut require_commitment = false;
+		let mut inbound_htlcs_failed = 0;
 		let mut value_to_self_msat_diff: i64 = 0;
 
 		{
 			// Take references explicitly so that we can hold multiple references to self.context.
 			let pending_inbound_htlcs: &mut Vec<_> = &mut self.context.pending_inbound_htlcs;
 			let pending_outbound_htlcs: &mut Vec<_> = &mut self.context.pending_outbound_htlcs;
+			let htlc_timeline = &mut self.context.htlc_timeline;
+			let channel_id = self.context.channel_id;
 
 			// We really shouldnt have two passes here, but retain gives a non-mutable ref (Rust bug)
 			pending_inbound_htlcs.retain(|htlc| {
 				if let &InboundHTLCState::LocalRemoved(ref reason) = &htlc.state {
 					log_trace!(logger, " ...removing inbound LocalRemoved {}", log_bytes!(htlc.payment_hash.0));
-					if let &InboundHTLCRemovalReason::Fulfill(_) = reason {
+					let stage = if let &InboundHTLCRemovalReason::Fulfill(_) = reason {
 						value_to_self_msat_diff += htlc.amount_msat as i64;
-					}
+						HTLCTimelineStage::Fulfilled
+					} else { HTLCTimelineStage::Failed };
+					htlc_timeline.record(channel_id, htlc.htlc_id, HTLCDirection::Inbound, htlc.payment_hash, stage);
 					false
 				} else { true }
 			});
 			pending_outbound_htlcs.retain(|htlc| {
 				if let &OutboundHTLCState::AwaitingRemovedRemoteRevoke(ref outcome) = &htlc.state {
 					log_trace!(logger, " ...removing outbound AwaitingRemovedRemoteRevoke {}", log_bytes!(htlc.payment_hash.0));
-					if let OutboundHTLCOutcome::Failure(reason) = outcome.clone() { // We really want take() here, but, again, non-mut ref :(
+					let stage = if let OutboundHTLCOutcome::Failure(reason) = outcome.clone() { // We really want take() here, but, again, non-mut ref :(
 						revoked_htlcs.push((htlc.source.clone(), htlc.payment_hash, reason));
+						HTLCTimelineStage::Failed
 					} else {
 						finalized_claimed_htlcs.push(htlc.source.clone());
 						// They fulfilled, so we sent them money
 						value_to_self_msat_diff -= htlc.amount_msat as i64;
-					}
+						HTLCTimelineStage::Fulfilled
+					};
+					htlc_timeline.record(channel_id, htlc.htlc_id, HTLCDirection::Outbound, htlc.payment_hash, stage);
 					false
 				} else { true }
 			});
@@ -3348,6 +4077,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 							PendingHTLCStatus::Fail(fail_msg) => {
 								log_trace!(logger, " ...promoting inbound AwaitingAnnouncedRemoteRevoke {} to LocalRemoved due to PendingHTLCStatus indicating failure", log_bytes!(htlc.payment_hash.0));
 								require_commitment = true;
+								inbound_htlcs_failed += 1;
 								match fail_msg {
 									HTLCFailureMsg::Relay(msg) => {
 										htlc.state = InboundHTLCState::LocalRemoved(InboundHTLCRemovalReason::FailRelay(msg.reason.clone()));
@@ -3363,6 +4093,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 								log_trace!(logger, " ...promoting inbound AwaitingAnnouncedRemoteRevoke {} to Committed", log_bytes!(htlc.payment_hash.0));
 								to_forward_infos.push((forward_info, htlc.htlc_id));
 								htlc.state = InboundHTLCState::Committed;
+								htlc_timeline.record(channel_id, htlc.htlc_id, HTLCDirection::Inbound, htlc.payment_hash, HTLCTimelineStage::Committed);
 							}
 						}
 					}
@@ -3372,6 +4103,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 				if let OutboundHTLCState::LocalAnnounced(_) = htlc.state {
 					log_trace!(logger, " ...promoting outbound LocalAnnounced {} to Committed", log_bytes!(htlc.payment_hash.0));
 					htlc.state = OutboundHTLCState::Committed;
+					htlc_timeline.record(channel_id, htlc.htlc_id, HTLCDirection::Outbound, htlc.payment_hash, HTLCTimelineStage::Committed);
 				}
 				if let &mut OutboundHTLCState::AwaitingRemoteRevokeToRemove(ref mut outcome) = &mut htlc.state {
 					log_trace!(logger, " ...promoting outbound AwaitingRemoteRevokeToRemove {} to AwaitingRemovedRemoteRevoke", log_bytes!(htlc.payment_hash.0));
@@ -3384,6 +4116,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			}
 		}
 		self.context.value_to_self_msat = (self.context.value_to_self_msat as i64 + value_to_self_msat_diff) as u64;
+		self.context.pending_inbound_htlcs_removed(inbound_htlcs_failed);
 
 		if let Some((feerate, update_state)) = self.context.pending_update_fee {
 			match update_state {
@@ -3425,7 +4158,10 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			return Ok((Vec::new(), self.push_ret_blockable_mon_update(monitor_update)));
 		}
 
-		match self.free_holding_cell_htlcs(fee_estimator, logger) {
+		let holding_cell_release = if self.context.awaiting_turn() { (None, Vec::new()) } else {
+			self.free_holding_cell_htlcs(fee_estimator, logger)
+		};
+		match holding_cell_release {
 			(Some(mut additional_update), htlcs_to_fail) => {
 				// free_holding_cell_htlcs may bump latest_monitor_id multiple times but we want them to be
 				// strictly increasing by one, so decrement it here.
@@ -3517,7 +4253,9 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			return None;
 		}
 
-		if (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::MonitorUpdateInProgress as u32)) != 0 {
+		if (self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::MonitorUpdateInProgress as u32)) != 0 ||
+			self.context.awaiting_turn()
+		{
 			force_holding_cell = true;
 		}
 
@@ -3557,6 +4295,10 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			self.context.announcement_sigs_state = AnnouncementSigsState::NotSent;
 		}
 
+		// Turn requests are sent again once the turn has been resynchronized upon reconnect.
+		self.context.turn_requested = false;
+		self.context.counterparty_requested_turn = false;
+
 		// Upon reconnect we have to start the closing_signed dance over, but shutdown messages
 		// will be retransmitted.
 		self.context.last_sent_closing_fee = None;
@@ -3590,6 +4332,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			}
 		});
 		self.context.next_counterparty_htlc_id -= inbound_drop_count;
+		self.context.pending_inbound_htlcs_removed(inbound_drop_count as usize);
 
 		if let Some((_, update_state)) = self.context.pending_update_fee {
 			if update_state == FeeUpdateState::RemoteAnnounced {
@@ -3730,6 +4473,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		if self.context.channel_state & (ChannelState::PeerDisconnected as u32) == ChannelState::PeerDisconnected as u32 {
 			return Err(ChannelError::Close("Peer sent update_fee when we needed a channel_reestablish".to_owned()));
 		}
+		self.check_counterparty_turn("update_fee")?;
 		Channel::<Signer>::check_remote_fee(&self.context.channel_type, fee_estimator, msg.feerate_per_kw, Some(self.context.feerate_per_kw), logger)?;
 		let feerate_over_dust_buffer = msg.feerate_per_kw > self.context.get_dust_buffer_feerate(None);
 
@@ -3898,6 +4642,22 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		// remaining cases either succeed or ErrorMessage-fail).
 		self.context.channel_state &= !(ChannelState::PeerDisconnected as u32);
 		self.context.sent_message_awaiting_response = None;
+		let sent_desired_channel_type = self.context.sent_desired_channel_type.take();
+
+		// A `yield` may have been lost while disconnected, or one side may have restarted with a
+		// stale view of the turn. If exactly one side claims the turn it keeps it, otherwise the
+		// turn goes to the funder. Both sides claimed the turn as of their own `channel_reestablish`
+		// so they're guaranteed to come to the same conclusion.
+		if let Some(our_turn) = self.context.simplified_update_turn {
+			let their_turn = msg.sender_holds_turn == Some(true);
+			let resolved_turn = if our_turn != their_turn { our_turn } else { self.context.is_outbound() };
+			if resolved_turn != our_turn {
+				log_debug!(logger, "Channel {} {} the turn upon reconnection as both sides {} it",
+					log_bytes!(self.context.channel_id()), if resolved_turn { "took" } else { "gave up" },
+					if our_turn { "claimed" } else { "disclaimed" });
+			}
+			self.context.simplified_update_turn = Some(resolved_turn);
+		}
 
 		let shutdown_msg = if self.context.channel_state & (ChannelState::LocalShutdownSent as u32) != 0 {
 			assert!(self.context.shutdown_scriptpubkey.is_some());
@@ -3922,6 +4682,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 					raa: None, commitment_update: None,
 					order: RAACommitmentOrder::CommitmentFirst,
 					shutdown_msg, announcement_sigs,
+					monitor_update: None,
 				});
 			}
 
@@ -3936,6 +4697,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 				raa: None, commitment_update: None,
 				order: RAACommitmentOrder::CommitmentFirst,
 				shutdown_msg, announcement_sigs,
+				monitor_update: None,
 			});
 		}
 
@@ -3981,11 +4743,16 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 				log_debug!(logger, "Reconnected channel {} with no loss", log_bytes!(self.context.channel_id()));
 			}
 
+			let monitor_update = if required_revoke.is_none() && !is_awaiting_remote_revoke {
+				self.maybe_upgrade_channel_type(msg, sent_desired_channel_type, logger)
+			} else { None };
+
 			Ok(ReestablishResponses {
 				channel_ready, shutdown_msg, announcement_sigs,
 				raa: required_revoke,
 				commitment_update: None,
 				order: self.context.resend_order.clone(),
+				monitor_update,
 			})
 		} else if msg.next_local_commitment_number == next_counterparty_commitment_number - 1 {
 			if required_revoke.is_some() {
@@ -4000,6 +4767,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 					channel_ready, shutdown_msg, announcement_sigs,
 					commitment_update: None, raa: None,
 					order: self.context.resend_order.clone(),
+					monitor_update: None,
 				})
 			} else {
 				Ok(ReestablishResponses {
@@ -4007,6 +4775,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 					raa: required_revoke,
 					commitment_update: Some(self.get_last_commitment_update(logger)),
 					order: self.context.resend_order.clone(),
+					monitor_update: None,
 				})
 			}
 		} else {
@@ -4014,6 +4783,109 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		}
 	}
 
+	/// Returns whether both we, per the config this channel was opened with, and our counterparty
+	/// support renegotiating its type. Channels written before we tracked the config they were
+	/// opened with use `default_config` instead.
+	fn negotiates_channel_type_upgrade(&self, default_config: &UserConfig, their_features: &InitFeatures) -> bool {
+		self.context.negotiate_channel_type_upgrade
+			.unwrap_or(default_config.channel_handshake_config.negotiate_channel_type_upgrade) &&
+			their_features.supports_channel_type_upgrade()
+	}
+
+	/// Returns the channel type we'd like to move this channel to, if it differs from the current
+	/// one and we're in a position to switch to it at the next `channel_reestablish`.
+	///
+	/// We only ever change whether the channel uses anchor outputs, and only while the channel has
+	/// no pending HTLCs or other updates in flight so that both sides can simply re-sign the
+	/// current state using the new type. Anchor outputs are only ever added to the current type,
+	/// never removed, unless a downgrade was explicitly requested via
+	/// [`Self::request_channel_type_downgrade`].
+	fn get_desired_channel_type(&self, default_config: &UserConfig, their_features: &InitFeatures) -> Option<ChannelTypeFeatures> {
+		if !self.negotiates_channel_type_upgrade(default_config, their_features) {
+			return None;
+		}
+		// Upgrades require both sides to send a commitment_signed at once, which the turn-based
+		// `option_simplified_update` protocol does not allow.
+		if self.context.simplified_update_turn.is_some() { return None; }
+		if !self.context.is_usable() { return None; }
+		if self.context.channel_state & (ChannelState::AwaitingRemoteRevoke as u32 | ChannelState::MonitorUpdateInProgress as u32) != 0 {
+			return None;
+		}
+		if !self.context.pending_inbound_htlcs.is_empty() || !self.context.pending_outbound_htlcs.is_empty() ||
+			!self.context.holding_cell_htlc_updates.is_empty() || self.context.pending_update_fee.is_some() ||
+			!self.context.blocked_monitor_updates.is_empty()
+		{
+			return None;
+		}
+
+		let mut desired_type = self.context.channel_type.clone();
+		if self.context.channel_type_downgrade_requested {
+			desired_type.clear_anchors_zero_fee_htlc_tx();
+		} else if default_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx && their_features.supports_anchors_zero_fee_htlc_tx() {
+			// As with new channels, we only want anchor outputs if our current default
+			// configuration negotiates them.
+			desired_type.set_anchors_zero_fee_htlc_tx_required();
+		}
+		if desired_type == self.context.channel_type { return None; }
+
+		// The funder pays for the commitment transaction (and anchors) under the new type and must
+		// still be able to meet its reserve afterwards.
+		let (funder_balance_msat, funder_reserve_satoshis) = if self.context.is_outbound() {
+			(self.context.value_to_self_msat, self.context.counterparty_selected_channel_reserve_satoshis.unwrap_or(0))
+		} else {
+			(self.context.channel_value_satoshis * 1000 - self.context.value_to_self_msat, self.context.holder_selected_channel_reserve_satoshis)
+		};
+		let anchors_msat = if desired_type.supports_anchors_zero_fee_htlc_tx() { ANCHOR_OUTPUT_VALUE_SATOSHI * 2 * 1000 } else { 0 };
+		let new_fee_msat = commit_tx_fee_msat(self.context.feerate_per_kw, 0, &desired_type);
+		if funder_balance_msat < new_fee_msat + anchors_msat + funder_reserve_satoshis * 1000 {
+			return None;
+		}
+		Some(desired_type)
+	}
+
+	/// Asks to move this channel off anchor outputs at the next `channel_reestablish` in which the
+	/// counterparty agrees to it. Fails if the channel does not currently use anchor outputs.
+	pub fn request_channel_type_downgrade(&mut self) -> Result<(), ChannelError> {
+		if !self.context.channel_type.supports_anchors_zero_fee_htlc_tx() {
+			return Err(ChannelError::Ignore("Channel does not use anchor outputs".to_owned()));
+		}
+		self.context.channel_type_downgrade_requested = true;
+		Ok(())
+	}
+
+	/// Switches the channel over to the channel type both sides asked for in their
+	/// `channel_reestablish`, if they agree and neither has any updates in flight, returning the
+	/// [`ChannelMonitorUpdate`] for our new commitment_signed.
+	fn maybe_upgrade_channel_type<L: Deref>(
+		&mut self, msg: &msgs::ChannelReestablish, sent_desired_channel_type: Option<ChannelTypeFeatures>, logger: &L
+	) -> Option<ChannelMonitorUpdate> where L::Target: Logger {
+		let new_type = match (sent_desired_channel_type, &msg.desired_channel_type) {
+			(Some(ours), Some(theirs)) if ours == *theirs => ours,
+			_ => return None,
+		};
+		if msg.current_channel_type.as_ref() != Some(&self.context.channel_type) { return None; }
+		if self.context.channel_state & (ChannelState::MonitorUpdateInProgress as u32) != 0 ||
+			!self.context.pending_inbound_htlcs.is_empty() || !self.context.pending_outbound_htlcs.is_empty() ||
+			!self.context.holding_cell_htlc_updates.is_empty() || self.context.pending_update_fee.is_some()
+		{
+			return None;
+		}
+
+		log_info!(logger, "Upgrading channel {} from channel type {} to {}",
+			log_bytes!(self.context.channel_id()), self.context.channel_type, new_type);
+		self.context.channel_type = new_type.clone();
+		self.context.channel_transaction_parameters.channel_type_features = new_type.clone();
+		if !new_type.supports_anchors_zero_fee_htlc_tx() {
+			self.context.channel_type_downgrade_requested = false;
+		}
+		self.context.holder_signer.provide_channel_parameters(&self.context.channel_transaction_parameters);
+
+		let mut monitor_update = self.build_commitment_no_status_check(logger);
+		monitor_update.updates.insert(0, ChannelMonitorUpdateStep::ChannelTypeUpgraded { channel_type: new_type });
+		self.monitor_updating_paused(false, true, false, Vec::new(), Vec::new(), Vec::new());
+		self.push_ret_blockable_mon_update(monitor_update)
+	}
+
 	/// Calculates and returns our minimum and maximum closing transaction fee amounts, in whole
 	/// satoshis. The amounts remain consistent unless a peer disconnects/reconnects or we restart,
 	/// at which point they will be recalculated.
@@ -4470,6 +5342,35 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		self.context.cur_counterparty_commitment_transaction_number + 2
 	}
 
+	/// Computes adaptor signatures for all branches of a contract's settlement bundle with our
+	/// funding key, for contracts whose collateral output is locked to this channel's funding keys.
+	pub fn sign_settlement_transactions_with_adaptor_points(
+		&self, bundle: &SettlementBundle, adaptor_points: &[PublicKey],
+	) -> Result<Vec<EcdsaAdaptorSignature>, ()> {
+		self.check_collateral_is_not_funding(bundle)?;
+		self.context.holder_signer.sign_settlement_transactions_with_adaptor_points(bundle, adaptor_points, &self.context.secp_ctx)
+	}
+
+	/// Signs the settlement transaction for the branch at `branch_idx` of a contract's settlement
+	/// bundle with our funding key, for contracts whose collateral output is locked to this
+	/// channel's funding keys.
+	pub fn sign_settlement_transaction(&self, bundle: &SettlementBundle, branch_idx: usize) -> Result<Signature, ()> {
+		self.check_collateral_is_not_funding(bundle)?;
+		self.context.holder_signer.sign_settlement_transaction(bundle, branch_idx, &self.context.secp_ctx)
+	}
+
+	/// Settlement transactions spending the funding output would allow our counterparty to take
+	/// the channel's funds, thus we never sign them, regardless of the signer in use.
+	fn check_collateral_is_not_funding(&self, bundle: &SettlementBundle) -> Result<(), ()> {
+		if self.context.get_funding_txo() == Some(bundle.collateral().outpoint) { Err(()) } else { Ok(()) }
+	}
+
+	/// Signs a message about a contract whose collateral output is locked to this channel's
+	/// funding keys with our funding key.
+	pub fn sign_contract_message(&self, msg: &[u8]) -> Result<Signature, ()> {
+		self.context.holder_signer.sign_contract_message(msg, &self.context.secp_ctx)
+	}
+
 	#[cfg(test)]
 	pub fn get_signer(&self) -> &Signer {
 		&self.context.holder_signer
@@ -4535,6 +5436,38 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		}
 	}
 
+	/// Hands a transaction releasing a contract's collateral to our [`ChannelMonitor`], which
+	/// will rebroadcast it until the collateral output is spent, returning the
+	/// [`ChannelMonitorUpdate`] to persist if it isn't blocked.
+	pub fn watch_collateral_release(&mut self, transaction: Transaction) -> Result<Option<ChannelMonitorUpdate>, ChannelError> {
+		if self.context.channel_state & (ChannelState::ChannelReady as u32) == 0 ||
+			self.context.channel_state & (ChannelState::ShutdownComplete as u32) != 0
+		{
+			return Err(ChannelError::Ignore("Cannot watch a collateral release on a channel which is not open".to_owned()));
+		}
+		self.context.latest_monitor_update_id += 1;
+		let monitor_update = ChannelMonitorUpdate {
+			update_id: self.context.latest_monitor_update_id,
+			updates: vec![ChannelMonitorUpdateStep::CollateralRelease { transaction }],
+		};
+		self.monitor_updating_paused(false, false, false, Vec::new(), Vec::new(), Vec::new());
+		Ok(self.push_ret_blockable_mon_update(monitor_update))
+	}
+
+	/// Updates the counterparty's node id after it rotated its node identity key, returning the
+	/// [`ChannelMonitorUpdate`] which moves our [`ChannelMonitor`] over to the new node id if it
+	/// isn't blocked.
+	pub fn migrate_counterparty_node_id(&mut self, counterparty_node_id: PublicKey) -> Option<ChannelMonitorUpdate> {
+		self.context.migrate_counterparty_node_id(counterparty_node_id);
+		self.context.latest_monitor_update_id += 1;
+		let monitor_update = ChannelMonitorUpdate {
+			update_id: self.context.latest_monitor_update_id,
+			updates: vec![ChannelMonitorUpdateStep::CounterpartyNodeIdUpdated { counterparty_node_id }],
+		};
+		self.monitor_updating_paused(false, false, false, Vec::new(), Vec::new(), Vec::new());
+		self.push_ret_blockable_mon_update(monitor_update)
+	}
+
 	pub fn blocked_monitor_updates_pending(&self) -> usize {
 		self.context.blocked_monitor_updates.len()
 	}
@@ -5047,7 +5980,13 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 
 	/// May panic if called on a channel that wasn't immediately-previously
 	/// self.remove_uncommitted_htlcs_and_mark_paused()'d
-	pub fn get_channel_reestablish<L: Deref>(&mut self, logger: &L) -> msgs::ChannelReestablish where L::Target: Logger {
+	///
+	/// Whether we try to upgrade the channel type is determined by the config the channel was
+	/// opened with, falling back to `default_config` for channels opened before it was tracked,
+	/// while the type we upgrade to is the one `default_config` would negotiate today.
+	pub fn get_channel_reestablish<L: Deref>(
+		&mut self, default_config: &UserConfig, their_features: &InitFeatures, logger: &L
+	) -> msgs::ChannelReestablish where L::Target: Logger {
 		assert_eq!(self.context.channel_state & ChannelState::PeerDisconnected as u32, ChannelState::PeerDisconnected as u32);
 		assert_ne!(self.context.cur_counterparty_commitment_transaction_number, INITIAL_COMMITMENT_NUMBER);
 		// Prior to static_remotekey, my_current_per_commitment_point was critical to claiming
@@ -5067,6 +6006,8 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			[0;32]
 		};
 		self.mark_awaiting_response();
+		let supports_upgrade = self.negotiates_channel_type_upgrade(default_config, their_features);
+		self.context.sent_desired_channel_type = self.get_desired_channel_type(default_config, their_features);
 		msgs::ChannelReestablish {
 			channel_id: self.context.channel_id(),
 			// The protocol has two different commitment number concepts - the "commitment
@@ -5093,6 +6034,9 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			// construction but have not received `tx_signatures` we MUST set `next_funding_txid` to the
 			// txid of that interactive transaction, else we MUST NOT set it.
 			next_funding_txid: None,
+			sender_holds_turn: self.context.simplified_update_turn,
+			desired_channel_type: self.context.sent_desired_channel_type.clone(),
+			current_channel_type: if supports_upgrade { Some(self.context.channel_type.clone()) } else { None },
 		}
 	}
 
@@ -5182,9 +6126,10 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		log_debug!(logger, "Pushing new outbound HTLC for {} msat {}", amount_msat,
 			if force_holding_cell { "into holding cell" }
 			else if need_holding_cell { "into holding cell as we're awaiting an RAA or monitor" }
+			else if self.context.awaiting_turn() { "into holding cell as we're awaiting our turn" }
 			else { "to peer" });
 
-		if need_holding_cell {
+		if need_holding_cell || self.context.awaiting_turn() {
 			force_holding_cell = true;
 		}
 
@@ -5209,7 +6154,10 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			state: OutboundHTLCState::LocalAnnounced(Box::new(onion_routing_packet.clone())),
 			source,
 			skimmed_fee_msat,
+			timer_ticks: 0,
 		});
+		self.context.htlc_timeline.record(self.context.channel_id, self.context.next_holder_htlc_id,
+			HTLCDirection::Outbound, payment_hash, HTLCTimelineStage::Added);
 
 		let res = msgs::UpdateAddHTLC {
 			channel_id: self.context.channel_id,
@@ -5258,7 +6206,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 		}
 		self.context.resend_order = RAACommitmentOrder::RevokeAndACKFirst;
 
-		let (counterparty_commitment_txid, mut htlcs_ref) = self.build_commitment_no_state_update(logger);
+		let (counterparty_commitment_txid, revokeable_output, mut htlcs_ref) = self.build_commitment_no_state_update(logger);
 		let htlcs: Vec<(HTLCOutputInCommitment, Option<Box<HTLCSource>>)> =
 			htlcs_ref.drain(..).map(|(htlc, htlc_source)| (htlc, htlc_source.map(|source_ref| Box::new(source_ref.clone())))).collect();
 
@@ -5273,14 +6221,15 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 				commitment_txid: counterparty_commitment_txid,
 				htlc_outputs: htlcs.clone(),
 				commitment_number: self.context.cur_counterparty_commitment_transaction_number,
-				their_per_commitment_point: self.context.counterparty_cur_commitment_point.unwrap()
+				their_per_commitment_point: self.context.counterparty_cur_commitment_point.unwrap(),
+				revokeable_output,
 			}]
 		};
 		self.context.channel_state |= ChannelState::AwaitingRemoteRevoke as u32;
 		monitor_update
 	}
 
-	fn build_commitment_no_state_update<L: Deref>(&self, logger: &L) -> (Txid, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>) where L::Target: Logger {
+	fn build_commitment_no_state_update<L: Deref>(&self, logger: &L) -> (Txid, Option<(u32, u64)>, Vec<(HTLCOutputInCommitment, Option<&HTLCSource>)>) where L::Target: Logger {
 		let counterparty_keys = self.context.build_remote_transaction_keys();
 		let commitment_stats = self.context.build_commitment_transaction(self.context.cur_counterparty_commitment_transaction_number, &counterparty_keys, false, true, logger);
 		let counterparty_commitment_txid = commitment_stats.tx.trust().txid();
@@ -5303,7 +6252,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			}
 		}
 
-		(counterparty_commitment_txid, commitment_stats.htlcs_included)
+		(counterparty_commitment_txid, self.context.counterparty_revokeable_output(&commitment_stats.tx), commitment_stats.htlcs_included)
 	}
 
 	/// Only fails in case of signer rejection. Used for channel_reestablish commitment_signed
@@ -5506,6 +6455,38 @@ impl<Signer: WriteableEcdsaChannelSigner> Channel<Signer> {
 			})
 			.chain(self.context.pending_outbound_htlcs.iter().map(|htlc| (&htlc.source, &htlc.payment_hash)))
 	}
+
+	/// Gets a snapshot of the commitment update state machine for debugging purposes. Payment
+	/// preimages in the holding cell are only counted, never included.
+	pub fn debug_state(&self) -> ChannelDebugState {
+		let holding_cell_htlc_adds = self.context.holding_cell_htlc_updates.iter()
+			.filter(|htlc_update| matches!(htlc_update, HTLCUpdateAwaitingACK::AddHTLC { .. }))
+			.count();
+		ChannelDebugState {
+			channel_id: self.context.channel_id,
+			counterparty_node_id: self.context.counterparty_node_id,
+			is_outbound: self.context.is_outbound(),
+			channel_state: self.context.channel_state,
+			next_local_commitment_number: INITIAL_COMMITMENT_NUMBER - self.context.cur_holder_commitment_transaction_number,
+			next_remote_commitment_number: (INITIAL_COMMITMENT_NUMBER - self.context.cur_counterparty_commitment_transaction_number).saturating_sub(1),
+			latest_monitor_update_id: self.context.latest_monitor_update_id,
+			blocked_monitor_updates: self.context.blocked_monitor_updates.len() as u64,
+			resend_commitment_first: self.context.resend_order == RAACommitmentOrder::CommitmentFirst,
+			monitor_pending_channel_ready: self.context.monitor_pending_channel_ready,
+			monitor_pending_revoke_and_ack: self.context.monitor_pending_revoke_and_ack,
+			monitor_pending_commitment_signed: self.context.monitor_pending_commitment_signed,
+			feerate_per_kw: self.context.feerate_per_kw,
+			pending_update_fee: self.context.pending_update_fee.map(|(feerate, _)| feerate),
+			holding_cell_update_fee: self.context.holding_cell_update_fee,
+			holding_cell_htlc_adds: holding_cell_htlc_adds as u64,
+			holding_cell_htlc_removals: (self.context.holding_cell_htlc_updates.len() - holding_cell_htlc_adds) as u64,
+			next_holder_htlc_id: self.context.next_holder_htlc_id,
+			next_counterparty_htlc_id: self.context.next_counterparty_htlc_id,
+			value_to_self_msat: self.context.value_to_self_msat,
+			simplified_update_turn: self.context.simplified_update_turn,
+			pending_htlcs: self.context.get_in_flight_htlc_details(),
+		}
+	}
 }
 
 /// A not-yet-funded outbound (from holder) channel using V1 channel establishment.
@@ -5552,6 +6533,10 @@ impl<Signer: WriteableEcdsaChannelSigner> OutboundV1Channel<Signer> {
 		let channel_type = Self::get_initial_channel_type(&config, their_features);
 		debug_assert!(channel_type.is_subset(&channelmanager::provided_channel_type_features(&config)));
 
+		// With `option_simplified_update` the funder starts out holding the turn.
+		let simplified_update_turn = if config.channel_handshake_config.negotiate_simplified_update &&
+			their_features.supports_simplified_update() { Some(true) } else { None };
+
 		let commitment_conf_target = if channel_type.supports_anchors_zero_fee_htlc_tx() {
 			ConfirmationTarget::MempoolMinimum
 		} else {
@@ -5620,6 +6605,7 @@ impl<Signer: WriteableEcdsaChannelSigner> OutboundV1Channel<Signer> {
 				value_to_self_msat,
 
 				pending_inbound_htlcs: Vec::new(),
+				htlc_timeline: HTLCTimelineRecorder::default(),
 				pending_outbound_htlcs: Vec::new(),
 				holding_cell_htlc_updates: Vec::new(),
 				pending_update_fee: None,
@@ -5699,6 +6685,9 @@ impl<Signer: WriteableEcdsaChannelSigner> OutboundV1Channel<Signer> {
 
 				workaround_lnd_bug_4006: None,
 				sent_message_awaiting_response: None,
+				sent_desired_channel_type: None,
+				negotiate_channel_type_upgrade: Some(config.channel_handshake_config.negotiate_channel_type_upgrade),
+				channel_type_downgrade_requested: false,
 
 				latest_inbound_scid_alias: None,
 				outbound_scid_alias,
@@ -5706,6 +6695,25 @@ impl<Signer: WriteableEcdsaChannelSigner> OutboundV1Channel<Signer> {
 				channel_pending_event_emitted: false,
 				channel_ready_event_emitted: false,
 
+				announced_htlc_minimum_msat: None,
+				announced_htlc_maximum_msat: None,
+
+				idle_timer_ticks: 0,
+				idle_htlc_id_checkpoint: 0,
+
+				unresponsive_timer_ticks: 0,
+				unresponsive_warning_issued: false,
+
+				simplified_update_turn,
+				turn_requested: false,
+				counterparty_requested_turn: false,
+
+				liquidity_fee: None,
+				fee_spike_dust_exposure_percent: None,
+				holder_custom_tlvs: Vec::new(),
+				counterparty_custom_tlvs: Vec::new(),
+				pending_inbound_htlc_counter: None,
+
 				#[cfg(any(test, fuzzing))]
 				historical_inbound_htlc_fulfills: HashSet::new(),
 
@@ -5881,6 +6889,7 @@ impl<Signer: WriteableEcdsaChannelSigner> OutboundV1Channel<Signer> {
 				None => Builder::new().into_script(),
 			}),
 			channel_type: Some(self.context.channel_type.clone()),
+			custom_tlvs: self.context.holder_custom_tlvs.clone(),
 		}
 	}
 
@@ -6008,6 +7017,7 @@ impl<Signer: WriteableEcdsaChannelSigner> OutboundV1Channel<Signer> {
 
 		self.context.counterparty_cur_commitment_point = Some(msg.first_per_commitment_point);
 		self.context.counterparty_shutdown_scriptpubkey = counterparty_shutdown_scriptpubkey;
+		self.context.counterparty_custom_tlvs = msg.custom_tlvs.clone();
 
 		self.context.channel_state = ChannelState::OurInitSent as u32 | ChannelState::TheirInitSent as u32;
 		self.context.inbound_handshake_limits_override = None; // We're done enforcing limits on our peer's handshake now.
@@ -6067,6 +7077,10 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 			channel_type
 		};
 
+		// With `option_simplified_update` the funder starts out holding the turn.
+		let simplified_update_turn = if config.channel_handshake_config.negotiate_simplified_update &&
+			their_features.supports_simplified_update() { Some(false) } else { None };
+
 		let channel_keys_id = signer_provider.generate_channel_keys_id(true, msg.funding_satoshis, user_id);
 		let holder_signer = signer_provider.derive_channel_signer(msg.funding_satoshis, channel_keys_id);
 		let pubkeys = holder_signer.pubkeys().clone();
@@ -6250,6 +7264,7 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 				value_to_self_msat: msg.push_msat,
 
 				pending_inbound_htlcs: Vec::new(),
+				htlc_timeline: HTLCTimelineRecorder::default(),
 				pending_outbound_htlcs: Vec::new(),
 				holding_cell_htlc_updates: Vec::new(),
 				pending_update_fee: None,
@@ -6333,6 +7348,9 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 
 				workaround_lnd_bug_4006: None,
 				sent_message_awaiting_response: None,
+				sent_desired_channel_type: None,
+				negotiate_channel_type_upgrade: Some(config.channel_handshake_config.negotiate_channel_type_upgrade),
+				channel_type_downgrade_requested: false,
 
 				latest_inbound_scid_alias: None,
 				outbound_scid_alias,
@@ -6340,6 +7358,25 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 				channel_pending_event_emitted: false,
 				channel_ready_event_emitted: false,
 
+				announced_htlc_minimum_msat: None,
+				announced_htlc_maximum_msat: None,
+
+				idle_timer_ticks: 0,
+				idle_htlc_id_checkpoint: 0,
+
+				unresponsive_timer_ticks: 0,
+				unresponsive_warning_issued: false,
+
+				simplified_update_turn,
+				turn_requested: false,
+				counterparty_requested_turn: false,
+
+				liquidity_fee: None,
+				fee_spike_dust_exposure_percent: None,
+				holder_custom_tlvs: Vec::new(),
+				counterparty_custom_tlvs: msg.custom_tlvs.clone(),
+				pending_inbound_htlc_counter: None,
+
 				#[cfg(any(test, fuzzing))]
 				historical_inbound_htlc_fulfills: HashSet::new(),
 
@@ -6419,6 +7456,7 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 			channel_type: Some(self.context.channel_type.clone()),
 			#[cfg(taproot)]
 			next_local_nonce: None,
+			custom_tlvs: self.context.holder_custom_tlvs.clone(),
 		}
 	}
 
@@ -6431,7 +7469,7 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 		self.generate_accept_channel_message()
 	}
 
-	fn funding_created_signature<L: Deref>(&mut self, sig: &Signature, logger: &L) -> Result<(Txid, CommitmentTransaction, Signature), ChannelError> where L::Target: Logger {
+	fn funding_created_signature<L: Deref>(&mut self, sig: &Signature, logger: &L) -> Result<(Txid, Option<(u32, u64)>, CommitmentTransaction, Signature), ChannelError> where L::Target: Logger {
 		let funding_script = self.context.get_funding_redeemscript();
 
 		let keys = self.context.build_holder_transaction_keys(self.context.cur_holder_commitment_transaction_number);
@@ -6460,7 +7498,7 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 				.map_err(|_| ChannelError::Close("Failed to get signatures for new commitment_signed".to_owned()))?.0;
 
 		// We sign "counterparty" commitment transaction, allowing them to broadcast the tx if they wish.
-		Ok((counterparty_initial_bitcoin_tx.txid, initial_commitment_tx, counterparty_signature))
+		Ok((counterparty_initial_bitcoin_tx.txid, self.context.counterparty_revokeable_output(&counterparty_initial_commitment_tx), initial_commitment_tx, counterparty_signature))
 	}
 
 	pub fn funding_created<SP: Deref, L: Deref>(
@@ -6494,7 +7532,7 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 		// funding_created_signature may fail.
 		self.context.holder_signer.provide_channel_parameters(&self.context.channel_transaction_parameters);
 
-		let (counterparty_initial_commitment_txid, initial_commitment_tx, signature) = match self.funding_created_signature(&msg.signature, logger) {
+		let (counterparty_initial_commitment_txid, counterparty_revokeable_output, initial_commitment_tx, signature) = match self.funding_created_signature(&msg.signature, logger) {
 			Ok(res) => res,
 			Err(ChannelError::Close(e)) => {
 				self.context.channel_transaction_parameters.funding_outpoint = None;
@@ -6535,7 +7573,7 @@ impl<Signer: WriteableEcdsaChannelSigner> InboundV1Channel<Signer> {
 		                                          obscure_factor,
 		                                          holder_commitment_tx, best_block, self.context.counterparty_node_id);
 
-		channel_monitor.provide_latest_counterparty_commitment_tx(counterparty_initial_commitment_txid, Vec::new(), self.context.cur_counterparty_commitment_transaction_number, self.context.counterparty_cur_commitment_point.unwrap(), logger);
+		channel_monitor.provide_latest_counterparty_commitment_tx(counterparty_initial_commitment_txid, Vec::new(), self.context.cur_counterparty_commitment_transaction_number, self.context.counterparty_cur_commitment_point.unwrap(), counterparty_revokeable_output, logger);
 
 		self.context.channel_state = ChannelState::FundingSent as u32;
 		self.context.channel_id = funding_txo.to_channel_id();
@@ -6897,6 +7935,7 @@ impl<Signer: WriteableEcdsaChannelSigner> Writeable for Channel<Signer> {
 
 		let channel_pending_event_emitted = Some(self.context.channel_pending_event_emitted);
 		let channel_ready_event_emitted = Some(self.context.channel_ready_event_emitted);
+		let idle_timer_ticks = Some(self.context.idle_timer_ticks);
 
 		// `user_id` used to be a single u64 value. In order to remain backwards compatible with
 		// versions prior to 0.0.113, the u128 is serialized as two separate u64 values. Therefore,
@@ -6936,6 +7975,14 @@ impl<Signer: WriteableEcdsaChannelSigner> Writeable for Channel<Signer> {
 			(31, channel_pending_event_emitted, option),
 			(35, pending_outbound_skimmed_fees, optional_vec),
 			(37, holding_cell_skimmed_fees, optional_vec),
+			(39, self.context.announced_htlc_minimum_msat, option),
+			(41, self.context.announced_htlc_maximum_msat, option),
+			(43, idle_timer_ticks, option),
+			(44, self.context.simplified_update_turn, option),
+			(45, self.context.liquidity_fee, option),
+			(49, self.context.counterparty_custom_tlvs, optional_vec),
+			(51, self.context.negotiate_channel_type_upgrade, option),
+			(53, self.context.channel_type_downgrade_requested, required),
 		});
 
 		Ok(())
@@ -7002,6 +8049,7 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 		let value_to_self_msat = Readable::read(reader)?;
 
 		let pending_inbound_htlc_count: u64 = Readable::read(reader)?;
+		check_collection_len_within(pending_inbound_htlc_count, 2 * MAX_HTLCS as u64)?;
 
 		let mut pending_inbound_htlcs = Vec::with_capacity(cmp::min(pending_inbound_htlc_count as usize, DEFAULT_MAX_HTLCS as usize));
 		for _ in 0..pending_inbound_htlc_count {
@@ -7017,10 +8065,12 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 					4 => InboundHTLCState::LocalRemoved(Readable::read(reader)?),
 					_ => return Err(DecodeError::InvalidValue),
 				},
+				timer_ticks: 0,
 			});
 		}
 
 		let pending_outbound_htlc_count: u64 = Readable::read(reader)?;
+		check_collection_len_within(pending_outbound_htlc_count, 2 * MAX_HTLCS as u64)?;
 		let mut pending_outbound_htlcs = Vec::with_capacity(cmp::min(pending_outbound_htlc_count as usize, DEFAULT_MAX_HTLCS as usize));
 		for _ in 0..pending_outbound_htlc_count {
 			pending_outbound_htlcs.push(OutboundHTLCOutput {
@@ -7047,10 +8097,12 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 					_ => return Err(DecodeError::InvalidValue),
 				},
 				skimmed_fee_msat: None,
+				timer_ticks: 0,
 			});
 		}
 
 		let holding_cell_htlc_update_count: u64 = Readable::read(reader)?;
+		check_collection_len_within(holding_cell_htlc_update_count, 2 * MAX_HTLCS as u64)?;
 		let mut holding_cell_htlc_updates = Vec::with_capacity(cmp::min(holding_cell_htlc_update_count as usize, DEFAULT_MAX_HTLCS as usize*2));
 		for _ in 0..holding_cell_htlc_update_count {
 			holding_cell_htlc_updates.push(match <u8 as Readable>::read(reader)? {
@@ -7219,6 +8271,15 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 		let mut pending_outbound_skimmed_fees_opt: Option<Vec<Option<u64>>> = None;
 		let mut holding_cell_skimmed_fees_opt: Option<Vec<Option<u64>>> = None;
 
+		let mut announced_htlc_minimum_msat: Option<u64> = None;
+		let mut announced_htlc_maximum_msat: Option<u64> = None;
+		let mut idle_timer_ticks: Option<u64> = None;
+		let mut simplified_update_turn: Option<bool> = None;
+		let mut liquidity_fee: Option<LiquidityFeeCredit> = None;
+		let mut counterparty_custom_tlvs = Some(Vec::new());
+		let mut negotiate_channel_type_upgrade = None;
+		let mut channel_type_downgrade_requested = None;
+
 		read_tlv_fields!(reader, {
 			(0, announcement_sigs, option),
 			(1, minimum_depth, option),
@@ -7244,6 +8305,14 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 			(31, channel_pending_event_emitted, option),
 			(35, pending_outbound_skimmed_fees_opt, optional_vec),
 			(37, holding_cell_skimmed_fees_opt, optional_vec),
+			(39, announced_htlc_minimum_msat, option),
+			(41, announced_htlc_maximum_msat, option),
+			(43, idle_timer_ticks, option),
+			(44, simplified_update_turn, option),
+			(45, liquidity_fee, option),
+			(49, counterparty_custom_tlvs, optional_vec),
+			(51, negotiate_channel_type_upgrade, option),
+			(53, channel_type_downgrade_requested, option),
 		});
 
 		let (channel_keys_id, holder_signer) = if let Some(channel_keys_id) = channel_keys_id {
@@ -7352,6 +8421,7 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 
 				holder_max_accepted_htlcs,
 				pending_inbound_htlcs,
+				htlc_timeline: HTLCTimelineRecorder::default(),
 				pending_outbound_htlcs,
 				holding_cell_htlc_updates,
 
@@ -7424,6 +8494,9 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 
 				workaround_lnd_bug_4006: None,
 				sent_message_awaiting_response: None,
+				sent_desired_channel_type: None,
+				negotiate_channel_type_upgrade,
+				channel_type_downgrade_requested: channel_type_downgrade_requested.unwrap_or(false),
 
 				latest_inbound_scid_alias,
 				// Later in the ChannelManager deserialization phase we scan for channels and assign scid aliases if its missing
@@ -7432,6 +8505,25 @@ impl<'a, 'b, 'c, ES: Deref, SP: Deref> ReadableArgs<(&'a ES, &'b SP, u32, &'c Ch
 				channel_pending_event_emitted: channel_pending_event_emitted.unwrap_or(true),
 				channel_ready_event_emitted: channel_ready_event_emitted.unwrap_or(true),
 
+				announced_htlc_minimum_msat,
+				announced_htlc_maximum_msat,
+
+				idle_timer_ticks: idle_timer_ticks.unwrap_or(0),
+				idle_htlc_id_checkpoint: next_holder_htlc_id + next_counterparty_htlc_id,
+
+				unresponsive_timer_ticks: 0,
+				unresponsive_warning_issued: false,
+
+				simplified_update_turn,
+				turn_requested: false,
+				counterparty_requested_turn: false,
+
+				liquidity_fee,
+				fee_spike_dust_exposure_percent: None,
+				holder_custom_tlvs: Vec::new(),
+				counterparty_custom_tlvs: counterparty_custom_tlvs.unwrap(),
+				pending_inbound_htlc_counter: None,
+
 				#[cfg(any(test, fuzzing))]
 				historical_inbound_htlc_fulfills,
 
@@ -7650,6 +8742,7 @@ mod tests {
 			payment_hash: PaymentHash(Sha256::hash(&[42; 32]).into_inner()),
 			cltv_expiry: 300000000,
 			state: InboundHTLCState::Committed,
+			timer_ticks: 0,
 		});
 
 		node_a_chan.context.pending_outbound_htlcs.push(OutboundHTLCOutput {
@@ -7665,6 +8758,7 @@ mod tests {
 				payment_id: PaymentId([42; 32]),
 			},
 			skimmed_fee_msat: None,
+			timer_ticks: 0,
 		});
 
 		// Make sure when Node A calculates their local commitment transaction, none of the HTLCs pass
@@ -7772,7 +8866,7 @@ mod tests {
 		// Now disconnect the two nodes and check that the commitment point in
 		// Node B's channel_reestablish message is sane.
 		node_b_chan.remove_uncommitted_htlcs_and_mark_paused(&&logger);
-		let msg = node_b_chan.get_channel_reestablish(&&logger);
+		let msg = node_b_chan.get_channel_reestablish(&config, &channelmanager::provided_init_features(&config), &&logger);
 		assert_eq!(msg.next_local_commitment_number, 1); // now called next_commitment_number
 		assert_eq!(msg.next_remote_commitment_number, 0); // now called next_revocation_number
 		assert_eq!(msg.your_last_per_commitment_secret, [0; 32]);
@@ -7780,7 +8874,7 @@ mod tests {
 		// Check that the commitment point in Node A's channel_reestablish message
 		// is sane.
 		node_a_chan.remove_uncommitted_htlcs_and_mark_paused(&&logger);
-		let msg = node_a_chan.get_channel_reestablish(&&logger);
+		let msg = node_a_chan.get_channel_reestablish(&config, &channelmanager::provided_init_features(&config), &&logger);
 		assert_eq!(msg.next_local_commitment_number, 1); // now called next_commitment_number
 		assert_eq!(msg.next_remote_commitment_number, 0); // now called next_revocation_number
 		assert_eq!(msg.your_last_per_commitment_secret, [0; 32]);
@@ -18,6 +18,7 @@
 //! imply it needs to fail HTLCs/payments/channels it manages).
 
 use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::script::Script;
 use bitcoin::blockdata::transaction::Transaction;
 use bitcoin::blockdata::constants::{genesis_block, ChainHash};
 use bitcoin::network::constants::Network;
@@ -28,11 +29,12 @@ use bitcoin::hash_types::{BlockHash, Txid};
 
 use bitcoin::secp256k1::{SecretKey,PublicKey};
 use bitcoin::secp256k1::Secp256k1;
+use bitcoin::secp256k1::ecdsa::Signature;
 use bitcoin::{LockTime, secp256k1, Sequence};
 
 use crate::chain;
 use crate::chain::{Confirm, ChannelMonitorUpdateStatus, Watch, BestBlock};
-use crate::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator, LowerBoundedFeeEstimator};
+use crate::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator, LowerBoundedFeeEstimator, TransactionMetadata, TransactionType};
 use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate, ChannelMonitorUpdateStep, HTLC_FAIL_BACK_BUFFER, CLTV_CLAIM_BUFFER, LATENCY_GRACE_PERIOD_BLOCKS, ANTI_REORG_DELAY, MonitorEvent, CLOSED_CHANNEL_UPDATE_ID};
 use crate::chain::transaction::{OutPoint, TransactionData};
 use crate::events;
@@ -45,30 +47,34 @@ use crate::ln::features::{ChannelFeatures, ChannelTypeFeatures, InitFeatures, No
 #[cfg(any(feature = "_test_utils", test))]
 use crate::ln::features::Bolt11InvoiceFeatures;
 use crate::routing::gossip::NetworkGraph;
-use crate::routing::router::{BlindedTail, DefaultRouter, InFlightHtlcs, Path, Payee, PaymentParameters, Route, RouteParameters, Router};
+use crate::routing::router::{BlindedTail, DefaultRouter, InFlightHtlcs, Path, Payee, PaymentParameters, Route, RouteHop, RouteParameters, Router};
 use crate::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringFeeParameters};
 use crate::ln::msgs;
 use crate::ln::onion_utils;
+use crate::onion_message::ChannelPeerLookup;
 use crate::ln::onion_utils::HTLCFailReason;
 use crate::ln::msgs::{ChannelMessageHandler, DecodeError, LightningError};
 #[cfg(test)]
 use crate::ln::outbound_payment;
-use crate::ln::outbound_payment::{OutboundPayments, PaymentAttempts, PendingOutboundPayment};
+use crate::ln::outbound_payment::{OutboundPayments, PaymentAttempts, PendingOutboundPayment, RetryBudgetTracker};
 use crate::ln::wire::Encode;
-use crate::sign::{EntropySource, KeysManager, NodeSigner, Recipient, SignerProvider, ChannelSigner, WriteableEcdsaChannelSigner};
-use crate::util::config::{UserConfig, ChannelConfig, ChannelConfigUpdate};
+use crate::sign::{EntropySource, InMemorySigner, KeysManager, NodeSigner, Recipient, SignerProvider, ChannelSigner, WriteableEcdsaChannelSigner};
+use crate::util::config::{UserConfig, ChannelConfig, ChannelConfigUpdate, ForwardingHarmonizationConfig, HTLCAcceptanceLimits, IdleChannelAction};
 use crate::util::wakers::{Future, Notifier};
 use crate::util::scid_utils::fake_scid;
 use crate::util::string::UntrustedString;
 use crate::util::ser::{BigSize, FixedLengthReader, Readable, ReadableArgs, MaybeReadable, Writeable, Writer, VecWriter};
 use crate::util::logger::{Level, Logger};
 use crate::util::errors::APIError;
+use crate::util::time::{ConfiguredTime, Time};
+#[cfg(feature = "htlc_timeline_events")]
+use crate::util::time::{DefaultTimeSource, TimeSource};
 
 use alloc::collections::BTreeMap;
 
 use crate::io;
 use crate::prelude::*;
-use core::{cmp, mem};
+use core::{cmp, fmt, mem};
 use core::cell::RefCell;
 use crate::io::Read;
 use crate::sync::{Arc, Mutex, RwLock, RwLockReadGuard, FairRwLock, LockTestExt, LockHeldState};
@@ -78,7 +84,11 @@ use core::ops::Deref;
 
 // Re-export this for use in the public API.
 pub use crate::ln::outbound_payment::{PaymentSendFailure, Retry, RetryableSendFailure, RecipientOnionFields};
-use crate::ln::script::ShutdownScript;
+use crate::ln::script::{ShutdownScript, ShutdownScriptPolicy};
+use crate::ln::peer_metadata::PeerMetadata;
+use crate::ln::contractmanager::{ContractPaymentSender, ContractSigner};
+use crate::ln::contracts::SettlementBundle;
+use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
 
 // We hold various information about HTLC relay in the HTLC objects in Channel itself:
 //
@@ -110,6 +120,7 @@ pub(super) enum PendingHTLCRouting {
 		payment_metadata: Option<Vec<u8>>,
 		incoming_cltv_expiry: u32, // Used to track when we should expire pending HTLCs that go unclaimed
 		phantom_shared_secret: Option<[u8; 32]>,
+		custom_tlvs: Vec<(u64, Vec<u8>)>,
 	},
 	ReceiveKeysend {
 		/// This was added in 0.0.116 and will break deserialization on downgrades.
@@ -117,6 +128,7 @@ pub(super) enum PendingHTLCRouting {
 		payment_preimage: PaymentPreimage,
 		payment_metadata: Option<Vec<u8>>,
 		incoming_cltv_expiry: u32, // Used to track when we should expire pending HTLCs that go unclaimed
+		custom_tlvs: Vec<(u64, Vec<u8>)>,
 	},
 }
 
@@ -164,6 +176,44 @@ pub(super) struct PendingAddHTLCInfo {
 	prev_user_channel_id: u128,
 }
 
+/// An HTLC paying us which is held until the user accepts or fails it, see
+/// [`InboundHTLCValidationConfig`].
+///
+/// [`InboundHTLCValidationConfig`]: crate::util::config::InboundHTLCValidationConfig
+struct HeldHTLC {
+	htlc: PendingAddHTLCInfo,
+	/// The number of timer ticks left until the HTLC is automatically failed back.
+	ticks_remaining: u16,
+}
+
+/// A cooperative close requested while our fee spike circuit breaker was engaged, which is
+/// initiated once it's released, see [`FeeSpikeCircuitBreakerConfig::defer_cooperative_closes`].
+///
+/// [`FeeSpikeCircuitBreakerConfig::defer_cooperative_closes`]: crate::util::config::FeeSpikeCircuitBreakerConfig::defer_cooperative_closes
+struct DeferredCooperativeClose {
+	channel_id: [u8; 32],
+	counterparty_node_id: PublicKey,
+	target_feerate_sats_per_1000_weight: Option<u32>,
+	shutdown_script: Option<ShutdownScript>,
+}
+
+/// The on-chain fallback of one of our invoices, which we watch for payments, see
+/// [`ChannelManager::watch_onchain_fallback`].
+struct OnchainFallback {
+	payment_hash: PaymentHash,
+	expected_amount_msat: Option<u64>,
+	/// The first output paying to the fallback script we've seen confirmed, which is only surfaced
+	/// once it reaches [`ANTI_REORG_DELAY`] confirmations.
+	payment: Option<OnchainFallbackPayment>,
+}
+
+struct OnchainFallbackPayment {
+	outpoint: bitcoin::OutPoint,
+	amount_satoshis: u64,
+	block_hash: BlockHash,
+	height: u32,
+}
+
 pub(super) enum HTLCForwardInfo {
 	AddHTLC(PendingAddHTLCInfo),
 	FailHTLC {
@@ -471,11 +521,13 @@ struct ClaimingPayment {
 	amount_msat: u64,
 	payment_purpose: events::PaymentPurpose,
 	receiver_node_id: PublicKey,
+	shortfall_msat: Option<u64>,
 }
 impl_writeable_tlv_based!(ClaimingPayment, {
 	(0, amount_msat, required),
 	(2, payment_purpose, required),
 	(4, receiver_node_id, required),
+	(5, shortfall_msat, option),
 });
 
 struct ClaimablePayment {
@@ -500,6 +552,77 @@ struct ClaimablePayments {
 	/// are waiting on a [`ChannelMonitorUpdate`] to complete in order to be surfaced to the user
 	/// as an [`events::Event::PaymentClaimed`].
 	pending_claiming_payments: HashMap<PaymentHash, ClaimingPayment>,
+
+	/// Map from payment hash to the conditions under which an incomplete MPP set may be surfaced
+	/// as claimable once it times out, rather than failed back. See
+	/// [`ChannelManager::allow_partial_claim`].
+	partial_claim_allowances: HashMap<PaymentHash, PartialClaimAllowance>,
+}
+
+/// An allowance to claim an incomplete MPP set, granted via [`ChannelManager::allow_partial_claim`].
+struct PartialClaimAllowance {
+	/// The minimum sender-intended value, in msats, of a set which may be claimed.
+	min_amount_msat: u64,
+	/// The time, in seconds since the UNIX epoch, after which the allowance is forgotten once no
+	/// set is pending for the payment, as with the invoice it was granted for.
+	expiry_time: u64,
+}
+
+impl_writeable_tlv_based!(PartialClaimAllowance, {
+	(0, min_amount_msat, required),
+	(2, expiry_time, required),
+});
+
+/// Counters describing how a [`ChannelManager`] applied its [`UserConfig::htlc_acceptance_limits`]
+/// to new inbound HTLCs, as returned by [`ChannelManager::htlc_acceptance_stats`].
+///
+/// Only HTLCs with a valid onion are counted.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HTLCAcceptanceStats {
+	/// The number of new inbound HTLCs which were within our limits.
+	pub accepted: u64,
+	/// The number of new inbound HTLCs failed back as they exceeded
+	/// [`HTLCAcceptanceLimits::max_htlcs_per_second`].
+	pub rate_limited: u64,
+	/// The number of new inbound HTLCs failed back as they exceeded
+	/// [`HTLCAcceptanceLimits::max_pending_htlcs`].
+	pub pending_limited: u64,
+}
+
+/// Enforces [`HTLCAcceptanceLimits::max_htlcs_per_second`] as a token bucket.
+struct HTLCAcceptanceLimiter {
+	/// The number of available tokens, in thousandths of a token.
+	millitokens: u64,
+	last_refill: ConfiguredTime,
+	stats: HTLCAcceptanceStats,
+}
+
+impl HTLCAcceptanceLimiter {
+	fn new(limits: &HTLCAcceptanceLimits) -> Self {
+		HTLCAcceptanceLimiter {
+			millitokens: limits.max_htlcs_per_second.unwrap_or(0) as u64 * 1000,
+			last_refill: ConfiguredTime::now(),
+			stats: HTLCAcceptanceStats::default(),
+		}
+	}
+
+	/// Attempts to take a token from the bucket, returning whether one was available.
+	fn try_consume(&mut self, htlcs_per_second: u32) -> bool {
+		let now = ConfiguredTime::now();
+		let elapsed_millis = now.duration_since(self.last_refill).as_millis() as u64;
+		if elapsed_millis > 0 {
+			self.millitokens = self.millitokens
+				.saturating_add(elapsed_millis.saturating_mul(htlcs_per_second as u64))
+				.min(htlcs_per_second as u64 * 1000);
+			self.last_refill = now;
+		}
+		if self.millitokens >= 1000 {
+			self.millitokens -= 1000;
+			true
+		} else {
+			false
+		}
+	}
 }
 
 /// Events which we process internally but cannot be processed immediately at the generation site
@@ -777,6 +900,27 @@ pub type SimpleRefChannelManager<'a, 'b, 'c, 'd, 'e, 'f, 'g, 'h, M, T, F, L> =
 		&'g L
 	>;
 
+/// [`AnyChannelManager`] is a [`ChannelManager`] whose dependencies are all `Arc`'d trait objects,
+/// using the [`InMemorySigner`] provided by [`KeysManager`] as its channel signer. As its type
+/// doesn't depend on the concrete types of its dependencies, it can be named by downstream crates
+/// and FFI layers without repeating the generic parameters of [`ChannelManager`] and its bounds,
+/// at the cost of dynamic dispatch. An `Arc<AnyChannelManager>` may itself be used as the
+/// `Arc<dyn ChannelMessageHandler + Send + Sync>` of an [`AnyPeerManager`].
+///
+/// This is not exported to bindings users as `Arc`s don't make sense in bindings.
+///
+/// [`AnyPeerManager`]: crate::ln::peer_handler::AnyPeerManager
+pub type AnyChannelManager = ChannelManager<
+	Arc<dyn chain::Watch<InMemorySigner> + Send + Sync>,
+	Arc<dyn BroadcasterInterface + Send + Sync>,
+	Arc<dyn EntropySource + Send + Sync>,
+	Arc<dyn NodeSigner + Send + Sync>,
+	Arc<dyn SignerProvider<Signer = InMemorySigner> + Send + Sync>,
+	Arc<dyn FeeEstimator + Send + Sync>,
+	Arc<dyn Router + Send + Sync>,
+	Arc<dyn Logger + Send + Sync>
+>;
+
 macro_rules! define_test_pub_trait { ($vis: vis) => {
 /// A trivial trait which describes any [`ChannelManager`] used in testing.
 $vis trait AChannelManager {
@@ -904,6 +1048,10 @@ where
 //  |   |
 //  |   |__`pending_intercepted_htlcs`
 //  |
+//  |__`pending_held_htlcs`
+//  |
+//  |__`manually_failed_forwards`
+//  |
 //  |__`per_peer_state`
 //  |   |
 //  |   |__`pending_inbound_payments`
@@ -993,6 +1141,21 @@ where
 	///
 	/// See `ChannelManager` struct-level documentation for lock order requirements.
 	pending_intercepted_htlcs: Mutex<HashMap<InterceptId, PendingAddHTLCInfo>>,
+	/// Storage for HTLCs paying us that have been held back for validation and bubbled up to the
+	/// user. We hold them here until the user accepts or fails them, see
+	/// [`InboundHTLCValidationConfig`].
+	///
+	/// See `ChannelManager` struct-level documentation for lock order requirements.
+	///
+	/// [`InboundHTLCValidationConfig`]: crate::util::config::InboundHTLCValidationConfig
+	pending_held_htlcs: Mutex<HashMap<InterceptId, HeldHTLC>>,
+
+	/// The previous hops of forwarded HTLCs which the user failed backwards via
+	/// [`ChannelManager::fail_forwarded_htlc`] before the outbound HTLC was resolved. Once the
+	/// outbound HTLC is resolved we must not attempt to resolve the inbound HTLC a second time.
+	///
+	/// See `ChannelManager` struct-level documentation for lock order requirements.
+	manually_failed_forwards: Mutex<HashSet<HTLCPreviousHopData>>,
 
 	/// The sets of payments which are claimable or currently being claimed. See
 	/// [`ClaimablePayments`]' individual field docs for more info.
@@ -1064,6 +1227,46 @@ where
 	/// keeping additional state.
 	probing_cookie_secret: [u8; 32],
 
+	/// The source of the timestamps of [`Event::HTLCTimeline`]s, shared with all our funded
+	/// channels, see [`Self::set_htlc_timeline_time_source`].
+	///
+	/// [`Event::HTLCTimeline`]: events::Event::HTLCTimeline
+	#[cfg(feature = "htlc_timeline_events")]
+	htlc_timeline_time_source: Mutex<Arc<dyn TimeSource + Send + Sync>>,
+
+	/// Policies over the shutdown scripts specific counterparties may use, set via
+	/// [`Self::set_peer_shutdown_script_policy`].
+	shutdown_script_policies: Mutex<HashMap<PublicKey, ShutdownScriptPolicy>>,
+
+	/// User-provided metadata about peers, see [`Self::set_peer_metadata`].
+	peer_metadata: Mutex<HashMap<PublicKey, PeerMetadata>>,
+
+	/// Counterparties whose channels are never closed automatically for being idle, see
+	/// [`Self::set_idle_close_allowlisted`].
+	idle_close_allowlist: Mutex<HashSet<PublicKey>>,
+
+	/// The total value of dust HTLCs we forwarded to each counterparty which were still pending
+	/// when the channel they were forwarded over closed, see [`Self::dust_htlcs_written_off_msat`].
+	closed_channel_dust_write_offs: Mutex<HashMap<PublicKey, u64>>,
+
+	/// The on-chain fallbacks of our invoices which we're watching for payments, see
+	/// [`Self::watch_onchain_fallback`].
+	onchain_fallbacks: Mutex<HashMap<Script, OnchainFallback>>,
+
+	/// Enforces [`UserConfig::htlc_acceptance_limits`], see [`Self::htlc_acceptance_stats`].
+	htlc_acceptance_limiter: Mutex<HTLCAcceptanceLimiter>,
+	/// The number of inbound HTLCs we have not yet begun removing across all our funded channels,
+	/// kept up to date by the channels themselves so that we can enforce
+	/// [`HTLCAcceptanceLimits::max_pending_htlcs`] without scanning them.
+	pending_inbound_htlcs: Arc<AtomicUsize>,
+
+	/// Whether our fee spike circuit breaker is engaged, see
+	/// [`UserConfig::fee_spike_circuit_breaker_config`].
+	fee_spike_circuit_breaker_engaged: AtomicBool,
+	/// Cooperative closes requested while our fee spike circuit breaker was engaged, which are
+	/// initiated once it's released.
+	deferred_cooperative_closes: Mutex<Vec<DeferredCooperativeClose>>,
+
 	/// The highest block timestamp we've seen, which is usually a good guess at the current time.
 	/// Assuming most miners are generating blocks with reasonable timestamps, this shouldn't be
 	/// very far in the past, and can only ever be up to two hours in the future.
@@ -1289,6 +1492,11 @@ const MAX_UNFUNDED_CHANNEL_PEERS: usize = 50;
 /// many peers we reject new (inbound) connections.
 const MAX_NO_CHANNEL_PEERS: usize = 250;
 
+/// The maximum total serialized length of the custom TLVs we attach to an `open_channel` or
+/// `accept_channel` message, leaving ample room for the rest of the message within the 65535 byte
+/// limit on Lightning messages.
+const MAX_CUSTOM_CHANNEL_TLVS_LEN: usize = 60_000;
+
 /// Information needed for constructing an invoice route hint for this channel.
 #[derive(Clone, Debug, PartialEq)]
 pub struct CounterpartyForwardingInfo {
@@ -1505,6 +1713,15 @@ pub struct ChannelDetails {
 	///
 	/// This field is only `None` for `ChannelDetails` objects serialized prior to LDK 0.0.109.
 	pub config: Option<ChannelConfig>,
+	/// The total value of the dust HTLCs we've forwarded over this channel which are still
+	/// pending, in millisatoshis.
+	///
+	/// Dust HTLCs are trimmed from the commitment transactions, so their value is lost to fees
+	/// rather than claimable on-chain if the channel is force-closed while they're pending.
+	///
+	/// See also [`ChannelManager::dust_htlcs_written_off_msat`] and
+	/// [`UserConfig::max_dust_htlc_write_off_msat`].
+	pub pending_dust_forwards_msat: u64,
 }
 
 impl ChannelDetails {
@@ -1580,6 +1797,7 @@ impl ChannelDetails {
 			inbound_htlc_maximum_msat: context.get_holder_htlc_maximum_msat(),
 			config: Some(context.config()),
 			channel_shutdown_state: Some(context.shutdown_state()),
+			pending_dust_forwards_msat: context.get_pending_dust_forwards_msat(true),
 		}
 	}
 }
@@ -1636,6 +1854,320 @@ pub enum RecentPaymentDetails {
 	},
 }
 
+/// Details of an outbound payment which has not yet been fulfilled, as returned by
+/// [`ChannelManager::list_outbound_payments`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutboundPaymentDetails {
+	/// The id of the payment, as passed when it was sent.
+	pub payment_id: PaymentId,
+	/// The hash of the payment.
+	pub payment_hash: PaymentHash,
+	/// Total amount (in msat, excluding fees) across all paths for this payment, not just the
+	/// amount currently in flight. `None` for abandoned payments, for which it is not tracked.
+	pub total_msat: Option<u64>,
+	/// The amount (in msat, excluding fees) currently in flight across all parts. `None` for
+	/// abandoned payments, for which it is not tracked.
+	pub pending_msat: Option<u64>,
+	/// The number of times the payment has been retried so far.
+	pub retry_count: usize,
+	/// Whether the payment will be retried automatically by the [`ChannelManager`] if one of its
+	/// parts fails, per the [`Retry`] it was sent with.
+	pub is_auto_retryable: bool,
+	/// Whether the payment has been abandoned, either because its retries were exhausted or via
+	/// [`ChannelManager::abandon_payment`]. An abandoned payment is listed until all its parts
+	/// have resolved and [`Event::PaymentFailed`] has been generated.
+	pub is_abandoned: bool,
+	/// Whether the payment may still be abandoned via [`ChannelManager::abandon_payment`]. Note
+	/// that abandoning a payment does not fail its in-flight parts, which may still be claimed by
+	/// the recipient.
+	pub can_abandon: bool,
+	/// The number of parts of the payment which are still pending, including any which are no
+	/// longer in one of our channels, e.g. because the channel was closed and the HTLC is being
+	/// resolved on-chain.
+	pub num_pending_parts: usize,
+	/// The parts of the payment which are currently pending in one of our channels.
+	pub parts: Vec<OutboundPaymentPartDetails>,
+}
+
+/// Details of a single part of an outbound payment, as reported in [`OutboundPaymentDetails`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutboundPaymentPartDetails {
+	/// The id of our channel the part is pending in.
+	pub channel_id: [u8; 32],
+	/// The node id of our counterparty in the channel the part is pending in, i.e. the first hop
+	/// of the part's path.
+	pub counterparty_node_id: PublicKey,
+	/// The short channel id of the channel the part was routed over, as used in its path.
+	pub first_hop_short_channel_id: u64,
+	/// The amount (in msat, excluding fees) the part delivers to the recipient.
+	pub amount_msat: u64,
+	/// The total fees (in msat) paid to intermediate nodes along the part's path.
+	pub fee_msat: u64,
+	/// The absolute block height at which the HTLC in our channel expires.
+	pub cltv_expiry: u32,
+	/// The stage of the commitment update process the HTLC in our channel is in, or `None` if it
+	/// is still in the channel's holding cell and has not yet been offered to our counterparty.
+	pub stage: Option<HTLCStage>,
+}
+
+/// The direction of an HTLC relative to us, as reported in [`InFlightHTLCDetails`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HTLCDirection {
+	/// The HTLC was offered to us by our counterparty.
+	Inbound,
+	/// The HTLC was offered by us to our counterparty.
+	Outbound,
+}
+
+/// An asymmetry between the forwarding parameters we advertise for one of our channels and those
+/// our counterparty advertises for it, as detected according to
+/// [`UserConfig::forwarding_harmonization_config`].
+///
+/// Returned by [`ChannelManager::list_forwarding_asymmetries`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ForwardingAsymmetry {
+	/// Our `cltv_expiry_delta` is too low relative to the one our counterparty advertises, leaving us
+	/// too little time to claim an incoming HTLC when forwarding over the channel.
+	CltvExpiryDeltaTooLow {
+		/// The channel_id of the channel.
+		channel_id: [u8; 32],
+		/// The node_id of our counterparty in the channel.
+		counterparty_node_id: PublicKey,
+		/// The [`ChannelConfig::cltv_expiry_delta`] we currently advertise.
+		ours: u16,
+		/// The `cltv_expiry_delta` our counterparty advertises.
+		theirs: u16,
+		/// The lowest `cltv_expiry_delta` which resolves the asymmetry, capped at
+		/// [`ForwardingHarmonizationConfig::max_cltv_expiry_delta`].
+		suggested: u16,
+	},
+	/// Our proportional fee is too high relative to the one our counterparty advertises, making it
+	/// unlikely the channel is used for forwarding in our direction.
+	ProportionalFeeTooHigh {
+		/// The channel_id of the channel.
+		channel_id: [u8; 32],
+		/// The node_id of our counterparty in the channel.
+		counterparty_node_id: PublicKey,
+		/// The [`ChannelConfig::forwarding_fee_proportional_millionths`] we currently advertise.
+		ours: u32,
+		/// The `fee_proportional_millionths` our counterparty advertises.
+		theirs: u32,
+		/// The highest `fee_proportional_millionths` which resolves the asymmetry, but no lower than
+		/// [`ForwardingHarmonizationConfig::min_fee_proportional_millionths`].
+		suggested: u32,
+	},
+}
+
+impl ForwardingAsymmetry {
+	/// The channel_id of the channel with the asymmetry.
+	pub fn channel_id(&self) -> [u8; 32] {
+		match self {
+			ForwardingAsymmetry::CltvExpiryDeltaTooLow { channel_id, .. } => *channel_id,
+			ForwardingAsymmetry::ProportionalFeeTooHigh { channel_id, .. } => *channel_id,
+		}
+	}
+
+	/// The node_id of our counterparty in the channel with the asymmetry.
+	pub fn counterparty_node_id(&self) -> PublicKey {
+		match self {
+			ForwardingAsymmetry::CltvExpiryDeltaTooLow { counterparty_node_id, .. } => *counterparty_node_id,
+			ForwardingAsymmetry::ProportionalFeeTooHigh { counterparty_node_id, .. } => *counterparty_node_id,
+		}
+	}
+
+	/// The update to our [`ChannelConfig`] which resolves the asymmetry, which may be applied with
+	/// [`ChannelManager::update_partial_channel_config`].
+	pub fn config_update(&self) -> ChannelConfigUpdate {
+		match self {
+			ForwardingAsymmetry::CltvExpiryDeltaTooLow { suggested, .. } => ChannelConfigUpdate {
+				cltv_expiry_delta: Some(*suggested),
+				..Default::default()
+			},
+			ForwardingAsymmetry::ProportionalFeeTooHigh { suggested, .. } => ChannelConfigUpdate {
+				forwarding_fee_proportional_millionths: Some(*suggested),
+				..Default::default()
+			},
+		}
+	}
+}
+
+/// Compares the forwarding parameters we advertise for a channel with those our counterparty
+/// advertises for it, returning any asymmetries exceeding the bounds in `config`.
+///
+/// Suggestions are clamped to the floor and ceiling in `config`, and asymmetries which can't be
+/// reduced within them aren't returned, so a counterparty can't push our parameters to extremes.
+fn check_forwarding_asymmetries(
+	config: &ForwardingHarmonizationConfig, channel_id: [u8; 32], counterparty_node_id: PublicKey,
+	ours: &ChannelConfig, theirs: &CounterpartyForwardingInfo,
+) -> Vec<ForwardingAsymmetry> {
+	let mut res = Vec::new();
+	if let Some(min_percent) = config.min_cltv_expiry_delta_percent {
+		let min_delta = (theirs.cltv_expiry_delta as u64 * min_percent as u64 + 99) / 100;
+		let suggested = cmp::max(
+			cmp::min(min_delta, config.max_cltv_expiry_delta as u64) as u16, MIN_CLTV_EXPIRY_DELTA);
+		if (ours.cltv_expiry_delta as u64) < min_delta && ours.cltv_expiry_delta < suggested {
+			res.push(ForwardingAsymmetry::CltvExpiryDeltaTooLow {
+				channel_id, counterparty_node_id,
+				ours: ours.cltv_expiry_delta,
+				theirs: theirs.cltv_expiry_delta,
+				suggested,
+			});
+		}
+	}
+	if let Some(max_multiple) = config.max_fee_proportional_millionths_multiple {
+		let max_fee = theirs.fee_proportional_millionths as u64 * max_multiple as u64;
+		let suggested = cmp::max(max_fee, config.min_fee_proportional_millionths as u64);
+		if theirs.fee_proportional_millionths != 0 &&
+			ours.forwarding_fee_proportional_millionths as u64 > suggested
+		{
+			res.push(ForwardingAsymmetry::ProportionalFeeTooHigh {
+				channel_id, counterparty_node_id,
+				ours: ours.forwarding_fee_proportional_millionths,
+				theirs: theirs.fee_proportional_millionths,
+				// As our fee exceeds `suggested`, it must fit in a u32.
+				suggested: suggested as u32,
+			});
+		}
+	}
+	res
+}
+
+/// The stage of the commitment update process an HTLC is in, as reported in
+/// [`InFlightHTLCDetails`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HTLCStage {
+	/// The HTLC has been offered but is not yet irrevocably committed to by both parties.
+	AwaitingCommitment,
+	/// The HTLC is irrevocably committed to by both parties and is awaiting resolution.
+	Committed,
+	/// The HTLC has been fulfilled or failed, but its removal is not yet irrevocably committed to by
+	/// both parties.
+	AwaitingRemoval,
+}
+
+/// Details of an HTLC which is pending in one of our channels, as returned by
+/// [`ChannelManager::list_in_flight_htlcs`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InFlightHTLCDetails {
+	/// The id of the channel the HTLC is pending in.
+	pub channel_id: [u8; 32],
+	/// The node id of our counterparty in the channel the HTLC is pending in.
+	pub counterparty_node_id: PublicKey,
+	/// The id of the HTLC, unique per channel and direction.
+	pub htlc_id: u64,
+	/// Whether the HTLC was offered to us or by us.
+	pub direction: HTLCDirection,
+	/// The stage of the commitment update process the HTLC is in.
+	pub stage: HTLCStage,
+	/// The value of the HTLC, in thousandths of a satoshi.
+	pub amount_msat: u64,
+	/// The payment hash of the HTLC.
+	pub payment_hash: PaymentHash,
+	/// The absolute block height at which the HTLC expires.
+	pub cltv_expiry: u32,
+	/// The number of calls to [`ChannelManager::timer_tick_occurred`] since the HTLC was added to
+	/// the channel.
+	///
+	/// This is not persisted and restarts from zero when the [`ChannelManager`] is reloaded.
+	pub age_timer_ticks: u32,
+	/// Whether this is an [`HTLCDirection::Outbound`] HTLC which we forwarded on behalf of an
+	/// inbound HTLC in another channel. If it is stuck, the inbound HTLC may be failed back via
+	/// [`ChannelManager::fail_forwarded_htlc`].
+	pub is_forward: bool,
+}
+
+/// A snapshot of the commitment update state machine of one of our channels, as returned by
+/// [`ChannelManager::export_channel_debug`].
+///
+/// This is intended to be attached to bug reports and thus contains no key material,
+/// per-commitment secrets or payment preimages. It may be serialized via [`Writeable`] or printed
+/// in a human-readable form via its [`fmt::Display`] implementation.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelDebugState {
+	/// The id of the channel.
+	pub channel_id: [u8; 32],
+	/// The node id of our counterparty.
+	pub counterparty_node_id: PublicKey,
+	/// Whether we funded the channel.
+	pub is_outbound: bool,
+	/// The raw internal channel state flags.
+	pub channel_state: u32,
+	/// The number of the next `commitment_signed` we expect to receive, as we'd send in
+	/// [`msgs::ChannelReestablish::next_local_commitment_number`].
+	pub next_local_commitment_number: u64,
+	/// The number of the next `revoke_and_ack` we expect to receive, as we'd send in
+	/// [`msgs::ChannelReestablish::next_remote_commitment_number`].
+	pub next_remote_commitment_number: u64,
+	/// The id of the latest [`ChannelMonitorUpdate`] we generated.
+	pub latest_monitor_update_id: u64,
+	/// The number of [`ChannelMonitorUpdate`]s which are blocked on other events before they may
+	/// be handed to the [`chain::Watch`].
+	pub blocked_monitor_updates: u64,
+	/// Whether we'd resend our `commitment_signed` before our `revoke_and_ack` if both are pending
+	/// upon reconnection.
+	pub resend_commitment_first: bool,
+	/// Whether a `channel_ready` is waiting on a monitor update to complete before being sent.
+	pub monitor_pending_channel_ready: bool,
+	/// Whether a `revoke_and_ack` is waiting on a monitor update to complete before being sent.
+	pub monitor_pending_revoke_and_ack: bool,
+	/// Whether a `commitment_signed` is waiting on a monitor update to complete before being
+	/// sent.
+	pub monitor_pending_commitment_signed: bool,
+	/// The feerate of the current commitment transactions, in satoshis per 1000 weight units.
+	pub feerate_per_kw: u32,
+	/// The feerate of an `update_fee` which has yet to be irrevocably committed, if any.
+	pub pending_update_fee: Option<u32>,
+	/// The feerate of an `update_fee` waiting in the holding cell, if any.
+	pub holding_cell_update_fee: Option<u32>,
+	/// The number of HTLC additions waiting in the holding cell.
+	pub holding_cell_htlc_adds: u64,
+	/// The number of HTLC claims and failures waiting in the holding cell.
+	pub holding_cell_htlc_removals: u64,
+	/// The id the next HTLC we offer will use.
+	pub next_holder_htlc_id: u64,
+	/// The id the next HTLC our counterparty offers is expected to use.
+	pub next_counterparty_htlc_id: u64,
+	/// Our balance in the channel, in thousandths of a satoshi, not including pending HTLCs.
+	pub value_to_self_msat: u64,
+	/// If the channel uses `option_simplified_update`, whether we currently hold the turn.
+	pub simplified_update_turn: Option<bool>,
+	/// The HTLCs pending in the channel, not including those still in the holding cell.
+	pub pending_htlcs: Vec<InFlightHTLCDetails>,
+}
+
+impl fmt::Display for ChannelDebugState {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "channel {} with {} ({})", log_bytes!(self.channel_id), self.counterparty_node_id,
+			if self.is_outbound { "outbound" } else { "inbound" })?;
+		writeln!(f, "  state flags: {:#x}", self.channel_state)?;
+		writeln!(f, "  next commitment numbers: local {}, remote {}", self.next_local_commitment_number,
+			self.next_remote_commitment_number)?;
+		writeln!(f, "  monitor updates: latest id {}, {} blocked", self.latest_monitor_update_id,
+			self.blocked_monitor_updates)?;
+		writeln!(f, "  pending on monitor: channel_ready {}, revoke_and_ack {}, commitment_signed {}",
+			self.monitor_pending_channel_ready, self.monitor_pending_revoke_and_ack,
+			self.monitor_pending_commitment_signed)?;
+		writeln!(f, "  resend order: {}",
+			if self.resend_commitment_first { "commitment first" } else { "revoke_and_ack first" })?;
+		writeln!(f, "  feerate: {} sat/kw, pending update {:?}, holding cell {:?}", self.feerate_per_kw,
+			self.pending_update_fee, self.holding_cell_update_fee)?;
+		writeln!(f, "  holding cell: {} adds, {} removals", self.holding_cell_htlc_adds,
+			self.holding_cell_htlc_removals)?;
+		writeln!(f, "  next htlc ids: holder {}, counterparty {}", self.next_holder_htlc_id,
+			self.next_counterparty_htlc_id)?;
+		writeln!(f, "  value to self: {} msat", self.value_to_self_msat)?;
+		if let Some(our_turn) = self.simplified_update_turn {
+			writeln!(f, "  simplified update turn: {}", if our_turn { "ours" } else { "theirs" })?;
+		}
+		for htlc in self.pending_htlcs.iter() {
+			writeln!(f, "  htlc {} {:?} {:?}: {} msat, hash {}, expiry {}, age {} ticks", htlc.htlc_id,
+				htlc.direction, htlc.stage, htlc.amount_msat, log_bytes!(htlc.payment_hash.0),
+				htlc.cltv_expiry, htlc.age_timer_ticks)?;
+		}
+		Ok(())
+	}
+}
+
 /// Route hints used in constructing invoices for [phantom node payents].
 ///
 /// [phantom node payments]: crate::sign::PhantomKeysManager
@@ -1727,6 +2259,14 @@ macro_rules! update_maps_on_chan_removal {
 			debug_assert!(alias_removed);
 		}
 		short_to_chan_info.remove(&$channel_context.outbound_scid_alias());
+		// Any dust HTLCs we forwarded which are still pending can no longer be claimed on-chain,
+		// so their value is written off. Channels which close cooperatively have none left.
+		let dust_htlcs_written_off_msat = $channel_context.get_pending_dust_forwards_msat(false);
+		if dust_htlcs_written_off_msat != 0 {
+			let mut closed_channel_dust_write_offs = $self.closed_channel_dust_write_offs.lock().unwrap();
+			let written_off_msat = closed_channel_dust_write_offs.entry($channel_context.get_counterparty_node_id()).or_insert(0);
+			*written_off_msat = written_off_msat.saturating_add(dust_htlcs_written_off_msat);
+		}
 	}}
 }
 
@@ -1848,6 +2388,7 @@ macro_rules! emit_channel_pending_event {
 				counterparty_node_id: $channel.context.get_counterparty_node_id(),
 				user_channel_id: $channel.context.get_user_id(),
 				funding_txo: $channel.context.get_funding_txo().unwrap().into_bitcoin_outpoint(),
+				counterparty_custom_tlvs: $channel.context.get_counterparty_custom_tlvs().clone(),
 			}, None));
 			$channel.context.set_channel_pending_event_emitted();
 		}
@@ -2005,6 +2546,9 @@ macro_rules! process_events_body {
 				if $self.process_pending_monitor_events() {
 					result = NotifyOption::DoPersist;
 				}
+
+				#[cfg(feature = "htlc_timeline_events")]
+				$self.collect_htlc_timeline_events();
 			}
 
 			let pending_events = $self.pending_events.lock().unwrap().clone();
@@ -2094,10 +2638,15 @@ where
 
 			outbound_scid_aliases: Mutex::new(HashSet::new()),
 			pending_inbound_payments: Mutex::new(HashMap::new()),
-			pending_outbound_payments: OutboundPayments::new(),
+			pending_outbound_payments: OutboundPayments::new(config.payment_retry_budget),
 			forward_htlcs: Mutex::new(HashMap::new()),
-			claimable_payments: Mutex::new(ClaimablePayments { claimable_payments: HashMap::new(), pending_claiming_payments: HashMap::new() }),
+			claimable_payments: Mutex::new(ClaimablePayments {
+				claimable_payments: HashMap::new(), pending_claiming_payments: HashMap::new(),
+				partial_claim_allowances: HashMap::new(),
+			}),
 			pending_intercepted_htlcs: Mutex::new(HashMap::new()),
+			pending_held_htlcs: Mutex::new(HashMap::new()),
+			manually_failed_forwards: Mutex::new(HashSet::new()),
 			id_to_peer: Mutex::new(HashMap::new()),
 			short_to_chan_info: FairRwLock::new(HashMap::new()),
 
@@ -2108,6 +2657,17 @@ where
 			fake_scid_rand_bytes: entropy_source.get_secure_random_bytes(),
 
 			probing_cookie_secret: entropy_source.get_secure_random_bytes(),
+			#[cfg(feature = "htlc_timeline_events")]
+			htlc_timeline_time_source: Mutex::new(Arc::new(DefaultTimeSource::new())),
+			shutdown_script_policies: Mutex::new(HashMap::new()),
+			peer_metadata: Mutex::new(HashMap::new()),
+			idle_close_allowlist: Mutex::new(HashSet::new()),
+			closed_channel_dust_write_offs: Mutex::new(HashMap::new()),
+			onchain_fallbacks: Mutex::new(HashMap::new()),
+			htlc_acceptance_limiter: Mutex::new(HTLCAcceptanceLimiter::new(&config.htlc_acceptance_limits)),
+			pending_inbound_htlcs: Arc::new(AtomicUsize::new(0)),
+			fee_spike_circuit_breaker_engaged: AtomicBool::new(false),
+			deferred_cooperative_closes: Mutex::new(Vec::new()),
 
 			highest_seen_timestamp: AtomicUsize::new(current_timestamp as usize),
 
@@ -2182,6 +2742,25 @@ where
 	/// [`Event::FundingGenerationReady::temporary_channel_id`]: events::Event::FundingGenerationReady::temporary_channel_id
 	/// [`Event::ChannelClosed::channel_id`]: events::Event::ChannelClosed::channel_id
 	pub fn create_channel(&self, their_network_key: PublicKey, channel_value_satoshis: u64, push_msat: u64, user_channel_id: u128, override_config: Option<UserConfig>) -> Result<[u8; 32], APIError> {
+		self.create_channel_with_custom_tlvs(their_network_key, channel_value_satoshis, push_msat, user_channel_id, override_config, Vec::new())
+	}
+
+	/// Creates a new outbound channel exactly like [`Self::create_channel`], but attaches the
+	/// given application-specific `custom_tlvs` to the `open_channel` message, e.g. to reference
+	/// the terms of a contract the channel is opened for.
+	///
+	/// Each TLV type must be odd and at least [`msgs::MIN_CUSTOM_CHANNEL_TLV_TYPE`], so that
+	/// counterparties which don't understand them will ignore them. Counterparties running LDK
+	/// surface them in [`Event::OpenChannelRequest::custom_tlvs`] and both sides see the other's in
+	/// [`Event::ChannelPending::counterparty_custom_tlvs`].
+	///
+	/// Raises [`APIError::APIMisuseError`] if any type is invalid or repeated, or the TLVs are too
+	/// large to fit in the `open_channel` message.
+	///
+	/// [`Event::OpenChannelRequest::custom_tlvs`]: events::Event::OpenChannelRequest::custom_tlvs
+	/// [`Event::ChannelPending::counterparty_custom_tlvs`]: events::Event::ChannelPending::counterparty_custom_tlvs
+	pub fn create_channel_with_custom_tlvs(&self, their_network_key: PublicKey, channel_value_satoshis: u64, push_msat: u64, user_channel_id: u128, override_config: Option<UserConfig>, mut custom_tlvs: Vec<(u64, Vec<u8>)>) -> Result<[u8; 32], APIError> {
+		Self::check_custom_channel_tlvs(&mut custom_tlvs)?;
 		if channel_value_satoshis < 1000 {
 			return Err(APIError::APIMisuseError { err: format!("Channel value must be at least 1000 satoshis. It was {}", channel_value_satoshis) });
 		}
@@ -2196,7 +2775,7 @@ where
 			.ok_or_else(|| APIError::APIMisuseError{ err: format!("Not connected to node: {}", their_network_key) })?;
 
 		let mut peer_state = peer_state_mutex.lock().unwrap();
-		let channel = {
+		let mut channel = {
 			let outbound_scid_alias = self.create_and_insert_outbound_scid_alias();
 			let their_features = &peer_state.latest_features;
 			let config = if override_config.is_some() { override_config.as_ref().unwrap() } else { &self.default_configuration };
@@ -2211,6 +2790,7 @@ where
 				},
 			}
 		};
+		channel.context.set_holder_custom_tlvs(custom_tlvs);
 		let res = channel.get_open_channel(self.genesis_hash.clone());
 
 		let temporary_channel_id = channel.context.channel_id();
@@ -2232,6 +2812,25 @@ where
 		Ok(temporary_channel_id)
 	}
 
+	/// Sorts the given custom `open_channel`/`accept_channel` TLVs by type, checking that they can
+	/// be sent.
+	fn check_custom_channel_tlvs(custom_tlvs: &mut Vec<(u64, Vec<u8>)>) -> Result<(), APIError> {
+		custom_tlvs.sort_unstable_by_key(|(typ, _)| *typ);
+		if let Some((typ, _)) = custom_tlvs.iter().find(|(typ, _)| *typ < msgs::MIN_CUSTOM_CHANNEL_TLV_TYPE || typ % 2 == 0) {
+			return Err(APIError::APIMisuseError { err: format!("Custom TLV types must be odd and at least {}, got {}", msgs::MIN_CUSTOM_CHANNEL_TLV_TYPE, typ) });
+		}
+		if let Some(tlvs) = custom_tlvs.windows(2).find(|tlvs| tlvs[0].0 == tlvs[1].0) {
+			return Err(APIError::APIMisuseError { err: format!("Custom TLV type {} was given more than once", tlvs[0].0) });
+		}
+		let tlvs_len: usize = custom_tlvs.iter().map(|(typ, value)| {
+			BigSize(*typ).serialized_length() + BigSize(value.len() as u64).serialized_length() + value.len()
+		}).sum();
+		if tlvs_len > MAX_CUSTOM_CHANNEL_TLVS_LEN {
+			return Err(APIError::APIMisuseError { err: format!("Custom TLVs may total at most {} bytes, got {}", MAX_CUSTOM_CHANNEL_TLVS_LEN, tlvs_len) });
+		}
+		Ok(())
+	}
+
 	fn list_funded_channels_with_filter<Fn: FnMut(&(&[u8; 32], &Channel<<SP::Target as SignerProvider>::Signer>)) -> bool + Copy>(&self, f: Fn) -> Vec<ChannelDetails> {
 		// Allocate our best estimate of the number of channels we have in the `res`
 		// Vec. Sadly the `short_to_chan_info` map doesn't cover channels without
@@ -2357,6 +2956,122 @@ where
 			.collect()
 	}
 
+	/// Gets the list of outbound payments which have not yet been fulfilled, in random order,
+	/// along with the state of each of their parts. See [`OutboundPaymentDetails`] field
+	/// documentation for more information.
+	///
+	/// Unlike [`Self::list_recent_payments`], this reports where each part of a payment currently
+	/// is, allowing in-flight payments to be monitored and managed without tracking events.
+	pub fn list_outbound_payments(&self) -> Vec<OutboundPaymentDetails> {
+		let mut parts_by_payment: HashMap<PaymentId, Vec<OutboundPaymentPartDetails>> = HashMap::new();
+		{
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			for (_cp_id, peer_state_mutex) in per_peer_state.iter() {
+				let peer_state_lock = peer_state_mutex.lock().unwrap();
+				for (_channel_id, channel) in peer_state_lock.channel_by_id.iter() {
+					for (payment_id, part) in channel.context.get_outbound_payment_parts() {
+						parts_by_payment.entry(payment_id).or_insert_with(Vec::new).push(part);
+					}
+				}
+			}
+		}
+
+		self.pending_outbound_payments.pending_outbound_payments.lock().unwrap().iter()
+			.filter_map(|(payment_id, pending_outbound_payment)| {
+				let parts = parts_by_payment.remove(payment_id).unwrap_or_else(Vec::new);
+				match pending_outbound_payment {
+					PendingOutboundPayment::Retryable {
+						payment_hash, total_msat, pending_amt_msat, attempts, session_privs, ..
+					} => Some(OutboundPaymentDetails {
+						payment_id: *payment_id,
+						payment_hash: *payment_hash,
+						total_msat: Some(*total_msat),
+						pending_msat: Some(*pending_amt_msat),
+						retry_count: attempts.count,
+						is_auto_retryable: pending_outbound_payment.is_auto_retryable_now(),
+						is_abandoned: false,
+						can_abandon: true,
+						num_pending_parts: session_privs.len(),
+						parts,
+					}),
+					PendingOutboundPayment::Abandoned { payment_hash, session_privs, .. } => {
+						Some(OutboundPaymentDetails {
+							payment_id: *payment_id,
+							payment_hash: *payment_hash,
+							total_msat: None,
+							pending_msat: None,
+							retry_count: 0,
+							is_auto_retryable: false,
+							is_abandoned: true,
+							can_abandon: false,
+							num_pending_parts: session_privs.len(),
+							parts,
+						})
+					},
+					PendingOutboundPayment::Fulfilled { .. } |
+					PendingOutboundPayment::Legacy { .. } => None,
+				}
+			})
+			.collect()
+	}
+
+	/// Gets the list of HTLCs pending across all our funded channels, in random order. See
+	/// [`InFlightHTLCDetails`] field documentation for more information.
+	///
+	/// This is useful for diagnosing HTLCs which appear stuck, which may then be resolved via
+	/// [`Self::fail_forwarded_htlc`] or [`Self::force_close_to_resolve_htlc`].
+	pub fn list_in_flight_htlcs(&self) -> Vec<InFlightHTLCDetails> {
+		let mut res = Vec::new();
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		for (_cp_id, peer_state_mutex) in per_peer_state.iter() {
+			let peer_state_lock = peer_state_mutex.lock().unwrap();
+			for (_channel_id, channel) in peer_state_lock.channel_by_id.iter() {
+				res.append(&mut channel.context.get_in_flight_htlc_details());
+			}
+		}
+		res
+	}
+
+	/// Gets the list of asymmetries between the forwarding parameters we advertise for our funded
+	/// channels and those our counterparties advertise for them, according to
+	/// [`UserConfig::forwarding_harmonization_config`]. See [`ForwardingAsymmetry`] for more
+	/// information.
+	///
+	/// Channels for which we have not yet received our counterparty's `channel_update` are skipped.
+	/// Asymmetries may be resolved by applying [`ForwardingAsymmetry::config_update`], which happens
+	/// automatically in [`Self::timer_tick_occurred`] if
+	/// [`ForwardingHarmonizationConfig::auto_harmonize`] is set.
+	pub fn list_forwarding_asymmetries(&self) -> Vec<ForwardingAsymmetry> {
+		let config = self.default_configuration.forwarding_harmonization_config;
+		let mut res = Vec::new();
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		for (counterparty_node_id, peer_state_mutex) in per_peer_state.iter() {
+			let peer_state_lock = peer_state_mutex.lock().unwrap();
+			for (channel_id, channel) in peer_state_lock.channel_by_id.iter() {
+				if let Some(their_info) = channel.context.counterparty_forwarding_info() {
+					res.append(&mut check_forwarding_asymmetries(&config, *channel_id,
+						*counterparty_node_id, &channel.context.config(), &their_info));
+				}
+			}
+		}
+		res
+	}
+
+	/// Gets a snapshot of the commitment update state machine of the funded channel with the given
+	/// id, suitable for attaching to bug reports. See [`ChannelDebugState`] for more information.
+	///
+	/// Returns `None` if no such funded channel exists.
+	pub fn export_channel_debug(&self, channel_id: &[u8; 32]) -> Option<ChannelDebugState> {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		for (_cp_id, peer_state_mutex) in per_peer_state.iter() {
+			let peer_state_lock = peer_state_mutex.lock().unwrap();
+			if let Some(channel) = peer_state_lock.channel_by_id.get(channel_id) {
+				return Some(channel.debug_state());
+			}
+		}
+		None
+	}
+
 	/// Helper function that issues the channel close events
 	fn issue_channel_close_events(&self, context: &ChannelContext<<SP::Target as SignerProvider>::Signer>, closure_reason: ClosureReason) {
 		let mut pending_events_lock = self.pending_events.lock().unwrap();
@@ -2376,6 +3091,31 @@ where
 	}
 
 	fn close_channel_internal(&self, channel_id: &[u8; 32], counterparty_node_id: &PublicKey, target_feerate_sats_per_1000_weight: Option<u32>, override_shutdown_script: Option<ShutdownScript>) -> Result<(), APIError> {
+		if self.fee_spike_circuit_breaker_engaged.load(Ordering::Acquire) &&
+			self.default_configuration.fee_spike_circuit_breaker_config.defer_cooperative_closes
+		{
+			{
+				let per_peer_state = self.per_peer_state.read().unwrap();
+				let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+					.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+				if !peer_state_mutex.lock().unwrap().channel_by_id.contains_key(channel_id) {
+					return Err(APIError::ChannelUnavailable {
+						err: format!("Channel with id {} not found for the passed counterparty node_id {}", log_bytes!(*channel_id), counterparty_node_id)
+					});
+				}
+			}
+			log_info!(self.logger, "Deferring cooperative close of channel {} until on-chain feerates drop", log_bytes!(*channel_id));
+			let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+			let mut deferred_closes = self.deferred_cooperative_closes.lock().unwrap();
+			// A repeated request replaces the parameters of the earlier one.
+			deferred_closes.retain(|close| close.channel_id != *channel_id);
+			deferred_closes.push(DeferredCooperativeClose {
+				channel_id: *channel_id, counterparty_node_id: *counterparty_node_id,
+				target_feerate_sats_per_1000_weight, shutdown_script: override_shutdown_script,
+			});
+			return Ok(());
+		}
+
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
 		let mut failed_htlcs: Vec<(HTLCSource, PaymentHash)>;
@@ -2458,12 +3198,16 @@ where
 	///
 	/// May generate a [`SendShutdown`] message event on success, which should be relayed.
 	///
+	/// While our fee spike circuit breaker is engaged, the close may be deferred until it is
+	/// released, see [`FeeSpikeCircuitBreakerConfig::defer_cooperative_closes`].
+	///
 	/// Raises [`APIError::ChannelUnavailable`] if the channel cannot be closed due to failing to
 	/// generate a shutdown scriptpubkey or destination script set by
 	/// [`SignerProvider::get_shutdown_scriptpubkey`]. A force-closure may be needed to close the
 	/// channel.
 	///
 	/// [`ChannelConfig::force_close_avoidance_max_fee_satoshis`]: crate::util::config::ChannelConfig::force_close_avoidance_max_fee_satoshis
+	/// [`FeeSpikeCircuitBreakerConfig::defer_cooperative_closes`]: crate::util::config::FeeSpikeCircuitBreakerConfig::defer_cooperative_closes
 	/// [`Background`]: crate::chain::chaininterface::ConfirmationTarget::Background
 	/// [`Normal`]: crate::chain::chaininterface::ConfirmationTarget::Normal
 	/// [`SendShutdown`]: crate::events::MessageSendEvent::SendShutdown
@@ -2492,12 +3236,16 @@ where
 	///
 	/// May generate a [`SendShutdown`] message event on success, which should be relayed.
 	///
+	/// While our fee spike circuit breaker is engaged, the close may be deferred until it is
+	/// released, see [`FeeSpikeCircuitBreakerConfig::defer_cooperative_closes`].
+	///
 	/// Raises [`APIError::ChannelUnavailable`] if the channel cannot be closed due to failing to
 	/// generate a shutdown scriptpubkey or destination script set by
 	/// [`SignerProvider::get_shutdown_scriptpubkey`]. A force-closure may be needed to close the
 	/// channel.
 	///
 	/// [`ChannelConfig::force_close_avoidance_max_fee_satoshis`]: crate::util::config::ChannelConfig::force_close_avoidance_max_fee_satoshis
+	/// [`FeeSpikeCircuitBreakerConfig::defer_cooperative_closes`]: crate::util::config::FeeSpikeCircuitBreakerConfig::defer_cooperative_closes
 	/// [`Background`]: crate::chain::chaininterface::ConfirmationTarget::Background
 	/// [`Normal`]: crate::chain::chaininterface::ConfirmationTarget::Normal
 	/// [`SendShutdown`]: crate::events::MessageSendEvent::SendShutdown
@@ -2615,6 +3363,79 @@ where
 		self.force_close_sending_error(channel_id, counterparty_node_id, false)
 	}
 
+	/// Fails backwards the inbound HTLC which we forwarded as the outbound HTLC with the given
+	/// `htlc_id` in the given channel, without waiting for the outbound HTLC to be resolved.
+	///
+	/// This is intended for manually unsticking payments where the next hop is not making progress
+	/// on an HTLC, see [`Self::list_in_flight_htlcs`]. Only outbound HTLCs for which
+	/// [`InFlightHTLCDetails::is_forward`] is set and which have not yet been removed by our
+	/// counterparty can be failed backwards.
+	///
+	/// Note that the outbound HTLC remains pending. If our counterparty later fulfills it, we will
+	/// be unable to claim the inbound HTLC and will lose the forwarded amount. Thus, this should
+	/// only be used if you are confident the outbound HTLC will fail or time out.
+	pub fn fail_forwarded_htlc(&self, channel_id: &[u8; 32], counterparty_node_id: &PublicKey, htlc_id: u64)
+	-> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let (prev_hop, payment_hash) = {
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+				.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+			let peer_state_lock = peer_state_mutex.lock().unwrap();
+			match peer_state_lock.channel_by_id.get(channel_id) {
+				Some(chan) => chan.context.get_forwarded_htlc_prev_hop(htlc_id)
+					.ok_or_else(|| APIError::APIMisuseError {
+						err: format!("No unresolved forwarded HTLC with id {} in channel {}", htlc_id, log_bytes!(*channel_id))
+					})?,
+				None => return Err(APIError::ChannelUnavailable {
+					err: format!("Channel with id {} not found for the passed counterparty node_id {}", log_bytes!(*channel_id), counterparty_node_id)
+				}),
+			}
+		};
+		if self.manually_failed_forwards.lock().unwrap().contains(&prev_hop) {
+			return Err(APIError::APIMisuseError {
+				err: format!("Forwarded HTLC with id {} in channel {} was already failed back", htlc_id, log_bytes!(*channel_id))
+			});
+		}
+
+		log_info!(self.logger, "Manually failing back inbound HTLC forwarded as HTLC {} in channel {}", htlc_id, log_bytes!(*channel_id));
+		let source = HTLCSource::PreviousHopData(prev_hop.clone());
+		let reason = HTLCFailReason::from_failure_code(0x2000 | 2);
+		let destination = HTLCDestination::NextHopChannel { node_id: Some(*counterparty_node_id), channel_id: *channel_id };
+		self.fail_htlc_backwards_internal(&source, &payment_hash, &reason, destination);
+		self.manually_failed_forwards.lock().unwrap().insert(prev_hop);
+		Ok(())
+	}
+
+	/// Force closes the given channel in order to resolve the given pending HTLC on-chain,
+	/// immediately broadcasting the latest local transaction(s).
+	///
+	/// HTLCs cannot be resolved on-chain individually, so this closes the whole channel, exactly
+	/// as [`Self::force_close_broadcasting_latest_txn`] does. It differs only in that it fails
+	/// without closing the channel if the given HTLC is no longer pending, avoiding needless
+	/// force-closes for HTLCs which were resolved since they were listed in
+	/// [`Self::list_in_flight_htlcs`].
+	pub fn force_close_to_resolve_htlc(&self, channel_id: &[u8; 32], counterparty_node_id: &PublicKey,
+		direction: HTLCDirection, htlc_id: u64) -> Result<(), APIError> {
+		{
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+				.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+			let peer_state_lock = peer_state_mutex.lock().unwrap();
+			let chan = peer_state_lock.channel_by_id.get(channel_id).ok_or_else(|| APIError::ChannelUnavailable {
+				err: format!("Channel with id {} not found for the passed counterparty node_id {}", log_bytes!(*channel_id), counterparty_node_id)
+			})?;
+			let htlc_pending = chan.context.get_in_flight_htlc_details().iter().any(|htlc|
+				htlc.direction == direction && htlc.htlc_id == htlc_id && htlc.stage != HTLCStage::AwaitingRemoval);
+			if !htlc_pending {
+				return Err(APIError::APIMisuseError {
+					err: format!("No unresolved {:?} HTLC with id {} in channel {}", direction, htlc_id, log_bytes!(*channel_id))
+				});
+			}
+		}
+		self.force_close_broadcasting_latest_txn(channel_id, counterparty_node_id)
+	}
+
 	/// Force close all channels, immediately broadcasting the latest local commitment transaction
 	/// for each to the chain and rejecting new HTLCs on each.
 	pub fn force_close_all_channels_broadcasting_latest_txn(&self) {
@@ -2680,7 +3501,14 @@ where
 					msg: "Got non final data with an HMAC of 0",
 				});
 			},
-			msgs::OnionHopDataFormat::FinalNode { payment_data, keysend_preimage, payment_metadata } => {
+			msgs::OnionHopDataFormat::BlindedNode { .. } | msgs::OnionHopDataFormat::BlindedReceive { .. } => {
+				return Err(ReceiveError {
+					err_code: 0x4000|22,
+					err_data: Vec::new(),
+					msg: "Receiving over blinded paths is not supported",
+				});
+			},
+			msgs::OnionHopDataFormat::FinalNode { payment_data, keysend_preimage, payment_metadata, custom_tlvs } => {
 				if let Some(payment_preimage) = keysend_preimage {
 					// We need to check that the sender knows the keysend preimage before processing this
 					// payment further. Otherwise, an intermediary routing hop forwarding non-keysend-HTLC X
@@ -2707,6 +3535,7 @@ where
 						payment_preimage,
 						payment_metadata,
 						incoming_cltv_expiry: hop_data.outgoing_cltv_value,
+						custom_tlvs,
 					}
 				} else if let Some(data) = payment_data {
 					PendingHTLCRouting::Receive {
@@ -2714,6 +3543,7 @@ where
 						payment_metadata,
 						incoming_cltv_expiry: hop_data.outgoing_cltv_value,
 						phantom_shared_secret,
+						custom_tlvs,
 					}
 				} else {
 					return Err(ReceiveError {
@@ -2810,7 +3640,15 @@ where
 				next_hop_data: msgs::OnionHopData { format: msgs::OnionHopDataFormat::FinalNode { .. }, .. }, ..
 			} => {
 				return_err!("Final Node OnionHopData provided for us as an intermediary node", 0x4000 | 22, &[0; 0]);
-			}
+			},
+			onion_utils::Hop::Forward {
+				next_hop_data: msgs::OnionHopData { format: msgs::OnionHopDataFormat::BlindedNode { .. }, .. }, ..
+			} |
+			onion_utils::Hop::Forward {
+				next_hop_data: msgs::OnionHopData { format: msgs::OnionHopDataFormat::BlindedReceive { .. }, .. }, ..
+			} => {
+				return_err!("Forwarding over blinded paths is not supported", 0x4000 | 22, &[0; 0]);
+			},
 		};
 
 		// Perform outbound checks here instead of in [`Self::construct_pending_htlc_info`] because we
@@ -2877,9 +3715,14 @@ where
 						break Some(("Forwarding channel is not in a ready state.", 0x1000 | 7, chan_update_opt));
 					}
 				}
-				if outgoing_amt_msat < chan.context.get_counterparty_htlc_minimum_msat() { // amount_below_minimum
+				if outgoing_amt_msat < chan.context.get_announced_htlc_min_msat() { // amount_below_minimum
 					break Some(("HTLC amount was below the htlc_minimum_msat", 0x1000 | 11, chan_update_opt));
 				}
+				if chan.context.get_announced_htlc_max_msat_override().is_some() &&
+					outgoing_amt_msat > chan.context.get_announced_htlc_max_msat()
+				{
+					break Some(("HTLC amount was above the htlc_maximum_msat", 0x1000 | 7, chan_update_opt));
+				}
 				if let Err((err, code)) = chan.htlc_satisfies_config(&msg, outgoing_amt_msat, outgoing_cltv_value) {
 					break Some((err, code, chan_update_opt));
 				}
@@ -2996,6 +3839,9 @@ where
 					msgs::OnionHopDataFormat::FinalNode { .. } => {
 						return_err!("Final Node OnionHopData provided for us as an intermediary node", 0x4000 | 22, &[0;0]);
 					},
+					msgs::OnionHopDataFormat::BlindedNode { .. } | msgs::OnionHopDataFormat::BlindedReceive { .. } => {
+						return_err!("Forwarding over blinded paths is not supported", 0x4000 | 22, &[0;0]);
+					},
 				};
 
 				PendingHTLCStatus::Forward(PendingHTLCInfo {
@@ -3076,7 +3922,7 @@ where
 			timestamp: chan.context.get_update_time_counter(),
 			flags: (!were_node_one) as u8 | ((!enabled as u8) << 1),
 			cltv_expiry_delta: chan.context.get_cltv_expiry_delta(),
-			htlc_minimum_msat: chan.context.get_counterparty_htlc_minimum_msat(),
+			htlc_minimum_msat: chan.context.get_announced_htlc_min_msat(),
 			htlc_maximum_msat: chan.context.get_announced_htlc_max_msat(),
 			fee_base_msat: chan.context.get_outbound_forwarding_fee_base_msat(),
 			fee_proportional_millionths: chan.context.get_fee_proportional_millionths(),
@@ -3249,6 +4095,30 @@ where
 				self.send_payment_along_path(path, payment_hash, recipient_onion, total_value, cur_height, payment_id, keysend_preimage, session_priv))
 	}
 
+	/// Similar to [`ChannelManager::send_payment_with_route`], but will automatically retry failed
+	/// payment paths based on `retry_strategy`, always retrying over paths from the given `route`
+	/// rather than finding a new one via our [`Router`].
+	///
+	/// On each retry, paths whose values sum to the amount which failed are selected from `route`
+	/// and passed to [`Router::update_pinned_route`], allowing them to be modified for that attempt
+	/// only. The route used by the initial attempt and by each retry is reported via an
+	/// [`Event::PaymentAttemptSent`].
+	///
+	/// The given `route` must have [`Route::payment_params`] set, as these are required to retry
+	/// the payment.
+	///
+	/// [`Event::PaymentAttemptSent`]: events::Event::PaymentAttemptSent
+	pub fn send_payment_with_pinned_route(&self, route: &Route, payment_hash: PaymentHash, recipient_onion: RecipientOnionFields, payment_id: PaymentId, retry_strategy: Retry) -> Result<(), PaymentSendFailure> {
+		let best_block_height = self.best_block.read().unwrap().height();
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		self.pending_outbound_payments
+			.send_payment_with_pinned_route(route, payment_hash, recipient_onion, payment_id,
+				retry_strategy, &self.entropy_source, &self.node_signer, best_block_height,
+				&self.pending_events,
+				|path, payment_hash, recipient_onion, total_value, cur_height, payment_id, keysend_preimage, session_priv|
+				self.send_payment_along_path(path, payment_hash, recipient_onion, total_value, cur_height, payment_id, keysend_preimage, session_priv))
+	}
+
 	#[cfg(test)]
 	pub(super) fn test_send_payment_internal(&self, route: &Route, payment_hash: PaymentHash, recipient_onion: RecipientOnionFields, keysend_preimage: Option<PaymentPreimage>, payment_id: PaymentId, recv_value_msat: Option<u64>, onion_session_privs: Vec<[u8; 32]>) -> Result<(), PaymentSendFailure> {
 		let best_block_height = self.best_block.read().unwrap().height();
@@ -3335,6 +4205,14 @@ where
 	/// Send a payment that is probing the given route for liquidity. We calculate the
 	/// [`PaymentHash`] of probes based on a static secret and a random [`PaymentId`], which allows
 	/// us to easily discern them from real payments.
+	///
+	/// The `path` may terminate in a [`BlindedTail`], e.g. to check that a [`Bolt12Invoice`] is
+	/// payable before paying it. Note that the introduction node of a blinded path hides which hop
+	/// within the path failed an HTLC, so any failure returned by it or the hops after it results in
+	/// an [`Event::ProbeSuccessful`], indicating only that the probe reached the blinded path.
+	///
+	/// [`BlindedTail`]: crate::routing::router::BlindedTail
+	/// [`Bolt12Invoice`]: crate::offers::invoice::Bolt12Invoice
 	pub fn send_probe(&self, path: Path) -> Result<(PaymentHash, PaymentId), PaymentSendFailure> {
 		let best_block_height = self.best_block.read().unwrap().height();
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
@@ -3361,7 +4239,7 @@ where
 
 		let mut peer_state_lock = peer_state_mutex.lock().unwrap();
 		let peer_state = &mut *peer_state_lock;
-		let (chan, msg) = match peer_state.outbound_v1_channel_by_id.remove(temporary_channel_id) {
+		let (mut chan, msg) = match peer_state.outbound_v1_channel_by_id.remove(temporary_channel_id) {
 			Some(chan) => {
 				let funding_txo = find_funding_output(&chan, &funding_transaction)?;
 
@@ -3407,6 +4285,9 @@ where
 				if id_to_peer.insert(chan.context.channel_id(), chan.context.get_counterparty_node_id()).is_some() {
 					panic!("id_to_peer map already contained funding txid, which shouldn't be possible");
 				}
+				chan.context.track_pending_inbound_htlcs(Arc::clone(&self.pending_inbound_htlcs));
+				#[cfg(feature = "htlc_timeline_events")]
+				chan.context.set_htlc_timeline_time_source(Arc::clone(&*self.htlc_timeline_time_source.lock().unwrap()));
 				e.insert(chan);
 			}
 		}
@@ -3612,20 +4493,252 @@ where
 		return self.update_partial_channel_config(counterparty_node_id, channel_ids, &(*config).into());
 	}
 
-	/// Attempts to forward an intercepted HTLC over the provided channel id and with the provided
-	/// amount to forward. Should only be called in response to an [`HTLCIntercepted`] event.
+	/// Asks to move the given channel off anchor outputs the next time we reconnect to the
+	/// counterparty, if both sides negotiate [`ChannelHandshakeConfig::negotiate_channel_type_upgrade`].
 	///
-	/// Intercepted HTLCs can be useful for Lightning Service Providers (LSPs) to open a just-in-time
-	/// channel to a receiving node if the node lacks sufficient inbound liquidity.
+	/// Renegotiating the channel type otherwise only ever adds anchor outputs to existing channels,
+	/// so this is the only way to drop them without closing the channel. As with upgrades, the
+	/// change only takes place if the counterparty agrees to it and the channel has no HTLCs
+	/// pending at the time of reconnection.
 	///
-	/// To make use of intercepted HTLCs, set [`UserConfig::accept_intercept_htlcs`] and use
-	/// [`ChannelManager::get_intercept_scid`] to generate short channel id(s) to put in the
-	/// receiver's invoice route hints. These route hints will signal to LDK to generate an
-	/// [`HTLCIntercepted`] event when it receives the forwarded HTLC, and this method or
-	/// [`ChannelManager::fail_intercepted_htlc`] MUST be called in response to the event.
+	/// Returns [`ChannelUnavailable`] when the channel is not found or an incorrect
+	/// `counterparty_node_id` is provided, and [`APIMisuseError`] if the channel does not use
+	/// anchor outputs.
 	///
-	/// Note that LDK does not enforce fee requirements in `amt_to_forward_msat`, and will not stop
-	/// you from forwarding more than you received. See
+	/// [`ChannelHandshakeConfig::negotiate_channel_type_upgrade`]: crate::util::config::ChannelHandshakeConfig::negotiate_channel_type_upgrade
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn request_channel_type_downgrade(&self, channel_id: &[u8; 32], counterparty_node_id: &PublicKey) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+		let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+		let peer_state = &mut *peer_state_lock;
+		match peer_state.channel_by_id.get_mut(channel_id) {
+			Some(channel) => channel.request_channel_type_downgrade()
+				.map_err(|e| APIError::APIMisuseError { err: e.to_string() }),
+			None => Err(APIError::ChannelUnavailable {
+				err: format!("Channel with ID {} was not found for the passed counterparty_node_id {}", log_bytes!(*channel_id), counterparty_node_id),
+			}),
+		}
+	}
+
+	/// Atomically overrides the `htlc_minimum_msat` and `htlc_maximum_msat` we advertise for the
+	/// given channels, e.g. to stop relaying dust-sized spam or to match new contract sizes without
+	/// reopening channels. Passing `None` reverts to the value derived from the channel parameters.
+	///
+	/// HTLCs forwarded over the channels which fall outside the new limits are failed back. Note
+	/// that the advertised minimum can never be below the counterparty's `htlc_minimum_msat`, and
+	/// the advertised maximum never above what the channel can carry.
+	///
+	/// Once the updates are applied, each eligible channel (advertised with a known short channel
+	/// ID and a change in its effective limits) has a [`BroadcastChannelUpdate`] event message
+	/// generated containing the new [`ChannelUpdate`] message which should be broadcast to the
+	/// network. Unannounced channels have a [`SendChannelUpdate`] generated for the counterparty
+	/// instead.
+	///
+	/// Returns [`ChannelUnavailable`] when a channel is not found or an incorrect
+	/// `counterparty_node_id` is provided.
+	///
+	/// Returns [`APIMisuseError`] when the resulting minimum would exceed the resulting maximum for
+	/// any of the channels.
+	///
+	/// If an error is returned, none of the updates should be considered applied.
+	///
+	/// [`BroadcastChannelUpdate`]: events::MessageSendEvent::BroadcastChannelUpdate
+	/// [`SendChannelUpdate`]: events::MessageSendEvent::SendChannelUpdate
+	/// [`ChannelUpdate`]: msgs::ChannelUpdate
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn update_channel_htlc_limits(
+		&self, counterparty_node_id: &PublicKey, channel_ids: &[[u8; 32]], htlc_minimum_msat: Option<u64>,
+		htlc_maximum_msat: Option<u64>,
+	) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+		let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+		let peer_state = &mut *peer_state_lock;
+		for channel_id in channel_ids {
+			let context = if let Some(channel) = peer_state.channel_by_id.get(channel_id) {
+				&channel.context
+			} else if let Some(channel) = peer_state.inbound_v1_channel_by_id.get(channel_id) {
+				&channel.context
+			} else if let Some(channel) = peer_state.outbound_v1_channel_by_id.get(channel_id) {
+				&channel.context
+			} else {
+				return Err(APIError::ChannelUnavailable {
+					err: format!("Channel with ID {} was not found for the passed counterparty_node_id {}", log_bytes!(*channel_id), counterparty_node_id),
+				});
+			};
+			context.check_announced_htlc_limits(htlc_minimum_msat, htlc_maximum_msat)?;
+		}
+		for channel_id in channel_ids {
+			if let Some(channel) = peer_state.channel_by_id.get_mut(channel_id) {
+				if !channel.context.update_announced_htlc_limits(htlc_minimum_msat, htlc_maximum_msat) {
+					continue;
+				}
+				if let Ok(msg) = self.get_channel_update_for_broadcast(channel) {
+					peer_state.pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate { msg });
+				} else if let Ok(msg) = self.get_channel_update_for_unicast(channel) {
+					peer_state.pending_msg_events.push(events::MessageSendEvent::SendChannelUpdate {
+						node_id: channel.context.get_counterparty_node_id(),
+						msg,
+					});
+				}
+			} else if let Some(channel) = peer_state.inbound_v1_channel_by_id.get_mut(channel_id) {
+				// We MUST NOT send a `channel_update` before `channel_ready`.
+				channel.context.update_announced_htlc_limits(htlc_minimum_msat, htlc_maximum_msat);
+			} else if let Some(channel) = peer_state.outbound_v1_channel_by_id.get_mut(channel_id) {
+				channel.context.update_announced_htlc_limits(htlc_minimum_msat, htlc_maximum_msat);
+			}
+		}
+		Ok(())
+	}
+
+	/// Sets a fee the counterparty owes us for the liquidity in a channel we opened to them, e.g.
+	/// a just-in-time channel opened in response to an [`HTLCIntercepted`] event whose fee was not
+	/// deducted from the channel's `push_msat`.
+	///
+	/// The fee is collected in-band by skimming it from the HTLCs we forward over the channel,
+	/// taking at most `max_skim_proportional_millionths` of each HTLC's amount and never leaving an
+	/// HTLC below the counterparty's `htlc_minimum_msat`. The skimmed amount is signaled to the
+	/// counterparty in each `update_add_htlc`, and they must have consented to paying the fee by
+	/// setting [`ChannelConfig::accept_underpaying_htlcs`], otherwise they'll reject the HTLCs.
+	/// Fees skimmed from HTLCs which are failed are skimmed from later HTLCs instead, and an
+	/// [`Event::LiquidityFeeCollected`] is generated whenever the counterparty claims an HTLC we
+	/// skimmed from.
+	///
+	/// The fee may be replaced until we first skim from an HTLC, after which this returns an
+	/// [`APIMisuseError`]. It also errors if we didn't open the channel or the fee exceeds the
+	/// channel's value.
+	///
+	/// [`HTLCIntercepted`]: events::Event::HTLCIntercepted
+	/// [`ChannelConfig::accept_underpaying_htlcs`]: crate::util::config::ChannelConfig::accept_underpaying_htlcs
+	/// [`Event::LiquidityFeeCollected`]: events::Event::LiquidityFeeCollected
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn set_channel_liquidity_fee(
+		&self, channel_id: &[u8; 32], counterparty_node_id: &PublicKey, fee_msat: u64,
+		max_skim_proportional_millionths: u32
+	) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+		let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+		let peer_state = &mut *peer_state_lock;
+		match peer_state.channel_by_id.get_mut(channel_id) {
+			Some(chan) => chan.context.set_liquidity_fee(fee_msat, max_skim_proportional_millionths),
+			None => Err(APIError::ChannelUnavailable {
+				err: format!("Funded channel with id {} not found for the passed counterparty node_id {}",
+					log_bytes!(*channel_id), counterparty_node_id)
+			}),
+		}
+	}
+
+	/// Gets the portion of the fee set via [`Self::set_channel_liquidity_fee`] which the
+	/// counterparty has yet to pay, including any we skimmed from HTLCs they haven't claimed yet.
+	///
+	/// Returns `None` if the channel wasn't found or has no liquidity fee set.
+	pub fn channel_liquidity_fee_owed(&self, channel_id: &[u8; 32], counterparty_node_id: &PublicKey) -> Option<u64> {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state = per_peer_state.get(counterparty_node_id)?.lock().unwrap();
+		peer_state.channel_by_id.get(channel_id)?.context.get_liquidity_fee_owed_msat()
+	}
+
+	/// Gets the total value of the dust HTLCs we've forwarded to the given counterparty which were
+	/// lost to fees as the channel they were pending on was force-closed.
+	///
+	/// Dust HTLCs are trimmed from the commitment transactions, so their value is lost to fees
+	/// rather than claimable on-chain if the channel is force-closed while they're pending. The
+	/// value currently at risk on each open channel is available in
+	/// [`ChannelDetails::pending_dust_forwards_msat`], and new dust forwards may be capped via
+	/// [`UserConfig::max_dust_htlc_write_off_msat`].
+	pub fn dust_htlcs_written_off_msat(&self, counterparty_node_id: &PublicKey) -> u64 {
+		self.closed_channel_dust_write_offs.lock().unwrap()
+			.get(counterparty_node_id).copied().unwrap_or(0)
+	}
+
+	/// Returns whether our fee spike circuit breaker is currently engaged, in which case
+	/// non-urgent on-chain operations, such as sweeping [`SpendableOutputDescriptor`]s, should be
+	/// deferred. See [`UserConfig::fee_spike_circuit_breaker_config`].
+	///
+	/// [`SpendableOutputDescriptor`]: crate::sign::SpendableOutputDescriptor
+	pub fn fee_spike_circuit_breaker_engaged(&self) -> bool {
+		self.fee_spike_circuit_breaker_engaged.load(Ordering::Acquire)
+	}
+
+	/// Engages or releases our fee spike circuit breaker based on the given feerate estimate,
+	/// generating an event if it changed state. Returns the percentage of their maximum dust
+	/// exposure channels should let new HTLCs use if the breaker is engaged.
+	fn update_fee_spike_circuit_breaker(&self, feerate_sat_per_1000_weight: u32) -> Option<u8> {
+		let config = self.default_configuration.fee_spike_circuit_breaker_config;
+		let was_engaged = self.fee_spike_circuit_breaker_engaged.load(Ordering::Acquire);
+		let engaged = match config.engage_feerate_sat_per_1000_weight {
+			Some(engage_feerate) if was_engaged => {
+				let release_feerate = config.release_feerate_sat_per_1000_weight.unwrap_or(engage_feerate);
+				feerate_sat_per_1000_weight >= cmp::min(release_feerate, engage_feerate)
+			},
+			Some(engage_feerate) => feerate_sat_per_1000_weight >= engage_feerate,
+			None => false,
+		};
+		if engaged != was_engaged {
+			self.fee_spike_circuit_breaker_engaged.store(engaged, Ordering::Release);
+			let event = if engaged {
+				log_info!(self.logger, "Engaging fee spike circuit breaker at a feerate of {} sat/kW", feerate_sat_per_1000_weight);
+				events::Event::FeeSpikeCircuitBreakerEngaged { feerate_sat_per_1000_weight }
+			} else {
+				log_info!(self.logger, "Releasing fee spike circuit breaker at a feerate of {} sat/kW", feerate_sat_per_1000_weight);
+				events::Event::FeeSpikeCircuitBreakerReleased { feerate_sat_per_1000_weight }
+			};
+			self.pending_events.lock().unwrap().push_back((event, None));
+		}
+		if engaged { Some(config.max_dust_htlc_exposure_percent) } else { None }
+	}
+
+	/// Gets counters describing how our [`UserConfig::htlc_acceptance_limits`] were applied to new
+	/// inbound HTLCs since startup.
+	pub fn htlc_acceptance_stats(&self) -> HTLCAcceptanceStats {
+		self.htlc_acceptance_limiter.lock().unwrap().stats
+	}
+
+	/// Checks whether a new inbound HTLC is within our [`UserConfig::htlc_acceptance_limits`],
+	/// recording the result in our [`HTLCAcceptanceStats`].
+	fn check_htlc_acceptance_limits(&self) -> Result<(), &'static str> {
+		let limits = self.default_configuration.htlc_acceptance_limits;
+		if let Some(max_pending_htlcs) = limits.max_pending_htlcs {
+			if self.pending_inbound_htlcs.load(Ordering::Acquire) >= max_pending_htlcs as usize {
+				self.htlc_acceptance_limiter.lock().unwrap().stats.pending_limited += 1;
+				return Err("we have too many inbound HTLCs pending");
+			}
+		}
+		let mut limiter = self.htlc_acceptance_limiter.lock().unwrap();
+		if let Some(max_htlcs_per_second) = limits.max_htlcs_per_second {
+			if !limiter.try_consume(max_htlcs_per_second) {
+				limiter.stats.rate_limited += 1;
+				return Err("we are receiving new HTLCs too quickly");
+			}
+		}
+		limiter.stats.accepted += 1;
+		Ok(())
+	}
+
+	/// Attempts to forward an intercepted HTLC over the provided channel id and with the provided
+	/// amount to forward. Should only be called in response to an [`HTLCIntercepted`] event.
+	///
+	/// Intercepted HTLCs can be useful for Lightning Service Providers (LSPs) to open a just-in-time
+	/// channel to a receiving node if the node lacks sufficient inbound liquidity.
+	///
+	/// To make use of intercepted HTLCs, set [`UserConfig::accept_intercept_htlcs`] and use
+	/// [`ChannelManager::get_intercept_scid`] to generate short channel id(s) to put in the
+	/// receiver's invoice route hints. These route hints will signal to LDK to generate an
+	/// [`HTLCIntercepted`] event when it receives the forwarded HTLC, and this method or
+	/// [`ChannelManager::fail_intercepted_htlc`] MUST be called in response to the event.
+	///
+	/// Note that LDK does not enforce fee requirements in `amt_to_forward_msat`, and will not stop
+	/// you from forwarding more than you received. See
 	/// [`HTLCIntercepted::expected_outbound_amount_msat`] for more on forwarding a different amount
 	/// than expected.
 	///
@@ -3722,6 +4835,69 @@ where
 		Ok(())
 	}
 
+	/// Accepts the held HTLC indicated by hold_id, letting it be processed as any other HTLC paying
+	/// us, i.e. becoming (part of) an [`Event::PaymentClaimable`]. Should only be called in response
+	/// to an [`HTLCHeldForValidation`] event.
+	///
+	/// Errors if the event was not handled in time, in which case the HTLC was automatically failed
+	/// backwards.
+	///
+	/// [`HTLCHeldForValidation`]: events::Event::HTLCHeldForValidation
+	pub fn accept_held_htlc(&self, hold_id: InterceptId) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+
+		let held_htlc = self.pending_held_htlcs.lock().unwrap().remove(&hold_id)
+			.ok_or_else(|| APIError::APIMisuseError {
+				err: format!("Payment with hold id {} not found", log_bytes!(hold_id.0))
+			})?;
+
+		// Held HTLCs skip `forward_htlcs()`, which would otherwise hold them again.
+		let mut forward_htlcs = self.forward_htlcs.lock().unwrap();
+		let push_forward_event = forward_htlcs.is_empty();
+		forward_htlcs.entry(0).or_insert_with(Vec::new).push(HTLCForwardInfo::AddHTLC(held_htlc.htlc));
+		mem::drop(forward_htlcs);
+		if push_forward_event { self.push_pending_forwards_ev() }
+		Ok(())
+	}
+
+	/// Fails the held HTLC indicated by hold_id back to the sender. Should only be called in
+	/// response to an [`HTLCHeldForValidation`] event. See [`ChannelManager::accept_held_htlc`].
+	///
+	/// Errors if the event was not handled in time, in which case the HTLC was automatically failed
+	/// backwards.
+	///
+	/// [`HTLCHeldForValidation`]: events::Event::HTLCHeldForValidation
+	pub fn fail_held_htlc(&self, hold_id: InterceptId) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+
+		let held_htlc = self.pending_held_htlcs.lock().unwrap().remove(&hold_id)
+			.ok_or_else(|| APIError::APIMisuseError {
+				err: format!("Payment with hold id {} not found", log_bytes!(hold_id.0))
+			})?;
+
+		let height = self.best_block.read().unwrap().height();
+		let (source, payment_hash, reason, destination) = Self::held_htlc_failure(&held_htlc.htlc, height);
+		self.fail_htlc_backwards_internal(&source, &payment_hash, &reason, destination);
+		Ok(())
+	}
+
+	/// Builds the failure for a held HTLC paying us which was rejected or not accepted in time.
+	fn held_htlc_failure(htlc: &PendingAddHTLCInfo, height: u32) -> (HTLCSource, PaymentHash, HTLCFailReason, HTLCDestination) {
+		let htlc_source = HTLCSource::PreviousHopData(HTLCPreviousHopData {
+			short_channel_id: htlc.prev_short_channel_id,
+			outpoint: htlc.prev_funding_outpoint,
+			htlc_id: htlc.prev_htlc_id,
+			incoming_packet_shared_secret: htlc.forward_info.incoming_shared_secret,
+			phantom_shared_secret: None,
+		});
+		let amount_msat = htlc.forward_info.incoming_amt_msat.unwrap_or(htlc.forward_info.outgoing_amt_msat);
+		let mut htlc_msat_height_data = amount_msat.to_be_bytes().to_vec();
+		htlc_msat_height_data.extend_from_slice(&height.to_be_bytes());
+		let payment_hash = htlc.forward_info.payment_hash;
+		(htlc_source, payment_hash, HTLCFailReason::reason(0x4000 | 15, htlc_msat_height_data),
+			HTLCDestination::FailedPayment { payment_hash })
+	}
+
 	/// Processes HTLCs which are pending waiting on random forward delay.
 	///
 	/// Should only really ever be called in response to a PendingHTLCsForwardable event.
@@ -3850,6 +5026,14 @@ where
 					}
 					let mut peer_state_lock = peer_state_mutex_opt.unwrap().lock().unwrap();
 					let peer_state = &mut *peer_state_lock;
+					let dust_write_off_budget_msat = self.default_configuration.max_dust_htlc_write_off_msat.map(|max_msat| {
+						let closed_msat = self.closed_channel_dust_write_offs.lock().unwrap()
+							.get(&counterparty_node_id).copied().unwrap_or(0);
+						let at_risk_msat = peer_state.channel_by_id.values()
+							.fold(closed_msat, |total, chan| total.saturating_add(chan.context.get_pending_dust_forwards_msat(true)));
+						max_msat.saturating_sub(at_risk_msat)
+					});
+					let mut dust_forwarded_msat = 0u64;
 					match peer_state.channel_by_id.entry(forward_chan_id) {
 						hash_map::Entry::Vacant(_) => {
 							forwarding_channel_not_found!();
@@ -3874,7 +5058,25 @@ where
 											// Phantom payments are only PendingHTLCRouting::Receive.
 											phantom_shared_secret: None,
 										});
-										if let Err(e) = chan.get_mut().queue_add_htlc(outgoing_amt_msat,
+										let liquidity_skim_msat = chan.get().context.get_liquidity_skim_msat(outgoing_amt_msat);
+										let skimmed_fee_msat = if liquidity_skim_msat == 0 { skimmed_fee_msat } else {
+											Some(skimmed_fee_msat.unwrap_or(0) + liquidity_skim_msat)
+										};
+										let forward_amt_msat = outgoing_amt_msat - liquidity_skim_msat;
+										let is_dust = chan.get().context.is_outbound_htlc_dust(forward_amt_msat);
+										if let Some(budget_msat) = dust_write_off_budget_msat {
+											if is_dust && dust_forwarded_msat.saturating_add(forward_amt_msat) > budget_msat {
+												log_debug!(self.logger, "Failed to forward dust HTLC with payment_hash {} as it'd exceed our limit of {} msat of dust HTLCs lost or at risk with {}",
+													log_bytes!(payment_hash.0), self.default_configuration.max_dust_htlc_write_off_msat.unwrap(), counterparty_node_id);
+												let (failure_code, data) = self.get_htlc_temp_fail_err_and_data(0x1000|7, short_chan_id, chan.get());
+												failed_forwards.push((htlc_source, payment_hash,
+													HTLCFailReason::reason(failure_code, data),
+													HTLCDestination::NextHopChannel { node_id: Some(chan.get().context.get_counterparty_node_id()), channel_id: forward_chan_id }
+												));
+												continue;
+											}
+										}
+										if let Err(e) = chan.get_mut().queue_add_htlc(outgoing_amt_msat - liquidity_skim_msat,
 											payment_hash, outgoing_cltv_value, htlc_source.clone(),
 											onion_packet, skimmed_fee_msat, &self.fee_estimator,
 											&self.logger)
@@ -3891,6 +5093,10 @@ where
 											));
 											continue;
 										}
+										chan.get_mut().context.record_liquidity_skim(htlc_source, liquidity_skim_msat);
+										if is_dust {
+											dust_forwarded_msat = dust_forwarded_msat.saturating_add(forward_amt_msat);
+										}
 									},
 									HTLCForwardInfo::AddHTLC { .. } => {
 										panic!("short_channel_id != 0 should imply any pending_forward entries are of type Forward");
@@ -3926,17 +5132,19 @@ where
 								}
 							}) => {
 								let (cltv_expiry, onion_payload, payment_data, phantom_shared_secret, mut onion_fields) = match routing {
-									PendingHTLCRouting::Receive { payment_data, payment_metadata, incoming_cltv_expiry, phantom_shared_secret } => {
+									PendingHTLCRouting::Receive { payment_data, payment_metadata, incoming_cltv_expiry, phantom_shared_secret, custom_tlvs } => {
 										let _legacy_hop_data = Some(payment_data.clone());
-										let onion_fields =
-											RecipientOnionFields { payment_secret: Some(payment_data.payment_secret), payment_metadata };
+										let onion_fields = RecipientOnionFields {
+											payment_secret: Some(payment_data.payment_secret), payment_metadata, custom_tlvs,
+										};
 										(incoming_cltv_expiry, OnionPayload::Invoice { _legacy_hop_data },
 											Some(payment_data), phantom_shared_secret, onion_fields)
 									},
-									PendingHTLCRouting::ReceiveKeysend { payment_data, payment_preimage, payment_metadata, incoming_cltv_expiry } => {
+									PendingHTLCRouting::ReceiveKeysend { payment_data, payment_preimage, payment_metadata, incoming_cltv_expiry, custom_tlvs } => {
 										let onion_fields = RecipientOnionFields {
 											payment_secret: payment_data.as_ref().map(|data| data.payment_secret),
-											payment_metadata
+											payment_metadata,
+											custom_tlvs,
 										};
 										(incoming_cltv_expiry, OnionPayload::Spontaneous(payment_preimage),
 											payment_data, None, onion_fields)
@@ -4052,6 +5260,10 @@ where
 											log_trace!(self.logger, "Failing HTLC with payment_hash {} as payment is already claimable",
 												log_bytes!(payment_hash.0));
 											fail_htlc!(claimable_htlc, payment_hash);
+										} else if htlcs.iter().any(|htlc| htlc.total_value_received.is_some()) {
+											log_trace!(self.logger, "Failing HTLC with payment_hash {} as payment is already claimable for a partial amount",
+												log_bytes!(payment_hash.0));
+											fail_htlc!(claimable_htlc, payment_hash);
 										} else if total_value >= claimable_htlc.total_msat {
 											#[allow(unused_assignments)] {
 												committed_to_claimable = true;
@@ -4327,6 +5539,17 @@ where
 	///    with the current [`ChannelConfig`].
 	///  * Removing peers which have disconnected but and no longer have any channels.
 	///  * Force-closing and removing channels which have not completed establishment in a timely manner.
+	///  * Detecting channels which have been idle for longer than configured in
+	///    [`UserConfig::idle_channel_config`], generating [`Event::ChannelIdle`]s and, if
+	///    configured, initiating cooperative closes of such channels.
+	///  * Force-closing channels with HTLCs pending while our counterparty has been unresponsive,
+	///    as configured in [`UserConfig::unresponsive_peer_config`], and generating
+	///    [`Event::ChannelUnresponsive`]s beforehand.
+	///  * Correcting asymmetries between our and our counterparties' forwarding parameters if
+	///    [`ForwardingHarmonizationConfig::auto_harmonize`] is set.
+	///  * Engaging or releasing our fee spike circuit breaker as configured in
+	///    [`UserConfig::fee_spike_circuit_breaker_config`], and initiating cooperative closes
+	///    deferred while it was engaged.
 	///
 	/// Note that this may cause reentrancy through [`chain::Watch::update_channel`] calls or feerate
 	/// estimate fetches.
@@ -4340,9 +5563,15 @@ where
 			let normal_feerate = self.fee_estimator.bounded_sat_per_1000_weight(ConfirmationTarget::Normal);
 			let min_mempool_feerate = self.fee_estimator.bounded_sat_per_1000_weight(ConfirmationTarget::MempoolMinimum);
 
+			let fee_spike_dust_exposure_percent = self.update_fee_spike_circuit_breaker(normal_feerate);
+
 			let mut handle_errors: Vec<(Result<(), _>, _)> = Vec::new();
 			let mut timed_out_mpp_htlcs = Vec::new();
 			let mut pending_peers_awaiting_removal = Vec::new();
+			let idle_timer_ticks_threshold = self.default_configuration.idle_channel_config.idle_timer_ticks_threshold;
+			let unresponsive_config = self.default_configuration.unresponsive_peer_config;
+			let harmonization_config = self.default_configuration.forwarding_harmonization_config;
+			let best_block_height = self.best_block.read().unwrap().height();
 			{
 				let per_peer_state = self.per_peer_state.read().unwrap();
 				for (counterparty_node_id, peer_state_mutex) in per_peer_state.iter() {
@@ -4358,6 +5587,7 @@ where
 						};
 						let chan_needs_persist = self.update_channel_fee(chan_id, chan, new_feerate);
 						if chan_needs_persist == NotifyOption::DoPersist { should_persist = NotifyOption::DoPersist; }
+						chan.context.set_fee_spike_dust_exposure_percent(fee_spike_dust_exposure_percent);
 
 						if let Err(e) = chan.timer_check_closing_negotiation_progress() {
 							let (needs_close, err) = convert_chan_err!(self, e, chan, chan_id);
@@ -4404,6 +5634,75 @@ where
 						}
 
 						chan.context.maybe_expire_prev_config();
+						chan.context.htlc_timer_tick_occurred();
+
+						let idle_timer_ticks = chan.context.idle_timer_tick_occurred();
+						if Some(idle_timer_ticks) == idle_timer_ticks_threshold {
+							log_info!(self.logger, "Channel {} has been idle for {} timer ticks",
+								log_bytes!(*chan_id), idle_timer_ticks);
+							self.pending_events.lock().unwrap().push_back((events::Event::ChannelIdle {
+								channel_id: *chan_id,
+								user_channel_id: chan.context.get_user_id(),
+								counterparty_node_id,
+								idle_timer_ticks,
+							}, None));
+						}
+
+						let unresponsive_timer_ticks = chan.context.unresponsive_timer_tick_occurred();
+						let unresponsive_htlc_expiry = unresponsive_config.unresponsive_timer_ticks_threshold
+							.filter(|threshold| unresponsive_timer_ticks >= *threshold)
+							.and(chan.context.get_earliest_pending_htlc_expiry());
+						if let Some(earliest_htlc_expiry) = unresponsive_htlc_expiry {
+							let force_close_height = earliest_htlc_expiry.saturating_sub(unresponsive_config.force_close_margin_blocks);
+							// Always warn before force-closing, even if the threshold was only reached
+							// after the warning window began.
+							if best_block_height.saturating_add(unresponsive_config.warning_margin_blocks) >= force_close_height
+								&& chan.context.mark_unresponsive_warning_issued()
+							{
+								log_info!(self.logger, "Channel {} will be force-closed at height {} unless our counterparty becomes responsive",
+									log_bytes!(*chan_id), force_close_height);
+								self.pending_events.lock().unwrap().push_back((events::Event::ChannelUnresponsive {
+									channel_id: *chan_id,
+									user_channel_id: chan.context.get_user_id(),
+									counterparty_node_id,
+									earliest_htlc_expiry,
+									force_close_height,
+								}, None));
+							}
+							if best_block_height >= force_close_height {
+								let err = ChannelError::Close(format!(
+									"Force-closing channel as our counterparty has been unresponsive for {} timer ticks with HTLCs expiring at height {}",
+									unresponsive_timer_ticks, earliest_htlc_expiry));
+								let (_, err) = convert_chan_err!(self, err, chan, chan_id);
+								handle_errors.push((Err(err), counterparty_node_id));
+								should_persist = NotifyOption::DoPersist;
+								return false;
+							}
+						}
+
+						let their_info = chan.context.counterparty_forwarding_info()
+							.filter(|_| harmonization_config.auto_harmonize);
+						if let Some(their_info) = their_info {
+							let mut config = chan.context.config();
+							for asymmetry in check_forwarding_asymmetries(&harmonization_config, *chan_id,
+								counterparty_node_id, &config, &their_info)
+							{
+								log_info!(self.logger, "Harmonizing forwarding parameters of channel {}: {:?}",
+									log_bytes!(*chan_id), asymmetry);
+								config.apply(&asymmetry.config_update());
+							}
+							if chan.context.update_config(&config) {
+								if let Ok(msg) = self.get_channel_update_for_broadcast(chan) {
+									pending_msg_events.push(events::MessageSendEvent::BroadcastChannelUpdate { msg });
+								} else if let Ok(msg) = self.get_channel_update_for_unicast(chan) {
+									pending_msg_events.push(events::MessageSendEvent::SendChannelUpdate {
+										node_id: counterparty_node_id,
+										msg,
+									});
+								}
+								should_persist = NotifyOption::DoPersist;
+							}
+						}
 
 						if chan.should_disconnect_peer_awaiting_response() {
 							log_debug!(self.logger, "Disconnecting peer {} due to not making any progress on channel {}",
@@ -4474,7 +5773,10 @@ where
 				}
 			}
 
-			self.claimable_payments.lock().unwrap().claimable_payments.retain(|payment_hash, payment| {
+			let mut partially_claimable_events = Vec::new();
+			let mut claimable_payments_lock = self.claimable_payments.lock().unwrap();
+			let ClaimablePayments { claimable_payments, partial_claim_allowances, .. } = &mut *claimable_payments_lock;
+			claimable_payments.retain(|payment_hash, payment| {
 				if payment.htlcs.is_empty() {
 					// This should be unreachable
 					debug_assert!(false);
@@ -4485,14 +5787,30 @@ where
 					// In this case we're not going to handle any timeouts of the parts here.
 					// This condition determining whether the MPP is complete here must match
 					// exactly the condition used in `process_pending_htlc_forwards`.
-					if payment.htlcs[0].total_msat <= payment.htlcs.iter()
-						.fold(0, |total, htlc| total + htlc.sender_intended_value)
-					{
+					let total_value = payment.htlcs.iter()
+						.fold(0, |total, htlc| total + htlc.sender_intended_value);
+					if payment.htlcs[0].total_msat <= total_value {
+						return true;
+					} else if payment.htlcs[0].total_value_received.is_some() {
+						// We already surfaced this incomplete set as partially claimable.
 						return true;
 					} else if payment.htlcs.iter_mut().any(|htlc| {
 						htlc.timer_ticks += 1;
 						return htlc.timer_ticks >= MPP_TIMEOUT_TICKS
 					}) {
+						if let Some(allowance) = partial_claim_allowances.get(payment_hash) {
+							if total_value >= allowance.min_amount_msat {
+								let amount_msat = payment.htlcs.iter().map(|htlc| htlc.value).sum();
+								payment.htlcs.iter_mut().for_each(|htlc| htlc.total_value_received = Some(amount_msat));
+								partially_claimable_events.push((*payment_hash, payment.purpose.clone(),
+									payment.onion_fields.clone(), amount_msat,
+									payment.htlcs.iter().map(|htlc| htlc.counterparty_skimmed_fee_msat.unwrap_or(0)).sum(),
+									payment.htlcs.iter().map(|htlc| htlc.cltv_expiry).min().unwrap(),
+									payment.htlcs.last().unwrap().prev_hop.clone()));
+								return true;
+							}
+						}
+						partial_claim_allowances.remove(payment_hash);
 						timed_out_mpp_htlcs.extend(payment.htlcs.drain(..)
 							.map(|htlc: ClaimableHTLC| (htlc.prev_hop, *payment_hash)));
 						return false;
@@ -4500,6 +5818,30 @@ where
 				}
 				true
 			});
+			mem::drop(claimable_payments_lock);
+
+			for (payment_hash, purpose, onion_fields, amount_msat, counterparty_skimmed_fee_msat, earliest_expiry, prev_hop)
+				in partially_claimable_events.drain(..)
+			{
+				log_info!(self.logger, "Surfacing incomplete MPP payment with payment_hash {} as claimable for {} msat",
+					log_bytes!(payment_hash.0), amount_msat);
+				let mut receiver_node_id = self.our_network_pubkey;
+				if prev_hop.phantom_shared_secret.is_some() {
+					receiver_node_id = self.node_signer.get_node_id(Recipient::PhantomNode)
+						.expect("Failed to get node_id for phantom node recipient");
+				}
+				self.pending_events.lock().unwrap().push_back((events::Event::PaymentClaimable {
+					receiver_node_id: Some(receiver_node_id),
+					payment_hash,
+					purpose,
+					amount_msat,
+					counterparty_skimmed_fee_msat,
+					via_channel_id: Some(prev_hop.outpoint.to_channel_id()),
+					via_user_channel_id: None,
+					claim_deadline: Some(earliest_expiry - HTLC_FAIL_BACK_BUFFER),
+					onion_fields,
+				}, None));
+			}
 
 			for htlc_source in timed_out_mpp_htlcs.drain(..) {
 				let source = HTLCSource::PreviousHopData(htlc_source.0.clone());
@@ -4508,11 +5850,31 @@ where
 				self.fail_htlc_backwards_internal(&source, &htlc_source.1, &reason, receiver);
 			}
 
+			let mut timed_out_held_htlcs = Vec::new();
+			let height = self.best_block.read().unwrap().height();
+			let mut pending_held_htlcs = self.pending_held_htlcs.lock().unwrap();
+			if !pending_held_htlcs.is_empty() { should_persist = NotifyOption::DoPersist; }
+			pending_held_htlcs.retain(|_, held_htlc| {
+				held_htlc.ticks_remaining = held_htlc.ticks_remaining.saturating_sub(1);
+				if held_htlc.ticks_remaining == 0 {
+					log_trace!(self.logger, "Failing held HTLC with payment hash {} as no decision was made in time",
+						log_bytes!(held_htlc.htlc.forward_info.payment_hash.0));
+					timed_out_held_htlcs.push(Self::held_htlc_failure(&held_htlc.htlc, height));
+					false
+				} else { true }
+			});
+			mem::drop(pending_held_htlcs);
+
+			for (source, payment_hash, reason, destination) in timed_out_held_htlcs.drain(..) {
+				self.fail_htlc_backwards_internal(&source, &payment_hash, &reason, destination);
+			}
+
 			for (err, counterparty_node_id) in handle_errors.drain(..) {
 				let _ = handle_error!(self, err, counterparty_node_id);
 			}
 
 			self.pending_outbound_payments.remove_stale_resolved_payments(&self.pending_events);
+			self.pending_outbound_payments.retry_budget_timer_tick_occurred();
 
 			// Technically we don't need to do this here, but if we have holding cell entries in a
 			// channel that need freeing, it's better to do that here and block a background task
@@ -4523,6 +5885,69 @@ where
 
 			should_persist
 		});
+
+		// `close_channel_internal` takes its own persistence guard, so idle and deferred channels
+		// have to be closed after the above one has been released.
+		self.close_idle_channels();
+		self.close_deferred_channels();
+	}
+
+	/// Initiates the cooperative closes which were deferred while our fee spike circuit breaker was
+	/// engaged, if it has since been released.
+	fn close_deferred_channels(&self) {
+		if self.fee_spike_circuit_breaker_engaged.load(Ordering::Acquire) { return; }
+		let deferred_closes = mem::take(&mut *self.deferred_cooperative_closes.lock().unwrap());
+		for close in deferred_closes {
+			log_info!(self.logger, "Initiating deferred cooperative close of channel {}", log_bytes!(close.channel_id));
+			if let Err(e) = self.close_channel_internal(&close.channel_id, &close.counterparty_node_id,
+				close.target_feerate_sats_per_1000_weight, close.shutdown_script)
+			{
+				log_error!(self.logger, "Failed to close channel {}: {:?}", log_bytes!(close.channel_id), e);
+			}
+		}
+	}
+
+	/// Initiates a cooperative close of all channels which have been idle for at least
+	/// [`IdleChannelConfig::idle_timer_ticks_threshold`] timer ticks, if configured to do so, the
+	/// current [`ConfirmationTarget::Background`] feerate is low enough and our fee spike circuit
+	/// breaker isn't engaged.
+	///
+	/// [`IdleChannelConfig::idle_timer_ticks_threshold`]: crate::util::config::IdleChannelConfig::idle_timer_ticks_threshold
+	fn close_idle_channels(&self) {
+		let idle_config = self.default_configuration.idle_channel_config;
+		let idle_threshold = match idle_config.idle_timer_ticks_threshold {
+			Some(threshold) if idle_config.action == IdleChannelAction::CooperativeClose => threshold,
+			_ => return,
+		};
+		let background_feerate = self.fee_estimator.bounded_sat_per_1000_weight(ConfirmationTarget::Background);
+		if background_feerate > idle_config.max_close_feerate_sat_per_1000_weight { return; }
+		if self.fee_spike_circuit_breaker_engaged.load(Ordering::Acquire) { return; }
+
+		let mut idle_channels = Vec::new();
+		{
+			let idle_close_allowlist = self.idle_close_allowlist.lock().unwrap().clone();
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			for (counterparty_node_id, peer_state_mutex) in per_peer_state.iter() {
+				if idle_close_allowlist.contains(counterparty_node_id) { continue; }
+				let peer_state = peer_state_mutex.lock().unwrap();
+				for (chan_id, chan) in peer_state.channel_by_id.iter() {
+					if chan.context.get_idle_timer_ticks() >= idle_threshold && chan.context.is_live() &&
+						chan.context.shutdown_state() == ChannelShutdownState::NotShuttingDown
+					{
+						idle_channels.push((*chan_id, *counterparty_node_id));
+					}
+				}
+			}
+		}
+
+		for (chan_id, counterparty_node_id) in idle_channels {
+			log_info!(self.logger, "Initiating cooperative close of idle channel {}", log_bytes!(chan_id));
+			if let Err(e) = self.close_channel_internal(&chan_id, &counterparty_node_id,
+				Some(idle_config.max_close_feerate_sat_per_1000_weight), None)
+			{
+				log_error!(self.logger, "Failed to close idle channel {}: {:?}", log_bytes!(chan_id), e);
+			}
+		}
 	}
 
 	/// Indicates that the preimage for payment_hash is unknown or the received amount is incorrect
@@ -4549,7 +5974,11 @@ where
 	pub fn fail_htlc_backwards_with_reason(&self, payment_hash: &PaymentHash, failure_code: FailureCode) {
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
-		let removed_source = self.claimable_payments.lock().unwrap().claimable_payments.remove(payment_hash);
+		let removed_source = {
+			let mut claimable_payments = self.claimable_payments.lock().unwrap();
+			claimable_payments.partial_claim_allowances.remove(payment_hash);
+			claimable_payments.claimable_payments.remove(payment_hash)
+		};
 		if let Some(payment) = removed_source {
 			for htlc in payment.htlcs {
 				let reason = self.get_htlc_fail_reason_from_failure_code(failure_code, &htlc);
@@ -4667,6 +6096,12 @@ where
 		// Note that we MUST NOT end up calling methods on self.chain_monitor here - we're called
 		// from block_connected which may run during initialization prior to the chain_monitor
 		// being fully configured. See the docs for `ChannelManagerReadArgs` for more.
+		if let HTLCSource::PreviousHopData(ref hop_data) = source {
+			if self.manually_failed_forwards.lock().unwrap().remove(hop_data) {
+				log_trace!(self.logger, "Not failing HTLC with payment_hash {} backwards as it was already failed back manually", log_bytes!(payment_hash.0));
+				return;
+			}
+		}
 		match source {
 			HTLCSource::OutboundRoute { ref path, ref session_priv, ref payment_id, .. } => {
 				if self.pending_outbound_payments.fail_htlc(source, payment_hash, onion_error, path,
@@ -4739,9 +6174,13 @@ where
 					}
 				}
 
+				claimable_payments.partial_claim_allowances.remove(&payment_hash);
+				let shortfall_msat = payment.htlcs[0].total_msat.saturating_sub(
+					payment.htlcs.iter().map(|source| source.sender_intended_value).sum());
 				let dup_purpose = claimable_payments.pending_claiming_payments.insert(payment_hash,
 					ClaimingPayment { amount_msat: payment.htlcs.iter().map(|source| source.value).sum(),
 					payment_purpose: payment.purpose, receiver_node_id,
+					shortfall_msat: if shortfall_msat > 0 { Some(shortfall_msat) } else { None },
 				});
 				if dup_purpose.is_some() {
 					debug_assert!(false, "Shouldn't get a duplicate pending claim event ever");
@@ -4944,6 +6383,11 @@ where
 				self.pending_outbound_payments.claim_htlc(payment_id, payment_preimage, session_priv, path, from_onchain, &self.pending_events, &self.logger);
 			},
 			HTLCSource::PreviousHopData(hop_data) => {
+				if self.manually_failed_forwards.lock().unwrap().remove(&hop_data) {
+					log_error!(self.logger, "Unable to claim inbound HTLC {} in channel {} as it was already failed back manually, forwarded funds have been lost",
+						hop_data.htlc_id, log_bytes!(hop_data.outpoint.to_channel_id()));
+					return;
+				}
 				let prev_outpoint = hop_data.outpoint;
 				let res = self.claim_funds_from_hop(hop_data, payment_preimage,
 					|htlc_claim_value_msat| {
@@ -4982,9 +6426,10 @@ where
 			match action {
 				MonitorUpdateCompletionAction::PaymentClaimed { payment_hash } => {
 					let payment = self.claimable_payments.lock().unwrap().pending_claiming_payments.remove(&payment_hash);
-					if let Some(ClaimingPayment { amount_msat, payment_purpose: purpose, receiver_node_id }) = payment {
+					if let Some(ClaimingPayment { amount_msat, payment_purpose: purpose, receiver_node_id, shortfall_msat }) = payment {
 						self.pending_events.lock().unwrap().push_back((events::Event::PaymentClaimed {
 							payment_hash, purpose, amount_msat, receiver_node_id: Some(receiver_node_id),
+							shortfall_msat,
 						}, None));
 					}
 				},
@@ -5063,7 +6508,11 @@ where
 
 		if let Some(tx) = funding_broadcastable {
 			log_info!(self.logger, "Broadcasting funding transaction with txid {}", tx.txid());
-			self.tx_broadcaster.broadcast_transactions(&[&tx]);
+			self.tx_broadcaster.broadcast_transactions_with_meta(&[(&tx, TransactionMetadata {
+				channel_id: Some(channel.context.channel_id()),
+				counterparty_node_id: Some(counterparty_node_id),
+				transaction_type: TransactionType::Funding,
+			})]);
 		}
 
 		{
@@ -5138,7 +6587,20 @@ where
 	/// [`Event::OpenChannelRequest`]: events::Event::OpenChannelRequest
 	/// [`Event::ChannelClosed::user_channel_id`]: events::Event::ChannelClosed::user_channel_id
 	pub fn accept_inbound_channel(&self, temporary_channel_id: &[u8; 32], counterparty_node_id: &PublicKey, user_channel_id: u128) -> Result<(), APIError> {
-		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, false, user_channel_id)
+		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, false, user_channel_id, Vec::new())
+	}
+
+	/// Accepts a request to open a channel after a [`Event::OpenChannelRequest`] exactly like
+	/// [`Self::accept_inbound_channel`], but attaches the given application-specific
+	/// `custom_tlvs` to the `accept_channel` message.
+	///
+	/// See [`Self::create_channel_with_custom_tlvs`] for the requirements on `custom_tlvs`. The
+	/// counterparty's own custom TLVs are available in [`Event::OpenChannelRequest::custom_tlvs`].
+	///
+	/// [`Event::OpenChannelRequest`]: events::Event::OpenChannelRequest
+	/// [`Event::OpenChannelRequest::custom_tlvs`]: events::Event::OpenChannelRequest::custom_tlvs
+	pub fn accept_inbound_channel_with_custom_tlvs(&self, temporary_channel_id: &[u8; 32], counterparty_node_id: &PublicKey, user_channel_id: u128, custom_tlvs: Vec<(u64, Vec<u8>)>) -> Result<(), APIError> {
+		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, false, user_channel_id, custom_tlvs)
 	}
 
 	/// Accepts a request to open a channel after a [`events::Event::OpenChannelRequest`], treating
@@ -5160,10 +6622,11 @@ where
 	/// [`Event::OpenChannelRequest`]: events::Event::OpenChannelRequest
 	/// [`Event::ChannelClosed::user_channel_id`]: events::Event::ChannelClosed::user_channel_id
 	pub fn accept_inbound_channel_from_trusted_peer_0conf(&self, temporary_channel_id: &[u8; 32], counterparty_node_id: &PublicKey, user_channel_id: u128) -> Result<(), APIError> {
-		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, true, user_channel_id)
+		self.do_accept_inbound_channel(temporary_channel_id, counterparty_node_id, true, user_channel_id, Vec::new())
 	}
 
-	fn do_accept_inbound_channel(&self, temporary_channel_id: &[u8; 32], counterparty_node_id: &PublicKey, accept_0conf: bool, user_channel_id: u128) -> Result<(), APIError> {
+	fn do_accept_inbound_channel(&self, temporary_channel_id: &[u8; 32], counterparty_node_id: &PublicKey, accept_0conf: bool, user_channel_id: u128, mut custom_tlvs: Vec<(u64, Vec<u8>)>) -> Result<(), APIError> {
+		Self::check_custom_channel_tlvs(&mut custom_tlvs)?;
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 
 		let peers_without_funded_channels =
@@ -5208,6 +6671,7 @@ where
 					}
 				}
 
+				channel.get_mut().context.set_holder_custom_tlvs(custom_tlvs);
 				peer_state.pending_msg_events.push(events::MessageSendEvent::SendAcceptChannel {
 					node_id: channel.get().context.get_counterparty_node_id(),
 					msg: channel.get_mut().accept_inbound_channel(user_channel_id),
@@ -5312,6 +6776,14 @@ where
 				msg.temporary_channel_id.clone()));
 		}
 
+		let policy_opt = self.shutdown_script_policies.lock().unwrap().get(counterparty_node_id).cloned();
+		if let Some(policy) = policy_opt {
+			if let Err(err) = policy.check_upfront(&msg.shutdown_scriptpubkey, &peer_state.latest_features) {
+				self.outbound_scid_aliases.lock().unwrap().remove(&outbound_scid_alias);
+				return Err(MsgHandleErrInternal::send_err_msg_no_close(err, msg.temporary_channel_id.clone()));
+			}
+		}
+
 		let mut channel = match InboundV1Channel::new(&self.fee_estimator, &self.entropy_source, &self.signer_provider,
 			counterparty_node_id.clone(), &self.channel_type_features(), &peer_state.latest_features, msg, user_channel_id,
 			&self.default_configuration, best_block_height, &self.logger, outbound_scid_alias)
@@ -5348,6 +6820,7 @@ where
 					funding_satoshis: msg.funding_satoshis,
 					push_msat: msg.push_msat,
 					channel_type: channel.context.get_channel_type().clone(),
+					custom_tlvs: msg.custom_tlvs.clone(),
 				}, None));
 			}
 			peer_state.inbound_v1_channel_by_id.insert(channel_id, channel);
@@ -5367,6 +6840,11 @@ where
 			let peer_state = &mut *peer_state_lock;
 			match peer_state.outbound_v1_channel_by_id.entry(msg.temporary_channel_id) {
 				hash_map::Entry::Occupied(mut chan) => {
+					let policy_opt = self.shutdown_script_policies.lock().unwrap().get(counterparty_node_id).cloned();
+					if let Some(policy) = policy_opt {
+						let policy_res = policy.check_upfront(&msg.shutdown_scriptpubkey, &peer_state.latest_features);
+						try_v1_outbound_chan_entry!(self, policy_res.map_err(|err| ChannelError::Close(err)), chan);
+					}
 					try_v1_outbound_chan_entry!(self, chan.get_mut().accept_channel(&msg, &self.default_configuration.channel_handshake_limits, &peer_state.latest_features), chan);
 					(chan.get().context.get_value_satoshis(), chan.get().context.get_funding_redeemscript().to_v0_p2wsh(), chan.get().context.get_user_id())
 				},
@@ -5396,7 +6874,7 @@ where
 
 		let mut peer_state_lock = peer_state_mutex.lock().unwrap();
 		let peer_state = &mut *peer_state_lock;
-		let (chan, funding_msg, monitor) =
+		let (mut chan, funding_msg, monitor) =
 			match peer_state.inbound_v1_channel_by_id.remove(&msg.temporary_channel_id) {
 				Some(inbound_chan) => {
 					match inbound_chan.funding_created(msg, best_block, &self.signer_provider, &self.logger) {
@@ -5444,6 +6922,9 @@ where
 
 				let monitor_res = self.chain_monitor.watch_channel(monitor.get_funding_txo().0, monitor);
 
+				chan.context.track_pending_inbound_htlcs(Arc::clone(&self.pending_inbound_htlcs));
+				#[cfg(feature = "htlc_timeline_events")]
+				chan.context.set_htlc_timeline_time_source(Arc::clone(&*self.htlc_timeline_time_source.lock().unwrap()));
 				let chan = e.insert(chan);
 				let mut res = handle_new_monitor_update!(self, monitor_res, peer_state_lock, peer_state,
 					per_peer_state, chan, MANUALLY_REMOVING_INITIAL_MONITOR,
@@ -5572,6 +7053,12 @@ where
 						if chan_entry.get().sent_shutdown() { " after we initiated shutdown" } else { "" });
 				}
 
+				let policy_opt = self.shutdown_script_policies.lock().unwrap().get(counterparty_node_id).cloned();
+				if let Some(policy) = policy_opt {
+					let policy_res = policy.check_script(&msg.scriptpubkey);
+					try_chan_entry!(self, policy_res.map_err(|err| ChannelError::Close(err)), chan_entry);
+				}
+
 				let funding_txo_opt = chan_entry.get().context.get_funding_txo();
 				let (shutdown, monitor_update_opt, htlcs) = try_chan_entry!(self,
 					chan_entry.get_mut().shutdown(&self.signer_provider, &peer_state.latest_features, &msg), chan_entry);
@@ -5639,7 +7126,11 @@ where
 		};
 		if let Some(broadcast_tx) = tx {
 			log_info!(self.logger, "Broadcasting {}", log_tx!(broadcast_tx));
-			self.tx_broadcaster.broadcast_transactions(&[&broadcast_tx]);
+			self.tx_broadcaster.broadcast_transactions_with_meta(&[(&broadcast_tx, TransactionMetadata {
+				channel_id: Some(msg.channel_id),
+				counterparty_node_id: Some(*counterparty_node_id),
+				transaction_type: TransactionType::CooperativeClose,
+			})]);
 		}
 		if let Some(chan) = chan_option {
 			if let Ok(update) = self.get_channel_update_for_broadcast(&chan) {
@@ -5665,6 +7156,13 @@ where
 		//but we should prevent it anyway.
 
 		let decoded_hop_res = self.decode_update_add_htlc_onion(msg);
+		let acceptance_res = match decoded_hop_res {
+			Ok(_) if self.fee_spike_circuit_breaker_engaged.load(Ordering::Acquire) &&
+				msg.amount_msat < self.default_configuration.fee_spike_circuit_breaker_config.min_inbound_htlc_msat
+				=> Err("it is too small to claim on chain while feerates are spiking"),
+			Ok(_) => self.check_htlc_acceptance_limits(),
+			Err(_) => Ok(()),
+		};
 		let per_peer_state = self.per_peer_state.read().unwrap();
 		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
 			.ok_or_else(|| {
@@ -5704,6 +7202,13 @@ where
 						_ => pending_forward_info
 					}
 				};
+				let pending_forward_info = match acceptance_res {
+					Ok(()) => pending_forward_info,
+					Err(reason) => {
+						log_debug!(self.logger, "Failing HTLC with payment_hash {} as {}", log_bytes!(msg.payment_hash.0), reason);
+						create_pending_htlc_status(chan.get(), pending_forward_info, 0x1000|7)
+					},
+				};
 				try_chan_entry!(self, chan.get_mut().update_add_htlc(&msg, pending_forward_info, create_pending_htlc_status, &self.fee_estimator, &self.logger), chan);
 			},
 			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close(format!("Got a message for a channel from the wrong node! No such channel for the passed counterparty_node_id {}", counterparty_node_id), msg.channel_id))
@@ -5723,7 +7228,16 @@ where
 			let peer_state = &mut *peer_state_lock;
 			match peer_state.channel_by_id.entry(msg.channel_id) {
 				hash_map::Entry::Occupied(mut chan) => {
-					try_chan_entry!(self, chan.get_mut().update_fulfill_htlc(&msg), chan)
+					let res = try_chan_entry!(self, chan.get_mut().update_fulfill_htlc(&msg), chan);
+					if let Some((amount_msat, remaining_msat)) = chan.get_mut().context.collect_liquidity_skim(&res.0) {
+						self.pending_events.lock().unwrap().push_back((events::Event::LiquidityFeeCollected {
+							channel_id: msg.channel_id,
+							counterparty_node_id: *counterparty_node_id,
+							amount_msat,
+							remaining_msat,
+						}, None));
+					}
+					res
 				},
 				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close(format!("Got a message for a channel from the wrong node! No such channel for the passed counterparty_node_id {}", counterparty_node_id), msg.channel_id))
 			}
@@ -5807,6 +7321,50 @@ where
 						PendingHTLCRouting::Receive { .. } => 0,
 						PendingHTLCRouting::ReceiveKeysend { .. } => 0,
 					};
+					if scid == 0 && self.default_configuration.inbound_htlc_validation_config.hold_inbound_htlcs {
+						let hold_id = InterceptId(Sha256::hash(&forward_info.incoming_shared_secret).into_inner());
+						let best_block_height = self.best_block.read().unwrap().height();
+						let mut pending_held_htlcs = self.pending_held_htlcs.lock().unwrap();
+						match pending_held_htlcs.entry(hold_id) {
+							hash_map::Entry::Vacant(entry) => {
+								let (onion_fields, cltv_expiry) = match forward_info.routing {
+									PendingHTLCRouting::Receive { ref payment_data, ref payment_metadata, incoming_cltv_expiry, ref custom_tlvs, .. } =>
+										(RecipientOnionFields {
+											payment_secret: Some(payment_data.payment_secret),
+											payment_metadata: payment_metadata.clone(),
+											custom_tlvs: custom_tlvs.clone(),
+										}, incoming_cltv_expiry),
+									PendingHTLCRouting::ReceiveKeysend { ref payment_data, ref payment_metadata, incoming_cltv_expiry, ref custom_tlvs, .. } =>
+										(RecipientOnionFields {
+											payment_secret: payment_data.as_ref().map(|data| data.payment_secret),
+											payment_metadata: payment_metadata.clone(),
+											custom_tlvs: custom_tlvs.clone(),
+										}, incoming_cltv_expiry),
+									PendingHTLCRouting::Forward { .. } => unreachable!(),
+								};
+								new_intercept_events.push_back((events::Event::HTLCHeldForValidation {
+									hold_id,
+									payment_hash: forward_info.payment_hash,
+									amount_msat: forward_info.incoming_amt_msat.unwrap_or(forward_info.outgoing_amt_msat),
+									onion_fields,
+									cltv_expiry,
+									via_channel_id: prev_funding_outpoint.to_channel_id(),
+								}, None));
+								entry.insert(HeldHTLC {
+									htlc: PendingAddHTLCInfo {
+										prev_short_channel_id, prev_funding_outpoint, prev_htlc_id, prev_user_channel_id, forward_info },
+									ticks_remaining: self.default_configuration.inbound_htlc_validation_config.decision_timeout_ticks,
+								});
+							},
+							hash_map::Entry::Occupied(_) => {
+								log_info!(self.logger, "Failed to receive incoming HTLC: detected duplicate held payment with hash {}", log_bytes!(forward_info.payment_hash.0));
+								let htlc = PendingAddHTLCInfo {
+									prev_short_channel_id, prev_funding_outpoint, prev_htlc_id, prev_user_channel_id, forward_info };
+								failed_intercept_forwards.push(Self::held_htlc_failure(&htlc, best_block_height));
+							},
+						}
+						continue;
+					}
 					// Pull this now to avoid introducing a lock order with `forward_htlcs`.
 					let is_our_scid = self.short_to_chan_info.read().unwrap().contains_key(&scid);
 
@@ -5958,6 +7516,24 @@ where
 		Ok(())
 	}
 
+	fn internal_yield(&self, counterparty_node_id: &PublicKey, msg: &msgs::Yield) -> Result<(), MsgHandleErrInternal> {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| {
+				debug_assert!(false);
+				MsgHandleErrInternal::send_err_msg_no_close(format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id), msg.channel_id)
+			})?;
+		let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+		let peer_state = &mut *peer_state_lock;
+		match peer_state.channel_by_id.entry(msg.channel_id) {
+			hash_map::Entry::Occupied(mut chan) => {
+				try_chan_entry!(self, chan.get_mut().yield_received(&msg), chan);
+			},
+			hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close(format!("Got a message for a channel from the wrong node! No such channel for the passed counterparty_node_id {}", counterparty_node_id), msg.channel_id))
+		}
+		Ok(())
+	}
+
 	fn internal_announcement_signatures(&self, counterparty_node_id: &PublicKey, msg: &msgs::AnnouncementSignatures) -> Result<(), MsgHandleErrInternal> {
 		let per_peer_state = self.per_peer_state.read().unwrap();
 		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
@@ -6074,6 +7650,13 @@ where
 					if let Some(upd) = channel_update {
 						peer_state.pending_msg_events.push(upd);
 					}
+					if let Some(monitor_update) = responses.monitor_update {
+						// The channel type was upgraded, so we have a new commitment_signed to
+						// send once the monitor has been updated.
+						let funding_txo = chan.get().context.get_funding_txo();
+						handle_new_monitor_update!(self, funding_txo.unwrap(), monitor_update, peer_state_lock,
+							peer_state, per_peer_state, chan)?;
+					}
 					need_lnd_workaround
 				},
 				hash_map::Entry::Vacant(_) => return Err(MsgHandleErrInternal::send_err_msg_no_close(format!("Got a message for a channel from the wrong node! No such channel for the passed counterparty_node_id {}", counterparty_node_id), msg.channel_id))
@@ -6090,14 +7673,57 @@ where
 		Ok(())
 	}
 
-	/// Process pending events from the [`chain::Watch`], returning whether any events were processed.
-	fn process_pending_monitor_events(&self) -> bool {
-		debug_assert!(self.total_consistency_lock.try_write().is_err()); // Caller holds read lock
-
-		let mut failed_channels = Vec::new();
-		let mut pending_monitor_events = self.chain_monitor.release_pending_monitor_events();
-		let has_pending_monitor_events = !pending_monitor_events.is_empty();
-		for (funding_outpoint, mut monitor_events, counterparty_node_id) in pending_monitor_events.drain(..) {
+	/// Sets the [`TimeSource`] the `timestamp`s of [`Event::HTLCTimeline`]s are taken from,
+	/// replacing the [`DefaultTimeSource`], which measures time since this [`ChannelManager`] was
+	/// created or loaded.
+	///
+	/// As with any [`TimeSource`], the timestamps are only meaningful relative to each other, unless
+	/// the [`TimeSource`] set measures time since the UNIX epoch.
+	///
+	/// [`Event::HTLCTimeline`]: events::Event::HTLCTimeline
+	#[cfg(feature = "htlc_timeline_events")]
+	pub fn set_htlc_timeline_time_source<TS: TimeSource + Send + Sync + 'static>(&self, time_source: TS) {
+		let time_source: Arc<dyn TimeSource + Send + Sync> = Arc::new(time_source);
+		*self.htlc_timeline_time_source.lock().unwrap() = Arc::clone(&time_source);
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		for (_cp_id, peer_state_mutex) in per_peer_state.iter() {
+			let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+			for (_channel_id, chan) in peer_state_lock.channel_by_id.iter_mut() {
+				chan.context.set_htlc_timeline_time_source(Arc::clone(&time_source));
+			}
+		}
+	}
+
+	/// Moves any [`Event::HTLCTimeline`]s generated by our channels into our pending events.
+	///
+	/// [`Event::HTLCTimeline`]: events::Event::HTLCTimeline
+	#[cfg(feature = "htlc_timeline_events")]
+	fn collect_htlc_timeline_events(&self) {
+		let mut timeline_events = Vec::new();
+		{
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			for (_cp_id, peer_state_mutex) in per_peer_state.iter() {
+				let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+				for (_channel_id, chan) in peer_state_lock.channel_by_id.iter_mut() {
+					timeline_events.append(&mut chan.context.take_htlc_timeline_events());
+				}
+			}
+		}
+		self.pending_events.lock().unwrap().extend(timeline_events.into_iter().map(|ev| (ev, None)));
+	}
+
+	/// Process pending events from the [`chain::Watch`], returning whether any events were processed.
+	fn process_pending_monitor_events(&self) -> bool {
+		debug_assert!(self.total_consistency_lock.try_write().is_err()); // Caller holds read lock
+
+		let mut failed_channels = Vec::new();
+		let mut pending_monitor_events = self.chain_monitor.release_pending_monitor_events();
+		let has_pending_monitor_events = !pending_monitor_events.is_empty();
+		for (funding_outpoint, mut monitor_events, counterparty_node_id) in pending_monitor_events.drain(..) {
+			// Prefer our own view of the counterparty, as events generated before a node key
+			// rotation (see `migrate_counterparty_node_id`) still carry the old node id.
+			let counterparty_node_id = self.id_to_peer.lock().unwrap()
+				.get(&funding_outpoint.to_channel_id()).cloned().or(counterparty_node_id);
 			for monitor_event in monitor_events.drain(..) {
 				match monitor_event {
 					MonitorEvent::HTLCEvent(htlc_update) => {
@@ -6180,6 +7806,7 @@ where
 	/// update was applied.
 	fn check_free_holding_cells(&self) -> bool {
 		let mut has_monitor_update = false;
+		let mut has_yield = false;
 		let mut failed_htlcs = Vec::new();
 		let mut handle_errors = Vec::new();
 
@@ -6213,6 +7840,13 @@ where
 							}
 							continue 'peer_loop;
 						}
+						// Only hand over (or ask for) the turn once we've sent whatever we could.
+						if let Some(msg) = chan.maybe_get_yield() {
+							has_yield = true;
+							peer_state.pending_msg_events.push(events::MessageSendEvent::SendYield {
+								node_id: counterparty_node_id, msg,
+							});
+						}
 					}
 					break 'chan_loop;
 				}
@@ -6220,7 +7854,7 @@ where
 			break 'peer_loop;
 		}
 
-		let has_update = has_monitor_update || !failed_htlcs.is_empty() || !handle_errors.is_empty();
+		let has_update = has_monitor_update || has_yield || !failed_htlcs.is_empty() || !handle_errors.is_empty();
 		for (failures, channel_id, counterparty_node_id) in failed_htlcs.drain(..) {
 			self.fail_holding_cell_htlcs(failures, channel_id, &counterparty_node_id);
 		}
@@ -6266,7 +7900,11 @@ where
 								self.issue_channel_close_events(&chan.context, ClosureReason::CooperativeClosure);
 
 								log_info!(self.logger, "Broadcasting {}", log_tx!(tx));
-								self.tx_broadcaster.broadcast_transactions(&[&tx]);
+								self.tx_broadcaster.broadcast_transactions_with_meta(&[(&tx, TransactionMetadata {
+									channel_id: Some(*channel_id),
+									counterparty_node_id: Some(chan.context.get_counterparty_node_id()),
+									transaction_type: TransactionType::CooperativeClose,
+								})]);
 								update_maps_on_chan_removal!(self, &chan.context);
 								false
 							} else { true }
@@ -6407,6 +8045,89 @@ where
 			min_final_cltv_expiry)
 	}
 
+	/// Watches for on-chain payments to `script_pubkey`, which has been included as an on-chain
+	/// fallback in an invoice with the given `payment_hash` and amount.
+	///
+	/// Once a transaction paying to the script reaches [`ANTI_REORG_DELAY`] confirmations, an
+	/// [`OnchainFallbackPaymentReceived`] event is generated and the script is no longer watched.
+	/// Watched scripts are persisted with the `ChannelManager` and handed to
+	/// [`chain::Watch::watch_onchain_fallback`] so that transactions paying to them are provided.
+	///
+	/// [`OnchainFallbackPaymentReceived`]: events::Event::OnchainFallbackPaymentReceived
+	pub fn watch_onchain_fallback(&self, script_pubkey: Script, payment_hash: PaymentHash, expected_amount_msat: Option<u64>) {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		log_debug!(self.logger, "Watching on-chain fallback script {} for payment hash {}",
+			log_bytes!(script_pubkey.as_bytes()[..]), log_bytes!(payment_hash.0));
+		self.chain_monitor.watch_onchain_fallback(&script_pubkey);
+		self.onchain_fallbacks.lock().unwrap().insert(script_pubkey, OnchainFallback {
+			payment_hash, expected_amount_msat, payment: None,
+		});
+	}
+
+	/// Stops watching an on-chain fallback script previously registered via
+	/// [`Self::watch_onchain_fallback`], e.g. because the invoice was paid over lightning or has
+	/// expired. Returns whether the script was being watched.
+	pub fn unwatch_onchain_fallback(&self, script_pubkey: &Script) -> bool {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		self.onchain_fallbacks.lock().unwrap().remove(script_pubkey).is_some()
+	}
+
+	/// Records the first output in `txdata` paying to each watched on-chain fallback script.
+	fn onchain_fallbacks_confirmed(&self, block_hash: &BlockHash, txdata: &TransactionData, height: u32) {
+		let mut onchain_fallbacks = self.onchain_fallbacks.lock().unwrap();
+		if onchain_fallbacks.is_empty() { return; }
+		for (_, tx) in txdata.iter() {
+			for (idx, output) in tx.output.iter().enumerate() {
+				if let Some(fallback) = onchain_fallbacks.get_mut(&output.script_pubkey) {
+					if fallback.payment.is_some() { continue; }
+					let outpoint = bitcoin::OutPoint { txid: tx.txid(), vout: idx as u32 };
+					log_info!(self.logger, "On-chain fallback payment of {} sats for payment hash {} confirmed in {}, waiting for {} confirmations",
+						output.value, log_bytes!(fallback.payment_hash.0), outpoint, ANTI_REORG_DELAY);
+					fallback.payment = Some(OnchainFallbackPayment {
+						outpoint, amount_satoshis: output.value, block_hash: *block_hash, height,
+					});
+				}
+			}
+		}
+	}
+
+	/// Generates an [`events::Event::OnchainFallbackPaymentReceived`] for each on-chain fallback
+	/// payment which reached [`ANTI_REORG_DELAY`] confirmations at `best_height`, after which the
+	/// script is no longer watched.
+	fn release_onchain_fallback_payments(&self, best_height: u32) {
+		let mut fallback_events = Vec::new();
+		self.onchain_fallbacks.lock().unwrap().retain(|_, fallback| {
+			match fallback.payment {
+				Some(ref payment) if best_height + 1 >= payment.height + ANTI_REORG_DELAY => {
+					fallback_events.push(events::Event::OnchainFallbackPaymentReceived {
+						payment_hash: fallback.payment_hash,
+						outpoint: payment.outpoint,
+						amount_satoshis: payment.amount_satoshis,
+						expected_amount_msat: fallback.expected_amount_msat,
+					});
+					false
+				},
+				_ => true,
+			}
+		});
+		if fallback_events.is_empty() { return; }
+		let mut pending_events = self.pending_events.lock().unwrap();
+		for event in fallback_events.drain(..) {
+			pending_events.push_back((event, None));
+		}
+	}
+
+	/// Forgets any on-chain fallback payments for which `is_reorged_out` returns true.
+	fn onchain_fallbacks_unconfirmed<F: Fn(&OnchainFallbackPayment) -> bool>(&self, is_reorged_out: F) {
+		for fallback in self.onchain_fallbacks.lock().unwrap().values_mut() {
+			if fallback.payment.as_ref().map_or(false, |payment| is_reorged_out(payment)) {
+				log_info!(self.logger, "On-chain fallback payment for payment hash {} was reorged out",
+					log_bytes!(fallback.payment_hash.0));
+				fallback.payment = None;
+			}
+		}
+	}
+
 	/// Gets an LDK-generated payment preimage from a payment hash and payment secret that were
 	/// previously returned from [`create_inbound_payment`].
 	///
@@ -6415,6 +8136,415 @@ where
 		inbound_payment::get_payment_preimage(payment_hash, payment_secret, &self.inbound_payment_key)
 	}
 
+	/// Allows an inbound invoice payment with the given [`PaymentHash`] to be claimed for less than
+	/// its total amount.
+	///
+	/// Normally, if the parts of an MPP payment do not add up to the invoice amount before the
+	/// MPP timeout, all parts are failed back to the sender. Once this is called, an incomplete
+	/// set of parts whose sender-intended value is at least `min_amount_msat` will instead be
+	/// surfaced in a [`PaymentClaimable`] event when it times out. Any further parts received for
+	/// the payment after that point are failed. If the payment is then claimed, the amount by
+	/// which it fell short is reported in [`PaymentClaimed::shortfall_msat`].
+	///
+	/// The allowance is used up once the payment is claimed or its parts are failed back, e.g.
+	/// because they fell short of `min_amount_msat`. It is also forgotten once we see a block with
+	/// a timestamp past `expiry_time`, in seconds since the UNIX epoch, unless parts of the payment
+	/// are pending at that time. This should generally be the expiry of the invoice for the
+	/// payment.
+	///
+	/// Note that claiming a partial payment still releases the payment preimage, giving the sender
+	/// proof-of-payment for the full invoice amount. Only use this for payments where partial
+	/// delivery is meaningful to both parties, such as margin top-ups.
+	///
+	/// [`PaymentClaimable`]: events::Event::PaymentClaimable
+	/// [`PaymentClaimed::shortfall_msat`]: events::Event::PaymentClaimed::shortfall_msat
+	pub fn allow_partial_claim(&self, payment_hash: PaymentHash, min_amount_msat: u64, expiry_time: u64) {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		self.claimable_payments.lock().unwrap().partial_claim_allowances.insert(payment_hash,
+			PartialClaimAllowance { min_amount_msat, expiry_time });
+	}
+
+	/// Revokes an allowance previously granted via [`Self::allow_partial_claim`]. Returns whether
+	/// an allowance existed for the given [`PaymentHash`].
+	///
+	/// This has no effect on a partial payment which has already been surfaced as claimable.
+	pub fn disallow_partial_claim(&self, payment_hash: &PaymentHash) -> bool {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		self.claimable_payments.lock().unwrap().partial_claim_allowances.remove(payment_hash).is_some()
+	}
+
+	/// Sets the [`ShutdownScriptPolicy`] for channels with the given counterparty, replacing any
+	/// existing one. Passing `None` removes the policy.
+	///
+	/// The policy is checked against the counterparty's `open_channel` and `accept_channel`
+	/// messages, rejecting the channel on violation, as well as against the script pubkey in its
+	/// `shutdown` message, force-closing the channel on violation. Channels already open are not
+	/// re-checked against their upfront shutdown script.
+	pub fn set_peer_shutdown_script_policy(&self, counterparty_node_id: &PublicKey, policy: Option<ShutdownScriptPolicy>) {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let mut policies = self.shutdown_script_policies.lock().unwrap();
+		match policy {
+			Some(policy) => { policies.insert(*counterparty_node_id, policy); },
+			None => { policies.remove(counterparty_node_id); },
+		}
+	}
+
+	/// Gets the [`PeerMetadata`] stored for the given peer, if any.
+	pub fn get_peer_metadata(&self, counterparty_node_id: &PublicKey) -> Option<PeerMetadata> {
+		self.peer_metadata.lock().unwrap().get(counterparty_node_id).cloned()
+	}
+
+	/// Gets the [`PeerMetadata`] stored for all peers.
+	pub fn list_peer_metadata(&self) -> Vec<(PublicKey, PeerMetadata)> {
+		self.peer_metadata.lock().unwrap().iter()
+			.map(|(node_id, metadata)| (*node_id, metadata.clone()))
+			.collect()
+	}
+
+	/// Sets the [`PeerMetadata`] stored for the given peer, replacing any existing metadata.
+	/// Passing `None` removes it.
+	///
+	/// Metadata is persisted as a part of the `ChannelManager` and is available regardless of
+	/// whether we have channels with or are connected to the peer. It is intended for policy logic,
+	/// e.g. when handling [`Event::OpenChannelRequest`], and can also be queried via
+	/// [`PeerManager::peer_metadata`].
+	///
+	/// [`PeerManager::peer_metadata`]: crate::ln::peer_handler::PeerManager::peer_metadata
+	pub fn set_peer_metadata(&self, counterparty_node_id: &PublicKey, metadata: Option<PeerMetadata>) {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let mut peer_metadata = self.peer_metadata.lock().unwrap();
+		match metadata {
+			Some(metadata) => { peer_metadata.insert(*counterparty_node_id, metadata); },
+			None => { peer_metadata.remove(counterparty_node_id); },
+		}
+	}
+
+	/// Updates the [`PeerMetadata`] stored for the given peer in place, starting from the default
+	/// metadata if there is none yet.
+	///
+	/// See [`Self::set_peer_metadata`] for more details.
+	///
+	/// This is not exported to bindings users as it takes a closure.
+	pub fn update_peer_metadata<F: FnOnce(&mut PeerMetadata)>(&self, counterparty_node_id: &PublicKey, update: F) {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let mut peer_metadata = self.peer_metadata.lock().unwrap();
+		update(peer_metadata.entry(*counterparty_node_id).or_insert_with(PeerMetadata::default));
+	}
+
+	/// Sets whether channels with the given counterparty are exempt from being cooperatively
+	/// closed for being idle when [`IdleChannelConfig::action`] is
+	/// [`IdleChannelAction::CooperativeClose`].
+	///
+	/// [`Event::ChannelIdle`] will still be generated for allow-listed channels. The allow-list is
+	/// persisted as a part of the `ChannelManager`.
+	///
+	/// [`IdleChannelConfig::action`]: crate::util::config::IdleChannelConfig::action
+	/// [`IdleChannelAction::CooperativeClose`]: crate::util::config::IdleChannelAction::CooperativeClose
+	pub fn set_idle_close_allowlisted(&self, counterparty_node_id: &PublicKey, allowlisted: bool) {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let mut idle_close_allowlist = self.idle_close_allowlist.lock().unwrap();
+		if allowlisted {
+			idle_close_allowlist.insert(*counterparty_node_id);
+		} else {
+			idle_close_allowlist.remove(counterparty_node_id);
+		}
+	}
+
+	/// Returns whether channels with the given counterparty are exempt from being closed for
+	/// being idle, see [`Self::set_idle_close_allowlisted`].
+	pub fn is_idle_close_allowlisted(&self, counterparty_node_id: &PublicKey) -> bool {
+		self.idle_close_allowlist.lock().unwrap().contains(counterparty_node_id)
+	}
+
+	/// Moves our channels and per-peer settings with `old_node_id` over to `new_node_id`, after
+	/// the counterparty rotated its node identity key.
+	///
+	/// Rotations are announced via a [`NodeKeyRotation`] in the counterparty's
+	/// `node_announcement`, which can be looked up via [`ReadOnlyNetworkGraph::node_key_rotation`].
+	/// The rotation is not checked here, so this must only be called for rotations which were
+	/// verified in some way.
+	///
+	/// Both node ids must be disconnected, we must not have any channels with `new_node_id` yet and
+	/// no [`ChannelMonitorUpdate`]s may be in flight for channels with `old_node_id`. Public
+	/// channels will exchange `announcement_signatures` again once reestablished, so that a
+	/// `channel_announcement` with the new node id can be broadcast. Each channel's
+	/// [`ChannelMonitor`] is moved over to `new_node_id` via a new [`ChannelMonitorUpdate`].
+	///
+	/// [`NodeKeyRotation`]: msgs::NodeKeyRotation
+	/// [`ReadOnlyNetworkGraph::node_key_rotation`]: crate::routing::gossip::ReadOnlyNetworkGraph::node_key_rotation
+	pub fn migrate_counterparty_node_id(&self, old_node_id: &PublicKey, new_node_id: &PublicKey) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		if old_node_id == new_node_id {
+			return Err(APIError::APIMisuseError { err: "Cannot migrate a counterparty to its own node id".to_owned() });
+		}
+
+		let mut per_peer_state = self.per_peer_state.write().unwrap();
+		if let Some(peer_state_mutex) = per_peer_state.get(new_node_id) {
+			if !peer_state_mutex.lock().unwrap().ok_to_remove(true) {
+				return Err(APIError::APIMisuseError { err: format!("Peer {} is connected or already has channels with us", new_node_id) });
+			}
+		}
+		match per_peer_state.get(old_node_id) {
+			None => return Err(APIError::APIMisuseError { err: format!("Can't find a peer matching the passed counterparty node_id {}", old_node_id) }),
+			Some(peer_state_mutex) => {
+				let peer_state = peer_state_mutex.lock().unwrap();
+				if peer_state.is_connected {
+					return Err(APIError::APIMisuseError { err: format!("Peer {} must be disconnected before it can be migrated", old_node_id) });
+				}
+				if !peer_state.in_flight_monitor_updates.is_empty() || !peer_state.monitor_update_blocked_actions.is_empty() ||
+					!peer_state.actions_blocking_raa_monitor_updates.is_empty() ||
+					peer_state.channel_by_id.values().any(|chan| chan.blocked_monitor_updates_pending() != 0)
+				{
+					return Err(APIError::APIMisuseError { err: format!("Peer {} has ChannelMonitorUpdates in flight, try again once they complete", old_node_id) });
+				}
+			},
+		}
+
+		let mut peer_state = per_peer_state.remove(old_node_id).unwrap().into_inner().unwrap();
+		let mut monitor_updates = Vec::new();
+		{
+			let mut id_to_peer = self.id_to_peer.lock().unwrap();
+			for (channel_id, chan) in peer_state.channel_by_id.iter_mut() {
+				let monitor_update_opt = chan.migrate_counterparty_node_id(*new_node_id);
+				if let (Some(funding_txo), Some(monitor_update)) = (chan.context.get_funding_txo(), monitor_update_opt) {
+					monitor_updates.push((*channel_id, funding_txo, monitor_update));
+				}
+				id_to_peer.insert(*channel_id, *new_node_id);
+			}
+		}
+		for chan in peer_state.outbound_v1_channel_by_id.values_mut() {
+			chan.context.migrate_counterparty_node_id(*new_node_id);
+		}
+		for chan in peer_state.inbound_v1_channel_by_id.values_mut() {
+			chan.context.migrate_counterparty_node_id(*new_node_id);
+		}
+		for (counterparty_node_id, _) in self.short_to_chan_info.write().unwrap().values_mut() {
+			if *counterparty_node_id == *old_node_id {
+				*counterparty_node_id = *new_node_id;
+			}
+		}
+		log_info!(self.logger, "Migrated {} channels from counterparty {} to {} after a node key rotation",
+			peer_state.total_channel_count(), log_pubkey!(*old_node_id), log_pubkey!(*new_node_id));
+		per_peer_state.insert(*new_node_id, Mutex::new(peer_state));
+		core::mem::drop(per_peer_state);
+
+		let mut peer_metadata = self.peer_metadata.lock().unwrap();
+		if let Some(metadata) = peer_metadata.remove(old_node_id) {
+			peer_metadata.insert(*new_node_id, metadata);
+		}
+		core::mem::drop(peer_metadata);
+		let mut shutdown_script_policies = self.shutdown_script_policies.lock().unwrap();
+		if let Some(policy) = shutdown_script_policies.remove(old_node_id) {
+			shutdown_script_policies.insert(*new_node_id, policy);
+		}
+		core::mem::drop(shutdown_script_policies);
+		let mut idle_close_allowlist = self.idle_close_allowlist.lock().unwrap();
+		if idle_close_allowlist.remove(old_node_id) {
+			idle_close_allowlist.insert(*new_node_id);
+		}
+		core::mem::drop(idle_close_allowlist);
+		let mut closed_channel_dust_write_offs = self.closed_channel_dust_write_offs.lock().unwrap();
+		if let Some(written_off_msat) = closed_channel_dust_write_offs.remove(old_node_id) {
+			let new_written_off_msat = closed_channel_dust_write_offs.entry(*new_node_id).or_insert(0);
+			*new_written_off_msat = new_written_off_msat.saturating_add(written_off_msat);
+		}
+		core::mem::drop(closed_channel_dust_write_offs);
+
+		// Finally, move the channels' `ChannelMonitor`s over to the new node id, so that the
+		// `MonitorEvent`s they generate (and any force-closes they lead to) are attributed to it.
+		for (channel_id, funding_txo, monitor_update) in monitor_updates {
+			let res = {
+				let per_peer_state = self.per_peer_state.read().unwrap();
+				let mut peer_state_lock = match per_peer_state.get(new_node_id) {
+					Some(peer_state_mutex) => peer_state_mutex.lock().unwrap(),
+					None => break,
+				};
+				let peer_state = &mut *peer_state_lock;
+				match peer_state.channel_by_id.entry(channel_id) {
+					hash_map::Entry::Occupied(mut chan_entry) =>
+						handle_new_monitor_update!(self, funding_txo, monitor_update,
+							peer_state_lock, peer_state, per_peer_state, chan_entry).map(|_| ()),
+					hash_map::Entry::Vacant(_) => Ok(()),
+				}
+			};
+			let _ = handle_error!(self, res, *new_node_id);
+		}
+		Ok(())
+	}
+
+	/// Computes adaptor signatures for the settlement transactions of all branches of `bundle`
+	/// with the funding key of the given channel, each encrypted to the entry of `adaptor_points`
+	/// at the same index.
+	///
+	/// This allows contracts to lock their collateral output to the funding keys of a channel, see
+	/// [`ContractManager`].
+	///
+	/// Returns [`ChannelUnavailable`] when a channel is not found or an incorrect
+	/// `counterparty_node_id` is provided, and [`APIMisuseError`] if the bundle's collateral
+	/// output isn't locked to the channel's funding key or is the channel's funding output.
+	///
+	/// [`ContractManager`]: crate::ln::contractmanager::ContractManager
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn sign_settlement_transactions_with_adaptor_points(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], bundle: &SettlementBundle,
+		adaptor_points: &[PublicKey],
+	) -> Result<Vec<EcdsaAdaptorSignature>, APIError> {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+		let peer_state_lock = peer_state_mutex.lock().unwrap();
+		let channel = peer_state_lock.channel_by_id.get(channel_id).ok_or_else(|| APIError::ChannelUnavailable {
+			err: format!("Channel with ID {} was not found for the passed counterparty_node_id {}", log_bytes!(*channel_id), counterparty_node_id),
+		})?;
+		channel.sign_settlement_transactions_with_adaptor_points(bundle, adaptor_points)
+			.map_err(|()| APIError::APIMisuseError {
+				err: format!("Failed to sign settlement transactions with the funding key of channel {}", log_bytes!(*channel_id)),
+			})
+	}
+
+	/// Computes the signature for the settlement transaction of the branch at `branch_idx` of
+	/// `bundle` with the funding key of the given channel, e.g. to settle a contract on-chain.
+	///
+	/// Returns [`ChannelUnavailable`] when a channel is not found or an incorrect
+	/// `counterparty_node_id` is provided, and [`APIMisuseError`] if the bundle's collateral
+	/// output isn't locked to the channel's funding key or is the channel's funding output.
+	///
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	/// [`APIMisuseError`]: APIError::APIMisuseError
+	pub fn sign_settlement_transaction(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], bundle: &SettlementBundle,
+		branch_idx: usize,
+	) -> Result<Signature, APIError> {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+		let peer_state_lock = peer_state_mutex.lock().unwrap();
+		let channel = peer_state_lock.channel_by_id.get(channel_id).ok_or_else(|| APIError::ChannelUnavailable {
+			err: format!("Channel with ID {} was not found for the passed counterparty_node_id {}", log_bytes!(*channel_id), counterparty_node_id),
+		})?;
+		channel.sign_settlement_transaction(bundle, branch_idx).map_err(|()| APIError::APIMisuseError {
+			err: format!("Failed to sign a settlement transaction with the funding key of channel {}", log_bytes!(*channel_id)),
+		})
+	}
+
+	/// Signs a message about a contract whose collateral output is locked to the funding keys of
+	/// the given channel with our funding key, see [`contract_message_digest`].
+	///
+	/// Returns [`ChannelUnavailable`] when a channel is not found or an incorrect
+	/// `counterparty_node_id` is provided.
+	///
+	/// [`contract_message_digest`]: crate::ln::contracts::contract_message_digest
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	pub fn sign_contract_message(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], msg: &[u8],
+	) -> Result<Signature, APIError> {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+		let peer_state_lock = peer_state_mutex.lock().unwrap();
+		let channel = peer_state_lock.channel_by_id.get(channel_id).ok_or_else(|| APIError::ChannelUnavailable {
+			err: format!("Channel with ID {} was not found for the passed counterparty_node_id {}", log_bytes!(*channel_id), counterparty_node_id),
+		})?;
+		channel.sign_contract_message(msg).map_err(|()| APIError::APIMisuseError {
+			err: format!("Failed to sign a contract message with the funding key of channel {}", log_bytes!(*channel_id)),
+		})
+	}
+
+	/// Sends a spontaneous payment of `amount_msat` directly to our counterparty over the given
+	/// channel, e.g. to transfer the balance owed when settling a contract off-chain.
+	///
+	/// The `payment_metadata` is provided to the recipient in the [`RecipientOnionFields`] of the
+	/// resulting [`Event::PaymentClaimable`]. As with [`Self::send_spontaneous_payment`], the
+	/// payment is identified by `payment_id`, and no further payment will be sent if one with the
+	/// same `payment_id` is still pending.
+	///
+	/// Returns [`ChannelUnavailable`] when the channel is not found, not usable, or an incorrect
+	/// `counterparty_node_id` is provided.
+	///
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	pub fn send_payment_over_channel(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], amount_msat: u64,
+		payment_metadata: Option<Vec<u8>>, payment_id: PaymentId,
+	) -> Result<PaymentHash, APIError> {
+		let details = self.list_usable_channels().into_iter()
+			.find(|details| details.channel_id == *channel_id && details.counterparty.node_id == *counterparty_node_id)
+			.ok_or_else(|| APIError::ChannelUnavailable {
+				err: format!("Channel with ID {} is not usable or was not found for the passed counterparty_node_id {}", log_bytes!(*channel_id), counterparty_node_id),
+			})?;
+		let short_channel_id = details.get_outbound_payment_scid().ok_or_else(|| APIError::ChannelUnavailable {
+			err: format!("Channel with ID {} has no short channel id to route over yet", log_bytes!(*channel_id)),
+		})?;
+		let route = Route {
+			paths: vec![Path { hops: vec![RouteHop {
+				pubkey: *counterparty_node_id,
+				node_features: details.counterparty.features.to_context(),
+				short_channel_id,
+				channel_features: details.counterparty.features.to_context(),
+				fee_msat: amount_msat,
+				cltv_expiry_delta: MIN_FINAL_CLTV_EXPIRY_DELTA as u32,
+			}], blinded_tail: None }],
+			payment_params: None,
+		};
+		let recipient_onion = RecipientOnionFields { payment_secret: None, payment_metadata, custom_tlvs: Vec::new() };
+		self.send_spontaneous_payment(&route, None, recipient_onion, payment_id).map_err(|e| match e {
+			PaymentSendFailure::ParameterError(err) => err,
+			PaymentSendFailure::DuplicatePayment => APIError::APIMisuseError {
+				err: "A payment with the given payment_id is already pending".to_owned(),
+			},
+			e => APIError::ChannelUnavailable {
+				err: format!("Failed to send payment over channel {}: {:?}", log_bytes!(*channel_id), e),
+			},
+		})
+	}
+
+	/// Hands a transaction releasing a contract's collateral, e.g. a cooperatively signed
+	/// settlement of a contract settled off-chain, to the channel's [`ChannelMonitor`]. The
+	/// monitor will broadcast it and keep rebroadcasting it via
+	/// [`ChannelMonitor::rebroadcast_pending_claims`] until a spend of the collateral output
+	/// reaches [`ANTI_REORG_DELAY`] confirmations.
+	///
+	/// Note that the monitor only learns of the collateral output being spent from the blocks (or
+	/// transactions) it's given, so users relying on [`chain::Filter`] must register the
+	/// collateral output themselves.
+	///
+	/// Returns [`ChannelUnavailable`] when the channel is not found, not open, or an incorrect
+	/// `counterparty_node_id` is provided.
+	///
+	/// [`ChannelUnavailable`]: APIError::ChannelUnavailable
+	pub fn watch_collateral_release(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], transaction: Transaction,
+	) -> Result<(), APIError> {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+
+		let result: Result<(), _> = loop {
+			let per_peer_state = self.per_peer_state.read().unwrap();
+			let peer_state_mutex = per_peer_state.get(counterparty_node_id)
+				.ok_or_else(|| APIError::ChannelUnavailable { err: format!("Can't find a peer matching the passed counterparty node_id {}", counterparty_node_id) })?;
+			let mut peer_state_lock = peer_state_mutex.lock().unwrap();
+			let peer_state = &mut *peer_state_lock;
+			match peer_state.channel_by_id.entry(*channel_id) {
+				hash_map::Entry::Occupied(mut chan_entry) => {
+					let funding_txo = chan_entry.get().context.get_funding_txo()
+						.ok_or_else(|| APIError::ChannelUnavailable { err: "Channel is not funded yet".to_owned() })?;
+					let monitor_update_opt = chan_entry.get_mut().watch_collateral_release(transaction)
+						.map_err(|e| APIError::ChannelUnavailable { err: e.to_string() })?;
+					if let Some(monitor_update) = monitor_update_opt {
+						break handle_new_monitor_update!(self, funding_txo, monitor_update,
+							peer_state_lock, peer_state, per_peer_state, chan_entry).map(|_| ());
+					}
+					break Ok(());
+				},
+				hash_map::Entry::Vacant(_) => return Err(APIError::ChannelUnavailable {
+					err: format!("Channel with id {} not found for the passed counterparty node_id {}", log_bytes!(*channel_id), counterparty_node_id)
+				}),
+			}
+		};
+
+		let _ = handle_error!(self, result, *counterparty_node_id);
+		Ok(())
+	}
+
 	/// Gets a fake short channel id for use in receiving [phantom node payments]. These fake scids
 	/// are used when constructing the phantom invoice's route hints.
 	///
@@ -6661,6 +8791,66 @@ where
 	}
 }
 
+impl<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref> ContractSigner for ChannelManager<M, T, ES, NS, SP, F, R, L>
+where
+	M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
+	T::Target: BroadcasterInterface,
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+	SP::Target: SignerProvider,
+	F::Target: FeeEstimator,
+	R::Target: Router,
+	L::Target: Logger,
+{
+	fn sign_settlement_transactions(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], bundle: &SettlementBundle,
+		adaptor_points: &[PublicKey],
+	) -> Result<Vec<EcdsaAdaptorSignature>, ()> {
+		self.sign_settlement_transactions_with_adaptor_points(counterparty_node_id, channel_id, bundle, adaptor_points)
+			.map_err(|_| ())
+	}
+
+	fn sign_settlement_transaction(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], bundle: &SettlementBundle,
+		branch_idx: usize,
+	) -> Result<Signature, ()> {
+		self.sign_settlement_transaction(counterparty_node_id, channel_id, bundle, branch_idx).map_err(|_| ())
+	}
+
+	fn sign_contract_message(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], msg: &[u8],
+	) -> Result<Signature, ()> {
+		self.sign_contract_message(counterparty_node_id, channel_id, msg).map_err(|_| ())
+	}
+}
+
+impl<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref> ContractPaymentSender for ChannelManager<M, T, ES, NS, SP, F, R, L>
+where
+	M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
+	T::Target: BroadcasterInterface,
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+	SP::Target: SignerProvider,
+	F::Target: FeeEstimator,
+	R::Target: Router,
+	L::Target: Logger,
+{
+	fn send_contract_payment(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], amount_msat: u64,
+		payment_metadata: Vec<u8>, payment_id: PaymentId,
+	) -> Result<(), ()> {
+		self.send_payment_over_channel(counterparty_node_id, channel_id, amount_msat, Some(payment_metadata), payment_id)
+			.map(|_| ()).map_err(|_| ())
+	}
+
+	fn watch_collateral_release(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], transaction: &Transaction,
+	) -> Result<(), ()> {
+		ChannelManager::watch_collateral_release(self, counterparty_node_id, channel_id, transaction.clone())
+			.map_err(|_| ())
+	}
+}
+
 impl<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref> EventsProvider for ChannelManager<M, T, ES, NS, SP, F, R, L>
 where
 	M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
@@ -6720,6 +8910,7 @@ where
 		}
 
 		self.do_chain_event(Some(new_height), |channel| channel.best_block_updated(new_height, header.time, self.genesis_hash.clone(), &self.node_signer, &self.default_configuration, &self.logger));
+		self.onchain_fallbacks_unconfirmed(|payment| payment.height >= height);
 	}
 }
 
@@ -6746,11 +8937,13 @@ where
 			&self.persistence_notifier, || -> NotifyOption { NotifyOption::DoPersist });
 		self.do_chain_event(Some(height), |channel| channel.transactions_confirmed(&block_hash, height, txdata, self.genesis_hash.clone(), &self.node_signer, &self.default_configuration, &self.logger)
 			.map(|(a, b)| (a, Vec::new(), b)));
+		self.onchain_fallbacks_confirmed(&block_hash, txdata, height);
 
 		let last_best_block_height = self.best_block.read().unwrap().height();
 		if height < last_best_block_height {
 			let timestamp = self.highest_seen_timestamp.load(Ordering::Acquire);
 			self.do_chain_event(Some(last_best_block_height), |channel| channel.best_block_updated(last_best_block_height, timestamp as u32, self.genesis_hash.clone(), &self.node_signer, &self.default_configuration, &self.logger));
+			self.release_onchain_fallback_payments(last_best_block_height);
 		}
 	}
 
@@ -6767,6 +8960,8 @@ where
 		*self.best_block.write().unwrap() = BestBlock::new(block_hash, height);
 
 		self.do_chain_event(Some(height), |channel| channel.best_block_updated(height, header.time, self.genesis_hash.clone(), &self.node_signer, &self.default_configuration, &self.logger));
+		self.pending_outbound_payments.abandon_payments_past_deadline(height, &self.pending_events, &self.logger);
+		self.release_onchain_fallback_payments(height);
 
 		macro_rules! max_time {
 			($timestamp: expr) => {
@@ -6789,6 +8984,13 @@ where
 		payment_secrets.retain(|_, inbound_payment| {
 			inbound_payment.expiry_time > header.time as u64
 		});
+		mem::drop(payment_secrets);
+
+		let mut claimable_payments_lock = self.claimable_payments.lock().unwrap();
+		let ClaimablePayments { claimable_payments, partial_claim_allowances, .. } = &mut *claimable_payments_lock;
+		partial_claim_allowances.retain(|payment_hash, allowance| {
+			allowance.expiry_time > header.time as u64 || claimable_payments.contains_key(payment_hash)
+		});
 	}
 
 	fn get_relevant_txids(&self) -> Vec<(Txid, Option<BlockHash>)> {
@@ -6802,6 +9004,11 @@ where
 				}
 			}
 		}
+		for fallback in self.onchain_fallbacks.lock().unwrap().values() {
+			if let Some(ref payment) = fallback.payment {
+				res.push((payment.outpoint.txid, Some(payment.block_hash)));
+			}
+		}
 		res
 	}
 
@@ -6815,6 +9022,7 @@ where
 				} else { Ok((None, Vec::new(), None)) }
 			} else { Ok((None, Vec::new(), None)) }
 		});
+		self.onchain_fallbacks_unconfirmed(|payment| payment.outpoint.txid == *txid);
 	}
 }
 
@@ -6933,7 +9141,9 @@ where
 		}
 
 		if let Some(height) = height_opt {
-			self.claimable_payments.lock().unwrap().claimable_payments.retain(|payment_hash, payment| {
+			let mut claimable_payments_lock = self.claimable_payments.lock().unwrap();
+			let ClaimablePayments { claimable_payments, partial_claim_allowances, .. } = &mut *claimable_payments_lock;
+			claimable_payments.retain(|payment_hash, payment| {
 				payment.htlcs.retain(|htlc| {
 					// If height is approaching the number of blocks we think it takes us to get
 					// our commitment transaction confirmed before the HTLC expires, plus the
@@ -6949,8 +9159,12 @@ where
 						false
 					} else { true }
 				});
+				if payment.htlcs.is_empty() {
+					partial_claim_allowances.remove(payment_hash);
+				}
 				!payment.htlcs.is_empty() // Only retain this entry if htlcs has at least one entry.
 			});
+			mem::drop(claimable_payments_lock);
 
 			let mut intercepted_htlcs = self.pending_intercepted_htlcs.lock().unwrap();
 			intercepted_htlcs.retain(|_, htlc| {
@@ -6974,6 +9188,21 @@ where
 					false
 				} else { true }
 			});
+			mem::drop(intercepted_htlcs);
+
+			self.pending_held_htlcs.lock().unwrap().retain(|_, held_htlc| {
+				let cltv_expiry = match held_htlc.htlc.forward_info.routing {
+					PendingHTLCRouting::Receive { incoming_cltv_expiry, .. } => incoming_cltv_expiry,
+					PendingHTLCRouting::ReceiveKeysend { incoming_cltv_expiry, .. } => incoming_cltv_expiry,
+					PendingHTLCRouting::Forward { .. } => unreachable!(),
+				};
+				if height >= cltv_expiry - HTLC_FAIL_BACK_BUFFER {
+					log_trace!(self.logger, "Timing out held HTLC with payment hash {}",
+						log_bytes!(held_htlc.htlc.forward_info.payment_hash.0));
+					timed_out_htlcs.push(Self::held_htlc_failure(&held_htlc.htlc, height));
+					false
+				} else { true }
+			});
 		}
 
 		self.handle_init_event_channel_failures(failed_channels);
@@ -7038,6 +9267,27 @@ where
 	}
 }
 
+impl<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref>
+	ChannelPeerLookup for ChannelManager<M, T, ES, NS, SP, F, R, L>
+where
+	M::Target: chain::Watch<<SP::Target as SignerProvider>::Signer>,
+	T::Target: BroadcasterInterface,
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+	SP::Target: SignerProvider,
+	F::Target: FeeEstimator,
+	R::Target: Router,
+	L::Target: Logger,
+{
+	fn has_channel_with(&self, peer_node_id: &PublicKey) -> bool {
+		let per_peer_state = self.per_peer_state.read().unwrap();
+		match per_peer_state.get(peer_node_id) {
+			Some(peer_state_mutex) => !peer_state_mutex.lock().unwrap().channel_by_id.is_empty(),
+			None => false,
+		}
+	}
+}
+
 impl<M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref>
 	ChannelMessageHandler for ChannelManager<M, T, ES, NS, SP, F, R, L>
 where
@@ -7132,6 +9382,11 @@ where
 		let _ = handle_error!(self, self.internal_update_fee(counterparty_node_id, msg), *counterparty_node_id);
 	}
 
+	fn handle_yield(&self, counterparty_node_id: &PublicKey, msg: &msgs::Yield) {
+		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
+		let _ = handle_error!(self, self.internal_yield(counterparty_node_id, msg), *counterparty_node_id);
+	}
+
 	fn handle_announcement_signatures(&self, counterparty_node_id: &PublicKey, msg: &msgs::AnnouncementSignatures) {
 		let _persistence_guard = PersistenceNotifierGuard::notify_on_drop(self);
 		let _ = handle_error!(self, self.internal_announcement_signatures(counterparty_node_id, msg), *counterparty_node_id);
@@ -7209,6 +9464,7 @@ where
 						// Channel Operations
 						&events::MessageSendEvent::UpdateHTLCs { .. } => false,
 						&events::MessageSendEvent::SendRevokeAndACK { .. } => false,
+						&events::MessageSendEvent::SendYield { .. } => false,
 						&events::MessageSendEvent::SendClosingSigned { .. } => false,
 						&events::MessageSendEvent::SendShutdown { .. } => false,
 						&events::MessageSendEvent::SendChannelReestablish { .. } => false,
@@ -7299,6 +9555,7 @@ where
 			let mut peer_state_lock = peer_state_mutex.lock().unwrap();
 			let peer_state = &mut *peer_state_lock;
 			let pending_msg_events = &mut peer_state.pending_msg_events;
+			let their_features = &peer_state.latest_features;
 
 			// Since unfunded channel maps are cleared upon disconnecting a peer, and they're not persisted
 			// (so won't be recovered after a crash) we don't need to bother closing unfunded channels and
@@ -7307,7 +9564,7 @@ where
 			peer_state.channel_by_id.iter_mut().for_each(|(_, chan)| {
 				pending_msg_events.push(events::MessageSendEvent::SendChannelReestablish {
 					node_id: chan.context.get_counterparty_node_id(),
-					msg: chan.get_channel_reestablish(&self.logger),
+					msg: chan.get_channel_reestablish(&self.default_configuration, their_features, &self.logger),
 				});
 			});
 		}
@@ -7369,6 +9626,39 @@ where
 		Some(vec![ChainHash::from(&self.genesis_hash[..])])
 	}
 
+	fn peer_metadata(&self, their_node_id: &PublicKey) -> Option<PeerMetadata> {
+		self.get_peer_metadata(their_node_id)
+	}
+
+	fn has_channels_with_peer(&self, their_node_id: &PublicKey) -> bool {
+		self.per_peer_state.read().unwrap().get(their_node_id)
+			.map(|peer_state_mutex| peer_state_mutex.lock().unwrap().total_channel_count() > 0)
+			.unwrap_or(false)
+	}
+
+	fn peer_address_seen(&self, their_node_id: &PublicKey, address: &msgs::NetAddress) {
+		// Peers usually reconnect from the address we last saw them on, so only persist if that
+		// changed rather than on every connection.
+		PersistenceNotifierGuard::optionally_notify(&self.total_consistency_lock, &self.persistence_notifier, || {
+			// Only track addresses for peers we care about, otherwise anyone connecting to us could
+			// grow our persisted state.
+			let has_channels = self.per_peer_state.read().unwrap().get(their_node_id)
+				.map(|peer_state_mutex| peer_state_mutex.lock().unwrap().total_channel_count() > 0)
+				.unwrap_or(false);
+			let mut peer_metadata = self.peer_metadata.lock().unwrap();
+			let metadata = match peer_metadata.entry(*their_node_id) {
+				hash_map::Entry::Occupied(entry) => entry.into_mut(),
+				hash_map::Entry::Vacant(entry) if has_channels => entry.insert(PeerMetadata::default()),
+				hash_map::Entry::Vacant(_) => return NotifyOption::SkipPersist,
+			};
+			if metadata.record_address(address.clone()) {
+				NotifyOption::DoPersist
+			} else {
+				NotifyOption::SkipPersist
+			}
+		});
+	}
+
 	fn handle_tx_add_input(&self, counterparty_node_id: &PublicKey, msg: &msgs::TxAddInput) {
 		let _: Result<(), _> = handle_error!(self, Err(MsgHandleErrInternal::send_err_msg_no_close(
 			"Dual-funded channels not supported".to_owned(),
@@ -7473,6 +9763,12 @@ pub fn provided_init_features(config: &UserConfig) -> InitFeatures {
 	if config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx {
 		features.set_anchors_zero_fee_htlc_tx_optional();
 	}
+	if config.channel_handshake_config.negotiate_simplified_update {
+		features.set_simplified_update_optional();
+	}
+	if config.channel_handshake_config.negotiate_channel_type_upgrade {
+		features.set_channel_type_upgrade_optional();
+	}
 	features
 }
 
@@ -7529,6 +9825,7 @@ impl Writeable for ChannelDetails {
 			(37, user_channel_id_high_opt, option),
 			(39, self.feerate_sat_per_1000_weight, option),
 			(41, self.channel_shutdown_state, option),
+			(43, self.pending_dust_forwards_msat, required),
 		});
 		Ok(())
 	}
@@ -7567,6 +9864,7 @@ impl Readable for ChannelDetails {
 			(37, user_channel_id_high_opt, option),
 			(39, feerate_sat_per_1000_weight, option),
 			(41, channel_shutdown_state, option),
+			(43, pending_dust_forwards_msat, (default_value, 0)),
 		});
 
 		// `user_channel_id` used to be a single u64 value. In order to remain backwards compatible with
@@ -7603,10 +9901,60 @@ impl Readable for ChannelDetails {
 			inbound_htlc_maximum_msat,
 			feerate_sat_per_1000_weight,
 			channel_shutdown_state,
+			pending_dust_forwards_msat: pending_dust_forwards_msat.0.unwrap(),
 		})
 	}
 }
 
+impl_writeable_tlv_based_enum!(HTLCDirection,
+	(0, Inbound) => {},
+	(2, Outbound) => {},
+);
+
+impl_writeable_tlv_based_enum!(HTLCStage,
+	(0, AwaitingCommitment) => {},
+	(2, Committed) => {},
+	(4, AwaitingRemoval) => {},
+);
+
+impl_writeable_tlv_based!(InFlightHTLCDetails, {
+	(0, channel_id, required),
+	(2, counterparty_node_id, required),
+	(4, htlc_id, required),
+	(6, direction, required),
+	(8, stage, required),
+	(10, amount_msat, required),
+	(12, payment_hash, required),
+	(14, cltv_expiry, required),
+	(16, age_timer_ticks, required),
+	(18, is_forward, required),
+});
+
+impl_writeable_tlv_based!(ChannelDebugState, {
+	(0, channel_id, required),
+	(2, counterparty_node_id, required),
+	(4, is_outbound, required),
+	(6, channel_state, required),
+	(8, next_local_commitment_number, required),
+	(10, next_remote_commitment_number, required),
+	(12, latest_monitor_update_id, required),
+	(14, blocked_monitor_updates, required),
+	(16, resend_commitment_first, required),
+	(18, monitor_pending_channel_ready, required),
+	(20, monitor_pending_revoke_and_ack, required),
+	(22, monitor_pending_commitment_signed, required),
+	(24, feerate_per_kw, required),
+	(25, pending_update_fee, option),
+	(27, holding_cell_update_fee, option),
+	(28, holding_cell_htlc_adds, required),
+	(30, holding_cell_htlc_removals, required),
+	(32, next_holder_htlc_id, required),
+	(34, next_counterparty_htlc_id, required),
+	(36, value_to_self_msat, required),
+	(37, simplified_update_turn, option),
+	(38, pending_htlcs, required_vec),
+});
+
 impl_writeable_tlv_based!(PhantomRouteHints, {
 	(2, channels, required_vec),
 	(4, phantom_scid, required),
@@ -7623,12 +9971,14 @@ impl_writeable_tlv_based_enum!(PendingHTLCRouting,
 		(1, phantom_shared_secret, option),
 		(2, incoming_cltv_expiry, required),
 		(3, payment_metadata, option),
+		(5, custom_tlvs, optional_vec),
 	},
 	(2, ReceiveKeysend) => {
 		(0, payment_preimage, required),
 		(2, incoming_cltv_expiry, required),
 		(3, payment_metadata, option),
 		(4, payment_data, option), // Added in 0.0.116
+		(5, custom_tlvs, optional_vec),
 	},
 ;);
 
@@ -7874,6 +10224,31 @@ impl_writeable_tlv_based!(PendingAddHTLCInfo, {
 	(6, prev_funding_outpoint, required),
 });
 
+impl_writeable_tlv_based!(HeldHTLC, {
+	(0, htlc, required),
+	(2, ticks_remaining, required),
+});
+
+impl_writeable_tlv_based!(DeferredCooperativeClose, {
+	(0, channel_id, required),
+	(2, counterparty_node_id, required),
+	(4, target_feerate_sats_per_1000_weight, option),
+	(6, shutdown_script, option),
+});
+
+impl_writeable_tlv_based!(OnchainFallback, {
+	(0, payment_hash, required),
+	(2, expected_amount_msat, option),
+	(4, payment, option),
+});
+
+impl_writeable_tlv_based!(OnchainFallbackPayment, {
+	(0, outpoint, required),
+	(2, amount_satoshis, required),
+	(4, block_hash, required),
+	(6, height, required),
+});
+
 impl_writeable_tlv_based_enum!(HTLCForwardInfo,
 	(1, FailHTLC) => {
 		(0, htlc_id, required),
@@ -8070,12 +10445,61 @@ where
 			}
 		}
 
-		let mut pending_intercepted_htlcs = None;
-		let our_pending_intercepts = self.pending_intercepted_htlcs.lock().unwrap();
-		if our_pending_intercepts.len() != 0 {
-			pending_intercepted_htlcs = Some(our_pending_intercepts);
+		let mut pending_intercepted_htlcs = None;
+		let our_pending_intercepts = self.pending_intercepted_htlcs.lock().unwrap();
+		if our_pending_intercepts.len() != 0 {
+			pending_intercepted_htlcs = Some(our_pending_intercepts);
+		}
+
+		let mut pending_held_htlcs = None;
+		let our_pending_held_htlcs = self.pending_held_htlcs.lock().unwrap();
+		if our_pending_held_htlcs.len() != 0 {
+			pending_held_htlcs = Some(our_pending_held_htlcs);
+		}
+
+		let mut partial_claim_allowances = None;
+		if !claimable_payments.partial_claim_allowances.is_empty() {
+			partial_claim_allowances = Some(&claimable_payments.partial_claim_allowances);
+		}
+
+		let manually_failed_forwards = self.manually_failed_forwards.lock().unwrap();
+		let mut manually_failed_forwards_opt = None;
+		if !manually_failed_forwards.is_empty() {
+			manually_failed_forwards_opt = Some(&*manually_failed_forwards);
+		}
+
+		let shutdown_script_policies = self.shutdown_script_policies.lock().unwrap();
+		let mut shutdown_script_policies_opt = None;
+		if !shutdown_script_policies.is_empty() {
+			shutdown_script_policies_opt = Some(&*shutdown_script_policies);
+		}
+
+		let peer_metadata = self.peer_metadata.lock().unwrap();
+		let mut peer_metadata_opt = None;
+		if !peer_metadata.is_empty() {
+			peer_metadata_opt = Some(&*peer_metadata);
+		}
+
+		let idle_close_allowlist = self.idle_close_allowlist.lock().unwrap();
+		let mut idle_close_allowlist_opt = None;
+		if !idle_close_allowlist.is_empty() {
+			idle_close_allowlist_opt = Some(&*idle_close_allowlist);
+		}
+
+		let closed_channel_dust_write_offs = self.closed_channel_dust_write_offs.lock().unwrap();
+		let mut closed_channel_dust_write_offs_opt = None;
+		if !closed_channel_dust_write_offs.is_empty() {
+			closed_channel_dust_write_offs_opt = Some(&*closed_channel_dust_write_offs);
 		}
 
+		let onchain_fallbacks = self.onchain_fallbacks.lock().unwrap();
+		let mut onchain_fallbacks_opt = None;
+		if !onchain_fallbacks.is_empty() {
+			onchain_fallbacks_opt = Some(&*onchain_fallbacks);
+		}
+
+		let deferred_cooperative_closes = self.deferred_cooperative_closes.lock().unwrap();
+
 		let mut pending_claiming_payments = Some(&claimable_payments.pending_claiming_payments);
 		if pending_claiming_payments.as_ref().unwrap().is_empty() {
 			// LDK versions prior to 0.0.113 do not know how to read the pending claimed payments
@@ -8106,6 +10530,15 @@ where
 			(10, in_flight_monitor_updates, option),
 			(11, self.probing_cookie_secret, required),
 			(13, htlc_onion_fields, optional_vec),
+			(15, partial_claim_allowances, option),
+			(17, shutdown_script_policies_opt, option),
+			(19, peer_metadata_opt, option),
+			(21, idle_close_allowlist_opt, option),
+			(25, closed_channel_dust_write_offs_opt, option),
+			(27, pending_held_htlcs, option),
+			(29, manually_failed_forwards_opt, option),
+			(31, onchain_fallbacks_opt, option),
+			(33, *deferred_cooperative_closes, optional_vec),
 		});
 
 		Ok(())
@@ -8325,6 +10758,9 @@ where
 		let channel_count: u64 = Readable::read(reader)?;
 		let mut funding_txo_set = HashSet::with_capacity(cmp::min(channel_count as usize, 128));
 		let mut peer_channels: HashMap<PublicKey, HashMap<[u8; 32], Channel<<SP::Target as SignerProvider>::Signer>>> = HashMap::with_capacity(cmp::min(channel_count as usize, 128));
+		let pending_inbound_htlcs = Arc::new(AtomicUsize::new(0));
+		#[cfg(feature = "htlc_timeline_events")]
+		let htlc_timeline_time_source: Arc<dyn TimeSource + Send + Sync> = Arc::new(DefaultTimeSource::new());
 		let mut id_to_peer = HashMap::with_capacity(cmp::min(channel_count as usize, 128));
 		let mut short_to_chan_info = HashMap::with_capacity(cmp::min(channel_count as usize, 128));
 		let mut channel_closures = VecDeque::new();
@@ -8386,6 +10822,9 @@ where
 					if channel.context.is_funding_initiated() {
 						id_to_peer.insert(channel.context.channel_id(), channel.context.get_counterparty_node_id());
 					}
+					channel.context.track_pending_inbound_htlcs(Arc::clone(&pending_inbound_htlcs));
+					#[cfg(feature = "htlc_timeline_events")]
+					channel.context.set_htlc_timeline_time_source(Arc::clone(&htlc_timeline_time_source));
 					match peer_channels.entry(channel.context.get_counterparty_node_id()) {
 						hash_map::Entry::Occupied(mut entry) => {
 							let by_id_map = entry.get_mut();
@@ -8531,12 +10970,21 @@ where
 		let mut pending_outbound_payments_no_retry: Option<HashMap<PaymentId, HashSet<[u8; 32]>>> = None;
 		let mut pending_outbound_payments = None;
 		let mut pending_intercepted_htlcs: Option<HashMap<InterceptId, PendingAddHTLCInfo>> = Some(HashMap::new());
+		let mut pending_held_htlcs: Option<HashMap<InterceptId, HeldHTLC>> = Some(HashMap::new());
 		let mut received_network_pubkey: Option<PublicKey> = None;
 		let mut fake_scid_rand_bytes: Option<[u8; 32]> = None;
 		let mut probing_cookie_secret: Option<[u8; 32]> = None;
 		let mut claimable_htlc_purposes = None;
 		let mut claimable_htlc_onion_fields = None;
 		let mut pending_claiming_payments = Some(HashMap::new());
+		let mut partial_claim_allowances = Some(HashMap::new());
+		let mut manually_failed_forwards: Option<HashSet<HTLCPreviousHopData>> = Some(HashSet::new());
+		let mut shutdown_script_policies: Option<HashMap<PublicKey, ShutdownScriptPolicy>> = Some(HashMap::new());
+		let mut peer_metadata: Option<HashMap<PublicKey, PeerMetadata>> = Some(HashMap::new());
+		let mut idle_close_allowlist: Option<HashSet<PublicKey>> = Some(HashSet::new());
+		let mut closed_channel_dust_write_offs: Option<HashMap<PublicKey, u64>> = Some(HashMap::new());
+		let mut onchain_fallbacks: Option<HashMap<Script, OnchainFallback>> = Some(HashMap::new());
+		let mut deferred_cooperative_closes: Option<Vec<DeferredCooperativeClose>> = Some(Vec::new());
 		let mut monitor_update_blocked_actions_per_peer: Option<Vec<(_, BTreeMap<_, Vec<_>>)>> = Some(Vec::new());
 		let mut events_override = None;
 		let mut in_flight_monitor_updates: Option<HashMap<(PublicKey, OutPoint), Vec<ChannelMonitorUpdate>>> = None;
@@ -8553,6 +11001,15 @@ where
 			(10, in_flight_monitor_updates, option),
 			(11, probing_cookie_secret, option),
 			(13, claimable_htlc_onion_fields, optional_vec),
+			(15, partial_claim_allowances, option),
+			(17, shutdown_script_policies, option),
+			(19, peer_metadata, option),
+			(21, idle_close_allowlist, option),
+			(25, closed_channel_dust_write_offs, option),
+			(27, pending_held_htlcs, option),
+			(29, manually_failed_forwards, option),
+			(31, onchain_fallbacks, option),
+			(33, deferred_cooperative_closes, optional_vec),
 		});
 		if fake_scid_rand_bytes.is_none() {
 			fake_scid_rand_bytes = Some(args.entropy_source.get_secure_random_bytes());
@@ -8581,7 +11038,8 @@ where
 		}
 		let pending_outbounds = OutboundPayments {
 			pending_outbound_payments: Mutex::new(pending_outbound_payments.unwrap()),
-			retry_lock: Mutex::new(())
+			retry_lock: Mutex::new(()),
+			retry_budget: Mutex::new(RetryBudgetTracker::new(args.default_config.payment_retry_budget)),
 		};
 
 		// We have to replay (or skip, if they were completed after we wrote the `ChannelManager`)
@@ -8734,11 +11192,13 @@ where
 										payment_hash: htlc.payment_hash,
 										payment_secret: None, // only used for retries, and we'll never retry on startup
 										payment_metadata: None, // only used for retries, and we'll never retry on startup
+										custom_tlvs: Vec::new(), // only used for retries, and we'll never retry on startup
 										keysend_preimage: None, // only used for retries, and we'll never retry on startup
 										pending_amt_msat: path_amt,
 										pending_fee_msat: Some(path_fee),
 										total_msat: path_amt,
 										starting_block_height: best_block_height,
+										pinned_route: None,
 									});
 									log_info!(args.logger, "Added a pending payment for {} msat with payment hash {} for path with session priv {}",
 										path_amt, log_bytes!(htlc.payment_hash.0),  log_bytes!(session_priv_bytes));
@@ -8953,8 +11413,13 @@ where
 		for (_, monitor) in args.channel_monitors.iter() {
 			for (payment_hash, payment_preimage) in monitor.get_stored_preimages() {
 				if let Some(payment) = claimable_payments.remove(&payment_hash) {
+					if let Some(allowances) = partial_claim_allowances.as_mut() {
+						allowances.remove(&payment_hash);
+					}
 					log_info!(args.logger, "Re-claiming HTLCs with payment hash {} as we've released the preimage to a ChannelMonitor!", log_bytes!(payment_hash.0));
 					let mut claimable_amt_msat = 0;
+					let shortfall_msat = payment.htlcs[0].total_msat.saturating_sub(
+						payment.htlcs.iter().map(|htlc| htlc.sender_intended_value).sum());
 					let mut receiver_node_id = Some(our_network_pubkey);
 					let phantom_shared_secret = payment.htlcs[0].prev_hop.phantom_shared_secret;
 					if phantom_shared_secret.is_some() {
@@ -8998,6 +11463,7 @@ where
 						payment_hash,
 						purpose: payment.purpose,
 						amount_msat: claimable_amt_msat,
+						shortfall_msat: if shortfall_msat > 0 { Some(shortfall_msat) } else { None },
 					}, None));
 				}
 			}
@@ -9032,6 +11498,11 @@ where
 			}
 		}
 
+		let onchain_fallbacks = onchain_fallbacks.unwrap();
+		for script_pubkey in onchain_fallbacks.keys() {
+			args.chain_monitor.watch_onchain_fallback(script_pubkey);
+		}
+
 		let channel_manager = ChannelManager {
 			genesis_hash,
 			fee_estimator: bounded_fee_estimator,
@@ -9045,15 +11516,31 @@ where
 			pending_inbound_payments: Mutex::new(pending_inbound_payments),
 			pending_outbound_payments: pending_outbounds,
 			pending_intercepted_htlcs: Mutex::new(pending_intercepted_htlcs.unwrap()),
+			pending_held_htlcs: Mutex::new(pending_held_htlcs.unwrap()),
+			manually_failed_forwards: Mutex::new(manually_failed_forwards.unwrap()),
 
 			forward_htlcs: Mutex::new(forward_htlcs),
-			claimable_payments: Mutex::new(ClaimablePayments { claimable_payments, pending_claiming_payments: pending_claiming_payments.unwrap() }),
+			claimable_payments: Mutex::new(ClaimablePayments {
+				claimable_payments, pending_claiming_payments: pending_claiming_payments.unwrap(),
+				partial_claim_allowances: partial_claim_allowances.unwrap(),
+			}),
 			outbound_scid_aliases: Mutex::new(outbound_scid_aliases),
 			id_to_peer: Mutex::new(id_to_peer),
 			short_to_chan_info: FairRwLock::new(short_to_chan_info),
 			fake_scid_rand_bytes: fake_scid_rand_bytes.unwrap(),
 
 			probing_cookie_secret: probing_cookie_secret.unwrap(),
+			#[cfg(feature = "htlc_timeline_events")]
+			htlc_timeline_time_source: Mutex::new(htlc_timeline_time_source),
+			shutdown_script_policies: Mutex::new(shutdown_script_policies.unwrap()),
+			peer_metadata: Mutex::new(peer_metadata.unwrap()),
+			idle_close_allowlist: Mutex::new(idle_close_allowlist.unwrap()),
+			closed_channel_dust_write_offs: Mutex::new(closed_channel_dust_write_offs.unwrap()),
+			onchain_fallbacks: Mutex::new(onchain_fallbacks),
+			htlc_acceptance_limiter: Mutex::new(HTLCAcceptanceLimiter::new(&args.default_config.htlc_acceptance_limits)),
+			pending_inbound_htlcs,
+			fee_spike_circuit_breaker_engaged: AtomicBool::new(false),
+			deferred_cooperative_closes: Mutex::new(deferred_cooperative_closes.unwrap()),
 
 			our_network_pubkey,
 			secp_ctx,
@@ -9105,16 +11592,17 @@ mod tests {
 	use bitcoin::hashes::sha256::Hash as Sha256;
 	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 	use core::sync::atomic::Ordering;
-	use crate::events::{Event, HTLCDestination, MessageSendEvent, MessageSendEventsProvider, ClosureReason};
+	use crate::chain::chaininterface::LowerBoundedFeeEstimator;
+	use crate::events::{Event, HTLCDestination, MessageSendEvent, MessageSendEventsProvider, ClosureReason, PaymentPurpose};
 	use crate::ln::{PaymentPreimage, PaymentHash, PaymentSecret};
-	use crate::ln::channelmanager::{inbound_payment, PaymentId, PaymentSendFailure, RecipientOnionFields, InterceptId};
+	use crate::ln::channelmanager::{inbound_payment, ChannelShutdownState, ForwardingAsymmetry, MIN_CLTV_EXPIRY_DELTA, PaymentId, PaymentSendFailure, RecipientOnionFields, InterceptId};
 	use crate::ln::functional_test_utils::*;
 	use crate::ln::msgs::{self, ErrorAction};
 	use crate::ln::msgs::ChannelMessageHandler;
 	use crate::routing::router::{PaymentParameters, RouteParameters, find_route};
 	use crate::util::errors::APIError;
 	use crate::util::test_utils;
-	use crate::util::config::{ChannelConfig, ChannelConfigUpdate};
+	use crate::util::config::{ChannelConfig, ChannelConfigUpdate, FeeSpikeCircuitBreakerConfig, ForwardingHarmonizationConfig, IdleChannelAction, IdleChannelConfig, UnresponsivePeerConfig, UserConfig};
 	use crate::sign::EntropySource;
 
 	#[test]
@@ -10078,6 +12566,7 @@ mod tests {
 				payment_data: Some(msgs::FinalOnionHopData {
 					payment_secret: PaymentSecret([0; 32]), total_msat: sender_intended_amt_msat,
 				}),
+				custom_tlvs: Vec::new(),
 			}
 		};
 		// Check that if the amount we received + the penultimate hop extra fee is less than the sender
@@ -10099,6 +12588,7 @@ mod tests {
 				payment_data: Some(msgs::FinalOnionHopData {
 					payment_secret: PaymentSecret([0; 32]), total_msat: sender_intended_amt_msat,
 				}),
+				custom_tlvs: Vec::new(),
 			}
 		};
 		assert!(node[0].node.construct_recv_pending_htlc_info(hop_data, [0; 32], PaymentHash([0; 32]),
@@ -10184,6 +12674,279 @@ mod tests {
 		check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
 	}
 
+	#[test]
+	fn test_custom_channel_tlvs() {
+		// Tests that custom TLVs attached to `open_channel` and `accept_channel` are surfaced to the
+		// counterparty in `OpenChannelRequest` and `ChannelPending` events.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut manual_accept_config = test_default_channel_config();
+		manual_accept_config.manually_accept_inbound_channels = true;
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(manual_accept_config)]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let node_a_id = nodes[0].node.get_our_node_id();
+		let node_b_id = nodes[1].node.get_our_node_id();
+
+		// Even types, types below the custom range and repeated types are all rejected.
+		for invalid_tlvs in [vec![(65536, vec![])], vec![(4241, vec![])], vec![(65537, vec![]), (65537, vec![1])]].iter() {
+			match nodes[0].node.create_channel_with_custom_tlvs(node_b_id, 100_000, 0, 42, None, invalid_tlvs.clone()) {
+				Err(APIError::APIMisuseError { .. }) => {},
+				res => panic!("Unexpected result {:?}", res),
+			}
+		}
+		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+
+		let open_tlvs = vec![(65539, vec![4, 2]), (65537, vec![1])];
+		nodes[0].node.create_channel_with_custom_tlvs(node_b_id, 100_000, 0, 42, None, open_tlvs).unwrap();
+		let open_channel_msg = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, node_b_id);
+		let sorted_open_tlvs = vec![(65537, vec![1]), (65539, vec![4, 2])];
+		assert_eq!(open_channel_msg.custom_tlvs, sorted_open_tlvs);
+
+		nodes[1].node.handle_open_channel(&node_a_id, &open_channel_msg);
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::OpenChannelRequest { temporary_channel_id, ref custom_tlvs, .. } => {
+				assert_eq!(*custom_tlvs, sorted_open_tlvs);
+				nodes[1].node.accept_inbound_channel_with_custom_tlvs(&temporary_channel_id, &node_a_id, 23, vec![(100_001, vec![7; 3])]).unwrap();
+			},
+			_ => panic!("Unexpected event"),
+		}
+		let accept_channel_msg = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, node_a_id);
+		nodes[0].node.handle_accept_channel(&node_b_id, &accept_channel_msg);
+
+		let (temporary_channel_id, tx, _) = create_funding_transaction(&nodes[0], &node_b_id, 100_000, 42);
+		nodes[0].node.funding_transaction_generated(&temporary_channel_id, &node_b_id, tx).unwrap();
+		let funding_created_msg = get_event_msg!(nodes[0], MessageSendEvent::SendFundingCreated, node_b_id);
+		nodes[1].node.handle_funding_created(&node_a_id, &funding_created_msg);
+		check_added_monitors!(nodes[1], 1);
+		let funding_signed_msg = get_event_msg!(nodes[1], MessageSendEvent::SendFundingSigned, node_a_id);
+		nodes[0].node.handle_funding_signed(&node_b_id, &funding_signed_msg);
+		check_added_monitors!(nodes[0], 1);
+
+		let expected_counterparty_tlvs = [vec![(100_001, vec![7; 3])], sorted_open_tlvs];
+		for (node, expected_tlvs) in nodes.iter().zip(expected_counterparty_tlvs.iter()) {
+			let events = node.node.get_and_clear_pending_events();
+			assert_eq!(events.len(), 1);
+			match events[0] {
+				Event::ChannelPending { ref counterparty_custom_tlvs, .. } =>
+					assert_eq!(counterparty_custom_tlvs, expected_tlvs),
+				_ => panic!("Unexpected event"),
+			}
+		}
+	}
+
+	/// Opens an announced channel from `nodes[0]` to `nodes[1]` without anchor outputs, despite
+	/// `upgrade_config` negotiating them, returning its id.
+	fn open_channel_without_anchors(nodes: &Vec<Node>, upgrade_config: &UserConfig) -> [u8; 32] {
+		let node_a_id = nodes[0].node.get_our_node_id();
+		let node_b_id = nodes[1].node.get_our_node_id();
+		let mut no_anchors_config = upgrade_config.clone();
+		no_anchors_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = false;
+		nodes[0].node.create_channel(node_b_id, 100_000, 0, 42, Some(no_anchors_config)).unwrap();
+		let open_channel_msg = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, node_b_id);
+		assert!(!open_channel_msg.channel_type.as_ref().unwrap().supports_anchors_zero_fee_htlc_tx());
+
+		nodes[1].node.handle_open_channel(&node_a_id, &open_channel_msg);
+		let events = nodes[1].node.get_and_clear_pending_events();
+		match events[0] {
+			Event::OpenChannelRequest { temporary_channel_id, .. } =>
+				nodes[1].node.accept_inbound_channel(&temporary_channel_id, &node_a_id, 42).unwrap(),
+			_ => panic!("Unexpected event"),
+		}
+		let accept_channel_msg = get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, node_a_id);
+		nodes[0].node.handle_accept_channel(&node_b_id, &accept_channel_msg);
+		let tx = sign_funding_transaction(&nodes[0], &nodes[1], 100_000, open_channel_msg.temporary_channel_id);
+		let (channel_ready, channel_id) = create_chan_between_nodes_with_value_confirm(&nodes[0], &nodes[1], &tx);
+		let (announcement, as_update, bs_update) = create_chan_between_nodes_with_value_b(&nodes[0], &nodes[1], &channel_ready);
+		update_nodes_with_chan_announce(&nodes, 0, 1, &announcement, &as_update, &bs_update);
+		assert!(!nodes[0].node.list_channels()[0].channel_type.as_ref().unwrap().supports_anchors_zero_fee_htlc_tx());
+		channel_id
+	}
+
+	/// Reconnects `nodes[0]` and `nodes[1]`, which must both want to change the type of the single
+	/// channel between them, and runs through the resulting upgrade.
+	fn reconnect_and_upgrade_channel_type(nodes: &Vec<Node>) {
+		let node_a_id = nodes[0].node.get_our_node_id();
+		let node_b_id = nodes[1].node.get_our_node_id();
+		nodes[0].node.peer_disconnected(&node_b_id);
+		nodes[1].node.peer_disconnected(&node_a_id);
+		nodes[0].node.peer_connected(&node_b_id, &msgs::Init {
+			features: nodes[1].node.init_features(), networks: None, remote_network_address: None
+		}, true).unwrap();
+		let as_reestablish = get_chan_reestablish_msgs!(nodes[0], nodes[1]);
+		nodes[1].node.peer_connected(&node_a_id, &msgs::Init {
+			features: nodes[0].node.init_features(), networks: None, remote_network_address: None
+		}, false).unwrap();
+		let bs_reestablish = get_chan_reestablish_msgs!(nodes[1], nodes[0]);
+		assert!(as_reestablish[0].desired_channel_type.is_some());
+		assert_eq!(as_reestablish[0].desired_channel_type, bs_reestablish[0].desired_channel_type);
+
+		// Each side upgrades upon receiving the other's channel_reestablish and sends a new
+		// commitment_signed once its monitor has been updated.
+		let get_commitment_signed = |events: Vec<MessageSendEvent>| {
+			let mut commitment_signed = None;
+			for event in events {
+				match event {
+					MessageSendEvent::UpdateHTLCs { updates, .. } => {
+						assert!(updates.update_add_htlcs.is_empty());
+						commitment_signed = Some(updates.commitment_signed);
+					},
+					MessageSendEvent::SendChannelUpdate { .. } => {},
+					_ => panic!("Unexpected event {:?}", event),
+				}
+			}
+			commitment_signed.unwrap()
+		};
+		nodes[1].node.handle_channel_reestablish(&node_a_id, &as_reestablish[0]);
+		check_added_monitors!(nodes[1], 1);
+		let bs_commitment_signed = get_commitment_signed(nodes[1].node.get_and_clear_pending_msg_events());
+		nodes[0].node.handle_channel_reestablish(&node_b_id, &bs_reestablish[0]);
+		check_added_monitors!(nodes[0], 1);
+		let as_commitment_signed = get_commitment_signed(nodes[0].node.get_and_clear_pending_msg_events());
+
+		nodes[0].node.handle_commitment_signed(&node_b_id, &bs_commitment_signed);
+		check_added_monitors!(nodes[0], 1);
+		let as_raa = get_event_msg!(nodes[0], MessageSendEvent::SendRevokeAndACK, node_b_id);
+		nodes[1].node.handle_commitment_signed(&node_a_id, &as_commitment_signed);
+		check_added_monitors!(nodes[1], 1);
+		let bs_raa = get_event_msg!(nodes[1], MessageSendEvent::SendRevokeAndACK, node_a_id);
+		nodes[0].node.handle_revoke_and_ack(&node_b_id, &bs_raa);
+		check_added_monitors!(nodes[0], 1);
+		nodes[1].node.handle_revoke_and_ack(&node_a_id, &as_raa);
+		check_added_monitors!(nodes[1], 1);
+		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	}
+
+	#[test]
+	fn test_channel_type_upgrade_on_reestablish() {
+		// Tests that a channel opened without anchor outputs is moved over to anchors on reconnect
+		// once both sides want it, with both sides re-signing the current state using the new type.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut upgrade_config = test_default_channel_config();
+		upgrade_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
+		upgrade_config.channel_handshake_config.negotiate_channel_type_upgrade = true;
+		upgrade_config.manually_accept_inbound_channels = true;
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(upgrade_config.clone()), Some(upgrade_config.clone())]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let node_a_id = nodes[0].node.get_our_node_id();
+		let node_b_id = nodes[1].node.get_our_node_id();
+
+		let channel_id = open_channel_without_anchors(&nodes, &upgrade_config);
+
+		reconnect_and_upgrade_channel_type(&nodes);
+
+		for node in nodes.iter() {
+			assert!(node.node.list_channels()[0].channel_type.as_ref().unwrap().supports_anchors_zero_fee_htlc_tx());
+			let commitment_tx = get_local_commitment_txn!(node, channel_id);
+			assert_eq!(commitment_tx[0].output.iter()
+				.filter(|output| output.value == crate::ln::channel::ANCHOR_OUTPUT_VALUE_SATOSHI).count(), 2);
+		}
+
+		// The upgraded channel can be used as normal, and isn't upgraded again on reconnect.
+		send_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+		nodes[0].node.peer_disconnected(&node_b_id);
+		nodes[1].node.peer_disconnected(&node_a_id);
+		reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+		send_payment(&nodes[1], &[&nodes[0]], 500_000);
+	}
+
+	#[test]
+	fn test_channel_type_downgrade_only_on_request() {
+		// Tests that a channel with anchor outputs is never moved off them just because the
+		// counterparty stops signaling support, but is once the user explicitly asks for it.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut upgrade_config = test_default_channel_config();
+		upgrade_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
+		upgrade_config.channel_handshake_config.negotiate_channel_type_upgrade = true;
+		upgrade_config.manually_accept_inbound_channels = true;
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(upgrade_config.clone()), Some(upgrade_config.clone())]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let node_a_id = nodes[0].node.get_our_node_id();
+		let node_b_id = nodes[1].node.get_our_node_id();
+
+		let channel_id = open_channel_without_anchors(&nodes, &upgrade_config);
+		match nodes[0].node.request_channel_type_downgrade(&channel_id, &node_b_id) {
+			Err(APIError::APIMisuseError { .. }) => {},
+			res => panic!("Unexpected result {:?}", res),
+		}
+		reconnect_and_upgrade_channel_type(&nodes);
+
+		// Reconnecting to a counterparty which no longer signals anchor outputs leaves the type as is.
+		nodes[0].node.peer_disconnected(&node_b_id);
+		nodes[1].node.peer_disconnected(&node_a_id);
+		let mut bs_features = nodes[1].node.init_features();
+		bs_features.clear_anchors_zero_fee_htlc_tx();
+		nodes[0].node.peer_connected(&node_b_id, &msgs::Init {
+			features: bs_features, networks: None, remote_network_address: None
+		}, true).unwrap();
+		let as_reestablish = get_chan_reestablish_msgs!(nodes[0], nodes[1]);
+		assert!(as_reestablish[0].desired_channel_type.is_none());
+		nodes[0].node.peer_disconnected(&node_b_id);
+		reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+		assert!(nodes[0].node.list_channels()[0].channel_type.as_ref().unwrap().supports_anchors_zero_fee_htlc_tx());
+
+		// Once both sides ask for it, the channel is downgraded on the next reconnect.
+		nodes[0].node.request_channel_type_downgrade(&channel_id, &node_b_id).unwrap();
+		nodes[1].node.request_channel_type_downgrade(&channel_id, &node_a_id).unwrap();
+		reconnect_and_upgrade_channel_type(&nodes);
+		for node in nodes.iter() {
+			assert!(!node.node.list_channels()[0].channel_type.as_ref().unwrap().supports_anchors_zero_fee_htlc_tx());
+		}
+		send_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+	}
+
+	#[test]
+	fn test_channel_type_upgrade_breach() {
+		// Tests that a commitment transaction revoked before the channel type was upgraded is
+		// punished using the scripts it was built with rather than those of the new type.
+		let mut chanmon_cfgs = create_chanmon_cfgs(2);
+		chanmon_cfgs[1].keys_manager.disable_revocation_policy_check = true;
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut upgrade_config = test_default_channel_config();
+		upgrade_config.channel_handshake_config.negotiate_anchors_zero_fee_htlc_tx = true;
+		upgrade_config.channel_handshake_config.negotiate_channel_type_upgrade = true;
+		upgrade_config.manually_accept_inbound_channels = true;
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(upgrade_config.clone()), Some(upgrade_config.clone())]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+		let channel_id = open_channel_without_anchors(&nodes, &upgrade_config);
+
+		// Give nodes[1] a balance and a pending HTLC, then grab its commitment transaction, which
+		// is revoked once the HTLC is claimed.
+		send_payment(&nodes[0], &[&nodes[1]], 10_000_000);
+		let payment_preimage = route_payment(&nodes[0], &[&nodes[1]], 3_000_000).0;
+		let revoked_txn = get_local_commitment_txn!(nodes[1], channel_id);
+		claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+
+		reconnect_and_upgrade_channel_type(&nodes);
+		assert!(nodes[0].node.list_channels()[0].channel_type.as_ref().unwrap().supports_anchors_zero_fee_htlc_tx());
+
+		mine_transaction(&nodes[0], &revoked_txn[0]);
+		check_added_monitors!(nodes[0], 1);
+		check_closed_broadcast!(nodes[0], true);
+		check_closed_event!(nodes[0], 1, ClosureReason::CommitmentTxConfirmed);
+
+		// Both nodes[1]'s balance and the HTLC are claimed, with each justice transaction spending
+		// the pre-upgrade scripts of the revoked commitment.
+		let node_txn = nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().split_off(0);
+		let justice_txn: Vec<_> = node_txn.iter()
+			.filter(|tx| tx.input.iter().all(|input| input.previous_output.txid == revoked_txn[0].txid()))
+			.collect();
+		assert!(!justice_txn.is_empty());
+		let mut claimed_outputs = Vec::new();
+		for justice_tx in justice_txn {
+			check_spends!(justice_tx, revoked_txn[0]);
+			claimed_outputs.extend(justice_tx.input.iter().map(|input| input.previous_output.vout));
+		}
+		claimed_outputs.sort_unstable();
+		claimed_outputs.dedup();
+		assert_eq!(claimed_outputs.len(), 2);
+	}
+
 	#[test]
 	fn test_update_channel_config() {
 		let chanmon_cfg = create_chanmon_cfgs(2);
@@ -10258,6 +13021,391 @@ mod tests {
 		let events = nodes[0].node.get_and_clear_pending_msg_events();
 		assert_eq!(events.len(), 0);
 	}
+
+	#[test]
+	fn test_update_channel_htlc_limits() {
+		let chanmon_cfgs = create_chanmon_cfgs(3);
+		let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+		let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+		create_announced_chan_between_nodes(&nodes, 0, 1);
+		let chan_1_2 = create_announced_chan_between_nodes(&nodes, 1, 2);
+		let chan_1_2_scid = chan_1_2.0.contents.short_channel_id;
+
+		nodes[1].node.update_channel_htlc_limits(&nodes[2].node.get_our_node_id(), &[chan_1_2.2], Some(50_000), Some(5_000_000)).unwrap();
+		let events = nodes[1].node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		match &events[0] {
+			MessageSendEvent::BroadcastChannelUpdate { msg } => {
+				assert_eq!(msg.contents.short_channel_id, chan_1_2_scid);
+				assert_eq!(msg.contents.htlc_minimum_msat, 50_000);
+				assert_eq!(msg.contents.htlc_maximum_msat, 5_000_000);
+			},
+			_ => panic!("expected BroadcastChannelUpdate event"),
+		}
+
+		// Re-applying the same limits doesn't result in a new channel_update.
+		nodes[1].node.update_channel_htlc_limits(&nodes[2].node.get_our_node_id(), &[chan_1_2.2], Some(50_000), Some(5_000_000)).unwrap();
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+		// A minimum above the maximum is rejected.
+		assert!(matches!(
+			nodes[1].node.update_channel_htlc_limits(&nodes[2].node.get_our_node_id(), &[chan_1_2.2], Some(6_000_000), Some(5_000_000)),
+			Err(APIError::APIMisuseError { .. })
+		));
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+		// nodes[0] hasn't seen the new channel_update yet, so it will try to route a payment below
+		// the new minimum, which nodes[1] fails back.
+		let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[2], 10_000);
+		nodes[0].node.send_payment_with_route(&route, payment_hash,
+			RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+		check_added_monitors!(nodes[0], 1);
+
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, true, true);
+
+		let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+		nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+		commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+		expect_payment_failed_conditions(&nodes[0], payment_hash, false,
+			PaymentFailedConditions::new().blamed_scid(chan_1_2_scid).blamed_chan_closed(false));
+	}
+
+	#[test]
+	fn test_idle_channel_detection_and_close() {
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut idle_config = test_default_channel_config();
+		idle_config.idle_channel_config = IdleChannelConfig {
+			idle_timer_ticks_threshold: Some(2),
+			action: IdleChannelAction::CooperativeClose,
+			max_close_feerate_sat_per_1000_weight: 253,
+		};
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(idle_config)]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+		let node_0_id = nodes[0].node.get_our_node_id();
+
+		// Feerates are too high to close idle channels, so we'll only be alerted.
+		*chanmon_cfgs[1].fee_estimator.sat_per_kw.lock().unwrap() = 1000;
+		nodes[1].node.timer_tick_occurred();
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+		// Forwarding a payment over the channel resets its idle counter.
+		send_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+		nodes[1].node.timer_tick_occurred();
+		nodes[1].node.timer_tick_occurred();
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+
+		nodes[1].node.timer_tick_occurred();
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::ChannelIdle { channel_id, counterparty_node_id, idle_timer_ticks, .. } => {
+				assert_eq!(channel_id, chan_id);
+				assert_eq!(counterparty_node_id, node_0_id);
+				assert_eq!(idle_timer_ticks, 2);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+		// Channels with allow-listed counterparties are not closed, even once feerates are low.
+		*chanmon_cfgs[1].fee_estimator.sat_per_kw.lock().unwrap() = 253;
+		nodes[1].node.set_idle_close_allowlisted(&node_0_id, true);
+		nodes[1].node.timer_tick_occurred();
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+		nodes[1].node.set_idle_close_allowlisted(&node_0_id, false);
+		nodes[1].node.timer_tick_occurred();
+		let node_1_shutdown = get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, node_0_id);
+		assert_eq!(node_1_shutdown.channel_id, chan_id);
+		expect_channel_shutdown_state!(nodes[1], chan_id, ChannelShutdownState::ShutdownInitiated);
+
+		// We don't try to close the channel again while it is already shutting down.
+		nodes[1].node.timer_tick_occurred();
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+	}
+
+	#[test]
+	fn test_fee_spike_circuit_breaker() {
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut breaker_config = test_default_channel_config();
+		breaker_config.fee_spike_circuit_breaker_config = FeeSpikeCircuitBreakerConfig {
+			engage_feerate_sat_per_1000_weight: Some(2000),
+			release_feerate_sat_per_1000_weight: Some(1000),
+			..Default::default()
+		};
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(breaker_config)]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let (chan_update, _, chan_id, _) = create_announced_chan_between_nodes(&nodes, 0, 1);
+		let node_0_id = nodes[0].node.get_our_node_id();
+		let node_1_id = nodes[1].node.get_our_node_id();
+
+		*chanmon_cfgs[1].fee_estimator.sat_per_kw.lock().unwrap() = 2500;
+		nodes[1].node.timer_tick_occurred();
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::FeeSpikeCircuitBreakerEngaged { feerate_sat_per_1000_weight } =>
+				assert_eq!(feerate_sat_per_1000_weight, 2500),
+			_ => panic!("Unexpected event"),
+		}
+		assert!(nodes[1].node.fee_spike_circuit_breaker_engaged());
+
+		// New HTLCs may only use half of our maximum dust exposure.
+		{
+			let per_peer_state = nodes[1].node.per_peer_state.read().unwrap();
+			let chan_lock = per_peer_state.get(&node_0_id).unwrap().lock().unwrap();
+			let chan = chan_lock.channel_by_id.get(&chan_id).unwrap();
+			let fee_estimator = LowerBoundedFeeEstimator::new(nodes[1].fee_estimator);
+			assert_eq!(chan.context.get_max_new_dust_htlc_exposure_msat(&fee_estimator),
+				chan.context.get_max_dust_htlc_exposure_msat(&fee_estimator) * 50 / 100);
+		}
+
+		// Small HTLCs are failed back, while larger ones are still accepted.
+		let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[1], 500_000);
+		nodes[0].node.send_payment_with_route(&route, payment_hash,
+			RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&node_0_id, &payment_event.msgs[0]);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+		let updates = get_htlc_update_msgs!(nodes[1], node_0_id);
+		assert_eq!(updates.update_fail_htlcs.len(), 1);
+		nodes[0].node.handle_update_fail_htlc(&node_1_id, &updates.update_fail_htlcs[0]);
+		commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+		expect_payment_failed_conditions(&nodes[0], payment_hash, false, PaymentFailedConditions::new()
+			.blamed_scid(chan_update.contents.short_channel_id).blamed_chan_closed(false));
+
+		send_payment(&nodes[0], &[&nodes[1]], 2_000_000);
+
+		// Cooperative closes are deferred until the breaker is released, which only happens once
+		// feerates drop below the release threshold.
+		nodes[1].node.close_channel(&chan_id, &node_0_id).unwrap();
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+
+		*chanmon_cfgs[1].fee_estimator.sat_per_kw.lock().unwrap() = 1500;
+		nodes[1].node.timer_tick_occurred();
+		assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+		assert!(nodes[1].node.get_and_clear_pending_msg_events().is_empty());
+		assert!(nodes[1].node.fee_spike_circuit_breaker_engaged());
+
+		*chanmon_cfgs[1].fee_estimator.sat_per_kw.lock().unwrap() = 900;
+		nodes[1].node.timer_tick_occurred();
+		let events = nodes[1].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::FeeSpikeCircuitBreakerReleased { feerate_sat_per_1000_weight } =>
+				assert_eq!(feerate_sat_per_1000_weight, 900),
+			_ => panic!("Unexpected event"),
+		}
+		assert!(!nodes[1].node.fee_spike_circuit_breaker_engaged());
+		let node_1_shutdown = get_event_msg!(nodes[1], MessageSendEvent::SendShutdown, node_0_id);
+		assert_eq!(node_1_shutdown.channel_id, chan_id);
+		expect_channel_shutdown_state!(nodes[1], chan_id, ChannelShutdownState::ShutdownInitiated);
+	}
+
+	#[test]
+	fn test_forwarding_parameter_harmonization() {
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut harmonizing_config = test_default_channel_config();
+		harmonizing_config.channel_config.forwarding_fee_proportional_millionths = 1000;
+		harmonizing_config.forwarding_harmonization_config = ForwardingHarmonizationConfig {
+			min_cltv_expiry_delta_percent: Some(50),
+			max_fee_proportional_millionths_multiple: Some(2),
+			max_cltv_expiry_delta: 60,
+			min_fee_proportional_millionths: 300,
+			auto_harmonize: true,
+		};
+		// Our counterparty's parameters would have us use a delta of 72 and fee of 200, but we clamp
+		// to the ceiling and floor configured above.
+		let mut counterparty_config = test_default_channel_config();
+		counterparty_config.channel_config.cltv_expiry_delta = 144;
+		counterparty_config.channel_config.forwarding_fee_proportional_millionths = 100;
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(harmonizing_config), Some(counterparty_config)]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+		let node_1_id = nodes[1].node.get_our_node_id();
+
+		let asymmetries = nodes[0].node.list_forwarding_asymmetries();
+		assert_eq!(asymmetries, vec![
+			ForwardingAsymmetry::CltvExpiryDeltaTooLow {
+				channel_id: chan_id, counterparty_node_id: node_1_id, ours: MIN_CLTV_EXPIRY_DELTA, theirs: 144, suggested: 60,
+			},
+			ForwardingAsymmetry::ProportionalFeeTooHigh {
+				channel_id: chan_id, counterparty_node_id: node_1_id, ours: 1000, theirs: 100, suggested: 300,
+			},
+		]);
+		assert!(nodes[1].node.list_forwarding_asymmetries().is_empty());
+
+		nodes[0].node.timer_tick_occurred();
+		let msg_events = nodes[0].node.get_and_clear_pending_msg_events();
+		assert_eq!(msg_events.len(), 1);
+		match msg_events[0] {
+			MessageSendEvent::BroadcastChannelUpdate { ref msg } => {
+				assert_eq!(msg.contents.cltv_expiry_delta, 60);
+				assert_eq!(msg.contents.fee_proportional_millionths, 300);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		let config = nodes[0].node.list_channels()[0].config.unwrap();
+		assert_eq!(config.cltv_expiry_delta, 60);
+		assert_eq!(config.forwarding_fee_proportional_millionths, 300);
+		assert!(nodes[0].node.list_forwarding_asymmetries().is_empty());
+
+		// Once harmonized, further timer ticks leave the channel alone.
+		nodes[0].node.timer_tick_occurred();
+		assert!(nodes[0].node.get_and_clear_pending_msg_events().is_empty());
+	}
+
+	#[test]
+	fn test_unresponsive_peer_force_close() {
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut config = test_default_channel_config();
+		config.unresponsive_peer_config = UnresponsivePeerConfig {
+			unresponsive_timer_ticks_threshold: Some(2),
+			force_close_margin_blocks: 20,
+			warning_margin_blocks: 10,
+		};
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+		let node_1_id = nodes[1].node.get_our_node_id();
+
+		route_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+		let earliest_htlc_expiry = nodes[0].node.list_in_flight_htlcs()[0].cltv_expiry;
+		let force_close_height = earliest_htlc_expiry - 20;
+		connect_blocks(&nodes[0], force_close_height - 10 - nodes[0].best_block_info().1);
+
+		// Pending HTLCs are fine as long as our counterparty is responsive.
+		nodes[0].node.timer_tick_occurred();
+		nodes[0].node.timer_tick_occurred();
+		nodes[0].node.timer_tick_occurred();
+		assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+
+		nodes[0].node.peer_disconnected(&node_1_id);
+		nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id());
+		nodes[0].node.timer_tick_occurred();
+		assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+		nodes[0].node.timer_tick_occurred();
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 1);
+		match events[0] {
+			Event::ChannelUnresponsive { channel_id, counterparty_node_id, earliest_htlc_expiry: expiry, force_close_height: height, .. } => {
+				assert_eq!(channel_id, chan_id);
+				assert_eq!(counterparty_node_id, node_1_id);
+				assert_eq!(expiry, earliest_htlc_expiry);
+				assert_eq!(height, force_close_height);
+			},
+			_ => panic!("Unexpected event"),
+		}
+
+		// We only warn once, and only force-close once the chain reaches the margin.
+		connect_blocks(&nodes[0], 9);
+		nodes[0].node.timer_tick_occurred();
+		assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+		assert_eq!(nodes[0].node.list_channels().len(), 1);
+		nodes[0].node.get_and_clear_pending_msg_events();
+		nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().clear();
+
+		connect_blocks(&nodes[0], 1);
+		nodes[0].node.timer_tick_occurred();
+		check_added_monitors!(nodes[0], 1);
+		check_closed_broadcast!(nodes[0], true);
+		check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: format!(
+			"Force-closing channel as our counterparty has been unresponsive for 4 timer ticks with HTLCs expiring at height {}",
+			earliest_htlc_expiry) });
+		assert!(nodes[0].node.list_channels().is_empty());
+		assert!(!nodes[0].tx_broadcaster.txn_broadcasted.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn test_unresponsive_peer_warned_before_late_force_close() {
+		// If our counterparty only becomes unresponsive once we're past the warning window, we still
+		// warn right before force-closing.
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let mut config = test_default_channel_config();
+		config.unresponsive_peer_config = UnresponsivePeerConfig {
+			unresponsive_timer_ticks_threshold: Some(2),
+			force_close_margin_blocks: 20,
+			warning_margin_blocks: 10,
+		};
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+		route_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+		let earliest_htlc_expiry = nodes[0].node.list_in_flight_htlcs()[0].cltv_expiry;
+		connect_blocks(&nodes[0], earliest_htlc_expiry - 20 - nodes[0].best_block_info().1);
+
+		nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+		nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id());
+		nodes[0].node.timer_tick_occurred();
+		assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+		nodes[0].node.timer_tick_occurred();
+		check_added_monitors!(nodes[0], 1);
+		check_closed_broadcast!(nodes[0], true);
+		let events = nodes[0].node.get_and_clear_pending_events();
+		assert_eq!(events.len(), 2);
+		match events[0] {
+			Event::ChannelUnresponsive { channel_id, force_close_height, .. } => {
+				assert_eq!(channel_id, chan_id);
+				assert_eq!(force_close_height, earliest_htlc_expiry - 20);
+			},
+			_ => panic!("Unexpected event"),
+		}
+		match events[1] {
+			Event::ChannelClosed { channel_id, .. } => assert_eq!(channel_id, chan_id),
+			_ => panic!("Unexpected event"),
+		}
+	}
+
+	#[test]
+	fn test_send_payment_over_channel() {
+		let chanmon_cfgs = create_chanmon_cfgs(3);
+		let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+		let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+		let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+		let other_chan_id = create_announced_chan_between_nodes(&nodes, 0, 2).2;
+		let node_1_id = nodes[1].node.get_our_node_id();
+
+		// The channel must be with the given counterparty.
+		match nodes[0].node.send_payment_over_channel(&node_1_id, &other_chan_id, 10_000, None, PaymentId([1; 32])) {
+			Err(APIError::ChannelUnavailable { .. }) => {},
+			res => panic!("Unexpected result {:?}", res),
+		}
+
+		let payment_id = PaymentId([42; 32]);
+		let payment_hash = nodes[0].node.send_payment_over_channel(&node_1_id, &chan_id, 10_000,
+			Some(vec![42; 32]), payment_id).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		match nodes[0].node.send_payment_over_channel(&node_1_id, &chan_id, 10_000, None, payment_id) {
+			Err(APIError::APIMisuseError { .. }) => {},
+			res => panic!("Unexpected result {:?}", res),
+		}
+
+		let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+		assert_eq!(events.len(), 1);
+		do_pass_along_path(&nodes[0], &[&nodes[1]], 10_000, payment_hash, None, events.pop().unwrap(), true, false, None);
+		let payment_preimage = match &nodes[1].node.get_and_clear_pending_events()[..] {
+			[Event::PaymentClaimable { purpose: PaymentPurpose::SpontaneousPayment(preimage), onion_fields, amount_msat, .. }] => {
+				assert_eq!(*amount_msat, 10_000);
+				assert_eq!(onion_fields.as_ref().unwrap().payment_metadata, Some(vec![42; 32]));
+				*preimage
+			},
+			events => panic!("Unexpected events {:?}", events),
+		};
+		claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+	}
 }
 
 #[cfg(ldk_bench)]
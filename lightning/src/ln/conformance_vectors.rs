@@ -0,0 +1,537 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Generation of protocol test vectors from caller-supplied seeds.
+//!
+//! Each generator deterministically derives all keys and randomness it needs from a 32-byte seed
+//! and emits a [`TestVector`] containing both its inputs and LDK's outputs, such that downstream
+//! test suites can feed the same inputs to another implementation and compare the results. This
+//! covers onion packets (BOLT 4), blinded paths (BOLT 4 route blinding), commitment transactions
+//! (BOLT 3) and adaptor-signed contract execution transactions (the DLC specification).
+//!
+//! Vectors can be rendered in the `key: value` line format used by BOLT 3 via
+//! [`TestVector::to_bolt3_format`] or as a flat JSON object as used by BOLT 4 and the DLC
+//! specification via [`TestVector::to_json`].
+//!
+//! This module is only available with the `conformance_vectors` feature.
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{EcdsaSighashType, Transaction};
+use bitcoin::consensus::encode;
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::hash_types::{Txid, WPubkeyHash};
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+use bitcoin::util::sighash::SighashCache;
+
+use crate::blinded_path::BlindedPath;
+use crate::chain::transaction::OutPoint;
+use crate::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
+use crate::ln::chan_utils::{self, ChannelPublicKeys, ChannelTransactionParameters, CommitmentTransaction, CounterpartyChannelTransactionParameters, HolderCommitmentTransaction, HTLCOutputInCommitment, TxCreationKeys};
+use crate::ln::channel::{ANCHOR_OUTPUT_VALUE_SATOSHI, commit_tx_fee_sat};
+use crate::ln::channelmanager::RecipientOnionFields;
+use crate::ln::contracts::{CollateralOutput, SettlementBranch, SettlementBundle};
+use crate::ln::features::{ChannelFeatures, ChannelTypeFeatures, NodeFeatures};
+use crate::ln::onion_utils;
+use crate::routing::router::{Path, RouteHop};
+use crate::sign::EntropySource;
+use crate::util::ser::Writeable;
+
+use crate::prelude::*;
+
+/// A single named test vector.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestVector {
+	/// A human-readable description of what the vector covers.
+	pub name: String,
+	/// The inputs and outputs of the vector, in the order they appear in the relevant
+	/// specification. Binary values are hex-encoded, numbers are written in decimal.
+	pub fields: Vec<(String, String)>,
+}
+
+impl TestVector {
+	fn new(name: String) -> Self {
+		Self { name, fields: Vec::new() }
+	}
+
+	fn push<V: ToString>(&mut self, key: &str, value: V) {
+		self.fields.push((key.to_owned(), value.to_string()));
+	}
+
+	/// Gets the value of the field with the given key, if any.
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.fields.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+	}
+
+	/// Renders this vector in the line format used by BOLT 3 Appendix C.
+	///
+	/// Fields are written as `key: value`, except for signatures which are written as
+	/// `key = value`. As in BOLT 3, the local party's signatures are commented out with a leading
+	/// `# ` as they are derived from the other fields rather than being inputs.
+	pub fn to_bolt3_format(&self) -> String {
+		let mut res = format!("name: {}\n", self.name);
+		for (key, value) in self.fields.iter() {
+			if key.ends_with("_signature") {
+				let comment = if key.starts_with("local_") { "# " } else { "" };
+				res.push_str(&format!("{}{} = {}\n", comment, key, value));
+			} else {
+				res.push_str(&format!("{}: {}\n", key, value));
+			}
+		}
+		res
+	}
+
+	/// Renders this vector as a flat JSON object, as used by BOLT 4 and the DLC specification.
+	pub fn to_json(&self) -> String {
+		let mut res = format!("{{\"name\":\"{}\"", json_escape(&self.name));
+		for (key, value) in self.fields.iter() {
+			res.push_str(&format!(",\"{}\":\"{}\"", json_escape(key), json_escape(value)));
+		}
+		res.push('}');
+		res
+	}
+}
+
+fn json_escape(s: &str) -> String {
+	let mut res = String::with_capacity(s.len());
+	for c in s.chars() {
+		match c {
+			'"' => res.push_str("\\\""),
+			'\\' => res.push_str("\\\\"),
+			'\n' => res.push_str("\\n"),
+			c if (c as u32) < 0x20 => res.push_str(&format!("\\u{:04x}", c as u32)),
+			c => res.push(c),
+		}
+	}
+	res
+}
+
+/// Derives 32 bytes for the given purpose and index from `seed`.
+fn derive_bytes(seed: &[u8; 32], label: &str, idx: u32) -> [u8; 32] {
+	let mut engine = Sha256::engine();
+	engine.input(seed);
+	engine.input(label.as_bytes());
+	engine.input(&idx.to_be_bytes());
+	Sha256::from_engine(engine).into_inner()
+}
+
+/// Derives a secret key for the given purpose and index from `seed`.
+fn derive_secret(seed: &[u8; 32], label: &str, idx: u32) -> SecretKey {
+	// A hash output is an invalid secret key only with negligible probability.
+	SecretKey::from_slice(&derive_bytes(seed, label, idx)).expect("Hash output is a valid secret key")
+}
+
+/// An [`EntropySource`] which deterministically derives its output from a seed.
+struct SeededEntropySource {
+	seed: [u8; 32],
+	counter: crate::util::atomic_counter::AtomicCounter,
+}
+
+impl EntropySource for SeededEntropySource {
+	fn get_secure_random_bytes(&self) -> [u8; 32] {
+		derive_bytes(&self.seed, "entropy", self.counter.get_increment() as u32)
+	}
+}
+
+fn serialize_hex<W: Writeable>(obj: &W) -> String {
+	obj.encode().to_hex()
+}
+
+/// Generates a BOLT 4 payment onion for a path of `hop_count` hops delivering `amount_msat` to
+/// the last hop, with the final CLTV expiry set to `final_cltv_expiry`.
+///
+/// Intermediate hops charge a fee of 1000 msat and a CLTV delta of 40 blocks each.
+pub fn onion_packet(seed: &[u8; 32], hop_count: usize, amount_msat: u64, final_cltv_expiry: u32) -> Result<TestVector, ()> {
+	if hop_count == 0 || hop_count > 20 { return Err(()); }
+	let secp_ctx = Secp256k1::new();
+
+	let session_priv = derive_secret(seed, "session_key", 0);
+	let prng_seed = derive_bytes(seed, "prng_seed", 0);
+	let payment_hash = PaymentHash(derive_bytes(seed, "payment_hash", 0));
+	let payment_secret = PaymentSecret(derive_bytes(seed, "payment_secret", 0));
+
+	let hops = (0..hop_count).map(|idx| {
+		let is_last = idx == hop_count - 1;
+		RouteHop {
+			pubkey: PublicKey::from_secret_key(&secp_ctx, &derive_secret(seed, "hop", idx as u32)),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: idx as u64 + 1,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: if is_last { amount_msat } else { 1000 },
+			cltv_expiry_delta: if is_last { 0 } else { 40 },
+		}
+	}).collect();
+	let path = Path { hops, blinded_tail: None };
+
+	let (payloads, _, _) = onion_utils::build_onion_payloads(&path, amount_msat,
+		RecipientOnionFields::secret_only(payment_secret), final_cltv_expiry, &None).map_err(|_| ())?;
+	let onion_keys = onion_utils::construct_onion_keys(&secp_ctx, &path, &session_priv).map_err(|_| ())?;
+	let onion = onion_utils::construct_onion_packet(payloads.clone(), onion_keys, prng_seed, &payment_hash)?;
+
+	let mut vector = TestVector::new(format!("payment onion with {} hops", hop_count));
+	vector.push("session_key", session_priv.secret_bytes().to_hex());
+	vector.push("associated_data", payment_hash.0.to_hex());
+	vector.push("prng_seed", prng_seed.to_hex());
+	for (idx, (hop, payload)) in path.hops.iter().zip(payloads.iter()).enumerate() {
+		vector.push(&format!("hop_{}_pubkey", idx), hop.pubkey.serialize().to_hex());
+		vector.push(&format!("hop_{}_payload", idx), serialize_hex(payload));
+	}
+	vector.push("onion", serialize_hex(&onion));
+	Ok(vector)
+}
+
+/// Generates a blinded path for onion messages through `hop_count` hops, the last of which is the
+/// recipient.
+pub fn blinded_path(seed: &[u8; 32], hop_count: usize) -> Result<TestVector, ()> {
+	let secp_ctx = Secp256k1::new();
+	let node_pks = (0..hop_count)
+		.map(|idx| PublicKey::from_secret_key(&secp_ctx, &derive_secret(seed, "hop", idx as u32)))
+		.collect::<Vec<_>>();
+	let entropy_source = SeededEntropySource {
+		seed: *seed, counter: crate::util::atomic_counter::AtomicCounter::new(),
+	};
+	let path = BlindedPath::new_for_message(&node_pks, &entropy_source, &secp_ctx)?;
+
+	let mut vector = TestVector::new(format!("blinded message path with {} hops", hop_count));
+	vector.push("session_key", derive_bytes(seed, "entropy", 0).to_hex());
+	for (idx, node_pk) in node_pks.iter().enumerate() {
+		vector.push(&format!("hop_{}_node_id", idx), node_pk.serialize().to_hex());
+	}
+	vector.push("introduction_node_id", path.introduction_node_id.serialize().to_hex());
+	vector.push("blinding_point", path.blinding_point.serialize().to_hex());
+	for (idx, hop) in path.blinded_hops.iter().enumerate() {
+		vector.push(&format!("hop_{}_blinded_node_id", idx), hop.blinded_node_id.serialize().to_hex());
+		vector.push(&format!("hop_{}_encrypted_data", idx), hop.encrypted_payload.to_hex());
+	}
+	Ok(vector)
+}
+
+fn derive_channel_keys(seed: &[u8; 32], side: &str, secp_ctx: &Secp256k1<bitcoin::secp256k1::All>) -> (SecretKey, SecretKey, ChannelPublicKeys) {
+	let point = |purpose: &str| {
+		PublicKey::from_secret_key(secp_ctx, &derive_secret(seed, &format!("{}_{}", side, purpose), 0))
+	};
+	let funding_key = derive_secret(seed, &format!("{}_funding", side), 0);
+	let htlc_base_key = derive_secret(seed, &format!("{}_htlc_basepoint", side), 0);
+	let pubkeys = ChannelPublicKeys {
+		funding_pubkey: PublicKey::from_secret_key(secp_ctx, &funding_key),
+		revocation_basepoint: point("revocation_basepoint"),
+		payment_point: point("payment_basepoint"),
+		delayed_payment_basepoint: point("delayed_payment_basepoint"),
+		htlc_basepoint: PublicKey::from_secret_key(secp_ctx, &htlc_base_key),
+	};
+	(funding_key, htlc_base_key, pubkeys)
+}
+
+/// An HTLC to be included in a commitment transaction vector, see [`commitment_transaction`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VectorHtlc {
+	/// Whether the HTLC was offered by the local (broadcasting) party.
+	pub offered: bool,
+	/// The value of the HTLC, in millisatoshis.
+	pub amount_msat: u64,
+	/// The CLTV expiry of the HTLC.
+	pub cltv_expiry: u32,
+}
+
+/// The `to_self_delay` the remote party imposes on the local party's outputs, as in BOLT 3.
+const LOCAL_DELAY: u16 = 144;
+/// The local party's dust limit, as in BOLT 3.
+const LOCAL_DUST_LIMIT_SATOSHIS: u64 = 546;
+
+/// Generates a fully signed BOLT 3 commitment transaction broadcastable by the local party, who
+/// funded the channel, along with the signed second-stage transaction for each of its untrimmed
+/// HTLC outputs.
+///
+/// As in BOLT 3 Appendix C, `to_local_msat` and `to_remote_msat` are the parties' balances before
+/// the commitment transaction fee (and, with `anchors`, the anchor output values) are paid by the
+/// local party, and HTLCs whose value does not cover the local dust limit plus the fee of their
+/// second-stage transaction are trimmed. Fails if the local balance cannot pay the fee.
+pub fn commitment_transaction(
+	seed: &[u8; 32], channel_value_satoshis: u64, commitment_number: u64, to_local_msat: u64,
+	to_remote_msat: u64, feerate_per_kw: u32, htlcs: &[VectorHtlc], anchors: bool,
+) -> Result<TestVector, ()> {
+	let secp_ctx = Secp256k1::new();
+	let (local_funding_key, local_htlc_base_key, local_pubkeys) = derive_channel_keys(seed, "local", &secp_ctx);
+	let (remote_funding_key, remote_htlc_base_key, remote_pubkeys) = derive_channel_keys(seed, "remote", &secp_ctx);
+	let per_commitment_secret = derive_secret(seed, "per_commitment_secret", 0);
+	let per_commitment_point = PublicKey::from_secret_key(&secp_ctx, &per_commitment_secret);
+	let funding_outpoint = OutPoint { txid: Txid::from_inner(derive_bytes(seed, "funding_txid", 0)), index: 0 };
+	let channel_type_features = if anchors {
+		ChannelTypeFeatures::anchors_zero_htlc_fee_and_dependencies()
+	} else {
+		ChannelTypeFeatures::only_static_remote_key()
+	};
+
+	let channel_parameters = ChannelTransactionParameters {
+		holder_pubkeys: local_pubkeys.clone(),
+		holder_selected_contest_delay: LOCAL_DELAY,
+		is_outbound_from_holder: true,
+		counterparty_parameters: Some(CounterpartyChannelTransactionParameters {
+			pubkeys: remote_pubkeys.clone(), selected_contest_delay: LOCAL_DELAY,
+		}),
+		funding_outpoint: Some(funding_outpoint),
+		channel_type_features: channel_type_features.clone(),
+	};
+	let directed_parameters = channel_parameters.as_holder_broadcastable();
+	let keys = TxCreationKeys::from_channel_static_keys(&per_commitment_point, &local_pubkeys, &remote_pubkeys, &secp_ctx);
+
+	let (htlc_timeout_fee, htlc_success_fee) = if anchors {
+		(0, 0)
+	} else {
+		(feerate_per_kw as u64 * chan_utils::htlc_timeout_tx_weight(&channel_type_features) / 1000,
+			feerate_per_kw as u64 * chan_utils::htlc_success_tx_weight(&channel_type_features) / 1000)
+	};
+	let preimages = (0..htlcs.len())
+		.map(|idx| PaymentPreimage(derive_bytes(seed, "payment_preimage", idx as u32)))
+		.collect::<Vec<_>>();
+	let mut htlcs_with_aux = htlcs.iter().zip(preimages.iter()).enumerate().filter(|(_, (htlc, _))| {
+		let second_stage_fee = if htlc.offered { htlc_timeout_fee } else { htlc_success_fee };
+		htlc.amount_msat / 1000 >= LOCAL_DUST_LIMIT_SATOSHIS + second_stage_fee
+	}).map(|(idx, (htlc, preimage))| {
+		(HTLCOutputInCommitment {
+			offered: htlc.offered,
+			amount_msat: htlc.amount_msat,
+			cltv_expiry: htlc.cltv_expiry,
+			payment_hash: PaymentHash(Sha256::hash(&preimage.0).into_inner()),
+			transaction_output_index: None,
+		}, (idx, *preimage))
+	}).collect::<Vec<_>>();
+
+	let anchors_value_satoshis = if anchors { ANCHOR_OUTPUT_VALUE_SATOSHI * 2 } else { 0 };
+	let fee_satoshis = commit_tx_fee_sat(feerate_per_kw, htlcs_with_aux.len(), &channel_type_features);
+	let to_local_satoshis = (to_local_msat / 1000).checked_sub(fee_satoshis + anchors_value_satoshis).ok_or(())?;
+	let to_remote_satoshis = to_remote_msat / 1000;
+	let trim_dust = |value: u64| if value < LOCAL_DUST_LIMIT_SATOSHIS { 0 } else { value };
+
+	let commitment_tx = CommitmentTransaction::new_with_auxiliary_htlc_data(
+		commitment_number, trim_dust(to_local_satoshis), trim_dust(to_remote_satoshis),
+		local_pubkeys.funding_pubkey, remote_pubkeys.funding_pubkey, keys.clone(), feerate_per_kw,
+		&mut htlcs_with_aux, &directed_parameters);
+
+	let funding_redeemscript = chan_utils::make_funding_redeemscript(&local_pubkeys.funding_pubkey, &remote_pubkeys.funding_pubkey);
+	let trusted_tx = commitment_tx.trust();
+	let built_tx = trusted_tx.built_transaction();
+	let local_sig = built_tx.sign_counterparty_commitment(&local_funding_key, &funding_redeemscript, channel_value_satoshis, &secp_ctx);
+	let remote_sig = built_tx.sign_counterparty_commitment(&remote_funding_key, &funding_redeemscript, channel_value_satoshis, &secp_ctx);
+	let signed_tx = HolderCommitmentTransaction::new(commitment_tx.clone(), remote_sig, Vec::new(),
+		&local_pubkeys.funding_pubkey, &remote_pubkeys.funding_pubkey)
+		.add_holder_sig(&funding_redeemscript, local_sig);
+
+	let local_htlc_key = chan_utils::derive_private_key(&secp_ctx, &per_commitment_point, &local_htlc_base_key);
+	let remote_htlc_key = chan_utils::derive_private_key(&secp_ctx, &per_commitment_point, &remote_htlc_base_key);
+
+	let mut vector = TestVector::new(format!("commitment tx with {} HTLCs{}", htlcs.len(),
+		if anchors { " and anchor outputs" } else { "" }));
+	vector.push("funding_tx_id", funding_outpoint.txid.to_hex());
+	vector.push("funding_output_index", funding_outpoint.index);
+	vector.push("funding_amount_satoshi", channel_value_satoshis);
+	vector.push("commitment_number", commitment_number);
+	vector.push("local_delay", LOCAL_DELAY);
+	vector.push("local_dust_limit_satoshi", LOCAL_DUST_LIMIT_SATOSHIS);
+	for (idx, (htlc, preimage)) in htlcs.iter().zip(preimages.iter()).enumerate() {
+		vector.push(&format!("htlc {} direction", idx), if htlc.offered { "local->remote" } else { "remote->local" });
+		vector.push(&format!("htlc {} amount_msat", idx), htlc.amount_msat);
+		vector.push(&format!("htlc {} expiry", idx), htlc.cltv_expiry);
+		vector.push(&format!("htlc {} payment_preimage", idx), preimage.0.to_hex());
+	}
+	vector.push("local_per_commitment_secret", per_commitment_secret.secret_bytes().to_hex());
+	vector.push("local_funding_privkey", local_funding_key.secret_bytes().to_hex());
+	vector.push("local_funding_pubkey", local_pubkeys.funding_pubkey.serialize().to_hex());
+	vector.push("remote_funding_privkey", remote_funding_key.secret_bytes().to_hex());
+	vector.push("remote_funding_pubkey", remote_pubkeys.funding_pubkey.serialize().to_hex());
+	vector.push("local_privkey", local_htlc_key.secret_bytes().to_hex());
+	vector.push("localpubkey", keys.broadcaster_htlc_key.serialize().to_hex());
+	vector.push("remotepubkey", keys.countersignatory_htlc_key.serialize().to_hex());
+	vector.push("local_delayedpubkey", keys.broadcaster_delayed_payment_key.serialize().to_hex());
+	vector.push("local_revocation_pubkey", keys.revocation_key.serialize().to_hex());
+	vector.push("to_local_msat", to_local_msat);
+	vector.push("to_remote_msat", to_remote_msat);
+	vector.push("local_feerate_per_kw", feerate_per_kw);
+	vector.push("remote_signature", remote_sig.serialize_der().to_hex());
+	vector.push("local_signature", local_sig.serialize_der().to_hex());
+	vector.push("output commit_tx", encode::serialize_hex(&signed_tx));
+	vector.push("num_htlcs", htlcs_with_aux.len());
+
+	let remote_sighash_type = if anchors { EcdsaSighashType::SinglePlusAnyoneCanPay } else { EcdsaSighashType::All };
+	for (htlc, (idx, preimage)) in htlcs_with_aux.iter() {
+		let mut htlc_tx = chan_utils::build_htlc_transaction(&trusted_tx.txid(), feerate_per_kw,
+			directed_parameters.contest_delay(), htlc, &channel_type_features,
+			&keys.broadcaster_delayed_payment_key, &keys.revocation_key);
+		let htlc_redeemscript = chan_utils::get_htlc_redeemscript(htlc, &channel_type_features, &keys);
+		let mut sighash_cache = SighashCache::new(&htlc_tx);
+		let mut sign = |key: &SecretKey, sighash_type: EcdsaSighashType| {
+			let sighash = sighash_cache.segwit_signature_hash(0, &htlc_redeemscript, htlc.amount_msat / 1000, sighash_type).unwrap();
+			secp_ctx.sign_ecdsa(&Message::from_slice(&sighash[..]).unwrap(), key)
+		};
+		let remote_htlc_sig = sign(&remote_htlc_key, remote_sighash_type);
+		let local_htlc_sig = sign(&local_htlc_key, EcdsaSighashType::All);
+		let preimage = if htlc.offered { None } else { Some(*preimage) };
+		htlc_tx.input[0].witness = chan_utils::build_htlc_input_witness(&local_htlc_sig, &remote_htlc_sig,
+			&preimage, &htlc_redeemscript, &channel_type_features);
+
+		vector.push("remote_htlc_signature", remote_htlc_sig.serialize_der().to_hex());
+		vector.push("local_htlc_signature", local_htlc_sig.serialize_der().to_hex());
+		vector.push(&format!("output {} {}", if htlc.offered { "htlc_timeout_tx" } else { "htlc_success_tx" }, idx),
+			encode::serialize_hex(&htlc_tx));
+	}
+	Ok(vector)
+}
+
+fn p2wpkh_script(pubkey: &PublicKey) -> Script {
+	Script::new_v0_p2wpkh(&WPubkeyHash::hash(&pubkey.serialize()))
+}
+
+/// Generates the contract execution transactions (CETs) of a DLC with the given `branches`, along
+/// with the local party's adaptor signature for each, encrypted to an oracle attestation point
+/// derived from `seed`, and the signature decrypted with the corresponding attestation secret.
+pub fn contract_execution_transactions(
+	seed: &[u8; 32], collateral_satoshis: u64, lock_time: u32, dust_limit_satoshis: u64,
+	branches: Vec<SettlementBranch>,
+) -> Result<TestVector, ()> {
+	let secp_ctx = Secp256k1::new();
+	let (local_funding_key, _, local_pubkeys) = derive_channel_keys(seed, "local", &secp_ctx);
+	let (_, _, remote_pubkeys) = derive_channel_keys(seed, "remote", &secp_ctx);
+	let collateral = CollateralOutput {
+		outpoint: OutPoint { txid: Txid::from_inner(derive_bytes(seed, "fund_txid", 0)), index: 0 },
+		value_satoshis: collateral_satoshis,
+		holder_funding_pubkey: local_pubkeys.funding_pubkey,
+		counterparty_funding_pubkey: remote_pubkeys.funding_pubkey,
+	};
+	let bundle = SettlementBundle::new(collateral.clone(), p2wpkh_script(&local_pubkeys.payment_point),
+		p2wpkh_script(&remote_pubkeys.payment_point), lock_time, dust_limit_satoshis, branches)?;
+
+	let attestation_secrets = (0..bundle.branches().len())
+		.map(|idx| derive_secret(seed, "oracle_attestation", idx as u32))
+		.collect::<Vec<_>>();
+	let adaptor_points = attestation_secrets.iter()
+		.map(|secret| PublicKey::from_secret_key(&secp_ctx, secret))
+		.collect::<Vec<_>>();
+	let adaptor_sigs = bundle.adaptor_sign_branches(&adaptor_points, &local_funding_key, &secp_ctx)?;
+
+	let mut vector = TestVector::new(format!("contract execution transactions for {} outcomes", bundle.branches().len()));
+	vector.push("fund_txid", collateral.outpoint.txid.to_hex());
+	vector.push("fund_output_index", collateral.outpoint.index);
+	vector.push("fund_output_value", collateral_satoshis);
+	vector.push("local_fund_privkey", local_funding_key.secret_bytes().to_hex());
+	vector.push("local_fund_pubkey", local_pubkeys.funding_pubkey.serialize().to_hex());
+	vector.push("remote_fund_pubkey", remote_pubkeys.funding_pubkey.serialize().to_hex());
+	vector.push("local_payout_spk", bundle.holder_payout_script().to_hex());
+	vector.push("remote_payout_spk", bundle.counterparty_payout_script().to_hex());
+	vector.push("cet_locktime", lock_time);
+	for (idx, branch) in bundle.branches().iter().enumerate() {
+		let cet: Transaction = bundle.settlement_transaction(idx);
+		let sig = adaptor_sigs[idx].decrypt(&attestation_secrets[idx])?;
+		vector.push(&format!("cet_{}_outcome", idx), branch.outcome.to_hex());
+		vector.push(&format!("cet_{}_adaptor_point", idx), adaptor_points[idx].serialize().to_hex());
+		vector.push(&format!("cet_{}_adaptor_secret", idx), attestation_secrets[idx].secret_bytes().to_hex());
+		vector.push(&format!("cet_{}_tx", idx), encode::serialize_hex(&cet));
+		vector.push(&format!("cet_{}_adaptor_signature", idx), serialize_hex(&adaptor_sigs[idx]));
+		vector.push(&format!("cet_{}_signature", idx), sig.serialize_der().to_hex());
+	}
+	Ok(vector)
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::transaction::EcdsaSighashType;
+	use bitcoin::hashes::hex::FromHex;
+	use bitcoin::secp256k1::{Message, PublicKey, Secp256k1};
+	use bitcoin::secp256k1::ecdsa::Signature;
+	use crate::ln::contracts::SettlementBranch;
+	use super::*;
+
+	#[test]
+	fn vectors_are_deterministic() {
+		let seed = [42; 32];
+		assert_eq!(onion_packet(&seed, 5, 100_000, 800_000), onion_packet(&seed, 5, 100_000, 800_000));
+		assert_ne!(onion_packet(&seed, 5, 100_000, 800_000), onion_packet(&[43; 32], 5, 100_000, 800_000));
+		assert_eq!(blinded_path(&seed, 3), blinded_path(&seed, 3));
+		assert!(onion_packet(&seed, 0, 100_000, 800_000).is_err());
+		assert!(blinded_path(&seed, 1).is_err());
+
+		let onion = onion_packet(&seed, 5, 100_000, 800_000).unwrap();
+		// A version byte, the ephemeral pubkey, 1300 bytes of hop data and the HMAC.
+		assert_eq!(onion.get("onion").unwrap().len(), (1 + 33 + 1300 + 32) * 2);
+	}
+
+	#[test]
+	fn commitment_transaction_matches_bolt3() {
+		let htlcs = [
+			VectorHtlc { offered: true, amount_msat: 2_000_000, cltv_expiry: 500 },
+			VectorHtlc { offered: false, amount_msat: 3_000_000, cltv_expiry: 501 },
+			// Below the dust limit, and thus trimmed.
+			VectorHtlc { offered: false, amount_msat: 500_000, cltv_expiry: 502 },
+		];
+		let vector = commitment_transaction(&[42; 32], 10_000_000, 42, 7_000_000_000, 3_000_000_000, 253, &htlcs, false).unwrap();
+		assert_eq!(vector, commitment_transaction(&[42; 32], 10_000_000, 42, 7_000_000_000, 3_000_000_000, 253, &htlcs, false).unwrap());
+		let tx: Transaction = encode::deserialize(&Vec::<u8>::from_hex(vector.get("output commit_tx").unwrap()).unwrap()).unwrap();
+		// The to_local, to_remote and two untrimmed HTLC outputs.
+		assert_eq!(tx.output.len(), 4);
+		assert_eq!(tx.input[0].witness.len(), 4);
+		assert_eq!(vector.get("num_htlcs"), Some("2"));
+		let fee = commit_tx_fee_sat(253, 2, &ChannelTypeFeatures::only_static_remote_key());
+		assert!(tx.output.iter().any(|output| output.value == 7_000_000 - fee));
+		assert!(tx.output.iter().any(|output| output.value == 3_000_000));
+
+		// Each untrimmed HTLC has a fully signed second-stage transaction spending its output.
+		for (key, htlc_idx) in [("output htlc_timeout_tx 0", 0), ("output htlc_success_tx 1", 1)].iter() {
+			let htlc_tx: Transaction = encode::deserialize(&Vec::<u8>::from_hex(vector.get(key).unwrap()).unwrap()).unwrap();
+			assert_eq!(htlc_tx.input[0].previous_output.txid, tx.txid());
+			assert_eq!(tx.output[htlc_tx.input[0].previous_output.vout as usize].value, htlcs[*htlc_idx].amount_msat / 1000);
+			assert_eq!(htlc_tx.input[0].witness.len(), 5);
+		}
+		assert!(vector.get("output htlc_success_tx 2").is_none());
+
+		let bolt3 = vector.to_bolt3_format();
+		assert!(bolt3.contains("\nhtlc 2 amount_msat: 500000\n"));
+		assert!(bolt3.contains("\nto_local_msat: 7000000000\n"));
+		assert!(bolt3.contains(&format!("\nremote_signature = {}\n", vector.get("remote_signature").unwrap())));
+		assert!(bolt3.contains(&format!("\n# local_signature = {}\n", vector.get("local_signature").unwrap())));
+		assert!(bolt3.contains("\nremote_htlc_signature = "));
+		assert!(bolt3.contains("\n# local_htlc_signature = "));
+
+		// With anchors the HTLCs are not trimmed by their second-stage fee, and two anchors are added.
+		let anchors_vector = commitment_transaction(&[42; 32], 10_000_000, 42, 7_000_000_000, 3_000_000_000, 253, &htlcs, true).unwrap();
+		let tx: Transaction = encode::deserialize(&Vec::<u8>::from_hex(anchors_vector.get("output commit_tx").unwrap()).unwrap()).unwrap();
+		assert_eq!(tx.output.len(), 6);
+
+		// The funder must be able to pay the commitment transaction fee.
+		assert!(commitment_transaction(&[42; 32], 10_000_000, 42, 1_000, 9_999_999_000, 253, &htlcs, false).is_err());
+	}
+
+	#[test]
+	fn contract_execution_signatures_verify() {
+		let secp_ctx = Secp256k1::new();
+		let branches = vec![
+			SettlementBranch { outcome: b"heads".to_vec(), holder_payout_satoshis: 99_000, counterparty_payout_satoshis: 0 },
+			SettlementBranch { outcome: b"tails".to_vec(), holder_payout_satoshis: 0, counterparty_payout_satoshis: 99_000 },
+		];
+		let vector = contract_execution_transactions(&[42; 32], 100_000, 800_000, 546, branches).unwrap();
+		let hex_field = |key: &str| Vec::<u8>::from_hex(vector.get(key).unwrap()).unwrap();
+		let local_fund_pubkey = PublicKey::from_slice(&hex_field("local_fund_pubkey")).unwrap();
+		let remote_fund_pubkey = PublicKey::from_slice(&hex_field("remote_fund_pubkey")).unwrap();
+		let redeemscript = chan_utils::make_funding_redeemscript(&local_fund_pubkey, &remote_fund_pubkey);
+		for idx in 0..2 {
+			let cet: Transaction = encode::deserialize(&hex_field(&format!("cet_{}_tx", idx))).unwrap();
+			let pays_local = cet.output.iter()
+				.any(|output| output.script_pubkey.to_hex() == vector.get("local_payout_spk").unwrap());
+			assert_eq!(pays_local, idx == 0);
+			let sig = Signature::from_der(&hex_field(&format!("cet_{}_signature", idx))).unwrap();
+			let sighash = bitcoin::util::sighash::SighashCache::new(&cet).segwit_signature_hash(
+				0, &redeemscript, 100_000, EcdsaSighashType::All).unwrap();
+			let msg = Message::from_slice(&sighash[..]).unwrap();
+			secp_ctx.verify_ecdsa(&msg, &sig, &local_fund_pubkey).unwrap();
+		}
+
+		let json = vector.to_json();
+		assert!(json.starts_with("{\"name\":\"contract execution transactions for 2 outcomes\",\"fund_txid\":\""));
+		assert!(vector.to_bolt3_format().starts_with("name: contract execution transactions for 2 outcomes\nfund_txid: "));
+	}
+}
@@ -0,0 +1,4598 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Tracking of contracts embedded in channels, and the peer-to-peer protocols used to manage them.
+//!
+//! A [`ContractManager`] tracks the fully signed [`SettlementBundle`]s of contracts whose
+//! collateral output is locked to the funding key of one of our channels, with new signatures
+//! being provided by a [`ContractSigner`] and payments over the channel being sent by a
+//! [`ContractPaymentSender`], both usually the [`ChannelManager`]. It is a
+//! [`CustomMessageHandler`] and thus must be provided to the [`PeerManager`]. It is also a
+//! [`CustomOnionMessageHandler`] for [margin calls](#margin-calls), and thus should be provided to
+//! the [`OnionMessenger`] as well.
+//!
+//! # Renewal
+//!
+//! A contract can be renewed, i.e. rolled over to new terms such as a later maturity, without
+//! closing the channel via [`ContractManager::propose_contract_renewal`]:
+//!  1. The proposer sends a [`ContractMessage::RenewalProposal`] with the new terms. From then on
+//!     the contract is quiesced, i.e. no other renewal may be started until this one completes or
+//!     is aborted.
+//!  2. The acceptor is notified via an [`Event::ContractRenewalRequest`] and either accepts the new
+//!     terms via [`ContractManager::accept_contract_renewal`], responding with a
+//!     [`ContractMessage::RenewalAccept`] carrying its adaptor signatures for the new settlement
+//!     bundle, or rejects them via [`ContractManager::abort_contract_renewal`].
+//!  3. The proposer checks the acceptor's signatures, responds with a
+//!     [`ContractMessage::RenewalSign`] carrying its own, and switches to the new contract.
+//!  4. The acceptor checks the proposer's signatures and switches to the new contract.
+//!
+//! Each side generates an [`Event::ContractRenewed`] with the new [`ContractId`] once it has
+//! switched, or an [`Event::ContractRenewalFailed`] if the renewal was aborted. The new
+//! [`ContractId`] is derived from the new terms via [`derive_contract_id`], and the acceptor
+//! aborts proposals whose id doesn't match its own derivation.
+//!
+//! Proposals must carry the [`OracleAnnouncement`] the adaptor points of the new branches were
+//! taken from, and the acceptor aborts proposals whose announcement isn't signed by the oracle or
+//! whose adaptor points aren't the announced attestation points, see also
+//! [Oracle Policy](#oracle-policy).
+//!
+//! If the other side's signatures don't arrive within [`RENEWAL_TIMEOUT_TICKS`] calls to
+//! [`ContractManager::timer_tick_occurred`] after we proposed or accepted a renewal, the renewal
+//! is aborted. As an acceptor which timed out already handed out its signatures for the new
+//! settlement bundle, the bundle is kept around to detect it being settled on-chain, as for
+//! superseded contracts.
+//!
+//! Note that the signatures for the previous settlement bundle remain valid after a renewal. Thus,
+//! the new terms should spend a new collateral output, with the one spent by the previous bundle
+//! being invalidated, e.g. by revoking the commitment transaction containing it.
+//!
+//! # Mutual Settlement
+//!
+//! Once the oracle has attested to an outcome, either party can propose settling the contract
+//! off-chain via [`ContractManager::propose_mutual_settlement`] rather than broadcasting the
+//! settlement transaction for the outcome:
+//!  1. The proposer sends a [`ContractMessage::SettlementProposal`] with the outcome, the oracle's
+//!     attestation to it and its signature for a release transaction spending the collateral
+//!     output, which returns each party's contribution to the collateral less its share of the
+//!     fee the outcome's settlement transaction would pay.
+//!  2. The acceptor checks the attestation against the outcome's adaptor point and the proposer's
+//!     signature, and is notified via an [`Event::ContractSettlementRequest`]. It either accepts
+//!     via [`ContractManager::accept_mutual_settlement`], responding with a
+//!     [`ContractMessage::SettlementAccept`] carrying its own signature for the release
+//!     transaction, or rejects via [`ContractManager::reject_mutual_settlement`].
+//!  3. The proposer checks the acceptor's signature upon receiving the
+//!     [`ContractMessage::SettlementAccept`].
+//!
+//! Each side then hands the fully signed release transaction to the [`ChannelMonitor`] of the
+//! contract's channel via a [`ChannelMonitorUpdate`], which broadcasts it until the collateral
+//! output is spent. As the release spends the same collateral output as the contract's settlement
+//! transactions, it invalidates them once confirmed. Only after it reached [`ANTI_REORG_DELAY`]
+//! confirmations is the contract settled: whichever party's payout is lower than its contribution
+//! to the collateral sends the difference to the other as a payment over the channel. The contract
+//! is no longer tracked afterwards, and each side generates an [`Event::ContractSettled`]. If a
+//! settlement transaction confirms instead, the contract is settled on-chain as usual.
+//!
+//! The release transaction is not presigned with a fee of its own beyond what the contract's
+//! branches leave unallocated, so either party may have to bump its fee via CPFP on its output.
+//!
+//! Contracts in a collateral pool are settled without a release transaction, i.e. purely
+//! off-chain, as their collateral output is shared with other contracts. Their settlement
+//! transactions thus remain valid, see [Collateral Pools](#collateral-pools).
+//!
+//! # Margin Calls
+//!
+//! Either party can request that its counterparty tops up the margin of a contract via
+//! [`ContractManager::send_margin_call`], which sends a [`MarginCallMessage::Request`] over an
+//! onion message, with any response being sent back over a blinded reply path:
+//!  1. The recipient is notified via an [`Event::MarginCallReceived`] and either pays the
+//!     requested amount over the contract's channel via [`ContractManager::pay_margin_call`], or
+//!     rejects the request via [`ContractManager::reject_margin_call`].
+//!  2. A payment is received as a spontaneous payment whose
+//!     [`RecipientOnionFields::payment_metadata`] is the contract id, while a rejection results in
+//!     an [`Event::MarginCallRejected`].
+//!
+//! As onion messages are not authenticated, the terms of both requests and rejections are signed
+//! with the sender's funding key of the contract's channel, see
+//! [`EcdsaChannelSigner::sign_contract_message`]. To prevent replays, a request is only surfaced
+//! if its deadline is later than that of any previous request for the contract.
+//!
+//! As margin calls are time-sensitive, it may be worth checking that our counterparty is reachable
+//! over onion messages first, e.g. when it is only reachable over a blinded path via its LSP, via
+//! [`OnionMessenger::send_liveness_probe`].
+//!
+//! # Oracle Policy
+//!
+//! An [`OraclePolicy`] set via [`ContractManager::set_oracle_policy`] restricts which contracts we
+//! agree to. The [`OracleAnnouncement`] carried by renewal proposals must comply with the policy,
+//! with all other proposals being aborted before an [`Event::ContractRenewalRequest`] is
+//! generated. Likewise, we only propose renewals whose announcement complies with our policy.
+//!
+//! As we can't tell which oracles are trustworthy by ourselves, no renewal is proposed, accepted
+//! or signed while no policy is set. The policy is persisted along with the contracts.
+//!
+//! # Collateral Pools
+//!
+//! Rather than locking up a separate collateral output per contract, several contracts with the
+//! same counterparty can share a collateral pool, i.e. a single collateral output whose value is
+//! sub-allocated to contracts off-chain. A pool is added on both sides via
+//! [`ContractManager::add_collateral_pool`], after which contracts are allocated a share of it:
+//!  1. The proposer sends a [`ContractMessage::PoolAllocationProposal`] via
+//!     [`ContractManager::propose_pool_allocation`].
+//!  2. The acceptor responds with a [`ContractMessage::PoolAllocationAccept`] if both parties'
+//!     free contributions to the pool cover the allocation, taking any allocation it proposed
+//!     itself into account, or with a [`ContractMessage::PoolAllocationReject`] otherwise.
+//!
+//! Each side generates an [`Event::PoolAllocationAdded`] once the allocation was added, or an
+//! [`Event::PoolAllocationFailed`] if it was rejected or the proposer didn't get a response within
+//! [`POOL_ALLOCATION_TIMEOUT_TICKS`]. Allocations follow their contract through renewals and are
+//! released once it is settled off-chain, or explicitly via
+//! [`ContractManager::release_pool_allocation`] for contracts which were never registered.
+//!
+//! A contract allocated a share of a pool must spend the pool's collateral output, with our share
+//! of the allocation as our contribution, which is enforced when allocating, registering and
+//! renewing it.
+//!
+//! Note that contracts in a pool can only be settled off-chain, as their settlement transactions
+//! would spend the whole collateral output. The pool's output itself should be covered by a
+//! contract reflecting the aggregate of its allocations.
+//!
+//! # Disputes
+//!
+//! The signatures for a contract's settlement transactions remain valid after it has been renewed,
+//! so a dishonest counterparty may still try to settle it according to the outdated terms. A
+//! `ContractManager` thus keeps the settlement bundles of superseded contracts and, as a
+//! [`chain::Listen`]er, watches for their settlement transactions confirming. Once one does, it
+//! assembles a [`DisputePackage`] with the evidence required to contest the settlement, surfaced
+//! via an [`Event::ContractDisputeDetected`]. A superseded contract is forgotten once a
+//! transaction spending its collateral output has reached [`ANTI_REORG_DELAY`] confirmations.
+//!
+//! # Automatic Exercise
+//!
+//! Once a contract has matured, i.e. the best block known to the `ContractManager` as a
+//! [`chain::Listen`]er reached the lock time of its settlement transactions, it is exercised on
+//! each [`ContractManager::timer_tick_occurred`], which the `BackgroundProcessor` from the
+//! `lightning-background-processor` crate calls regularly if the `ContractManager` is provided to
+//! it:
+//!  1. If no oracle attestation was recorded for the contract yet, the user is asked to fetch it
+//!     and provide it via [`ContractManager::record_oracle_attestation`].
+//!  2. Once it is, we propose settling the contract off-chain for the attested outcome as
+//!     described [above](#mutual-settlement).
+//!  3. If our counterparty doesn't accept within [`MUTUAL_SETTLEMENT_TIMEOUT_TICKS`], or rejects
+//!     the proposal, we broadcast the settlement transaction for the outcome instead, and
+//!     rebroadcast it every [`SETTLEMENT_REBROADCAST_INTERVAL_TICKS`] until it confirms.
+//!
+//! Each step generates an [`Event::ContractExerciseProgress`]. As settlement transactions are
+//! presigned with a fixed fee, the event for a broadcast includes the fee a child transaction
+//! spending our output would have to add to get it confirmed swiftly, with a new event being
+//! generated whenever that fee rises. If our payout output is P2WPKH, each broadcast requiring a
+//! child also generates an [`Event::BumpTransaction`], which a [`BumpTransactionEventHandler`]
+//! handles by attaching the fee via CPFP. Contracts in a collateral pool are never settled on-chain;
+//! instead, the proposal is repeated until our counterparty accepts it.
+//!
+//! Once any settlement transaction of a tracked contract has reached [`ANTI_REORG_DELAY`]
+//! confirmations, whether broadcast by us or by our counterparty, the contract is no longer
+//! tracked and an [`Event::ContractSettled`] is generated.
+//!
+//! # Offer Evaluation
+//!
+//! Before accepting a renewal proposal, [`ContractManager::evaluate_renewal_proposal`] can be used
+//! to estimate what settling each branch of the new contract on-chain would cost us across a range
+//! of feerates, and whether our reserves could cover that cost. It returns an [`OfferEvaluation`]
+//! including an [`OfferRecommendation`] on whether to accept the proposal. Only branches paying us
+//! a non-dust output can be bumped by us via CPFP, and our channel reserve is only counted for
+//! channels supporting anchor outputs.
+//!
+//! # Reporting
+//!
+//! [`ContractManager::portfolio_snapshot`] aggregates our positions in all tracked contracts with
+//! the balances of our channels into a [`PortfolioSnapshot`], e.g. for display on a dashboard.
+//!
+//! [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+//! [`derive_contract_id`]: crate::ln::contracts::derive_contract_id
+//! [`PeerManager`]: crate::ln::peer_handler::PeerManager
+//! [`BumpTransactionEventHandler`]: crate::events::bump_transaction::BumpTransactionEventHandler
+//! [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+//! [`ChannelMonitorUpdate`]: crate::chain::channelmonitor::ChannelMonitorUpdate
+//! [`OnionMessenger`]: crate::onion_message::OnionMessenger
+//! [`OnionMessenger::send_liveness_probe`]: crate::onion_message::OnionMessenger::send_liveness_probe
+//! [`RecipientOnionFields::payment_metadata`]: crate::ln::channelmanager::RecipientOnionFields::payment_metadata
+//! [`EcdsaChannelSigner::sign_contract_message`]: crate::sign::EcdsaChannelSigner::sign_contract_message
+
+use bitcoin::secp256k1::{self, PublicKey, Secp256k1, SecretKey};
+use bitcoin::secp256k1::ecdsa::Signature;
+
+use bitcoin::blockdata::block::BlockHeader;
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::hash_types::{BlockHash, WPubkeyHash};
+use bitcoin::hashes::Hash;
+
+use crate::blinded_path::BlindedPath;
+use crate::chain;
+use crate::chain::ClaimId;
+use crate::chain::chaininterface::{BroadcasterInterface, ConfirmationTarget, FeeEstimator, TransactionMetadata, TransactionType, fee_for_weight};
+use crate::chain::channelmonitor::ANTI_REORG_DELAY;
+use crate::chain::transaction::{OutPoint, TransactionData};
+use crate::events::{Event, EventHandler, EventsProvider};
+use crate::events::bump_transaction::{BumpTransactionEvent, Input, Utxo};
+use crate::ln::channelmanager::{ChannelDetails, PaymentId};
+use crate::ln::contracts::{CollateralOutput, SettlementBranch, SettlementBundle, contract_message_digest, derive_contract_id};
+use crate::ln::features::{InitFeatures, NodeFeatures};
+use crate::ln::msgs::{DecodeError, ErrorAction, LightningError};
+use crate::ln::oracle::{OracleAnnouncement, OracleAttestation, OraclePolicy, OracleThreshold};
+use crate::ln::peer_handler::CustomMessageHandler;
+use crate::ln::wire;
+use crate::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, Destination};
+use crate::sign::EntropySource;
+use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
+use crate::util::errors::APIError;
+use crate::util::logger::{Level, Logger};
+use crate::util::ser::{Readable, ReadableArgs, RequiredWrapper, Writeable, Writer};
+
+use crate::io;
+use crate::prelude::*;
+use crate::sync::Mutex;
+use core::ops::Deref;
+
+/// A unique identifier of a contract tracked by a [`ContractManager`], usually derived via
+/// [`derive_contract_id`].
+///
+/// This is not exported to bindings users as we just use [u8; 32] directly
+#[derive(Hash, Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ContractId(pub [u8; 32]);
+
+impl Writeable for ContractId {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		self.0.write(w)
+	}
+}
+
+impl Readable for ContractId {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let buf: [u8; 32] = Readable::read(r)?;
+		Ok(ContractId(buf))
+	}
+}
+
+/// The number of [`ContractManager::timer_tick_occurred`] calls after which we abort a renewal we
+/// proposed or accepted if our counterparty didn't send its signatures.
+pub const RENEWAL_TIMEOUT_TICKS: u16 = 30;
+
+/// The number of [`ContractManager::timer_tick_occurred`] calls after which we abandon a pool
+/// allocation we proposed if our counterparty didn't respond to it.
+pub const POOL_ALLOCATION_TIMEOUT_TICKS: u16 = 30;
+
+/// The wire message type of a [`ContractMessage::RenewalProposal`].
+pub const CONTRACT_RENEWAL_PROPOSAL_TYPE: u16 = 52_801;
+
+/// The wire message type of a [`ContractMessage::RenewalAccept`].
+pub const CONTRACT_RENEWAL_ACCEPT_TYPE: u16 = 52_803;
+
+/// The wire message type of a [`ContractMessage::RenewalSign`].
+pub const CONTRACT_RENEWAL_SIGN_TYPE: u16 = 52_805;
+
+/// The wire message type of a [`ContractMessage::RenewalAbort`].
+pub const CONTRACT_RENEWAL_ABORT_TYPE: u16 = 52_807;
+
+/// The wire message type of a [`ContractMessage::SettlementProposal`].
+pub const CONTRACT_SETTLEMENT_PROPOSAL_TYPE: u16 = 52_809;
+
+/// The wire message type of a [`ContractMessage::SettlementAccept`].
+pub const CONTRACT_SETTLEMENT_ACCEPT_TYPE: u16 = 52_811;
+
+/// The wire message type of a [`ContractMessage::SettlementReject`].
+pub const CONTRACT_SETTLEMENT_REJECT_TYPE: u16 = 52_813;
+
+/// The wire message type of a [`ContractMessage::PoolAllocationProposal`].
+pub const POOL_ALLOCATION_PROPOSAL_TYPE: u16 = 52_815;
+
+/// The wire message type of a [`ContractMessage::PoolAllocationAccept`].
+pub const POOL_ALLOCATION_ACCEPT_TYPE: u16 = 52_817;
+
+/// The wire message type of a [`ContractMessage::PoolAllocationReject`].
+pub const POOL_ALLOCATION_REJECT_TYPE: u16 = 52_819;
+
+/// The wire message type of a [`ContractMessage::PoolAllocationRelease`].
+pub const POOL_ALLOCATION_RELEASE_TYPE: u16 = 52_821;
+
+/// The onion message TLV type of a [`MarginCallMessage::Request`].
+pub const MARGIN_CALL_REQUEST_TLV_TYPE: u64 = 65_541;
+
+/// The onion message TLV type of a [`MarginCallMessage::Rejection`].
+pub const MARGIN_CALL_REJECTION_TLV_TYPE: u64 = 65_543;
+
+/// A message exchanged between the [`ContractManager`]s of two peers.
+///
+/// Note that, as with any Lightning message, each message is limited to 65535 bytes, which limits
+/// the number of branches a contract can be renewed with to a few hundred.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContractMessage {
+	/// Proposes renewing a contract with new terms.
+	RenewalProposal {
+		/// The contract to renew.
+		contract_id: ContractId,
+		/// The id the contract will have once renewed.
+		new_contract_id: ContractId,
+		/// The collateral output spent by the new settlement bundle, from the sender's point of
+		/// view, i.e. with the `holder` fields referring to the sender.
+		collateral: CollateralOutput,
+		/// The lock time of the new settlement transactions, i.e. the new maturity.
+		lock_time: u32,
+		/// The branches of the new settlement bundle, from the sender's point of view.
+		branches: Vec<SettlementBranch>,
+		/// The adaptor point for each entry in `branches`.
+		adaptor_points: Vec<PublicKey>,
+		/// The sender's contribution to the value of `collateral`, in satoshis.
+		holder_collateral_satoshis: u64,
+		/// The announcement of the oracle whose attestation points are the `adaptor_points`.
+		///
+		/// This is always set by senders, proposals without one are aborted.
+		oracle_announcement: Option<OracleAnnouncement>,
+	},
+	/// Accepts a [`ContractMessage::RenewalProposal`].
+	RenewalAccept {
+		/// The contract being renewed.
+		contract_id: ContractId,
+		/// The `new_contract_id` of the proposal being accepted.
+		new_contract_id: ContractId,
+		/// The sender's adaptor signatures for all branches of the new settlement bundle.
+		adaptor_signatures: Vec<EcdsaAdaptorSignature>,
+	},
+	/// Completes a renewal after receiving a [`ContractMessage::RenewalAccept`].
+	RenewalSign {
+		/// The contract being renewed.
+		contract_id: ContractId,
+		/// The `new_contract_id` of the proposal being completed.
+		new_contract_id: ContractId,
+		/// The sender's adaptor signatures for all branches of the new settlement bundle.
+		adaptor_signatures: Vec<EcdsaAdaptorSignature>,
+	},
+	/// Aborts a renewal before it completes, e.g. as its terms were rejected.
+	RenewalAbort {
+		/// The contract which was to be renewed.
+		contract_id: ContractId,
+		/// The `new_contract_id` of the proposal being aborted.
+		new_contract_id: ContractId,
+		/// A human-readable reason for the abort.
+		reason: String,
+	},
+	/// Proposes settling a contract off-chain according to an outcome the oracle attested to.
+	SettlementProposal {
+		/// The contract to settle.
+		contract_id: ContractId,
+		/// The outcome to settle the contract for.
+		outcome: Vec<u8>,
+		/// The oracle's attestation to `outcome`, i.e. the secret key for its adaptor point.
+		attestation: SecretKey,
+		/// The sender's signature for the transaction releasing the contract's collateral.
+		///
+		/// This is `None` for contracts in a collateral pool, which are settled purely off-chain.
+		release_signature: Option<Signature>,
+	},
+	/// Accepts a [`ContractMessage::SettlementProposal`].
+	SettlementAccept {
+		/// The contract being settled.
+		contract_id: ContractId,
+		/// The sender's signature for the transaction releasing the contract's collateral.
+		///
+		/// This is `None` for contracts in a collateral pool, which are settled purely off-chain.
+		release_signature: Option<Signature>,
+	},
+	/// Rejects a [`ContractMessage::SettlementProposal`].
+	SettlementReject {
+		/// The contract which was to be settled.
+		contract_id: ContractId,
+		/// A human-readable reason for the rejection.
+		reason: String,
+	},
+	/// Proposes allocating a share of a collateral pool to a contract.
+	PoolAllocationProposal {
+		/// The collateral output of the pool.
+		pool_outpoint: OutPoint,
+		/// The contract to allocate collateral to.
+		contract_id: ContractId,
+		/// The share of the sender's contribution to the pool to allocate, in satoshis.
+		holder_collateral_satoshis: u64,
+		/// The share of the recipient's contribution to the pool to allocate, in satoshis.
+		counterparty_collateral_satoshis: u64,
+	},
+	/// Accepts a [`ContractMessage::PoolAllocationProposal`].
+	PoolAllocationAccept {
+		/// The collateral output of the pool.
+		pool_outpoint: OutPoint,
+		/// The contract collateral was allocated to.
+		contract_id: ContractId,
+	},
+	/// Rejects a [`ContractMessage::PoolAllocationProposal`].
+	PoolAllocationReject {
+		/// The collateral output of the pool.
+		pool_outpoint: OutPoint,
+		/// The contract collateral was to be allocated to.
+		contract_id: ContractId,
+		/// A human-readable reason for the rejection.
+		reason: String,
+	},
+	/// Releases the share of a collateral pool allocated to a contract which was never registered.
+	PoolAllocationRelease {
+		/// The collateral output of the pool.
+		pool_outpoint: OutPoint,
+		/// The contract whose allocation is released.
+		contract_id: ContractId,
+	},
+}
+
+impl_writeable_tlv_based_enum!(ContractMessage,
+	(0, RenewalProposal) => {
+		(0, contract_id, required),
+		(2, new_contract_id, required),
+		(4, collateral, required),
+		(6, lock_time, required),
+		(8, branches, required_vec),
+		(10, adaptor_points, required_vec),
+		(12, holder_collateral_satoshis, required),
+		(14, oracle_announcement, option),
+	},
+	(2, RenewalAccept) => {
+		(0, contract_id, required),
+		(2, new_contract_id, required),
+		(4, adaptor_signatures, required_vec),
+	},
+	(4, RenewalSign) => {
+		(0, contract_id, required),
+		(2, new_contract_id, required),
+		(4, adaptor_signatures, required_vec),
+	},
+	(6, RenewalAbort) => {
+		(0, contract_id, required),
+		(2, new_contract_id, required),
+		(4, reason, required),
+	},
+	(8, SettlementProposal) => {
+		(0, contract_id, required),
+		(2, outcome, required),
+		(4, attestation, required),
+		(6, release_signature, option),
+	},
+	(10, SettlementAccept) => {
+		(0, contract_id, required),
+		(2, release_signature, option),
+	},
+	(12, SettlementReject) => {
+		(0, contract_id, required),
+		(2, reason, required),
+	},
+	(14, PoolAllocationProposal) => {
+		(0, pool_outpoint, required),
+		(2, contract_id, required),
+		(4, holder_collateral_satoshis, required),
+		(6, counterparty_collateral_satoshis, required),
+	},
+	(16, PoolAllocationAccept) => {
+		(0, pool_outpoint, required),
+		(2, contract_id, required),
+	},
+	(18, PoolAllocationReject) => {
+		(0, pool_outpoint, required),
+		(2, contract_id, required),
+		(4, reason, required),
+	},
+	(20, PoolAllocationRelease) => {
+		(0, pool_outpoint, required),
+		(2, contract_id, required),
+	};
+);
+
+impl wire::Type for ContractMessage {
+	fn type_id(&self) -> u16 {
+		match self {
+			ContractMessage::RenewalProposal { .. } => CONTRACT_RENEWAL_PROPOSAL_TYPE,
+			ContractMessage::RenewalAccept { .. } => CONTRACT_RENEWAL_ACCEPT_TYPE,
+			ContractMessage::RenewalSign { .. } => CONTRACT_RENEWAL_SIGN_TYPE,
+			ContractMessage::RenewalAbort { .. } => CONTRACT_RENEWAL_ABORT_TYPE,
+			ContractMessage::SettlementProposal { .. } => CONTRACT_SETTLEMENT_PROPOSAL_TYPE,
+			ContractMessage::SettlementAccept { .. } => CONTRACT_SETTLEMENT_ACCEPT_TYPE,
+			ContractMessage::SettlementReject { .. } => CONTRACT_SETTLEMENT_REJECT_TYPE,
+			ContractMessage::PoolAllocationProposal { .. } => POOL_ALLOCATION_PROPOSAL_TYPE,
+			ContractMessage::PoolAllocationAccept { .. } => POOL_ALLOCATION_ACCEPT_TYPE,
+			ContractMessage::PoolAllocationReject { .. } => POOL_ALLOCATION_REJECT_TYPE,
+			ContractMessage::PoolAllocationRelease { .. } => POOL_ALLOCATION_RELEASE_TYPE,
+		}
+	}
+}
+
+/// A margin call sent over onion messages, see the [module-level documentation] for details.
+///
+/// [module-level documentation]: crate::ln::contractmanager#margin-calls
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MarginCallMessage {
+	/// Requests that the recipient tops up the margin of a contract by paying the sender.
+	Request {
+		/// The contract whose margin is to be topped up.
+		contract_id: ContractId,
+		/// The amount to be paid, in satoshis.
+		top_up_satoshis: u64,
+		/// The time by which the amount is to be paid, as a UNIX timestamp.
+		deadline: u64,
+		/// The blinded path a [`MarginCallMessage::Rejection`] is to be sent over.
+		reply_path: BlindedPath,
+		/// The sender's signature over the above with its funding key of the contract's channel.
+		signature: Signature,
+	},
+	/// Rejects a [`MarginCallMessage::Request`].
+	Rejection {
+		/// The contract whose margin was to be topped up.
+		contract_id: ContractId,
+		/// The `top_up_satoshis` of the request being rejected.
+		top_up_satoshis: u64,
+		/// The `deadline` of the request being rejected.
+		deadline: u64,
+		/// A human-readable reason for the rejection.
+		reason: String,
+		/// The sender's signature over the above with its funding key of the contract's channel.
+		signature: Signature,
+	},
+}
+
+impl_writeable_tlv_based_enum!(MarginCallMessage,
+	(0, Request) => {
+		(0, contract_id, required),
+		(2, top_up_satoshis, required),
+		(4, deadline, required),
+		(6, reply_path, required),
+		(8, signature, required),
+	},
+	(2, Rejection) => {
+		(0, contract_id, required),
+		(2, top_up_satoshis, required),
+		(4, deadline, required),
+		(6, reason, required),
+		(8, signature, required),
+	};
+);
+
+impl CustomOnionMessageContents for MarginCallMessage {
+	fn tlv_type(&self) -> u64 {
+		match self {
+			MarginCallMessage::Request { .. } => MARGIN_CALL_REQUEST_TLV_TYPE,
+			MarginCallMessage::Rejection { .. } => MARGIN_CALL_REJECTION_TLV_TYPE,
+		}
+	}
+}
+
+impl MarginCallMessage {
+	/// Reads a [`MarginCallMessage`] of the given onion message TLV type, returning `Ok(None)` if
+	/// the type is not one of [`MARGIN_CALL_REQUEST_TLV_TYPE`] or
+	/// [`MARGIN_CALL_REJECTION_TLV_TYPE`].
+	///
+	/// Useful for [`CustomOnionMessageHandler`]s which handle other messages in addition to these.
+	pub fn read_custom_message<R: io::Read>(message_type: u64, buffer: &mut R) -> Result<Option<Self>, DecodeError> {
+		if message_type != MARGIN_CALL_REQUEST_TLV_TYPE && message_type != MARGIN_CALL_REJECTION_TLV_TYPE {
+			return Ok(None);
+		}
+		let message: Self = Readable::read(buffer)?;
+		if message.tlv_type() != message_type { return Err(DecodeError::InvalidValue); }
+		Ok(Some(message))
+	}
+
+	/// The bytes covered by the message's signature, i.e. all fields except for the signature
+	/// itself, prefixed by the message's TLV type.
+	fn signed_bytes(&self) -> Vec<u8> {
+		match self {
+			MarginCallMessage::Request { contract_id, top_up_satoshis, deadline, reply_path, .. } =>
+				margin_call_request_bytes(contract_id, *top_up_satoshis, *deadline, reply_path),
+			MarginCallMessage::Rejection { contract_id, top_up_satoshis, deadline, reason, .. } =>
+				margin_call_rejection_bytes(contract_id, *top_up_satoshis, *deadline, reason),
+		}
+	}
+}
+
+fn margin_call_request_bytes(
+	contract_id: &ContractId, top_up_satoshis: u64, deadline: u64, reply_path: &BlindedPath,
+) -> Vec<u8> {
+	((MARGIN_CALL_REQUEST_TLV_TYPE, contract_id), top_up_satoshis, deadline, reply_path).encode()
+}
+
+fn margin_call_rejection_bytes(
+	contract_id: &ContractId, top_up_satoshis: u64, deadline: u64, reason: &str,
+) -> Vec<u8> {
+	let mut bytes = ((MARGIN_CALL_REJECTION_TLV_TYPE, contract_id), top_up_satoshis, deadline).encode();
+	bytes.extend_from_slice(reason.as_bytes());
+	bytes
+}
+
+/// Provides signatures for the settlement transactions of contracts whose collateral output is
+/// locked to the funding key of one of our channels, as well as for messages about them.
+///
+/// This is implemented by the [`ChannelManager`], which uses the respective channel's signer.
+///
+/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+pub trait ContractSigner {
+	/// Computes adaptor signatures for the settlement transactions of all branches of `bundle`,
+	/// each encrypted to the entry of `adaptor_points` at the same index, using the funding key of
+	/// the given channel.
+	fn sign_settlement_transactions(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], bundle: &SettlementBundle,
+		adaptor_points: &[PublicKey],
+	) -> Result<Vec<EcdsaAdaptorSignature>, ()>;
+
+	/// Signs the settlement transaction for the branch of `bundle` at `branch_idx` using the
+	/// funding key of the given channel, e.g. to release a contract's collateral when settling it
+	/// off-chain.
+	fn sign_settlement_transaction(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], bundle: &SettlementBundle,
+		branch_idx: usize,
+	) -> Result<Signature, ()>;
+
+	/// Signs [`contract_message_digest`] of `msg` using the funding key of the given channel.
+	fn sign_contract_message(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], msg: &[u8],
+	) -> Result<Signature, ()>;
+}
+
+/// Sends payments directly to the counterparty of a contract over the channel the contract is
+/// embedded in, e.g. to transfer the balance owed when settling a contract off-chain.
+///
+/// This is implemented by the [`ChannelManager`].
+///
+/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+pub trait ContractPaymentSender {
+	/// Sends a spontaneous payment of `amount_msat` to our counterparty over the given channel,
+	/// providing `payment_metadata` to the recipient.
+	///
+	/// No further payment should be sent if one with the same `payment_id` is still pending.
+	fn send_contract_payment(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], amount_msat: u64,
+		payment_metadata: Vec<u8>, payment_id: PaymentId,
+	) -> Result<(), ()>;
+
+	/// Hands the fully signed `transaction` releasing a contract's collateral to the
+	/// [`ChannelMonitor`] of the given channel, which should broadcast it until the collateral
+	/// output is irrevocably spent.
+	///
+	/// [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+	fn watch_collateral_release(
+		&self, counterparty_node_id: &PublicKey, channel_id: &[u8; 32], transaction: &Transaction,
+	) -> Result<(), ()>;
+}
+
+/// Details of a contract tracked by a [`ContractManager`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContractDetails {
+	/// The id of the contract.
+	pub contract_id: ContractId,
+	/// The `node_id` of our counterparty in the contract.
+	pub counterparty_node_id: PublicKey,
+	/// The `channel_id` of the channel whose funding key locks the contract's collateral output.
+	pub channel_id: [u8; 32],
+	/// The contract's fully signed settlement bundle.
+	pub settlement_bundle: SettlementBundle,
+	/// Our contribution to the value of the contract's collateral output, in satoshis.
+	pub holder_collateral_satoshis: u64,
+	/// The id the contract will have once a pending renewal completes, if any.
+	pub pending_renewal_contract_id: Option<ContractId>,
+	/// The total amount we paid to top up the margin of the contract via
+	/// [`ContractManager::pay_margin_call`], in satoshis.
+	pub paid_margin_satoshis: u64,
+}
+
+/// The evidence that a superseded contract was settled according to its outdated terms, as
+/// surfaced via an [`Event::ContractDisputeDetected`].
+///
+/// This is self-contained, such that it can be handed off to operators or arbitration services
+/// as is. Further details about the state of the contract's channel can be obtained from its
+/// [`ChannelMonitor`], e.g. via [`ChainMonitor::get_monitor`].
+///
+/// [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+/// [`ChainMonitor::get_monitor`]: crate::chain::chainmonitor::ChainMonitor::get_monitor
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DisputePackage {
+	/// The id of the contract currently in force.
+	pub contract_id: ContractId,
+	/// The `node_id` of our counterparty in the contract.
+	pub counterparty_node_id: PublicKey,
+	/// The `channel_id` of the channel whose funding key locks the contract's collateral output.
+	pub channel_id: [u8; 32],
+	/// The fully signed settlement bundle of the contract currently in force.
+	pub settlement_bundle: SettlementBundle,
+	/// Our contribution to the value of the current contract's collateral output, in satoshis.
+	pub holder_collateral_satoshis: u64,
+	/// The outcome and the oracle's attestation to it recorded via
+	/// [`ContractManager::record_oracle_attestation`] for the current contract, if any.
+	pub oracle_attestation: Option<(Vec<u8>, SecretKey)>,
+	/// The current contract's settlement transaction for the attested outcome, if an attestation
+	/// was recorded.
+	///
+	/// This lacks the parties' signatures. It can be fully signed via
+	/// [`SettlementBundle::build_settlement_transaction_from_attestation`] with our signature for
+	/// it.
+	pub settlement_transaction: Option<Transaction>,
+	/// The id of the superseded contract which was settled.
+	pub outdated_contract_id: ContractId,
+	/// The fully signed settlement bundle of the superseded contract.
+	pub outdated_settlement_bundle: SettlementBundle,
+	/// The outcome the superseded contract was settled for.
+	pub outdated_outcome: Vec<u8>,
+	/// The confirmed settlement transaction of the superseded contract, including its witness.
+	pub outdated_settlement_transaction: Transaction,
+	/// The hash of the block the outdated settlement transaction confirmed in.
+	pub block_hash: BlockHash,
+	/// The height of the block the outdated settlement transaction confirmed in.
+	pub confirmation_height: u32,
+}
+
+impl_writeable_tlv_based!(DisputePackage, {
+	(0, contract_id, required),
+	(2, counterparty_node_id, required),
+	(4, channel_id, required),
+	(6, settlement_bundle, required),
+	(8, holder_collateral_satoshis, required),
+	(10, oracle_attestation, option),
+	(12, settlement_transaction, option),
+	(14, outdated_contract_id, required),
+	(16, outdated_settlement_bundle, required),
+	(18, outdated_outcome, required),
+	(20, outdated_settlement_transaction, required),
+	(22, block_hash, required),
+	(24, confirmation_height, required),
+});
+
+/// A share of a collateral pool allocated to a contract, see the [module-level documentation].
+///
+/// [module-level documentation]: crate::ln::contractmanager#collateral-pools
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolAllocation {
+	/// The contract the collateral is allocated to.
+	pub contract_id: ContractId,
+	/// The share of our contribution to the pool allocated to the contract, in satoshis.
+	pub holder_collateral_satoshis: u64,
+	/// The share of our counterparty's contribution to the pool allocated to the contract, in
+	/// satoshis.
+	pub counterparty_collateral_satoshis: u64,
+}
+
+impl_writeable_tlv_based!(PoolAllocation, {
+	(0, contract_id, required),
+	(2, holder_collateral_satoshis, required),
+	(4, counterparty_collateral_satoshis, required),
+});
+
+/// Details of a collateral pool tracked by a [`ContractManager`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollateralPoolDetails {
+	/// The `node_id` of our counterparty in all of the pool's contracts.
+	pub counterparty_node_id: PublicKey,
+	/// The `channel_id` of the channel whose funding key locks the pool's collateral output.
+	pub channel_id: [u8; 32],
+	/// The pool's collateral output.
+	pub collateral: CollateralOutput,
+	/// Our contribution to the value of the pool's collateral output, in satoshis.
+	pub holder_contribution_satoshis: u64,
+	/// The shares of the pool allocated to contracts.
+	pub allocations: Vec<PoolAllocation>,
+	/// The allocation we proposed which our counterparty has yet to accept, if any.
+	pub pending_allocation: Option<PoolAllocation>,
+}
+
+impl CollateralPoolDetails {
+	/// The part of our contribution to the pool not allocated to any contract, including
+	/// [`Self::pending_allocation`], in satoshis.
+	pub fn holder_available_satoshis(&self) -> u64 {
+		let allocated: u64 = self.allocations.iter().chain(self.pending_allocation.iter())
+			.map(|allocation| allocation.holder_collateral_satoshis).sum();
+		self.holder_contribution_satoshis.saturating_sub(allocated)
+	}
+
+	/// The part of our counterparty's contribution to the pool not allocated to any contract,
+	/// including [`Self::pending_allocation`], in satoshis.
+	pub fn counterparty_available_satoshis(&self) -> u64 {
+		let allocated: u64 = self.allocations.iter().chain(self.pending_allocation.iter())
+			.map(|allocation| allocation.counterparty_collateral_satoshis).sum();
+		(self.collateral.value_satoshis - self.holder_contribution_satoshis).saturating_sub(allocated)
+	}
+
+	/// Checks that a contract allocated a share of the pool spends the pool's collateral output,
+	/// with our share of the allocation as our contribution.
+	fn backs_contract(
+		&self, allocation: &PoolAllocation, counterparty_node_id: &PublicKey, channel_id: &[u8; 32],
+		collateral: &CollateralOutput, holder_collateral_satoshis: u64,
+	) -> bool {
+		self.counterparty_node_id == *counterparty_node_id && self.channel_id == *channel_id
+			&& collateral.outpoint == self.collateral.outpoint
+			&& allocation.holder_collateral_satoshis == holder_collateral_satoshis
+	}
+
+	/// Checks whether `allocation` fits into the pool alongside all existing allocations and
+	/// doesn't duplicate one.
+	fn can_allocate(&self, allocation: &PoolAllocation) -> bool {
+		if self.allocations.iter().chain(self.pending_allocation.iter())
+			.any(|existing| existing.contract_id == allocation.contract_id)
+		{
+			return false;
+		}
+		allocation.holder_collateral_satoshis <= self.holder_available_satoshis()
+			&& allocation.counterparty_collateral_satoshis <= self.counterparty_available_satoshis()
+	}
+}
+
+impl_writeable_tlv_based!(CollateralPoolDetails, {
+	(0, counterparty_node_id, required),
+	(2, channel_id, required),
+	(4, collateral, required),
+	(6, holder_contribution_satoshis, required),
+	(8, allocations, optional_vec),
+	(10, pending_allocation, option),
+});
+
+/// Which way our payout from a contract moves across its outcomes, see
+/// [`ContractPosition::direction`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContractDirection {
+	/// Our payout for the last outcome is higher than for the first.
+	Long,
+	/// Our payout for the last outcome is lower than for the first.
+	Short,
+	/// Our payout for the first and last outcomes is the same.
+	Neutral,
+}
+
+impl_writeable_tlv_based_enum!(ContractDirection,
+	(0, Long) => {},
+	(2, Short) => {},
+	(4, Neutral) => {};
+);
+
+/// Our position in a single contract tracked by a [`ContractManager`], as reported in a
+/// [`PortfolioSnapshot`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContractPosition {
+	/// The id of the contract.
+	pub contract_id: ContractId,
+	/// The `node_id` of our counterparty in the contract.
+	pub counterparty_node_id: PublicKey,
+	/// The `channel_id` of the channel whose funding key locks the contract's collateral output.
+	pub channel_id: [u8; 32],
+	/// The direction of our position.
+	///
+	/// This compares our payouts for the first and last branches of the contract's
+	/// [`SettlementBundle`], and is thus only meaningful if its branches are ordered, as they are
+	/// for bundles built from a [`PayoutCurve`].
+	///
+	/// [`PayoutCurve`]: crate::ln::contracts::PayoutCurve
+	pub direction: ContractDirection,
+	/// The value of the contract's collateral output, i.e. the total amount at stake between us
+	/// and our counterparty, in satoshis.
+	pub notional_satoshis: u64,
+	/// The lock time of the contract's settlement transactions, i.e. the block height or
+	/// timestamp from which the contract can be settled on-chain.
+	pub maturity: u32,
+	/// Our contribution to the value of the contract's collateral output, in satoshis.
+	pub holder_collateral_satoshis: u64,
+	/// Our lowest payout across all outcomes of the contract, in satoshis.
+	pub min_holder_payout_satoshis: u64,
+	/// Our highest payout across all outcomes of the contract, in satoshis.
+	pub max_holder_payout_satoshis: u64,
+}
+
+impl ContractPosition {
+	/// The most we can lose on the contract, i.e. by how much our contribution to its collateral
+	/// exceeds our lowest payout, in satoshis.
+	pub fn max_loss_satoshis(&self) -> u64 {
+		self.holder_collateral_satoshis.saturating_sub(self.min_holder_payout_satoshis)
+	}
+
+	/// The most we can gain on the contract, i.e. by how much our highest payout exceeds our
+	/// contribution to its collateral, in satoshis.
+	pub fn max_gain_satoshis(&self) -> u64 {
+		self.max_holder_payout_satoshis.saturating_sub(self.holder_collateral_satoshis)
+	}
+}
+
+impl_writeable_tlv_based!(ContractPosition, {
+	(0, contract_id, required),
+	(2, counterparty_node_id, required),
+	(4, channel_id, required),
+	(6, direction, required),
+	(8, notional_satoshis, required),
+	(10, maturity, required),
+	(12, holder_collateral_satoshis, required),
+	(14, min_holder_payout_satoshis, required),
+	(16, max_holder_payout_satoshis, required),
+});
+
+/// A snapshot of our open contracts together with the balances of our channels, as returned by
+/// [`ContractManager::portfolio_snapshot`].
+///
+/// Snapshots can be serialized, e.g. to be handed off to a dashboard.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortfolioSnapshot {
+	/// Our positions in all tracked contracts, ordered by maturity.
+	pub positions: Vec<ContractPosition>,
+	/// The sum of our contributions to the collateral of all tracked contracts, in satoshis.
+	pub total_collateral_satoshis: u64,
+	/// The sum of [`ContractPosition::max_loss_satoshis`] across all tracked contracts.
+	pub total_max_loss_satoshis: u64,
+	/// The sum of [`ContractPosition::max_gain_satoshis`] across all tracked contracts.
+	pub total_max_gain_satoshis: u64,
+	/// The sum of [`ChannelDetails::balance_msat`] across the channels the snapshot was taken
+	/// with.
+	pub lightning_balance_msat: u64,
+	/// The sum of [`ChannelDetails::outbound_capacity_msat`] across the channels the snapshot
+	/// was taken with.
+	pub outbound_capacity_msat: u64,
+	/// The sum of [`ChannelDetails::inbound_capacity_msat`] across the channels the snapshot
+	/// was taken with.
+	pub inbound_capacity_msat: u64,
+}
+
+impl_writeable_tlv_based!(PortfolioSnapshot, {
+	(0, positions, optional_vec),
+	(2, total_collateral_satoshis, required),
+	(4, total_max_loss_satoshis, required),
+	(6, total_max_gain_satoshis, required),
+	(8, lightning_balance_msat, required),
+	(10, outbound_capacity_msat, required),
+	(12, inbound_capacity_msat, required),
+});
+
+/// The weight of a transaction spending a single P2WPKH output to a single P2WPKH output, i.e. of
+/// the child we'd broadcast to bump the fee of a settlement transaction via CPFP.
+const CPFP_CHILD_WEIGHT: u64 = 438;
+
+/// The parameters a renewal proposal is evaluated under by
+/// [`ContractManager::evaluate_renewal_proposal`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OfferEvaluationConfig {
+	/// The lowest feerate we expect to have to settle a contract on-chain at, in satoshis per 1000
+	/// weight units, which determines the best-case settlement cost.
+	pub min_feerate_sat_per_1000_weight: u32,
+	/// The highest feerate we want to be able to settle a contract on-chain at, in satoshis per
+	/// 1000 weight units, which determines the worst-case settlement cost.
+	pub max_feerate_sat_per_1000_weight: u32,
+	/// The on-chain funds we keep available to bump the fees of our transactions, e.g. via anchor
+	/// outputs, in satoshis.
+	pub anchor_reserve_satoshis: u64,
+}
+
+/// The estimated cost of settling a single branch of a contract on-chain, as reported in an
+/// [`OfferEvaluation`].
+///
+/// As settlement transactions are pre-signed, their fee can only be bumped via CPFP, which requires
+/// the settlement transaction to pay us a non-dust output for the child to spend. The cost of a
+/// branch we can bump is the fee we'd have to add via a CPFP child for the settlement transaction to
+/// reach a given feerate. Branches we can't bump are left to our counterparty, and cost us nothing.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BranchSettlementCost {
+	/// The oracle outcome the branch corresponds to.
+	pub outcome: Vec<u8>,
+	/// Our payout in the branch, in satoshis.
+	pub holder_payout_satoshis: u64,
+	/// Whether the settlement transaction pays us an output a CPFP child could spend, i.e. whether
+	/// our payout isn't dust.
+	pub bumpable: bool,
+	/// The cost of settling the branch at [`OfferEvaluationConfig::min_feerate_sat_per_1000_weight`],
+	/// in satoshis.
+	pub min_cost_satoshis: u64,
+	/// The cost of settling the branch at [`OfferEvaluationConfig::max_feerate_sat_per_1000_weight`],
+	/// in satoshis.
+	pub max_cost_satoshis: u64,
+}
+
+impl BranchSettlementCost {
+	/// The part of [`Self::max_cost_satoshis`] we can't pay out of our own payout in the branch, and
+	/// which thus has to be covered by our reserves.
+	pub fn uncovered_cost_satoshis(&self) -> u64 {
+		self.max_cost_satoshis.saturating_sub(self.holder_payout_satoshis)
+	}
+}
+
+/// What [`ContractManager::evaluate_renewal_proposal`] recommends doing with a renewal proposal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OfferRecommendation {
+	/// Our anchor reserve covers the worst-case settlement cost of every branch.
+	Accept,
+	/// Settling some branch may require spending our channel reserve, which only becomes available
+	/// once the channel has been force-closed, and is thus only relied upon for channels supporting
+	/// anchor outputs, whose commitment transaction we can bump.
+	Warn {
+		/// A human-readable explanation of the warning.
+		reason: String,
+	},
+	/// Our reserves can't cover the worst-case settlement cost of some branch.
+	Reject {
+		/// A human-readable explanation of the rejection.
+		reason: String,
+	},
+}
+
+/// The result of [`ContractManager::evaluate_renewal_proposal`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OfferEvaluation {
+	/// The id of the contract being renewed.
+	pub contract_id: ContractId,
+	/// The id the contract will have once renewed.
+	pub new_contract_id: ContractId,
+	/// The estimated settlement cost of each branch of the new contract, in the order of its
+	/// [`SettlementBundle`].
+	pub branch_costs: Vec<BranchSettlementCost>,
+	/// The lowest [`BranchSettlementCost::min_cost_satoshis`] across all branches.
+	pub best_case_cost_satoshis: u64,
+	/// The highest [`BranchSettlementCost::max_cost_satoshis`] across all branches.
+	pub worst_case_cost_satoshis: u64,
+	/// The highest [`BranchSettlementCost::uncovered_cost_satoshis`] across all branches.
+	pub uncovered_cost_satoshis: u64,
+	/// Our reserve in the contract's channel, in satoshis, or zero if the channel wasn't provided or
+	/// doesn't support anchor outputs.
+	pub channel_reserve_satoshis: u64,
+	/// What we recommend doing with the proposal.
+	pub recommendation: OfferRecommendation,
+}
+
+/// Estimates the fee we'd have to add via CPFP for the settlement transaction of the branch at
+/// `branch_idx` to reach the given feerate, or zero if it doesn't pay us an output to bump it with.
+fn settlement_cost_satoshis(bundle: &SettlementBundle, branch_idx: usize, feerate_sat_per_1000_weight: u32) -> u64 {
+	if !settlement_bumpable(bundle, branch_idx) { return 0; }
+	let weight = bundle.settlement_transaction_weight(branch_idx);
+	let presigned_fee_satoshis = bundle.settlement_fee_satoshis(branch_idx);
+	if presigned_fee_satoshis >= fee_for_weight(feerate_sat_per_1000_weight, weight) { return 0; }
+	fee_for_weight(feerate_sat_per_1000_weight, weight + CPFP_CHILD_WEIGHT).saturating_sub(presigned_fee_satoshis)
+}
+
+/// Checks whether the settlement transaction of the branch at `branch_idx` pays us an output a CPFP
+/// child could spend.
+fn settlement_bumpable(bundle: &SettlementBundle, branch_idx: usize) -> bool {
+	let holder_payout_satoshis = bundle.branches()[branch_idx].holder_payout_satoshis;
+	holder_payout_satoshis > 0 && holder_payout_satoshis >= bundle.dust_limit_satoshis()
+}
+
+/// The number of [`ContractManager::timer_tick_occurred`] calls after which we broadcast the
+/// settlement transaction of a matured contract if our counterparty didn't accept settling it
+/// off-chain.
+pub const MUTUAL_SETTLEMENT_TIMEOUT_TICKS: u16 = 30;
+
+/// The number of [`ContractManager::timer_tick_occurred`] calls between rebroadcasts of the
+/// settlement transaction of a matured contract.
+pub const SETTLEMENT_REBROADCAST_INTERVAL_TICKS: u16 = 6;
+
+/// Lock times below this are block heights, and UNIX timestamps otherwise.
+const LOCK_TIME_THRESHOLD: u32 = 500_000_000;
+
+/// The progress of automatically exercising a matured contract, see
+/// [`Event::ContractExerciseProgress`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ContractExerciseStatus {
+	/// The contract matured, but no oracle attestation was recorded for it yet. It should be
+	/// fetched from the oracle and provided via [`ContractManager::record_oracle_attestation`].
+	AttestationRequested {
+		/// The lock time of the contract's settlement transactions.
+		maturity: u32,
+	},
+	/// We proposed settling the contract off-chain for the attested outcome.
+	MutualSettlementProposed {
+		/// The attested outcome.
+		outcome: Vec<u8>,
+	},
+	/// We (re-)broadcast the settlement transaction for the attested outcome as our counterparty
+	/// didn't accept settling the contract off-chain.
+	SettlementBroadcast {
+		/// The attested outcome.
+		outcome: Vec<u8>,
+		/// The fully signed settlement transaction.
+		transaction: Transaction,
+		/// The feerate we'd like the settlement transaction to confirm at.
+		feerate_sat_per_1000_weight: u32,
+		/// The fee a child transaction spending our output of the settlement transaction would
+		/// have to add to reach `feerate_sat_per_1000_weight`, or 0 if its presigned fee suffices.
+		cpfp_fee_satoshis: u64,
+	},
+}
+
+impl_writeable_tlv_based_enum!(ContractExerciseStatus,
+	(0, AttestationRequested) => {
+		(0, maturity, required),
+	},
+	(2, MutualSettlementProposed) => {
+		(0, outcome, required_vec),
+	},
+	(4, SettlementBroadcast) => {
+		(0, outcome, required_vec),
+		(2, transaction, required),
+		(4, feerate_sat_per_1000_weight, required),
+		(6, cpfp_fee_satoshis, required),
+	};
+);
+
+enum ExerciseState {
+	/// The contract matured and we're waiting for the user to record the oracle's attestation.
+	AwaitingAttestation,
+	/// We proposed settling the contract off-chain `ticks` timer ticks ago.
+	AwaitingCounterparty { ticks: u16 },
+	/// We broadcast the settlement transaction, last `ticks` timer ticks ago, asking for a CPFP fee
+	/// of up to `cpfp_fee_satoshis`.
+	Broadcast { transaction: Transaction, ticks: u16, cpfp_fee_satoshis: u64 },
+}
+
+impl_writeable_tlv_based_enum!(ExerciseState,
+	(0, AwaitingAttestation) => {},
+	(2, AwaitingCounterparty) => {
+		(0, ticks, required),
+	},
+	(4, Broadcast) => {
+		(0, transaction, required),
+		(2, ticks, required),
+		(4, cpfp_fee_satoshis, required),
+	};
+);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RenewalState {
+	/// We sent a proposal and are waiting for our counterparty's signatures.
+	ProposalSent,
+	/// We received a proposal and are waiting for the user to accept or reject it.
+	ProposalReceived,
+	/// We accepted a proposal and are waiting for our counterparty's signatures.
+	AcceptSent,
+}
+
+impl_writeable_tlv_based_enum!(RenewalState,
+	(0, ProposalSent) => {},
+	(2, ProposalReceived) => {},
+	(4, AcceptSent) => {};
+);
+
+struct PendingRenewal {
+	new_contract_id: ContractId,
+	bundle: SettlementBundle,
+	adaptor_points: Vec<PublicKey>,
+	state: RenewalState,
+	holder_collateral_satoshis: u64,
+	// The number of timer ticks we've been waiting for our counterparty's signatures.
+	ticks: u16,
+}
+
+impl_writeable_tlv_based!(PendingRenewal, {
+	(0, new_contract_id, required),
+	(2, bundle, required),
+	(4, adaptor_points, required_vec),
+	(6, state, required),
+	(8, holder_collateral_satoshis, required),
+	(10, ticks, (default_value, 0)),
+});
+
+struct ReceivedSettlement {
+	outcome: Vec<u8>,
+	release_signature: Option<Signature>,
+}
+
+impl_writeable_tlv_based!(ReceivedSettlement, {
+	(0, outcome, required),
+	(2, release_signature, option),
+});
+
+struct ConfirmedSettlement {
+	outcome: Vec<u8>,
+	// The height of the block the settlement transaction confirmed in.
+	confirmation_height: u32,
+}
+
+impl_writeable_tlv_based!(ConfirmedSettlement, {
+	(0, outcome, required),
+	(2, confirmation_height, required),
+});
+
+struct PendingRelease {
+	outcome: Vec<u8>,
+	transaction: Transaction,
+	// The height of the block the release transaction confirmed in, if it did.
+	confirmation_height: Option<u32>,
+}
+
+impl_writeable_tlv_based!(PendingRelease, {
+	(0, outcome, required),
+	(2, transaction, required),
+	(4, confirmation_height, option),
+});
+
+/// The terms of a [`ContractMessage::RenewalProposal`] from the proposer's point of view, whose
+/// serialization is the offer the id of the renewed contract is derived from.
+struct RenewalOffer {
+	contract_id: ContractId,
+	collateral: CollateralOutput,
+	lock_time: u32,
+	branches: Vec<SettlementBranch>,
+	adaptor_points: Vec<PublicKey>,
+	proposer_collateral_satoshis: u64,
+}
+
+impl_writeable_tlv_based!(RenewalOffer, {
+	(0, contract_id, required),
+	(2, collateral, required),
+	(4, lock_time, required),
+	(6, branches, required_vec),
+	(8, adaptor_points, required_vec),
+	(10, proposer_collateral_satoshis, required),
+});
+
+struct MarginCall {
+	top_up_satoshis: u64,
+	deadline: u64,
+	// Only set for margin calls we received.
+	reply_path: Option<BlindedPath>,
+}
+
+impl_writeable_tlv_based!(MarginCall, {
+	(0, top_up_satoshis, required),
+	(2, deadline, required),
+	(4, reply_path, option),
+});
+
+struct SupersededContract {
+	contract_id: ContractId,
+	bundle: SettlementBundle,
+	// The height at which a transaction spending the collateral output confirmed, after which
+	// the superseded contract is pruned once the spend can no longer be reorged out.
+	spend_height: Option<u32>,
+}
+
+impl_writeable_tlv_based!(SupersededContract, {
+	(0, contract_id, required),
+	(2, bundle, required),
+	(4, spend_height, option),
+});
+
+struct Contract {
+	counterparty_node_id: PublicKey,
+	channel_id: [u8; 32],
+	bundle: SettlementBundle,
+	pending_renewal: Option<PendingRenewal>,
+	holder_collateral_satoshis: u64,
+	// The outcome we proposed settling the contract for, if any.
+	pending_settlement: Option<Vec<u8>>,
+	// The settlement our counterparty proposed which the user has not accepted or rejected yet.
+	received_settlement: Option<ReceivedSettlement>,
+	// The agreed release of the contract's collateral, waiting to confirm.
+	release: Option<PendingRelease>,
+	// The margin call we sent which has not been rejected, if any.
+	sent_margin_call: Option<MarginCall>,
+	// The margin call we received which has not been paid or rejected yet, if any.
+	received_margin_call: Option<MarginCall>,
+	// The deadline of the latest margin call we received, used to ignore replayed requests.
+	latest_margin_call_deadline: u64,
+	// The total amount of the margin calls we paid since the contract was registered or renewed.
+	paid_margin_satoshis: u64,
+	// The contracts this one was renewed from, whose settlement transactions are still valid.
+	superseded_contracts: Vec<SupersededContract>,
+	// The oracle's attestation recorded via `record_oracle_attestation`, if any.
+	oracle_attestation: Option<(Vec<u8>, SecretKey)>,
+	// How far automatically exercising the contract got once it matured, if it did.
+	exercise: Option<ExerciseState>,
+	// The settlement transaction which confirmed on-chain, if any, until it reached
+	// `ANTI_REORG_DELAY` confirmations.
+	confirmed_settlement: Option<ConfirmedSettlement>,
+}
+
+impl_writeable_tlv_based!(Contract, {
+	(0, counterparty_node_id, required),
+	(2, channel_id, required),
+	(4, bundle, required),
+	(6, pending_renewal, option),
+	(8, holder_collateral_satoshis, required),
+	(10, pending_settlement, option),
+	(12, sent_margin_call, option),
+	(14, received_margin_call, option),
+	(16, latest_margin_call_deadline, (default_value, 0)),
+	(18, superseded_contracts, optional_vec),
+	(20, oracle_attestation, option),
+	(22, exercise, option),
+	(24, received_settlement, option),
+	(26, release, option),
+	(28, paid_margin_satoshis, (default_value, 0)),
+	(30, confirmed_settlement, option),
+});
+
+impl Contract {
+	fn is_quiescent(&self) -> bool {
+		self.pending_renewal.is_none() && self.pending_settlement.is_none() &&
+			self.received_settlement.is_none() && self.release.is_none()
+	}
+
+	fn has_matured(&self, best_block_height: u32, best_block_time: u32) -> bool {
+		let lock_time = self.bundle.lock_time();
+		if lock_time < LOCK_TIME_THRESHOLD { best_block_height >= lock_time } else { best_block_time >= lock_time }
+	}
+
+	fn position(&self, contract_id: ContractId) -> ContractPosition {
+		let branches = self.bundle.branches();
+		let payouts = || branches.iter().map(|branch| branch.holder_payout_satoshis);
+		// Bundles always have at least one branch.
+		let (first_payout, last_payout) = (branches[0].holder_payout_satoshis,
+			branches[branches.len() - 1].holder_payout_satoshis);
+		let direction = if last_payout > first_payout {
+			ContractDirection::Long
+		} else if last_payout < first_payout {
+			ContractDirection::Short
+		} else {
+			ContractDirection::Neutral
+		};
+		ContractPosition {
+			contract_id,
+			counterparty_node_id: self.counterparty_node_id,
+			channel_id: self.channel_id,
+			direction,
+			notional_satoshis: self.bundle.collateral().value_satoshis,
+			maturity: self.bundle.lock_time(),
+			holder_collateral_satoshis: self.holder_collateral_satoshis,
+			min_holder_payout_satoshis: payouts().min().unwrap_or(0),
+			max_holder_payout_satoshis: payouts().max().unwrap_or(0),
+		}
+	}
+}
+
+fn counterparty_collateral(collateral: &CollateralOutput) -> CollateralOutput {
+	CollateralOutput {
+		outpoint: collateral.outpoint,
+		value_satoshis: collateral.value_satoshis,
+		holder_funding_pubkey: collateral.counterparty_funding_pubkey,
+		counterparty_funding_pubkey: collateral.holder_funding_pubkey,
+	}
+}
+
+fn counterparty_branch(branch: &SettlementBranch) -> SettlementBranch {
+	SettlementBranch {
+		outcome: branch.outcome.clone(),
+		holder_payout_satoshis: branch.counterparty_payout_satoshis,
+		counterparty_payout_satoshis: branch.holder_payout_satoshis,
+	}
+}
+
+/// Builds the bundle releasing the contract's collateral when settling it off-chain for the branch
+/// at `branch_idx`, whose only branch returns each party's contribution to the collateral.
+///
+/// The release keeps the fee the branch's settlement transaction would pay. Both parties must
+/// build the same transaction, so the one with the lower funding pubkey pays the odd satoshi.
+fn release_bundle(contract: &Contract, branch_idx: usize) -> Result<SettlementBundle, ()> {
+	let bundle = &contract.bundle;
+	let collateral = bundle.collateral();
+	let branch = bundle.branches().get(branch_idx).ok_or(())?;
+	let fee_satoshis = collateral.value_satoshis
+		.checked_sub(branch.holder_payout_satoshis)
+		.and_then(|value| value.checked_sub(branch.counterparty_payout_satoshis))
+		.ok_or(())?;
+	let holder_fee_satoshis =
+		if collateral.holder_funding_pubkey.serialize() < collateral.counterparty_funding_pubkey.serialize() {
+			fee_satoshis - fee_satoshis / 2
+		} else {
+			fee_satoshis / 2
+		};
+	let counterparty_collateral_satoshis = collateral.value_satoshis
+		.checked_sub(contract.holder_collateral_satoshis).ok_or(())?;
+	let release_branch = SettlementBranch {
+		outcome: branch.outcome.clone(),
+		holder_payout_satoshis: contract.holder_collateral_satoshis.saturating_sub(holder_fee_satoshis),
+		counterparty_payout_satoshis: counterparty_collateral_satoshis
+			.saturating_sub(fee_satoshis - holder_fee_satoshis),
+	};
+	SettlementBundle::new(collateral.clone(), bundle.holder_payout_script().clone(),
+		bundle.counterparty_payout_script().clone(), 0, bundle.dust_limit_satoshis(), vec![release_branch])
+}
+
+/// Gets the index of the branch of `bundle` whose settlement transaction is `tx`, if any.
+fn settlement_branch_index(bundle: &SettlementBundle, tx: &Transaction) -> Option<usize> {
+	let collateral_outpoint = bundle.collateral().outpoint.into_bitcoin_outpoint();
+	if tx.input.len() != 1 || tx.input[0].previous_output != collateral_outpoint { return None; }
+	let txid = tx.txid();
+	(0..bundle.branches().len()).find(|idx| bundle.settlement_transaction(*idx).txid() == txid)
+}
+
+/// Checks that, if the given contract is allocated a share of a collateral pool, it spends the
+/// pool's collateral output according to its allocation.
+fn check_pool_collateral(
+	pools: &HashMap<OutPoint, CollateralPoolDetails>, contract_id: &ContractId,
+	counterparty_node_id: &PublicKey, channel_id: &[u8; 32], collateral: &CollateralOutput,
+	holder_collateral_satoshis: u64,
+) -> Result<(), &'static str> {
+	for pool in pools.values() {
+		let allocation = pool.allocations.iter().chain(pool.pending_allocation.iter())
+			.find(|allocation| allocation.contract_id == *contract_id);
+		if let Some(allocation) = allocation {
+			if !pool.backs_contract(allocation, counterparty_node_id, channel_id, collateral, holder_collateral_satoshis) {
+				return Err("The contract doesn't spend its collateral pool's output according to its allocation");
+			}
+		}
+	}
+	Ok(())
+}
+
+fn ignore_msg(err: &str) -> LightningError {
+	LightningError { err: err.to_owned(), action: ErrorAction::IgnoreAndLog(Level::Debug) }
+}
+
+/// Tracks contracts embedded in our channels and drives the protocols used to manage them with
+/// our counterparties, see the [module-level documentation] for details.
+///
+/// The `ContractManager` must be persisted via [`Writeable::write`] after each call which changes
+/// its state, including the handling of messages from peers, and read back via [`ReadableArgs`]
+/// on startup.
+///
+/// [module-level documentation]: crate::ln::contractmanager
+pub struct ContractManager<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	entropy_source: ES,
+	contract_signer: CS,
+	payment_sender: CP,
+	broadcaster: T,
+	fee_estimator: F,
+	logger: L,
+	secp_ctx: Secp256k1<secp256k1::All>,
+	// Our node id, which is committed to by the ids of contracts derived via `derive_contract_id`.
+	our_node_id: PublicKey,
+	contracts: Mutex<HashMap<ContractId, Contract>>,
+	oracle_policy: Mutex<Option<OraclePolicy>>,
+	collateral_pools: Mutex<HashMap<OutPoint, CollateralPoolDetails>>,
+	// How many ticks we've been waiting for a response to each pending pool allocation, which
+	// restarts from zero on reload.
+	pool_allocation_ticks: Mutex<HashMap<OutPoint, u16>>,
+	// The height and timestamp of the best block we've seen.
+	best_block: Mutex<(u32, u32)>,
+	pending_msgs: Mutex<Vec<(PublicKey, ContractMessage)>>,
+	pending_onion_msgs: Mutex<Vec<(MarginCallMessage, Destination, Option<BlindedPath>)>>,
+	pending_events: Mutex<Vec<Event>>,
+}
+
+/// A generic trait which is implemented for all [`ContractManager`]s. This makes bounding functions
+/// or structs on any [`ContractManager`] much simpler as only this trait is needed as a bound,
+/// rather than the full set of bounds on [`ContractManager`] itself.
+///
+/// This is not exported to bindings users as general cover traits aren't useful in other
+/// languages.
+#[allow(missing_docs)]
+pub trait AContractManager {
+	type EST: EntropySource + ?Sized;
+	type ES: Deref<Target=Self::EST>;
+	type CST: ContractSigner + ?Sized;
+	type CS: Deref<Target=Self::CST>;
+	type CPT: ContractPaymentSender + ?Sized;
+	type CP: Deref<Target=Self::CPT>;
+	type BT: BroadcasterInterface + ?Sized;
+	type T: Deref<Target=Self::BT>;
+	type FT: FeeEstimator + ?Sized;
+	type F: Deref<Target=Self::FT>;
+	type LT: Logger + ?Sized;
+	type L: Deref<Target=Self::LT>;
+	/// Gets a reference to the underlying [`ContractManager`].
+	fn as_ref(&self) -> &ContractManager<Self::ES, Self::CS, Self::CP, Self::T, Self::F, Self::L>;
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> AContractManager for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	type EST = <ES as Deref>::Target;
+	type ES = ES;
+	type CST = <CS as Deref>::Target;
+	type CS = CS;
+	type CPT = <CP as Deref>::Target;
+	type CP = CP;
+	type BT = <T as Deref>::Target;
+	type T = T;
+	type FT = <F as Deref>::Target;
+	type F = F;
+	type LT = <L as Deref>::Target;
+	type L = L;
+	fn as_ref(&self) -> &ContractManager<ES, CS, CP, T, F, L> { self }
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	/// Constructs a new `ContractManager` without any contracts.
+	///
+	/// `our_node_id` is committed to by the ids of contracts derived via [`derive_contract_id`],
+	/// e.g. as returned by [`ChannelManager::get_our_node_id`].
+	///
+	/// [`ChannelManager::get_our_node_id`]: crate::ln::channelmanager::ChannelManager::get_our_node_id
+	pub fn new(
+		entropy_source: ES, contract_signer: CS, payment_sender: CP, broadcaster: T, fee_estimator: F,
+		logger: L, our_node_id: PublicKey,
+	) -> Self {
+		Self::from_contracts(entropy_source, contract_signer, payment_sender, broadcaster, fee_estimator,
+			logger, our_node_id, HashMap::new(), None, HashMap::new(), 0, 0, Vec::new())
+	}
+
+	fn from_contracts(
+		entropy_source: ES, contract_signer: CS, payment_sender: CP, broadcaster: T, fee_estimator: F,
+		logger: L, our_node_id: PublicKey, contracts: HashMap<ContractId, Contract>, oracle_policy: Option<OraclePolicy>,
+		collateral_pools: HashMap<OutPoint, CollateralPoolDetails>, best_block_height: u32,
+		best_block_time: u32, pending_msgs: Vec<(PublicKey, ContractMessage)>,
+	) -> Self {
+		let mut secp_ctx = Secp256k1::new();
+		secp_ctx.seeded_randomize(&entropy_source.get_secure_random_bytes());
+		ContractManager {
+			entropy_source,
+			contract_signer,
+			payment_sender,
+			broadcaster,
+			fee_estimator,
+			logger,
+			secp_ctx,
+			our_node_id,
+			contracts: Mutex::new(contracts),
+			oracle_policy: Mutex::new(oracle_policy),
+			collateral_pools: Mutex::new(collateral_pools),
+			pool_allocation_ticks: Mutex::new(HashMap::new()),
+			best_block: Mutex::new((best_block_height, best_block_time)),
+			pending_msgs: Mutex::new(pending_msgs),
+			pending_onion_msgs: Mutex::new(Vec::new()),
+			pending_events: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Starts tracking a contract with the given counterparty, whose collateral output is locked
+	/// to the funding key of the channel with the given `channel_id`.
+	///
+	/// The `contract_id` must have been agreed upon with our counterparty when setting up the
+	/// contract, as it is used to refer to the contract in messages.
+	///
+	/// `holder_collateral_satoshis` is our contribution to the value of the collateral output,
+	/// which determines how much is owed to either party when settling the contract off-chain.
+	///
+	/// Fails if we don't have our counterparty's signatures for all branches of `bundle` yet, if
+	/// `holder_collateral_satoshis` exceeds the value of the collateral output, if the contract
+	/// is allocated a share of a collateral pool whose output `bundle` doesn't spend according to
+	/// the allocation, or if we already track a contract with the given `contract_id`.
+	pub fn register_contract(
+		&self, contract_id: ContractId, counterparty_node_id: PublicKey, channel_id: [u8; 32],
+		bundle: SettlementBundle, holder_collateral_satoshis: u64,
+	) -> Result<(), APIError> {
+		if !bundle.is_fully_signed() {
+			return Err(APIError::APIMisuseError {
+				err: "Contracts may only be registered once fully signed".to_owned()
+			});
+		}
+		if holder_collateral_satoshis > bundle.collateral().value_satoshis {
+			return Err(APIError::APIMisuseError {
+				err: "Our contribution may not exceed the value of the collateral output".to_owned()
+			});
+		}
+		check_pool_collateral(&self.collateral_pools.lock().unwrap(), &contract_id, &counterparty_node_id,
+			&channel_id, bundle.collateral(), holder_collateral_satoshis)
+			.map_err(|err| APIError::APIMisuseError { err: err.to_owned() })?;
+		match self.contracts.lock().unwrap().entry(contract_id) {
+			hash_map::Entry::Occupied(_) => Err(APIError::APIMisuseError {
+				err: format!("Contract {} is already registered", log_bytes!(contract_id.0))
+			}),
+			hash_map::Entry::Vacant(entry) => {
+				entry.insert(Contract {
+					counterparty_node_id, channel_id, bundle, pending_renewal: None,
+					holder_collateral_satoshis, pending_settlement: None, received_settlement: None,
+					release: None, sent_margin_call: None, received_margin_call: None, latest_margin_call_deadline: 0,
+					paid_margin_satoshis: 0, superseded_contracts: Vec::new(), oracle_attestation: None, exercise: None,
+					confirmed_settlement: None,
+				});
+				Ok(())
+			},
+		}
+	}
+
+	/// Stops tracking the given contract, e.g. once it has been settled, returning its settlement
+	/// bundle.
+	pub fn remove_contract(&self, contract_id: &ContractId) -> Result<SettlementBundle, APIError> {
+		self.contracts.lock().unwrap().remove(contract_id)
+			.map(|contract| contract.bundle)
+			.ok_or_else(|| APIError::APIMisuseError {
+				err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+			})
+	}
+
+	/// Gets the details of all tracked contracts.
+	pub fn list_contracts(&self) -> Vec<ContractDetails> {
+		self.contracts.lock().unwrap().iter().map(|(contract_id, contract)| ContractDetails {
+			contract_id: *contract_id,
+			counterparty_node_id: contract.counterparty_node_id,
+			channel_id: contract.channel_id,
+			settlement_bundle: contract.bundle.clone(),
+			holder_collateral_satoshis: contract.holder_collateral_satoshis,
+			pending_renewal_contract_id: contract.pending_renewal.as_ref()
+				.map(|renewal| renewal.new_contract_id),
+			paid_margin_satoshis: contract.paid_margin_satoshis,
+		}).collect()
+	}
+
+	/// Starts tracking a collateral pool with the given counterparty, whose collateral output is
+	/// locked to the funding key of the channel with the given `channel_id`. See the
+	/// [module-level documentation] for details.
+	///
+	/// `holder_contribution_satoshis` is our contribution to the value of the collateral output,
+	/// which must have been agreed upon with our counterparty when setting up the pool.
+	///
+	/// Fails if `holder_contribution_satoshis` exceeds the value of the collateral output or if we
+	/// already track a pool with the same collateral output.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager#collateral-pools
+	pub fn add_collateral_pool(
+		&self, counterparty_node_id: PublicKey, channel_id: [u8; 32], collateral: CollateralOutput,
+		holder_contribution_satoshis: u64,
+	) -> Result<(), APIError> {
+		if holder_contribution_satoshis > collateral.value_satoshis {
+			return Err(APIError::APIMisuseError {
+				err: "Our contribution may not exceed the value of the collateral output".to_owned()
+			});
+		}
+		match self.collateral_pools.lock().unwrap().entry(collateral.outpoint) {
+			hash_map::Entry::Occupied(_) => Err(APIError::APIMisuseError {
+				err: format!("Collateral pool {} is already tracked", collateral.outpoint.txid)
+			}),
+			hash_map::Entry::Vacant(entry) => {
+				entry.insert(CollateralPoolDetails {
+					counterparty_node_id, channel_id, collateral, holder_contribution_satoshis,
+					allocations: Vec::new(), pending_allocation: None,
+				});
+				Ok(())
+			},
+		}
+	}
+
+	/// Stops tracking the given collateral pool, e.g. once its collateral output was spent.
+	///
+	/// Fails if any of the pool's collateral is allocated to a contract.
+	pub fn remove_collateral_pool(&self, pool_outpoint: &OutPoint) -> Result<(), APIError> {
+		let mut pools = self.collateral_pools.lock().unwrap();
+		match pools.get(pool_outpoint) {
+			Some(pool) if pool.allocations.is_empty() && pool.pending_allocation.is_none() => {},
+			Some(_) => return Err(APIError::APIMisuseError {
+				err: format!("Collateral pool {} still has allocations", pool_outpoint.txid)
+			}),
+			None => return Err(APIError::APIMisuseError {
+				err: format!("Unknown collateral pool {}", pool_outpoint.txid)
+			}),
+		}
+		pools.remove(pool_outpoint);
+		Ok(())
+	}
+
+	/// Gets the details of all tracked collateral pools.
+	pub fn list_collateral_pools(&self) -> Vec<CollateralPoolDetails> {
+		self.collateral_pools.lock().unwrap().values().cloned().collect()
+	}
+
+	/// Proposes allocating `holder_collateral_satoshis` of our and
+	/// `counterparty_collateral_satoshis` of our counterparty's contribution to the given
+	/// collateral pool to a contract. See the [module-level documentation] for details.
+	///
+	/// The `contract_id` must have been agreed upon with our counterparty, usually ahead of
+	/// registering the contract via [`Self::register_contract`].
+	///
+	/// Fails if we already proposed an allocation for the pool which is still pending, if either
+	/// party's free contribution to the pool doesn't cover the allocation, if collateral is
+	/// already allocated to the contract, or if the contract is already registered but doesn't
+	/// spend the pool's collateral output with `holder_collateral_satoshis` as our contribution.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager#collateral-pools
+	pub fn propose_pool_allocation(
+		&self, pool_outpoint: &OutPoint, contract_id: ContractId, holder_collateral_satoshis: u64,
+		counterparty_collateral_satoshis: u64,
+	) -> Result<(), APIError> {
+		let contracts = self.contracts.lock().unwrap();
+		let mut pools = self.collateral_pools.lock().unwrap();
+		let pool = pools.get_mut(pool_outpoint).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown collateral pool {}", pool_outpoint.txid)
+		})?;
+		if pool.pending_allocation.is_some() {
+			return Err(APIError::APIMisuseError {
+				err: format!("An allocation of collateral pool {} is already pending", pool_outpoint.txid)
+			});
+		}
+		let allocation = PoolAllocation { contract_id, holder_collateral_satoshis, counterparty_collateral_satoshis };
+		if !pool.can_allocate(&allocation) {
+			return Err(APIError::APIMisuseError {
+				err: format!("Collateral pool {} can't cover an allocation to contract {}",
+					pool_outpoint.txid, log_bytes!(contract_id.0))
+			});
+		}
+		if let Some(contract) = contracts.get(&contract_id) {
+			if !pool.backs_contract(&allocation, &contract.counterparty_node_id, &contract.channel_id,
+				contract.bundle.collateral(), contract.holder_collateral_satoshis)
+			{
+				return Err(APIError::APIMisuseError {
+					err: format!("Contract {} doesn't spend the output of collateral pool {} according to the allocation",
+						log_bytes!(contract_id.0), pool_outpoint.txid)
+				});
+			}
+		}
+
+		self.pending_msgs.lock().unwrap().push((pool.counterparty_node_id, ContractMessage::PoolAllocationProposal {
+			pool_outpoint: *pool_outpoint, contract_id, holder_collateral_satoshis,
+			counterparty_collateral_satoshis,
+		}));
+		pool.pending_allocation = Some(allocation);
+		Ok(())
+	}
+
+	/// Releases the share of the given collateral pool allocated to a contract which we don't
+	/// track, e.g. as it was never registered, notifying our counterparty.
+	///
+	/// Allocations to contracts we track are released automatically once they are settled.
+	pub fn release_pool_allocation(&self, pool_outpoint: &OutPoint, contract_id: &ContractId) -> Result<(), APIError> {
+		let contracts = self.contracts.lock().unwrap();
+		if contracts.contains_key(contract_id) {
+			return Err(APIError::APIMisuseError {
+				err: format!("Contract {} must be settled to release its allocation", log_bytes!(contract_id.0))
+			});
+		}
+		let mut pools = self.collateral_pools.lock().unwrap();
+		let pool = pools.get_mut(pool_outpoint).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown collateral pool {}", pool_outpoint.txid)
+		})?;
+		let allocation_count = pool.allocations.len();
+		pool.allocations.retain(|allocation| allocation.contract_id != *contract_id);
+		if pool.allocations.len() == allocation_count {
+			return Err(APIError::APIMisuseError {
+				err: format!("No collateral of pool {} is allocated to contract {}", pool_outpoint.txid,
+					log_bytes!(contract_id.0))
+			});
+		}
+		self.pending_msgs.lock().unwrap().push((pool.counterparty_node_id, ContractMessage::PoolAllocationRelease {
+			pool_outpoint: *pool_outpoint, contract_id: *contract_id,
+		}));
+		Ok(())
+	}
+
+	/// Takes a [`PortfolioSnapshot`] of all tracked contracts, together with the balances of the
+	/// given channels, usually those returned by [`ChannelManager::list_channels`].
+	///
+	/// [`ChannelManager::list_channels`]: crate::ln::channelmanager::ChannelManager::list_channels
+	pub fn portfolio_snapshot(&self, channels: &[ChannelDetails]) -> PortfolioSnapshot {
+		let mut positions = self.contracts.lock().unwrap().iter()
+			.map(|(contract_id, contract)| contract.position(*contract_id))
+			.collect::<Vec<_>>();
+		positions.sort_unstable_by_key(|position| (position.maturity, position.contract_id.0));
+		PortfolioSnapshot {
+			total_collateral_satoshis: positions.iter().map(|position| position.holder_collateral_satoshis).sum(),
+			total_max_loss_satoshis: positions.iter().map(|position| position.max_loss_satoshis()).sum(),
+			total_max_gain_satoshis: positions.iter().map(|position| position.max_gain_satoshis()).sum(),
+			positions,
+			lightning_balance_msat: channels.iter().map(|channel| channel.balance_msat).sum(),
+			outbound_capacity_msat: channels.iter().map(|channel| channel.outbound_capacity_msat).sum(),
+			inbound_capacity_msat: channels.iter().map(|channel| channel.inbound_capacity_msat).sum(),
+		}
+	}
+
+	/// Evaluates the renewal of the given contract proposed by our counterparty, estimating the
+	/// cost of settling each branch of the new contract on-chain under the feerates in `config` and
+	/// checking whether our reserves can cover it. See the [module-level documentation] for
+	/// details.
+	///
+	/// `channels` should contain the contract's channel, usually as returned by
+	/// [`ChannelManager::list_channels`], to account for our channel reserve.
+	///
+	/// Fails if no renewal of the contract is awaiting acceptance.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager
+	/// [`ChannelManager::list_channels`]: crate::ln::channelmanager::ChannelManager::list_channels
+	pub fn evaluate_renewal_proposal(
+		&self, contract_id: &ContractId, channels: &[ChannelDetails], config: &OfferEvaluationConfig,
+	) -> Result<OfferEvaluation, APIError> {
+		let contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		let renewal = match contract.pending_renewal.as_ref() {
+			Some(renewal) if renewal.state == RenewalState::ProposalReceived => renewal,
+			_ => return Err(APIError::APIMisuseError {
+				err: format!("No renewal of contract {} is awaiting acceptance", log_bytes!(contract_id.0))
+			}),
+		};
+
+		let branch_costs = renewal.bundle.branches().iter().enumerate().map(|(idx, branch)| BranchSettlementCost {
+			outcome: branch.outcome.clone(),
+			holder_payout_satoshis: branch.holder_payout_satoshis,
+			bumpable: settlement_bumpable(&renewal.bundle, idx),
+			min_cost_satoshis: settlement_cost_satoshis(&renewal.bundle, idx, config.min_feerate_sat_per_1000_weight),
+			max_cost_satoshis: settlement_cost_satoshis(&renewal.bundle, idx, config.max_feerate_sat_per_1000_weight),
+		}).collect::<Vec<_>>();
+		let best_case_cost_satoshis = branch_costs.iter().map(|cost| cost.min_cost_satoshis).min().unwrap_or(0);
+		let worst_case_cost_satoshis = branch_costs.iter().map(|cost| cost.max_cost_satoshis).max().unwrap_or(0);
+		let uncovered_cost_satoshis = branch_costs.iter().map(|cost| cost.uncovered_cost_satoshis()).max().unwrap_or(0);
+		let channel_reserve_satoshis = channels.iter()
+			.find(|channel| channel.channel_id == contract.channel_id)
+			.filter(|channel| channel.channel_type.as_ref()
+				.map_or(false, |channel_type| channel_type.supports_anchors_zero_fee_htlc_tx()))
+			.and_then(|channel| channel.unspendable_punishment_reserve)
+			.unwrap_or(0);
+
+		let recommendation = if uncovered_cost_satoshis > config.anchor_reserve_satoshis.saturating_add(channel_reserve_satoshis) {
+			OfferRecommendation::Reject {
+				reason: format!("Settling may cost up to {} sat, exceeding our reserves of {} sat",
+					uncovered_cost_satoshis, config.anchor_reserve_satoshis.saturating_add(channel_reserve_satoshis)),
+			}
+		} else if uncovered_cost_satoshis > config.anchor_reserve_satoshis {
+			OfferRecommendation::Warn {
+				reason: format!("Settling may cost up to {} sat, exceeding our anchor reserve of {} sat",
+					uncovered_cost_satoshis, config.anchor_reserve_satoshis),
+			}
+		} else {
+			OfferRecommendation::Accept
+		};
+		Ok(OfferEvaluation {
+			contract_id: *contract_id, new_contract_id: renewal.new_contract_id, branch_costs,
+			best_case_cost_satoshis, worst_case_cost_satoshis, uncovered_cost_satoshis,
+			channel_reserve_satoshis, recommendation,
+		})
+	}
+
+	/// Sets the [`OraclePolicy`] renewals must comply with, or removes it, refusing all renewals
+	/// until a policy is set again. See the [module-level documentation] for details.
+	///
+	/// Renewals which are already pending are not re-validated against a new policy, though
+	/// pending renewals can no longer be accepted once the policy has been removed.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager
+	pub fn set_oracle_policy(&self, oracle_policy: Option<OraclePolicy>) {
+		*self.oracle_policy.lock().unwrap() = oracle_policy;
+	}
+
+	/// Gets the [`OraclePolicy`] set via [`Self::set_oracle_policy`], if any.
+	pub fn oracle_policy(&self) -> Option<OraclePolicy> {
+		self.oracle_policy.lock().unwrap().clone()
+	}
+
+	/// Proposes renewing the given contract to a new settlement bundle spending `collateral` with
+	/// the given branches and lock time, returning the id the contract will have once renewed.
+	///
+	/// The payout scripts and dust limit of the contract's current settlement bundle are reused.
+	/// The contract is quiesced until the renewal completes, see the [module-level documentation]
+	/// for details.
+	///
+	/// The `adaptor_points` must be the attestation points `oracle_announcement` announced for
+	/// the outcomes of the `branches`.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager
+	pub fn propose_contract_renewal(
+		&self, contract_id: &ContractId, collateral: CollateralOutput, lock_time: u32,
+		branches: Vec<SettlementBranch>, adaptor_points: Vec<PublicKey>, holder_collateral_satoshis: u64,
+		oracle_announcement: OracleAnnouncement,
+	) -> Result<ContractId, APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let counterparty_node_id = contracts.get(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?.counterparty_node_id;
+		let new_contract_id = self.renewal_contract_id(&counterparty_node_id, &RenewalOffer {
+			contract_id: *contract_id, collateral: collateral.clone(), lock_time, branches: branches.clone(),
+			adaptor_points: adaptor_points.clone(), proposer_collateral_satoshis: holder_collateral_satoshis,
+		});
+		if contracts.contains_key(&new_contract_id) {
+			return Err(APIError::APIMisuseError {
+				err: format!("Contract {} is already registered", log_bytes!(new_contract_id.0))
+			});
+		}
+		let contract = contracts.get_mut(contract_id).unwrap();
+		if !contract.is_quiescent() {
+			return Err(APIError::APIMisuseError {
+				err: format!("A renewal or settlement of contract {} is already pending", log_bytes!(contract_id.0))
+			});
+		}
+		if adaptor_points.len() != branches.len() {
+			return Err(APIError::APIMisuseError {
+				err: "Exactly one adaptor point must be provided per branch".to_owned()
+			});
+		}
+		if let Err(violation) = oracle_announcement.validate_adaptor_points(&branches, &adaptor_points) {
+			return Err(APIError::APIMisuseError {
+				err: format!("The adaptor points don't match the oracle announcement: {:?}", violation)
+			});
+		}
+		match self.oracle_policy.lock().unwrap().as_ref() {
+			Some(policy) => if let Err(violation) = policy.validate_contract(
+				&oracle_announcement, &branches, &adaptor_points, &self.secp_ctx
+			) {
+				return Err(APIError::APIMisuseError {
+					err: format!("The oracle announcement violates our oracle policy: {:?}", violation)
+				});
+			},
+			None => return Err(APIError::APIMisuseError {
+				err: "No oracle policy is set, refusing to propose renewals".to_owned()
+			}),
+		}
+		if holder_collateral_satoshis > collateral.value_satoshis {
+			return Err(APIError::APIMisuseError {
+				err: "Our contribution may not exceed the value of the collateral output".to_owned()
+			});
+		}
+		check_pool_collateral(&self.collateral_pools.lock().unwrap(), contract_id, &contract.counterparty_node_id,
+			&contract.channel_id, &collateral, holder_collateral_satoshis)
+			.map_err(|err| APIError::APIMisuseError { err: err.to_owned() })?;
+		let bundle = SettlementBundle::new(collateral.clone(),
+			contract.bundle.holder_payout_script().clone(),
+			contract.bundle.counterparty_payout_script().clone(), lock_time,
+			contract.bundle.dust_limit_satoshis(), branches.clone())
+			.map_err(|()| APIError::APIMisuseError { err: "Invalid settlement branches".to_owned() })?;
+
+		log_debug!(self.logger, "Proposing renewal of contract {} to {}", log_bytes!(contract_id.0),
+			log_bytes!(new_contract_id.0));
+		self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::RenewalProposal {
+			contract_id: *contract_id, new_contract_id, collateral, lock_time, branches,
+			adaptor_points: adaptor_points.clone(), holder_collateral_satoshis,
+			oracle_announcement: Some(oracle_announcement),
+		}));
+		contract.pending_renewal = Some(PendingRenewal {
+			new_contract_id, bundle, adaptor_points, state: RenewalState::ProposalSent,
+			holder_collateral_satoshis, ticks: 0,
+		});
+		Ok(new_contract_id)
+	}
+
+	/// Accepts the renewal of the given contract proposed by our counterparty, as surfaced via an
+	/// [`Event::ContractRenewalRequest`], signing the new settlement bundle via our
+	/// [`ContractSigner`].
+	pub fn accept_contract_renewal(&self, contract_id: &ContractId) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		let renewal = match contract.pending_renewal.as_mut() {
+			Some(renewal) if renewal.state == RenewalState::ProposalReceived => renewal,
+			_ => return Err(APIError::APIMisuseError {
+				err: format!("No renewal of contract {} is awaiting acceptance", log_bytes!(contract_id.0))
+			}),
+		};
+		if self.oracle_policy.lock().unwrap().is_none() {
+			return Err(APIError::APIMisuseError {
+				err: "No oracle policy is set, refusing to sign renewals".to_owned()
+			});
+		}
+		let adaptor_signatures = self.contract_signer.sign_settlement_transactions(
+			&contract.counterparty_node_id, &contract.channel_id, &renewal.bundle, &renewal.adaptor_points
+		).map_err(|()| APIError::APIMisuseError {
+			err: "Failed to sign the new settlement bundle".to_owned()
+		})?;
+		renewal.state = RenewalState::AcceptSent;
+		renewal.ticks = 0;
+		self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::RenewalAccept {
+			contract_id: *contract_id, new_contract_id: renewal.new_contract_id, adaptor_signatures,
+		}));
+		Ok(())
+	}
+
+	/// Aborts a pending renewal of the given contract, either rejecting a renewal proposed by our
+	/// counterparty or abandoning one we proposed but which our counterparty has not accepted yet.
+	///
+	/// Once we've accepted a renewal, it can no longer be aborted.
+	pub fn abort_contract_renewal(&self, contract_id: &ContractId) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		match contract.pending_renewal.as_ref().map(|renewal| renewal.state) {
+			Some(RenewalState::ProposalSent) | Some(RenewalState::ProposalReceived) => {},
+			Some(RenewalState::AcceptSent) => return Err(APIError::APIMisuseError {
+				err: format!("The renewal of contract {} has already been accepted", log_bytes!(contract_id.0))
+			}),
+			None => return Err(APIError::APIMisuseError {
+				err: format!("No renewal of contract {} is pending", log_bytes!(contract_id.0))
+			}),
+		}
+		let renewal = contract.pending_renewal.take().unwrap();
+		self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::RenewalAbort {
+			contract_id: *contract_id, new_contract_id: renewal.new_contract_id,
+			reason: "Renewal aborted by peer".to_owned(),
+		}));
+		Ok(())
+	}
+
+	/// Exercises all matured contracts, see the [module-level documentation] for details. Also
+	/// aborts renewals for which we've been waiting for our counterparty's signatures for
+	/// [`RENEWAL_TIMEOUT_TICKS`], as well as pool allocations we proposed which our counterparty
+	/// didn't respond to within [`POOL_ALLOCATION_TIMEOUT_TICKS`].
+	///
+	/// This should be called regularly, e.g. every ten seconds. The `BackgroundProcessor` from the
+	/// `lightning-background-processor` crate does so if the `ContractManager` is provided to it.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager#automatic-exercise
+	pub fn timer_tick_occurred(&self) {
+		let (best_block_height, best_block_time) = *self.best_block.lock().unwrap();
+		let mut contracts = self.contracts.lock().unwrap();
+		let mut events = Vec::new();
+		for (contract_id, contract) in contracts.iter_mut() {
+			self.check_renewal_timeout(contract_id, contract, &mut events);
+			// A renewal may well roll over a contract once it matured, so we leave it be until the
+			// renewal completed or was aborted.
+			// Once we agreed to release the collateral, we wait for the release to confirm.
+			// Once a settlement transaction confirmed, we wait for it to reach `ANTI_REORG_DELAY`.
+			if !contract.has_matured(best_block_height, best_block_time) || contract.pending_renewal.is_some() ||
+				contract.release.is_some() || contract.confirmed_settlement.is_some()
+			{
+				continue;
+			}
+			if let Some(status) = self.exercise_contract(contract_id, contract) {
+				events.push(Event::ContractExerciseProgress {
+					contract_id: *contract_id, counterparty_node_id: contract.counterparty_node_id, status,
+				});
+			}
+		}
+
+		let mut pools = self.collateral_pools.lock().unwrap();
+		let mut pool_allocation_ticks = self.pool_allocation_ticks.lock().unwrap();
+		pool_allocation_ticks.retain(|pool_outpoint, _|
+			pools.get(pool_outpoint).map_or(false, |pool| pool.pending_allocation.is_some()));
+		for (pool_outpoint, pool) in pools.iter_mut() {
+			if pool.pending_allocation.is_none() { continue; }
+			let ticks = pool_allocation_ticks.entry(*pool_outpoint).or_insert(0);
+			*ticks += 1;
+			if *ticks < POOL_ALLOCATION_TIMEOUT_TICKS { continue; }
+			pool_allocation_ticks.remove(pool_outpoint);
+			let allocation = pool.pending_allocation.take().unwrap();
+			log_debug!(self.logger, "Timed out waiting for a response to the allocation of collateral pool {} to contract {}",
+				pool_outpoint.txid, log_bytes!(allocation.contract_id.0));
+			// Our counterparty may have accepted the allocation without us receiving the response.
+			self.pending_msgs.lock().unwrap().push((pool.counterparty_node_id, ContractMessage::PoolAllocationRelease {
+				pool_outpoint: *pool_outpoint, contract_id: allocation.contract_id,
+			}));
+			events.push(Event::PoolAllocationFailed {
+				pool_outpoint: *pool_outpoint, contract_id: allocation.contract_id,
+				counterparty_node_id: pool.counterparty_node_id,
+				reason: "Timed out waiting for the allocation response".to_owned(),
+			});
+		}
+		self.pending_events.lock().unwrap().append(&mut events);
+	}
+
+	/// Aborts the pending renewal of the given contract if we've been waiting for our
+	/// counterparty's signatures for [`RENEWAL_TIMEOUT_TICKS`].
+	fn check_renewal_timeout(&self, contract_id: &ContractId, contract: &mut Contract, events: &mut Vec<Event>) {
+		match contract.pending_renewal.as_mut() {
+			Some(renewal) if renewal.state != RenewalState::ProposalReceived => {
+				renewal.ticks += 1;
+				if renewal.ticks < RENEWAL_TIMEOUT_TICKS { return; }
+			},
+			_ => return,
+		}
+		let renewal = contract.pending_renewal.take().unwrap();
+		log_debug!(self.logger, "Timed out waiting for signatures for the renewal of contract {}",
+			log_bytes!(contract_id.0));
+		if renewal.state == RenewalState::AcceptSent {
+			// Our counterparty may have completed the new settlement bundle with our signatures.
+			contract.superseded_contracts.push(SupersededContract {
+				contract_id: renewal.new_contract_id, bundle: renewal.bundle, spend_height: None,
+			});
+		}
+		let reason = "Timed out waiting for the renewal signatures".to_owned();
+		self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::RenewalAbort {
+			contract_id: *contract_id, new_contract_id: renewal.new_contract_id, reason: reason.clone(),
+		}));
+		events.push(Event::ContractRenewalFailed {
+			contract_id: *contract_id, new_contract_id: renewal.new_contract_id,
+			counterparty_node_id: contract.counterparty_node_id, reason,
+		});
+	}
+
+	/// Proposes settling the given contract off-chain for `outcome`, which the oracle attested to
+	/// with `attestation`. See the [module-level documentation] for details.
+	///
+	/// Fails if a renewal or settlement of the contract is already pending, if our counterparty's
+	/// signatures for the contract are not adaptor signatures, if there is no branch for
+	/// `outcome`, if `attestation` doesn't match the outcome's adaptor point, or if we fail to sign
+	/// the transaction releasing the contract's collateral.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager#mutual-settlement
+	pub fn propose_mutual_settlement(
+		&self, contract_id: &ContractId, outcome: Vec<u8>, attestation: SecretKey,
+	) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		if !contract.is_quiescent() {
+			return Err(APIError::APIMisuseError {
+				err: format!("A renewal or settlement of contract {} is already pending", log_bytes!(contract_id.0))
+			});
+		}
+		let branch_idx = self.check_attestation(&contract.bundle, &outcome, &attestation)
+			.map_err(|err| APIError::APIMisuseError { err: err.to_owned() })?;
+		let release_signature = self.sign_release(contract_id, contract, branch_idx)
+			.map_err(|()| APIError::APIMisuseError { err: "Failed to sign the collateral release".to_owned() })?;
+
+		self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::SettlementProposal {
+			contract_id: *contract_id, outcome: outcome.clone(), attestation, release_signature,
+		}));
+		contract.pending_settlement = Some(outcome);
+		Ok(())
+	}
+
+	/// Accepts our counterparty's proposal to settle the given contract off-chain, as surfaced via
+	/// an [`Event::ContractSettlementRequest`]. See the [module-level documentation] for details.
+	///
+	/// Fails if no proposal is pending acceptance or if we fail to sign the transaction releasing
+	/// the contract's collateral.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager#mutual-settlement
+	pub fn accept_mutual_settlement(&self, contract_id: &ContractId) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let received = contracts.get_mut(contract_id).and_then(|contract| contract.received_settlement.take())
+			.ok_or_else(|| APIError::APIMisuseError {
+				err: format!("No settlement of contract {} is pending acceptance", log_bytes!(contract_id.0))
+			})?;
+		self.accept_settlement(&mut contracts, *contract_id, received, true)
+			.map_err(|err| APIError::APIMisuseError { err: err.to_owned() })
+	}
+
+	/// Rejects our counterparty's proposal to settle the given contract off-chain, as surfaced via
+	/// an [`Event::ContractSettlementRequest`], sending it a
+	/// [`ContractMessage::SettlementReject`] with the given `reason`.
+	pub fn reject_mutual_settlement(&self, contract_id: &ContractId, reason: String) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id)
+			.filter(|contract| contract.received_settlement.is_some())
+			.ok_or_else(|| APIError::APIMisuseError {
+				err: format!("No settlement of contract {} is pending acceptance", log_bytes!(contract_id.0))
+			})?;
+		contract.received_settlement = None;
+		self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::SettlementReject {
+			contract_id: *contract_id, reason,
+		}));
+		Ok(())
+	}
+
+	/// Whether the given contract was allocated a share of a collateral pool.
+	fn is_pooled(&self, contract_id: &ContractId) -> bool {
+		self.collateral_pools.lock().unwrap().values()
+			.any(|pool| pool.allocations.iter().any(|allocation| allocation.contract_id == *contract_id))
+	}
+
+	/// Signs the transaction releasing the contract's collateral when settling it off-chain for the
+	/// branch at `branch_idx`, returning `None` for contracts in a collateral pool.
+	fn sign_release(&self, contract_id: &ContractId, contract: &Contract, branch_idx: usize) -> Result<Option<Signature>, ()> {
+		if self.is_pooled(contract_id) { return Ok(None); }
+		let release = release_bundle(contract, branch_idx)?;
+		self.contract_signer.sign_settlement_transaction(&contract.counterparty_node_id,
+			&contract.channel_id, &release, 0).map(Some)
+	}
+
+	/// Settles a contract off-chain as agreed with our counterparty in `settlement`, sending our
+	/// [`ContractMessage::SettlementAccept`] unless `settlement` is our counterparty's acceptance.
+	/// If both of us proposed settling, our counterparty may already have settled upon receiving
+	/// our proposal, in which case it ignores our acceptance.
+	///
+	/// Contracts in a collateral pool are settled right away. Otherwise, the transaction releasing
+	/// the contract's collateral is completed with our counterparty's `release_signature` and
+	/// handed to the contract's channel, with the contract being settled once it confirmed.
+	fn accept_settlement(
+		&self, contracts: &mut HashMap<ContractId, Contract>, contract_id: ContractId,
+		settlement: ReceivedSettlement, send_accept: bool,
+	) -> Result<(), &'static str> {
+		let contract = contracts.get_mut(&contract_id).ok_or("Unknown contract")?;
+		let branch_idx = contract.bundle.branch_index(&settlement.outcome).ok_or("No branch for the outcome")?;
+		contract.pending_settlement = None;
+		let counterparty_signature = match settlement.release_signature {
+			Some(signature) if !self.is_pooled(&contract_id) => signature,
+			None if self.is_pooled(&contract_id) => {
+				if send_accept {
+					self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::SettlementAccept {
+						contract_id, release_signature: None,
+					}));
+				}
+				if let Some(contract) = contracts.remove(&contract_id) {
+					self.settle(contract_id, contract, settlement.outcome, branch_idx);
+				}
+				return Ok(());
+			},
+			_ => return Err("Collateral release signature missing or unexpected"),
+		};
+
+		let mut release = release_bundle(contract, branch_idx).map_err(|()| "Invalid collateral release")?;
+		release.set_counterparty_signatures(vec![counterparty_signature], &self.secp_ctx)
+			.map_err(|()| "Invalid collateral release signature")?;
+		let holder_signature = self.contract_signer.sign_settlement_transaction(&contract.counterparty_node_id,
+			&contract.channel_id, &release, 0).map_err(|()| "Failed to sign the collateral release")?;
+		let transaction = release.build_signed_settlement_transaction(&settlement.outcome, &holder_signature)
+			.ok_or("Failed to build the collateral release")?;
+		if send_accept {
+			self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::SettlementAccept {
+				contract_id, release_signature: Some(holder_signature),
+			}));
+		}
+
+		log_info!(self.logger, "Releasing the collateral of contract {} in transaction {}",
+			log_bytes!(contract_id.0), transaction.txid());
+		if self.payment_sender.watch_collateral_release(&contract.counterparty_node_id, &contract.channel_id, &transaction).is_err() {
+			log_error!(self.logger, "Failed to hand the collateral release of contract {} to its channel, broadcasting it ourselves",
+				log_bytes!(contract_id.0));
+			self.broadcaster.broadcast_transactions_with_meta(&[(&transaction, TransactionMetadata {
+				channel_id: Some(contract.channel_id),
+				counterparty_node_id: Some(contract.counterparty_node_id),
+				transaction_type: TransactionType::ContractSettlement,
+			})]);
+		}
+		contract.received_settlement = None;
+		contract.release = Some(PendingRelease { outcome: settlement.outcome, transaction, confirmation_height: None });
+		Ok(())
+	}
+
+	/// Records the oracle's attestation to `outcome` for the given contract, e.g. once the oracle
+	/// published it, such that it is included in any [`DisputePackage`] for the contract.
+	///
+	/// Fails if there is no branch for `outcome` or if `attestation` doesn't match the outcome's
+	/// adaptor point.
+	pub fn record_oracle_attestation(
+		&self, contract_id: &ContractId, outcome: Vec<u8>, attestation: SecretKey,
+	) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		self.check_attestation(&contract.bundle, &outcome, &attestation)
+			.map_err(|err| APIError::APIMisuseError { err: err.to_owned() })?;
+		contract.oracle_attestation = Some((outcome, attestation));
+		Ok(())
+	}
+
+	/// Records the attestations received so far from the oracles of a `t`-of-`n` `threshold`
+	/// for the given contract, as via [`Self::record_oracle_attestation`] once all oracles of the
+	/// combination the contract was adaptor signed for agreed on an outcome.
+	///
+	/// Returns whether the attestations were recorded, or `false` if not enough oracles have
+	/// attested yet. Fails if any attestation doesn't pass
+	/// [`OracleThreshold::verify_attestation`].
+	pub fn record_threshold_attestations(
+		&self, contract_id: &ContractId, threshold: &OracleThreshold, attestations: &[OracleAttestation],
+	) -> Result<bool, APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		for combination in threshold.combinations() {
+			let aggregated = threshold.aggregate_combination_attestations(attestations, &combination, &self.secp_ctx)
+				.map_err(|violation| APIError::APIMisuseError { err: format!("Invalid attestation: {:?}", violation) })?;
+			if let Some(aggregated) = aggregated {
+				if self.check_attestation(&contract.bundle, &aggregated.outcome, &aggregated.adaptor_secret).is_ok() {
+					contract.oracle_attestation = Some((aggregated.outcome, aggregated.adaptor_secret));
+					return Ok(true);
+				}
+			}
+		}
+		Ok(false)
+	}
+
+	/// Checks that `attestation` is the secret key for the adaptor point of the branch for
+	/// `outcome`, returning the branch's index.
+	fn check_attestation(
+		&self, bundle: &SettlementBundle, outcome: &[u8], attestation: &SecretKey,
+	) -> Result<usize, &'static str> {
+		let branch_idx = bundle.branch_index(outcome).ok_or("No branch for the attested outcome")?;
+		match bundle.adaptor_points().get(branch_idx) {
+			Some(adaptor_point) if *adaptor_point == PublicKey::from_secret_key(&self.secp_ctx, attestation) =>
+				Ok(branch_idx),
+			Some(_) => Err("Attestation does not match the outcome's adaptor point"),
+			None => Err("The contract was not signed with adaptor signatures"),
+		}
+	}
+
+	/// Settles a contract we no longer track for the branch at `branch_idx`, paying our
+	/// counterparty whatever we owe them over the contract's channel.
+	fn settle(&self, contract_id: ContractId, contract: Contract, outcome: Vec<u8>, branch_idx: usize) {
+		let branch = &contract.bundle.branches()[branch_idx];
+		let mut payment_id = None;
+		if branch.holder_payout_satoshis < contract.holder_collateral_satoshis {
+			let amount_msat = (contract.holder_collateral_satoshis - branch.holder_payout_satoshis) * 1000;
+			let id = PaymentId(contract_id.0);
+			match self.payment_sender.send_contract_payment(&contract.counterparty_node_id,
+				&contract.channel_id, amount_msat, contract_id.0.to_vec(), id)
+			{
+				Ok(()) => payment_id = Some(id),
+				Err(()) => log_error!(self.logger, "Failed to pay the {} msat owed for settling contract {}",
+					amount_msat, log_bytes!(contract_id.0)),
+			}
+		}
+		for pool in self.collateral_pools.lock().unwrap().values_mut() {
+			pool.allocations.retain(|allocation| allocation.contract_id != contract_id);
+		}
+		log_info!(self.logger, "Settled contract {} off-chain", log_bytes!(contract_id.0));
+		self.pending_events.lock().unwrap().push(Event::ContractSettled {
+			contract_id, counterparty_node_id: contract.counterparty_node_id, outcome,
+			holder_payout_satoshis: branch.holder_payout_satoshis,
+			counterparty_payout_satoshis: branch.counterparty_payout_satoshis, payment_id,
+		});
+	}
+
+	/// Stops tracking a contract whose settlement transaction for the branch at `branch_idx`
+	/// confirmed on-chain.
+	fn settled_on_chain(&self, contract_id: ContractId, contract: Contract, branch_idx: usize) {
+		let branch = &contract.bundle.branches()[branch_idx];
+		for pool in self.collateral_pools.lock().unwrap().values_mut() {
+			pool.allocations.retain(|allocation| allocation.contract_id != contract_id);
+		}
+		log_info!(self.logger, "Settled contract {} on-chain", log_bytes!(contract_id.0));
+		self.pending_events.lock().unwrap().push(Event::ContractSettled {
+			contract_id, counterparty_node_id: contract.counterparty_node_id, outcome: branch.outcome.clone(),
+			holder_payout_satoshis: branch.holder_payout_satoshis,
+			counterparty_payout_satoshis: branch.counterparty_payout_satoshis, payment_id: None,
+		});
+	}
+
+	/// Takes the next step in exercising a matured contract, returning the status to notify the
+	/// user of, if any.
+	fn exercise_contract(&self, contract_id: &ContractId, contract: &mut Contract) -> Option<ContractExerciseStatus> {
+		let (outcome, attestation) = match contract.oracle_attestation.clone() {
+			Some(oracle_attestation) => oracle_attestation,
+			None => {
+				if contract.exercise.is_some() { return None; }
+				contract.exercise = Some(ExerciseState::AwaitingAttestation);
+				return Some(ContractExerciseStatus::AttestationRequested { maturity: contract.bundle.lock_time() });
+			},
+		};
+		let branch_idx = match contract.bundle.branch_index(&outcome) {
+			Some(branch_idx) => branch_idx,
+			None => {
+				log_error!(self.logger, "Recorded an attestation for an unknown outcome of contract {}", log_bytes!(contract_id.0));
+				return None;
+			},
+		};
+		let pooled = self.is_pooled(contract_id);
+
+		match contract.exercise.take().unwrap_or(ExerciseState::AwaitingAttestation) {
+			ExerciseState::AwaitingAttestation => {
+				// The user may have proposed settling the contract already.
+				if contract.pending_settlement.is_none() {
+					let release_signature = match self.sign_release(contract_id, contract, branch_idx) {
+						Ok(release_signature) => release_signature,
+						Err(()) => {
+							log_error!(self.logger, "Failed to sign the collateral release of contract {}", log_bytes!(contract_id.0));
+							return None;
+						},
+					};
+					self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::SettlementProposal {
+						contract_id: *contract_id, outcome: outcome.clone(), attestation, release_signature,
+					}));
+					contract.pending_settlement = Some(outcome.clone());
+				}
+				contract.exercise = Some(ExerciseState::AwaitingCounterparty { ticks: 0 });
+				Some(ContractExerciseStatus::MutualSettlementProposed { outcome })
+			},
+			ExerciseState::AwaitingCounterparty { ticks } => {
+				let ticks = ticks.saturating_add(1);
+				if contract.pending_settlement.is_some() && ticks < MUTUAL_SETTLEMENT_TIMEOUT_TICKS {
+					contract.exercise = Some(ExerciseState::AwaitingCounterparty { ticks });
+					return None;
+				}
+				if pooled {
+					if contract.pending_settlement.is_some() {
+						// Our counterparty may have missed the proposal, e.g. as it was offline.
+						// Pooled contracts are settled without releasing their collateral.
+						self.pending_msgs.lock().unwrap().push((contract.counterparty_node_id, ContractMessage::SettlementProposal {
+							contract_id: *contract_id, outcome, attestation, release_signature: None,
+						}));
+						contract.exercise = Some(ExerciseState::AwaitingCounterparty { ticks: 0 });
+					} else {
+						// Our counterparty rejected the proposal, so we propose again on the next
+						// tick.
+						contract.exercise = Some(ExerciseState::AwaitingAttestation);
+					}
+					return None;
+				}
+
+				let transaction = self.contract_signer.sign_settlement_transaction(&contract.counterparty_node_id,
+					&contract.channel_id, &contract.bundle, branch_idx).ok()
+					.and_then(|signature| contract.bundle.build_settlement_transaction_from_attestation(
+						&outcome, &signature, &attestation, &self.secp_ctx));
+				let transaction = match transaction {
+					Some(transaction) => transaction,
+					None => {
+						log_error!(self.logger, "Failed to sign the settlement transaction of contract {}", log_bytes!(contract_id.0));
+						contract.exercise = Some(ExerciseState::AwaitingCounterparty { ticks });
+						return None;
+					},
+				};
+				// Any acceptance arriving from now on is ignored, as we may no longer be able to
+				// stop the settlement transaction from confirming.
+				contract.pending_settlement = None;
+				let (feerate_sat_per_1000_weight, cpfp_fee_satoshis) =
+					self.broadcast_settlement(contract_id, contract, branch_idx, &transaction);
+				contract.exercise = Some(ExerciseState::Broadcast { transaction: transaction.clone(), ticks: 0, cpfp_fee_satoshis });
+				Some(ContractExerciseStatus::SettlementBroadcast {
+					outcome, transaction, feerate_sat_per_1000_weight, cpfp_fee_satoshis,
+				})
+			},
+			ExerciseState::Broadcast { transaction, ticks, cpfp_fee_satoshis: previous_cpfp_fee_satoshis } => {
+				let ticks = ticks.saturating_add(1);
+				if ticks < SETTLEMENT_REBROADCAST_INTERVAL_TICKS {
+					contract.exercise = Some(ExerciseState::Broadcast { transaction, ticks, cpfp_fee_satoshis: previous_cpfp_fee_satoshis });
+					return None;
+				}
+				let (feerate_sat_per_1000_weight, cpfp_fee_satoshis) =
+					self.broadcast_settlement(contract_id, contract, branch_idx, &transaction);
+				contract.exercise = Some(ExerciseState::Broadcast {
+					transaction: transaction.clone(), ticks: 0,
+					cpfp_fee_satoshis: core::cmp::max(cpfp_fee_satoshis, previous_cpfp_fee_satoshis),
+				});
+				// Only bother the user if the fee they have to bump the transaction with rose.
+				if cpfp_fee_satoshis <= previous_cpfp_fee_satoshis { return None; }
+				Some(ContractExerciseStatus::SettlementBroadcast {
+					outcome, transaction, feerate_sat_per_1000_weight, cpfp_fee_satoshis,
+				})
+			},
+		}
+	}
+
+	/// Broadcasts the settlement transaction `tx` for the branch of the contract's bundle at
+	/// `branch_idx`, returning the feerate we'd like it to confirm at and the fee a CPFP child would
+	/// have to add to reach it.
+	///
+	/// If a child is required and our payout output can be spent by one, an
+	/// [`Event::BumpTransaction`] is generated for it.
+	fn broadcast_settlement(
+		&self, contract_id: &ContractId, contract: &Contract, branch_idx: usize, tx: &Transaction,
+	) -> (u32, u64) {
+		log_info!(self.logger, "Broadcasting settlement transaction {} of contract {}", tx.txid(), log_bytes!(contract_id.0));
+		self.broadcaster.broadcast_transactions_with_meta(&[(tx, TransactionMetadata {
+			channel_id: Some(contract.channel_id),
+			counterparty_node_id: Some(contract.counterparty_node_id),
+			transaction_type: TransactionType::ContractSettlement,
+		})]);
+		let feerate_sat_per_1000_weight = self.fee_estimator.get_est_sat_per_1000_weight(ConfirmationTarget::HighPriority);
+		let cpfp_fee_satoshis = settlement_cost_satoshis(&contract.bundle, branch_idx, feerate_sat_per_1000_weight);
+		if cpfp_fee_satoshis == 0 { return (feerate_sat_per_1000_weight, 0); }
+
+		// We can only estimate the weight of spending P2WPKH payout outputs.
+		let payout_script = contract.bundle.holder_payout_script();
+		let payout_utxo = tx.output.iter().enumerate()
+			.find(|(_, txout)| txout.script_pubkey == *payout_script && payout_script.is_v0_p2wpkh())
+			.and_then(|(idx, txout)| WPubkeyHash::from_slice(&payout_script.as_bytes()[2..]).ok().map(|pubkey_hash| {
+				let outpoint = bitcoin::OutPoint { txid: tx.txid(), vout: idx as u32 };
+				Utxo::new_v0_p2wpkh(outpoint, txout.value, &pubkey_hash)
+			}));
+		match payout_utxo {
+			Some(utxo) => self.pending_events.lock().unwrap().push(Event::BumpTransaction(
+				BumpTransactionEvent::ContractSettlement {
+					claim_id: ClaimId(tx.txid().into_inner()),
+					package_target_feerate_sat_per_1000_weight: feerate_sat_per_1000_weight,
+					settlement_tx: tx.clone(),
+					settlement_tx_fee_satoshis: contract.bundle.settlement_fee_satoshis(branch_idx),
+					payout_input: Input {
+						outpoint: utxo.outpoint, previous_utxo: utxo.output, satisfaction_weight: utxo.satisfaction_weight,
+					},
+				}
+			)),
+			None => log_warn!(self.logger, "Unable to bump the fee of settlement transaction {} of contract {} as it doesn't pay us a P2WPKH output",
+				tx.txid(), log_bytes!(contract_id.0)),
+		}
+		(feerate_sat_per_1000_weight, cpfp_fee_satoshis)
+	}
+
+	/// Requests that our counterparty tops up the margin of the given contract by paying us
+	/// `top_up_satoshis` before `deadline`, a UNIX timestamp. See the [module-level
+	/// documentation] for details.
+	///
+	/// Any rejection will be sent over `reply_path`, which must lead to us. The `deadline` must be
+	/// later than that of any previous margin call for the contract, as our counterparty will
+	/// otherwise ignore the request.
+	///
+	/// [module-level documentation]: crate::ln::contractmanager#margin-calls
+	pub fn send_margin_call(
+		&self, contract_id: &ContractId, top_up_satoshis: u64, deadline: u64, reply_path: BlindedPath,
+	) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		if top_up_satoshis == 0 || top_up_satoshis.checked_mul(1000).is_none() {
+			return Err(APIError::APIMisuseError { err: "Margin calls must request a non-zero, payable amount".to_owned() });
+		}
+		let signature = self.contract_signer.sign_contract_message(
+			&contract.counterparty_node_id, &contract.channel_id,
+			&margin_call_request_bytes(contract_id, top_up_satoshis, deadline, &reply_path)
+		).map_err(|()| APIError::APIMisuseError { err: "Failed to sign the margin call".to_owned() })?;
+		let request = MarginCallMessage::Request {
+			contract_id: *contract_id, top_up_satoshis, deadline, reply_path, signature,
+		};
+
+		log_debug!(self.logger, "Sending margin call for {} sats for contract {}", top_up_satoshis, log_bytes!(contract_id.0));
+		self.pending_onion_msgs.lock().unwrap().push((request, Destination::Node(contract.counterparty_node_id), None));
+		contract.sent_margin_call = Some(MarginCall { top_up_satoshis, deadline, reply_path: None });
+		Ok(())
+	}
+
+	/// Pays the margin call for the given contract surfaced via an [`Event::MarginCallReceived`]
+	/// over the contract's channel via our [`ContractPaymentSender`], returning the id of the
+	/// payment.
+	///
+	/// The paid amount is added to [`ContractDetails::paid_margin_satoshis`], which is persisted
+	/// along with the contract.
+	pub fn pay_margin_call(&self, contract_id: &ContractId) -> Result<PaymentId, APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		let top_up_satoshis = match contract.received_margin_call.as_ref() {
+			Some(margin_call) => margin_call.top_up_satoshis,
+			None => return Err(APIError::APIMisuseError {
+				err: format!("No margin call for contract {} is pending", log_bytes!(contract_id.0))
+			}),
+		};
+		let amount_msat = top_up_satoshis.checked_mul(1000).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("The margin call for contract {} requests more than can be paid", log_bytes!(contract_id.0))
+		})?;
+		let payment_id = PaymentId(self.entropy_source.get_secure_random_bytes());
+		self.payment_sender.send_contract_payment(&contract.counterparty_node_id, &contract.channel_id,
+			amount_msat, contract_id.0.to_vec(), payment_id
+		).map_err(|()| APIError::ChannelUnavailable {
+			err: format!("Failed to pay the margin call for contract {}", log_bytes!(contract_id.0))
+		})?;
+		contract.received_margin_call = None;
+		contract.paid_margin_satoshis = contract.paid_margin_satoshis.saturating_add(top_up_satoshis);
+		Ok(payment_id)
+	}
+
+	/// Rejects the margin call for the given contract surfaced via an
+	/// [`Event::MarginCallReceived`], sending a [`MarginCallMessage::Rejection`] over the reply
+	/// path provided by our counterparty.
+	pub fn reject_margin_call(&self, contract_id: &ContractId, reason: String) -> Result<(), APIError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = contracts.get_mut(contract_id).ok_or_else(|| APIError::APIMisuseError {
+			err: format!("Unknown contract {}", log_bytes!(contract_id.0))
+		})?;
+		let (top_up_satoshis, deadline, reply_path) = match contract.received_margin_call.as_ref() {
+			Some(MarginCall { top_up_satoshis, deadline, reply_path: Some(reply_path) }) =>
+				(*top_up_satoshis, *deadline, reply_path.clone()),
+			_ => return Err(APIError::APIMisuseError {
+				err: format!("No margin call for contract {} is pending", log_bytes!(contract_id.0))
+			}),
+		};
+		let signature = self.contract_signer.sign_contract_message(
+			&contract.counterparty_node_id, &contract.channel_id,
+			&margin_call_rejection_bytes(contract_id, top_up_satoshis, deadline, &reason)
+		).map_err(|()| APIError::APIMisuseError { err: "Failed to sign the margin call rejection".to_owned() })?;
+		let rejection = MarginCallMessage::Rejection {
+			contract_id: *contract_id, top_up_satoshis, deadline, reason, signature,
+		};
+
+		self.pending_onion_msgs.lock().unwrap().push((rejection, Destination::BlindedPath(reply_path), None));
+		contract.received_margin_call = None;
+		Ok(())
+	}
+
+	fn handle_margin_call_message(&self, msg: MarginCallMessage) {
+		let (contract_id, signature) = match &msg {
+			MarginCallMessage::Request { contract_id, signature, .. } |
+			MarginCallMessage::Rejection { contract_id, signature, .. } => (*contract_id, *signature),
+		};
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = match contracts.get_mut(&contract_id) {
+			Some(contract) => contract,
+			None => {
+				log_trace!(self.logger, "Ignoring margin call message for unknown contract {}", log_bytes!(contract_id.0));
+				return;
+			},
+		};
+		let digest = contract_message_digest(&msg.signed_bytes());
+		let counterparty_funding_pubkey = &contract.bundle.collateral().counterparty_funding_pubkey;
+		if self.secp_ctx.verify_ecdsa(&digest, &signature, counterparty_funding_pubkey).is_err() {
+			log_debug!(self.logger, "Ignoring margin call message with an invalid signature for contract {}", log_bytes!(contract_id.0));
+			return;
+		}
+
+		match msg {
+			MarginCallMessage::Request { top_up_satoshis, deadline, reply_path, .. } => {
+				if top_up_satoshis.checked_mul(1000).is_none() {
+					log_debug!(self.logger, "Ignoring unpayable margin call for contract {}", log_bytes!(contract_id.0));
+					return;
+				}
+				if deadline <= contract.latest_margin_call_deadline {
+					log_debug!(self.logger, "Ignoring stale margin call for contract {}", log_bytes!(contract_id.0));
+					return;
+				}
+				contract.latest_margin_call_deadline = deadline;
+				contract.received_margin_call = Some(MarginCall {
+					top_up_satoshis, deadline, reply_path: Some(reply_path),
+				});
+				self.pending_events.lock().unwrap().push(Event::MarginCallReceived {
+					contract_id, counterparty_node_id: contract.counterparty_node_id, top_up_satoshis,
+					deadline,
+				});
+			},
+			MarginCallMessage::Rejection { top_up_satoshis, deadline, reason, .. } => {
+				match contract.sent_margin_call {
+					Some(ref margin_call) if margin_call.top_up_satoshis == top_up_satoshis
+						&& margin_call.deadline == deadline => {},
+					_ => {
+						log_debug!(self.logger, "Ignoring rejection of an unknown margin call for contract {}", log_bytes!(contract_id.0));
+						return;
+					},
+				}
+				contract.sent_margin_call = None;
+				self.pending_events.lock().unwrap().push(Event::MarginCallRejected {
+					contract_id, counterparty_node_id: contract.counterparty_node_id, top_up_satoshis,
+					deadline, reason,
+				});
+			},
+		}
+	}
+
+	fn renewal_contract_id(&self, counterparty_node_id: &PublicKey, offer: &RenewalOffer) -> ContractId {
+		derive_contract_id(&offer.encode(), &self.our_node_id, counterparty_node_id,
+			&offer.collateral.outpoint)
+	}
+
+	fn handle_renewal_proposal(
+		&self, counterparty_node_id: &PublicKey, contract_id: ContractId, new_contract_id: ContractId,
+		collateral: CollateralOutput, lock_time: u32, branches: Vec<SettlementBranch>,
+		adaptor_points: Vec<PublicKey>, counterparty_collateral_satoshis: u64,
+		oracle_announcement: Option<OracleAnnouncement>,
+	) -> Result<(), LightningError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let new_contract_id_in_use = contracts.contains_key(&new_contract_id);
+		let contract = match contracts.get_mut(&contract_id) {
+			Some(contract) if contract.counterparty_node_id == *counterparty_node_id => contract,
+			_ => return Err(ignore_msg("Received a renewal proposal for an unknown contract")),
+		};
+		let abort = |reason: &str| {
+			log_debug!(self.logger, "Rejecting renewal of contract {}: {}", log_bytes!(contract_id.0), reason);
+			self.pending_msgs.lock().unwrap().push((*counterparty_node_id, ContractMessage::RenewalAbort {
+				contract_id, new_contract_id, reason: reason.to_owned(),
+			}));
+			Ok(())
+		};
+		if !contract.is_quiescent() {
+			// If both sides proposed a renewal at the same time, each aborts the other's proposal.
+			return abort("A renewal or settlement is already pending");
+		}
+		if new_contract_id_in_use {
+			return abort("The new contract id is already in use");
+		}
+		if adaptor_points.len() != branches.len() {
+			return abort("Exactly one adaptor point must be provided per branch");
+		}
+		let derived_contract_id = self.renewal_contract_id(counterparty_node_id, &RenewalOffer {
+			contract_id, collateral: collateral.clone(), lock_time, branches: branches.clone(),
+			adaptor_points: adaptor_points.clone(), proposer_collateral_satoshis: counterparty_collateral_satoshis,
+		});
+		if new_contract_id != derived_contract_id {
+			return abort("The new contract id does not match the proposed terms");
+		}
+		let announcement = match oracle_announcement.as_ref() {
+			Some(announcement) => announcement,
+			None => return abort("Renewal proposals must carry an oracle announcement"),
+		};
+		if announcement.verify_signature(&self.secp_ctx).is_err() {
+			return abort("Invalid oracle announcement signature");
+		}
+		if let Err(violation) = announcement.validate_adaptor_points(&branches, &adaptor_points) {
+			return abort(&format!("Adaptor points don't match the oracle announcement: {:?}", violation));
+		}
+		let holder_collateral_satoshis = match collateral.value_satoshis.checked_sub(counterparty_collateral_satoshis) {
+			Some(holder_collateral_satoshis) => holder_collateral_satoshis,
+			None => return abort("Contribution exceeds the value of the collateral output"),
+		};
+		if let Err(err) = check_pool_collateral(&self.collateral_pools.lock().unwrap(), &contract_id,
+			counterparty_node_id, &contract.channel_id, &collateral, holder_collateral_satoshis)
+		{
+			return abort(err);
+		}
+		match self.oracle_policy.lock().unwrap().as_ref() {
+			Some(policy) => if let Err(violation) = policy.validate_contract(announcement, &branches, &adaptor_points, &self.secp_ctx) {
+				return abort(&format!("Oracle announcement violates our oracle policy: {:?}", violation));
+			},
+			None => return abort("No oracle policy is set"),
+		}
+		let branches = branches.iter().map(counterparty_branch).collect();
+		let bundle = match SettlementBundle::new(counterparty_collateral(&collateral),
+			contract.bundle.holder_payout_script().clone(),
+			contract.bundle.counterparty_payout_script().clone(), lock_time,
+			contract.bundle.dust_limit_satoshis(), branches)
+		{
+			Ok(bundle) => bundle,
+			Err(()) => return abort("Invalid settlement branches"),
+		};
+
+		self.pending_events.lock().unwrap().push(Event::ContractRenewalRequest {
+			contract_id, new_contract_id, counterparty_node_id: *counterparty_node_id,
+			settlement_bundle: bundle.clone(), oracle_announcement,
+		});
+		contract.pending_renewal = Some(PendingRenewal {
+			new_contract_id, bundle, adaptor_points, state: RenewalState::ProposalReceived,
+			holder_collateral_satoshis, ticks: 0,
+		});
+		Ok(())
+	}
+
+	fn handle_renewal_signatures(
+		&self, counterparty_node_id: &PublicKey, contract_id: ContractId, new_contract_id: ContractId,
+		adaptor_signatures: Vec<EcdsaAdaptorSignature>, expected_state: RenewalState,
+	) -> Result<(), LightningError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let new_contract_id_in_use = contracts.contains_key(&new_contract_id);
+		let contract = match contracts.get_mut(&contract_id) {
+			Some(contract) if contract.counterparty_node_id == *counterparty_node_id => contract,
+			_ => return Err(ignore_msg("Received renewal signatures for an unknown contract")),
+		};
+		match contract.pending_renewal.as_ref() {
+			Some(renewal) if renewal.new_contract_id == new_contract_id && renewal.state == expected_state => {},
+			_ => return Err(ignore_msg("Received renewal signatures for an unknown renewal")),
+		}
+		let mut renewal = contract.pending_renewal.take().unwrap();
+
+		// The new contract id may have been taken by a contract registered since the proposal.
+		let res = if new_contract_id_in_use { Err("The new contract id is already in use") } else { Ok(()) }
+			.and_then(|()| renewal.bundle.set_counterparty_adaptor_signatures(
+				adaptor_signatures, renewal.adaptor_points.clone(), &self.secp_ctx
+			).map_err(|()| "Invalid adaptor signatures")).and_then(|()| {
+			if expected_state != RenewalState::ProposalSent { return Ok(()); }
+			if self.oracle_policy.lock().unwrap().is_none() {
+				return Err("No oracle policy is set");
+			}
+			// As the proposer, we only sign once we've checked the acceptor's signatures.
+			let adaptor_signatures = self.contract_signer.sign_settlement_transactions(
+				&contract.counterparty_node_id, &contract.channel_id, &renewal.bundle,
+				&renewal.adaptor_points
+			).map_err(|()| "Failed to sign the new settlement bundle")?;
+			self.pending_msgs.lock().unwrap().push((*counterparty_node_id, ContractMessage::RenewalSign {
+				contract_id, new_contract_id, adaptor_signatures,
+			}));
+			Ok(())
+		});
+		if let Err(reason) = res {
+			log_debug!(self.logger, "Aborting renewal of contract {}: {}", log_bytes!(contract_id.0), reason);
+			self.pending_msgs.lock().unwrap().push((*counterparty_node_id, ContractMessage::RenewalAbort {
+				contract_id, new_contract_id, reason: reason.to_owned(),
+			}));
+			self.pending_events.lock().unwrap().push(Event::ContractRenewalFailed {
+				contract_id, new_contract_id, counterparty_node_id: *counterparty_node_id,
+				reason: reason.to_owned(),
+			});
+			return Ok(());
+		}
+
+		// Atomically switch over to the renewed contract, keeping the previous one around to
+		// detect outdated settlements.
+		let previous_contract = contracts.remove(&contract_id).unwrap();
+		for pool in self.collateral_pools.lock().unwrap().values_mut() {
+			for allocation in pool.allocations.iter_mut().filter(|allocation| allocation.contract_id == contract_id) {
+				allocation.contract_id = new_contract_id;
+			}
+		}
+		let mut superseded_contracts = previous_contract.superseded_contracts;
+		superseded_contracts.push(SupersededContract {
+			contract_id, bundle: previous_contract.bundle, spend_height: None,
+		});
+		contracts.insert(new_contract_id, Contract {
+			counterparty_node_id: *counterparty_node_id, channel_id: previous_contract.channel_id,
+			bundle: renewal.bundle, pending_renewal: None,
+			holder_collateral_satoshis: renewal.holder_collateral_satoshis, pending_settlement: None,
+			received_settlement: None, release: None, sent_margin_call: None, received_margin_call: None, latest_margin_call_deadline: 0,
+			paid_margin_satoshis: 0, superseded_contracts, oracle_attestation: None, exercise: None,
+			confirmed_settlement: None,
+		});
+		log_info!(self.logger, "Renewed contract {} as {}", log_bytes!(contract_id.0), log_bytes!(new_contract_id.0));
+		self.pending_events.lock().unwrap().push(Event::ContractRenewed {
+			previous_contract_id: contract_id, contract_id: new_contract_id,
+			counterparty_node_id: *counterparty_node_id,
+		});
+		Ok(())
+	}
+
+	fn handle_renewal_abort(
+		&self, counterparty_node_id: &PublicKey, contract_id: ContractId, new_contract_id: ContractId,
+		reason: String,
+	) -> Result<(), LightningError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = match contracts.get_mut(&contract_id) {
+			Some(contract) if contract.counterparty_node_id == *counterparty_node_id => contract,
+			_ => return Err(ignore_msg("Received a renewal abort for an unknown contract")),
+		};
+		match contract.pending_renewal.as_ref() {
+			Some(renewal) if renewal.new_contract_id == new_contract_id => {},
+			_ => return Err(ignore_msg("Received a renewal abort for an unknown renewal")),
+		}
+		contract.pending_renewal = None;
+		log_debug!(self.logger, "Peer aborted renewal of contract {}: {}", log_bytes!(contract_id.0), reason);
+		self.pending_events.lock().unwrap().push(Event::ContractRenewalFailed {
+			contract_id, new_contract_id, counterparty_node_id: *counterparty_node_id, reason,
+		});
+		Ok(())
+	}
+
+	fn handle_settlement_proposal(
+		&self, counterparty_node_id: &PublicKey, contract_id: ContractId, outcome: Vec<u8>,
+		attestation: SecretKey, release_signature: Option<Signature>,
+	) -> Result<(), LightningError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = match contracts.get_mut(&contract_id) {
+			Some(contract) if contract.counterparty_node_id == *counterparty_node_id => contract,
+			_ => return Err(ignore_msg("Received a settlement proposal for an unknown contract")),
+		};
+		let reject = |reason: &str| {
+			log_debug!(self.logger, "Rejecting settlement of contract {}: {}", log_bytes!(contract_id.0), reason);
+			self.pending_msgs.lock().unwrap().push((*counterparty_node_id, ContractMessage::SettlementReject {
+				contract_id, reason: reason.to_owned(),
+			}));
+			Ok(())
+		};
+		if contract.pending_renewal.is_some() {
+			return reject("A renewal is pending");
+		}
+		if let Some(ExerciseState::Broadcast { .. }) = contract.exercise {
+			return reject("The contract is being settled on-chain");
+		}
+		if contract.confirmed_settlement.is_some() {
+			return reject("The contract was settled on-chain");
+		}
+		if contract.release.is_some() {
+			return Err(ignore_msg("Received a settlement proposal for a contract whose collateral is being released"));
+		}
+		// If both sides proposed settling at the same time, the proposals only conflict if the
+		// oracle attested to more than one outcome.
+		if contract.pending_settlement.as_ref().map_or(false, |pending| *pending != outcome) {
+			return reject("A settlement for a different outcome is pending");
+		}
+		let branch_idx = match self.check_attestation(&contract.bundle, &outcome, &attestation) {
+			Ok(branch_idx) => branch_idx,
+			Err(reason) => return reject(reason),
+		};
+		match (self.is_pooled(&contract_id), release_signature.as_ref()) {
+			(true, None) => {},
+			(false, Some(signature)) => {
+				let valid = release_bundle(contract, branch_idx)
+					.and_then(|release| release.verify_counterparty_signature(0, signature, &self.secp_ctx));
+				if valid.is_err() { return reject("Invalid collateral release signature"); }
+			},
+			(true, Some(_)) => return reject("Unexpected collateral release signature for a pooled contract"),
+			(false, None) => return reject("Missing collateral release signature"),
+		}
+
+		let settlement = ReceivedSettlement { outcome, release_signature };
+		// If we proposed settling for the same outcome ourselves, we already agreed to it.
+		if contract.pending_settlement.is_some() {
+			if let Err(reason) = self.accept_settlement(&mut contracts, contract_id, settlement, true) {
+				log_error!(self.logger, "Failed to settle contract {}: {}", log_bytes!(contract_id.0), reason);
+			}
+			return Ok(());
+		}
+		let branch = &contract.bundle.branches()[branch_idx];
+		let event = Event::ContractSettlementRequest {
+			contract_id, counterparty_node_id: *counterparty_node_id, outcome: settlement.outcome.clone(),
+			holder_payout_satoshis: branch.holder_payout_satoshis,
+			counterparty_payout_satoshis: branch.counterparty_payout_satoshis,
+		};
+		// Our counterparty may repeat its proposal, e.g. if it thinks we missed it.
+		if contract.received_settlement.as_ref().map_or(true, |received| received.outcome != settlement.outcome) {
+			self.pending_events.lock().unwrap().push(event);
+		}
+		contract.received_settlement = Some(settlement);
+		Ok(())
+	}
+
+	fn handle_settlement_accept(
+		&self, counterparty_node_id: &PublicKey, contract_id: ContractId, release_signature: Option<Signature>,
+	) -> Result<(), LightningError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let outcome = match contracts.get(&contract_id) {
+			Some(contract) if contract.counterparty_node_id == *counterparty_node_id => {
+				match contract.pending_settlement.clone() {
+					Some(outcome) => outcome,
+					None => return Err(ignore_msg("Received a settlement accept for an unknown settlement")),
+				}
+			},
+			_ => return Err(ignore_msg("Received a settlement accept for an unknown settlement")),
+		};
+		if let Err(reason) = self.accept_settlement(&mut contracts, contract_id, ReceivedSettlement { outcome, release_signature }, false) {
+			log_debug!(self.logger, "Failed to complete settlement of contract {}: {}", log_bytes!(contract_id.0), reason);
+			if let Some(contract) = contracts.get_mut(&contract_id) {
+				contract.pending_settlement = None;
+			}
+			self.pending_events.lock().unwrap().push(Event::ContractSettlementFailed {
+				contract_id, counterparty_node_id: *counterparty_node_id, reason: reason.to_owned(),
+			});
+		}
+		Ok(())
+	}
+
+	fn handle_settlement_reject(
+		&self, counterparty_node_id: &PublicKey, contract_id: ContractId, reason: String,
+	) -> Result<(), LightningError> {
+		let mut contracts = self.contracts.lock().unwrap();
+		let contract = match contracts.get_mut(&contract_id) {
+			Some(contract) if contract.counterparty_node_id == *counterparty_node_id => contract,
+			_ => return Err(ignore_msg("Received a settlement reject for an unknown contract")),
+		};
+		if contract.pending_settlement.take().is_none() {
+			return Err(ignore_msg("Received a settlement reject for an unknown settlement"));
+		}
+		log_debug!(self.logger, "Peer rejected settlement of contract {}: {}", log_bytes!(contract_id.0), reason);
+		self.pending_events.lock().unwrap().push(Event::ContractSettlementFailed {
+			contract_id, counterparty_node_id: *counterparty_node_id, reason,
+		});
+		Ok(())
+	}
+
+	fn handle_pool_allocation_proposal(
+		&self, counterparty_node_id: &PublicKey, pool_outpoint: OutPoint, allocation: PoolAllocation,
+	) -> Result<(), LightningError> {
+		let contracts = self.contracts.lock().unwrap();
+		let mut pools = self.collateral_pools.lock().unwrap();
+		let pool = match pools.get_mut(&pool_outpoint) {
+			Some(pool) if pool.counterparty_node_id == *counterparty_node_id => pool,
+			_ => return Err(ignore_msg("Received an allocation proposal for an unknown collateral pool")),
+		};
+		// If both sides proposed an allocation at the same time, each checks the other's proposal
+		// against the pool including its own, such that both come to the same conclusion.
+		let rejection_reason = if !pool.can_allocate(&allocation) {
+			Some("The pool can't cover the allocation")
+		} else {
+			match contracts.get(&allocation.contract_id) {
+				Some(contract) if !pool.backs_contract(&allocation, &contract.counterparty_node_id,
+					&contract.channel_id, contract.bundle.collateral(), contract.holder_collateral_satoshis) =>
+					Some("The contract doesn't spend the pool's output according to the allocation"),
+				_ => None,
+			}
+		};
+		if let Some(reason) = rejection_reason {
+			log_debug!(self.logger, "Rejecting allocation of collateral pool {} to contract {}: {}",
+				pool_outpoint.txid, log_bytes!(allocation.contract_id.0), reason);
+			self.pending_msgs.lock().unwrap().push((*counterparty_node_id, ContractMessage::PoolAllocationReject {
+				pool_outpoint, contract_id: allocation.contract_id, reason: reason.to_owned(),
+			}));
+			return Ok(());
+		}
+
+		self.pending_msgs.lock().unwrap().push((*counterparty_node_id, ContractMessage::PoolAllocationAccept {
+			pool_outpoint, contract_id: allocation.contract_id,
+		}));
+		self.pending_events.lock().unwrap().push(Event::PoolAllocationAdded {
+			pool_outpoint, contract_id: allocation.contract_id, counterparty_node_id: *counterparty_node_id,
+			holder_collateral_satoshis: allocation.holder_collateral_satoshis,
+			counterparty_collateral_satoshis: allocation.counterparty_collateral_satoshis,
+		});
+		pool.allocations.push(allocation);
+		Ok(())
+	}
+
+	fn handle_pool_allocation_response(
+		&self, counterparty_node_id: &PublicKey, pool_outpoint: OutPoint, contract_id: ContractId,
+		rejection_reason: Option<String>,
+	) -> Result<(), LightningError> {
+		let mut pools = self.collateral_pools.lock().unwrap();
+		let pool = match pools.get_mut(&pool_outpoint) {
+			Some(pool) if pool.counterparty_node_id == *counterparty_node_id
+				&& pool.pending_allocation.as_ref().map_or(false, |allocation| allocation.contract_id == contract_id) => pool,
+			_ => return Err(ignore_msg("Received an allocation response for an unknown allocation")),
+		};
+		let allocation = pool.pending_allocation.take().unwrap();
+		match rejection_reason {
+			Some(reason) => {
+				log_debug!(self.logger, "Peer rejected allocation of collateral pool {} to contract {}: {}",
+					pool_outpoint.txid, log_bytes!(contract_id.0), reason);
+				self.pending_events.lock().unwrap().push(Event::PoolAllocationFailed {
+					pool_outpoint, contract_id, counterparty_node_id: *counterparty_node_id, reason,
+				});
+			},
+			None => {
+				self.pending_events.lock().unwrap().push(Event::PoolAllocationAdded {
+					pool_outpoint, contract_id, counterparty_node_id: *counterparty_node_id,
+					holder_collateral_satoshis: allocation.holder_collateral_satoshis,
+					counterparty_collateral_satoshis: allocation.counterparty_collateral_satoshis,
+				});
+				pool.allocations.push(allocation);
+			},
+		}
+		Ok(())
+	}
+
+	fn handle_pool_allocation_release(
+		&self, counterparty_node_id: &PublicKey, pool_outpoint: OutPoint, contract_id: ContractId,
+	) -> Result<(), LightningError> {
+		let contracts = self.contracts.lock().unwrap();
+		if contracts.contains_key(&contract_id) {
+			return Err(ignore_msg("Received an allocation release for a contract which is not settled"));
+		}
+		let mut pools = self.collateral_pools.lock().unwrap();
+		match pools.get_mut(&pool_outpoint) {
+			Some(pool) if pool.counterparty_node_id == *counterparty_node_id => {
+				pool.allocations.retain(|allocation| allocation.contract_id != contract_id);
+				Ok(())
+			},
+			_ => Err(ignore_msg("Received an allocation release for an unknown collateral pool")),
+		}
+	}
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> wire::CustomMessageReader for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	type CustomMessage = ContractMessage;
+
+	fn read<R: io::Read>(&self, message_type: u16, buffer: &mut R) -> Result<Option<ContractMessage>, DecodeError> {
+		match message_type {
+			CONTRACT_RENEWAL_PROPOSAL_TYPE | CONTRACT_RENEWAL_ACCEPT_TYPE |
+			CONTRACT_RENEWAL_SIGN_TYPE | CONTRACT_RENEWAL_ABORT_TYPE |
+			CONTRACT_SETTLEMENT_PROPOSAL_TYPE | CONTRACT_SETTLEMENT_ACCEPT_TYPE |
+			CONTRACT_SETTLEMENT_REJECT_TYPE | POOL_ALLOCATION_PROPOSAL_TYPE |
+			POOL_ALLOCATION_ACCEPT_TYPE | POOL_ALLOCATION_REJECT_TYPE |
+			POOL_ALLOCATION_RELEASE_TYPE => {
+				let message: ContractMessage = Readable::read(buffer)?;
+				if wire::Type::type_id(&message) != message_type { return Err(DecodeError::InvalidValue); }
+				Ok(Some(message))
+			},
+			_ => Ok(None),
+		}
+	}
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> CustomMessageHandler for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	fn handle_custom_message(&self, msg: ContractMessage, sender_node_id: &PublicKey) -> Result<(), LightningError> {
+		match msg {
+			ContractMessage::RenewalProposal {
+				contract_id, new_contract_id, collateral, lock_time, branches, adaptor_points,
+				holder_collateral_satoshis, oracle_announcement,
+			} => self.handle_renewal_proposal(sender_node_id, contract_id, new_contract_id, collateral,
+				lock_time, branches, adaptor_points, holder_collateral_satoshis, oracle_announcement),
+			ContractMessage::RenewalAccept { contract_id, new_contract_id, adaptor_signatures } =>
+				self.handle_renewal_signatures(sender_node_id, contract_id, new_contract_id,
+					adaptor_signatures, RenewalState::ProposalSent),
+			ContractMessage::RenewalSign { contract_id, new_contract_id, adaptor_signatures } =>
+				self.handle_renewal_signatures(sender_node_id, contract_id, new_contract_id,
+					adaptor_signatures, RenewalState::AcceptSent),
+			ContractMessage::RenewalAbort { contract_id, new_contract_id, reason } =>
+				self.handle_renewal_abort(sender_node_id, contract_id, new_contract_id, reason),
+			ContractMessage::SettlementProposal { contract_id, outcome, attestation, release_signature } =>
+				self.handle_settlement_proposal(sender_node_id, contract_id, outcome, attestation, release_signature),
+			ContractMessage::SettlementAccept { contract_id, release_signature } =>
+				self.handle_settlement_accept(sender_node_id, contract_id, release_signature),
+			ContractMessage::SettlementReject { contract_id, reason } =>
+				self.handle_settlement_reject(sender_node_id, contract_id, reason),
+			ContractMessage::PoolAllocationProposal {
+				pool_outpoint, contract_id, holder_collateral_satoshis, counterparty_collateral_satoshis,
+			} => self.handle_pool_allocation_proposal(sender_node_id, pool_outpoint, PoolAllocation {
+				// The sender's view is mirrored for us.
+				contract_id, holder_collateral_satoshis: counterparty_collateral_satoshis,
+				counterparty_collateral_satoshis: holder_collateral_satoshis,
+			}),
+			ContractMessage::PoolAllocationAccept { pool_outpoint, contract_id } =>
+				self.handle_pool_allocation_response(sender_node_id, pool_outpoint, contract_id, None),
+			ContractMessage::PoolAllocationReject { pool_outpoint, contract_id, reason } =>
+				self.handle_pool_allocation_response(sender_node_id, pool_outpoint, contract_id, Some(reason)),
+			ContractMessage::PoolAllocationRelease { pool_outpoint, contract_id } =>
+				self.handle_pool_allocation_release(sender_node_id, pool_outpoint, contract_id),
+		}
+	}
+
+	fn get_and_clear_pending_msg(&self) -> Vec<(PublicKey, ContractMessage)> {
+		core::mem::take(&mut *self.pending_msgs.lock().unwrap())
+	}
+
+	fn provided_node_features(&self) -> NodeFeatures { NodeFeatures::empty() }
+
+	fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures { InitFeatures::empty() }
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> chain::Listen for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	fn filtered_block_connected(&self, header: &BlockHeader, txdata: &TransactionData, height: u32) {
+		*self.best_block.lock().unwrap() = (height, header.time);
+		let mut contracts = self.contracts.lock().unwrap();
+		for (_, tx) in txdata.iter() {
+			for (contract_id, contract) in contracts.iter_mut() {
+				if let Some(release) = contract.release.as_mut() {
+					if release.transaction.txid() == tx.txid() {
+						release.confirmation_height = Some(height);
+						continue;
+					}
+				}
+				// The same transaction may be valid for the current contract if it reuses the
+				// collateral output and terms of a superseded one.
+				if let Some(branch_idx) = settlement_branch_index(&contract.bundle, tx) {
+					contract.confirmed_settlement = Some(ConfirmedSettlement {
+						outcome: contract.bundle.branches()[branch_idx].outcome.clone(), confirmation_height: height,
+					});
+					continue;
+				}
+				for superseded in contract.superseded_contracts.iter_mut() {
+					let collateral_outpoint = superseded.bundle.collateral().outpoint.into_bitcoin_outpoint();
+					if !tx.input.iter().any(|input| input.previous_output == collateral_outpoint) { continue; }
+					superseded.spend_height = Some(height);
+					let branch_idx = match settlement_branch_index(&superseded.bundle, tx) {
+						Some(branch_idx) => branch_idx,
+						None => continue,
+					};
+					log_error!(self.logger, "Superseded contract {} of contract {} was settled on-chain in transaction {}",
+						log_bytes!(superseded.contract_id.0), log_bytes!(contract_id.0), tx.txid());
+					let settlement_transaction = contract.oracle_attestation.as_ref()
+						.and_then(|(outcome, _)| contract.bundle.branch_index(outcome))
+						.map(|idx| contract.bundle.settlement_transaction(idx));
+					self.pending_events.lock().unwrap().push(Event::ContractDisputeDetected {
+						package: DisputePackage {
+							contract_id: *contract_id,
+							counterparty_node_id: contract.counterparty_node_id,
+							channel_id: contract.channel_id,
+							settlement_bundle: contract.bundle.clone(),
+							holder_collateral_satoshis: contract.holder_collateral_satoshis,
+							oracle_attestation: contract.oracle_attestation.clone(),
+							settlement_transaction,
+							outdated_contract_id: superseded.contract_id,
+							outdated_settlement_bundle: superseded.bundle.clone(),
+							outdated_outcome: superseded.bundle.branches()[branch_idx].outcome.clone(),
+							outdated_settlement_transaction: (*tx).clone(),
+							block_hash: header.block_hash(),
+							confirmation_height: height,
+						},
+					});
+				}
+			}
+		}
+
+		// Only once a settlement transaction can no longer be reorged out do we stop tracking its
+		// contract.
+		let settled_contracts = contracts.iter().filter_map(|(contract_id, contract)| {
+			let settlement = contract.confirmed_settlement.as_ref()?;
+			if height + 1 < settlement.confirmation_height + ANTI_REORG_DELAY { return None; }
+			Some(*contract_id)
+		}).collect::<Vec<_>>();
+		for contract_id in settled_contracts {
+			let contract = match contracts.remove(&contract_id) { Some(contract) => contract, None => continue };
+			let outcome = &contract.confirmed_settlement.as_ref().unwrap().outcome;
+			match contract.bundle.branch_index(outcome) {
+				Some(branch_idx) => self.settled_on_chain(contract_id, contract, branch_idx),
+				None => log_error!(self.logger, "Settled contract {} on-chain for an unknown outcome",
+					log_bytes!(contract_id.0)),
+			}
+		}
+
+		// Once their collateral output was spent for good, superseded contracts can no longer be
+		// settled and are pruned.
+		for contract in contracts.values_mut() {
+			contract.superseded_contracts.retain(|superseded| superseded.spend_height
+				.map_or(true, |spend_height| height + 1 < spend_height + ANTI_REORG_DELAY));
+		}
+
+		// Only once the release can no longer be reorged out are the settlement transactions
+		// spending the same collateral output invalidated for good.
+		let released_contracts = contracts.iter().filter_map(|(contract_id, contract)| {
+			let release = contract.release.as_ref()?;
+			if height + 1 < release.confirmation_height? + ANTI_REORG_DELAY { return None; }
+			Some((*contract_id, release.outcome.clone()))
+		}).collect::<Vec<_>>();
+		for (contract_id, outcome) in released_contracts {
+			let contract = match contracts.remove(&contract_id) { Some(contract) => contract, None => continue };
+			match contract.bundle.branch_index(&outcome) {
+				Some(branch_idx) => self.settle(contract_id, contract, outcome, branch_idx),
+				None => log_error!(self.logger, "Released the collateral of contract {} for an unknown outcome",
+					log_bytes!(contract_id.0)),
+			}
+		}
+	}
+
+	fn block_disconnected(&self, _header: &BlockHeader, height: u32) {
+		self.best_block.lock().unwrap().0 = height.saturating_sub(1);
+		for contract in self.contracts.lock().unwrap().values_mut() {
+			if let Some(release) = contract.release.as_mut() {
+				if release.confirmation_height.map_or(false, |confirmation_height| confirmation_height >= height) {
+					release.confirmation_height = None;
+				}
+			}
+			if contract.confirmed_settlement.as_ref().map_or(false, |settlement| settlement.confirmation_height >= height) {
+				contract.confirmed_settlement = None;
+			}
+			for superseded in contract.superseded_contracts.iter_mut() {
+				if superseded.spend_height.map_or(false, |spend_height| spend_height >= height) {
+					superseded.spend_height = None;
+				}
+			}
+		}
+	}
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> CustomOnionMessageHandler for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	type CustomMessage = MarginCallMessage;
+
+	fn handle_custom_message(&self, msg: MarginCallMessage) -> Option<MarginCallMessage> {
+		self.handle_margin_call_message(msg);
+		None
+	}
+
+	fn read_custom_message<R: io::Read>(&self, message_type: u64, buffer: &mut R) -> Result<Option<MarginCallMessage>, DecodeError> {
+		MarginCallMessage::read_custom_message(message_type, buffer)
+	}
+
+	fn release_pending_custom_messages(&self) -> Vec<(MarginCallMessage, Destination, Option<BlindedPath>)> {
+		core::mem::take(&mut *self.pending_onion_msgs.lock().unwrap())
+	}
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> EventsProvider for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	/// Processes [`Event::ContractRenewalRequest`], [`Event::ContractRenewed`],
+	/// [`Event::ContractRenewalFailed`], [`Event::ContractSettlementRequest`],
+	/// [`Event::ContractSettled`], [`Event::ContractSettlementFailed`], [`Event::MarginCallReceived`] and
+	/// [`Event::MarginCallRejected`], [`Event::PoolAllocationAdded`] and
+	/// [`Event::PoolAllocationFailed`] events generated while handling messages from our peers, as
+	/// well as [`Event::ContractDisputeDetected`] and [`Event::ContractSettled`] events generated
+	/// while processing blocks and [`Event::ContractExerciseProgress`] and
+	/// [`Event::BumpTransaction`] events generated on timer ticks.
+	///
+	/// An [`EventHandler`] may safely call back to the provider, e.g. to accept a renewal.
+	fn process_pending_events<H: Deref>(&self, handler: H) where H::Target: EventHandler {
+		let events = core::mem::take(&mut *self.pending_events.lock().unwrap());
+		for event in events {
+			handler.handle_event(event);
+		}
+	}
+}
+
+const SERIALIZATION_VERSION: u8 = 1;
+const MIN_SERIALIZATION_VERSION: u8 = 1;
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> Writeable for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+		write_ver_prefix!(writer, SERIALIZATION_VERSION, MIN_SERIALIZATION_VERSION);
+
+		let contracts = self.contracts.lock().unwrap();
+		(contracts.len() as u64).write(writer)?;
+		for (contract_id, contract) in contracts.iter() {
+			contract_id.write(writer)?;
+			contract.write(writer)?;
+		}
+
+		let oracle_policy = self.oracle_policy.lock().unwrap().clone();
+		let collateral_pools: Vec<_> = self.collateral_pools.lock().unwrap().values().cloned().collect();
+		let (best_block_height, best_block_time) = *self.best_block.lock().unwrap();
+		let pending_msgs = self.pending_msgs.lock().unwrap().clone();
+		write_tlv_fields!(writer, {
+			(0, self.our_node_id, required),
+			(1, oracle_policy, option),
+			(3, collateral_pools, optional_vec),
+			(5, best_block_height, required),
+			(7, best_block_time, required),
+			(9, pending_msgs, optional_vec),
+		});
+		Ok(())
+	}
+}
+
+impl<ES: Deref, CS: Deref, CP: Deref, T: Deref, F: Deref, L: Deref> ReadableArgs<(ES, CS, CP, T, F, L)> for ContractManager<ES, CS, CP, T, F, L>
+where ES::Target: EntropySource, CS::Target: ContractSigner, CP::Target: ContractPaymentSender, T::Target: BroadcasterInterface, F::Target: FeeEstimator, L::Target: Logger {
+	fn read<R: io::Read>(reader: &mut R, args: (ES, CS, CP, T, F, L)) -> Result<Self, DecodeError> {
+		let (entropy_source, contract_signer, payment_sender, broadcaster, fee_estimator, logger) = args;
+		let _ver = read_ver_prefix!(reader, SERIALIZATION_VERSION);
+
+		let contracts_count: u64 = Readable::read(reader)?;
+		let mut contracts = HashMap::new();
+		for _ in 0..contracts_count {
+			let contract_id = Readable::read(reader)?;
+			let contract = Readable::read(reader)?;
+			if contracts.insert(contract_id, contract).is_some() {
+				return Err(DecodeError::InvalidValue);
+			}
+		}
+
+		let mut our_node_id = RequiredWrapper(None);
+		let mut oracle_policy = None;
+		let mut collateral_pools: Option<Vec<CollateralPoolDetails>> = Some(Vec::new());
+		let mut best_block_height: Option<u32> = None;
+		let mut best_block_time: Option<u32> = None;
+		let mut pending_msgs: Option<Vec<(PublicKey, ContractMessage)>> = Some(Vec::new());
+		read_tlv_fields!(reader, {
+			(0, our_node_id, required),
+			(1, oracle_policy, option),
+			(3, collateral_pools, optional_vec),
+			(5, best_block_height, option),
+			(7, best_block_time, option),
+			(9, pending_msgs, optional_vec),
+		});
+		let collateral_pools = collateral_pools.unwrap().into_iter()
+			.map(|pool| (pool.collateral.outpoint, pool)).collect();
+		Ok(Self::from_contracts(entropy_source, contract_signer, payment_sender, broadcaster, fee_estimator,
+			logger, our_node_id.0.unwrap(), contracts, oracle_policy, collateral_pools, best_block_height.unwrap_or(0),
+			best_block_time.unwrap_or(0), pending_msgs.unwrap()))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ContractId, ContractManager, ContractMessage, ContractPaymentSender, ContractSigner,
+		MarginCallMessage, POOL_ALLOCATION_TIMEOUT_TICKS, RENEWAL_TIMEOUT_TICKS};
+	use crate::blinded_path::BlindedPath;
+	use crate::chain::Listen;
+	use crate::chain::channelmonitor::ANTI_REORG_DELAY;
+	use crate::chain::transaction::OutPoint;
+	use crate::events::{Event, EventsProvider};
+	use crate::ln::channelmanager::PaymentId;
+	use crate::ln::contracts::{CollateralOutput, SettlementBranch, SettlementBundle, contract_message_digest};
+	use crate::ln::functional_test_utils::create_dummy_header;
+	use crate::ln::oracle::{EventDescriptor, OracleAnnouncement, OracleAttestation, OraclePolicy,
+		OracleSignature, OracleThreshold, UnsignedOracleAnnouncement};
+	use crate::ln::peer_handler::CustomMessageHandler;
+	use crate::ln::wire::{self, CustomMessageReader};
+	use crate::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, Destination};
+	use crate::sign::KeysManager;
+	use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
+	use crate::util::ser::{ReadableArgs, Writeable};
+	use crate::util::test_utils::{TestBroadcaster, TestFeeEstimator, TestLogger};
+
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::blockdata::transaction::Transaction;
+	use bitcoin::hash_types::{BlockHash, Txid};
+	use bitcoin::hashes::Hash;
+	use bitcoin::network::constants::Network;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use bitcoin::secp256k1::ecdsa::Signature;
+
+	use crate::prelude::*;
+	use crate::sync::Mutex;
+
+	struct TestContractSigner {
+		funding_key: SecretKey,
+		signed_bundles: Mutex<usize>,
+	}
+
+	impl ContractSigner for TestContractSigner {
+		fn sign_settlement_transactions(
+			&self, _counterparty_node_id: &PublicKey, _channel_id: &[u8; 32], bundle: &SettlementBundle,
+			adaptor_points: &[PublicKey],
+		) -> Result<Vec<EcdsaAdaptorSignature>, ()> {
+			*self.signed_bundles.lock().unwrap() += 1;
+			bundle.adaptor_sign_branches(adaptor_points, &self.funding_key, &Secp256k1::new())
+		}
+
+		fn sign_settlement_transaction(
+			&self, _counterparty_node_id: &PublicKey, _channel_id: &[u8; 32], bundle: &SettlementBundle,
+			branch_idx: usize,
+		) -> Result<Signature, ()> {
+			bundle.sign_branch(branch_idx, &self.funding_key, &Secp256k1::new())
+		}
+
+		fn sign_contract_message(
+			&self, _counterparty_node_id: &PublicKey, _channel_id: &[u8; 32], msg: &[u8],
+		) -> Result<Signature, ()> {
+			Ok(Secp256k1::new().sign_ecdsa(&contract_message_digest(msg), &self.funding_key))
+		}
+	}
+
+	struct TestContractPaymentSender {
+		sent_payments: Mutex<Vec<(PublicKey, u64, Vec<u8>, PaymentId)>>,
+		watched_releases: Mutex<Vec<Transaction>>,
+	}
+
+	impl TestContractPaymentSender {
+		fn new() -> Self {
+			Self { sent_payments: Mutex::new(Vec::new()), watched_releases: Mutex::new(Vec::new()) }
+		}
+	}
+
+	impl ContractPaymentSender for TestContractPaymentSender {
+		fn send_contract_payment(
+			&self, counterparty_node_id: &PublicKey, _channel_id: &[u8; 32], amount_msat: u64,
+			payment_metadata: Vec<u8>, payment_id: PaymentId,
+		) -> Result<(), ()> {
+			self.sent_payments.lock().unwrap().push((*counterparty_node_id, amount_msat, payment_metadata, payment_id));
+			Ok(())
+		}
+
+		fn watch_collateral_release(
+			&self, _counterparty_node_id: &PublicKey, _channel_id: &[u8; 32], transaction: &Transaction,
+		) -> Result<(), ()> {
+			self.watched_releases.lock().unwrap().push(transaction.clone());
+			Ok(())
+		}
+	}
+
+	type TestContractManager<'a> = ContractManager<&'a KeysManager, &'a TestContractSigner,
+		&'a TestContractPaymentSender, &'a TestBroadcaster, &'a TestFeeEstimator, &'a TestLogger>;
+
+	fn collateral(idx: u8, holder_key: &SecretKey, counterparty_key: &SecretKey) -> CollateralOutput {
+		let secp_ctx = Secp256k1::new();
+		CollateralOutput {
+			outpoint: OutPoint { txid: Txid::from_slice(&[idx; 32]).unwrap(), index: 0 },
+			value_satoshis: 100_000,
+			holder_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, holder_key),
+			counterparty_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, counterparty_key),
+		}
+	}
+
+	fn branches() -> Vec<SettlementBranch> {
+		(0..4u64).map(|outcome| SettlementBranch {
+			outcome: vec![outcome as u8],
+			holder_payout_satoshis: outcome * 25_000,
+			counterparty_payout_satoshis: 100_000 - outcome * 25_000,
+		}).collect()
+	}
+
+	fn adaptor_points() -> Vec<PublicKey> {
+		let secp_ctx = Secp256k1::new();
+		(0..4u8).map(|outcome| PublicKey::from_secret_key(&secp_ctx,
+			&SecretKey::from_slice(&[outcome + 1; 32]).unwrap())).collect()
+	}
+
+	fn oracle_announcement(oracle_key: &SecretKey) -> OracleAnnouncement {
+		let secp_ctx = Secp256k1::new();
+		let contents = UnsignedOracleAnnouncement {
+			oracle_pubkey: PublicKey::from_secret_key(&secp_ctx, oracle_key),
+			event_id: "event".to_owned(),
+			event_maturity: 500_000,
+			event_descriptor: EventDescriptor::Enum { outcomes: (0..4u8).map(|outcome| vec![outcome]).collect() },
+			attestation_points: adaptor_points(),
+		};
+		let signature = OracleSignature::Ecdsa(secp_ctx.sign_ecdsa(&contents.signing_digest(), oracle_key));
+		OracleAnnouncement { contents, signature }
+	}
+
+	// An announcement whose attestation points are `adaptor_points`, by the oracle trusted in
+	// `enforces_oracle_policy`.
+	fn trusted_announcement() -> OracleAnnouncement {
+		oracle_announcement(&SecretKey::from_slice(&[44; 32]).unwrap())
+	}
+
+	// A policy trusting the oracle of `trusted_announcement`.
+	fn trusted_policy() -> OraclePolicy {
+		let secp_ctx = Secp256k1::new();
+		OraclePolicy::new(vec![PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[44; 32]).unwrap())])
+	}
+
+	// Builds both parties' views of a fully signed settlement bundle.
+	fn signed_bundles(
+		alice_key: &SecretKey, bob_key: &SecretKey, alice_script: &Script, bob_script: &Script,
+	) -> (SettlementBundle, SettlementBundle) {
+		let secp_ctx = Secp256k1::new();
+		let mut alice_bundle = SettlementBundle::new(collateral(1, alice_key, bob_key), alice_script.clone(),
+			bob_script.clone(), 500_000, 546, branches()).unwrap();
+		let bob_branches = branches().iter().map(super::counterparty_branch).collect();
+		let mut bob_bundle = SettlementBundle::new(collateral(1, bob_key, alice_key), bob_script.clone(),
+			alice_script.clone(), 500_000, 546, bob_branches).unwrap();
+		let alice_sigs = alice_bundle.adaptor_sign_branches(&adaptor_points(), alice_key, &secp_ctx).unwrap();
+		let bob_sigs = bob_bundle.adaptor_sign_branches(&adaptor_points(), bob_key, &secp_ctx).unwrap();
+		alice_bundle.set_counterparty_adaptor_signatures(bob_sigs, adaptor_points(), &secp_ctx).unwrap();
+		bob_bundle.set_counterparty_adaptor_signatures(alice_sigs, adaptor_points(), &secp_ctx).unwrap();
+		(alice_bundle, bob_bundle)
+	}
+
+	fn deliver_msgs(from: &TestContractManager, from_node_id: &PublicKey, to: &TestContractManager) -> usize {
+		let msgs = from.get_and_clear_pending_msg();
+		let msg_count = msgs.len();
+		for (_, msg) in msgs {
+			// Round-trip each message through its wire encoding.
+			let encoded = msg.encode();
+			let decoded = to.read(wire::Type::type_id(&msg), &mut &encoded[..]).unwrap().unwrap();
+			assert_eq!(decoded, msg);
+			to.handle_custom_message(decoded, from_node_id).unwrap();
+		}
+		msg_count
+	}
+
+	fn take_events(manager: &TestContractManager) -> Vec<Event> {
+		let events = Mutex::new(Vec::new());
+		manager.process_pending_events(&|event| events.lock().unwrap().push(event));
+		events.into_inner().unwrap()
+	}
+
+	#[test]
+	fn renews_contract() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+		alice.set_oracle_policy(Some(trusted_policy()));
+		bob.set_oracle_policy(Some(trusted_policy()));
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let unsigned_bundle = SettlementBundle::new(collateral(1, &alice_key, &bob_key),
+			alice_script.clone(), bob_script.clone(), 500_000, 546, branches()).unwrap();
+		let contract_id = ContractId([42; 32]);
+		assert!(alice.register_contract(contract_id, bob_node_id, [0; 32], unsigned_bundle.clone(), 50_000).is_err());
+		assert!(alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle.clone(), 100_001).is_err());
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle.clone(), 50_000).unwrap();
+		assert!(alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle, 50_000).is_err());
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		// Bob rejects the first proposal, unquiescing the contract on both sides.
+		let new_collateral = collateral(2, &alice_key, &bob_key);
+		let rejected_contract_id = alice.propose_contract_renewal(&contract_id, new_collateral.clone(),
+			600_000, branches(), adaptor_points(), 50_000, trusted_announcement()).unwrap();
+		assert!(alice.propose_contract_renewal(&contract_id, new_collateral.clone(), 600_000, branches(),
+			adaptor_points(), 50_000, trusted_announcement()).is_err());
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		match &take_events(&bob)[..] {
+			[Event::ContractRenewalRequest { contract_id: id, new_contract_id, settlement_bundle, .. }] => {
+				assert_eq!(*id, contract_id);
+				assert_eq!(*new_contract_id, rejected_contract_id);
+				assert_eq!(settlement_bundle.lock_time(), 600_000);
+				assert_eq!(settlement_bundle.branches()[1].holder_payout_satoshis, 75_000);
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+		bob.abort_contract_renewal(&contract_id).unwrap();
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		match &take_events(&alice)[..] {
+			[Event::ContractRenewalFailed { new_contract_id, .. }] => assert_eq!(*new_contract_id, rejected_contract_id),
+			events => panic!("Unexpected events {:?}", events),
+		}
+		assert!(alice.list_contracts()[0].pending_renewal_contract_id.is_none());
+		assert!(bob.list_contracts()[0].pending_renewal_contract_id.is_none());
+
+		// The second proposal is accepted, after which both sides switch to the new contract, with
+		// Alice now contributing most of the collateral.
+		let new_contract_id = alice.propose_contract_renewal(&contract_id, new_collateral.clone(),
+			600_000, branches(), adaptor_points(), 70_000, trusted_announcement()).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(take_events(&bob).len(), 1);
+		bob.accept_contract_renewal(&contract_id).unwrap();
+		assert!(bob.abort_contract_renewal(&contract_id).is_err());
+
+		// Bob's state, including the pending renewal and the message accepting it, survives a
+		// restart.
+		let bob = <TestContractManager as ReadableArgs<_>>::read(&mut &bob.encode()[..],
+			(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger)).unwrap();
+		assert_eq!(bob.list_contracts()[0].pending_renewal_contract_id, Some(new_contract_id));
+
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(*alice_signer.signed_bundles.lock().unwrap(), 1);
+		assert_eq!(*bob_signer.signed_bundles.lock().unwrap(), 1);
+		for (manager, counterparty_node_id) in [(&alice, bob_node_id), (&bob, alice_node_id)].iter() {
+			match &take_events(manager)[..] {
+				[Event::ContractRenewed { previous_contract_id, contract_id: id, counterparty_node_id: node_id }] => {
+					assert_eq!(*previous_contract_id, contract_id);
+					assert_eq!(*id, new_contract_id);
+					assert_eq!(node_id, counterparty_node_id);
+				},
+				events => panic!("Unexpected events {:?}", events),
+			}
+			let contracts = manager.list_contracts();
+			assert_eq!(contracts.len(), 1);
+			assert_eq!(contracts[0].contract_id, new_contract_id);
+			assert_eq!(contracts[0].settlement_bundle.lock_time(), 600_000);
+			assert!(contracts[0].settlement_bundle.is_fully_signed());
+		}
+		assert_eq!(alice.list_contracts()[0].settlement_bundle.collateral(), &new_collateral);
+		assert_eq!(alice.list_contracts()[0].holder_collateral_satoshis, 70_000);
+		assert_eq!(bob.list_contracts()[0].holder_collateral_satoshis, 30_000);
+
+		// Signatures which don't verify are rejected, aborting the renewal.
+		let next_contract_id = alice.propose_contract_renewal(&new_contract_id,
+			collateral(3, &alice_key, &bob_key), 700_000, branches(), adaptor_points(), 50_000, trusted_announcement()).unwrap();
+		alice.get_and_clear_pending_msg();
+		let bad_sigs = unsigned_bundle.adaptor_sign_branches(&adaptor_points(), &alice_key, &secp_ctx).unwrap();
+		alice.handle_custom_message(ContractMessage::RenewalAccept {
+			contract_id: new_contract_id, new_contract_id: next_contract_id, adaptor_signatures: bad_sigs,
+		}, &bob_node_id).unwrap();
+		match &alice.get_and_clear_pending_msg()[..] {
+			[(node_id, ContractMessage::RenewalAbort { .. })] => assert_eq!(*node_id, bob_node_id),
+			msgs => panic!("Unexpected messages {:?}", msgs),
+		}
+		assert_eq!(take_events(&alice).len(), 1);
+		assert_eq!(alice.list_contracts()[0].contract_id, new_contract_id);
+		assert!(alice.list_contracts()[0].pending_renewal_contract_id.is_none());
+
+		// Proposals whose new contract id wasn't derived from the proposed terms are aborted.
+		bob.handle_custom_message(ContractMessage::RenewalProposal {
+			contract_id: new_contract_id, new_contract_id: ContractId([0; 32]),
+			collateral: collateral(3, &alice_key, &bob_key), lock_time: 700_000, branches: branches(),
+			adaptor_points: adaptor_points(), holder_collateral_satoshis: 50_000, oracle_announcement: None,
+		}, &alice_node_id).unwrap();
+		match &bob.get_and_clear_pending_msg()[..] {
+			[(_, ContractMessage::RenewalAbort { reason, .. })] =>
+				assert_eq!(reason, "The new contract id does not match the proposed terms"),
+			msgs => panic!("Unexpected messages {:?}", msgs),
+		}
+		assert!(take_events(&bob).is_empty());
+		assert!(bob.list_contracts()[0].pending_renewal_contract_id.is_none());
+
+		// As are proposals without an oracle announcement or whose adaptor points weren't announced,
+		// which we refuse to propose in the first place.
+		let mut swapped_points = adaptor_points();
+		swapped_points.swap(0, 1);
+		assert!(alice.propose_contract_renewal(&new_contract_id, collateral(3, &alice_key, &bob_key), 700_000,
+			branches(), swapped_points.clone(), 50_000, trusted_announcement()).is_err());
+		for (points, announcement, expected_reason) in [
+			(adaptor_points(), None, "Renewal proposals must carry an oracle announcement"),
+			(swapped_points, Some(trusted_announcement()),
+				"Adaptor points don't match the oracle announcement: InvalidAttestationPoints"),
+		].iter() {
+			let offer = super::RenewalOffer {
+				contract_id: new_contract_id, collateral: collateral(3, &alice_key, &bob_key),
+				lock_time: 700_000, branches: branches(), adaptor_points: points.clone(),
+				proposer_collateral_satoshis: 50_000,
+			};
+			bob.handle_custom_message(ContractMessage::RenewalProposal {
+				contract_id: new_contract_id, new_contract_id: alice.renewal_contract_id(&bob_node_id, &offer),
+				collateral: offer.collateral, lock_time: 700_000, branches: branches(),
+				adaptor_points: points.clone(), holder_collateral_satoshis: 50_000,
+				oracle_announcement: announcement.clone(),
+			}, &alice_node_id).unwrap();
+			match &bob.get_and_clear_pending_msg()[..] {
+				[(_, ContractMessage::RenewalAbort { reason, .. })] => assert_eq!(reason, expected_reason),
+				msgs => panic!("Unexpected messages {:?}", msgs),
+			}
+		}
+		assert!(take_events(&bob).is_empty());
+		assert!(bob.list_contracts()[0].pending_renewal_contract_id.is_none());
+
+		// Messages from peers other than the contract's counterparty are ignored.
+		assert!(alice.handle_custom_message(ContractMessage::RenewalAbort {
+			contract_id: new_contract_id, new_contract_id: ContractId([0; 32]), reason: String::new(),
+		}, &alice_node_id).is_err());
+		let removed_bundle = alice.remove_contract(&new_contract_id).unwrap();
+		assert_eq!(removed_bundle.lock_time(), 600_000);
+		assert!(alice.list_contracts().is_empty());
+		assert!(payment_sender.sent_payments.lock().unwrap().is_empty());
+	}
+
+	#[test]
+	fn settles_contract_mutually() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let alice_payment_sender = TestContractPaymentSender::new();
+		let bob_payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &alice_payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &bob_payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+		alice.set_oracle_policy(Some(trusted_policy()));
+		bob.set_oracle_policy(Some(trusted_policy()));
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle, 50_000).unwrap();
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		// The oracle attests to outcome 1, paying Alice 25_000 and Bob 75_000 satoshis.
+		let outcome = vec![1];
+		let attestation = SecretKey::from_slice(&[2; 32]).unwrap();
+		let wrong_attestation = SecretKey::from_slice(&[3; 32]).unwrap();
+		assert!(alice.propose_mutual_settlement(&contract_id, outcome.clone(), wrong_attestation).is_err());
+		assert!(alice.propose_mutual_settlement(&contract_id, vec![4], attestation).is_err());
+
+		// Bob rejects a proposal whose attestation doesn't match, unquiescing the contract.
+		alice.propose_mutual_settlement(&contract_id, outcome.clone(), attestation).unwrap();
+		assert!(alice.propose_contract_renewal(&contract_id, collateral(2, &alice_key, &bob_key), 600_000,
+			branches(), adaptor_points(), 50_000, trusted_announcement()).is_err());
+		alice.get_and_clear_pending_msg();
+		bob.handle_custom_message(ContractMessage::SettlementProposal {
+			contract_id, outcome: outcome.clone(), attestation: wrong_attestation, release_signature: None,
+		}, &alice_node_id).unwrap();
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		match &take_events(&alice)[..] {
+			[Event::ContractSettlementFailed { contract_id: id, counterparty_node_id, .. }] => {
+				assert_eq!(*id, contract_id);
+				assert_eq!(*counterparty_node_id, bob_node_id);
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+		assert!(take_events(&bob).is_empty());
+		assert_eq!(alice.list_contracts().len(), 1);
+		assert_eq!(bob.list_contracts().len(), 1);
+
+		// A valid proposal is surfaced to Bob, who may reject it.
+		alice.propose_mutual_settlement(&contract_id, outcome.clone(), attestation).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		match &take_events(&bob)[..] {
+			[Event::ContractSettlementRequest {
+				contract_id: id, counterparty_node_id, outcome: requested_outcome, holder_payout_satoshis,
+				counterparty_payout_satoshis,
+			}] => {
+				assert_eq!(*id, contract_id);
+				assert_eq!(*counterparty_node_id, alice_node_id);
+				assert_eq!(*requested_outcome, outcome);
+				assert_eq!((*holder_payout_satoshis, *counterparty_payout_satoshis), (75_000, 25_000));
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+		bob.reject_mutual_settlement(&contract_id, "Not yet".to_owned()).unwrap();
+		assert!(bob.accept_mutual_settlement(&contract_id).is_err());
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		match &take_events(&alice)[..] {
+			[Event::ContractSettlementFailed { reason, .. }] => assert_eq!(reason, "Not yet"),
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// Once Bob accepts, both sides hand the same release transaction, returning each party's
+		// contribution, to their channel.
+		alice.propose_mutual_settlement(&contract_id, outcome.clone(), attestation).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(take_events(&bob).len(), 1);
+		bob.accept_mutual_settlement(&contract_id).unwrap();
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		let release_tx = alice_payment_sender.watched_releases.lock().unwrap()[0].clone();
+		assert_eq!(*bob_payment_sender.watched_releases.lock().unwrap(), vec![release_tx.clone()]);
+		assert_eq!(release_tx.input[0].previous_output, collateral(1, &alice_key, &bob_key).outpoint.into_bitcoin_outpoint());
+		assert_eq!(release_tx.lock_time.0, 0);
+		let mut release_values = release_tx.output.iter().map(|txout| txout.value).collect::<Vec<_>>();
+		release_values.sort_unstable();
+		assert_eq!(release_values, vec![50_000, 50_000]);
+		assert_eq!(release_tx.input[0].witness.len(), 4);
+
+		// The contract remains tracked, and no payment is sent, until the release is reorg-safe.
+		assert!(alice.propose_mutual_settlement(&contract_id, outcome.clone(), attestation).is_err());
+		let header = create_dummy_header(BlockHash::all_zeros(), 42);
+		for manager in [&alice, &bob].iter() {
+			manager.filtered_block_connected(&header, &[(0, &release_tx)], 500_000);
+		}
+		for height in 500_001..500_000 + ANTI_REORG_DELAY - 1 {
+			alice.filtered_block_connected(&header, &[], height);
+			bob.filtered_block_connected(&header, &[], height);
+		}
+		// A reorg resets the confirmation count.
+		alice.block_disconnected(&header, 500_000);
+		alice.filtered_block_connected(&header, &[], 500_000);
+		alice.filtered_block_connected(&header, &[(0, &release_tx)], 500_001);
+		bob.filtered_block_connected(&header, &[], 500_000 + ANTI_REORG_DELAY - 1);
+		assert_eq!(alice.list_contracts().len(), 1);
+		assert!(bob.list_contracts().is_empty());
+		for height in 500_002..500_001 + ANTI_REORG_DELAY - 1 {
+			alice.filtered_block_connected(&header, &[], height);
+		}
+		assert_eq!(alice.list_contracts().len(), 1);
+		alice.filtered_block_connected(&header, &[], 500_000 + ANTI_REORG_DELAY);
+		assert!(alice.list_contracts().is_empty());
+
+		// Alice pays Bob the 25_000 satoshis she owes him.
+		assert!(bob_payment_sender.sent_payments.lock().unwrap().is_empty());
+		assert_eq!(*alice_payment_sender.sent_payments.lock().unwrap(),
+			vec![(bob_node_id, 25_000_000, contract_id.0.to_vec(), PaymentId(contract_id.0))]);
+		for (manager, payouts, expected_payment_id) in [
+			(&alice, (25_000, 75_000), Some(PaymentId(contract_id.0))), (&bob, (75_000, 25_000), None)
+		].iter() {
+			match &take_events(manager)[..] {
+				[Event::ContractSettled {
+					contract_id: id, outcome: settled_outcome, holder_payout_satoshis,
+					counterparty_payout_satoshis, payment_id, ..
+				}] => {
+					assert_eq!(*id, contract_id);
+					assert_eq!(*settled_outcome, outcome);
+					assert_eq!((*holder_payout_satoshis, *counterparty_payout_satoshis), *payouts);
+					assert_eq!(payment_id, expected_payment_id);
+				},
+				events => panic!("Unexpected events {:?}", events),
+			}
+		}
+	}
+
+	#[test]
+	fn times_out_renewals() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+		alice.set_oracle_policy(Some(trusted_policy()));
+		bob.set_oracle_policy(Some(trusted_policy()));
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle, 50_000).unwrap();
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		let new_contract_id = alice.propose_contract_renewal(&contract_id, collateral(2, &alice_key, &bob_key),
+			600_000, branches(), adaptor_points(), 50_000, trusted_announcement()).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(take_events(&bob).len(), 1);
+		bob.accept_contract_renewal(&contract_id).unwrap();
+		// Bob's signatures never make it to Alice.
+		assert_eq!(bob.get_and_clear_pending_msg().len(), 1);
+
+		for _ in 0..RENEWAL_TIMEOUT_TICKS - 1 {
+			alice.timer_tick_occurred();
+			bob.timer_tick_occurred();
+		}
+		assert!(take_events(&alice).is_empty());
+		assert!(take_events(&bob).is_empty());
+		assert_eq!(bob.list_contracts()[0].pending_renewal_contract_id, Some(new_contract_id));
+
+		alice.timer_tick_occurred();
+		bob.timer_tick_occurred();
+		for manager in [&alice, &bob].iter() {
+			match &take_events(manager)[..] {
+				[Event::ContractRenewalFailed { new_contract_id: id, .. }] => assert_eq!(*id, new_contract_id),
+				events => panic!("Unexpected events {:?}", events),
+			}
+			match &manager.get_and_clear_pending_msg()[..] {
+				[(_, ContractMessage::RenewalAbort { .. })] => {},
+				msgs => panic!("Unexpected messages {:?}", msgs),
+			}
+			let contracts = manager.list_contracts();
+			assert_eq!(contracts[0].contract_id, contract_id);
+			assert!(contracts[0].pending_renewal_contract_id.is_none());
+		}
+
+		// Bob keeps the bundle he handed out signatures for around, as Alice may still settle it
+		// on-chain.
+		assert_eq!(bob.contracts.lock().unwrap()[&contract_id].superseded_contracts.len(), 1);
+		assert!(alice.contracts.lock().unwrap()[&contract_id].superseded_contracts.is_empty());
+	}
+
+	#[test]
+	fn enforces_oracle_policy() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let trusted_oracle_key = SecretKey::from_slice(&[44; 32]).unwrap();
+		let untrusted_oracle_key = SecretKey::from_slice(&[45; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle, 50_000).unwrap();
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		// Without a policy, renewals are neither proposed nor accepted.
+		let new_collateral = collateral(2, &alice_key, &bob_key);
+		assert!(alice.propose_contract_renewal(&contract_id, new_collateral.clone(), 600_000, branches(),
+			adaptor_points(), 50_000, trusted_announcement()).is_err());
+		let untrusted_oracle = PublicKey::from_secret_key(&secp_ctx, &untrusted_oracle_key);
+		alice.set_oracle_policy(Some(OraclePolicy::new(vec![
+			PublicKey::from_secret_key(&secp_ctx, &trusted_oracle_key), untrusted_oracle
+		])));
+		alice.propose_contract_renewal(&contract_id, new_collateral.clone(), 600_000, branches(),
+			adaptor_points(), 50_000, trusted_announcement()).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert!(take_events(&bob).is_empty());
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		match &take_events(&alice)[..] {
+			[Event::ContractRenewalFailed { .. }] => {},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// Bob's policy, which survives a restart, only trusts one oracle.
+		let policy = OraclePolicy::new(vec![PublicKey::from_secret_key(&secp_ctx, &trusted_oracle_key)]);
+		bob.set_oracle_policy(Some(policy.clone()));
+		let bob = <TestContractManager as ReadableArgs<_>>::read(&mut &bob.encode()[..],
+			(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger)).unwrap();
+		assert_eq!(bob.oracle_policy(), Some(policy));
+
+		// Proposals depending on other oracles are aborted.
+		alice.propose_contract_renewal(&contract_id, new_collateral.clone(), 600_000, branches(),
+			adaptor_points(), 50_000, oracle_announcement(&untrusted_oracle_key)).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert!(take_events(&bob).is_empty());
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		match &take_events(&alice)[..] {
+			[Event::ContractRenewalFailed { .. }] => {},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		let announcement = oracle_announcement(&trusted_oracle_key);
+		alice.propose_contract_renewal(&contract_id, new_collateral, 600_000, branches(),
+			adaptor_points(), 50_000, announcement.clone()).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		match &take_events(&bob)[..] {
+			[Event::ContractRenewalRequest { oracle_announcement, .. }] =>
+				assert_eq!(*oracle_announcement, Some(announcement)),
+			events => panic!("Unexpected events {:?}", events),
+		}
+	}
+
+	// Relays the onion messages released by `from` to `to`, round-tripping each through its
+	// encoding.
+	fn deliver_onion_msgs(from: &TestContractManager, to: &TestContractManager) -> Vec<Destination> {
+		from.release_pending_custom_messages().into_iter().map(|(msg, destination, _)| {
+			let encoded = msg.encode();
+			let decoded = MarginCallMessage::read_custom_message(msg.tlv_type(), &mut &encoded[..]).unwrap().unwrap();
+			assert_eq!(decoded, msg);
+			assert!(CustomOnionMessageHandler::handle_custom_message(to, decoded).is_none());
+			destination
+		}).collect()
+	}
+
+	#[test]
+	fn sends_margin_calls() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let alice_payment_sender = TestContractPaymentSender::new();
+		let bob_payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &alice_payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &bob_payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle, 50_000).unwrap();
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+		let reply_path = BlindedPath::new_for_message(&[bob_node_id, alice_node_id], &alice_keys, &secp_ctx).unwrap();
+
+		// Bob rejects the first margin call, with the rejection going over Alice's reply path.
+		assert!(bob.pay_margin_call(&contract_id).is_err());
+		alice.send_margin_call(&contract_id, 10_000, 1_000, reply_path.clone()).unwrap();
+		let request = alice.release_pending_custom_messages().pop().unwrap();
+		match &request.1 {
+			Destination::Node(node_id) => assert_eq!(*node_id, bob_node_id),
+			Destination::BlindedPath(_) => panic!(),
+		}
+		assert!(CustomOnionMessageHandler::handle_custom_message(&bob, request.0.clone()).is_none());
+		match &take_events(&bob)[..] {
+			[Event::MarginCallReceived { contract_id: id, counterparty_node_id, top_up_satoshis, deadline }] => {
+				assert_eq!(*id, contract_id);
+				assert_eq!(*counterparty_node_id, alice_node_id);
+				assert_eq!((*top_up_satoshis, *deadline), (10_000, 1_000));
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// Replayed requests and requests with tampered terms are ignored.
+		assert!(CustomOnionMessageHandler::handle_custom_message(&bob, request.0.clone()).is_none());
+		let mut tampered_request = request.0;
+		if let MarginCallMessage::Request { ref mut deadline, .. } = tampered_request { *deadline = 2_000; }
+		assert!(CustomOnionMessageHandler::handle_custom_message(&bob, tampered_request).is_none());
+		assert!(take_events(&bob).is_empty());
+
+		bob.reject_margin_call(&contract_id, "Insufficient funds".to_owned()).unwrap();
+		assert!(bob.reject_margin_call(&contract_id, String::new()).is_err());
+		match &deliver_onion_msgs(&bob, &alice)[..] {
+			[Destination::BlindedPath(path)] => assert_eq!(*path, reply_path),
+			destinations => panic!("Unexpected destinations {:?}", destinations.len()),
+		}
+		match &take_events(&alice)[..] {
+			[Event::MarginCallRejected { contract_id: id, top_up_satoshis, reason, .. }] => {
+				assert_eq!(*id, contract_id);
+				assert_eq!(*top_up_satoshis, 10_000);
+				assert_eq!(reason, "Insufficient funds");
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// Bob pays the second margin call, which persists across a restart, over the channel.
+		alice.send_margin_call(&contract_id, 20_000, 2_000, reply_path).unwrap();
+		assert_eq!(deliver_onion_msgs(&alice, &bob).len(), 1);
+		assert_eq!(take_events(&bob).len(), 1);
+		let bob = <TestContractManager as ReadableArgs<_>>::read(&mut &bob.encode()[..],
+			(&bob_keys, &bob_signer, &bob_payment_sender, &broadcaster, &fee_estimator, &logger)).unwrap();
+		let payment_id = bob.pay_margin_call(&contract_id).unwrap();
+		assert!(bob.pay_margin_call(&contract_id).is_err());
+		assert_eq!(*bob_payment_sender.sent_payments.lock().unwrap(),
+			vec![(alice_node_id, 20_000_000, contract_id.0.to_vec(), payment_id)]);
+		assert!(bob.release_pending_custom_messages().is_empty());
+
+		// The paid top-up survives a restart.
+		let bob = <TestContractManager as ReadableArgs<_>>::read(&mut &bob.encode()[..],
+			(&bob_keys, &bob_signer, &bob_payment_sender, &broadcaster, &fee_estimator, &logger)).unwrap();
+		assert_eq!(bob.list_contracts()[0].paid_margin_satoshis, 20_000);
+
+		// Margin calls whose amount can't be expressed in millisatoshis are refused.
+		assert!(alice.send_margin_call(&contract_id, u64::max_value() / 1000 + 1, 3_000,
+			BlindedPath::new_for_message(&[bob_node_id, alice_node_id], &alice_keys, &secp_ctx).unwrap()).is_err());
+	}
+
+	#[test]
+	#[cfg(not(feature = "no-std"))]
+	fn reports_portfolio() {
+		use super::{ContractDirection, ContractPosition, PortfolioSnapshot};
+		use crate::routing::router::bench_utils::first_hop;
+		use crate::util::ser::Readable;
+
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+
+		let mut channel = first_hop(alice_node_id);
+		channel.inbound_capacity_msat = 5_000_000;
+		let snapshot = bob.portfolio_snapshot(&[channel.clone()]);
+		assert!(snapshot.positions.is_empty());
+		assert_eq!(snapshot.lightning_balance_msat, channel.balance_msat);
+		assert_eq!(snapshot.outbound_capacity_msat, channel.outbound_capacity_msat);
+		assert_eq!(snapshot.inbound_capacity_msat, 5_000_000);
+
+		// Bob's payout falls from 100k to 25k sats across the outcomes, so he's short.
+		let (_, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 60_000).unwrap();
+		let snapshot = bob.portfolio_snapshot(&[channel.clone(), channel.clone()]);
+		assert_eq!(snapshot.positions, vec![ContractPosition {
+			contract_id,
+			counterparty_node_id: alice_node_id,
+			channel_id: [0; 32],
+			direction: ContractDirection::Short,
+			notional_satoshis: 100_000,
+			maturity: 500_000,
+			holder_collateral_satoshis: 60_000,
+			min_holder_payout_satoshis: 25_000,
+			max_holder_payout_satoshis: 100_000,
+		}]);
+		assert_eq!(snapshot.total_collateral_satoshis, 60_000);
+		assert_eq!(snapshot.total_max_loss_satoshis, 35_000);
+		assert_eq!(snapshot.total_max_gain_satoshis, 40_000);
+		assert_eq!(snapshot.lightning_balance_msat, 2 * channel.balance_msat);
+		assert_eq!(snapshot.inbound_capacity_msat, 10_000_000);
+
+		let decoded: PortfolioSnapshot = Readable::read(&mut &snapshot.encode()[..]).unwrap();
+		assert_eq!(decoded, snapshot);
+	}
+
+	#[test]
+	fn detects_outdated_settlements() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+		alice.set_oracle_policy(Some(trusted_policy()));
+		bob.set_oracle_policy(Some(trusted_policy()));
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle.clone(), 50_000).unwrap();
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		let new_contract_id = alice.propose_contract_renewal(&contract_id, collateral(2, &alice_key, &bob_key),
+			600_000, branches(), adaptor_points(), 50_000, trusted_announcement()).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(take_events(&bob).len(), 1);
+		bob.accept_contract_renewal(&contract_id).unwrap();
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(take_events(&bob).len(), 1);
+
+		let attestation = SecretKey::from_slice(&[2; 32]).unwrap();
+		let wrong_attestation = SecretKey::from_slice(&[3; 32]).unwrap();
+		assert!(bob.record_oracle_attestation(&new_contract_id, vec![1], wrong_attestation).is_err());
+		assert!(bob.record_oracle_attestation(&contract_id, vec![1], attestation).is_err());
+		bob.record_oracle_attestation(&new_contract_id, vec![1], attestation).unwrap();
+
+		// Once a settlement transaction of the superseded contract confirms, even after a restart,
+		// Bob gets all the evidence he needs to dispute it.
+		let bob = <TestContractManager as ReadableArgs<_>>::read(&mut &bob.encode()[..],
+			(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger)).unwrap();
+		let header = create_dummy_header(BlockHash::all_zeros(), 42);
+		let current_settlement_tx = alice_bundle.settlement_transaction(1);
+		let unrelated_tx = SettlementBundle::new(collateral(3, &alice_key, &bob_key), alice_script.clone(),
+			bob_script.clone(), 500_000, 546, branches()).unwrap().settlement_transaction(1);
+		bob.filtered_block_connected(&header, &[(0, &unrelated_tx), (1, &current_settlement_tx)], 500_000);
+		match &take_events(&bob)[..] {
+			[Event::ContractDisputeDetected { package }] => {
+				assert_eq!(package.contract_id, new_contract_id);
+				assert_eq!(package.counterparty_node_id, alice_node_id);
+				assert_eq!(package.settlement_bundle.lock_time(), 600_000);
+				assert_eq!(package.oracle_attestation, Some((vec![1], attestation)));
+				let settlement_tx = package.settlement_transaction.as_ref().unwrap();
+				assert_eq!(*settlement_tx, package.settlement_bundle.settlement_transaction(1));
+				assert_eq!(package.outdated_contract_id, contract_id);
+				assert_eq!(package.outdated_settlement_bundle.lock_time(), 500_000);
+				assert_eq!(package.outdated_outcome, vec![1]);
+				assert_eq!(package.outdated_settlement_transaction, current_settlement_tx);
+				assert_eq!(package.block_hash, header.block_hash());
+				assert_eq!(package.confirmation_height, 500_000);
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// The superseded contract is pruned once the spend of its collateral output is final,
+		// but not if the spend is reorged out before.
+		bob.block_disconnected(&header, 500_000);
+		for height in 500_000..500_000 + ANTI_REORG_DELAY {
+			bob.filtered_block_connected(&create_dummy_header(BlockHash::all_zeros(), height), &[], height);
+		}
+		assert_eq!(bob.contracts.lock().unwrap()[&new_contract_id].superseded_contracts.len(), 1);
+		bob.filtered_block_connected(&header, &[(0, &current_settlement_tx)], 500_000 + ANTI_REORG_DELAY);
+		assert_eq!(take_events(&bob).len(), 1);
+		for height in 500_001 + ANTI_REORG_DELAY..500_000 + 2 * ANTI_REORG_DELAY - 1 {
+			bob.filtered_block_connected(&create_dummy_header(BlockHash::all_zeros(), height), &[], height);
+		}
+		assert_eq!(bob.contracts.lock().unwrap()[&new_contract_id].superseded_contracts.len(), 1);
+		let height = 500_000 + 2 * ANTI_REORG_DELAY - 1;
+		bob.filtered_block_connected(&create_dummy_header(BlockHash::all_zeros(), height), &[], height);
+		assert!(bob.contracts.lock().unwrap()[&new_contract_id].superseded_contracts.is_empty());
+	}
+
+	#[test]
+	fn records_threshold_attestations() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+
+		let (_, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		// Under a 1-of-2 threshold, the contract was signed for the combination of the second
+		// oracle only, whose attestation points are the contract's adaptor points.
+		let oracle_keys = [SecretKey::from_slice(&[44; 32]).unwrap(), SecretKey::from_slice(&[45; 32]).unwrap()];
+		let threshold = OracleThreshold::new(oracle_keys.iter().map(oracle_announcement).collect(), 1).unwrap();
+		let attestation = |oracle_key: &SecretKey, secret: u8| OracleAttestation {
+			oracle_pubkey: PublicKey::from_secret_key(&secp_ctx, oracle_key), event_id: "event".to_owned(),
+			outcome: vec![secret - 1], attestation: SecretKey::from_slice(&[secret; 32]).unwrap(),
+		};
+		assert_eq!(bob.record_threshold_attestations(&contract_id, &threshold, &[]), Ok(false));
+		let mut invalid_attestation = attestation(&oracle_keys[1], 2);
+		invalid_attestation.outcome = vec![0];
+		assert!(bob.record_threshold_attestations(&contract_id, &threshold, &[invalid_attestation]).is_err());
+		assert_eq!(bob.record_threshold_attestations(&contract_id, &threshold,
+			&[attestation(&oracle_keys[1], 2)]), Ok(true));
+		assert_eq!(bob.contracts.lock().unwrap()[&contract_id].oracle_attestation,
+			Some((vec![1], SecretKey::from_slice(&[2; 32]).unwrap())));
+	}
+
+	#[test]
+	fn allocates_pooled_collateral() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+
+		// Alice contributes 60k sats to the pool and Bob 40k.
+		let pool_outpoint = collateral(5, &alice_key, &bob_key).outpoint;
+		assert!(alice.add_collateral_pool(bob_node_id, [0; 32], collateral(5, &alice_key, &bob_key), 100_001).is_err());
+		alice.add_collateral_pool(bob_node_id, [0; 32], collateral(5, &alice_key, &bob_key), 60_000).unwrap();
+		assert!(alice.add_collateral_pool(bob_node_id, [0; 32], collateral(5, &alice_key, &bob_key), 60_000).is_err());
+		bob.add_collateral_pool(alice_node_id, [0; 32], collateral(5, &bob_key, &alice_key), 40_000).unwrap();
+
+		let (first_id, second_id, third_id) = (ContractId([1; 32]), ContractId([2; 32]), ContractId([3; 32]));
+		assert!(alice.propose_pool_allocation(&pool_outpoint, first_id, 60_001, 0).is_err());
+		assert!(alice.propose_pool_allocation(&pool_outpoint, first_id, 0, 40_001).is_err());
+		alice.propose_pool_allocation(&pool_outpoint, first_id, 30_000, 20_000).unwrap();
+		assert!(alice.propose_pool_allocation(&pool_outpoint, second_id, 1_000, 1_000).is_err());
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 1);
+		for (manager, holder_collateral, counterparty_collateral) in [(&alice, 30_000, 20_000), (&bob, 20_000, 30_000)].iter() {
+			match &take_events(manager)[..] {
+				[Event::PoolAllocationAdded { pool_outpoint: outpoint, contract_id, holder_collateral_satoshis, counterparty_collateral_satoshis, .. }] => {
+					assert_eq!(*outpoint, pool_outpoint);
+					assert_eq!(*contract_id, first_id);
+					assert_eq!(holder_collateral_satoshis, holder_collateral);
+					assert_eq!(counterparty_collateral_satoshis, counterparty_collateral);
+				},
+				events => panic!("Unexpected events {:?}", events),
+			}
+		}
+		let pool = alice.list_collateral_pools()[0].clone();
+		assert_eq!((pool.holder_available_satoshis(), pool.counterparty_available_satoshis()), (30_000, 20_000));
+
+		// Concurrent proposals which don't fit into the pool together are rejected on both sides.
+		alice.propose_pool_allocation(&pool_outpoint, second_id, 20_000, 10_000).unwrap();
+		bob.propose_pool_allocation(&pool_outpoint, third_id, 5_000, 15_000).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 2);
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		for manager in [&alice, &bob].iter() {
+			match &take_events(manager)[..] {
+				[Event::PoolAllocationFailed { .. }] => {},
+				events => panic!("Unexpected events {:?}", events),
+			}
+			let pool = manager.list_collateral_pools()[0].clone();
+			assert_eq!(pool.allocations.len(), 1);
+			assert!(pool.pending_allocation.is_none());
+		}
+
+		// Smaller concurrent proposals both fit and are accepted.
+		alice.propose_pool_allocation(&pool_outpoint, second_id, 10_000, 5_000).unwrap();
+		bob.propose_pool_allocation(&pool_outpoint, third_id, 5_000, 10_000).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(deliver_msgs(&bob, &bob_node_id, &alice), 2);
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		assert_eq!(take_events(&alice).len(), 2);
+		assert_eq!(take_events(&bob).len(), 2);
+		let pool = alice.list_collateral_pools()[0].clone();
+		assert_eq!((pool.holder_available_satoshis(), pool.counterparty_available_satoshis()), (10_000, 10_000));
+
+		// Contracts allocated a share of a pool must spend its collateral output.
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+		let (alice_bundle, _) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		assert!(alice.register_contract(first_id, bob_node_id, [0; 32], alice_bundle.clone(), 30_000).is_err());
+		let unpooled_id = ContractId([4; 32]);
+		alice.register_contract(unpooled_id, bob_node_id, [0; 32], alice_bundle, 5_000).unwrap();
+		assert!(alice.propose_pool_allocation(&pool_outpoint, unpooled_id, 5_000, 5_000).is_err());
+
+		// Allocations which our counterparty never responds to are abandoned eventually.
+		let unanswered_id = ContractId([5; 32]);
+		alice.propose_pool_allocation(&pool_outpoint, unanswered_id, 5_000, 5_000).unwrap();
+		assert_eq!(alice.get_and_clear_pending_msg().len(), 1);
+		for _ in 1..POOL_ALLOCATION_TIMEOUT_TICKS {
+			alice.timer_tick_occurred();
+		}
+		assert!(alice.list_collateral_pools()[0].pending_allocation.is_some());
+		alice.timer_tick_occurred();
+		assert!(alice.list_collateral_pools()[0].pending_allocation.is_none());
+		match &take_events(&alice)[..] {
+			[Event::PoolAllocationFailed { contract_id, .. }] => assert_eq!(*contract_id, unanswered_id),
+			events => panic!("Unexpected events {:?}", events),
+		}
+		match &alice.get_and_clear_pending_msg()[..] {
+			[(_, ContractMessage::PoolAllocationRelease { contract_id, .. })] => assert_eq!(*contract_id, unanswered_id),
+			msgs => panic!("Unexpected messages {:?}", msgs),
+		}
+		alice.remove_contract(&unpooled_id).unwrap();
+
+		// Pools survive a restart, and allocations can be released once the contracts are gone.
+		let bob = <TestContractManager as ReadableArgs<_>>::read(&mut &bob.encode()[..],
+			(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger)).unwrap();
+		let pool = bob.list_collateral_pools()[0].clone();
+		assert_eq!(pool.allocations.len(), 3);
+		assert_eq!((pool.holder_available_satoshis(), pool.counterparty_available_satoshis()), (10_000, 10_000));
+		assert!(bob.remove_collateral_pool(&pool_outpoint).is_err());
+		for contract_id in [first_id, second_id, third_id].iter() {
+			alice.release_pool_allocation(&pool_outpoint, contract_id).unwrap();
+		}
+		assert!(alice.release_pool_allocation(&pool_outpoint, &first_id).is_err());
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 3);
+		assert!(bob.list_collateral_pools()[0].allocations.is_empty());
+		bob.remove_collateral_pool(&pool_outpoint).unwrap();
+		assert!(bob.list_collateral_pools().is_empty());
+	}
+
+	#[test]
+	#[cfg(not(feature = "no-std"))]
+	fn evaluates_renewal_proposals() {
+		use super::{CPFP_CHILD_WEIGHT, OfferEvaluationConfig, OfferRecommendation};
+		use crate::chain::chaininterface::fee_for_weight;
+		use crate::ln::features::ChannelTypeFeatures;
+		use crate::routing::router::bench_utils::first_hop;
+
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let (alice_script, bob_script) = (Script::new_op_return(&[1]), Script::new_op_return(&[2]));
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+		alice.set_oracle_policy(Some(trusted_policy()));
+		bob.set_oracle_policy(Some(trusted_policy()));
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle, 50_000).unwrap();
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		// Alice proposes new terms under which Bob gets nothing for the first outcome.
+		let alice_branches = branches().iter().map(super::counterparty_branch).collect();
+		let new_contract_id = alice.propose_contract_renewal(&contract_id, collateral(2, &alice_key, &bob_key),
+			600_000, alice_branches, adaptor_points(), 50_000, trusted_announcement()).unwrap();
+		assert_eq!(deliver_msgs(&alice, &alice_node_id, &bob), 1);
+		let bundle = match &take_events(&bob)[..] {
+			[Event::ContractRenewalRequest { settlement_bundle, .. }] => settlement_bundle.clone(),
+			events => panic!("Unexpected events {:?}", events),
+		};
+
+		let mut config = OfferEvaluationConfig {
+			min_feerate_sat_per_1000_weight: 253,
+			max_feerate_sat_per_1000_weight: 30_000,
+			anchor_reserve_satoshis: 100_000,
+		};
+		let mut channel = first_hop(alice_node_id);
+		channel.unspendable_punishment_reserve = Some(10_000);
+		channel.channel_type = Some(ChannelTypeFeatures::anchors_zero_htlc_fee_and_dependencies());
+
+		// Only the recipient of a proposal can evaluate it.
+		assert!(alice.evaluate_renewal_proposal(&contract_id, &[channel.clone()], &config).is_err());
+
+		let evaluation = bob.evaluate_renewal_proposal(&contract_id, &[channel.clone()], &config).unwrap();
+		assert_eq!(evaluation.new_contract_id, new_contract_id);
+		assert_eq!(evaluation.channel_reserve_satoshis, 10_000);
+		assert_eq!(evaluation.branch_costs.len(), 4);
+		// Bob isn't paid anything in the first branch, so he can't bump it and leaves it to Alice.
+		assert_eq!(evaluation.branch_costs[0].holder_payout_satoshis, 0);
+		assert!(!evaluation.branch_costs[0].bumpable);
+		assert_eq!(evaluation.branch_costs[0].max_cost_satoshis, 0);
+		assert_eq!(evaluation.best_case_cost_satoshis, 0);
+		// None of the other settlement transactions pay a fee by themselves, so they have to be
+		// bumped via CPFP, with the cost exceeding Bob's payout in the second branch.
+		let max_cost = fee_for_weight(30_000, bundle.settlement_transaction_weight(1) + CPFP_CHILD_WEIGHT);
+		assert_eq!(evaluation.branch_costs[1].holder_payout_satoshis, 25_000);
+		assert!(evaluation.branch_costs[1].bumpable);
+		assert_eq!(evaluation.branch_costs[1].max_cost_satoshis, max_cost);
+		assert!(max_cost > 25_000);
+		assert_eq!(evaluation.uncovered_cost_satoshis, max_cost - 25_000);
+		assert_eq!(evaluation.recommendation, OfferRecommendation::Accept);
+
+		// Without an anchor reserve we'd have to rely on our channel reserve for the second branch.
+		config.anchor_reserve_satoshis = 0;
+		match bob.evaluate_renewal_proposal(&contract_id, &[channel.clone()], &config).unwrap().recommendation {
+			OfferRecommendation::Warn { .. } => {},
+			recommendation => panic!("Unexpected recommendation {:?}", recommendation),
+		}
+		// ...which we can't do if the channel's commitment transaction can't be bumped.
+		channel.channel_type = Some(ChannelTypeFeatures::only_static_remote_key());
+		let evaluation = bob.evaluate_renewal_proposal(&contract_id, &[channel], &config).unwrap();
+		assert_eq!(evaluation.channel_reserve_satoshis, 0);
+		match evaluation.recommendation {
+			OfferRecommendation::Reject { .. } => {},
+			recommendation => panic!("Unexpected recommendation {:?}", recommendation),
+		}
+		match bob.evaluate_renewal_proposal(&contract_id, &[], &config).unwrap().recommendation {
+			OfferRecommendation::Reject { .. } => {},
+			recommendation => panic!("Unexpected recommendation {:?}", recommendation),
+		}
+
+		// Once accepted, the proposal can no longer be evaluated.
+		bob.accept_contract_renewal(&contract_id).unwrap();
+		assert!(bob.evaluate_renewal_proposal(&contract_id, &[], &config).is_err());
+	}
+
+	#[test]
+	fn exercises_matured_contracts() {
+		use super::{ContractExerciseStatus, MUTUAL_SETTLEMENT_TIMEOUT_TICKS, SETTLEMENT_REBROADCAST_INTERVAL_TICKS};
+		use crate::events::bump_transaction::BumpTransactionEvent;
+		use crate::sync::Arc;
+		use bitcoin::WPubkeyHash;
+		use bitcoin::blockdata::constants::genesis_block;
+
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		// Alice's payout is P2WPKH, such that she can bump the fee of her settlement transaction.
+		let alice_script = Script::new_v0_p2wpkh(&WPubkeyHash::hash(&[1]));
+		let bob_script = Script::new_op_return(&[2]);
+
+		let logger = TestLogger::new();
+		let broadcaster = TestBroadcaster::with_blocks(Arc::new(Mutex::new(vec![(genesis_block(Network::Testnet), 500_000)])));
+		let fee_estimator = TestFeeEstimator { sat_per_kw: Mutex::new(253) };
+		let alice_keys = KeysManager::new(&[1; 32], 42, 42);
+		let bob_keys = KeysManager::new(&[2; 32], 42, 42);
+		let alice_signer = TestContractSigner { funding_key: alice_key, signed_bundles: Mutex::new(0) };
+		let bob_signer = TestContractSigner { funding_key: bob_key, signed_bundles: Mutex::new(0) };
+		let payment_sender = TestContractPaymentSender::new();
+		let alice = ContractManager::new(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, alice_node_id);
+		let bob = ContractManager::new(&bob_keys, &bob_signer, &payment_sender, &broadcaster, &fee_estimator, &logger, bob_node_id);
+
+		let (alice_bundle, bob_bundle) = signed_bundles(&alice_key, &bob_key, &alice_script, &bob_script);
+		let contract_id = ContractId([42; 32]);
+		alice.register_contract(contract_id, bob_node_id, [0; 32], alice_bundle.clone(), 50_000).unwrap();
+		bob.register_contract(contract_id, alice_node_id, [0; 32], bob_bundle, 50_000).unwrap();
+
+		// Nothing happens before the contract matured.
+		let header = create_dummy_header(BlockHash::all_zeros(), 42);
+		alice.filtered_block_connected(&header, &[], 499_999);
+		alice.timer_tick_occurred();
+		assert!(take_events(&alice).is_empty());
+
+		// Once it did, Alice is asked for the attestation, but only once.
+		alice.filtered_block_connected(&header, &[], 500_000);
+		alice.timer_tick_occurred();
+		alice.timer_tick_occurred();
+		match &take_events(&alice)[..] {
+			[Event::ContractExerciseProgress { contract_id: id, counterparty_node_id, status }] => {
+				assert_eq!(*id, contract_id);
+				assert_eq!(*counterparty_node_id, bob_node_id);
+				assert_eq!(*status, ContractExerciseStatus::AttestationRequested { maturity: 500_000 });
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// With the attestation recorded, Alice proposes settling off-chain, which survives a
+		// restart.
+		let attestation = SecretKey::from_slice(&[2; 32]).unwrap();
+		alice.record_oracle_attestation(&contract_id, vec![1], attestation).unwrap();
+		alice.timer_tick_occurred();
+		match &take_events(&alice)[..] {
+			[Event::ContractExerciseProgress { status: ContractExerciseStatus::MutualSettlementProposed { outcome }, .. }] =>
+				assert_eq!(*outcome, vec![1]),
+			events => panic!("Unexpected events {:?}", events),
+		}
+		match &alice.get_and_clear_pending_msg()[..] {
+			[(node_id, ContractMessage::SettlementProposal { contract_id: id, outcome, .. })] => {
+				assert_eq!(*node_id, bob_node_id);
+				assert_eq!(*id, contract_id);
+				assert_eq!(*outcome, vec![1]);
+			},
+			msgs => panic!("Unexpected messages {:?}", msgs),
+		}
+		let alice = <TestContractManager as ReadableArgs<_>>::read(&mut &alice.encode()[..],
+			(&alice_keys, &alice_signer, &payment_sender, &broadcaster, &fee_estimator, &logger)).unwrap();
+
+		// As Bob never responds, Alice eventually broadcasts the settlement transaction, bumping
+		// its fee via CPFP on her payout output as it doesn't pay any by itself.
+		for _ in 1..MUTUAL_SETTLEMENT_TIMEOUT_TICKS {
+			alice.timer_tick_occurred();
+		}
+		assert!(take_events(&alice).is_empty());
+		assert!(broadcaster.txn_broadcast().is_empty());
+		alice.timer_tick_occurred();
+		let expect_bump = |events: &[Event], settlement_tx: &Transaction, feerate: u32| match events {
+			[Event::BumpTransaction(BumpTransactionEvent::ContractSettlement {
+				package_target_feerate_sat_per_1000_weight, settlement_tx: tx, settlement_tx_fee_satoshis, payout_input, ..
+			})] => {
+				assert_eq!(*package_target_feerate_sat_per_1000_weight, feerate);
+				assert_eq!(tx, settlement_tx);
+				assert_eq!(*settlement_tx_fee_satoshis, 0);
+				assert_eq!(payout_input.outpoint.txid, settlement_tx.txid());
+				assert_eq!(payout_input.previous_utxo, settlement_tx.output[payout_input.outpoint.vout as usize]);
+				assert_eq!(payout_input.previous_utxo.script_pubkey, alice_script);
+			},
+			events => panic!("Unexpected events {:?}", events),
+		};
+		let events = take_events(&alice);
+		let (settlement_tx, first_cpfp_fee_satoshis) = match &events[1..] {
+			[Event::ContractExerciseProgress { status: ContractExerciseStatus::SettlementBroadcast {
+				outcome, transaction, feerate_sat_per_1000_weight, cpfp_fee_satoshis,
+			}, .. }] => {
+				assert_eq!(*outcome, vec![1]);
+				assert_eq!(transaction.txid(), alice_bundle.settlement_transaction(1).txid());
+				assert_eq!(*feerate_sat_per_1000_weight, 253);
+				assert!(*cpfp_fee_satoshis > 0);
+				(transaction.clone(), *cpfp_fee_satoshis)
+			},
+			events => panic!("Unexpected events {:?}", events),
+		};
+		expect_bump(&events[..1], &settlement_tx, 253);
+		assert_eq!(broadcaster.txn_broadcast(), vec![settlement_tx.clone()]);
+
+		// Bob's late proposal is rejected as the contract is being settled on-chain.
+		bob.propose_mutual_settlement(&contract_id, vec![1], attestation).unwrap();
+		deliver_msgs(&bob, &bob_node_id, &alice);
+		assert!(take_events(&alice).is_empty());
+		deliver_msgs(&alice, &alice_node_id, &bob);
+		match &take_events(&bob)[..] {
+			[Event::ContractSettlementFailed { reason, .. }] => assert_eq!(reason, "The contract is being settled on-chain"),
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// The transaction is rebroadcast and bumped regularly, with the user only being notified
+		// again once the fee required to get it confirmed rose.
+		for _ in 0..SETTLEMENT_REBROADCAST_INTERVAL_TICKS {
+			alice.timer_tick_occurred();
+		}
+		assert_eq!(broadcaster.txn_broadcast(), vec![settlement_tx.clone()]);
+		expect_bump(&take_events(&alice), &settlement_tx, 253);
+		*fee_estimator.sat_per_kw.lock().unwrap() = 10_000;
+		for _ in 0..SETTLEMENT_REBROADCAST_INTERVAL_TICKS {
+			alice.timer_tick_occurred();
+		}
+		assert_eq!(broadcaster.txn_broadcast(), vec![settlement_tx.clone()]);
+		let events = take_events(&alice);
+		expect_bump(&events[..1], &settlement_tx, 10_000);
+		match &events[1..] {
+			[Event::ContractExerciseProgress { status: ContractExerciseStatus::SettlementBroadcast {
+				feerate_sat_per_1000_weight, cpfp_fee_satoshis, ..
+			}, .. }] => {
+				assert_eq!(*feerate_sat_per_1000_weight, 10_000);
+				assert!(*cpfp_fee_satoshis > first_cpfp_fee_satoshis);
+			},
+			events => panic!("Unexpected events {:?}", events),
+		}
+
+		// Once the transaction confirms, the contract is no longer exercised, but only considered
+		// settled once the transaction can no longer be reorged out.
+		for manager in [&alice, &bob].iter() {
+			manager.filtered_block_connected(&header, &[(0, &settlement_tx)], 500_001);
+			for height in 500_002..500_000 + ANTI_REORG_DELAY {
+				manager.filtered_block_connected(&create_dummy_header(BlockHash::all_zeros(), height), &[], height);
+			}
+			assert!(take_events(manager).is_empty());
+			assert_eq!(manager.list_contracts().len(), 1);
+		}
+		for _ in 0..SETTLEMENT_REBROADCAST_INTERVAL_TICKS {
+			alice.timer_tick_occurred();
+		}
+		assert!(broadcaster.txn_broadcast().is_empty());
+		assert!(take_events(&alice).is_empty());
+
+		// If the transaction is reorged out, Bob doesn't consider the contract settled until it
+		// confirmed again.
+		bob.block_disconnected(&header, 500_001);
+		let height = 500_000 + ANTI_REORG_DELAY;
+		bob.filtered_block_connected(&create_dummy_header(BlockHash::all_zeros(), height), &[], height);
+		assert!(take_events(&bob).is_empty());
+		bob.filtered_block_connected(&header, &[(0, &settlement_tx)], 500_001);
+		for height in 500_002..500_000 + ANTI_REORG_DELAY {
+			bob.filtered_block_connected(&create_dummy_header(BlockHash::all_zeros(), height), &[], height);
+		}
+		assert!(take_events(&bob).is_empty());
+
+		for manager in [&alice, &bob].iter() {
+			let height = 500_000 + ANTI_REORG_DELAY;
+			manager.filtered_block_connected(&create_dummy_header(BlockHash::all_zeros(), height), &[], height);
+			match &take_events(manager)[..] {
+				[Event::ContractSettled { contract_id: id, outcome, payment_id, .. }] => {
+					assert_eq!(*id, contract_id);
+					assert_eq!(*outcome, vec![1]);
+					assert_eq!(*payment_id, None);
+				},
+				events => panic!("Unexpected events {:?}", events),
+			}
+			assert!(manager.list_contracts().is_empty());
+		}
+		assert!(payment_sender.sent_payments.lock().unwrap().is_empty());
+	}
+}
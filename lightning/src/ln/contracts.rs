@@ -0,0 +1,977 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Utilities for building and verifying bundles of pre-signed settlement transactions which
+//! spend a 2-of-2 collateral output.
+//!
+//! A [`SettlementBundle`] holds one [`SettlementBranch`] per possible oracle outcome, each of
+//! which pays the collateral out to the two parties in a different split, in the style of the
+//! contract execution transactions (CETs) used by discreet log contracts. Both parties sign every
+//! branch ahead of time, such that once the oracle attests to an outcome the corresponding
+//! settlement transaction can be completed and broadcast without further interaction.
+//!
+//! To prevent either party from broadcasting a branch for an outcome the oracle hasn't attested
+//! to, signatures can instead be exchanged as [`EcdsaAdaptorSignature`]s encrypted to the oracle's
+//! anticipated attestation point for each outcome, see
+//! [`SettlementBundle::set_counterparty_adaptor_signatures`].
+//!
+//! The collateral output uses the same script as a channel funding output, see
+//! [`make_funding_redeemscript`], allowing the signature for a branch to be produced by a
+//! channel's signer via [`EcdsaChannelSigner::sign_settlement_transaction`].
+//!
+//! Contracts over numeric outcomes can be described compactly via a [`PayoutCurve`], which both
+//! parties expand into the same branches locally.
+//!
+//! [`EcdsaChannelSigner::sign_settlement_transaction`]: crate::sign::EcdsaChannelSigner::sign_settlement_transaction
+
+use bitcoin::blockdata::script::Script;
+use bitcoin::blockdata::transaction::{EcdsaSighashType, Transaction, TxIn, TxOut};
+use bitcoin::util::sighash;
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::{PackedLockTime, Sequence, Witness};
+
+use bitcoin::secp256k1::{self, Message, PublicKey, Secp256k1, SecretKey};
+use bitcoin::secp256k1::ecdsa::Signature;
+
+use crate::chain::transaction::OutPoint;
+use crate::io;
+use crate::ln::chan_utils::make_funding_redeemscript;
+use crate::ln::contractmanager::ContractId;
+use crate::ln::msgs::DecodeError;
+use crate::util::crypto::sign;
+use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
+use crate::util::ser::{Readable, Writeable, Writer};
+use crate::util::transaction_utils::sort_outputs;
+
+use crate::prelude::*;
+use core::cmp;
+
+/// A 2-of-2 output locking up the collateral of a contract between us (the holder) and our
+/// counterparty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollateralOutput {
+	/// The outpoint of the collateral output.
+	pub outpoint: OutPoint,
+	/// The value of the collateral output, in satoshis.
+	pub value_satoshis: u64,
+	/// Our public key in the 2-of-2 multisig.
+	pub holder_funding_pubkey: PublicKey,
+	/// Our counterparty's public key in the 2-of-2 multisig.
+	pub counterparty_funding_pubkey: PublicKey,
+}
+
+impl CollateralOutput {
+	/// Gets the witness script of the collateral output.
+	pub fn redeemscript(&self) -> Script {
+		make_funding_redeemscript(&self.holder_funding_pubkey, &self.counterparty_funding_pubkey)
+	}
+
+	/// Gets the P2WSH script pubkey of the collateral output.
+	pub fn script_pubkey(&self) -> Script {
+		self.redeemscript().to_v0_p2wsh()
+	}
+}
+
+impl_writeable_tlv_based!(CollateralOutput, {
+	(0, outpoint, required),
+	(2, value_satoshis, required),
+	(4, holder_funding_pubkey, required),
+	(6, counterparty_funding_pubkey, required),
+});
+
+/// One possible way of settling a contract, identified by the oracle outcome it corresponds to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettlementBranch {
+	/// The outcome, as attested to by the oracle, which this branch settles the contract for.
+	pub outcome: Vec<u8>,
+	/// The value paid out to us in this branch, in satoshis.
+	pub holder_payout_satoshis: u64,
+	/// The value paid out to our counterparty in this branch, in satoshis.
+	pub counterparty_payout_satoshis: u64,
+}
+
+impl_writeable_tlv_based!(SettlementBranch, {
+	(0, outcome, required),
+	(2, holder_payout_satoshis, required),
+	(4, counterparty_payout_satoshis, required),
+});
+
+/// The tag of the hash signed by [`EcdsaChannelSigner::sign_contract_message`].
+///
+/// [`EcdsaChannelSigner::sign_contract_message`]: crate::sign::EcdsaChannelSigner::sign_contract_message
+const CONTRACT_MESSAGE_TAG: &[u8] = b"LDK contract message";
+
+/// Computes the digest signed by [`EcdsaChannelSigner::sign_contract_message`] for the given
+/// message, a BIP 340-style tagged hash which can never be mistaken for a transaction sighash.
+///
+/// [`EcdsaChannelSigner::sign_contract_message`]: crate::sign::EcdsaChannelSigner::sign_contract_message
+pub fn contract_message_digest(msg: &[u8]) -> Message {
+	let tag = Sha256::hash(CONTRACT_MESSAGE_TAG);
+	let mut engine = Sha256::engine();
+	engine.input(&tag[..]);
+	engine.input(&tag[..]);
+	engine.input(msg);
+	hash_to_message!(&Sha256::from_engine(engine)[..])
+}
+
+/// The tag of the hash computed by [`derive_contract_id`].
+const CONTRACT_ID_TAG: &[u8] = b"LDK contract id";
+
+/// Derives the canonical id of a contract from the serialized TLVs of the offer it was agreed upon
+/// with, the node ids of both parties and the outpoint of its collateral output.
+///
+/// The node ids may be given in either order, such that both parties derive the same id. As a
+/// collateral output can only be spent once, the ids of distinct contracts only collide if they
+/// reuse the same collateral output on identical terms.
+pub fn derive_contract_id(
+	offer_tlvs: &[u8], node_id_a: &PublicKey, node_id_b: &PublicKey, collateral_outpoint: &OutPoint,
+) -> ContractId {
+	let (node_id_a, node_id_b) = (node_id_a.serialize(), node_id_b.serialize());
+	let (first_node_id, second_node_id) =
+		if node_id_a[..] < node_id_b[..] { (node_id_a, node_id_b) } else { (node_id_b, node_id_a) };
+	let tag = Sha256::hash(CONTRACT_ID_TAG);
+	let mut engine = Sha256::engine();
+	engine.input(&tag[..]);
+	engine.input(&tag[..]);
+	engine.input(&first_node_id);
+	engine.input(&second_node_id);
+	engine.input(&collateral_outpoint.txid[..]);
+	engine.input(&collateral_outpoint.index.to_be_bytes());
+	engine.input(offer_tlvs);
+	ContractId(Sha256::from_engine(engine).into_inner())
+}
+
+/// The weight of the witness spending a collateral output, including the segwit marker and flag
+/// and assuming signatures of the maximum size.
+pub const SETTLEMENT_WITNESS_WEIGHT: u64 = 2 /* marker and flag */ + 1 /* item count */ +
+	1 /* multisig dummy */ + 2 * (1 + 73) /* signatures */ + 1 + 71 /* redeemscript */;
+
+/// Builds an unsigned settlement transaction spending the given collateral output.
+///
+/// Payouts below `dust_limit_satoshis` are omitted and, along with whatever part of the
+/// collateral isn't paid out, go towards the transaction fee. The transaction may not be
+/// confirmed before `lock_time`, which should usually be set to the contract's maturity.
+pub fn build_settlement_transaction(
+	collateral_outpoint: OutPoint, lock_time: u32, dust_limit_satoshis: u64,
+	holder_payout_script: Script, holder_payout_satoshis: u64,
+	counterparty_payout_script: Script, counterparty_payout_satoshis: u64,
+) -> Transaction {
+	let txins = vec![TxIn {
+		previous_output: collateral_outpoint.into_bitcoin_outpoint(),
+		script_sig: Script::new(),
+		sequence: Sequence::ENABLE_LOCKTIME_NO_RBF,
+		witness: Witness::new(),
+	}];
+
+	let mut txouts: Vec<(TxOut, ())> = Vec::new();
+	if counterparty_payout_satoshis >= dust_limit_satoshis && counterparty_payout_satoshis > 0 {
+		txouts.push((TxOut {
+			script_pubkey: counterparty_payout_script,
+			value: counterparty_payout_satoshis,
+		}, ()));
+	}
+	if holder_payout_satoshis >= dust_limit_satoshis && holder_payout_satoshis > 0 {
+		txouts.push((TxOut {
+			script_pubkey: holder_payout_script,
+			value: holder_payout_satoshis,
+		}, ()));
+	}
+	sort_outputs(&mut txouts, |_, _| { cmp::Ordering::Equal }); // Both outputs are identical if the scripts are
+
+	Transaction {
+		version: 2,
+		lock_time: PackedLockTime(lock_time),
+		input: txins,
+		output: txouts.drain(..).map(|(txout, _)| txout).collect(),
+	}
+}
+
+/// A set of [`SettlementBranch`]es spending the same [`CollateralOutput`], along with our
+/// counterparty's signatures for them once received.
+///
+/// Both parties build the same bundle (with the holder and counterparty fields swapped) and
+/// exchange signatures for all branches before the collateral output is funded.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SettlementBundle {
+	collateral: CollateralOutput,
+	holder_payout_script: Script,
+	counterparty_payout_script: Script,
+	lock_time: u32,
+	dust_limit_satoshis: u64,
+	branches: Vec<SettlementBranch>,
+	// Either empty or containing exactly one signature per branch.
+	counterparty_signatures: Vec<Signature>,
+	// Either empty or containing exactly one adaptor signature and point per branch.
+	counterparty_adaptor_signatures: Vec<EcdsaAdaptorSignature>,
+	adaptor_points: Vec<PublicKey>,
+}
+
+impl SettlementBundle {
+	/// Constructs a new bundle.
+	///
+	/// Fails if there are no branches, if any branch pays out more than the collateral output is
+	/// worth, or if two branches correspond to the same outcome.
+	pub fn new(
+		collateral: CollateralOutput, holder_payout_script: Script, counterparty_payout_script: Script,
+		lock_time: u32, dust_limit_satoshis: u64, branches: Vec<SettlementBranch>,
+	) -> Result<Self, ()> {
+		if branches.is_empty() { return Err(()); }
+		let mut outcomes = HashSet::with_capacity(branches.len());
+		for branch in branches.iter() {
+			let total_payout = branch.holder_payout_satoshis.checked_add(branch.counterparty_payout_satoshis);
+			if total_payout.map_or(true, |payout| payout > collateral.value_satoshis) {
+				return Err(());
+			}
+			if !outcomes.insert(&branch.outcome) { return Err(()); }
+		}
+		Ok(SettlementBundle {
+			collateral,
+			holder_payout_script,
+			counterparty_payout_script,
+			lock_time,
+			dust_limit_satoshis,
+			branches,
+			counterparty_signatures: Vec::new(),
+			counterparty_adaptor_signatures: Vec::new(),
+			adaptor_points: Vec::new(),
+		})
+	}
+
+	/// The collateral output all branches spend.
+	pub fn collateral(&self) -> &CollateralOutput {
+		&self.collateral
+	}
+
+	/// The branches of this bundle, one per oracle outcome.
+	pub fn branches(&self) -> &[SettlementBranch] {
+		&self.branches
+	}
+
+	/// The lock time of all settlement transactions in this bundle.
+	pub fn lock_time(&self) -> u32 {
+		self.lock_time
+	}
+
+	/// The script our payouts are sent to.
+	pub fn holder_payout_script(&self) -> &Script {
+		&self.holder_payout_script
+	}
+
+	/// The script our counterparty's payouts are sent to.
+	pub fn counterparty_payout_script(&self) -> &Script {
+		&self.counterparty_payout_script
+	}
+
+	/// Payouts below this value are omitted from settlement transactions.
+	pub fn dust_limit_satoshis(&self) -> u64 {
+		self.dust_limit_satoshis
+	}
+
+	/// Gets the index of the branch for the given oracle outcome, if any.
+	pub fn branch_index(&self, outcome: &[u8]) -> Option<usize> {
+		self.branches.iter().position(|branch| &branch.outcome[..] == outcome)
+	}
+
+	/// Builds the unsigned settlement transaction for the branch at `branch_idx`.
+	///
+	/// Panics if `branch_idx` is out of bounds.
+	pub fn settlement_transaction(&self, branch_idx: usize) -> Transaction {
+		let branch = &self.branches[branch_idx];
+		build_settlement_transaction(
+			self.collateral.outpoint, self.lock_time, self.dust_limit_satoshis,
+			self.holder_payout_script.clone(), branch.holder_payout_satoshis,
+			self.counterparty_payout_script.clone(), branch.counterparty_payout_satoshis,
+		)
+	}
+
+	/// Gets the weight of the settlement transaction for the branch at `branch_idx` once signed,
+	/// see [`SETTLEMENT_WITNESS_WEIGHT`].
+	///
+	/// Panics if `branch_idx` is out of bounds.
+	pub fn settlement_transaction_weight(&self, branch_idx: usize) -> u64 {
+		self.settlement_transaction(branch_idx).weight() as u64 + SETTLEMENT_WITNESS_WEIGHT
+	}
+
+	/// Gets the fee paid by the settlement transaction for the branch at `branch_idx`, i.e. the
+	/// part of the collateral which isn't paid out to either party, including dust payouts.
+	///
+	/// Panics if `branch_idx` is out of bounds.
+	pub fn settlement_fee_satoshis(&self, branch_idx: usize) -> u64 {
+		let paid_out_satoshis: u64 = self.settlement_transaction(branch_idx).output.iter()
+			.map(|txout| txout.value).sum();
+		self.collateral.value_satoshis.saturating_sub(paid_out_satoshis)
+	}
+
+	/// Checks that `transaction` is exactly the settlement transaction we'd build for the branch
+	/// at `branch_idx`, e.g. before signing a transaction provided by our counterparty.
+	pub fn verify_settlement_transaction(&self, branch_idx: usize, transaction: &Transaction) -> Result<(), ()> {
+		if branch_idx >= self.branches.len() { return Err(()); }
+		if self.settlement_transaction(branch_idx) != *transaction { return Err(()); }
+		Ok(())
+	}
+
+	/// Gets the SIGHASH_ALL sighash of the settlement transaction for the branch at `branch_idx`.
+	///
+	/// Panics if `branch_idx` is out of bounds.
+	pub fn get_sighash_all(&self, branch_idx: usize) -> Message {
+		let tx = self.settlement_transaction(branch_idx);
+		let sighash = &sighash::SighashCache::new(&tx).segwit_signature_hash(
+			0, &self.collateral.redeemscript(), self.collateral.value_satoshis, EcdsaSighashType::All
+		).unwrap()[..];
+		hash_to_message!(sighash)
+	}
+
+	/// Signs the settlement transaction for the branch at `branch_idx` with the secret key
+	/// corresponding to our [`CollateralOutput::holder_funding_pubkey`].
+	///
+	/// Signers should generally use [`EcdsaChannelSigner::sign_settlement_transaction`] instead.
+	///
+	/// [`EcdsaChannelSigner::sign_settlement_transaction`]: crate::sign::EcdsaChannelSigner::sign_settlement_transaction
+	pub fn sign_branch<T: secp256k1::Signing>(
+		&self, branch_idx: usize, funding_key: &SecretKey, secp_ctx: &Secp256k1<T>
+	) -> Result<Signature, ()> {
+		if branch_idx >= self.branches.len() { return Err(()); }
+		if PublicKey::from_secret_key(secp_ctx, funding_key) != self.collateral.holder_funding_pubkey {
+			return Err(());
+		}
+		Ok(sign(secp_ctx, &self.get_sighash_all(branch_idx), funding_key))
+	}
+
+	/// Checks a signature from our counterparty for the branch at `branch_idx`.
+	pub fn verify_counterparty_signature<T: secp256k1::Verification>(
+		&self, branch_idx: usize, signature: &Signature, secp_ctx: &Secp256k1<T>
+	) -> Result<(), ()> {
+		if branch_idx >= self.branches.len() { return Err(()); }
+		secp_ctx.verify_ecdsa(&self.get_sighash_all(branch_idx), signature,
+			&self.collateral.counterparty_funding_pubkey).map_err(|_| ())
+	}
+
+	/// Stores our counterparty's signatures for all branches, in the same order as
+	/// [`Self::branches`], after checking each of them.
+	///
+	/// This should be done before the collateral output is funded, as otherwise our counterparty
+	/// could hold our funds hostage.
+	pub fn set_counterparty_signatures<T: secp256k1::Verification>(
+		&mut self, signatures: Vec<Signature>, secp_ctx: &Secp256k1<T>
+	) -> Result<(), ()> {
+		if signatures.len() != self.branches.len() { return Err(()); }
+		for (branch_idx, signature) in signatures.iter().enumerate() {
+			self.verify_counterparty_signature(branch_idx, signature, secp_ctx)?;
+		}
+		self.counterparty_signatures = signatures;
+		Ok(())
+	}
+
+	/// Computes adaptor signatures for the settlement transactions of all branches at once with
+	/// the secret key corresponding to our [`CollateralOutput::holder_funding_pubkey`], each
+	/// encrypted to the entry of `adaptor_points` at the same index.
+	///
+	/// The adaptor point for a branch should be the oracle's anticipated attestation point for the
+	/// branch's outcome, such that the signature can only be completed once the oracle attests to
+	/// that outcome.
+	///
+	/// Signers should generally use
+	/// [`EcdsaChannelSigner::sign_settlement_transactions_with_adaptor_points`] instead.
+	///
+	/// [`EcdsaChannelSigner::sign_settlement_transactions_with_adaptor_points`]: crate::sign::EcdsaChannelSigner::sign_settlement_transactions_with_adaptor_points
+	pub fn adaptor_sign_branches<T: secp256k1::Signing + secp256k1::Verification>(
+		&self, adaptor_points: &[PublicKey], funding_key: &SecretKey, secp_ctx: &Secp256k1<T>
+	) -> Result<Vec<EcdsaAdaptorSignature>, ()> {
+		if adaptor_points.len() != self.branches.len() { return Err(()); }
+		if PublicKey::from_secret_key(secp_ctx, funding_key) != self.collateral.holder_funding_pubkey {
+			return Err(());
+		}
+		let redeemscript = self.collateral.redeemscript();
+		adaptor_points.iter().enumerate().map(|(branch_idx, adaptor_point)| {
+			let tx = self.settlement_transaction(branch_idx);
+			let sighash = &sighash::SighashCache::new(&tx).segwit_signature_hash(
+				0, &redeemscript, self.collateral.value_satoshis, EcdsaSighashType::All
+			).unwrap()[..];
+			Ok(EcdsaAdaptorSignature::encrypt(secp_ctx, &hash_to_message!(sighash), funding_key, adaptor_point))
+		}).collect()
+	}
+
+	/// Stores our counterparty's adaptor signatures for all branches, in the same order as
+	/// [`Self::branches`], after checking each of them against the adaptor point at the same
+	/// index.
+	///
+	/// As with [`Self::set_counterparty_signatures`], this should be done before the collateral
+	/// output is funded.
+	pub fn set_counterparty_adaptor_signatures<T: secp256k1::Signing + secp256k1::Verification>(
+		&mut self, signatures: Vec<EcdsaAdaptorSignature>, adaptor_points: Vec<PublicKey>,
+		secp_ctx: &Secp256k1<T>
+	) -> Result<(), ()> {
+		if signatures.len() != self.branches.len() || adaptor_points.len() != self.branches.len() {
+			return Err(());
+		}
+		for (branch_idx, (signature, adaptor_point)) in signatures.iter().zip(adaptor_points.iter()).enumerate() {
+			signature.verify(secp_ctx, &self.get_sighash_all(branch_idx),
+				&self.collateral.counterparty_funding_pubkey, adaptor_point)?;
+		}
+		self.counterparty_adaptor_signatures = signatures;
+		self.adaptor_points = adaptor_points;
+		Ok(())
+	}
+
+	/// The adaptor point for each branch, as passed to [`Self::set_counterparty_adaptor_signatures`].
+	///
+	/// Empty unless our counterparty's signatures are adaptor signatures.
+	pub fn adaptor_points(&self) -> &[PublicKey] {
+		&self.adaptor_points
+	}
+
+	/// Whether we have received our counterparty's (adaptor) signatures for all branches.
+	pub fn is_fully_signed(&self) -> bool {
+		!self.counterparty_signatures.is_empty() || !self.counterparty_adaptor_signatures.is_empty()
+	}
+
+	/// Builds the fully signed settlement transaction for the given oracle outcome from our
+	/// `holder_signature` and our counterparty's signature stored via
+	/// [`Self::set_counterparty_signatures`].
+	///
+	/// Returns `None` if there is no branch for `outcome` or we don't have our counterparty's
+	/// signatures yet.
+	pub fn build_signed_settlement_transaction(&self, outcome: &[u8], holder_signature: &Signature) -> Option<Transaction> {
+		let branch_idx = self.branch_index(outcome)?;
+		let counterparty_signature = self.counterparty_signatures.get(branch_idx)?;
+		Some(self.build_signed_branch(branch_idx, holder_signature, counterparty_signature))
+	}
+
+	/// Builds the fully signed settlement transaction for the given oracle outcome from our
+	/// `holder_signature` and our counterparty's adaptor signature stored via
+	/// [`Self::set_counterparty_adaptor_signatures`], decrypted with `adaptor_secret`, i.e. the
+	/// oracle's attestation to `outcome`.
+	///
+	/// Returns `None` if there is no branch for `outcome`, we don't have our counterparty's
+	/// adaptor signatures yet, or `adaptor_secret` doesn't match the branch's adaptor point.
+	pub fn build_settlement_transaction_from_attestation<T: secp256k1::Signing>(
+		&self, outcome: &[u8], holder_signature: &Signature, adaptor_secret: &SecretKey,
+		secp_ctx: &Secp256k1<T>
+	) -> Option<Transaction> {
+		let branch_idx = self.branch_index(outcome)?;
+		let adaptor_signature = self.counterparty_adaptor_signatures.get(branch_idx)?;
+		if PublicKey::from_secret_key(secp_ctx, adaptor_secret) != self.adaptor_points[branch_idx] {
+			return None;
+		}
+		let counterparty_signature = adaptor_signature.decrypt(adaptor_secret).ok()?;
+		Some(self.build_signed_branch(branch_idx, holder_signature, &counterparty_signature))
+	}
+
+	fn build_signed_branch(&self, branch_idx: usize, holder_signature: &Signature, counterparty_signature: &Signature) -> Transaction {
+		let mut tx = self.settlement_transaction(branch_idx);
+
+		tx.input[0].witness.push(Vec::new()); // First is the multisig dummy
+
+		let mut holder_sig = holder_signature.serialize_der().to_vec();
+		holder_sig.push(EcdsaSighashType::All as u8);
+		let mut cp_sig = counterparty_signature.serialize_der().to_vec();
+		cp_sig.push(EcdsaSighashType::All as u8);
+		if self.collateral.holder_funding_pubkey.serialize()[..] < self.collateral.counterparty_funding_pubkey.serialize()[..] {
+			tx.input[0].witness.push(holder_sig);
+			tx.input[0].witness.push(cp_sig);
+		} else {
+			tx.input[0].witness.push(cp_sig);
+			tx.input[0].witness.push(holder_sig);
+		}
+
+		tx.input[0].witness.push(self.collateral.redeemscript().into_bytes());
+		tx
+	}
+}
+
+impl_writeable_tlv_based!(SettlementBundle, {
+	(0, collateral, required),
+	(2, holder_payout_script, required),
+	(4, counterparty_payout_script, required),
+	(6, lock_time, required),
+	(8, dust_limit_satoshis, required),
+	(10, branches, required_vec),
+	(12, counterparty_signatures, optional_vec),
+	(14, counterparty_adaptor_signatures, optional_vec),
+	(16, adaptor_points, optional_vec),
+});
+
+/// Encodes a numeric outcome as used in [`SettlementBranch::outcome`], i.e. as 8 big-endian bytes.
+pub fn numeric_outcome_bytes(outcome: u64) -> Vec<u8> {
+	outcome.to_be_bytes().to_vec()
+}
+
+/// The maximum number of outcomes a [`PayoutCurve`] may cover, bounding the number of
+/// [`SettlementBranch`]es it expands into.
+pub const MAX_PAYOUT_CURVE_OUTCOMES: u64 = 1 << 16;
+
+/// The maximum number of [`PayoutPoint`]s or [`RoundingInterval`]s in a [`PayoutCurve`].
+pub const MAX_PAYOUT_CURVE_POINTS: usize = 256;
+
+/// A point on a [`PayoutCurve`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PayoutPoint {
+	/// The numeric outcome at this point.
+	pub outcome: u64,
+	/// Our payout for this outcome, in satoshis.
+	pub holder_payout_satoshis: u64,
+}
+
+impl_writeable_tlv_based!(PayoutPoint, {
+	(0, outcome, required),
+	(2, holder_payout_satoshis, required),
+});
+
+/// Rounding applied to payouts for all outcomes from `begin_outcome` up to the `begin_outcome` of
+/// the next [`RoundingInterval`] of a [`PayoutCurve`].
+///
+/// Rounding payouts to a coarser granularity results in longer runs of outcomes with the same
+/// payout, and thus fewer distinct [`SettlementBranch`]es to sign.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RoundingInterval {
+	/// The first outcome this rounding applies to.
+	pub begin_outcome: u64,
+	/// Payouts are rounded to the nearest multiple of this value, in satoshis.
+	pub rounding_modulus_satoshis: u64,
+}
+
+impl_writeable_tlv_based!(RoundingInterval, {
+	(0, begin_outcome, required),
+	(2, rounding_modulus_satoshis, required),
+});
+
+/// A compact description of the payouts of a contract over a range of numeric outcomes.
+///
+/// Rather than enumerating the payout for every possible outcome, which may easily exceed the size
+/// limits of the messages used to negotiate a contract, payouts are described as a piecewise
+/// linear function of the outcome along with [`RoundingInterval`]s. Both parties expand the curve
+/// into the same [`SettlementBranch`]es locally via [`PayoutCurve::to_branches`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PayoutCurve {
+	points: Vec<PayoutPoint>,
+	rounding_intervals: Vec<RoundingInterval>,
+	total_collateral_satoshis: u64,
+}
+
+impl PayoutCurve {
+	/// Constructs a new payout curve interpolating linearly between `points`, with the
+	/// counterparty receiving whatever part of `total_collateral_satoshis` we don't.
+	///
+	/// Fails if there are fewer than two or more than [`MAX_PAYOUT_CURVE_POINTS`] points, the
+	/// points' outcomes aren't strictly increasing, the curve covers more than
+	/// [`MAX_PAYOUT_CURVE_OUTCOMES`] outcomes, any point pays us more than
+	/// `total_collateral_satoshis`, there are more than [`MAX_PAYOUT_CURVE_POINTS`] rounding
+	/// intervals, the rounding intervals' `begin_outcome`s aren't strictly increasing, or any
+	/// rounding modulus is zero.
+	pub fn new(
+		points: Vec<PayoutPoint>, rounding_intervals: Vec<RoundingInterval>, total_collateral_satoshis: u64,
+	) -> Result<Self, ()> {
+		if points.len() < 2 || points.len() > MAX_PAYOUT_CURVE_POINTS { return Err(()); }
+		if rounding_intervals.len() > MAX_PAYOUT_CURVE_POINTS { return Err(()); }
+		if points.windows(2).any(|pair| pair[0].outcome >= pair[1].outcome) { return Err(()); }
+		if points[points.len() - 1].outcome - points[0].outcome >= MAX_PAYOUT_CURVE_OUTCOMES {
+			return Err(());
+		}
+		if points.iter().any(|point| point.holder_payout_satoshis > total_collateral_satoshis) {
+			return Err(());
+		}
+		if rounding_intervals.windows(2).any(|pair| pair[0].begin_outcome >= pair[1].begin_outcome) {
+			return Err(());
+		}
+		if rounding_intervals.iter().any(|interval| interval.rounding_modulus_satoshis == 0) {
+			return Err(());
+		}
+		Ok(PayoutCurve { points, rounding_intervals, total_collateral_satoshis })
+	}
+
+	/// The lowest outcome covered by this curve.
+	pub fn min_outcome(&self) -> u64 {
+		self.points[0].outcome
+	}
+
+	/// The highest outcome covered by this curve.
+	pub fn max_outcome(&self) -> u64 {
+		self.points[self.points.len() - 1].outcome
+	}
+
+	/// The total value paid out to both parties for any outcome, in satoshis.
+	pub fn total_collateral_satoshis(&self) -> u64 {
+		self.total_collateral_satoshis
+	}
+
+	/// Gets our (rounded) payout for the given outcome, or `None` if it isn't covered by this
+	/// curve.
+	pub fn holder_payout(&self, outcome: u64) -> Option<u64> {
+		if outcome < self.min_outcome() || outcome > self.max_outcome() { return None; }
+		let upper_idx = self.points.iter().position(|point| point.outcome >= outcome).unwrap();
+		let upper = &self.points[upper_idx];
+		let payout = if upper.outcome == outcome {
+			upper.holder_payout_satoshis
+		} else {
+			// `outcome` lies strictly between two points, interpolate between them.
+			let lower = &self.points[upper_idx - 1];
+			let range = (upper.outcome - lower.outcome) as u128;
+			let offset = (outcome - lower.outcome) as u128;
+			if upper.holder_payout_satoshis >= lower.holder_payout_satoshis {
+				let delta = (upper.holder_payout_satoshis - lower.holder_payout_satoshis) as u128;
+				lower.holder_payout_satoshis + ((delta * offset + range / 2) / range) as u64
+			} else {
+				let delta = (lower.holder_payout_satoshis - upper.holder_payout_satoshis) as u128;
+				lower.holder_payout_satoshis - ((delta * offset + range / 2) / range) as u64
+			}
+		};
+		Some(self.round_payout(outcome, payout))
+	}
+
+	fn round_payout(&self, outcome: u64, payout: u64) -> u64 {
+		let modulus = self.rounding_intervals.iter().rev()
+			.find(|interval| interval.begin_outcome <= outcome)
+			.map_or(1, |interval| interval.rounding_modulus_satoshis);
+		let remainder = payout % modulus;
+		let rounded = if remainder >= modulus - remainder {
+			(payout - remainder).saturating_add(modulus)
+		} else {
+			payout - remainder
+		};
+		cmp::min(rounded, self.total_collateral_satoshis)
+	}
+
+	/// The last outcome, starting from `outcome`, up to which payouts are monotonic, i.e. the end
+	/// of the current linear segment or [`RoundingInterval`], whichever comes first.
+	fn monotonic_piece_end(&self, outcome: u64) -> u64 {
+		let next_point = self.points.iter().map(|point| point.outcome)
+			.find(|point_outcome| *point_outcome > outcome)
+			.unwrap_or(self.max_outcome());
+		let next_rounding = self.rounding_intervals.iter().map(|interval| interval.begin_outcome)
+			.find(|begin_outcome| *begin_outcome > outcome);
+		match next_rounding {
+			Some(begin_outcome) if begin_outcome <= next_point => begin_outcome - 1,
+			_ => next_point,
+		}
+	}
+
+	/// Groups all covered outcomes into maximal runs of consecutive outcomes paying out the same,
+	/// returned as `(first_outcome, last_outcome, holder_payout_satoshis)` in increasing order.
+	///
+	/// As payouts are monotonic within each linear segment and [`RoundingInterval`], the end of
+	/// each run is found by binary search, so this takes time logarithmic in the size of the
+	/// outcome range per run rather than visiting every outcome.
+	pub fn payout_ranges(&self) -> Vec<(u64, u64, u64)> {
+		let mut ranges: Vec<(u64, u64, u64)> = Vec::new();
+		let mut outcome = self.min_outcome();
+		loop {
+			let payout = self.holder_payout(outcome).unwrap();
+			let (mut last_outcome, mut upper_bound) = (outcome, self.monotonic_piece_end(outcome));
+			while last_outcome < upper_bound {
+				let mid = last_outcome + (upper_bound - last_outcome + 1) / 2;
+				if self.holder_payout(mid).unwrap() == payout {
+					last_outcome = mid;
+				} else {
+					upper_bound = mid - 1;
+				}
+			}
+			match ranges.last_mut() {
+				Some(range) if range.2 == payout => range.1 = last_outcome,
+				_ => ranges.push((outcome, last_outcome, payout)),
+			}
+			if last_outcome == self.max_outcome() { break; }
+			outcome = last_outcome + 1;
+		}
+		ranges
+	}
+
+	/// Expands this curve into one [`SettlementBranch`] per covered outcome, with outcomes encoded
+	/// via [`numeric_outcome_bytes`].
+	///
+	/// The number of branches is bounded by [`MAX_PAYOUT_CURVE_OUTCOMES`].
+	pub fn to_branches(&self) -> Vec<SettlementBranch> {
+		let mut branches = Vec::new();
+		for (first_outcome, last_outcome, holder_payout_satoshis) in self.payout_ranges() {
+			for outcome in first_outcome..=last_outcome {
+				branches.push(SettlementBranch {
+					outcome: numeric_outcome_bytes(outcome),
+					holder_payout_satoshis,
+					counterparty_payout_satoshis: self.total_collateral_satoshis - holder_payout_satoshis,
+				});
+			}
+		}
+		branches
+	}
+}
+
+impl Writeable for PayoutCurve {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> {
+		write_tlv_fields!(writer, {
+			(0, self.points, required_vec),
+			(2, self.rounding_intervals, optional_vec),
+			(4, self.total_collateral_satoshis, required),
+		});
+		Ok(())
+	}
+}
+
+impl Readable for PayoutCurve {
+	fn read<R: io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+		_init_and_read_tlv_fields!(reader, {
+			(0, points, required_vec),
+			(2, rounding_intervals, optional_vec),
+			(4, total_collateral_satoshis, required),
+		});
+		PayoutCurve::new(points, rounding_intervals.unwrap(), total_collateral_satoshis.0.unwrap())
+			.map_err(|()| DecodeError::InvalidValue)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{CollateralOutput, MAX_PAYOUT_CURVE_OUTCOMES, MAX_PAYOUT_CURVE_POINTS, PayoutCurve, PayoutPoint, RoundingInterval, SettlementBranch, SettlementBundle, derive_contract_id, numeric_outcome_bytes};
+	use crate::ln::msgs::DecodeError;
+	use crate::chain::transaction::OutPoint;
+	use crate::util::ser::{Readable, Writeable};
+
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::blockdata::transaction::TxOut;
+	use bitcoin::consensus::encode;
+	use bitcoin::hash_types::Txid;
+	use bitcoin::hashes::Hash;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+	fn bundle_for(
+		holder_key: &SecretKey, counterparty_key: &SecretKey, holder_script: &Script,
+		counterparty_script: &Script, swap_payouts: bool,
+	) -> SettlementBundle {
+		let secp_ctx = Secp256k1::new();
+		let collateral = CollateralOutput {
+			outpoint: OutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), index: 1 },
+			value_satoshis: 100_000,
+			holder_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, holder_key),
+			counterparty_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, counterparty_key),
+		};
+		let branches = [(b"win".to_vec(), 99_000, 0), (b"lose".to_vec(), 0, 99_000), (b"draw".to_vec(), 49_500, 49_500)]
+			.iter().map(|(outcome, a, b)| {
+				let (holder, counterparty) = if swap_payouts { (*b, *a) } else { (*a, *b) };
+				SettlementBranch { outcome: outcome.clone(), holder_payout_satoshis: holder, counterparty_payout_satoshis: counterparty }
+			}).collect();
+		SettlementBundle::new(collateral, holder_script.clone(), counterparty_script.clone(), 800_000, 354, branches).unwrap()
+	}
+
+	#[test]
+	fn rejects_invalid_bundles() {
+		let secp_ctx = Secp256k1::new();
+		let collateral = CollateralOutput {
+			outpoint: OutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), index: 1 },
+			value_satoshis: 100_000,
+			holder_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap()),
+			counterparty_funding_pubkey: PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap()),
+		};
+		let branch = |outcome: &[u8], holder, counterparty| SettlementBranch {
+			outcome: outcome.to_vec(), holder_payout_satoshis: holder, counterparty_payout_satoshis: counterparty,
+		};
+		let new_bundle = |branches| SettlementBundle::new(collateral.clone(), Script::new(), Script::new(), 0, 354, branches);
+
+		assert!(new_bundle(Vec::new()).is_err());
+		assert!(new_bundle(vec![branch(b"a", 60_000, 50_000)]).is_err());
+		assert!(new_bundle(vec![branch(b"a", u64::max_value(), 1)]).is_err());
+		assert!(new_bundle(vec![branch(b"a", 50_000, 50_000), branch(b"a", 0, 100_000)]).is_err());
+		assert!(new_bundle(vec![branch(b"a", 50_000, 50_000), branch(b"b", 0, 100_000)]).is_ok());
+	}
+
+	#[test]
+	fn settles_via_pre_signed_branches() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[1; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[2; 32]).unwrap();
+		let alice_script = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::hash(&[1; 33]));
+		let bob_script = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::hash(&[2; 33]));
+
+		let mut alice_bundle = bundle_for(&alice_key, &bob_key, &alice_script, &bob_script, false);
+		let mut bob_bundle = bundle_for(&bob_key, &alice_key, &bob_script, &alice_script, true);
+		for branch_idx in 0..alice_bundle.branches().len() {
+			assert_eq!(alice_bundle.settlement_transaction(branch_idx), bob_bundle.settlement_transaction(branch_idx));
+			bob_bundle.verify_settlement_transaction(branch_idx, &alice_bundle.settlement_transaction(branch_idx)).unwrap();
+		}
+
+		// Signing with a key which isn't part of the collateral output fails.
+		assert!(alice_bundle.sign_branch(0, &bob_key, &secp_ctx).is_err());
+
+		let alice_sigs = (0..alice_bundle.branches().len())
+			.map(|idx| alice_bundle.sign_branch(idx, &alice_key, &secp_ctx).unwrap()).collect::<Vec<_>>();
+		let bob_sigs = (0..bob_bundle.branches().len())
+			.map(|idx| bob_bundle.sign_branch(idx, &bob_key, &secp_ctx).unwrap()).collect::<Vec<_>>();
+
+		// Signatures for the wrong branches are rejected.
+		let mut shuffled_sigs = bob_sigs.clone();
+		shuffled_sigs.swap(0, 1);
+		assert!(alice_bundle.set_counterparty_signatures(shuffled_sigs, &secp_ctx).is_err());
+		assert!(!alice_bundle.is_fully_signed());
+		assert!(alice_bundle.build_signed_settlement_transaction(b"win", &alice_sigs[0]).is_none());
+
+		alice_bundle.set_counterparty_signatures(bob_sigs, &secp_ctx).unwrap();
+		bob_bundle.set_counterparty_signatures(alice_sigs.clone(), &secp_ctx).unwrap();
+		assert!(alice_bundle.is_fully_signed());
+		assert!(alice_bundle.build_signed_settlement_transaction(b"unknown", &alice_sigs[0]).is_none());
+
+		// The bundle survives a serialization roundtrip.
+		let read_bundle: SettlementBundle = Readable::read(&mut &alice_bundle.encode()[..]).unwrap();
+		assert_eq!(read_bundle, alice_bundle);
+
+		let draw_idx = alice_bundle.branch_index(b"draw").unwrap();
+		let settlement_tx = alice_bundle.build_signed_settlement_transaction(b"draw", &alice_sigs[draw_idx]).unwrap();
+		assert_eq!(settlement_tx.output.len(), 2);
+		assert_eq!(settlement_tx.lock_time.0, 800_000);
+		assert!(settlement_tx.weight() as u64 <= alice_bundle.settlement_transaction_weight(draw_idx));
+		assert_eq!(alice_bundle.settlement_fee_satoshis(draw_idx), 1_000);
+
+		// The settlement transaction is a valid spend of the collateral output.
+		let collateral_txout = TxOut {
+			value: alice_bundle.collateral().value_satoshis,
+			script_pubkey: alice_bundle.collateral().script_pubkey(),
+		};
+		collateral_txout.script_pubkey.verify(0, bitcoin::Amount::from_sat(collateral_txout.value),
+			&encode::serialize(&settlement_tx)).unwrap();
+
+		// Outputs below the dust limit are omitted.
+		let win_idx = bob_bundle.branch_index(b"win").unwrap();
+		let bob_sig = bob_bundle.sign_branch(win_idx, &bob_key, &secp_ctx).unwrap();
+		let win_tx = alice_bundle.build_signed_settlement_transaction(b"win", &alice_sigs[win_idx]).unwrap();
+		assert_eq!(win_tx, bob_bundle.build_signed_settlement_transaction(b"win", &bob_sig).unwrap());
+		assert_eq!(win_tx.output.len(), 1);
+		assert_eq!(win_tx.output[0].script_pubkey, alice_script);
+	}
+
+	#[test]
+	fn payout_curve_compression() {
+		let point = |outcome, holder_payout_satoshis| PayoutPoint { outcome, holder_payout_satoshis };
+		let rounding = |begin_outcome, rounding_modulus_satoshis| RoundingInterval { begin_outcome, rounding_modulus_satoshis };
+
+		assert!(PayoutCurve::new(vec![point(0, 0)], Vec::new(), 100_000).is_err());
+		assert!(PayoutCurve::new(vec![point(10, 0), point(10, 0)], Vec::new(), 100_000).is_err());
+		assert!(PayoutCurve::new(vec![point(0, 0), point(10, 100_001)], Vec::new(), 100_000).is_err());
+		assert!(PayoutCurve::new(vec![point(0, 0), point(10, 0)], vec![rounding(0, 0)], 100_000).is_err());
+		assert!(PayoutCurve::new(vec![point(0, 0), point(10, 0)], vec![rounding(5, 1), rounding(5, 1)], 100_000).is_err());
+		assert!(PayoutCurve::new(vec![point(0, 0), point(MAX_PAYOUT_CURVE_OUTCOMES, 0)], Vec::new(), 100_000).is_err());
+		assert!(PayoutCurve::new(vec![point(0, 0), point(MAX_PAYOUT_CURVE_OUTCOMES - 1, 0)], Vec::new(), 100_000).is_ok());
+		let many_points = (0..MAX_PAYOUT_CURVE_POINTS as u64 + 1).map(|outcome| point(outcome, 0)).collect();
+		assert!(PayoutCurve::new(many_points, Vec::new(), 100_000).is_err());
+
+		// A call option-like curve: nothing below 1000, linearly increasing up to the full
+		// collateral at 2000, with payouts above 1500 rounded to 10,000 sats.
+		let curve = PayoutCurve::new(
+			vec![point(0, 0), point(1000, 0), point(2000, 100_000), point(4095, 100_000)],
+			vec![rounding(0, 1), rounding(1500, 10_000)], 100_000).unwrap();
+		assert_eq!(curve.holder_payout(0), Some(0));
+		assert_eq!(curve.holder_payout(999), Some(0));
+		assert_eq!(curve.holder_payout(1001), Some(100));
+		assert_eq!(curve.holder_payout(1250), Some(25_000));
+		assert_eq!(curve.holder_payout(1549), Some(50_000));
+		assert_eq!(curve.holder_payout(1551), Some(60_000));
+		assert_eq!(curve.holder_payout(3000), Some(100_000));
+		assert_eq!(curve.holder_payout(4096), None);
+
+		let ranges = curve.payout_ranges();
+		assert_eq!(ranges[0], (0, 1000, 0));
+		assert_eq!(ranges[ranges.len() - 1], (1950, 4095, 100_000));
+		for pair in ranges.windows(2) {
+			assert_eq!(pair[0].1 + 1, pair[1].0);
+			assert_ne!(pair[0].2, pair[1].2);
+		}
+		for (first_outcome, last_outcome, payout) in ranges.iter() {
+			for outcome in *first_outcome..=*last_outcome {
+				assert_eq!(curve.holder_payout(outcome), Some(*payout));
+			}
+		}
+
+		let branches = curve.to_branches();
+		assert_eq!(branches.len(), 4096);
+		assert_eq!(branches[1250].outcome, numeric_outcome_bytes(1250));
+		assert_eq!(branches[1250].holder_payout_satoshis, 25_000);
+		assert_eq!(branches[1250].counterparty_payout_satoshis, 75_000);
+
+		// The compressed curve is much smaller than the expanded branches.
+		let encoded = curve.encode();
+		assert!(encoded.len() * 100 < branches.iter().map(|branch| branch.serialized_length()).sum::<usize>());
+		let read_curve: PayoutCurve = Readable::read(&mut &encoded[..]).unwrap();
+		assert_eq!(read_curve, curve);
+
+		// Invalid curves are rejected on read.
+		let invalid_curve = PayoutCurve { points: vec![point(0, 0)], rounding_intervals: Vec::new(), total_collateral_satoshis: 0 };
+		let read_res: Result<PayoutCurve, DecodeError> = Readable::read(&mut &invalid_curve.encode()[..]);
+		assert_eq!(read_res, Err(DecodeError::InvalidValue));
+		let oversized_curve = PayoutCurve {
+			points: vec![point(0, 0), point(u64::max_value(), 0)], rounding_intervals: Vec::new(), total_collateral_satoshis: 0,
+		};
+		let read_res: Result<PayoutCurve, DecodeError> = Readable::read(&mut &oversized_curve.encode()[..]);
+		assert_eq!(read_res, Err(DecodeError::InvalidValue));
+	}
+
+	#[test]
+	fn settles_via_adaptor_signatures() {
+		let secp_ctx = Secp256k1::new();
+		let alice_key = SecretKey::from_slice(&[1; 32]).unwrap();
+		let bob_key = SecretKey::from_slice(&[2; 32]).unwrap();
+		let alice_script = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::hash(&[1; 33]));
+		let bob_script = Script::new_v0_p2wpkh(&bitcoin::WPubkeyHash::hash(&[2; 33]));
+
+		let mut alice_bundle = bundle_for(&alice_key, &bob_key, &alice_script, &bob_script, false);
+		let bob_bundle = bundle_for(&bob_key, &alice_key, &bob_script, &alice_script, true);
+
+		// Stand-ins for the oracle's attestations to each outcome and the corresponding points.
+		let attestations = (0..alice_bundle.branches().len())
+			.map(|idx| SecretKey::from_slice(&[10 + idx as u8; 32]).unwrap()).collect::<Vec<_>>();
+		let adaptor_points = attestations.iter()
+			.map(|secret| PublicKey::from_secret_key(&secp_ctx, secret)).collect::<Vec<_>>();
+
+		assert!(bob_bundle.adaptor_sign_branches(&adaptor_points[1..], &bob_key, &secp_ctx).is_err());
+		assert!(bob_bundle.adaptor_sign_branches(&adaptor_points, &alice_key, &secp_ctx).is_err());
+		let bob_adaptor_sigs = bob_bundle.adaptor_sign_branches(&adaptor_points, &bob_key, &secp_ctx).unwrap();
+
+		// Adaptor signatures encrypted to the wrong points are rejected.
+		let mut shuffled_points = adaptor_points.clone();
+		shuffled_points.swap(0, 1);
+		assert!(alice_bundle.set_counterparty_adaptor_signatures(bob_adaptor_sigs.clone(), shuffled_points, &secp_ctx).is_err());
+		assert!(!alice_bundle.is_fully_signed());
+		alice_bundle.set_counterparty_adaptor_signatures(bob_adaptor_sigs, adaptor_points, &secp_ctx).unwrap();
+		assert!(alice_bundle.is_fully_signed());
+
+		let read_bundle: SettlementBundle = Readable::read(&mut &alice_bundle.encode()[..]).unwrap();
+		assert_eq!(read_bundle, alice_bundle);
+
+		// Once the oracle attests to "lose", only that branch can be completed.
+		let lose_idx = alice_bundle.branch_index(b"lose").unwrap();
+		let win_idx = alice_bundle.branch_index(b"win").unwrap();
+		let alice_sig = alice_bundle.sign_branch(lose_idx, &alice_key, &secp_ctx).unwrap();
+		assert!(alice_bundle.build_settlement_transaction_from_attestation(
+			b"win", &alice_sig, &attestations[lose_idx], &secp_ctx).is_none());
+		assert!(alice_bundle.build_settlement_transaction_from_attestation(
+			b"lose", &alice_sig, &attestations[win_idx], &secp_ctx).is_none());
+		let settlement_tx = alice_bundle.build_settlement_transaction_from_attestation(
+			b"lose", &alice_sig, &attestations[lose_idx], &secp_ctx).unwrap();
+		assert_eq!(settlement_tx.output.len(), 1);
+		assert_eq!(settlement_tx.output[0].script_pubkey, bob_script);
+
+		alice_bundle.collateral().script_pubkey().verify(0,
+			bitcoin::Amount::from_sat(alice_bundle.collateral().value_satoshis),
+			&encode::serialize(&settlement_tx)).unwrap();
+	}
+
+	#[test]
+	fn derives_contract_ids() {
+		let secp_ctx = Secp256k1::new();
+		let alice_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[1; 32]).unwrap());
+		let bob_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[2; 32]).unwrap());
+		let carol_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[3; 32]).unwrap());
+		let outpoint = OutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), index: 1 };
+
+		// Both parties derive the same id, regardless of the order of the node ids.
+		let contract_id = derive_contract_id(&[1, 2, 3], &alice_node_id, &bob_node_id, &outpoint);
+		assert_eq!(contract_id, derive_contract_id(&[1, 2, 3], &bob_node_id, &alice_node_id, &outpoint));
+
+		// Any change to the offer, the parties or the collateral outpoint changes the id.
+		assert_ne!(contract_id, derive_contract_id(&[1, 2, 4], &alice_node_id, &bob_node_id, &outpoint));
+		assert_ne!(contract_id, derive_contract_id(&[1, 2, 3], &alice_node_id, &carol_node_id, &outpoint));
+		let other_outpoint = OutPoint { txid: outpoint.txid, index: 2 };
+		assert_ne!(contract_id, derive_contract_id(&[1, 2, 3], &alice_node_id, &bob_node_id, &other_outpoint));
+	}
+}
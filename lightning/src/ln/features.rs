@@ -43,9 +43,13 @@
 //!     (see [BOLT-2](https://github.com/lightning/bolts/blob/master/02-peer-protocol.md#the-open_channel-message) for more information).
 //! - `ShutdownAnySegwit` - requires/supports that future segwit versions are allowed in `shutdown`
 //!     (see [BOLT-2](https://github.com/lightning/bolts/blob/master/02-peer-protocol.md) for more information).
+//! - `SimplifiedUpdate` - requires/supports the turn-taking `option_simplified_update` commitment
+//!     update protocol (see [BOLT-2](https://github.com/lightning/bolts/pull/867) for more information).
 //! - `OnionMessages` - requires/supports forwarding onion messages
 //!     (see [BOLT-7](https://github.com/lightning/bolts/pull/759/files) for more information).
 //     TODO: update link
+//! - `ChannelTypeUpgrade` - supports renegotiating the type of existing channels on reconnection
+//!     (see [BOLT-2](https://github.com/lightning/bolts/pull/868) for more information).
 //! - `ChannelType` - node supports the channel_type field in open/accept
 //!     (see [BOLT-2](https://github.com/lightning/bolts/blob/master/02-peer-protocol.md) for more information).
 //! - `SCIDPrivacy` - supply channel aliases for routing
@@ -60,6 +64,8 @@
 //!      for more info).
 //! - `Keysend` - send funds to a node without an invoice
 //!     (see the [`Keysend` feature assignment proposal](https://github.com/lightning/bolts/issues/605#issuecomment-606679798) for more information).
+//! - `OnionMessageFragmentation` - supports reassembling oversized onion messages which were split
+//!     into fragments by the [`OnionMessenger`], an LDK-specific extension.
 //! - `AnchorsZeroFeeHtlcTx` - requires/supports that commitment transactions include anchor outputs
 //!     and HTLC transactions are pre-signed with zero fee (see
 //!     [BOLT-3](https://github.com/lightning/bolts/blob/master/03-transactions.md) for more
@@ -73,6 +79,7 @@
 //!
 //! [BOLT #9]: https://github.com/lightning/bolts/blob/master/09-features.md
 //! [messages]: crate::ln::msgs
+//! [`OnionMessenger`]: crate::onion_message::OnionMessenger
 
 use crate::{io, io_extras};
 use crate::prelude::*;
@@ -145,11 +152,15 @@ mod sealed {
 		// Byte 3
 		ShutdownAnySegwit,
 		// Byte 4
-		OnionMessages,
+		SimplifiedUpdate | OnionMessages,
 		// Byte 5
-		ChannelType | SCIDPrivacy,
+		ChannelTypeUpgrade | ChannelType | SCIDPrivacy,
 		// Byte 6
 		ZeroConf,
+		// Byte 7
+		,
+		// Byte 8
+		OnionMessageFragmentation,
 	]);
 	define_context!(NodeContext, [
 		// Byte 0
@@ -161,11 +172,15 @@ mod sealed {
 		// Byte 3
 		ShutdownAnySegwit,
 		// Byte 4
-		OnionMessages,
+		SimplifiedUpdate | OnionMessages,
 		// Byte 5
-		ChannelType | SCIDPrivacy,
+		ChannelTypeUpgrade | ChannelType | SCIDPrivacy,
 		// Byte 6
 		ZeroConf | Keysend,
+		// Byte 7
+		,
+		// Byte 8
+		OnionMessageFragmentation,
 	]);
 	define_context!(ChannelContext, []);
 	define_context!(Bolt11InvoiceContext, [
@@ -394,9 +409,15 @@ mod sealed {
 	define_feature!(27, ShutdownAnySegwit, [InitContext, NodeContext],
 		"Feature flags for `opt_shutdown_anysegwit`.", set_shutdown_any_segwit_optional,
 		set_shutdown_any_segwit_required, supports_shutdown_anysegwit, requires_shutdown_anysegwit);
+	define_feature!(37, SimplifiedUpdate, [InitContext, NodeContext],
+		"Feature flags for `option_simplified_update`.", set_simplified_update_optional,
+		set_simplified_update_required, supports_simplified_update, requires_simplified_update);
 	define_feature!(39, OnionMessages, [InitContext, NodeContext],
 		"Feature flags for `option_onion_messages`.", set_onion_messages_optional,
 		set_onion_messages_required, supports_onion_messages, requires_onion_messages);
+	define_feature!(43, ChannelTypeUpgrade, [InitContext, NodeContext],
+		"Feature flags for `option_channel_upgrade`.", set_channel_type_upgrade_optional,
+		set_channel_type_upgrade_required, supports_channel_type_upgrade, requires_channel_type_upgrade);
 	define_feature!(45, ChannelType, [InitContext, NodeContext],
 		"Feature flags for `option_channel_type`.", set_channel_type_optional,
 		set_channel_type_required, supports_channel_type, requires_channel_type);
@@ -412,6 +433,10 @@ mod sealed {
 	define_feature!(55, Keysend, [NodeContext],
 		"Feature flags for keysend payments.", set_keysend_optional, set_keysend_required,
 		supports_keysend, requires_keysend);
+	define_feature!(65, OnionMessageFragmentation, [InitContext, NodeContext],
+		"Feature flags for reassembling onion messages sent as fragments.",
+		set_onion_message_fragmentation_optional, set_onion_message_fragmentation_required,
+		supports_onion_message_fragmentation, requires_onion_message_fragmentation);
 	// Note: update the module-level docs when a new feature bit is added!
 
 	#[cfg(test)]
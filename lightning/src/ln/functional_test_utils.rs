@@ -519,6 +519,7 @@ impl<'a, 'b, 'c> Drop for Node<'a, 'b, 'c> {
 
 			let broadcaster = test_utils::TestBroadcaster {
 				txn_broadcasted: Mutex::new(self.tx_broadcaster.txn_broadcasted.lock().unwrap().clone()),
+				txn_broadcasted_metadata: Mutex::new(self.tx_broadcaster.txn_broadcasted_metadata.lock().unwrap().clone()),
 				blocks: Arc::new(Mutex::new(self.tx_broadcaster.blocks.lock().unwrap().clone())),
 			};
 
@@ -711,6 +712,9 @@ pub fn remove_first_msg_event_to_node(msg_node_id: &PublicKey, msg_events: &mut
 		MessageSendEvent::SendRevokeAndACK { node_id, .. } => {
 			node_id == msg_node_id
 		},
+		MessageSendEvent::SendYield { node_id, .. } => {
+			node_id == msg_node_id
+		},
 		MessageSendEvent::SendClosingSigned { node_id, .. } => {
 			node_id == msg_node_id
 		},
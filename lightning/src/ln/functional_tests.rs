@@ -10105,3 +10105,173 @@ fn test_remove_expired_inbound_unfunded_channels() {
 
 	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
 }
+
+#[test]
+fn test_simplified_update_turn_taking() {
+	// Checks that with `option_simplified_update` a node which doesn't hold the turn queues its
+	// updates in the holding cell and only sends them once its counterparty yielded the turn.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = test_default_channel_config();
+	config.channel_handshake_config.negotiate_simplified_update = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	// nodes[0] funded the channel and thus holds the turn, so nodes[1] has to ask for it first.
+	let (route, payment_hash, payment_preimage, payment_secret) = get_route_and_payment_hash!(nodes[1], nodes[0], 100_000);
+	nodes[1].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[1], 0);
+	let request = get_event_msg!(nodes[1], MessageSendEvent::SendYield, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_yield(&nodes[1].node.get_our_node_id(), &request);
+	let handover = get_event_msg!(nodes[0], MessageSendEvent::SendYield, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_yield(&nodes[0].node.get_our_node_id(), &handover);
+
+	let mut events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	check_added_monitors!(nodes[1], 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[0].node.handle_update_add_htlc(&nodes[1].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[0]);
+	expect_payment_claimable!(nodes[0], payment_hash, payment_secret, 100_000);
+
+	// nodes[1] now holds the turn, so nodes[0]'s claim has to wait in the holding cell.
+	nodes[0].node.claim_funds(payment_preimage);
+	expect_payment_claimed!(nodes[0], payment_hash, 100_000);
+	check_added_monitors!(nodes[0], 1);
+	let request = get_event_msg!(nodes[0], MessageSendEvent::SendYield, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_yield(&nodes[0].node.get_our_node_id(), &request);
+	let handover = get_event_msg!(nodes[1], MessageSendEvent::SendYield, nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_yield(&nodes[1].node.get_our_node_id(), &handover);
+
+	let updates = get_htlc_update_msgs!(nodes[0], nodes[1].node.get_our_node_id());
+	check_added_monitors!(nodes[0], 1);
+	nodes[1].node.handle_update_fulfill_htlc(&nodes[0].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], updates.commitment_signed, false);
+	expect_payment_sent!(nodes[1], payment_preimage);
+
+	// An update sent while we hold the turn and have updates in flight is a protocol violation.
+	let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[1], 100_000);
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	let update_fail = msgs::UpdateFailHTLC {
+		channel_id: payment_event.msgs[0].channel_id,
+		htlc_id: payment_event.msgs[0].htlc_id,
+		reason: msgs::OnionErrorPacket { data: Vec::new() },
+	};
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &update_fail);
+	check_closed_broadcast!(nodes[0], true);
+	check_added_monitors!(nodes[0], 1);
+	check_closed_event!(nodes[0], 1, ClosureReason::ProcessingError { err: "Peer sent update_fail_htlc when it wasn't its turn".to_string() });
+}
+
+#[test]
+fn test_migrate_counterparty_node_id() {
+	// Test that our channels with a counterparty, including their `ChannelMonitor`s, can be moved
+	// over to a new node id after it rotated its node key. As we can't rotate a node's key in the
+	// test harness, we migrate to a new node id and back again, checking that the channel keeps
+	// working.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	let node_b_id = nodes[1].node.get_our_node_id();
+	let new_node_b_id = PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[42; 32]).unwrap());
+
+	// The counterparty must be disconnected to be migrated.
+	assert!(nodes[0].node.migrate_counterparty_node_id(&node_b_id, &new_node_b_id).is_err());
+
+	nodes[0].node.peer_disconnected(&node_b_id);
+	nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id());
+
+	nodes[0].node.migrate_counterparty_node_id(&node_b_id, &new_node_b_id).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let channels = nodes[0].node.list_channels();
+	assert_eq!(channels.len(), 1);
+	assert_eq!(channels[0].counterparty.node_id, new_node_b_id);
+	assert_eq!(get_monitor!(nodes[0], chan_id).get_counterparty_node_id(), Some(new_node_b_id));
+	assert!(nodes[0].node.migrate_counterparty_node_id(&node_b_id, &new_node_b_id).is_err());
+
+	nodes[0].node.migrate_counterparty_node_id(&new_node_b_id, &node_b_id).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	assert_eq!(nodes[0].node.list_channels()[0].counterparty.node_id, node_b_id);
+	assert_eq!(get_monitor!(nodes[0], chan_id).get_counterparty_node_id(), Some(node_b_id));
+
+	reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+	send_payment(&nodes[0], &[&nodes[1]], 1_000_000);
+}
+
+#[test]
+fn test_simplified_update_turn_after_reconnect() {
+	// Checks that with `option_simplified_update` the turn is resynchronized upon reconnection if a
+	// `yield` got lost, with the node which didn't get the turn asking for it again.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut config = test_default_channel_config();
+	config.channel_handshake_config.negotiate_simplified_update = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), Some(config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let take_yields = |node_idx: usize| nodes[node_idx].node.get_and_clear_pending_msg_events().into_iter()
+		.filter_map(|event| match event {
+			MessageSendEvent::SendYield { msg, .. } => Some(msg),
+			_ => None,
+		}).collect::<Vec<_>>();
+	// Reconnects the nodes, returning whether each claimed the turn and the `yield`s each sent.
+	let reconnect = || {
+		nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+		nodes[1].node.peer_disconnected(&nodes[0].node.get_our_node_id());
+		nodes[0].node.peer_connected(&nodes[1].node.get_our_node_id(), &msgs::Init {
+			features: nodes[1].node.init_features(), networks: None, remote_network_address: None
+		}, true).unwrap();
+		let as_reestablish = get_chan_reestablish_msgs!(nodes[0], nodes[1]).pop().unwrap();
+		nodes[1].node.peer_connected(&nodes[0].node.get_our_node_id(), &msgs::Init {
+			features: nodes[0].node.init_features(), networks: None, remote_network_address: None
+		}, false).unwrap();
+		let bs_reestablish = get_chan_reestablish_msgs!(nodes[1], nodes[0]).pop().unwrap();
+		nodes[1].node.handle_channel_reestablish(&nodes[0].node.get_our_node_id(), &as_reestablish);
+		nodes[0].node.handle_channel_reestablish(&nodes[1].node.get_our_node_id(), &bs_reestablish);
+		((as_reestablish.sender_holds_turn, bs_reestablish.sender_holds_turn), take_yields(0), take_yields(1))
+	};
+
+	// nodes[1]'s request for the turn gets lost. As nodes[0] keeps the turn upon reconnection,
+	// nodes[1] asks for it again.
+	let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[1], nodes[0], 100_000);
+	nodes[1].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[1], 0);
+	assert_eq!(take_yields(1).len(), 1);
+	let (claimed_turns, as_yields, mut bs_yields) = reconnect();
+	assert_eq!(claimed_turns, (Some(true), Some(false)));
+	assert!(as_yields.is_empty());
+	assert_eq!(bs_yields.len(), 1);
+
+	// nodes[0]'s handover gets lost. As neither node claims the turn upon reconnection, it goes back
+	// to nodes[0], the funder, with nodes[1] asking for it again.
+	nodes[0].node.handle_yield(&nodes[1].node.get_our_node_id(), &bs_yields.pop().unwrap());
+	assert_eq!(take_yields(0).len(), 1);
+	let (claimed_turns, as_yields, mut bs_yields) = reconnect();
+	assert_eq!(claimed_turns, (Some(false), Some(false)));
+	assert!(as_yields.is_empty());
+	assert_eq!(bs_yields.len(), 1);
+
+	// Once nodes[0] hands over the turn, nodes[1] sends its payment.
+	nodes[0].node.handle_yield(&nodes[1].node.get_our_node_id(), &bs_yields.pop().unwrap());
+	let handover = get_event_msg!(nodes[0], MessageSendEvent::SendYield, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_yield(&nodes[0].node.get_our_node_id(), &handover);
+	let mut events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	check_added_monitors!(nodes[1], 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[0].node.handle_update_add_htlc(&nodes[1].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable!(nodes[0]);
+	expect_payment_claimable!(nodes[0], payment_hash, payment_secret, 100_000);
+}
@@ -14,10 +14,18 @@
 pub mod functional_test_utils;
 
 pub mod channelmanager;
+pub mod contractmanager;
 pub mod inbound_payment;
 pub mod msgs;
 pub mod peer_handler;
+pub mod peer_access;
+pub mod peer_metadata;
+pub mod spend_guard;
 pub mod chan_utils;
+pub mod contracts;
+#[cfg(feature = "conformance_vectors")]
+pub mod conformance_vectors;
+pub mod oracle;
 pub mod features;
 pub mod script;
 
@@ -32,6 +40,7 @@ pub mod channel;
 pub(crate) mod channel;
 
 pub(crate) mod onion_utils;
+pub mod onion_payment;
 mod outbound_payment;
 pub mod wire;
 
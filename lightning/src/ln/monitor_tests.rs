@@ -10,12 +10,13 @@
 //! Further functional tests which test blockchain reorganizations.
 
 use crate::sign::EcdsaChannelSigner;
-use crate::chain::channelmonitor::{ANTI_REORG_DELAY, LATENCY_GRACE_PERIOD_BLOCKS, Balance};
+use crate::chain::channelmonitor::{self, ANTI_REORG_DELAY, LATENCY_GRACE_PERIOD_BLOCKS, Balance};
 use crate::chain::transaction::OutPoint;
+use crate::chain::watchtower::BreachHint;
 use crate::chain::chaininterface::{LowerBoundedFeeEstimator, compute_feerate_sat_per_1000_weight};
 use crate::events::bump_transaction::{BumpTransactionEvent, WalletSource};
 use crate::events::{Event, MessageSendEvent, MessageSendEventsProvider, ClosureReason, HTLCDestination};
-use crate::ln::channel;
+use crate::ln::{chan_utils, channel};
 use crate::ln::channelmanager::{BREAKDOWN_TIMEOUT, ChannelManager, PaymentId, RecipientOnionFields};
 use crate::ln::msgs::ChannelMessageHandler;
 use crate::util::config::UserConfig;
@@ -231,6 +232,40 @@ fn chanmon_claim_value_coop_close() {
 	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
 }
 
+#[test]
+fn chanmon_balance_statement() {
+	// Tests that a signed `BalanceStatement` reflects `get_claimable_balances` and can be verified
+	// against our node id.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let (_, _, _, funding_tx) =
+		create_announced_chan_between_nodes_with_value(&nodes, 0, 1, 1_000_000, 1_000_000);
+	let funding_outpoint = OutPoint { txid: funding_tx.txid(), index: 0 };
+
+	let (block_hash, block_height) = nodes[1].best_block_info();
+	let monitor = nodes[1].chain_monitor.chain_monitor.get_monitor(funding_outpoint).unwrap();
+	assert!(monitor.get_balance_statement(block_height - 1).is_err());
+	let statement = monitor.get_balance_statement(block_height).unwrap();
+	assert_eq!(statement.funding_txo, funding_outpoint);
+	assert_eq!(statement.counterparty_node_id, Some(nodes[0].node.get_our_node_id()));
+	assert_eq!(statement.block_hash, block_hash);
+	assert_eq!(statement.claimable_balance_satoshis, 1_000);
+	drop(monitor);
+	assert_eq!(vec![statement.clone()],
+		nodes[1].chain_monitor.chain_monitor.get_balance_statements(block_height).unwrap());
+
+	let signed = statement.sign(nodes[1].keys_manager).unwrap();
+	assert!(signed.verify(&nodes[1].node.get_our_node_id()));
+	assert!(!signed.verify(&nodes[0].node.get_our_node_id()));
+
+	let mut tampered = signed.clone();
+	tampered.statement.claimable_balance_satoshis += 1;
+	assert!(!tampered.verify(&nodes[1].node.get_our_node_id()));
+}
+
 fn sorted_vec<T: Ord>(mut v: Vec<T>) -> Vec<T> {
 	v.sort_unstable();
 	v
@@ -2252,3 +2287,96 @@ fn test_anchors_aggregated_revoked_htlc_tx() {
 	// revoked commitment which Bob has the preimage for.
 	assert_eq!(nodes[1].chain_monitor.chain_monitor.get_claimable_balances(&[]).len(), 6);
 }
+
+#[test]
+fn test_justice_blobs_for_revoked_commitments() {
+	// Check that the blob built for a revoked counterparty commitment transaction can only be
+	// decrypted with its txid and carries the keys needed to punish its broadcast, as well as a
+	// valid pre-signed justice transaction for its `to_local` output.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let (_, _, chan_id, _) = create_announced_chan_between_nodes(&nodes, 0, 1);
+	// Give nodes[1] a non-dust `to_local` output to be punished for.
+	send_payment(&nodes[0], &[&nodes[1]], 10_000_000);
+	let (payment_preimage, ..) = route_payment(&nodes[0], &[&nodes[1]], 3_000_000);
+	let revoked_local_txn = get_local_commitment_txn!(nodes[1], chan_id);
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+	let current_local_txn = get_local_commitment_txn!(nodes[1], chan_id);
+
+	// Claiming the payment had nodes[1] revoke the commitment transaction with the HTLC in it.
+	let update = nodes[0].chain_monitor.monitor_updates.lock().unwrap().get(&chan_id).unwrap()
+		.iter().rev()
+		.find(|update| update.updates.iter().any(|step| match step {
+			channelmonitor::ChannelMonitorUpdateStep::CommitmentSecret { .. } => true,
+			_ => false,
+		}))
+		.unwrap().clone();
+	let monitor = get_monitor!(nodes[0], chan_id);
+	let blobs = monitor.justice_blobs_from_update(&update, 253);
+	assert_eq!(blobs.len(), 1);
+
+	let revoked_txid = revoked_local_txn[0].txid();
+	assert_eq!(blobs[0].hint, BreachHint::from_txid(&revoked_txid));
+	assert!(blobs[0].decrypt(&current_local_txn[0].txid()).is_err());
+
+	let data = blobs[0].decrypt(&revoked_txid).unwrap();
+	assert_eq!(data.commitment_number, monitor.get_min_seen_secret());
+	assert_eq!(data.revoked_htlcs.len(), 1);
+	assert_eq!(data.revoked_htlcs[0].amount_msat, 3_000_000);
+	let revokeable_p2wsh = chan_utils::get_revokeable_redeemscript(
+		&data.revocation_pubkey, data.to_self_delay, &data.broadcaster_delayed_payment_key
+	).to_v0_p2wsh();
+	assert!(revoked_local_txn[0].output.iter().any(|output| output.script_pubkey == revokeable_p2wsh));
+
+	let justice_tx = data.build_to_local_justice_tx(&revoked_txid).unwrap();
+	check_spends!(justice_tx, revoked_local_txn[0]);
+	let to_local_output = &revoked_local_txn[0].output[justice_tx.input[0].previous_output.vout as usize];
+	assert_eq!(to_local_output.script_pubkey, revokeable_p2wsh);
+	assert_eq!(justice_tx.output[0].script_pubkey, data.sweep_script);
+	assert!(justice_tx.output[0].value < to_local_output.value);
+}
+
+#[test]
+fn test_collateral_release_rebroadcast() {
+	// Check that a collateral release handed to the ChannelMonitor is broadcast, and rebroadcast
+	// until a transaction spending the collateral output reached ANTI_REORG_DELAY confirmations.
+	use bitcoin::hashes::Hash;
+
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let (_, _, chan_id, _) = create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let collateral_input = TxIn {
+		previous_output: bitcoin::OutPoint { txid: bitcoin::Txid::from_slice(&[42; 32]).unwrap(), vout: 0 },
+		..Default::default()
+	};
+	let release_tx = Transaction {
+		version: 2, lock_time: PackedLockTime::ZERO, input: vec![collateral_input.clone()],
+		output: vec![TxOut { value: 50_000, script_pubkey: Script::new() }],
+	};
+	nodes[0].node.watch_collateral_release(&nodes[1].node.get_our_node_id(), &chan_id, release_tx.clone()).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	assert_eq!(nodes[0].tx_broadcaster.txn_broadcast(), vec![release_tx.clone()]);
+	nodes[0].chain_monitor.chain_monitor.rebroadcast_pending_claims();
+	assert_eq!(nodes[0].tx_broadcaster.txn_broadcast(), vec![release_tx.clone()]);
+
+	// A conflicting spend of the collateral output only stops the rebroadcasts once reorg-safe.
+	let conflicting_tx = Transaction {
+		version: 2, lock_time: PackedLockTime::ZERO, input: vec![collateral_input],
+		output: vec![TxOut { value: 40_000, script_pubkey: Script::new() }],
+	};
+	mine_transaction(&nodes[0], &conflicting_tx);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 2);
+	nodes[0].chain_monitor.chain_monitor.rebroadcast_pending_claims();
+	assert_eq!(nodes[0].tx_broadcaster.txn_broadcast(), vec![release_tx]);
+
+	connect_blocks(&nodes[0], 1);
+	nodes[0].chain_monitor.chain_monitor.rebroadcast_pending_claims();
+	assert!(nodes[0].tx_broadcaster.txn_broadcast().is_empty());
+}
@@ -25,14 +25,17 @@
 //! track the network on the less-secure system.
 
 use bitcoin::blockdata::constants::ChainHash;
-use bitcoin::secp256k1::PublicKey;
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 use bitcoin::secp256k1::ecdsa::Signature;
 use bitcoin::{secp256k1, Witness};
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::sha256d::Hash as Sha256dHash;
 use bitcoin::blockdata::script::Script;
 use bitcoin::hash_types::{Txid, BlockHash};
 
 use crate::ln::features::{ChannelFeatures, ChannelTypeFeatures, InitFeatures, NodeFeatures};
 use crate::ln::onion_utils;
+use crate::ln::peer_metadata::PeerMetadata;
 use crate::onion_message;
 
 use crate::prelude::*;
@@ -43,7 +46,7 @@ use crate::io_extras::read_to_end;
 
 use crate::events::{MessageSendEventsProvider, OnionMessageProvider};
 use crate::util::logger;
-use crate::util::ser::{LengthReadable, Readable, ReadableArgs, Writeable, Writer, WithoutLength, FixedLengthReader, HighZeroBytesDroppedBigSize, Hostname, TransactionU16LenLimited};
+use crate::util::ser::{BigSize, LengthReadable, Readable, ReadableArgs, Writeable, Writer, WithoutLength, FixedLengthReader, HighZeroBytesDroppedBigSize, Hostname, TransactionU16LenLimited, VecWriter};
 
 use crate::ln::{PaymentPreimage, PaymentHash, PaymentSecret};
 
@@ -161,6 +164,20 @@ pub struct Pong {
 	pub byteslen: u16,
 }
 
+/// The minimum type of the custom TLVs which may be attached to [`OpenChannel`] and
+/// [`AcceptChannel`] messages, see [`OpenChannel::custom_tlvs`].
+pub const MIN_CUSTOM_CHANNEL_TLV_TYPE: u64 = 1 << 16;
+
+/// The minimum type of the custom TLVs which may be included in the final hop of a payment onion,
+/// see [`RecipientOnionFields::with_custom_tlvs`].
+///
+/// [`RecipientOnionFields::with_custom_tlvs`]: crate::ln::outbound_payment::RecipientOnionFields::with_custom_tlvs
+pub const MIN_CUSTOM_ONION_TLV_TYPE: u64 = 1 << 16;
+
+/// The TLV type of the keysend preimage in the final hop of a payment onion, see
+/// <https://github.com/lightning/blips/blob/master/blip-0003.md>.
+const KEYSEND_PREIMAGE_TLV_TYPE: u64 = 5482373484;
+
 /// An [`open_channel`] message to be sent to or received from a peer.
 ///
 /// Used in V1 channel establishment
@@ -213,6 +230,11 @@ pub struct OpenChannel {
 	/// If this is `None`, we derive the channel type from the intersection of our
 	/// feature bits with our counterparty's feature bits from the [`Init`] message.
 	pub channel_type: Option<ChannelTypeFeatures>,
+	/// Application-specific TLVs, sorted by type, which are ignored by LDK.
+	///
+	/// All types are odd and at least [`MIN_CUSTOM_CHANNEL_TLV_TYPE`], so that counterparties
+	/// which don't understand them may safely ignore them.
+	pub custom_tlvs: Vec<(u64, Vec<u8>)>,
 }
 
 /// An open_channel2 message to be sent by or received from the channel initiator.
@@ -320,6 +342,10 @@ pub struct AcceptChannel {
 	#[cfg(taproot)]
 	/// Next nonce the channel initiator should use to create a funding output signature against
 	pub next_local_nonce: Option<musig2::types::PublicNonce>,
+	/// Application-specific TLVs, sorted by type, which are ignored by LDK.
+	///
+	/// See [`OpenChannel::custom_tlvs`] for the requirements on their types.
+	pub custom_tlvs: Vec<(u64, Vec<u8>)>,
 }
 
 /// An accept_channel2 message to be sent by or received from the channel accepter.
@@ -710,6 +736,17 @@ pub struct UpdateFee {
 	pub feerate_per_kw: u32,
 }
 
+/// A `yield` message to be sent to or received from a peer in channels using the simplified
+/// commitment update protocol.
+///
+/// When sent by the peer holding the turn, the turn passes to the recipient. Otherwise, it
+/// requests that the recipient passes the turn once it's done with its updates.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Yield {
+	/// The channel ID
+	pub channel_id: [u8; 32],
+}
+
 /// A [`channel_reestablish`] message to be sent to or received from a peer.
 ///
 /// [`channel_reestablish`]: https://github.com/lightning/bolts/blob/master/02-peer-protocol.md#message-retransmission
@@ -728,6 +765,14 @@ pub struct ChannelReestablish {
 	pub my_current_per_commitment_point: PublicKey,
 	/// The next funding transaction ID
 	pub next_funding_txid: Option<Txid>,
+	/// Whether the sender believes it holds the turn, if the channel uses
+	/// `option_simplified_update`
+	pub sender_holds_turn: Option<bool>,
+	/// The channel type the sender wishes to move the channel to, if the channel has no pending
+	/// updates and both sides support `option_channel_upgrade`
+	pub desired_channel_type: Option<ChannelTypeFeatures>,
+	/// The sender's view of the current channel type, if it supports `option_channel_upgrade`
+	pub current_channel_type: Option<ChannelTypeFeatures>,
 }
 
 /// An [`announcement_signatures`] message to be sent to or received from a peer.
@@ -954,6 +999,48 @@ pub struct NodeAnnouncement {
 	pub contents: UnsignedNodeAnnouncement,
 }
 
+/// The type of the TLV record carrying a [`NodeKeyRotation`] in a [`node_announcement`].
+///
+/// [`node_announcement`]: https://github.com/lightning/bolts/blob/master/07-routing-gossip.md#the-node_announcement-message
+pub const NODE_KEY_ROTATION_TLV_TYPE: u64 = 65_549;
+
+/// A statement that a node is moving to a new node identity key, e.g. because its current one may
+/// have been exposed.
+///
+/// It is carried in the TLV stream of a [`NodeAnnouncement`] signed by the node's previous key
+/// (see [`UnsignedNodeAnnouncement::node_key_rotation`]) and is itself signed by the new key, so
+/// that both keys endorse the transition. Such announcements are broadcast once a rotation is set
+/// via [`PeerManager::set_node_key_rotation`].
+///
+/// [`PeerManager::set_node_key_rotation`]: crate::ln::peer_handler::PeerManager::set_node_key_rotation
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct NodeKeyRotation {
+	/// The node's new identity key.
+	pub new_node_id: NodeId,
+	/// A signature by `new_node_id` over [`NodeKeyRotation::message_hash`].
+	pub new_node_signature: Signature,
+}
+
+impl NodeKeyRotation {
+	/// Creates a statement rotating `previous_node_id` to the node id of `new_node_secret`.
+	pub fn new<C: secp256k1::Signing>(
+		previous_node_id: NodeId, new_node_secret: &SecretKey, secp_ctx: &Secp256k1<C>
+	) -> Self {
+		let new_node_id = NodeId::from_pubkey(&PublicKey::from_secret_key(secp_ctx, new_node_secret));
+		let msg_hash = NodeKeyRotation::message_hash(&previous_node_id, &new_node_id);
+		NodeKeyRotation { new_node_id, new_node_signature: secp_ctx.sign_ecdsa(&msg_hash, new_node_secret) }
+	}
+
+	/// The hash signed by the new node id, committing to both the previous and the new node id.
+	pub fn message_hash(previous_node_id: &NodeId, new_node_id: &NodeId) -> secp256k1::Message {
+		let mut engine = Sha256dHash::engine();
+		engine.input(b"node_key_rotation");
+		engine.input(previous_node_id.as_slice());
+		engine.input(new_node_id.as_slice());
+		hash_to_message!(&Sha256dHash::from_engine(engine)[..])
+	}
+}
+
 /// The unsigned part of a [`channel_announcement`] message.
 ///
 /// [`channel_announcement`]: https://github.com/lightning/bolts/blob/master/07-routing-gossip.md#the-channel_announcement-message
@@ -992,6 +1079,47 @@ pub struct ChannelAnnouncement {
 	pub contents: UnsignedChannelAnnouncement,
 }
 
+/// The unsigned part of an experimental, taproot-based `channel_announcement_2` message, as
+/// proposed for gossip v1.5.
+#[cfg(taproot)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsignedChannelAnnouncement2 {
+	/// The genesis hash of the blockchain where the channel is to be opened
+	pub chain_hash: BlockHash,
+	/// The advertised channel features
+	pub features: ChannelFeatures,
+	/// The short channel ID
+	pub short_channel_id: u64,
+	/// The value of the channel's funding output, in satoshis
+	pub capacity_satoshis: u64,
+	/// One of the two `node_id`s which are endpoints of this channel
+	pub node_id_1: NodeId,
+	/// The other of the two `node_id`s which are endpoints of this channel
+	pub node_id_2: NodeId,
+	/// The funding key for the first node, if the funding output commits to it directly.
+	///
+	/// Either both or neither funding key must be set.
+	pub bitcoin_key_1: Option<PublicKey>,
+	/// The funding key for the second node, if the funding output commits to it directly.
+	pub bitcoin_key_2: Option<PublicKey>,
+	/// The root of the script tree the funding output's key is tweaked with, if any.
+	pub merkle_root_hash: Option<[u8; 32]>,
+}
+
+/// An experimental, taproot-based `channel_announcement_2` message, as proposed for gossip v1.5,
+/// to be sent to or received from a peer.
+///
+/// Rather than four separate ECDSA signatures, it is authenticated by a single BIP 340 signature
+/// under the MuSig2 aggregate of the node ids and, if present, the funding keys.
+#[cfg(taproot)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelAnnouncement2 {
+	/// The aggregate signature of the announcement by both nodes and their funding keys
+	pub signature: bitcoin::secp256k1::schnorr::Signature,
+	/// The actual announcement
+	pub contents: UnsignedChannelAnnouncement2,
+}
+
 /// The unsigned part of a [`channel_update`] message.
 ///
 /// [`channel_update`]: https://github.com/lightning/bolts/blob/master/07-routing-gossip.md#the-channel_update-message
@@ -1268,6 +1396,8 @@ pub trait ChannelMessageHandler : MessageSendEventsProvider {
 
 	/// Handle an incoming `update_fee` message from the given peer.
 	fn handle_update_fee(&self, their_node_id: &PublicKey, msg: &UpdateFee);
+	/// Handle an incoming `yield` message from the given peer.
+	fn handle_yield(&self, their_node_id: &PublicKey, msg: &Yield);
 
 	// Channel-to-announce:
 	/// Handle an incoming `announcement_signatures` message from the given peer.
@@ -1311,6 +1441,22 @@ pub trait ChannelMessageHandler : MessageSendEventsProvider {
 	/// If it's `None`, then no particular network chain hash compatibility will be enforced when
 	/// connecting to peers.
 	fn get_genesis_hashes(&self) -> Option<Vec<ChainHash>>;
+
+
+	// Peer metadata:
+	/// Gets the [`PeerMetadata`] stored for the given peer, if any.
+	fn peer_metadata(&self, _their_node_id: &PublicKey) -> Option<PeerMetadata> { None }
+
+	/// Indicates that we connected to the given peer at the given address, after
+	/// [`Self::peer_connected`] succeeded. Implementors storing [`PeerMetadata`] may record it.
+	fn peer_address_seen(&self, _their_node_id: &PublicKey, _address: &NetAddress) {}
+
+	/// Returns whether we have any channels with the given peer. Used by the [`PeerManager`] to
+	/// apply [`PeerManagerConfig::channel_peers`] to it.
+	///
+	/// [`PeerManager`]: crate::ln::peer_handler::PeerManager
+	/// [`PeerManagerConfig::channel_peers`]: crate::ln::peer_handler::PeerManagerConfig::channel_peers
+	fn has_channels_with_peer(&self, _their_node_id: &PublicKey) -> bool { false }
 }
 
 /// A trait to describe an object which can receive routing messages.
@@ -1327,6 +1473,14 @@ pub trait RoutingMessageHandler : MessageSendEventsProvider {
 	/// Handle a `channel_announcement` message, returning `true` if it should be forwarded on, `false`
 	/// or returning an `Err` otherwise.
 	fn handle_channel_announcement(&self, msg: &ChannelAnnouncement) -> Result<bool, LightningError>;
+	/// Handle an experimental `channel_announcement_2` message, returning `true` if it was
+	/// accepted, `false` or returning an `Err` otherwise.
+	///
+	/// Such announcements are not yet relayed to other peers. Ignores the message by default.
+	#[cfg(taproot)]
+	fn handle_channel_announcement_2(&self, _msg: &ChannelAnnouncement2) -> Result<bool, LightningError> {
+		Ok(false)
+	}
 	/// Handle an incoming `channel_update` message, returning true if it should be forwarded on,
 	/// `false` or returning an `Err` otherwise.
 	fn handle_channel_update(&self, msg: &ChannelUpdate) -> Result<bool, LightningError>;
@@ -1398,6 +1552,14 @@ pub trait OnionMessageHandler : OnionMessageProvider {
 	/// drop and refuse to forward onion messages to this peer.
 	fn peer_disconnected(&self, their_node_id: &PublicKey);
 
+	/// Called on each [`PeerManager::timer_tick_occurred`], allowing the handler to do periodic
+	/// work, e.g. timing out requests which have not been responded to.
+	///
+	/// Does nothing by default.
+	///
+	/// [`PeerManager::timer_tick_occurred`]: crate::ln::peer_handler::PeerManager::timer_tick_occurred
+	fn timer_tick_occurred(&self) {}
+
 	// Handler information:
 	/// Gets the node feature flags which this handler itself supports. All available handlers are
 	/// queried similarly and their feature flags are OR'd together to form the [`NodeFeatures`]
@@ -1413,6 +1575,7 @@ pub trait OnionMessageHandler : OnionMessageProvider {
 }
 
 mod fuzzy_internal_msgs {
+	use bitcoin::secp256k1::PublicKey;
 	use crate::prelude::*;
 	use crate::ln::{PaymentPreimage, PaymentSecret};
 
@@ -1434,6 +1597,23 @@ mod fuzzy_internal_msgs {
 			payment_data: Option<FinalOnionHopData>,
 			payment_metadata: Option<Vec<u8>>,
 			keysend_preimage: Option<PaymentPreimage>,
+			/// Odd TLVs of at least [`MIN_CUSTOM_ONION_TLV_TYPE`], sorted by type.
+			///
+			/// [`MIN_CUSTOM_ONION_TLV_TYPE`]: super::MIN_CUSTOM_ONION_TLV_TYPE
+			custom_tlvs: Vec<(u64, Vec<u8>)>,
+		},
+		/// A hop within a blinded path we're sending to. As we do not yet support forwarding or
+		/// receiving over blinded paths, these are only ever built, never read.
+		BlindedNode {
+			encrypted_tlvs: Vec<u8>,
+			intro_node_blinding_point: Option<PublicKey>,
+		},
+		/// The recipient at the end of a blinded path we're sending to. As with
+		/// [`OnionHopDataFormat::BlindedNode`], these are only ever built, never read.
+		BlindedReceive {
+			total_msat: u64,
+			encrypted_tlvs: Vec<u8>,
+			intro_node_blinding_point: Option<PublicKey>,
 		},
 	}
 
@@ -1456,8 +1636,11 @@ pub use self::fuzzy_internal_msgs::*;
 #[cfg(not(fuzzing))]
 pub(crate) use self::fuzzy_internal_msgs::*;
 
+/// A BOLT 4 payment onion, as carried by an [`UpdateAddHTLC`] message.
+///
+/// May be constructed and peeled via the utilities in [`crate::ln::onion_payment`].
 #[derive(Clone)]
-pub(crate) struct OnionPacket {
+pub struct OnionPacket {
 	pub(crate) version: u8,
 	/// In order to ensure we always return an error on onion decode in compliance with [BOLT
 	/// #4](https://github.com/lightning/bolts/blob/master/04-onion-routing.md), we have to
@@ -1531,7 +1714,7 @@ impl From<io::Error> for DecodeError {
 }
 
 #[cfg(not(taproot))]
-impl_writeable_msg!(AcceptChannel, {
+impl_writeable_msg_with_custom_tlvs!(AcceptChannel, {
 	temporary_channel_id,
 	dust_limit_satoshis,
 	max_htlc_value_in_flight_msat,
@@ -1549,10 +1732,10 @@ impl_writeable_msg!(AcceptChannel, {
 }, {
 	(0, shutdown_scriptpubkey, (option, encoding: (Script, WithoutLength))), // Don't encode length twice.
 	(1, channel_type, option),
-});
+}, custom_tlvs);
 
 #[cfg(taproot)]
-impl_writeable_msg!(AcceptChannel, {
+impl_writeable_msg_with_custom_tlvs!(AcceptChannel, {
 	temporary_channel_id,
 	dust_limit_satoshis,
 	max_htlc_value_in_flight_msat,
@@ -1571,7 +1754,7 @@ impl_writeable_msg!(AcceptChannel, {
 	(0, shutdown_scriptpubkey, (option, encoding: (Script, WithoutLength))), // Don't encode length twice.
 	(1, channel_type, option),
 	(4, next_local_nonce, option),
-});
+}, custom_tlvs);
 
 impl_writeable_msg!(AcceptChannelV2, {
 	temporary_channel_id,
@@ -1664,6 +1847,9 @@ impl_writeable_msg!(ChannelReestablish, {
 	my_current_per_commitment_point,
 }, {
 	(0, next_funding_txid, option),
+	(1, sender_holds_turn, option),
+	(3, desired_channel_type, option),
+	(5, current_channel_type, option),
 });
 
 impl_writeable_msg!(ClosingSigned,
@@ -1671,6 +1857,11 @@ impl_writeable_msg!(ClosingSigned,
 	{ (1, fee_range, option) }
 );
 
+impl_writeable!(NodeKeyRotation, {
+	new_node_id,
+	new_node_signature
+});
+
 impl_writeable!(ClosingSignedFeeRange, {
 	min_fee_satoshis,
 	max_fee_satoshis
@@ -1769,7 +1960,7 @@ impl Readable for Init {
 	}
 }
 
-impl_writeable_msg!(OpenChannel, {
+impl_writeable_msg_with_custom_tlvs!(OpenChannel, {
 	chain_hash,
 	temporary_channel_id,
 	funding_satoshis,
@@ -1791,7 +1982,7 @@ impl_writeable_msg!(OpenChannel, {
 }, {
 	(0, shutdown_scriptpubkey, (option, encoding: (Script, WithoutLength))), // Don't encode length twice.
 	(1, channel_type, option),
-});
+}, custom_tlvs);
 
 impl_writeable_msg!(OpenChannelV2, {
 	chain_hash,
@@ -1858,6 +2049,10 @@ impl_writeable_msg!(UpdateFee, {
 	feerate_per_kw
 }, {});
 
+impl_writeable_msg!(Yield, {
+	channel_id,
+}, {});
+
 impl_writeable_msg!(UpdateFulfillHTLC, {
 	channel_id,
 	htlc_id,
@@ -1961,13 +2156,41 @@ impl Writeable for OnionHopData {
 					(6, short_channel_id, required)
 				});
 			},
-			OnionHopDataFormat::FinalNode { ref payment_data, ref payment_metadata, ref keysend_preimage } => {
-				_encode_varint_length_prefixed_tlv!(w, {
+			OnionHopDataFormat::FinalNode { ref payment_data, ref payment_metadata, ref keysend_preimage, ref custom_tlvs } => {
+				// Custom TLVs may be of a lower or higher type than the keysend preimage, so we write
+				// both after the fixed fields, sorted by type.
+				let keysend_tlv = keysend_preimage.map(|preimage| (KEYSEND_PREIMAGE_TLV_TYPE, preimage.encode()));
+				let mut extra_tlvs: Vec<&(u64, Vec<u8>)> = custom_tlvs.iter().chain(keysend_tlv.iter()).collect();
+				extra_tlvs.sort_unstable_by_key(|(typ, _)| *typ);
+
+				let mut tlvs = VecWriter(Vec::new());
+				encode_tlv_stream!(&mut tlvs, {
 					(2, HighZeroBytesDroppedBigSize(self.amt_to_forward), required),
 					(4, HighZeroBytesDroppedBigSize(self.outgoing_cltv_value), required),
 					(8, payment_data, option),
-					(16, payment_metadata.as_ref().map(|m| WithoutLength(m)), option),
-					(5482373484, keysend_preimage, option)
+					(16, payment_metadata.as_ref().map(|m| WithoutLength(m)), option)
+				});
+				for (typ, value) in extra_tlvs {
+					BigSize(*typ).write(&mut tlvs)?;
+					BigSize(value.len() as u64).write(&mut tlvs)?;
+					tlvs.write_all(value)?;
+				}
+				BigSize(tlvs.0.len() as u64).write(w)?;
+				w.write_all(&tlvs.0)?;
+			},
+			OnionHopDataFormat::BlindedNode { ref encrypted_tlvs, ref intro_node_blinding_point } => {
+				_encode_varint_length_prefixed_tlv!(w, {
+					(10, WithoutLength(encrypted_tlvs), required),
+					(12, intro_node_blinding_point, option)
+				});
+			},
+			OnionHopDataFormat::BlindedReceive { total_msat, ref encrypted_tlvs, ref intro_node_blinding_point } => {
+				_encode_varint_length_prefixed_tlv!(w, {
+					(2, HighZeroBytesDroppedBigSize(self.amt_to_forward), required),
+					(4, HighZeroBytesDroppedBigSize(self.outgoing_cltv_value), required),
+					(10, WithoutLength(encrypted_tlvs), required),
+					(12, intro_node_blinding_point, option),
+					(18, HighZeroBytesDroppedBigSize(total_msat), required)
 				});
 			},
 		}
@@ -1983,15 +2206,25 @@ impl Readable for OnionHopData {
 		let mut payment_data: Option<FinalOnionHopData> = None;
 		let mut payment_metadata: Option<WithoutLength<Vec<u8>>> = None;
 		let mut keysend_preimage: Option<PaymentPreimage> = None;
-		read_tlv_fields!(r, {
+		let mut custom_tlvs = Vec::new();
+
+		let tlv_len: BigSize = Readable::read(r)?;
+		crate::util::ser::check_tlv_record_len(tlv_len.0)?;
+		let mut rd = FixedLengthReader::new(r, tlv_len.0);
+		decode_tlv_stream_with_custom_tlv_decode!(&mut rd, {
 			(2, amt, required),
 			(4, cltv_value, required),
 			(6, short_id, option),
 			(8, payment_data, option),
 			(16, payment_metadata, option),
-			// See https://github.com/lightning/blips/blob/master/blip-0003.md
-			(5482373484, keysend_preimage, option)
+			(KEYSEND_PREIMAGE_TLV_TYPE, keysend_preimage, option)
+		}, |typ: u64, tlv_reader: &mut FixedLengthReader<_>| -> Result<bool, DecodeError> {
+			// Leave anything else to the decoder, which rejects unknown even types.
+			if typ < MIN_CUSTOM_ONION_TLV_TYPE || typ % 2 == 0 { return Ok(false) }
+			custom_tlvs.push((typ, read_to_end(tlv_reader)?));
+			Ok(true)
 		});
+		rd.eat_remaining().map_err(|_| DecodeError::ShortRead)?;
 
 		let format = if let Some(short_channel_id) = short_id {
 			if payment_data.is_some() { return Err(DecodeError::InvalidValue); }
@@ -2009,6 +2242,7 @@ impl Readable for OnionHopData {
 				payment_data,
 				payment_metadata: payment_metadata.map(|w| w.0),
 				keysend_preimage,
+				custom_tlvs,
 			}
 		};
 
@@ -2108,6 +2342,80 @@ impl_writeable!(ChannelAnnouncement, {
 	contents
 });
 
+#[cfg(taproot)]
+impl Writeable for UnsignedChannelAnnouncement2 {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		encode_tlv_stream!(w, {
+			(0, self.chain_hash, required),
+			(2, self.features, required),
+			(4, self.short_channel_id, required),
+			(6, self.capacity_satoshis, required),
+			(8, self.node_id_1, required),
+			(10, self.node_id_2, required),
+			(12, self.bitcoin_key_1, option),
+			(14, self.bitcoin_key_2, option),
+			(16, self.merkle_root_hash, option),
+		});
+		Ok(())
+	}
+}
+
+#[cfg(taproot)]
+impl Writeable for ChannelAnnouncement2 {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		self.contents.write(w)?;
+		encode_tlv_stream!(w, {
+			(160, self.signature, required),
+		});
+		Ok(())
+	}
+}
+
+#[cfg(taproot)]
+impl Readable for ChannelAnnouncement2 {
+	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
+		_init_tlv_field_var!(chain_hash, required);
+		_init_tlv_field_var!(features, required);
+		_init_tlv_field_var!(short_channel_id, required);
+		_init_tlv_field_var!(capacity_satoshis, required);
+		_init_tlv_field_var!(node_id_1, required);
+		_init_tlv_field_var!(node_id_2, required);
+		let mut bitcoin_key_1: Option<PublicKey> = None;
+		let mut bitcoin_key_2: Option<PublicKey> = None;
+		let mut merkle_root_hash: Option<[u8; 32]> = None;
+		_init_tlv_field_var!(signature, required);
+		decode_tlv_stream!(r, {
+			(0, chain_hash, required),
+			(2, features, required),
+			(4, short_channel_id, required),
+			(6, capacity_satoshis, required),
+			(8, node_id_1, required),
+			(10, node_id_2, required),
+			(12, bitcoin_key_1, option),
+			(14, bitcoin_key_2, option),
+			(16, merkle_root_hash, option),
+			(160, signature, required),
+		});
+		if bitcoin_key_1.is_some() != bitcoin_key_2.is_some() {
+			return Err(DecodeError::InvalidValue);
+		}
+		Ok(ChannelAnnouncement2 {
+			signature: signature.0.unwrap(),
+			contents: UnsignedChannelAnnouncement2 {
+				chain_hash: chain_hash.0.unwrap(),
+				features: features.0.unwrap(),
+				short_channel_id: short_channel_id.0.unwrap(),
+				capacity_satoshis: capacity_satoshis.0.unwrap(),
+				node_id_1: node_id_1.0.unwrap(),
+				node_id_2: node_id_2.0.unwrap(),
+				bitcoin_key_1,
+				bitcoin_key_2,
+				merkle_root_hash,
+			},
+		})
+	}
+}
+
 impl Writeable for UnsignedChannelUpdate {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
 		// `message_flags` used to indicate presence of `htlc_maximum_msat`, but was deprecated in the spec.
@@ -2229,6 +2537,38 @@ impl Writeable for UnsignedNodeAnnouncement {
 	}
 }
 
+impl UnsignedNodeAnnouncement {
+	/// Returns the [`NodeKeyRotation`] carried in this announcement's TLV stream, if any.
+	///
+	/// Note that this does not check the statement's signature, which is done as a part of
+	/// [`verify_node_announcement`].
+	///
+	/// [`verify_node_announcement`]: crate::routing::gossip::verify_node_announcement
+	pub fn node_key_rotation(&self) -> Option<NodeKeyRotation> {
+		let mut reader = io::Cursor::new(&self.excess_data[..]);
+		while (reader.position() as usize) < self.excess_data.len() {
+			let typ: BigSize = Readable::read(&mut reader).ok()?;
+			let len: BigSize = Readable::read(&mut reader).ok()?;
+			let mut record = FixedLengthReader::new(&mut reader, len.0);
+			if typ.0 == NODE_KEY_ROTATION_TLV_TYPE {
+				let rotation = Readable::read(&mut record).ok()?;
+				if record.bytes_remain() { return None; }
+				return Some(rotation);
+			}
+			record.eat_remaining().ok()?;
+		}
+		None
+	}
+
+	/// Appends the given [`NodeKeyRotation`] to this announcement's TLV stream.
+	pub(crate) fn append_node_key_rotation(&mut self, rotation: &NodeKeyRotation) {
+		let encoded = rotation.encode();
+		self.excess_data.extend_from_slice(&BigSize(NODE_KEY_ROTATION_TLV_TYPE).encode());
+		self.excess_data.extend_from_slice(&BigSize(encoded.len() as u64).encode());
+		self.excess_data.extend_from_slice(&encoded);
+	}
+}
+
 impl Readable for UnsignedNodeAnnouncement {
 	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
 		let features: NodeFeatures = Readable::read(r)?;
@@ -2478,6 +2818,9 @@ mod tests {
 			your_last_per_commitment_secret: [9;32],
 			my_current_per_commitment_point: public_key,
 			next_funding_txid: None,
+			sender_holds_turn: None,
+			desired_channel_type: None,
+			current_channel_type: None,
 		};
 
 		let encoded_value = cr.encode();
@@ -2493,6 +2836,30 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn encoding_channel_reestablish_with_channel_types() {
+		let public_key = {
+			let secp_ctx = Secp256k1::new();
+			PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&hex::decode("0101010101010101010101010101010101010101010101010101010101010101").unwrap()[..]).unwrap())
+		};
+
+		let cr = msgs::ChannelReestablish {
+			channel_id: [4; 32],
+			next_local_commitment_number: 3,
+			next_remote_commitment_number: 4,
+			your_last_per_commitment_secret: [9;32],
+			my_current_per_commitment_point: public_key,
+			next_funding_txid: None,
+			sender_holds_turn: None,
+			desired_channel_type: Some(ChannelTypeFeatures::anchors_zero_htlc_fee_and_dependencies()),
+			current_channel_type: Some(ChannelTypeFeatures::only_static_remote_key()),
+		};
+
+		let encoded_value = cr.encode();
+		let decoded: msgs::ChannelReestablish = Readable::read(&mut &encoded_value[..]).unwrap();
+		assert_eq!(decoded, cr);
+	}
+
 	#[test]
 	fn encoding_channel_reestablish_with_next_funding_txid() {
 		let public_key = {
@@ -2509,6 +2876,9 @@ mod tests {
 			next_funding_txid: Some(Txid::from_hash(bitcoin::hashes::Hash::from_slice(&[
 				48, 167, 250, 69, 152, 48, 103, 172, 164, 99, 59, 19, 23, 11, 92, 84, 15, 80, 4, 12, 98, 82, 75, 31, 201, 11, 91, 23, 98, 23, 53, 124,
 			]).unwrap())),
+			sender_holds_turn: None,
+			desired_channel_type: None,
+			current_channel_type: None,
 		};
 
 		let encoded_value = cr.encode();
@@ -2725,6 +3095,39 @@ mod tests {
 		do_encoding_node_announcement(false, false, true, false, true, false, false, false);
 	}
 
+	#[test]
+	fn node_key_rotation_in_node_announcement() {
+		let secp_ctx = Secp256k1::new();
+		let (_, pubkey_1) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
+		let (privkey_2, pubkey_2) = get_keys_from!("0202020202020202020202020202020202020202020202020202020202020202", secp_ctx);
+		let mut announcement = msgs::UnsignedNodeAnnouncement {
+			features: NodeFeatures::empty(),
+			timestamp: 20190119,
+			node_id: NodeId::from_pubkey(&pubkey_1),
+			rgb: [32; 3],
+			alias: NodeAlias([16; 32]),
+			addresses: Vec::new(),
+			excess_address_data: Vec::new(),
+			// An unknown odd TLV record, which must be skipped.
+			excess_data: vec![1, 2, 42, 42],
+		};
+		assert_eq!(announcement.node_key_rotation(), None);
+
+		let rotation = msgs::NodeKeyRotation::new(NodeId::from_pubkey(&pubkey_1), &privkey_2, &secp_ctx);
+		assert_eq!(rotation.new_node_id, NodeId::from_pubkey(&pubkey_2));
+		let msg_hash = msgs::NodeKeyRotation::message_hash(&NodeId::from_pubkey(&pubkey_1), &rotation.new_node_id);
+		assert!(secp_ctx.verify_ecdsa(&msg_hash, &rotation.new_node_signature, &pubkey_2).is_ok());
+		assert!(secp_ctx.verify_ecdsa(&msg_hash, &rotation.new_node_signature, &pubkey_1).is_err());
+
+		announcement.append_node_key_rotation(&rotation);
+		let decoded: msgs::UnsignedNodeAnnouncement = Readable::read(&mut Cursor::new(announcement.encode())).unwrap();
+		assert_eq!(decoded.node_key_rotation(), Some(rotation));
+
+		// A truncated record is ignored rather than misparsed.
+		announcement.excess_data.pop();
+		assert_eq!(announcement.node_key_rotation(), None);
+	}
+
 	fn do_encoding_channel_update(direction: bool, disable: bool, excess_data: bool) {
 		let secp_ctx = Secp256k1::new();
 		let (privkey_1, _) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
@@ -2808,6 +3211,7 @@ mod tests {
 			channel_flags: if random_bit { 1 << 5 } else { 0 },
 			shutdown_scriptpubkey: if shutdown { Some(Address::p2pkh(&::bitcoin::PublicKey{compressed: true, inner: pubkey_1}, Network::Testnet).script_pubkey()) } else { None },
 			channel_type: if incl_chan_type { Some(ChannelTypeFeatures::empty()) } else { None },
+			custom_tlvs: Vec::new(),
 		};
 		let encoded_value = open_channel.encode();
 		let mut target_value = Vec::new();
@@ -2931,7 +3335,7 @@ mod tests {
 		do_encoding_open_channelv2(true, true, true, true);
 	}
 
-	fn do_encoding_accept_channel(shutdown: bool) {
+	fn do_encoding_accept_channel(shutdown: bool, custom_tlvs: bool) {
 		let secp_ctx = Secp256k1::new();
 		let (_, pubkey_1) = get_keys_from!("0101010101010101010101010101010101010101010101010101010101010101", secp_ctx);
 		let (_, pubkey_2) = get_keys_from!("0202020202020202020202020202020202020202020202020202020202020202", secp_ctx);
@@ -2958,19 +3362,31 @@ mod tests {
 			channel_type: None,
 			#[cfg(taproot)]
 			next_local_nonce: None,
+			custom_tlvs: if custom_tlvs { vec![(65537, vec![1, 2, 3]), (65539, Vec::new())] } else { Vec::new() },
 		};
 		let encoded_value = accept_channel.encode();
 		let mut target_value = hex::decode("020202020202020202020202020202020202020202020202020202020202020212345678901234562334032891223698321446687011447600083a840000034d000c89d4c0bcc0bc031b84c5567b126440995d3ed5aaba0565d71e1834604819ff9c17f5e9d5dd078f024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d076602531fe6068134503d2723133227c867ac8fa6c83c537e9a44c3c5bdbdcb1fe33703462779ad4aad39514614751a71085f2f10e1c7a593e4e030efb5b8721ce55b0b0362c0a046dacce86ddd0343c6d3c7c79c2208ba0d9c9cf24a6d046d21d21f90f703f006a18d5653c4edf5391ff23a61f03ff83d237e880ee61187fa9f379a028e0a").unwrap();
 		if shutdown {
 			target_value.append(&mut hex::decode("001976a91479b000887626b294a914501a4cd226b58b23598388ac").unwrap());
 		}
+		if custom_tlvs {
+			target_value.append(&mut hex::decode("fe0001000103010203fe0001000300").unwrap());
+		}
 		assert_eq!(encoded_value, target_value);
+		assert_eq!(msgs::AcceptChannel::read(&mut &encoded_value[..]).unwrap(), accept_channel);
+
+		// Unknown even types in the custom range must still be understood.
+		let mut even_type_value = encoded_value.clone();
+		even_type_value.append(&mut hex::decode("fe0001000400").unwrap());
+		assert_eq!(msgs::AcceptChannel::read(&mut &even_type_value[..]).unwrap_err(), msgs::DecodeError::UnknownRequiredFeature);
 	}
 
 	#[test]
 	fn encoding_accept_channel() {
-		do_encoding_accept_channel(false);
-		do_encoding_accept_channel(true);
+		do_encoding_accept_channel(false, false);
+		do_encoding_accept_channel(true, false);
+		do_encoding_accept_channel(false, true);
+		do_encoding_accept_channel(true, true);
 	}
 
 	fn do_encoding_accept_channelv2(shutdown: bool) {
@@ -3445,6 +3861,16 @@ mod tests {
 		assert_eq!(encoded_value, target_value);
 	}
 
+	#[test]
+	fn encoding_yield() {
+		let yield_msg = msgs::Yield {
+			channel_id: [2; 32],
+		};
+		let encoded_value = yield_msg.encode();
+		let target_value = hex::decode("0202020202020202020202020202020202020202020202020202020202020202").unwrap();
+		assert_eq!(encoded_value, target_value);
+	}
+
 	#[test]
 	fn encoding_init() {
 		let mainnet_hash = ChainHash::from_hex("6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000").unwrap();
@@ -3551,6 +3977,7 @@ mod tests {
 				payment_data: None,
 				payment_metadata: None,
 				keysend_preimage: None,
+				custom_tlvs: Vec::new(),
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -3575,6 +4002,7 @@ mod tests {
 				}),
 				payment_metadata: None,
 				keysend_preimage: None,
+				custom_tlvs: Vec::new(),
 			},
 			amt_to_forward: 0x0badf00d01020304,
 			outgoing_cltv_value: 0xffffffff,
@@ -3590,13 +4018,54 @@ mod tests {
 			}),
 			payment_metadata: None,
 			keysend_preimage: None,
+			custom_tlvs,
 		} = msg.format {
 			assert_eq!(payment_secret, expected_payment_secret);
+			assert!(custom_tlvs.is_empty());
 		} else { panic!(); }
 		assert_eq!(msg.amt_to_forward, 0x0badf00d01020304);
 		assert_eq!(msg.outgoing_cltv_value, 0xffffffff);
 	}
 
+	#[test]
+	fn encoding_final_onion_hop_data_with_custom_tlvs() {
+		// Custom TLVs are written in order around the keysend preimage and read back.
+		let custom_tlvs = vec![(65537, vec![1, 2, 3]), (5482373485, vec![42; 40])];
+		let msg = msgs::OnionHopData {
+			format: OnionHopDataFormat::FinalNode {
+				payment_data: None,
+				payment_metadata: None,
+				keysend_preimage: Some(PaymentPreimage([3; 32])),
+				custom_tlvs: custom_tlvs.clone(),
+			},
+			amt_to_forward: 0x0badf00d01020304,
+			outgoing_cltv_value: 0xffffffff,
+		};
+		let encoded_value = msg.encode();
+		let decoded: msgs::OnionHopData = Readable::read(&mut Cursor::new(&encoded_value[..])).unwrap();
+		if let OnionHopDataFormat::FinalNode {
+			keysend_preimage: Some(PaymentPreimage(preimage)), custom_tlvs: ref decoded_tlvs, ..
+		} = decoded.format {
+			assert_eq!(preimage, [3; 32]);
+			assert_eq!(*decoded_tlvs, custom_tlvs);
+		} else { panic!(); }
+		assert_eq!(decoded.encode(), encoded_value);
+
+		// Unknown even custom TLVs are rejected.
+		let msg = msgs::OnionHopData {
+			format: OnionHopDataFormat::FinalNode {
+				payment_data: None,
+				payment_metadata: None,
+				keysend_preimage: None,
+				custom_tlvs: vec![(65538, vec![1, 2, 3])],
+			},
+			amt_to_forward: 0x0badf00d01020304,
+			outgoing_cltv_value: 0xffffffff,
+		};
+		let res: Result<msgs::OnionHopData, _> = Readable::read(&mut Cursor::new(&msg.encode()[..]));
+		assert_eq!(res.err(), Some(msgs::DecodeError::UnknownRequiredFeature));
+	}
+
 	#[test]
 	fn query_channel_range_end_blocknum() {
 		let tests: Vec<(u32, u32, u32)> = vec![
@@ -0,0 +1,244 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Utilities for constructing and peeling payment onions outside of a [`ChannelManager`].
+//!
+//! These allow applications to build the [`OnionPacket`] for a payment along a [`Path`] and to
+//! process one received for us, for example to carry payment onions over a transport other than
+//! Lightning channels or to test other implementations against LDK's.
+//!
+//! See [`onion_message::create_onion_message`] and [`onion_message::peel_onion_message`] for the
+//! onion message equivalents.
+//!
+//! [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+//! [`onion_message::create_onion_message`]: crate::onion_message::create_onion_message
+//! [`onion_message::peel_onion_message`]: crate::onion_message::peel_onion_message
+
+use bitcoin::secp256k1::{self, Secp256k1, SecretKey};
+
+use crate::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
+use crate::ln::channelmanager::RecipientOnionFields;
+use crate::ln::msgs::{self, OnionPacket};
+use crate::ln::onion_utils;
+use crate::routing::router::Path;
+use crate::sign::{NodeSigner, Recipient};
+use crate::util::errors::APIError;
+
+use crate::prelude::*;
+use core::ops::Deref;
+
+/// Constructs the onion for a payment of `total_msat` along `path`, returning it along with the
+/// amount and CLTV expiry of the HTLC which should carry it to the first hop.
+///
+/// `cur_block_height` is the current best block height, which the CLTV expiries of all hops are
+/// relative to. `session_priv` and `prng_seed` must be freshly generated random values.
+pub fn create_payment_onion<T: secp256k1::Signing>(
+	secp_ctx: &Secp256k1<T>, path: &Path, session_priv: &SecretKey, total_msat: u64,
+	recipient_onion: RecipientOnionFields, cur_block_height: u32, payment_hash: &PaymentHash,
+	keysend_preimage: &Option<PaymentPreimage>, prng_seed: [u8; 32]
+) -> Result<(OnionPacket, u64, u32), APIError> {
+	let onion_keys = onion_utils::construct_onion_keys(secp_ctx, path, session_priv)
+		.map_err(|_| APIError::InvalidRoute { err: "Pubkey along hop was maliciously selected".to_owned() })?;
+	let (onion_payloads, htlc_msat, htlc_cltv) = onion_utils::build_onion_payloads(
+		path, total_msat, recipient_onion, cur_block_height, keysend_preimage)?;
+	let onion_packet = onion_utils::construct_onion_packet(onion_payloads, onion_keys, prng_seed, payment_hash)
+		.map_err(|_| APIError::InvalidRoute { err: "Route size too large considering onion data".to_owned() })?;
+	Ok((onion_packet, htlc_msat, htlc_cltv))
+}
+
+/// The contents of a payment onion after being peeled via [`peel_payment_onion`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PeeledPaymentOnion {
+	/// The payment should be forwarded to the next hop.
+	Forward {
+		/// The channel the HTLC should be forwarded over.
+		short_channel_id: u64,
+		/// The amount, in msat, the forwarded HTLC should carry.
+		amt_to_forward_msat: u64,
+		/// The CLTV expiry the forwarded HTLC should have.
+		outgoing_cltv_value: u32,
+		/// The onion to include in the forwarded HTLC.
+		next_onion: OnionPacket,
+	},
+	/// We're the recipient of the payment.
+	Receive {
+		/// The amount, in msat, the sender intended for us to receive in this HTLC.
+		amt_msat: u64,
+		/// The CLTV expiry the sender intended the HTLC to have.
+		cltv_expiry: u32,
+		/// The payment secret, if the sender included one.
+		payment_secret: Option<PaymentSecret>,
+		/// The total amount of the payment across all parts, if the sender included a payment
+		/// secret.
+		total_msat: Option<u64>,
+		/// The payment metadata, if the sender included any.
+		payment_metadata: Option<Vec<u8>>,
+		/// The preimage of a spontaneous payment, if the sender included one.
+		keysend_preimage: Option<PaymentPreimage>,
+		/// The custom TLVs the sender included, sorted by type, see
+		/// [`RecipientOnionFields::with_custom_tlvs`].
+		///
+		/// [`RecipientOnionFields::with_custom_tlvs`]: crate::ln::outbound_payment::RecipientOnionFields::with_custom_tlvs
+		custom_tlvs: Vec<(u64, Vec<u8>)>,
+	},
+}
+
+/// An error encountered while peeling a payment onion via [`peel_payment_onion`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaymentOnionPeelError {
+	/// A human-readable description of the error.
+	pub err_msg: &'static str,
+	/// The BOLT 4 failure code the error should be reported to the sender with.
+	pub err_code: u16,
+	/// Whether the onion was malformed, in which case the error should be reported via
+	/// [`msgs::UpdateFailMalformedHTLC`] rather than an encrypted [`msgs::UpdateFailHTLC`].
+	pub malformed: bool,
+}
+
+/// Peels one layer of a payment onion received for the payment with the given `payment_hash`,
+/// decrypting it with our node's key as provided by `node_signer`.
+///
+/// Note that, unlike a [`ChannelManager`], this only decodes the onion and doesn't check whether
+/// the resulting HTLC is acceptable, e.g. whether the amount and CLTV expiry we received it with
+/// are sufficient.
+///
+/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+pub fn peel_payment_onion<NS: Deref, T: secp256k1::Signing + secp256k1::Verification>(
+	onion: &OnionPacket, payment_hash: PaymentHash, node_signer: &NS, secp_ctx: &Secp256k1<T>
+) -> Result<PeeledPaymentOnion, PaymentOnionPeelError> where NS::Target: NodeSigner {
+	macro_rules! return_err {
+		($msg: expr, $err_code: expr, $malformed: expr) => {
+			return Err(PaymentOnionPeelError { err_msg: $msg, err_code: $err_code, malformed: $malformed })
+		}
+	}
+
+	let onion_pubkey = match onion.public_key {
+		Ok(pubkey) => pubkey,
+		Err(_) => return_err!("invalid ephemeral pubkey", 0x8000 | 0x4000 | 6, true),
+	};
+	if onion.version != 0 {
+		return_err!("Unknown onion packet version", 0x8000 | 0x4000 | 4, true);
+	}
+	let shared_secret = match node_signer.ecdh(Recipient::Node, &onion_pubkey, None) {
+		Ok(shared_secret) => shared_secret.secret_bytes(),
+		Err(()) => return_err!("Failed to compute onion packet shared secret", 0x2000 | 2, false),
+	};
+
+	let next_hop = match onion_utils::decode_next_payment_hop(shared_secret, &onion.hop_data[..], onion.hmac, payment_hash) {
+		Ok(next_hop) => next_hop,
+		Err(onion_utils::OnionDecodeErr::Malformed { err_msg, err_code }) => return_err!(err_msg, err_code, true),
+		Err(onion_utils::OnionDecodeErr::Relay { err_msg, err_code }) => return_err!(err_msg, err_code, false),
+	};
+	match next_hop {
+		onion_utils::Hop::Forward {
+			next_hop_data: msgs::OnionHopData {
+				format: msgs::OnionHopDataFormat::NonFinalNode { short_channel_id }, amt_to_forward,
+				outgoing_cltv_value,
+			}, next_hop_hmac, new_packet_bytes,
+		} => {
+			let next_onion = OnionPacket {
+				version: 0,
+				public_key: onion_utils::next_hop_packet_pubkey(secp_ctx, onion_pubkey, &shared_secret),
+				hop_data: new_packet_bytes,
+				hmac: next_hop_hmac,
+			};
+			Ok(PeeledPaymentOnion::Forward {
+				short_channel_id, amt_to_forward_msat: amt_to_forward, outgoing_cltv_value, next_onion,
+			})
+		},
+		onion_utils::Hop::Receive(msgs::OnionHopData {
+			format: msgs::OnionHopDataFormat::FinalNode { payment_data, payment_metadata, keysend_preimage, custom_tlvs },
+			amt_to_forward, outgoing_cltv_value,
+		}) => {
+			Ok(PeeledPaymentOnion::Receive {
+				amt_msat: amt_to_forward,
+				cltv_expiry: outgoing_cltv_value,
+				payment_secret: payment_data.as_ref().map(|data| data.payment_secret),
+				total_msat: payment_data.as_ref().map(|data| data.total_msat),
+				payment_metadata,
+				keysend_preimage,
+				custom_tlvs,
+			})
+		},
+		onion_utils::Hop::Forward {
+			next_hop_data: msgs::OnionHopData { format: msgs::OnionHopDataFormat::FinalNode { .. }, .. }, ..
+		} => return_err!("Final Node OnionHopData provided for us as an intermediary node", 0x4000 | 22, false),
+		onion_utils::Hop::Receive(msgs::OnionHopData { format: msgs::OnionHopDataFormat::NonFinalNode { .. }, .. }) =>
+			return_err!("Got non final data with an HMAC of 0", 0x4000 | 22, false),
+		_ => return_err!("Blinded paths are not supported", 0x4000 | 22, false),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::network::constants::Network;
+	use bitcoin::secp256k1::{Secp256k1, SecretKey};
+
+	use crate::ln::{PaymentHash, PaymentSecret};
+	use crate::ln::channelmanager::RecipientOnionFields;
+	use crate::ln::features::{ChannelFeatures, NodeFeatures};
+	use crate::routing::router::{Path, RouteHop};
+	use crate::sign::{NodeSigner, Recipient};
+	use crate::util::test_utils::TestKeysInterface;
+
+	use super::{create_payment_onion, peel_payment_onion, PeeledPaymentOnion};
+
+	#[test]
+	fn create_and_peel_payment_onion() {
+		let secp_ctx = Secp256k1::new();
+		let hop_keys = [
+			TestKeysInterface::new(&[1; 32], Network::Testnet),
+			TestKeysInterface::new(&[2; 32], Network::Testnet),
+		];
+		let hops = hop_keys.iter().enumerate().map(|(idx, keys)| RouteHop {
+			pubkey: keys.get_node_id(Recipient::Node).unwrap(),
+			node_features: NodeFeatures::empty(),
+			short_channel_id: 42 + idx as u64,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: if idx == 0 { 1_000 } else { 100_000 },
+			cltv_expiry_delta: if idx == 0 { 40 } else { 18 },
+		}).collect();
+		let path = Path { hops, blinded_tail: None };
+
+		let payment_hash = PaymentHash([3; 32]);
+		let payment_secret = PaymentSecret([4; 32]);
+		let session_priv = SecretKey::from_slice(&[5; 32]).unwrap();
+		let (onion, htlc_msat, htlc_cltv) = create_payment_onion(&secp_ctx, &path, &session_priv,
+			100_000, RecipientOnionFields::secret_only(payment_secret), 800_000, &payment_hash, &None,
+			[6; 32]).unwrap();
+		assert_eq!(htlc_msat, 101_000);
+		assert_eq!(htlc_cltv, 800_058);
+
+		let next_onion = match peel_payment_onion(&onion, payment_hash, &&hop_keys[0], &secp_ctx).unwrap() {
+			PeeledPaymentOnion::Forward { short_channel_id, amt_to_forward_msat, outgoing_cltv_value, next_onion } => {
+				assert_eq!(short_channel_id, 43);
+				assert_eq!(amt_to_forward_msat, 100_000);
+				assert_eq!(outgoing_cltv_value, 800_018);
+				next_onion
+			},
+			_ => panic!("Unexpected peeled onion"),
+		};
+		assert!(peel_payment_onion(&next_onion, payment_hash, &&hop_keys[0], &secp_ctx).is_err());
+
+		assert_eq!(peel_payment_onion(&next_onion, payment_hash, &&hop_keys[1], &secp_ctx).unwrap(),
+			PeeledPaymentOnion::Receive {
+				amt_msat: 100_000,
+				cltv_expiry: 800_018,
+				payment_secret: Some(payment_secret),
+				total_msat: Some(100_000),
+				payment_metadata: None,
+				keysend_preimage: None,
+				custom_tlvs: Vec::new(),
+			});
+
+		// The onion is bound to the payment hash.
+		let err = peel_payment_onion(&onion, PaymentHash([7; 32]), &&hop_keys[0], &secp_ctx).unwrap_err();
+		assert!(err.malformed);
+	}
+}
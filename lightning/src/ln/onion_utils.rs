@@ -105,11 +105,21 @@ pub(crate) fn next_hop_packet_pubkey<T: secp256k1::Signing + secp256k1::Verifica
 // can only fail if an intermediary hop has an invalid public key or session_priv is invalid
 #[inline]
 pub(super) fn construct_onion_keys_callback<T: secp256k1::Signing, FType: FnMut(SharedSecret, [u8; 32], PublicKey, &RouteHop, usize)> (secp_ctx: &Secp256k1<T>, path: &Vec<RouteHop>, session_priv: &SecretKey, mut callback: FType) -> Result<(), secp256k1::Error> {
+	construct_onion_keys_for_pubkeys(secp_ctx, path.iter().map(|hop| &hop.pubkey), session_priv,
+		|shared_secret, blinding_factor, ephemeral_pubkey, idx| {
+			callback(shared_secret, blinding_factor, ephemeral_pubkey, &path[idx], idx)
+		})
+}
+
+/// Derives the onion keys for each of the given hop public keys in turn.
+fn construct_onion_keys_for_pubkeys<'a, T: secp256k1::Signing, I: Iterator<Item = &'a PublicKey>, FType: FnMut(SharedSecret, [u8; 32], PublicKey, usize)>(
+	secp_ctx: &Secp256k1<T>, pubkeys: I, session_priv: &SecretKey, mut callback: FType
+) -> Result<(), secp256k1::Error> {
 	let mut blinded_priv = session_priv.clone();
 	let mut blinded_pub = PublicKey::from_secret_key(secp_ctx, &blinded_priv);
 
-	for (idx, hop) in path.iter().enumerate() {
-		let shared_secret = SharedSecret::new(&hop.pubkey, &blinded_priv);
+	for (idx, pubkey) in pubkeys.enumerate() {
+		let shared_secret = SharedSecret::new(pubkey, &blinded_priv);
 
 		let mut sha = Sha256::engine();
 		sha.input(&blinded_pub.serialize()[..]);
@@ -121,7 +131,7 @@ pub(super) fn construct_onion_keys_callback<T: secp256k1::Signing, FType: FnMut(
 		blinded_priv = blinded_priv.mul_tweak(&Scalar::from_be_bytes(blinding_factor).unwrap())?;
 		blinded_pub = PublicKey::from_secret_key(secp_ctx, &blinded_priv);
 
-		callback(shared_secret, blinding_factor, ephemeral_pubkey, hop, idx);
+		callback(shared_secret, blinding_factor, ephemeral_pubkey, idx);
 	}
 
 	Ok(())
@@ -129,9 +139,15 @@ pub(super) fn construct_onion_keys_callback<T: secp256k1::Signing, FType: FnMut(
 
 // can only fail if an intermediary hop has an invalid public key or session_priv is invalid
 pub(super) fn construct_onion_keys<T: secp256k1::Signing>(secp_ctx: &Secp256k1<T>, path: &Path, session_priv: &SecretKey) -> Result<Vec<OnionKeys>, secp256k1::Error> {
-	let mut res = Vec::with_capacity(path.hops.len());
-
-	construct_onion_keys_callback(secp_ctx, &path.hops, session_priv, |shared_secret, _blinding_factor, ephemeral_pubkey, _, _| {
+	let blinded_hop_count = path.blinded_tail.as_ref().map_or(0, |tail| tail.hops.len().saturating_sub(1));
+	let mut res = Vec::with_capacity(path.hops.len() + blinded_hop_count);
+
+	// The introduction node of a blinded path is the last of our unblinded hops, so we only need the
+	// blinded node ids of the hops after it.
+	let unblinded_pubkeys = path.hops.iter().map(|hop| &hop.pubkey);
+	let blinded_pubkeys = path.blinded_tail.iter()
+		.flat_map(|tail| tail.hops.iter().skip(1).map(|hop| &hop.blinded_node_id));
+	construct_onion_keys_for_pubkeys(secp_ctx, unblinded_pubkeys.chain(blinded_pubkeys), session_priv, |shared_secret, _blinding_factor, ephemeral_pubkey, _| {
 		let (rho, mu) = gen_rho_mu_from_shared_secret(shared_secret.as_ref());
 
 		res.push(OnionKeys {
@@ -161,26 +177,58 @@ pub(super) fn build_onion_payloads(path: &Path, total_msat: u64, mut recipient_o
 		// the intended recipient).
 		let value_msat = if cur_value_msat == 0 { hop.fee_msat } else { cur_value_msat };
 		let cltv = if cur_cltv == starting_htlc_offset { hop.cltv_expiry_delta + starting_htlc_offset } else { cur_cltv };
-		res.insert(0, msgs::OnionHopData {
-			format: if idx == 0 {
-				msgs::OnionHopDataFormat::FinalNode {
-					payment_data: if let Some(secret) = recipient_onion.payment_secret.take() {
-						Some(msgs::FinalOnionHopData {
-							payment_secret: secret,
+		if let (0, Some(blinded_tail)) = (idx, &path.blinded_tail) {
+			// The last unblinded hop is the introduction node, whose payload is the first of the
+			// blinded path's. The fee and CLTV delta of the whole blinded path are carried by it.
+			let mut blinding_point = Some(blinded_tail.blinding_point);
+			for (i, blinded_hop) in blinded_tail.hops.iter().enumerate() {
+				if i == blinded_tail.hops.len() - 1 {
+					cur_value_msat += blinded_tail.final_value_msat;
+					cur_cltv += blinded_tail.excess_final_cltv_expiry_delta;
+					res.push(msgs::OnionHopData {
+						format: msgs::OnionHopDataFormat::BlindedReceive {
 							total_msat,
-						})
-					} else { None },
-					payment_metadata: recipient_onion.payment_metadata.take(),
-					keysend_preimage: *keysend_preimage,
-				}
-			} else {
-				msgs::OnionHopDataFormat::NonFinalNode {
-					short_channel_id: last_short_channel_id,
+							encrypted_tlvs: blinded_hop.encrypted_payload.clone(),
+							intro_node_blinding_point: blinding_point.take(),
+						},
+						amt_to_forward: blinded_tail.final_value_msat,
+						outgoing_cltv_value: cltv,
+					});
+				} else {
+					res.push(msgs::OnionHopData {
+						format: msgs::OnionHopDataFormat::BlindedNode {
+							encrypted_tlvs: blinded_hop.encrypted_payload.clone(),
+							intro_node_blinding_point: blinding_point.take(),
+						},
+						// Blinded hops learn the amount and CLTV from their encrypted payloads.
+						amt_to_forward: 0,
+						outgoing_cltv_value: 0,
+					});
 				}
-			},
-			amt_to_forward: value_msat,
-			outgoing_cltv_value: cltv,
-		});
+			}
+		} else {
+			res.insert(0, msgs::OnionHopData {
+				format: if idx == 0 {
+					msgs::OnionHopDataFormat::FinalNode {
+						payment_data: if let Some(secret) = recipient_onion.payment_secret.take() {
+							Some(msgs::FinalOnionHopData {
+								payment_secret: secret,
+								total_msat,
+							})
+						} else { None },
+						payment_metadata: recipient_onion.payment_metadata.take(),
+						keysend_preimage: *keysend_preimage,
+						custom_tlvs: core::mem::take(&mut recipient_onion.custom_tlvs),
+					}
+				} else {
+					msgs::OnionHopDataFormat::NonFinalNode {
+						short_channel_id: last_short_channel_id,
+					}
+				},
+				amt_to_forward: value_msat,
+				outgoing_cltv_value: cltv,
+			});
+		}
 		cur_value_msat += hop.fee_msat;
 		if cur_value_msat >= 21000000 * 100000000 * 1000 {
 			return Err(APIError::InvalidRoute{err: "Channel fees overflowed?".to_owned()});
@@ -591,6 +639,17 @@ pub(super) fn process_onion_failure<T: secp256k1::Signing, L: Deref>(secp_ctx: &
 				}
 			}
 		}).expect("Route that we sent via spontaneously grew invalid keys in the middle of it?");
+		if path.blinded_tail.is_some() && is_from_final_node {
+			// When sending to a blinded path, our last unblinded hop is its introduction node, which
+			// returns an opaque `invalid_onion_blinding` error for any failure within the blinded path.
+			// Thus we cannot tell which hop or channel actually failed, so shouldn't penalize any of
+			// them, but should treat the payment as having reached the recipient's blinded path.
+			if let Some((network_update, short_channel_id, payment_retryable)) = res.as_mut() {
+				*network_update = None;
+				*short_channel_id = None;
+				*payment_retryable = false;
+			}
+		}
 		if let Some((channel_update, short_channel_id, payment_retryable)) = res {
 			(channel_update, short_channel_id, payment_retryable, error_code_ret, error_packet_ret)
 		} else {
@@ -889,9 +948,11 @@ fn decode_next_hop<T, R: ReadableArgs<T>, N: NextPacketBytes>(shared_secret: [u8
 mod tests {
 	use crate::io;
 	use crate::prelude::*;
+	use crate::blinded_path::BlindedHop;
 	use crate::ln::PaymentHash;
+	use crate::ln::channelmanager::RecipientOnionFields;
 	use crate::ln::features::{ChannelFeatures, NodeFeatures};
-	use crate::routing::router::{Path, Route, RouteHop};
+	use crate::routing::router::{BlindedTail, Path, Route, RouteHop};
 	use crate::ln::msgs;
 	use crate::util::ser::{Writeable, Writer, VecWriter};
 
@@ -1097,6 +1158,80 @@ mod tests {
 		assert_eq!(onion_packet_5.data, hex::decode("9c5add3963fc7f6ed7f148623c84134b5647e1306419dbe2174e523fa9e2fbed3a06a19f899145610741c83ad40b7712aefaddec8c6baf7325d92ea4ca4d1df8bce517f7e54554608bf2bd8071a4f52a7a2f7ffbb1413edad81eeea5785aa9d990f2865dc23b4bc3c301a94eec4eabebca66be5cf638f693ec256aec514620cc28ee4a94bd9565bc4d4962b9d3641d4278fb319ed2b84de5b665f307a2db0f7fbb757366067d88c50f7e829138fde4f78d39b5b5802f1b92a8a820865af5cc79f9f30bc3f461c66af95d13e5e1f0381c184572a91dee1c849048a647a1158cf884064deddbf1b0b88dfe2f791428d0ba0f6fb2f04e14081f69165ae66d9297c118f0907705c9c4954a199bae0bb96fad763d690e7daa6cfda59ba7f2c8d11448b604d12d").unwrap());
 	}
 
+	#[test]
+	fn builds_onion_for_blinded_tail() {
+		let secp_ctx = Secp256k1::new();
+		let pubkey = |byte: u8| PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[byte; 32]).unwrap());
+
+		let path = Path {
+			hops: vec![
+				RouteHop {
+					pubkey: pubkey(1), channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
+					short_channel_id: 1, fee_msat: 100, cltv_expiry_delta: 10,
+				},
+				// The introduction node, which carries the fee and CLTV delta of the blinded path.
+				RouteHop {
+					pubkey: pubkey(2), channel_features: ChannelFeatures::empty(), node_features: NodeFeatures::empty(),
+					short_channel_id: 2, fee_msat: 50, cltv_expiry_delta: 20,
+				},
+			],
+			blinded_tail: Some(BlindedTail {
+				hops: vec![
+					BlindedHop { blinded_node_id: pubkey(3), encrypted_payload: vec![3; 32] },
+					BlindedHop { blinded_node_id: pubkey(4), encrypted_payload: vec![4; 32] },
+					BlindedHop { blinded_node_id: pubkey(5), encrypted_payload: vec![5; 32] },
+				],
+				blinding_point: pubkey(6),
+				excess_final_cltv_expiry_delta: 5,
+				final_value_msat: 1000,
+			}),
+		};
+
+		// Keys are derived for both unblinded hops, including the introduction node using its real
+		// node id, followed by the blinded hops after it.
+		let onion_keys = super::construct_onion_keys(&secp_ctx, &path, &get_test_session_key()).unwrap();
+		assert_eq!(onion_keys.len(), 4);
+
+		let (payloads, htlc_msat, htlc_cltv) = super::build_onion_payloads(
+			&path, 1000, RecipientOnionFields::spontaneous_empty(), 100, &None).unwrap();
+		assert_eq!(payloads.len(), onion_keys.len());
+		assert_eq!(htlc_msat, 1150);
+		assert_eq!(htlc_cltv, 135);
+
+		match payloads[0].format {
+			msgs::OnionHopDataFormat::NonFinalNode { short_channel_id } => assert_eq!(short_channel_id, 2),
+			_ => panic!("Expected a forward to the introduction node"),
+		}
+		assert_eq!(payloads[0].amt_to_forward, 1050);
+		assert_eq!(payloads[0].outgoing_cltv_value, 125);
+		match payloads[1].format {
+			msgs::OnionHopDataFormat::BlindedNode { ref encrypted_tlvs, intro_node_blinding_point } => {
+				assert_eq!(encrypted_tlvs, &vec![3; 32]);
+				assert_eq!(intro_node_blinding_point, Some(pubkey(6)));
+			},
+			_ => panic!("Expected a blinded payload for the introduction node"),
+		}
+		match payloads[2].format {
+			msgs::OnionHopDataFormat::BlindedNode { ref encrypted_tlvs, intro_node_blinding_point } => {
+				assert_eq!(encrypted_tlvs, &vec![4; 32]);
+				assert!(intro_node_blinding_point.is_none());
+			},
+			_ => panic!("Expected a blinded forward payload"),
+		}
+		match payloads[3].format {
+			msgs::OnionHopDataFormat::BlindedReceive { total_msat, ref encrypted_tlvs, intro_node_blinding_point } => {
+				assert_eq!(total_msat, 1000);
+				assert_eq!(encrypted_tlvs, &vec![5; 32]);
+				assert!(intro_node_blinding_point.is_none());
+			},
+			_ => panic!("Expected a blinded receive payload"),
+		}
+		assert_eq!(payloads[3].amt_to_forward, 1000);
+
+		// The packet itself must fit within the onion.
+		super::construct_onion_packet(payloads, onion_keys, [0; 32], &PaymentHash([42; 32])).unwrap();
+	}
+
 	struct RawOnionHopData {
 		data: Vec<u8>
 	}
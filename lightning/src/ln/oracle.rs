@@ -0,0 +1,796 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Oracle announcements and attestations, and the [`OraclePolicy`] used to decide which oracles
+//! contracts may depend on.
+//!
+//! Ahead of an event, an oracle publishes an [`OracleAnnouncement`] committing to one attestation
+//! point per possible outcome of the event. Once the event occurred, it publishes an
+//! [`OracleAttestation`] revealing the secret key for the point of the actual outcome. Adaptor
+//! signatures over a contract's settlement transactions are encrypted to these points, see
+//! [`SettlementBundle::set_counterparty_adaptor_signatures`].
+//!
+//! Contracts may also depend on several oracles attesting to the same event, any `t` of which have
+//! to agree on the outcome, see [`OracleThreshold`].
+//!
+//! [`SettlementBundle::set_counterparty_adaptor_signatures`]: crate::ln::contracts::SettlementBundle::set_counterparty_adaptor_signatures
+
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256d::Hash as Sha256dHash;
+use bitcoin::secp256k1::{self, Message, PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoin::secp256k1::ecdsa::Signature;
+use bitcoin::secp256k1::schnorr;
+
+use crate::ln::contracts::{SettlementBranch, numeric_outcome_bytes};
+use crate::util::ser::Writeable;
+
+use crate::prelude::*;
+use core::cmp;
+
+/// The default for [`OraclePolicy::max_outcomes`].
+pub const DEFAULT_MAX_OUTCOMES: u64 = 1024;
+
+/// The maximum number of combinations of oracles an [`OracleThreshold`] may have, each of which
+/// requires a full set of adaptor signatures.
+pub const MAX_ORACLE_COMBINATIONS: u64 = 1024;
+
+/// The outcomes of an event an oracle attests to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EventDescriptor {
+	/// An event with an arbitrary set of outcomes.
+	Enum {
+		/// The possible outcomes of the event.
+		outcomes: Vec<Vec<u8>>,
+	},
+	/// An event with a numeric outcome in the given range, with outcomes encoded via
+	/// [`numeric_outcome_bytes`].
+	Numeric {
+		/// The lowest possible outcome.
+		min_outcome: u64,
+		/// The highest possible outcome.
+		max_outcome: u64,
+	},
+}
+
+impl_writeable_tlv_based_enum!(EventDescriptor,
+	(0, Enum) => {
+		(0, outcomes, required_vec),
+	},
+	(2, Numeric) => {
+		(0, min_outcome, required),
+		(2, max_outcome, required),
+	};
+);
+
+impl EventDescriptor {
+	/// The number of possible outcomes of the event, or `None` if the descriptor is invalid, i.e.
+	/// if it has no outcomes or a numeric range is empty or too large to be enumerated.
+	pub fn outcome_count(&self) -> Option<u64> {
+		match self {
+			EventDescriptor::Enum { outcomes } if outcomes.is_empty() => None,
+			EventDescriptor::Enum { outcomes } => Some(outcomes.len() as u64),
+			EventDescriptor::Numeric { min_outcome, max_outcome } =>
+				max_outcome.checked_sub(*min_outcome)?.checked_add(1),
+		}
+	}
+
+	/// Gets the index of the given outcome, if it is a possible outcome of the event.
+	pub fn outcome_index(&self, outcome: &[u8]) -> Option<usize> {
+		match self {
+			EventDescriptor::Enum { outcomes } => outcomes.iter().position(|o| &o[..] == outcome),
+			EventDescriptor::Numeric { min_outcome, max_outcome } => {
+				if outcome.len() != 8 { return None; }
+				let mut bytes = [0; 8];
+				bytes.copy_from_slice(outcome);
+				let value = u64::from_be_bytes(bytes);
+				if value < *min_outcome || value > *max_outcome { return None; }
+				Some((value - min_outcome) as usize)
+			},
+		}
+	}
+
+	/// Gets the outcome at the given index, as used in [`SettlementBranch::outcome`].
+	pub fn outcome(&self, idx: usize) -> Option<Vec<u8>> {
+		match self {
+			EventDescriptor::Enum { outcomes } => outcomes.get(idx).cloned(),
+			EventDescriptor::Numeric { min_outcome, max_outcome } => {
+				let value = min_outcome.checked_add(idx as u64)?;
+				if value > *max_outcome { return None; }
+				Some(numeric_outcome_bytes(value))
+			},
+		}
+	}
+}
+
+/// The signature scheme an oracle signs its announcements with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OracleSigningScheme {
+	/// ECDSA signatures over the double-SHA256 of the announcement's contents.
+	Ecdsa,
+	/// BIP 340 Schnorr signatures over the double-SHA256 of the announcement's contents.
+	Schnorr,
+}
+
+impl_writeable_tlv_based_enum!(OracleSigningScheme,
+	(0, Ecdsa) => {},
+	(2, Schnorr) => {},
+;);
+
+/// An oracle's signature over an [`UnsignedOracleAnnouncement`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OracleSignature {
+	/// An [`OracleSigningScheme::Ecdsa`] signature.
+	Ecdsa(Signature),
+	/// An [`OracleSigningScheme::Schnorr`] signature.
+	Schnorr(schnorr::Signature),
+}
+
+impl_writeable_tlv_based_enum!(OracleSignature, ;
+	(0, Ecdsa),
+	(2, Schnorr),
+);
+
+impl OracleSignature {
+	/// The scheme this signature was produced with.
+	pub fn scheme(&self) -> OracleSigningScheme {
+		match self {
+			OracleSignature::Ecdsa(_) => OracleSigningScheme::Ecdsa,
+			OracleSignature::Schnorr(_) => OracleSigningScheme::Schnorr,
+		}
+	}
+}
+
+/// The contents of an [`OracleAnnouncement`], as signed by the oracle.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsignedOracleAnnouncement {
+	/// The oracle's public key.
+	pub oracle_pubkey: PublicKey,
+	/// An identifier of the event, unique amongst the oracle's events.
+	pub event_id: String,
+	/// The time at which the oracle expects to attest to the event's outcome, as a UNIX
+	/// timestamp.
+	pub event_maturity: u32,
+	/// The possible outcomes of the event.
+	pub event_descriptor: EventDescriptor,
+	/// One attestation point per outcome, in the order described by `event_descriptor`.
+	pub attestation_points: Vec<PublicKey>,
+}
+
+impl_writeable_tlv_based!(UnsignedOracleAnnouncement, {
+	(0, oracle_pubkey, required),
+	(2, event_id, required),
+	(4, event_maturity, required),
+	(6, event_descriptor, required),
+	(8, attestation_points, required_vec),
+});
+
+impl UnsignedOracleAnnouncement {
+	/// The message the oracle signs.
+	pub fn signing_digest(&self) -> Message {
+		hash_to_message!(&Sha256dHash::hash(&self.encode()[..])[..])
+	}
+
+	/// Gets the attestation point for the given outcome, if it is a possible outcome of the
+	/// event.
+	pub fn attestation_point(&self, outcome: &[u8]) -> Option<&PublicKey> {
+		self.event_descriptor.outcome_index(outcome).and_then(|idx| self.attestation_points.get(idx))
+	}
+}
+
+/// An oracle's commitment to the attestation points for the outcomes of an event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OracleAnnouncement {
+	/// The signed contents of the announcement.
+	pub contents: UnsignedOracleAnnouncement,
+	/// The oracle's signature over `contents`.
+	pub signature: OracleSignature,
+}
+
+impl_writeable_tlv_based!(OracleAnnouncement, {
+	(0, contents, required),
+	(2, signature, required),
+});
+
+impl OracleAnnouncement {
+	/// Checks the oracle's signature over the announcement's contents.
+	pub fn verify_signature<C: secp256k1::Verification>(&self, secp_ctx: &Secp256k1<C>) -> Result<(), ()> {
+		let digest = self.contents.signing_digest();
+		match &self.signature {
+			OracleSignature::Ecdsa(sig) =>
+				secp_ctx.verify_ecdsa(&digest, sig, &self.contents.oracle_pubkey).map_err(|_| ()),
+			OracleSignature::Schnorr(sig) => {
+				let pubkey = self.contents.oracle_pubkey.into();
+				secp_ctx.verify_schnorr(sig, &digest, &pubkey).map_err(|_| ())
+			},
+		}
+	}
+
+	/// Checks that each branch's outcome is a possible outcome of the event and its adaptor point
+	/// is the announced attestation point for it.
+	///
+	/// Note that this does not check the oracle's signature, see [`Self::verify_signature`].
+	pub fn validate_adaptor_points(
+		&self, branches: &[SettlementBranch], adaptor_points: &[PublicKey],
+	) -> Result<(), OraclePolicyViolation> {
+		if branches.len() != adaptor_points.len() {
+			return Err(OraclePolicyViolation::InvalidAttestationPoints);
+		}
+		for (branch, adaptor_point) in branches.iter().zip(adaptor_points.iter()) {
+			match self.contents.attestation_point(&branch.outcome) {
+				Some(point) if point == adaptor_point => {},
+				Some(_) => return Err(OraclePolicyViolation::InvalidAttestationPoints),
+				None => return Err(OraclePolicyViolation::UnknownOutcome),
+			}
+		}
+		Ok(())
+	}
+}
+
+/// An oracle's attestation to the outcome of an event, revealing the secret key for the
+/// attestation point it announced for the outcome.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OracleAttestation {
+	/// The oracle's public key.
+	pub oracle_pubkey: PublicKey,
+	/// The identifier of the event, as in [`UnsignedOracleAnnouncement::event_id`].
+	pub event_id: String,
+	/// The outcome of the event.
+	pub outcome: Vec<u8>,
+	/// The secret key for the attestation point of `outcome`.
+	pub attestation: SecretKey,
+}
+
+impl_writeable_tlv_based!(OracleAttestation, {
+	(0, oracle_pubkey, required),
+	(2, event_id, required),
+	(4, outcome, required),
+	(6, attestation, required),
+});
+
+/// The ways in which an [`OracleAnnouncement`] or [`OracleAttestation`] can violate an
+/// [`OraclePolicy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OraclePolicyViolation {
+	/// The oracle is not in [`OraclePolicy::trusted_oracles`].
+	UntrustedOracle,
+	/// The announcement was signed with a scheme not in [`OraclePolicy::allowed_signing_schemes`].
+	DisallowedSigningScheme,
+	/// The announcement's signature does not verify.
+	InvalidSignature,
+	/// The announcement's event descriptor has no outcomes or an invalid numeric range.
+	InvalidEventDescriptor,
+	/// The event has more than [`OraclePolicy::max_outcomes`] outcomes.
+	TooManyOutcomes,
+	/// The announcement does not have exactly one attestation point per outcome.
+	InvalidAttestationPoints,
+	/// The attestation is for a different oracle or event than the announcement.
+	AnnouncementMismatch,
+	/// The outcome is not a possible outcome of the announced event.
+	UnknownOutcome,
+	/// The attestation does not match the announced attestation point for its outcome.
+	InvalidAttestation,
+}
+
+/// Which oracles and oracle announcements contracts may depend on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OraclePolicy {
+	/// The public keys of the oracles we trust to attest to outcomes honestly.
+	pub trusted_oracles: Vec<PublicKey>,
+	/// The signing schemes we accept for oracle announcements.
+	pub allowed_signing_schemes: Vec<OracleSigningScheme>,
+	/// The maximum number of outcomes of an event, bounding the number of settlement transactions
+	/// which have to be signed for a contract.
+	pub max_outcomes: u64,
+}
+
+impl_writeable_tlv_based!(OraclePolicy, {
+	(0, trusted_oracles, required_vec),
+	(2, allowed_signing_schemes, required_vec),
+	(4, max_outcomes, required),
+});
+
+impl OraclePolicy {
+	/// Constructs a policy trusting the given oracles, accepting any signing scheme and events with
+	/// at most [`DEFAULT_MAX_OUTCOMES`] outcomes.
+	pub fn new(trusted_oracles: Vec<PublicKey>) -> Self {
+		OraclePolicy {
+			trusted_oracles,
+			allowed_signing_schemes: vec![OracleSigningScheme::Ecdsa, OracleSigningScheme::Schnorr],
+			max_outcomes: DEFAULT_MAX_OUTCOMES,
+		}
+	}
+
+	/// Whether the given oracle is trusted.
+	pub fn is_trusted(&self, oracle_pubkey: &PublicKey) -> bool {
+		self.trusted_oracles.contains(oracle_pubkey)
+	}
+
+	/// Checks that the given announcement was signed by a trusted oracle using an allowed scheme
+	/// and describes an event we're willing to contract on.
+	pub fn validate_announcement<C: secp256k1::Verification>(
+		&self, announcement: &OracleAnnouncement, secp_ctx: &Secp256k1<C>,
+	) -> Result<(), OraclePolicyViolation> {
+		let contents = &announcement.contents;
+		if !self.is_trusted(&contents.oracle_pubkey) {
+			return Err(OraclePolicyViolation::UntrustedOracle);
+		}
+		if !self.allowed_signing_schemes.contains(&announcement.signature.scheme()) {
+			return Err(OraclePolicyViolation::DisallowedSigningScheme);
+		}
+		announcement.verify_signature(secp_ctx).map_err(|()| OraclePolicyViolation::InvalidSignature)?;
+		let outcome_count = contents.event_descriptor.outcome_count()
+			.ok_or(OraclePolicyViolation::InvalidEventDescriptor)?;
+		if outcome_count > self.max_outcomes {
+			return Err(OraclePolicyViolation::TooManyOutcomes);
+		}
+		if contents.attestation_points.len() as u64 != outcome_count {
+			return Err(OraclePolicyViolation::InvalidAttestationPoints);
+		}
+		Ok(())
+	}
+
+	/// Checks that the given contract branches and adaptor points only depend on the outcomes of
+	/// an announcement which complies with this policy, i.e. that each branch's outcome is a
+	/// possible outcome of the event and its adaptor point is the announced attestation point.
+	pub fn validate_contract<C: secp256k1::Verification>(
+		&self, announcement: &OracleAnnouncement, branches: &[SettlementBranch],
+		adaptor_points: &[PublicKey], secp_ctx: &Secp256k1<C>,
+	) -> Result<(), OraclePolicyViolation> {
+		self.validate_announcement(announcement, secp_ctx)?;
+		announcement.validate_adaptor_points(branches, adaptor_points)
+	}
+
+	/// Checks that the given attestation was made by a trusted oracle and matches the attestation
+	/// point it announced for the outcome.
+	pub fn validate_attestation<C: secp256k1::Signing + secp256k1::Verification>(
+		&self, announcement: &OracleAnnouncement, attestation: &OracleAttestation,
+		secp_ctx: &Secp256k1<C>,
+	) -> Result<(), OraclePolicyViolation> {
+		self.validate_announcement(announcement, secp_ctx)?;
+		let contents = &announcement.contents;
+		if attestation.oracle_pubkey != contents.oracle_pubkey || attestation.event_id != contents.event_id {
+			return Err(OraclePolicyViolation::AnnouncementMismatch);
+		}
+		let point = contents.attestation_point(&attestation.outcome)
+			.ok_or(OraclePolicyViolation::UnknownOutcome)?;
+		if PublicKey::from_secret_key(secp_ctx, &attestation.attestation) != *point {
+			return Err(OraclePolicyViolation::InvalidAttestation);
+		}
+		Ok(())
+	}
+}
+
+/// A `t`-of-`n` configuration of oracles attesting to the same event, such that a contract settles
+/// once any `t` of them agree on its outcome.
+///
+/// For each outcome and each combination of `t` oracles, the adaptor point is the sum of the
+/// attestation points the oracles in the combination announced for the outcome, which can thus
+/// only be decrypted once all of them attested to the outcome. As an adaptor signature is
+/// encrypted to a single point, a contract's settlement transactions have to be adaptor signed
+/// once per combination, e.g. via one [`SettlementBundle`] per combination, all spending the same
+/// collateral output.
+///
+/// Note that the number of combinations grows quickly with `n`, and is thus limited to
+/// [`MAX_ORACLE_COMBINATIONS`].
+///
+/// [`SettlementBundle`]: crate::ln::contracts::SettlementBundle
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OracleThreshold {
+	announcements: Vec<OracleAnnouncement>,
+	threshold: usize,
+}
+
+/// The outcome `t` oracles of an [`OracleThreshold`] agreed on, as returned by
+/// [`OracleThreshold::aggregate_attestations`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ThresholdAttestation {
+	/// The outcome of the event.
+	pub outcome: Vec<u8>,
+	/// The indices of the oracles which attested to `outcome`, as in
+	/// [`OracleThreshold::combinations`].
+	pub combination: Vec<usize>,
+	/// The secret key for the adaptor point of `outcome` and `combination`, i.e. the sum of the
+	/// oracles' attestations.
+	pub adaptor_secret: SecretKey,
+}
+
+impl OracleThreshold {
+	/// Constructs a configuration requiring `threshold` of the oracles which published the given
+	/// announcements to agree on an outcome.
+	///
+	/// The announcements are not validated beyond their consistency, which should be done via
+	/// [`OraclePolicy::validate_announcement`] beforehand.
+	///
+	/// Fails if `threshold` is zero or exceeds the number of announcements, if there would be more
+	/// than [`MAX_ORACLE_COMBINATIONS`] combinations, if two announcements are from the same
+	/// oracle, or if the announcements don't all describe the same outcomes with one attestation
+	/// point each.
+	pub fn new(announcements: Vec<OracleAnnouncement>, threshold: usize) -> Result<Self, ()> {
+		if threshold == 0 || threshold > announcements.len() { return Err(()); }
+		let (n, k) = (announcements.len() as u64, cmp::min(threshold, announcements.len() - threshold) as u64);
+		let mut combination_count: u64 = 1;
+		for i in 0..k {
+			// Each intermediate result is itself a binomial coefficient and thus an integer.
+			combination_count = combination_count.checked_mul(n - i).ok_or(())? / (i + 1);
+			if combination_count > MAX_ORACLE_COMBINATIONS { return Err(()); }
+		}
+		let descriptor = &announcements[0].contents.event_descriptor;
+		let outcome_count = descriptor.outcome_count().ok_or(())?;
+		for (idx, announcement) in announcements.iter().enumerate() {
+			let contents = &announcement.contents;
+			if contents.event_descriptor != *descriptor || contents.attestation_points.len() as u64 != outcome_count {
+				return Err(());
+			}
+			if announcements[..idx].iter().any(|a| a.contents.oracle_pubkey == contents.oracle_pubkey) {
+				return Err(());
+			}
+		}
+		Ok(OracleThreshold { announcements, threshold })
+	}
+
+	/// The announcements of the oracles, indexed as in [`Self::combinations`].
+	pub fn announcements(&self) -> &[OracleAnnouncement] {
+		&self.announcements
+	}
+
+	/// The number of oracles which have to agree on an outcome.
+	pub fn threshold(&self) -> usize {
+		self.threshold
+	}
+
+	/// Enumerates all combinations of [`Self::threshold`] oracles, as ascending indices into
+	/// [`Self::announcements`], in lexicographic order.
+	pub fn combinations(&self) -> Vec<Vec<usize>> {
+		let (n, t) = (self.announcements.len(), self.threshold);
+		let mut combinations = Vec::new();
+		let mut combination: Vec<usize> = (0..t).collect();
+		loop {
+			combinations.push(combination.clone());
+			// Find the rightmost index which can still be incremented, resetting those after it.
+			let idx = match (0..t).rev().find(|i| combination[*i] != i + n - t) {
+				Some(idx) => idx,
+				None => return combinations,
+			};
+			let first = combination[idx] + 1;
+			for (offset, slot) in combination[idx..].iter_mut().enumerate() {
+				*slot = first + offset;
+			}
+		}
+	}
+
+	fn is_valid_combination(&self, combination: &[usize]) -> bool {
+		combination.len() == self.threshold && combination.iter().all(|idx| *idx < self.announcements.len())
+			&& combination.windows(2).all(|pair| pair[0] < pair[1])
+	}
+
+	/// Gets the adaptor point for the given outcome and combination of oracles, i.e. the sum of
+	/// the attestation points they announced for the outcome.
+	///
+	/// Returns `None` if `outcome` is not a possible outcome of the event or `combination` is not
+	/// one of [`Self::combinations`].
+	pub fn adaptor_point(&self, outcome: &[u8], combination: &[usize]) -> Option<PublicKey> {
+		if !self.is_valid_combination(combination) { return None; }
+		let mut adaptor_point: Option<PublicKey> = None;
+		for idx in combination {
+			let point = self.announcements[*idx].contents.attestation_point(outcome)?;
+			adaptor_point = Some(match adaptor_point {
+				Some(sum) => sum.combine(point).ok()?,
+				None => *point,
+			});
+		}
+		adaptor_point
+	}
+
+	/// Gets the adaptor points for the given branches and combination of oracles, as passed to
+	/// [`SettlementBundle::adaptor_sign_branches`] when signing the combination's bundle.
+	///
+	/// Returns `None` if any branch's outcome is not a possible outcome of the event or
+	/// `combination` is not one of [`Self::combinations`].
+	///
+	/// [`SettlementBundle::adaptor_sign_branches`]: crate::ln::contracts::SettlementBundle::adaptor_sign_branches
+	pub fn adaptor_points(&self, branches: &[SettlementBranch], combination: &[usize]) -> Option<Vec<PublicKey>> {
+		branches.iter().map(|branch| self.adaptor_point(&branch.outcome, combination)).collect()
+	}
+
+	/// Checks an attestation from one of the oracles as it arrives, returning the index of the
+	/// oracle which made it.
+	///
+	/// Fails with [`OraclePolicyViolation::AnnouncementMismatch`] if the attestation is not from
+	/// one of the oracles or for a different event.
+	pub fn verify_attestation<C: secp256k1::Signing>(
+		&self, attestation: &OracleAttestation, secp_ctx: &Secp256k1<C>,
+	) -> Result<usize, OraclePolicyViolation> {
+		let idx = self.announcements.iter().position(|announcement|
+			announcement.contents.oracle_pubkey == attestation.oracle_pubkey
+				&& announcement.contents.event_id == attestation.event_id
+		).ok_or(OraclePolicyViolation::AnnouncementMismatch)?;
+		let point = self.announcements[idx].contents.attestation_point(&attestation.outcome)
+			.ok_or(OraclePolicyViolation::UnknownOutcome)?;
+		if PublicKey::from_secret_key(secp_ctx, &attestation.attestation) != *point {
+			return Err(OraclePolicyViolation::InvalidAttestation);
+		}
+		Ok(idx)
+	}
+
+	/// Aggregates the attestations received so far, returning the outcome and adaptor secret once
+	/// all oracles of the given combination attested to the same outcome, e.g. as a contract's
+	/// settlement transactions were only adaptor signed for that combination.
+	///
+	/// Fails if `combination` is not one of [`Self::combinations`] or any attestation doesn't
+	/// pass [`Self::verify_attestation`].
+	pub fn aggregate_combination_attestations<C: secp256k1::Signing>(
+		&self, attestations: &[OracleAttestation], combination: &[usize], secp_ctx: &Secp256k1<C>,
+	) -> Result<Option<ThresholdAttestation>, OraclePolicyViolation> {
+		if !self.is_valid_combination(combination) { return Err(OraclePolicyViolation::AnnouncementMismatch); }
+		let oracle_attestations = self.verify_attestations(attestations, secp_ctx)?;
+		let outcome = match oracle_attestations[combination[0]] {
+			Some(attestation) => &attestation.outcome,
+			None => return Ok(None),
+		};
+		if !combination.iter().all(|idx| oracle_attestations[*idx].map_or(false, |a| a.outcome == *outcome)) {
+			return Ok(None);
+		}
+		Self::sum_attestations(&oracle_attestations, combination.to_vec()).map(Some)
+	}
+
+	fn verify_attestations<'a, C: secp256k1::Signing>(
+		&self, attestations: &'a [OracleAttestation], secp_ctx: &Secp256k1<C>,
+	) -> Result<Vec<Option<&'a OracleAttestation>>, OraclePolicyViolation> {
+		let mut oracle_attestations: Vec<Option<&OracleAttestation>> = vec![None; self.announcements.len()];
+		for attestation in attestations {
+			let idx = self.verify_attestation(attestation, secp_ctx)?;
+			oracle_attestations[idx] = Some(attestation);
+		}
+		Ok(oracle_attestations)
+	}
+
+	// Sums the attestations of the given combination's oracles, which must all be present and
+	// agree on the outcome.
+	fn sum_attestations(
+		oracle_attestations: &[Option<&OracleAttestation>], combination: Vec<usize>,
+	) -> Result<ThresholdAttestation, OraclePolicyViolation> {
+		let mut adaptor_secret: Option<SecretKey> = None;
+		for idx in combination.iter() {
+			let secret = oracle_attestations[*idx].unwrap().attestation;
+			adaptor_secret = Some(match adaptor_secret {
+				Some(sum) => sum.add_tweak(&Scalar::from(secret))
+					.map_err(|_| OraclePolicyViolation::InvalidAttestation)?,
+				None => secret,
+			});
+		}
+		Ok(ThresholdAttestation {
+			outcome: oracle_attestations[combination[0]].unwrap().outcome.clone(),
+			combination,
+			adaptor_secret: adaptor_secret.unwrap(),
+		})
+	}
+
+	/// Aggregates the attestations received so far, returning the outcome and adaptor secret
+	/// once [`Self::threshold`] distinct oracles attested to the same outcome.
+	///
+	/// If more oracles than required agree, the combination of those with the lowest indices is
+	/// used. Fails if any attestation doesn't pass [`Self::verify_attestation`].
+	pub fn aggregate_attestations<C: secp256k1::Signing>(
+		&self, attestations: &[OracleAttestation], secp_ctx: &Secp256k1<C>,
+	) -> Result<Option<ThresholdAttestation>, OraclePolicyViolation> {
+		let oracle_attestations = self.verify_attestations(attestations, secp_ctx)?;
+		for attestation in oracle_attestations.iter().filter_map(|a| *a) {
+			let combination: Vec<usize> = oracle_attestations.iter().enumerate()
+				.filter(|(_, a)| a.map_or(false, |a| a.outcome == attestation.outcome))
+				.map(|(idx, _)| idx).take(self.threshold).collect();
+			if combination.len() < self.threshold { continue; }
+			return Self::sum_attestations(&oracle_attestations, combination).map(Some);
+		}
+		Ok(None)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{EventDescriptor, OracleAnnouncement, OracleAttestation, OraclePolicy, OraclePolicyViolation,
+		OracleSignature, OracleSigningScheme, OracleThreshold, UnsignedOracleAnnouncement};
+	use crate::ln::contracts::{SettlementBranch, numeric_outcome_bytes};
+	use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
+	use crate::util::ser::{Readable, Writeable};
+
+	use bitcoin::secp256k1::{KeyPair, Message, PublicKey, Scalar, Secp256k1, SecretKey};
+
+	use crate::prelude::*;
+
+	fn attestation_key(idx: u8) -> SecretKey {
+		SecretKey::from_slice(&[idx + 1; 32]).unwrap()
+	}
+
+	fn announcement(oracle_key: &SecretKey, descriptor: EventDescriptor, schnorr: bool) -> OracleAnnouncement {
+		let secp_ctx = Secp256k1::new();
+		let outcome_count = descriptor.outcome_count().unwrap() as u8;
+		let contents = UnsignedOracleAnnouncement {
+			oracle_pubkey: PublicKey::from_secret_key(&secp_ctx, oracle_key),
+			event_id: "btcusd-2023-12-31".to_owned(),
+			event_maturity: 1_704_067_200,
+			event_descriptor: descriptor,
+			attestation_points: (0..outcome_count)
+				.map(|idx| PublicKey::from_secret_key(&secp_ctx, &attestation_key(idx))).collect(),
+		};
+		let digest = contents.signing_digest();
+		let signature = if schnorr {
+			let keys = KeyPair::from_secret_key(&secp_ctx, oracle_key);
+			OracleSignature::Schnorr(secp_ctx.sign_schnorr_no_aux_rand(&digest, &keys))
+		} else {
+			OracleSignature::Ecdsa(secp_ctx.sign_ecdsa(&digest, oracle_key))
+		};
+		OracleAnnouncement { contents, signature }
+	}
+
+	#[test]
+	fn validates_announcements() {
+		let secp_ctx = Secp256k1::new();
+		let oracle_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let oracle_pubkey = PublicKey::from_secret_key(&secp_ctx, &oracle_key);
+		let other_oracle_key = SecretKey::from_slice(&[43; 32]).unwrap();
+		let descriptor = EventDescriptor::Numeric { min_outcome: 10, max_outcome: 13 };
+
+		let mut policy = OraclePolicy::new(vec![oracle_pubkey]);
+		let ecdsa_announcement = announcement(&oracle_key, descriptor.clone(), false);
+		let schnorr_announcement = announcement(&oracle_key, descriptor.clone(), true);
+		assert_eq!(policy.validate_announcement(&ecdsa_announcement, &secp_ctx), Ok(()));
+		assert_eq!(policy.validate_announcement(&schnorr_announcement, &secp_ctx), Ok(()));
+		assert_eq!(policy.validate_announcement(&announcement(&other_oracle_key, descriptor.clone(), true), &secp_ctx),
+			Err(OraclePolicyViolation::UntrustedOracle));
+
+		let mut tampered_announcement = schnorr_announcement.clone();
+		tampered_announcement.contents.event_maturity += 1;
+		assert_eq!(policy.validate_announcement(&tampered_announcement, &secp_ctx),
+			Err(OraclePolicyViolation::InvalidSignature));
+
+		assert!(EventDescriptor::Numeric { min_outcome: 13, max_outcome: 10 }.outcome_count().is_none());
+		assert!(EventDescriptor::Enum { outcomes: Vec::new() }.outcome_count().is_none());
+
+		policy.max_outcomes = 3;
+		assert_eq!(policy.validate_announcement(&schnorr_announcement, &secp_ctx),
+			Err(OraclePolicyViolation::TooManyOutcomes));
+		policy.max_outcomes = 4;
+		policy.allowed_signing_schemes = vec![OracleSigningScheme::Schnorr];
+		assert_eq!(policy.validate_announcement(&ecdsa_announcement, &secp_ctx),
+			Err(OraclePolicyViolation::DisallowedSigningScheme));
+
+		// Contracts must use the announced attestation points for their outcomes.
+		let branches: Vec<_> = (10..14).map(|outcome| SettlementBranch {
+			outcome: numeric_outcome_bytes(outcome), holder_payout_satoshis: outcome, counterparty_payout_satoshis: 0,
+		}).collect();
+		let adaptor_points = schnorr_announcement.contents.attestation_points.clone();
+		assert_eq!(policy.validate_contract(&schnorr_announcement, &branches, &adaptor_points, &secp_ctx), Ok(()));
+		let mut swapped_points = adaptor_points.clone();
+		swapped_points.swap(0, 1);
+		assert_eq!(policy.validate_contract(&schnorr_announcement, &branches, &swapped_points, &secp_ctx),
+			Err(OraclePolicyViolation::InvalidAttestationPoints));
+		let mut unknown_branches = branches.clone();
+		unknown_branches[3].outcome = numeric_outcome_bytes(14);
+		assert_eq!(policy.validate_contract(&schnorr_announcement, &unknown_branches, &adaptor_points, &secp_ctx),
+			Err(OraclePolicyViolation::UnknownOutcome));
+
+		// Policies survive a serialization roundtrip.
+		let decoded: OraclePolicy = Readable::read(&mut &policy.encode()[..]).unwrap();
+		assert_eq!(decoded, policy);
+		let decoded: OracleAnnouncement = Readable::read(&mut &schnorr_announcement.encode()[..]).unwrap();
+		assert_eq!(decoded, schnorr_announcement);
+	}
+
+	#[test]
+	fn validates_attestations() {
+		let secp_ctx = Secp256k1::new();
+		let oracle_key = SecretKey::from_slice(&[42; 32]).unwrap();
+		let oracle_pubkey = PublicKey::from_secret_key(&secp_ctx, &oracle_key);
+		let descriptor = EventDescriptor::Enum { outcomes: vec![b"yes".to_vec(), b"no".to_vec()] };
+		let announcement = announcement(&oracle_key, descriptor, true);
+		let policy = OraclePolicy::new(vec![oracle_pubkey]);
+
+		let mut attestation = OracleAttestation {
+			oracle_pubkey, event_id: announcement.contents.event_id.clone(), outcome: b"no".to_vec(),
+			attestation: attestation_key(1),
+		};
+		assert_eq!(policy.validate_attestation(&announcement, &attestation, &secp_ctx), Ok(()));
+
+		attestation.attestation = attestation_key(0);
+		assert_eq!(policy.validate_attestation(&announcement, &attestation, &secp_ctx),
+			Err(OraclePolicyViolation::InvalidAttestation));
+		attestation.outcome = b"maybe".to_vec();
+		assert_eq!(policy.validate_attestation(&announcement, &attestation, &secp_ctx),
+			Err(OraclePolicyViolation::UnknownOutcome));
+		attestation.event_id = "another-event".to_owned();
+		assert_eq!(policy.validate_attestation(&announcement, &attestation, &secp_ctx),
+			Err(OraclePolicyViolation::AnnouncementMismatch));
+	}
+
+	#[test]
+	fn aggregates_threshold_attestations() {
+		let secp_ctx = Secp256k1::new();
+		let descriptor = EventDescriptor::Enum { outcomes: vec![b"yes".to_vec(), b"no".to_vec()] };
+		let attestation_key = |oracle: u8, outcome: u8| SecretKey::from_slice(&[oracle * 16 + outcome + 1; 32]).unwrap();
+		let oracle_announcement = |oracle: u8| {
+			let oracle_key = SecretKey::from_slice(&[42 + oracle; 32]).unwrap();
+			let mut contents = announcement(&oracle_key, descriptor.clone(), false).contents;
+			contents.attestation_points = (0..2)
+				.map(|outcome| PublicKey::from_secret_key(&secp_ctx, &attestation_key(oracle, outcome))).collect();
+			let signature = OracleSignature::Ecdsa(secp_ctx.sign_ecdsa(&contents.signing_digest(), &oracle_key));
+			OracleAnnouncement { contents, signature }
+		};
+		let oracle_attestation = |oracle: u8, outcome: u8| OracleAttestation {
+			oracle_pubkey: PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42 + oracle; 32]).unwrap()),
+			event_id: "btcusd-2023-12-31".to_owned(),
+			outcome: descriptor.outcome(outcome as usize).unwrap(),
+			attestation: attestation_key(oracle, outcome),
+		};
+		let announcements: Vec<_> = (0..3).map(oracle_announcement).collect();
+
+		assert!(OracleThreshold::new(announcements.clone(), 0).is_err());
+		assert!(OracleThreshold::new(announcements.clone(), 4).is_err());
+		assert!(OracleThreshold::new(vec![announcements[0].clone(), announcements[0].clone()], 1).is_err());
+		let mut mismatched_announcements = announcements.clone();
+		mismatched_announcements[2].contents.event_descriptor = EventDescriptor::Enum { outcomes: vec![b"yes".to_vec()] };
+		assert!(OracleThreshold::new(mismatched_announcements, 2).is_err());
+
+		// Thresholds with too many combinations are refused, even if the number of oracles is fine.
+		let many_announcements: Vec<_> = (0..14).map(oracle_announcement).collect();
+		assert!(OracleThreshold::new(many_announcements.clone(), 3).is_ok());
+		assert!(OracleThreshold::new(many_announcements.clone(), 7).is_err());
+		assert!(OracleThreshold::new(many_announcements, 13).is_ok());
+
+		let threshold = OracleThreshold::new(announcements, 2).unwrap();
+		assert_eq!(threshold.combinations(), vec![vec![0, 1], vec![0, 2], vec![1, 2]]);
+		assert_eq!(OracleThreshold::new(threshold.announcements().to_vec(), 3).unwrap().combinations(), vec![vec![0, 1, 2]]);
+		assert_eq!(OracleThreshold::new(threshold.announcements().to_vec(), 1).unwrap().combinations().len(), 3);
+
+		let adaptor_secret = attestation_key(0, 1).add_tweak(&Scalar::from(attestation_key(2, 1))).unwrap();
+		let adaptor_point = PublicKey::from_secret_key(&secp_ctx, &adaptor_secret);
+		assert_eq!(threshold.adaptor_point(b"no", &[0, 2]), Some(adaptor_point));
+		assert!(threshold.adaptor_point(b"maybe", &[0, 2]).is_none());
+		assert!(threshold.adaptor_point(b"no", &[2, 0]).is_none());
+		assert!(threshold.adaptor_point(b"no", &[0, 3]).is_none());
+		assert!(threshold.adaptor_point(b"no", &[0]).is_none());
+		let branches: Vec<_> = [&b"yes"[..], &b"no"[..]].iter().map(|outcome| SettlementBranch {
+			outcome: outcome.to_vec(), holder_payout_satoshis: 0, counterparty_payout_satoshis: 0,
+		}).collect();
+		assert_eq!(threshold.adaptor_points(&branches, &[0, 2]).unwrap()[1], adaptor_point);
+
+		// Attestations are checked as they arrive, and aggregated once two oracles agree.
+		assert_eq!(threshold.verify_attestation(&oracle_attestation(2, 1), &secp_ctx), Ok(2));
+		let mut invalid_attestation = oracle_attestation(1, 1);
+		invalid_attestation.attestation = attestation_key(1, 0);
+		assert_eq!(threshold.verify_attestation(&invalid_attestation, &secp_ctx),
+			Err(OraclePolicyViolation::InvalidAttestation));
+		invalid_attestation.oracle_pubkey = PublicKey::from_secret_key(&secp_ctx, &attestation_key(1, 0));
+		assert_eq!(threshold.verify_attestation(&invalid_attestation, &secp_ctx),
+			Err(OraclePolicyViolation::AnnouncementMismatch));
+		assert_eq!(threshold.aggregate_attestations(&[invalid_attestation], &secp_ctx),
+			Err(OraclePolicyViolation::AnnouncementMismatch));
+
+		let mut attestations = vec![oracle_attestation(2, 1)];
+		assert_eq!(threshold.aggregate_attestations(&attestations, &secp_ctx), Ok(None));
+		attestations.push(oracle_attestation(1, 0));
+		assert_eq!(threshold.aggregate_attestations(&attestations, &secp_ctx), Ok(None));
+		attestations.push(oracle_attestation(0, 1));
+		let aggregated = threshold.aggregate_attestations(&attestations, &secp_ctx).unwrap().unwrap();
+		assert_eq!(aggregated.outcome, b"no".to_vec());
+		assert_eq!(aggregated.combination, vec![0, 2]);
+		assert_eq!(aggregated.adaptor_secret, adaptor_secret);
+
+		// Only the oracles of the requested combination are considered.
+		assert_eq!(threshold.aggregate_combination_attestations(&attestations, &[0, 1], &secp_ctx), Ok(None));
+		assert_eq!(threshold.aggregate_combination_attestations(&attestations, &[0, 2], &secp_ctx), Ok(Some(aggregated.clone())));
+		assert!(threshold.aggregate_combination_attestations(&attestations, &[2, 0], &secp_ctx).is_err());
+
+		// The aggregated secret decrypts adaptor signatures encrypted to the combination's point.
+		let msg = Message::from_slice(&[42; 32]).unwrap();
+		let signing_key = SecretKey::from_slice(&[99; 32]).unwrap();
+		let adaptor_sig = EcdsaAdaptorSignature::encrypt(&secp_ctx, &msg, &signing_key, &adaptor_point);
+		let sig = adaptor_sig.decrypt(&aggregated.adaptor_secret).unwrap();
+		assert!(secp_ctx.verify_ecdsa(&msg, &sig, &PublicKey::from_secret_key(&secp_ctx, &signing_key)).is_ok());
+	}
+}
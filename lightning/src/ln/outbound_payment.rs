@@ -17,15 +17,16 @@ use crate::sign::{EntropySource, NodeSigner, Recipient};
 use crate::events::{self, PaymentFailureReason};
 use crate::ln::{PaymentHash, PaymentPreimage, PaymentSecret};
 use crate::ln::channelmanager::{ChannelDetails, EventCompletionAction, HTLCSource, IDEMPOTENCY_TIMEOUT_TICKS, PaymentId};
+use crate::ln::msgs::MIN_CUSTOM_ONION_TLV_TYPE;
 use crate::ln::onion_utils::HTLCFailReason;
-use crate::routing::router::{InFlightHtlcs, Path, PaymentParameters, Route, RouteParameters, Router};
+use crate::routing::router::{InFlightHtlcs, Path, PaymentParameters, Route, RouteFuture, RouteParameters, RouteResult, Router};
+use crate::util::config::PaymentRetryBudget;
 use crate::util::errors::APIError;
 use crate::util::logger::Logger;
-use crate::util::time::Time;
-#[cfg(all(not(feature = "no-std"), test))]
-use crate::util::time::tests::SinceEpoch;
+use crate::util::time::{ConfiguredTime, Time};
 use crate::util::ser::ReadableArgs;
 
+use core::cmp;
 use core::fmt::{self, Display, Formatter};
 use core::ops::Deref;
 
@@ -46,6 +47,7 @@ pub(crate) enum PendingOutboundPayment {
 		payment_hash: PaymentHash,
 		payment_secret: Option<PaymentSecret>,
 		payment_metadata: Option<Vec<u8>>,
+		custom_tlvs: Vec<(u64, Vec<u8>)>,
 		keysend_preimage: Option<PaymentPreimage>,
 		pending_amt_msat: u64,
 		/// Used to track the fee paid. Only present if the payment was serialized on 0.0.103+.
@@ -54,6 +56,12 @@ pub(crate) enum PendingOutboundPayment {
 		total_msat: u64,
 		/// Our best known block height at the time this payment was initiated.
 		starting_block_height: u32,
+		/// If set, retries are sent over (a subset of) this route rather than one returned by the
+		/// [`Router`]. Only present for payments sent via
+		/// [`ChannelManager::send_payment_with_pinned_route`].
+		///
+		/// [`ChannelManager::send_payment_with_pinned_route`]: crate::ln::channelmanager::ChannelManager::send_payment_with_pinned_route
+		pinned_route: Option<Route>,
 	},
 	/// When a pending payment is fulfilled, we continue tracking it until all pending HTLCs have
 	/// been resolved. This ensures we don't look up pending payments in ChannelMonitors on restart
@@ -80,7 +88,7 @@ impl PendingOutboundPayment {
 			attempts.count += 1;
 		}
 	}
-	fn is_auto_retryable_now(&self) -> bool {
+	pub(super) fn is_auto_retryable_now(&self) -> bool {
 		match self {
 			PendingOutboundPayment::Retryable {
 				retry_strategy: Some(strategy), attempts, payment_params: Some(_), ..
@@ -237,12 +245,9 @@ impl Retry {
 			(Retry::Attempts(max_retry_count), PaymentAttempts { count, .. }) => {
 				max_retry_count > count
 			},
-			#[cfg(all(not(feature = "no-std"), not(test)))]
-			(Retry::Timeout(max_duration), PaymentAttempts { first_attempted_at, .. }) =>
-				*max_duration >= crate::util::time::MonotonicTime::now().duration_since(*first_attempted_at),
-			#[cfg(all(not(feature = "no-std"), test))]
+			#[cfg(not(feature = "no-std"))]
 			(Retry::Timeout(max_duration), PaymentAttempts { first_attempted_at, .. }) =>
-				*max_duration >= SinceEpoch::now().duration_since(*first_attempted_at),
+				*max_duration >= ConfiguredTime::now().duration_since(*first_attempted_at),
 		}
 	}
 }
@@ -257,6 +262,28 @@ pub(super) fn has_expired(route_params: &RouteParameters) -> bool {
 	false
 }
 
+/// Returns an [`Event::PaymentDeadlineExceeded`] for the payment if our best known block height
+/// has reached its [`PaymentParameters::deadline_block_height`] or, with the `std` feature, the
+/// current time has reached its [`PaymentParameters::deadline_time`].
+///
+/// [`Event::PaymentDeadlineExceeded`]: crate::events::Event::PaymentDeadlineExceeded
+pub(super) fn deadline_exceeded(
+	payment_id: PaymentId, payment_hash: PaymentHash, payment_params: &PaymentParameters,
+	best_block_height: u32
+) -> Option<events::Event> {
+	let deadline_block_height = payment_params.deadline_block_height
+		.filter(|deadline| best_block_height >= *deadline);
+	#[cfg(feature = "std")]
+	let deadline_time = payment_params.deadline_time.filter(|deadline| {
+		std::time::SystemTime::UNIX_EPOCH.elapsed()
+			.map_or(false, |elapsed| elapsed >= core::time::Duration::from_secs(*deadline))
+	});
+	#[cfg(not(feature = "std"))]
+	let deadline_time = None;
+	if deadline_block_height.is_none() && deadline_time.is_none() { return None; }
+	Some(events::Event::PaymentDeadlineExceeded { payment_id, payment_hash, deadline_block_height, deadline_time })
+}
+
 pub(crate) type PaymentAttempts = PaymentAttemptsUsingTime<ConfiguredTime>;
 
 /// Storing minimal payment attempts information required for determining if a outbound payment can
@@ -273,13 +300,6 @@ pub(crate) struct PaymentAttemptsUsingTime<T: Time> {
 
 }
 
-#[cfg(not(any(feature = "no-std", test)))]
-type ConfiguredTime = crate::util::time::MonotonicTime;
-#[cfg(feature = "no-std")]
-type ConfiguredTime = crate::util::time::Eternity;
-#[cfg(all(not(feature = "no-std"), test))]
-type ConfiguredTime = SinceEpoch;
-
 impl<T: Time> PaymentAttemptsUsingTime<T> {
 	pub(crate) fn new() -> Self {
 		PaymentAttemptsUsingTime {
@@ -431,10 +451,13 @@ pub struct RecipientOnionFields {
 	/// [`Self::payment_secret`] and while nearly all lightning senders support secrets, metadata
 	/// may not be supported as universally.
 	pub payment_metadata: Option<Vec<u8>>,
+	/// See [`Self::custom_tlvs`] for more info.
+	pub(super) custom_tlvs: Vec<(u64, Vec<u8>)>,
 }
 
 impl_writeable_tlv_based!(RecipientOnionFields, {
 	(0, payment_secret, option),
+	(1, custom_tlvs, optional_vec),
 	(2, payment_metadata, option),
 });
 
@@ -443,7 +466,7 @@ impl RecipientOnionFields {
 	/// set of onion fields for today's BOLT11 invoices - most nodes require a [`PaymentSecret`]
 	/// but do not require or provide any further data.
 	pub fn secret_only(payment_secret: PaymentSecret) -> Self {
-		Self { payment_secret: Some(payment_secret), payment_metadata: None }
+		Self { payment_secret: Some(payment_secret), payment_metadata: None, custom_tlvs: Vec::new() }
 	}
 
 	/// Creates a new [`RecipientOnionFields`] with no fields. This generally does not create
@@ -455,7 +478,36 @@ impl RecipientOnionFields {
 	/// [`ChannelManager::send_spontaneous_payment`]: super::channelmanager::ChannelManager::send_spontaneous_payment
 	/// [`RecipientOnionFields::secret_only`]: RecipientOnionFields::secret_only
 	pub fn spontaneous_empty() -> Self {
-		Self { payment_secret: None, payment_metadata: None }
+		Self { payment_secret: None, payment_metadata: None, custom_tlvs: Vec::new() }
+	}
+
+	/// Includes the given custom TLVs in the onion of the final hop, e.g. to pass an order or
+	/// contract reference to a recipient which holds inbound HTLCs for validation.
+	///
+	/// All types must be odd, at least [`MIN_CUSTOM_ONION_TLV_TYPE`] and unique, or an `Err` is
+	/// returned. Note that the custom TLVs share the space in the onion with the rest of the route,
+	/// so large values may make it impossible to send over longer paths.
+	///
+	/// [`MIN_CUSTOM_ONION_TLV_TYPE`]: crate::ln::msgs::MIN_CUSTOM_ONION_TLV_TYPE
+	pub fn with_custom_tlvs(mut self, mut custom_tlvs: Vec<(u64, Vec<u8>)>) -> Result<Self, ()> {
+		custom_tlvs.sort_unstable_by_key(|(typ, _)| *typ);
+		if custom_tlvs.iter().any(|(typ, _)| *typ < MIN_CUSTOM_ONION_TLV_TYPE || typ % 2 == 0) {
+			return Err(());
+		}
+		if custom_tlvs.windows(2).any(|tlvs| tlvs[0].0 == tlvs[1].0) {
+			return Err(());
+		}
+		self.custom_tlvs = custom_tlvs;
+		Ok(self)
+	}
+
+	/// The custom TLVs to include in the onion of the final hop, sorted by type, see
+	/// [`Self::with_custom_tlvs`].
+	///
+	/// For received payments, these are the custom TLVs the sender included, which are only
+	/// retained if all parts of a multi-part payment included them.
+	pub fn custom_tlvs(&self) -> &Vec<(u64, Vec<u8>)> {
+		&self.custom_tlvs
 	}
 
 	/// When we have received some HTLC(s) towards an MPP payment, as we receive further HTLC(s) we
@@ -469,20 +521,84 @@ impl RecipientOnionFields {
 		if self.payment_secret != further_htlc_fields.payment_secret { return Err(()); }
 		if self.payment_metadata != further_htlc_fields.payment_metadata { return Err(()); }
 		// For custom TLVs we should just drop non-matching ones, but not reject the payment.
+		let further_tlvs = &further_htlc_fields.custom_tlvs;
+		self.custom_tlvs.retain(|tlv| further_tlvs.contains(tlv));
+		let tlvs = &self.custom_tlvs;
+		further_htlc_fields.custom_tlvs.retain(|tlv| tlvs.contains(tlv));
 		Ok(())
 	}
 }
 
+/// The number of timer ticks (which occur roughly once per minute) making up one window of the
+/// [`PaymentRetryBudget::max_retry_fees_msat_per_hour`] limit.
+const RETRY_FEE_BUDGET_WINDOW_TICKS: u8 = 60;
+
+/// Tracks the usage of a [`PaymentRetryBudget`] across all outbound payments.
+pub(super) struct RetryBudgetTracker {
+	budget: PaymentRetryBudget,
+	/// The routing fees committed to by retries since the start of the current window.
+	fees_msat_in_window: u64,
+	/// The number of timer ticks since the start of the current window.
+	ticks_in_window: u8,
+}
+
+impl RetryBudgetTracker {
+	pub(super) fn new(budget: PaymentRetryBudget) -> Self {
+		Self { budget, fees_msat_in_window: 0, ticks_in_window: 0 }
+	}
+
+	/// Checks that a retry leaving `retry_value_in_flight_msat` pending across all retried
+	/// payments and paying `retry_fee_msat` in routing fees is within budget, consuming the fees
+	/// from the budget if so.
+	fn try_consume(&mut self, retry_value_in_flight_msat: u64, retry_fee_msat: u64) -> Result<(), &'static str> {
+		if let Some(max_value_msat) = self.budget.max_retry_value_in_flight_msat {
+			if retry_value_in_flight_msat > max_value_msat {
+				return Err("value in flight across retried payments");
+			}
+		}
+		let fees_msat_in_window = self.fees_msat_in_window.saturating_add(retry_fee_msat);
+		if let Some(max_fees_msat) = self.budget.max_retry_fees_msat_per_hour {
+			if fees_msat_in_window > max_fees_msat {
+				return Err("routing fees spent on retries in the last hour");
+			}
+		}
+		self.fees_msat_in_window = fees_msat_in_window;
+		Ok(())
+	}
+
+	fn timer_tick_occurred(&mut self) {
+		self.ticks_in_window += 1;
+		if self.ticks_in_window >= RETRY_FEE_BUDGET_WINDOW_TICKS {
+			self.ticks_in_window = 0;
+			self.fees_msat_in_window = 0;
+		}
+	}
+}
+
+/// A route for retrying a payment which is being found asynchronously, see
+/// [`Router::find_route_async`].
+struct PendingRouteRequest {
+	/// The parameters the route was requested for. If they no longer match those of the retry once
+	/// the route is found, the route is discarded and a new one requested.
+	route_params: RouteParameters,
+	future: RouteFuture,
+}
+
 pub(super) struct OutboundPayments {
 	pub(super) pending_outbound_payments: Mutex<HashMap<PaymentId, PendingOutboundPayment>>,
 	pub(super) retry_lock: Mutex<()>,
+	pub(super) retry_budget: Mutex<RetryBudgetTracker>,
+	/// Lock order: must be taken after `pending_outbound_payments`, if both are held.
+	pending_route_requests: Mutex<HashMap<PaymentId, PendingRouteRequest>>,
 }
 
 impl OutboundPayments {
-	pub(super) fn new() -> Self {
+	pub(super) fn new(retry_budget: PaymentRetryBudget) -> Self {
 		Self {
 			pending_outbound_payments: Mutex::new(HashMap::new()),
 			retry_lock: Mutex::new(()),
+			retry_budget: Mutex::new(RetryBudgetTracker::new(retry_budget)),
+			pending_route_requests: Mutex::new(HashMap::new()),
 		}
 	}
 
@@ -520,10 +636,48 @@ impl OutboundPayments {
 	{
 		let onion_session_privs = self.add_new_pending_payment(payment_hash, recipient_onion.clone(), payment_id, None, route, None, None, entropy_source, best_block_height)?;
 		self.pay_route_internal(route, payment_hash, recipient_onion, None, payment_id, None,
-			onion_session_privs, node_signer, best_block_height, &send_payment_along_path)
+			false, onion_session_privs, node_signer, best_block_height, &send_payment_along_path)
 			.map_err(|e| { self.remove_outbound_if_all_failed(payment_id, &e); e })
 	}
 
+	pub(super) fn send_payment_with_pinned_route<ES: Deref, NS: Deref, F>(
+		&self, route: &Route, payment_hash: PaymentHash, recipient_onion: RecipientOnionFields,
+		payment_id: PaymentId, retry_strategy: Retry, entropy_source: &ES, node_signer: &NS,
+		best_block_height: u32,
+		pending_events: &Mutex<VecDeque<(events::Event, Option<EventCompletionAction>)>>,
+		send_payment_along_path: F
+	) -> Result<(), PaymentSendFailure>
+	where
+		ES::Target: EntropySource,
+		NS::Target: NodeSigner,
+		F: Fn(&Path, &PaymentHash, RecipientOnionFields, u64, u32, PaymentId,
+			&Option<PaymentPreimage>, [u8; 32]) -> Result<(), APIError>
+	{
+		let payment_params = match route.payment_params {
+			Some(ref params) => params.clone(),
+			None => return Err(PaymentSendFailure::ParameterError(APIError::APIMisuseError {
+				err: "Pinned routes must include payment_params to be retried".to_owned()
+			})),
+		};
+		let onion_session_privs = self.add_new_pending_payment(payment_hash, recipient_onion.clone(),
+			payment_id, None, route, Some(retry_strategy), Some(payment_params), entropy_source,
+			best_block_height)?;
+		if let Some(PendingOutboundPayment::Retryable { pinned_route, .. }) =
+			self.pending_outbound_payments.lock().unwrap().get_mut(&payment_id)
+		{
+			*pinned_route = Some(route.clone());
+		}
+		let res = self.pay_route_internal(route, payment_hash, recipient_onion, None, payment_id, None,
+			false, onion_session_privs, node_signer, best_block_height, &send_payment_along_path)
+			.map_err(|e| { self.remove_outbound_if_all_failed(payment_id, &e); e });
+		if res.is_ok() || matches!(res, Err(PaymentSendFailure::PartialFailure { .. })) {
+			pending_events.lock().unwrap().push_back((events::Event::PaymentAttemptSent {
+				payment_id, payment_hash, attempt: 0, route: route.clone(),
+			}, None));
+		}
+		res
+	}
+
 	pub(super) fn send_spontaneous_payment<R: Deref, ES: Deref, NS: Deref, IH, SP, L: Deref>(
 		&self, payment_preimage: Option<PaymentPreimage>, recipient_onion: RecipientOnionFields,
 		payment_id: PaymentId, retry_strategy: Retry, route_params: RouteParameters, router: &R,
@@ -567,7 +721,7 @@ impl OutboundPayments {
 			payment_id, Some(preimage), &route, None, None, entropy_source, best_block_height)?;
 
 		match self.pay_route_internal(route, payment_hash, recipient_onion, Some(preimage),
-			payment_id, None, onion_session_privs, node_signer, best_block_height, &send_payment_along_path
+			payment_id, None, false, onion_session_privs, node_signer, best_block_height, &send_payment_along_path
 		) {
 			Ok(()) => Ok(payment_hash),
 			Err(e) => {
@@ -596,9 +750,13 @@ impl OutboundPayments {
 		let _single_thread = self.retry_lock.lock().unwrap();
 		loop {
 			let mut outbounds = self.pending_outbound_payments.lock().unwrap();
+			let pending_route_requests = self.pending_route_requests.lock().unwrap();
 			let mut retry_id_route_params = None;
 			for (pmt_id, pmt) in outbounds.iter_mut() {
-				if pmt.is_auto_retryable_now() {
+				// Payments waiting on a route are retried once the route has been found.
+				let awaiting_route = pending_route_requests.get(pmt_id)
+					.map_or(false, |request| !request.future.is_resolved());
+				if pmt.is_auto_retryable_now() && !awaiting_route {
 					if let PendingOutboundPayment::Retryable { pending_amt_msat, total_msat, payment_params: Some(params), payment_hash, .. } = pmt {
 						if pending_amt_msat < total_msat {
 							retry_id_route_params = Some((*payment_hash, *pmt_id, RouteParameters {
@@ -610,6 +768,7 @@ impl OutboundPayments {
 					} else { debug_assert!(false); }
 				}
 			}
+			core::mem::drop(pending_route_requests);
 			core::mem::drop(outbounds);
 			if let Some((payment_hash, payment_id, route_params)) = retry_id_route_params {
 				self.retry_payment_internal(payment_hash, payment_id, route_params, router, first_hops(), &inflight_htlcs, entropy_source, node_signer, best_block_height, logger, pending_events, &send_payment_along_path)
@@ -632,6 +791,46 @@ impl OutboundPayments {
 			}
 			retain
 		});
+		// Routes for payments which completed or were abandoned in the meantime are no longer needed.
+		self.pending_route_requests.lock().unwrap().retain(|pmt_id, _|
+			outbounds.get(pmt_id).map_or(false, |pmt| pmt.is_auto_retryable_now()));
+	}
+
+	/// Stops retrying any payments whose [`PaymentParameters::deadline_block_height`] or
+	/// [`PaymentParameters::deadline_time`] has been reached, generating an
+	/// [`Event::PaymentDeadlineExceeded`] for each.
+	///
+	/// [`Event::PaymentDeadlineExceeded`]: crate::events::Event::PaymentDeadlineExceeded
+	pub(super) fn abandon_payments_past_deadline<L: Deref>(
+		&self, best_block_height: u32,
+		pending_events: &Mutex<VecDeque<(events::Event, Option<EventCompletionAction>)>>, logger: &L
+	) where L::Target: Logger {
+		let mut outbounds = self.pending_outbound_payments.lock().unwrap();
+		let mut pending_events = pending_events.lock().unwrap();
+		outbounds.retain(|payment_id, payment| {
+			let deadline_exceeded_event = match payment {
+				PendingOutboundPayment::Retryable { payment_hash, payment_params: Some(params), .. } =>
+					deadline_exceeded(*payment_id, *payment_hash, params, best_block_height),
+				_ => None,
+			};
+			if let Some(deadline_exceeded_event) = deadline_exceeded_event {
+				log_info!(logger, "Payment deadline reached at height {}, abandoning payment {}",
+					best_block_height, log_bytes!(payment_id.0));
+				payment.mark_abandoned(PaymentFailureReason::PaymentExpired);
+				let (payment_hash, reason) = match payment {
+					PendingOutboundPayment::Abandoned { payment_hash, reason, .. } => (*payment_hash, *reason),
+					_ => { debug_assert!(false); return true },
+				};
+				pending_events.push_back((deadline_exceeded_event, None));
+				if payment.remaining_parts() == 0 {
+					pending_events.push_back((events::Event::PaymentFailed {
+						payment_id: *payment_id, payment_hash, reason,
+					}, None));
+					return false;
+				}
+			}
+			true
+		});
 	}
 
 	pub(super) fn needs_abandon(&self) -> bool {
@@ -666,6 +865,9 @@ impl OutboundPayments {
 				return Err(RetryableSendFailure::PaymentExpired)
 			}
 		}
+		if deadline_exceeded(payment_id, payment_hash, &route_params.payment_params, best_block_height).is_some() {
+			return Err(RetryableSendFailure::PaymentExpired)
+		}
 
 		let route = router.find_route_with_id(
 			&node_signer.get_node_id(Recipient::Node).unwrap(), &route_params,
@@ -679,7 +881,7 @@ impl OutboundPayments {
 			.map_err(|_| RetryableSendFailure::DuplicatePayment)?;
 
 		let res = self.pay_route_internal(&route, payment_hash, recipient_onion, None, payment_id, None,
-			onion_session_privs, node_signer, best_block_height, &send_payment_along_path);
+			false, onion_session_privs, node_signer, best_block_height, &send_payment_along_path);
 		log_info!(logger, "Result sending payment with id {}: {:?}", log_bytes!(payment_id.0), res);
 		if let Err(e) = res {
 			self.handle_pay_route_err(e, payment_id, payment_hash, route, route_params, router, first_hops, &inflight_htlcs, entropy_source, node_signer, best_block_height, logger, pending_events, &send_payment_along_path);
@@ -709,17 +911,71 @@ impl OutboundPayments {
 				return
 			}
 		}
+		if let Some(deadline_exceeded_event) = deadline_exceeded(payment_id, payment_hash, &route_params.payment_params, best_block_height) {
+			log_error!(logger, "Payment deadline reached on retry, abandoning payment {}", log_bytes!(payment_id.0));
+			pending_events.lock().unwrap().push_back((deadline_exceeded_event, None));
+			self.abandon_payment(payment_id, PaymentFailureReason::PaymentExpired, pending_events);
+			return
+		}
 
-		let route = match router.find_route_with_id(
-			&node_signer.get_node_id(Recipient::Node).unwrap(), &route_params,
-			Some(&first_hops.iter().collect::<Vec<_>>()), inflight_htlcs(),
-			payment_hash, payment_id,
-		) {
-			Ok(route) => route,
-			Err(e) => {
-				log_error!(logger, "Failed to find a route on retry, abandoning payment {}: {:#?}", log_bytes!(payment_id.0), e);
-				self.abandon_payment(payment_id, PaymentFailureReason::RouteNotFound, pending_events);
-				return
+		let pinned_route = match self.pending_outbound_payments.lock().unwrap().get(&payment_id) {
+			Some(PendingOutboundPayment::Retryable { pinned_route: Some(route), attempts, .. }) =>
+				Some((route.clone(), attempts.count + 1)),
+			_ => None,
+		};
+		let route = if let Some((pinned_route, attempt)) = pinned_route.as_ref() {
+			match pinned_paths_for_amount(pinned_route, route_params.final_value_msat) {
+				Some(mut route) => {
+					router.update_pinned_route(&mut route, payment_hash, payment_id, *attempt);
+					route
+				},
+				None => {
+					log_error!(logger, "Pinned route has no set of paths for the {} msat to retry, abandoning payment {}",
+						route_params.final_value_msat, log_bytes!(payment_id.0));
+					self.abandon_payment(payment_id, PaymentFailureReason::RouteNotFound, pending_events);
+					return
+				}
+			}
+		} else {
+			let pending_request = self.pending_route_requests.lock().unwrap().remove(&payment_id);
+			let resolved_result = match pending_request {
+				Some(request) => match request.future.take_result() {
+					Some(result) if request.route_params == route_params => Some(result),
+					Some(_) => {
+						log_debug!(logger, "Payment {} changed while finding a route to retry it, finding a new route",
+							log_bytes!(payment_id.0));
+						None
+					},
+					None => {
+						self.pending_route_requests.lock().unwrap().insert(payment_id, request);
+						return
+					},
+				},
+				None => None,
+			};
+			let result = match resolved_result {
+				Some(result) => result,
+				None => match router.find_route_async(
+					&node_signer.get_node_id(Recipient::Node).unwrap(), &route_params,
+					Some(&first_hops.iter().collect::<Vec<_>>()), inflight_htlcs(),
+					payment_hash, payment_id,
+				) {
+					RouteResult::Sync(result) => result,
+					RouteResult::Async(future) => {
+						log_trace!(logger, "Finding a route to retry payment {} asynchronously", log_bytes!(payment_id.0));
+						self.pending_route_requests.lock().unwrap().insert(payment_id,
+							PendingRouteRequest { route_params, future });
+						return
+					},
+				},
+			};
+			match result {
+				Ok(route) => route,
+				Err(e) => {
+					log_error!(logger, "Failed to find a route on retry, abandoning payment {}: {:#?}", log_bytes!(payment_id.0), e);
+					self.abandon_payment(payment_id, PaymentFailureReason::RouteNotFound, pending_events);
+					return
+				}
 			}
 		};
 		for path in route.paths.iter() {
@@ -753,11 +1009,16 @@ impl OutboundPayments {
 		}
 		let (total_msat, recipient_onion, keysend_preimage) = {
 			let mut outbounds = self.pending_outbound_payments.lock().unwrap();
+			let retried_value_in_flight_msat: u64 = outbounds.values().filter_map(|pmt| match pmt {
+				PendingOutboundPayment::Retryable { attempts, pending_amt_msat, .. } if attempts.count > 0 =>
+					Some(*pending_amt_msat),
+				_ => None,
+			}).sum();
 			match outbounds.entry(payment_id) {
 				hash_map::Entry::Occupied(mut payment) => {
 					let res = match payment.get() {
 						PendingOutboundPayment::Retryable {
-							total_msat, keysend_preimage, payment_secret, payment_metadata, pending_amt_msat, ..
+							total_msat, keysend_preimage, payment_secret, payment_metadata, custom_tlvs, pending_amt_msat, ..
 						} => {
 							let retry_amt_msat = route.get_total_amount();
 							if retry_amt_msat + *pending_amt_msat > *total_msat * (100 + RETRY_OVERFLOW_PERCENTAGE) / 100 {
@@ -768,6 +1029,7 @@ impl OutboundPayments {
 							(*total_msat, RecipientOnionFields {
 									payment_secret: *payment_secret,
 									payment_metadata: payment_metadata.clone(),
+									custom_tlvs: custom_tlvs.clone(),
 								}, *keysend_preimage)
 						},
 						PendingOutboundPayment::Legacy { .. } => {
@@ -788,6 +1050,15 @@ impl OutboundPayments {
 						abandon_with_entry!(payment, PaymentFailureReason::RetriesExhausted);
 						return
 					}
+					let retry_value_in_flight_msat = retried_value_in_flight_msat + route.get_total_amount();
+					if let Err(limit) = self.retry_budget.lock().unwrap()
+						.try_consume(retry_value_in_flight_msat, route.get_total_fees())
+					{
+						log_error!(logger, "Retrying payment id {} would exceed our retry budget for {}, abandoning payment",
+							log_bytes!(payment_id.0), limit);
+						abandon_with_entry!(payment, PaymentFailureReason::RetriesExhausted);
+						return
+					}
 					payment.get_mut().increment_attempts();
 					for (path, session_priv_bytes) in route.paths.iter().zip(onion_session_privs.iter()) {
 						assert!(payment.get_mut().insert(*session_priv_bytes, path));
@@ -801,9 +1072,15 @@ impl OutboundPayments {
 			}
 		};
 		let res = self.pay_route_internal(&route, payment_hash, recipient_onion, keysend_preimage,
-			payment_id, Some(total_msat), onion_session_privs, node_signer, best_block_height,
+			payment_id, Some(total_msat), false, onion_session_privs, node_signer, best_block_height,
 			&send_payment_along_path);
 		log_info!(logger, "Result retrying payment id {}: {:?}", log_bytes!(payment_id.0), res);
+		let sent_any = res.is_ok() || matches!(res, Err(PaymentSendFailure::PartialFailure { .. }));
+		if let (Some((_, attempt)), true) = (pinned_route, sent_any) {
+			pending_events.lock().unwrap().push_back((events::Event::PaymentAttemptSent {
+				payment_id, payment_hash, attempt: attempt as u32, route: route.clone(),
+			}, None));
+		}
 		if let Err(e) = res {
 			self.handle_pay_route_err(e, payment_id, payment_hash, route, route_params, router, first_hops, inflight_htlcs, entropy_source, node_signer, best_block_height, logger, pending_events, send_payment_along_path);
 		}
@@ -913,7 +1190,8 @@ impl OutboundPayments {
 			entropy_source, best_block_height)?;
 
 		match self.pay_route_internal(&route, payment_hash, RecipientOnionFields::spontaneous_empty(),
-			None, payment_id, None, onion_session_privs, node_signer, best_block_height, &send_payment_along_path
+			None, payment_id, None, true, onion_session_privs, node_signer, best_block_height,
+			&send_payment_along_path
 		) {
 			Ok(()) => Ok((payment_hash, payment_id)),
 			Err(e) => {
@@ -967,9 +1245,11 @@ impl OutboundPayments {
 					payment_hash,
 					payment_secret: recipient_onion.payment_secret,
 					payment_metadata: recipient_onion.payment_metadata,
+					custom_tlvs: recipient_onion.custom_tlvs,
 					keysend_preimage,
 					starting_block_height: best_block_height,
 					total_msat: route.get_total_amount(),
+					pinned_route: None,
 				});
 
 				for (path, session_priv_bytes) in route.paths.iter().zip(onion_session_privs.iter()) {
@@ -984,8 +1264,8 @@ impl OutboundPayments {
 	fn pay_route_internal<NS: Deref, F>(
 		&self, route: &Route, payment_hash: PaymentHash, recipient_onion: RecipientOnionFields,
 		keysend_preimage: Option<PaymentPreimage>, payment_id: PaymentId, recv_value_msat: Option<u64>,
-		onion_session_privs: Vec<[u8; 32]>, node_signer: &NS, best_block_height: u32,
-		send_payment_along_path: &F
+		allow_blinded_tails: bool, onion_session_privs: Vec<[u8; 32]>, node_signer: &NS,
+		best_block_height: u32, send_payment_along_path: &F
 	) -> Result<(), PaymentSendFailure>
 	where
 		NS::Target: NodeSigner,
@@ -1006,7 +1286,7 @@ impl OutboundPayments {
 				path_errs.push(Err(APIError::InvalidRoute{err: "Path didn't go anywhere/had bogus size".to_owned()}));
 				continue 'path_check;
 			}
-			if path.blinded_tail.is_some() {
+			if path.blinded_tail.is_some() && !allow_blinded_tails {
 				path_errs.push(Err(APIError::InvalidRoute{err: "Sending to blinded paths isn't supported yet".to_owned()}));
 				continue 'path_check;
 			}
@@ -1102,7 +1382,7 @@ impl OutboundPayments {
 			&Option<PaymentPreimage>, [u8; 32]) -> Result<(), APIError>
 	{
 		self.pay_route_internal(route, payment_hash, recipient_onion, keysend_preimage, payment_id,
-			recv_value_msat, onion_session_privs, node_signer, best_block_height,
+			recv_value_msat, false, onion_session_privs, node_signer, best_block_height,
 			&send_payment_along_path)
 			.map_err(|e| { self.remove_outbound_if_all_failed(payment_id, &e); e })
 	}
@@ -1185,6 +1465,10 @@ impl OutboundPayments {
 		}
 	}
 
+	pub(super) fn retry_budget_timer_tick_occurred(&self) {
+		self.retry_budget.lock().unwrap().timer_tick_occurred();
+	}
+
 	pub(super) fn remove_stale_resolved_payments(&self,
 		pending_events: &Mutex<VecDeque<(events::Event, Option<EventCompletionAction>)>>)
 	{
@@ -1379,6 +1663,28 @@ impl OutboundPayments {
 	}
 }
 
+/// The maximum number of paths in a pinned [`Route`] which we'll search through for a set of paths
+/// to retry over.
+const MAX_PINNED_ROUTE_PATHS: usize = 16;
+
+/// Selects paths from a pinned [`Route`] whose values sum to exactly `amount_msat`, returning
+/// `None` if no such selection exists.
+fn pinned_paths_for_amount(pinned_route: &Route, amount_msat: u64) -> Option<Route> {
+	let path_count = cmp::min(pinned_route.paths.len(), MAX_PINNED_ROUTE_PATHS);
+	for selection in 1..(1u32 << path_count) {
+		let selected_paths = pinned_route.paths.iter().take(path_count).enumerate()
+			.filter(|(idx, _)| selection & (1 << idx) != 0)
+			.map(|(_, path)| path);
+		if selected_paths.clone().map(|path| path.final_value_msat()).sum::<u64>() == amount_msat {
+			return Some(Route {
+				paths: selected_paths.cloned().collect(),
+				payment_params: pinned_route.payment_params.clone(),
+			});
+		}
+	}
+	None
+}
+
 /// Returns whether a payment with the given [`PaymentHash`] and [`PaymentId`] is, in fact, a
 /// payment probe.
 pub(super) fn payment_is_probe(payment_hash: &PaymentHash, payment_id: &PaymentId,
@@ -1417,7 +1723,9 @@ impl_writeable_tlv_based_enum_upgradable!(PendingOutboundPayment,
 		(6, total_msat, required),
 		(7, payment_metadata, option),
 		(8, pending_amt_msat, required),
+		(9, pinned_route, option),
 		(10, starting_block_height, required),
+		(11, custom_tlvs, optional_vec),
 		(not_written, retry_strategy, (static_value, None)),
 		(not_written, attempts, (static_value, PaymentAttempts::new())),
 	},
@@ -1435,17 +1743,19 @@ mod tests {
 
 	use crate::events::{Event, PathFailure, PaymentFailureReason};
 	use crate::ln::PaymentHash;
-	use crate::ln::channelmanager::{PaymentId, RecipientOnionFields};
+	use crate::ln::channelmanager::{ChannelDetails, PaymentId, RecipientOnionFields};
 	use crate::ln::features::{ChannelFeatures, NodeFeatures};
 	use crate::ln::msgs::{ErrorAction, LightningError};
-	use crate::ln::outbound_payment::{OutboundPayments, Retry, RetryableSendFailure};
+	use crate::ln::outbound_payment::{OutboundPayments, Retry, RetryableSendFailure, RetryBudgetTracker, RETRY_FEE_BUDGET_WINDOW_TICKS, pinned_paths_for_amount};
 	use crate::routing::gossip::NetworkGraph;
-	use crate::routing::router::{InFlightHtlcs, Path, PaymentParameters, Route, RouteHop, RouteParameters};
+	use crate::routing::router::{InFlightHtlcs, Path, PaymentParameters, Route, RouteFuture, RouteHop, RouteParameters, RouteResult, Router};
 	use crate::sync::{Arc, Mutex};
+	use crate::util::config::PaymentRetryBudget;
 	use crate::util::errors::APIError;
 	use crate::util::test_utils;
 
 	use alloc::collections::VecDeque;
+	use core::cell::Cell;
 
 	#[test]
 	#[cfg(feature = "std")]
@@ -1455,7 +1765,7 @@ mod tests {
 	}
 	#[cfg(feature = "std")]
 	fn do_fails_paying_after_expiration(on_retry: bool) {
-		let outbound_payments = OutboundPayments::new();
+		let outbound_payments = OutboundPayments::new(PaymentRetryBudget::default());
 		let logger = test_utils::TestLogger::new();
 		let network_graph = Arc::new(NetworkGraph::new(Network::Testnet, &logger));
 		let scorer = Mutex::new(test_utils::TestScorer::new());
@@ -1503,7 +1813,7 @@ mod tests {
 		do_find_route_error(true);
 	}
 	fn do_find_route_error(on_retry: bool) {
-		let outbound_payments = OutboundPayments::new();
+		let outbound_payments = OutboundPayments::new(PaymentRetryBudget::default());
 		let logger = test_utils::TestLogger::new();
 		let network_graph = Arc::new(NetworkGraph::new(Network::Testnet, &logger));
 		let scorer = Mutex::new(test_utils::TestScorer::new());
@@ -1546,7 +1856,7 @@ mod tests {
 
 	#[test]
 	fn initial_send_payment_path_failed_evs() {
-		let outbound_payments = OutboundPayments::new();
+		let outbound_payments = OutboundPayments::new(PaymentRetryBudget::default());
 		let logger = test_utils::TestLogger::new();
 		let network_graph = Arc::new(NetworkGraph::new(Network::Testnet, &logger));
 		let scorer = Mutex::new(test_utils::TestScorer::new());
@@ -1626,4 +1936,204 @@ mod tests {
 		} else { panic!("Unexpected event"); }
 		if let Event::PaymentFailed { .. } = events[1].0 { } else { panic!("Unexpected event"); }
 	}
+
+	#[test]
+	fn selects_pinned_paths_for_retry_amount() {
+		let secp_ctx = Secp256k1::new();
+		let receiver_pk = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[43; 32]).unwrap());
+		let path_for_amount = |scid: u64, amount_msat: u64| Path { hops: vec![RouteHop {
+			pubkey: receiver_pk,
+			node_features: NodeFeatures::empty(),
+			short_channel_id: scid,
+			channel_features: ChannelFeatures::empty(),
+			fee_msat: amount_msat,
+			cltv_expiry_delta: 0,
+		}], blinded_tail: None };
+		let pinned_route = Route {
+			paths: vec![path_for_amount(1, 1000), path_for_amount(2, 2000)],
+			payment_params: Some(PaymentParameters::from_node_id(receiver_pk, 0)),
+		};
+
+		let route = pinned_paths_for_amount(&pinned_route, 2000).unwrap();
+		assert_eq!(route.paths, vec![path_for_amount(2, 2000)]);
+		assert_eq!(route.payment_params, pinned_route.payment_params);
+
+		let route = pinned_paths_for_amount(&pinned_route, 3000).unwrap();
+		assert_eq!(route.paths, pinned_route.paths);
+
+		assert!(pinned_paths_for_amount(&pinned_route, 1500).is_none());
+		assert!(pinned_paths_for_amount(&pinned_route, 0).is_none());
+	}
+
+	#[test]
+	fn retry_budget_limits_value_and_fees() {
+		let mut tracker = RetryBudgetTracker::new(PaymentRetryBudget {
+			max_retry_value_in_flight_msat: Some(100_000),
+			max_retry_fees_msat_per_hour: Some(1_000),
+		});
+
+		assert!(tracker.try_consume(100_001, 0).is_err());
+		assert!(tracker.try_consume(100_000, 600).is_ok());
+		assert!(tracker.try_consume(50_000, 600).is_err());
+		assert!(tracker.try_consume(50_000, 400).is_ok());
+		assert!(tracker.try_consume(1, 1).is_err());
+
+		// Once the window passes, fees may be spent again.
+		for _ in 0..RETRY_FEE_BUDGET_WINDOW_TICKS - 1 { tracker.timer_tick_occurred(); }
+		assert!(tracker.try_consume(1, 1).is_err());
+		tracker.timer_tick_occurred();
+		assert!(tracker.try_consume(1, 1_000).is_ok());
+	}
+
+	#[test]
+	fn abandons_payments_past_deadline() {
+		let outbound_payments = OutboundPayments::new(PaymentRetryBudget::default());
+		let logger = test_utils::TestLogger::new();
+		let secp_ctx = Secp256k1::new();
+		let keys_manager = test_utils::TestKeysInterface::new(&[0; 32], Network::Testnet);
+
+		let payment_params = PaymentParameters::from_node_id(
+			PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap()), 0)
+			.with_deadline_block_height(100);
+		let pending_events = Mutex::new(VecDeque::new());
+		outbound_payments.add_new_pending_payment(PaymentHash([0; 32]), RecipientOnionFields::spontaneous_empty(),
+			PaymentId([0; 32]), None, &Route { paths: vec![], payment_params: None },
+			Some(Retry::Attempts(1)), Some(payment_params), &&keys_manager, 0).unwrap();
+
+		outbound_payments.abandon_payments_past_deadline(99, &pending_events, &&logger);
+		assert!(pending_events.lock().unwrap().is_empty());
+		assert!(outbound_payments.has_pending_payments());
+
+		outbound_payments.abandon_payments_past_deadline(100, &pending_events, &&logger);
+		let events = pending_events.lock().unwrap();
+		assert_eq!(events.len(), 2);
+		if let Event::PaymentDeadlineExceeded { deadline_block_height, deadline_time, .. } = events[0].0 {
+			assert_eq!(deadline_block_height, Some(100));
+			assert_eq!(deadline_time, None);
+		} else { panic!("Unexpected event"); }
+		if let Event::PaymentFailed { ref reason, .. } = events[1].0 {
+			assert_eq!(reason.unwrap(), PaymentFailureReason::PaymentExpired);
+		} else { panic!("Unexpected event"); }
+		assert!(!outbound_payments.has_pending_payments());
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn abandons_payment_past_deadline_time_on_retry() {
+		let outbound_payments = OutboundPayments::new(PaymentRetryBudget::default());
+		let logger = test_utils::TestLogger::new();
+		let network_graph = Arc::new(NetworkGraph::new(Network::Testnet, &logger));
+		let scorer = Mutex::new(test_utils::TestScorer::new());
+		let router = test_utils::TestRouter::new(network_graph, &scorer);
+		let secp_ctx = Secp256k1::new();
+		let keys_manager = test_utils::TestKeysInterface::new(&[0; 32], Network::Testnet);
+
+		let past_deadline_time = std::time::SystemTime::UNIX_EPOCH.elapsed().unwrap().as_secs() - 2;
+		let payment_params = PaymentParameters::from_node_id(
+				PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap()),
+				0
+			).with_deadline_time(past_deadline_time);
+		let route_params = RouteParameters { payment_params, final_value_msat: 0 };
+		let pending_events = Mutex::new(VecDeque::new());
+		outbound_payments.add_new_pending_payment(PaymentHash([0; 32]), RecipientOnionFields::spontaneous_empty(),
+			PaymentId([0; 32]), None, &Route { paths: vec![], payment_params: None },
+			Some(Retry::Attempts(1)), Some(route_params.payment_params.clone()),
+			&&keys_manager, 0).unwrap();
+		outbound_payments.retry_payment_internal(
+			PaymentHash([0; 32]), PaymentId([0; 32]), route_params, &&router, vec![],
+			&|| InFlightHtlcs::new(), &&keys_manager, &&keys_manager, 0, &&logger,
+			&pending_events, &|_, _, _, _, _, _, _, _| Ok(()));
+
+		let events = pending_events.lock().unwrap();
+		assert_eq!(events.len(), 2);
+		if let Event::PaymentDeadlineExceeded { deadline_block_height, deadline_time, .. } = events[0].0 {
+			assert_eq!(deadline_block_height, None);
+			assert_eq!(deadline_time, Some(past_deadline_time));
+		} else { panic!("Unexpected event"); }
+		if let Event::PaymentFailed { ref reason, .. } = events[1].0 {
+			assert_eq!(reason.unwrap(), PaymentFailureReason::PaymentExpired);
+		} else { panic!("Unexpected event"); }
+	}
+
+	/// A [`Router`] which always resolves asynchronously, handing out its [`RouteFuture`]s.
+	struct AsyncRouter {
+		futures: Mutex<Vec<RouteFuture>>,
+	}
+
+	impl Router for AsyncRouter {
+		fn find_route(
+			&self, _payer: &PublicKey, _route_params: &RouteParameters,
+			_first_hops: Option<&[&ChannelDetails]>, _inflight_htlcs: InFlightHtlcs
+		) -> Result<Route, LightningError> {
+			panic!("Retries should find routes asynchronously");
+		}
+
+		fn find_route_async(
+			&self, _payer: &PublicKey, _route_params: &RouteParameters,
+			_first_hops: Option<&[&ChannelDetails]>, _inflight_htlcs: InFlightHtlcs,
+			_payment_hash: PaymentHash, _payment_id: PaymentId
+		) -> RouteResult {
+			let future = RouteFuture::new();
+			self.futures.lock().unwrap().push(future.clone());
+			RouteResult::Async(future)
+		}
+	}
+
+	#[test]
+	fn retries_with_async_routes() {
+		let outbound_payments = OutboundPayments::new(PaymentRetryBudget::default());
+		let logger = test_utils::TestLogger::new();
+		let router = AsyncRouter { futures: Mutex::new(Vec::new()) };
+		let secp_ctx = Secp256k1::new();
+		let keys_manager = test_utils::TestKeysInterface::new(&[0; 32], Network::Testnet);
+		let pending_events = Mutex::new(VecDeque::new());
+		let payment_id = PaymentId([0; 32]);
+
+		let receiver_pk = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[42; 32]).unwrap());
+		let route = Route {
+			paths: vec![Path { hops: vec![RouteHop {
+				pubkey: receiver_pk,
+				node_features: NodeFeatures::empty(),
+				short_channel_id: 42,
+				channel_features: ChannelFeatures::empty(),
+				fee_msat: 1_000,
+				cltv_expiry_delta: 0,
+			}], blinded_tail: None }],
+			payment_params: None,
+		};
+		let session_privs = outbound_payments.add_new_pending_payment(PaymentHash([0; 32]),
+			RecipientOnionFields::spontaneous_empty(), payment_id, None, &route,
+			Some(Retry::Attempts(2)), Some(PaymentParameters::from_node_id(receiver_pk, 0)),
+			&&keys_manager, 0).unwrap();
+		// Fail the initial path, leaving the full amount to be retried.
+		assert!(outbound_payments.pending_outbound_payments.lock().unwrap().get_mut(&payment_id).unwrap()
+			.remove(&session_privs[0], Some(&route.paths[0])));
+
+		let paths_sent = Cell::new(0);
+		let check_retry_payments = || outbound_payments.check_retry_payments(&&router, || Vec::new(),
+			|| InFlightHtlcs::new(), &&keys_manager, &&keys_manager, 0, &pending_events, &&logger,
+			|_, _, _, _, _, _, _, _| { paths_sent.set(paths_sent.get() + 1); Ok(()) });
+
+		// While pathfinding is pending, the payment isn't retried and no further routes are requested.
+		check_retry_payments();
+		check_retry_payments();
+		assert_eq!(router.futures.lock().unwrap().len(), 1);
+		assert_eq!(paths_sent.get(), 0);
+
+		// If the payment changes while pathfinding, the now-stale route is discarded in favor of a
+		// new one.
+		outbound_payments.pending_outbound_payments.lock().unwrap().get_mut(&payment_id).unwrap()
+			.insert_previously_failed_scid(43);
+		router.futures.lock().unwrap()[0].resolve(Ok(route.clone()));
+		check_retry_payments();
+		assert_eq!(router.futures.lock().unwrap().len(), 2);
+		assert_eq!(paths_sent.get(), 0);
+
+		router.futures.lock().unwrap()[1].resolve(Ok(route.clone()));
+		check_retry_payments();
+		assert_eq!(router.futures.lock().unwrap().len(), 2);
+		assert_eq!(paths_sent.get(), 1);
+		assert!(pending_events.lock().unwrap().is_empty());
+		assert!(outbound_payments.pending_route_requests.lock().unwrap().is_empty());
+	}
 }
@@ -17,7 +17,7 @@ use crate::sign::EntropySource;
 use crate::chain::transaction::OutPoint;
 use crate::events::{ClosureReason, Event, HTLCDestination, MessageSendEvent, MessageSendEventsProvider, PathFailure, PaymentFailureReason};
 use crate::ln::channel::EXPIRE_PREV_CONFIG_TICKS;
-use crate::ln::channelmanager::{BREAKDOWN_TIMEOUT, ChannelManager, MPP_TIMEOUT_TICKS, MIN_CLTV_EXPIRY_DELTA, PaymentId, PaymentSendFailure, IDEMPOTENCY_TIMEOUT_TICKS, RecentPaymentDetails, RecipientOnionFields, HTLCForwardInfo, PendingHTLCRouting, PendingAddHTLCInfo};
+use crate::ln::channelmanager::{BREAKDOWN_TIMEOUT, ChannelManager, MPP_TIMEOUT_TICKS, MIN_CLTV_EXPIRY_DELTA, PaymentId, PaymentSendFailure, IDEMPOTENCY_TIMEOUT_TICKS, RecentPaymentDetails, OutboundPaymentDetails, ChannelDebugState, HTLCDirection, HTLCStage, RecipientOnionFields, HTLCForwardInfo, PendingHTLCRouting, PendingAddHTLCInfo, HTLCAcceptanceStats};
 use crate::ln::features::Bolt11InvoiceFeatures;
 use crate::ln::{msgs, PaymentSecret, PaymentPreimage};
 use crate::ln::msgs::ChannelMessageHandler;
@@ -27,7 +27,7 @@ use crate::routing::router::{get_route, Path, PaymentParameters, Route, Router,
 use crate::routing::scoring::ChannelUsage;
 use crate::util::test_utils;
 use crate::util::errors::APIError;
-use crate::util::ser::Writeable;
+use crate::util::ser::{Readable, Writeable};
 use crate::util::string::UntrustedString;
 
 use bitcoin::network::constants::Network;
@@ -236,6 +236,133 @@ fn mpp_receive_timeout() {
 	do_mpp_receive_timeout(false);
 }
 
+#[test]
+fn mpp_partial_claim() {
+	// Check that an incomplete MPP set can be surfaced as claimable on timeout if the recipient
+	// explicitly allowed a partial claim, and that the shortfall is reported once claimed.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	let (chan_1_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 0, 1);
+	let (chan_2_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 0, 2);
+	let (chan_3_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 1, 3);
+	let (chan_4_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 2, 3);
+
+	let (mut route, payment_hash, payment_preimage, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[3], 100_000);
+	let path = route.paths[0].clone();
+	route.paths.push(path);
+	route.paths[0].hops[0].pubkey = nodes[1].node.get_our_node_id();
+	route.paths[0].hops[0].short_channel_id = chan_1_update.contents.short_channel_id;
+	route.paths[0].hops[1].short_channel_id = chan_3_update.contents.short_channel_id;
+	route.paths[1].hops[0].pubkey = nodes[2].node.get_our_node_id();
+	route.paths[1].hops[0].short_channel_id = chan_2_update.contents.short_channel_id;
+	route.paths[1].hops[1].short_channel_id = chan_4_update.contents.short_channel_id;
+
+	nodes[3].node.allow_partial_claim(payment_hash, 100_000, u64::max_value());
+
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 2);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+
+	// Only deliver the first half of the payment.
+	let node_1_msgs = remove_first_msg_event_to_node(&nodes[1].node.get_our_node_id(), &mut events);
+	pass_along_path(&nodes[0], &[&nodes[1], &nodes[3]], 200_000, payment_hash, Some(payment_secret), node_1_msgs, false, None);
+
+	for _ in 0..MPP_TIMEOUT_TICKS {
+		nodes[3].node.timer_tick_occurred();
+	}
+	let events = nodes[3].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentClaimable { payment_hash: ev_payment_hash, amount_msat, .. } => {
+			assert_eq!(ev_payment_hash, payment_hash);
+			assert_eq!(amount_msat, 100_000);
+		},
+		_ => panic!("Unexpected event"),
+	}
+
+	// Further ticks must not fail the now-claimable partial set.
+	for _ in 0..MPP_TIMEOUT_TICKS {
+		nodes[3].node.timer_tick_occurred();
+	}
+	assert!(nodes[3].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[3].node.get_and_clear_pending_msg_events().is_empty());
+
+	nodes[3].node.claim_funds(payment_preimage);
+	let events = nodes[3].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentClaimed { payment_hash: ev_payment_hash, amount_msat, shortfall_msat, .. } => {
+			assert_eq!(ev_payment_hash, payment_hash);
+			assert_eq!(amount_msat, 100_000);
+			assert_eq!(shortfall_msat, Some(100_000));
+		},
+		_ => panic!("Unexpected event"),
+	}
+	check_added_monitors!(nodes[3], 1);
+	let updates = get_htlc_update_msgs!(nodes[3], nodes[1].node.get_our_node_id());
+	assert_eq!(updates.update_fulfill_htlcs.len(), 1);
+}
+
+#[test]
+fn mpp_partial_claim_allowance_removed() {
+	// Check that an allowance to claim an incomplete MPP set is dropped once the set is failed
+	// back for falling short of it, as well as once it expires without a set pending.
+	let chanmon_cfgs = create_chanmon_cfgs(4);
+	let node_cfgs = create_node_cfgs(4, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(4, &node_cfgs, &[None, None, None, None]);
+	let nodes = create_network(4, &node_cfgs, &node_chanmgrs);
+
+	let (chan_1_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 0, 1);
+	let (chan_2_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 0, 2);
+	let (chan_3_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 1, 3);
+	let (chan_4_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 2, 3);
+
+	let (mut route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[3], 100_000);
+	let path = route.paths[0].clone();
+	route.paths.push(path);
+	route.paths[0].hops[0].pubkey = nodes[1].node.get_our_node_id();
+	route.paths[0].hops[0].short_channel_id = chan_1_update.contents.short_channel_id;
+	route.paths[0].hops[1].short_channel_id = chan_3_update.contents.short_channel_id;
+	route.paths[1].hops[0].pubkey = nodes[2].node.get_our_node_id();
+	route.paths[1].hops[0].short_channel_id = chan_2_update.contents.short_channel_id;
+	route.paths[1].hops[1].short_channel_id = chan_4_update.contents.short_channel_id;
+
+	// Only allow claiming more than the half of the payment which will be delivered.
+	nodes[3].node.allow_partial_claim(payment_hash, 150_000, u64::max_value());
+
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 2);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 2);
+	let node_1_msgs = remove_first_msg_event_to_node(&nodes[1].node.get_our_node_id(), &mut events);
+	pass_along_path(&nodes[0], &[&nodes[1], &nodes[3]], 200_000, payment_hash, Some(payment_secret), node_1_msgs, false, None);
+
+	for _ in 0..MPP_TIMEOUT_TICKS {
+		nodes[3].node.timer_tick_occurred();
+	}
+	let failed_destination = HTLCDestination::FailedPayment { payment_hash };
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[3], vec![failed_destination]);
+	let updates = get_htlc_update_msgs!(nodes[3], nodes[1].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	check_added_monitors!(nodes[3], 1);
+	assert!(!nodes[3].node.disallow_partial_claim(&payment_hash));
+
+	// An allowance for a payment which never arrives expires along with its invoice.
+	let expiry_time = nodes[3].best_block_info().1 as u64 + 1;
+	nodes[3].node.allow_partial_claim(payment_hash, 100_000, expiry_time);
+	connect_blocks(&nodes[3], 1);
+	assert!(nodes[3].node.disallow_partial_claim(&payment_hash));
+	nodes[3].node.allow_partial_claim(payment_hash, 100_000, expiry_time);
+	connect_blocks(&nodes[3], 1);
+	assert!(!nodes[3].node.disallow_partial_claim(&payment_hash));
+}
+
 #[test]
 fn test_mpp_keysend() {
 	let mut mpp_keysend_config = test_default_channel_config();
@@ -1548,6 +1675,69 @@ fn test_holding_cell_inflight_htlcs() {
 	nodes[0].node.get_and_clear_pending_msg_events();
 }
 
+#[test]
+fn test_list_outbound_payments() {
+	// Test that in-flight payments are listed with the state of each of their parts, including
+	// parts still in the holding cell, and that abandoning a payment is reflected.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let channel_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	let (route, payment_hash_1, _, payment_secret_1) = get_route_and_payment_hash!(nodes[0], nodes[1], 1000000);
+	let (_, payment_hash_2, payment_secret_2) = get_payment_preimage_hash!(nodes[1]);
+	let payment_id_1 = PaymentId(payment_hash_1.0);
+	let payment_id_2 = PaymentId(payment_hash_2.0);
+	assert!(nodes[0].node.list_outbound_payments().is_empty());
+
+	// As in `test_holding_cell_inflight_htlcs`, the second payment goes into the holding cell.
+	nodes[0].node.send_payment_with_route(&route, payment_hash_1,
+		RecipientOnionFields::secret_only(payment_secret_1), payment_id_1).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	nodes[0].node.send_payment_with_route(&route, payment_hash_2,
+		RecipientOnionFields::secret_only(payment_secret_2), payment_id_2).unwrap();
+	check_added_monitors!(nodes[0], 0);
+
+	let find_payment = |payments: &Vec<OutboundPaymentDetails>, payment_id| {
+		payments.iter().find(|payment| payment.payment_id == payment_id).unwrap().clone()
+	};
+	let payments = nodes[0].node.list_outbound_payments();
+	assert_eq!(payments.len(), 2);
+	for (payment_id, payment_hash, stage) in [
+		(payment_id_1, payment_hash_1, Some(HTLCStage::AwaitingCommitment)), (payment_id_2, payment_hash_2, None)
+	].iter() {
+		let payment = find_payment(&payments, *payment_id);
+		assert_eq!(payment.payment_hash, *payment_hash);
+		assert_eq!(payment.total_msat, Some(1000000));
+		assert_eq!(payment.pending_msat, Some(1000000));
+		assert_eq!(payment.retry_count, 0);
+		assert!(!payment.is_auto_retryable);
+		assert!(!payment.is_abandoned);
+		assert!(payment.can_abandon);
+		assert_eq!(payment.num_pending_parts, 1);
+		assert_eq!(payment.parts.len(), 1);
+		let part = &payment.parts[0];
+		assert_eq!(part.channel_id, channel_id);
+		assert_eq!(part.counterparty_node_id, nodes[1].node.get_our_node_id());
+		assert_eq!(part.first_hop_short_channel_id, route.paths[0].hops[0].short_channel_id);
+		assert_eq!(part.amount_msat, 1000000);
+		assert_eq!(part.fee_msat, 0);
+		assert_eq!(part.stage, *stage);
+	}
+
+	// An abandoned payment remains listed until its parts resolve, but may not be abandoned again.
+	nodes[0].node.abandon_payment(payment_id_2);
+	let payment = find_payment(&nodes[0].node.list_outbound_payments(), payment_id_2);
+	assert!(payment.is_abandoned);
+	assert!(!payment.can_abandon);
+	assert_eq!(payment.total_msat, None);
+	assert_eq!(payment.parts.len(), 1);
+
+	// Clear pending events so test doesn't throw a "Had excess message on node..." error
+	nodes[0].node.get_and_clear_pending_msg_events();
+}
+
 #[test]
 fn intercepted_payment() {
 	// Test that detecting an intercept scid on payment forward will signal LDK to generate an
@@ -1863,6 +2053,196 @@ fn do_accept_underpaying_htlcs_config(num_mpp_parts: usize) {
 		Some(Some(total_fee_msat - skimmed_fee_msat * num_mpp_parts as u64)), true);
 }
 
+#[test]
+fn collects_channel_liquidity_fee() {
+	// Check that a liquidity fee set on a channel is skimmed from the HTLCs we forward over it, is
+	// skimmed again from a later HTLC if the one it was skimmed from fails, and is paid off once
+	// our counterparty claims.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let mut underpay_config = test_default_channel_config();
+	underpay_config.channel_config.accept_underpaying_htlcs = true;
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, Some(underpay_config)]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	let chan_id = create_announced_chan_between_nodes(&nodes, 1, 2).2;
+	let node_1_id = nodes[1].node.get_our_node_id();
+	let node_2_id = nodes[2].node.get_our_node_id();
+
+	// Only the node which opened the channel may charge for its liquidity.
+	match nodes[2].node.set_channel_liquidity_fee(&chan_id, &node_1_id, 1_000, 100_000) {
+		Err(APIError::APIMisuseError { .. }) => {},
+		_ => panic!("Unexpected result"),
+	}
+	nodes[1].node.set_channel_liquidity_fee(&chan_id, &node_2_id, 1_000, 100_000).unwrap();
+	assert_eq!(nodes[1].node.channel_liquidity_fee_owed(&chan_id, &node_2_id), Some(1_000));
+
+	// At most 10% of each HTLC is skimmed, so only 400 msat of the fee is taken from the first
+	// payment. Once it fails, the whole fee is taken from the next one.
+	let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[2], 4_000);
+	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[2]]], 3_600, payment_hash, payment_secret);
+	match nodes[1].node.set_channel_liquidity_fee(&chan_id, &node_2_id, 2_000, 100_000) {
+		Err(APIError::APIMisuseError { .. }) => {},
+		_ => panic!("Unexpected result"),
+	}
+	fail_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_hash);
+	assert_eq!(nodes[1].node.channel_liquidity_fee_owed(&chan_id, &node_2_id), Some(1_000));
+
+	let (route, payment_hash, payment_preimage, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[2], 20_000);
+	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[2]]], 19_000, payment_hash, payment_secret);
+	nodes[2].node.claim_funds(payment_preimage);
+	expect_payment_claimed!(nodes[2], payment_hash, 19_000);
+	check_added_monitors!(nodes[2], 1);
+
+	let updates = get_htlc_update_msgs!(nodes[2], node_1_id);
+	nodes[1].node.handle_update_fulfill_htlc(&node_2_id, &updates.update_fulfill_htlcs[0]);
+	check_added_monitors!(nodes[1], 1);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 2);
+	match events[0] {
+		Event::LiquidityFeeCollected { channel_id, counterparty_node_id, amount_msat, remaining_msat } => {
+			assert_eq!(channel_id, chan_id);
+			assert_eq!(counterparty_node_id, node_2_id);
+			assert_eq!(amount_msat, 1_000);
+			assert_eq!(remaining_msat, 0);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	match events[1] {
+		Event::PaymentForwarded { .. } => {},
+		_ => panic!("Unexpected event"),
+	}
+	assert_eq!(nodes[1].node.channel_liquidity_fee_owed(&chan_id, &node_2_id), Some(0));
+	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
+
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fulfill_htlc(&node_1_id, &updates.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_sent!(nodes[0], payment_preimage);
+
+	// With the fee paid off, later payments are forwarded in full.
+	let (route, payment_hash, payment_preimage, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[2], 20_000);
+	send_along_route_with_secret(&nodes[0], route, &[&[&nodes[1], &nodes[2]]], 20_000, payment_hash, payment_secret);
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage);
+}
+
+#[test]
+fn limits_dust_htlc_write_offs() {
+	// Check that dust HTLCs we forward count against the configured limit only while they're
+	// pending or once they've been lost to a force-close, and that dust forwards beyond the limit
+	// fail.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let mut limited_config = test_default_channel_config();
+	limited_config.max_dust_htlc_write_off_msat = Some(30_000);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, Some(limited_config), None]);
+	let nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+	let (chan_update, _, chan_id, _) = create_announced_chan_between_nodes(&nodes, 1, 2);
+	let node_1_id = nodes[1].node.get_our_node_id();
+	let node_2_id = nodes[2].node.get_our_node_id();
+
+	// HTLCs above the dust limit are never written off.
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 10_000_000).0;
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage);
+	assert_eq!(nodes[1].node.dust_htlcs_written_off_msat(&node_2_id), 0);
+
+	let pending_dust_forwards_msat = || nodes[1].node.list_channels().into_iter()
+		.find(|chan| chan.channel_id == chan_id).unwrap().pending_dust_forwards_msat;
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 20_000).0;
+	assert_eq!(pending_dust_forwards_msat(), 20_000);
+	assert_eq!(nodes[1].node.dust_htlcs_written_off_msat(&node_2_id), 0);
+
+	// Another 20_000 msat dust HTLC would put more than our limit at risk, so it's failed back.
+	let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[2], 20_000);
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1],
+		[HTLCDestination::NextHopChannel { node_id: Some(node_2_id), channel_id: chan_id }]);
+	check_added_monitors!(nodes[1], 1);
+
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fail_htlc(&node_1_id, &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_failed_conditions(&nodes[0], payment_hash, false,
+		PaymentFailedConditions::new().blamed_scid(chan_update.contents.short_channel_id).blamed_chan_closed(false));
+
+	// Once the pending dust HTLC is claimed it no longer counts against the limit, so the same
+	// dust HTLC may now be forwarded.
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage);
+	assert_eq!(pending_dust_forwards_msat(), 0);
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 20_000).0;
+	claim_payment(&nodes[0], &[&nodes[1], &nodes[2]], payment_preimage);
+	assert_eq!(nodes[1].node.dust_htlcs_written_off_msat(&node_2_id), 0);
+
+	// Dust HTLCs still pending when the channel is force-closed are written off.
+	route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 10_000);
+	assert_eq!(pending_dust_forwards_msat(), 10_000);
+	nodes[1].node.force_close_broadcasting_latest_txn(&chan_id, &node_2_id).unwrap();
+	check_closed_broadcast!(nodes[1], true);
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::HolderForceClosed);
+	assert_eq!(nodes[1].node.dust_htlcs_written_off_msat(&node_2_id), 10_000);
+	assert_eq!(nodes[1].node.dust_htlcs_written_off_msat(&nodes[0].node.get_our_node_id()), 0);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn limits_htlc_acceptance() {
+	// Check that new inbound HTLCs beyond our configured rate or number of pending HTLCs are failed
+	// back with `temporary_channel_failure`, and that both limits are reported in our stats.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut limited_config = test_default_channel_config();
+	limited_config.htlc_acceptance_limits.max_htlcs_per_second = Some(2);
+	limited_config.htlc_acceptance_limits.max_pending_htlcs = Some(1);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(limited_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let (chan_update, _, _, _) = create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let send_and_expect_temporary_failure = || {
+		let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[1], 100_000);
+		nodes[0].node.send_payment_with_route(&route, payment_hash,
+			RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+		check_added_monitors!(nodes[0], 1);
+		let payment_event = SendEvent::from_node(&nodes[0]);
+		nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+		check_added_monitors!(nodes[1], 0);
+		commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false, true);
+
+		let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+		assert_eq!(updates.update_fail_htlcs.len(), 1);
+		nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+		commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+		expect_payment_failed_conditions(&nodes[0], payment_hash, false, PaymentFailedConditions::new()
+			.blamed_scid(chan_update.contents.short_channel_id).blamed_chan_closed(false));
+	};
+
+	// While one HTLC is pending, further ones are failed.
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1]], 100_000).0;
+	send_and_expect_temporary_failure();
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+
+	// Once our burst of two HTLCs per second is used up, further ones are failed until time passes.
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1]], 100_000).0;
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+	send_and_expect_temporary_failure();
+
+	SinceEpoch::advance(Duration::from_millis(500));
+	let payment_preimage = route_payment(&nodes[0], &[&nodes[1]], 100_000).0;
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+
+	assert_eq!(nodes[1].node.htlc_acceptance_stats(), HTLCAcceptanceStats {
+		accepted: 3, rate_limited: 1, pending_limited: 1,
+	});
+}
+
 #[derive(PartialEq)]
 enum AutoRetry {
 	Success,
@@ -3366,7 +3746,7 @@ fn do_test_payment_metadata_consistency(do_reload: bool, do_modify: bool) {
 
 	// Send the MPP payment, delivering the updated commitment state to nodes[1].
 	nodes[0].node.send_payment(payment_hash, RecipientOnionFields {
-			payment_secret: Some(payment_secret), payment_metadata: Some(payment_metadata),
+			payment_secret: Some(payment_secret), payment_metadata: Some(payment_metadata), custom_tlvs: Vec::new(),
 		}, payment_id, route_params.clone(), Retry::Attempts(1)).unwrap();
 	check_added_monitors!(nodes[0], 2);
 
@@ -3474,3 +3854,261 @@ fn test_payment_metadata_consistency() {
 	do_test_payment_metadata_consistency(false, true);
 	do_test_payment_metadata_consistency(false, false);
 }
+
+#[test]
+fn test_list_and_fail_back_stuck_forwarded_htlc() {
+	// Check that a forwarding node can list its in-flight HTLCs and manually fail back an inbound
+	// HTLC whose outbound leg is stuck, without double-failing once the outbound leg resolves,
+	// even across a restart.
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let persister: test_utils::TestPersister;
+	let new_chain_monitor: test_utils::TestChainMonitor;
+	let nodes_1_deserialized: ChannelManager<&test_utils::TestChainMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestRouter, &test_utils::TestLogger>;
+	let mut nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+
+	let chan_1_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+	let chan_2_id = create_announced_chan_between_nodes(&nodes, 1, 2).2;
+
+	let (_, payment_hash, _) = route_payment(&nodes[0], &[&nodes[1], &nodes[2]], 100_000);
+
+	let mut htlcs = nodes[1].node.list_in_flight_htlcs();
+	htlcs.sort_by_key(|htlc| htlc.direction == HTLCDirection::Outbound);
+	assert_eq!(htlcs.len(), 2);
+	assert_eq!(htlcs[0].channel_id, chan_1_id);
+	assert_eq!(htlcs[0].direction, HTLCDirection::Inbound);
+	assert!(!htlcs[0].is_forward);
+	assert_eq!(htlcs[1].channel_id, chan_2_id);
+	assert_eq!(htlcs[1].direction, HTLCDirection::Outbound);
+	assert!(htlcs[1].is_forward);
+	for htlc in htlcs.iter() {
+		assert_eq!(htlc.stage, HTLCStage::Committed);
+		assert_eq!(htlc.payment_hash, payment_hash);
+		assert_eq!(htlc.age_timer_ticks, 0);
+	}
+
+	nodes[1].node.timer_tick_occurred();
+	assert!(nodes[1].node.list_in_flight_htlcs().iter().all(|htlc| htlc.age_timer_ticks == 1));
+
+	// Inbound HTLCs cannot be failed back as forwards.
+	assert!(nodes[1].node.fail_forwarded_htlc(&chan_1_id, &nodes[0].node.get_our_node_id(), htlcs[0].htlc_id).is_err());
+
+	nodes[1].node.fail_forwarded_htlc(&chan_2_id, &nodes[2].node.get_our_node_id(), htlcs[1].htlc_id).unwrap();
+	assert!(nodes[1].node.fail_forwarded_htlc(&chan_2_id, &nodes[2].node.get_our_node_id(), htlcs[1].htlc_id).is_err());
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1],
+		vec![HTLCDestination::NextHopChannel { node_id: Some(nodes[2].node.get_our_node_id()), channel_id: chan_2_id }]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+	expect_payment_failed_conditions(&nodes[0], payment_hash, false,
+		PaymentFailedConditions::new().expected_htlc_error_data(0x2000 | 2, &[]));
+
+	// Reload nodes[1] to check that it still remembers having failed the inbound HTLC back.
+	let node_1_serialized = nodes[1].node.encode();
+	let chan_1_monitor_serialized = get_monitor!(nodes[1], chan_1_id).encode();
+	let chan_2_monitor_serialized = get_monitor!(nodes[1], chan_2_id).encode();
+	reload_node!(nodes[1], node_1_serialized, &[&chan_1_monitor_serialized, &chan_2_monitor_serialized], persister, new_chain_monitor, nodes_1_deserialized);
+	nodes[0].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+	nodes[2].node.peer_disconnected(&nodes[1].node.get_our_node_id());
+	reconnect_nodes(&nodes[0], &nodes[1], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+	reconnect_nodes(&nodes[1], &nodes[2], (false, false), (0, 0), (0, 0), (0, 0), (0, 0), (0, 0), (false, false));
+	assert!(nodes[1].node.fail_forwarded_htlc(&chan_2_id, &nodes[2].node.get_our_node_id(), htlcs[1].htlc_id).is_err());
+
+	// Once the stuck outbound HTLC fails, we must not fail the inbound HTLC back again.
+	nodes[2].node.fail_htlc_backwards(&payment_hash);
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[2], vec![HTLCDestination::FailedPayment { payment_hash }]);
+	check_added_monitors!(nodes[2], 1);
+	let updates = get_htlc_update_msgs!(nodes[2], nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_update_fail_htlc(&nodes[2].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[1], nodes[2], updates.commitment_signed, false);
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	assert!(nodes[1].node.list_in_flight_htlcs().is_empty());
+}
+
+#[test]
+#[cfg(feature = "htlc_timeline_events")]
+fn test_htlc_timeline_events() {
+	// Check that both ends of a channel surface the lifecycle of an HTLC as `HTLCTimeline` events.
+	use crate::events::HTLCTimelineStage;
+	use crate::ln::PaymentHash;
+	use crate::util::time::TimeSource;
+
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	// Timestamps are taken from the injected time source rather than the system clock.
+	let time_source = test_utils::TestTimeSource::new();
+	time_source.advance(Duration::from_secs(42));
+	nodes[0].node.set_htlc_timeline_time_source(time_source.clone());
+	nodes[1].node.set_htlc_timeline_time_source(time_source.clone());
+
+	let timeline = |node: &Node| -> Vec<(u64, HTLCDirection, PaymentHash, HTLCTimelineStage)> {
+		node.node.get_and_clear_pending_events().into_iter().filter_map(|event| match event {
+			Event::HTLCTimeline { channel_id, htlc_id, direction, payment_hash, stage, timestamp } => {
+				assert_eq!(channel_id, chan_id);
+				assert_eq!(timestamp, time_source.now());
+				Some((htlc_id, direction, payment_hash, stage))
+			},
+			_ => None,
+		}).collect()
+	};
+
+	let (route, payment_hash, payment_preimage, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[1], 100_000);
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let payment_event = SendEvent::from_node(&nodes[0]);
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], payment_event.commitment_msg, false);
+
+	assert_eq!(timeline(&nodes[0]), vec![
+		(0, HTLCDirection::Outbound, payment_hash, HTLCTimelineStage::Added),
+		(0, HTLCDirection::Outbound, payment_hash, HTLCTimelineStage::Committed),
+	]);
+	assert_eq!(timeline(&nodes[1]), vec![
+		(0, HTLCDirection::Inbound, payment_hash, HTLCTimelineStage::Added),
+		(0, HTLCDirection::Inbound, payment_hash, HTLCTimelineStage::Committed),
+	]);
+
+	nodes[1].node.process_pending_htlc_forwards();
+	assert!(timeline(&nodes[1]).is_empty());
+	time_source.advance(Duration::from_secs(1));
+	nodes[1].node.claim_funds(payment_preimage);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	nodes[0].node.handle_update_fulfill_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fulfill_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+
+	assert_eq!(timeline(&nodes[0]), vec![(0, HTLCDirection::Outbound, payment_hash, HTLCTimelineStage::Fulfilled)]);
+	assert_eq!(timeline(&nodes[1]), vec![(0, HTLCDirection::Inbound, payment_hash, HTLCTimelineStage::Fulfilled)]);
+}
+
+#[test]
+fn test_export_channel_debug() {
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	assert!(nodes[0].node.export_channel_debug(&[42; 32]).is_none());
+
+	let (payment_preimage, payment_hash, _) = route_payment(&nodes[0], &[&nodes[1]], 100_000);
+	let state = nodes[0].node.export_channel_debug(&chan_id).unwrap();
+	assert_eq!(state.channel_id, chan_id);
+	assert_eq!(state.counterparty_node_id, nodes[1].node.get_our_node_id());
+	assert!(state.is_outbound);
+	assert_eq!(state.next_holder_htlc_id, 1);
+	assert_eq!(state.next_counterparty_htlc_id, 0);
+	assert_eq!(state.holding_cell_htlc_adds + state.holding_cell_htlc_removals, 0);
+	assert!(state.pending_update_fee.is_none());
+	assert_eq!(state.pending_htlcs.len(), 1);
+	assert_eq!(state.pending_htlcs[0].direction, HTLCDirection::Outbound);
+	assert_eq!(state.pending_htlcs[0].stage, HTLCStage::Committed);
+	assert_eq!(state.pending_htlcs[0].payment_hash, payment_hash);
+
+	let encoded = state.encode();
+	assert_eq!(ChannelDebugState::read(&mut &encoded[..]).unwrap(), state);
+
+	// The counterparty's view mirrors ours, and never includes the preimage once it's claimed.
+	nodes[1].node.claim_funds(payment_preimage);
+	expect_payment_claimed!(nodes[1], payment_hash, 100_000);
+	check_added_monitors!(nodes[1], 1);
+	let state = nodes[1].node.export_channel_debug(&chan_id).unwrap();
+	assert!(!state.is_outbound);
+	assert_eq!(state.next_counterparty_htlc_id, 1);
+	assert_eq!(state.pending_htlcs[0].direction, HTLCDirection::Inbound);
+	assert_eq!(state.pending_htlcs[0].stage, HTLCStage::AwaitingRemoval);
+	assert!(!format!("{}", state).contains(&log_bytes!(payment_preimage.0).to_string()));
+	get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+}
+
+#[test]
+fn held_inbound_htlcs() {
+	// Test that HTLCs paying us are held for validation when configured to, only becoming
+	// claimable once accepted, and that HTLCs no decision is made for are failed back.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let mut hold_config = test_default_channel_config();
+	hold_config.inbound_htlc_validation_config.hold_inbound_htlcs = true;
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, Some(hold_config)]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let amt_msat = 100_000;
+	let custom_tlvs = vec![(65537, vec![1, 2, 3])];
+	let (route, payment_hash, payment_preimage, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[1], amt_msat);
+	let onion_fields = RecipientOnionFields::secret_only(payment_secret).with_custom_tlvs(custom_tlvs.clone()).unwrap();
+	nodes[0].node.send_payment_with_route(&route, payment_hash, onion_fields, PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], &payment_event.commitment_msg, false, true);
+
+	// The HTLC is held rather than queued for forwarding, with the custom TLVs surfaced.
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	let hold_id = match events[0] {
+		Event::HTLCHeldForValidation { hold_id, payment_hash: held_payment_hash, amount_msat, ref onion_fields, .. } => {
+			assert_eq!(held_payment_hash, payment_hash);
+			assert_eq!(amount_msat, amt_msat);
+			assert_eq!(onion_fields.payment_secret, Some(payment_secret));
+			assert_eq!(onion_fields.custom_tlvs(), &custom_tlvs);
+			hold_id
+		},
+		_ => panic!("Unexpected event"),
+	};
+
+	nodes[1].node.accept_held_htlc(hold_id).unwrap();
+	assert!(nodes[1].node.accept_held_htlc(hold_id).is_err());
+	expect_pending_htlcs_forwardable!(nodes[1]);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		Event::PaymentClaimable { payment_hash: claimable_payment_hash, ref onion_fields, .. } => {
+			assert_eq!(claimable_payment_hash, payment_hash);
+			assert_eq!(onion_fields.as_ref().unwrap().custom_tlvs(), &custom_tlvs);
+		},
+		_ => panic!("Unexpected event"),
+	}
+	claim_payment(&nodes[0], &[&nodes[1]], payment_preimage);
+
+	// A held HTLC we never decide on is failed back once the decision timeout elapses.
+	let (route, payment_hash, _, payment_secret) = get_route_and_payment_hash!(nodes[0], nodes[1], amt_msat);
+	nodes[0].node.send_payment_with_route(&route, payment_hash,
+		RecipientOnionFields::secret_only(payment_secret), PaymentId(payment_hash.0)).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let mut events = nodes[0].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	let payment_event = SendEvent::from_event(events.remove(0));
+	nodes[1].node.handle_update_add_htlc(&nodes[0].node.get_our_node_id(), &payment_event.msgs[0]);
+	commitment_signed_dance!(nodes[1], nodes[0], &payment_event.commitment_msg, false, true);
+	let events = nodes[1].node.get_and_clear_pending_events();
+	assert_eq!(events.len(), 1);
+	assert!(matches!(events[0], Event::HTLCHeldForValidation { .. }));
+
+	let decision_timeout_ticks = hold_config.inbound_htlc_validation_config.decision_timeout_ticks;
+	for _ in 0..decision_timeout_ticks - 1 {
+		nodes[1].node.timer_tick_occurred();
+	}
+	assert!(nodes[1].node.get_and_clear_pending_events().is_empty());
+	nodes[1].node.timer_tick_occurred();
+	expect_pending_htlcs_forwardable_and_htlc_handling_failed!(nodes[1], vec![HTLCDestination::FailedPayment { payment_hash }]);
+	check_added_monitors!(nodes[1], 1);
+	let updates = get_htlc_update_msgs!(nodes[1], nodes[0].node.get_our_node_id());
+	assert_eq!(updates.update_fail_htlcs.len(), 1);
+	nodes[0].node.handle_update_fail_htlc(&nodes[1].node.get_our_node_id(), &updates.update_fail_htlcs[0]);
+	commitment_signed_dance!(nodes[0], nodes[1], updates.commitment_signed, false);
+
+	let mut expected_err_data = amt_msat.to_be_bytes().to_vec();
+	expected_err_data.extend_from_slice(&nodes[1].best_block_info().1.to_be_bytes());
+	expect_payment_failed!(nodes[0], payment_hash, true, 0x4000 | 15, expected_err_data);
+}
@@ -0,0 +1,273 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Allow and deny rules deciding which peers we accept connections, channel opens and onion
+//! messages from.
+//!
+//! Rules are managed at runtime via the [`PeerManager`], which consults them whenever a peer
+//! connects, proposes a channel, or sends us an onion message, and can be persisted via
+//! [`PeerManager::persist_access_rules`].
+//!
+//! [`PeerManager`]: crate::ln::peer_handler::PeerManager
+//! [`PeerManager::persist_access_rules`]: crate::ln::peer_handler::PeerManager::persist_access_rules
+
+use bitcoin::secp256k1::PublicKey;
+
+use crate::prelude::*;
+
+/// The key under which [`PeerManager::persist_access_rules`] persists [`PeerAccessRules`].
+///
+/// [`PeerManager::persist_access_rules`]: crate::ln::peer_handler::PeerManager::persist_access_rules
+pub const PEER_ACCESS_RULES_PERSISTENCE_KEY: &str = "peer_access_rules";
+
+/// The peers a [`PeerAccessRule`] applies to.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PeerAccessTarget {
+	/// Only the peer with the given node id.
+	Node(PublicKey),
+	/// All peers, e.g. to deny everyone not explicitly allowed by a higher priority rule.
+	AnyNode,
+}
+
+impl PeerAccessTarget {
+	fn matches(&self, node_id: &PublicKey) -> bool {
+		match self {
+			PeerAccessTarget::Node(target_node_id) => target_node_id == node_id,
+			PeerAccessTarget::AnyNode => true,
+		}
+	}
+}
+
+impl_writeable_tlv_based_enum!(PeerAccessTarget,
+	(0, AnyNode) => {},
+	;
+	(2, Node),
+);
+
+/// What a [`PeerAccessRule`] controls access to.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum PeerAccessScope {
+	/// Establishing a connection, whether inbound or outbound.
+	Connection,
+	/// Proposing a new channel to us.
+	ChannelOpen,
+	/// Sending us onion messages, whether destined for us or to be forwarded.
+	OnionMessage,
+	/// All of the above.
+	All,
+}
+
+impl PeerAccessScope {
+	fn covers(&self, scope: PeerAccessScope) -> bool {
+		*self == PeerAccessScope::All || *self == scope
+	}
+}
+
+impl_writeable_tlv_based_enum!(PeerAccessScope,
+	(0, Connection) => {},
+	(2, ChannelOpen) => {},
+	(4, OnionMessage) => {},
+	(6, All) => {},
+;);
+
+/// Whether a [`PeerAccessRule`] allows or denies access.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PeerAccessAction {
+	/// Access is allowed.
+	Allow,
+	/// Access is denied.
+	Deny,
+}
+
+impl_writeable_tlv_based_enum!(PeerAccessAction,
+	(0, Allow) => {},
+	(2, Deny) => {},
+;);
+
+/// A rule allowing or denying peers access to our node.
+///
+/// Of the unexpired rules applying to a peer and scope, the one with the highest
+/// [`Self::priority`] decides whether access is allowed, with [`PeerAccessAction::Deny`] winning
+/// ties. Peers which no rule applies to are allowed.
+///
+/// Thus, an allowlist can be built by denying [`PeerAccessTarget::AnyNode`] at a low priority and
+/// allowing individual peers at a higher one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerAccessRule {
+	/// The peers the rule applies to.
+	pub target: PeerAccessTarget,
+	/// What the rule controls access to.
+	pub scope: PeerAccessScope,
+	/// Whether the rule allows or denies access.
+	pub action: PeerAccessAction,
+	/// The priority of the rule relative to other rules applying to the same peer and scope.
+	pub priority: u32,
+	/// The time, in seconds since the UNIX epoch, after which the rule no longer applies, if any.
+	///
+	/// Expiries are not enforced in `no-std` builds, where rules apply until they are removed.
+	pub expires_at: Option<u64>,
+}
+
+impl PeerAccessRule {
+	fn is_expired(&self, now: u64) -> bool {
+		// `no-std` builds have no clock, so `now` is always 0 there.
+		self.expires_at.map_or(false, |expires_at| now != 0 && expires_at <= now)
+	}
+}
+
+impl_writeable_tlv_based!(PeerAccessRule, {
+	(0, target, required),
+	(2, scope, required),
+	(4, action, required),
+	(6, priority, required),
+	(8, expires_at, option),
+});
+
+/// A set of [`PeerAccessRule`]s, as persisted via [`PeerManager::persist_access_rules`].
+///
+/// At most one rule exists for each combination of [`PeerAccessRule::target`] and
+/// [`PeerAccessRule::scope`]. Once read, the rules can be handed to a new [`PeerManager`] via
+/// [`PeerManager::set_access_rules`].
+///
+/// [`PeerManager`]: crate::ln::peer_handler::PeerManager
+/// [`PeerManager::persist_access_rules`]: crate::ln::peer_handler::PeerManager::persist_access_rules
+/// [`PeerManager::set_access_rules`]: crate::ln::peer_handler::PeerManager::set_access_rules
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerAccessRules {
+	rules: Vec<PeerAccessRule>,
+}
+
+impl PeerAccessRules {
+	/// Adds `rule`, replacing any existing rule with the same target and scope.
+	pub fn add_rule(&mut self, rule: PeerAccessRule) {
+		self.remove_rule(&rule.target, rule.scope);
+		self.rules.push(rule);
+	}
+
+	/// Removes the rule with the given target and scope, returning whether one existed.
+	pub fn remove_rule(&mut self, target: &PeerAccessTarget, scope: PeerAccessScope) -> bool {
+		let rules_len = self.rules.len();
+		self.rules.retain(|rule| rule.target != *target || rule.scope != scope);
+		self.rules.len() != rules_len
+	}
+
+	/// Returns all rules, including any which have expired but were not yet pruned.
+	pub fn rules(&self) -> &[PeerAccessRule] {
+		&self.rules
+	}
+
+	/// Returns whether the peer with the given node id is allowed access in the given `scope` at
+	/// time `now`, in seconds since the UNIX epoch.
+	pub fn is_allowed(&self, node_id: &PublicKey, scope: PeerAccessScope, now: u64) -> bool {
+		let mut deciding_rule: Option<&PeerAccessRule> = None;
+		for rule in self.rules.iter() {
+			if !rule.target.matches(node_id) || !rule.scope.covers(scope) || rule.is_expired(now) {
+				continue;
+			}
+			deciding_rule = match deciding_rule {
+				Some(current) if current.priority > rule.priority => Some(current),
+				Some(current) if current.priority == rule.priority && current.action == PeerAccessAction::Deny => Some(current),
+				_ => Some(rule),
+			};
+		}
+		deciding_rule.map_or(true, |rule| rule.action == PeerAccessAction::Allow)
+	}
+
+	/// Removes all rules which have expired as of `now`, in seconds since the UNIX epoch,
+	/// returning whether any were removed.
+	pub fn prune_expired(&mut self, now: u64) -> bool {
+		let rules_len = self.rules.len();
+		self.rules.retain(|rule| !rule.is_expired(now));
+		self.rules.len() != rules_len
+	}
+}
+
+impl_writeable_tlv_based!(PeerAccessRules, {
+	(0, rules, required_vec),
+});
+
+#[cfg(test)]
+mod tests {
+	use super::{PeerAccessAction, PeerAccessRule, PeerAccessRules, PeerAccessScope, PeerAccessTarget};
+	use crate::util::ser::{Readable, Writeable};
+
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+	fn node_id(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	fn rule(target: PeerAccessTarget, scope: PeerAccessScope, action: PeerAccessAction, priority: u32) -> PeerAccessRule {
+		PeerAccessRule { target, scope, action, priority, expires_at: None }
+	}
+
+	#[test]
+	fn evaluates_rules_by_priority() {
+		let (alice, bob) = (node_id(1), node_id(2));
+		let mut rules = PeerAccessRules::default();
+		assert!(rules.is_allowed(&alice, PeerAccessScope::Connection, 100));
+
+		// Build an allowlist for channel opens only.
+		rules.add_rule(rule(PeerAccessTarget::AnyNode, PeerAccessScope::ChannelOpen, PeerAccessAction::Deny, 0));
+		rules.add_rule(rule(PeerAccessTarget::Node(alice), PeerAccessScope::ChannelOpen, PeerAccessAction::Allow, 10));
+		assert!(rules.is_allowed(&alice, PeerAccessScope::ChannelOpen, 100));
+		assert!(!rules.is_allowed(&bob, PeerAccessScope::ChannelOpen, 100));
+		assert!(rules.is_allowed(&bob, PeerAccessScope::Connection, 100));
+
+		// Denies win ties with allows of the same priority.
+		rules.add_rule(rule(PeerAccessTarget::Node(alice), PeerAccessScope::All, PeerAccessAction::Deny, 10));
+		assert!(!rules.is_allowed(&alice, PeerAccessScope::ChannelOpen, 100));
+		assert!(!rules.is_allowed(&alice, PeerAccessScope::OnionMessage, 100));
+
+		// Adding a rule with the same target and scope replaces the existing one.
+		rules.add_rule(rule(PeerAccessTarget::Node(alice), PeerAccessScope::All, PeerAccessAction::Deny, 5));
+		assert_eq!(rules.rules().len(), 3);
+		assert!(rules.is_allowed(&alice, PeerAccessScope::ChannelOpen, 100));
+		assert!(!rules.is_allowed(&alice, PeerAccessScope::OnionMessage, 100));
+
+		assert!(rules.remove_rule(&PeerAccessTarget::Node(alice), PeerAccessScope::All));
+		assert!(!rules.remove_rule(&PeerAccessTarget::Node(alice), PeerAccessScope::All));
+		assert!(rules.is_allowed(&alice, PeerAccessScope::OnionMessage, 100));
+	}
+
+	#[test]
+	fn expired_rules_no_longer_apply() {
+		let alice = node_id(1);
+		let mut rules = PeerAccessRules::default();
+		rules.add_rule(PeerAccessRule {
+			target: PeerAccessTarget::Node(alice),
+			scope: PeerAccessScope::All,
+			action: PeerAccessAction::Deny,
+			priority: 0,
+			expires_at: Some(200),
+		});
+		assert!(!rules.is_allowed(&alice, PeerAccessScope::Connection, 199));
+		assert!(rules.is_allowed(&alice, PeerAccessScope::Connection, 200));
+
+		assert!(!rules.prune_expired(199));
+		assert!(rules.prune_expired(200));
+		assert!(rules.rules().is_empty());
+	}
+
+	#[test]
+	fn rules_serialization_roundtrip() {
+		let mut rules = PeerAccessRules::default();
+		rules.add_rule(rule(PeerAccessTarget::AnyNode, PeerAccessScope::OnionMessage, PeerAccessAction::Deny, 1));
+		rules.add_rule(PeerAccessRule {
+			target: PeerAccessTarget::Node(node_id(1)),
+			scope: PeerAccessScope::All,
+			action: PeerAccessAction::Allow,
+			priority: 2,
+			expires_at: Some(1_700_000_000),
+		});
+		let encoded = rules.encode();
+		let decoded: PeerAccessRules = Readable::read(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded, rules);
+	}
+}
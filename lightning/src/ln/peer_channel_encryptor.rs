@@ -41,7 +41,9 @@ const NOISE_H: [u8; 32] = [0xd1, 0xfb, 0xf6, 0xde, 0xe4, 0xf6, 0x86, 0xf1, 0x32,
 
 enum NoiseSecretKey<'a, 'b, NS: Deref> where NS::Target: NodeSigner {
 	InMemory(&'a SecretKey),
-	NodeSigner(&'b NS)
+	NodeSigner(&'b NS),
+	/// The node secret we rotated away from, see [`NodeSigner::get_previous_node_id`].
+	PreviousNodeSigner(&'b NS),
 }
 
 pub enum NextNoiseStep {
@@ -118,11 +120,7 @@ impl PeerChannelEncryptor {
 	}
 
 	pub fn new_inbound<NS: Deref>(node_signer: &NS) -> PeerChannelEncryptor where NS::Target: NodeSigner {
-		let mut sha = Sha256::engine();
-		sha.input(&NOISE_H);
 		let our_node_id = node_signer.get_node_id(Recipient::Node).unwrap();
-		sha.input(&our_node_id.serialize()[..]);
-		let h = Sha256::from_engine(sha).into_inner();
 
 		PeerChannelEncryptor {
 			their_node_id: None,
@@ -133,14 +131,24 @@ impl PeerChannelEncryptor {
 					re: None,
 					temp_k2: None,
 				},
-				bidirectional_state: BidirectionalNoiseState {
-					h,
-					ck: NOISE_CK,
-				},
+				bidirectional_state: PeerChannelEncryptor::inbound_noise_state(&our_node_id),
 			}
 		}
 	}
 
+	#[inline]
+	fn inbound_noise_state(our_node_id: &PublicKey) -> BidirectionalNoiseState {
+		let mut sha = Sha256::engine();
+		sha.input(&NOISE_H);
+		sha.input(&our_node_id.serialize()[..]);
+		let h = Sha256::from_engine(sha).into_inner();
+
+		BidirectionalNoiseState {
+			h,
+			ck: NOISE_CK,
+		}
+	}
+
 	#[inline]
 	fn encrypt_with_ad(res: &mut[u8], n: u64, key: &[u8; 32], h: &[u8], plaintext: &[u8]) {
 		let mut nonce = [0; 12];
@@ -236,6 +244,12 @@ impl PeerChannelEncryptor {
 					err: "Failed to derive shared secret".to_owned(),
 					action: msgs::ErrorAction::DisconnectPeer { msg: None }
 				})?,
+			NoiseSecretKey::PreviousNodeSigner(node_signer) => node_signer
+				.previous_node_ecdh(&their_pub)
+				.map_err(|_| LightningError {
+					err: "Failed to derive shared secret".to_owned(),
+					action: msgs::ErrorAction::DisconnectPeer { msg: None }
+				})?,
 		};
 		let temp_k = PeerChannelEncryptor::hkdf(state, ss);
 
@@ -282,7 +296,21 @@ impl PeerChannelEncryptor {
 							panic!("Requested act at wrong step");
 						}
 
-						let (their_pub, _) = PeerChannelEncryptor::inbound_noise_act(bidirectional_state, act_one, NoiseSecretKey::NodeSigner(node_signer))?;
+						let (their_pub, _) = match PeerChannelEncryptor::inbound_noise_act(bidirectional_state, act_one, NoiseSecretKey::NodeSigner(node_signer)) {
+							Ok(res) => res,
+							Err(e) => {
+								// The act didn't verify against our current node id, so check whether
+								// the initiator is still addressing us by the one we rotated away from,
+								// as peers which have not yet learned of the rotation would.
+								let previous_node_id = match node_signer.get_previous_node_id() {
+									Some(node_id) => node_id,
+									None => return Err(e),
+								};
+								*bidirectional_state = PeerChannelEncryptor::inbound_noise_state(&previous_node_id);
+								PeerChannelEncryptor::inbound_noise_act(bidirectional_state, act_one, NoiseSecretKey::PreviousNodeSigner(node_signer))
+									.map_err(|_| e)?
+							},
+						};
 						ie.get_or_insert(their_pub);
 
 						re.get_or_insert(our_ephemeral);
@@ -648,6 +676,32 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn noise_responder_accepts_previous_node_id() {
+		// The test vector initiator addresses the responder by the node id of 0x21..21. Check that
+		// a responder whose signer rotated away from that key still completes the handshake, and
+		// only then.
+		let previous_node_secret = SecretKey::from_slice(&hex::decode("2121212121212121212121212121212121212121212121212121212121212121").unwrap()[..]).unwrap();
+		let our_ephemeral = SecretKey::from_slice(&hex::decode("2222222222222222222222222222222222222222222222222222222222222222").unwrap()[..]).unwrap();
+		let secp_ctx = Secp256k1::new();
+		let act_one = hex::decode("00036360e856310ce5d294e8be33fc807077dc56ac80d95d9cd4ddbd21325eff73f70df6086551151f58b8afe6c195782c6a").unwrap().to_vec();
+
+		let node_signer = TestNodeSigner::new(SecretKey::from_slice(&[42; 32]).unwrap());
+		let mut inbound_peer = PeerChannelEncryptor::new_inbound(&&node_signer);
+		assert!(inbound_peer.process_act_one_with_keys(&act_one[..], &&node_signer, our_ephemeral.clone(), &secp_ctx).is_err());
+
+		let node_signer = TestNodeSigner::with_previous_node_secret(SecretKey::from_slice(&[42; 32]).unwrap(), SecretKey::from_slice(&[43; 32]).unwrap());
+		let mut inbound_peer = PeerChannelEncryptor::new_inbound(&&node_signer);
+		assert!(inbound_peer.process_act_one_with_keys(&act_one[..], &&node_signer, our_ephemeral.clone(), &secp_ctx).is_err());
+
+		let node_signer = TestNodeSigner::with_previous_node_secret(SecretKey::from_slice(&[42; 32]).unwrap(), previous_node_secret);
+		let mut inbound_peer = PeerChannelEncryptor::new_inbound(&&node_signer);
+		assert_eq!(inbound_peer.process_act_one_with_keys(&act_one[..], &&node_signer, our_ephemeral.clone(), &secp_ctx).unwrap()[..], hex::decode("0002466d7fcae563e5cb09a0d1870bb580344804617879a14949cf22285f1bae3f276e2470b93aac583c9ef6eafca3f730ae").unwrap()[..]);
+
+		let act_three = hex::decode("00b9e3a702e93e3a9948c2ed6e5fd7590a6e1c3a0344cfc9d5b57357049aa22355361aa02e55a8fc28fef5bd6d71ad0c38228dc68b1c466263b47fdf31e560e139ba").unwrap().to_vec();
+		assert_eq!(inbound_peer.process_act_three(&act_three[..]).unwrap().serialize()[..], hex::decode("034f355bdcb7cc0af728ef3cceb9615d90684bb5b2ca5f859ab0f0b704075871aa").unwrap()[..]);
+	}
+
 	#[test]
 	fn noise_responder_test_vectors() {
 		let our_node_id = SecretKey::from_slice(&hex::decode("2121212121212121212121212121212121212121212121212121212121212121").unwrap()[..]).unwrap();
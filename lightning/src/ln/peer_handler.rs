@@ -18,7 +18,9 @@
 use bitcoin::blockdata::constants::ChainHash;
 use bitcoin::secp256k1::{self, Secp256k1, SecretKey, PublicKey};
 
-use crate::sign::{KeysManager, NodeSigner, Recipient};
+use crate::chain;
+use crate::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use crate::sign::{InMemorySigner, KeysManager, NodeSigner, Recipient};
 use crate::events::{MessageSendEvent, MessageSendEventsProvider, OnionMessageProvider};
 use crate::ln::features::{InitFeatures, NodeFeatures};
 use crate::ln::msgs;
@@ -26,13 +28,19 @@ use crate::ln::msgs::{ChannelMessageHandler, LightningError, NetAddress, OnionMe
 use crate::ln::channelmanager::{SimpleArcChannelManager, SimpleRefChannelManager};
 use crate::util::ser::{VecWriter, Writeable, Writer};
 use crate::ln::peer_channel_encryptor::{PeerChannelEncryptor,NextNoiseStep};
+use crate::ln::peer_access::{PEER_ACCESS_RULES_PERSISTENCE_KEY, PeerAccessRule, PeerAccessRules, PeerAccessScope, PeerAccessTarget};
+use crate::ln::peer_metadata::PeerMetadata;
 use crate::ln::wire;
 use crate::ln::wire::{Encode, Type};
-use crate::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, OffersMessage, OffersMessageHandler, SimpleArcOnionMessenger, SimpleRefOnionMessenger};
+use crate::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, DlcMessage, DlcMessageHandler, OffersMessage, OffersMessageHandler, OnionMessageReceivedVia, Responder, SimpleArcOnionMessenger, SimpleRefOnionMessenger};
 use crate::routing::gossip::{NetworkGraph, P2PGossipSync, NodeId, NodeAlias};
+use crate::routing::utxo::UtxoLookup;
+use crate::routing::scoring::PeerLatencies;
 use crate::util::atomic_counter::AtomicCounter;
 use crate::util::logger::Logger;
+use crate::util::persist::KVStorePersister;
 use crate::util::string::PrintableString;
+use crate::util::time::{ConfiguredTime, Time};
 
 use crate::prelude::*;
 use crate::io;
@@ -121,6 +129,11 @@ impl OnionMessageHandler for IgnoringMessageHandler {
 impl OffersMessageHandler for IgnoringMessageHandler {
 	fn handle_message(&self, _msg: OffersMessage) -> Option<OffersMessage> { None }
 }
+impl DlcMessageHandler for IgnoringMessageHandler {
+	fn handle_message(
+		&self, _msg: DlcMessage, _received_via: OnionMessageReceivedVia, _responder: Option<Responder>
+	) -> Option<DlcMessage> { None }
+}
 impl CustomOnionMessageHandler for IgnoringMessageHandler {
 	type CustomMessage = Infallible;
 	fn handle_custom_message(&self, _msg: Infallible) -> Option<Infallible> {
@@ -247,6 +260,9 @@ impl ChannelMessageHandler for ErroringMessageHandler {
 	fn handle_update_fee(&self, their_node_id: &PublicKey, msg: &msgs::UpdateFee) {
 		ErroringMessageHandler::push_error(self, their_node_id, msg.channel_id);
 	}
+	fn handle_yield(&self, their_node_id: &PublicKey, msg: &msgs::Yield) {
+		ErroringMessageHandler::push_error(self, their_node_id, msg.channel_id);
+	}
 	fn handle_announcement_signatures(&self, their_node_id: &PublicKey, msg: &msgs::AnnouncementSignatures) {
 		ErroringMessageHandler::push_error(self, their_node_id, msg.channel_id);
 	}
@@ -335,6 +351,72 @@ impl Deref for ErroringMessageHandler {
 	fn deref(&self) -> &Self { self }
 }
 
+/// Timer behavior the [`PeerManager`] applies to one class of peers. See [`PeerManagerConfig`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PeerTimerConfig {
+	/// The number of calls to [`PeerManager::timer_tick_occurred`] between the pings we send the
+	/// peer.
+	///
+	/// Default value: 1
+	pub ping_interval_ticks: u16,
+	/// The number of timer ticks, per connected peer, a peer may take to respond to a ping before
+	/// we disconnect it. Independent of this value, a peer is disconnected if it doesn't send us a
+	/// single message during a timer tick while we're awaiting its response.
+	///
+	/// The allowance scales with the number of connected peers as we may need to churn through
+	/// messages from all of them before processing a response.
+	///
+	/// Default value: 4
+	pub pong_timeout_ticks_per_peer: u16,
+	/// If set, caps the number of timer ticks a peer may take to respond to a ping, regardless of
+	/// the number of connected peers.
+	///
+	/// Default value: None
+	pub max_pong_timeout_ticks: Option<u16>,
+	/// The number of messages we send a peer between pings before we stop sending it gossip until
+	/// it responds. At twice this many messages, gossip broadcasts for the peer are dropped
+	/// entirely.
+	///
+	/// Default value: 32
+	pub gossip_backlog_msgs: usize,
+}
+
+impl Default for PeerTimerConfig {
+	fn default() -> Self {
+		Self {
+			ping_interval_ticks: 1,
+			pong_timeout_ticks_per_peer: MAX_BUFFER_DRAIN_TICK_INTERVALS_PER_PEER as u16,
+			max_pong_timeout_ticks: None,
+			gossip_backlog_msgs: BUFFER_DRAIN_MSGS_PER_TICK,
+		}
+	}
+}
+
+impl PeerTimerConfig {
+	fn pong_timeout_ticks(&self, peer_count: usize) -> u64 {
+		let timeout = self.pong_timeout_ticks_per_peer as u64 * peer_count as u64;
+		match self.max_pong_timeout_ticks {
+			Some(max_timeout) => cmp::min(timeout, max_timeout as u64),
+			None => timeout,
+		}
+	}
+}
+
+/// Configuration for the [`PeerManager`]'s timer behavior, passed to
+/// [`PeerManager::new_with_config`].
+///
+/// Peers with which our [`ChannelMessageHandler`] has channels (see
+/// [`ChannelMessageHandler::has_channels_with_peer`]) are subject to [`Self::channel_peers`],
+/// allowing for stricter liveness requirements than for peers we only exchange gossip with. A
+/// peer's class is re-evaluated on every [`PeerManager::timer_tick_occurred`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PeerManagerConfig {
+	/// Timer behavior for peers with which we have channels.
+	pub channel_peers: PeerTimerConfig,
+	/// Timer behavior for all other peers.
+	pub other_peers: PeerTimerConfig,
+}
+
 /// Provides references to trait impls which handle different types of messages.
 pub struct MessageHandler<CM: Deref, RM: Deref, OM: Deref, CustomM: Deref> where
 	CM::Target: ChannelMessageHandler,
@@ -395,6 +477,24 @@ pub trait SocketDescriptor : cmp::Eq + hash::Hash + Clone {
 	/// `resume_read` may be set indicating that read events on this descriptor should resume. A
 	/// `resume_read` of false carries no meaning, and should not cause any action.
 	fn send_data(&mut self, data: &[u8], resume_read: bool) -> usize;
+	/// Attempts to send some data from the given slices to the peer, in order, e.g. via a single
+	/// vectored write.
+	///
+	/// Behaves exactly like [`Self::send_data`] called with the concatenation of all slices,
+	/// including the requirements around [`PeerManager::write_buffer_space_avail`] if the returned
+	/// size is smaller than the sum of the slices' lengths.
+	///
+	/// By default, calls [`Self::send_data`] for each slice until one isn't sent fully.
+	fn send_data_vectored(&mut self, data: &[&[u8]], resume_read: bool) -> usize {
+		if data.is_empty() { return self.send_data(&[], resume_read); }
+		let mut sent = 0;
+		for slice in data {
+			let slice_sent = self.send_data(slice, resume_read);
+			sent += slice_sent;
+			if slice_sent < slice.len() { break; }
+		}
+		sent
+	}
 	/// Disconnect the socket pointed to by this SocketDescriptor.
 	///
 	/// You do *not* need to call [`PeerManager::socket_disconnected`] with this socket after this
@@ -470,6 +570,63 @@ const MAX_BUFFER_DRAIN_TICK_INTERVALS_PER_PEER: i8 = 4;
 /// [`FORWARD_INIT_SYNC_BUFFER_LIMIT_RATIO`]) than a hard limit.
 const BUFFER_DRAIN_MSGS_PER_TICK: usize = 32;
 
+/// The maximum number of buffered messages we hand to [`SocketDescriptor::send_data_vectored`] at
+/// once.
+const MAX_VECTORED_WRITE_MSGS: usize = 16;
+
+/// Informational hints about the software a peer is running, collected from what it sent us over
+/// the lifetime of its current connection.
+///
+/// Different Lightning implementations tend to differ in the features they set in their
+/// [`msgs::Init`], whether they include optional fields in it, how often and with which
+/// parameters they send [`msgs::Ping`]s, and how they drive gossip sync. None of these are
+/// guaranteed to identify a given implementation, so this only exposes the raw observations,
+/// leaving it to the user to correlate them with known counterparty behavior, e.g. when
+/// debugging interop failures or working around known bugs in specific software.
+///
+/// Retrieved via [`PeerManager::peer_fingerprint`] or [`PeerManager::list_peer_fingerprints`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerFingerprint {
+	/// The features the peer set in its [`msgs::Init`].
+	pub features: InitFeatures,
+	/// Whether the peer's [`msgs::Init`] included the optional `networks` field.
+	pub sent_networks: bool,
+	/// Whether the peer's [`msgs::Init`] reported our network address back to us.
+	pub sent_remote_address: bool,
+	/// The number of [`msgs::Ping`]s we've received from the peer.
+	pub pings_received: u64,
+	/// The number of [`PeerManager::timer_tick_occurred`] calls between the last two
+	/// [`msgs::Ping`]s we received from the peer, if it has sent us at least two.
+	pub last_ping_interval_ticks: Option<u64>,
+	/// The `ponglen` of the last [`msgs::Ping`] we received from the peer.
+	pub last_ping_ponglen: Option<u16>,
+	/// The `byteslen` of the last [`msgs::Ping`] we received from the peer.
+	pub last_ping_byteslen: Option<u16>,
+	/// The `first_timestamp` of the first [`msgs::GossipTimestampFilter`] the peer sent us.
+	pub gossip_timestamp_filter_first_timestamp: Option<u32>,
+	/// Whether the peer has sent us a [`msgs::QueryChannelRange`].
+	pub sent_query_channel_range: bool,
+	/// The number of messages of unknown odd types we've received from the peer.
+	pub unknown_odd_messages_received: u64,
+}
+
+impl PeerFingerprint {
+	fn new() -> Self {
+		Self {
+			features: InitFeatures::empty(),
+			sent_networks: false,
+			sent_remote_address: false,
+			pings_received: 0,
+			last_ping_interval_ticks: None,
+			last_ping_ponglen: None,
+			last_ping_byteslen: None,
+			gossip_timestamp_filter_first_timestamp: None,
+			sent_query_channel_range: false,
+			unknown_odd_messages_received: 0,
+		}
+	}
+}
+
 struct Peer {
 	channel_encryptor: PeerChannelEncryptor,
 	/// We cache a `NodeId` here to avoid serializing peers' keys every time we forward gossip
@@ -491,6 +648,14 @@ struct Peer {
 	///
 	/// Note that these messages are *not* encrypted/MAC'd, and are only serialized.
 	gossip_broadcast_buffer: LinkedList<Vec<u8>>,
+	/// Gossip and onion messages sent directly to this peer which were enqueued while
+	/// `pending_outbound_buffer` was non-empty. These are held back until it has drained so that
+	/// channel messages such as `commitment_signed` and `revoke_and_ack` are not stuck behind them
+	/// on bandwidth-constrained connections.
+	///
+	/// As with `gossip_broadcast_buffer`, these messages are *not* encrypted/MAC'd, as the noise
+	/// nonce requires messages to be encrypted in the order they are sent.
+	low_priority_outbound_buffer: LinkedList<Vec<u8>>,
 	awaiting_write_event: bool,
 
 	pending_read_buffer: Vec<u8>,
@@ -499,8 +664,14 @@ struct Peer {
 
 	sync_status: InitSyncTracker,
 
+	/// The timer behavior for the peer's class, see [`PeerManagerConfig`].
+	timer_config: PeerTimerConfig,
+	ticks_since_ping: u16,
 	msgs_sent_since_pong: usize,
 	awaiting_pong_timer_tick_intervals: i64,
+	/// When we sent each [`msgs::Ping`] we're still awaiting a [`msgs::Pong`] for, oldest first.
+	/// Peers respond to pings in order, so each pong answers the oldest outstanding ping.
+	pings_sent_at: VecDeque<ConfiguredTime>,
 	received_message_since_timer_tick: bool,
 	sent_gossip_timestamp_filter: bool,
 
@@ -511,6 +682,15 @@ struct Peer {
 	received_channel_announce_since_backlogged: bool,
 
 	inbound_connection: bool,
+
+	/// What we've observed of the peer's behavior, see [`PeerFingerprint`]. The `features` are
+	/// only valid once [`Peer::handshake_complete`].
+	fingerprint: PeerFingerprint,
+	/// The number of timer ticks since the connection was established, used to measure the
+	/// peer's ping cadence.
+	ticks_since_connected: u64,
+	/// The value of `ticks_since_connected` when we last received a [`msgs::Ping`].
+	last_ping_received_tick: Option<u64>,
 }
 
 impl Peer {
@@ -560,7 +740,7 @@ impl Peer {
 		if !gossip_processing_backlogged {
 			self.received_channel_announce_since_backlogged = false;
 		}
-		self.pending_outbound_buffer.len() < OUTBOUND_BUFFER_LIMIT_READ_PAUSE &&
+		self.pending_outbound_buffer.len() + self.low_priority_outbound_buffer.len() < OUTBOUND_BUFFER_LIMIT_READ_PAUSE &&
 			(!gossip_processing_backlogged || !self.received_channel_announce_since_backlogged)
 	}
 
@@ -568,7 +748,8 @@ impl Peer {
 	/// outbound buffer. This is checked every time the peer's buffer may have been drained.
 	fn should_buffer_gossip_backfill(&self) -> bool {
 		self.pending_outbound_buffer.is_empty() && self.gossip_broadcast_buffer.is_empty()
-			&& self.msgs_sent_since_pong < BUFFER_DRAIN_MSGS_PER_TICK
+			&& self.low_priority_outbound_buffer.is_empty()
+			&& self.msgs_sent_since_pong < self.timer_config.gossip_backlog_msgs
 			&& self.handshake_complete()
 	}
 
@@ -576,23 +757,24 @@ impl Peer {
 	/// every time the peer's buffer may have been drained.
 	fn should_buffer_onion_message(&self) -> bool {
 		self.pending_outbound_buffer.is_empty() && self.handshake_complete()
-			&& self.msgs_sent_since_pong < BUFFER_DRAIN_MSGS_PER_TICK
+			&& self.msgs_sent_since_pong < self.timer_config.gossip_backlog_msgs
 	}
 
 	/// Determines if we should push additional gossip broadcast messages onto a peer's outbound
 	/// buffer. This is checked every time the peer's buffer may have been drained.
 	fn should_buffer_gossip_broadcast(&self) -> bool {
 		self.pending_outbound_buffer.is_empty() && self.handshake_complete()
-			&& self.msgs_sent_since_pong < BUFFER_DRAIN_MSGS_PER_TICK
+			&& self.msgs_sent_since_pong < self.timer_config.gossip_backlog_msgs
 	}
 
 	/// Returns whether this peer's outbound buffers are full and we should drop gossip broadcasts.
 	fn buffer_full_drop_gossip_broadcast(&self) -> bool {
 		let total_outbound_buffered =
-			self.gossip_broadcast_buffer.len() + self.pending_outbound_buffer.len();
+			self.gossip_broadcast_buffer.len() + self.pending_outbound_buffer.len() +
+			self.low_priority_outbound_buffer.len();
 
 		total_outbound_buffered > OUTBOUND_BUFFER_LIMIT_DROP_GOSSIP ||
-			self.msgs_sent_since_pong > BUFFER_DRAIN_MSGS_PER_TICK * FORWARD_INIT_SYNC_BUFFER_LIMIT_RATIO
+			self.msgs_sent_since_pong > self.timer_config.gossip_backlog_msgs * FORWARD_INIT_SYNC_BUFFER_LIMIT_RATIO
 	}
 
 	fn set_their_node_id(&mut self, node_id: PublicKey) {
@@ -637,6 +819,28 @@ pub type SimpleRefPeerManager<
 	&'c KeysManager
 >;
 
+/// AnyPeerManager is a PeerManager whose message handlers and dependencies are `Arc`'d trait
+/// objects, sparing downstream crates and FFI layers from repeating the generic parameters of
+/// [`PeerManager`]. An `Arc<`[`AnyChannelManager`]`>` and `Arc<`[`AnyOnionMessenger`]`>` may be
+/// used as its channel and onion message handlers.
+///
+/// The [`CustomMessageHandler`] remains a type parameter, as its trait can't be made into a trait
+/// object, and should be [`IgnoringMessageHandler`] if custom messages aren't used.
+///
+/// This is not exported to bindings users as `Arc`s don't make sense in bindings.
+///
+/// [`AnyChannelManager`]: crate::ln::channelmanager::AnyChannelManager
+/// [`AnyOnionMessenger`]: crate::onion_message::AnyOnionMessenger
+pub type AnyPeerManager<SD, CMH> = PeerManager<
+	SD,
+	Arc<dyn ChannelMessageHandler + Send + Sync>,
+	Arc<dyn RoutingMessageHandler + Send + Sync>,
+	Arc<dyn OnionMessageHandler + Send + Sync>,
+	Arc<dyn Logger + Send + Sync>,
+	Arc<CMH>,
+	Arc<dyn NodeSigner + Send + Sync>
+>;
+
 
 /// A generic trait which is implemented for all [`PeerManager`]s. This makes bounding functions or
 /// structs on any [`PeerManager`] much simpler as only this trait is needed as a bound, rather
@@ -715,6 +919,7 @@ pub struct PeerManager<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: D
 		CMH::Target: CustomMessageHandler,
 		NS::Target: NodeSigner {
 	message_handler: MessageHandler<CM, RM, OM, CMH>,
+	config: PeerManagerConfig,
 	/// Connection state for each connected peer - we have an outer read-write lock which is taken
 	/// as read while we're doing processing for a peer and taken write when a peer is being added
 	/// or removed.
@@ -746,6 +951,14 @@ pub struct PeerManager<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: D
 	/// value increases strictly since we don't assume access to a time source.
 	last_node_announcement_serial: AtomicU32,
 
+	/// The statement included in our node_announcements, see [`Self::set_node_key_rotation`].
+	node_key_rotation: Mutex<Option<msgs::NodeKeyRotation>>,
+	/// Where we record ping round trips, see [`Self::set_peer_latencies`].
+	peer_latencies: Mutex<Option<Arc<PeerLatencies>>>,
+	/// The rules deciding which peers we accept connections, channel opens and onion messages from,
+	/// see [`Self::add_access_rule`].
+	access_rules: Mutex<PeerAccessRules>,
+
 	ephemeral_key_midstate: Sha256Engine,
 
 	peer_counter: AtomicCounter,
@@ -840,6 +1053,59 @@ impl<Descriptor: SocketDescriptor, RM: Deref, L: Deref, NS: Deref> PeerManager<D
 	}
 }
 
+impl<SD: SocketDescriptor, M, T, F, C, L> SimpleArcPeerManager<SD, M, T, F, C, L> where
+		M: chain::Watch<InMemorySigner> + Send + Sync + 'static,
+		T: BroadcasterInterface + Send + Sync + 'static,
+		F: FeeEstimator + Send + Sync + 'static,
+		C: UtxoLookup,
+		L: Logger + Send + Sync + 'static {
+	/// Constructs a new [`SimpleArcPeerManager`] along with the [`SimpleArcOnionMessenger`] wired
+	/// into it, so that onion messages are handled without assembling the [`MessageHandler`] by
+	/// hand.
+	///
+	/// The onion messenger finds paths using a [`DefaultMessageRouter`] over the `gossip_sync`'s
+	/// network graph, uses the `channel_manager` to look up channel peers for
+	/// [`OnionMessageForwardingPolicy::ChannelPeers`], and ignores offers and custom onion
+	/// messages. If you need your own handlers, construct the [`OnionMessenger`] with
+	/// [`OnionMessenger::with_default_router`] and pass it to [`PeerManager::new`] instead.
+	///
+	/// The background processor does not process the events of the returned onion messenger, so
+	/// you must call [`EventsProvider::process_pending_events`] on it yourself to learn of, e.g.,
+	/// [`Event::OnionMessageTimedOut`] or [`Event::OnionMessagesEvicted`].
+	///
+	/// See [`PeerManager::new`] for the meaning of `current_time` and `ephemeral_random_data`.
+	///
+	/// This is not exported to bindings users as `Arc`s don't make sense in bindings.
+	///
+	/// [`DefaultMessageRouter`]: crate::onion_message::DefaultMessageRouter
+	/// [`OnionMessageForwardingPolicy::ChannelPeers`]: crate::onion_message::OnionMessageForwardingPolicy::ChannelPeers
+	/// [`OnionMessenger`]: crate::onion_message::OnionMessenger
+	/// [`OnionMessenger::with_default_router`]: crate::onion_message::OnionMessenger::with_default_router
+	/// [`EventsProvider::process_pending_events`]: crate::events::EventsProvider::process_pending_events
+	/// [`Event::OnionMessageTimedOut`]: crate::events::Event::OnionMessageTimedOut
+	/// [`Event::OnionMessagesEvicted`]: crate::events::Event::OnionMessagesEvicted
+	pub fn new_with_onion_messenger(
+		channel_manager: Arc<SimpleArcChannelManager<M, T, F, L>>,
+		gossip_sync: Arc<P2PGossipSync<Arc<NetworkGraph<Arc<L>>>, Arc<C>, Arc<L>>>,
+		keys_manager: Arc<KeysManager>, logger: Arc<L>, current_time: u32,
+		ephemeral_random_data: &[u8; 32]
+	) -> (Self, Arc<SimpleArcOnionMessenger<L>>) {
+		let onion_messenger = Arc::new(SimpleArcOnionMessenger::with_default_router(
+			Arc::clone(&keys_manager), Arc::clone(&keys_manager), Arc::clone(&logger),
+			Arc::clone(gossip_sync.network_graph()), IgnoringMessageHandler {}, IgnoringMessageHandler {},
+			IgnoringMessageHandler {}
+		));
+		onion_messenger.set_channel_peer_lookup(Arc::clone(&channel_manager));
+		let peer_manager = Self::new(MessageHandler {
+			chan_handler: channel_manager,
+			route_handler: gossip_sync,
+			onion_message_handler: Arc::clone(&onion_messenger),
+			custom_message_handler: IgnoringMessageHandler {},
+		}, current_time, ephemeral_random_data, logger, keys_manager);
+		(peer_manager, onion_messenger)
+	}
+}
+
 /// A simple wrapper that optionally prints ` from <pubkey>` for an optional pubkey.
 /// This works around `format!()` taking a reference to each argument, preventing
 /// `if let Some(node_id) = peer.their_node_id { format!(.., node_id) } else { .. }` from compiling
@@ -899,6 +1165,12 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 	/// timestamp, however if it is not available a persistent counter that increases once per
 	/// minute should suffice.
 	pub fn new(message_handler: MessageHandler<CM, RM, OM, CMH>, current_time: u32, ephemeral_random_data: &[u8; 32], logger: L, node_signer: NS) -> Self {
+		Self::new_with_config(message_handler, PeerManagerConfig::default(), current_time, ephemeral_random_data, logger, node_signer)
+	}
+
+	/// Constructs a new `PeerManager` with the given message handlers and a non-default
+	/// [`PeerManagerConfig`]. See [`Self::new`] for the remaining parameters.
+	pub fn new_with_config(message_handler: MessageHandler<CM, RM, OM, CMH>, config: PeerManagerConfig, current_time: u32, ephemeral_random_data: &[u8; 32], logger: L, node_signer: NS) -> Self {
 		let mut ephemeral_key_midstate = Sha256::engine();
 		ephemeral_key_midstate.input(ephemeral_random_data);
 
@@ -908,6 +1180,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 
 		PeerManager {
 			message_handler,
+			config,
 			peers: FairRwLock::new(HashMap::new()),
 			node_id_to_descriptor: Mutex::new(HashMap::new()),
 			event_processing_state: AtomicI32::new(0),
@@ -916,6 +1189,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			gossip_processing_backlogged: AtomicBool::new(false),
 			gossip_processing_backlog_lifted: AtomicBool::new(false),
 			last_node_announcement_serial: AtomicU32::new(current_time),
+			node_key_rotation: Mutex::new(None),
+			peer_latencies: Mutex::new(None),
+			access_rules: Mutex::new(PeerAccessRules::default()),
 			logger,
 			node_signer,
 			secp_ctx,
@@ -943,6 +1219,139 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 		}).collect()
 	}
 
+	/// Gets the [`PeerFingerprint`] of the given peer, if it is connected and has completed the
+	/// initial handshake.
+	pub fn peer_fingerprint(&self, node_id: &PublicKey) -> Option<PeerFingerprint> {
+		let peers = self.peers.read().unwrap();
+		let descriptor = self.node_id_to_descriptor.lock().unwrap().get(node_id)?.clone();
+		let peer = peers.get(&descriptor)?.lock().unwrap();
+		if !peer.handshake_complete() {
+			return None;
+		}
+		Some(peer.fingerprint.clone())
+	}
+
+	/// Gets the [`PeerFingerprint`]s of all peers which have completed the initial handshake.
+	pub fn list_peer_fingerprints(&self) -> Vec<(PublicKey, PeerFingerprint)> {
+		let peers = self.peers.read().unwrap();
+		peers.values().filter_map(|peer_mutex| {
+			let p = peer_mutex.lock().unwrap();
+			if !p.handshake_complete() {
+				return None;
+			}
+			Some((p.their_node_id.unwrap().0, p.fingerprint.clone()))
+		}).collect()
+	}
+
+	/// Gets the [`PeerMetadata`] our [`ChannelMessageHandler`] stores for the given peer, if any,
+	/// for use in connection policy decisions.
+	///
+	/// This works whether or not the peer is currently connected.
+	pub fn peer_metadata(&self, node_id: &PublicKey) -> Option<PeerMetadata> {
+		self.message_handler.chan_handler.peer_metadata(node_id)
+	}
+
+	/// Sets a [`msgs::NodeKeyRotation`] statement to include in the `node_announcement`s we
+	/// broadcast via [`Self::broadcast_node_announcement`], announcing that we are about to move to
+	/// a new node identity key.
+	///
+	/// The statement should be announced for a while before switching our [`NodeSigner`] over to
+	/// the new key, so that peers can learn of it and migrate their channels with us (see
+	/// [`ChannelManager::migrate_counterparty_node_id`]). It is not persisted, so must be set again
+	/// on startup for as long as it should be announced. Once switched over, peers which have not
+	/// yet migrated may keep connecting to our previous node id for as long as the [`NodeSigner`]
+	/// returns it from [`NodeSigner::get_previous_node_id`].
+	///
+	/// [`ChannelManager::migrate_counterparty_node_id`]: crate::ln::channelmanager::ChannelManager::migrate_counterparty_node_id
+	pub fn set_node_key_rotation(&self, rotation: Option<msgs::NodeKeyRotation>) {
+		*self.node_key_rotation.lock().unwrap() = rotation;
+	}
+
+	/// Sets where the round-trip time of our `ping`s to each peer is recorded, if anywhere.
+	///
+	/// The same [`PeerLatencies`] may be given to a [`DefaultRouter`] via
+	/// [`DefaultRouter::with_peer_latencies`] to prefer low-latency hops for payments marked
+	/// [`PaymentParameters::latency_sensitive`]. Round trips are not measured in `no-std` builds.
+	///
+	/// [`DefaultRouter`]: crate::routing::router::DefaultRouter
+	/// [`DefaultRouter::with_peer_latencies`]: crate::routing::router::DefaultRouter::with_peer_latencies
+	/// [`PaymentParameters::latency_sensitive`]: crate::routing::router::PaymentParameters::latency_sensitive
+	pub fn set_peer_latencies(&self, peer_latencies: Option<Arc<PeerLatencies>>) {
+		*self.peer_latencies.lock().unwrap() = peer_latencies;
+	}
+
+	/// Adds a rule allowing or denying peers access to our node, replacing any existing rule with
+	/// the same [`PeerAccessRule::target`] and [`PeerAccessRule::scope`].
+	///
+	/// Rules are consulted when a peer connects, proposes a channel, or sends us an onion message.
+	/// Connected peers which are no longer allowed to connect are disconnected immediately, via
+	/// [`SocketDescriptor::disconnect_socket`], so be careful about reentrancy.
+	///
+	/// Rules are not persisted automatically, see [`Self::persist_access_rules`].
+	pub fn add_access_rule(&self, rule: PeerAccessRule) {
+		self.access_rules.lock().unwrap().add_rule(rule);
+		self.disconnect_denied_peers();
+	}
+
+	/// Removes the rule with the given target and scope, returning whether one existed.
+	///
+	/// As with [`Self::add_access_rule`], connected peers which are no longer allowed to connect,
+	/// e.g. as an allow rule overriding a deny rule was removed, are disconnected immediately.
+	pub fn remove_access_rule(&self, target: &PeerAccessTarget, scope: PeerAccessScope) -> bool {
+		let removed = self.access_rules.lock().unwrap().remove_rule(target, scope);
+		self.disconnect_denied_peers();
+		removed
+	}
+
+	/// Gets all rules added via [`Self::add_access_rule`] or [`Self::set_access_rules`] which have
+	/// not yet been removed.
+	pub fn list_access_rules(&self) -> Vec<PeerAccessRule> {
+		self.access_rules.lock().unwrap().rules().to_vec()
+	}
+
+	/// Replaces all rules with the given ones, e.g. as read on startup after being persisted via
+	/// [`Self::persist_access_rules`].
+	///
+	/// As with [`Self::add_access_rule`], connected peers which are no longer allowed to connect
+	/// are disconnected immediately.
+	pub fn set_access_rules(&self, rules: PeerAccessRules) {
+		*self.access_rules.lock().unwrap() = rules;
+		self.disconnect_denied_peers();
+	}
+
+	/// Returns whether our rules allow the peer with the given node id access in the given scope.
+	pub fn is_peer_allowed(&self, node_id: &PublicKey, scope: PeerAccessScope) -> bool {
+		self.access_rules.lock().unwrap().is_allowed(node_id, scope, Self::access_rules_time())
+	}
+
+	/// Persists our current access rules under [`PEER_ACCESS_RULES_PERSISTENCE_KEY`].
+	///
+	/// This should be called after changing the rules, which can be restored on startup via
+	/// [`Self::set_access_rules`].
+	pub fn persist_access_rules<K: KVStorePersister>(&self, persister: &K) -> Result<(), io::Error> {
+		let rules = self.access_rules.lock().unwrap().clone();
+		persister.persist(PEER_ACCESS_RULES_PERSISTENCE_KEY, &rules)
+	}
+
+	/// The current time to evaluate access rule expiries against, in seconds since the UNIX epoch.
+	fn access_rules_time() -> u64 {
+		ConfiguredTime::duration_since_epoch().as_secs()
+	}
+
+	/// Disconnects all connected peers which our access rules no longer allow to connect.
+	fn disconnect_denied_peers(&self) {
+		let denied_node_ids: Vec<PublicKey> = {
+			let node_id_to_descriptor = self.node_id_to_descriptor.lock().unwrap();
+			node_id_to_descriptor.keys()
+				.filter(|node_id| !self.is_peer_allowed(node_id, PeerAccessScope::Connection))
+				.copied().collect()
+		};
+		for node_id in denied_node_ids {
+			log_debug!(self.logger, "Disconnecting peer {} which is no longer allowed to connect", log_pubkey!(node_id));
+			self.disconnect_by_node_id(node_id);
+		}
+	}
+
 	fn get_ephemeral_key(&self) -> SecretKey {
 		let mut ephemeral_hash = self.ephemeral_key_midstate.clone();
 		let counter = self.peer_counter.get_increment();
@@ -973,6 +1382,10 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 	///
 	/// [`socket_disconnected`]: PeerManager::socket_disconnected
 	pub fn new_outbound_connection(&self, their_node_id: PublicKey, descriptor: Descriptor, remote_network_address: Option<NetAddress>) -> Result<Vec<u8>, PeerHandleError> {
+		if !self.is_peer_allowed(&their_node_id, PeerAccessScope::Connection) {
+			log_debug!(self.logger, "Refusing to connect to peer {} denied by our access rules", log_pubkey!(their_node_id));
+			return Err(PeerHandleError { });
+		}
 		let mut peer_encryptor = PeerChannelEncryptor::new_outbound(their_node_id.clone(), self.get_ephemeral_key());
 		let res = peer_encryptor.get_act_one(&self.secp_ctx).to_vec();
 		let pending_read_buffer = [0; 50].to_vec(); // Noise act two is 50 bytes
@@ -993,6 +1406,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 					pending_outbound_buffer: LinkedList::new(),
 					pending_outbound_buffer_first_msg_offset: 0,
 					gossip_broadcast_buffer: LinkedList::new(),
+					low_priority_outbound_buffer: LinkedList::new(),
 					awaiting_write_event: false,
 
 					pending_read_buffer,
@@ -1001,13 +1415,20 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 
 					sync_status: InitSyncTracker::NoSyncRequested,
 
+					timer_config: self.config.other_peers,
+					ticks_since_ping: 0,
 					msgs_sent_since_pong: 0,
 					awaiting_pong_timer_tick_intervals: 0,
+					pings_sent_at: VecDeque::new(),
 					received_message_since_timer_tick: false,
 					sent_gossip_timestamp_filter: false,
 
 					received_channel_announce_since_backlogged: false,
 					inbound_connection: false,
+
+					fingerprint: PeerFingerprint::new(),
+					ticks_since_connected: 0,
+					last_ping_received_tick: None,
 				}));
 				Ok(res)
 			}
@@ -1049,6 +1470,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 					pending_outbound_buffer: LinkedList::new(),
 					pending_outbound_buffer_first_msg_offset: 0,
 					gossip_broadcast_buffer: LinkedList::new(),
+					low_priority_outbound_buffer: LinkedList::new(),
 					awaiting_write_event: false,
 
 					pending_read_buffer,
@@ -1057,13 +1479,20 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 
 					sync_status: InitSyncTracker::NoSyncRequested,
 
+					timer_config: self.config.other_peers,
+					ticks_since_ping: 0,
 					msgs_sent_since_pong: 0,
 					awaiting_pong_timer_tick_intervals: 0,
+					pings_sent_at: VecDeque::new(),
 					received_message_since_timer_tick: false,
 					sent_gossip_timestamp_filter: false,
 
 					received_channel_announce_since_backlogged: false,
 					inbound_connection: true,
+
+					fingerprint: PeerFingerprint::new(),
+					ticks_since_connected: 0,
+					last_ping_received_tick: None,
 				}));
 				Ok(())
 			}
@@ -1085,6 +1514,11 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 	fn do_attempt_write_data(&self, descriptor: &mut Descriptor, peer: &mut Peer, force_one_write: bool) {
 		let mut have_written = false;
 		while !peer.awaiting_write_event {
+			if peer.pending_outbound_buffer.is_empty() {
+				if let Some(msg) = peer.low_priority_outbound_buffer.pop_front() {
+					peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_buffer(&msg[..]));
+				}
+			}
 			if peer.should_buffer_onion_message() {
 				if let Some((peer_node_id, _)) = peer.their_node_id {
 					if let Some(next_onion_message) =
@@ -1136,33 +1570,45 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 					},
 				}
 			}
-			if peer.msgs_sent_since_pong >= BUFFER_DRAIN_MSGS_PER_TICK {
+			if peer.msgs_sent_since_pong >= peer.timer_config.gossip_backlog_msgs {
 				self.maybe_send_extra_ping(peer);
 			}
 
 			let should_read = self.peer_should_read(peer);
-			let next_buff = match peer.pending_outbound_buffer.front() {
-				None => {
-					if force_one_write && !have_written {
-						if should_read {
-							let data_sent = descriptor.send_data(&[], should_read);
-							debug_assert_eq!(data_sent, 0, "Can't write more than no data");
-						}
+			if peer.pending_outbound_buffer.is_empty() {
+				if force_one_write && !have_written {
+					if should_read {
+						let data_sent = descriptor.send_data(&[], should_read);
+						debug_assert_eq!(data_sent, 0, "Can't write more than no data");
 					}
-					return
-				},
-				Some(buff) => buff,
-			};
+				}
+				return
+			}
 
-			let pending = &next_buff[peer.pending_outbound_buffer_first_msg_offset..];
-			let data_sent = descriptor.send_data(pending, should_read);
+			// Hand as many buffered messages as we can to the descriptor at once, allowing it to
+			// write them with a single syscall.
+			let mut pending: [&[u8]; MAX_VECTORED_WRITE_MSGS] = [&[]; MAX_VECTORED_WRITE_MSGS];
+			let mut pending_count = 0;
+			for buff in peer.pending_outbound_buffer.iter().take(MAX_VECTORED_WRITE_MSGS) {
+				let offset = if pending_count == 0 { peer.pending_outbound_buffer_first_msg_offset } else { 0 };
+				pending[pending_count] = &buff[offset..];
+				pending_count += 1;
+			}
+			let pending_len: usize = pending[..pending_count].iter().map(|buff| buff.len()).sum();
+			let mut data_sent = descriptor.send_data_vectored(&pending[..pending_count], should_read);
 			have_written = true;
-			peer.pending_outbound_buffer_first_msg_offset += data_sent;
-			if peer.pending_outbound_buffer_first_msg_offset == next_buff.len() {
+			if data_sent < pending_len {
+				peer.awaiting_write_event = true;
+			}
+			while let Some(next_buff) = peer.pending_outbound_buffer.front() {
+				let next_buff_remaining = next_buff.len() - peer.pending_outbound_buffer_first_msg_offset;
+				if data_sent < next_buff_remaining {
+					peer.pending_outbound_buffer_first_msg_offset += data_sent;
+					break;
+				}
+				data_sent -= next_buff_remaining;
 				peer.pending_outbound_buffer_first_msg_offset = 0;
 				peer.pending_outbound_buffer.pop_front();
-			} else {
-				peer.awaiting_write_event = true;
 			}
 		}
 	}
@@ -1233,7 +1679,13 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			log_trace!(self.logger, "Enqueueing message {:?} to {}", message, log_pubkey!(peer.their_node_id.unwrap().0))
 		}
 		peer.msgs_sent_since_pong += 1;
-		peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(message));
+		if is_low_priority_msg(message.type_id()) &&
+			(!peer.pending_outbound_buffer.is_empty() || !peer.low_priority_outbound_buffer.is_empty())
+		{
+			peer.low_priority_outbound_buffer.push_back(encode_msg!(message));
+		} else {
+			peer.pending_outbound_buffer.push_back(peer.channel_encryptor.encrypt_message(message));
+		}
 	}
 
 	/// Append a message to a peer's pending outbound/write gossip broadcast buffer
@@ -1374,6 +1826,10 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 							NextNoiseStep::ActThree => {
 								let their_node_id = try_potential_handleerror!(peer,
 									peer.channel_encryptor.process_act_three(&peer.pending_read_buffer[..]));
+								if !self.is_peer_allowed(&their_node_id, PeerAccessScope::Connection) {
+									log_debug!(self.logger, "Rejecting connection from peer {} denied by our access rules", log_pubkey!(their_node_id));
+									return Err(PeerHandleError { });
+								}
 								peer.pending_read_buffer = [0; 18].to_vec(); // Message length header is 18 bytes
 								peer.pending_read_is_header = true;
 								peer.set_their_node_id(their_node_id);
@@ -1548,7 +2004,14 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 				log_debug!(self.logger, "Onion Message Handler decided we couldn't communicate with peer {}", log_pubkey!(their_node_id));
 				return Err(PeerHandleError { }.into());
 			}
+			if let Some(address) = &peer_lock.their_net_address {
+				self.message_handler.chan_handler.peer_address_seen(&their_node_id, address);
+			}
 
+			peer_lock.timer_config = self.peer_timer_config(&their_node_id);
+			peer_lock.fingerprint.features = msg.features.clone();
+			peer_lock.fingerprint.sent_networks = msg.networks.is_some();
+			peer_lock.fingerprint.sent_remote_address = msg.remote_network_address.is_some();
 			peer_lock.their_features = Some(msg.features);
 			return Ok(None);
 		} else if peer_lock.their_features.is_none() {
@@ -1556,7 +2019,10 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			return Err(PeerHandleError { }.into());
 		}
 
-		if let wire::Message::GossipTimestampFilter(_msg) = message {
+		if let wire::Message::GossipTimestampFilter(msg) = message {
+			if peer_lock.fingerprint.gossip_timestamp_filter_first_timestamp.is_none() {
+				peer_lock.fingerprint.gossip_timestamp_filter_first_timestamp = Some(msg.first_timestamp);
+			}
 			// When supporting gossip messages, start inital gossip sync only after we receive
 			// a GossipTimestampFilter
 			if peer_lock.their_features.as_ref().unwrap().supports_gossip_queries() &&
@@ -1571,6 +2037,26 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			peer_lock.received_channel_announce_since_backlogged = true;
 		}
 
+		match message {
+			wire::Message::Ping(ref msg) => {
+				let now = peer_lock.ticks_since_connected;
+				if let Some(last_ping_tick) = peer_lock.last_ping_received_tick {
+					peer_lock.fingerprint.last_ping_interval_ticks = Some(now - last_ping_tick);
+				}
+				peer_lock.last_ping_received_tick = Some(now);
+				peer_lock.fingerprint.pings_received += 1;
+				peer_lock.fingerprint.last_ping_ponglen = Some(msg.ponglen);
+				peer_lock.fingerprint.last_ping_byteslen = Some(msg.byteslen);
+			},
+			wire::Message::QueryChannelRange(_) => {
+				peer_lock.fingerprint.sent_query_channel_range = true;
+			},
+			wire::Message::Unknown(_) if !message.is_even() => {
+				peer_lock.fingerprint.unknown_odd_messages_received += 1;
+			},
+			_ => {},
+		}
+
 		mem::drop(peer_lock);
 
 		if is_gossip_msg(message.type_id()) {
@@ -1610,14 +2096,32 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 				let mut peer_lock = peer_mutex.lock().unwrap();
 				peer_lock.awaiting_pong_timer_tick_intervals = 0;
 				peer_lock.msgs_sent_since_pong = 0;
+				let ping_sent_at = peer_lock.pings_sent_at.pop_front();
+				#[cfg(not(feature = "no-std"))] {
+					if let (Some(sent_at), Some((_, node_id))) = (ping_sent_at, &peer_lock.their_node_id) {
+						if let Some(peer_latencies) = &*self.peer_latencies.lock().unwrap() {
+							peer_latencies.record_round_trip(node_id, sent_at.elapsed());
+						}
+					}
+				}
+				#[cfg(feature = "no-std")]
+				let _ = ping_sent_at;
 			},
 
 			// Channel messages:
 			wire::Message::OpenChannel(msg) => {
-				self.message_handler.chan_handler.handle_open_channel(&their_node_id, &msg);
+				if self.is_peer_allowed(&their_node_id, PeerAccessScope::ChannelOpen) {
+					self.message_handler.chan_handler.handle_open_channel(&their_node_id, &msg);
+				} else {
+					self.reject_denied_channel_open(peer_mutex, &their_node_id, msg.temporary_channel_id);
+				}
 			},
 			wire::Message::OpenChannelV2(msg) => {
-				self.message_handler.chan_handler.handle_open_channel_v2(&their_node_id, &msg);
+				if self.is_peer_allowed(&their_node_id, PeerAccessScope::ChannelOpen) {
+					self.message_handler.chan_handler.handle_open_channel_v2(&their_node_id, &msg);
+				} else {
+					self.reject_denied_channel_open(peer_mutex, &their_node_id, msg.temporary_channel_id);
+				}
 			},
 			wire::Message::AcceptChannel(msg) => {
 				self.message_handler.chan_handler.handle_accept_channel(&their_node_id, &msg);
@@ -1695,6 +2199,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			wire::Message::UpdateFee(msg) => {
 				self.message_handler.chan_handler.handle_update_fee(&their_node_id, &msg);
 			},
+			wire::Message::Yield(msg) => {
+				self.message_handler.chan_handler.handle_yield(&their_node_id, &msg);
+			},
 			wire::Message::ChannelReestablish(msg) => {
 				self.message_handler.chan_handler.handle_channel_reestablish(&their_node_id, &msg);
 			},
@@ -1710,6 +2217,14 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 				}
 				self.update_gossip_backlogged();
 			},
+			#[cfg(taproot)]
+			wire::Message::ChannelAnnouncement2(msg) => {
+				// Relaying of experimental announcements is not yet supported, so we only hand
+				// them to the route handler.
+				self.message_handler.route_handler.handle_channel_announcement_2(&msg)
+					.map_err(|e| -> MessageHandlingError { e.into() })?;
+				self.update_gossip_backlogged();
+			},
 			wire::Message::NodeAnnouncement(msg) => {
 				if self.message_handler.route_handler.handle_node_announcement(&msg)
 						.map_err(|e| -> MessageHandlingError { e.into() })? {
@@ -1740,7 +2255,11 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 
 			// Onion message:
 			wire::Message::OnionMessage(msg) => {
-				self.message_handler.onion_message_handler.handle_onion_message(&their_node_id, &msg);
+				if self.is_peer_allowed(&their_node_id, PeerAccessScope::OnionMessage) {
+					self.message_handler.onion_message_handler.handle_onion_message(&their_node_id, &msg);
+				} else {
+					log_trace!(self.logger, "Dropping onion message from peer {} denied by our access rules", log_pubkey!(their_node_id));
+				}
 			},
 
 			// Unknown messages:
@@ -1758,6 +2277,17 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 		Ok(should_forward)
 	}
 
+	/// Responds to a channel open from a peer our access rules deny channel opens from with an
+	/// error for the proposed channel.
+	fn reject_denied_channel_open(&self, peer_mutex: &Mutex<Peer>, their_node_id: &PublicKey, temporary_channel_id: [u8; 32]) {
+		log_debug!(self.logger, "Rejecting channel open from peer {} denied by our access rules", log_pubkey!(their_node_id));
+		let msg = msgs::ErrorMessage {
+			channel_id: temporary_channel_id,
+			data: "Channel opens from this peer are not allowed".to_owned(),
+		};
+		self.enqueue_message(&mut *peer_mutex.lock().unwrap(), &msg);
+	}
+
 	fn forward_broadcast_msg(&self, peers: &HashMap<Descriptor, Mutex<Peer>>, msg: &wire::Message<<<CMH as core::ops::Deref>::Target as wire::CustomMessageReader>::CustomMessage>, except_node: Option<&PublicKey>) {
 		match msg {
 			wire::Message::ChannelAnnouncement(ref msg) => {
@@ -2046,6 +2576,12 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 									log_bytes!(msg.channel_id));
 							self.enqueue_message(&mut *get_peer_for_forwarding!(node_id), msg);
 						},
+						MessageSendEvent::SendYield { ref node_id, ref msg } => {
+							log_debug!(self.logger, "Handling SendYield event in peer_handler for node {} for channel {}",
+									log_pubkey!(node_id),
+									log_bytes!(msg.channel_id));
+							self.enqueue_message(&mut *get_peer_for_forwarding!(node_id), msg);
+						},
 						MessageSendEvent::SendClosingSigned { ref node_id, ref msg } => {
 							log_debug!(self.logger, "Handling SendClosingSigned event in peer_handler for node {} for channel {}",
 									log_pubkey!(node_id),
@@ -2236,10 +2772,19 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			log_trace!(self.logger, "Disconnecting peer with id {} due to {}", node_id, reason);
 			self.message_handler.chan_handler.peer_disconnected(&node_id);
 			self.message_handler.onion_message_handler.peer_disconnected(&node_id);
+			self.forget_peer_latency(&node_id);
 		}
 		descriptor.disconnect_socket();
 	}
 
+	/// Drops the round trips recorded for a peer we've disconnected from, as we only measure
+	/// latencies to connected peers and they may be very different once it reconnects.
+	fn forget_peer_latency(&self, node_id: &PublicKey) {
+		if let Some(peer_latencies) = &*self.peer_latencies.lock().unwrap() {
+			peer_latencies.remove_node(&NodeId::from_pubkey(node_id));
+		}
+	}
+
 	fn disconnect_event_internal(&self, descriptor: &Descriptor) {
 		let mut peers = self.peers.write().unwrap();
 		let peer_option = peers.remove(descriptor);
@@ -2258,6 +2803,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 					if !peer.handshake_complete() { return; }
 					self.message_handler.chan_handler.peer_disconnected(&node_id);
 					self.message_handler.onion_message_handler.peer_disconnected(&node_id);
+					self.forget_peer_latency(&node_id);
 				}
 			}
 		};
@@ -2291,6 +2837,15 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 		}
 	}
 
+	/// Gets the timer behavior for the given peer based on whether we have channels with it.
+	fn peer_timer_config(&self, their_node_id: &PublicKey) -> PeerTimerConfig {
+		if self.message_handler.chan_handler.has_channels_with_peer(their_node_id) {
+			self.config.channel_peers
+		} else {
+			self.config.other_peers
+		}
+	}
+
 	/// This is called when we're blocked on sending additional gossip messages until we receive a
 	/// pong. If we aren't waiting on a pong, we take this opportunity to send a ping (setting
 	/// `awaiting_pong_timer_tick_intervals` to a special flag value to indicate this).
@@ -2301,6 +2856,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 				ponglen: 0,
 				byteslen: 64,
 			};
+			peer.pings_sent_at.push_back(ConfiguredTime::now());
 			self.enqueue_message(peer, &ping);
 		}
 	}
@@ -2309,14 +2865,20 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 	/// pings.
 	///
 	/// This may be called on any timescale you want, however, roughly once every ten seconds is
-	/// preferred. The call rate determines both how often we send a ping to our peers and how much
-	/// time they have to respond before we disconnect them.
+	/// preferred. The call rate, together with the [`PeerManagerConfig`], determines both how often
+	/// we send a ping to our peers and how much time they have to respond before we disconnect
+	/// them.
+	///
+	/// Also calls [`OnionMessageHandler::timer_tick_occurred`].
 	///
 	/// May call [`send_data`] on all [`SocketDescriptor`]s. Thus, be very careful with reentrancy
 	/// issues!
 	///
 	/// [`send_data`]: SocketDescriptor::send_data
 	pub fn timer_tick_occurred(&self) {
+		self.message_handler.onion_message_handler.timer_tick_occurred();
+		self.access_rules.lock().unwrap().prune_expired(Self::access_rules_time());
+
 		let mut descriptors_needing_disconnect = Vec::new();
 		{
 			let peers_lock = self.peers.read().unwrap();
@@ -2342,6 +2904,8 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 				}
 				debug_assert!(peer.channel_encryptor.is_ready_for_encryption());
 				debug_assert!(peer.their_node_id.is_some());
+				peer.timer_config = self.peer_timer_config(&peer.their_node_id.unwrap().0);
+				peer.ticks_since_connected += 1;
 
 				loop { // Used as a `goto` to skip writing a Ping message.
 					if peer.awaiting_pong_timer_tick_intervals == -1 {
@@ -2353,7 +2917,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 
 					if (peer.awaiting_pong_timer_tick_intervals > 0 && !peer.received_message_since_timer_tick)
 						|| peer.awaiting_pong_timer_tick_intervals as u64 >
-							MAX_BUFFER_DRAIN_TICK_INTERVALS_PER_PEER as u64 * peers_lock.len() as u64
+							peer.timer_config.pong_timeout_ticks(peers_lock.len())
 					{
 						descriptors_needing_disconnect.push(descriptor.clone());
 						break;
@@ -2365,11 +2929,18 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 						break;
 					}
 
+					peer.ticks_since_ping = peer.ticks_since_ping.saturating_add(1);
+					if peer.ticks_since_ping < peer.timer_config.ping_interval_ticks {
+						break;
+					}
+
+					peer.ticks_since_ping = 0;
 					peer.awaiting_pong_timer_tick_intervals = 1;
 					let ping = msgs::Ping {
 						ponglen: 0,
 						byteslen: 64,
 					};
+					peer.pings_sent_at.push_back(ConfiguredTime::now());
 					self.enqueue_message(&mut *peer, &ping);
 					break;
 				}
@@ -2433,7 +3004,7 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			| self.message_handler.route_handler.provided_node_features()
 			| self.message_handler.onion_message_handler.provided_node_features()
 			| self.message_handler.custom_message_handler.provided_node_features();
-		let announcement = msgs::UnsignedNodeAnnouncement {
+		let mut announcement = msgs::UnsignedNodeAnnouncement {
 			features,
 			timestamp: self.last_node_announcement_serial.fetch_add(1, Ordering::AcqRel),
 			node_id: NodeId::from_pubkey(&self.node_signer.get_node_id(Recipient::Node).unwrap()),
@@ -2443,6 +3014,9 @@ impl<Descriptor: SocketDescriptor, CM: Deref, RM: Deref, OM: Deref, L: Deref, CM
 			excess_address_data: Vec::new(),
 			excess_data: Vec::new(),
 		};
+		if let Some(rotation) = self.node_key_rotation.lock().unwrap().as_ref() {
+			announcement.append_node_key_rotation(rotation);
+		}
 		let node_announce_sig = match self.node_signer.sign_gossip_message(
 			msgs::UnsignedGossipMessage::NodeAnnouncement(&announcement)
 		) {
@@ -2473,16 +3047,25 @@ fn is_gossip_msg(type_id: u16) -> bool {
 		msgs::ReplyChannelRange::TYPE |
 		msgs::QueryShortChannelIds::TYPE |
 		msgs::ReplyShortChannelIdsEnd::TYPE => true,
+		#[cfg(taproot)]
+		msgs::ChannelAnnouncement2::TYPE => true,
 		_ => false
 	}
 }
 
+/// Returns whether messages of the given type may be queued behind channel messages which are
+/// enqueued after them, see [`Peer::low_priority_outbound_buffer`].
+fn is_low_priority_msg(type_id: u16) -> bool {
+	is_gossip_msg(type_id) || type_id == msgs::OnionMessage::TYPE
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::sign::{NodeSigner, Recipient};
 	use crate::events;
 	use crate::io;
 	use crate::ln::features::{InitFeatures, NodeFeatures};
+	use crate::ln::peer_access::{PeerAccessAction, PeerAccessRule, PeerAccessScope, PeerAccessTarget};
 	use crate::ln::peer_channel_encryptor::PeerChannelEncryptor;
 	use crate::ln::peer_handler::{CustomMessageHandler, PeerManager, MessageHandler, SocketDescriptor, IgnoringMessageHandler, filter_addresses};
 	use crate::ln::{msgs, wire};
@@ -2491,7 +3074,7 @@ mod tests {
 
 	use bitcoin::Network;
 	use bitcoin::blockdata::constants::ChainHash;
-	use bitcoin::secp256k1::{PublicKey, SecretKey};
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 
 	use crate::prelude::*;
 	use crate::sync::{Arc, Mutex};
@@ -2826,6 +3409,60 @@ mod tests {
 		}
 	}
 
+	#[test]
+	fn test_connection_to_previous_node_id() {
+		// Check that after rotating its node key, a peer still accepts connections from peers which
+		// address it by its previous node id, as long as its signer still reports it.
+		let mut cfgs = create_peermgr_cfgs(2);
+		let secp_ctx = Secp256k1::new();
+		let previous_node_secret = SecretKey::from_slice(&[42; 32]).unwrap();
+		let previous_node_id = PublicKey::from_secret_key(&secp_ctx, &previous_node_secret);
+		cfgs[0].node_signer = test_utils::TestNodeSigner::with_previous_node_secret(
+			SecretKey::from_slice(&[99; 32]).unwrap(), previous_node_secret);
+		let peers = create_network(2, &cfgs);
+		let id_b = peers[1].node_signer.get_node_id(Recipient::Node).unwrap();
+
+		// Connections to any other node id are still rejected.
+		let other_node_id = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[98; 32]).unwrap());
+		let mut fd_a = FileDescriptor {
+			fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())),
+			disconnect: Arc::new(AtomicBool::new(false)),
+		};
+		let fd_b = FileDescriptor {
+			fd: 1, outbound_data: Arc::new(Mutex::new(Vec::new())),
+			disconnect: Arc::new(AtomicBool::new(false)),
+		};
+		let initial_data = peers[1].new_outbound_connection(other_node_id, fd_b.clone(), None).unwrap();
+		peers[0].new_inbound_connection(fd_a.clone(), None).unwrap();
+		assert!(peers[0].read_event(&mut fd_a, &initial_data).is_err());
+		peers[1].socket_disconnected(&fd_b);
+
+		let mut fd_a = FileDescriptor {
+			fd: 2, outbound_data: Arc::new(Mutex::new(Vec::new())),
+			disconnect: Arc::new(AtomicBool::new(false)),
+		};
+		let mut fd_b = FileDescriptor {
+			fd: 2, outbound_data: Arc::new(Mutex::new(Vec::new())),
+			disconnect: Arc::new(AtomicBool::new(false)),
+		};
+		let initial_data = peers[1].new_outbound_connection(previous_node_id, fd_b.clone(), None).unwrap();
+		peers[0].new_inbound_connection(fd_a.clone(), None).unwrap();
+		assert_eq!(peers[0].read_event(&mut fd_a, &initial_data).unwrap(), false);
+		peers[0].process_events();
+
+		let a_data = fd_a.outbound_data.lock().unwrap().split_off(0);
+		assert_eq!(peers[1].read_event(&mut fd_b, &a_data).unwrap(), false);
+		peers[1].process_events();
+		let b_data = fd_b.outbound_data.lock().unwrap().split_off(0);
+		assert_eq!(peers[0].read_event(&mut fd_a, &b_data).unwrap(), false);
+		peers[0].process_events();
+		let a_data = fd_a.outbound_data.lock().unwrap().split_off(0);
+		assert_eq!(peers[1].read_event(&mut fd_b, &a_data).unwrap(), false);
+
+		assert!(peers[0].get_peer_node_ids().contains(&(id_b, None)));
+		assert!(peers[1].get_peer_node_ids().contains(&(previous_node_id, None)));
+	}
+
 	#[test]
 	fn test_disconnect_peer() {
 		// Simple test which builds a network of PeerManager, connects and brings them to NoiseState::Finished and
@@ -2937,6 +3574,217 @@ mod tests {
 		assert_eq!(peers[0].peers.read().unwrap().len(), 0);
 	}
 
+	#[test]
+	fn test_peer_fingerprint() {
+		// Check that we record what peers send us in their `PeerFingerprint`.
+		let cfgs = create_peermgr_cfgs(3);
+		let peers = create_network(2, &cfgs);
+		let id_a = peers[0].node_signer.get_node_id(Recipient::Node).unwrap();
+		let id_b = peers[1].node_signer.get_node_id(Recipient::Node).unwrap();
+		let (mut fd_a, fd_b) = establish_connection(&peers[0], &peers[1]);
+
+		let fingerprint = peers[0].peer_fingerprint(&id_b).unwrap();
+		assert_eq!(fingerprint.features, peers[1].init_features(&id_a));
+		assert_eq!(fingerprint.pings_received, 0);
+		assert_eq!(fingerprint.last_ping_byteslen, None);
+		assert_eq!(peers[0].list_peer_fingerprints(), vec![(id_b, fingerprint)]);
+
+		let id_c = cfgs[2].node_signer.get_node_id(Recipient::Node).unwrap();
+		assert!(peers[0].peer_fingerprint(&id_c).is_none());
+
+		// Have peers[1] ping peers[0] and check it shows up.
+		peers[1].timer_tick_occurred();
+		let b_data = fd_b.outbound_data.lock().unwrap().split_off(0);
+		assert_eq!(peers[0].read_event(&mut fd_a, &b_data).unwrap(), false);
+
+		let fingerprint = peers[0].peer_fingerprint(&id_b).unwrap();
+		assert_eq!(fingerprint.pings_received, 1);
+		assert_eq!(fingerprint.last_ping_ponglen, Some(0));
+		assert_eq!(fingerprint.last_ping_byteslen, Some(64));
+		assert_eq!(fingerprint.last_ping_interval_ticks, None);
+	}
+
+	#[test]
+	fn test_new_with_onion_messenger() {
+		// Check that the onion messenger constructed along with a `SimpleArcPeerManager` is wired in
+		// as its onion message handler.
+		use crate::chain::{BestBlock, chainmonitor};
+		use crate::ln::channelmanager::{ChainParameters, SimpleArcChannelManager};
+		use crate::ln::peer_handler::SimpleArcPeerManager;
+		use crate::routing::gossip::{NetworkGraph, P2PGossipSync};
+		use crate::routing::router::DefaultRouter;
+		use crate::routing::scoring::{ProbabilisticScorer, ProbabilisticScoringDecayParameters, ProbabilisticScoringFeeParameters};
+		use crate::sign::{InMemorySigner, KeysManager};
+		use crate::util::config::UserConfig;
+
+		let network = Network::Testnet;
+		let logger = Arc::new(test_utils::TestLogger::new());
+		let broadcaster = Arc::new(test_utils::TestBroadcaster::new(network));
+		let fee_estimator = Arc::new(test_utils::TestFeeEstimator { sat_per_kw: Mutex::new(253) });
+		let keys_manager = Arc::new(KeysManager::new(&[42; 32], 42, 42));
+		let chain_monitor: Arc<chainmonitor::ChainMonitor<InMemorySigner, Arc<test_utils::TestChainSource>, _, _, _, _>> =
+			Arc::new(chainmonitor::ChainMonitor::new(None, Arc::clone(&broadcaster), Arc::clone(&logger),
+				Arc::clone(&fee_estimator), Arc::new(test_utils::TestPersister::new())));
+		let network_graph = Arc::new(NetworkGraph::new(network, Arc::clone(&logger)));
+		let scorer = Arc::new(Mutex::new(ProbabilisticScorer::new(
+			ProbabilisticScoringDecayParameters::default(), Arc::clone(&network_graph), Arc::clone(&logger))));
+		let router = Arc::new(DefaultRouter::new(Arc::clone(&network_graph), Arc::clone(&logger), [42; 32],
+			scorer, ProbabilisticScoringFeeParameters::default()));
+		let channel_manager: Arc<SimpleArcChannelManager<_, _, _, test_utils::TestLogger>> = Arc::new(
+			crate::ln::channelmanager::ChannelManager::new(fee_estimator, chain_monitor, broadcaster, router,
+				Arc::clone(&logger), Arc::clone(&keys_manager), Arc::clone(&keys_manager), Arc::clone(&keys_manager),
+				UserConfig::default(), ChainParameters { network, best_block: BestBlock::from_network(network) }, 0));
+		let gossip_sync = Arc::new(P2PGossipSync::new(
+			Arc::clone(&network_graph), None::<Arc<test_utils::TestChainSource>>, Arc::clone(&logger)));
+
+		let (peer_manager, onion_messenger): (SimpleArcPeerManager<FileDescriptor, _, _, _, _, _>, _) =
+			SimpleArcPeerManager::new_with_onion_messenger(channel_manager, gossip_sync, keys_manager, logger, 0, &[1; 32]);
+		let their_node_id = PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[2; 32]).unwrap());
+		assert!(peer_manager.init_features(&their_node_id).supports_onion_messages());
+		assert!(Arc::ptr_eq(&peer_manager.message_handler.onion_message_handler, &onion_messenger));
+	}
+
+	#[test]
+	fn test_peer_access_rules() {
+		// Check that peers denied by our access rules are disconnected and may not connect to us or
+		// be connected to.
+		let cfgs = create_peermgr_cfgs(2);
+		let peers = create_network(2, &cfgs);
+		let id_a = peers[0].node_signer.get_node_id(Recipient::Node).unwrap();
+		let id_b = peers[1].node_signer.get_node_id(Recipient::Node).unwrap();
+		let (_, established_fd_b) = establish_connection(&peers[0], &peers[1]);
+
+		// Only denying onion messages leaves the connection up.
+		peers[0].add_access_rule(PeerAccessRule {
+			target: PeerAccessTarget::Node(id_b), scope: PeerAccessScope::OnionMessage,
+			action: PeerAccessAction::Deny, priority: 0, expires_at: None,
+		});
+		assert_eq!(peers[0].peers.read().unwrap().len(), 1);
+		assert!(!peers[0].is_peer_allowed(&id_b, PeerAccessScope::OnionMessage));
+		assert!(peers[0].is_peer_allowed(&id_b, PeerAccessScope::Connection));
+
+		// A wildcard deny disconnects the peer, unless it is allowed at a higher priority.
+		peers[0].add_access_rule(PeerAccessRule {
+			target: PeerAccessTarget::Node(id_b), scope: PeerAccessScope::Connection,
+			action: PeerAccessAction::Allow, priority: 10, expires_at: None,
+		});
+		peers[0].add_access_rule(PeerAccessRule {
+			target: PeerAccessTarget::AnyNode, scope: PeerAccessScope::All,
+			action: PeerAccessAction::Deny, priority: 0, expires_at: None,
+		});
+		assert_eq!(peers[0].peers.read().unwrap().len(), 1);
+		assert!(peers[0].remove_access_rule(&PeerAccessTarget::Node(id_b), PeerAccessScope::Connection));
+		assert_eq!(peers[0].peers.read().unwrap().len(), 0);
+		assert_eq!(peers[0].list_access_rules().len(), 2);
+		peers[1].socket_disconnected(&established_fd_b);
+
+		// We refuse to connect to the denied peer...
+		let fd = FileDescriptor {
+			fd: 2, outbound_data: Arc::new(Mutex::new(Vec::new())),
+			disconnect: Arc::new(AtomicBool::new(false)),
+		};
+		assert!(peers[0].new_outbound_connection(id_b, fd, None).is_err());
+
+		// ...and reject its connections once the handshake reveals its node id.
+		let mut fd_a = FileDescriptor {
+			fd: 3, outbound_data: Arc::new(Mutex::new(Vec::new())),
+			disconnect: Arc::new(AtomicBool::new(false)),
+		};
+		let mut fd_b = FileDescriptor {
+			fd: 3, outbound_data: Arc::new(Mutex::new(Vec::new())),
+			disconnect: Arc::new(AtomicBool::new(false)),
+		};
+		let initial_data = peers[1].new_outbound_connection(id_a, fd_b.clone(), None).unwrap();
+		peers[0].new_inbound_connection(fd_a.clone(), None).unwrap();
+		assert_eq!(peers[0].read_event(&mut fd_a, &initial_data).unwrap(), false);
+		peers[0].process_events();
+		let a_data = fd_a.outbound_data.lock().unwrap().split_off(0);
+		assert_eq!(peers[1].read_event(&mut fd_b, &a_data).unwrap(), false);
+		peers[1].process_events();
+		let b_data = fd_b.outbound_data.lock().unwrap().split_off(0);
+		assert!(peers[0].read_event(&mut fd_a, &b_data).is_err());
+		peers[1].socket_disconnected(&fd_b);
+
+		// Once the rules are cleared, the peers may connect again.
+		peers[0].set_access_rules(Default::default());
+		assert!(peers[0].list_access_rules().is_empty());
+		establish_connection(&peers[0], &peers[1]);
+	}
+
+	#[test]
+	fn test_ping_round_trip_latency() {
+		// Check that the time peers take to respond to our pings is recorded in our `PeerLatencies`.
+		use crate::routing::gossip::NodeId;
+		use crate::routing::scoring::PeerLatencies;
+		use crate::util::time::tests::SinceEpoch;
+		use core::time::Duration;
+
+		let cfgs = create_peermgr_cfgs(2);
+		let peers = create_network(2, &cfgs);
+		let id_b = peers[1].node_signer.get_node_id(Recipient::Node).unwrap();
+		let peer_latencies = Arc::new(PeerLatencies::new());
+		peers[0].set_peer_latencies(Some(Arc::clone(&peer_latencies)));
+		let (mut fd_a, mut fd_b) = establish_connection(&peers[0], &peers[1]);
+
+		let mut ping_round_trip = |round_trip: Duration| {
+			peers[0].timer_tick_occurred();
+			let a_data = fd_a.outbound_data.lock().unwrap().split_off(0);
+			assert_eq!(peers[1].read_event(&mut fd_b, &a_data).unwrap(), false);
+			peers[1].process_events();
+			SinceEpoch::advance(round_trip);
+			let b_data = fd_b.outbound_data.lock().unwrap().split_off(0);
+			assert_eq!(peers[0].read_event(&mut fd_a, &b_data).unwrap(), false);
+		};
+
+		ping_round_trip(Duration::from_millis(160));
+		assert_eq!(peer_latencies.round_trip(&NodeId::from_pubkey(&id_b)), Some(Duration::from_millis(160)));
+
+		// Later observations are smoothed with earlier ones.
+		ping_round_trip(Duration::from_millis(80));
+		assert_eq!(peer_latencies.round_trip(&NodeId::from_pubkey(&id_b)), Some(Duration::from_millis(150)));
+		assert_eq!(peers[0].peers.read().unwrap().len(), 1);
+
+		// An unsolicited pong isn't measured against any ping.
+		peers[1].enqueue_message(&mut *peers[1].peers.read().unwrap().get(&fd_b).unwrap().lock().unwrap(), &msgs::Pong { byteslen: 64 });
+		peers[1].process_events();
+		let b_data = fd_b.outbound_data.lock().unwrap().split_off(0);
+		assert_eq!(peers[0].read_event(&mut fd_a, &b_data).unwrap(), false);
+		assert_eq!(peer_latencies.round_trip(&NodeId::from_pubkey(&id_b)), Some(Duration::from_millis(150)));
+
+		// Latencies are forgotten once the peer disconnects.
+		peers[0].socket_disconnected(&fd_a);
+		assert_eq!(peer_latencies.round_trip(&NodeId::from_pubkey(&id_b)), None);
+	}
+
+	#[test]
+	fn test_configured_ping_interval() {
+		// Check that peers are only pinged every `ping_interval_ticks` and are still disconnected if
+		// they fail to respond.
+		let cfgs = create_peermgr_cfgs(2);
+		let mut peers = create_network(2, &cfgs);
+		let config = PeerManagerConfig {
+			other_peers: PeerTimerConfig { ping_interval_ticks: 3, ..Default::default() },
+			..Default::default()
+		};
+		let msg_handler = MessageHandler {
+			chan_handler: &cfgs[0].chan_handler, route_handler: &cfgs[0].routing_handler,
+			onion_message_handler: IgnoringMessageHandler {}, custom_message_handler: &cfgs[0].custom_handler
+		};
+		peers[0] = PeerManager::new_with_config(msg_handler, config, 0, &[0; 32], &cfgs[0].logger, &cfgs[0].node_signer);
+		let (fd_a, _fd_b) = establish_connection(&peers[0], &peers[1]);
+
+		for _ in 0..2 {
+			peers[0].timer_tick_occurred();
+			assert!(fd_a.outbound_data.lock().unwrap().is_empty());
+		}
+		peers[0].timer_tick_occurred();
+		assert!(!fd_a.outbound_data.lock().unwrap().split_off(0).is_empty());
+		assert_eq!(peers[0].peers.read().unwrap().len(), 1);
+
+		peers[0].timer_tick_occurred();
+		assert_eq!(peers[0].peers.read().unwrap().len(), 0);
+	}
+
 	#[test]
 	fn test_do_attempt_write_data() {
 		// Create 2 peers with custom TestRoutingMessageHandlers and connect them.
@@ -2979,6 +3827,69 @@ mod tests {
 		assert_eq!(cfgs[1].routing_handler.chan_anns_recvd.load(Ordering::Acquire), 54);
 	}
 
+	#[test]
+	fn test_channel_msgs_prioritized_over_gossip() {
+		// Checks that when a peer's outbound buffer is backed up, gossip messages enqueued before a
+		// `revoke_and_ack` are held back until the `revoke_and_ack` has been written out.
+		let cfgs = create_peermgr_cfgs(2);
+		let peers = create_network(2, &cfgs);
+		let (mut fd_a, mut fd_b) = establish_connection(&peers[0], &peers[1]);
+		let their_id = peers[1].node_signer.get_node_id(Recipient::Node).unwrap();
+
+		// Simulate a socket which is unable to accept any more data.
+		peers[0].peers.read().unwrap().get(&fd_a).unwrap().lock().unwrap().awaiting_write_event = true;
+
+		let shutdown = msgs::Shutdown { channel_id: [42; 32], scriptpubkey: bitcoin::Script::new() };
+		let reply_channel_range = msgs::ReplyChannelRange {
+			chain_hash: bitcoin::blockdata::constants::genesis_block(Network::Testnet).header.block_hash(),
+			first_blocknum: 0, number_of_blocks: 1, sync_complete: true, short_channel_ids: vec![],
+		};
+		let revoke_and_ack = msgs::RevokeAndACK {
+			channel_id: [42; 32], per_commitment_secret: [43; 32], next_per_commitment_point: their_id,
+			#[cfg(taproot)]
+			next_local_nonce: None,
+		};
+		{
+			let mut pending_events = cfgs[0].chan_handler.pending_events.lock().unwrap();
+			pending_events.push(events::MessageSendEvent::SendShutdown {
+				node_id: their_id, msg: shutdown.clone(),
+			});
+			pending_events.push(events::MessageSendEvent::SendReplyChannelRange {
+				node_id: their_id, msg: reply_channel_range,
+			});
+			pending_events.push(events::MessageSendEvent::SendRevokeAndACK {
+				node_id: their_id, msg: revoke_and_ack.clone(),
+			});
+		}
+		peers[0].process_events();
+		assert!(fd_a.outbound_data.lock().unwrap().is_empty());
+
+		{
+			let peers_lock = peers[0].peers.read().unwrap();
+			let peer = peers_lock.get(&fd_a).unwrap().lock().unwrap();
+			// The `shutdown` and `revoke_and_ack` are queued for the wire, with the
+			// `reply_channel_range` held back behind them.
+			assert!(peer.pending_outbound_buffer.len() >= 2);
+			assert_eq!(peer.low_priority_outbound_buffer.len(), 1);
+		}
+
+		cfgs[1].chan_handler.expect_receive_msg(wire::Message::Shutdown(shutdown));
+		cfgs[1].chan_handler.expect_receive_msg(wire::Message::RevokeAndACK(revoke_and_ack));
+		peers[0].write_buffer_space_avail(&mut fd_a).unwrap();
+
+		{
+			let peers_lock = peers[0].peers.read().unwrap();
+			let peer = peers_lock.get(&fd_a).unwrap().lock().unwrap();
+			assert!(peer.pending_outbound_buffer.is_empty());
+			assert!(peer.low_priority_outbound_buffer.is_empty());
+		}
+
+		// The peer is able to decrypt everything, implying the messages were encrypted in the order
+		// in which they were written.
+		let a_data = fd_a.outbound_data.lock().unwrap().split_off(0);
+		assert_eq!(peers[1].read_event(&mut fd_b, &a_data).unwrap(), false);
+	}
+
 	#[test]
 	fn test_handshake_timeout() {
 		// Tests that we time out a peer still waiting on handshake completion after a full timer
@@ -0,0 +1,129 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Persistent per-peer metadata which policy logic can consult when deciding how to treat a peer.
+//!
+//! Metadata is stored in and persisted with the [`ChannelManager`], and can be queried from the
+//! [`PeerManager`] as well.
+//!
+//! [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+//! [`PeerManager`]: crate::ln::peer_handler::PeerManager
+
+use crate::ln::msgs::NetAddress;
+
+use crate::prelude::*;
+
+/// The maximum number of addresses kept in [`PeerMetadata::last_seen_addresses`].
+pub const MAX_LAST_SEEN_ADDRESSES: usize = 4;
+
+/// How much we trust a peer, as decided by the user.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrustTier {
+	/// The peer is not trusted at all, e.g. as it previously misbehaved.
+	Untrusted,
+	/// The peer is treated like any other. This is the default for new peers.
+	Default,
+	/// The peer is trusted, e.g. as it is operated by us or a business partner.
+	Trusted,
+}
+
+impl Default for TrustTier {
+	fn default() -> Self { TrustTier::Default }
+}
+
+impl_writeable_tlv_based_enum!(TrustTier,
+	(0, Untrusted) => {},
+	(2, Default) => {},
+	(4, Trusted) => {},
+;);
+
+/// Metadata about a peer, set via [`ChannelManager::set_peer_metadata`] or
+/// [`ChannelManager::update_peer_metadata`].
+///
+/// [`ChannelManager::set_peer_metadata`]: crate::ln::channelmanager::ChannelManager::set_peer_metadata
+/// [`ChannelManager::update_peer_metadata`]: crate::ln::channelmanager::ChannelManager::update_peer_metadata
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerMetadata {
+	/// A human-readable label for the peer.
+	pub label: Option<String>,
+	/// How much the peer is trusted.
+	pub trust_tier: TrustTier,
+	/// The addresses we most recently connected to the peer on, most recent first.
+	///
+	/// This is maintained automatically by the [`PeerManager`] for peers which have metadata or
+	/// channels with us, and holds at most [`MAX_LAST_SEEN_ADDRESSES`] entries.
+	///
+	/// [`PeerManager`]: crate::ln::peer_handler::PeerManager
+	pub last_seen_addresses: Vec<NetAddress>,
+	/// Arbitrary application-specific data. LDK never interprets this.
+	pub custom_data: Vec<u8>,
+}
+
+impl PeerMetadata {
+	/// Records that we saw the peer at the given address, moving it to the front of
+	/// [`Self::last_seen_addresses`] and dropping the oldest address if we'd exceed
+	/// [`MAX_LAST_SEEN_ADDRESSES`].
+	///
+	/// Returns whether [`Self::last_seen_addresses`] changed, i.e. `false` if `address` already was
+	/// the most recently seen address.
+	pub fn record_address(&mut self, address: NetAddress) -> bool {
+		if self.last_seen_addresses.first() == Some(&address) { return false; }
+		self.last_seen_addresses.retain(|addr| *addr != address);
+		self.last_seen_addresses.insert(0, address);
+		self.last_seen_addresses.truncate(MAX_LAST_SEEN_ADDRESSES);
+		true
+	}
+}
+
+impl_writeable_tlv_based!(PeerMetadata, {
+	(0, label, option),
+	(2, trust_tier, required),
+	(4, last_seen_addresses, optional_vec),
+	(6, custom_data, optional_vec),
+});
+
+#[cfg(test)]
+mod tests {
+	use super::{MAX_LAST_SEEN_ADDRESSES, PeerMetadata, TrustTier};
+	use crate::ln::msgs::NetAddress;
+	use crate::util::ser::{Readable, Writeable};
+
+	#[test]
+	fn records_most_recent_addresses() {
+		let mut metadata = PeerMetadata::default();
+		for port in 0..MAX_LAST_SEEN_ADDRESSES as u16 + 2 {
+			metadata.record_address(NetAddress::IPv4 { addr: [127, 0, 0, 1], port });
+		}
+		assert_eq!(metadata.last_seen_addresses.len(), MAX_LAST_SEEN_ADDRESSES);
+		assert_eq!(metadata.last_seen_addresses[0], NetAddress::IPv4 { addr: [127, 0, 0, 1], port: 5 });
+
+		// Seeing the most recent address again changes nothing.
+		assert!(!metadata.record_address(NetAddress::IPv4 { addr: [127, 0, 0, 1], port: 5 }));
+
+		// Seeing a known address again moves it to the front without duplicating it.
+		assert!(metadata.record_address(NetAddress::IPv4 { addr: [127, 0, 0, 1], port: 3 }));
+		assert_eq!(metadata.last_seen_addresses.len(), MAX_LAST_SEEN_ADDRESSES);
+		assert_eq!(metadata.last_seen_addresses[0], NetAddress::IPv4 { addr: [127, 0, 0, 1], port: 3 });
+		assert_eq!(metadata.last_seen_addresses[1], NetAddress::IPv4 { addr: [127, 0, 0, 1], port: 5 });
+	}
+
+	#[test]
+	fn metadata_serialization_roundtrip() {
+		let mut metadata = PeerMetadata {
+			label: Some("cold storage partner".to_owned()),
+			trust_tier: TrustTier::Trusted,
+			last_seen_addresses: Vec::new(),
+			custom_data: vec![1, 2, 3],
+		};
+		metadata.record_address(NetAddress::IPv4 { addr: [10, 0, 0, 1], port: 9735 });
+		let encoded = metadata.encode();
+		let decoded: PeerMetadata = Readable::read(&mut &encoded[..]).unwrap();
+		assert_eq!(decoded, metadata);
+	}
+}
@@ -11,13 +11,15 @@
 
 use crate::chain::{ChannelMonitorUpdateStatus, Watch};
 use crate::chain::chaininterface::LowerBoundedFeeEstimator;
-use crate::chain::channelmonitor::ChannelMonitor;
+use crate::chain::channelmonitor::{ANTI_REORG_DELAY, ChannelMonitor};
 use crate::sign::EntropySource;
 use crate::chain::transaction::OutPoint;
 use crate::events::{ClosureReason, Event, HTLCDestination, MessageSendEvent, MessageSendEventsProvider};
+use crate::ln::PaymentHash;
 use crate::ln::channelmanager::{ChannelManager, ChannelManagerReadArgs, PaymentId, RecipientOnionFields};
 use crate::ln::msgs;
 use crate::ln::msgs::{ChannelMessageHandler, RoutingMessageHandler, ErrorAction};
+use crate::ln::peer_metadata::TrustTier;
 use crate::util::enforcing_trait_impls::EnforcingSigner;
 use crate::util::test_utils;
 use crate::util::errors::APIError;
@@ -25,7 +27,9 @@ use crate::util::ser::{Writeable, ReadableArgs};
 use crate::util::config::UserConfig;
 use crate::util::string::UntrustedString;
 
+use bitcoin::{PackedLockTime, Script, Transaction, TxOut, WPubkeyHash};
 use bitcoin::hash_types::BlockHash;
+use bitcoin::hashes::Hash;
 
 use crate::prelude::*;
 use core::default::Default;
@@ -1101,3 +1105,81 @@ fn removed_payment_no_manager_persistence() {
 
 	expect_payment_failed!(nodes[0], payment_hash, false);
 }
+
+#[test]
+fn test_peer_metadata_persisted() {
+	let chanmon_cfgs = create_chanmon_cfgs(3);
+	let node_cfgs = create_node_cfgs(3, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(3, &node_cfgs, &[None, None, None]);
+	let persister: test_utils::TestPersister;
+	let new_chain_monitor: test_utils::TestChainMonitor;
+	let nodes_0_deserialized: ChannelManager<&test_utils::TestChainMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestRouter, &test_utils::TestLogger>;
+	let mut nodes = create_network(3, &node_cfgs, &node_chanmgrs);
+	let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+	// Addresses are only recorded for peers we have channels or metadata with.
+	let address = msgs::NetAddress::IPv4 { addr: [127, 0, 0, 1], port: 9735 };
+	nodes[0].node.peer_address_seen(&nodes[1].node.get_our_node_id(), &address);
+	nodes[0].node.peer_address_seen(&nodes[2].node.get_our_node_id(), &address);
+	assert_eq!(nodes[0].node.get_peer_metadata(&nodes[1].node.get_our_node_id()).unwrap().last_seen_addresses, vec![address.clone()]);
+	assert!(nodes[0].node.get_peer_metadata(&nodes[2].node.get_our_node_id()).is_none());
+
+	nodes[0].node.update_peer_metadata(&nodes[2].node.get_our_node_id(), |metadata| {
+		metadata.label = Some("market maker".to_owned());
+		metadata.trust_tier = TrustTier::Trusted;
+		metadata.custom_data = vec![42; 3];
+	});
+	nodes[0].node.peer_address_seen(&nodes[2].node.get_our_node_id(), &address);
+	let node_2_metadata = nodes[0].node.get_peer_metadata(&nodes[2].node.get_our_node_id()).unwrap();
+	assert_eq!(node_2_metadata.last_seen_addresses, vec![address.clone()]);
+	assert_eq!(nodes[0].node.list_peer_metadata().len(), 2);
+
+	let chan_0_monitor_serialized = get_monitor!(nodes[0], chan_id).encode();
+	reload_node!(nodes[0], nodes[0].node.encode(), &[&chan_0_monitor_serialized], persister, new_chain_monitor, nodes_0_deserialized);
+
+	assert_eq!(nodes[0].node.get_peer_metadata(&nodes[2].node.get_our_node_id()), Some(node_2_metadata));
+	assert_eq!(nodes[0].node.peer_metadata(&nodes[1].node.get_our_node_id()).unwrap().last_seen_addresses, vec![address]);
+
+	nodes[0].node.set_peer_metadata(&nodes[1].node.get_our_node_id(), None);
+	assert!(nodes[0].node.get_peer_metadata(&nodes[1].node.get_our_node_id()).is_none());
+}
+
+#[test]
+fn test_onchain_fallback_payment_persisted() {
+	// Checks that on-chain fallback payments are only surfaced once they're reorg-safe, and that
+	// both the watched scripts and payments still waiting for confirmations survive a restart.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let persister: test_utils::TestPersister;
+	let new_chain_monitor: test_utils::TestChainMonitor;
+	let nodes_0_deserialized: ChannelManager<&test_utils::TestChainMonitor, &test_utils::TestBroadcaster, &test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestKeysInterface, &test_utils::TestFeeEstimator, &test_utils::TestRouter, &test_utils::TestLogger>;
+	let mut nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	let fallback_script = Script::new_v0_p2wpkh(&WPubkeyHash::from_slice(&[42; 20]).unwrap());
+	let payment_hash = PaymentHash([42; 32]);
+	nodes[0].node.watch_onchain_fallback(fallback_script.clone(), payment_hash, Some(10_000_000));
+	let tx = Transaction {
+		version: 2, lock_time: PackedLockTime::ZERO, input: Vec::new(),
+		output: vec![TxOut { value: 10_000, script_pubkey: fallback_script.clone() }],
+	};
+
+	// A payment which is reorged out before it's reorg-safe is forgotten.
+	mine_transaction(&nodes[0], &tx);
+	disconnect_blocks(&nodes[0], 1);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY);
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+
+	mine_transaction(&nodes[0], &tx);
+	reload_node!(nodes[0], nodes[0].node.encode(), &[], persister, new_chain_monitor, nodes_0_deserialized);
+	connect_blocks(&nodes[0], ANTI_REORG_DELAY - 2);
+	assert!(nodes[0].node.get_and_clear_pending_events().is_empty());
+	connect_blocks(&nodes[0], 1);
+	assert_eq!(nodes[0].node.get_and_clear_pending_events(), vec![Event::OnchainFallbackPaymentReceived {
+		payment_hash,
+		outpoint: bitcoin::OutPoint { txid: tx.txid(), vout: 0 },
+		amount_satoshis: 10_000,
+		expected_amount_msat: Some(10_000_000),
+	}]);
+	assert!(!nodes[0].node.unwatch_onchain_fallback(&fallback_script));
+}
@@ -12,6 +12,8 @@ use crate::ln::features::InitFeatures;
 use crate::ln::msgs::DecodeError;
 use crate::util::ser::{Readable, Writeable, Writer};
 
+use crate::prelude::*;
+
 use core::convert::TryFrom;
 use crate::io;
 
@@ -172,6 +174,117 @@ impl core::fmt::Display for ShutdownScript{
 	}
 }
 
+/// How a [`ShutdownScriptPolicy`] treats `option_upfront_shutdown_script` for a peer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpfrontShutdownScriptMode {
+	/// The counterparty may or may not commit to a shutdown script in `open_channel` or
+	/// `accept_channel`. This matches the behavior without a policy.
+	Optional,
+	/// The counterparty must commit to a shutdown script in `open_channel` or `accept_channel`,
+	/// otherwise the channel is rejected.
+	Required,
+	/// Channels with a counterparty which requires `option_upfront_shutdown_script` or commits to
+	/// a shutdown script in `open_channel` or `accept_channel` are rejected.
+	RejectIfDemanded,
+}
+
+impl_writeable_tlv_based_enum!(UpfrontShutdownScriptMode,
+	(0, Optional) => {},
+	(2, Required) => {},
+	(4, RejectIfDemanded) => {},
+;);
+
+/// A class of script pubkeys which a [`ShutdownScriptPolicy`] accepts from a counterparty.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AllowedShutdownScript {
+	/// Any P2WPKH script pubkey.
+	P2WPKH,
+	/// Any P2WSH script pubkey.
+	P2WSH,
+	/// Any P2TR script pubkey.
+	P2TR,
+	/// Exactly the given script pubkey, e.g., a cold wallet agreed upon out-of-band.
+	Exact(Script),
+}
+
+impl_writeable_tlv_based_enum!(AllowedShutdownScript,
+	(0, P2WPKH) => {},
+	(2, P2WSH) => {},
+	(4, P2TR) => {},
+	;
+	(6, Exact),
+);
+
+impl AllowedShutdownScript {
+	/// Returns whether the given script pubkey falls into this class.
+	pub fn matches(&self, script: &Script) -> bool {
+		match self {
+			AllowedShutdownScript::P2WPKH => script.is_v0_p2wpkh(),
+			AllowedShutdownScript::P2WSH => script.is_v0_p2wsh(),
+			AllowedShutdownScript::P2TR => script.is_v1_p2tr(),
+			AllowedShutdownScript::Exact(exact) => exact == script,
+		}
+	}
+}
+
+/// A per-peer policy over the shutdown scripts a counterparty may use, set via
+/// [`ChannelManager::set_peer_shutdown_script_policy`].
+///
+/// The policy is enforced when handling `open_channel`, `accept_channel` and `shutdown` messages.
+/// Channels violating it are rejected or closed.
+///
+/// [`ChannelManager::set_peer_shutdown_script_policy`]: crate::ln::channelmanager::ChannelManager::set_peer_shutdown_script_policy
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShutdownScriptPolicy {
+	/// How `option_upfront_shutdown_script` is treated for the peer.
+	pub upfront_shutdown_mode: UpfrontShutdownScriptMode,
+	/// If set, the only script pubkeys the counterparty may commit to upfront or close the channel
+	/// to. An empty list rejects every script.
+	pub allowed_scripts: Option<Vec<AllowedShutdownScript>>,
+}
+
+impl_writeable_tlv_based!(ShutdownScriptPolicy, {
+	(0, upfront_shutdown_mode, required),
+	(2, allowed_scripts, optional_vec),
+});
+
+impl ShutdownScriptPolicy {
+	/// Checks the counterparty's `open_channel` or `accept_channel` against this policy, given
+	/// the shutdown script it committed to, if any, and its features.
+	pub(crate) fn check_upfront(&self, script: &Option<Script>, their_features: &InitFeatures) -> Result<(), String> {
+		let script = script.as_ref().filter(|script| !script.is_empty());
+		match self.upfront_shutdown_mode {
+			UpfrontShutdownScriptMode::Optional => {},
+			UpfrontShutdownScriptMode::Required => if script.is_none() {
+				return Err("Peer did not commit to an upfront shutdown script as required by our policy".to_owned());
+			},
+			UpfrontShutdownScriptMode::RejectIfDemanded => {
+				if their_features.requires_upfront_shutdown_script() {
+					return Err("Peer requires option_upfront_shutdown_script which our policy rejects".to_owned());
+				}
+				if script.is_some() {
+					return Err("Peer committed to an upfront shutdown script which our policy rejects".to_owned());
+				}
+			},
+		}
+		match script {
+			Some(script) => self.check_script(script),
+			None => Ok(()),
+		}
+	}
+
+	/// Checks a script pubkey the counterparty wishes to close the channel to against this
+	/// policy's allowed scripts.
+	pub(crate) fn check_script(&self, script: &Script) -> Result<(), String> {
+		if let Some(allowed_scripts) = &self.allowed_scripts {
+			if !allowed_scripts.iter().any(|allowed| allowed.matches(script)) {
+				return Err(format!("Peer provided a shutdown scriptpubkey not allowed by our policy: {}", script));
+			}
+		}
+		Ok(())
+	}
+}
+
 #[cfg(test)]
 mod shutdown_script_tests {
 	use super::ShutdownScript;
@@ -10,13 +10,14 @@
 //! Tests of our shutdown and closing_signed negotiation logic.
 
 use crate::sign::{EntropySource, SignerProvider};
+use crate::chain::chaininterface::{TransactionMetadata, TransactionType};
 use crate::chain::transaction::OutPoint;
 use crate::events::{Event, MessageSendEvent, MessageSendEventsProvider, ClosureReason};
 use crate::ln::channelmanager::{self, PaymentSendFailure, PaymentId, RecipientOnionFields, ChannelShutdownState, ChannelDetails};
 use crate::routing::router::{PaymentParameters, get_route};
 use crate::ln::msgs;
 use crate::ln::msgs::{ChannelMessageHandler, ErrorAction};
-use crate::ln::script::ShutdownScript;
+use crate::ln::script::{AllowedShutdownScript, ShutdownScript, ShutdownScriptPolicy, UpfrontShutdownScriptMode};
 use crate::util::test_utils;
 use crate::util::test_utils::OnGetShutdownScriptpubkey;
 use crate::util::errors::APIError;
@@ -315,6 +316,38 @@ fn updates_shutdown_wait() {
 	check_closed_event!(nodes[2], 1, ClosureReason::CooperativeClosure);
 }
 
+#[test]
+fn labels_funding_and_closing_transactions() {
+	// Test that the funding and cooperative closing transactions we broadcast are passed to
+	// `broadcast_transactions_with_meta` along with the channel they belong to.
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1);
+
+	let funding_metadata = TransactionMetadata {
+		channel_id: Some(chan.2),
+		counterparty_node_id: Some(nodes[1].node.get_our_node_id()),
+		transaction_type: TransactionType::Funding,
+	};
+	assert!(nodes[0].tx_broadcaster.txn_broadcasted_metadata.lock().unwrap()
+		.contains(&(chan.3.txid(), funding_metadata)));
+
+	let (_, _, closing_tx) = close_channel(&nodes[0], &nodes[1], &chan.2, chan.3, true);
+	check_closed_event!(nodes[0], 1, ClosureReason::CooperativeClosure);
+	check_closed_event!(nodes[1], 1, ClosureReason::CooperativeClosure);
+	for (node, counterparty) in [(&nodes[0], &nodes[1]), (&nodes[1], &nodes[0])].iter() {
+		let closing_metadata = TransactionMetadata {
+			channel_id: Some(chan.2),
+			counterparty_node_id: Some(counterparty.node.get_our_node_id()),
+			transaction_type: TransactionType::CooperativeClose,
+		};
+		assert!(node.tx_broadcaster.txn_broadcasted_metadata.lock().unwrap()
+			.contains(&(closing_tx.txid(), closing_metadata)));
+	}
+}
+
 #[test]
 fn htlc_fail_async_shutdown() {
 	// Test HTLCs fail if shutdown starts even if messages are delivered out-of-order
@@ -755,6 +788,91 @@ fn test_invalid_upfront_shutdown_script() {
 	}
 }
 
+fn expect_open_channel_rejection(nodes: &Vec<Node>, open_channel: &msgs::OpenChannel, expected_err: &str) {
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), open_channel);
+	let events = nodes[1].node.get_and_clear_pending_msg_events();
+	assert_eq!(events.len(), 1);
+	match events[0] {
+		MessageSendEvent::HandleError { action: ErrorAction::SendErrorMessage { ref msg }, node_id } => {
+			assert_eq!(node_id, nodes[0].node.get_our_node_id());
+			assert_eq!(msg.data, expected_err);
+		},
+		_ => panic!("Unexpected event"),
+	}
+}
+
+#[test]
+fn test_upfront_shutdown_script_policy() {
+	let mut config = test_default_channel_config();
+	config.channel_handshake_config.commit_upfront_shutdown_pubkey = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	nodes[1].node.set_peer_shutdown_script_policy(&nodes[0].node.get_our_node_id(), Some(ShutdownScriptPolicy {
+		upfront_shutdown_mode: UpfrontShutdownScriptMode::Required, allowed_scripts: None,
+	}));
+
+	// nodes[0] doesn't commit to an upfront shutdown script, which nodes[1] requires.
+	nodes[0].node.create_channel(nodes[1].node.get_our_node_id(), 100000, 10001, 42, None).unwrap();
+	let mut open_channel = get_event_msg!(nodes[0], MessageSendEvent::SendOpenChannel, nodes[1].node.get_our_node_id());
+	expect_open_channel_rejection(&nodes, &open_channel,
+		"Peer did not commit to an upfront shutdown script as required by our policy");
+
+	let upfront_script = chanmon_cfgs[0].keys_manager.get_shutdown_scriptpubkey().unwrap().into_inner();
+	open_channel.shutdown_scriptpubkey = Some(upfront_script.clone());
+	nodes[1].node.set_peer_shutdown_script_policy(&nodes[0].node.get_our_node_id(), Some(ShutdownScriptPolicy {
+		upfront_shutdown_mode: UpfrontShutdownScriptMode::RejectIfDemanded, allowed_scripts: None,
+	}));
+	expect_open_channel_rejection(&nodes, &open_channel,
+		"Peer committed to an upfront shutdown script which our policy rejects");
+
+	// The upfront script is a P2WPKH, so it is only accepted once that class is allowed.
+	nodes[1].node.set_peer_shutdown_script_policy(&nodes[0].node.get_our_node_id(), Some(ShutdownScriptPolicy {
+		upfront_shutdown_mode: UpfrontShutdownScriptMode::Required,
+		allowed_scripts: Some(vec![AllowedShutdownScript::P2WSH]),
+	}));
+	expect_open_channel_rejection(&nodes, &open_channel,
+		&format!("Peer provided a shutdown scriptpubkey not allowed by our policy: {}", upfront_script));
+
+	nodes[1].node.set_peer_shutdown_script_policy(&nodes[0].node.get_our_node_id(), Some(ShutdownScriptPolicy {
+		upfront_shutdown_mode: UpfrontShutdownScriptMode::Required,
+		allowed_scripts: Some(vec![AllowedShutdownScript::P2WSH, AllowedShutdownScript::P2WPKH]),
+	}));
+	nodes[1].node.handle_open_channel(&nodes[0].node.get_our_node_id(), &open_channel);
+	get_event_msg!(nodes[1], MessageSendEvent::SendAcceptChannel, nodes[0].node.get_our_node_id());
+}
+
+#[test]
+fn test_shutdown_script_policy_on_shutdown() {
+	let mut config = test_default_channel_config();
+	config.channel_handshake_config.commit_upfront_shutdown_pubkey = false;
+	let chanmon_cfgs = create_chanmon_cfgs(2);
+	let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+	let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[Some(config), None]);
+	let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+
+	// nodes[1] only lets nodes[0] close to a specific script, e.g. a shared cold wallet.
+	let cold_wallet_script = Builder::new().push_int(0).push_slice(&[42; 32]).into_script();
+	nodes[1].node.set_peer_shutdown_script_policy(&nodes[0].node.get_our_node_id(), Some(ShutdownScriptPolicy {
+		upfront_shutdown_mode: UpfrontShutdownScriptMode::Optional,
+		allowed_scripts: Some(vec![AllowedShutdownScript::Exact(cold_wallet_script)]),
+	}));
+
+	let chan = create_announced_chan_between_nodes(&nodes, 0, 1);
+	nodes[0].node.close_channel(&chan.2, &nodes[1].node.get_our_node_id()).unwrap();
+	check_added_monitors!(nodes[0], 1);
+	let node_0_shutdown = get_event_msg!(nodes[0], MessageSendEvent::SendShutdown, nodes[1].node.get_our_node_id());
+	nodes[1].node.handle_shutdown(&nodes[0].node.get_our_node_id(), &node_0_shutdown);
+
+	check_closed_broadcast!(nodes[1], true).unwrap();
+	check_added_monitors!(nodes[1], 1);
+	check_closed_event!(nodes[1], 1, ClosureReason::ProcessingError {
+		err: format!("Peer provided a shutdown scriptpubkey not allowed by our policy: {}", node_0_shutdown.scriptpubkey)
+	});
+}
+
 #[test]
 fn test_segwit_v0_shutdown_script() {
 	let mut config = UserConfig::default();
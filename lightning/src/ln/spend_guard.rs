@@ -0,0 +1,457 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A guard requiring an application-supplied authorization token for broadcasting transactions
+//! which spend our funds.
+//!
+//! The guard is enforced by [`SpendGuardedBroadcaster`], a [`BroadcasterInterface`] wrapping the
+//! one which actually broadcasts transactions. Passing it to the [`ChannelManager`] and
+//! [`ChainMonitor`] (and using it for any sweeps and fee bumps) ensures that every transaction
+//! LDK broadcasts passes the guard, whatever caused it to be broadcast, e.g., force-closes,
+//! cooperative closes to an arbitrary script or rebroadcasts of
+//! [`ChannelMonitor::get_latest_holder_commitment_txn`].
+//!
+//! Once enabled via [`SpendGuardedBroadcaster::enable`], transactions are held rather than
+//! broadcast unless they belong to a channel authorized via
+//! [`SpendGuardedBroadcaster::authorize_channel`] or are broadcast via
+//! [`SpendGuardedBroadcaster::broadcast_authorized`], both of which require presenting a
+//! [`SpendAuthorizationToken`] matching the hash the guard was enabled with. This ensures that an
+//! application layer which was compromised, but which doesn't hold the token, can't trivially
+//! drain channel funds via forced or cooperative closes.
+//!
+//! Only spends we initiate ourselves are guarded. Funding transactions and
+//! [`TransactionType::Claim`]s, i.e. claims reacting to our counterparty or to on-chain events
+//! such as justice transactions and HTLC claims, are always broadcast, as holding them could let
+//! our counterparty take the funds once the relevant timelocks expire. Transactions broadcast
+//! without [`TransactionMetadata`] can't be told apart and are held. Held transactions should be
+//! reviewed via [`SpendGuardedBroadcaster::held_transactions`] and released via
+//! [`SpendGuardedBroadcaster::release_held_transactions`] in a timely manner.
+//!
+//! [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+//! [`ChainMonitor`]: crate::chain::chainmonitor::ChainMonitor
+//! [`ChannelMonitor::get_latest_holder_commitment_txn`]: crate::chain::channelmonitor::ChannelMonitor::get_latest_holder_commitment_txn
+
+use bitcoin::blockdata::transaction::Transaction;
+use bitcoin::hashes::Hash;
+use bitcoin::hashes::sha256::Hash as Sha256;
+
+use crate::chain::chaininterface::{BroadcasterInterface, TransactionMetadata, TransactionType};
+use crate::io;
+use crate::ln::msgs::DecodeError;
+use crate::sync::Mutex;
+use crate::util::errors::APIError;
+use crate::util::ser::{ReadableArgs, Writeable, Writer};
+
+use core::ops::Deref;
+
+use crate::prelude::*;
+
+/// A secret held by the application which authorizes broadcasts held by a
+/// [`SpendGuardedBroadcaster`].
+///
+/// Only the SHA-256 hash of the token is stored, see [`SpendAuthorizationToken::hash`]. `Debug` is
+/// intentionally not implemented to avoid logging the token.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SpendAuthorizationToken(pub [u8; 32]);
+
+impl SpendAuthorizationToken {
+	/// Returns the SHA-256 hash of the token, with which the spend guard is enabled.
+	pub fn hash(&self) -> [u8; 32] {
+		Sha256::hash(&self.0).into_inner()
+	}
+}
+
+#[derive(Clone, PartialEq)]
+struct HeldTransaction {
+	tx: Transaction,
+	metadata: Option<TransactionMetadata>,
+}
+
+impl_writeable_tlv_based!(HeldTransaction, {
+	(0, tx, required),
+	(2, metadata, option),
+});
+
+/// Transactions passed to a single broadcast call, which are held or broadcast together.
+#[derive(Clone, PartialEq)]
+struct HeldPackage {
+	transactions: Vec<HeldTransaction>,
+}
+
+impl_writeable_tlv_based!(HeldPackage, {
+	(0, transactions, required_vec),
+});
+
+struct SpendGuardState {
+	token_hash: Option<[u8; 32]>,
+	authorized_channels: HashSet<[u8; 32]>,
+	held_packages: Vec<HeldPackage>,
+}
+
+impl SpendGuardState {
+	fn check(&self, token: &SpendAuthorizationToken) -> Result<(), APIError> {
+		match self.token_hash {
+			Some(token_hash) if token.hash() != token_hash => Err(APIError::APIMisuseError {
+				err: "Invalid authorization token for the spend guard".to_owned()
+			}),
+			_ => Ok(()),
+		}
+	}
+
+	/// Returns whether a package may be broadcast, i.e. the guard is disabled or all of its
+	/// transactions are unguarded or belong to authorized channels.
+	fn is_authorized(&self, package: &HeldPackage) -> bool {
+		self.token_hash.is_none() || package.transactions.iter().all(|held_tx| match held_tx.metadata {
+			Some(TransactionMetadata { transaction_type: TransactionType::Funding, .. }) |
+			Some(TransactionMetadata { transaction_type: TransactionType::Claim, .. }) => true,
+			Some(TransactionMetadata { channel_id: Some(channel_id), .. }) =>
+				self.authorized_channels.contains(&channel_id),
+			_ => false,
+		})
+	}
+
+	/// Removes and returns the held packages which are now authorized.
+	fn take_authorized_packages(&mut self) -> Vec<HeldPackage> {
+		let mut authorized = Vec::new();
+		let held_packages = core::mem::take(&mut self.held_packages);
+		for package in held_packages {
+			if self.is_authorized(&package) {
+				authorized.push(package);
+			} else {
+				self.held_packages.push(package);
+			}
+		}
+		authorized
+	}
+}
+
+/// A [`BroadcasterInterface`] which enforces a spend guard, holding transactions unless they were
+/// authorized via a [`SpendAuthorizationToken`].
+///
+/// The guard is disabled until [`Self::enable`] is called, in which case all transactions are
+/// passed through to the wrapped broadcaster. The guard's state, including any held
+/// transactions, should be persisted via its [`Writeable`] implementation whenever it changes,
+/// e.g. along with the [`ChannelManager`], and read back via [`ReadableArgs`] on startup. See the
+/// [module-level documentation] for more details.
+///
+/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+///
+/// [module-level documentation]: crate::ln::spend_guard
+pub struct SpendGuardedBroadcaster<B: Deref> where B::Target: BroadcasterInterface {
+	broadcaster: B,
+	state: Mutex<SpendGuardState>,
+}
+
+impl<B: Deref> SpendGuardedBroadcaster<B> where B::Target: BroadcasterInterface {
+	/// Wraps the given `broadcaster` with a disabled spend guard.
+	pub fn new(broadcaster: B) -> Self {
+		Self {
+			broadcaster,
+			state: Mutex::new(SpendGuardState {
+				token_hash: None,
+				authorized_channels: HashSet::new(),
+				held_packages: Vec::new(),
+			}),
+		}
+	}
+
+	/// Enables the spend guard, requiring a [`SpendAuthorizationToken`] whose
+	/// [`SpendAuthorizationToken::hash`] matches `token_hash` to be presented before transactions
+	/// are broadcast.
+	///
+	/// Fails if the guard is already enabled, in which case it must first be disabled via
+	/// [`Self::disable`].
+	pub fn enable(&self, token_hash: [u8; 32]) -> Result<(), APIError> {
+		let mut state = self.state.lock().unwrap();
+		if state.token_hash.is_some() {
+			return Err(APIError::APIMisuseError { err: "The spend guard is already enabled".to_owned() });
+		}
+		state.token_hash = Some(token_hash);
+		state.authorized_channels.clear();
+		Ok(())
+	}
+
+	/// Disables the spend guard enabled via [`Self::enable`], broadcasting any held transactions.
+	/// Fails unless the given token matches the one it was enabled with.
+	pub fn disable(&self, token: &SpendAuthorizationToken) -> Result<(), APIError> {
+		let packages = {
+			let mut state = self.state.lock().unwrap();
+			state.check(token)?;
+			state.token_hash = None;
+			state.authorized_channels.clear();
+			state.take_authorized_packages()
+		};
+		self.broadcast_packages(packages);
+		Ok(())
+	}
+
+	/// Returns whether the spend guard is enabled.
+	pub fn is_enabled(&self) -> bool {
+		self.state.lock().unwrap().token_hash.is_some()
+	}
+
+	/// Authorizes broadcasting all transactions of the channel with the given `channel_id`, as
+	/// identified by their [`TransactionMetadata::channel_id`], e.g. before force-closing it via
+	/// [`ChannelManager::force_close_broadcasting_latest_txn`]. Any held transactions of the channel
+	/// are broadcast immediately.
+	///
+	/// Fails unless the given token matches the one the guard was enabled with.
+	///
+	/// [`ChannelManager::force_close_broadcasting_latest_txn`]: crate::ln::channelmanager::ChannelManager::force_close_broadcasting_latest_txn
+	pub fn authorize_channel(&self, channel_id: [u8; 32], token: &SpendAuthorizationToken) -> Result<(), APIError> {
+		let packages = {
+			let mut state = self.state.lock().unwrap();
+			state.check(token)?;
+			if state.token_hash.is_some() {
+				state.authorized_channels.insert(channel_id);
+			}
+			state.take_authorized_packages()
+		};
+		self.broadcast_packages(packages);
+		Ok(())
+	}
+
+	/// Broadcasts the given transactions, e.g. sweeps or fee bumps built by the application,
+	/// failing without broadcasting them unless the given token matches the one the guard was
+	/// enabled with.
+	pub fn broadcast_authorized(&self, txs: &[&Transaction], token: &SpendAuthorizationToken) -> Result<(), APIError> {
+		self.state.lock().unwrap().check(token)?;
+		self.broadcaster.broadcast_transactions(txs);
+		Ok(())
+	}
+
+	/// Returns the packages of transactions currently held by the guard, along with the metadata
+	/// they were broadcast with, if any.
+	pub fn held_transactions(&self) -> Vec<Vec<(Transaction, Option<TransactionMetadata>)>> {
+		self.state.lock().unwrap().held_packages.iter().map(|package| {
+			package.transactions.iter().map(|held_tx| (held_tx.tx.clone(), held_tx.metadata)).collect()
+		}).collect()
+	}
+
+	/// Broadcasts all transactions currently held by the guard, failing without broadcasting them
+	/// unless the given token matches the one the guard was enabled with.
+	pub fn release_held_transactions(&self, token: &SpendAuthorizationToken) -> Result<(), APIError> {
+		let packages = {
+			let mut state = self.state.lock().unwrap();
+			state.check(token)?;
+			state.held_packages.drain(..).collect()
+		};
+		self.broadcast_packages(packages);
+		Ok(())
+	}
+
+	fn broadcast_packages(&self, packages: Vec<HeldPackage>) {
+		for package in packages {
+			if package.transactions.iter().all(|held_tx| held_tx.metadata.is_some()) {
+				let txs = package.transactions.iter()
+					.map(|held_tx| (&held_tx.tx, held_tx.metadata.unwrap())).collect::<Vec<_>>();
+				self.broadcaster.broadcast_transactions_with_meta(&txs);
+			} else {
+				let txs = package.transactions.iter().map(|held_tx| &held_tx.tx).collect::<Vec<_>>();
+				self.broadcaster.broadcast_transactions(&txs);
+			}
+		}
+	}
+
+	fn hold_or_broadcast(&self, package: HeldPackage) {
+		{
+			let mut state = self.state.lock().unwrap();
+			if !state.is_authorized(&package) {
+				// LDK may rebroadcast the same package repeatedly, which we only need to hold once.
+				// Held packages are never dropped, as they may spend funds we'd otherwise lose.
+				if state.held_packages.contains(&package) { return; }
+				state.held_packages.push(package);
+				return;
+			}
+		}
+		self.broadcast_packages(vec![package]);
+	}
+}
+
+impl<B: Deref> BroadcasterInterface for SpendGuardedBroadcaster<B> where B::Target: BroadcasterInterface {
+	fn broadcast_transactions(&self, txs: &[&Transaction]) {
+		self.hold_or_broadcast(HeldPackage {
+			transactions: txs.iter().map(|tx| HeldTransaction { tx: (*tx).clone(), metadata: None }).collect(),
+		});
+	}
+
+	fn broadcast_transactions_with_meta(&self, txs: &[(&Transaction, TransactionMetadata)]) {
+		self.hold_or_broadcast(HeldPackage {
+			transactions: txs.iter()
+				.map(|(tx, metadata)| HeldTransaction { tx: (*tx).clone(), metadata: Some(*metadata) })
+				.collect(),
+		});
+	}
+
+	fn rebroadcast_pending_transactions(&self) {
+		self.broadcaster.rebroadcast_pending_transactions();
+	}
+}
+
+impl<B: Deref> Writeable for SpendGuardedBroadcaster<B> where B::Target: BroadcasterInterface {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		let state = self.state.lock().unwrap();
+		write_tlv_fields!(w, {
+			(0, state.token_hash, option),
+			(2, state.authorized_channels, required),
+			(4, state.held_packages, optional_vec),
+		});
+		Ok(())
+	}
+}
+
+impl<B: Deref> ReadableArgs<B> for SpendGuardedBroadcaster<B> where B::Target: BroadcasterInterface {
+	fn read<R: io::Read>(r: &mut R, broadcaster: B) -> Result<Self, DecodeError> {
+		let mut token_hash = None;
+		let mut authorized_channels = HashSet::new();
+		let mut held_packages = Some(Vec::new());
+		read_tlv_fields!(r, {
+			(0, token_hash, option),
+			(2, authorized_channels, required),
+			(4, held_packages, optional_vec),
+		});
+		Ok(Self {
+			broadcaster,
+			state: Mutex::new(SpendGuardState {
+				token_hash, authorized_channels, held_packages: held_packages.unwrap(),
+			}),
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::blockdata::transaction::{Transaction, TxOut};
+	use bitcoin::network::constants::Network;
+	use bitcoin::PackedLockTime;
+
+	use crate::chain::chaininterface::{BroadcasterInterface, TransactionMetadata, TransactionType};
+	use crate::util::ser::{ReadableArgs, Writeable};
+	use crate::util::test_utils::TestBroadcaster;
+	use super::{SpendAuthorizationToken, SpendGuardedBroadcaster};
+
+	fn dummy_tx(value: u64) -> Transaction {
+		Transaction {
+			version: 2, lock_time: PackedLockTime::ZERO, input: Vec::new(),
+			output: vec![TxOut { value, script_pubkey: Script::new() }],
+		}
+	}
+
+	fn metadata(channel_id: [u8; 32]) -> TransactionMetadata {
+		TransactionMetadata {
+			channel_id: Some(channel_id), counterparty_node_id: None,
+			transaction_type: TransactionType::UnilateralClose,
+		}
+	}
+
+	fn claim_metadata(channel_id: [u8; 32]) -> TransactionMetadata {
+		TransactionMetadata { transaction_type: TransactionType::Claim, ..metadata(channel_id) }
+	}
+
+	#[test]
+	fn holds_unauthorized_transactions() {
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let guarded = SpendGuardedBroadcaster::new(&broadcaster);
+		let token = SpendAuthorizationToken([42; 32]);
+		let wrong_token = SpendAuthorizationToken([43; 32]);
+
+		// While disabled, everything passes through.
+		guarded.broadcast_transactions(&[&dummy_tx(1)]);
+		assert_eq!(broadcaster.txn_broadcast(), vec![dummy_tx(1)]);
+
+		guarded.enable(token.hash()).unwrap();
+		assert!(guarded.enable(wrong_token.hash()).is_err());
+
+		// Claims reacting to our counterparty are never held.
+		guarded.broadcast_transactions_with_meta(&[(&dummy_tx(12), claim_metadata([2; 32]))]);
+		assert_eq!(broadcaster.txn_broadcast(), vec![dummy_tx(12)]);
+
+		// Any spend we initiate, or which can't be told apart, is held.
+		guarded.broadcast_transactions(&[&dummy_tx(2)]);
+		guarded.broadcast_transactions_with_meta(&[(&dummy_tx(3), metadata([1; 32]))]);
+		guarded.broadcast_transactions_with_meta(&[(&dummy_tx(3), metadata([1; 32]))]);
+		guarded.broadcast_transactions_with_meta(&[(&dummy_tx(4), metadata([2; 32]))]);
+		assert!(broadcaster.txn_broadcast().is_empty());
+		assert_eq!(guarded.held_transactions().len(), 3);
+
+		// Authorizing a channel releases its transactions and lets later ones through.
+		assert!(guarded.authorize_channel([1; 32], &wrong_token).is_err());
+		guarded.authorize_channel([1; 32], &token).unwrap();
+		assert_eq!(broadcaster.txn_broadcast(), vec![dummy_tx(3)]);
+		guarded.broadcast_transactions_with_meta(&[(&dummy_tx(5), metadata([1; 32]))]);
+		assert_eq!(broadcaster.txn_broadcast(), vec![dummy_tx(5)]);
+		// A package is only broadcast if all of its transactions are authorized.
+		guarded.broadcast_transactions_with_meta(&[(&dummy_tx(6), metadata([1; 32])), (&dummy_tx(7), metadata([2; 32]))]);
+		assert!(broadcaster.txn_broadcast().is_empty());
+
+		assert!(guarded.broadcast_authorized(&[&dummy_tx(8)], &wrong_token).is_err());
+		guarded.broadcast_authorized(&[&dummy_tx(8)], &token).unwrap();
+		assert_eq!(broadcaster.txn_broadcast(), vec![dummy_tx(8)]);
+
+		// The guard, including held transactions, survives a restart.
+		let held_transactions = guarded.held_transactions();
+		assert_eq!(held_transactions.len(), 3);
+		let guarded = SpendGuardedBroadcaster::read(&mut &guarded.encode()[..], &broadcaster).unwrap();
+		assert!(guarded.is_enabled());
+		assert!(guarded.held_transactions() == held_transactions);
+		guarded.broadcast_transactions_with_meta(&[(&dummy_tx(9), metadata([1; 32]))]);
+		assert_eq!(broadcaster.txn_broadcast(), vec![dummy_tx(9)]);
+		guarded.broadcast_transactions(&[&dummy_tx(10)]);
+		assert_eq!(guarded.held_transactions().len(), 4);
+		assert!(guarded.release_held_transactions(&wrong_token).is_err());
+		guarded.release_held_transactions(&token).unwrap();
+		assert_eq!(broadcaster.txn_broadcast(),
+			vec![dummy_tx(2), dummy_tx(4), dummy_tx(6), dummy_tx(7), dummy_tx(10)]);
+
+		// Disabling the guard broadcasts anything still held.
+		guarded.broadcast_transactions(&[&dummy_tx(11)]);
+		assert!(guarded.disable(&wrong_token).is_err());
+		guarded.disable(&token).unwrap();
+		assert!(!guarded.is_enabled());
+		assert_eq!(broadcaster.txn_broadcast(), vec![dummy_tx(11)]);
+	}
+
+	#[test]
+	fn holds_force_close_until_channel_authorized() {
+		use crate::events::ClosureReason;
+		use crate::ln::functional_test_utils::*;
+
+		let chanmon_cfgs = create_chanmon_cfgs(2);
+		let node_cfgs = create_node_cfgs(2, &chanmon_cfgs);
+		let node_chanmgrs = create_node_chanmgrs(2, &node_cfgs, &[None, None]);
+		let nodes = create_network(2, &node_cfgs, &node_chanmgrs);
+		let chan_id = create_announced_chan_between_nodes(&nodes, 0, 1).2;
+
+		let broadcaster = TestBroadcaster::new(Network::Testnet);
+		let guarded = SpendGuardedBroadcaster::new(&broadcaster);
+		let token = SpendAuthorizationToken([42; 32]);
+		guarded.enable(token.hash()).unwrap();
+
+		// Replay the commitment transaction broadcast upon force-closing through the guard.
+		nodes[0].node.force_close_broadcasting_latest_txn(&chan_id, &nodes[1].node.get_our_node_id()).unwrap();
+		check_closed_broadcast!(nodes[0], true);
+		check_added_monitors!(nodes[0], 1);
+		check_closed_event!(nodes[0], 1, ClosureReason::HolderForceClosed);
+		let commitment_tx = nodes[0].tx_broadcaster.txn_broadcast().pop().unwrap();
+		let (_, metadata) = nodes[0].tx_broadcaster.txn_broadcasted_metadata.lock().unwrap()
+			.iter().find(|(txid, _)| *txid == commitment_tx.txid()).cloned().unwrap();
+		guarded.broadcast_transactions_with_meta(&[(&commitment_tx, metadata)]);
+
+		// As does broadcasting the latest holder commitment transaction directly.
+		let holder_txn = get_monitor!(nodes[0], chan_id).get_latest_holder_commitment_txn(&nodes[0].logger);
+		guarded.broadcast_transactions(&holder_txn.iter().collect::<Vec<_>>());
+		assert!(broadcaster.txn_broadcast().is_empty());
+		assert_eq!(guarded.held_transactions().len(), 2);
+
+		guarded.authorize_channel(chan_id, &token).unwrap();
+		assert_eq!(broadcaster.txn_broadcast(), vec![commitment_tx]);
+		assert_eq!(guarded.held_transactions().len(), 1);
+	}
+}
@@ -79,9 +79,12 @@ pub(crate) enum Message<T> where T: core::fmt::Debug + Type + TestEq {
 	CommitmentSigned(msgs::CommitmentSigned),
 	RevokeAndACK(msgs::RevokeAndACK),
 	UpdateFee(msgs::UpdateFee),
+	Yield(msgs::Yield),
 	ChannelReestablish(msgs::ChannelReestablish),
 	AnnouncementSignatures(msgs::AnnouncementSignatures),
 	ChannelAnnouncement(msgs::ChannelAnnouncement),
+	#[cfg(taproot)]
+	ChannelAnnouncement2(msgs::ChannelAnnouncement2),
 	NodeAnnouncement(msgs::NodeAnnouncement),
 	ChannelUpdate(msgs::ChannelUpdate),
 	QueryShortChannelIds(msgs::QueryShortChannelIds),
@@ -130,9 +133,12 @@ impl<T> Writeable for Message<T> where T: core::fmt::Debug + Type + TestEq {
 			&Message::CommitmentSigned(ref msg) => msg.write(writer),
 			&Message::RevokeAndACK(ref msg) => msg.write(writer),
 			&Message::UpdateFee(ref msg) => msg.write(writer),
+			&Message::Yield(ref msg) => msg.write(writer),
 			&Message::ChannelReestablish(ref msg) => msg.write(writer),
 			&Message::AnnouncementSignatures(ref msg) => msg.write(writer),
 			&Message::ChannelAnnouncement(ref msg) => msg.write(writer),
+			#[cfg(taproot)]
+			&Message::ChannelAnnouncement2(ref msg) => msg.write(writer),
 			&Message::NodeAnnouncement(ref msg) => msg.write(writer),
 			&Message::ChannelUpdate(ref msg) => msg.write(writer),
 			&Message::QueryShortChannelIds(ref msg) => msg.write(writer),
@@ -181,9 +187,12 @@ impl<T> Type for Message<T> where T: core::fmt::Debug + Type + TestEq {
 			&Message::CommitmentSigned(ref msg) => msg.type_id(),
 			&Message::RevokeAndACK(ref msg) => msg.type_id(),
 			&Message::UpdateFee(ref msg) => msg.type_id(),
+			&Message::Yield(ref msg) => msg.type_id(),
 			&Message::ChannelReestablish(ref msg) => msg.type_id(),
 			&Message::AnnouncementSignatures(ref msg) => msg.type_id(),
 			&Message::ChannelAnnouncement(ref msg) => msg.type_id(),
+			#[cfg(taproot)]
+			&Message::ChannelAnnouncement2(ref msg) => msg.type_id(),
 			&Message::NodeAnnouncement(ref msg) => msg.type_id(),
 			&Message::ChannelUpdate(ref msg) => msg.type_id(),
 			&Message::QueryShortChannelIds(ref msg) => msg.type_id(),
@@ -318,6 +327,9 @@ fn do_read<R: io::Read, T, H: core::ops::Deref>(buffer: &mut R, message_type: u1
 		msgs::UpdateFee::TYPE => {
 			Ok(Message::UpdateFee(Readable::read(buffer)?))
 		},
+		msgs::Yield::TYPE => {
+			Ok(Message::Yield(Readable::read(buffer)?))
+		},
 		msgs::ChannelReestablish::TYPE => {
 			Ok(Message::ChannelReestablish(Readable::read(buffer)?))
 		},
@@ -327,6 +339,10 @@ fn do_read<R: io::Read, T, H: core::ops::Deref>(buffer: &mut R, message_type: u1
 		msgs::ChannelAnnouncement::TYPE => {
 			Ok(Message::ChannelAnnouncement(Readable::read(buffer)?))
 		},
+		#[cfg(taproot)]
+		msgs::ChannelAnnouncement2::TYPE => {
+			Ok(Message::ChannelAnnouncement2(Readable::read(buffer)?))
+		},
 		msgs::NodeAnnouncement::TYPE => {
 			Ok(Message::NodeAnnouncement(Readable::read(buffer)?))
 		},
@@ -536,6 +552,10 @@ impl Encode for msgs::ChannelReestablish {
 	const TYPE: u16 = 136;
 }
 
+impl Encode for msgs::Yield {
+	const TYPE: u16 = 138;
+}
+
 impl Encode for msgs::AnnouncementSignatures {
 	const TYPE: u16 = 259;
 }
@@ -544,6 +564,11 @@ impl Encode for msgs::ChannelAnnouncement {
 	const TYPE: u16 = 256;
 }
 
+#[cfg(taproot)]
+impl Encode for msgs::ChannelAnnouncement2 {
+	const TYPE: u16 = 267;
+}
+
 impl Encode for msgs::NodeAnnouncement {
 	const TYPE: u16 = 257;
 }
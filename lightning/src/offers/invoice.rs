@@ -94,6 +94,7 @@
 //! ```
 
 use bitcoin::blockdata::constants::ChainHash;
+use bitcoin::blockdata::script::Script;
 use bitcoin::hash_types::{WPubkeyHash, WScriptHash};
 use bitcoin::hashes::Hash;
 use bitcoin::network::constants::Network;
@@ -321,6 +322,21 @@ impl<'a, S: SigningPubkeyStrategy> InvoiceBuilder<'a, S> {
 		self
 	}
 
+	/// Adds the address paid to by `script_pubkey` to [`Bolt12Invoice::fallbacks`], e.g. a script
+	/// from a [`ChangeDestinationSource`].
+	///
+	/// Successive calls to this method will add another address. Only segwit scripts can be used
+	/// as fallbacks, any other script is ignored.
+	///
+	/// [`ChangeDestinationSource`]: crate::sign::ChangeDestinationSource
+	pub fn fallback_script(mut self, script_pubkey: &Script) -> Self {
+		if let Some(Payload::WitnessProgram { version, program }) = Payload::from_script(script_pubkey) {
+			let address = FallbackAddress { version: version.to_num(), program };
+			self.invoice.fields_mut().fallbacks.get_or_insert_with(Vec::new).push(address);
+		}
+		self
+	}
+
 	/// Sets [`Bolt12Invoice::features`] to indicate MPP may be used. Otherwise, MPP is disallowed.
 	pub fn allow_mpp(mut self) -> Self {
 		self.invoice.fields_mut().features.set_basic_mpp_optional();
@@ -571,6 +587,11 @@ impl Bolt12Invoice {
 		}
 	}
 
+	/// The public key used by the payer to sign the invoice request or refund the invoice is for.
+	pub fn payer_id(&self) -> PublicKey {
+		self.contents.payer_id()
+	}
+
 	/// Features pertaining to paying an invoice.
 	pub fn features(&self) -> &Bolt12InvoiceFeatures {
 		&self.contents.fields().features
@@ -628,6 +649,13 @@ impl InvoiceContents {
 		}
 	}
 
+	fn payer_id(&self) -> PublicKey {
+		match self {
+			InvoiceContents::ForOffer { invoice_request, .. } => invoice_request.payer_id(),
+			InvoiceContents::ForRefund { refund, .. } => refund.payer_id(),
+		}
+	}
+
 	fn description(&self) -> PrintableString {
 		match self {
 			InvoiceContents::ForOffer { invoice_request, .. } => {
@@ -1017,6 +1045,7 @@ mod tests {
 		assert_eq!(invoice.fallbacks(), vec![]);
 		assert_eq!(invoice.features(), &Bolt12InvoiceFeatures::empty());
 		assert_eq!(invoice.signing_pubkey(), recipient_pubkey());
+		assert_eq!(invoice.payer_id(), payer_pubkey());
 		assert!(
 			merkle::verify_signature(
 				&invoice.signature, SIGNATURE_TAG, &invoice.bytes, recipient_pubkey()
@@ -1100,6 +1129,7 @@ mod tests {
 		assert_eq!(invoice.fallbacks(), vec![]);
 		assert_eq!(invoice.features(), &Bolt12InvoiceFeatures::empty());
 		assert_eq!(invoice.signing_pubkey(), recipient_pubkey());
+		assert_eq!(invoice.payer_id(), payer_pubkey());
 		assert!(
 			merkle::verify_signature(
 				&invoice.signature, SIGNATURE_TAG, &invoice.bytes, recipient_pubkey()
@@ -1430,6 +1460,26 @@ mod tests {
 		);
 	}
 
+	#[test]
+	fn builds_invoice_with_fallback_script() {
+		let pubkey = bitcoin::util::key::PublicKey::new(recipient_pubkey());
+		let p2wpkh = Address::p2wpkh(&pubkey, Network::Bitcoin).unwrap();
+		let p2pkh = Address::p2pkh(&pubkey, Network::Bitcoin);
+
+		let invoice = OfferBuilder::new("foo".into(), recipient_pubkey())
+			.amount_msats(1000)
+			.build().unwrap()
+			.request_invoice(vec![1; 32], payer_pubkey()).unwrap()
+			.build().unwrap()
+			.sign(payer_sign).unwrap()
+			.respond_with_no_std(payment_paths(), payment_hash(), now()).unwrap()
+			.fallback_script(&p2wpkh.script_pubkey())
+			.fallback_script(&p2pkh.script_pubkey())
+			.build().unwrap()
+			.sign(recipient_sign).unwrap();
+		assert_eq!(invoice.fallbacks(), vec![p2wpkh]);
+	}
+
 	#[test]
 	fn builds_invoice_with_allow_mpp() {
 		let mut features = Bolt12InvoiceFeatures::empty();
@@ -15,7 +15,7 @@
 pub mod invoice;
 pub mod invoice_error;
 pub mod invoice_request;
-mod merkle;
+pub(crate) mod merkle;
 pub mod offer;
 pub mod parse;
 mod payer;
@@ -0,0 +1,226 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Message handling for the negotiation of discreet log contracts.
+
+use bitcoin::secp256k1::{PublicKey, SecretKey};
+
+use crate::io::{self, Read};
+use crate::ln::contractmanager::ContractId;
+use crate::ln::contracts::{CollateralOutput, SettlementBranch, derive_contract_id};
+use crate::ln::msgs::DecodeError;
+use crate::ln::oracle::OracleAnnouncement;
+use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
+use crate::util::ser::{Readable, ReadableArgs, Writeable, Writer};
+use super::messenger::{OnionMessageReceivedVia, Responder};
+
+use crate::prelude::*;
+
+/// The onion message TLV type of a [`DlcMessage::Offer`].
+pub const DLC_OFFER_TLV_TYPE: u64 = 65_559;
+
+/// The onion message TLV type of a [`DlcMessage::Accept`].
+pub const DLC_ACCEPT_TLV_TYPE: u64 = 65_561;
+
+/// The onion message TLV type of a [`DlcMessage::Sign`].
+pub const DLC_SIGN_TLV_TYPE: u64 = 65_563;
+
+/// The onion message TLV type of a [`DlcMessage::Settle`].
+pub const DLC_SETTLE_TLV_TYPE: u64 = 65_565;
+
+/// A handler for an [`OnionMessage`] containing a [`DlcMessage`] as its payload.
+///
+/// Negotiating contracts over onion messages rather than direct peer messages allows the parties
+/// to do so privately over blinded paths, e.g. before deciding to open a channel with each other.
+///
+/// [`OnionMessage`]: crate::ln::msgs::OnionMessage
+pub trait DlcMessageHandler {
+	/// Handles the given message, returning a response to send over the message's reply path, if
+	/// any, e.g. a [`DlcMessage::Accept`] in response to an acceptable [`DlcMessage::Offer`].
+	///
+	/// As onion messages don't identify their sender, `received_via` tells which of the blinded
+	/// paths we handed out the message was received along, if any, allowing the handler to
+	/// associate it with the counterparty the path was given to. The `responder` carries the
+	/// sender's reply path, if any, and may be held on to in order to respond later, in which case
+	/// `None` should be returned.
+	fn handle_message(
+		&self, message: DlcMessage, received_via: OnionMessageReceivedVia,
+		responder: Option<Responder>
+	) -> Option<DlcMessage>;
+}
+
+/// Possible messages sent and received via an [`OnionMessage`] to negotiate a discreet log
+/// contract.
+///
+/// A contract is negotiated in three steps: the offerer sends a [`DlcMessage::Offer`] with the
+/// contract terms, the acceptor responds with a [`DlcMessage::Accept`] carrying its adaptor
+/// signatures for the settlement bundle, and the offerer completes the contract with a
+/// [`DlcMessage::Sign`] carrying its own. Once the oracle has attested to an outcome, either party
+/// may propose settling off-chain with a [`DlcMessage::Settle`].
+///
+/// [`OnionMessage`]: crate::ln::msgs::OnionMessage
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DlcMessage {
+	/// Offers a contract with the given terms.
+	Offer(DlcOffer),
+	/// Accepts a [`DlcMessage::Offer`].
+	Accept(DlcAccept),
+	/// Completes a contract after receiving a [`DlcMessage::Accept`].
+	Sign(DlcSign),
+	/// Proposes settling a contract according to an outcome the oracle attested to.
+	Settle(DlcSettle),
+}
+
+/// The terms of a contract offered via [`DlcMessage::Offer`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DlcOffer {
+	/// The id of the offered contract, derived via [`Self::derive_contract_id`].
+	pub contract_id: ContractId,
+	/// The collateral output spent by the settlement bundle, from the sender's point of view, i.e.
+	/// with the `holder` fields referring to the sender.
+	pub collateral: CollateralOutput,
+	/// The lock time of the settlement transactions, i.e. the contract's maturity.
+	pub lock_time: u32,
+	/// The branches of the settlement bundle, from the sender's point of view.
+	pub branches: Vec<SettlementBranch>,
+	/// The adaptor point for each entry in `branches`.
+	pub adaptor_points: Vec<PublicKey>,
+	/// The sender's contribution to the value of `collateral`, in satoshis.
+	pub holder_collateral_satoshis: u64,
+	/// The announcement of the oracle whose attestation points are the `adaptor_points`.
+	///
+	/// The recipient must check the `adaptor_points` against it via
+	/// [`OracleAnnouncement::validate_adaptor_points`] before accepting the offer.
+	pub oracle_announcement: OracleAnnouncement,
+}
+
+impl_writeable_tlv_based!(DlcOffer, {
+	(0, contract_id, required),
+	(2, collateral, required),
+	(4, lock_time, required),
+	(6, branches, required_vec),
+	(8, adaptor_points, required_vec),
+	(10, holder_collateral_satoshis, required),
+	(12, oracle_announcement, required),
+});
+
+impl DlcOffer {
+	/// Derives the id of the offered contract from its terms, with `contract_id` itself zeroed,
+	/// and the node ids of both parties.
+	///
+	/// The recipient of an offer must check that `contract_id` matches the derived id before
+	/// accepting it, as otherwise the sender may reuse the id of an unrelated contract.
+	pub fn derive_contract_id(
+		&self, offerer_node_id: &PublicKey, acceptor_node_id: &PublicKey
+	) -> ContractId {
+		let mut terms = self.clone();
+		terms.contract_id = ContractId([0; 32]);
+		derive_contract_id(
+			&terms.encode(), offerer_node_id, acceptor_node_id, &self.collateral.outpoint
+		)
+	}
+}
+
+/// The acceptance of a [`DlcOffer`] sent via [`DlcMessage::Accept`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DlcAccept {
+	/// The contract being accepted.
+	pub contract_id: ContractId,
+	/// The sender's adaptor signatures for all branches of the settlement bundle.
+	pub adaptor_signatures: Vec<EcdsaAdaptorSignature>,
+}
+
+impl_writeable_tlv_based!(DlcAccept, {
+	(0, contract_id, required),
+	(2, adaptor_signatures, required_vec),
+});
+
+/// The offerer's signatures completing a contract, sent via [`DlcMessage::Sign`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DlcSign {
+	/// The contract being completed.
+	pub contract_id: ContractId,
+	/// The sender's adaptor signatures for all branches of the settlement bundle.
+	pub adaptor_signatures: Vec<EcdsaAdaptorSignature>,
+}
+
+impl_writeable_tlv_based!(DlcSign, {
+	(0, contract_id, required),
+	(2, adaptor_signatures, required_vec),
+});
+
+/// A proposal to settle a contract off-chain, sent via [`DlcMessage::Settle`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DlcSettle {
+	/// The contract to settle.
+	pub contract_id: ContractId,
+	/// The outcome to settle the contract for.
+	pub outcome: Vec<u8>,
+	/// The oracle's attestation to `outcome`, i.e. the secret key for its adaptor point.
+	pub attestation: SecretKey,
+}
+
+impl_writeable_tlv_based!(DlcSettle, {
+	(0, contract_id, required),
+	(2, outcome, required),
+	(4, attestation, required),
+});
+
+impl DlcMessage {
+	/// Returns whether `tlv_type` corresponds to a TLV record for a [`DlcMessage`].
+	pub fn is_known_type(tlv_type: u64) -> bool {
+		match tlv_type {
+			DLC_OFFER_TLV_TYPE | DLC_ACCEPT_TLV_TYPE | DLC_SIGN_TLV_TYPE | DLC_SETTLE_TLV_TYPE => true,
+			_ => false,
+		}
+	}
+
+	/// The TLV record type for the message as used in an `onionmsg_tlv` TLV stream.
+	pub fn tlv_type(&self) -> u64 {
+		match self {
+			DlcMessage::Offer(_) => DLC_OFFER_TLV_TYPE,
+			DlcMessage::Accept(_) => DLC_ACCEPT_TLV_TYPE,
+			DlcMessage::Sign(_) => DLC_SIGN_TLV_TYPE,
+			DlcMessage::Settle(_) => DLC_SETTLE_TLV_TYPE,
+		}
+	}
+
+	/// The contract the message relates to.
+	pub fn contract_id(&self) -> ContractId {
+		match self {
+			DlcMessage::Offer(msg) => msg.contract_id,
+			DlcMessage::Accept(msg) => msg.contract_id,
+			DlcMessage::Sign(msg) => msg.contract_id,
+			DlcMessage::Settle(msg) => msg.contract_id,
+		}
+	}
+}
+
+impl Writeable for DlcMessage {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		match self {
+			DlcMessage::Offer(message) => message.write(w),
+			DlcMessage::Accept(message) => message.write(w),
+			DlcMessage::Sign(message) => message.write(w),
+			DlcMessage::Settle(message) => message.write(w),
+		}
+	}
+}
+
+impl ReadableArgs<u64> for DlcMessage {
+	fn read<R: Read>(r: &mut R, tlv_type: u64) -> Result<Self, DecodeError> {
+		match tlv_type {
+			DLC_OFFER_TLV_TYPE => Ok(DlcMessage::Offer(Readable::read(r)?)),
+			DLC_ACCEPT_TLV_TYPE => Ok(DlcMessage::Accept(Readable::read(r)?)),
+			DLC_SIGN_TLV_TYPE => Ok(DlcMessage::Sign(Readable::read(r)?)),
+			DLC_SETTLE_TLV_TYPE => Ok(DlcMessage::Settle(Readable::read(r)?)),
+			_ => Err(DecodeError::InvalidValue),
+		}
+	}
+}
@@ -10,16 +10,24 @@
 //! Onion message testing and test utilities live here.
 
 use crate::blinded_path::BlindedPath;
+use crate::events::{Event, EventsProvider};
 use crate::sign::{NodeSigner, Recipient};
-use crate::ln::features::InitFeatures;
+use crate::ln::contractmanager::ContractId;
+use crate::ln::features::{ChannelFeatures, InitFeatures, NodeFeatures};
 use crate::ln::msgs::{self, DecodeError, OnionMessageHandler};
-use super::{CustomOnionMessageContents, CustomOnionMessageHandler, Destination, MessageRouter, OffersMessage, OffersMessageHandler, OnionMessageContents, OnionMessagePath, OnionMessenger, SendError};
-use crate::util::ser::{Writeable, Writer};
+use super::{AnyOnionMessenger, ChannelPeerLookup, create_onion_message, CustomOnionMessageContents, CustomOnionMessageDelivery, CustomOnionMessageHandler, DefaultMessageRouter, DefaultMessageRouterParams, Destination, DlcAccept, DlcMessage, DlcMessageHandler, DlcSettle, MessageRouter, OffersMessage, OffersMessageHandler, OnionMessageContents, OnionMessageDedupConfig, OnionMessageDeliveryId, OnionMessageEvictionPolicy, OnionMessageForwardingPolicy, OnionMessageForwardingStats, OnionMessageMailboxConfig, OnionMessagePath, OnionMessagePriority, OnionMessageRpc, OnionMessageService, OnionMessageRateLimit, OnionMessageRateLimitObserver, OnionMessageRateLimits, OnionMessageReceivedVia, OnionMessageRequestId, OnionMessenger, OnionMessengerConfig, OnionMessengerStats, peel_onion_message, PeeledOnion, PendingOnionMessages, PENDING_ONION_MESSAGES_PERSISTENCE_KEY, RateLimitDirection, Responder, RpcRequestFailure, SendError, KeyDelegation, SignedCustomMessage, SignedCustomMessageVerifier};
+use crate::routing::gossip::{NetworkGraph, P2PGossipSync};
+use crate::routing::test_utils::{add_channel, add_or_update_node, get_nodes};
+use crate::util::persist::KVStorePersister;
+use crate::util::ser::{Readable, Writeable, Writer};
 use crate::util::test_utils;
 
 use bitcoin::network::constants::Network;
-use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
 
+use core::ops::Range;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
 use crate::io;
 use crate::io_extras::read_to_end;
 use crate::sync::{Arc, Mutex};
@@ -34,8 +42,11 @@ struct MessengerNode {
 		Arc<test_utils::TestLogger>,
 		Arc<TestMessageRouter>,
 		Arc<TestOffersMessageHandler>,
+		Arc<TestDlcMessageHandler>,
 		Arc<TestCustomMessageHandler>
 	>,
+	message_router: Arc<TestMessageRouter>,
+	dlc_message_handler: Arc<TestDlcMessageHandler>,
 	custom_message_handler: Arc<TestCustomMessageHandler>,
 }
 
@@ -45,7 +56,15 @@ impl MessengerNode {
 	}
 }
 
-struct TestMessageRouter {}
+struct TestMessageRouter {
+	supports_fragmentation: AtomicBool,
+}
+
+impl TestMessageRouter {
+	fn new() -> Self {
+		Self { supports_fragmentation: AtomicBool::new(true) }
+	}
+}
 
 impl MessageRouter for TestMessageRouter {
 	fn find_path(
@@ -54,8 +73,13 @@ impl MessageRouter for TestMessageRouter {
 		Ok(OnionMessagePath {
 			intermediate_nodes: vec![],
 			destination,
+			first_node_addresses: None,
 		})
 	}
+
+	fn supports_fragmentation(&self, _destination: &Destination) -> bool {
+		self.supports_fragmentation.load(Ordering::Acquire)
+	}
 }
 
 struct TestOffersMessageHandler {}
@@ -66,22 +90,55 @@ impl OffersMessageHandler for TestOffersMessageHandler {
 	}
 }
 
+struct TestDlcMessageHandler {
+	received_messages: Mutex<Vec<DlcMessage>>,
+	received_via: Mutex<Vec<OnionMessageReceivedVia>>,
+}
+
+impl TestDlcMessageHandler {
+	fn new() -> Self {
+		Self { received_messages: Mutex::new(Vec::new()), received_via: Mutex::new(Vec::new()) }
+	}
+}
+
+impl DlcMessageHandler for TestDlcMessageHandler {
+	fn handle_message(
+		&self, message: DlcMessage, received_via: OnionMessageReceivedVia,
+		_responder: Option<Responder>
+	) -> Option<DlcMessage> {
+		self.received_messages.lock().unwrap().push(message.clone());
+		self.received_via.lock().unwrap().push(received_via);
+		match message {
+			DlcMessage::Settle(settle) => Some(DlcMessage::Accept(DlcAccept {
+				contract_id: settle.contract_id,
+				adaptor_signatures: Vec::new(),
+			})),
+			_ => None,
+		}
+	}
+}
+
 #[derive(Clone, Debug, PartialEq)]
 enum TestCustomMessage {
 	Request,
 	Response,
+	/// A message too large to fit in a single onion message packet.
+	Large,
 }
 
 const CUSTOM_REQUEST_MESSAGE_TYPE: u64 = 4242;
 const CUSTOM_RESPONSE_MESSAGE_TYPE: u64 = 4343;
 const CUSTOM_REQUEST_MESSAGE_CONTENTS: [u8; 32] = [42; 32];
 const CUSTOM_RESPONSE_MESSAGE_CONTENTS: [u8; 32] = [43; 32];
+const CUSTOM_LARGE_MESSAGE_TYPE: u64 = 4444;
+const CUSTOM_LARGE_MESSAGE_LEN: usize = 40_000;
 
 impl CustomOnionMessageContents for TestCustomMessage {
 	fn tlv_type(&self) -> u64 {
 		match self {
 			TestCustomMessage::Request => CUSTOM_REQUEST_MESSAGE_TYPE,
 			TestCustomMessage::Response => CUSTOM_RESPONSE_MESSAGE_TYPE,
+			TestCustomMessage::Large => CUSTOM_LARGE_MESSAGE_TYPE,
 		}
 	}
 }
@@ -91,17 +148,54 @@ impl Writeable for TestCustomMessage {
 		match self {
 			TestCustomMessage::Request => Ok(CUSTOM_REQUEST_MESSAGE_CONTENTS.write(w)?),
 			TestCustomMessage::Response => Ok(CUSTOM_RESPONSE_MESSAGE_CONTENTS.write(w)?),
+			TestCustomMessage::Large => w.write_all(&vec![44; CUSTOM_LARGE_MESSAGE_LEN]),
 		}
 	}
 }
 
 struct TestCustomMessageHandler {
 	expected_messages: Mutex<VecDeque<TestCustomMessage>>,
+	respond_later: Mutex<bool>,
+	deferred_responders: Mutex<Vec<Responder>>,
+	pending_responses: Mutex<Vec<(TestCustomMessage, Destination, Option<BlindedPath>)>>,
+	pending_path_responses: Mutex<Vec<(TestCustomMessage, OnionMessagePath, Option<BlindedPath>)>>,
+	received_responses: Mutex<Vec<OnionMessageRequestId>>,
+	received_via: Mutex<Vec<(OnionMessageReceivedVia, Option<BlindedPath>)>>,
 }
 
 impl TestCustomMessageHandler {
 	fn new() -> Self {
-		Self { expected_messages: Mutex::new(VecDeque::new()) }
+		Self {
+			expected_messages: Mutex::new(VecDeque::new()),
+			respond_later: Mutex::new(false),
+			deferred_responders: Mutex::new(Vec::new()),
+			pending_responses: Mutex::new(Vec::new()),
+			pending_path_responses: Mutex::new(Vec::new()),
+			received_responses: Mutex::new(Vec::new()),
+			received_via: Mutex::new(Vec::new()),
+		}
+	}
+
+	fn respond_later(&self) {
+		*self.respond_later.lock().unwrap() = true;
+	}
+
+	/// Completes all requests which were deferred via [`Self::respond_later`].
+	fn complete_deferred_responses(&self) {
+		let mut pending_responses = self.pending_responses.lock().unwrap();
+		for responder in self.deferred_responders.lock().unwrap().drain(..) {
+			pending_responses.push(responder.respond(TestCustomMessage::Response));
+		}
+	}
+
+	/// Completes all requests which were deferred via [`Self::respond_later`], responding along
+	/// paths through the given `intermediate_nodes` rather than ones found by the messenger.
+	fn complete_deferred_responses_via(&self, intermediate_nodes: Vec<PublicKey>) {
+		let mut pending_path_responses = self.pending_path_responses.lock().unwrap();
+		for responder in self.deferred_responders.lock().unwrap().drain(..) {
+			let path = responder.path_via(intermediate_nodes.clone());
+			pending_path_responses.push(responder.respond_along_path(TestCustomMessage::Response, path, None));
+		}
 	}
 
 	fn expect_message(&self, message: TestCustomMessage) {
@@ -122,6 +216,37 @@ impl Drop for TestCustomMessageHandler {
 
 impl CustomOnionMessageHandler for TestCustomMessageHandler {
 	type CustomMessage = TestCustomMessage;
+	fn handle_custom_message_with_responder(
+		&self, msg: Self::CustomMessage, responder: Option<Responder>
+	) -> Option<Self::CustomMessage> {
+		if *self.respond_later.lock().unwrap() && msg == TestCustomMessage::Request {
+			self.handle_custom_message(msg);
+			self.deferred_responders.lock().unwrap().push(responder.unwrap());
+			return None;
+		}
+		self.handle_custom_message(msg)
+	}
+	fn handle_custom_message_with_context(
+		&self, msg: Self::CustomMessage, received_via: OnionMessageReceivedVia,
+		responder: Option<Responder>
+	) -> Option<Self::CustomMessage> {
+		let reply_path = responder.as_ref().map(|responder| responder.reply_path().clone());
+		self.received_via.lock().unwrap().push((received_via, reply_path));
+		self.handle_custom_message_with_responder(msg, responder)
+	}
+	fn handle_custom_response(
+		&self, msg: Self::CustomMessage, request_id: OnionMessageRequestId,
+		responder: Option<Responder>
+	) -> Option<Self::CustomMessage> {
+		self.received_responses.lock().unwrap().push(request_id);
+		self.handle_custom_message_with_responder(msg, responder)
+	}
+	fn release_pending_custom_messages(&self) -> Vec<(Self::CustomMessage, Destination, Option<BlindedPath>)> {
+		core::mem::take(&mut *self.pending_responses.lock().unwrap())
+	}
+	fn release_pending_custom_messages_along_paths(&self) -> Vec<(Self::CustomMessage, OnionMessagePath, Option<BlindedPath>)> {
+		core::mem::take(&mut *self.pending_path_responses.lock().unwrap())
+	}
 	fn handle_custom_message(&self, msg: Self::CustomMessage) -> Option<Self::CustomMessage> {
 		match self.expected_messages.lock().unwrap().pop_front() {
 			Some(expected_msg) => assert_eq!(expected_msg, msg),
@@ -130,7 +255,7 @@ impl CustomOnionMessageHandler for TestCustomMessageHandler {
 
 		match msg {
 			TestCustomMessage::Request => Some(TestCustomMessage::Response),
-			TestCustomMessage::Response => None,
+			TestCustomMessage::Response | TestCustomMessage::Large => None,
 		}
 	}
 	fn read_custom_message<R: io::Read>(&self, message_type: u64, buffer: &mut R) -> Result<Option<Self::CustomMessage>, DecodeError> where Self: Sized {
@@ -145,6 +270,11 @@ impl CustomOnionMessageHandler for TestCustomMessageHandler {
 				assert_eq!(buf, CUSTOM_RESPONSE_MESSAGE_CONTENTS);
 				Ok(Some(TestCustomMessage::Response))
 			},
+			CUSTOM_LARGE_MESSAGE_TYPE => {
+				let buf = read_to_end(buffer)?;
+				assert_eq!(buf, vec![44; CUSTOM_LARGE_MESSAGE_LEN]);
+				Ok(Some(TestCustomMessage::Large))
+			},
 			_ => Ok(None),
 		}
 	}
@@ -156,15 +286,18 @@ fn create_nodes(num_messengers: u8) -> Vec<MessengerNode> {
 		let logger = Arc::new(test_utils::TestLogger::with_id(format!("node {}", i)));
 		let seed = [i as u8; 32];
 		let keys_manager = Arc::new(test_utils::TestKeysInterface::new(&seed, Network::Testnet));
-		let message_router = Arc::new(TestMessageRouter {});
+		let message_router = Arc::new(TestMessageRouter::new());
 		let offers_message_handler = Arc::new(TestOffersMessageHandler {});
+		let dlc_message_handler = Arc::new(TestDlcMessageHandler::new());
 		let custom_message_handler = Arc::new(TestCustomMessageHandler::new());
 		nodes.push(MessengerNode {
 			keys_manager: keys_manager.clone(),
 			messenger: OnionMessenger::new(
-				keys_manager.clone(), keys_manager, logger.clone(), message_router,
-				offers_message_handler, custom_message_handler.clone()
+				keys_manager.clone(), keys_manager, logger.clone(), message_router.clone(),
+				offers_message_handler, dlc_message_handler.clone(), custom_message_handler.clone()
 			),
+			message_router,
+			dlc_message_handler,
 			custom_message_handler,
 		});
 	}
@@ -201,6 +334,7 @@ fn one_hop() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
 	};
 	nodes[0].messenger.send_onion_message(path, test_msg, None).unwrap();
 	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
@@ -215,6 +349,7 @@ fn two_unblinded_hops() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![nodes[1].get_node_pk()],
 		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
 	};
 	nodes[0].messenger.send_onion_message(path, test_msg, None).unwrap();
 	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Response);
@@ -231,6 +366,7 @@ fn two_unblinded_two_blinded() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![nodes[1].get_node_pk(), nodes[2].get_node_pk()],
 		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
 	};
 
 	nodes[0].messenger.send_onion_message(path, test_msg, None).unwrap();
@@ -248,6 +384,7 @@ fn three_blinded_hops() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
 	};
 
 	nodes[0].messenger.send_onion_message(path, test_msg, None).unwrap();
@@ -266,11 +403,128 @@ fn too_big_packet_error() {
 	let path = OnionMessagePath {
 		intermediate_nodes: hops,
 		destination: Destination::Node(hop_node_id),
+		first_node_addresses: None,
 	};
 	let err = nodes[0].messenger.send_onion_message(path, test_msg, None).unwrap_err();
 	assert_eq!(err, SendError::TooBigPacket);
 }
 
+#[test]
+fn fragmented_message() {
+	// Messages too large for a single packet are split into fragments, which are reassembled by
+	// the recipient regardless of the order they arrive in.
+	let nodes = create_nodes(3);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Large), None).unwrap();
+
+	let fragments = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap();
+	assert_eq!(fragments.len(), 2);
+	for fragment in fragments.iter().rev() {
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), fragment);
+	}
+
+	let fragments = nodes[1].messenger.release_pending_msgs().remove(&nodes[2].get_node_pk()).unwrap();
+	assert_eq!(fragments.len(), 2);
+	nodes[2].messenger.handle_onion_message(&nodes[1].get_node_pk(), &fragments[0]);
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Large);
+	nodes[2].messenger.handle_onion_message(&nodes[1].get_node_pk(), &fragments[1]);
+}
+
+#[test]
+fn fragmented_message_timeout() {
+	// Partially received messages are dropped after a number of timer ticks, such that any
+	// remaining fragments are ignored.
+	let nodes = create_nodes(2);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Large), None).unwrap();
+
+	let fragments = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap();
+	assert_eq!(fragments.len(), 2);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &fragments[0]);
+	for _ in 0..=super::messenger::REASSEMBLY_TIMEOUT_TICKS {
+		nodes[1].messenger.timer_tick_occurred();
+	}
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &fragments[1]);
+}
+
+#[test]
+fn fragmentation_requires_recipient_support() {
+	// Messages too large for a single packet aren't split into fragments unless the router knows
+	// the recipient can reassemble them.
+	let nodes = create_nodes(2);
+	nodes[0].message_router.supports_fragmentation.store(false, Ordering::Release);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let err = nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Large), None).unwrap_err();
+	assert_eq!(err, SendError::TooBigPacket);
+	assert!(nodes[0].messenger.release_pending_msgs().get(&nodes[1].get_node_pk()).map_or(true, |msgs| msgs.is_empty()));
+}
+
+#[test]
+fn fragments_queued_atomically() {
+	// If not all fragments of a message fit in the peer's buffer, none of them are queued.
+	let nodes = create_nodes(2);
+	nodes[0].messenger.set_config(OnionMessengerConfig {
+		max_peer_buffer_bytes: 48 * 1024,
+		..Default::default()
+	});
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let err = nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Large), None).unwrap_err();
+	assert_eq!(err, SendError::BufferFull);
+	assert!(nodes[0].messenger.release_pending_msgs().get(&nodes[1].get_node_pk()).map_or(true, |msgs| msgs.is_empty()));
+
+	// Messages which fit are still sent.
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	pass_along_path(&nodes);
+}
+
+#[test]
+fn fragmented_message_reassembly_limited_per_peer() {
+	// A single peer can only have a few messages reassembled at once, leaving room for others.
+	let nodes = create_nodes(2);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let mut first_fragments = Vec::new();
+	let mut last_fragments = Vec::new();
+	for _ in 0..5 {
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Large), None).unwrap();
+		let mut fragments = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap();
+		assert_eq!(fragments.len(), 2);
+		last_fragments.push(fragments.pop().unwrap());
+		first_fragments.push(fragments.pop().unwrap());
+	}
+	for fragment in first_fragments.iter() {
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), fragment);
+	}
+	// Only the first four messages were being reassembled, so the last fragment of the fifth one
+	// doesn't complete it.
+	for _ in 0..4 {
+		nodes[1].custom_message_handler.expect_message(TestCustomMessage::Large);
+	}
+	for fragment in last_fragments.iter() {
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), fragment);
+	}
+}
+
 #[test]
 fn we_are_intro_node() {
 	// If we are sending straight to a blinded path and we are the introduction node, we need to
@@ -283,6 +537,7 @@ fn we_are_intro_node() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
 	};
 
 	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg.clone()), None).unwrap();
@@ -294,6 +549,7 @@ fn we_are_intro_node() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
 	};
 	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg), None).unwrap();
 	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
@@ -314,6 +570,7 @@ fn invalid_blinded_path_error() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
 	};
 	let err = nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg.clone()), None).unwrap_err();
 	assert_eq!(err, SendError::TooFewBlindedHops);
@@ -325,6 +582,7 @@ fn invalid_blinded_path_error() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
 	};
 	let err = nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg), None).unwrap_err();
 	assert_eq!(err, SendError::TooFewBlindedHops);
@@ -340,6 +598,7 @@ fn reply_path() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![nodes[1].get_node_pk(), nodes[2].get_node_pk()],
 		destination: Destination::Node(nodes[3].get_node_pk()),
+		first_node_addresses: None,
 	};
 	let reply_path = BlindedPath::new_for_message(&[nodes[2].get_node_pk(), nodes[1].get_node_pk(), nodes[0].get_node_pk()], &*nodes[0].keys_manager, &secp_ctx).unwrap();
 	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg.clone()), Some(reply_path)).unwrap();
@@ -355,6 +614,7 @@ fn reply_path() {
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
 	};
 	let reply_path = BlindedPath::new_for_message(&[nodes[2].get_node_pk(), nodes[1].get_node_pk(), nodes[0].get_node_pk()], &*nodes[0].keys_manager, &secp_ctx).unwrap();
 
@@ -369,63 +629,1791 @@ fn reply_path() {
 }
 
 #[test]
-fn invalid_custom_message_type() {
-	let nodes = create_nodes(2);
+fn automatic_reply_path() {
+	let mut nodes = create_nodes(3);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message_with_reply(path, OnionMessageContents::Custom(TestCustomMessage::Request)).unwrap();
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
 
-	struct InvalidCustomMessage{}
-	impl CustomOnionMessageContents for InvalidCustomMessage {
-		fn tlv_type(&self) -> u64 {
-			// Onion message contents must have a TLV >= 64.
-			63
-		}
+	// The reply path goes through our only peer.
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	pass_along_path(&nodes);
+
+	// Without any peers, no reply path can be built.
+	let nodes = create_nodes(1);
+	assert_eq!(nodes[0].messenger.create_reply_path(), Err(SendError::ReplyPathNotFound));
+}
+
+#[test]
+fn standalone_create_and_peel() {
+	// Check that onion messages can be built and processed without going through an
+	// `OnionMessenger`.
+	let nodes = create_nodes(3);
+	let secp_ctx = Secp256k1::new();
+	let logger = test_utils::TestLogger::new();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let (first_node_id, onion_msg) = create_onion_message(&nodes[0].keys_manager, &nodes[0].keys_manager,
+		&secp_ctx, path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	assert_eq!(first_node_id, nodes[1].get_node_pk());
+
+	let onion_msg = match peel_onion_message(&onion_msg, &secp_ctx, &*nodes[1].keys_manager, &logger, &*nodes[1].custom_message_handler) {
+		Ok(PeeledOnion::Forward(next_node_id, onion_msg)) => {
+			assert_eq!(next_node_id, nodes[2].get_node_pk());
+			onion_msg
+		},
+		_ => panic!("Unexpected peeled onion"),
+	};
+	assert!(peel_onion_message(&onion_msg, &secp_ctx, &*nodes[1].keys_manager, &logger, &*nodes[1].custom_message_handler).is_err());
+	match peel_onion_message(&onion_msg, &secp_ctx, &*nodes[2].keys_manager, &logger, &*nodes[2].custom_message_handler) {
+		Ok(PeeledOnion::Receive(OnionMessageContents::Custom(TestCustomMessage::Response), None, None)) => {},
+		_ => panic!("Unexpected peeled onion"),
 	}
 
-	impl Writeable for InvalidCustomMessage {
-		fn write<W: Writer>(&self, _w: &mut W) -> Result<(), io::Error> { unreachable!() }
+	// Messages too large for a single packet aren't split into fragments.
+	let err = create_onion_message(&nodes[0].keys_manager, &nodes[0].keys_manager, &secp_ctx, path,
+		OnionMessageContents::Custom(TestCustomMessage::Large), None).unwrap_err();
+	assert_eq!(err, SendError::TooBigPacket);
+}
+
+#[test]
+fn batch_send() {
+	// Check that a batch of messages is sent together, with each failing independently.
+	let nodes = create_nodes(3);
+	let test_msg = || OnionMessageContents::Custom(TestCustomMessage::Response);
+
+	let secp_ctx = Secp256k1::new();
+	let mut blinded_path = BlindedPath::new_for_message(&[nodes[1].get_node_pk(), nodes[2].get_node_pk()], &*nodes[2].keys_manager, &secp_ctx).unwrap();
+	blinded_path.blinded_hops.clear();
+	let results = nodes[0].messenger.send_onion_messages(vec![
+		(OnionMessagePath {
+			intermediate_nodes: vec![],
+			destination: Destination::Node(nodes[1].get_node_pk()),
+			first_node_addresses: None,
+		}, test_msg(), None),
+		(OnionMessagePath {
+			intermediate_nodes: vec![],
+			destination: Destination::BlindedPath(blinded_path),
+			first_node_addresses: None,
+		}, test_msg(), None),
+		(OnionMessagePath {
+			intermediate_nodes: vec![],
+			destination: Destination::Node(nodes[2].get_node_pk()),
+			first_node_addresses: None,
+		}, test_msg(), None),
+		(OnionMessagePath {
+			intermediate_nodes: vec![nodes[1].get_node_pk()],
+			destination: Destination::Node(nodes[2].get_node_pk()),
+			first_node_addresses: None,
+		}, test_msg(), None),
+	]);
+	assert_eq!(results, vec![Ok(()), Err(SendError::TooFewBlindedHops), Err(SendError::InvalidFirstHop), Ok(())]);
+
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap();
+	assert_eq!(onion_msgs.len(), 2);
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	for onion_msg in onion_msgs {
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	}
+	let onion_msgs = nodes[1].messenger.release_pending_msgs().remove(&nodes[2].get_node_pk()).unwrap();
+	assert_eq!(onion_msgs.len(), 1);
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[2].messenger.handle_onion_message(&nodes[1].get_node_pk(), &onion_msgs[0]);
+}
+
+#[test]
+fn received_via_context() {
+	// Check that handlers learn whether a message was sent to us directly or along a blinded path
+	// we created, along with the sender's reply path.
+	let mut nodes = create_nodes(3);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message_with_reply(path, OnionMessageContents::Custom(TestCustomMessage::Request)).unwrap();
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
+	{
+		let received_via = nodes[2].custom_message_handler.received_via.lock().unwrap();
+		assert_eq!(received_via.len(), 1);
+		assert_eq!(received_via[0].0, OnionMessageReceivedVia::Direct);
+		assert_eq!(received_via[0].1.as_ref().unwrap().introduction_node_id, nodes[1].get_node_pk());
+	}
+
+	// The response is sent along the reply path node 0 created, which identifies itself.
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	pass_along_path(&nodes);
+	let received_via = nodes[2].custom_message_handler.received_via.lock().unwrap();
+	assert_eq!(received_via.len(), 1);
+	match received_via[0] {
+		(OnionMessageReceivedVia::BlindedPath { .. }, None) => {},
+		_ => panic!("Unexpected context: {:?}", received_via[0]),
 	}
+}
+
+#[test]
+fn deferred_reply() {
+	// Check that a handler may hold on to a `Responder` and reply once it is ready, rather than
+	// responding inline from `handle_custom_message_with_responder`.
+	let mut nodes = create_nodes(3);
+	let secp_ctx = Secp256k1::new();
+
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let reply_path = BlindedPath::new_for_message(&[nodes[1].get_node_pk(), nodes[0].get_node_pk()], &*nodes[0].keys_manager, &secp_ctx).unwrap();
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Request), Some(reply_path)).unwrap();
+	nodes[2].custom_message_handler.respond_later();
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
+
+	// No response is sent until the handler completes the request.
+	assert!(nodes[2].messenger.release_pending_msgs().values().all(|msgs| msgs.is_empty()));
+
+	nodes[2].custom_message_handler.complete_deferred_responses();
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	pass_along_path(&nodes);
+}
+
+#[test]
+fn deferred_reply_along_path() {
+	// Check that a handler may reply along a path of its choosing, rather than one found by the
+	// messenger's `MessageRouter`.
+	let mut nodes = create_nodes(4);
+	let secp_ctx = Secp256k1::new();
+
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk(), nodes[2].get_node_pk()],
+		destination: Destination::Node(nodes[3].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let reply_path = BlindedPath::new_for_message(&[nodes[1].get_node_pk(), nodes[0].get_node_pk()], &*nodes[0].keys_manager, &secp_ctx).unwrap();
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Request), Some(reply_path)).unwrap();
+	nodes[3].custom_message_handler.respond_later();
+	nodes[3].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
+	assert_eq!(
+		nodes[3].custom_message_handler.deferred_responders.lock().unwrap()[0].received_via(),
+		OnionMessageReceivedVia::Direct
+	);
+
+	// The reply path's introduction node isn't our peer, so the response must be sent through
+	// nodes[2] explicitly.
+	nodes[3].custom_message_handler.complete_deferred_responses_via(vec![nodes[2].get_node_pk()]);
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	pass_along_path(&nodes);
+}
+
+#[test]
+fn request_response_correlation() {
+	// Check that responses to requests sent via `send_onion_message_request` are matched to the
+	// request they respond to.
+	let mut nodes = create_nodes(3);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let request_id = OnionMessageRequestId([42; 32]);
+	nodes[0].messenger.send_onion_message_request(path.clone(), TestCustomMessage::Request, request_id, 1).unwrap();
+	assert_eq!(nodes[0].messenger.list_pending_requests(), vec![request_id]);
+
+	// Only one request with a given id may be outstanding at once.
+	assert_eq!(
+		nodes[0].messenger.send_onion_message_request(path, TestCustomMessage::Request, request_id, 1),
+		Err(SendError::DuplicateRequestId)
+	);
+
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	pass_along_path(&nodes);
+
+	assert_eq!(*nodes[2].custom_message_handler.received_responses.lock().unwrap(), vec![request_id]);
+	assert!(nodes[2].messenger.list_pending_requests().is_empty());
 
-	let test_msg = OnionMessageContents::Custom(InvalidCustomMessage {});
+	// Ticking no longer times out the completed request.
+	nodes[2].messenger.timer_tick_occurred();
+	nodes[2].messenger.timer_tick_occurred();
+	let events = Mutex::new(Vec::new());
+	nodes[2].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert!(events.lock().unwrap().is_empty());
+}
+
+#[test]
+fn request_timeout() {
+	// Check that requests which are not responded to in time generate an
+	// `Event::OnionMessageTimedOut`, and that late responses are no longer correlated.
+	let mut nodes = create_nodes(2);
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
 	};
-	let err = nodes[0].messenger.send_onion_message(path, test_msg, None).unwrap_err();
-	assert_eq!(err, SendError::InvalidMessage);
+	let request_id = OnionMessageRequestId([42; 32]);
+	nodes[0].messenger.send_onion_message_request(path, TestCustomMessage::Request, request_id, 1).unwrap();
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
+
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.timer_tick_occurred();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert!(events.lock().unwrap().is_empty());
+
+	nodes[0].messenger.timer_tick_occurred();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageTimedOut { request_id }]);
+	assert!(nodes[0].messenger.list_pending_requests().is_empty());
+
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	pass_along_path(&nodes);
+	assert!(nodes[1].custom_message_handler.received_responses.lock().unwrap().is_empty());
 }
 
 #[test]
-fn peer_buffer_full() {
-	let nodes = create_nodes(2);
-	let test_msg = TestCustomMessage::Request;
+fn forged_responses_not_correlated() {
+	// Check that a node which knows the id of a request cannot forge a response to it by building
+	// its own blinded path to us with the id as the `path_id`, and that the forged `path_id` isn't
+	// reported to the handler as one of ours.
+	let mut nodes = create_nodes(2);
 	let path = OnionMessagePath {
 		intermediate_nodes: vec![],
 		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
 	};
-	for _ in 0..188 { // Based on MAX_PER_PEER_BUFFER_SIZE in OnionMessenger
-		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(test_msg.clone()), None).unwrap();
-	}
-	let err = nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg), None).unwrap_err();
-	assert_eq!(err, SendError::BufferFull);
+	let request_id = OnionMessageRequestId([42; 32]);
+	nodes[0].messenger.send_onion_message_request(path, TestCustomMessage::Request, request_id, 1).unwrap();
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
+
+	let secp_ctx = Secp256k1::new();
+	let forged_path = BlindedPath::new_for_message_with_path_id(
+		&[nodes[1].get_node_pk(), nodes[0].get_node_pk()], Some(request_id.0), &*nodes[1].keys_manager,
+		&secp_ctx
+	).unwrap();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::BlindedPath(forged_path),
+		first_node_addresses: None,
+	};
+	nodes[1].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	pass_along_path(&nodes);
+	assert!(nodes[1].custom_message_handler.received_responses.lock().unwrap().is_empty());
+	assert_eq!(nodes[1].messenger.list_pending_requests(), vec![request_id]);
+	let received_via = nodes[1].custom_message_handler.received_via.lock().unwrap();
+	assert_eq!(received_via.len(), 1);
+	assert_eq!(received_via[0].0, OnionMessageReceivedVia::Direct);
 }
 
 #[test]
-fn many_hops() {
-	// Check we can send over a route with many hops. This will exercise our logic for onion messages
-	// of size [`crate::onion_message::packet::BIG_PACKET_HOP_DATA_LEN`].
-	let num_nodes: usize = 25;
-	let nodes = create_nodes(num_nodes as u8);
-	let test_msg = TestCustomMessage::Response;
+fn delivery_receipts() {
+	// Check that messages sent via `send_onion_message_with_receipt` are acknowledged by their
+	// recipient, including along blinded paths, generating an `Event::OnionMessageDelivered`, and
+	// that unacknowledged messages generate an `Event::OnionMessageReceiptTimedOut` once they time
+	// out.
+	let mut nodes = create_nodes(3);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let id = OnionMessageDeliveryId([42; 32]);
+	let test_msg = OnionMessageContents::Custom(TestCustomMessage::Response);
+	nodes[0].messenger.send_onion_message_with_receipt(path.clone(), test_msg, id, 1).unwrap();
+	assert_eq!(nodes[0].messenger.list_pending_receipts(), vec![id]);
 
-	let mut intermediate_nodes = vec![];
-	for i in 1..(num_nodes-1) {
-		intermediate_nodes.push(nodes[i].get_node_pk());
-	}
+	// Only one message with a given id may be awaiting a receipt at once.
+	let test_msg = OnionMessageContents::Custom(TestCustomMessage::Response);
+	assert_eq!(
+		nodes[0].messenger.send_onion_message_with_receipt(path.clone(), test_msg, id, 1),
+		Err(SendError::DuplicateDeliveryId)
+	);
 
-	let path = OnionMessagePath {
-		intermediate_nodes,
-		destination: Destination::Node(nodes[num_nodes-1].get_node_pk()),
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Response);
+	pass_along_path(&nodes);
+	nodes.reverse();
+	pass_along_path(&nodes);
+	nodes.reverse();
+
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageDelivered { id }]);
+	assert!(nodes[0].messenger.list_pending_receipts().is_empty());
+
+	// Messages sent along a blinded path are acknowledged with a key derived for the path.
+	let blinded_path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::BlindedPath(nodes[2].messenger.create_reply_path().unwrap()),
+		first_node_addresses: None,
 	};
-	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg), None).unwrap();
-	nodes[num_nodes-1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	let id = OnionMessageDeliveryId([44; 32]);
+	let test_msg = OnionMessageContents::Custom(TestCustomMessage::Response);
+	nodes[0].messenger.send_onion_message_with_receipt(blinded_path, test_msg, id, 1).unwrap();
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Response);
+	pass_along_path(&nodes);
+	nodes.reverse();
 	pass_along_path(&nodes);
+	nodes.reverse();
+	events.lock().unwrap().clear();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageDelivered { id }]);
+
+	// Once a message which was never acknowledged times out, we stop waiting for its receipt.
+	let id = OnionMessageDeliveryId([43; 32]);
+	let test_msg = OnionMessageContents::Custom(TestCustomMessage::Response);
+	nodes[0].messenger.send_onion_message_with_receipt(path, test_msg, id, 1).unwrap();
+	nodes[0].messenger.timer_tick_occurred();
+	assert_eq!(nodes[0].messenger.list_pending_receipts(), vec![id]);
+	nodes[0].messenger.timer_tick_occurred();
+	assert!(nodes[0].messenger.list_pending_receipts().is_empty());
+	events.lock().unwrap().clear();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageReceiptTimedOut { id }]);
+}
+
+#[test]
+fn path_probes() {
+	// Check that probes are acknowledged by their recipient without reaching its handler,
+	// generating an `Event::OnionMessageProbeSuccessful`, and that unacknowledged probes generate
+	// an `Event::OnionMessageProbeFailed` once they time out.
+	let mut nodes = create_nodes(3);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(nodes[2].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let id = nodes[0].messenger.probe_path(path.clone()).unwrap();
+	assert!(nodes[0].messenger.list_pending_receipts().is_empty());
+	pass_along_path(&nodes);
+	nodes.reverse();
+	pass_along_path(&nodes);
+	nodes.reverse();
+
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageProbeSuccessful { id }]);
+
+	let id = nodes[0].messenger.probe_path(path).unwrap();
+	for _ in 0..OnionMessengerConfig::default().probe_timeout_ticks {
+		nodes[0].messenger.timer_tick_occurred();
+	}
+	events.lock().unwrap().clear();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert!(events.lock().unwrap().is_empty());
+	nodes[0].messenger.timer_tick_occurred();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageProbeFailed { id }]);
+
+	// Probes along blinded paths the recipient didn't create through its messenger are
+	// acknowledged with a one-time key rather than its node key, but still succeed.
+	nodes[0].messenger.release_pending_msgs();
+	let secp_ctx = Secp256k1::new();
+	let blinded_path = BlindedPath::new_for_message(
+		&[nodes[1].get_node_pk(), nodes[2].get_node_pk()], &*nodes[2].keys_manager, &secp_ctx).unwrap();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::BlindedPath(blinded_path),
+		first_node_addresses: None,
+	};
+	let id = nodes[0].messenger.probe_path(path).unwrap();
+	pass_along_path(&nodes);
+	nodes.reverse();
+	pass_along_path(&nodes);
+	nodes.reverse();
+	events.lock().unwrap().clear();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageProbeSuccessful { id }]);
+}
+
+#[test]
+fn liveness_probes() {
+	// Check that liveness probes are answered by their recipient without reaching its handler,
+	// including along blinded paths, generating an `Event::LivenessProbeCompleted` with the
+	// round-trip time measured by our `TimeSource`, and that outcomes are recorded in the
+	// `LivenessStats` of the probed destination.
+	let mut nodes = create_nodes(3);
+	let time_source = test_utils::TestTimeSource::new();
+	nodes[0].messenger.set_time_source(time_source.clone());
+	let destination = Destination::Node(nodes[2].get_node_pk());
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: destination.clone(),
+		first_node_addresses: None,
+	};
+	let id = nodes[0].messenger.send_liveness_probe(path.clone()).unwrap();
+	assert!(nodes[0].messenger.list_pending_receipts().is_empty());
+	pass_along_path(&nodes);
+	time_source.advance(Duration::from_millis(250));
+	nodes.reverse();
+	pass_along_path(&nodes);
+	nodes.reverse();
+
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::LivenessProbeCompleted { id, round_trip_time_ms: Some(250) }]);
+
+	let blinded_path = nodes[2].messenger.create_reply_path().unwrap();
+	let blinded_destination = Destination::BlindedPath(blinded_path);
+	let blinded_path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: blinded_destination.clone(),
+		first_node_addresses: None,
+	};
+	let id = nodes[0].messenger.send_liveness_probe(blinded_path).unwrap();
+	pass_along_path(&nodes);
+	time_source.advance(Duration::from_millis(50));
+	nodes.reverse();
+	pass_along_path(&nodes);
+	nodes.reverse();
+	events.lock().unwrap().clear();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::LivenessProbeCompleted { id, round_trip_time_ms: Some(50) }]);
+
+	// Statistics are kept per destination.
+	let stats = nodes[0].messenger.liveness_stats(&destination).unwrap();
+	assert_eq!(stats.probes_sent, 1);
+	assert_eq!(stats.probes_answered, 1);
+	assert_eq!(stats.last_round_trip_time, Some(Duration::from_millis(250)));
+	let stats = nodes[0].messenger.liveness_stats(&blinded_destination).unwrap();
+	assert_eq!(stats.average_round_trip_time(), Some(Duration::from_millis(50)));
+	assert!(nodes[0].messenger.liveness_stats(&Destination::Node(nodes[1].get_node_pk())).is_none());
+}
+
+#[test]
+fn liveness_probe_timeout() {
+	// Check that unanswered liveness probes time out with an `Event::LivenessProbeCompleted`
+	// without a round-trip time, and that answers which arrive once a probe timed out are ignored.
+	let mut nodes = create_nodes(2);
+	let destination = Destination::Node(nodes[1].get_node_pk());
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: destination.clone(),
+		first_node_addresses: None,
+	};
+	let id = nodes[0].messenger.send_liveness_probe(path).unwrap();
+	pass_along_path(&nodes);
+	for _ in 0..OnionMessengerConfig::default().probe_timeout_ticks {
+		nodes[0].messenger.timer_tick_occurred();
+	}
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert!(events.lock().unwrap().is_empty());
+	nodes[0].messenger.timer_tick_occurred();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::LivenessProbeCompleted { id, round_trip_time_ms: None }]);
+
+	nodes.reverse();
+	pass_along_path(&nodes);
+	nodes.reverse();
+	events.lock().unwrap().clear();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert!(events.lock().unwrap().is_empty());
+	let stats = nodes[0].messenger.liveness_stats(&destination).unwrap();
+	assert_eq!((stats.probes_sent, stats.probes_answered, stats.probes_timed_out), (1, 0, 1));
+}
+
+#[test]
+fn custom_message_events() {
+	// Check that custom messages are queued as `Event::CustomOnionMessageReceived`s rather than
+	// passed to the handler once delivery via events is set, including reassembled messages and
+	// responses to our requests.
+	let mut nodes = create_nodes(2);
+	nodes[0].messenger.set_custom_message_delivery(CustomOnionMessageDelivery::Events);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let request_id = OnionMessageRequestId([42; 32]);
+	nodes[0].messenger.send_onion_message_request(path, TestCustomMessage::Request, request_id, 1).unwrap();
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Request);
+	pass_along_path(&nodes);
+	nodes.reverse();
+	pass_along_path(&nodes);
+	nodes.reverse();
+
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	match &events.lock().unwrap()[..] {
+		[Event::CustomOnionMessageReceived {
+			tlv_type, data, received_via: OnionMessageReceivedVia::BlindedPath { .. },
+			request_id: Some(received_request_id), reply_path: None,
+		}] => {
+			assert_eq!(*tlv_type, CUSTOM_RESPONSE_MESSAGE_TYPE);
+			assert_eq!(*data, CUSTOM_RESPONSE_MESSAGE_CONTENTS.to_vec());
+			assert_eq!(*received_request_id, request_id);
+		},
+		e => panic!("Unexpected events: {:?}", e),
+	}
+	assert!(nodes[0].messenger.list_pending_requests().is_empty());
+	assert!(nodes[0].custom_message_handler.received_via.lock().unwrap().is_empty());
+
+	// Fragmented messages are delivered as events once reassembled.
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[0].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[1].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Large), None).unwrap();
+	let fragments = nodes[1].messenger.release_pending_msgs().remove(&nodes[0].get_node_pk()).unwrap();
+	assert_eq!(fragments.len(), 2);
+	for fragment in fragments.iter() {
+		nodes[0].messenger.handle_onion_message(&nodes[1].get_node_pk(), fragment);
+	}
+
+	events.lock().unwrap().clear();
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::CustomOnionMessageReceived {
+		tlv_type: CUSTOM_LARGE_MESSAGE_TYPE,
+		data: vec![44; CUSTOM_LARGE_MESSAGE_LEN],
+		received_via: OnionMessageReceivedVia::Direct,
+		request_id: None,
+		reply_path: None,
+	}]);
+
+	// Switching back passes messages to the handler again.
+	nodes[0].messenger.set_custom_message_delivery(CustomOnionMessageDelivery::Handler);
+	nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes.reverse();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	pass_along_path(&nodes);
+}
+
+#[test]
+fn duplicate_messages_dropped() {
+	// With deduplication enabled, a replayed custom message is dropped before reaching the handler
+	// until it's forgotten after the configured number of ticks.
+	let nodes = create_nodes(2);
+	nodes[1].messenger.set_dedup_config(Some(OnionMessageDedupConfig { ttl_ticks: 1, ..Default::default() }));
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let onion_msg = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap().pop_front().unwrap();
+
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	assert_eq!(nodes[1].messenger.stats().dropped, 1);
+
+	nodes[1].messenger.timer_tick_occurred();
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	assert_eq!(nodes[1].messenger.stats().dropped, 2);
+
+	nodes[1].messenger.timer_tick_occurred();
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+
+	// Disabling deduplication handles every message.
+	nodes[1].messenger.set_dedup_config(None);
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+}
+
+#[test]
+fn identical_responses_not_deduplicated() {
+	// Identical responses to distinct requests arrive along distinct reply paths, so they aren't
+	// treated as duplicates of one another.
+	let nodes = create_nodes(2);
+	let (node_0_pk, node_1_pk) = (nodes[0].get_node_pk(), nodes[1].get_node_pk());
+	nodes[0].messenger.set_dedup_config(Some(OnionMessageDedupConfig::default()));
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	};
+	let request_ids = [OnionMessageRequestId([42; 32]), OnionMessageRequestId([43; 32])];
+	for request_id in request_ids.iter() {
+		nodes[0].messenger.send_onion_message_request(path.clone(), TestCustomMessage::Request, *request_id, 1).unwrap();
+	}
+
+	for onion_msg in nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap() {
+		nodes[1].custom_message_handler.expect_message(TestCustomMessage::Request);
+		nodes[1].messenger.handle_onion_message(&node_0_pk, &onion_msg);
+	}
+	for onion_msg in nodes[1].messenger.release_pending_msgs().remove(&node_0_pk).unwrap() {
+		nodes[0].custom_message_handler.expect_message(TestCustomMessage::Response);
+		nodes[0].messenger.handle_onion_message(&node_1_pk, &onion_msg);
+	}
+	assert_eq!(*nodes[0].custom_message_handler.received_responses.lock().unwrap(), request_ids.to_vec());
+	assert_eq!(nodes[0].messenger.stats().dropped, 0);
+	assert!(nodes[0].messenger.list_pending_requests().is_empty());
+}
+
+#[test]
+fn signed_custom_messages() {
+	// Signed messages authenticate the sending node, whether signed with its node key or with a
+	// delegated key, and fail to read if tampered with, sent to another node, or signed with an
+	// expired or revoked delegation.
+	let nodes = create_nodes(2);
+	let (node_0_pk, node_1_pk) = (nodes[0].get_node_pk(), nodes[1].get_node_pk());
+	let mut verifier = SignedCustomMessageVerifier::new(node_1_pk);
+	let now = Duration::from_secs(1_000_000);
+	let read = |encoded: &[u8], verifier: &SignedCustomMessageVerifier, now: Duration|
+		SignedCustomMessage::read(&mut &encoded[..], CUSTOM_REQUEST_MESSAGE_TYPE, verifier, now,
+			|message_type, buffer| nodes[1].custom_message_handler.read_custom_message(message_type, buffer));
+
+	let signed = SignedCustomMessage::sign(TestCustomMessage::Request, node_1_pk, None, &nodes[0].keys_manager).unwrap();
+	assert_eq!(signed.tlv_type(), CUSTOM_REQUEST_MESSAGE_TYPE);
+	let mut encoded = signed.encode();
+	let read_signed = read(&encoded, &verifier, now).unwrap().unwrap();
+	assert_eq!(read_signed.node_id(), node_0_pk);
+	assert!(read_signed.delegation().is_none());
+	assert!(read_signed.verify_responder(None).is_ok());
+	assert_eq!(read_signed.into_contents(), TestCustomMessage::Request);
+
+	// Reading the payload as a different message type invalidates the signature.
+	assert_eq!(SignedCustomMessage::read(&mut &encoded[..], CUSTOM_RESPONSE_MESSAGE_TYPE, &verifier, now,
+		|message_type, buffer| nodes[1].custom_message_handler.read_custom_message(message_type, buffer)).unwrap_err(),
+		DecodeError::InvalidValue);
+
+	// As does reading a message signed for another node.
+	let signed = SignedCustomMessage::sign(TestCustomMessage::Request, node_0_pk, None, &nodes[0].keys_manager).unwrap();
+	assert_eq!(read(&signed.encode(), &verifier, now).unwrap_err(), DecodeError::InvalidValue);
+
+	// Flipping a bit of the payload, which directly follows its TLV type and length, does too.
+	encoded[3] ^= 1;
+	assert_eq!(read(&encoded, &verifier, now).unwrap_err(), DecodeError::InvalidValue);
+
+	// The reply path is signed, so a responder with a different one is rejected.
+	let secp_ctx = Secp256k1::new();
+	let reply_path = BlindedPath::new_for_message(&[node_1_pk, node_0_pk], &*nodes[0].keys_manager, &secp_ctx).unwrap();
+	let signed = SignedCustomMessage::sign(
+		TestCustomMessage::Request, node_1_pk, Some(reply_path.clone()), &nodes[0].keys_manager).unwrap();
+	let read_signed = read(&signed.encode(), &verifier, now).unwrap().unwrap();
+	assert_eq!(read_signed.reply_path(), Some(&reply_path));
+	assert!(read_signed.verify_responder(None).is_err());
+
+	let delegate_key = SecretKey::from_slice(&[42; 32]).unwrap();
+	let delegate_pk = PublicKey::from_secret_key(&secp_ctx, &delegate_key);
+	let expires_at = now + Duration::from_secs(60);
+	let delegation = KeyDelegation::new(delegate_pk, expires_at, &nodes[0].keys_manager).unwrap();
+	assert_eq!(delegation.node_id(), node_0_pk);
+	assert!(SignedCustomMessage::sign_with_delegated_key(TestCustomMessage::Request, node_1_pk, None,
+		&SecretKey::from_slice(&[41; 32]).unwrap(), delegation.clone(), &secp_ctx).is_err());
+	let signed = SignedCustomMessage::sign_with_delegated_key(
+		TestCustomMessage::Request, node_1_pk, None, &delegate_key, delegation.clone(), &secp_ctx).unwrap();
+	let encoded = signed.encode();
+	let read_signed = read(&encoded, &verifier, now).unwrap().unwrap();
+	assert_eq!(read_signed.node_id(), node_0_pk);
+	assert_eq!(read_signed.delegation(), Some(&delegation));
+
+	// Delegations stop being accepted once they expire or are revoked.
+	assert!(read(&encoded, &verifier, expires_at).is_ok());
+	assert_eq!(read(&encoded, &verifier, expires_at + Duration::from_secs(1)).unwrap_err(),
+		DecodeError::InvalidValue);
+	verifier.revoke_delegation(node_0_pk, delegate_pk);
+	assert_eq!(read(&encoded, &verifier, now).unwrap_err(), DecodeError::InvalidValue);
+
+	// A delegation not signed by the claimed node is rejected.
+	let verifier = SignedCustomMessageVerifier::new(node_1_pk);
+	let forged_delegation = KeyDelegation::new(delegate_pk, expires_at, &nodes[1].keys_manager).unwrap();
+	let signed = SignedCustomMessage::sign_with_delegated_key(
+		TestCustomMessage::Request, node_1_pk, None, &delegate_key, forged_delegation, &secp_ctx).unwrap();
+	let mut forged = signed.encode();
+	let pos = forged.windows(33).position(|w| w == &node_1_pk.serialize()[..]).unwrap();
+	forged[pos..pos + 33].copy_from_slice(&node_0_pk.serialize());
+	assert_eq!(read(&forged, &verifier, now).unwrap_err(), DecodeError::InvalidValue);
+}
+
+#[test]
+fn redundant_send() {
+	let nodes = create_nodes(2);
+	let destination = Destination::Node(nodes[1].get_node_pk());
+	assert_eq!(nodes[0].messenger.send_onion_message_redundant(
+		destination.clone(), TestCustomMessage::Response, None, 0), Ok(0));
+
+	// Our test router only finds a single path, so only one copy is sent.
+	assert_eq!(nodes[0].messenger.send_onion_message_redundant(
+		destination, TestCustomMessage::Response, None, 3), Ok(1));
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	pass_along_path(&nodes);
+
+	// If no copy can be queued, the send fails with the error for the first.
+	let unknown_node = PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[1; 32]).unwrap());
+	assert_eq!(nodes[0].messenger.send_onion_message_redundant(
+		Destination::Node(unknown_node), TestCustomMessage::Response, None, 3), Err(SendError::InvalidFirstHop));
+}
+
+#[test]
+fn invalid_custom_message_type() {
+	let nodes = create_nodes(2);
+
+	struct InvalidCustomMessage(u64);
+	impl CustomOnionMessageContents for InvalidCustomMessage {
+		fn tlv_type(&self) -> u64 { self.0 }
+	}
+
+	impl Writeable for InvalidCustomMessage {
+		fn write<W: Writer>(&self, _w: &mut W) -> Result<(), io::Error> { unreachable!() }
+	}
+
+	// Onion message contents must have a TLV >= 64, and custom messages must not use a type
+	// reserved for messages the `OnionMessenger` handles itself.
+	for tlv_type in [63, 64, 68, 65_551, 65_555, 65_557, 65_559, 65_565, 65_567, 65_569].iter() {
+		let test_msg = OnionMessageContents::Custom(InvalidCustomMessage(*tlv_type));
+		let path = OnionMessagePath {
+			intermediate_nodes: vec![],
+			destination: Destination::Node(nodes[1].get_node_pk()),
+			first_node_addresses: None,
+		};
+		let err = nodes[0].messenger.send_onion_message(path, test_msg, None).unwrap_err();
+		assert_eq!(err, SendError::InvalidMessage);
+	}
+}
+
+#[test]
+fn peer_buffer_full() {
+	let nodes = create_nodes(2);
+	let test_msg = TestCustomMessage::Request;
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(test_msg.clone()), None).unwrap();
+	// Messages are accepted until the buffered bytes reach the configured per-peer limit.
+	let occupancy = nodes[0].messenger.peer_buffer_occupancy(&nodes[1].get_node_pk()).unwrap();
+	let max_messages = (occupancy.max_bytes + occupancy.bytes - 1) / occupancy.bytes;
+	for _ in 1..max_messages {
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(test_msg.clone()), None).unwrap();
+	}
+	let err = nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg), None).unwrap_err();
+	assert_eq!(err, SendError::BufferFull);
+}
+
+#[test]
+fn buffer_eviction_policy() {
+	// Once a peer's buffer is full, new messages are rejected or the oldest ones we forwarded
+	// evicted, depending on our configured policy. Messages we sent ourselves are never evicted.
+	let nodes = create_nodes(3);
+	let node_2_pk = nodes[2].get_node_pk();
+	let forwarded_path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+	let forward = |msg: TestCustomMessage| {
+		nodes[0].messenger.send_onion_message(forwarded_path.clone(), OnionMessageContents::Custom(msg), None).unwrap();
+		let onion_msg = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap().pop_front().unwrap();
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	};
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+	let send = || nodes[1].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None);
+
+	forward(TestCustomMessage::Request);
+	let message_len = nodes[1].messenger.peer_buffer_occupancy(&node_2_pk).unwrap().bytes;
+	let config = OnionMessengerConfig { max_peer_buffer_bytes: message_len + 1, ..Default::default() };
+	nodes[1].messenger.set_config(config);
+	forward(TestCustomMessage::Response);
+	forward(TestCustomMessage::Response);
+	assert_eq!(nodes[1].messenger.forwarding_stats().dropped_buffer_full, 1);
+
+	// Evictions are reported in a single event per peer until it's processed.
+	nodes[1].messenger.set_config(OnionMessengerConfig { eviction_policy: OnionMessageEvictionPolicy::DropOldest, ..config });
+	forward(TestCustomMessage::Response);
+	forward(TestCustomMessage::Response);
+	let occupancy = nodes[1].messenger.peer_buffer_occupancy(&node_2_pk).unwrap();
+	assert_eq!(occupancy.messages, 2);
+	assert_eq!(occupancy.max_bytes, message_len + 1);
+	let events = Mutex::new(Vec::new());
+	nodes[1].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessagesEvicted { peer_node_id: node_2_pk, count: 2 }]);
+	assert_eq!(nodes[1].messenger.stats().dropped, 3);
+
+	// Our own messages evict those we forwarded, but aren't evicted themselves.
+	send().unwrap();
+	send().unwrap();
+	assert_eq!(send(), Err(SendError::BufferFull));
+	forward(TestCustomMessage::Response);
+	assert_eq!(nodes[1].messenger.forwarding_stats().dropped_buffer_full, 2);
+	let events = Mutex::new(Vec::new());
+	nodes[1].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessagesEvicted { peer_node_id: node_2_pk, count: 2 }]);
+
+	for onion_msg in nodes[1].messenger.release_pending_msgs().remove(&node_2_pk).unwrap() {
+		nodes[2].custom_message_handler.expect_message(TestCustomMessage::Response);
+		nodes[2].messenger.handle_onion_message(&nodes[1].get_node_pk(), &onion_msg);
+	}
+}
+
+#[test]
+fn buffer_backpressure_events() {
+	// Once a peer's buffer crosses the high-water mark we generate an event, and another once it
+	// drains to the low-water mark or the peer disconnects.
+	let nodes = create_nodes(2);
+	let node_1_pk = nodes[1].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	};
+	let send = || nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let take_events = || {
+		let events = Mutex::new(Vec::new());
+		nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+		events.into_inner().unwrap()
+	};
+
+	send();
+	let message_len = nodes[0].messenger.peer_buffer_occupancy(&node_1_pk).unwrap().bytes;
+	nodes[0].messenger.set_config(OnionMessengerConfig {
+		buffer_high_water_mark_bytes: Some(2 * message_len),
+		buffer_low_water_mark_bytes: message_len,
+		..Default::default()
+	});
+	assert!(take_events().is_empty());
+
+	send();
+	let buffered_bytes = 2 * message_len as u64;
+	assert_eq!(take_events(), vec![Event::OnionMessageBufferHighWater { peer_node_id: node_1_pk, buffered_bytes }]);
+	send();
+	assert!(take_events().is_empty());
+
+	assert!(nodes[0].messenger.next_onion_message_for_peer(node_1_pk).is_some());
+	assert!(take_events().is_empty());
+	assert!(nodes[0].messenger.next_onion_message_for_peer(node_1_pk).is_some());
+	let buffered_bytes = message_len as u64;
+	assert_eq!(take_events(), vec![Event::OnionMessageBufferDrained { peer_node_id: node_1_pk, buffered_bytes }]);
+
+	send();
+	assert_eq!(take_events().len(), 1);
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	assert_eq!(take_events(), vec![Event::OnionMessageBufferDrained { peer_node_id: node_1_pk, buffered_bytes: 0 }]);
+}
+
+#[test]
+fn custom_message_events_bounded() {
+	// Check that only a bounded number of `Event::CustomOnionMessageReceived`s are queued until
+	// they're processed, with further messages dropped.
+	let nodes = create_nodes(2);
+	nodes[1].messenger.set_custom_message_delivery(CustomOnionMessageDelivery::Events);
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	let max_events = super::messenger::MAX_PENDING_CUSTOM_MESSAGE_EVENTS;
+	for _ in 0..max_events + 1 {
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+		pass_along_path(&nodes);
+	}
+	assert_eq!(nodes[1].messenger.stats().dropped, 1);
+
+	let event_count = Mutex::new(0);
+	nodes[1].messenger.process_pending_events(&|_| *event_count.lock().unwrap() += 1);
+	assert_eq!(*event_count.lock().unwrap(), max_events);
+
+	// Once processed, messages are queued as events again.
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	pass_along_path(&nodes);
+	nodes[1].messenger.process_pending_events(&|_| *event_count.lock().unwrap() += 1);
+	assert_eq!(*event_count.lock().unwrap(), max_events + 1);
+	assert_eq!(nodes[1].messenger.stats().dropped, 1);
+}
+
+#[test]
+fn failed_sends_retried_with_backoff() {
+	// With retries enabled, messages which fail to send due to a full buffer or a disconnected
+	// first hop are retried on timer ticks, backing off exponentially until the retry limit.
+	let nodes = create_nodes(2);
+	let node_1_pk = nodes[1].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	};
+	let send = |msg: TestCustomMessage| nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(msg), None);
+
+	send(TestCustomMessage::Request).unwrap();
+	let message_len = nodes[0].messenger.peer_buffer_occupancy(&node_1_pk).unwrap().bytes;
+	nodes[0].messenger.set_config(OnionMessengerConfig {
+		max_peer_buffer_bytes: message_len,
+		max_send_retries: 2,
+		retry_backoff_ticks: 1,
+		..Default::default()
+	});
+	send(TestCustomMessage::Response).unwrap();
+	assert_eq!(nodes[0].messenger.stats().pending_retries, 1);
+
+	// The first retry fails as the buffer is still full, so the next is two ticks later.
+	nodes[0].messenger.timer_tick_occurred();
+	assert_eq!(nodes[0].messenger.stats().pending_retries, 1);
+	assert_eq!(nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap().len(), 1);
+	nodes[0].messenger.timer_tick_occurred();
+	assert!(nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap().is_empty());
+	nodes[0].messenger.timer_tick_occurred();
+	assert_eq!(nodes[0].messenger.stats().pending_retries, 0);
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap();
+	assert_eq!(onion_msgs.len(), 1);
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msgs[0]);
+
+	// Messages queued for a peer which disconnects are retried once it reconnects.
+	send(TestCustomMessage::Response).unwrap();
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	assert_eq!(nodes[0].messenger.stats().pending_retries, 1);
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[0].messenger.peer_connected(&node_1_pk, &init_msg, true).unwrap();
+	nodes[0].messenger.timer_tick_occurred();
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap();
+	assert_eq!(onion_msgs.len(), 1);
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msgs[0]);
+
+	// Once its retries are exhausted, a message is dropped.
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	send(TestCustomMessage::Response).unwrap();
+	for _ in 0..3 {
+		nodes[0].messenger.timer_tick_occurred();
+	}
+	assert_eq!(nodes[0].messenger.stats().pending_retries, 0);
+	assert_eq!(nodes[0].messenger.stats().dropped, 1);
+
+	// Messages we were forwarding aren't retried once their next hop disconnects.
+	let nodes = create_nodes(3);
+	let (node_1_pk, node_2_pk) = (nodes[1].get_node_pk(), nodes[2].get_node_pk());
+	nodes[1].messenger.set_config(OnionMessengerConfig {
+		max_send_retries: 2,
+		retry_backoff_ticks: 1,
+		..Default::default()
+	});
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![node_1_pk],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let onion_msg = nodes[0].messenger.next_onion_message_for_peer(node_1_pk).unwrap();
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	assert_eq!(nodes[1].messenger.stats().forwarded, 1);
+	nodes[1].messenger.peer_disconnected(&node_2_pk);
+	assert_eq!(nodes[1].messenger.stats().pending_retries, 0);
+}
+
+#[test]
+fn prioritized_messages() {
+	// Higher priority messages are released ahead of lower priority ones queued before them.
+	let nodes = create_nodes(2);
+	let node_1_pk = nodes[1].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	};
+	let send = |msg: TestCustomMessage, priority: OnionMessagePriority| nodes[0].messenger
+		.send_onion_message_with_priority(path.clone(), OnionMessageContents::Custom(msg), None, priority);
+
+	send(TestCustomMessage::Request, OnionMessagePriority::Low).unwrap();
+	send(TestCustomMessage::Request, OnionMessagePriority::Normal).unwrap();
+	send(TestCustomMessage::Response, OnionMessagePriority::High).unwrap();
+
+	// The response is released ahead of the normal priority request, followed by the low priority
+	// one.
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap();
+	assert_eq!(onion_msgs.len(), 3);
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msgs[0]);
+	for onion_msg in onion_msgs.iter().skip(1) {
+		nodes[1].custom_message_handler.expect_message(TestCustomMessage::Request);
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), onion_msg);
+	}
+}
+
+struct TestRateLimitObserver {
+	violations: Arc<Mutex<Vec<(PublicKey, RateLimitDirection, u64)>>>,
+}
+
+impl OnionMessageRateLimitObserver for TestRateLimitObserver {
+	fn peer_rate_limited(&self, peer_node_id: &PublicKey, direction: RateLimitDirection, violations: u64) {
+		self.violations.lock().unwrap().push((*peer_node_id, direction, violations));
+	}
+}
+
+#[test]
+fn outbound_rate_limited() {
+	let nodes = create_nodes(2);
+	let violations = Arc::new(Mutex::new(Vec::new()));
+	nodes[0].messenger.set_rate_limits(OnionMessageRateLimits {
+		inbound: None,
+		outbound: Some(OnionMessageRateLimit { messages_per_second: 1, burst: 2 }),
+	});
+	nodes[0].messenger.set_rate_limit_observer(TestRateLimitObserver { violations: Arc::clone(&violations) });
+	let time_source = test_utils::TestTimeSource::new();
+	nodes[0].messenger.set_time_source(time_source.clone());
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	for _ in 0..2 {
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	}
+	for _ in 0..2 {
+		let err = nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap_err();
+		assert_eq!(err, SendError::RateLimited);
+	}
+	// The observer is only notified, once per peer and direction, when events are processed.
+	assert!(violations.lock().unwrap().is_empty());
+	nodes[0].messenger.process_pending_events(&|_| {});
+	assert_eq!(*violations.lock().unwrap(), vec![(nodes[1].get_node_pk(), RateLimitDirection::Outbound, 2)]);
+	nodes[0].messenger.process_pending_events(&|_| {});
+	assert_eq!(violations.lock().unwrap().len(), 1);
+
+	// Reconnecting the peer does not reset its limit, even across a timer tick.
+	nodes[0].messenger.peer_disconnected(&nodes[1].get_node_pk());
+	nodes[0].messenger.timer_tick_occurred();
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[0].messenger.peer_connected(&nodes[1].get_node_pk(), &init_msg, true).unwrap();
+	let err = nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap_err();
+	assert_eq!(err, SendError::RateLimited);
+	nodes[0].messenger.process_pending_events(&|_| {});
+	assert_eq!(violations.lock().unwrap()[1], (nodes[1].get_node_pk(), RateLimitDirection::Outbound, 3));
+
+	// Once a second has passed, one more message may be sent.
+	time_source.advance(Duration::from_secs(1));
+	nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let err = nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap_err();
+	assert_eq!(err, SendError::RateLimited);
+}
+
+#[test]
+fn inbound_rate_limited() {
+	let nodes = create_nodes(2);
+	let violations = Arc::new(Mutex::new(Vec::new()));
+	nodes[1].messenger.set_rate_limits(OnionMessageRateLimits {
+		inbound: Some(OnionMessageRateLimit { messages_per_second: 1, burst: 1 }),
+		outbound: None,
+	});
+	nodes[1].messenger.set_rate_limit_observer(TestRateLimitObserver { violations: Arc::clone(&violations) });
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	for _ in 0..2 {
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	}
+
+	// Only the first message is handled, the second is dropped.
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	let msgs = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap();
+	assert_eq!(msgs.len(), 2);
+	for msg in msgs.iter() {
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), msg);
+	}
+	nodes[1].messenger.process_pending_events(&|_| {});
+	assert_eq!(*violations.lock().unwrap(), vec![(nodes[0].get_node_pk(), RateLimitDirection::Inbound, 1)]);
+}
+
+struct TestStore {
+	entries: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl KVStorePersister for TestStore {
+	fn persist<W: Writeable>(&self, key: &str, object: &W) -> Result<(), io::Error> {
+		self.entries.lock().unwrap().insert(key.to_owned(), object.encode());
+		Ok(())
+	}
+}
+
+#[test]
+fn pending_messages_survive_restart() {
+	let nodes = create_nodes(2);
+	let node_1_pk = nodes[1].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+
+	let store = TestStore { entries: Mutex::new(HashMap::new()) };
+	nodes[0].messenger.persist_pending_messages(&store).unwrap();
+	let encoded = store.entries.lock().unwrap().get(PENDING_ONION_MESSAGES_PERSISTENCE_KEY).unwrap().clone();
+	let pending: PendingOnionMessages = Readable::read(&mut &encoded[..]).unwrap();
+	assert_eq!(pending.len(), 1);
+
+	// Disconnecting drops the queued message, as would a restart.
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	nodes[0].messenger.restore_pending_messages(pending);
+	assert!(nodes[0].messenger.release_pending_msgs().is_empty());
+
+	// Once the peer reconnects, the restored message is released to it.
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[0].messenger.peer_connected(&node_1_pk, &init_msg, true).unwrap();
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	pass_along_path(&nodes);
+}
+
+#[test]
+fn restored_messages_keep_priority() {
+	let nodes = create_nodes(2);
+	let node_1_pk = nodes[1].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message_with_priority(path.clone(),
+		OnionMessageContents::Custom(TestCustomMessage::Request), None, OnionMessagePriority::Low).unwrap();
+	nodes[0].messenger.send_onion_message_with_priority(path,
+		OnionMessageContents::Custom(TestCustomMessage::Response), None, OnionMessagePriority::High).unwrap();
+
+	let store = TestStore { entries: Mutex::new(HashMap::new()) };
+	nodes[0].messenger.persist_pending_messages(&store).unwrap();
+	let encoded = store.entries.lock().unwrap().get(PENDING_ONION_MESSAGES_PERSISTENCE_KEY).unwrap().clone();
+	let pending: PendingOnionMessages = Readable::read(&mut &encoded[..]).unwrap();
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	nodes[0].messenger.restore_pending_messages(pending);
+
+	// Once restored, the high priority response is still released ahead of the low priority
+	// request, even though a normal priority message is queued in between.
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[0].messenger.peer_connected(&node_1_pk, &init_msg, true).unwrap();
+	nodes[0].messenger.send_onion_message(OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	}, OnionMessageContents::Custom(TestCustomMessage::Request), None).unwrap();
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap();
+	assert_eq!(onion_msgs.len(), 3);
+	nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msgs[0]);
+	for onion_msg in onion_msgs.iter().skip(1) {
+		nodes[1].custom_message_handler.expect_message(TestCustomMessage::Request);
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), onion_msg);
+	}
+}
+
+#[test]
+fn restored_messages_expire() {
+	let nodes = create_nodes(2);
+	let node_1_pk = nodes[1].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let store = TestStore { entries: Mutex::new(HashMap::new()) };
+	nodes[0].messenger.persist_pending_messages(&store).unwrap();
+	let encoded = store.entries.lock().unwrap().get(PENDING_ONION_MESSAGES_PERSISTENCE_KEY).unwrap().clone();
+	let pending: PendingOnionMessages = Readable::read(&mut &encoded[..]).unwrap();
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	nodes[0].messenger.restore_pending_messages(pending);
+
+	// If the peer doesn't connect in time, the restored message is dropped rather than being
+	// queued forever.
+	for _ in 0..=super::messenger::RESTORED_MESSAGES_TIMEOUT_TICKS {
+		nodes[0].messenger.timer_tick_occurred();
+	}
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[0].messenger.peer_connected(&node_1_pk, &init_msg, true).unwrap();
+	assert!(nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap().is_empty());
+}
+
+struct TestChannelPeers(Vec<PublicKey>);
+
+impl ChannelPeerLookup for TestChannelPeers {
+	fn has_channel_with(&self, peer_node_id: &PublicKey) -> bool {
+		self.0.contains(peer_node_id)
+	}
+}
+
+#[test]
+fn mailbox_delivers_on_reconnect() {
+	// With the mailbox enabled, messages forwarded to a disconnected peer are stored and delivered
+	// once it reconnects.
+	let nodes = create_nodes(3);
+	let node_2_pk = nodes[2].get_node_pk();
+	nodes[1].messenger.peer_disconnected(&node_2_pk);
+	nodes[1].messenger.set_mailbox_config(Some(OnionMessageMailboxConfig::default()));
+	nodes[1].messenger.set_channel_peer_lookup(TestChannelPeers(vec![node_2_pk]));
+
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let onion_msg = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap().pop_front().unwrap();
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	assert!(nodes[1].messenger.release_pending_msgs().get(&node_2_pk).is_none());
+
+	let events = Mutex::new(Vec::new());
+	nodes[1].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageStored { peer_node_id: node_2_pk }]);
+
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[1].messenger.peer_connected(&node_2_pk, &init_msg, true).unwrap();
+	let onion_msgs = nodes[1].messenger.release_pending_msgs().remove(&node_2_pk).unwrap();
+	assert_eq!(onion_msgs.len(), 1);
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[2].messenger.handle_onion_message(&nodes[1].get_node_pk(), &onion_msgs[0]);
+}
+
+#[test]
+fn mailbox_limits_and_expiry() {
+	// Stored messages are bounded per peer and expire after the configured number of ticks.
+	let nodes = create_nodes(3);
+	let node_2_pk = nodes[2].get_node_pk();
+	nodes[1].messenger.peer_disconnected(&node_2_pk);
+	nodes[1].messenger.set_mailbox_config(Some(OnionMessageMailboxConfig {
+		max_messages_per_peer: 1,
+		expiry_ticks: 2,
+		..Default::default()
+	}));
+	nodes[1].messenger.set_channel_peer_lookup(TestChannelPeers(vec![node_2_pk]));
+
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+	for _ in 0..2 {
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	}
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap();
+	assert_eq!(onion_msgs.len(), 2);
+	for onion_msg in onion_msgs.iter() {
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), onion_msg);
+	}
+
+	let events = Mutex::new(Vec::new());
+	nodes[1].messenger.timer_tick_occurred();
+	nodes[1].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessageStored { peer_node_id: node_2_pk }]);
+
+	events.lock().unwrap().clear();
+	nodes[1].messenger.timer_tick_occurred();
+	nodes[1].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::OnionMessagesExpired { peer_node_id: node_2_pk, count: 1 }]);
+
+	// Nothing is delivered once the peer reconnects.
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[1].messenger.peer_connected(&node_2_pk, &init_msg, true).unwrap();
+	assert!(nodes[1].messenger.release_pending_msgs().get(&node_2_pk).unwrap().is_empty());
+}
+
+#[test]
+fn connection_needed() {
+	// Messages for a node we're not connected to are queued if we know how to connect to it, and
+	// sent once it connects or dropped if it doesn't in time.
+	let nodes = create_nodes(2);
+	let node_1_pk = nodes[1].get_node_pk();
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	let addresses = vec![msgs::NetAddress::IPv4 { addr: [127, 0, 0, 1], port: 9735 }];
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(node_1_pk),
+		first_node_addresses: Some(addresses.clone()),
+	};
+	let send = || nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None);
+
+	let err = nodes[0].messenger.send_onion_message(
+		OnionMessagePath { first_node_addresses: None, ..path.clone() },
+		OnionMessageContents::Custom(TestCustomMessage::Response), None
+	).unwrap_err();
+	assert_eq!(err, SendError::InvalidFirstHop);
+
+	// Only a single event is generated while we wait for the connection.
+	send().unwrap();
+	send().unwrap();
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![Event::ConnectionNeeded { node_id: node_1_pk, addresses: addresses.clone() }]);
+
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	nodes[0].messenger.peer_connected(&node_1_pk, &init_msg, false).unwrap();
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap();
+	assert_eq!(onion_msgs.len(), 2);
+	for onion_msg in onion_msgs.iter() {
+		nodes[1].custom_message_handler.expect_message(TestCustomMessage::Response);
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), onion_msg);
+	}
+
+	// Once timed out, queued messages are dropped.
+	nodes[0].messenger.peer_disconnected(&node_1_pk);
+	send().unwrap();
+	for _ in 0..=super::messenger::CONNECTION_NEEDED_TIMEOUT_TICKS {
+		nodes[0].messenger.timer_tick_occurred();
+	}
+	let events = Mutex::new(Vec::new());
+	nodes[0].messenger.process_pending_events(&|event| events.lock().unwrap().push(event));
+	assert_eq!(*events.lock().unwrap(), vec![
+		Event::ConnectionNeeded { node_id: node_1_pk, addresses },
+		Event::ConnectionNeededTimedOut { node_id: node_1_pk, count: 1 },
+	]);
+	nodes[0].messenger.peer_connected(&node_1_pk, &init_msg, false).unwrap();
+	assert!(nodes[0].messenger.release_pending_msgs().get(&node_1_pk).unwrap().is_empty());
+	assert_eq!(nodes[0].messenger.stats().dropped, 1);
+}
+
+#[test]
+fn forwarding_policy() {
+	// Forwards are only queued if allowed by the forwarding node's policy, and counted either way.
+	let nodes = create_nodes(3);
+	let node_2_pk = nodes[2].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+	let forwarded_with_policy = |policy: OnionMessageForwardingPolicy| {
+		nodes[1].messenger.set_forwarding_policy(policy);
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+		let onion_msg = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap().pop_front().unwrap();
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+		nodes[1].messenger.release_pending_msgs().get(&node_2_pk).map_or(false, |msgs| !msgs.is_empty())
+	};
+
+	assert!(forwarded_with_policy(OnionMessageForwardingPolicy::All));
+	assert!(!forwarded_with_policy(OnionMessageForwardingPolicy::Disabled));
+
+	let mut allowlist = HashSet::new();
+	allowlist.insert(nodes[0].get_node_pk());
+	assert!(!forwarded_with_policy(OnionMessageForwardingPolicy::Allowlist(allowlist.clone())));
+	allowlist.insert(node_2_pk);
+	assert!(forwarded_with_policy(OnionMessageForwardingPolicy::Allowlist(allowlist)));
+
+	// Nothing is forwarded to channel peers until we know who they are.
+	assert!(!forwarded_with_policy(OnionMessageForwardingPolicy::ChannelPeers));
+	nodes[1].messenger.set_channel_peer_lookup(TestChannelPeers(vec![nodes[0].get_node_pk()]));
+	assert!(!forwarded_with_policy(OnionMessageForwardingPolicy::ChannelPeers));
+	nodes[1].messenger.set_channel_peer_lookup(TestChannelPeers(vec![node_2_pk]));
+	assert!(forwarded_with_policy(OnionMessageForwardingPolicy::ChannelPeers));
+
+	assert_eq!(nodes[1].messenger.forwarding_stats(), OnionMessageForwardingStats {
+		forwarded: 3,
+		dropped_by_policy: 4,
+		..Default::default()
+	});
+}
+
+#[test]
+fn mailbox_only_stores_for_channel_peers_within_sender_limit() {
+	// Messages are only stored for peers we have a channel with, and a single sender can only
+	// take up a limited share of the mailbox.
+	let nodes = create_nodes(3);
+	let node_2_pk = nodes[2].get_node_pk();
+	nodes[1].messenger.peer_disconnected(&node_2_pk);
+
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![nodes[1].get_node_pk()],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+	for _ in 0..2 {
+		nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	}
+	let onion_msgs = nodes[0].messenger.release_pending_msgs().remove(&nodes[1].get_node_pk()).unwrap();
+	assert_eq!(onion_msgs.len(), 2);
+
+	nodes[1].messenger.set_mailbox_config(Some(OnionMessageMailboxConfig {
+		max_bytes_per_sender: onion_msgs[0].serialized_length(),
+		..Default::default()
+	}));
+	nodes[1].messenger.set_channel_peer_lookup(TestChannelPeers(vec![]));
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msgs[0]);
+	assert_eq!(nodes[1].messenger.stats().intercepted_messages, 0);
+
+	nodes[1].messenger.set_channel_peer_lookup(TestChannelPeers(vec![node_2_pk]));
+	for onion_msg in onion_msgs.iter() {
+		nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), onion_msg);
+	}
+	assert_eq!(nodes[1].messenger.stats().intercepted_messages, 1);
+	assert_eq!(nodes[1].messenger.forwarding_stats().stored, 1);
+	assert_eq!(nodes[1].messenger.forwarding_stats().dropped_peer_disconnected, 2);
+}
+
+#[test]
+fn messenger_stats() {
+	// Messages sent, received, forwarded and dropped are counted, and buffered messages reported.
+	let nodes = create_nodes(3);
+	let node_1_pk = nodes[1].get_node_pk();
+	let node_2_pk = nodes[2].get_node_pk();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![node_1_pk],
+		destination: Destination::Node(node_2_pk),
+		first_node_addresses: None,
+	};
+
+	nodes[0].messenger.send_onion_message(path.clone(), OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let occupancy = nodes[0].messenger.peer_buffer_occupancy(&node_1_pk).unwrap();
+	assert_eq!(occupancy.messages, 1);
+	assert!(occupancy.bytes > 0 && occupancy.bytes < occupancy.max_bytes);
+	assert_eq!(nodes[0].messenger.list_peer_buffer_occupancy(), vec![(node_1_pk, occupancy)]);
+	assert_eq!(nodes[0].messenger.peer_buffer_occupancy(&node_2_pk), None);
+	assert_eq!(nodes[0].messenger.stats(), OnionMessengerStats { sent: 1, ..Default::default() });
+
+	let onion_msg = nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap().pop_front().unwrap();
+	assert_eq!(nodes[0].messenger.peer_buffer_occupancy(&node_1_pk).unwrap().messages, 0);
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	assert_eq!(nodes[1].messenger.stats(), OnionMessengerStats { forwarded: 1, ..Default::default() });
+
+	let onion_msg = nodes[1].messenger.release_pending_msgs().remove(&node_2_pk).unwrap().pop_front().unwrap();
+	nodes[2].custom_message_handler.expect_message(TestCustomMessage::Response);
+	nodes[2].messenger.handle_onion_message(&node_1_pk, &onion_msg);
+	assert_eq!(nodes[2].messenger.stats(), OnionMessengerStats { received: 1, ..Default::default() });
+
+	// Messages for a disconnected peer are intercepted by the mailbox until they expire.
+	nodes[1].messenger.peer_disconnected(&node_2_pk);
+	nodes[1].messenger.set_mailbox_config(Some(OnionMessageMailboxConfig { expiry_ticks: 1, ..Default::default() }));
+	nodes[1].messenger.set_channel_peer_lookup(TestChannelPeers(vec![node_2_pk]));
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Response), None).unwrap();
+	let onion_msg = nodes[0].messenger.release_pending_msgs().remove(&node_1_pk).unwrap().pop_front().unwrap();
+	nodes[1].messenger.handle_onion_message(&nodes[0].get_node_pk(), &onion_msg);
+	assert_eq!(nodes[1].messenger.peer_buffer_occupancy(&node_2_pk), None);
+	let stats = nodes[1].messenger.stats();
+	assert_eq!(stats.intercepted_messages, 1);
+	assert_eq!(stats.intercepted_bytes, onion_msg.serialized_length());
+
+	nodes[1].messenger.timer_tick_occurred();
+	assert_eq!(nodes[1].messenger.stats(), OnionMessengerStats { forwarded: 1, dropped: 1, ..Default::default() });
+}
+
+#[test]
+fn default_message_router_finds_paths() {
+	// Build the graph:
+	// peer -(1)- node1 -(2)- node2 -(3)- node3 -(6)- node5
+	//    \                             /
+	//     (4)------- node4 -------(5)
+	// where all nodes but node5 support onion messages.
+	let secp_ctx = Secp256k1::new();
+	let logger = Arc::new(test_utils::TestLogger::new());
+	let network_graph = Arc::new(NetworkGraph::new(Network::Testnet, Arc::clone(&logger)));
+	let gossip_sync = P2PGossipSync::new(Arc::clone(&network_graph), None, Arc::clone(&logger));
+	let (_, our_id, privkeys, pubkeys) = get_nodes(&secp_ctx);
+	for (scid, (a, b)) in [(0, 1), (1, 2), (2, 3), (0, 4), (4, 3), (3, 5)].iter().enumerate() {
+		add_channel(&gossip_sync, &secp_ctx, &privkeys[*a], &privkeys[*b], ChannelFeatures::empty(), scid as u64 + 1);
+	}
+	let mut features = NodeFeatures::empty();
+	features.set_onion_messages_optional();
+	for privkey in privkeys[..5].iter() {
+		add_or_update_node(&gossip_sync, &secp_ctx, privkey, features.clone(), 1);
+	}
+	add_or_update_node(&gossip_sync, &secp_ctx, &privkeys[5], NodeFeatures::empty(), 1);
+
+	let router = DefaultMessageRouter::new(Arc::clone(&network_graph));
+	let peers = vec![pubkeys[0]];
+	let path = router.find_path(our_id, peers.clone(), Destination::Node(pubkeys[3])).unwrap();
+	assert_eq!(path.intermediate_nodes, vec![pubkeys[0], pubkeys[4]]);
+	let path = router.find_path(our_id, peers.clone(), Destination::Node(pubkeys[0])).unwrap();
+	assert!(path.intermediate_nodes.is_empty());
+	assert!(router.find_path(our_id, peers.clone(), Destination::Node(pubkeys[5])).is_err());
+
+	// Disjoint paths share no intermediate nodes, so with a single peer only one is found, while
+	// with both node0 and node4 as peers we find one via each.
+	let paths = router.find_disjoint_paths(our_id, peers.clone(), Destination::Node(pubkeys[3]), 2).unwrap();
+	assert_eq!(paths.len(), 1);
+	let both_peers = vec![pubkeys[0], pubkeys[4]];
+	let paths = router.find_disjoint_paths(our_id, both_peers.clone(), Destination::Node(pubkeys[3]), 3).unwrap();
+	assert_eq!(paths.iter().map(|path| path.intermediate_nodes.clone()).collect::<Vec<_>>(),
+		vec![vec![pubkeys[4]], vec![pubkeys[0], pubkeys[1], pubkeys[2]]]);
+	// Only one path may reach a peer directly.
+	let paths = router.find_disjoint_paths(our_id, both_peers, Destination::Node(pubkeys[0]), 3).unwrap();
+	assert_eq!(paths.iter().map(|path| path.intermediate_nodes.clone()).collect::<Vec<_>>(),
+		vec![vec![], vec![pubkeys[4]]]);
+
+	// Avoiding node4 forces us onto the longer path, which is too long with fewer max hops.
+	let mut params = DefaultMessageRouterParams::default();
+	params.avoided_nodes.insert(pubkeys[4]);
+	let router = DefaultMessageRouter::with_params(Arc::clone(&network_graph), params.clone());
+	let path = router.find_path(our_id, peers.clone(), Destination::Node(pubkeys[3])).unwrap();
+	assert_eq!(path.intermediate_nodes, vec![pubkeys[0], pubkeys[1], pubkeys[2]]);
+	params.max_hops = 3;
+	let router = DefaultMessageRouter::with_params(Arc::clone(&network_graph), params);
+	assert!(router.find_path(our_id, peers, Destination::Node(pubkeys[3])).is_err());
+}
+
+#[test]
+fn type_erased_messenger() {
+	// An `AnyOnionMessenger` may be built from any implementations of its dependencies.
+	let keys_manager = Arc::new(test_utils::TestKeysInterface::new(&[42; 32], Network::Testnet));
+	let messenger = AnyOnionMessenger::<TestCustomMessageHandler>::new(
+		keys_manager.clone(), keys_manager, Arc::new(test_utils::TestLogger::new()),
+		Arc::new(TestMessageRouter::new()), Arc::new(TestOffersMessageHandler {}),
+		Arc::new(TestDlcMessageHandler::new()), Arc::new(TestCustomMessageHandler::new())
+	);
+
+	let secp_ctx = Secp256k1::new();
+	let peer = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[43; 32]).unwrap());
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	messenger.peer_connected(&peer, &init_msg, true).unwrap();
+
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![], destination: Destination::Node(peer), first_node_addresses: None,
+	};
+	messenger.send_onion_message(path, OnionMessageContents::Custom(TestCustomMessage::Request), None).unwrap();
+	assert_eq!(messenger.release_pending_msgs().get(&peer).unwrap().len(), 1);
+}
+
+#[test]
+fn messenger_with_default_router() {
+	let secp_ctx = Secp256k1::new();
+	let logger = Arc::new(test_utils::TestLogger::new());
+	let network_graph = Arc::new(NetworkGraph::new(Network::Testnet, Arc::clone(&logger)));
+	let gossip_sync = P2PGossipSync::new(Arc::clone(&network_graph), None, Arc::clone(&logger));
+	let keys_manager = Arc::new(test_utils::TestKeysInterface::new(&[42; 32], Network::Testnet));
+	let messenger = OnionMessenger::with_default_router(
+		Arc::clone(&keys_manager), keys_manager, logger, Arc::clone(&network_graph),
+		Arc::new(TestOffersMessageHandler {}), Arc::new(TestDlcMessageHandler::new()),
+		Arc::new(TestCustomMessageHandler::new())
+	);
+
+	let (_, _, privkeys, pubkeys) = get_nodes(&secp_ctx);
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	messenger.peer_connected(&pubkeys[0], &init_msg, true).unwrap();
+
+	// Our peer isn't in the graph yet, so the default router won't pick it for a reply path.
+	assert_eq!(messenger.create_reply_path(), Err(SendError::ReplyPathNotFound));
+
+	let mut features = NodeFeatures::empty();
+	features.set_onion_messages_optional();
+	add_or_update_node(&gossip_sync, &secp_ctx, &privkeys[0], features, 1);
+	assert_eq!(messenger.create_reply_path().unwrap().introduction_node_id, pubkeys[0]);
+}
+
+#[test]
+fn many_hops() {
+	// Check we can send over a route with many hops. This will exercise our logic for onion messages
+	// of size [`crate::onion_message::packet::BIG_PACKET_HOP_DATA_LEN`].
+	let num_nodes: usize = 25;
+	let nodes = create_nodes(num_nodes as u8);
+	let test_msg = TestCustomMessage::Response;
+
+	let mut intermediate_nodes = vec![];
+	for i in 1..(num_nodes-1) {
+		intermediate_nodes.push(nodes[i].get_node_pk());
+	}
+
+	let path = OnionMessagePath {
+		intermediate_nodes,
+		destination: Destination::Node(nodes[num_nodes-1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(path, OnionMessageContents::Custom(test_msg), None).unwrap();
+	nodes[num_nodes-1].custom_message_handler.expect_message(TestCustomMessage::Response);
+	pass_along_path(&nodes);
+}
+
+#[derive(Clone, Default)]
+struct IncrementService {
+	responses: Arc<Mutex<Vec<(OnionMessageRequestId, u64)>>>,
+	failures: Arc<Mutex<Vec<(OnionMessageRequestId, RpcRequestFailure)>>>,
+}
+
+impl OnionMessageService for IncrementService {
+	const TLV_TYPES: Range<u64> = 5_000..5_010;
+	type Request = u64;
+	type Response = u64;
+
+	fn handle_request(&self, request: u64, _received_via: OnionMessageReceivedVia) -> Option<u64> {
+		Some(request + 1)
+	}
+
+	fn handle_response(&self, response: u64, request_id: OnionMessageRequestId) {
+		self.responses.lock().unwrap().push((request_id, response));
+	}
+
+	fn handle_request_failed(&self, request_id: OnionMessageRequestId, failure: RpcRequestFailure) {
+		self.failures.lock().unwrap().push((request_id, failure));
+	}
+}
+
+/// Speaks the protocol of an [`IncrementService`], but responds with a `u8` rather than a `u64`.
+struct TruncatingService {}
+
+impl OnionMessageService for TruncatingService {
+	const TLV_TYPES: Range<u64> = 5_000..5_010;
+	type Request = u64;
+	type Response = u8;
+
+	fn handle_request(&self, request: u64, _received_via: OnionMessageReceivedVia) -> Option<u8> {
+		Some(request as u8)
+	}
+
+	fn handle_response(&self, _response: u8, _request_id: OnionMessageRequestId) {}
+}
+
+struct OverlappingService {}
+
+impl OnionMessageService for OverlappingService {
+	const TLV_TYPES: Range<u64> = 5_008..5_020;
+	type Request = u64;
+	type Response = u64;
+
+	fn handle_request(&self, _request: u64, _received_via: OnionMessageReceivedVia) -> Option<u64> {
+		None
+	}
+
+	fn handle_response(&self, _response: u64, _request_id: OnionMessageRequestId) {}
+}
+
+#[test]
+fn rpc_requests_and_responses() {
+	// Node 0 sends requests to node 1, which runs an `IncrementService`, and node 2, which responds
+	// with responses node 0 fails to decode.
+	let mut messengers = Vec::new();
+	let mut rpcs = Vec::new();
+	let service = IncrementService::default();
+	for i in 0..3 {
+		let keys_manager = Arc::new(test_utils::TestKeysInterface::new(&[i as u8; 32], Network::Testnet));
+		let mut rpc = OnionMessageRpc::with_request_timeout(Arc::clone(&keys_manager), 1);
+		if i == 2 {
+			rpc.register_service(TruncatingService {}).unwrap();
+		} else {
+			rpc.register_service(service.clone()).unwrap();
+		}
+		assert!(rpc.register_service(OverlappingService {}).is_err());
+		let rpc = Arc::new(rpc);
+		messengers.push(OnionMessenger::new(
+			Arc::clone(&keys_manager), keys_manager, Arc::new(test_utils::TestLogger::new()),
+			Arc::new(TestMessageRouter::new()), Arc::new(TestOffersMessageHandler {}),
+			Arc::new(TestDlcMessageHandler::new()), Arc::clone(&rpc)
+		));
+		rpcs.push(rpc);
+	}
+	let node_ids: Vec<PublicKey> = (0..3).map(|i|
+		test_utils::TestKeysInterface::new(&[i as u8; 32], Network::Testnet).get_node_id(Recipient::Node).unwrap()
+	).collect();
+	let mut features = InitFeatures::empty();
+	features.set_onion_messages_optional();
+	let init_msg = msgs::Init { features, networks: None, remote_network_address: None };
+	for i in 1..3 {
+		messengers[0].peer_connected(&node_ids[i], &init_msg, true).unwrap();
+		messengers[i].peer_connected(&node_ids[0], &init_msg, false).unwrap();
+	}
+
+	// Only registered services may be used to send requests.
+	assert!(rpcs[0].send_request::<OverlappingService>(&41, Destination::Node(node_ids[1])).is_err());
+
+	let request_id = rpcs[0].send_request::<IncrementService>(&41, Destination::Node(node_ids[1])).unwrap();
+	assert_eq!(rpcs[0].list_pending_requests(), vec![request_id]);
+	let request = messengers[0].release_pending_msgs().remove(&node_ids[1]).unwrap();
+	assert_eq!(request.len(), 1);
+	assert_eq!(messengers[0].list_pending_requests(), vec![request_id]);
+	messengers[1].handle_onion_message(&node_ids[0], &request[0]);
+
+	// The response is matched with our request by the messenger and passed to the service.
+	let response = messengers[1].release_pending_msgs().remove(&node_ids[0]).unwrap();
+	assert_eq!(response.len(), 1);
+	messengers[0].handle_onion_message(&node_ids[1], &response[0]);
+	assert_eq!(*service.responses.lock().unwrap(), vec![(request_id, 42)]);
+	assert!(rpcs[0].list_pending_requests().is_empty());
+	assert!(messengers[0].list_pending_requests().is_empty());
+
+	// Duplicate responses are dropped.
+	messengers[0].handle_onion_message(&node_ids[1], &response[0]);
+	assert_eq!(service.responses.lock().unwrap().len(), 1);
+
+	// Responses which fail to decode are reported to the service.
+	let request_id = rpcs[0].send_request::<IncrementService>(&41, Destination::Node(node_ids[2])).unwrap();
+	let request = messengers[0].release_pending_msgs().remove(&node_ids[2]).unwrap();
+	messengers[2].handle_onion_message(&node_ids[0], &request[0]);
+	let response = messengers[2].release_pending_msgs().remove(&node_ids[0]).unwrap();
+	messengers[0].handle_onion_message(&node_ids[2], &response[0]);
+	assert_eq!(service.responses.lock().unwrap().len(), 1);
+	assert_eq!(*service.failures.lock().unwrap(),
+		vec![(request_id, RpcRequestFailure::InvalidResponse(DecodeError::ShortRead))]);
+	assert!(rpcs[0].list_pending_requests().is_empty());
+
+	// Requests which aren't responded to time out on the messenger's timer ticks.
+	service.failures.lock().unwrap().clear();
+	let request_id = rpcs[0].send_request::<IncrementService>(&1, Destination::Node(node_ids[1])).unwrap();
+	messengers[0].release_pending_msgs();
+	messengers[0].timer_tick_occurred();
+	assert!(service.failures.lock().unwrap().is_empty());
+	messengers[0].timer_tick_occurred();
+	assert_eq!(*service.failures.lock().unwrap(), vec![(request_id, RpcRequestFailure::NoResponse)]);
+	assert!(rpcs[0].list_pending_requests().is_empty());
+}
+
+#[test]
+fn dlc_messages() {
+	let nodes = create_nodes(2);
+	let contract_id = ContractId([42; 32]);
+	let settle = DlcMessage::Settle(DlcSettle {
+		contract_id,
+		outcome: vec![1, 2, 3],
+		attestation: SecretKey::from_slice(&[43; 32]).unwrap(),
+	});
+
+	// DLC messages are passed to the `DlcMessageHandler` rather than the custom message handler,
+	// with its response sent back over the reply path.
+	let reply_path = nodes[0].messenger.create_reply_path().unwrap();
+	let path = OnionMessagePath {
+		intermediate_nodes: vec![],
+		destination: Destination::Node(nodes[1].get_node_pk()),
+		first_node_addresses: None,
+	};
+	nodes[0].messenger.send_onion_message(
+		path, OnionMessageContents::<TestCustomMessage>::Dlc(settle.clone()), Some(reply_path)
+	).unwrap();
+	pass_along_path(&nodes);
+	assert_eq!(*nodes[1].dlc_message_handler.received_messages.lock().unwrap(), vec![settle]);
+	assert_eq!(*nodes[1].dlc_message_handler.received_via.lock().unwrap(), vec![OnionMessageReceivedVia::Direct]);
+
+	let response = nodes[1].messenger.release_pending_msgs().remove(&nodes[0].get_node_pk()).unwrap();
+	assert_eq!(response.len(), 1);
+	nodes[0].messenger.handle_onion_message(&nodes[1].get_node_pk(), &response[0]);
+	assert_eq!(*nodes[0].dlc_message_handler.received_messages.lock().unwrap(), vec![
+		DlcMessage::Accept(DlcAccept { contract_id, adaptor_signatures: Vec::new() }),
+	]);
+	// The response arrived along the reply path we created, which the handler is told about.
+	match nodes[0].dlc_message_handler.received_via.lock().unwrap()[..] {
+		[OnionMessageReceivedVia::BlindedPath { .. }] => {},
+		ref received_via => panic!("Unexpected received_via {:?}", received_via),
+	}
 }
@@ -11,25 +11,33 @@
 //! more information.
 
 use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::cmp::fixed_time_eq;
 use bitcoin::hashes::hmac::{Hmac, HmacEngine};
 use bitcoin::hashes::sha256::Hash as Sha256;
-use bitcoin::secp256k1::{self, PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoin::secp256k1::{self, Message, PublicKey, Scalar, Secp256k1, SecretKey};
+use bitcoin::secp256k1::ecdsa::Signature;
 
 use crate::blinded_path::{BlindedPath, ForwardTlvs, ReceiveTlvs, utils};
 use crate::sign::{EntropySource, KeysManager, NodeSigner, Recipient};
-use crate::events::OnionMessageProvider;
+use crate::events::{Event, EventHandler, EventsProvider, OnionMessageProvider};
 use crate::ln::features::{InitFeatures, NodeFeatures};
 use crate::ln::msgs::{self, OnionMessageHandler};
 use crate::ln::onion_utils;
 use crate::ln::peer_handler::IgnoringMessageHandler;
+use crate::routing::gossip::{NetworkGraph, NodeId};
 pub use super::packet::{CustomOnionMessageContents, OnionMessageContents};
-use super::offers::OffersMessageHandler;
+use super::dlc::{DlcMessage, DlcMessageHandler};
+use super::offers::{OffersMessage, OffersMessageHandler};
 use super::packet::{BIG_PACKET_HOP_DATA_LEN, ForwardControlTlvs, Packet, Payload, ReceiveControlTlvs, SMALL_PACKET_HOP_DATA_LEN};
 use crate::util::logger::Logger;
-use crate::util::ser::Writeable;
+use crate::util::persist::KVStorePersister;
+use crate::util::ser::{BigSize, FixedLengthReader, Readable, ReadableArgs, Writeable, Writer};
+use crate::util::time::{DefaultTimeSource, TimeSource};
 
 use core::ops::Deref;
+use core::time::Duration;
 use crate::io;
+use crate::io_extras::read_to_end;
 use crate::sync::{Arc, Mutex};
 use crate::prelude::*;
 
@@ -73,11 +81,12 @@ use crate::prelude::*;
 /// # let message_router = Arc::new(FakeMessageRouter {});
 /// # let custom_message_handler = IgnoringMessageHandler {};
 /// # let offers_message_handler = IgnoringMessageHandler {};
+/// # let dlc_message_handler = IgnoringMessageHandler {};
 /// // Create the onion messenger. This must use the same `keys_manager` as is passed to your
 /// // ChannelManager.
 /// let onion_messenger = OnionMessenger::new(
 ///     &keys_manager, &keys_manager, logger, message_router, &offers_message_handler,
-///     &custom_message_handler
+///     &dlc_message_handler, &custom_message_handler
 /// );
 ///
 /// # struct YourCustomMessage {}
@@ -97,6 +106,7 @@ use crate::prelude::*;
 /// let path = OnionMessagePath {
 /// 	intermediate_nodes: vec![hop_node_id1, hop_node_id2],
 /// 	destination: Destination::Node(destination_node_id),
+/// 	first_node_addresses: None,
 /// };
 /// let reply_path = None;
 /// # let your_custom_message = YourCustomMessage {};
@@ -112,6 +122,7 @@ use crate::prelude::*;
 /// let path = OnionMessagePath {
 /// 	intermediate_nodes: vec![hop_node_id1, hop_node_id2],
 /// 	destination: Destination::BlindedPath(blinded_path),
+/// 	first_node_addresses: None,
 /// };
 /// let reply_path = None;
 /// # let your_custom_message = YourCustomMessage {};
@@ -121,216 +132,3028 @@ use crate::prelude::*;
 ///
 /// [offers]: <https://github.com/lightning/bolts/pull/798>
 /// [`OnionMessenger`]: crate::onion_message::OnionMessenger
-pub struct OnionMessenger<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, CMH: Deref>
+pub struct OnionMessenger<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, DMH: Deref, CMH: Deref>
 where
 	ES::Target: EntropySource,
 	NS::Target: NodeSigner,
 	L::Target: Logger,
 	MR::Target: MessageRouter,
 	OMH::Target: OffersMessageHandler,
+	DMH::Target: DlcMessageHandler,
 	CMH:: Target: CustomOnionMessageHandler,
 {
 	entropy_source: ES,
 	node_signer: NS,
 	logger: L,
-	pending_messages: Mutex<HashMap<PublicKey, VecDeque<msgs::OnionMessage>>>,
+	pending_messages: Mutex<HashMap<PublicKey, PeerMessageQueue>>,
+	/// Messages restored via [`OnionMessenger::restore_pending_messages`] or sent along a path with
+	/// [`OnionMessagePath::first_node_addresses`] for peers which are not connected yet, moved to
+	/// `pending_messages` once they connect.
+	offline_messages: Mutex<HashMap<PublicKey, PeerMessageQueue>>,
+	/// Nodes we have `offline_messages` for, e.g. as we've generated an [`Event::ConnectionNeeded`]
+	/// for them, along with the number of timer ticks left until we drop the messages queued for
+	/// them.
+	connection_needed: Mutex<HashMap<PublicKey, u16>>,
+	/// Messages we originated which failed to send due to a full buffer or a disconnected first
+	/// hop, awaiting a retry per [`OnionMessengerConfig::max_send_retries`].
+	pending_retries: Mutex<Vec<PendingRetry>>,
 	secp_ctx: Secp256k1<secp256k1::All>,
 	message_router: MR,
 	offers_handler: OMH,
+	dlc_handler: DMH,
 	custom_handler: CMH,
+	rate_limiter: Mutex<RateLimiter>,
+	/// The key the `path_id`s of the blinded paths we create are authenticated with, derived from
+	/// our [`NodeSigner::get_inbound_payment_key_material`].
+	path_id_key: [u8; 32],
+	/// Requests sent via [`OnionMessenger::send_onion_message_request`] which have not yet been
+	/// responded to, by the `path_id` of the reply path sent along with them, along with the
+	/// number of timer ticks left until they time out.
+	pending_requests: Mutex<HashMap<[u8; 32], (OnionMessageRequestId, u16)>>,
+	/// Messages sent via [`OnionMessenger::send_onion_message_with_receipt`] which we have not yet
+	/// received a receipt for.
+	pending_receipts: Mutex<HashMap<OnionMessageDeliveryId, PendingReceipt>>,
+	/// Liveness probes sent via [`OnionMessenger::send_liveness_probe`] and the statistics of the
+	/// destinations they were sent to.
+	liveness: Mutex<Liveness>,
+	pending_events: Mutex<Vec<Event>>,
+	/// The number and total size of the [`Event::CustomOnionMessageReceived`]s in `pending_events`,
+	/// bounded by [`MAX_PENDING_CUSTOM_MESSAGE_EVENTS`] and
+	/// [`MAX_PENDING_CUSTOM_MESSAGE_EVENT_BYTES`].
+	pending_custom_message_events: Mutex<(usize, usize)>,
+	/// Messages received as [`MessageFragment`]s which are not yet complete, by message id.
+	pending_reassemblies: Mutex<HashMap<[u8; 32], PartialMessage>>,
+	mailbox: Mutex<Mailbox>,
+	dedup_cache: Mutex<DedupCache>,
+	forwarding: Mutex<Forwarding>,
+	message_counts: Mutex<MessageCounts>,
+	config: Mutex<OnionMessengerConfig>,
+	/// Peers we've generated an [`Event::OnionMessageBufferHighWater`] for which we haven't yet
+	/// generated an [`Event::OnionMessageBufferDrained`] for.
+	backpressured_peers: Mutex<HashSet<PublicKey>>,
+	custom_message_delivery: Mutex<CustomOnionMessageDelivery>,
 }
 
-/// A trait defining behavior for routing an [`OnionMessage`].
+/// An identifier for a request sent via [`OnionMessenger::send_onion_message_request`], used to
+/// match it with its response.
 ///
-/// [`OnionMessage`]: msgs::OnionMessage
-pub trait MessageRouter {
-	/// Returns a route for sending an [`OnionMessage`] to the given [`Destination`].
-	///
-	/// [`OnionMessage`]: msgs::OnionMessage
-	fn find_path(
-		&self, sender: PublicKey, peers: Vec<PublicKey>, destination: Destination
-	) -> Result<OnionMessagePath, ()>;
+/// The reply path sent along with the request carries a `path_id` authenticating the id with a
+/// key only we know, encrypted such that only we can read it. Thus, the id is never revealed to
+/// the recipient, and responses to it cannot be forged by nodes which did not receive the reply
+/// path, even if they know or guess the id.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct OnionMessageRequestId(pub [u8; 32]);
+
+impl Writeable for OnionMessageRequestId {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		self.0.write(w)
+	}
 }
 
-/// A [`MessageRouter`] that always fails.
-pub struct DefaultMessageRouter;
+impl Readable for OnionMessageRequestId {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, msgs::DecodeError> {
+		let buf: [u8; 32] = Readable::read(r)?;
+		Ok(OnionMessageRequestId(buf))
+	}
+}
 
-impl MessageRouter for DefaultMessageRouter {
-	fn find_path(
-		&self, _sender: PublicKey, _peers: Vec<PublicKey>, _destination: Destination
-	) -> Result<OnionMessagePath, ()> {
-		Err(())
+/// An identifier for a message sent via [`OnionMessenger::send_onion_message_with_receipt`],
+/// used to match it with its receipt.
+///
+/// As with an [`OnionMessageRequestId`], the id is only included in the reply path the receipt is
+/// sent over, encrypted such that only we can read it.
+///
+/// Also identifies probes sent via [`OnionMessenger::probe_path`] and
+/// [`OnionMessenger::send_liveness_probe`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct OnionMessageDeliveryId(pub [u8; 32]);
+
+impl Writeable for OnionMessageDeliveryId {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		self.0.write(w)
 	}
 }
 
-/// A path for sending an [`msgs::OnionMessage`].
-#[derive(Clone)]
-pub struct OnionMessagePath {
-	/// Nodes on the path between the sender and the destination.
-	pub intermediate_nodes: Vec<PublicKey>,
+impl Readable for OnionMessageDeliveryId {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, msgs::DecodeError> {
+		let buf: [u8; 32] = Readable::read(r)?;
+		Ok(OnionMessageDeliveryId(buf))
+	}
+}
 
-	/// The recipient of the message.
-	pub destination: Destination,
+/// A message sent via [`OnionMessenger::send_onion_message_with_receipt`] or a probe sent via
+/// [`OnionMessenger::probe_path`] awaiting its receipt.
+struct PendingReceipt {
+	/// The nonce the receipt must be signed over.
+	nonce: [u8; 32],
+	/// The node the receipt must be signed by, if the message was sent to an unblinded node.
+	recipient: Option<PublicKey>,
+	ticks_remaining: u16,
+	/// Whether this is an [`OnionMessageProbe`], whose outcome is reported either way.
+	is_probe: bool,
 }
 
-/// The destination of an onion message.
-#[derive(Clone)]
-pub enum Destination {
-	/// We're sending this onion message to a node.
-	Node(PublicKey),
-	/// We're sending this onion message to a blinded path.
-	BlindedPath(BlindedPath),
+/// A liveness probe sent via [`OnionMessenger::send_liveness_probe`] awaiting its answer.
+struct PendingLivenessProbe {
+	id: OnionMessageDeliveryId,
+	destination: Destination,
+	/// The [`TimeSource::now`] at which we sent the probe.
+	sent_at: Duration,
+	ticks_remaining: u16,
 }
 
-impl Destination {
-	pub(super) fn num_hops(&self) -> usize {
-		match self {
-			Destination::Node(_) => 1,
-			Destination::BlindedPath(BlindedPath { blinded_hops, .. }) => blinded_hops.len(),
+/// The maximum number of destinations we keep [`LivenessStats`] for, beyond which those of the
+/// destination probed least recently are dropped.
+const MAX_LIVENESS_STATS_DESTINATIONS: usize = 256;
+
+/// Liveness probes sent via [`OnionMessenger::send_liveness_probe`].
+#[derive(Default)]
+struct Liveness {
+	/// Probes awaiting an answer, by the `path_id` of the reply path sent along with them.
+	pending: HashMap<[u8; 32], PendingLivenessProbe>,
+	/// The statistics of each probed destination, along with the value of `probes_sent` when it was
+	/// last probed.
+	stats: HashMap<Destination, (LivenessStats, u64)>,
+	/// The number of probes sent to any destination, used to tell which destination was probed
+	/// least recently.
+	probes_sent: u64,
+}
+
+impl Liveness {
+	fn record_probe_sent(&mut self, destination: &Destination) {
+		if !self.stats.contains_key(destination) && self.stats.len() >= MAX_LIVENESS_STATS_DESTINATIONS {
+			let least_recent = self.stats.iter()
+				.min_by_key(|(_, (_, last_probed))| *last_probed)
+				.map(|(destination, _)| destination.clone());
+			if let Some(least_recent) = least_recent {
+				self.stats.remove(&least_recent);
+			}
 		}
+		let (stats, last_probed) = self.stats.entry(destination.clone())
+			.or_insert_with(|| (LivenessStats::default(), 0));
+		stats.probes_sent += 1;
+		*last_probed = self.probes_sent;
+		self.probes_sent += 1;
 	}
 }
 
-/// Errors that may occur when [sending an onion message].
+/// Statistics about the liveness probes sent to a destination via
+/// [`OnionMessenger::send_liveness_probe`].
 ///
-/// [sending an onion message]: OnionMessenger::send_onion_message
-#[derive(Debug, PartialEq, Eq)]
-pub enum SendError {
-	/// Errored computing onion message packet keys.
-	Secp256k1(secp256k1::Error),
-	/// Because implementations such as Eclair will drop onion messages where the message packet
-	/// exceeds 32834 bytes, we refuse to send messages where the packet exceeds this size.
-	TooBigPacket,
-	/// The provided [`Destination`] was an invalid [`BlindedPath`], due to having fewer than two
-	/// blinded hops.
-	TooFewBlindedHops,
-	/// Our next-hop peer was offline or does not support onion message forwarding.
-	InvalidFirstHop,
-	/// Onion message contents must have a TLV type >= 64.
-	InvalidMessage,
-	/// Our next-hop peer's buffer was full or our total outbound buffer was full.
-	BufferFull,
-	/// Failed to retrieve our node id from the provided [`NodeSigner`].
-	///
-	/// [`NodeSigner`]: crate::sign::NodeSigner
-	GetNodeIdFailed,
-	/// We attempted to send to a blinded path where we are the introduction node, and failed to
-	/// advance the blinded path to make the second hop the new introduction node. Either
-	/// [`NodeSigner::ecdh`] failed, we failed to tweak the current blinding point to get the
-	/// new blinding point, or we were attempting to send to ourselves.
-	BlindedPathAdvanceFailed,
+/// Round-trip times are measured with the [`TimeSource`] set via
+/// [`OnionMessenger::set_time_source`], so are always zero without the `std` feature unless one
+/// backed by a real clock was set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LivenessStats {
+	/// The number of probes sent.
+	pub probes_sent: u64,
+	/// The number of probes the destination answered.
+	pub probes_answered: u64,
+	/// The number of probes which timed out.
+	pub probes_timed_out: u64,
+	/// The round-trip time of the most recently answered probe, if any.
+	pub last_round_trip_time: Option<Duration>,
+	/// The lowest round-trip time of any answered probe, if any.
+	pub min_round_trip_time: Option<Duration>,
+	/// The highest round-trip time of any answered probe, if any.
+	pub max_round_trip_time: Option<Duration>,
+	/// The sum of the round-trip times of all answered probes.
+	pub total_round_trip_time: Duration,
 }
 
-/// Handler for custom onion messages. If you are using [`SimpleArcOnionMessenger`],
-/// [`SimpleRefOnionMessenger`], or prefer to ignore inbound custom onion messages,
-/// [`IgnoringMessageHandler`] must be provided to [`OnionMessenger::new`]. Otherwise, a custom
-/// implementation of this trait must be provided, with [`CustomMessage`] specifying the supported
-/// message types.
+impl LivenessStats {
+	/// Gets the average round-trip time of all answered probes, if any.
+	pub fn average_round_trip_time(&self) -> Option<Duration> {
+		if self.probes_answered == 0 { return None; }
+		Some(Duration::from_nanos((self.total_round_trip_time.as_nanos() / self.probes_answered as u128) as u64))
+	}
+
+	fn record_round_trip_time(&mut self, round_trip_time: Duration) {
+		self.probes_answered += 1;
+		self.last_round_trip_time = Some(round_trip_time);
+		self.min_round_trip_time = Some(self.min_round_trip_time.map_or(round_trip_time, |min| min.min(round_trip_time)));
+		self.max_round_trip_time = Some(self.max_round_trip_time.map_or(round_trip_time, |max| max.max(round_trip_time)));
+		self.total_round_trip_time += round_trip_time;
+	}
+}
+
+/// A message which failed to send, to be retried once `ticks_remaining` reaches zero.
+struct PendingRetry {
+	prepared: PreparedOnionMessage,
+	priority: OnionMessagePriority,
+	/// The number of retries already attempted.
+	attempts: u8,
+	ticks_remaining: u16,
+}
+
+/// The maximum number of messages awaiting a retry at once, beyond which failed sends are not
+/// retried.
+const MAX_PENDING_RETRIES: usize = 256;
+
+/// The onion message TLV type of a [`MessageFragment`].
+const FRAGMENT_TLV_TYPE: u64 = 65_551;
+
+/// The onion message TLV type of an [`OnionMessageReceipt`].
+const RECEIPT_TLV_TYPE: u64 = 65_555;
+
+/// The onion message TLV type of an [`OnionMessageProbe`].
+const PROBE_TLV_TYPE: u64 = 65_557;
+
+/// The onion message TLV type of a [`LivenessPing`].
+const LIVENESS_PING_TLV_TYPE: u64 = 65_567;
+
+/// The onion message TLV type of a [`LivenessPong`].
+const LIVENESS_PONG_TLV_TYPE: u64 = 65_569;
+
+/// Returns whether onion messages of type `tlv_type` are handled by the [`OnionMessenger`] itself
+/// rather than passed to the [`CustomOnionMessageHandler`], and thus can't be used by custom
+/// messages.
+fn is_reserved_tlv_type(tlv_type: u64) -> bool {
+	tlv_type == FRAGMENT_TLV_TYPE || tlv_type == RECEIPT_TLV_TYPE || tlv_type == PROBE_TLV_TYPE ||
+		tlv_type == LIVENESS_PING_TLV_TYPE || tlv_type == LIVENESS_PONG_TLV_TYPE ||
+		OffersMessage::is_known_type(tlv_type) || DlcMessage::is_known_type(tlv_type)
+}
+
+/// Fails if `message` is a custom message claiming a reserved TLV type, as the recipient would
+/// never pass it to its [`CustomOnionMessageHandler`].
+fn check_custom_tlv_type<T: CustomOnionMessageContents>(
+	message: &OnionMessageContents<T>
+) -> Result<(), SendError> {
+	match message {
+		OnionMessageContents::Custom(msg) if is_reserved_tlv_type(msg.tlv_type()) =>
+			Err(SendError::InvalidMessage),
+		_ => Ok(()),
+	}
+}
+
+/// The maximum number of fragments a message may be split into, limiting messages to roughly a
+/// megabyte.
+const MAX_FRAGMENTS_PER_MESSAGE: u16 = 32;
+
+/// The maximum number of partially received messages we reassemble at once.
+const MAX_PENDING_REASSEMBLIES: usize = 16;
+
+/// The maximum number of partially received messages we reassemble at once whose first fragment
+/// was delivered by the same peer, so that a single peer can't take all reassembly slots.
+const MAX_PENDING_REASSEMBLIES_PER_PEER: usize = 4;
+
+/// The maximum total size, in bytes, of the fragments of all partially received messages we hold.
+/// Fragments which would exceed it cause the message they belong to to be dropped.
+const MAX_PENDING_REASSEMBLY_BYTES: usize = 2 * 1024 * 1024;
+
+/// The maximum number of [`Event::CustomOnionMessageReceived`]s we queue before they're processed,
+/// beyond which further custom messages received while delivering them as events are dropped.
+pub(super) const MAX_PENDING_CUSTOM_MESSAGE_EVENTS: usize = 1024;
+
+/// The maximum total size, in bytes, of the data of the [`Event::CustomOnionMessageReceived`]s we
+/// queue before they're processed, beyond which further custom messages are dropped.
+const MAX_PENDING_CUSTOM_MESSAGE_EVENT_BYTES: usize = 4 * 1024 * 1024;
+
+/// The number of timer ticks after which we give up on reassembling a partially received message.
+pub(super) const REASSEMBLY_TIMEOUT_TICKS: u16 = 6;
+
+/// The number of timer ticks after which we drop messages queued for a node we generated an
+/// [`Event::ConnectionNeeded`] for, if it hasn't connected by then.
+pub(super) const CONNECTION_NEEDED_TIMEOUT_TICKS: u16 = 6;
+
+/// The number of timer ticks after which we drop messages restored via
+/// [`OnionMessenger::restore_pending_messages`] for a peer which hasn't connected by then. This is
+/// longer than [`CONNECTION_NEEDED_TIMEOUT_TICKS`] to give peers time to reconnect after a restart.
+pub(super) const RESTORED_MESSAGES_TIMEOUT_TICKS: u16 = 30;
+
+/// Extra room left in each fragment for the length prefixes which grow along with its data, over
+/// the overhead measured with empty data.
+const FRAGMENT_ENCODING_SLACK: usize = 16;
+
+/// A piece of an onion message which was too large to fit in a single onion message packet.
 ///
-/// See [`OnionMessenger`] for example usage.
+/// The message's TLV record, i.e. its type, length and value, is split across `count` fragments
+/// sharing a random `message_id`, which are reassembled by the recipient in `index` order,
+/// regardless of the order they arrive in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct MessageFragment {
+	message_id: [u8; 32],
+	index: u16,
+	count: u16,
+	data: Vec<u8>,
+}
+
+impl_writeable_tlv_based!(MessageFragment, {
+	(0, message_id, required),
+	(2, index, required),
+	(4, count, required),
+	(6, data, required_vec),
+});
+
+impl CustomOnionMessageContents for MessageFragment {
+	fn tlv_type(&self) -> u64 { FRAGMENT_TLV_TYPE }
+}
+
+/// A signed acknowledgement that an onion message which requested a receipt was received, sent
+/// back to its sender over the message's reply path.
 ///
-/// [`IgnoringMessageHandler`]: crate::ln::peer_handler::IgnoringMessageHandler
-/// [`CustomMessage`]: Self::CustomMessage
-pub trait CustomOnionMessageHandler {
-	/// The message known to the handler. To support multiple message types, you may want to make this
-	/// an enum with a variant for each supported message.
-	type CustomMessage: CustomOnionMessageContents;
+/// The `signature` is by `signing_pubkey` over the nonce included in the request. For messages
+/// sent to us directly, `signing_pubkey` is our node id, see
+/// [`NodeSigner::sign_onion_message_receipt`]. For messages received along a blinded path we
+/// created, it's instead a key derived for that path, so that the receipt doesn't reveal who is
+/// behind the path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct OnionMessageReceipt {
+	signing_pubkey: PublicKey,
+	signature: Signature,
+}
 
-	/// Called with the custom message that was received, returning a response to send, if any.
-	fn handle_custom_message(&self, msg: Self::CustomMessage) -> Option<Self::CustomMessage>;
+impl_writeable_tlv_based!(OnionMessageReceipt, {
+	(0, signing_pubkey, required),
+	(2, signature, required),
+});
 
-	/// Read a custom message of type `message_type` from `buffer`, returning `Ok(None)` if the
-	/// message type is unknown.
-	fn read_custom_message<R: io::Read>(&self, message_type: u64, buffer: &mut R) -> Result<Option<Self::CustomMessage>, msgs::DecodeError>;
+impl CustomOnionMessageContents for OnionMessageReceipt {
+	fn tlv_type(&self) -> u64 { RECEIPT_TLV_TYPE }
 }
 
-impl<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, CMH: Deref>
-OnionMessenger<ES, NS, L, MR, OMH, CMH>
-where
-	ES::Target: EntropySource,
-	NS::Target: NodeSigner,
-	L::Target: Logger,
-	MR::Target: MessageRouter,
-	OMH::Target: OffersMessageHandler,
-	CMH::Target: CustomOnionMessageHandler,
-{
-	/// Constructs a new `OnionMessenger` to send, forward, and delegate received onion messages to
-	/// their respective handlers.
-	pub fn new(
-		entropy_source: ES, node_signer: NS, logger: L, message_router: MR, offers_handler: OMH,
-		custom_handler: CMH
-	) -> Self {
-		let mut secp_ctx = Secp256k1::new();
-		secp_ctx.seeded_randomize(&entropy_source.get_secure_random_bytes());
-		OnionMessenger {
-			entropy_source,
-			node_signer,
-			pending_messages: Mutex::new(HashMap::new()),
-			secp_ctx,
-			logger,
-			message_router,
-			offers_handler,
-			custom_handler,
+/// An empty message sent via [`OnionMessenger::probe_path`], which its recipient only acknowledges
+/// with an [`OnionMessageReceipt`] rather than passing it to any handler.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct OnionMessageProbe {}
+
+impl_writeable_tlv_based!(OnionMessageProbe, {});
+
+impl CustomOnionMessageContents for OnionMessageProbe {
+	fn tlv_type(&self) -> u64 { PROBE_TLV_TYPE }
+}
+
+/// An empty message sent via [`OnionMessenger::send_liveness_probe`], which its recipient answers
+/// with a [`LivenessPong`] over the included reply path rather than passing it to any handler.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct LivenessPing {}
+
+impl_writeable_tlv_based!(LivenessPing, {});
+
+impl CustomOnionMessageContents for LivenessPing {
+	fn tlv_type(&self) -> u64 { LIVENESS_PING_TLV_TYPE }
+}
+
+/// The answer to a [`LivenessPing`], authenticated by the `path_id` of the reply path it is
+/// received over, which only we could have created.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct LivenessPong {}
+
+impl_writeable_tlv_based!(LivenessPong, {});
+
+impl CustomOnionMessageContents for LivenessPong {
+	fn tlv_type(&self) -> u64 { LIVENESS_PONG_TLV_TYPE }
+}
+
+/// Returns the message which is signed to produce an [`OnionMessageReceipt`] for a message which
+/// requested one with the given `nonce`.
+pub(crate) fn onion_message_receipt_hash(nonce: &[u8; 32]) -> Message {
+	let mut engine = Sha256::engine();
+	engine.input(b"LDK onion message receipt");
+	engine.input(nonce);
+	hash_to_message!(&Sha256::from_engine(engine).into_inner())
+}
+
+/// A message we're receiving as [`MessageFragment`]s.
+struct PartialMessage {
+	fragments: Vec<Option<Vec<u8>>>,
+	received: u16,
+	/// The total size, in bytes, of the `fragments` received.
+	received_bytes: usize,
+	/// The peer which delivered the first fragment we received, whose reassembly slots the message
+	/// takes up.
+	peer_node_id: PublicKey,
+	/// The `path_id` all fragments must have been received with.
+	path_id: Option<[u8; 32]>,
+	/// The reply path included with the first fragment.
+	reply_path: Option<BlindedPath>,
+	/// The receipt nonce included with the first fragment.
+	receipt_nonce: Option<[u8; 32]>,
+	ticks_remaining: u16,
+}
+
+/// A custom onion message as read from the wire, which may also be a [`MessageFragment`], an
+/// [`OnionMessageReceipt`], an [`OnionMessageProbe`], a [`LivenessPing`] or a [`LivenessPong`].
+enum ReceivedCustomMessage<T: CustomOnionMessageContents> {
+	Message(T),
+	/// A message left unread as we deliver custom messages via
+	/// [`CustomOnionMessageDelivery::Events`].
+	Raw { tlv_type: u64, data: Vec<u8> },
+	Fragment(MessageFragment),
+	Receipt(OnionMessageReceipt),
+	Probe(OnionMessageProbe),
+	LivenessPing(LivenessPing),
+	LivenessPong(LivenessPong),
+}
+
+impl<T: CustomOnionMessageContents> Writeable for ReceivedCustomMessage<T> {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		match self {
+			ReceivedCustomMessage::Message(msg) => msg.write(w),
+			ReceivedCustomMessage::Raw { data, .. } => w.write_all(data),
+			ReceivedCustomMessage::Fragment(fragment) => fragment.write(w),
+			ReceivedCustomMessage::Receipt(receipt) => receipt.write(w),
+			ReceivedCustomMessage::Probe(probe) => probe.write(w),
+			ReceivedCustomMessage::LivenessPing(ping) => ping.write(w),
+			ReceivedCustomMessage::LivenessPong(pong) => pong.write(w),
 		}
 	}
+}
 
-	/// Send an onion message with contents `message` to the destination of `path`.
+impl<T: CustomOnionMessageContents> CustomOnionMessageContents for ReceivedCustomMessage<T> {
+	fn tlv_type(&self) -> u64 {
+		match self {
+			ReceivedCustomMessage::Message(msg) => msg.tlv_type(),
+			ReceivedCustomMessage::Raw { tlv_type, .. } => *tlv_type,
+			ReceivedCustomMessage::Fragment(fragment) => fragment.tlv_type(),
+			ReceivedCustomMessage::Receipt(receipt) => receipt.tlv_type(),
+			ReceivedCustomMessage::Probe(probe) => probe.tlv_type(),
+			ReceivedCustomMessage::LivenessPing(ping) => ping.tlv_type(),
+			ReceivedCustomMessage::LivenessPong(pong) => pong.tlv_type(),
+		}
+	}
+}
+
+/// Wraps a [`CustomOnionMessageHandler`] to read [`MessageFragment`]s, [`OnionMessageReceipt`]s,
+/// [`OnionMessageProbe`]s, [`LivenessPing`]s and [`LivenessPong`]s in addition to its own messages. It is only used to read onion
+/// message payloads, never to handle messages.
+struct InternalMessageReadingHandler<'a, H: CustomOnionMessageHandler + ?Sized> {
+	handler: &'a H,
+	/// Whether custom messages are left unread, as [`ReceivedCustomMessage::Raw`], rather than
+	/// read by `handler`.
+	read_raw: bool,
+}
+
+impl<'a, H: CustomOnionMessageHandler + ?Sized> CustomOnionMessageHandler for InternalMessageReadingHandler<'a, H> {
+	type CustomMessage = ReceivedCustomMessage<H::CustomMessage>;
+
+	fn handle_custom_message(&self, _msg: Self::CustomMessage) -> Option<Self::CustomMessage> {
+		debug_assert!(false, "InternalMessageReadingHandler is only used for reading messages");
+		None
+	}
+
+	fn read_custom_message<R: io::Read>(&self, message_type: u64, buffer: &mut R) -> Result<Option<Self::CustomMessage>, msgs::DecodeError> {
+		if message_type == FRAGMENT_TLV_TYPE {
+			return Ok(Some(ReceivedCustomMessage::Fragment(Readable::read(buffer)?)));
+		}
+		if message_type == RECEIPT_TLV_TYPE {
+			return Ok(Some(ReceivedCustomMessage::Receipt(Readable::read(buffer)?)));
+		}
+		if message_type == PROBE_TLV_TYPE {
+			return Ok(Some(ReceivedCustomMessage::Probe(Readable::read(buffer)?)));
+		}
+		if message_type == LIVENESS_PING_TLV_TYPE {
+			return Ok(Some(ReceivedCustomMessage::LivenessPing(Readable::read(buffer)?)));
+		}
+		if message_type == LIVENESS_PONG_TLV_TYPE {
+			return Ok(Some(ReceivedCustomMessage::LivenessPong(Readable::read(buffer)?)));
+		}
+		if self.read_raw {
+			let data = read_to_end(buffer)?;
+			return Ok(Some(ReceivedCustomMessage::Raw { tlv_type: message_type, data }));
+		}
+		Ok(self.handler.read_custom_message(message_type, buffer)?.map(|msg| ReceivedCustomMessage::Message(msg)))
+	}
+}
+
+/// The key under which [`OnionMessenger::persist_pending_messages`] persists
+/// [`PendingOnionMessages`].
+pub const PENDING_ONION_MESSAGES_PERSISTENCE_KEY: &str = "onion_messages";
+
+/// Outbound onion messages which were queued for peers, as persisted via
+/// [`OnionMessenger::persist_pending_messages`].
+///
+/// Messages are stored in their final, encrypted form, so any reply paths they include are
+/// persisted along with them. Once read, they can be handed to a new [`OnionMessenger`] via
+/// [`OnionMessenger::restore_pending_messages`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PendingOnionMessages {
+	messages: HashMap<PublicKey, Vec<msgs::OnionMessage>>,
+	/// The priority of each of the `messages` for the same peer. Missing for messages persisted
+	/// before we tracked priorities, which are restored with [`OnionMessagePriority::Normal`].
+	priorities: HashMap<PublicKey, Vec<OnionMessagePriority>>,
+}
+
+impl PendingOnionMessages {
+	/// Returns the number of persisted messages.
+	pub fn len(&self) -> usize {
+		self.messages.values().map(|msgs| msgs.len()).sum()
+	}
+
+	/// Returns whether no messages were persisted.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+}
+
+impl_writeable_tlv_based!(PendingOnionMessages, {
+	(0, messages, required),
+	(1, priorities, (default_value, HashMap::new())),
+});
+
+/// Configures the outbound buffers of an [`OnionMessenger`], set via [`OnionMessenger::set_config`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnionMessengerConfig {
+	/// The maximum total size, in bytes, of the onion messages queued for sending to any single
+	/// peer, beyond which [`Self::eviction_policy`] applies.
 	///
-	/// See [`OnionMessenger`] for example usage.
-	pub fn send_onion_message<T: CustomOnionMessageContents>(
-		&self, path: OnionMessagePath, message: OnionMessageContents<T>,
-		reply_path: Option<BlindedPath>
-	) -> Result<(), SendError> {
-		let OnionMessagePath { intermediate_nodes, mut destination } = path;
-		if let Destination::BlindedPath(BlindedPath { ref blinded_hops, .. }) = destination {
-			if blinded_hops.len() < 2 {
-				return Err(SendError::TooFewBlindedHops);
-			}
+	/// Default value: 256 KiB.
+	pub max_peer_buffer_bytes: usize,
+	/// The maximum total size, in bytes, of the onion messages queued for sending to all peers,
+	/// beyond which [`Self::eviction_policy`] applies.
+	///
+	/// Default value: 128 MiB.
+	pub max_total_buffer_bytes: usize,
+	/// What to do with a new message for a peer once our buffers are full.
+	///
+	/// Default value: [`OnionMessageEvictionPolicy::RejectNew`].
+	pub eviction_policy: OnionMessageEvictionPolicy,
+	/// The number of times a message we send is retried if it fails due to a transient condition,
+	/// i.e. our buffers being full or its first hop not being connected, or being disconnected
+	/// before the message is released to it.
+	///
+	/// Retries are driven by [`OnionMessageHandler::timer_tick_occurred`], which makes them
+	/// available without `std`. A message scheduled for a retry is not reported as failed, instead
+	/// being dropped and counted in [`OnionMessengerStats::dropped`] once its retries are
+	/// exhausted.
+	///
+	/// Default value: 0, i.e. failed sends are not retried.
+	pub max_send_retries: u8,
+	/// The number of timer ticks before the first retry of a failed send, doubling with each
+	/// subsequent retry.
+	///
+	/// Default value: 1.
+	pub retry_backoff_ticks: u16,
+	/// The number of timer ticks we wait for a probe sent via [`OnionMessenger::probe_path`] or
+	/// [`OnionMessenger::send_liveness_probe`] to be acknowledged before considering it failed.
+	///
+	/// Default value: 6, i.e. roughly one minute when used with a [`PeerManager`].
+	///
+	/// [`PeerManager`]: crate::ln::peer_handler::PeerManager
+	pub probe_timeout_ticks: u16,
+	/// The total size, in bytes, of the onion messages queued for a single peer at which we
+	/// generate an [`Event::OnionMessageBufferHighWater`], allowing applications to pause sending
+	/// to the peer before its buffer fills and sends fail with [`SendError::BufferFull`].
+	///
+	/// Default value: `None`, i.e. no such events are generated.
+	pub buffer_high_water_mark_bytes: Option<usize>,
+	/// The total size, in bytes, of the onion messages queued for a peer at or below which we
+	/// generate an [`Event::OnionMessageBufferDrained`] once it crossed
+	/// [`Self::buffer_high_water_mark_bytes`].
+	///
+	/// Default value: 64 KiB.
+	pub buffer_low_water_mark_bytes: usize,
+}
+
+impl Default for OnionMessengerConfig {
+	fn default() -> Self {
+		OnionMessengerConfig {
+			max_peer_buffer_bytes: 256 * 1024,
+			max_total_buffer_bytes: 128 * 1024 * 1024,
+			eviction_policy: OnionMessageEvictionPolicy::RejectNew,
+			max_send_retries: 0,
+			retry_backoff_ticks: 1,
+			probe_timeout_ticks: 6,
+			buffer_high_water_mark_bytes: None,
+			buffer_low_water_mark_bytes: 64 * 1024,
 		}
+	}
+}
 
-		if message.tlv_type() < 64 { return Err(SendError::InvalidMessage) }
+/// What an [`OnionMessenger`] does with a new message for a peer once its outbound buffers are
+/// full, see [`OnionMessengerConfig::eviction_policy`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OnionMessageEvictionPolicy {
+	/// The new message is dropped, or fails to send with [`SendError::BufferFull`] if it's ours.
+	RejectNew,
+	/// The oldest messages of the lowest [`OnionMessagePriority`] queued for the same peer are
+	/// dropped to make room for the new message, generating an [`Event::OnionMessagesEvicted`].
+	/// Evictions for a peer are reported in a single event until it has been processed.
+	///
+	/// Messages we sent ourselves are never dropped. If the buffers are full even without any of
+	/// the messages for the peer which we forwarded, the new message is dropped as with
+	/// [`Self::RejectNew`].
+	DropOldest,
+}
 
-		// If we are sending straight to a blinded path and we are the introduction node, we need to
-		// advance the blinded path by 1 hop so the second hop is the new introduction node.
-		if intermediate_nodes.len() == 0 {
-			if let Destination::BlindedPath(ref mut blinded_path) = destination {
-				let our_node_id = self.node_signer.get_node_id(Recipient::Node)
-					.map_err(|()| SendError::GetNodeIdFailed)?;
-				if blinded_path.introduction_node_id == our_node_id {
-					blinded_path.advance_message_path_by_one(&self.node_signer, &self.secp_ctx)
-						.map_err(|()| SendError::BlindedPathAdvanceFailed)?;
-				}
+/// Configures the mailbox of an [`OnionMessenger`], set via [`OnionMessenger::set_mailbox_config`].
+///
+/// When enabled, onion messages we're asked to forward to a peer which is not currently connected
+/// are stored, rather than dropped, and delivered once the peer reconnects. This allows peers
+/// which are often offline, e.g. mobile nodes, to receive messages sent while they were away.
+///
+/// Messages are only stored for peers we have a channel with, as reported by the
+/// [`ChannelPeerLookup`] set via [`OnionMessenger::set_channel_peer_lookup`]. If no lookup has
+/// been set, no messages are stored.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnionMessageMailboxConfig {
+	/// The maximum number of messages stored for any single peer, beyond which further messages
+	/// for that peer are dropped.
+	///
+	/// Default value: 32.
+	pub max_messages_per_peer: usize,
+	/// The maximum total size, in bytes, of the messages stored for all peers, beyond which
+	/// further messages are dropped.
+	///
+	/// Default value: 4 MiB.
+	pub max_total_bytes: usize,
+	/// The maximum total size, in bytes, of the stored messages forwarded to us by any single
+	/// peer, beyond which further messages from that peer are dropped. This keeps a single peer
+	/// from taking up all of [`Self::max_total_bytes`].
+	///
+	/// Default value: 512 KiB.
+	pub max_bytes_per_sender: usize,
+	/// The number of calls to [`OnionMessageHandler::timer_tick_occurred`] after which a stored
+	/// message expires, generating an [`Event::OnionMessagesExpired`].
+	///
+	/// Default value: 360, i.e. roughly an hour if [`PeerManager::timer_tick_occurred`] is called
+	/// every ten seconds, as recommended.
+	///
+	/// [`PeerManager::timer_tick_occurred`]: crate::ln::peer_handler::PeerManager::timer_tick_occurred
+	pub expiry_ticks: u16,
+}
+
+impl Default for OnionMessageMailboxConfig {
+	fn default() -> Self {
+		OnionMessageMailboxConfig {
+			max_messages_per_peer: 32,
+			max_total_bytes: 4 * 1024 * 1024,
+			max_bytes_per_sender: 512 * 1024,
+			expiry_ticks: 360,
+		}
+	}
+}
+
+struct StoredMessage {
+	message: msgs::OnionMessage,
+	/// The peer which forwarded the message to us.
+	sender_node_id: PublicKey,
+	ticks_remaining: u16,
+}
+
+/// Configures the deduplication of custom onion messages we receive, set via
+/// [`OnionMessenger::set_dedup_config`].
+///
+/// When enabled, custom messages are identified by their receipt nonce if the sender requested a
+/// receipt, which is unique to each message sent, or otherwise by a hash of their contents and
+/// reply path. Messages identical to one received within [`Self::ttl_ticks`] are dropped before
+/// reaching the [`CustomOnionMessageHandler`], such that a sender replaying or aggressively
+/// retrying a message doesn't cause it to be handled more than once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnionMessageDedupConfig {
+	/// The maximum number of messages remembered at once, beyond which the oldest are forgotten.
+	///
+	/// Default value: 1024.
+	pub max_entries: usize,
+	/// The number of calls to [`OnionMessageHandler::timer_tick_occurred`] after which a message
+	/// is forgotten, such that an identical one is handled again.
+	///
+	/// Default value: 60, i.e. roughly ten minutes if [`PeerManager::timer_tick_occurred`] is
+	/// called every ten seconds, as recommended.
+	///
+	/// [`PeerManager::timer_tick_occurred`]: crate::ln::peer_handler::PeerManager::timer_tick_occurred
+	pub ttl_ticks: u16,
+}
+
+impl Default for OnionMessageDedupConfig {
+	fn default() -> Self {
+		OnionMessageDedupConfig {
+			max_entries: 1024,
+			ttl_ticks: 60,
+		}
+	}
+}
+
+/// The custom onion messages we've recently received, see [`OnionMessageDedupConfig`].
+#[derive(Default)]
+struct DedupCache {
+	/// `None` if deduplication is disabled.
+	config: Option<OnionMessageDedupConfig>,
+	/// The number of timer ticks left until each message is forgotten, by its key.
+	entries: HashMap<[u8; 32], u16>,
+	/// The keys in `entries`, oldest first.
+	order: VecDeque<[u8; 32]>,
+}
+
+impl DedupCache {
+	/// Remembers a received message, returning whether it's new, i.e. should be handled.
+	fn insert(&mut self, key: [u8; 32]) -> bool {
+		let config = match self.config { Some(config) => config, None => return true };
+		if self.entries.contains_key(&key) { return false }
+		if config.max_entries == 0 { return true }
+		while self.entries.len() >= config.max_entries {
+			match self.order.pop_front() {
+				Some(oldest) => { self.entries.remove(&oldest); },
+				None => break,
 			}
 		}
+		self.entries.insert(key, config.ttl_ticks);
+		self.order.push_back(key);
+		true
+	}
 
-		let blinding_secret_bytes = self.entropy_source.get_secure_random_bytes();
-		let blinding_secret = SecretKey::from_slice(&blinding_secret_bytes[..]).expect("RNG is busted");
-		let (introduction_node_id, blinding_point) = if intermediate_nodes.len() != 0 {
-			(intermediate_nodes[0], PublicKey::from_secret_key(&self.secp_ctx, &blinding_secret))
+	fn expire_entries(&mut self) {
+		self.entries.retain(|_, ticks_remaining| {
+			if *ticks_remaining == 0 { return false }
+			*ticks_remaining -= 1;
+			true
+		});
+		let entries = &self.entries;
+		self.order.retain(|key| entries.contains_key(key));
+	}
+}
+
+/// Computes the key identifying a received custom message in our [`DedupCache`].
+///
+/// The key covers the `path_id` the message was received with, so that identical responses to
+/// distinct requests aren't treated as duplicates.
+fn dedup_key<T: CustomOnionMessageContents>(
+	message: &T, path_id: Option<[u8; 32]>, reply_path: &Option<BlindedPath>,
+	receipt_nonce: Option<[u8; 32]>
+) -> [u8; 32] {
+	let mut engine = Sha256::engine();
+	engine.input(&path_id.encode());
+	match receipt_nonce {
+		Some(nonce) => {
+			engine.input(b"receipt_nonce");
+			engine.input(&nonce);
+		},
+		None => {
+			engine.input(b"payload");
+			engine.input(&BigSize(message.tlv_type()).encode());
+			engine.input(&message.encode());
+			engine.input(&reply_path.encode());
+		},
+	}
+	Sha256::from_engine(engine).into_inner()
+}
+
+/// How urgently an onion message we send should be released to the peer it's queued for, see
+/// [`OnionMessenger::send_onion_message_with_priority`].
+///
+/// Messages queued for a peer are released in order of priority, and then in the order they were
+/// queued, so that time-sensitive messages aren't held up behind bulk traffic when our outbound
+/// buffers are contended. When evicting messages per [`OnionMessageEvictionPolicy::DropOldest`],
+/// lower priority messages are dropped first.
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum OnionMessagePriority {
+	/// Released ahead of all other messages.
+	High,
+	/// The priority of messages sent via [`OnionMessenger::send_onion_message`], as well as of
+	/// messages we forward or respond with.
+	Normal,
+	/// Released only once no higher priority messages are queued.
+	Low,
+}
+
+impl Default for OnionMessagePriority {
+	fn default() -> Self {
+		OnionMessagePriority::Normal
+	}
+}
+
+impl_writeable_tlv_based_enum!(OnionMessagePriority,
+	(0, High) => {},
+	(2, Normal) => {},
+	(4, Low) => {},
+;);
+
+/// An onion message queued for sending to a peer.
+struct QueuedMessage {
+	message: msgs::OnionMessage,
+	/// Whether we sent the message ourselves, rather than forwarding it or delivering it from our
+	/// mailbox. Such messages are never evicted.
+	originated: bool,
+}
+
+/// Onion messages queued for sending to a peer, one queue per [`OnionMessagePriority`].
+#[derive(Default)]
+struct PeerMessageQueue {
+	queues: [VecDeque<QueuedMessage>; 3],
+}
+
+impl PeerMessageQueue {
+	fn push(&mut self, priority: OnionMessagePriority, message: msgs::OnionMessage, originated: bool) {
+		self.queues[priority as usize].push_back(QueuedMessage { message, originated });
+	}
+
+	/// Removes the next message to release, i.e. the oldest of the highest priority.
+	fn pop_front(&mut self) -> Option<msgs::OnionMessage> {
+		self.queues.iter_mut().find_map(|queue| queue.pop_front()).map(|queued| queued.message)
+	}
+
+	/// The total size, in bytes, of the queued messages.
+	fn serialized_length(&self) -> usize {
+		self.iter().map(|om| om.serialized_length()).sum()
+	}
+
+	/// Removes the `count` most recently queued messages of the given priority.
+	fn truncate_back(&mut self, priority: OnionMessagePriority, count: usize) {
+		let queue = &mut self.queues[priority as usize];
+		queue.truncate(queue.len().saturating_sub(count));
+	}
+
+	/// Removes the next message to evict, i.e. the oldest of the lowest priority which we didn't
+	/// originate.
+	fn pop_evictable(&mut self) -> Option<msgs::OnionMessage> {
+		for queue in self.queues.iter_mut().rev() {
+			if let Some(pos) = queue.iter().position(|queued| !queued.originated) {
+				return queue.remove(pos).map(|queued| queued.message);
+			}
+		}
+		None
+	}
+
+	/// The total size, in bytes, of the queued messages which may be evicted.
+	fn evictable_length(&self) -> usize {
+		self.queues.iter().flat_map(|queue| queue.iter())
+			.filter(|queued| !queued.originated)
+			.map(|queued| queued.message.serialized_length())
+			.sum()
+	}
+
+	/// Iterates over the queued messages in the order they'd be released.
+	fn iter(&self) -> impl Iterator<Item = &msgs::OnionMessage> {
+		self.queues.iter().flat_map(|queue| queue.iter()).map(|queued| &queued.message)
+	}
+
+	/// Iterates over the queued messages along with their priority, in the order they'd be
+	/// released.
+	fn iter_with_priority(&self) -> impl Iterator<Item = (OnionMessagePriority, &msgs::OnionMessage)> {
+		[OnionMessagePriority::High, OnionMessagePriority::Normal, OnionMessagePriority::Low].iter()
+			.zip(self.queues.iter())
+			.flat_map(|(priority, queue)| queue.iter().map(move |queued| (*priority, &queued.message)))
+	}
+
+	fn len(&self) -> usize {
+		self.queues.iter().map(|queue| queue.len()).sum()
+	}
+
+	fn is_empty(&self) -> bool {
+		self.queues.iter().all(|queue| queue.is_empty())
+	}
+
+	#[cfg(test)]
+	fn take(&mut self) -> VecDeque<msgs::OnionMessage> {
+		let mut messages = VecDeque::new();
+		for queue in self.queues.iter_mut() {
+			messages.extend(queue.drain(..).map(|queued| queued.message));
+		}
+		messages
+	}
+}
+
+/// Onion messages stored for offline peers, see [`OnionMessageMailboxConfig`].
+#[derive(Default)]
+struct Mailbox {
+	/// `None` if the mailbox is disabled.
+	config: Option<OnionMessageMailboxConfig>,
+	messages: HashMap<PublicKey, VecDeque<StoredMessage>>,
+	total_bytes: usize,
+	/// The total size of the stored messages forwarded to us by each peer.
+	sender_bytes: HashMap<PublicKey, usize>,
+}
+
+/// Accounts for a message forwarded by `sender_node_id` of `message_len` bytes no longer being
+/// stored.
+fn release_stored_bytes(
+	total_bytes: &mut usize, sender_bytes: &mut HashMap<PublicKey, usize>,
+	sender_node_id: &PublicKey, message_len: usize
+) {
+	*total_bytes -= message_len;
+	if let hash_map::Entry::Occupied(mut e) = sender_bytes.entry(*sender_node_id) {
+		*e.get_mut() -= message_len;
+		if *e.get() == 0 { e.remove(); }
+	}
+}
+
+impl Mailbox {
+	/// Stores a message forwarded by `sender_node_id` for the given peer, returning whether there
+	/// was room for it.
+	fn store(
+		&mut self, peer_node_id: PublicKey, sender_node_id: PublicKey, message: msgs::OnionMessage
+	) -> bool {
+		let config = match self.config { Some(config) => config, None => return false };
+		let message_len = message.serialized_length();
+		if self.total_bytes + message_len > config.max_total_bytes { return false; }
+		let sender_bytes = self.sender_bytes.get(&sender_node_id).copied().unwrap_or(0);
+		if sender_bytes + message_len > config.max_bytes_per_sender { return false; }
+		let peer_messages = self.messages.entry(peer_node_id).or_insert_with(VecDeque::new);
+		if peer_messages.len() >= config.max_messages_per_peer { return false; }
+		peer_messages.push_back(StoredMessage { message, sender_node_id, ticks_remaining: config.expiry_ticks });
+		self.total_bytes += message_len;
+		*self.sender_bytes.entry(sender_node_id).or_insert(0) += message_len;
+		true
+	}
+
+	/// Removes and returns all messages stored for the given peer.
+	fn take(&mut self, peer_node_id: &PublicKey) -> Option<VecDeque<msgs::OnionMessage>> {
+		let peer_messages = self.messages.remove(peer_node_id)?;
+		Some(peer_messages.into_iter().map(|stored| {
+			release_stored_bytes(&mut self.total_bytes, &mut self.sender_bytes, &stored.sender_node_id,
+				stored.message.serialized_length());
+			stored.message
+		}).collect())
+	}
+
+	/// Counts a timer tick against all stored messages, dropping those which have been stored for
+	/// [`OnionMessageMailboxConfig::expiry_ticks`] and returning the number dropped per peer.
+	fn expire_messages(&mut self) -> Vec<(PublicKey, u64)> {
+		let mut expired = Vec::new();
+		let total_bytes = &mut self.total_bytes;
+		let sender_bytes = &mut self.sender_bytes;
+		self.messages.retain(|peer_node_id, peer_messages| {
+			let mut expired_count = 0;
+			for stored in peer_messages.iter_mut() {
+				stored.ticks_remaining = stored.ticks_remaining.saturating_sub(1);
+				if stored.ticks_remaining == 0 {
+					release_stored_bytes(total_bytes, sender_bytes, &stored.sender_node_id,
+						stored.message.serialized_length());
+					expired_count += 1;
+				}
+			}
+			peer_messages.retain(|stored| stored.ticks_remaining != 0);
+			if expired_count > 0 {
+				expired.push((*peer_node_id, expired_count));
+			}
+			!peer_messages.is_empty()
+		});
+		expired
+	}
+}
+
+/// A limit on the rate at which onion messages may be exchanged with a single peer, enforced as a
+/// token bucket.
+///
+/// A peer's limit is tracked across reconnections, so a peer can't reset it by reconnecting. Its
+/// state is only forgotten once it is disconnected with its `burst` fully replenished.
+///
+/// Note that without the `std` feature time does not advance, so the limit is never replenished
+/// and a peer may only exchange `burst` messages with us in total.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OnionMessageRateLimit {
+	/// The sustained number of onion messages per second allowed.
+	pub messages_per_second: u32,
+	/// The number of onion messages which may be exchanged in a burst, i.e. the maximum number of
+	/// messages allowed after a period without any.
+	pub burst: u32,
+}
+
+/// Per-peer limits on the rate of onion messages handled by an [`OnionMessenger`], set via
+/// [`OnionMessenger::set_rate_limits`].
+///
+/// These apply in addition to the limit on the size of buffered outbound messages, which results
+/// in [`SendError::BufferFull`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OnionMessageRateLimits {
+	/// The limit on onion messages received from each peer, beyond which they are dropped.
+	///
+	/// Default value: `None`, i.e. no limit.
+	pub inbound: Option<OnionMessageRateLimit>,
+	/// The limit on onion messages sent or forwarded to each peer, beyond which sends fail with
+	/// [`SendError::RateLimited`] and forwards are dropped.
+	///
+	/// Default value: `None`, i.e. no limit.
+	pub outbound: Option<OnionMessageRateLimit>,
+}
+
+/// The direction of onion message traffic which exceeded an [`OnionMessageRateLimit`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum RateLimitDirection {
+	/// The peer sent us onion messages faster than [`OnionMessageRateLimits::inbound`] allows.
+	Inbound,
+	/// We attempted to send or forward onion messages to the peer faster than
+	/// [`OnionMessageRateLimits::outbound`] allows.
+	Outbound,
+}
+
+/// A hook to observe peers which exceed the [`OnionMessageRateLimits`] of an [`OnionMessenger`],
+/// e.g. to disconnect or ban peers which persistently flood us with onion messages.
+///
+/// The observer is called from [`OnionMessenger::process_pending_events`] rather than when a
+/// message is rejected, so that no locks are held and it may safely call back into LDK, e.g. to
+/// disconnect the peer via the [`PeerManager`].
+///
+/// [`OnionMessenger::process_pending_events`]: crate::events::EventsProvider::process_pending_events
+/// [`PeerManager`]: crate::ln::peer_handler::PeerManager
+pub trait OnionMessageRateLimitObserver {
+	/// Called for each peer and `direction` in which onion messages were rejected due to a rate
+	/// limit since the last call. `violations` is the total number of messages rejected in the
+	/// given `direction` since we started tracking the peer's limit (see [`OnionMessageRateLimit`]).
+	fn peer_rate_limited(&self, peer_node_id: &PublicKey, direction: RateLimitDirection, violations: u64);
+}
+
+struct TokenBucket {
+	/// The number of available tokens, in thousandths of a token.
+	millitokens: u64,
+	/// The [`TimeSource::now`] at which we last refilled the bucket.
+	last_refill: Duration,
+	violations: u64,
+}
+
+impl TokenBucket {
+	fn new(limit: &OnionMessageRateLimit, now: Duration) -> Self {
+		TokenBucket {
+			millitokens: limit.burst as u64 * 1000,
+			last_refill: now,
+			violations: 0,
+		}
+	}
+
+	fn refill(&mut self, limit: &OnionMessageRateLimit, now: Duration) {
+		let elapsed_millis = now.saturating_sub(self.last_refill).as_millis() as u64;
+		if elapsed_millis > 0 {
+			self.millitokens = self.millitokens
+				.saturating_add(elapsed_millis.saturating_mul(limit.messages_per_second as u64))
+				.min(limit.burst as u64 * 1000);
+			self.last_refill = now;
+		}
+	}
+
+	/// Returns whether the bucket has its full burst available, i.e. is indistinguishable from a
+	/// new one other than its violation count.
+	fn is_full(&mut self, limit: &OnionMessageRateLimit, now: Duration) -> bool {
+		self.refill(limit, now);
+		self.millitokens >= limit.burst as u64 * 1000
+	}
+
+	/// Attempts to take `count` tokens from the bucket, taking none and returning the total number
+	/// of violations if not enough were available.
+	fn try_consume(&mut self, limit: &OnionMessageRateLimit, now: Duration, count: u64) -> Result<(), u64> {
+		self.refill(limit, now);
+		let millitokens = count.saturating_mul(1000);
+		if self.millitokens >= millitokens {
+			self.millitokens -= millitokens;
+			Ok(())
+		} else {
+			self.violations += 1;
+			Err(self.violations)
+		}
+	}
+}
+
+#[derive(Default)]
+struct PeerRateLimits {
+	inbound: Option<TokenBucket>,
+	outbound: Option<TokenBucket>,
+}
+
+struct RateLimiter {
+	limits: OnionMessageRateLimits,
+	peers: HashMap<PublicKey, PeerRateLimits>,
+	observer: Option<Arc<dyn OnionMessageRateLimitObserver + Send + Sync>>,
+	/// The latest violation count of each peer and direction with violations our observer has not
+	/// yet been notified of.
+	pending_violations: HashMap<(PublicKey, RateLimitDirection), u64>,
+	time_source: Arc<dyn TimeSource + Send + Sync>,
+}
+
+impl RateLimiter {
+	/// Forgets the limits of disconnected peers which have their full burst available again, as
+	/// tracking them further would not limit them any more than starting over.
+	fn prune_disconnected_peers(&mut self, connected_peers: &HashMap<PublicKey, PeerMessageQueue>) {
+		let limits = self.limits;
+		let now = self.time_source.now();
+		self.peers.retain(|peer_node_id, peer| {
+			if connected_peers.contains_key(peer_node_id) { return true }
+			let inbound_full = match (&mut peer.inbound, &limits.inbound) {
+				(Some(bucket), Some(limit)) => bucket.is_full(limit, now),
+				_ => true,
+			};
+			let outbound_full = match (&mut peer.outbound, &limits.outbound) {
+				(Some(bucket), Some(limit)) => bucket.is_full(limit, now),
+				_ => true,
+			};
+			!inbound_full || !outbound_full
+		});
+	}
+
+	/// Returns whether a message may be exchanged with the given peer in the given direction,
+	/// queueing a notification for our observer if not.
+	fn allow(&mut self, peer_node_id: &PublicKey, direction: RateLimitDirection) -> bool {
+		self.allow_many(peer_node_id, direction, 1)
+	}
+
+	/// Returns whether all of `count` messages may be exchanged with the given peer in the given
+	/// direction, allowing none of them and queueing a notification for our observer if not.
+	fn allow_many(&mut self, peer_node_id: &PublicKey, direction: RateLimitDirection, count: u64) -> bool {
+		let limit = match direction {
+			RateLimitDirection::Inbound => self.limits.inbound,
+			RateLimitDirection::Outbound => self.limits.outbound,
+		};
+		let limit = match limit { Some(limit) => limit, None => return true };
+		let now = self.time_source.now();
+		let peer = self.peers.entry(*peer_node_id).or_insert_with(PeerRateLimits::default);
+		let bucket = match direction {
+			RateLimitDirection::Inbound => &mut peer.inbound,
+			RateLimitDirection::Outbound => &mut peer.outbound,
+		};
+		match bucket.get_or_insert_with(|| TokenBucket::new(&limit, now)).try_consume(&limit, now, count) {
+			Ok(()) => true,
+			Err(violations) => {
+				if self.observer.is_some() {
+					self.pending_violations.insert((*peer_node_id, direction), violations);
+				}
+				false
+			},
+		}
+	}
+}
+
+/// Which onion messages an [`OnionMessenger`] forwards on behalf of other nodes, set via
+/// [`OnionMessenger::set_forwarding_policy`].
+///
+/// Messages which are not forwarded due to the policy are counted in
+/// [`OnionMessageForwardingStats::dropped_by_policy`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OnionMessageForwardingPolicy {
+	/// Forward onion messages to any peer. This is the default.
+	All,
+	/// Never forward onion messages, only handling those destined for us.
+	Disabled,
+	/// Only forward onion messages to peers we have a channel with, as reported by the
+	/// [`ChannelPeerLookup`] set via [`OnionMessenger::set_channel_peer_lookup`].
+	///
+	/// If no lookup has been set, no messages are forwarded.
+	ChannelPeers,
+	/// Only forward onion messages to the given peers.
+	Allowlist(HashSet<PublicKey>),
+}
+
+impl Default for OnionMessageForwardingPolicy {
+	fn default() -> Self { OnionMessageForwardingPolicy::All }
+}
+
+/// How an [`OnionMessenger`] delivers the custom onion messages it receives, set via
+/// [`OnionMessenger::set_custom_message_delivery`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CustomOnionMessageDelivery {
+	/// Custom messages are read and handled by the [`CustomOnionMessageHandler`] the messenger was
+	/// constructed with. This is the default.
+	Handler,
+	/// Custom messages are not read, but queued as [`Event::CustomOnionMessageReceived`]s with
+	/// their raw TLV type and value, for applications without a static handler type.
+	///
+	/// The [`CustomOnionMessageHandler`] is still polled for messages to send, but no longer
+	/// reads or handles any received messages.
+	///
+	/// Only a bounded number of these events are queued until they're processed via
+	/// [`EventsProvider::process_pending_events`], beyond which further messages are dropped and
+	/// counted in [`OnionMessengerStats::dropped`].
+	Events,
+}
+
+impl Default for CustomOnionMessageDelivery {
+	fn default() -> Self { CustomOnionMessageDelivery::Handler }
+}
+
+/// Looks up whether we have a channel with a given peer, used to enforce
+/// [`OnionMessageForwardingPolicy::ChannelPeers`].
+///
+/// This is implemented for [`ChannelManager`] and any type which dereferences to one.
+///
+/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+pub trait ChannelPeerLookup {
+	/// Returns whether we have at least one funded channel with the given peer.
+	fn has_channel_with(&self, peer_node_id: &PublicKey) -> bool;
+}
+
+impl<T: Deref> ChannelPeerLookup for T where T::Target: ChannelPeerLookup {
+	fn has_channel_with(&self, peer_node_id: &PublicKey) -> bool {
+		self.deref().has_channel_with(peer_node_id)
+	}
+}
+
+/// Counters for onion messages an [`OnionMessenger`] was asked to forward since it was created,
+/// returned by [`OnionMessenger::forwarding_stats`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OnionMessageForwardingStats {
+	/// The number of messages queued to be forwarded to a connected peer.
+	pub forwarded: u64,
+	/// The number of messages stored in our mailbox for a disconnected peer.
+	///
+	/// See [`OnionMessenger::set_mailbox_config`].
+	pub stored: u64,
+	/// The number of messages dropped due to our [`OnionMessageForwardingPolicy`].
+	pub dropped_by_policy: u64,
+	/// The number of messages dropped as the next peer was not connected and the message could
+	/// not be stored in our mailbox.
+	pub dropped_peer_disconnected: u64,
+	/// The number of messages dropped as our outbound buffer for the next peer was full.
+	pub dropped_buffer_full: u64,
+	/// The number of messages dropped due to [`OnionMessageRateLimits::outbound`].
+	pub dropped_rate_limited: u64,
+}
+
+/// A snapshot of the state of an [`OnionMessenger`], returned by [`OnionMessenger::stats`].
+///
+/// Counters cover the lifetime of the [`OnionMessenger`], while the remaining fields reflect the
+/// moment the snapshot was taken.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OnionMessengerStats {
+	/// The number of onion messages we originated which were queued to be sent. Messages too big
+	/// for a single packet count once per fragment.
+	pub sent: u64,
+	/// The number of onion messages received from peers which were destined for us.
+	pub received: u64,
+	/// The number of onion messages received from peers which were queued to be forwarded. See
+	/// [`OnionMessenger::forwarding_stats`] for details.
+	pub forwarded: u64,
+	/// The number of onion messages dropped, whether received from a peer and rate limited,
+	/// failing to decode or duplicating one already received per [`OnionMessageDedupConfig`], not
+	/// forwarded for any of the reasons counted in [`OnionMessageForwardingStats`], evicted from our
+	/// outbound buffers, failing to send after exhausting their retries, expired from our mailbox,
+	/// or received while too many [`Event::CustomOnionMessageReceived`]s were awaiting processing.
+	pub dropped: u64,
+	/// The number of forwarded onion messages currently held in our mailbox, intercepted as their
+	/// next peer was not connected. See [`OnionMessenger::set_mailbox_config`].
+	pub intercepted_messages: usize,
+	/// The total size, in bytes, of [`Self::intercepted_messages`].
+	pub intercepted_bytes: usize,
+	/// The number of onion messages which failed to send and are awaiting a retry, see
+	/// [`OnionMessengerConfig::max_send_retries`].
+	pub pending_retries: usize,
+}
+
+/// The onion messages currently queued for sending to a connected peer, returned by
+/// [`OnionMessenger::peer_buffer_occupancy`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OnionMessageBufferOccupancy {
+	/// The number of queued messages.
+	pub messages: usize,
+	/// The total size, in bytes, of the queued messages. Once this reaches
+	/// [`Self::max_bytes`], our [`OnionMessengerConfig::eviction_policy`] applies.
+	pub bytes: usize,
+	/// The maximum size, in bytes, of the messages queued for a single peer, see
+	/// [`OnionMessengerConfig::max_peer_buffer_bytes`].
+	pub max_bytes: usize,
+}
+
+/// Counters for [`OnionMessengerStats`] which aren't tracked elsewhere.
+#[derive(Default)]
+struct MessageCounts {
+	sent: u64,
+	received: u64,
+	dropped: u64,
+}
+
+struct Forwarding {
+	policy: OnionMessageForwardingPolicy,
+	channel_peers: Option<Box<dyn ChannelPeerLookup + Send + Sync>>,
+	stats: OnionMessageForwardingStats,
+}
+
+impl Forwarding {
+	fn allows(&self, next_node_id: &PublicKey) -> bool {
+		match &self.policy {
+			OnionMessageForwardingPolicy::All => true,
+			OnionMessageForwardingPolicy::Disabled => false,
+			OnionMessageForwardingPolicy::ChannelPeers => self.channel_peers.as_ref()
+				.map_or(false, |channel_peers| channel_peers.has_channel_with(next_node_id)),
+			OnionMessageForwardingPolicy::Allowlist(allowed) => allowed.contains(next_node_id),
+		}
+	}
+}
+
+/// A trait defining behavior for routing an [`OnionMessage`].
+///
+/// [`OnionMessage`]: msgs::OnionMessage
+pub trait MessageRouter {
+	/// Returns a route for sending an [`OnionMessage`] to the given [`Destination`].
+	///
+	/// [`OnionMessage`]: msgs::OnionMessage
+	fn find_path(
+		&self, sender: PublicKey, peers: Vec<PublicKey>, destination: Destination
+	) -> Result<OnionMessagePath, ()>;
+
+	/// Returns whether the given [`Destination`] is known to support reassembling messages split
+	/// into fragments, i.e. advertises [`NodeFeatures::supports_onion_message_fragmentation`].
+	/// Messages which don't fit in a single packet fail with [`SendError::TooBigPacket`] otherwise.
+	///
+	/// The default implementation returns `false`.
+	fn supports_fragmentation(&self, _destination: &Destination) -> bool { false }
+
+	/// Returns the unblinded hops of a reply path to `recipient`, i.e. us, starting at one of our
+	/// `peers` and ending in `recipient`, used by [`OnionMessenger::send_onion_message_with_reply`].
+	///
+	/// At least two hops must be returned. The default implementation uses the first of `peers` as
+	/// the introduction node.
+	fn find_reply_path(&self, recipient: PublicKey, peers: Vec<PublicKey>) -> Result<Vec<PublicKey>, ()> {
+		peers.first().map(|peer| vec![*peer, recipient]).ok_or(())
+	}
+
+	/// Returns up to `count` paths for sending an [`OnionMessage`] to the given [`Destination`]
+	/// which share no intermediate nodes, used by [`OnionMessenger::send_onion_message_redundant`].
+	///
+	/// At least one path must be returned on success. The default implementation returns the
+	/// single path found by [`Self::find_path`].
+	///
+	/// [`OnionMessage`]: msgs::OnionMessage
+	fn find_disjoint_paths(
+		&self, sender: PublicKey, peers: Vec<PublicKey>, destination: Destination, count: usize
+	) -> Result<Vec<OnionMessagePath>, ()> {
+		self.find_path(sender, peers, destination).map(|path| vec![path])
+	}
+}
+
+/// Parameters for finding paths with a [`DefaultMessageRouter`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DefaultMessageRouterParams {
+	/// The maximum number of hops a path may have, counting the destination, or the introduction
+	/// node of a blinded destination, but not any further blinded hops.
+	///
+	/// Default value: 4.
+	pub max_hops: u8,
+	/// Nodes which must not be used as intermediate nodes, including as our first hop. Messages
+	/// may still be sent to these nodes directly.
+	///
+	/// Default value: empty.
+	pub avoided_nodes: HashSet<PublicKey>,
+	/// Whether, if no path through our peers is found, to return a path which reaches the
+	/// destination, or the introduction node of a blinded destination, directly once we've
+	/// connected to it via its announced addresses, see [`Event::ConnectionNeeded`].
+	///
+	/// Connecting directly reveals to that node that the message came from us, so this should
+	/// only be enabled if that is acceptable.
+	///
+	/// Default value: false.
+	pub connect_directly: bool,
+}
+
+impl Default for DefaultMessageRouterParams {
+	fn default() -> Self {
+		Self { max_hops: 4, avoided_nodes: HashSet::new(), connect_directly: false }
+	}
+}
+
+/// A [`MessageRouter`] which finds the shortest path to a destination over announced channels in
+/// the [`NetworkGraph`], starting at one of our connected peers.
+///
+/// All intermediate nodes, as well as the destination, must have announced support for onion
+/// messages. Destinations which are connected peers are always reached directly.
+pub struct DefaultMessageRouter<G: Deref<Target=NetworkGraph<L>>, L: Deref>
+where
+	L::Target: Logger,
+{
+	network_graph: G,
+	params: DefaultMessageRouterParams,
+}
+
+impl<G: Deref<Target=NetworkGraph<L>>, L: Deref> DefaultMessageRouter<G, L>
+where
+	L::Target: Logger,
+{
+	/// Creates a [`DefaultMessageRouter`] using the [`DefaultMessageRouterParams::default`].
+	pub fn new(network_graph: G) -> Self {
+		Self::with_params(network_graph, DefaultMessageRouterParams::default())
+	}
+
+	/// Creates a [`DefaultMessageRouter`] using the given parameters.
+	pub fn with_params(network_graph: G, params: DefaultMessageRouterParams) -> Self {
+		Self { network_graph, params }
+	}
+
+	/// Finds the shortest path to `destination` which doesn't use any of `excluded_nodes` as
+	/// intermediate nodes, and which only reaches the destination directly if `allow_direct`.
+	fn find_path_avoiding(
+		&self, sender: PublicKey, peers: &[PublicKey], destination: Destination,
+		excluded_nodes: &HashSet<PublicKey>, allow_direct: bool
+	) -> Result<OnionMessagePath, ()> {
+		let first_node = match &destination {
+			Destination::Node(node_id) => *node_id,
+			Destination::BlindedPath(BlindedPath { introduction_node_id, .. }) => *introduction_node_id,
+		};
+		if first_node == sender || (allow_direct && peers.contains(&first_node)) {
+			return Ok(OnionMessagePath { intermediate_nodes: vec![], destination, first_node_addresses: None });
+		}
+
+		let network_graph = self.network_graph.read_only();
+		let supports_onion_messages = |node_id: &NodeId| {
+			network_graph.node(node_id)
+				.and_then(|node| node.announcement_info.as_ref())
+				.map_or(false, |info| info.features.supports_onion_messages())
+		};
+		let target = NodeId::from_pubkey(&first_node);
+		if !supports_onion_messages(&target) { return Err(()); }
+
+		// Breadth-first search from our usable peers, tracking the node each node was reached from,
+		// such that the first time we reach the target we've found a shortest path.
+		let mut previous_hops: HashMap<NodeId, Option<NodeId>> = HashMap::new();
+		let mut frontier = Vec::new();
+		let usable_peers = peers.iter().filter(|peer| {
+			!self.params.avoided_nodes.contains(*peer) && !excluded_nodes.contains(*peer) && **peer != first_node
+		});
+		for peer in usable_peers {
+			let node_id = NodeId::from_pubkey(peer);
+			if previous_hops.insert(node_id, None).is_none() {
+				frontier.push(node_id);
+			}
+		}
+		let avoided_nodes: HashSet<NodeId> = self.params.avoided_nodes.iter().chain(excluded_nodes.iter())
+			.map(|node_id| NodeId::from_pubkey(node_id)).collect();
+		// Each iteration extends paths by one hop, with the initial frontier at one hop.
+		for _ in 1..self.params.max_hops {
+			let mut next_frontier = Vec::new();
+			for node_id in frontier.drain(..) {
+				let node = match network_graph.node(&node_id) { Some(node) => node, None => continue };
+				for scid in node.channels.iter() {
+					let channel = match network_graph.channel(*scid) { Some(channel) => channel, None => continue };
+					let next_node_id = if channel.node_one == node_id { channel.node_two } else { channel.node_one };
+					if previous_hops.contains_key(&next_node_id) { continue; }
+					if next_node_id == target {
+						let mut intermediate_nodes = Vec::new();
+						let mut hop = Some(node_id);
+						while let Some(hop_id) = hop {
+							intermediate_nodes.push(hop_id.as_pubkey().map_err(|_| ())?);
+							hop = previous_hops.get(&hop_id).copied().flatten();
+						}
+						intermediate_nodes.reverse();
+						return Ok(OnionMessagePath { intermediate_nodes, destination, first_node_addresses: None });
+					}
+					if avoided_nodes.contains(&next_node_id) || !supports_onion_messages(&next_node_id) {
+						continue;
+					}
+					previous_hops.insert(next_node_id, Some(node_id));
+					next_frontier.push(next_node_id);
+				}
+			}
+			frontier = next_frontier;
+		}
+
+		// Failing that, send directly to the target once we've connected to it, if we know how and
+		// don't mind revealing ourselves to it.
+		if !allow_direct || !self.params.connect_directly { return Err(()); }
+		let first_node_addresses = network_graph.node(&target)
+			.and_then(|node| node.announcement_info.as_ref())
+			.map(|info| info.addresses().to_vec())
+			.filter(|addresses| !addresses.is_empty())
+			.ok_or(())?;
+		Ok(OnionMessagePath {
+			intermediate_nodes: vec![], destination, first_node_addresses: Some(first_node_addresses),
+		})
+	}
+}
+
+impl<G: Deref<Target=NetworkGraph<L>>, L: Deref> MessageRouter for DefaultMessageRouter<G, L>
+where
+	L::Target: Logger,
+{
+	fn find_path(
+		&self, sender: PublicKey, peers: Vec<PublicKey>, destination: Destination
+	) -> Result<OnionMessagePath, ()> {
+		self.find_path_avoiding(sender, &peers, destination, &HashSet::new(), true)
+	}
+
+	/// Finds paths which don't share any intermediate nodes by repeatedly searching for the
+	/// shortest path while excluding the intermediate nodes of those already found. At most one
+	/// path reaches the destination directly.
+	fn find_disjoint_paths(
+		&self, sender: PublicKey, peers: Vec<PublicKey>, destination: Destination, count: usize
+	) -> Result<Vec<OnionMessagePath>, ()> {
+		let mut paths: Vec<OnionMessagePath> = Vec::with_capacity(count);
+		let mut excluded_nodes = HashSet::new();
+		while paths.len() < count {
+			let allow_direct = paths.iter().all(|path| !path.intermediate_nodes.is_empty());
+			match self.find_path_avoiding(sender, &peers, destination.clone(), &excluded_nodes, allow_direct) {
+				Ok(path) => {
+					excluded_nodes.extend(path.intermediate_nodes.iter().copied());
+					paths.push(path);
+				},
+				Err(()) => break,
+			}
+		}
+		if paths.is_empty() { Err(()) } else { Ok(paths) }
+	}
+
+	/// Looks up the features announced by the destination node. Blinded destinations are never
+	/// considered to support fragmentation, as the recipient behind them is unknown.
+	fn supports_fragmentation(&self, destination: &Destination) -> bool {
+		match destination {
+			Destination::Node(node_id) => self.network_graph.read_only()
+				.node(&NodeId::from_pubkey(node_id))
+				.and_then(|node| node.announcement_info.as_ref())
+				.map_or(false, |info| info.features.supports_onion_message_fragmentation()),
+			Destination::BlindedPath(_) => false,
+		}
+	}
+
+	/// Picks the peer which has announced support for onion messages and has the most announced
+	/// channels as the introduction node, making it likely the reply path is reachable by the
+	/// recipient of our message. Avoided nodes are never used.
+	fn find_reply_path(&self, recipient: PublicKey, peers: Vec<PublicKey>) -> Result<Vec<PublicKey>, ()> {
+		let network_graph = self.network_graph.read_only();
+		peers.into_iter()
+			.filter(|peer| !self.params.avoided_nodes.contains(peer))
+			.filter_map(|peer| {
+				let node = network_graph.node(&NodeId::from_pubkey(&peer))?;
+				let supports_onion_messages = node.announcement_info.as_ref()
+					.map_or(false, |info| info.features.supports_onion_messages());
+				if supports_onion_messages { Some((peer, node.channels.len())) } else { None }
+			})
+			.max_by_key(|(_, channel_count)| *channel_count)
+			.map(|(peer, _)| vec![peer, recipient])
+			.ok_or(())
+	}
+}
+
+/// A path for sending an [`msgs::OnionMessage`].
+#[derive(Clone)]
+pub struct OnionMessagePath {
+	/// Nodes on the path between the sender and the destination.
+	pub intermediate_nodes: Vec<PublicKey>,
+
+	/// The recipient of the message.
+	pub destination: Destination,
+
+	/// Addresses that may be used to connect to the first node of the path, i.e. the first of
+	/// [`Self::intermediate_nodes`] or the destination's introduction node, if it isn't one of our
+	/// peers.
+	///
+	/// If set and the first node is not connected, the message is queued and an
+	/// [`Event::ConnectionNeeded`] generated, rather than failing with
+	/// [`SendError::InvalidFirstHop`].
+	pub first_node_addresses: Option<Vec<msgs::NetAddress>>,
+}
+
+/// A handle for replying to a received onion message after its handler has returned, see
+/// [`CustomOnionMessageHandler::handle_custom_message_with_responder`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Responder {
+	/// The path the sender of the message asked us to reply along.
+	reply_path: BlindedPath,
+	/// How the message being replied to reached us.
+	received_via: OnionMessageReceivedVia,
+}
+
+impl Responder {
+	#[cfg(test)]
+	pub(crate) fn new(reply_path: BlindedPath, received_via: OnionMessageReceivedVia) -> Self {
+		Self { reply_path, received_via }
+	}
+
+	/// Returns the [`Destination`] a response should be sent to.
+	pub fn destination(&self) -> Destination {
+		Destination::BlindedPath(self.reply_path.clone())
+	}
+
+	/// Returns the path the sender of the message asked us to reply along.
+	pub fn reply_path(&self) -> &BlindedPath {
+		&self.reply_path
+	}
+
+	/// Returns how the message this handle was provided with reached us.
+	pub fn received_via(&self) -> OnionMessageReceivedVia {
+		self.received_via
+	}
+
+	/// Builds an [`OnionMessagePath`] to the sender's reply path through the given
+	/// `intermediate_nodes`, the first of which must be our peer and the last a peer of the reply
+	/// path's introduction node, for use with [`Self::respond_along_path`].
+	pub fn path_via(&self, intermediate_nodes: Vec<PublicKey>) -> OnionMessagePath {
+		OnionMessagePath {
+			intermediate_nodes,
+			destination: self.destination(),
+			first_node_addresses: None,
+		}
+	}
+
+	/// Builds a response to the message this handle was provided with, in the form expected by
+	/// [`CustomOnionMessageHandler::release_pending_custom_messages`].
+	pub fn respond<T>(self, response: T) -> (T, Destination, Option<BlindedPath>) {
+		(response, Destination::BlindedPath(self.reply_path), None)
+	}
+
+	/// Builds a response to the message this handle was provided with which is sent along the
+	/// given `path` rather than one found by our [`MessageRouter`], in the form expected by
+	/// [`CustomOnionMessageHandler::release_pending_custom_messages_along_paths`].
+	///
+	/// The `path` is generally built via [`Self::path_via`], but may lead anywhere. The response
+	/// may include a `reply_path` for the sender to respond along in turn, e.g. a fresh one
+	/// created via [`OnionMessenger::create_reply_path`].
+	pub fn respond_along_path<T>(
+		self, response: T, path: OnionMessagePath, reply_path: Option<BlindedPath>
+	) -> (T, OnionMessagePath, Option<BlindedPath>) {
+		(response, path, reply_path)
+	}
+}
+
+/// How a received onion message reached us, see
+/// [`CustomOnionMessageHandler::handle_custom_message_with_context`].
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub enum OnionMessageReceivedVia {
+	/// The message was sent to our node id directly, or along a blinded path to us which we
+	/// didn't create.
+	Direct,
+	/// The message was sent along a blinded path to us which we created, such as those created
+	/// via [`OnionMessenger::create_reply_path`], carrying the given identifier.
+	///
+	/// The identifiers of the paths we create are random and authenticated with a key derived
+	/// from our node secret, so they can't be forged by a sender building its own blinded path to
+	/// us. A handler may thus check `path_id` against the identifiers of paths it handed out to
+	/// authorize the sender.
+	BlindedPath {
+		/// The identifier included in the blinded path.
+		path_id: [u8; 32],
+	},
+}
+
+impl_writeable_tlv_based_enum!(OnionMessageReceivedVia,
+	(0, Direct) => {},
+	(2, BlindedPath) => {
+		(0, path_id, required),
+	},
+;);
+
+/// An onion message which has been validated and had its packets constructed, ready to be queued
+/// for sending to the first node of its path.
+#[derive(Clone)]
+struct PreparedOnionMessage {
+	first_node_id: PublicKey,
+	first_node_addresses: Option<Vec<msgs::NetAddress>>,
+	/// The packets to send, more than one if the message was split into fragments.
+	messages: Vec<msgs::OnionMessage>,
+}
+
+/// The destination of an onion message.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub enum Destination {
+	/// We're sending this onion message to a node.
+	Node(PublicKey),
+	/// We're sending this onion message to a blinded path.
+	BlindedPath(BlindedPath),
+}
+
+impl Destination {
+	pub(super) fn num_hops(&self) -> usize {
+		match self {
+			Destination::Node(_) => 1,
+			Destination::BlindedPath(BlindedPath { blinded_hops, .. }) => blinded_hops.len(),
+		}
+	}
+}
+
+/// Errors that may occur when [sending an onion message].
+///
+/// [sending an onion message]: OnionMessenger::send_onion_message
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError {
+	/// Errored computing onion message packet keys.
+	Secp256k1(secp256k1::Error),
+	/// Because implementations such as Eclair will drop onion messages where the message packet
+	/// exceeds 32834 bytes, we refuse to send messages where the packet exceeds this size.
+	///
+	/// Larger messages are split into fragments which are reassembled by the recipient, so this is
+	/// only returned if the recipient isn't known to support fragmentation (see
+	/// [`MessageRouter::supports_fragmentation`]), the path leaves no room for fragments or the
+	/// message would require too many of them.
+	TooBigPacket,
+	/// The provided [`Destination`] was an invalid [`BlindedPath`], due to having fewer than two
+	/// blinded hops.
+	TooFewBlindedHops,
+	/// Our next-hop peer was offline or does not support onion message forwarding, and no
+	/// [`OnionMessagePath::first_node_addresses`] were given to connect to it.
+	InvalidFirstHop,
+	/// Onion message contents must have a TLV type >= 64, and custom messages must not use a TLV
+	/// type reserved as documented in [`CustomOnionMessageContents::tlv_type`].
+	InvalidMessage,
+	/// Our next-hop peer's buffer was full or our total outbound buffer was full.
+	BufferFull,
+	/// We've sent onion messages to our next-hop peer faster than
+	/// [`OnionMessageRateLimits::outbound`] allows.
+	RateLimited,
+	/// Failed to retrieve our node id from the provided [`NodeSigner`].
+	///
+	/// [`NodeSigner`]: crate::sign::NodeSigner
+	GetNodeIdFailed,
+	/// We attempted to send to a blinded path where we are the introduction node, and failed to
+	/// advance the blinded path to make the second hop the new introduction node. Either
+	/// [`NodeSigner::ecdh`] failed, we failed to tweak the current blinding point to get the
+	/// new blinding point, or we were attempting to send to ourselves.
+	BlindedPathAdvanceFailed,
+	/// Our [`MessageRouter`] failed to find a reply path back to us, or we failed to construct a
+	/// [`BlindedPath`] along it.
+	ReplyPathNotFound,
+	/// A request with the given [`OnionMessageRequestId`] is already awaiting a response.
+	DuplicateRequestId,
+	/// A message with the given [`OnionMessageDeliveryId`] is already awaiting a receipt.
+	DuplicateDeliveryId,
+	/// Our [`MessageRouter`] failed to find a path to the [`Destination`].
+	PathNotFound,
+}
+
+/// Handler for custom onion messages. If you are using [`SimpleArcOnionMessenger`],
+/// [`SimpleRefOnionMessenger`], or prefer to ignore inbound custom onion messages,
+/// [`IgnoringMessageHandler`] must be provided to [`OnionMessenger::new`]. Otherwise, a custom
+/// implementation of this trait must be provided, with [`CustomMessage`] specifying the supported
+/// message types.
+///
+/// See [`OnionMessenger`] for example usage.
+///
+/// [`IgnoringMessageHandler`]: crate::ln::peer_handler::IgnoringMessageHandler
+/// [`CustomMessage`]: Self::CustomMessage
+pub trait CustomOnionMessageHandler {
+	/// The message known to the handler. To support multiple message types, you may want to make this
+	/// an enum with a variant for each supported message.
+	type CustomMessage: CustomOnionMessageContents;
+
+	/// Called with the custom message that was received, returning a response to send, if any.
+	fn handle_custom_message(&self, msg: Self::CustomMessage) -> Option<Self::CustomMessage>;
+
+	/// Called with the custom message that was received along with a [`Responder`] for replying
+	/// to it, if the sender included a reply path. Returns a response to send immediately, if any.
+	///
+	/// Handlers which cannot respond inline, e.g. because they first need to perform disk or
+	/// network I/O, should return `None` and hold on to the [`Responder`]. Once the response is
+	/// ready, it can be passed to [`Responder::respond`] and the result returned from
+	/// [`Self::release_pending_custom_messages`], avoiding blocking onion message handling.
+	///
+	/// The default implementation ignores the [`Responder`] and calls
+	/// [`Self::handle_custom_message`].
+	fn handle_custom_message_with_responder(
+		&self, msg: Self::CustomMessage, _responder: Option<Responder>
+	) -> Option<Self::CustomMessage> {
+		self.handle_custom_message(msg)
+	}
+
+	/// Called with the custom message that was received, how it reached us and a [`Responder`]
+	/// for replying to it, if the sender included a reply path. Returns a response to send
+	/// immediately, if any.
+	///
+	/// This allows handlers to restrict messages to those received along blinded paths they
+	/// handed out, and to inspect the sender's reply path via [`Responder::reply_path`] before
+	/// deciding whether and when to reply.
+	///
+	/// The default implementation ignores `received_via` and calls
+	/// [`Self::handle_custom_message_with_responder`].
+	fn handle_custom_message_with_context(
+		&self, msg: Self::CustomMessage, _received_via: OnionMessageReceivedVia,
+		responder: Option<Responder>
+	) -> Option<Self::CustomMessage> {
+		self.handle_custom_message_with_responder(msg, responder)
+	}
+
+	/// Called with the response to a request we sent via
+	/// [`OnionMessenger::send_onion_message_request`] with the given `request_id`, if it was
+	/// received before the request timed out. Returns a response to send immediately, if any.
+	///
+	/// The default implementation calls [`Self::handle_custom_message_with_responder`].
+	fn handle_custom_response(
+		&self, msg: Self::CustomMessage, _request_id: OnionMessageRequestId,
+		responder: Option<Responder>
+	) -> Option<Self::CustomMessage> {
+		self.handle_custom_message_with_responder(msg, responder)
+	}
+
+	/// Read a custom message of type `message_type` from `buffer`, returning `Ok(None)` if the
+	/// message type is unknown.
+	fn read_custom_message<R: io::Read>(&self, message_type: u64, buffer: &mut R) -> Result<Option<Self::CustomMessage>, msgs::DecodeError>;
+
+	/// Releases any custom messages which should be sent, along with the [`Destination`] to send
+	/// each to and an optional reply path. Called by [`OnionMessenger`] whenever it is polled for
+	/// outbound onion messages, with paths to each destination found via its [`MessageRouter`].
+	///
+	/// The default implementation never releases any messages.
+	fn release_pending_custom_messages(&self) -> Vec<(Self::CustomMessage, Destination, Option<BlindedPath>)> {
+		Vec::new()
+	}
+
+	/// Releases any custom messages which should be sent along the given [`OnionMessagePath`]
+	/// rather than one found via our [`MessageRouter`], e.g. responses built via
+	/// [`Responder::respond_along_path`], along with an optional reply path. Called by
+	/// [`OnionMessenger`] whenever it is polled for outbound onion messages.
+	///
+	/// The default implementation never releases any messages.
+	fn release_pending_custom_messages_along_paths(&self) -> Vec<(Self::CustomMessage, OnionMessagePath, Option<BlindedPath>)> {
+		Vec::new()
+	}
+
+	/// Releases any custom messages which should be sent as requests expecting a response, along
+	/// with the [`Destination`] to send each to, the [`OnionMessageRequestId`] identifying it and
+	/// the number of timer ticks after which it times out, as passed to
+	/// [`OnionMessenger::send_onion_message_request`]. Called by [`OnionMessenger`] whenever it is
+	/// polled for outbound onion messages, with paths to each destination found via its
+	/// [`MessageRouter`].
+	///
+	/// Responses are passed to [`Self::handle_custom_response`], while requests which can't be
+	/// sent or time out are passed to [`Self::handle_custom_request_failed`].
+	///
+	/// The default implementation never releases any requests.
+	fn release_pending_custom_requests(&self) -> Vec<(Self::CustomMessage, Destination, OnionMessageRequestId, u16)> {
+		Vec::new()
+	}
+
+	/// Called when a request released via [`Self::release_pending_custom_requests`] couldn't be
+	/// sent, or a request sent via [`OnionMessenger::send_onion_message_request`] or released via
+	/// [`Self::release_pending_custom_requests`] timed out without a response, in which case an
+	/// [`Event::OnionMessageTimedOut`] is generated as well.
+	///
+	/// The default implementation does nothing.
+	fn handle_custom_request_failed(&self, _request_id: OnionMessageRequestId) {}
+}
+
+impl<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, DMH: Deref, CMH: Deref>
+OnionMessenger<ES, NS, L, MR, OMH, DMH, CMH>
+where
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+	L::Target: Logger,
+	MR::Target: MessageRouter,
+	OMH::Target: OffersMessageHandler,
+	DMH::Target: DlcMessageHandler,
+	CMH::Target: CustomOnionMessageHandler,
+{
+	/// Constructs a new `OnionMessenger` to send, forward, and delegate received onion messages to
+	/// their respective handlers.
+	pub fn new(
+		entropy_source: ES, node_signer: NS, logger: L, message_router: MR, offers_handler: OMH,
+		dlc_handler: DMH, custom_handler: CMH
+	) -> Self {
+		let mut secp_ctx = Secp256k1::new();
+		secp_ctx.seeded_randomize(&entropy_source.get_secure_random_bytes());
+		let mut path_id_key = HmacEngine::<Sha256>::new(&node_signer.get_inbound_payment_key_material().0);
+		path_id_key.input(b"LDK Onion Message Path ID Key");
+		let path_id_key = Hmac::from_engine(path_id_key).into_inner();
+		OnionMessenger {
+			entropy_source,
+			node_signer,
+			pending_messages: Mutex::new(HashMap::new()),
+			offline_messages: Mutex::new(HashMap::new()),
+			connection_needed: Mutex::new(HashMap::new()),
+			pending_retries: Mutex::new(Vec::new()),
+			secp_ctx,
+			logger,
+			message_router,
+			offers_handler,
+			dlc_handler,
+			custom_handler,
+			rate_limiter: Mutex::new(RateLimiter {
+				limits: OnionMessageRateLimits::default(),
+				peers: HashMap::new(),
+				observer: None,
+				pending_violations: HashMap::new(),
+				time_source: Arc::new(DefaultTimeSource::new()),
+			}),
+			path_id_key,
+			pending_requests: Mutex::new(HashMap::new()),
+			pending_receipts: Mutex::new(HashMap::new()),
+			liveness: Mutex::new(Liveness::default()),
+			pending_events: Mutex::new(Vec::new()),
+			pending_custom_message_events: Mutex::new((0, 0)),
+			pending_reassemblies: Mutex::new(HashMap::new()),
+			mailbox: Mutex::new(Mailbox::default()),
+			dedup_cache: Mutex::new(DedupCache::default()),
+			forwarding: Mutex::new(Forwarding {
+				policy: OnionMessageForwardingPolicy::default(),
+				channel_peers: None,
+				stats: OnionMessageForwardingStats::default(),
+			}),
+			message_counts: Mutex::new(MessageCounts::default()),
+			config: Mutex::new(OnionMessengerConfig::default()),
+			backpressured_peers: Mutex::new(HashSet::new()),
+			custom_message_delivery: Mutex::new(CustomOnionMessageDelivery::default()),
+		}
+	}
+
+	/// Persists all onion messages currently queued for peers, including those restored via
+	/// [`Self::restore_pending_messages`] which were not yet released and those stored in our
+	/// mailbox for offline peers, under [`PENDING_ONION_MESSAGES_PERSISTENCE_KEY`].
+	///
+	/// This should be called on shutdown, as queued messages are otherwise lost. Messages remain
+	/// queued after being persisted.
+	pub fn persist_pending_messages<K: KVStorePersister>(&self, persister: &K) -> Result<(), io::Error> {
+		let mut pending = PendingOnionMessages::default();
+		{
+			let pending_messages = self.pending_messages.lock().unwrap();
+			let offline_messages = self.offline_messages.lock().unwrap();
+			for (peer_node_id, msgs) in pending_messages.iter().chain(offline_messages.iter()) {
+				if msgs.is_empty() { continue; }
+				for (priority, message) in msgs.iter_with_priority() {
+					pending.messages.entry(*peer_node_id).or_insert_with(Vec::new).push(message.clone());
+					pending.priorities.entry(*peer_node_id).or_insert_with(Vec::new).push(priority);
+				}
+			}
+			let mailbox = self.mailbox.lock().unwrap();
+			for (peer_node_id, msgs) in mailbox.messages.iter() {
+				pending.messages.entry(*peer_node_id).or_insert_with(Vec::new)
+					.extend(msgs.iter().map(|stored| stored.message.clone()));
+				pending.priorities.entry(*peer_node_id).or_insert_with(Vec::new)
+					.extend(msgs.iter().map(|_| OnionMessagePriority::Normal));
+			}
+		}
+		persister.persist(PENDING_ONION_MESSAGES_PERSISTENCE_KEY, &pending)
+	}
+
+	/// Queues onion messages previously persisted via [`Self::persist_pending_messages`], e.g.
+	/// after a restart. Messages for connected peers are released immediately, while those for
+	/// other peers are released once they connect.
+	///
+	/// Messages which don't fit into our outbound buffers are dropped, as are those for peers which
+	/// don't connect within 30 calls to [`OnionMessageHandler::timer_tick_occurred`].
+	///
+	/// [`OnionMessageHandler::timer_tick_occurred`]: crate::ln::msgs::OnionMessageHandler::timer_tick_occurred
+	pub fn restore_pending_messages(&self, pending: PendingOnionMessages) {
+		let config = *self.config.lock().unwrap();
+		let mut pending_messages = self.pending_messages.lock().unwrap();
+		let mut offline_messages = self.offline_messages.lock().unwrap();
+		let mut connection_needed = self.connection_needed.lock().unwrap();
+		let mut dropped = 0;
+		let mut priorities = pending.priorities;
+		for (peer_node_id, msgs) in pending.messages {
+			let msg_priorities = priorities.remove(&peer_node_id)
+				.filter(|msg_priorities| msg_priorities.len() == msgs.len())
+				.unwrap_or_else(|| vec![OnionMessagePriority::Normal; msgs.len()]);
+			let buffer = if pending_messages.contains_key(&peer_node_id) {
+				&mut *pending_messages
+			} else {
+				let ticks_remaining = connection_needed.entry(peer_node_id).or_insert(0);
+				*ticks_remaining = core::cmp::max(*ticks_remaining, RESTORED_MESSAGES_TIMEOUT_TICKS);
+				&mut *offline_messages
+			};
+			for (msg, priority) in msgs.into_iter().zip(msg_priorities.into_iter()) {
+				if outbound_buffer_full(&peer_node_id, buffer, &config) {
+					dropped += 1;
+					continue;
+				}
+				buffer.entry(peer_node_id).or_insert_with(PeerMessageQueue::default)
+					.push(priority, msg, false);
+			}
+			if let Some(peer_buf) = pending_messages.get(&peer_node_id) {
+				self.check_buffer_high_water(&peer_node_id, peer_buf, &config);
+			}
+		}
+		if dropped > 0 {
+			log_warn!(self.logger, "Dropped {} restored onion messages as our outbound buffers are full", dropped);
+		}
+	}
+
+	/// Sets the limits on our outbound buffers and what happens once they're reached.
+	///
+	/// Messages already queued are kept, even if they exceed new, lower limits.
+	pub fn set_config(&self, config: OnionMessengerConfig) {
+		*self.config.lock().unwrap() = config;
+	}
+
+	/// Sets the per-peer limits on the rate of inbound and outbound onion messages. By default,
+	/// no limits apply.
+	///
+	/// Changing the limits resets any tracked rates and violation counts.
+	pub fn set_rate_limits(&self, limits: OnionMessageRateLimits) {
+		let mut rate_limiter = self.rate_limiter.lock().unwrap();
+		rate_limiter.limits = limits;
+		rate_limiter.peers.clear();
+	}
+
+	/// Enables our mailbox for offline peers with the given configuration, or disables it if
+	/// `None`. The mailbox is disabled by default.
+	///
+	/// Disabling the mailbox drops any messages stored in it.
+	pub fn set_mailbox_config(&self, config: Option<OnionMessageMailboxConfig>) {
+		let mut mailbox = self.mailbox.lock().unwrap();
+		if config.is_none() {
+			*mailbox = Mailbox::default();
+		}
+		mailbox.config = config;
+	}
+
+	/// Enables the deduplication of custom onion messages we receive with the given configuration,
+	/// or disables it if `None`. Deduplication is disabled by default.
+	///
+	/// Changing the configuration forgets any messages already received.
+	pub fn set_dedup_config(&self, config: Option<OnionMessageDedupConfig>) {
+		*self.dedup_cache.lock().unwrap() = DedupCache { config, ..Default::default() };
+	}
+
+	/// Sets which onion messages we forward on behalf of other nodes. By default, we forward
+	/// messages to any peer.
+	pub fn set_forwarding_policy(&self, policy: OnionMessageForwardingPolicy) {
+		self.forwarding.lock().unwrap().policy = policy;
+	}
+
+	/// Sets how we deliver the custom onion messages we receive. By default, they are read and
+	/// handled by our [`CustomOnionMessageHandler`].
+	///
+	/// Fragmented messages which are partially received when this is changed are delivered
+	/// according to the setting at the time their last fragment arrives.
+	pub fn set_custom_message_delivery(&self, delivery: CustomOnionMessageDelivery) {
+		*self.custom_message_delivery.lock().unwrap() = delivery;
+	}
+
+	/// Sets the [`ChannelPeerLookup`] used to enforce [`OnionMessageForwardingPolicy::ChannelPeers`],
+	/// replacing any previously set. This is generally an `Arc` of your [`ChannelManager`].
+	///
+	/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+	pub fn set_channel_peer_lookup<C: ChannelPeerLookup + Send + Sync + 'static>(&self, channel_peers: C) {
+		self.forwarding.lock().unwrap().channel_peers = Some(Box::new(channel_peers));
+	}
+
+	/// Returns counters for the onion messages we've been asked to forward, including those we
+	/// dropped and why.
+	pub fn forwarding_stats(&self) -> OnionMessageForwardingStats {
+		self.forwarding.lock().unwrap().stats
+	}
+
+	/// Returns a snapshot of the onion messages we've sent, received, forwarded and dropped, as
+	/// well as those currently held in our mailbox.
+	pub fn stats(&self) -> OnionMessengerStats {
+		let forwarding_stats = self.forwarding_stats();
+		let pending_retries: usize = self.pending_retries.lock().unwrap().iter()
+			.map(|retry| retry.prepared.messages.len()).sum();
+		let counts = self.message_counts.lock().unwrap();
+		let mailbox = self.mailbox.lock().unwrap();
+		OnionMessengerStats {
+			sent: counts.sent,
+			received: counts.received,
+			forwarded: forwarding_stats.forwarded,
+			dropped: counts.dropped + forwarding_stats.dropped_by_policy
+				+ forwarding_stats.dropped_peer_disconnected + forwarding_stats.dropped_buffer_full
+				+ forwarding_stats.dropped_rate_limited,
+			intercepted_messages: mailbox.messages.values().map(|msgs| msgs.len()).sum(),
+			intercepted_bytes: mailbox.total_bytes,
+			pending_retries,
+		}
+	}
+
+	/// Returns the onion messages currently queued for sending to the given peer, or `None` if it
+	/// is not connected or doesn't support onion messages.
+	pub fn peer_buffer_occupancy(&self, peer_node_id: &PublicKey) -> Option<OnionMessageBufferOccupancy> {
+		let config = *self.config.lock().unwrap();
+		self.pending_messages.lock().unwrap().get(peer_node_id)
+			.map(|msgs| buffer_occupancy(msgs, &config))
+	}
+
+	/// Returns the onion messages currently queued for sending to each connected peer which
+	/// supports onion messages.
+	pub fn list_peer_buffer_occupancy(&self) -> Vec<(PublicKey, OnionMessageBufferOccupancy)> {
+		let config = *self.config.lock().unwrap();
+		self.pending_messages.lock().unwrap().iter()
+			.map(|(peer_node_id, msgs)| (*peer_node_id, buffer_occupancy(msgs, &config)))
+			.collect()
+	}
+
+	/// Accounts for messages to the given peer evicted from our outbound buffers, adding to any
+	/// [`Event::OnionMessagesEvicted`] for the peer which hasn't been processed yet.
+	fn messages_evicted(&self, peer_node_id: PublicKey, count: u64) {
+		if count == 0 { return; }
+		log_trace!(self.logger, "Evicted {} onion messages queued for peer {} as our outbound buffers are full",
+			count, peer_node_id);
+		self.message_counts.lock().unwrap().dropped += count;
+		let mut pending_events = self.pending_events.lock().unwrap();
+		let pending_eviction = pending_events.iter_mut().find_map(|event| match event {
+			Event::OnionMessagesEvicted { peer_node_id: evicted_peer, count: pending_count }
+				if *evicted_peer == peer_node_id => Some(pending_count),
+			_ => None,
+		});
+		match pending_eviction {
+			Some(pending_count) => *pending_count += count,
+			None => pending_events.push(Event::OnionMessagesEvicted { peer_node_id, count }),
+		}
+	}
+
+	/// Generates an [`Event::OnionMessageBufferHighWater`] if the messages queued for the given
+	/// peer crossed [`OnionMessengerConfig::buffer_high_water_mark_bytes`].
+	fn check_buffer_high_water(
+		&self, peer_node_id: &PublicKey, peer_buf: &PeerMessageQueue, config: &OnionMessengerConfig
+	) {
+		let high_water_mark = match config.buffer_high_water_mark_bytes { Some(bytes) => bytes, None => return };
+		let mut backpressured_peers = self.backpressured_peers.lock().unwrap();
+		// Avoid summing the queue's size on every send while the peer is backpressured anyway.
+		if backpressured_peers.contains(peer_node_id) { return }
+		let buffered_bytes = peer_buf.serialized_length();
+		if buffered_bytes < high_water_mark { return }
+		backpressured_peers.insert(*peer_node_id);
+		log_debug!(self.logger, "Onion message buffer for peer {} reached its high-water mark with {} bytes queued",
+			peer_node_id, buffered_bytes);
+		self.pending_events.lock().unwrap().push(Event::OnionMessageBufferHighWater {
+			peer_node_id: *peer_node_id, buffered_bytes: buffered_bytes as u64,
+		});
+	}
+
+	/// Generates an [`Event::OnionMessageBufferDrained`] if the messages queued for the given peer,
+	/// which previously crossed our high-water mark, drained to
+	/// [`OnionMessengerConfig::buffer_low_water_mark_bytes`]. A `None` `peer_buf` is treated as
+	/// empty.
+	fn check_buffer_drained(
+		&self, peer_node_id: &PublicKey, peer_buf: Option<&PeerMessageQueue>, config: &OnionMessengerConfig
+	) {
+		let mut backpressured_peers = self.backpressured_peers.lock().unwrap();
+		if !backpressured_peers.contains(peer_node_id) { return }
+		let buffered_bytes = peer_buf.map_or(0, |peer_buf| peer_buf.serialized_length());
+		if buffered_bytes > config.buffer_low_water_mark_bytes { return }
+		backpressured_peers.remove(peer_node_id);
+		log_debug!(self.logger, "Onion message buffer for peer {} drained to {} bytes", peer_node_id,
+			buffered_bytes);
+		self.pending_events.lock().unwrap().push(Event::OnionMessageBufferDrained {
+			peer_node_id: *peer_node_id, buffered_bytes: buffered_bytes as u64,
+		});
+	}
+
+	/// Sets the [`OnionMessageRateLimitObserver`] to notify whenever a peer exceeds our
+	/// [`OnionMessageRateLimits`], replacing any previously set.
+	pub fn set_rate_limit_observer<O: OnionMessageRateLimitObserver + Send + Sync + 'static>(&self, observer: O) {
+		self.rate_limiter.lock().unwrap().observer = Some(Arc::new(observer));
+	}
+
+	/// Sets the [`TimeSource`] our [`OnionMessageRateLimits`] are enforced and the round-trip times
+	/// of liveness probes are measured with, replacing the [`DefaultTimeSource`].
+	///
+	/// Without the `std` feature, time never passes for the [`DefaultTimeSource`], so a peer's
+	/// limit never refills once its burst is used up and all round-trip times are zero. `no-std`
+	/// users enforcing rate limits or measuring round-trip times should thus set a [`TimeSource`]
+	/// backed by a real clock.
+	///
+	/// As the limits tracked so far were measured against the previous [`TimeSource`], they are
+	/// reset.
+	pub fn set_time_source<TS: TimeSource + Send + Sync + 'static>(&self, time_source: TS) {
+		let mut rate_limiter = self.rate_limiter.lock().unwrap();
+		rate_limiter.time_source = Arc::new(time_source);
+		rate_limiter.peers.clear();
+	}
+
+	/// Gets the current time according to our [`TimeSource`].
+	fn now(&self) -> Duration {
+		self.rate_limiter.lock().unwrap().time_source.now()
+	}
+
+	/// Notifies our [`OnionMessageRateLimitObserver`] of any pending rate limit violations. Must be
+	/// called without holding any locks.
+	fn notify_rate_limit_observer(&self) {
+		let mut rate_limiter = self.rate_limiter.lock().unwrap();
+		if rate_limiter.pending_violations.is_empty() { return }
+		let violations = core::mem::take(&mut rate_limiter.pending_violations);
+		let observer = match &rate_limiter.observer { Some(observer) => Arc::clone(observer), None => return };
+		core::mem::drop(rate_limiter);
+		for ((peer_node_id, direction), violations) in violations {
+			observer.peer_rate_limited(&peer_node_id, direction, violations);
+		}
+	}
+
+	/// Send an onion message with contents `message` to the destination of `path`, including a
+	/// reply path back to us created via [`Self::create_reply_path`].
+	pub fn send_onion_message_with_reply<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>
+	) -> Result<(), SendError> {
+		let reply_path = self.create_reply_path()?;
+		self.send_onion_message(path, message, Some(reply_path))
+	}
+
+	/// Creates a [`BlindedPath`] back to us through one of our connected peers, as picked by our
+	/// [`MessageRouter::find_reply_path`], suitable for use as a reply path.
+	///
+	/// The path carries a random, authenticated identifier, so that messages received along it
+	/// are passed to [`CustomOnionMessageHandler::handle_custom_message_with_context`] as
+	/// [`OnionMessageReceivedVia::BlindedPath`].
+	pub fn create_reply_path(&self) -> Result<BlindedPath, SendError> {
+		let nonce = self.entropy_source.get_secure_random_bytes();
+		self.create_reply_path_with_id(Some(self.reply_path_id(&nonce[..16])))
+	}
+
+	/// Gets the `path_id` of a reply path created via [`Self::create_reply_path`], consisting of
+	/// the given nonce followed by a MAC of it under our `path_id_key`.
+	fn reply_path_id(&self, nonce: &[u8]) -> [u8; 32] {
+		let mut hmac = HmacEngine::<Sha256>::new(&self.path_id_key);
+		hmac.input(b"reply path");
+		hmac.input(nonce);
+		let mut path_id = [0; 32];
+		path_id[..16].copy_from_slice(nonce);
+		path_id[16..].copy_from_slice(&Hmac::from_engine(hmac).into_inner()[..16]);
+		path_id
+	}
+
+	/// Determines how a message received with the given `path_id` reached us, only reporting a
+	/// [`OnionMessageReceivedVia::BlindedPath`] if we created the path, i.e. it's the reply path
+	/// of a pending request or its `path_id` authenticates.
+	fn received_via(&self, path_id: Option<[u8; 32]>, is_response: bool) -> OnionMessageReceivedVia {
+		match path_id {
+			Some(path_id) if is_response || fixed_time_eq(&self.reply_path_id(&path_id[..16]), &path_id) =>
+				OnionMessageReceivedVia::BlindedPath { path_id },
+			Some(path_id) => {
+				log_trace!(self.logger, "Treating onion message received with unauthenticated path_id {:02x?} as direct", path_id);
+				OnionMessageReceivedVia::Direct
+			},
+			None => OnionMessageReceivedVia::Direct,
+		}
+	}
+
+	fn create_reply_path_with_id(&self, path_id: Option<[u8; 32]>) -> Result<BlindedPath, SendError> {
+		let our_node_id = self.node_signer.get_node_id(Recipient::Node)
+			.map_err(|()| SendError::GetNodeIdFailed)?;
+		let peers = self.pending_messages.lock().unwrap().keys().copied().collect();
+		let hops = self.message_router.find_reply_path(our_node_id, peers)
+			.map_err(|()| SendError::ReplyPathNotFound)?;
+		if hops.last() != Some(&our_node_id) { return Err(SendError::ReplyPathNotFound); }
+		BlindedPath::new_for_message_with_path_id(&hops, path_id, &*self.entropy_source, &self.secp_ctx)
+			.map_err(|()| SendError::ReplyPathNotFound)
+	}
+
+	/// Send a custom onion message `message` to the destination of `path` as a request expecting
+	/// a response, including a reply path back to us which identifies it by `request_id`.
+	///
+	/// If a response is received along the reply path before the request times out, it is passed
+	/// to [`CustomOnionMessageHandler::handle_custom_response`] along with `request_id`. Otherwise,
+	/// an [`Event::OnionMessageTimedOut`] is generated, to be handled via
+	/// [`EventsProvider::process_pending_events`]. The request times out on the first call to
+	/// [`OnionMessageHandler::timer_tick_occurred`] after `timeout_ticks` calls have passed, so it
+	/// is given at least `timeout_ticks` full tick intervals to be responded to.
+	///
+	/// Note that [`PeerManager::timer_tick_occurred`] calls
+	/// [`OnionMessageHandler::timer_tick_occurred`], so when used with a [`PeerManager`] each
+	/// tick is roughly ten seconds.
+	///
+	/// [`PeerManager`]: crate::ln::peer_handler::PeerManager
+	/// [`PeerManager::timer_tick_occurred`]: crate::ln::peer_handler::PeerManager::timer_tick_occurred
+	pub fn send_onion_message_request<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: T, request_id: OnionMessageRequestId,
+		timeout_ticks: u16
+	) -> Result<(), SendError> {
+		let path_id = self.request_path_id(&request_id);
+		let mut pending_requests = self.pending_requests.lock().unwrap();
+		if pending_requests.contains_key(&path_id) {
+			return Err(SendError::DuplicateRequestId);
+		}
+		let reply_path = self.create_reply_path_with_id(Some(path_id))?;
+		self.send_onion_message(path, OnionMessageContents::Custom(message), Some(reply_path))?;
+		pending_requests.insert(path_id, (request_id, timeout_ticks));
+		Ok(())
+	}
+
+	/// Gets the ids of all requests sent via [`Self::send_onion_message_request`] which are
+	/// awaiting a response.
+	pub fn list_pending_requests(&self) -> Vec<OnionMessageRequestId> {
+		self.pending_requests.lock().unwrap().values().map(|(request_id, _)| *request_id).collect()
+	}
+
+	/// Gets the `path_id` of the reply path for the request with the given id, which can only be
+	/// computed with our `path_id_key`.
+	fn request_path_id(&self, request_id: &OnionMessageRequestId) -> [u8; 32] {
+		let mut hmac = HmacEngine::<Sha256>::new(&self.path_id_key);
+		hmac.input(b"request");
+		hmac.input(&request_id.0);
+		Hmac::from_engine(hmac).into_inner()
+	}
+
+	/// Send an onion message with contents `message` to the destination of `path`, requesting the
+	/// recipient acknowledge it with a signed receipt, identified by `id`.
+	///
+	/// A reply path back to us is included for the receipt, which may also be used by the
+	/// recipient to respond to the message. Recipients which support receipts send one
+	/// automatically once they've received the message, upon which an
+	/// [`Event::OnionMessageDelivered`] is generated, to be handled via
+	/// [`EventsProvider::process_pending_events`]. If the destination of `path` is an unblinded
+	/// node, the receipt must be signed by it. Receipts for messages sent to a blinded path are
+	/// instead signed with a key the recipient derives for the path, so don't identify it.
+	///
+	/// If no receipt is received within `timeout_ticks` calls to
+	/// [`OnionMessageHandler::timer_tick_occurred`], we stop waiting for it and generate an
+	/// [`Event::OnionMessageReceiptTimedOut`], at which point the message may be resent. Note that
+	/// recipients which don't support receipts will never send one.
+	pub fn send_onion_message_with_receipt<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>, id: OnionMessageDeliveryId,
+		timeout_ticks: u16
+	) -> Result<(), SendError> {
+		self.send_with_receipt(path, message, id, timeout_ticks, false)
+	}
+
+	/// Sends a probe along `path`, returning an id with which its outcome is reported.
+	///
+	/// The probe is an empty message requesting a receipt, which recipients that support receipts
+	/// acknowledge without passing it to any handler. If a valid receipt is received, an
+	/// [`Event::OnionMessageProbeSuccessful`] is generated, otherwise an
+	/// [`Event::OnionMessageProbeFailed`] is generated after
+	/// [`OnionMessengerConfig::probe_timeout_ticks`] calls to
+	/// [`OnionMessageHandler::timer_tick_occurred`]. Both are to be handled via
+	/// [`EventsProvider::process_pending_events`].
+	///
+	/// This allows a path to be validated before relying on it, e.g. before starting a negotiation
+	/// with a counterparty over it. Note that a successful probe doesn't guarantee later messages
+	/// along the path will be delivered, nor does a failed one tell which hop failed.
+	///
+	/// Probe receipts are signed with a one-time key rather than the recipient's node key, so that
+	/// probing a blinded path doesn't reveal who it leads to. As the probe's receipt nonce can only
+	/// be read by its recipient, a receipt still proves the probe was delivered.
+	pub fn probe_path(&self, path: OnionMessagePath) -> Result<OnionMessageDeliveryId, SendError> {
+		let id = OnionMessageDeliveryId(self.entropy_source.get_secure_random_bytes());
+		let timeout_ticks = self.config.lock().unwrap().probe_timeout_ticks;
+		let probe = OnionMessageContents::Custom(OnionMessageProbe {});
+		self.send_with_receipt(path, probe, id, timeout_ticks, true)?;
+		Ok(id)
+	}
+
+	fn send_with_receipt<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>, id: OnionMessageDeliveryId,
+		timeout_ticks: u16, is_probe: bool
+	) -> Result<(), SendError> {
+		let mut pending_receipts = self.pending_receipts.lock().unwrap();
+		if pending_receipts.contains_key(&id) {
+			return Err(SendError::DuplicateDeliveryId);
+		}
+		let recipient = match path.destination {
+			Destination::Node(node_id) => Some(node_id),
+			Destination::BlindedPath(_) => None,
+		};
+		let nonce = self.entropy_source.get_secure_random_bytes();
+		let reply_path = self.create_reply_path_with_id(Some(id.0))?;
+		let prepared = if is_probe {
+			self.prepare_onion_message_unchecked(path, message, Some(reply_path), Some(nonce))?
 		} else {
-			match destination {
-				Destination::Node(pk) => (pk, PublicKey::from_secret_key(&self.secp_ctx, &blinding_secret)),
-				Destination::BlindedPath(BlindedPath { introduction_node_id, blinding_point, .. }) =>
-					(introduction_node_id, blinding_point),
+			self.prepare_onion_message(path, message, Some(reply_path), Some(nonce))?
+		};
+		{
+			let config = *self.config.lock().unwrap();
+			let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
+			self.enqueue_or_retry_onion_message(
+				prepared, OnionMessagePriority::Normal, &config, &mut pending_per_peer_msgs)?;
+		}
+		pending_receipts.insert(id, PendingReceipt { nonce, recipient, ticks_remaining: timeout_ticks, is_probe });
+		Ok(())
+	}
+
+	/// Sends a liveness probe along `path`, returning an id with which its outcome is reported.
+	///
+	/// Unlike a probe sent via [`Self::probe_path`], which only proves a message was delivered, a
+	/// liveness probe is answered by its recipient over a reply path we include, proving the
+	/// destination is online and able to reach us, and measuring the round trip. Recipients answer
+	/// it automatically without passing it to any handler. This is useful to check that a
+	/// counterparty is reachable before starting a time-sensitive exchange with it, e.g. when it is
+	/// only reachable over a blinded path via its LSP.
+	///
+	/// Once the answer is received, or after [`OnionMessengerConfig::probe_timeout_ticks`] calls to
+	/// [`OnionMessageHandler::timer_tick_occurred`] without one, an
+	/// [`Event::LivenessProbeCompleted`] is generated, to be handled via
+	/// [`EventsProvider::process_pending_events`]. The outcome is also recorded in the
+	/// [`LivenessStats`] of the destination, see [`Self::liveness_stats`].
+	///
+	/// Answers are matched by the `path_id` of the reply path, which is computed from the probe's id
+	/// with a key only we know and can only be read by us, so they can't be forged by nodes which
+	/// did not receive the probe.
+	pub fn send_liveness_probe(&self, path: OnionMessagePath) -> Result<OnionMessageDeliveryId, SendError> {
+		let id = OnionMessageDeliveryId(self.entropy_source.get_secure_random_bytes());
+		let path_id = self.liveness_probe_path_id(&id);
+		let destination = path.destination.clone();
+		let mut liveness = self.liveness.lock().unwrap();
+		let reply_path = self.create_reply_path_with_id(Some(path_id))?;
+		let ping = OnionMessageContents::Custom(LivenessPing {});
+		let prepared = self.prepare_onion_message_unchecked(path, ping, Some(reply_path), None)?;
+		let config = *self.config.lock().unwrap();
+		{
+			let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
+			self.enqueue_or_retry_onion_message(
+				prepared, OnionMessagePriority::Normal, &config, &mut pending_per_peer_msgs)?;
+		}
+		liveness.record_probe_sent(&destination);
+		liveness.pending.insert(path_id, PendingLivenessProbe {
+			id, destination, sent_at: self.now(), ticks_remaining: config.probe_timeout_ticks,
+		});
+		Ok(id)
+	}
+
+	/// Gets the statistics about the liveness probes sent to `destination` via
+	/// [`Self::send_liveness_probe`], if any were.
+	///
+	/// Statistics are not persisted, and are only kept for a limited number of destinations,
+	/// dropping those probed least recently first.
+	pub fn liveness_stats(&self, destination: &Destination) -> Option<LivenessStats> {
+		self.liveness.lock().unwrap().stats.get(destination).map(|(stats, _)| stats.clone())
+	}
+
+	/// Gets the `path_id` of the reply path for the liveness probe with the given id, which can only
+	/// be computed with our `path_id_key`.
+	fn liveness_probe_path_id(&self, id: &OnionMessageDeliveryId) -> [u8; 32] {
+		let mut hmac = HmacEngine::<Sha256>::new(&self.path_id_key);
+		hmac.input(b"liveness probe");
+		hmac.input(&id.0);
+		Hmac::from_engine(hmac).into_inner()
+	}
+
+	/// Gets the ids of all messages sent via [`Self::send_onion_message_with_receipt`] which are
+	/// awaiting a receipt.
+	pub fn list_pending_receipts(&self) -> Vec<OnionMessageDeliveryId> {
+		self.pending_receipts.lock().unwrap().iter()
+			.filter(|(_, pending)| !pending.is_probe)
+			.map(|(id, _)| *id)
+			.collect()
+	}
+
+	/// Send an onion message with contents `message` to the destination of `path`.
+	///
+	/// Messages which don't fit in a single onion message packet are transparently split into
+	/// fragments, each sent along `path`, which are reassembled by the recipient. Note that this
+	/// requires the recipient to be known to support fragmentation, as determined by
+	/// [`MessageRouter::supports_fragmentation`].
+	///
+	/// See [`OnionMessenger`] for example usage.
+	pub fn send_onion_message<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>,
+		reply_path: Option<BlindedPath>
+	) -> Result<(), SendError> {
+		self.send_onion_message_with_priority(path, message, reply_path, OnionMessagePriority::Normal)
+	}
+
+	/// Send an onion message with contents `message` to the destination of `path`, as with
+	/// [`Self::send_onion_message`], but released to the first hop ahead of or behind other queued
+	/// messages according to `priority`.
+	pub fn send_onion_message_with_priority<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>,
+		reply_path: Option<BlindedPath>, priority: OnionMessagePriority
+	) -> Result<(), SendError> {
+		let prepared = self.prepare_onion_message(path, message, reply_path, None)?;
+		let config = *self.config.lock().unwrap();
+		let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
+		self.enqueue_or_retry_onion_message(prepared, priority, &config, &mut pending_per_peer_msgs)
+	}
+
+	/// Send a batch of onion messages, each with contents, path and reply path as passed to
+	/// [`Self::send_onion_message`], returning the result of sending each in order.
+	///
+	/// All messages are validated and have their packets constructed before any are queued, after
+	/// which they're queued together, without other messages being queued in between or any
+	/// being released to our peers before the whole batch is queued. This is useful for
+	/// broadcasting an update, such as a new quote, to many counterparties at once.
+	pub fn send_onion_messages<T: CustomOnionMessageContents>(
+		&self, messages: Vec<(OnionMessagePath, OnionMessageContents<T>, Option<BlindedPath>)>
+	) -> Vec<Result<(), SendError>> {
+		let prepared_messages: Vec<_> = messages.into_iter()
+			.map(|(path, message, reply_path)| self.prepare_onion_message(path, message, reply_path, None))
+			.collect();
+		let config = *self.config.lock().unwrap();
+		let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
+		let mut results = Vec::with_capacity(prepared_messages.len());
+		for prepared in prepared_messages {
+			results.push(match prepared {
+				Ok(prepared) => self.enqueue_or_retry_onion_message(
+					prepared, OnionMessagePriority::Normal, &config, &mut pending_per_peer_msgs),
+				Err(e) => Err(e),
+			});
+		}
+		results
+	}
+
+	/// Send copies of a custom onion message `message` to `destination` over up to `num_paths`
+	/// paths which share no intermediate nodes, as found by
+	/// [`MessageRouter::find_disjoint_paths`], returning the number of copies queued.
+	///
+	/// This improves the chance a critical message, such as a settlement notification, is
+	/// delivered in spite of unreliable or misbehaving intermediate nodes. As with
+	/// [`Self::send_onion_messages`], all copies are queued together, and the message is considered
+	/// sent if any copy is queued. Recipients may receive several copies, which they should
+	/// discard after the first, e.g. by configuring [`OnionMessenger::set_dedup_config`], which
+	/// recognizes copies as they share the same contents and `reply_path`.
+	pub fn send_onion_message_redundant<T: CustomOnionMessageContents + Clone>(
+		&self, destination: Destination, message: T, reply_path: Option<BlindedPath>, num_paths: usize
+	) -> Result<usize, SendError> {
+		if num_paths == 0 { return Ok(0); }
+		let our_node_id = self.node_signer.get_node_id(Recipient::Node)
+			.map_err(|()| SendError::GetNodeIdFailed)?;
+		let peers = self.pending_messages.lock().unwrap().keys().copied().collect();
+		let paths = self.message_router.find_disjoint_paths(our_node_id, peers, destination, num_paths)
+			.map_err(|()| SendError::PathNotFound)?;
+		let prepared_messages: Vec<_> = paths.into_iter().take(num_paths)
+			.map(|path| self.prepare_onion_message(
+				path, OnionMessageContents::Custom(message.clone()), reply_path.clone(), None))
+			.collect();
+
+		let config = *self.config.lock().unwrap();
+		let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
+		let mut queued = 0;
+		let mut first_err = None;
+		for prepared in prepared_messages {
+			let res = prepared.and_then(|prepared| self.enqueue_or_retry_onion_message(
+				prepared, OnionMessagePriority::Normal, &config, &mut pending_per_peer_msgs));
+			match res {
+				Ok(()) => queued += 1,
+				Err(e) => if first_err.is_none() { first_err = Some(e) },
+			}
+		}
+		match first_err {
+			Some(e) if queued == 0 => Err(e),
+			_ => Ok(queued),
+		}
+	}
+
+	/// Validates an onion message with contents `message` to the destination of `path` and
+	/// constructs its packets, splitting it into fragments if it doesn't fit in a single one.
+	fn prepare_onion_message<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>,
+		reply_path: Option<BlindedPath>, receipt_nonce: Option<[u8; 32]>
+	) -> Result<PreparedOnionMessage, SendError> {
+		check_custom_tlv_type(&message)?;
+		self.prepare_onion_message_unchecked(path, message, reply_path, receipt_nonce)
+	}
+
+	/// Prepares an onion message as with [`Self::prepare_onion_message`], but allowing custom
+	/// messages of reserved TLV types, for the fragments, receipts and probes we send ourselves.
+	fn prepare_onion_message_unchecked<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>,
+		reply_path: Option<BlindedPath>, receipt_nonce: Option<[u8; 32]>
+	) -> Result<PreparedOnionMessage, SendError> {
+		match create_onion_message_or_oversized(
+			&self.entropy_source, &self.node_signer, &self.secp_ctx, path, message, reply_path,
+			receipt_nonce
+		)? {
+			CreatedOnionMessage::Packet { first_node_id, first_node_addresses, message } => {
+				Ok(PreparedOnionMessage { first_node_id, first_node_addresses, messages: vec![message] })
+			},
+			CreatedOnionMessage::TooBig { path, message, reply_path, receipt_nonce } => {
+				if !self.message_router.supports_fragmentation(&path.destination) {
+					return Err(SendError::TooBigPacket);
+				}
+				self.prepare_fragmented_onion_message(path, message, reply_path, receipt_nonce)
+			},
+		}
+	}
+
+	/// Queues a [`PreparedOnionMessage`] as with [`Self::enqueue_onion_message`], scheduling a retry
+	/// instead of failing if it can't be queued due to a transient condition and
+	/// [`OnionMessengerConfig::max_send_retries`] allows.
+	fn enqueue_or_retry_onion_message(
+		&self, prepared: PreparedOnionMessage, priority: OnionMessagePriority,
+		config: &OnionMessengerConfig, pending_per_peer_msgs: &mut HashMap<PublicKey, PeerMessageQueue>
+	) -> Result<(), SendError> {
+		if config.max_send_retries == 0 {
+			return self.enqueue_onion_message(prepared, priority, config, pending_per_peer_msgs);
+		}
+		let retry = prepared.clone();
+		match self.enqueue_onion_message(prepared, priority, config, pending_per_peer_msgs) {
+			Err(e @ SendError::BufferFull) | Err(e @ SendError::InvalidFirstHop) => {
+				if self.schedule_retry(retry, priority, 0, config) { Ok(()) } else { Err(e) }
+			},
+			res => res,
+		}
+	}
+
+	/// Schedules a retry of a message which failed to send after `attempts` retries, returning
+	/// whether one was scheduled.
+	fn schedule_retry(
+		&self, prepared: PreparedOnionMessage, priority: OnionMessagePriority, attempts: u8,
+		config: &OnionMessengerConfig
+	) -> bool {
+		if attempts >= config.max_send_retries { return false }
+		let mut pending_retries = self.pending_retries.lock().unwrap();
+		if pending_retries.len() >= MAX_PENDING_RETRIES { return false }
+		let ticks_remaining = config.retry_backoff_ticks
+			.checked_shl(attempts as u32).unwrap_or(u16::max_value());
+		log_trace!(self.logger, "Retrying onion message to {} in {} timer ticks", prepared.first_node_id,
+			ticks_remaining);
+		pending_retries.push(PendingRetry { prepared, priority, attempts, ticks_remaining });
+		true
+	}
+
+	/// Queues the packets of a [`PreparedOnionMessage`] for sending to the first node of its path,
+	/// or until we connect to it if it isn't one of our peers.
+	fn enqueue_onion_message(
+		&self, prepared: PreparedOnionMessage, priority: OnionMessagePriority,
+		config: &OnionMessengerConfig, pending_per_peer_msgs: &mut HashMap<PublicKey, PeerMessageQueue>
+	) -> Result<(), SendError> {
+		let PreparedOnionMessage { first_node_id: introduction_node_id, first_node_addresses, messages } = prepared;
+		// The fragments of a message are only useful together, so either all are queued or none.
+		let fragmented = messages.len() > 1;
+		if fragmented {
+			let fragments_len = messages.iter().map(|om| om.serialized_length()).sum();
+			if pending_per_peer_msgs.contains_key(&introduction_node_id) {
+				if !outbound_buffer_has_room(&introduction_node_id, pending_per_peer_msgs, config, fragments_len) {
+					return Err(SendError::BufferFull)
+				}
+				let fragment_count = messages.len() as u64;
+				if !self.rate_limiter.lock().unwrap().allow_many(&introduction_node_id, RateLimitDirection::Outbound, fragment_count) {
+					return Err(SendError::RateLimited);
+				}
+			} else {
+				if first_node_addresses.is_none() { return Err(SendError::InvalidFirstHop) }
+				let offline_messages = self.offline_messages.lock().unwrap();
+				if !outbound_buffer_has_room(&introduction_node_id, &offline_messages, config, fragments_len) {
+					return Err(SendError::BufferFull)
+				}
+			}
+		}
+		let peer_connected = pending_per_peer_msgs.contains_key(&introduction_node_id);
+		let mut queued = 0;
+		let res = self.enqueue_packets(
+			&introduction_node_id, first_node_addresses, messages, fragmented, priority, config,
+			pending_per_peer_msgs, &mut queued
+		);
+		if res.is_err() && queued != 0 {
+			// The checks above should prevent failing part way through a fragmented message, but
+			// never leave some of its fragments queued as they'd be useless to the recipient.
+			debug_assert!(false, "Failed to queue all fragments of an onion message");
+			if peer_connected {
+				if let Some(peer_buf) = pending_per_peer_msgs.get_mut(&introduction_node_id) {
+					peer_buf.truncate_back(priority, queued);
+				}
+			} else if let Some(peer_buf) = self.offline_messages.lock().unwrap().get_mut(&introduction_node_id) {
+				peer_buf.truncate_back(priority, queued);
+			}
+			let mut message_counts = self.message_counts.lock().unwrap();
+			message_counts.sent = message_counts.sent.saturating_sub(queued as u64);
+		}
+		res
+	}
+
+	/// Queues each of `messages` in turn as part of [`Self::enqueue_onion_message`], counting
+	/// those queued in `queued`.
+	fn enqueue_packets(
+		&self, introduction_node_id: &PublicKey, first_node_addresses: Option<Vec<msgs::NetAddress>>,
+		messages: Vec<msgs::OnionMessage>, fragmented: bool, priority: OnionMessagePriority,
+		config: &OnionMessengerConfig, pending_per_peer_msgs: &mut HashMap<PublicKey, PeerMessageQueue>,
+		queued: &mut usize
+	) -> Result<(), SendError> {
+		let introduction_node_id = *introduction_node_id;
+		for message in messages {
+			let buffer_full = outbound_buffer_full(&introduction_node_id, pending_per_peer_msgs, config);
+			let peer_connected = pending_per_peer_msgs.contains_key(&introduction_node_id);
+			if buffer_full && (config.eviction_policy == OnionMessageEvictionPolicy::RejectNew || !peer_connected) {
+				return Err(SendError::BufferFull)
+			}
+			if !peer_connected {
+				let addresses = first_node_addresses.clone().ok_or(SendError::InvalidFirstHop)?;
+				let mut offline_messages = self.offline_messages.lock().unwrap();
+				if outbound_buffer_full(&introduction_node_id, &offline_messages, config) { return Err(SendError::BufferFull) }
+				offline_messages.entry(introduction_node_id).or_insert_with(PeerMessageQueue::default)
+					.push(priority, message, true);
+				*queued += 1;
+				self.message_counts.lock().unwrap().sent += 1;
+				let mut connection_needed = self.connection_needed.lock().unwrap();
+				if let hash_map::Entry::Vacant(e) = connection_needed.entry(introduction_node_id) {
+					e.insert(CONNECTION_NEEDED_TIMEOUT_TICKS);
+					log_trace!(self.logger, "Queueing onion message until we connect to {}", introduction_node_id);
+					self.pending_events.lock().unwrap().push(Event::ConnectionNeeded {
+						node_id: introduction_node_id, addresses,
+					});
+				}
+				continue
+			}
+
+			if !fragmented && !self.rate_limiter.lock().unwrap().allow(&introduction_node_id, RateLimitDirection::Outbound) {
+				return Err(SendError::RateLimited);
+			}
+			if buffer_full {
+				let evicted = evict_oldest(&introduction_node_id, pending_per_peer_msgs, config)
+					.ok_or(SendError::BufferFull)?;
+				self.messages_evicted(introduction_node_id, evicted);
+			}
+			let peer_buf = pending_per_peer_msgs.entry(introduction_node_id)
+				.or_insert_with(PeerMessageQueue::default);
+			peer_buf.push(priority, message, true);
+			*queued += 1;
+			self.check_buffer_high_water(&introduction_node_id, peer_buf, config);
+			self.message_counts.lock().unwrap().sent += 1;
+		}
+		Ok(())
+	}
+
+	/// Splits `message` into [`MessageFragment`]s small enough to each fit in an onion message
+	/// packet along `path` and prepares them, including `reply_path` and `receipt_nonce` with the
+	/// first fragment only.
+	fn prepare_fragmented_onion_message<T: CustomOnionMessageContents>(
+		&self, path: OnionMessagePath, message: OnionMessageContents<T>,
+		reply_path: Option<BlindedPath>, receipt_nonce: Option<[u8; 32]>
+	) -> Result<PreparedOnionMessage, SendError> {
+		// Fragments are always sized to fit, so never try to fragment one further.
+		if message.tlv_type() == FRAGMENT_TLV_TYPE { return Err(SendError::TooBigPacket) }
+
+		let message_id = self.entropy_source.get_secure_random_bytes();
+		let fragment_overhead = {
+			let session_priv = SecretKey::from_slice(&self.entropy_source.get_secure_random_bytes()[..])
+				.expect("RNG is busted");
+			let empty_fragment = MessageFragment { message_id, index: 0, count: 0, data: Vec::new() };
+			let (payloads, _) = packet_payloads_and_keys(
+				&self.secp_ctx, &path.intermediate_nodes, path.destination.clone(),
+				OnionMessageContents::Custom(empty_fragment), reply_path.clone(), receipt_nonce, &session_priv
+			).map_err(|e| SendError::Secp256k1(e))?;
+			onion_utils::payloads_serialized_length(&payloads)
+		};
+		let max_data_len = BIG_PACKET_HOP_DATA_LEN.saturating_sub(fragment_overhead + FRAGMENT_ENCODING_SLACK);
+		if max_data_len == 0 { return Err(SendError::TooBigPacket) }
+
+		let message_value = message.encode();
+		let mut message_bytes = BigSize(message.tlv_type()).encode();
+		message_bytes.extend_from_slice(&BigSize(message_value.len() as u64).encode());
+		message_bytes.extend_from_slice(&message_value);
+
+		let fragment_count = (message_bytes.len() + max_data_len - 1) / max_data_len;
+		if fragment_count > MAX_FRAGMENTS_PER_MESSAGE as usize { return Err(SendError::TooBigPacket) }
+
+		log_trace!(self.logger, "Sending onion message of {} bytes as {} fragments", message_bytes.len(), fragment_count);
+		let mut prepared_fragments: Option<PreparedOnionMessage> = None;
+		for (index, data) in message_bytes.chunks(max_data_len).enumerate() {
+			let fragment = MessageFragment {
+				message_id,
+				index: index as u16,
+				count: fragment_count as u16,
+				data: data.to_vec(),
+			};
+			let (reply_path, receipt_nonce) = if index == 0 { (reply_path.clone(), receipt_nonce) } else { (None, None) };
+			let prepared = self.prepare_onion_message_unchecked(
+				path.clone(), OnionMessageContents::Custom(fragment), reply_path, receipt_nonce)?;
+			match prepared_fragments {
+				Some(ref mut prepared_fragments) => prepared_fragments.messages.extend(prepared.messages),
+				None => prepared_fragments = Some(prepared),
+			}
+		}
+		prepared_fragments.ok_or(SendError::TooBigPacket)
+	}
+
+	/// Stores a received [`MessageFragment`], returning the complete message's TLV record along
+	/// with its reply path and receipt nonce once all fragments have been received.
+	fn reassemble_fragment(
+		&self, peer_node_id: &PublicKey, fragment: MessageFragment, path_id: Option<[u8; 32]>,
+		reply_path: Option<BlindedPath>, receipt_nonce: Option<[u8; 32]>
+	) -> Option<(Vec<u8>, Option<BlindedPath>, Option<[u8; 32]>)> {
+		let MessageFragment { message_id, index, count, data } = fragment;
+		if count == 0 || count > MAX_FRAGMENTS_PER_MESSAGE || index >= count {
+			log_trace!(self.logger, "Dropping onion message fragment {} of {}: invalid fragment count", index, count);
+			return None;
+		}
+
+		let mut pending_reassemblies = self.pending_reassemblies.lock().unwrap();
+		if !pending_reassemblies.contains_key(&message_id) {
+			if pending_reassemblies.len() >= MAX_PENDING_REASSEMBLIES {
+				log_trace!(self.logger, "Dropping onion message fragment: too many messages being reassembled");
+				return None;
+			}
+			let peer_reassemblies = pending_reassemblies.values()
+				.filter(|partial_message| partial_message.peer_node_id == *peer_node_id)
+				.count();
+			if peer_reassemblies >= MAX_PENDING_REASSEMBLIES_PER_PEER {
+				log_trace!(self.logger, "Dropping onion message fragment: too many messages from peer {} being reassembled", peer_node_id);
+				return None;
 			}
+		}
+		let pending_bytes: usize = pending_reassemblies.values()
+			.map(|partial_message| partial_message.received_bytes)
+			.sum();
+		if pending_bytes.saturating_add(data.len()) > MAX_PENDING_REASSEMBLY_BYTES {
+			log_trace!(self.logger, "Dropping partially received onion message: too many bytes being reassembled");
+			pending_reassemblies.remove(&message_id);
+			return None;
+		}
+		let partial_message = pending_reassemblies.entry(message_id).or_insert_with(|| PartialMessage {
+			fragments: vec![None; count as usize],
+			received: 0,
+			received_bytes: 0,
+			peer_node_id: *peer_node_id,
+			path_id,
+			reply_path: None,
+			receipt_nonce: None,
+			ticks_remaining: REASSEMBLY_TIMEOUT_TICKS,
+		});
+		if partial_message.fragments.len() != count as usize || partial_message.path_id != path_id {
+			log_trace!(self.logger, "Dropping onion message fragment which doesn't match prior fragments");
+			return None;
+		}
+		if partial_message.fragments[index as usize].is_some() {
+			log_trace!(self.logger, "Dropping duplicate onion message fragment {} of {}", index, count);
+			return None;
+		}
+		partial_message.received_bytes += data.len();
+		partial_message.fragments[index as usize] = Some(data);
+		partial_message.received += 1;
+		if index == 0 {
+			partial_message.reply_path = reply_path;
+			partial_message.receipt_nonce = receipt_nonce;
+		}
+		if partial_message.received < count { return None }
+
+		let partial_message = pending_reassemblies.remove(&message_id).unwrap();
+		let message_bytes = partial_message.fragments.into_iter().flatten().flatten().collect();
+		Some((message_bytes, partial_message.reply_path, partial_message.receipt_nonce))
+	}
+
+	/// Reads the message from the TLV record of a reassembled fragmented message.
+	fn read_reassembled_message(
+		&self, message_bytes: &[u8]
+	) -> Result<OnionMessageContents<ReceivedCustomMessage<<<CMH as Deref>::Target as CustomOnionMessageHandler>::CustomMessage>>, msgs::DecodeError> {
+		let mut reader = message_bytes;
+		let tlv_type: BigSize = Readable::read(&mut reader)?;
+		let tlv_len: BigSize = Readable::read(&mut reader)?;
+		if tlv_type.0 < 64 || tlv_type.0 == FRAGMENT_TLV_TYPE || tlv_type.0 == RECEIPT_TLV_TYPE ||
+			tlv_type.0 == PROBE_TLV_TYPE || tlv_type.0 == LIVENESS_PING_TLV_TYPE ||
+			tlv_type.0 == LIVENESS_PONG_TLV_TYPE
+		{
+			return Err(msgs::DecodeError::InvalidValue)
+		}
+
+		let mut value_reader = FixedLengthReader::new(&mut reader, tlv_len.0);
+		let message = if OffersMessage::is_known_type(tlv_type.0) {
+			OnionMessageContents::Offers(OffersMessage::read(&mut value_reader, (tlv_type.0, &*self.logger))?)
+		} else if DlcMessage::is_known_type(tlv_type.0) {
+			OnionMessageContents::Dlc(DlcMessage::read(&mut value_reader, tlv_type.0)?)
+		} else {
+			match self.message_reading_handler().read_custom_message(tlv_type.0, &mut value_reader)? {
+				Some(msg) => OnionMessageContents::Custom(msg),
+				None => return Err(msgs::DecodeError::UnknownRequiredFeature),
+			}
+		};
+		value_reader.eat_remaining()?;
+		if !reader.is_empty() { return Err(msgs::DecodeError::InvalidValue) }
+		Ok(message)
+	}
+
+	/// Returns the handler to read received onion message payloads with, which reads custom
+	/// messages according to our [`CustomOnionMessageDelivery`].
+	fn message_reading_handler(&self) -> InternalMessageReadingHandler<<CMH as Deref>::Target> {
+		InternalMessageReadingHandler {
+			handler: &*self.custom_handler,
+			read_raw: *self.custom_message_delivery.lock().unwrap() == CustomOnionMessageDelivery::Events,
+		}
+	}
+
+	/// Returns the id of the request the message received with `path_id` responds to, if any,
+	/// marking the request as responded to.
+	fn take_pending_request(&self, path_id: Option<[u8; 32]>) -> Option<OnionMessageRequestId> {
+		path_id.and_then(|path_id| self.pending_requests.lock().unwrap().remove(&path_id))
+			.map(|(request_id, _)| request_id)
+	}
+
+	/// Passes a message received along a blinded path to us to the appropriate handler, or queues
+	/// it as an [`Event::CustomOnionMessageReceived`], sending any response along the
+	/// `reply_path`.
+	fn handle_received_message(
+		&self, message: OnionMessageContents<ReceivedCustomMessage<<<CMH as Deref>::Target as CustomOnionMessageHandler>::CustomMessage>>,
+		path_id: Option<[u8; 32]>, reply_path: Option<BlindedPath>
+	) {
+		let response = match message {
+			OnionMessageContents::Offers(msg) => {
+				self.offers_handler.handle_message(msg)
+					.map(|msg| OnionMessageContents::Offers(msg))
+			},
+			OnionMessageContents::Dlc(msg) => {
+				let received_via = self.received_via(path_id, false);
+				let responder = reply_path.clone().map(|reply_path| Responder { reply_path, received_via });
+				self.dlc_handler.handle_message(msg, received_via, responder)
+					.map(|msg| OnionMessageContents::Dlc(msg))
+			},
+			OnionMessageContents::Custom(ReceivedCustomMessage::Raw { tlv_type, data }) => {
+				let mut pending_custom_message_events = self.pending_custom_message_events.lock().unwrap();
+				let (pending_count, pending_bytes) = &mut *pending_custom_message_events;
+				if *pending_count >= MAX_PENDING_CUSTOM_MESSAGE_EVENTS ||
+					pending_bytes.saturating_add(data.len()) > MAX_PENDING_CUSTOM_MESSAGE_EVENT_BYTES
+				{
+					log_debug!(self.logger, "Dropping custom onion message of type {} as too many are awaiting processing", tlv_type);
+					self.message_counts.lock().unwrap().dropped += 1;
+					return;
+				}
+				*pending_count += 1;
+				*pending_bytes += data.len();
+				let request_id = self.take_pending_request(path_id);
+				let received_via = self.received_via(path_id, request_id.is_some());
+				log_trace!(self.logger, "Queueing custom onion message of type {} as an event", tlv_type);
+				self.pending_events.lock().unwrap().push(Event::CustomOnionMessageReceived {
+					tlv_type, data, received_via,
+					request_id, reply_path: reply_path.clone(),
+				});
+				None
+			},
+			OnionMessageContents::Custom(ReceivedCustomMessage::Fragment(_)) |
+			OnionMessageContents::Custom(ReceivedCustomMessage::Receipt(_)) |
+			OnionMessageContents::Custom(ReceivedCustomMessage::Probe(_)) |
+			OnionMessageContents::Custom(ReceivedCustomMessage::LivenessPing(_)) |
+			OnionMessageContents::Custom(ReceivedCustomMessage::LivenessPong(_)) => {
+				debug_assert!(false, "Fragments, receipts, probes and liveness messages must be handled before reaching here");
+				None
+			},
+			OnionMessageContents::Custom(ReceivedCustomMessage::Message(msg)) => {
+				let request_id = self.take_pending_request(path_id);
+				let received_via = self.received_via(path_id, request_id.is_some());
+				let responder = reply_path.clone().map(|reply_path| Responder { reply_path, received_via });
+				let response = match request_id {
+					Some(request_id) => {
+						log_trace!(self.logger, "Received response to onion message request {:02x?}", request_id.0);
+						self.custom_handler.handle_custom_response(msg, request_id, responder)
+					},
+					None => self.custom_handler.handle_custom_message_with_context(msg, received_via, responder),
+				};
+				response.map(|msg| OnionMessageContents::Custom(msg))
+			},
+		};
+
+		if let Some(response) = response {
+			if let Err(e) = check_custom_tlv_type(&response) {
+				log_debug!(self.logger, "Not responding to onion message with path_id {:02x?}: {:?}", path_id, e);
+				return;
+			}
+			self.respond_with_onion_message(response, path_id, reply_path);
+		}
+	}
+
+	/// Sends a receipt for a message received with the given `receipt_nonce` over its
+	/// `reply_path`.
+	///
+	/// Messages received along a blinded path we created are acknowledged with a key derived for
+	/// the path rather than our node key, so as not to reveal our node id to their sender. Probes
+	/// are always acknowledged with a one-time key, as they may have been sent along a blinded path
+	/// we didn't create.
+	fn send_receipt(
+		&self, receipt_nonce: [u8; 32], path_id: Option<[u8; 32]>, reply_path: Option<BlindedPath>,
+		is_probe: bool
+	) {
+		let signing_key = if is_probe {
+			Some(self.probe_receipt_key(&receipt_nonce))
+		} else {
+			path_id.map(|path_id| self.blinded_receipt_key(&path_id))
+		};
+		let (signing_pubkey, signature) = match signing_key {
+			Some(signing_key) => {
+				let signature = self.secp_ctx.sign_ecdsa(&onion_message_receipt_hash(&receipt_nonce), &signing_key);
+				(PublicKey::from_secret_key(&self.secp_ctx, &signing_key), signature)
+			},
+			None => {
+				let node_id = match self.node_signer.get_node_id(Recipient::Node) {
+					Ok(node_id) => node_id,
+					Err(()) => {
+						log_warn!(self.logger, "Unable to retrieve node id when sending onion message receipt");
+						return;
+					},
+				};
+				match self.node_signer.sign_onion_message_receipt(&receipt_nonce) {
+					Ok(signature) => (node_id, signature),
+					Err(()) => {
+						log_trace!(self.logger, "Not sending onion message receipt as our NodeSigner failed to sign it");
+						return;
+					},
+				}
+			},
+		};
+		log_trace!(self.logger, "Sending receipt for onion message with path_id {:02x?}", path_id);
+		let receipt = OnionMessageReceipt { signing_pubkey, signature };
+		self.respond_with_onion_message(OnionMessageContents::Custom(receipt), path_id, reply_path);
+	}
+
+	/// Derives the key we sign receipts for messages received along the blinded path with the
+	/// given `path_id` with, which is the same for all messages received along the path but can't
+	/// be linked to our node id.
+	fn blinded_receipt_key(&self, path_id: &[u8; 32]) -> SecretKey {
+		let mut hmac = HmacEngine::<Sha256>::new(&self.path_id_key);
+		hmac.input(b"receipt key");
+		hmac.input(path_id);
+		SecretKey::from_slice(&Hmac::from_engine(hmac).into_inner()).expect("HMAC output is a valid key")
+	}
+
+	/// Derives the key we sign the receipt for a probe with the given `receipt_nonce` with, which is
+	/// unique to the probe and can't be linked to our node id.
+	fn probe_receipt_key(&self, receipt_nonce: &[u8; 32]) -> SecretKey {
+		let mut hmac = HmacEngine::<Sha256>::new(&self.path_id_key);
+		hmac.input(b"probe receipt key");
+		hmac.input(receipt_nonce);
+		SecretKey::from_slice(&Hmac::from_engine(hmac).into_inner()).expect("HMAC output is a valid key")
+	}
+
+	/// Handles a receipt received along the reply path of a message sent via
+	/// [`Self::send_onion_message_with_receipt`] or a probe sent via [`Self::probe_path`],
+	/// generating an [`Event::OnionMessageDelivered`] or [`Event::OnionMessageProbeSuccessful`] if
+	/// it is valid.
+	fn handle_receipt(&self, receipt: OnionMessageReceipt, path_id: Option<[u8; 32]>) {
+		let id = match path_id {
+			Some(path_id) => OnionMessageDeliveryId(path_id),
+			None => {
+				log_trace!(self.logger, "Ignoring onion message receipt received without a path_id");
+				return;
+			},
+		};
+		let mut pending_receipts = self.pending_receipts.lock().unwrap();
+		let valid = match pending_receipts.get(&id) {
+			Some(pending) => {
+				// Probe receipts are signed with a one-time key, but only the probe's recipient could
+				// have read its nonce.
+				(pending.is_probe || pending.recipient.map_or(true, |recipient| recipient == receipt.signing_pubkey)) &&
+					self.secp_ctx.verify_ecdsa(
+						&onion_message_receipt_hash(&pending.nonce), &receipt.signature, &receipt.signing_pubkey
+					).is_ok()
+			},
+			None => {
+				log_trace!(self.logger, "Ignoring receipt for unknown onion message {:02x?}", id.0);
+				return;
+			},
+		};
+		if !valid {
+			log_trace!(self.logger, "Ignoring invalid receipt for onion message {:02x?}", id.0);
+			return;
+		}
+		let pending = pending_receipts.remove(&id).unwrap();
+		log_trace!(self.logger, "Received receipt for onion message {:02x?}", id.0);
+		let event = if pending.is_probe {
+			Event::OnionMessageProbeSuccessful { id }
+		} else {
+			Event::OnionMessageDelivered { id }
 		};
-		let (packet_payloads, packet_keys) = packet_payloads_and_keys(
-			&self.secp_ctx, &intermediate_nodes, destination, message, reply_path, &blinding_secret)
-			.map_err(|e| SendError::Secp256k1(e))?;
-
-		let prng_seed = self.entropy_source.get_secure_random_bytes();
-		let onion_routing_packet = construct_onion_message_packet(
-			packet_payloads, packet_keys, prng_seed).map_err(|()| SendError::TooBigPacket)?;
+		self.pending_events.lock().unwrap().push(event);
+	}
 
-		let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
-		if outbound_buffer_full(&introduction_node_id, &pending_per_peer_msgs) { return Err(SendError::BufferFull) }
-		match pending_per_peer_msgs.entry(introduction_node_id) {
-			hash_map::Entry::Vacant(_) => Err(SendError::InvalidFirstHop),
-			hash_map::Entry::Occupied(mut e) => {
-				e.get_mut().push_back(msgs::OnionMessage { blinding_point, onion_routing_packet });
-				Ok(())
-			}
+	fn handle_liveness_pong(&self, path_id: Option<[u8; 32]>) {
+		let mut liveness = self.liveness.lock().unwrap();
+		let probe = match path_id.and_then(|path_id| liveness.pending.remove(&path_id)) {
+			Some(probe) => probe,
+			None => {
+				log_trace!(self.logger, "Ignoring liveness pong for unknown probe with path_id {:02x?}", path_id);
+				return;
+			},
+		};
+		let round_trip_time = self.now().saturating_sub(probe.sent_at);
+		log_trace!(self.logger, "Liveness probe {:02x?} answered after {:?}", probe.id.0, round_trip_time);
+		if let Some((stats, _)) = liveness.stats.get_mut(&probe.destination) {
+			stats.record_round_trip_time(round_trip_time);
 		}
+		self.pending_events.lock().unwrap().push(Event::LivenessProbeCompleted {
+			id: probe.id, round_trip_time_ms: Some(round_trip_time.as_millis() as u64),
+		});
 	}
 
 	fn respond_with_onion_message<T: CustomOnionMessageContents>(
@@ -374,7 +3197,14 @@ where
 
 		log_trace!(self.logger, "Responding to onion message with path_id {:02x?}", path_id);
 
-		if let Err(e) = self.send_onion_message(path, response, None) {
+		// Handler responses were checked by the caller, and receipts and pongs use a reserved type.
+		let res = self.prepare_onion_message_unchecked(path, response, None, None).and_then(|prepared| {
+			let config = *self.config.lock().unwrap();
+			let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
+			self.enqueue_or_retry_onion_message(
+				prepared, OnionMessagePriority::Normal, &config, &mut pending_per_peer_msgs)
+		});
+		if let Err(e) = res {
 			log_trace!(
 				self.logger, "Failed responding to onion message with path_id {:02x?}: {:?}",
 				path_id, e
@@ -383,34 +3213,137 @@ where
 		}
 	}
 
+	/// Enqueues any messages released by our [`CustomOnionMessageHandler`] for sending.
+	fn enqueue_pending_custom_messages(&self) {
+		for (message, path, reply_path) in self.custom_handler.release_pending_custom_messages_along_paths() {
+			if let Err(e) = self.send_onion_message(path, OnionMessageContents::Custom(message), reply_path) {
+				log_trace!(self.logger, "Failed sending custom onion message along its given path: {:?}", e);
+			}
+		}
+
+		let pending_custom_messages = self.custom_handler.release_pending_custom_messages();
+		let pending_custom_requests = self.custom_handler.release_pending_custom_requests();
+		if pending_custom_messages.is_empty() && pending_custom_requests.is_empty() { return; }
+
+		let sender = match self.node_signer.get_node_id(Recipient::Node) {
+			Ok(node_id) => node_id,
+			Err(_) => {
+				log_warn!(self.logger, "Unable to retrieve node id when sending custom onion messages");
+				return;
+			}
+		};
+		let peers: Vec<PublicKey> = self.pending_messages.lock().unwrap().keys().copied().collect();
+
+		for (message, destination, reply_path) in pending_custom_messages {
+			let path = match self.message_router.find_path(sender, peers.clone(), destination) {
+				Ok(path) => path,
+				Err(()) => {
+					log_trace!(self.logger, "Failed to find path when sending custom onion message");
+					continue;
+				},
+			};
+			if let Err(e) = self.send_onion_message(path, OnionMessageContents::Custom(message), reply_path) {
+				log_trace!(self.logger, "Failed sending custom onion message: {:?}", e);
+			}
+		}
+
+		for (message, destination, request_id, timeout_ticks) in pending_custom_requests {
+			let res = self.message_router.find_path(sender, peers.clone(), destination)
+				.map_err(|()| SendError::PathNotFound)
+				.and_then(|path| self.send_onion_message_request(path, message, request_id, timeout_ticks));
+			if let Err(e) = res {
+				log_trace!(self.logger, "Failed sending custom onion message request {:02x?}: {:?}", request_id.0, e);
+				self.custom_handler.handle_custom_request_failed(request_id);
+			}
+		}
+	}
+
 	#[cfg(test)]
 	pub(super) fn release_pending_msgs(&self) -> HashMap<PublicKey, VecDeque<msgs::OnionMessage>> {
+		self.enqueue_pending_custom_messages();
 		let mut pending_msgs = self.pending_messages.lock().unwrap();
 		let mut msgs = HashMap::new();
 		// We don't want to disconnect the peers by removing them entirely from the original map, so we
 		// swap the pending message buffers individually.
 		for (peer_node_id, pending_messages) in &mut *pending_msgs {
-			msgs.insert(*peer_node_id, core::mem::take(pending_messages));
+			msgs.insert(*peer_node_id, pending_messages.take());
 		}
 		msgs
 	}
 }
 
-fn outbound_buffer_full(peer_node_id: &PublicKey, buffer: &HashMap<PublicKey, VecDeque<msgs::OnionMessage>>) -> bool {
-	const MAX_TOTAL_BUFFER_SIZE: usize = (1 << 20) * 128;
-	const MAX_PER_PEER_BUFFER_SIZE: usize = (1 << 10) * 256;
+fn buffer_occupancy(peer_buf: &PeerMessageQueue, config: &OnionMessengerConfig) -> OnionMessageBufferOccupancy {
+	OnionMessageBufferOccupancy {
+		messages: peer_buf.len(),
+		bytes: peer_buf.serialized_length(),
+		max_bytes: config.max_peer_buffer_bytes,
+	}
+}
+
+/// Drops the oldest of the lowest priority messages queued for `peer_node_id` until `buffer` has
+/// room for another one, returning the number dropped, or `None` if dropping all of them wouldn't
+/// suffice, in which case none are dropped. Messages we originated are never dropped.
+fn evict_oldest(
+	peer_node_id: &PublicKey, buffer: &mut HashMap<PublicKey, PeerMessageQueue>,
+	config: &OnionMessengerConfig
+) -> Option<u64> {
+	let mut total_buffered_bytes: usize = buffer.values()
+		.map(|peer_buf| peer_buf.iter().map(|om| om.serialized_length()).sum::<usize>()).sum();
+	let peer_buf = buffer.get_mut(peer_node_id)?;
+	let mut peer_buffered_bytes: usize = peer_buf.iter().map(|om| om.serialized_length()).sum();
+	let evictable_bytes = peer_buf.evictable_length();
+	if total_buffered_bytes - evictable_bytes >= config.max_total_buffer_bytes ||
+		peer_buffered_bytes - evictable_bytes >= config.max_peer_buffer_bytes
+	{
+		return None
+	}
+	let mut evicted = 0;
+	while total_buffered_bytes >= config.max_total_buffer_bytes ||
+		peer_buffered_bytes >= config.max_peer_buffer_bytes
+	{
+		let om_len = match peer_buf.pop_evictable() { Some(om) => om.serialized_length(), None => break };
+		total_buffered_bytes -= om_len;
+		peer_buffered_bytes -= om_len;
+		evicted += 1;
+	}
+	Some(evicted)
+}
+
+/// Returns whether `additional_bytes` worth of messages can be queued for `peer_node_id` in
+/// `buffer` without reaching the per-peer or total buffer limits.
+fn outbound_buffer_has_room(
+	peer_node_id: &PublicKey, buffer: &HashMap<PublicKey, PeerMessageQueue>,
+	config: &OnionMessengerConfig, additional_bytes: usize
+) -> bool {
+	let mut total_buffered_bytes = additional_bytes;
+	let mut peer_buffered_bytes = additional_bytes;
+	for (pk, peer_buf) in buffer {
+		let buffered_bytes: usize = peer_buf.iter().map(|om| om.serialized_length()).sum();
+		if pk == peer_node_id {
+			peer_buffered_bytes += buffered_bytes;
+		}
+		total_buffered_bytes += buffered_bytes;
+	}
+	total_buffered_bytes < config.max_total_buffer_bytes &&
+		peer_buffered_bytes < config.max_peer_buffer_bytes
+}
+
+fn outbound_buffer_full(
+	peer_node_id: &PublicKey, buffer: &HashMap<PublicKey, PeerMessageQueue>,
+	config: &OnionMessengerConfig
+) -> bool {
 	let mut total_buffered_bytes = 0;
 	let mut peer_buffered_bytes = 0;
 	for (pk, peer_buf) in buffer {
-		for om in peer_buf {
+		for om in peer_buf.iter() {
 			let om_len = om.serialized_length();
 			if pk == peer_node_id {
 				peer_buffered_bytes += om_len;
 			}
 			total_buffered_bytes += om_len;
 
-			if total_buffered_bytes >= MAX_TOTAL_BUFFER_SIZE ||
-				peer_buffered_bytes >= MAX_PER_PEER_BUFFER_SIZE
+			if total_buffered_bytes >= config.max_total_buffer_bytes ||
+				peer_buffered_bytes >= config.max_peer_buffer_bytes
 			{
 				return true
 			}
@@ -419,187 +3352,455 @@ fn outbound_buffer_full(peer_node_id: &PublicKey, buffer: &HashMap<PublicKey, Ve
 	false
 }
 
-impl<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, CMH: Deref> OnionMessageHandler
-for OnionMessenger<ES, NS, L, MR, OMH, CMH>
+impl<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, DMH: Deref, CMH: Deref> OnionMessageHandler
+for OnionMessenger<ES, NS, L, MR, OMH, DMH, CMH>
 where
 	ES::Target: EntropySource,
 	NS::Target: NodeSigner,
 	L::Target: Logger,
 	MR::Target: MessageRouter,
 	OMH::Target: OffersMessageHandler,
+	DMH::Target: DlcMessageHandler,
 	CMH::Target: CustomOnionMessageHandler,
 {
 	/// Handle an incoming onion message. Currently, if a message was destined for us we will log, but
 	/// soon we'll delegate the onion message to a handler that can generate invoices or send
 	/// payments.
-	fn handle_onion_message(&self, _peer_node_id: &PublicKey, msg: &msgs::OnionMessage) {
-		let control_tlvs_ss = match self.node_signer.ecdh(Recipient::Node, &msg.blinding_point, None) {
-			Ok(ss) => ss,
-			Err(e) =>  {
-				log_error!(self.logger, "Failed to retrieve node secret: {:?}", e);
-				return
-			}
-		};
-		let onion_decode_ss = {
-			let blinding_factor = {
-				let mut hmac = HmacEngine::<Sha256>::new(b"blinded_node_id");
-				hmac.input(control_tlvs_ss.as_ref());
-				Hmac::from_engine(hmac).into_inner()
-			};
-			match self.node_signer.ecdh(Recipient::Node, &msg.onion_routing_packet.public_key,
-				Some(&Scalar::from_be_bytes(blinding_factor).unwrap()))
-			{
-				Ok(ss) => ss.secret_bytes(),
-				Err(()) => {
-					log_trace!(self.logger, "Failed to compute onion packet shared secret");
-					return
-				}
-			}
-		};
-		match onion_utils::decode_next_untagged_hop(
-			onion_decode_ss, &msg.onion_routing_packet.hop_data[..], msg.onion_routing_packet.hmac,
-			(control_tlvs_ss, &*self.custom_handler, &*self.logger)
-		) {
-			Ok((Payload::Receive::<<<CMH as Deref>::Target as CustomOnionMessageHandler>::CustomMessage> {
-				message, control_tlvs: ReceiveControlTlvs::Unblinded(ReceiveTlvs { path_id }), reply_path,
-			}, None)) => {
+	fn handle_onion_message(&self, peer_node_id: &PublicKey, msg: &msgs::OnionMessage) {
+		if !self.rate_limiter.lock().unwrap().allow(peer_node_id, RateLimitDirection::Inbound) {
+			log_trace!(self.logger, "Dropping onion message from peer {}: rate limit exceeded", peer_node_id);
+			self.message_counts.lock().unwrap().dropped += 1;
+			return
+		}
+		let peeled = peel_onion_message_with_handler(
+			msg, &self.secp_ctx, &*self.node_signer, &*self.logger, &self.message_reading_handler()
+		);
+		match peeled {
+			Ok((PeeledOnion::Receive(message, path_id, reply_path), receipt_nonce)) => {
 				log_trace!(self.logger,
 					"Received an onion message with path_id {:02x?} and {} reply_path",
 						path_id, if reply_path.is_some() { "a" } else { "no" });
+				self.message_counts.lock().unwrap().received += 1;
 
-				let response = match message {
-					OnionMessageContents::Offers(msg) => {
-						self.offers_handler.handle_message(msg)
-							.map(|msg| OnionMessageContents::Offers(msg))
+				let (message, reply_path, receipt_nonce) = match message {
+					OnionMessageContents::Custom(ReceivedCustomMessage::Fragment(fragment)) => {
+						match self.reassemble_fragment(peer_node_id, fragment, path_id, reply_path, receipt_nonce) {
+							Some((message_bytes, reply_path, receipt_nonce)) => {
+								match self.read_reassembled_message(&message_bytes) {
+									Ok(message) => (message, reply_path, receipt_nonce),
+									Err(e) => {
+										log_trace!(self.logger, "Failed to read reassembled onion message: {:?}", e);
+										return
+									},
+								}
+							},
+							None => return,
+						}
+					},
+					OnionMessageContents::Custom(ReceivedCustomMessage::Receipt(receipt)) => {
+						self.handle_receipt(receipt, path_id);
+						return
+					},
+					OnionMessageContents::Custom(ReceivedCustomMessage::Probe(_)) => {
+						log_trace!(self.logger, "Received onion message probe with path_id {:02x?}", path_id);
+						if let Some(receipt_nonce) = receipt_nonce {
+							self.send_receipt(receipt_nonce, path_id, reply_path, true);
+						}
+						return
+					},
+					OnionMessageContents::Custom(ReceivedCustomMessage::LivenessPing(_)) => {
+						log_trace!(self.logger, "Answering liveness ping with path_id {:02x?}", path_id);
+						self.respond_with_onion_message(OnionMessageContents::Custom(LivenessPong {}), path_id, reply_path);
+						return
 					},
-					OnionMessageContents::Custom(msg) => {
-						self.custom_handler.handle_custom_message(msg)
-							.map(|msg| OnionMessageContents::Custom(msg))
+					OnionMessageContents::Custom(ReceivedCustomMessage::LivenessPong(_)) => {
+						self.handle_liveness_pong(path_id);
+						return
 					},
+					message => (message, reply_path, receipt_nonce),
 				};
-
-				if let Some(response) = response {
-					self.respond_with_onion_message(response, path_id, reply_path);
-				}
-			},
-			Ok((Payload::Forward(ForwardControlTlvs::Unblinded(ForwardTlvs {
-				next_node_id, next_blinding_override
-			})), Some((next_hop_hmac, new_packet_bytes)))) => {
-				// TODO: we need to check whether `next_node_id` is our node, in which case this is a dummy
-				// blinded hop and this onion message is destined for us. In this situation, we should keep
-				// unwrapping the onion layers to get to the final payload. Since we don't have the option
-				// of creating blinded paths with dummy hops currently, we should be ok to not handle this
-				// for now.
-				let new_pubkey = match onion_utils::next_hop_packet_pubkey(&self.secp_ctx, msg.onion_routing_packet.public_key, &onion_decode_ss) {
-					Ok(pk) => pk,
-					Err(e) => {
-						log_trace!(self.logger, "Failed to compute next hop packet pubkey: {}", e);
+				if let OnionMessageContents::Custom(ref msg) = message {
+					let key = dedup_key(msg, path_id, &reply_path, receipt_nonce);
+					if !self.dedup_cache.lock().unwrap().insert(key) {
+						log_trace!(self.logger, "Dropping duplicate custom onion message of type {}", msg.tlv_type());
+						self.message_counts.lock().unwrap().dropped += 1;
+						// The sender may be retrying as it didn't get our receipt, so send another.
+						if let Some(receipt_nonce) = receipt_nonce {
+							self.send_receipt(receipt_nonce, path_id, reply_path, false);
+						}
 						return
 					}
-				};
-				let outgoing_packet = Packet {
-					version: 0,
-					public_key: new_pubkey,
-					hop_data: new_packet_bytes,
-					hmac: next_hop_hmac,
-				};
-				let onion_message = msgs::OnionMessage {
-					blinding_point: match next_blinding_override {
-						Some(blinding_point) => blinding_point,
-						None => {
-							let blinding_factor = {
-								let mut sha = Sha256::engine();
-								sha.input(&msg.blinding_point.serialize()[..]);
-								sha.input(control_tlvs_ss.as_ref());
-								Sha256::from_engine(sha).into_inner()
-							};
-							let next_blinding_point = msg.blinding_point;
-							match next_blinding_point.mul_tweak(&self.secp_ctx, &Scalar::from_be_bytes(blinding_factor).unwrap()) {
-								Ok(bp) => bp,
-								Err(e) => {
-									log_trace!(self.logger, "Failed to compute next blinding point: {}", e);
-									return
-								}
-							}
-						},
+				}
+				match receipt_nonce {
+					Some(receipt_nonce) => {
+						self.handle_received_message(message, path_id, reply_path.clone());
+						self.send_receipt(receipt_nonce, path_id, reply_path, false);
 					},
-					onion_routing_packet: outgoing_packet,
-				};
+					None => self.handle_received_message(message, path_id, reply_path),
+				}
+			},
+			Ok((PeeledOnion::Forward(next_node_id, onion_message), _)) => {
+				let mut forwarding = self.forwarding.lock().unwrap();
+				if !forwarding.allows(&next_node_id) {
+					log_trace!(self.logger, "Dropping forwarded onion message to peer {:?}: not allowed by our forwarding policy", next_node_id);
+					forwarding.stats.dropped_by_policy += 1;
+					return
+				}
 
+				let config = *self.config.lock().unwrap();
 				let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
-				if outbound_buffer_full(&next_node_id, &pending_per_peer_msgs) {
+
+				#[cfg(fuzzing)]
+				pending_per_peer_msgs.entry(next_node_id).or_insert_with(PeerMessageQueue::default);
+
+				let buffer_full = outbound_buffer_full(&next_node_id, &pending_per_peer_msgs, &config);
+				let peer_connected = pending_per_peer_msgs.contains_key(&next_node_id);
+				if buffer_full && (config.eviction_policy == OnionMessageEvictionPolicy::RejectNew || !peer_connected) {
 					log_trace!(self.logger, "Dropping forwarded onion message to peer {:?}: outbound buffer full", next_node_id);
+					forwarding.stats.dropped_buffer_full += 1;
 					return
 				}
 
-				#[cfg(fuzzing)]
-				pending_per_peer_msgs.entry(next_node_id).or_insert_with(VecDeque::new);
-
-				match pending_per_peer_msgs.entry(next_node_id) {
-					hash_map::Entry::Vacant(_) => {
+				if !peer_connected {
+					let mut mailbox = self.mailbox.lock().unwrap();
+					let is_channel_peer = forwarding.channel_peers.as_ref()
+						.map_or(false, |channel_peers| channel_peers.has_channel_with(&next_node_id));
+					if mailbox.config.is_none() || !is_channel_peer {
 						log_trace!(self.logger, "Dropping forwarded onion message to disconnected peer {:?}", next_node_id);
-						return
-					},
-					hash_map::Entry::Occupied(mut e) => {
-						e.get_mut().push_back(onion_message);
-						log_trace!(self.logger, "Forwarding an onion message to peer {}", next_node_id);
+						forwarding.stats.dropped_peer_disconnected += 1;
+					} else if mailbox.store(next_node_id, *peer_node_id, onion_message) {
+						log_trace!(self.logger, "Storing forwarded onion message for disconnected peer {:?}", next_node_id);
+						forwarding.stats.stored += 1;
+						core::mem::drop(mailbox);
+						self.pending_events.lock().unwrap().push(Event::OnionMessageStored { peer_node_id: next_node_id });
+					} else {
+						log_trace!(self.logger, "Dropping forwarded onion message to disconnected peer {:?}: mailbox full", next_node_id);
+						forwarding.stats.dropped_peer_disconnected += 1;
 					}
-				};
-			},
-			Err(e) => {
-				log_trace!(self.logger, "Errored decoding onion message packet: {:?}", e);
+					return
+				}
+
+				if !self.rate_limiter.lock().unwrap().allow(&next_node_id, RateLimitDirection::Outbound) {
+					log_trace!(self.logger, "Dropping forwarded onion message to peer {:?}: rate limit exceeded", next_node_id);
+					forwarding.stats.dropped_rate_limited += 1;
+					return
+				}
+				if buffer_full {
+					match evict_oldest(&next_node_id, &mut pending_per_peer_msgs, &config) {
+						Some(evicted) => self.messages_evicted(next_node_id, evicted),
+						None => {
+							log_trace!(self.logger, "Dropping forwarded onion message to peer {:?}: outbound buffer full", next_node_id);
+							forwarding.stats.dropped_buffer_full += 1;
+							return
+						},
+					}
+				}
+				let peer_buf = pending_per_peer_msgs.entry(next_node_id)
+					.or_insert_with(PeerMessageQueue::default);
+				peer_buf.push(OnionMessagePriority::Normal, onion_message, false);
+				self.check_buffer_high_water(&next_node_id, peer_buf, &config);
+				forwarding.stats.forwarded += 1;
+				log_trace!(self.logger, "Forwarding an onion message to peer {}", next_node_id);
 			},
-			_ => {
-				log_trace!(self.logger, "Received bogus onion message packet, either the sender encoded a final hop as a forwarding hop or vice versa");
+			Err(()) => {
+				self.message_counts.lock().unwrap().dropped += 1;
 			},
 		};
 	}
 
 	fn peer_connected(&self, their_node_id: &PublicKey, init: &msgs::Init, _inbound: bool) -> Result<(), ()> {
 		if init.features.supports_onion_messages() {
+			let config = *self.config.lock().unwrap();
 			let mut peers = self.pending_messages.lock().unwrap();
-			peers.insert(their_node_id.clone(), VecDeque::new());
+			self.connection_needed.lock().unwrap().remove(their_node_id);
+			let mut msgs = self.offline_messages.lock().unwrap().remove(their_node_id)
+				.unwrap_or_else(PeerMessageQueue::default);
+			if let Some(stored_msgs) = self.mailbox.lock().unwrap().take(their_node_id) {
+				log_trace!(self.logger, "Delivering {} onion messages stored while peer {} was offline",
+					stored_msgs.len(), their_node_id);
+				for msg in stored_msgs {
+					msgs.push(OnionMessagePriority::Normal, msg, false);
+				}
+			}
+			self.check_buffer_high_water(their_node_id, &msgs, &config);
+			peers.insert(their_node_id.clone(), msgs);
 		}
 		Ok(())
 	}
 
 	fn peer_disconnected(&self, their_node_id: &PublicKey) {
 		let mut pending_msgs = self.pending_messages.lock().unwrap();
-		pending_msgs.remove(their_node_id);
+		if let Some(msgs) = pending_msgs.remove(their_node_id) {
+			let config = *self.config.lock().unwrap();
+			if config.max_send_retries > 0 {
+				for (priority, queue) in [OnionMessagePriority::High, OnionMessagePriority::Normal,
+					OnionMessagePriority::Low].iter().zip(msgs.queues.iter())
+				{
+					// Only retry messages we originated, so that other senders' traffic can't crowd
+					// ours out of the retry queue.
+					let messages: Vec<_> = queue.iter()
+						.filter(|queued| queued.originated)
+						.map(|queued| queued.message.clone())
+						.collect();
+					if messages.is_empty() { continue }
+					let message_count = messages.len() as u64;
+					let prepared = PreparedOnionMessage {
+						first_node_id: *their_node_id,
+						first_node_addresses: None,
+						messages,
+					};
+					if !self.schedule_retry(prepared, *priority, 0, &config) {
+						self.message_counts.lock().unwrap().dropped += message_count;
+					}
+				}
+			}
+		}
+		core::mem::drop(pending_msgs);
+		self.check_buffer_drained(their_node_id, None, &self.config.lock().unwrap());
+	}
+
+	fn timer_tick_occurred(&self) {
+		{
+			let connected_peers = self.pending_messages.lock().unwrap();
+			self.rate_limiter.lock().unwrap().prune_disconnected_peers(&connected_peers);
+		}
+
+		let mut pending_events = self.pending_events.lock().unwrap();
+		let mut timed_out_requests = Vec::new();
+		self.pending_requests.lock().unwrap().retain(|_, (request_id, ticks_remaining)| {
+			if *ticks_remaining == 0 {
+				log_debug!(self.logger, "Onion message request {:02x?} timed out", request_id.0);
+				pending_events.push(Event::OnionMessageTimedOut { request_id: *request_id });
+				timed_out_requests.push(*request_id);
+				return false;
+			}
+			*ticks_remaining -= 1;
+			true
+		});
+		core::mem::drop(pending_events);
+		for request_id in timed_out_requests {
+			self.custom_handler.handle_custom_request_failed(request_id);
+		}
+
+		let mut timed_out_receipts = Vec::new();
+		self.pending_receipts.lock().unwrap().retain(|id, pending| {
+			if pending.ticks_remaining == 0 {
+				log_debug!(self.logger, "Timed out waiting for a receipt for onion message {:02x?}", id.0);
+				timed_out_receipts.push((*id, pending.is_probe));
+				return false;
+			}
+			pending.ticks_remaining -= 1;
+			true
+		});
+		self.pending_events.lock().unwrap().extend(timed_out_receipts.into_iter().map(|(id, is_probe)| {
+			if is_probe {
+				Event::OnionMessageProbeFailed { id }
+			} else {
+				Event::OnionMessageReceiptTimedOut { id }
+			}
+		}));
+
+		let mut timed_out_liveness_probes = Vec::new();
+		{
+			let mut liveness = self.liveness.lock().unwrap();
+			let Liveness { pending, stats, .. } = &mut *liveness;
+			pending.retain(|_, probe| {
+				if probe.ticks_remaining == 0 {
+					log_debug!(self.logger, "Liveness probe {:02x?} timed out", probe.id.0);
+					if let Some((stats, _)) = stats.get_mut(&probe.destination) {
+						stats.probes_timed_out += 1;
+					}
+					timed_out_liveness_probes.push(probe.id);
+					return false;
+				}
+				probe.ticks_remaining -= 1;
+				true
+			});
+		}
+		self.pending_events.lock().unwrap().extend(timed_out_liveness_probes.into_iter().map(|id| {
+			Event::LivenessProbeCompleted { id, round_trip_time_ms: None }
+		}));
+
+		self.pending_reassemblies.lock().unwrap().retain(|_, partial_message| {
+			if partial_message.ticks_remaining == 0 {
+				log_trace!(self.logger, "Dropping partially received onion message after {} of {} fragments",
+					partial_message.received, partial_message.fragments.len());
+				return false;
+			}
+			partial_message.ticks_remaining -= 1;
+			true
+		});
+
+		let mut connections_failed = Vec::new();
+		self.connection_needed.lock().unwrap().retain(|node_id, ticks_remaining| {
+			if *ticks_remaining == 0 {
+				connections_failed.push(*node_id);
+				return false;
+			}
+			*ticks_remaining -= 1;
+			true
+		});
+		for node_id in connections_failed {
+			if let Some(msgs) = self.offline_messages.lock().unwrap().remove(&node_id) {
+				log_trace!(self.logger, "Dropping {} onion messages queued for {} as it didn't connect in time",
+					msgs.len(), node_id);
+				let count = msgs.len() as u64;
+				self.message_counts.lock().unwrap().dropped += count;
+				self.pending_events.lock().unwrap().push(Event::ConnectionNeededTimedOut { node_id, count });
+			}
+		}
+
+		let due_retries = {
+			let mut pending_retries = self.pending_retries.lock().unwrap();
+			for retry in pending_retries.iter_mut() {
+				retry.ticks_remaining = retry.ticks_remaining.saturating_sub(1);
+			}
+			let (due_retries, remaining_retries): (Vec<_>, Vec<_>) = core::mem::take(&mut *pending_retries)
+				.into_iter().partition(|retry| retry.ticks_remaining == 0);
+			*pending_retries = remaining_retries;
+			due_retries
+		};
+		if !due_retries.is_empty() {
+			let config = *self.config.lock().unwrap();
+			let mut pending_per_peer_msgs = self.pending_messages.lock().unwrap();
+			for PendingRetry { prepared, priority, attempts, .. } in due_retries {
+				let first_node_id = prepared.first_node_id;
+				let message_count = prepared.messages.len() as u64;
+				let retry = prepared.clone();
+				match self.enqueue_onion_message(prepared, priority, &config, &mut pending_per_peer_msgs) {
+					Ok(()) => {},
+					Err(SendError::BufferFull) | Err(SendError::InvalidFirstHop)
+						if self.schedule_retry(retry, priority, attempts + 1, &config) => {},
+					Err(e) => {
+						log_trace!(self.logger, "Dropping onion message to {} after {} retries: {:?}",
+							first_node_id, attempts + 1, e);
+						self.message_counts.lock().unwrap().dropped += message_count;
+					},
+				}
+			}
+		}
+
+		self.dedup_cache.lock().unwrap().expire_entries();
+
+		let expired = self.mailbox.lock().unwrap().expire_messages();
+		if !expired.is_empty() {
+			let mut pending_events = self.pending_events.lock().unwrap();
+			for (peer_node_id, count) in expired {
+				log_trace!(self.logger, "Dropping {} expired onion messages stored for peer {}", count, peer_node_id);
+				self.message_counts.lock().unwrap().dropped += count;
+				pending_events.push(Event::OnionMessagesExpired { peer_node_id, count });
+			}
+		}
 	}
 
 	fn provided_node_features(&self) -> NodeFeatures {
 		let mut features = NodeFeatures::empty();
 		features.set_onion_messages_optional();
+		features.set_onion_message_fragmentation_optional();
 		features
 	}
 
 	fn provided_init_features(&self, _their_node_id: &PublicKey) -> InitFeatures {
 		let mut features = InitFeatures::empty();
 		features.set_onion_messages_optional();
+		features.set_onion_message_fragmentation_optional();
 		features
 	}
 }
 
-impl<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, CMH: Deref> OnionMessageProvider
-for OnionMessenger<ES, NS, L, MR, OMH, CMH>
+impl<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, DMH: Deref, CMH: Deref> OnionMessageProvider
+for OnionMessenger<ES, NS, L, MR, OMH, DMH, CMH>
 where
 	ES::Target: EntropySource,
 	NS::Target: NodeSigner,
 	L::Target: Logger,
 	MR::Target: MessageRouter,
 	OMH::Target: OffersMessageHandler,
+	DMH::Target: DlcMessageHandler,
 	CMH::Target: CustomOnionMessageHandler,
 {
 	fn next_onion_message_for_peer(&self, peer_node_id: PublicKey) -> Option<msgs::OnionMessage> {
+		self.enqueue_pending_custom_messages();
+		let config = *self.config.lock().unwrap();
 		let mut pending_msgs = self.pending_messages.lock().unwrap();
 		if let Some(msgs) = pending_msgs.get_mut(&peer_node_id) {
-			return msgs.pop_front()
+			let msg = msgs.pop_front();
+			// Check even if nothing was queued, as messages may have been dropped from the queue
+			// other than by us handing them out here.
+			self.check_buffer_drained(&peer_node_id, Some(msgs), &config);
+			return msg
 		}
 		None
 	}
 }
 
+impl<ES: Deref, NS: Deref, L: Deref, MR: Deref, OMH: Deref, DMH: Deref, CMH: Deref> EventsProvider
+for OnionMessenger<ES, NS, L, MR, OMH, DMH, CMH>
+where
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+	L::Target: Logger,
+	MR::Target: MessageRouter,
+	OMH::Target: OffersMessageHandler,
+	DMH::Target: DlcMessageHandler,
+	CMH::Target: CustomOnionMessageHandler,
+{
+	/// Processes [`Event::OnionMessageTimedOut`] events generated for requests sent via
+	/// [`OnionMessenger::send_onion_message_request`], as well as [`Event::OnionMessageStored`] and
+	/// [`Event::OnionMessagesExpired`] events generated by our mailbox, if enabled via
+	/// [`OnionMessenger::set_mailbox_config`], and [`Event::ConnectionNeeded`] events generated for
+	/// messages sent along an [`OnionMessagePath`] with [`OnionMessagePath::first_node_addresses`],
+	/// followed by an [`Event::ConnectionNeededTimedOut`] if the node doesn't connect in time.
+	/// [`Event::OnionMessagesEvicted`] events are generated if messages are evicted per
+	/// [`OnionMessageEvictionPolicy::DropOldest`], and [`Event::OnionMessageDelivered`] events once
+	/// a receipt is received for a message sent via
+	/// [`OnionMessenger::send_onion_message_with_receipt`]. [`Event::OnionMessageBufferHighWater`]
+	/// and [`Event::OnionMessageBufferDrained`] events are generated if enabled via
+	/// [`OnionMessengerConfig::buffer_high_water_mark_bytes`].
+	///
+	/// Any [`OnionMessageRateLimitObserver`] set via [`OnionMessenger::set_rate_limit_observer`] is
+	/// notified of rate limit violations here, before events are handled.
+	///
+	/// See the trait-level documentation of [`EventsProvider`] for requirements.
+	fn process_pending_events<H: Deref>(&self, handler: H) where H::Target: EventHandler {
+		self.notify_rate_limit_observer();
+		let pending_events = {
+			let mut pending_custom_message_events = self.pending_custom_message_events.lock().unwrap();
+			*pending_custom_message_events = (0, 0);
+			core::mem::take(&mut *self.pending_events.lock().unwrap())
+		};
+		for event in pending_events {
+			handler.handle_event(event);
+		}
+	}
+}
+
+impl<ES: Deref, NS: Deref, L: Deref, G: Deref<Target=NetworkGraph<L>>, OMH: Deref, DMH: Deref, CMH: Deref>
+OnionMessenger<ES, NS, L, Arc<DefaultMessageRouter<G, L>>, OMH, DMH, CMH>
+where
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+	L::Target: Logger,
+	OMH::Target: OffersMessageHandler,
+	DMH::Target: DlcMessageHandler,
+	CMH::Target: CustomOnionMessageHandler,
+{
+	/// Constructs a new `OnionMessenger` which finds paths using a [`DefaultMessageRouter`] over
+	/// the given `network_graph` with default parameters.
+	///
+	/// Pass [`IgnoringMessageHandler`]s as the `offers_handler`, `dlc_handler` or `custom_handler`
+	/// if you do not handle those messages.
+	///
+	/// This is not exported to bindings users as `Arc`s don't make sense in bindings.
+	pub fn with_default_router(
+		entropy_source: ES, node_signer: NS, logger: L, network_graph: G, offers_handler: OMH,
+		dlc_handler: DMH, custom_handler: CMH
+	) -> Self {
+		let message_router = Arc::new(DefaultMessageRouter::new(network_graph));
+		Self::new(entropy_source, node_signer, logger, message_router, offers_handler, dlc_handler, custom_handler)
+	}
+}
+
 // TODO: parameterize the below Simple* types with OnionMessenger and handle the messages it
 // produces
 /// Useful for simplifying the parameters of [`SimpleArcChannelManager`] and
@@ -613,7 +3814,8 @@ pub type SimpleArcOnionMessenger<L> = OnionMessenger<
 	Arc<KeysManager>,
 	Arc<KeysManager>,
 	Arc<L>,
-	Arc<DefaultMessageRouter>,
+	Arc<DefaultMessageRouter<Arc<NetworkGraph<Arc<L>>>, Arc<L>>>,
+	IgnoringMessageHandler,
 	IgnoringMessageHandler,
 	IgnoringMessageHandler
 >;
@@ -629,16 +3831,277 @@ pub type SimpleRefOnionMessenger<'a, 'b, 'c, L> = OnionMessenger<
 	&'a KeysManager,
 	&'a KeysManager,
 	&'b L,
-	&'c DefaultMessageRouter,
+	&'c DefaultMessageRouter<&'c NetworkGraph<&'b L>, &'b L>,
+	IgnoringMessageHandler,
 	IgnoringMessageHandler,
 	IgnoringMessageHandler
 >;
 
+/// An [`OnionMessenger`] whose dependencies are `Arc`'d trait objects, allowing it to be named by
+/// downstream crates and FFI layers without repeating its generic parameters. See
+/// [`AnyChannelManager`] and [`AnyPeerManager`] for its counterparts.
+///
+/// The [`CustomOnionMessageHandler`] remains a type parameter, as its trait can't be made into a
+/// trait object, and should be [`IgnoringMessageHandler`] if custom messages aren't used.
+///
+/// This is not exported to bindings users as `Arc`s don't make sense in bindings.
+///
+/// [`AnyChannelManager`]: crate::ln::channelmanager::AnyChannelManager
+/// [`AnyPeerManager`]: crate::ln::peer_handler::AnyPeerManager
+pub type AnyOnionMessenger<CMH> = OnionMessenger<
+	Arc<dyn EntropySource + Send + Sync>,
+	Arc<dyn NodeSigner + Send + Sync>,
+	Arc<dyn Logger + Send + Sync>,
+	Arc<dyn MessageRouter + Send + Sync>,
+	Arc<dyn OffersMessageHandler + Send + Sync>,
+	Arc<dyn DlcMessageHandler + Send + Sync>,
+	Arc<CMH>
+>;
+
+/// Creates an onion message with contents `contents` to the destination of `path`, returning the
+/// node it should be sent to along with the message itself.
+///
+/// This allows onion messages to be built without an [`OnionMessenger`], e.g. to carry them over
+/// a transport other than Lightning peer connections. Unlike
+/// [`OnionMessenger::send_onion_message`], messages which don't fit in a single onion message
+/// packet fail with [`SendError::TooBigPacket`] rather than being split into fragments.
+///
+/// See [`peel_onion_message`] for processing a received onion message.
+pub fn create_onion_message<ES: Deref, NS: Deref, T: CustomOnionMessageContents>(
+	entropy_source: &ES, node_signer: &NS, secp_ctx: &Secp256k1<secp256k1::All>,
+	path: OnionMessagePath, contents: OnionMessageContents<T>, reply_path: Option<BlindedPath>
+) -> Result<(PublicKey, msgs::OnionMessage), SendError>
+where
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+{
+	match create_onion_message_or_oversized(entropy_source, node_signer, secp_ctx, path, contents, reply_path, None)? {
+		CreatedOnionMessage::Packet { first_node_id, message, .. } => Ok((first_node_id, message)),
+		CreatedOnionMessage::TooBig { .. } => Err(SendError::TooBigPacket),
+	}
+}
+
+/// An onion message after being peeled via [`peel_onion_message`].
+#[derive(Debug)]
+pub enum PeeledOnion<T: CustomOnionMessageContents> {
+	/// The message should be forwarded to the node with the given id, as the given message.
+	Forward(PublicKey, msgs::OnionMessage),
+	/// We're the recipient of the message, which has the given contents, the `path_id` of the
+	/// blinded path it was received along, if any, and a reply path, if the sender included one.
+	Receive(OnionMessageContents<T>, Option<[u8; 32]>, Option<BlindedPath>),
+}
+
+/// Peels one layer of a received onion message, decrypting it with our node's key as provided by
+/// `node_signer` and reading any custom contents via `custom_handler`.
+///
+/// This allows onion messages to be processed without an [`OnionMessenger`], e.g. when carried
+/// over a transport other than Lightning peer connections. Note that fragments of messages which
+/// were too large to be sent in a single packet are only read, and reassembled, by an
+/// [`OnionMessenger`].
+///
+/// See [`create_onion_message`] for constructing an onion message.
+pub fn peel_onion_message<NS: Deref, L: Deref, CMH: Deref>(
+	msg: &msgs::OnionMessage, secp_ctx: &Secp256k1<secp256k1::All>, node_signer: NS, logger: L,
+	custom_handler: CMH
+) -> Result<PeeledOnion<<<CMH as Deref>::Target as CustomOnionMessageHandler>::CustomMessage>, ()>
+where
+	NS::Target: NodeSigner,
+	L::Target: Logger,
+	CMH::Target: CustomOnionMessageHandler,
+{
+	peel_onion_message_with_handler(msg, secp_ctx, &*node_signer, &*logger, &*custom_handler)
+		.map(|(peeled, _)| peeled)
+}
+
+/// Peels one layer of a received onion message as [`peel_onion_message`] does, additionally
+/// returning the nonce the sender requested a receipt over, if any.
+fn peel_onion_message_with_handler<NS: NodeSigner + ?Sized, L: Logger + ?Sized, H: CustomOnionMessageHandler + ?Sized>(
+	msg: &msgs::OnionMessage, secp_ctx: &Secp256k1<secp256k1::All>, node_signer: &NS, logger: &L,
+	custom_handler: &H
+) -> Result<(PeeledOnion<H::CustomMessage>, Option<[u8; 32]>), ()> {
+	let control_tlvs_ss = match node_signer.ecdh(Recipient::Node, &msg.blinding_point, None) {
+		Ok(ss) => ss,
+		Err(e) =>  {
+			log_error!(logger, "Failed to retrieve node secret: {:?}", e);
+			return Err(());
+		}
+	};
+	let onion_decode_ss = {
+		let blinding_factor = {
+			let mut hmac = HmacEngine::<Sha256>::new(b"blinded_node_id");
+			hmac.input(control_tlvs_ss.as_ref());
+			Hmac::from_engine(hmac).into_inner()
+		};
+		match node_signer.ecdh(Recipient::Node, &msg.onion_routing_packet.public_key,
+			Some(&Scalar::from_be_bytes(blinding_factor).unwrap()))
+		{
+			Ok(ss) => ss.secret_bytes(),
+			Err(()) => {
+				log_trace!(logger, "Failed to compute onion packet shared secret");
+				return Err(());
+			}
+		}
+	};
+	match onion_utils::decode_next_untagged_hop(
+		onion_decode_ss, &msg.onion_routing_packet.hop_data[..], msg.onion_routing_packet.hmac,
+		(control_tlvs_ss, custom_handler, logger)
+	) {
+		Ok((Payload::Receive::<H::CustomMessage> {
+			message, control_tlvs: ReceiveControlTlvs::Unblinded(ReceiveTlvs { path_id }), reply_path,
+			receipt_nonce,
+		}, None)) => {
+			Ok((PeeledOnion::Receive(message, path_id, reply_path), receipt_nonce))
+		},
+		Ok((Payload::Forward(ForwardControlTlvs::Unblinded(ForwardTlvs {
+			next_node_id, next_blinding_override
+		})), Some((next_hop_hmac, new_packet_bytes)))) => {
+			// TODO: we need to check whether `next_node_id` is our node, in which case this is a dummy
+			// blinded hop and this onion message is destined for us. In this situation, we should keep
+			// unwrapping the onion layers to get to the final payload. Since we don't have the option
+			// of creating blinded paths with dummy hops currently, we should be ok to not handle this
+			// for now.
+			let new_pubkey = match onion_utils::next_hop_packet_pubkey(secp_ctx, msg.onion_routing_packet.public_key, &onion_decode_ss) {
+				Ok(pk) => pk,
+				Err(e) => {
+					log_trace!(logger, "Failed to compute next hop packet pubkey: {}", e);
+					return Err(())
+				}
+			};
+			let outgoing_packet = Packet {
+				version: 0,
+				public_key: new_pubkey,
+				hop_data: new_packet_bytes,
+				hmac: next_hop_hmac,
+			};
+			let onion_message = msgs::OnionMessage {
+				blinding_point: match next_blinding_override {
+					Some(blinding_point) => blinding_point,
+					None => {
+						let blinding_factor = {
+							let mut sha = Sha256::engine();
+							sha.input(&msg.blinding_point.serialize()[..]);
+							sha.input(control_tlvs_ss.as_ref());
+							Sha256::from_engine(sha).into_inner()
+						};
+						let next_blinding_point = msg.blinding_point;
+						match next_blinding_point.mul_tweak(secp_ctx, &Scalar::from_be_bytes(blinding_factor).unwrap()) {
+							Ok(bp) => bp,
+							Err(e) => {
+								log_trace!(logger, "Failed to compute next blinding point: {}", e);
+								return Err(())
+							}
+						}
+					},
+				},
+				onion_routing_packet: outgoing_packet,
+			};
+			Ok((PeeledOnion::Forward(next_node_id, onion_message), None))
+		},
+		Err(e) => {
+			log_trace!(logger, "Errored decoding onion message packet: {:?}", e);
+			Err(())
+		},
+		_ => {
+			log_trace!(logger, "Received bogus onion message packet, either the sender encoded a final hop as a forwarding hop or vice versa");
+			Err(())
+		},
+	}
+}
+
+/// The result of [`create_onion_message_or_oversized`].
+enum CreatedOnionMessage<T: CustomOnionMessageContents> {
+	/// The message fit in a single onion message packet.
+	Packet {
+		first_node_id: PublicKey,
+		first_node_addresses: Option<Vec<msgs::NetAddress>>,
+		message: msgs::OnionMessage,
+	},
+	/// The message was too large for a single packet and is handed back to be split into
+	/// fragments.
+	TooBig {
+		path: OnionMessagePath,
+		message: OnionMessageContents<T>,
+		reply_path: Option<BlindedPath>,
+		receipt_nonce: Option<[u8; 32]>,
+	},
+}
+
+fn create_onion_message_or_oversized<ES: Deref, NS: Deref, T: CustomOnionMessageContents>(
+	entropy_source: &ES, node_signer: &NS, secp_ctx: &Secp256k1<secp256k1::All>,
+	path: OnionMessagePath, message: OnionMessageContents<T>, reply_path: Option<BlindedPath>,
+	receipt_nonce: Option<[u8; 32]>
+) -> Result<CreatedOnionMessage<T>, SendError>
+where
+	ES::Target: EntropySource,
+	NS::Target: NodeSigner,
+{
+		let OnionMessagePath { intermediate_nodes, mut destination, first_node_addresses } = path;
+		if let Destination::BlindedPath(BlindedPath { ref blinded_hops, .. }) = destination {
+			if blinded_hops.len() < 2 {
+				return Err(SendError::TooFewBlindedHops);
+			}
+		}
+
+		if message.tlv_type() < 64 { return Err(SendError::InvalidMessage) }
+
+		// If we are sending straight to a blinded path and we are the introduction node, we need to
+		// advance the blinded path by 1 hop so the second hop is the new introduction node.
+		if intermediate_nodes.len() == 0 {
+			if let Destination::BlindedPath(ref mut blinded_path) = destination {
+				let our_node_id = node_signer.get_node_id(Recipient::Node)
+					.map_err(|()| SendError::GetNodeIdFailed)?;
+				if blinded_path.introduction_node_id == our_node_id {
+					blinded_path.advance_message_path_by_one(node_signer, secp_ctx)
+						.map_err(|()| SendError::BlindedPathAdvanceFailed)?;
+				}
+			}
+		}
+
+		let blinding_secret_bytes = entropy_source.get_secure_random_bytes();
+		let blinding_secret = SecretKey::from_slice(&blinding_secret_bytes[..]).expect("RNG is busted");
+		let (introduction_node_id, blinding_point) = if intermediate_nodes.len() != 0 {
+			(intermediate_nodes[0], PublicKey::from_secret_key(secp_ctx, &blinding_secret))
+		} else {
+			match destination {
+				Destination::Node(pk) => (pk, PublicKey::from_secret_key(secp_ctx, &blinding_secret)),
+				Destination::BlindedPath(BlindedPath { introduction_node_id, blinding_point, .. }) =>
+					(introduction_node_id, blinding_point),
+			}
+		};
+		let fragment_destination = destination.clone();
+		let (mut packet_payloads, packet_keys) = packet_payloads_and_keys(
+			secp_ctx, &intermediate_nodes, destination, message, reply_path, receipt_nonce, &blinding_secret)
+			.map_err(|e| SendError::Secp256k1(e))?;
+
+		if onion_utils::payloads_serialized_length(&packet_payloads) > BIG_PACKET_HOP_DATA_LEN {
+			return match packet_payloads.pop() {
+				Some((Payload::Receive { message, reply_path, receipt_nonce, .. }, _)) => {
+					let path = OnionMessagePath {
+						intermediate_nodes, destination: fragment_destination, first_node_addresses,
+					};
+					Ok(CreatedOnionMessage::TooBig { path, message, reply_path, receipt_nonce })
+				},
+				_ => Err(SendError::TooBigPacket),
+			};
+		}
+
+		let prng_seed = entropy_source.get_secure_random_bytes();
+		let onion_routing_packet = construct_onion_message_packet(
+			packet_payloads, packet_keys, prng_seed).map_err(|()| SendError::TooBigPacket)?;
+
+		Ok(CreatedOnionMessage::Packet {
+			first_node_id: introduction_node_id,
+			first_node_addresses,
+			message: msgs::OnionMessage { blinding_point, onion_routing_packet },
+		})
+}
+
 /// Construct onion packet payloads and keys for sending an onion message along the given
 /// `unblinded_path` to the given `destination`.
 fn packet_payloads_and_keys<T: CustomOnionMessageContents, S: secp256k1::Signing + secp256k1::Verification>(
 	secp_ctx: &Secp256k1<S>, unblinded_path: &[PublicKey], destination: Destination,
-	message: OnionMessageContents<T>, mut reply_path: Option<BlindedPath>, session_priv: &SecretKey
+	message: OnionMessageContents<T>, mut reply_path: Option<BlindedPath>,
+	receipt_nonce: Option<[u8; 32]>, session_priv: &SecretKey
 ) -> Result<(Vec<(Payload<T>, [u8; 32])>, Vec<onion_utils::OnionKeys>), secp256k1::Error> {
 	let num_hops = unblinded_path.len() + destination.num_hops();
 	let mut payloads = Vec::with_capacity(num_hops);
@@ -698,12 +4161,14 @@ fn packet_payloads_and_keys<T: CustomOnionMessageContents, S: secp256k1::Signing
 		payloads.push((Payload::Receive {
 			control_tlvs,
 			reply_path: reply_path.take(),
+			receipt_nonce,
 			message,
 		}, prev_control_tlvs_ss.unwrap()));
 	} else {
 		payloads.push((Payload::Receive {
 			control_tlvs: ReceiveControlTlvs::Unblinded(ReceiveTlvs { path_id: None, }),
 			reply_path: reply_path.take(),
+			receipt_nonce,
 			message,
 		}, prev_control_tlvs_ss.unwrap()));
 	}
@@ -20,13 +20,21 @@
 //! [offers]: <https://github.com/lightning/bolts/pull/798>
 //! [blinded paths]: crate::blinded_path::BlindedPath
 
+mod dlc;
 mod messenger;
 mod offers;
 mod packet;
+mod rpc;
+mod signed;
 #[cfg(test)]
 mod functional_tests;
 
 // Re-export structs so they can be imported with just the `onion_message::` module prefix.
-pub use self::messenger::{CustomOnionMessageContents, CustomOnionMessageHandler, DefaultMessageRouter, Destination, MessageRouter, OnionMessageContents, OnionMessagePath, OnionMessenger, SendError, SimpleArcOnionMessenger, SimpleRefOnionMessenger};
+pub use self::messenger::{AnyOnionMessenger, ChannelPeerLookup, create_onion_message, CustomOnionMessageContents, CustomOnionMessageDelivery, CustomOnionMessageHandler, DefaultMessageRouter, DefaultMessageRouterParams, Destination, LivenessStats, MessageRouter, OnionMessageBufferOccupancy, OnionMessageContents, OnionMessageDedupConfig, OnionMessageDeliveryId, OnionMessageEvictionPolicy, OnionMessageForwardingPolicy, OnionMessageForwardingStats, OnionMessageMailboxConfig, OnionMessagePath, OnionMessagePriority, OnionMessageRateLimit, OnionMessageRateLimitObserver, OnionMessageRateLimits, OnionMessageReceivedVia, OnionMessageRequestId, OnionMessenger, OnionMessengerConfig, OnionMessengerStats, peel_onion_message, PeeledOnion, PendingOnionMessages, PENDING_ONION_MESSAGES_PERSISTENCE_KEY, RateLimitDirection, Responder, SendError, SimpleArcOnionMessenger, SimpleRefOnionMessenger};
+pub(crate) use self::messenger::onion_message_receipt_hash;
+pub use self::dlc::{DLC_ACCEPT_TLV_TYPE, DLC_OFFER_TLV_TYPE, DLC_SETTLE_TLV_TYPE, DLC_SIGN_TLV_TYPE, DlcAccept, DlcMessage, DlcMessageHandler, DlcOffer, DlcSettle, DlcSign};
 pub use self::offers::{OffersMessage, OffersMessageHandler};
 pub(crate) use self::packet::{ControlTlvs, Packet};
+pub use self::rpc::{DEFAULT_RPC_REQUEST_TIMEOUT_TICKS, OnionMessageRpc, OnionMessageService, RpcMessage, RpcRequestFailure};
+pub use self::signed::{KeyDelegation, SignedCustomMessage, SignedCustomMessageVerifier};
+pub(crate) use self::signed::{key_delegation_hash, signed_custom_message_hash};
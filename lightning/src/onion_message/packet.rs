@@ -16,6 +16,7 @@ use crate::blinded_path::{BlindedPath, ForwardTlvs, ReceiveTlvs};
 use crate::ln::msgs::DecodeError;
 use crate::ln::onion_utils;
 use super::messenger::CustomOnionMessageHandler;
+use super::dlc::DlcMessage;
 use super::offers::OffersMessage;
 use crate::util::chacha20poly1305rfc::{ChaChaPolyReadAdapter, ChaChaPolyWriteAdapter};
 use crate::util::logger::Logger;
@@ -102,6 +103,8 @@ pub(super) enum Payload<T: CustomOnionMessageContents> {
 	Receive {
 		control_tlvs: ReceiveControlTlvs,
 		reply_path: Option<BlindedPath>,
+		/// If set, the sender requests a signed receipt over `reply_path` committing to this nonce.
+		receipt_nonce: Option<[u8; 32]>,
 		message: OnionMessageContents<T>,
 	}
 }
@@ -112,6 +115,8 @@ pub(super) enum Payload<T: CustomOnionMessageContents> {
 pub enum OnionMessageContents<T: CustomOnionMessageContents> {
 	/// A message related to BOLT 12 Offers.
 	Offers(OffersMessage),
+	/// A message negotiating a discreet log contract.
+	Dlc(DlcMessage),
 	/// A custom onion message specified by the user.
 	Custom(T),
 }
@@ -123,6 +128,7 @@ impl<T: CustomOnionMessageContents> OnionMessageContents<T> {
 	pub fn tlv_type(&self) -> u64 {
 		match self {
 			&OnionMessageContents::Offers(ref msg) => msg.tlv_type(),
+			&OnionMessageContents::Dlc(ref msg) => msg.tlv_type(),
 			&OnionMessageContents::Custom(ref msg) => msg.tlv_type(),
 		}
 	}
@@ -133,6 +139,7 @@ impl<T: CustomOnionMessageContents> Writeable for OnionMessageContents<T> {
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
 		match self {
 			OnionMessageContents::Offers(msg) => Ok(msg.write(w)?),
+			OnionMessageContents::Dlc(msg) => Ok(msg.write(w)?),
 			OnionMessageContents::Custom(msg) => Ok(msg.write(w)?),
 		}
 	}
@@ -141,9 +148,28 @@ impl<T: CustomOnionMessageContents> Writeable for OnionMessageContents<T> {
 /// The contents of a custom onion message.
 pub trait CustomOnionMessageContents: Writeable {
 	/// Returns the TLV type identifying the message contents. MUST be >= 64.
+	///
+	/// The following types are reserved for messages handled by the [`OnionMessenger`] itself,
+	/// which are never passed to a [`CustomOnionMessageHandler`], and MUST NOT be used:
+	///  * 64, 66 and 68, used by BOLT 12 [`OffersMessage`]s,
+	///  * 65_551, 65_555 and 65_557, used for message fragments, delivery receipts and path probes,
+	///  * 65_559 through 65_565, used by [`DlcMessage`]s,
+	///  * 65_567 and 65_569, used for liveness probes.
+	///
+	/// Sending a custom message of a reserved type fails with [`SendError::InvalidMessage`].
+	///
+	/// [`OnionMessenger`]: super::OnionMessenger
+	/// [`CustomOnionMessageHandler`]: super::CustomOnionMessageHandler
+	/// [`OffersMessage`]: super::OffersMessage
+	/// [`DlcMessage`]: super::DlcMessage
+	/// [`SendError::InvalidMessage`]: super::SendError::InvalidMessage
 	fn tlv_type(&self) -> u64;
 }
 
+/// The onion message payload TLV type requesting a delivery receipt. It is odd, so that nodes which
+/// don't support receipts ignore it, and below the range of message TLV types.
+pub(super) const RECEIPT_REQUEST_TLV_TYPE: u64 = 63;
+
 /// Forward control TLVs in their blinded and unblinded form.
 pub(super) enum ForwardControlTlvs {
 	/// If we're sending to a blinded path, the node that constructed the blinded path has provided
@@ -173,11 +199,12 @@ impl<T: CustomOnionMessageContents> Writeable for (Payload<T>, [u8; 32]) {
 				})
 			},
 			Payload::Receive {
-				control_tlvs: ReceiveControlTlvs::Blinded(encrypted_bytes), reply_path, message,
+				control_tlvs: ReceiveControlTlvs::Blinded(encrypted_bytes), reply_path, receipt_nonce, message,
 			} => {
 				_encode_varint_length_prefixed_tlv!(w, {
 					(2, reply_path, option),
 					(4, *encrypted_bytes, required_vec),
+					(RECEIPT_REQUEST_TLV_TYPE, receipt_nonce, option),
 					(message.tlv_type(), message, required)
 				})
 			},
@@ -188,12 +215,13 @@ impl<T: CustomOnionMessageContents> Writeable for (Payload<T>, [u8; 32]) {
 				})
 			},
 			Payload::Receive {
-				control_tlvs: ReceiveControlTlvs::Unblinded(control_tlvs), reply_path, message,
+				control_tlvs: ReceiveControlTlvs::Unblinded(control_tlvs), reply_path, receipt_nonce, message,
 			} => {
 				let write_adapter = ChaChaPolyWriteAdapter::new(self.1, &control_tlvs);
 				_encode_varint_length_prefixed_tlv!(w, {
 					(2, reply_path, option),
 					(4, write_adapter, required),
+					(RECEIPT_REQUEST_TLV_TYPE, receipt_nonce, option),
 					(message.tlv_type(), message, required)
 				})
 			},
@@ -213,11 +241,14 @@ ReadableArgs<(SharedSecret, &H, &L)> for Payload<<H as CustomOnionMessageHandler
 		let mut reply_path: Option<BlindedPath> = None;
 		let mut read_adapter: Option<ChaChaPolyReadAdapter<ControlTlvs>> = None;
 		let rho = onion_utils::gen_rho_from_shared_secret(&encrypted_tlvs_ss.secret_bytes());
+		let mut receipt_nonce: Option<[u8; 32]> = None;
 		let mut message_type: Option<u64> = None;
 		let mut message = None;
 		decode_tlv_stream_with_custom_tlv_decode!(&mut rd, {
 			(2, reply_path, option),
 			(4, read_adapter, (option: LengthReadableArgs, rho)),
+			// Must match RECEIPT_REQUEST_TLV_TYPE.
+			(63, receipt_nonce, option),
 		}, |msg_type, msg_reader| {
 			if msg_type < 64 { return Ok(false) }
 			// Don't allow reading more than one data TLV from an onion message.
@@ -230,6 +261,11 @@ ReadableArgs<(SharedSecret, &H, &L)> for Payload<<H as CustomOnionMessageHandler
 					message = Some(OnionMessageContents::Offers(msg));
 					Ok(true)
 				},
+				tlv_type if DlcMessage::is_known_type(tlv_type) => {
+					let msg = DlcMessage::read(msg_reader, tlv_type)?;
+					message = Some(OnionMessageContents::Dlc(msg));
+					Ok(true)
+				},
 				_ => match handler.read_custom_message(msg_type, msg_reader)? {
 					Some(msg) => {
 						message = Some(OnionMessageContents::Custom(msg));
@@ -253,6 +289,7 @@ ReadableArgs<(SharedSecret, &H, &L)> for Payload<<H as CustomOnionMessageHandler
 				Ok(Payload::Receive {
 					control_tlvs: ReceiveControlTlvs::Unblinded(tlvs),
 					reply_path,
+					receipt_nonce,
 					message: message.ok_or(DecodeError::InvalidValue)?,
 				})
 			},
@@ -0,0 +1,303 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! A typed request/response layer over custom onion messages.
+
+use crate::ln::msgs::DecodeError;
+use crate::sign::EntropySource;
+use super::messenger::{CustomOnionMessageHandler, Destination, OnionMessageReceivedVia, OnionMessageRequestId, Responder};
+use super::packet::CustomOnionMessageContents;
+use crate::util::ser::{Readable, Writeable, Writer};
+
+use core::ops::{Deref, Range};
+use crate::io;
+use crate::io_extras::read_to_end;
+use crate::sync::Mutex;
+use crate::prelude::*;
+
+/// The number of calls to [`OnionMessageHandler::timer_tick_occurred`] after which a request we
+/// sent is considered timed out if no response has been received, unless configured otherwise.
+///
+/// [`OnionMessageHandler::timer_tick_occurred`]: crate::ln::msgs::OnionMessageHandler::timer_tick_occurred
+pub const DEFAULT_RPC_REQUEST_TIMEOUT_TICKS: u16 = 6;
+
+/// The reason a request sent via [`OnionMessageRpc::send_request`] failed, as passed to
+/// [`OnionMessageService::handle_request_failed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RpcRequestFailure {
+	/// The request couldn't be sent, e.g. as no path to its destination was found, or no response
+	/// was received before it timed out.
+	NoResponse,
+	/// A response was received, but couldn't be decoded as the service's response type.
+	InvalidResponse(DecodeError),
+}
+
+/// A request/response protocol run over onion messages via an [`OnionMessageRpc`].
+///
+/// Services declare the TLV types they use along with their request and response types, which
+/// are (de)serialized automatically, leaving only the handling of each message to implement.
+pub trait OnionMessageService {
+	/// The range of custom onion message TLV types reserved for this service. It must contain at
+	/// least two types, all of which must be >= 64 and not reserved as documented in
+	/// [`CustomOnionMessageContents::tlv_type`], and must not overlap with the range of any other
+	/// service registered with the same [`OnionMessageRpc`].
+	///
+	/// Requests are sent with the first type in the range and responses with the second. Any
+	/// further types are reserved for the service, e.g. for later versions of its protocol, and
+	/// are ignored.
+	const TLV_TYPES: Range<u64>;
+
+	/// The requests handled by the service.
+	type Request: Writeable + Readable;
+
+	/// The responses to [`Self::Request`]s.
+	type Response: Writeable + Readable;
+
+	/// Called with a request received from another node and how it reached us, returning the
+	/// response to send back over the request's reply path, if any.
+	fn handle_request(
+		&self, request: Self::Request, received_via: OnionMessageReceivedVia
+	) -> Option<Self::Response>;
+
+	/// Called with the response to a request we sent via [`OnionMessageRpc::send_request`] with
+	/// the given `request_id`, if it was received before the request timed out.
+	fn handle_response(&self, response: Self::Response, request_id: OnionMessageRequestId);
+
+	/// Called when a request we sent via [`OnionMessageRpc::send_request`] with the given
+	/// `request_id` failed, i.e. it couldn't be sent, timed out or was responded to with an invalid
+	/// response. No further response to the request is passed to [`Self::handle_response`].
+	///
+	/// The default implementation does nothing.
+	fn handle_request_failed(&self, _request_id: OnionMessageRequestId, _failure: RpcRequestFailure) {}
+}
+
+/// An [`OnionMessageService`] with its message types erased, allowing services with different
+/// message types to be registered with the same [`OnionMessageRpc`].
+trait ErasedService {
+	fn handle_request(
+		&self, payload: &[u8], received_via: OnionMessageReceivedVia
+	) -> Result<Option<Vec<u8>>, DecodeError>;
+	fn handle_response(&self, payload: &[u8], request_id: OnionMessageRequestId) -> Result<(), DecodeError>;
+	fn handle_request_failed(&self, request_id: OnionMessageRequestId, failure: RpcRequestFailure);
+}
+
+struct ServiceAdapter<S: OnionMessageService>(S);
+
+impl<S: OnionMessageService> ErasedService for ServiceAdapter<S> {
+	fn handle_request(
+		&self, payload: &[u8], received_via: OnionMessageReceivedVia
+	) -> Result<Option<Vec<u8>>, DecodeError> {
+		let request: S::Request = Readable::read(&mut &payload[..])?;
+		Ok(self.0.handle_request(request, received_via).map(|response| response.encode()))
+	}
+
+	fn handle_response(&self, payload: &[u8], request_id: OnionMessageRequestId) -> Result<(), DecodeError> {
+		let response: S::Response = Readable::read(&mut &payload[..])?;
+		self.0.handle_response(response, request_id);
+		Ok(())
+	}
+
+	fn handle_request_failed(&self, request_id: OnionMessageRequestId, failure: RpcRequestFailure) {
+		self.0.handle_request_failed(request_id, failure)
+	}
+}
+
+struct RegisteredService {
+	tlv_types: Range<u64>,
+	service: Box<dyn ErasedService + Send + Sync>,
+}
+
+impl RegisteredService {
+	fn request_type(&self) -> u64 { self.tlv_types.start }
+	fn response_type(&self) -> u64 { self.tlv_types.start + 1 }
+}
+
+/// A request or response of an [`OnionMessageService`] as sent over the wire.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RpcMessage {
+	tlv_type: u64,
+	payload: Vec<u8>,
+}
+
+impl CustomOnionMessageContents for RpcMessage {
+	fn tlv_type(&self) -> u64 { self.tlv_type }
+}
+
+impl Writeable for RpcMessage {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		w.write_all(&self.payload)
+	}
+}
+
+/// A [`CustomOnionMessageHandler`] dispatching requests and responses to registered
+/// [`OnionMessageService`]s based on their TLV types.
+///
+/// Requests queued via [`Self::send_request`] are sent by the [`OnionMessenger`] via
+/// [`OnionMessenger::send_onion_message_request`], which matches responses with requests via
+/// their reply paths and times out requests on [`OnionMessageHandler::timer_tick_occurred`].
+/// Responses are passed to [`OnionMessageService::handle_response`], while requests which can't
+/// be sent, time out or are responded to with an invalid response are passed to
+/// [`OnionMessageService::handle_request_failed`].
+///
+/// Services should be registered via [`Self::register_service`] before the handler is passed to
+/// [`OnionMessenger::new`]. To combine services with other custom onion messages, wrap the
+/// handler in one which delegates messages of the services' types to it.
+///
+/// [`OnionMessenger`]: super::OnionMessenger
+/// [`OnionMessenger::new`]: super::OnionMessenger::new
+/// [`OnionMessenger::send_onion_message_request`]: super::OnionMessenger::send_onion_message_request
+/// [`OnionMessageHandler::timer_tick_occurred`]: crate::ln::msgs::OnionMessageHandler::timer_tick_occurred
+pub struct OnionMessageRpc<ES: Deref> where ES::Target: EntropySource {
+	entropy_source: ES,
+	request_timeout_ticks: u16,
+	services: Vec<RegisteredService>,
+	/// The request type of each request we sent which has neither been responded to nor failed.
+	pending_requests: Mutex<HashMap<OnionMessageRequestId, u64>>,
+	pending_messages: Mutex<Vec<(RpcMessage, Destination, OnionMessageRequestId, u16)>>,
+}
+
+impl<ES: Deref> OnionMessageRpc<ES> where ES::Target: EntropySource {
+	/// Constructs a new [`OnionMessageRpc`] with no registered services, timing out requests after
+	/// [`DEFAULT_RPC_REQUEST_TIMEOUT_TICKS`].
+	pub fn new(entropy_source: ES) -> Self {
+		Self::with_request_timeout(entropy_source, DEFAULT_RPC_REQUEST_TIMEOUT_TICKS)
+	}
+
+	/// Constructs a new [`OnionMessageRpc`] with no registered services, timing out requests as
+	/// described in [`OnionMessenger::send_onion_message_request`] with the given
+	/// `request_timeout_ticks`.
+	///
+	/// [`OnionMessenger::send_onion_message_request`]: super::OnionMessenger::send_onion_message_request
+	pub fn with_request_timeout(entropy_source: ES, request_timeout_ticks: u16) -> Self {
+		OnionMessageRpc {
+			entropy_source,
+			request_timeout_ticks,
+			services: Vec::new(),
+			pending_requests: Mutex::new(HashMap::new()),
+			pending_messages: Mutex::new(Vec::new()),
+		}
+	}
+
+	/// Registers `service` to handle messages of its [`OnionMessageService::TLV_TYPES`].
+	///
+	/// Fails if the service's range of TLV types is invalid or overlaps with that of an already
+	/// registered service.
+	pub fn register_service<S: OnionMessageService + Send + Sync + 'static>(
+		&mut self, service: S
+	) -> Result<(), ()> {
+		let tlv_types = S::TLV_TYPES;
+		if tlv_types.start < 64 || tlv_types.end.saturating_sub(tlv_types.start) < 2 {
+			return Err(());
+		}
+		let overlaps = self.services.iter().any(|registered|
+			registered.tlv_types.start < tlv_types.end && tlv_types.start < registered.tlv_types.end
+		);
+		if overlaps { return Err(()); }
+		self.services.push(RegisteredService { tlv_types, service: Box::new(ServiceAdapter(service)) });
+		Ok(())
+	}
+
+	/// Queues `request` for sending to `destination` via the registered service `S`, returning the
+	/// [`OnionMessageRequestId`] its response will be passed to
+	/// [`OnionMessageService::handle_response`] with.
+	///
+	/// The request is sent, along with a reply path back to us for the response, the next time the
+	/// [`OnionMessenger`] is polled for outbound messages.
+	///
+	/// Fails if no service of type `S` has been registered.
+	///
+	/// [`OnionMessenger`]: super::OnionMessenger
+	pub fn send_request<S: OnionMessageService>(
+		&self, request: &S::Request, destination: Destination
+	) -> Result<OnionMessageRequestId, ()> {
+		let request_type = self.services.iter()
+			.find(|registered| registered.tlv_types == S::TLV_TYPES)
+			.map(|registered| registered.request_type())
+			.ok_or(())?;
+		let request_id = OnionMessageRequestId(self.entropy_source.get_secure_random_bytes());
+		self.pending_requests.lock().unwrap().insert(request_id, request_type);
+		let message = RpcMessage { tlv_type: request_type, payload: request.encode() };
+		self.pending_messages.lock().unwrap()
+			.push((message, destination, request_id, self.request_timeout_ticks));
+		Ok(request_id)
+	}
+
+	/// Returns the ids of requests we sent which have neither been responded to nor failed.
+	pub fn list_pending_requests(&self) -> Vec<OnionMessageRequestId> {
+		self.pending_requests.lock().unwrap().keys().copied().collect()
+	}
+
+	fn service_for_type(&self, tlv_type: u64) -> Option<&RegisteredService> {
+		self.services.iter().find(|registered| registered.tlv_types.contains(&tlv_type))
+	}
+
+	/// Removes the pending request with the given id, returning the service which sent it.
+	fn take_pending_request(&self, request_id: &OnionMessageRequestId) -> Option<&RegisteredService> {
+		let request_type = self.pending_requests.lock().unwrap().remove(request_id)?;
+		self.service_for_type(request_type)
+	}
+}
+
+impl<ES: Deref> CustomOnionMessageHandler for OnionMessageRpc<ES> where ES::Target: EntropySource {
+	type CustomMessage = RpcMessage;
+
+	fn handle_custom_message(&self, msg: RpcMessage) -> Option<RpcMessage> {
+		self.handle_custom_message_with_context(msg, OnionMessageReceivedVia::Direct, None)
+	}
+
+	fn handle_custom_message_with_context(
+		&self, msg: RpcMessage, received_via: OnionMessageReceivedVia,
+		_responder: Option<Responder>
+	) -> Option<RpcMessage> {
+		// Responses are only accepted via `handle_custom_response`, i.e. along the reply path of a
+		// request we sent.
+		let registered = self.service_for_type(msg.tlv_type)
+			.filter(|registered| msg.tlv_type == registered.request_type())?;
+		match registered.service.handle_request(&msg.payload, received_via) {
+			Ok(Some(payload)) => Some(RpcMessage { tlv_type: registered.response_type(), payload }),
+			Ok(None) | Err(_) => None,
+		}
+	}
+
+	fn handle_custom_response(
+		&self, msg: RpcMessage, request_id: OnionMessageRequestId, _responder: Option<Responder>
+	) -> Option<RpcMessage> {
+		let registered = self.take_pending_request(&request_id)?;
+		let res = if msg.tlv_type == registered.response_type() {
+			registered.service.handle_response(&msg.payload, request_id)
+		} else {
+			Err(DecodeError::InvalidValue)
+		};
+		if let Err(e) = res {
+			registered.service.handle_request_failed(request_id, RpcRequestFailure::InvalidResponse(e));
+		}
+		None
+	}
+
+	fn handle_custom_request_failed(&self, request_id: OnionMessageRequestId) {
+		if let Some(registered) = self.take_pending_request(&request_id) {
+			registered.service.handle_request_failed(request_id, RpcRequestFailure::NoResponse);
+		}
+	}
+
+	fn read_custom_message<R: io::Read>(
+		&self, message_type: u64, buffer: &mut R
+	) -> Result<Option<RpcMessage>, DecodeError> {
+		match self.service_for_type(message_type) {
+			Some(registered) if message_type <= registered.response_type() => {},
+			_ => return Ok(None),
+		}
+		let payload = read_to_end(buffer)?;
+		Ok(Some(RpcMessage { tlv_type: message_type, payload }))
+	}
+
+	fn release_pending_custom_requests(&self) -> Vec<(RpcMessage, Destination, OnionMessageRequestId, u16)> {
+		core::mem::take(&mut *self.pending_messages.lock().unwrap())
+	}
+}
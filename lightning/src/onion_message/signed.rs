@@ -0,0 +1,310 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Custom onion message payloads authenticated by the sender's node key.
+
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::sha256::Hash as Sha256;
+use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signing, Verification};
+use bitcoin::secp256k1::ecdsa::Signature;
+
+use crate::blinded_path::BlindedPath;
+use crate::ln::msgs::DecodeError;
+use crate::sign::{NodeSigner, Recipient};
+use super::messenger::Responder;
+use super::packet::CustomOnionMessageContents;
+use crate::util::ser::{BigSize, Readable, Writeable, Writer};
+
+use core::ops::Deref;
+use core::time::Duration;
+use crate::io;
+use crate::prelude::*;
+
+/// Returns the message which is signed to produce a [`SignedCustomMessage`] for a custom message
+/// with the given `tlv_type` and encoded `payload`, sent to `recipient` with `reply_path`.
+pub(crate) fn signed_custom_message_hash(
+	tlv_type: u64, recipient: &PublicKey, reply_path: Option<&BlindedPath>, payload: &[u8]
+) -> Message {
+	let mut engine = Sha256::engine();
+	engine.input(b"LDK signed custom onion message");
+	engine.input(&BigSize(tlv_type).encode());
+	engine.input(&recipient.serialize());
+	match reply_path {
+		Some(reply_path) => {
+			engine.input(&[1]);
+			engine.input(&reply_path.encode());
+		},
+		None => engine.input(&[0]),
+	}
+	engine.input(payload);
+	hash_to_message!(&Sha256::from_engine(engine).into_inner())
+}
+
+/// Returns the message which is signed to produce a [`KeyDelegation`] from `node_id` to
+/// `delegate_pubkey`, valid until `expires_at`.
+pub(crate) fn key_delegation_hash(
+	node_id: &PublicKey, delegate_pubkey: &PublicKey, expires_at: Duration
+) -> Message {
+	let mut engine = Sha256::engine();
+	engine.input(b"LDK onion message key delegation");
+	engine.input(&node_id.serialize());
+	engine.input(&delegate_pubkey.serialize());
+	engine.input(&expires_at.as_secs().to_be_bytes());
+	hash_to_message!(&Sha256::from_engine(engine).into_inner())
+}
+
+/// A certificate, signed with a node's secret key, authorizing another key to sign
+/// [`SignedCustomMessage`]s on the node's behalf until it expires.
+///
+/// This allows the component producing messages (e.g. an order-entry service) to authenticate
+/// them as coming from the node without having access to the node secret. Delegations should be
+/// short-lived and renewed as needed. One which must stop being accepted before it expires, e.g.
+/// because the delegate key was compromised, can be revoked by recipients via
+/// [`SignedCustomMessageVerifier::revoke_delegation`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyDelegation {
+	node_id: PublicKey,
+	delegate_pubkey: PublicKey,
+	expires_at: Duration,
+	signature: Signature,
+}
+
+impl KeyDelegation {
+	/// Creates a delegation from the node of `node_signer` to `delegate_pubkey`, valid until
+	/// `expires_at`, given as seconds since the Unix epoch.
+	///
+	/// Fails if the [`NodeSigner`] fails to provide our node id or to sign the delegation.
+	pub fn new<NS: Deref>(
+		delegate_pubkey: PublicKey, expires_at: Duration, node_signer: &NS
+	) -> Result<Self, ()>
+	where NS::Target: NodeSigner {
+		let node_id = node_signer.get_node_id(Recipient::Node)?;
+		let expires_at = Duration::from_secs(expires_at.as_secs());
+		let signature = node_signer.sign_onion_message_key_delegation(&delegate_pubkey, expires_at)?;
+		Ok(Self { node_id, delegate_pubkey, expires_at, signature })
+	}
+
+	/// The node on whose behalf the delegate key may sign.
+	pub fn node_id(&self) -> PublicKey { self.node_id }
+
+	/// The key authorized to sign messages on behalf of [`Self::node_id`].
+	pub fn delegate_pubkey(&self) -> PublicKey { self.delegate_pubkey }
+
+	/// The time, as seconds since the Unix epoch, after which the delegation is no longer valid.
+	pub fn expires_at(&self) -> Duration { self.expires_at }
+
+	/// Whether the delegation has expired at the given time since the Unix epoch.
+	pub fn is_expired(&self, duration_since_epoch: Duration) -> bool {
+		duration_since_epoch > self.expires_at
+	}
+
+	fn verify<C: Verification>(&self, secp_ctx: &Secp256k1<C>) -> Result<(), ()> {
+		let msg_hash = key_delegation_hash(&self.node_id, &self.delegate_pubkey, self.expires_at);
+		secp_ctx.verify_ecdsa(&msg_hash, &self.signature, &self.node_id).map_err(|_| ())
+	}
+}
+
+/// The state needed to verify [`SignedCustomMessage`]s sent to us.
+///
+/// Signatures commit to the recipient's node id, so a message signed for another node cannot be
+/// replayed to us. Delegations which should no longer be accepted can be revoked via
+/// [`Self::revoke_delegation`]. Revocations are not persisted and must be re-applied on startup.
+pub struct SignedCustomMessageVerifier {
+	our_node_id: PublicKey,
+	revoked_delegations: HashSet<(PublicKey, PublicKey)>,
+}
+
+impl SignedCustomMessageVerifier {
+	/// Creates a verifier for messages sent to the node with id `our_node_id`.
+	pub fn new(our_node_id: PublicKey) -> Self {
+		Self { our_node_id, revoked_delegations: HashSet::new() }
+	}
+
+	/// Stops accepting messages signed by `delegate_pubkey` on behalf of `node_id`, regardless of
+	/// the expiry of the delegation.
+	pub fn revoke_delegation(&mut self, node_id: PublicKey, delegate_pubkey: PublicKey) {
+		self.revoked_delegations.insert((node_id, delegate_pubkey));
+	}
+
+	/// Whether the given delegation was revoked via [`Self::revoke_delegation`].
+	pub fn is_revoked(&self, delegation: &KeyDelegation) -> bool {
+		self.revoked_delegations.contains(&(delegation.node_id, delegation.delegate_pubkey))
+	}
+}
+
+/// A [`CustomOnionMessageContents`] wrapped with a signature over its encoding, its recipient and
+/// the reply path it was sent with, allowing the recipient to authenticate the node which sent it.
+///
+/// Onion messages are anonymous by design, so a recipient otherwise has no way to tell who sent a
+/// message, which is required for e.g. accepting order flow from a derivative counterparty. The
+/// signature is made either with the sender's node key via [`NodeSigner`] or with a key the node
+/// has delegated signing to via a [`KeyDelegation`].
+///
+/// The wrapper uses the TLV type of the inner contents, so a [`CustomOnionMessageHandler`] should
+/// parse messages of types it expects to be signed with [`SignedCustomMessage::read`], which only
+/// succeeds if the signature is valid and the message was signed for us. Note that a valid
+/// signature only authenticates the node returned by [`SignedCustomMessage::node_id`]; the handler
+/// must still check it is a node it expects messages from, and should only reply along the signed
+/// reply path, checked with [`SignedCustomMessage::verify_responder`]. Signatures do not protect
+/// against replays to the same recipient, which should be handled by including a nonce or sequence
+/// number in the contents.
+///
+/// [`CustomOnionMessageHandler`]: super::CustomOnionMessageHandler
+#[derive(Clone, Debug)]
+pub struct SignedCustomMessage<T: CustomOnionMessageContents> {
+	contents: T,
+	payload: Vec<u8>,
+	node_id: PublicKey,
+	reply_path: Option<BlindedPath>,
+	signature: Signature,
+	delegation: Option<KeyDelegation>,
+}
+
+impl<T: CustomOnionMessageContents> SignedCustomMessage<T> {
+	/// Signs `contents`, to be sent to `recipient` with `reply_path`, with the node key of
+	/// `node_signer`.
+	///
+	/// Fails if the [`NodeSigner`] fails to provide our node id or to sign the message.
+	pub fn sign<NS: Deref>(
+		contents: T, recipient: PublicKey, reply_path: Option<BlindedPath>, node_signer: &NS
+	) -> Result<Self, ()>
+	where NS::Target: NodeSigner {
+		let node_id = node_signer.get_node_id(Recipient::Node)?;
+		let payload = contents.encode();
+		let signature = node_signer.sign_custom_onion_message(
+			contents.tlv_type(), &recipient, reply_path.as_ref(), &payload)?;
+		Ok(Self { contents, payload, node_id, reply_path, signature, delegation: None })
+	}
+
+	/// Signs `contents`, to be sent to `recipient` with `reply_path`, with `delegate_key`, which
+	/// must be the key authorized by `delegation`.
+	pub fn sign_with_delegated_key<C: Signing>(
+		contents: T, recipient: PublicKey, reply_path: Option<BlindedPath>,
+		delegate_key: &SecretKey, delegation: KeyDelegation, secp_ctx: &Secp256k1<C>
+	) -> Result<Self, ()> {
+		if PublicKey::from_secret_key(secp_ctx, delegate_key) != delegation.delegate_pubkey {
+			return Err(());
+		}
+		let payload = contents.encode();
+		let msg_hash = signed_custom_message_hash(
+			contents.tlv_type(), &recipient, reply_path.as_ref(), &payload);
+		let signature = secp_ctx.sign_ecdsa(&msg_hash, delegate_key);
+		Ok(Self {
+			contents, payload, node_id: delegation.node_id, reply_path, signature,
+			delegation: Some(delegation),
+		})
+	}
+
+	/// Reads a [`SignedCustomMessage`] of type `message_type`, parsing its contents with
+	/// `read_contents` and verifying its signature against `verifier` at the given time since the
+	/// Unix epoch.
+	///
+	/// Returns `Ok(None)` if `read_contents` does not recognize the message type, in the same way as
+	/// [`CustomOnionMessageHandler::read_custom_message`], and fails if the signature is invalid,
+	/// the message was signed for another node, or it was signed with a delegation which expired
+	/// or was revoked.
+	///
+	/// [`CustomOnionMessageHandler::read_custom_message`]: super::CustomOnionMessageHandler::read_custom_message
+	pub fn read<R: io::Read, F>(
+		reader: &mut R, message_type: u64, verifier: &SignedCustomMessageVerifier,
+		duration_since_epoch: Duration, read_contents: F
+	) -> Result<Option<Self>, DecodeError>
+	where F: FnOnce(u64, &mut &[u8]) -> Result<Option<T>, DecodeError> {
+		_init_and_read_tlv_fields!(reader, {
+			(0, payload, required_vec),
+			(2, node_id, required),
+			(4, signature, required),
+			(6, delegate_pubkey, option),
+			(8, delegation_signature, option),
+			(10, delegation_expiry, option),
+			(12, reply_path, option),
+		});
+		let node_id: PublicKey = node_id.0.unwrap();
+		let signature: Signature = signature.0.unwrap();
+		let delegation = match (delegate_pubkey, delegation_signature, delegation_expiry) {
+			(Some(delegate_pubkey), Some(signature), Some(expiry)) => Some(KeyDelegation {
+				node_id, delegate_pubkey, expires_at: Duration::from_secs(expiry), signature,
+			}),
+			(None, None, None) => None,
+			_ => return Err(DecodeError::InvalidValue),
+		};
+
+		let secp_ctx = Secp256k1::verification_only();
+		let signing_pubkey = match &delegation {
+			Some(delegation) => {
+				delegation.verify(&secp_ctx).map_err(|()| DecodeError::InvalidValue)?;
+				if delegation.is_expired(duration_since_epoch) || verifier.is_revoked(delegation) {
+					return Err(DecodeError::InvalidValue);
+				}
+				delegation.delegate_pubkey
+			},
+			None => node_id,
+		};
+		let msg_hash = signed_custom_message_hash(
+			message_type, &verifier.our_node_id, reply_path.as_ref(), &payload);
+		secp_ctx.verify_ecdsa(&msg_hash, &signature, &signing_pubkey)
+			.map_err(|_| DecodeError::InvalidValue)?;
+
+		let contents = match read_contents(message_type, &mut &payload[..])? {
+			Some(contents) => contents,
+			None => return Ok(None),
+		};
+		if contents.tlv_type() != message_type {
+			return Err(DecodeError::InvalidValue);
+		}
+		Ok(Some(Self { contents, payload, node_id, reply_path, signature, delegation }))
+	}
+
+	/// The signed contents.
+	pub fn contents(&self) -> &T { &self.contents }
+
+	/// Consumes the message, returning the signed contents.
+	pub fn into_contents(self) -> T { self.contents }
+
+	/// The node which signed the message, either directly or via [`Self::delegation`].
+	pub fn node_id(&self) -> PublicKey { self.node_id }
+
+	/// The delegation used to sign the message, if it was not signed with the node key directly.
+	pub fn delegation(&self) -> Option<&KeyDelegation> { self.delegation.as_ref() }
+
+	/// The reply path the message was signed with.
+	pub fn reply_path(&self) -> Option<&BlindedPath> { self.reply_path.as_ref() }
+
+	/// Checks that the [`Responder`] a message was received with replies along the signed reply
+	/// path, failing if the reply path was replaced or added by someone other than the signer.
+	pub fn verify_responder(&self, responder: Option<&Responder>) -> Result<(), ()> {
+		if responder.map(|responder| responder.reply_path()) == self.reply_path.as_ref() {
+			Ok(())
+		} else {
+			Err(())
+		}
+	}
+}
+
+impl<T: CustomOnionMessageContents> CustomOnionMessageContents for SignedCustomMessage<T> {
+	fn tlv_type(&self) -> u64 { self.contents.tlv_type() }
+}
+
+impl<T: CustomOnionMessageContents> Writeable for SignedCustomMessage<T> {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		let delegate_pubkey = self.delegation.as_ref().map(|d| d.delegate_pubkey);
+		let delegation_signature = self.delegation.as_ref().map(|d| d.signature);
+		let delegation_expiry = self.delegation.as_ref().map(|d| d.expires_at.as_secs());
+		write_tlv_fields!(w, {
+			(0, self.payload, required_vec),
+			(2, self.node_id, required),
+			(4, self.signature, required),
+			(6, delegate_pubkey, option),
+			(8, delegation_signature, option),
+			(10, delegation_expiry, option),
+			(12, self.reply_path, option),
+		});
+		Ok(())
+	}
+}
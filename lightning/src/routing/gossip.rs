@@ -34,6 +34,18 @@ use crate::util::logger::{Logger, Level};
 use crate::util::scid_utils::{block_from_scid, scid_from_parts, MAX_SCID_BLOCK};
 use crate::util::string::PrintableString;
 use crate::util::indexed_map::{IndexedMap, Entry as IndexedMapEntry};
+#[cfg(taproot)]
+use crate::offers::merkle::message_digest;
+#[cfg(taproot)]
+use bitcoin::blockdata::script::Script;
+#[cfg(taproot)]
+use bitcoin::secp256k1::Scalar;
+#[cfg(taproot)]
+use bitcoin::hashes::HashEngine;
+#[cfg(taproot)]
+use bitcoin::hashes::sha256::Hash as Sha256;
+#[cfg(taproot)]
+use bitcoin::util::taproot::TapBranchHash;
 
 use crate::io;
 use crate::io_extras::{copy, sink};
@@ -409,13 +421,19 @@ macro_rules! get_pubkey_from_node_id {
 	}
 }
 
-/// Verifies the signature of a [`NodeAnnouncement`].
+/// Verifies the signature of a [`NodeAnnouncement`], as well as that of the
+/// [`msgs::NodeKeyRotation`] it may carry.
 ///
-/// Returns an error if it is invalid.
+/// Returns an error if either is invalid.
 pub fn verify_node_announcement<C: Verification>(msg: &NodeAnnouncement, secp_ctx: &Secp256k1<C>) -> Result<(), LightningError> {
 	let msg_hash = hash_to_message!(&Sha256dHash::hash(&msg.contents.encode()[..])[..]);
 	secp_verify_sig!(secp_ctx, &msg_hash, &msg.signature, &get_pubkey_from_node_id!(msg.contents.node_id, "node_announcement"), "node_announcement");
 
+	if let Some(rotation) = msg.contents.node_key_rotation() {
+		let rotation_hash = msgs::NodeKeyRotation::message_hash(&msg.contents.node_id, &rotation.new_node_id);
+		secp_verify_sig!(secp_ctx, &rotation_hash, &rotation.new_node_signature, &get_pubkey_from_node_id!(rotation.new_node_id, "node_announcement"), "node_announcement");
+	}
+
 	Ok(())
 }
 
@@ -432,6 +450,142 @@ pub fn verify_channel_announcement<C: Verification>(msg: &ChannelAnnouncement, s
 	Ok(())
 }
 
+/// The tag used when computing the digest an experimental `channel_announcement_2` is signed over.
+#[cfg(taproot)]
+const CHANNEL_ANNOUNCEMENT_2_SIGNATURE_TAG: &str = "lightningchannel_announcement_2signature";
+
+/// Reduces a 256-bit big-endian integer modulo the curve order. As the curve order is larger than
+/// 2^255, at most one subtraction is required.
+#[cfg(taproot)]
+fn reduce_scalar(mut bytes: [u8; 32]) -> Scalar {
+	if let Ok(scalar) = Scalar::from_be_bytes(bytes) { return scalar; }
+	let mut borrow = 0;
+	for i in (0..32).rev() {
+		let diff = bytes[i] as i16 - bitcoin::secp256k1::constants::CURVE_ORDER[i] as i16 - borrow;
+		if diff < 0 {
+			bytes[i] = (diff + 256) as u8;
+			borrow = 1;
+		} else {
+			bytes[i] = diff as u8;
+			borrow = 0;
+		}
+	}
+	Scalar::from_be_bytes(bytes).expect("Values above the curve order are reduced by one subtraction")
+}
+
+/// Sorts the given keys as in BIP 327 (MuSig2) `KeySort`.
+#[cfg(taproot)]
+fn musig2_key_sort(keys: &[PublicKey]) -> Vec<PublicKey> {
+	let mut keys = keys.to_vec();
+	keys.sort_unstable_by_key(|key| key.serialize());
+	keys
+}
+
+/// Computes each of the given keys' BIP 327 (MuSig2) `KeyAgg` coefficient, in the given order,
+/// where `None` denotes the coefficient one given to the second distinct key.
+#[cfg(taproot)]
+fn musig2_key_agg_coefficients(keys: &[PublicKey]) -> Vec<(PublicKey, Option<Scalar>)> {
+	let tagged_engine = |tag: &[u8]| {
+		let tag = Sha256::hash(tag);
+		let mut engine = Sha256::engine();
+		engine.input(&tag[..]);
+		engine.input(&tag[..]);
+		engine
+	};
+
+	let mut list_engine = tagged_engine(b"KeyAgg list");
+	for key in keys.iter() {
+		list_engine.input(&key.serialize());
+	}
+	let list_hash = Sha256::from_engine(list_engine);
+
+	let second_key = keys.iter().find(|key| **key != keys[0]).copied();
+	keys.iter().map(|key| {
+		if Some(*key) == second_key {
+			return (*key, None);
+		}
+		let mut coefficient_engine = tagged_engine(b"KeyAgg coefficient");
+		coefficient_engine.input(&list_hash[..]);
+		coefficient_engine.input(&key.serialize());
+		(*key, Some(reduce_scalar(Sha256::from_engine(coefficient_engine).into_inner())))
+	}).collect()
+}
+
+/// Computes the BIP 327 (MuSig2) `KeyAgg` of the given keys, in the given order.
+#[cfg(taproot)]
+fn musig2_key_agg<C: Verification>(keys: &[PublicKey], secp_ctx: &Secp256k1<C>) -> Result<PublicKey, ()> {
+	let mut tweaked_keys = Vec::with_capacity(keys.len());
+	for (key, coefficient) in musig2_key_agg_coefficients(keys) {
+		match coefficient {
+			Some(coefficient) => tweaked_keys.push(key.mul_tweak(secp_ctx, &coefficient).map_err(|_| ())?),
+			None => tweaked_keys.push(key),
+		}
+	}
+	let tweaked_keys: Vec<&PublicKey> = tweaked_keys.iter().collect();
+	PublicKey::combine_keys(&tweaked_keys).map_err(|_| ())
+}
+
+/// Computes the BIP 327 (MuSig2) aggregate of the given keys, sorting them first.
+#[cfg(taproot)]
+fn musig2_aggregate_key<C: Verification>(keys: &[PublicKey], secp_ctx: &Secp256k1<C>) -> Result<PublicKey, ()> {
+	musig2_key_agg(&musig2_key_sort(keys), secp_ctx)
+}
+
+/// Computes the taproot output script the funding output of an experimental
+/// `channel_announcement_2` must pay to.
+///
+/// Its internal key is the aggregate of the bitcoin keys, if present, or of the node ids
+/// otherwise, tweaked by the announced merkle root, if any.
+#[cfg(taproot)]
+fn channel_announcement_2_funding_script<C: Verification>(
+	msg: &msgs::UnsignedChannelAnnouncement2, secp_ctx: &Secp256k1<C>
+) -> Result<Script, LightningError> {
+	let keys = match (msg.bitcoin_key_1, msg.bitcoin_key_2) {
+		(Some(key_1), Some(key_2)) => vec![key_1, key_2],
+		_ => vec![
+			get_pubkey_from_node_id!(msg.node_id_1, "channel_announcement_2"),
+			get_pubkey_from_node_id!(msg.node_id_2, "channel_announcement_2"),
+		],
+	};
+	let internal_key = musig2_aggregate_key(&keys, secp_ctx).map_err(|_| LightningError {
+		err: "Failed to aggregate channel_announcement_2 funding keys".to_owned(),
+		action: ErrorAction::IgnoreError,
+	})?;
+	let merkle_root = msg.merkle_root_hash.map(TapBranchHash::from_inner);
+	Ok(Script::new_v1_p2tr(secp_ctx, internal_key.x_only_public_key().0, merkle_root))
+}
+
+/// Verifies the aggregate signature of an experimental `channel_announcement_2` message.
+///
+/// The signature must be a BIP 340 signature, under the MuSig2 aggregate of both node ids and, if
+/// present, both bitcoin keys, over the merkle root of the unsigned TLV stream.
+#[cfg(taproot)]
+pub fn verify_channel_announcement_2<C: Verification>(msg: &msgs::ChannelAnnouncement2, secp_ctx: &Secp256k1<C>) -> Result<(), LightningError> {
+	let invalid_signature = || LightningError {
+		err: "Invalid signature on channel_announcement_2 message".to_owned(),
+		action: ErrorAction::SendWarningMessage {
+			msg: msgs::WarningMessage {
+				channel_id: [0; 32],
+				data: "Invalid signature on channel_announcement_2 message".to_owned(),
+			},
+			log_level: Level::Trace,
+		},
+	};
+
+	let mut keys = vec![
+		get_pubkey_from_node_id!(msg.contents.node_id_1, "channel_announcement_2"),
+		get_pubkey_from_node_id!(msg.contents.node_id_2, "channel_announcement_2"),
+	];
+	if let (Some(key_1), Some(key_2)) = (msg.contents.bitcoin_key_1, msg.contents.bitcoin_key_2) {
+		keys.push(key_1);
+		keys.push(key_2);
+	}
+	let aggregate_key = musig2_aggregate_key(&keys, secp_ctx).map_err(|_| invalid_signature())?;
+	let digest = message_digest(CHANNEL_ANNOUNCEMENT_2_SIGNATURE_TAG, &msg.contents.encode());
+	secp_ctx.verify_schnorr(&msg.signature, &digest, &aggregate_key.x_only_public_key().0)
+		.map_err(|_| invalid_signature())
+}
+
 impl<G: Deref<Target=NetworkGraph<L>>, U: Deref, L: Deref> RoutingMessageHandler for P2PGossipSync<G, U, L>
 where U::Target: UtxoLookup, L::Target: Logger
 {
@@ -447,6 +601,12 @@ where U::Target: UtxoLookup, L::Target: Logger
 		Ok(msg.contents.excess_data.len() <= MAX_EXCESS_BYTES_FOR_RELAY)
 	}
 
+	#[cfg(taproot)]
+	fn handle_channel_announcement_2(&self, msg: &msgs::ChannelAnnouncement2) -> Result<bool, LightningError> {
+		self.network_graph.update_channel_from_announcement_2(msg, &self.utxo_lookup)?;
+		Ok(true)
+	}
+
 	fn handle_channel_update(&self, msg: &msgs::ChannelUpdate) -> Result<bool, LightningError> {
 		self.network_graph.update_channel(msg)?;
 		Ok(msg.contents.excess_data.len() <= MAX_EXCESS_BYTES_FOR_RELAY)
@@ -1595,17 +1755,73 @@ impl<L: Deref> NetworkGraph<L> where L::Target: Logger {
 		Ok(())
 	}
 
-	fn update_channel_from_unsigned_announcement_intern<U: Deref>(
-		&self, msg: &msgs::UnsignedChannelAnnouncement, full_msg: Option<&msgs::ChannelAnnouncement>, utxo_lookup: &Option<U>
+	/// Store or update channel info from an experimental, taproot-based `channel_announcement_2`
+	/// message, as proposed for gossip v1.5.
+	///
+	/// The aggregate signature is always verified. If a [`UtxoLookup`] object is provided, the
+	/// funding output must match the announced keys and capacity. As with
+	/// [`Self::update_channel_from_announcement`], the lookup may resolve asynchronously, in which
+	/// case the channel is added once the [`UtxoFuture`] is resolved.
+	///
+	/// As such announcements cannot yet be relayed, the resulting [`ChannelInfo`] has no
+	/// `announcement_message`.
+	///
+	/// [`UtxoFuture`]: crate::routing::utxo::UtxoFuture
+	#[cfg(taproot)]
+	pub fn update_channel_from_announcement_2<U: Deref>(
+		&self, msg: &msgs::ChannelAnnouncement2, utxo_lookup: &Option<U>,
 	) -> Result<(), LightningError>
 	where
 		U::Target: UtxoLookup,
 	{
-		if msg.node_id_1 == msg.node_id_2 || msg.bitcoin_key_1 == msg.bitcoin_key_2 {
+		let contents = &msg.contents;
+		if contents.node_id_1 == contents.node_id_2 ||
+			(contents.bitcoin_key_1.is_some() && contents.bitcoin_key_1 == contents.bitcoin_key_2) {
 			return Err(LightningError{err: "Channel announcement node had a channel with itself".to_owned(), action: ErrorAction::IgnoreError});
 		}
 
-		if msg.chain_hash != self.genesis_hash {
+		verify_channel_announcement_2(msg, &self.secp_ctx)?;
+
+		self.check_channel_announcement_is_new(&contents.chain_hash, contents.short_channel_id,
+			&contents.node_id_1, &contents.node_id_2, utxo_lookup.is_some())?;
+
+		let utxo_value = if utxo_lookup.is_some() {
+			let expected_script = channel_announcement_2_funding_script(contents, &self.secp_ctx)?;
+			self.pending_checks.check_channel_announcement_2(utxo_lookup, msg, &expected_script)?
+		} else { None };
+
+		#[allow(unused_mut, unused_assignments)]
+		let mut announcement_received_time = 0;
+		#[cfg(feature = "std")]
+		{
+			announcement_received_time = SystemTime::now().duration_since(UNIX_EPOCH).expect("Time must be > 1970").as_secs();
+		}
+
+		let chan_info = ChannelInfo {
+			features: contents.features.clone(),
+			node_one: contents.node_id_1,
+			one_to_two: None,
+			node_two: contents.node_id_2,
+			two_to_one: None,
+			capacity_sats: utxo_value,
+			announcement_message: None,
+			announcement_received_time,
+		};
+
+		self.add_channel_between_nodes(contents.short_channel_id, chan_info, utxo_value)?;
+
+		log_gossip!(self.logger, "Added channel_announcement_2 for {}", contents.short_channel_id);
+		Ok(())
+	}
+
+	/// Checks that a channel announcement for the given chain, SCID and nodes is one we may want to
+	/// add to the graph, i.e. it is for our chain, we don't already have it and neither it nor its
+	/// nodes were removed recently.
+	fn check_channel_announcement_is_new(
+		&self, chain_hash: &BlockHash, short_channel_id: u64, node_id_1: &NodeId, node_id_2: &NodeId,
+		has_utxo_lookup: bool,
+	) -> Result<(), LightningError> {
+		if *chain_hash != self.genesis_hash {
 			return Err(LightningError {
 				err: "Channel announcement chain hash does not match genesis hash".to_owned(),
 				action: ErrorAction::IgnoreAndLog(Level::Debug),
@@ -1615,7 +1831,7 @@ impl<L: Deref> NetworkGraph<L> where L::Target: Logger {
 		{
 			let channels = self.channels.read().unwrap();
 
-			if let Some(chan) = channels.get(&msg.short_channel_id) {
+			if let Some(chan) = channels.get(&short_channel_id) {
 				if chan.capacity_sats.is_some() {
 					// If we'd previously looked up the channel on-chain and checked the script
 					// against what appears on-chain, ignore the duplicate announcement.
@@ -1628,13 +1844,13 @@ impl<L: Deref> NetworkGraph<L> where L::Target: Logger {
 					// We use the Node IDs rather than the bitcoin_keys to check for "equivalence"
 					// as we didn't (necessarily) store the bitcoin keys, and we only really care
 					// if the peers on the channel changed anyway.
-					if msg.node_id_1 == chan.node_one && msg.node_id_2 == chan.node_two {
+					if *node_id_1 == chan.node_one && *node_id_2 == chan.node_two {
 						return Err(LightningError {
 							err: "Already have chain-validated channel".to_owned(),
 							action: ErrorAction::IgnoreDuplicateGossip
 						});
 					}
-				} else if utxo_lookup.is_none() {
+				} else if !has_utxo_lookup {
 					// Similarly, if we can't check the chain right now anyway, ignore the
 					// duplicate announcement without bothering to take the channels write lock.
 					return Err(LightningError {
@@ -1648,14 +1864,29 @@ impl<L: Deref> NetworkGraph<L> where L::Target: Logger {
 		{
 			let removed_channels = self.removed_channels.lock().unwrap();
 			let removed_nodes = self.removed_nodes.lock().unwrap();
-			if removed_channels.contains_key(&msg.short_channel_id) ||
-				removed_nodes.contains_key(&msg.node_id_1) ||
-				removed_nodes.contains_key(&msg.node_id_2) {
+			if removed_channels.contains_key(&short_channel_id) ||
+				removed_nodes.contains_key(node_id_1) ||
+				removed_nodes.contains_key(node_id_2) {
 				return Err(LightningError{
-					err: format!("Channel with SCID {} or one of its nodes was removed from our network graph recently", &msg.short_channel_id),
+					err: format!("Channel with SCID {} or one of its nodes was removed from our network graph recently", short_channel_id),
 					action: ErrorAction::IgnoreAndLog(Level::Gossip)});
 			}
 		}
+		Ok(())
+	}
+
+	fn update_channel_from_unsigned_announcement_intern<U: Deref>(
+		&self, msg: &msgs::UnsignedChannelAnnouncement, full_msg: Option<&msgs::ChannelAnnouncement>, utxo_lookup: &Option<U>
+	) -> Result<(), LightningError>
+	where
+		U::Target: UtxoLookup,
+	{
+		if msg.node_id_1 == msg.node_id_2 || msg.bitcoin_key_1 == msg.bitcoin_key_2 {
+			return Err(LightningError{err: "Channel announcement node had a channel with itself".to_owned(), action: ErrorAction::IgnoreError});
+		}
+
+		self.check_channel_announcement_is_new(&msg.chain_hash, msg.short_channel_id,
+			&msg.node_id_1, &msg.node_id_2, utxo_lookup.is_some())?;
 
 		let utxo_value = self.pending_checks.check_channel_announcement(
 			utxo_lookup, msg, full_msg)?;
@@ -2018,6 +2249,17 @@ impl ReadOnlyNetworkGraph<'_> {
 		self.nodes.get(node_id)
 	}
 
+	/// Returns the new node id the given node announced it is rotating to via a
+	/// [`msgs::NodeKeyRotation`] in its latest `node_announcement`, if any.
+	///
+	/// Only statements in relayable announcements whose signatures were checked are considered.
+	pub fn node_key_rotation(&self, node_id: &NodeId) -> Option<NodeId> {
+		self.nodes.get(node_id)?.announcement_info.as_ref()?
+			.announcement_message.as_ref()?
+			.contents.node_key_rotation()
+			.map(|rotation| rotation.new_node_id)
+	}
+
 	#[cfg(c_bindings)] // Non-bindings users should use `nodes`
 	/// Returns the list of nodes in the graph
 	pub fn list_nodes(&self) -> Vec<NodeId> {
@@ -2044,7 +2286,7 @@ pub(crate) mod tests {
 	use crate::routing::utxo::{UtxoLookupError, UtxoResult};
 	use crate::ln::msgs::{RoutingMessageHandler, UnsignedNodeAnnouncement, NodeAnnouncement,
 		UnsignedChannelAnnouncement, ChannelAnnouncement, UnsignedChannelUpdate, ChannelUpdate,
-		ReplyChannelRange, QueryChannelRange, QueryShortChannelIds, MAX_VALUE_MSAT};
+		ReplyChannelRange, QueryChannelRange, QueryShortChannelIds, NodeKeyRotation, MAX_VALUE_MSAT};
 	use crate::util::config::UserConfig;
 	use crate::util::test_utils;
 	use crate::util::ser::{ReadableArgs, Readable, Writeable};
@@ -2064,6 +2306,12 @@ pub(crate) mod tests {
 
 	use bitcoin::secp256k1::{PublicKey, SecretKey};
 	use bitcoin::secp256k1::{All, Secp256k1};
+	#[cfg(taproot)]
+	use bitcoin::secp256k1::{KeyPair, Scalar};
+	#[cfg(taproot)]
+	use crate::ln::msgs::{ChannelAnnouncement2, UnsignedChannelAnnouncement2};
+	#[cfg(taproot)]
+	use crate::offers::merkle::message_digest;
 
 	use crate::io;
 	use bitcoin::secp256k1;
@@ -2175,6 +2423,198 @@ pub(crate) mod tests {
 		}
 	}
 
+	/// Computes the secret key matching [`super::musig2_aggregate_key`] for the given secrets.
+	#[cfg(taproot)]
+	fn musig2_aggregate_secret(secrets: &[SecretKey], secp_ctx: &Secp256k1<All>) -> SecretKey {
+		let keys: Vec<PublicKey> = secrets.iter().map(|secret| PublicKey::from_secret_key(secp_ctx, secret)).collect();
+		let mut aggregate_secret: Option<SecretKey> = None;
+		for (key, coefficient) in super::musig2_key_agg_coefficients(&super::musig2_key_sort(&keys)) {
+			let secret = secrets[keys.iter().position(|k| *k == key).unwrap()];
+			let tweaked_secret = match coefficient {
+				Some(coefficient) => secret.mul_tweak(&coefficient).unwrap(),
+				None => secret,
+			};
+			aggregate_secret = Some(match aggregate_secret {
+				Some(aggregate) => aggregate.add_tweak(&Scalar::from(tweaked_secret)).unwrap(),
+				None => tweaked_secret,
+			});
+		}
+		aggregate_secret.unwrap()
+	}
+
+	#[cfg(taproot)]
+	fn get_signed_channel_announcement_2<F: Fn(&mut UnsignedChannelAnnouncement2)>(f: F, node_1_key: &SecretKey, node_2_key: &SecretKey, secp_ctx: &Secp256k1<All>) -> ChannelAnnouncement2 {
+		let node_1_btckey = SecretKey::from_slice(&[40; 32]).unwrap();
+		let node_2_btckey = SecretKey::from_slice(&[39; 32]).unwrap();
+
+		let mut unsigned_announcement = UnsignedChannelAnnouncement2 {
+			chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+			features: channelmanager::provided_channel_features(&UserConfig::default()),
+			short_channel_id: 0,
+			capacity_satoshis: 100_000,
+			node_id_1: NodeId::from_pubkey(&PublicKey::from_secret_key(secp_ctx, node_1_key)),
+			node_id_2: NodeId::from_pubkey(&PublicKey::from_secret_key(secp_ctx, node_2_key)),
+			bitcoin_key_1: Some(PublicKey::from_secret_key(secp_ctx, &node_1_btckey)),
+			bitcoin_key_2: Some(PublicKey::from_secret_key(secp_ctx, &node_2_btckey)),
+			merkle_root_hash: None,
+		};
+		f(&mut unsigned_announcement);
+		let mut secrets = vec![*node_1_key, *node_2_key];
+		if unsigned_announcement.bitcoin_key_1.is_some() {
+			secrets.push(node_1_btckey);
+			secrets.push(node_2_btckey);
+		}
+		let keypair = KeyPair::from_secret_key(secp_ctx, &musig2_aggregate_secret(&secrets, secp_ctx));
+		let digest = message_digest(super::CHANNEL_ANNOUNCEMENT_2_SIGNATURE_TAG, &unsigned_announcement.encode());
+		ChannelAnnouncement2 {
+			signature: secp_ctx.sign_schnorr_no_aux_rand(&digest, &keypair),
+			contents: unsigned_announcement,
+		}
+	}
+
+	#[test]
+	#[cfg(taproot)]
+	fn musig2_key_agg_test_vectors() {
+		// Test vectors from BIP 327's key_agg_vectors.json.
+		let secp_ctx = Secp256k1::verification_only();
+		let keys: Vec<PublicKey> = [
+			"02F9308A019258C31049344F85F89D5229B531C845836F99B08601F113BCE036F9",
+			"03DFF1D77F2A671C5F36183726DB2341BE58FEAE1DA2DECED843240F7B502BA659",
+			"023590A94E768F8E1815C2F24B4D80A8E3149316C3518CE7B7AD338368D038CA66",
+		].iter().map(|key| PublicKey::from_slice(&hex::decode(key).unwrap()).unwrap()).collect();
+		for (key_indices, expected) in [
+			(&[0, 1, 2][..], "90539EEDE565F5D054F32CC0C220126889ED1E5D193BAF15AEF344FE59D4610C"),
+			(&[2, 1, 0][..], "6204DE8B083426DC6EAF9502D27024D53FC826BF7D2012148A0575435DF54B2B"),
+			(&[0, 0, 0][..], "B436E3BAD62B8CD409969A224731C193D051162D8C5AE8B109306127DA3AA935"),
+			(&[0, 0, 1, 1][..], "69BC22BFA5D106306E48A20679DE1D7389386124D07571D0D872686028C26A3E"),
+		].iter() {
+			let keys: Vec<PublicKey> = key_indices.iter().map(|idx| keys[*idx]).collect();
+			let aggregate_key = super::musig2_key_agg(&keys, &secp_ctx).unwrap();
+			assert_eq!(aggregate_key.x_only_public_key().0.serialize().to_vec(), hex::decode(expected).unwrap());
+		}
+
+		// `KeySort` makes the aggregate independent of the order the keys are given in.
+		assert_eq!(super::musig2_aggregate_key(&[keys[0], keys[1], keys[2]], &secp_ctx),
+			super::musig2_aggregate_key(&[keys[2], keys[1], keys[0]], &secp_ctx));
+	}
+
+	#[test]
+	#[cfg(taproot)]
+	fn async_channel_announcement_2_lookup() {
+		use crate::routing::utxo::UtxoFuture;
+
+		let secp_ctx = Secp256k1::new();
+		let logger = test_utils::TestLogger::new();
+		let chain_source = test_utils::TestChainSource::new(Network::Testnet);
+		let network_graph = NetworkGraph::new(Network::Testnet, &logger);
+
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let announcement = get_signed_channel_announcement_2(|_| {}, node_1_privkey, node_2_privkey, &secp_ctx);
+		let good_script = super::channel_announcement_2_funding_script(&announcement.contents, &secp_ctx).unwrap();
+
+		let future = UtxoFuture::new();
+		*chain_source.utxo_ret.lock().unwrap() = UtxoResult::Async(future.clone());
+		assert_eq!(
+			network_graph.update_channel_from_announcement_2(&announcement, &Some(&chain_source)).unwrap_err().err,
+			"Channel being checked async");
+		assert_eq!(
+			network_graph.update_channel_from_announcement_2(&announcement, &Some(&chain_source)).unwrap_err().err,
+			"Channel announcement is already being checked");
+		assert!(network_graph.read_only().channels().get(&0).is_none());
+
+		// Channel updates received while the lookup is pending are applied once it completes.
+		let chan_update = get_signed_channel_update(|msg| msg.flags = 0, node_1_privkey, &secp_ctx);
+		assert_eq!(network_graph.update_channel(&chan_update).unwrap_err().err,
+			"Awaiting channel_announcement validation to accept channel_update");
+
+		future.resolve_without_forwarding(&network_graph, Ok(TxOut { value: 100_000, script_pubkey: good_script }));
+		let read_only_graph = network_graph.read_only();
+		let channel = read_only_graph.channels().get(&0).unwrap();
+		assert_eq!(channel.capacity_sats, Some(100_000));
+		assert!(channel.one_to_two.is_some());
+
+		// A lookup resolving to the wrong script does not add the channel.
+		let other_announcement = get_signed_channel_announcement_2(|unsigned_announcement| {
+			unsigned_announcement.short_channel_id = 1;
+		}, node_1_privkey, node_2_privkey, &secp_ctx);
+		let future = UtxoFuture::new();
+		*chain_source.utxo_ret.lock().unwrap() = UtxoResult::Async(future.clone());
+		core::mem::drop(read_only_graph);
+		assert_eq!(
+			network_graph.update_channel_from_announcement_2(&other_announcement, &Some(&chain_source)).unwrap_err().err,
+			"Channel being checked async");
+		future.resolve_without_forwarding(&network_graph, Ok(TxOut { value: 100_000, script_pubkey: Script::new() }));
+		assert!(network_graph.read_only().channels().get(&1).is_none());
+	}
+
+	#[test]
+	#[cfg(taproot)]
+	fn handling_channel_announcement_2() {
+		let secp_ctx = Secp256k1::new();
+		let logger = test_utils::TestLogger::new();
+
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+
+		let valid_announcement = get_signed_channel_announcement_2(|_| {}, node_1_privkey, node_2_privkey, &secp_ctx);
+		let encoded_announcement = valid_announcement.encode();
+		assert_eq!(ChannelAnnouncement2::read(&mut &encoded_announcement[..]).unwrap(), valid_announcement);
+
+		// Without UTXO lookups the channel is accepted but not relayable.
+		let network_graph = NetworkGraph::new(Network::Testnet, &logger);
+		let mut gossip_sync = P2PGossipSync::new(&network_graph, None, &logger);
+		assert!(gossip_sync.handle_channel_announcement_2(&valid_announcement).unwrap());
+		{
+			let read_only_graph = network_graph.read_only();
+			let channel = read_only_graph.channels().get(&0).unwrap();
+			assert!(channel.announcement_message.is_none());
+			assert_eq!(channel.capacity_sats, None);
+		}
+		match gossip_sync.handle_channel_announcement_2(&valid_announcement) {
+			Ok(_) => panic!(),
+			Err(e) => assert_eq!(e.err, "Already have non-chain-validated channel")
+		};
+
+		// A signature which doesn't commit to the contents is rejected.
+		let mut invalid_announcement = get_signed_channel_announcement_2(|unsigned_announcement| {
+			unsigned_announcement.short_channel_id += 1;
+		}, node_1_privkey, node_2_privkey, &secp_ctx);
+		invalid_announcement.contents.capacity_satoshis += 1;
+		match gossip_sync.handle_channel_announcement_2(&invalid_announcement) {
+			Ok(_) => panic!(),
+			Err(e) => assert_eq!(e.err, "Invalid signature on channel_announcement_2 message")
+		};
+
+		// With UTXO lookups, the funding output must pay to the aggregate funding key.
+		let chain_source = test_utils::TestChainSource::new(Network::Testnet);
+		let network_graph = NetworkGraph::new(Network::Testnet, &logger);
+		gossip_sync = P2PGossipSync::new(&network_graph, Some(&chain_source), &logger);
+		*chain_source.utxo_ret.lock().unwrap() =
+			UtxoResult::Sync(Ok(TxOut { value: 100_000, script_pubkey: get_channel_script(&secp_ctx) }));
+		match gossip_sync.handle_channel_announcement_2(&valid_announcement) {
+			Ok(_) => panic!(),
+			Err(e) => assert!(e.err.contains("didn't match on-chain script"))
+		};
+
+		let good_script = super::channel_announcement_2_funding_script(&valid_announcement.contents, &secp_ctx).unwrap();
+		*chain_source.utxo_ret.lock().unwrap() =
+			UtxoResult::Sync(Ok(TxOut { value: 100_000, script_pubkey: good_script }));
+		assert!(gossip_sync.handle_channel_announcement_2(&valid_announcement).unwrap());
+		assert_eq!(network_graph.read_only().channels().get(&0).unwrap().capacity_sats, Some(100_000));
+
+		// Announcements without funding keys commit to the aggregate of the node ids instead.
+		let keyless_announcement = get_signed_channel_announcement_2(|unsigned_announcement| {
+			unsigned_announcement.short_channel_id += 2;
+			unsigned_announcement.bitcoin_key_1 = None;
+			unsigned_announcement.bitcoin_key_2 = None;
+		}, node_1_privkey, node_2_privkey, &secp_ctx);
+		let keyless_script = super::channel_announcement_2_funding_script(&keyless_announcement.contents, &secp_ctx).unwrap();
+		*chain_source.utxo_ret.lock().unwrap() =
+			UtxoResult::Sync(Ok(TxOut { value: 100_000, script_pubkey: keyless_script }));
+		assert!(gossip_sync.handle_channel_announcement_2(&keyless_announcement).unwrap());
+	}
+
 	#[test]
 	fn handling_node_announcements() {
 		let network_graph = create_network_graph();
@@ -2235,6 +2675,52 @@ pub(crate) mod tests {
 		};
 	}
 
+	#[test]
+	fn handling_node_key_rotations() {
+		let network_graph = create_network_graph();
+		let (secp_ctx, gossip_sync) = create_gossip_sync(&network_graph);
+
+		let node_1_privkey = &SecretKey::from_slice(&[42; 32]).unwrap();
+		let node_2_privkey = &SecretKey::from_slice(&[41; 32]).unwrap();
+		let new_node_1_privkey = &SecretKey::from_slice(&[43; 32]).unwrap();
+		let node_1_id = NodeId::from_pubkey(&PublicKey::from_secret_key(&secp_ctx, node_1_privkey));
+		let new_node_1_id = NodeId::from_pubkey(&PublicKey::from_secret_key(&secp_ctx, new_node_1_privkey));
+
+		let channel_announcement = get_signed_channel_announcement(|_| {}, node_1_privkey, node_2_privkey, &secp_ctx);
+		assert!(gossip_sync.handle_channel_announcement(&channel_announcement).unwrap());
+		assert_eq!(network_graph.read_only().node_key_rotation(&node_1_id), None);
+
+		// A rotation signed by a key other than the new one is rejected.
+		let bad_rotation = NodeKeyRotation {
+			new_node_id: new_node_1_id,
+			new_node_signature: NodeKeyRotation::new(node_1_id, node_2_privkey, &secp_ctx).new_node_signature,
+		};
+		let bad_announcement = get_signed_node_announcement(|unsigned_announcement| {
+			unsigned_announcement.append_node_key_rotation(&bad_rotation);
+		}, node_1_privkey, &secp_ctx);
+		match gossip_sync.handle_node_announcement(&bad_announcement) {
+			Ok(_) => panic!(),
+			Err(e) => assert_eq!(e.err, "Invalid signature on node_announcement message")
+		};
+		assert_eq!(network_graph.read_only().node_key_rotation(&node_1_id), None);
+
+		let rotation = NodeKeyRotation::new(node_1_id, new_node_1_privkey, &secp_ctx);
+		assert_eq!(rotation.new_node_id, new_node_1_id);
+		let announcement = get_signed_node_announcement(|unsigned_announcement| {
+			unsigned_announcement.append_node_key_rotation(&rotation);
+		}, node_1_privkey, &secp_ctx);
+		assert_eq!(announcement.contents.node_key_rotation(), Some(rotation));
+		assert!(gossip_sync.handle_node_announcement(&announcement).unwrap());
+		assert_eq!(network_graph.read_only().node_key_rotation(&node_1_id), Some(new_node_1_id));
+
+		// Once the node stops announcing the rotation it is no longer reported.
+		let announcement = get_signed_node_announcement(|unsigned_announcement| {
+			unsigned_announcement.timestamp += 1;
+		}, node_1_privkey, &secp_ctx);
+		assert!(gossip_sync.handle_node_announcement(&announcement).unwrap());
+		assert_eq!(network_graph.read_only().node_key_rotation(&node_1_id), None);
+	}
+
 	#[test]
 	fn handling_channel_announcements() {
 		let secp_ctx = Secp256k1::new();
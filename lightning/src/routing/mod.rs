@@ -13,5 +13,6 @@ pub mod utxo;
 pub mod gossip;
 pub mod router;
 pub mod scoring;
+pub mod remote_router;
 #[cfg(test)]
-mod test_utils;
+pub(crate) mod test_utils;
@@ -0,0 +1,641 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Delegating pathfinding to a remote route server over onion messages.
+//!
+//! A [`RemoteRouter`] implements [`Router`] by sending a [`RouteServerMessage::Request`] to a
+//! route server and handing out the route in the corresponding [`RouteServerMessage::Response`]
+//! once it arrives, allowing thin clients to find routes without maintaining a [`NetworkGraph`].
+//! The route server answers such requests using a [`RouteServer`], which wraps any [`Router`].
+//!
+//! Neither blocks the thread handling onion messages: the [`RemoteRouter`] never waits for the
+//! route server, and the [`RouteServer`] only finds routes once
+//! [`RouteServer::process_pending_requests`] is called.
+//!
+//! Both are [`CustomOnionMessageHandler`]s and thus must be provided to the respective
+//! [`OnionMessenger`], which in turn must be configured with a [`MessageRouter`] able to find
+//! paths between the client and the route server.
+//!
+//! Note that the route server learns the payee, amount, and our channels for each payment we
+//! route through it.
+//!
+//! [`NetworkGraph`]: crate::routing::gossip::NetworkGraph
+//! [`OnionMessenger`]: crate::onion_message::OnionMessenger
+//! [`MessageRouter`]: crate::onion_message::MessageRouter
+
+use bitcoin::secp256k1::{self, PublicKey, Secp256k1};
+
+use crate::blinded_path::BlindedPath;
+use crate::ln::channelmanager::ChannelDetails;
+use crate::ln::msgs::{DecodeError, ErrorAction, LightningError};
+use crate::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, Destination, OnionMessageReceivedVia, Responder};
+use crate::routing::router::{InFlightHtlcs, Route, RouteParameters, Router};
+use crate::sign::EntropySource;
+use crate::util::logger::Logger;
+use crate::util::ser::{Readable, Writeable};
+use crate::util::wakers::{Future, Notifier};
+
+use crate::io;
+use crate::prelude::*;
+use crate::sync::Mutex;
+use core::ops::Deref;
+
+/// The onion message TLV type of a [`RouteServerMessage::Request`].
+pub const ROUTE_REQUEST_TLV_TYPE: u64 = 65_537;
+
+/// The onion message TLV type of a [`RouteServerMessage::Response`].
+pub const ROUTE_RESPONSE_TLV_TYPE: u64 = 65_539;
+
+/// The maximum serialized length of a [`RouteServerMessage`].
+///
+/// Onion messages are limited to 32 KiB of hop data, which must also fit the reply path and
+/// control TLVs. Requests or responses exceeding this length are not sent, but are failed
+/// immediately instead of leaving the [`RemoteRouter`] waiting for an answer which never comes.
+pub const MAX_ROUTE_SERVER_MESSAGE_LEN: usize = 30_000;
+
+/// A message exchanged between a [`RemoteRouter`] and a route server's [`RouteServer`].
+pub enum RouteServerMessage {
+	/// Requests a [`Route`] from the route server, mirroring the arguments of
+	/// [`Router::find_route`].
+	Request {
+		/// An identifier echoed back in the corresponding [`RouteServerMessage::Response`].
+		request_id: [u8; 32],
+		/// The node which will send the payment.
+		payer: PublicKey,
+		/// The parameters of the payment to find a route for.
+		route_params: RouteParameters,
+		/// The payer's usable channels, if provided to [`Router::find_route`].
+		first_hops: Option<Vec<ChannelDetails>>,
+		/// The payer's in-flight HTLCs.
+		inflight_htlcs: InFlightHtlcs,
+	},
+	/// The route server's answer to a [`RouteServerMessage::Request`].
+	Response {
+		/// The `request_id` of the request being answered.
+		request_id: [u8; 32],
+		/// The route found, if any.
+		route: Option<Route>,
+		/// The error encountered by the route server if no route was found.
+		error: Option<String>,
+	},
+}
+
+impl_writeable_tlv_based_enum!(RouteServerMessage,
+	(0, Request) => {
+		(0, request_id, required),
+		(2, payer, required),
+		(4, route_params, required),
+		(6, first_hops, option),
+		(8, inflight_htlcs, required),
+	},
+	(1, Response) => {
+		(0, request_id, required),
+		(2, route, option),
+		(4, error, option),
+	};
+);
+
+impl CustomOnionMessageContents for RouteServerMessage {
+	fn tlv_type(&self) -> u64 {
+		match self {
+			RouteServerMessage::Request { .. } => ROUTE_REQUEST_TLV_TYPE,
+			RouteServerMessage::Response { .. } => ROUTE_RESPONSE_TLV_TYPE,
+		}
+	}
+}
+
+impl RouteServerMessage {
+	/// Reads a [`RouteServerMessage`] of the given onion message TLV type, returning `Ok(None)` if
+	/// the type is not one of [`ROUTE_REQUEST_TLV_TYPE`] or [`ROUTE_RESPONSE_TLV_TYPE`].
+	///
+	/// Useful for [`CustomOnionMessageHandler`]s which handle other messages in addition to these.
+	pub fn read_custom_message<R: io::Read>(message_type: u64, buffer: &mut R) -> Result<Option<Self>, DecodeError> {
+		if message_type != ROUTE_REQUEST_TLV_TYPE && message_type != ROUTE_RESPONSE_TLV_TYPE {
+			return Ok(None);
+		}
+		let message: Self = Readable::read(buffer)?;
+		if message.tlv_type() != message_type { return Err(DecodeError::InvalidValue); }
+		Ok(Some(message))
+	}
+}
+
+/// A request sent to the route server, along with its answer once received.
+struct RouteRequest {
+	payer: PublicKey,
+	route_params: RouteParameters,
+	/// The number of timer ticks since the request was queued.
+	ticks_elapsed: u32,
+	/// The route server's answer, once received.
+	response: Option<Result<Route, String>>,
+}
+
+/// A [`Router`] which delegates pathfinding to a remote route server running a [`RouteServer`].
+///
+/// [`Router::find_route`] never waits for the route server. Instead, the first call for a given
+/// payer and [`RouteParameters`] queues a request and fails, as do further calls until the route
+/// server responds. Once it has, the next such call returns its answer. Callers should thus retry
+/// once the [`Future`] returned by [`Self::get_response_future`] completes, e.g. by retrying a
+/// payment which failed with [`RetryableSendFailure::RouteNotFound`].
+///
+/// Requests are sent via the [`OnionMessenger`] this is provided to as its
+/// [`CustomOnionMessageHandler`], and thus will only go out once the messenger is polled for
+/// outbound messages, i.e., when [`PeerManager::process_events`] is called. Requests which are not
+/// answered within the configured number of calls to [`Self::timer_tick_occurred`] are forgotten,
+/// so that the next call to [`Router::find_route`] requests a route anew.
+///
+/// Requests whose serialized length exceeds [`MAX_ROUTE_SERVER_MESSAGE_LEN`], e.g. because of a
+/// large number of first hops, fail immediately without being sent.
+///
+/// Replies are requested via a blinded path through the route server to us, thus the route server
+/// should be one of our peers.
+///
+/// [`RetryableSendFailure::RouteNotFound`]: crate::ln::outbound_payment::RetryableSendFailure::RouteNotFound
+/// [`OnionMessenger`]: crate::onion_message::OnionMessenger
+/// [`PeerManager::process_events`]: crate::ln::peer_handler::PeerManager::process_events
+pub struct RemoteRouter<ES: Deref, L: Deref> where ES::Target: EntropySource, L::Target: Logger {
+	our_node_id: PublicKey,
+	route_server_node_id: PublicKey,
+	entropy_source: ES,
+	logger: L,
+	secp_ctx: Secp256k1<secp256k1::All>,
+	response_timeout_ticks: u32,
+	pending_messages: Mutex<Vec<RouteServerMessage>>,
+	requests: Mutex<HashMap<[u8; 32], RouteRequest>>,
+	response_notifier: Notifier,
+}
+
+impl<ES: Deref, L: Deref> RemoteRouter<ES, L> where ES::Target: EntropySource, L::Target: Logger {
+	/// Creates a new router requesting routes from the route server with the given node id and
+	/// giving up on any request which isn't answered within `response_timeout_ticks` calls to
+	/// [`Self::timer_tick_occurred`].
+	pub fn new(
+		our_node_id: PublicKey, route_server_node_id: PublicKey, entropy_source: ES, logger: L,
+		response_timeout_ticks: u32
+	) -> Self {
+		Self {
+			our_node_id,
+			route_server_node_id,
+			entropy_source,
+			logger,
+			secp_ctx: Secp256k1::new(),
+			response_timeout_ticks,
+			pending_messages: Mutex::new(Vec::new()),
+			requests: Mutex::new(HashMap::new()),
+			response_notifier: Notifier::new(),
+		}
+	}
+
+	/// Gets a [`Future`] that completes when the route server has responded to one of our
+	/// requests, after which [`Router::find_route`] should be retried.
+	pub fn get_response_future(&self) -> Future {
+		self.response_notifier.get_future()
+	}
+
+	/// Forgets about requests which have not been answered within the configured number of ticks,
+	/// as well as answers which were never picked up.
+	///
+	/// Should be called roughly once per minute, e.g. alongside
+	/// [`ChannelManager::timer_tick_occurred`].
+	///
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	pub fn timer_tick_occurred(&self) {
+		let mut timed_out = Vec::new();
+		self.requests.lock().unwrap().retain(|request_id, request| {
+			request.ticks_elapsed += 1;
+			if request.ticks_elapsed <= self.response_timeout_ticks { return true; }
+			if request.response.is_none() { timed_out.push(*request_id); }
+			false
+		});
+		if timed_out.is_empty() { return; }
+		log_trace!(self.logger, "Timed out waiting for {} route(s) from route server {}",
+			timed_out.len(), self.route_server_node_id);
+		// If the requests were never sent, don't send them anymore.
+		self.pending_messages.lock().unwrap().retain(|message| match message {
+			RouteServerMessage::Request { request_id, .. } => !timed_out.contains(request_id),
+			RouteServerMessage::Response { .. } => true,
+		});
+	}
+}
+
+impl<ES: Deref, L: Deref> Router for RemoteRouter<ES, L> where ES::Target: EntropySource, L::Target: Logger {
+	fn find_route(
+		&self, payer: &PublicKey, route_params: &RouteParameters,
+		first_hops: Option<&[&ChannelDetails]>, inflight_htlcs: InFlightHtlcs
+	) -> Result<Route, LightningError> {
+		let mut requests = self.requests.lock().unwrap();
+		let existing_request = requests.iter()
+			.find(|(_, request)| request.payer == *payer && request.route_params == *route_params)
+			.map(|(request_id, request)| (*request_id, request.response.is_some()));
+		match existing_request {
+			Some((request_id, true)) => {
+				return requests.remove(&request_id).and_then(|request| request.response)
+					.unwrap_or_else(|| Err("unknown error".to_owned()))
+					.map_err(|error| LightningError {
+						err: format!("Route server failed to find a route: {}", error),
+						action: ErrorAction::IgnoreError,
+					});
+			},
+			Some((_, false)) => return Err(LightningError {
+				err: "Still waiting for a route from the route server".to_owned(),
+				action: ErrorAction::IgnoreError,
+			}),
+			None => {},
+		}
+
+		let request_id = self.entropy_source.get_secure_random_bytes();
+		let request = RouteServerMessage::Request {
+			request_id,
+			payer: *payer,
+			route_params: route_params.clone(),
+			first_hops: first_hops.map(|hops| hops.iter().map(|details| (*details).clone()).collect()),
+			inflight_htlcs,
+		};
+		if request.serialized_length() > MAX_ROUTE_SERVER_MESSAGE_LEN {
+			return Err(LightningError {
+				err: "Route request is too large to send to the route server".to_owned(),
+				action: ErrorAction::IgnoreError,
+			});
+		}
+		requests.insert(request_id, RouteRequest {
+			payer: *payer, route_params: route_params.clone(), ticks_elapsed: 0, response: None,
+		});
+		self.pending_messages.lock().unwrap().push(request);
+		log_trace!(self.logger, "Requesting route for {} msat from route server {}",
+			route_params.final_value_msat, self.route_server_node_id);
+		Err(LightningError {
+			err: "Requested a route from the route server, retry once it responds".to_owned(),
+			action: ErrorAction::IgnoreError,
+		})
+	}
+}
+
+impl<ES: Deref, L: Deref> CustomOnionMessageHandler for RemoteRouter<ES, L>
+where ES::Target: EntropySource, L::Target: Logger {
+	type CustomMessage = RouteServerMessage;
+
+	fn handle_custom_message(&self, msg: RouteServerMessage) -> Option<RouteServerMessage> {
+		match msg {
+			RouteServerMessage::Response { request_id, route, error } => {
+				match self.requests.lock().unwrap().get_mut(&request_id) {
+					Some(request) if request.response.is_none() => {
+						request.response = Some(route.ok_or_else(|| error.unwrap_or_else(|| "unknown error".to_owned())));
+						self.response_notifier.notify();
+					},
+					_ => log_trace!(self.logger, "Ignoring route server response to an unknown request"),
+				}
+			},
+			RouteServerMessage::Request { .. } => {
+				log_trace!(self.logger, "Ignoring route request as we are not a route server");
+			},
+		}
+		None
+	}
+
+	fn read_custom_message<R: io::Read>(&self, message_type: u64, buffer: &mut R) -> Result<Option<RouteServerMessage>, DecodeError> {
+		RouteServerMessage::read_custom_message(message_type, buffer)
+	}
+
+	fn release_pending_custom_messages(&self) -> Vec<(RouteServerMessage, Destination, Option<BlindedPath>)> {
+		let requests = core::mem::take(&mut *self.pending_messages.lock().unwrap());
+		requests.into_iter().filter_map(|request| {
+			let reply_path = match BlindedPath::new_for_message(
+				&[self.route_server_node_id, self.our_node_id], &*self.entropy_source, &self.secp_ctx
+			) {
+				Ok(reply_path) => reply_path,
+				Err(()) => {
+					log_error!(self.logger, "Failed to construct a reply path for a route request");
+					return None;
+				},
+			};
+			Some((request, Destination::Node(self.route_server_node_id), Some(reply_path)))
+		}).collect()
+	}
+}
+
+/// Limits on which requests a [`RouteServer`] answers.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteServerConfig {
+	/// If set, only requests received along blinded paths to us carrying one of these identifiers
+	/// are answered, see [`OnionMessageReceivedVia::BlindedPath`]. Such paths may be created via
+	/// [`OnionMessenger::create_reply_path`] and handed to each client out of band.
+	///
+	/// Default value: `None`, i.e. requests from anyone are answered.
+	///
+	/// [`OnionMessenger::create_reply_path`]: crate::onion_message::OnionMessenger::create_reply_path
+	pub allowed_path_ids: Option<HashSet<[u8; 32]>>,
+	/// The maximum number of requests answered per requester between calls to
+	/// [`RouteServer::timer_tick_occurred`], beyond which further requests are dropped.
+	///
+	/// As onion messages don't identify their sender, requesters are told apart by the blinded
+	/// path to us the request was received along, if any, with all other requests sharing a
+	/// single limit.
+	///
+	/// Default value: `None`, i.e. no limit.
+	pub max_requests_per_tick: Option<u32>,
+}
+
+/// Answers [`RouteServerMessage::Request`]s sent by [`RemoteRouter`]s using the given [`Router`].
+///
+/// Requests are only queued as they are received, allowing pathfinding to happen off the thread
+/// handling onion messages. They are answered once [`Self::process_pending_requests`] is called,
+/// with the responses sent when the [`OnionMessenger`] is next polled for outbound messages.
+///
+/// [`OnionMessenger`]: crate::onion_message::OnionMessenger
+pub struct RouteServer<R: Deref, L: Deref> where R::Target: Router, L::Target: Logger {
+	router: R,
+	logger: L,
+	config: RouteServerConfig,
+	pending_requests: Mutex<Vec<(RouteServerMessage, Responder)>>,
+	pending_responses: Mutex<Vec<(RouteServerMessage, Destination, Option<BlindedPath>)>>,
+	requests_this_tick: Mutex<HashMap<OnionMessageReceivedVia, u32>>,
+	request_notifier: Notifier,
+}
+
+impl<R: Deref, L: Deref> RouteServer<R, L> where R::Target: Router, L::Target: Logger {
+	/// Creates a new route server answering requests permitted by `config` using the given
+	/// `router`.
+	pub fn new(router: R, logger: L, config: RouteServerConfig) -> Self {
+		Self {
+			router,
+			logger,
+			config,
+			pending_requests: Mutex::new(Vec::new()),
+			pending_responses: Mutex::new(Vec::new()),
+			requests_this_tick: Mutex::new(HashMap::new()),
+			request_notifier: Notifier::new(),
+		}
+	}
+
+	/// Gets a [`Future`] that completes when requests are waiting for
+	/// [`Self::process_pending_requests`].
+	pub fn get_request_future(&self) -> Future {
+		self.request_notifier.get_future()
+	}
+
+	/// Finds routes for all requests received since the last call, queueing the responses to be
+	/// sent when the [`OnionMessenger`] is next polled for outbound messages.
+	///
+	/// [`OnionMessenger`]: crate::onion_message::OnionMessenger
+	pub fn process_pending_requests(&self) {
+		let requests = core::mem::take(&mut *self.pending_requests.lock().unwrap());
+		for (request, responder) in requests {
+			if let RouteServerMessage::Request { request_id, payer, route_params, first_hops, inflight_htlcs } = request {
+				log_trace!(self.logger, "Finding route for {} msat requested by {}",
+					route_params.final_value_msat, payer);
+				let first_hops: Option<Vec<&ChannelDetails>> =
+					first_hops.as_ref().map(|hops| hops.iter().collect());
+				let (route, error) = match self.router.find_route(
+					&payer, &route_params, first_hops.as_ref().map(|hops| &hops[..]), inflight_htlcs
+				) {
+					Ok(route) => (Some(route), None),
+					Err(e) => (None, Some(e.err)),
+				};
+				let mut response = RouteServerMessage::Response { request_id, route, error };
+				if response.serialized_length() > MAX_ROUTE_SERVER_MESSAGE_LEN {
+					log_trace!(self.logger, "Route for {} is too large to return", payer);
+					response = RouteServerMessage::Response {
+						request_id, route: None, error: Some("Route is too large to return".to_owned()),
+					};
+				}
+				self.pending_responses.lock().unwrap().push(responder.respond(response));
+			}
+		}
+	}
+
+	/// Resets the number of requests each requester may make, see
+	/// [`RouteServerConfig::max_requests_per_tick`].
+	///
+	/// Should be called roughly once per minute.
+	pub fn timer_tick_occurred(&self) {
+		self.requests_this_tick.lock().unwrap().clear();
+	}
+}
+
+impl<R: Deref, L: Deref> CustomOnionMessageHandler for RouteServer<R, L>
+where R::Target: Router, L::Target: Logger {
+	type CustomMessage = RouteServerMessage;
+
+	fn handle_custom_message(&self, msg: RouteServerMessage) -> Option<RouteServerMessage> {
+		self.handle_custom_message_with_context(msg, OnionMessageReceivedVia::Direct, None)
+	}
+
+	fn handle_custom_message_with_context(
+		&self, msg: RouteServerMessage, received_via: OnionMessageReceivedVia,
+		responder: Option<Responder>
+	) -> Option<RouteServerMessage> {
+		match msg {
+			RouteServerMessage::Request { .. } => {
+				let responder = match responder {
+					Some(responder) => responder,
+					None => {
+						log_trace!(self.logger, "Ignoring route request without a reply path");
+						return None;
+					},
+				};
+				if let Some(allowed_path_ids) = &self.config.allowed_path_ids {
+					let allowed = match received_via {
+						OnionMessageReceivedVia::BlindedPath { path_id } => allowed_path_ids.contains(&path_id),
+						OnionMessageReceivedVia::Direct => false,
+					};
+					if !allowed {
+						log_trace!(self.logger, "Ignoring route request from a requester which is not allowed");
+						return None;
+					}
+				}
+				if let Some(max_requests) = self.config.max_requests_per_tick {
+					let mut requests_this_tick = self.requests_this_tick.lock().unwrap();
+					let requests = requests_this_tick.entry(received_via).or_insert(0);
+					if *requests >= max_requests {
+						log_trace!(self.logger, "Ignoring route request from a requester which exceeded its rate limit");
+						return None;
+					}
+					*requests += 1;
+				}
+				self.pending_requests.lock().unwrap().push((msg, responder));
+				self.request_notifier.notify();
+			},
+			RouteServerMessage::Response { .. } => {
+				log_trace!(self.logger, "Ignoring route server response as we are a route server");
+			},
+		}
+		None
+	}
+
+	fn read_custom_message<R2: io::Read>(&self, message_type: u64, buffer: &mut R2) -> Result<Option<RouteServerMessage>, DecodeError> {
+		RouteServerMessage::read_custom_message(message_type, buffer)
+	}
+
+	fn release_pending_custom_messages(&self) -> Vec<(RouteServerMessage, Destination, Option<BlindedPath>)> {
+		core::mem::take(&mut *self.pending_responses.lock().unwrap())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{RemoteRouter, RouteServer, RouteServerConfig, RouteServerMessage, ROUTE_REQUEST_TLV_TYPE, ROUTE_RESPONSE_TLV_TYPE};
+	use crate::blinded_path::BlindedPath;
+	use crate::ln::channelmanager::ChannelDetails;
+	use crate::ln::features::{ChannelFeatures, NodeFeatures};
+	use crate::ln::msgs::LightningError;
+	use crate::onion_message::{CustomOnionMessageContents, CustomOnionMessageHandler, Destination, OnionMessageReceivedVia, Responder};
+	use crate::routing::router::{InFlightHtlcs, Path, PaymentParameters, Route, RouteHop, RouteParameters, Router};
+	use crate::util::ser::Writeable;
+	use crate::util::test_utils::{TestKeysInterface, TestLogger};
+
+	use bitcoin::network::constants::Network;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+	use crate::io;
+	use crate::prelude::*;
+	use crate::sync::Arc;
+
+	struct FixedRouter(Route);
+
+	impl Router for FixedRouter {
+		fn find_route(
+			&self, _payer: &PublicKey, _route_params: &RouteParameters,
+			_first_hops: Option<&[&ChannelDetails]>, _inflight_htlcs: InFlightHtlcs
+		) -> Result<Route, LightningError> {
+			Ok(self.0.clone())
+		}
+	}
+
+	fn pubkey(byte: u8) -> PublicKey {
+		PublicKey::from_secret_key(&Secp256k1::new(), &SecretKey::from_slice(&[byte; 32]).unwrap())
+	}
+
+	fn route_to(payee: PublicKey) -> Route {
+		Route {
+			paths: vec![Path {
+				hops: vec![RouteHop {
+					pubkey: payee, node_features: NodeFeatures::empty(), short_channel_id: 42,
+					channel_features: ChannelFeatures::empty(), fee_msat: 1_000, cltv_expiry_delta: 40,
+				}],
+				blinded_tail: None,
+			}],
+			payment_params: None,
+		}
+	}
+
+	fn route_params(payee: PublicKey) -> RouteParameters {
+		RouteParameters { payment_params: PaymentParameters::from_node_id(payee, 40), final_value_msat: 1_000 }
+	}
+
+	fn responder(keys: &TestKeysInterface, received_via: OnionMessageReceivedVia) -> Responder {
+		let reply_path = BlindedPath::new_for_message(&[pubkey(2), pubkey(1)], keys, &Secp256k1::new()).unwrap();
+		Responder::new(reply_path, received_via)
+	}
+
+	/// Round-trips a message through its onion message encoding.
+	fn encode_decode(message: RouteServerMessage) -> RouteServerMessage {
+		let message_type = message.tlv_type();
+		let encoded = message.encode();
+		RouteServerMessage::read_custom_message(message_type, &mut io::Cursor::new(&encoded))
+			.unwrap().unwrap()
+	}
+
+	#[test]
+	fn requests_route_from_route_server() {
+		let payee = pubkey(3);
+		let route = route_to(payee);
+		let logger = Arc::new(TestLogger::new());
+		let keys = Arc::new(TestKeysInterface::new(&[42; 32], Network::Testnet));
+		let client = RemoteRouter::new(pubkey(1), pubkey(2), Arc::clone(&keys), Arc::clone(&logger), 3);
+		let server = RouteServer::new(Arc::new(FixedRouter(route.clone())), Arc::clone(&logger), RouteServerConfig::default());
+
+		// The first call queues a request rather than waiting for the route server.
+		let err = client.find_route(&pubkey(1), &route_params(payee), None, InFlightHtlcs::new()).unwrap_err();
+		assert_eq!(err.err, "Requested a route from the route server, retry once it responds");
+		let mut pending_messages = client.release_pending_custom_messages();
+		assert_eq!(pending_messages.len(), 1);
+		let (request, destination, reply_path) = pending_messages.pop().unwrap();
+		assert_eq!(request.tlv_type(), ROUTE_REQUEST_TLV_TYPE);
+		match destination {
+			Destination::Node(node_id) => assert_eq!(node_id, pubkey(2)),
+			Destination::BlindedPath(_) => panic!(),
+		}
+		assert_eq!(reply_path.unwrap().introduction_node_id, pubkey(2));
+
+		// Until the route server responds, the request isn't sent again.
+		let err = client.find_route(&pubkey(1), &route_params(payee), None, InFlightHtlcs::new()).unwrap_err();
+		assert_eq!(err.err, "Still waiting for a route from the route server");
+		assert!(client.release_pending_custom_messages().is_empty());
+
+		// The route server only finds the route once asked to process its pending requests.
+		assert!(server.handle_custom_message_with_context(
+			encode_decode(request), OnionMessageReceivedVia::Direct, Some(responder(&keys, OnionMessageReceivedVia::Direct))
+		).is_none());
+		assert!(server.get_request_future().poll_is_complete());
+		assert!(server.release_pending_custom_messages().is_empty());
+		server.process_pending_requests();
+		let mut pending_messages = server.release_pending_custom_messages();
+		assert_eq!(pending_messages.len(), 1);
+		let (response, destination, _) = pending_messages.pop().unwrap();
+		assert_eq!(response.tlv_type(), ROUTE_RESPONSE_TLV_TYPE);
+		match destination {
+			Destination::BlindedPath(path) => assert_eq!(path.introduction_node_id, pubkey(2)),
+			Destination::Node(_) => panic!(),
+		}
+
+		assert!(client.handle_custom_message(encode_decode(response)).is_none());
+		assert!(client.get_response_future().poll_is_complete());
+		assert_eq!(client.find_route(&pubkey(1), &route_params(payee), None, InFlightHtlcs::new()).unwrap(), route);
+
+		// Each answer is only handed out once.
+		let err = client.find_route(&pubkey(1), &route_params(payee), None, InFlightHtlcs::new()).unwrap_err();
+		assert_eq!(err.err, "Requested a route from the route server, retry once it responds");
+	}
+
+	#[test]
+	fn times_out_without_response() {
+		let logger = Arc::new(TestLogger::new());
+		let keys = Arc::new(TestKeysInterface::new(&[42; 32], Network::Testnet));
+		let client = RemoteRouter::new(pubkey(1), pubkey(2), keys, logger, 1);
+		client.find_route(&pubkey(1), &route_params(pubkey(3)), None, InFlightHtlcs::new()).unwrap_err();
+
+		client.timer_tick_occurred();
+		let err = client.find_route(&pubkey(1), &route_params(pubkey(3)), None, InFlightHtlcs::new()).unwrap_err();
+		assert_eq!(err.err, "Still waiting for a route from the route server");
+
+		// The request which timed out is no longer sent, and the next call requests a route anew.
+		client.timer_tick_occurred();
+		assert!(client.release_pending_custom_messages().is_empty());
+		let err = client.find_route(&pubkey(1), &route_params(pubkey(3)), None, InFlightHtlcs::new()).unwrap_err();
+		assert_eq!(err.err, "Requested a route from the route server, retry once it responds");
+		assert_eq!(client.release_pending_custom_messages().len(), 1);
+	}
+
+	#[test]
+	fn route_server_limits_requests() {
+		let logger = Arc::new(TestLogger::new());
+		let keys = Arc::new(TestKeysInterface::new(&[42; 32], Network::Testnet));
+		let mut allowed_path_ids = HashSet::new();
+		allowed_path_ids.insert([1; 32]);
+		let config = RouteServerConfig { allowed_path_ids: Some(allowed_path_ids), max_requests_per_tick: Some(1) };
+		let server = RouteServer::new(Arc::new(FixedRouter(route_to(pubkey(3)))), Arc::clone(&logger), config);
+
+		let answered = |received_via| {
+			let request = RouteServerMessage::Request {
+				request_id: [42; 32], payer: pubkey(1), route_params: route_params(pubkey(3)),
+				first_hops: None, inflight_htlcs: InFlightHtlcs::new(),
+			};
+			server.handle_custom_message_with_context(request, received_via, Some(responder(&keys, received_via)));
+			server.process_pending_requests();
+			server.release_pending_custom_messages().len() == 1
+		};
+
+		// Only requests along the allowed blinded path are answered, up to once per tick.
+		assert!(!answered(OnionMessageReceivedVia::Direct));
+		assert!(!answered(OnionMessageReceivedVia::BlindedPath { path_id: [2; 32] }));
+		assert!(answered(OnionMessageReceivedVia::BlindedPath { path_id: [1; 32] }));
+		assert!(!answered(OnionMessageReceivedVia::BlindedPath { path_id: [1; 32] }));
+		server.timer_tick_occurred();
+		assert!(answered(OnionMessageReceivedVia::BlindedPath { path_id: [1; 32] }));
+	}
+}
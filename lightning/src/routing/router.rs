@@ -20,8 +20,8 @@ use crate::ln::features::{Bolt11InvoiceFeatures, Bolt12InvoiceFeatures, ChannelF
 use crate::ln::msgs::{DecodeError, ErrorAction, LightningError, MAX_VALUE_MSAT};
 use crate::offers::invoice::{BlindedPayInfo, Bolt12Invoice};
 use crate::routing::gossip::{DirectedChannelInfo, EffectiveCapacity, ReadOnlyNetworkGraph, NetworkGraph, NodeId, RoutingFees};
-use crate::routing::scoring::{ChannelUsage, LockableScore, Score};
-use crate::util::ser::{Writeable, Readable, ReadableArgs, Writer};
+use crate::routing::scoring::{ChannelUsage, LockableScore, PeerLatencies, Score};
+use crate::util::ser::{Writeable, Readable, ReadableArgs, Writer, check_collection_len};
 use crate::util::logger::{Level, Logger};
 use crate::util::chacha20::ChaCha20;
 
@@ -29,7 +29,9 @@ use crate::io;
 use crate::prelude::*;
 use crate::sync::{Mutex};
 use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
 use core::{cmp, fmt};
+use core::convert::TryInto;
 use core::ops::{Deref, DerefMut};
 
 /// A [`Router`] implemented using [`find_route`].
@@ -41,7 +43,10 @@ pub struct DefaultRouter<G: Deref<Target = NetworkGraph<L>>, L: Deref, S: Deref,
 	logger: L,
 	random_seed_bytes: Mutex<[u8; 32]>,
 	scorer: S,
-	score_params: SP
+	score_params: SP,
+	use_min_cost_flow: bool,
+	route_cache: Option<Mutex<RouteCache>>,
+	peer_latencies: Option<(Arc<PeerLatencies>, u64)>,
 }
 
 impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, S: Deref, SP: Sized, Sc: Score<ScoreParams = SP>> DefaultRouter<G, L, S, SP, Sc> where
@@ -51,10 +56,269 @@ impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, S: Deref, SP: Sized, Sc: Scor
 	/// Creates a new router.
 	pub fn new(network_graph: G, logger: L, random_seed_bytes: [u8; 32], scorer: S, score_params: SP) -> Self {
 		let random_seed_bytes = Mutex::new(random_seed_bytes);
-		Self { network_graph, logger, random_seed_bytes, scorer, score_params }
+		Self {
+			network_graph, logger, random_seed_bytes, scorer, score_params, use_min_cost_flow: false,
+			route_cache: None, peer_latencies: None,
+		}
+	}
+
+	/// Finds routes using [`find_route_min_cost_flow`] rather than [`find_route`], jointly
+	/// optimizing the split of MPP payments across their paths.
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_min_cost_flow_splitting(mut self) -> Self {
+		self.use_min_cost_flow = true;
+		self
+	}
+
+	/// Caches single-path routes found for repeated payments to the same payee, see
+	/// [`RouteCacheConfig`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_route_cache(mut self, config: RouteCacheConfig) -> Self {
+		self.route_cache = Some(Mutex::new(RouteCache::new(config)));
+		self
+	}
+
+	/// Penalizes hops by their observed round-trip latency when routing payments marked
+	/// [`PaymentParameters::latency_sensitive`], adding `penalty_msat_per_ms` for each millisecond
+	/// of smoothed round-trip time to a hop's target node. See [`ScorerAccountingForLatency`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_peer_latencies(mut self, peer_latencies: Arc<PeerLatencies>, penalty_msat_per_ms: u64) -> Self {
+		self.peer_latencies = Some((peer_latencies, penalty_msat_per_ms));
+		self
+	}
+
+	/// Drops all routes from the route cache, if enabled via [`Self::with_route_cache`].
+	pub fn clear_route_cache(&self) {
+		if let Some(route_cache) = &self.route_cache {
+			route_cache.lock().unwrap().routes.clear();
+		}
 	}
 }
 
+/// Configuration for the route cache of a [`DefaultRouter`], enabled via
+/// [`DefaultRouter::with_route_cache`].
+///
+/// Routes are cached by payer, [`PaymentParameters`] and amount band, where each band covers
+/// amounts with the same most significant bit. A cached route is reused for any amount in its
+/// band, with fees recomputed for the new amount, until either one of the channels it uses
+/// receives a new update in the [`NetworkGraph`] or the total [`Score`] penalty of its channels
+/// shifts by more than [`Self::max_penalty_shift_msat`]. Until then, cheaper routes which become
+/// available are not considered.
+///
+/// Only routes consisting of a single path which does not end in a blinded path are cached.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RouteCacheConfig {
+	/// The maximum number of routes to cache. Once reached, the oldest route is dropped to make
+	/// room for a new one.
+	///
+	/// Default value: 128
+	pub max_entries: usize,
+	/// The amount by which the total penalty our [`Score`] assigns to the channels of a cached
+	/// route may change before the route is no longer used.
+	///
+	/// Default value: 10,000 msat
+	pub max_penalty_shift_msat: u64,
+}
+
+impl Default for RouteCacheConfig {
+	fn default() -> Self {
+		Self { max_entries: 128, max_penalty_shift_msat: 10_000 }
+	}
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+struct RouteCacheKey {
+	payer: PublicKey,
+	payment_params: PaymentParameters,
+	amount_band: u8,
+}
+
+impl RouteCacheKey {
+	fn new(payer: &PublicKey, route_params: &RouteParameters) -> Self {
+		Self {
+			payer: *payer,
+			payment_params: route_params.payment_params.clone(),
+			amount_band: (64 - route_params.final_value_msat.leading_zeros()) as u8,
+		}
+	}
+}
+
+struct CachedRoute {
+	/// The path as originally found.
+	path: Path,
+	/// The total penalty of `path` at the time it was found.
+	penalty_msat: u64,
+	/// The `last_update` of each hop's channel in the [`NetworkGraph`] at the time `path` was
+	/// found, or `None` if it was not in the graph.
+	channel_updates: Vec<Option<u32>>,
+	/// Used to find the oldest entry to evict.
+	insertion_index: u64,
+}
+
+struct RouteCache {
+	config: RouteCacheConfig,
+	routes: HashMap<RouteCacheKey, CachedRoute>,
+	insertion_count: u64,
+}
+
+impl RouteCache {
+	fn new(config: RouteCacheConfig) -> Self {
+		Self { config, routes: HashMap::new(), insertion_count: 0 }
+	}
+
+	/// Gets the cached route for `key`, adjusted to the amount in `route_params`, if it is still
+	/// valid. Drops the entry if it is not.
+	fn get<S: Score>(
+		&mut self, key: &RouteCacheKey, route_params: &RouteParameters,
+		first_hops: Option<&[&ChannelDetails]>, network_graph: &ReadOnlyNetworkGraph, scorer: &S,
+		score_params: &S::ScoreParams
+	) -> Option<Route> {
+		let path = {
+			let cached = self.routes.get(key)?;
+			let still_valid = channel_updates(&key.payer, &cached.path, network_graph) == cached.channel_updates && {
+				let penalty_msat = path_penalty_msat(&key.payer, &cached.path, network_graph, scorer, score_params);
+				cmp::max(penalty_msat, cached.penalty_msat) - cmp::min(penalty_msat, cached.penalty_msat)
+					<= self.config.max_penalty_shift_msat
+			};
+			if still_valid {
+				rescale_path(&key.payer, &cached.path, route_params, first_hops, network_graph)
+			} else {
+				None
+			}
+		};
+		match path {
+			Some(path) => Some(Route { paths: vec![path], payment_params: Some(route_params.payment_params.clone()) }),
+			None => {
+				self.routes.remove(key);
+				None
+			},
+		}
+	}
+
+	fn insert<S: Score>(
+		&mut self, key: RouteCacheKey, route: &Route, network_graph: &ReadOnlyNetworkGraph,
+		scorer: &S, score_params: &S::ScoreParams
+	) {
+		if route.paths.len() != 1 || route.paths[0].blinded_tail.is_some() { return; }
+		if self.config.max_entries == 0 { return; }
+		if !self.routes.contains_key(&key) && self.routes.len() >= self.config.max_entries {
+			let oldest = self.routes.iter().min_by_key(|(_, cached)| cached.insertion_index)
+				.map(|(key, _)| key.clone());
+			if let Some(oldest) = oldest { self.routes.remove(&oldest); }
+		}
+		let path = route.paths[0].clone();
+		let cached = CachedRoute {
+			penalty_msat: path_penalty_msat(&key.payer, &path, network_graph, scorer, score_params),
+			channel_updates: channel_updates(&key.payer, &path, network_graph),
+			insertion_index: self.insertion_count,
+			path,
+		};
+		self.insertion_count += 1;
+		self.routes.insert(key, cached);
+	}
+}
+
+/// Returns the source of each hop's channel in `path`, starting from `payer`.
+fn hop_sources<'a>(payer: &'a PublicKey, path: &'a Path) -> impl Iterator<Item = &'a PublicKey> + 'a {
+	core::iter::once(payer).chain(path.hops.iter().map(|hop| &hop.pubkey))
+}
+
+fn channel_updates(payer: &PublicKey, path: &Path, network_graph: &ReadOnlyNetworkGraph) -> Vec<Option<u32>> {
+	path.hops.iter().zip(hop_sources(payer, path)).map(|(hop, source)| {
+		network_graph.channel(hop.short_channel_id)
+			.and_then(|channel| channel.as_directed_from(&NodeId::from_pubkey(source)))
+			.map(|(directed_channel, _)| directed_channel.direction().last_update)
+	}).collect()
+}
+
+fn path_penalty_msat<S: Score>(
+	payer: &PublicKey, path: &Path, network_graph: &ReadOnlyNetworkGraph, scorer: &S,
+	score_params: &S::ScoreParams
+) -> u64 {
+	let mut penalty_msat = 0u64;
+	for (idx, (hop, source)) in path.hops.iter().zip(hop_sources(payer, path)).enumerate() {
+		let source = NodeId::from_pubkey(source);
+		let target = NodeId::from_pubkey(&hop.pubkey);
+		let amount_msat: u64 = path.hops[idx..].iter().map(|hop| hop.fee_msat).sum();
+		let effective_capacity = network_graph.channel(hop.short_channel_id)
+			.and_then(|channel| channel.as_directed_from(&source))
+			.map(|(directed_channel, _)| directed_channel.effective_capacity())
+			.unwrap_or(EffectiveCapacity::Unknown);
+		let usage = ChannelUsage { amount_msat, inflight_htlc_msat: 0, effective_capacity };
+		penalty_msat = penalty_msat.saturating_add(
+			scorer.channel_penalty_msat(hop.short_channel_id, &source, &target, usage, score_params));
+	}
+	penalty_msat
+}
+
+/// Recomputes the fees of `path` for sending `route_params.final_value_msat` along it, returning
+/// `None` if any of its channels can no longer carry the new amount.
+fn rescale_path(
+	payer: &PublicKey, path: &Path, route_params: &RouteParameters,
+	first_hops: Option<&[&ChannelDetails]>, network_graph: &ReadOnlyNetworkGraph
+) -> Option<Path> {
+	let route_hints: &[RouteHint] = match &route_params.payment_params.payee {
+		Payee::Clear { route_hints, .. } => route_hints,
+		Payee::Blinded { .. } => return None,
+	};
+	let mut hops = path.hops.clone();
+	let mut amount_msat = route_params.final_value_msat;
+	hops.last_mut()?.fee_msat = amount_msat;
+	for idx in (1..hops.len()).rev() {
+		let source = hops[idx - 1].pubkey;
+		let short_channel_id = hops[idx].short_channel_id;
+		let hint_hop = route_hints.iter().flat_map(|hint| hint.0.iter())
+			.find(|hint_hop| hint_hop.short_channel_id == short_channel_id && hint_hop.src_node_id == source);
+		let (fees, cltv_expiry_delta, htlc_minimum_msat, htlc_maximum_msat) = match hint_hop {
+			Some(hint_hop) => (
+				hint_hop.fees, hint_hop.cltv_expiry_delta, hint_hop.htlc_minimum_msat.unwrap_or(0),
+				hint_hop.htlc_maximum_msat.unwrap_or(u64::max_value()),
+			),
+			None => {
+				let (directed_channel, target) = network_graph.channel(short_channel_id)?
+					.as_directed_from(&NodeId::from_pubkey(&source))?;
+				if *target != NodeId::from_pubkey(&hops[idx].pubkey) { return None; }
+				let direction = directed_channel.direction();
+				if !direction.enabled { return None; }
+				(direction.fees, direction.cltv_expiry_delta, direction.htlc_minimum_msat, direction.htlc_maximum_msat)
+			},
+		};
+		if amount_msat < htlc_minimum_msat || amount_msat > htlc_maximum_msat { return None; }
+		if hops[idx - 1].cltv_expiry_delta != cltv_expiry_delta as u32 { return None; }
+		let fee_msat = compute_fees(amount_msat, fees)?;
+		hops[idx - 1].fee_msat = fee_msat;
+		amount_msat = amount_msat.checked_add(fee_msat)?;
+	}
+
+	// The first hop is over one of our own channels, which we check against `first_hops` if
+	// given, as they are what `find_route` would have used.
+	let first_hop = &hops[0];
+	match first_hops {
+		Some(first_hops) => {
+			let channel = first_hops.iter().find(|channel| {
+				channel.get_outbound_payment_scid() == Some(first_hop.short_channel_id) &&
+					channel.counterparty.node_id == first_hop.pubkey
+			})?;
+			if amount_msat > channel.next_outbound_htlc_limit_msat ||
+				amount_msat < channel.next_outbound_htlc_minimum_msat
+			{
+				return None;
+			}
+		},
+		None => {
+			let (directed_channel, target) = network_graph.channel(first_hop.short_channel_id)?
+				.as_directed_from(&NodeId::from_pubkey(payer))?;
+			if *target != NodeId::from_pubkey(&first_hop.pubkey) { return None; }
+			let direction = directed_channel.direction();
+			if !direction.enabled || amount_msat > direction.htlc_maximum_msat { return None; }
+		},
+	}
+	Some(Path { hops, blinded_tail: None })
+}
+
 impl< G: Deref<Target = NetworkGraph<L>>, L: Deref, S: Deref, SP: Sized, Sc: Score<ScoreParams = SP>> Router for DefaultRouter<G, L, S, SP, Sc> where
 	L::Target: Logger,
 	S::Target: for <'a> LockableScore<'a, Score = Sc>,
@@ -71,12 +335,45 @@ impl< G: Deref<Target = NetworkGraph<L>>, L: Deref, S: Deref, SP: Sized, Sc: Sco
 			*locked_random_seed_bytes = Sha256::hash(&*locked_random_seed_bytes).into_inner();
 			*locked_random_seed_bytes
 		};
-		find_route(
-			payer, params, &self.network_graph, first_hops, &*self.logger,
-			&ScorerAccountingForInFlightHtlcs::new(self.scorer.lock().deref_mut(), &inflight_htlcs),
-			&self.score_params,
-			&random_seed_bytes
-		)
+		let mut locked_scorer = self.scorer.lock();
+		let mut inflight_scorer = ScorerAccountingForInFlightHtlcs::new(locked_scorer.deref_mut(), &inflight_htlcs);
+		let (peer_latencies, penalty_msat_per_ms) = match &self.peer_latencies {
+			Some((peer_latencies, penalty_msat_per_ms)) if params.payment_params.latency_sensitive =>
+				(Some(&**peer_latencies), *penalty_msat_per_ms),
+			_ => (None, 0),
+		};
+		let scorer = ScorerAccountingForLatency::new(&mut inflight_scorer, peer_latencies, penalty_msat_per_ms);
+
+		let cache_key = self.route_cache.as_ref().map(|_| RouteCacheKey::new(payer, params));
+		if let (Some(route_cache), Some(cache_key)) = (&self.route_cache, &cache_key) {
+			let cached_route = route_cache.lock().unwrap().get(
+				cache_key, params, first_hops, &self.network_graph.read_only(), &scorer,
+				&self.score_params
+			);
+			if let Some(route) = cached_route {
+				log_trace!(self.logger, "Using cached route for payment of {}msat", params.final_value_msat);
+				return Ok(route);
+			}
+		}
+
+		let route = if self.use_min_cost_flow {
+			find_route_min_cost_flow(
+				payer, params, &self.network_graph, first_hops, &*self.logger, &scorer,
+				&self.score_params, &random_seed_bytes
+			)
+		} else {
+			find_route(
+				payer, params, &self.network_graph, first_hops, &*self.logger, &scorer,
+				&self.score_params, &random_seed_bytes
+			)
+		}?;
+
+		if let (Some(route_cache), Some(cache_key)) = (&self.route_cache, cache_key) {
+			route_cache.lock().unwrap().insert(
+				cache_key, &route, &self.network_graph.read_only(), &scorer, &self.score_params
+			);
+		}
+		Ok(route)
 	}
 }
 
@@ -104,6 +401,90 @@ pub trait Router {
 	) -> Result<Route, LightningError> {
 		self.find_route(payer, route_params, first_hops, inflight_htlcs)
 	}
+	/// Called before each retry of a payment sent via
+	/// [`ChannelManager::send_payment_with_pinned_route`], allowing the (subset of the) pinned
+	/// [`Route`] which is about to be retried to be modified for this attempt only.
+	///
+	/// `attempt` is the number of retries which have been made so far, including this one. The
+	/// default implementation leaves the pinned route untouched.
+	///
+	/// [`ChannelManager::send_payment_with_pinned_route`]: crate::ln::channelmanager::ChannelManager::send_payment_with_pinned_route
+	fn update_pinned_route(
+		&self, _route: &mut Route, _payment_hash: PaymentHash, _payment_id: PaymentId, _attempt: usize
+	) {}
+	/// Finds a [`Route`] for retrying a payment, which may resolve asynchronously by returning
+	/// [`RouteResult::Async`].
+	///
+	/// This is used by [`ChannelManager`]'s automatic payment retries in place of
+	/// [`Router::find_route_with_id`], allowing pathfinding to happen on another thread or be
+	/// delegated elsewhere without blocking the processing of other payments and HTLCs. The
+	/// arguments are identical to those of [`Router::find_route_with_id`].
+	///
+	/// The default implementation calls [`Router::find_route_with_id`] and resolves synchronously.
+	///
+	/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+	fn find_route_async(
+		&self, payer: &PublicKey, route_params: &RouteParameters,
+		first_hops: Option<&[&ChannelDetails]>, inflight_htlcs: InFlightHtlcs,
+		payment_hash: PaymentHash, payment_id: PaymentId
+	) -> RouteResult {
+		RouteResult::Sync(self.find_route_with_id(
+			payer, route_params, first_hops, inflight_htlcs, payment_hash, payment_id
+		))
+	}
+}
+
+/// The result of a [`Router::find_route_async`] call. A call may resolve either synchronously,
+/// returning the `Sync` variant, or asynchronously, returning a [`RouteFuture`] in the `Async`
+/// variant.
+#[derive(Clone)]
+pub enum RouteResult {
+	/// A result which was resolved synchronously.
+	Sync(Result<Route, LightningError>),
+	/// A result which will be resolved asynchronously. It includes a [`RouteFuture`], a `clone` of
+	/// which you must keep locally and call [`RouteFuture::resolve`] on once pathfinding completes.
+	///
+	/// The payment will not be retried again until the future is resolved. Thus, you should keep
+	/// a timeout on pathfinding and resolve the future with an error once it is hit.
+	Async(RouteFuture),
+}
+
+/// Represents a future resolution of a [`Router::find_route_async`] query resolving async.
+///
+/// See [`RouteResult::Async`] and [`RouteFuture::resolve`] for more info.
+#[derive(Clone)]
+pub struct RouteFuture {
+	result: Arc<Mutex<Option<Result<Route, LightningError>>>>,
+}
+
+impl RouteFuture {
+	/// Builds a new future for later resolution.
+	pub fn new() -> Self {
+		Self { result: Arc::new(Mutex::new(None)) }
+	}
+
+	/// Resolves this future with the given `result`.
+	///
+	/// The payment will be retried along the resulting [`Route`] (or abandoned if no route was
+	/// found) the next time [`ChannelManager::process_pending_htlc_forwards`] is called, which you
+	/// may wish to call after this.
+	///
+	/// If further parts of the payment failed or were retried while pathfinding was pending, the
+	/// [`RouteParameters`] the route was requested for are stale and a new route will be requested
+	/// instead.
+	///
+	/// [`ChannelManager::process_pending_htlc_forwards`]: crate::ln::channelmanager::ChannelManager::process_pending_htlc_forwards
+	pub fn resolve(&self, result: Result<Route, LightningError>) {
+		*self.result.lock().unwrap() = Some(result);
+	}
+
+	pub(crate) fn is_resolved(&self) -> bool {
+		self.result.lock().unwrap().is_some()
+	}
+
+	pub(crate) fn take_result(&self) -> Option<Result<Route, LightningError>> {
+		self.result.lock().unwrap().take()
+	}
 }
 
 /// [`Score`] implementation that factors in in-flight HTLC liquidity.
@@ -167,6 +548,67 @@ impl<'a, S: Score<ScoreParams = SP>, SP: Sized> Score for ScorerAccountingForInF
 	}
 }
 
+/// [`Score`] implementation that penalizes hops by the observed round-trip latency to their target
+/// node.
+///
+/// Useful for custom [`Router`] implementations to wrap their [`Score`] on-the-fly when calling
+/// [`find_route`] for payments marked [`PaymentParameters::latency_sensitive`]. Hops to nodes for
+/// which no latency has been observed are not penalized.
+///
+/// [`Score`]: crate::routing::scoring::Score
+pub struct ScorerAccountingForLatency<'a, S: Score<ScoreParams = SP>, SP: Sized> {
+	scorer: &'a mut S,
+	peer_latencies: Option<&'a PeerLatencies>,
+	penalty_msat_per_ms: u64,
+}
+
+impl<'a, S: Score<ScoreParams = SP>, SP: Sized> ScorerAccountingForLatency<'a, S, SP> {
+	/// Initialize a new `ScorerAccountingForLatency`, adding `penalty_msat_per_ms` for each
+	/// millisecond of round-trip time to a hop's target node as observed in `peer_latencies`.
+	pub fn new(scorer: &'a mut S, peer_latencies: Option<&'a PeerLatencies>, penalty_msat_per_ms: u64) -> Self {
+		ScorerAccountingForLatency {
+			scorer,
+			peer_latencies,
+			penalty_msat_per_ms,
+		}
+	}
+}
+
+#[cfg(c_bindings)]
+impl<'a, S: Score<ScoreParams = SP>, SP: Sized> Writeable for ScorerAccountingForLatency<'a, S, SP> {
+	fn write<W: Writer>(&self, writer: &mut W) -> Result<(), io::Error> { self.scorer.write(writer) }
+}
+
+impl<'a, S: Score<ScoreParams = SP>, SP: Sized> Score for ScorerAccountingForLatency<'a, S, SP>  {
+	type ScoreParams = S::ScoreParams;
+	fn channel_penalty_msat(&self, short_channel_id: u64, source: &NodeId, target: &NodeId, usage: ChannelUsage, score_params: &Self::ScoreParams) -> u64 {
+		let penalty_msat = self.scorer.channel_penalty_msat(short_channel_id, source, target, usage, score_params);
+		let latency_penalty_msat = self.peer_latencies
+			.and_then(|peer_latencies| peer_latencies.round_trip(target))
+			.map_or(0, |round_trip| {
+				let millis = cmp::min(round_trip.as_millis(), u64::max_value() as u128) as u64;
+				millis.saturating_mul(self.penalty_msat_per_ms)
+			});
+		penalty_msat.saturating_add(latency_penalty_msat)
+	}
+
+	fn payment_path_failed(&mut self, path: &Path, short_channel_id: u64) {
+		self.scorer.payment_path_failed(path, short_channel_id)
+	}
+
+	fn payment_path_successful(&mut self, path: &Path) {
+		self.scorer.payment_path_successful(path)
+	}
+
+	fn probe_failed(&mut self, path: &Path, short_channel_id: u64) {
+		self.scorer.probe_failed(path, short_channel_id)
+	}
+
+	fn probe_successful(&mut self, path: &Path) {
+		self.scorer.probe_successful(path)
+	}
+}
+
 /// A data structure for tracking in-flight HTLCs. May be used during pathfinding to account for
 /// in-use channel liquidity.
 #[derive(Clone)]
@@ -348,7 +790,7 @@ impl Path {
 
 /// A route directs a payment from the sender (us) to the recipient. If the recipient supports MPP,
 /// it can take multiple paths. Each path is composed of one or more hops through the network.
-#[derive(Clone, Hash, PartialEq, Eq)]
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub struct Route {
 	/// The list of [`Path`]s taken for a single (potentially-)multi-part payment. If no
 	/// [`BlindedTail`]s are present, then the pubkey of the last [`RouteHop`] in each path must be
@@ -412,6 +854,7 @@ impl Readable for Route {
 		let _ver = read_ver_prefix!(reader, SERIALIZATION_VERSION);
 		let path_count: u64 = Readable::read(reader)?;
 		if path_count == 0 { return Err(DecodeError::InvalidValue); }
+		check_collection_len(path_count)?;
 		let mut paths = Vec::with_capacity(cmp::min(path_count, 128) as usize);
 		let mut min_final_cltv_expiry_delta = u32::max_value();
 		for _ in 0..path_count {
@@ -547,6 +990,31 @@ pub struct PaymentParameters {
 	/// payment to fail. Future attempts for the same payment shouldn't be relayed through any of
 	/// these SCIDs.
 	pub previously_failed_channels: Vec<u64>,
+
+	/// The block height at which this payment must no longer be attempted.
+	///
+	/// Once our best known block height reaches this value, `ChannelManager` will not send or
+	/// retry the payment any further, allowing any in-flight HTLCs to fail and generating an
+	/// [`Event::PaymentDeadlineExceeded`].
+	///
+	/// [`Event::PaymentDeadlineExceeded`]: crate::events::Event::PaymentDeadlineExceeded
+	pub deadline_block_height: Option<u32>,
+
+	/// The time, in seconds relative to the UNIX epoch, at which this payment must no longer be
+	/// attempted.
+	///
+	/// Behaves like [`Self::deadline_block_height`], but is only checked with the `std` feature
+	/// enabled, as is [`Self::expiry_time`].
+	pub deadline_time: Option<u64>,
+
+	/// Whether this payment should prefer hops with low observed round-trip latency over otherwise
+	/// cheaper ones.
+	///
+	/// This only has an effect when routing with a [`DefaultRouter`] which was given a
+	/// [`PeerLatencies`] via [`DefaultRouter::with_peer_latencies`].
+	///
+	/// Default value: false
+	pub latency_sensitive: bool,
 }
 
 impl Writeable for PaymentParameters {
@@ -568,6 +1036,9 @@ impl Writeable for PaymentParameters {
 			(7, self.previously_failed_channels, required_vec),
 			(8, *blinded_hints, optional_vec),
 			(9, self.payee.final_cltv_expiry_delta(), option),
+			(11, self.deadline_block_height, option),
+			(13, self.latency_sensitive, required),
+			(15, self.deadline_time, option),
 		});
 		Ok(())
 	}
@@ -586,6 +1057,9 @@ impl ReadableArgs<u32> for PaymentParameters {
 			(7, previously_failed_channels, optional_vec),
 			(8, blinded_route_hints, optional_vec),
 			(9, final_cltv_expiry_delta, (default_value, default_final_cltv_expiry_delta)),
+			(11, deadline_block_height, option),
+			(13, latency_sensitive, (default_value, false)),
+			(15, deadline_time, option),
 		});
 		let blinded_route_hints = blinded_route_hints.unwrap_or(vec![]);
 		let payee = if blinded_route_hints.len() != 0 {
@@ -609,6 +1083,9 @@ impl ReadableArgs<u32> for PaymentParameters {
 			max_channel_saturation_power_of_half: _init_tlv_based_struct_field!(max_channel_saturation_power_of_half, (default_value, unused)),
 			expiry_time,
 			previously_failed_channels: previously_failed_channels.unwrap_or(Vec::new()),
+			deadline_block_height,
+			deadline_time,
+			latency_sensitive: latency_sensitive.0.unwrap(),
 		})
 	}
 }
@@ -627,6 +1104,9 @@ impl PaymentParameters {
 			max_path_count: DEFAULT_MAX_PATH_COUNT,
 			max_channel_saturation_power_of_half: DEFAULT_MAX_CHANNEL_SATURATION_POW_HALF,
 			previously_failed_channels: Vec::new(),
+			deadline_block_height: None,
+			deadline_time: None,
+			latency_sensitive: false,
 		}
 	}
 
@@ -664,6 +1144,9 @@ impl PaymentParameters {
 			max_path_count: DEFAULT_MAX_PATH_COUNT,
 			max_channel_saturation_power_of_half: DEFAULT_MAX_CHANNEL_SATURATION_POW_HALF,
 			previously_failed_channels: Vec::new(),
+			deadline_block_height: None,
+			deadline_time: None,
+			latency_sensitive: false,
 		}
 	}
 
@@ -718,6 +1201,29 @@ impl PaymentParameters {
 		Self { expiry_time: Some(expiry_time), ..self }
 	}
 
+	/// Includes a block height at which the payment must no longer be attempted. See
+	/// [`PaymentParameters::deadline_block_height`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_deadline_block_height(self, deadline_block_height: u32) -> Self {
+		Self { deadline_block_height: Some(deadline_block_height), ..self }
+	}
+
+	/// Includes a time, in seconds relative to the UNIX epoch, at which the payment must no longer
+	/// be attempted. See [`PaymentParameters::deadline_time`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_deadline_time(self, deadline_time: u64) -> Self {
+		Self { deadline_time: Some(deadline_time), ..self }
+	}
+
+	/// Marks the payment as latency-sensitive. See [`PaymentParameters::latency_sensitive`].
+	///
+	/// This is not exported to bindings users since bindings don't support move semantics
+	pub fn with_latency_sensitive(self, latency_sensitive: bool) -> Self {
+		Self { latency_sensitive, ..self }
+	}
+
 	/// Includes a limit for the total CLTV expiry delta which is considered during routing
 	///
 	/// This is not exported to bindings users since bindings don't support move semantics
@@ -2664,13 +3170,378 @@ fn build_route_from_hops_internal<L: Deref>(
 		logger, &scorer, &(), random_seed_bytes)
 }
 
+/// The number of units the payment amount is split into by [`find_route_min_cost_flow`].
+const MIN_COST_FLOW_UNITS: u64 = 64;
+
+/// The number of times [`find_route_min_cost_flow`] re-runs pathfinding to discover additional
+/// candidate paths.
+const MIN_COST_FLOW_CANDIDATE_ROUNDS: usize = 4;
+
+/// Finds a route from us (payer) to the given target node (payee), splitting the payment across
+/// multiple paths by solving a min-cost flow problem rather than greedily collecting paths one at
+/// a time as [`find_route`] does.
+///
+/// A set of candidate paths is first gathered by repeatedly running the regular pathfinding
+/// algorithm, treating the paths found in earlier rounds as in-flight so that later rounds explore
+/// alternative liquidity. The payment amount is then split into a fixed number of units, each of
+/// which is assigned to the candidate path which increases the total cost the least, after which
+/// single units are moved between paths for as long as doing so reduces the total cost. The total
+/// cost is the sum of the fees paid and the [`Score`]'s penalty for the *combined* amount sent
+/// over each channel, such that parts sharing a channel are optimized jointly against its
+/// estimated liquidity. This generally improves the success probability of very large payments.
+///
+/// The resulting route is only used if it is cheaper than the one [`find_route`] returns, which is
+/// returned as-is for payees which do not support MPP or which are behind blinded paths.
+///
+/// The same restrictions as for [`find_route`] apply.
+pub fn find_route_min_cost_flow<L: Deref, GL: Deref, S: Score>(
+	our_node_pubkey: &PublicKey, route_params: &RouteParameters,
+	network_graph: &NetworkGraph<GL>, first_hops: Option<&[&ChannelDetails]>, logger: L,
+	scorer: &S, score_params: &S::ScoreParams, random_seed_bytes: &[u8; 32]
+) -> Result<Route, LightningError>
+where L::Target: Logger, GL::Target: Logger {
+	let graph_lock = network_graph.read_only();
+	let payment_params = &route_params.payment_params;
+	let mut route = get_route(our_node_pubkey, payment_params, &graph_lock, first_hops,
+		route_params.final_value_msat, &*logger, scorer, score_params, random_seed_bytes)?;
+	if payment_params.payee.node_id().is_some() && payment_params.payee.supports_basic_mpp() &&
+		payment_params.max_path_count > 1
+	{
+		if let Some(mcf_route) = get_min_cost_flow_route(our_node_pubkey, route_params, &graph_lock,
+			first_hops, &*logger, scorer, score_params, random_seed_bytes, &route)
+		{
+			log_debug!(logger, "Using min-cost flow route with {} paths in place of route with {} paths",
+				mcf_route.paths.len(), route.paths.len());
+			route = mcf_route;
+		}
+	}
+	add_random_cltv_offset(&mut route, payment_params, &graph_lock, random_seed_bytes);
+	Ok(route)
+}
+
+/// Returns a route splitting the payment via a min-cost flow over candidate paths, if one is found
+/// which is cheaper than `baseline_route`. See [`find_route_min_cost_flow`].
+fn get_min_cost_flow_route<L: Deref, S: Score>(
+	our_node_pubkey: &PublicKey, route_params: &RouteParameters, network_graph: &ReadOnlyNetworkGraph,
+	first_hops: Option<&[&ChannelDetails]>, logger: L, scorer: &S, score_params: &S::ScoreParams,
+	random_seed_bytes: &[u8; 32], baseline_route: &Route
+) -> Option<Route> where L::Target: Logger {
+	let payment_params = &route_params.payment_params;
+	let our_node_id = NodeId::from_pubkey(our_node_pubkey);
+
+	// Gather candidate paths, starting with the ones in the baseline route such that the baseline
+	// allocation can be expressed in terms of the candidates.
+	let mut candidates: Vec<MinCostFlowPath> = Vec::new();
+	let mut inflight_htlcs = InFlightHtlcs::new();
+	let mut round_route = baseline_route.clone();
+	for round in 0..=MIN_COST_FLOW_CANDIDATE_ROUNDS {
+		let mut found_new_path = false;
+		for path in round_route.paths.iter() {
+			inflight_htlcs.process_path(path, *our_node_pubkey);
+			if candidates.iter().any(|candidate| iter_equal(
+				candidate.hops.iter().map(|hop| hop.short_channel_id),
+				path.hops.iter().map(|hop| hop.short_channel_id)))
+			{
+				continue;
+			}
+			match MinCostFlowPath::new(path, our_node_id, payment_params, network_graph, first_hops) {
+				Some(candidate) => {
+					candidates.push(candidate);
+					found_new_path = true;
+				},
+				None if round == 0 => return None,
+				None => {},
+			}
+		}
+		if round == MIN_COST_FLOW_CANDIDATE_ROUNDS || !found_new_path { break; }
+		let round_scorer = InFlightAccountingScorer { scorer, inflight_htlcs: &inflight_htlcs };
+		round_route = match get_route(our_node_pubkey, payment_params, network_graph, first_hops,
+			route_params.final_value_msat, &*logger, &round_scorer, score_params, random_seed_bytes)
+		{
+			Ok(route) => route,
+			Err(_) => break,
+		};
+	}
+	if candidates.len() <= 1 { return None; }
+
+	let mut baseline_flow = MinCostFlow::new(&candidates, scorer, score_params);
+	for (idx, path) in baseline_route.paths.iter().enumerate() {
+		baseline_flow.set_value(idx, path.final_value_msat())?;
+	}
+
+	let mut excluded = vec![false; candidates.len()];
+	loop {
+		let flow = MinCostFlow::solve(&candidates, &excluded, route_params.final_value_msat,
+			scorer, score_params)?;
+		let mut used_paths: Vec<usize> = (0..candidates.len()).filter(|idx| flow.values[*idx] > 0).collect();
+		if used_paths.len() > payment_params.max_path_count as usize {
+			// Drop the path carrying the least value and try again without it.
+			used_paths.sort_unstable_by_key(|idx| flow.values[*idx]);
+			excluded[used_paths[0]] = true;
+			continue;
+		}
+		if flow.total_cost >= baseline_flow.total_cost { return None; }
+		let paths = used_paths.iter()
+			.map(|idx| candidates[*idx].to_path(flow.values[*idx]))
+			.collect::<Option<Vec<_>>>()?;
+		return Some(Route { paths, payment_params: Some(payment_params.clone()) });
+	}
+}
+
+/// A candidate path considered by [`find_route_min_cost_flow`], along with the information needed
+/// to send arbitrary amounts over it.
+struct MinCostFlowPath {
+	hops: Vec<RouteHop>,
+	/// The source and target of each hop's channel.
+	nodes: Vec<(NodeId, NodeId)>,
+	/// The fees charged for forwarding over each hop's channel, which are always zero for the
+	/// first hop.
+	fees: Vec<RoutingFees>,
+	capacities: Vec<EffectiveCapacity>,
+	htlc_minimums_msat: Vec<u64>,
+}
+
+impl MinCostFlowPath {
+	fn new(
+		path: &Path, our_node_id: NodeId, payment_params: &PaymentParameters,
+		network_graph: &ReadOnlyNetworkGraph, first_hops: Option<&[&ChannelDetails]>
+	) -> Option<Self> {
+		if path.blinded_tail.is_some() { return None; }
+
+		let mut nodes = Vec::with_capacity(path.hops.len());
+		let mut fees = Vec::with_capacity(path.hops.len());
+		let mut capacities = Vec::with_capacity(path.hops.len());
+		let mut htlc_minimums_msat = Vec::with_capacity(path.hops.len());
+		let mut source = our_node_id;
+		for (idx, hop) in path.hops.iter().enumerate() {
+			let target = NodeId::from_pubkey(&hop.pubkey);
+			let first_hop = if idx == 0 {
+				first_hops.and_then(|hops| hops.iter()
+					.find(|details| details.get_outbound_payment_scid() == Some(hop.short_channel_id))
+					.copied())
+			} else { None };
+			let candidate = if let Some(details) = first_hop {
+				CandidateRouteHop::FirstHop { details }
+			} else if let Some((info, _)) = network_graph.channels().get(&hop.short_channel_id)
+				.and_then(|channel| channel.as_directed_from(&source))
+			{
+				CandidateRouteHop::PublicHop { info, short_channel_id: hop.short_channel_id }
+			} else if let Some(hint) = payment_params.payee.unblinded_route_hints().iter()
+				.flat_map(|route_hint| route_hint.0.iter())
+				.find(|hint| hint.short_channel_id == hop.short_channel_id &&
+					NodeId::from_pubkey(&hint.src_node_id) == source)
+			{
+				CandidateRouteHop::PrivateHop { hint }
+			} else {
+				return None;
+			};
+			nodes.push((source, target));
+			fees.push(if idx == 0 { RoutingFees { base_msat: 0, proportional_millionths: 0 } } else { candidate.fees() });
+			capacities.push(candidate.effective_capacity());
+			htlc_minimums_msat.push(candidate.htlc_minimum_msat());
+			source = target;
+		}
+
+		Some(Self { hops: path.hops.clone(), nodes, fees, capacities, htlc_minimums_msat })
+	}
+
+	/// Returns the amount sent over each hop's channel when delivering `value_msat` to the payee.
+	fn channel_amounts_msat(&self, value_msat: u64) -> Option<Vec<u64>> {
+		let mut amounts_msat = vec![0; self.hops.len()];
+		let mut amount_msat = value_msat;
+		for idx in (0..self.hops.len()).rev() {
+			amounts_msat[idx] = amount_msat;
+			amount_msat = amount_msat.checked_add(compute_fees(amount_msat, self.fees[idx])?)?;
+		}
+		Some(amounts_msat)
+	}
+
+	fn to_path(&self, value_msat: u64) -> Option<Path> {
+		let amounts_msat = self.channel_amounts_msat(value_msat)?;
+		let mut hops = self.hops.clone();
+		for idx in 0..hops.len() {
+			hops[idx].fee_msat = match amounts_msat.get(idx + 1) {
+				Some(next_amount_msat) => amounts_msat[idx] - next_amount_msat,
+				None => value_msat,
+			};
+		}
+		Some(Path { hops, blinded_tail: None })
+	}
+}
+
+/// An allocation of value to [`MinCostFlowPath`]s, tracking the total amount sent over each
+/// directed channel and the resulting total cost.
+struct MinCostFlow<'a, S: Score> {
+	candidates: &'a [MinCostFlowPath],
+	scorer: &'a S,
+	score_params: &'a S::ScoreParams,
+	values: Vec<u64>,
+	channel_amounts_msat: Vec<Vec<u64>>,
+	/// Keyed by short channel id and direction, as in [`InFlightHtlcs`].
+	channel_flows_msat: HashMap<(u64, bool), u64>,
+	total_cost: u64,
+}
+
+impl<'a, S: Score> MinCostFlow<'a, S> {
+	fn new(candidates: &'a [MinCostFlowPath], scorer: &'a S, score_params: &'a S::ScoreParams) -> Self {
+		Self {
+			candidates, scorer, score_params,
+			values: vec![0; candidates.len()],
+			channel_amounts_msat: candidates.iter().map(|candidate| vec![0; candidate.hops.len()]).collect(),
+			channel_flows_msat: HashMap::new(),
+			total_cost: 0,
+		}
+	}
+
+	/// Allocates `final_value_msat` across the non-`excluded` candidates.
+	fn solve(
+		candidates: &'a [MinCostFlowPath], excluded: &[bool], final_value_msat: u64, scorer: &'a S,
+		score_params: &'a S::ScoreParams
+	) -> Option<Self> {
+		let mut flow = Self::new(candidates, scorer, score_params);
+		let unit_count = cmp::max(cmp::min(MIN_COST_FLOW_UNITS, final_value_msat), 1);
+		let unit_msat = final_value_msat / unit_count;
+
+		// Greedily assign each unit to the path where it is cheapest, with any remainder added to
+		// the first unit.
+		for unit_idx in 0..unit_count {
+			let step_msat = if unit_idx == 0 { unit_msat + final_value_msat % unit_count } else { unit_msat };
+			let mut best: Option<(usize, u64)> = None;
+			for idx in (0..candidates.len()).filter(|idx| !excluded[*idx]) {
+				if let Some(cost) = flow.cost_with_value(idx, flow.values[idx] + step_msat) {
+					if best.map_or(true, |(_, best_cost)| cost < best_cost) { best = Some((idx, cost)); }
+				}
+			}
+			let (idx, _) = best?;
+			flow.set_value(idx, flow.values[idx] + step_msat)?;
+		}
+
+		// Move single units between paths for as long as doing so reduces the total cost.
+		for _ in 0..unit_count {
+			let mut best: Option<(usize, usize, u64)> = None;
+			for from_idx in 0..candidates.len() {
+				let from_value = flow.values[from_idx];
+				if from_value < unit_msat { continue; }
+				if flow.set_value(from_idx, from_value - unit_msat).is_none() { continue; }
+				for to_idx in (0..candidates.len()).filter(|idx| !excluded[*idx] && *idx != from_idx) {
+					if let Some(cost) = flow.cost_with_value(to_idx, flow.values[to_idx] + unit_msat) {
+						if best.map_or(true, |(_, _, best_cost)| cost < best_cost) {
+							best = Some((from_idx, to_idx, cost));
+						}
+					}
+				}
+				flow.set_value(from_idx, from_value)?;
+			}
+			match best {
+				Some((from_idx, to_idx, cost)) if cost < flow.total_cost => {
+					flow.set_value(from_idx, flow.values[from_idx] - unit_msat)?;
+					flow.set_value(to_idx, flow.values[to_idx] + unit_msat)?;
+				},
+				_ => break,
+			}
+		}
+		Some(flow)
+	}
+
+	/// Returns the total cost of the flow if the value sent over the given candidate were changed
+	/// to `value_msat`, or `None` if doing so is infeasible.
+	fn cost_with_value(&self, candidate_idx: usize, value_msat: u64) -> Option<u64> {
+		let candidate = &self.candidates[candidate_idx];
+		let old_amounts_msat = &self.channel_amounts_msat[candidate_idx];
+		let new_amounts_msat = candidate.channel_amounts_msat(value_msat)?;
+		let mut cost = self.total_cost as i128;
+		for (hop_idx, hop) in candidate.hops.iter().enumerate() {
+			if value_msat > 0 && new_amounts_msat[hop_idx] < candidate.htlc_minimums_msat[hop_idx] {
+				return None;
+			}
+			let (source, target) = candidate.nodes[hop_idx];
+			let old_flow_msat = self.channel_flows_msat
+				.get(&(hop.short_channel_id, source < target)).copied().unwrap_or(0);
+			let new_flow_msat = old_flow_msat - old_amounts_msat[hop_idx] + new_amounts_msat[hop_idx];
+			cost += self.channel_cost(candidate_idx, hop_idx, new_flow_msat)? as i128;
+			cost -= self.channel_cost(candidate_idx, hop_idx, old_flow_msat)? as i128;
+		}
+		let old_fee_msat = old_amounts_msat[0] - self.values[candidate_idx];
+		let new_fee_msat = new_amounts_msat[0] - value_msat;
+		cost += new_fee_msat as i128 - old_fee_msat as i128;
+		cost.try_into().ok()
+	}
+
+	fn set_value(&mut self, candidate_idx: usize, value_msat: u64) -> Option<()> {
+		let total_cost = self.cost_with_value(candidate_idx, value_msat)?;
+		let candidate = &self.candidates[candidate_idx];
+		let new_amounts_msat = candidate.channel_amounts_msat(value_msat)?;
+		for (hop_idx, hop) in candidate.hops.iter().enumerate() {
+			let (source, target) = candidate.nodes[hop_idx];
+			let flow_msat = self.channel_flows_msat.entry((hop.short_channel_id, source < target)).or_insert(0);
+			*flow_msat = *flow_msat - self.channel_amounts_msat[candidate_idx][hop_idx] + new_amounts_msat[hop_idx];
+		}
+		self.channel_amounts_msat[candidate_idx] = new_amounts_msat;
+		self.values[candidate_idx] = value_msat;
+		self.total_cost = total_cost;
+		Some(())
+	}
+
+	/// Returns the penalty for sending a total of `flow_msat` over the given hop's channel, or
+	/// `None` if it cannot carry that amount.
+	fn channel_cost(&self, candidate_idx: usize, hop_idx: usize, flow_msat: u64) -> Option<u64> {
+		if flow_msat == 0 { return Some(0); }
+		let candidate = &self.candidates[candidate_idx];
+		let effective_capacity = candidate.capacities[hop_idx];
+		if flow_msat > max_htlc_from_capacity(effective_capacity, 0) { return None; }
+		let (source, target) = candidate.nodes[hop_idx];
+		let usage = ChannelUsage { amount_msat: flow_msat, inflight_htlc_msat: 0, effective_capacity };
+		let penalty_msat = self.scorer.channel_penalty_msat(
+			candidate.hops[hop_idx].short_channel_id, &source, &target, usage, self.score_params);
+		if penalty_msat == u64::max_value() { None } else { Some(penalty_msat) }
+	}
+}
+
+/// A [`Score`] which accounts for the liquidity used by the given [`InFlightHtlcs`], used by
+/// [`find_route_min_cost_flow`] to discover alternative candidate paths.
+struct InFlightAccountingScorer<'a, S: Score> {
+	scorer: &'a S,
+	inflight_htlcs: &'a InFlightHtlcs,
+}
+
+impl<'a, S: Score> Score for InFlightAccountingScorer<'a, S> {
+	type ScoreParams = S::ScoreParams;
+	fn channel_penalty_msat(&self, short_channel_id: u64, source: &NodeId, target: &NodeId,
+		usage: ChannelUsage, score_params: &Self::ScoreParams) -> u64
+	{
+		let used_liquidity_msat = self.inflight_htlcs
+			.used_liquidity_msat(source, target, short_channel_id).unwrap_or(0);
+		let usage = ChannelUsage {
+			inflight_htlc_msat: usage.inflight_htlc_msat.saturating_add(used_liquidity_msat),
+			..usage
+		};
+		self.scorer.channel_penalty_msat(short_channel_id, source, target, usage, score_params)
+	}
+
+	fn payment_path_failed(&mut self, _path: &Path, _short_channel_id: u64) {}
+
+	fn payment_path_successful(&mut self, _path: &Path) {}
+
+	fn probe_failed(&mut self, _path: &Path, _short_channel_id: u64) {}
+
+	fn probe_successful(&mut self, _path: &Path) {}
+}
+
+impl<'a, S: Score> Writeable for InFlightAccountingScorer<'a, S> {
+	#[inline]
+	fn write<W: Writer>(&self, _w: &mut W) -> Result<(), io::Error> {
+		unreachable!();
+	}
+}
+
 #[cfg(test)]
 mod tests {
 	use crate::blinded_path::{BlindedHop, BlindedPath};
 	use crate::routing::gossip::{NetworkGraph, P2PGossipSync, NodeId, EffectiveCapacity};
 	use crate::routing::utxo::UtxoResult;
 	use crate::routing::router::{get_route, build_route_from_hops_internal, add_random_cltv_offset, default_node_features,
-		BlindedTail, InFlightHtlcs, Path, PaymentParameters, Route, RouteHint, RouteHintHop, RouteHop, RoutingFees,
+		find_route_min_cost_flow, BlindedTail, DefaultRouter, InFlightHtlcs, Path, PaymentParameters, Route, RouteCacheConfig,
+		RouteHint, RouteHintHop, RouteHop, RouteParameters, Router, RoutingFees,
 		DEFAULT_MAX_TOTAL_CLTV_EXPIRY_DELTA, MAX_PATH_LENGTH_ESTIMATE};
 	use crate::routing::scoring::{ChannelUsage, FixedPenaltyScorer, Score, ProbabilisticScorer, ProbabilisticScoringFeeParameters, ProbabilisticScoringDecayParameters};
 	use crate::routing::test_utils::{add_channel, add_or_update_node, build_graph, build_line_graph, id_to_feature_flags, get_nodes, update_channel};
@@ -2701,7 +3572,7 @@ mod tests {
 
 	use crate::io::Cursor;
 	use crate::prelude::*;
-	use crate::sync::Arc;
+	use crate::sync::{Arc, Mutex};
 
 	use core::convert::TryInto;
 
@@ -2740,6 +3611,7 @@ mod tests {
 			config: None,
 			feerate_sat_per_1000_weight: None,
 			channel_shutdown_state: Some(channelmanager::ChannelShutdownState::NotShuttingDown),
+			pending_dust_forwards_msat: 0,
 		}
 	}
 
@@ -2776,6 +3648,93 @@ mod tests {
 		assert_eq!(route.paths[0].hops[1].channel_features.le_flags(), &id_to_feature_flags(4));
 	}
 
+	#[test]
+	#[cfg(not(c_bindings))]
+	fn route_cache_test() {
+		// Check that `DefaultRouter` reuses cached routes until one of their channels is updated
+		// or their penalty shifts.
+		let (secp_ctx, network_graph, gossip_sync, _, logger) = build_graph();
+		let (_, our_id, privkeys, nodes) = get_nodes(&secp_ctx);
+		let scorer = Mutex::new(FixedPenaltyScorer::with_penalty(0));
+		let router = DefaultRouter::new(network_graph.clone(), Arc::clone(&logger), [42; 32], &scorer, ())
+			.with_route_cache(RouteCacheConfig::default());
+		let route_params = |final_value_msat| RouteParameters {
+			payment_params: PaymentParameters::from_node_id(nodes[2], 42), final_value_msat,
+		};
+		let route_scids = |route: &Route| route.paths[0].hops.iter().map(|hop| hop.short_channel_id).collect::<Vec<_>>();
+		let update_chan_13 = |timestamp, fee_proportional_millionths| {
+			update_channel(&gossip_sync, &secp_ctx, &privkeys[7], UnsignedChannelUpdate {
+				chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+				short_channel_id: 13,
+				timestamp,
+				flags: 0,
+				cltv_expiry_delta: (13 << 4) | 1,
+				htlc_minimum_msat: 0,
+				htlc_maximum_msat: MAX_VALUE_MSAT,
+				fee_base_msat: 0,
+				fee_proportional_millionths,
+				excess_data: Vec::new()
+			});
+		};
+
+		let route = router.find_route(&our_id, &route_params(100), None, InFlightHtlcs::new()).unwrap();
+		assert_eq!(route_scids(&route), vec![2, 4]);
+
+		// Make the route via node 7 free. As no channel along the cached route changed, it is still
+		// used for amounts in the same band, with fees adjusted to the new amount.
+		update_chan_13(2, 0);
+		let route = router.find_route(&our_id, &route_params(110), None, InFlightHtlcs::new()).unwrap();
+		assert_eq!(route_scids(&route), vec![2, 4]);
+		assert_eq!(route.paths[0].hops[0].fee_msat, 110);
+		assert_eq!(route.paths[0].hops[1].fee_msat, 110);
+
+		// Amounts in another band aren't served from the cache.
+		let route = router.find_route(&our_id, &route_params(1000), None, InFlightHtlcs::new()).unwrap();
+		assert_eq!(route_scids(&route), vec![12, 13]);
+
+		// Once the penalty of the cached route shifts, a new route is found.
+		*scorer.lock().unwrap() = FixedPenaltyScorer::with_penalty(100_000);
+		let route = router.find_route(&our_id, &route_params(100), None, InFlightHtlcs::new()).unwrap();
+		assert_eq!(route_scids(&route), vec![12, 13]);
+
+		// As is the case when a channel along the cached route is updated.
+		update_chan_13(3, 2_000_000);
+		let route = router.find_route(&our_id, &route_params(100), None, InFlightHtlcs::new()).unwrap();
+		assert_eq!(route_scids(&route), vec![2, 4]);
+
+		router.clear_route_cache();
+	}
+
+	#[test]
+	#[cfg(not(c_bindings))]
+	fn peer_latency_test() {
+		// Check that `DefaultRouter` avoids high-latency hops only for latency-sensitive payments.
+		use crate::routing::scoring::PeerLatencies;
+		use core::time::Duration;
+
+		let (secp_ctx, network_graph, _, _, logger) = build_graph();
+		let (_, our_id, _, nodes) = get_nodes(&secp_ctx);
+		let scorer = Mutex::new(FixedPenaltyScorer::with_penalty(0));
+		let peer_latencies = Arc::new(PeerLatencies::new());
+		let router = DefaultRouter::new(network_graph.clone(), Arc::clone(&logger), [42; 32], &scorer, ())
+			.with_peer_latencies(Arc::clone(&peer_latencies), 1_000);
+		let route_params = |latency_sensitive| RouteParameters {
+			payment_params: PaymentParameters::from_node_id(nodes[2], 42)
+				.with_latency_sensitive(latency_sensitive),
+			final_value_msat: 100,
+		};
+		let route_scids = |route: &Route| route.paths[0].hops.iter().map(|hop| hop.short_channel_id).collect::<Vec<_>>();
+
+		peer_latencies.record_round_trip(&NodeId::from_pubkey(&nodes[1]), Duration::from_millis(100));
+		peer_latencies.record_round_trip(&NodeId::from_pubkey(&nodes[7]), Duration::from_millis(10));
+
+		let route = router.find_route(&our_id, &route_params(false), None, InFlightHtlcs::new()).unwrap();
+		assert_eq!(route_scids(&route), vec![2, 4]);
+
+		let route = router.find_route(&our_id, &route_params(true), None, InFlightHtlcs::new()).unwrap();
+		assert_eq!(route_scids(&route), vec![12, 13]);
+	}
+
 	#[test]
 	fn invalid_first_hop_test() {
 		let (secp_ctx, network_graph, _, _, logger) = build_graph();
@@ -6122,6 +7081,60 @@ mod tests {
 			(route.paths[1].hops[1].short_channel_id == 4 && route.paths[0].hops[1].short_channel_id == 13));
 	}
 
+	#[test]
+	fn min_cost_flow_splits_across_paths() {
+		let (secp_ctx, network_graph, gossip_sync, _, logger) = build_graph();
+		let (_, our_id, privkeys, nodes) = get_nodes(&secp_ctx);
+		let decay_params = ProbabilisticScoringDecayParameters::default();
+		let scorer = ProbabilisticScorer::new(decay_params, &*network_graph, Arc::clone(&logger));
+
+		// As in `avoids_saturating_channels`, give us two equivalent paths to node 2 (via node 7 and
+		// via node 1).
+		for (privkey, short_channel_id) in [(&privkeys[1], 4), (&privkeys[7], 13)].iter() {
+			update_channel(&gossip_sync, &secp_ctx, privkey, UnsignedChannelUpdate {
+				chain_hash: genesis_block(Network::Testnet).header.block_hash(),
+				short_channel_id: *short_channel_id,
+				timestamp: 2,
+				flags: 0,
+				cltv_expiry_delta: ((*short_channel_id as u16) << 4) | 1,
+				htlc_minimum_msat: 0,
+				htlc_maximum_msat: 250_000_000,
+				fee_base_msat: 0,
+				fee_proportional_millionths: 0,
+				excess_data: Vec::new()
+			});
+		}
+
+		let config = UserConfig::default();
+		let payment_params = PaymentParameters::from_node_id(nodes[2], 42).with_bolt11_features(channelmanager::provided_invoice_features(&config)).unwrap();
+		let route_params = RouteParameters { payment_params: payment_params.clone(), final_value_msat: 100_000_000 };
+		let keys_manager = ln_test_utils::TestKeysInterface::new(&[0u8; 32], Network::Testnet);
+		let random_seed_bytes = keys_manager.get_secure_random_bytes();
+		let route = find_route_min_cost_flow(&our_id, &route_params, &*network_graph, None,
+			Arc::clone(&logger), &scorer, &ProbabilisticScoringFeeParameters::default(), &random_seed_bytes).unwrap();
+
+		// The flow must be split across both paths, deliver exactly the requested amount, and each
+		// path's fees must be consistent with the amount it carries.
+		assert!(route.paths.len() >= 2);
+		assert!(route.paths.len() <= payment_params.max_path_count as usize);
+		assert!(route.paths.iter().any(|path| path.hops[1].short_channel_id == 4));
+		assert!(route.paths.iter().any(|path| path.hops[1].short_channel_id == 13));
+		assert_eq!(route.get_total_amount(), 100_000_000);
+		for path in route.paths.iter() {
+			assert_eq!(path.hops.last().unwrap().pubkey, nodes[2]);
+			assert!(path.final_value_msat() > 0);
+		}
+
+		// Payees which don't support MPP are routed over a single path.
+		let single_path_params = RouteParameters {
+			payment_params: PaymentParameters::from_node_id(nodes[2], 42), final_value_msat: 100_000,
+		};
+		let route = find_route_min_cost_flow(&our_id, &single_path_params, &*network_graph, None,
+			Arc::clone(&logger), &scorer, &ProbabilisticScoringFeeParameters::default(), &random_seed_bytes).unwrap();
+		assert_eq!(route.paths.len(), 1);
+		assert_eq!(route.get_total_amount(), 100_000);
+	}
+
 	#[cfg(not(feature = "no-std"))]
 	pub(super) fn random_init_seed() -> u64 {
 		// Because the default HashMap in std pulls OS randomness, we can use it as a (bad) RNG.
@@ -6811,6 +7824,7 @@ pub(crate) mod bench_utils {
 			config: None,
 			feerate_sat_per_1000_weight: None,
 			channel_shutdown_state: Some(channelmanager::ChannelShutdownState::NotShuttingDown),
+			pending_dust_forwards_msat: 0,
 		}
 	}
 
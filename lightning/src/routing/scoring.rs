@@ -61,7 +61,7 @@ use crate::routing::gossip::{EffectiveCapacity, NetworkGraph, NodeId};
 use crate::routing::router::Path;
 use crate::util::ser::{Readable, ReadableArgs, Writeable, Writer};
 use crate::util::logger::Logger;
-use crate::util::time::Time;
+use crate::util::time::{ConfiguredTime, Time};
 
 use crate::prelude::*;
 use core::{cmp, fmt};
@@ -316,12 +316,50 @@ impl ReadableArgs<u64> for FixedPenaltyScorer {
 	}
 }
 
-#[cfg(not(feature = "no-std"))]
-type ConfiguredTime = crate::util::time::MonotonicTime;
-#[cfg(feature = "no-std")]
-use crate::util::time::Eternity;
-#[cfg(feature = "no-std")]
-type ConfiguredTime = Eternity;
+/// Round-trip latencies observed to peers, used to prefer low-latency hops for payments marked
+/// [`PaymentParameters::latency_sensitive`].
+///
+/// Latencies are usually fed in by a [`PeerManager`] via [`PeerManager::set_peer_latencies`],
+/// which measures the time its peers take to respond to `ping`s, and consumed by a
+/// [`DefaultRouter`] via [`DefaultRouter::with_peer_latencies`]. Each observation is smoothed with
+/// those before it, so that a single slow response doesn't dominate.
+///
+/// [`PaymentParameters::latency_sensitive`]: crate::routing::router::PaymentParameters::latency_sensitive
+/// [`PeerManager`]: crate::ln::peer_handler::PeerManager
+/// [`PeerManager::set_peer_latencies`]: crate::ln::peer_handler::PeerManager::set_peer_latencies
+/// [`DefaultRouter`]: crate::routing::router::DefaultRouter
+/// [`DefaultRouter::with_peer_latencies`]: crate::routing::router::DefaultRouter::with_peer_latencies
+pub struct PeerLatencies {
+	/// The smoothed round-trip time to each node, in microseconds.
+	round_trip_micros: Mutex<HashMap<NodeId, u64>>,
+}
+
+impl PeerLatencies {
+	/// Creates a new, empty set of latencies.
+	pub fn new() -> Self {
+		Self { round_trip_micros: Mutex::new(HashMap::new()) }
+	}
+
+	/// Records an observed round trip to the given node.
+	///
+	/// As with TCP's smoothed round-trip time, the new observation is given a weight of 1/8.
+	pub fn record_round_trip(&self, node_id: &NodeId, round_trip: Duration) {
+		let observed = cmp::min(round_trip.as_micros(), u64::max_value() as u128) as u64;
+		self.round_trip_micros.lock().unwrap().entry(*node_id)
+			.and_modify(|smoothed| *smoothed = *smoothed - *smoothed / 8 + observed / 8)
+			.or_insert(observed);
+	}
+
+	/// Returns the smoothed round-trip time to the given node, if we've observed any.
+	pub fn round_trip(&self, node_id: &NodeId) -> Option<Duration> {
+		self.round_trip_micros.lock().unwrap().get(node_id).map(|micros| Duration::from_micros(*micros))
+	}
+
+	/// Forgets all latencies observed for the given node.
+	pub fn remove_node(&self, node_id: &NodeId) {
+		self.round_trip_micros.lock().unwrap().remove(node_id);
+	}
+}
 
 /// [`Score`] implementation using channel success probability distributions.
 ///
@@ -563,6 +601,18 @@ impl ProbabilisticScoringFeeParameters {
 	}
 }
 
+impl_writeable_tlv_based!(ProbabilisticScoringFeeParameters, {
+	(0, base_penalty_msat, required),
+	(2, base_penalty_amount_multiplier_msat, required),
+	(4, liquidity_penalty_multiplier_msat, required),
+	(6, liquidity_penalty_amount_multiplier_msat, required),
+	(8, historical_liquidity_penalty_multiplier_msat, required),
+	(10, historical_liquidity_penalty_amount_multiplier_msat, required),
+	(12, manual_node_penalties, required),
+	(14, anti_probing_penalty_msat, required),
+	(16, considered_impossible_penalty_msat, required),
+});
+
 #[cfg(test)]
 impl ProbabilisticScoringFeeParameters {
 	fn zero_penalty() -> Self {
@@ -582,9 +632,10 @@ impl ProbabilisticScoringFeeParameters {
 
 /// Parameters for configuring [`ProbabilisticScorer`].
 ///
-/// Used to configure decay parameters that are static throughout the lifetime of the scorer.
-/// these decay parameters affect the score of the channel penalty and are not changed on a
-/// per-route penalty cost call.
+/// Used to configure decay parameters which, unlike [`ProbabilisticScoringFeeParameters`], are
+/// not provided on a per-route penalty cost call. They are set at construction, are persisted
+/// alongside the scorer's learned state, and may be re-tuned at runtime via
+/// [`ProbabilisticScorerUsingTime::set_decay_params`] without discarding any learned data.
 #[derive(Copy, Clone)]
 pub struct ProbabilisticScoringDecayParameters {
 	/// If we aren't learning any new datapoints for a channel, the historical liquidity bounds
@@ -620,6 +671,29 @@ pub struct ProbabilisticScoringDecayParameters {
 	/// When built with the `no-std` feature, time will never elapse. Therefore, the channel
 	/// liquidity knowledge will never decay except when the bounds cross.
 	pub liquidity_offset_half_life: Duration,
+
+	/// Each time a new datapoint is added to a channel's historical liquidity buckets, the weight
+	/// of all existing datapoints is multiplied by `(divisor - 1) / divisor`. Lower values thus
+	/// weight recent observations more heavily relative to older ones. For example, a value of
+	/// 64 roughly halves the weight of an observation once 44 newer observations have been made,
+	/// whereas the default only does so after roughly 1,400.
+	///
+	/// Values below 2 are treated as 2.
+	///
+	/// Default value: 2048
+	pub historical_datapoint_decay_divisor: u16,
+
+	/// If set, in addition to the channel-wide historical liquidity buckets, a separate set of
+	/// buckets is tracked for each six-hour window of the week. When scoring, the buckets for the
+	/// current window are used in place of the channel-wide ones once at least one datapoint has
+	/// been observed in that window, allowing the scorer to learn recurring patterns, e.g., a
+	/// counterparty whose channels are regularly depleted at the same time each day.
+	///
+	/// This increases the memory and serialized size of each tracked channel by roughly 900 bytes
+	/// once datapoints have been observed for it.
+	///
+	/// Default value: false
+	pub weekly_seasonality: bool,
 }
 
 impl Default for ProbabilisticScoringDecayParameters {
@@ -627,16 +701,27 @@ impl Default for ProbabilisticScoringDecayParameters {
 		Self {
 			liquidity_offset_half_life: Duration::from_secs(6 * 60 * 60),
 			historical_no_updates_half_life: Duration::from_secs(60 * 60 * 24 * 14),
+			historical_datapoint_decay_divisor: 2048,
+			weekly_seasonality: false,
 		}
 	}
 }
 
+impl_writeable_tlv_based!(ProbabilisticScoringDecayParameters, {
+	(0, historical_no_updates_half_life, required),
+	(2, liquidity_offset_half_life, required),
+	(4, historical_datapoint_decay_divisor, (default_value, 2048)),
+	(6, weekly_seasonality, (default_value, false)),
+});
+
 #[cfg(test)]
 impl ProbabilisticScoringDecayParameters {
 	fn zero_penalty() -> Self {
 		Self {
 			liquidity_offset_half_life: Duration::from_secs(6 * 60 * 60),
 			historical_no_updates_half_life: Duration::from_secs(60 * 60 * 24 * 14),
+			historical_datapoint_decay_divisor: 2048,
+			weekly_seasonality: false,
 		}
 	}
 }
@@ -650,7 +735,7 @@ struct HistoricalBucketRangeTracker {
 
 impl HistoricalBucketRangeTracker {
 	fn new() -> Self { Self { buckets: [0; 8] } }
-	fn track_datapoint(&mut self, liquidity_offset_msat: u64, capacity_msat: u64) {
+	fn track_datapoint(&mut self, liquidity_offset_msat: u64, capacity_msat: u64, decay_divisor: u16) {
 		// We have 8 leaky buckets for min and max liquidity. Each bucket tracks the amount of time
 		// we spend in each bucket as a 16-bit fixed-point number with a 5 bit fractional part.
 		//
@@ -670,7 +755,9 @@ impl HistoricalBucketRangeTracker {
 		// datapoints.
 		//
 		// The constants were picked experimentally, selecting a decay amount that restricts us
-		// from overflowing buckets without having to cap them manually.
+		// from overflowing buckets without having to cap them manually. Users may configure a
+		// smaller divisor via `historical_datapoint_decay_divisor`, which decays faster and thus
+		// cannot overflow either.
 
 		// Ensure the bucket index is in the range [0, 7], even if the liquidity offset is zero or
 		// the channel's capacity, though the second should generally never happen.
@@ -679,8 +766,9 @@ impl HistoricalBucketRangeTracker {
 			.try_into().unwrap_or(32); // 32 is bogus for 8 buckets, and will be ignored
 		debug_assert!(bucket_idx < 8);
 		if bucket_idx < 8 {
+			let decay_divisor = cmp::max(decay_divisor, 2) as u32;
 			for e in self.buckets.iter_mut() {
-				*e = ((*e as u32) * 2047 / 2048) as u16;
+				*e = ((*e as u32) * (decay_divisor - 1) / decay_divisor) as u16;
 			}
 			self.buckets[bucket_idx as usize] = self.buckets[bucket_idx as usize].saturating_add(32);
 		}
@@ -696,6 +784,47 @@ impl HistoricalBucketRangeTracker {
 
 impl_writeable_tlv_based!(HistoricalBucketRangeTracker, { (0, buckets, required) });
 
+/// The number of windows per week for which [`SeasonalLiquidityHistory`] tracks buckets.
+const SEASONAL_WINDOWS: usize = 28;
+/// The length of each window tracked by [`SeasonalLiquidityHistory`].
+const SEASONAL_WINDOW_SECS: u64 = 7 * 24 * 60 * 60 / SEASONAL_WINDOWS as u64;
+
+/// Historical liquidity buckets tracked separately for each window of the week. See
+/// [`ProbabilisticScoringDecayParameters::weekly_seasonality`].
+struct SeasonalLiquidityHistory {
+	min_liquidity_offset_history: Vec<HistoricalBucketRangeTracker>,
+	max_liquidity_offset_history: Vec<HistoricalBucketRangeTracker>,
+}
+
+impl SeasonalLiquidityHistory {
+	fn new() -> Self {
+		Self {
+			min_liquidity_offset_history: vec![HistoricalBucketRangeTracker::new(); SEASONAL_WINDOWS],
+			max_liquidity_offset_history: vec![HistoricalBucketRangeTracker::new(); SEASONAL_WINDOWS],
+		}
+	}
+
+	fn current_window_idx<T: Time>() -> usize {
+		((T::duration_since_epoch().as_secs() / SEASONAL_WINDOW_SECS) % SEASONAL_WINDOWS as u64) as usize
+	}
+
+	/// Returns the min and max buckets for the current window, if at least one full datapoint has
+	/// been tracked in it.
+	fn current_window<T: Time>(&self) -> Option<(&HistoricalBucketRangeTracker, &HistoricalBucketRangeTracker)> {
+		let idx = Self::current_window_idx::<T>();
+		let min = self.min_liquidity_offset_history.get(idx)?;
+		let max = self.max_liquidity_offset_history.get(idx)?;
+		let has_datapoint = |tracker: &HistoricalBucketRangeTracker|
+			tracker.buckets.iter().map(|b| *b as u32).sum::<u32>() >= 32;
+		if has_datapoint(min) && has_datapoint(max) { Some((min, max)) } else { None }
+	}
+}
+
+impl_writeable_tlv_based!(SeasonalLiquidityHistory, {
+	(0, min_liquidity_offset_history, optional_vec),
+	(2, max_liquidity_offset_history, optional_vec),
+});
+
 struct HistoricalMinMaxBuckets<'a> {
 	min_liquidity_offset_history: &'a HistoricalBucketRangeTracker,
 	max_liquidity_offset_history: &'a HistoricalBucketRangeTracker,
@@ -799,6 +928,10 @@ struct ChannelLiquidity<T: Time> {
 
 	min_liquidity_offset_history: HistoricalBucketRangeTracker,
 	max_liquidity_offset_history: HistoricalBucketRangeTracker,
+
+	/// Per-window historical buckets, only tracked if
+	/// [`ProbabilisticScoringDecayParameters::weekly_seasonality`] is set.
+	seasonal_history: Option<SeasonalLiquidityHistory>,
 }
 
 /// A snapshot of [`ChannelLiquidity`] in one direction assuming a certain channel capacity and
@@ -827,6 +960,21 @@ impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, T: Time> ProbabilisticScorerU
 		}
 	}
 
+	/// Returns the decay parameters currently in use by this scorer.
+	pub fn decay_params(&self) -> ProbabilisticScoringDecayParameters {
+		self.decay_params
+	}
+
+	/// Replaces the decay parameters used by this scorer.
+	///
+	/// Learned liquidity bounds and historical buckets are retained as-is. Because decay is
+	/// applied lazily based on the time elapsed since each channel was last updated, the new
+	/// half-lives take effect for all channels immediately, including for time which elapsed
+	/// before this call.
+	pub fn set_decay_params(&mut self, decay_params: ProbabilisticScoringDecayParameters) {
+		self.decay_params = decay_params;
+	}
+
 	#[cfg(test)]
 	fn with_channel(mut self, short_channel_id: u64, liquidity: ChannelLiquidity<T>) -> Self {
 		assert!(self.channel_liquidities.insert(short_channel_id, liquidity).is_none());
@@ -953,6 +1101,32 @@ impl<T: Time> ChannelLiquidity<T> {
 			min_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
 			max_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
 			last_updated: T::now(),
+			seasonal_history: None,
+		}
+	}
+
+	/// Tracks the current (decayed) liquidity bounds in the buckets for the current window of the
+	/// week, if [`ProbabilisticScoringDecayParameters::weekly_seasonality`] is set.
+	fn update_seasonal_history(&mut self, capacity_msat: u64, decay_params: ProbabilisticScoringDecayParameters) {
+		if !decay_params.weekly_seasonality { return; }
+
+		let decays = T::now().duration_since(self.last_updated).as_secs()
+			.checked_div(decay_params.liquidity_offset_half_life.as_secs());
+		let decayed_offset_msat = |offset_msat: u64| decays
+			.and_then(|decays| offset_msat.checked_shr(decays as u32))
+			.unwrap_or(0);
+		let min_liquidity_offset_msat = decayed_offset_msat(self.min_liquidity_offset_msat);
+		let max_liquidity_offset_msat = decayed_offset_msat(self.max_liquidity_offset_msat);
+
+		let idx = SeasonalLiquidityHistory::current_window_idx::<T>();
+		let history = self.seasonal_history.get_or_insert_with(SeasonalLiquidityHistory::new);
+		if let Some(tracker) = history.min_liquidity_offset_history.get_mut(idx) {
+			tracker.track_datapoint(min_liquidity_offset_msat, capacity_msat,
+				decay_params.historical_datapoint_decay_divisor);
+		}
+		if let Some(tracker) = history.max_liquidity_offset_history.get_mut(idx) {
+			tracker.track_datapoint(max_liquidity_offset_msat, capacity_msat,
+				decay_params.historical_datapoint_decay_divisor);
 		}
 	}
 
@@ -961,13 +1135,16 @@ impl<T: Time> ChannelLiquidity<T> {
 	fn as_directed(
 		&self, source: &NodeId, target: &NodeId, inflight_htlc_msat: u64, capacity_msat: u64, decay_params: ProbabilisticScoringDecayParameters
 	) -> DirectedChannelLiquidity<&u64, &HistoricalBucketRangeTracker, T, &T> {
+		let seasonal_history = if decay_params.weekly_seasonality {
+			self.seasonal_history.as_ref().and_then(|history| history.current_window::<T>())
+		} else { None };
+		let (min_history, max_history) = seasonal_history
+			.unwrap_or((&self.min_liquidity_offset_history, &self.max_liquidity_offset_history));
 		let (min_liquidity_offset_msat, max_liquidity_offset_msat, min_liquidity_offset_history, max_liquidity_offset_history) =
 			if source < target {
-				(&self.min_liquidity_offset_msat, &self.max_liquidity_offset_msat,
-					&self.min_liquidity_offset_history, &self.max_liquidity_offset_history)
+				(&self.min_liquidity_offset_msat, &self.max_liquidity_offset_msat, min_history, max_history)
 			} else {
-				(&self.max_liquidity_offset_msat, &self.min_liquidity_offset_msat,
-					&self.max_liquidity_offset_history, &self.min_liquidity_offset_history)
+				(&self.max_liquidity_offset_msat, &self.min_liquidity_offset_msat, max_history, min_history)
 			};
 
 		DirectedChannelLiquidity {
@@ -1189,11 +1366,11 @@ impl<L: DerefMut<Target = u64>, BRT: DerefMut<Target = HistoricalBucketRangeTrac
 
 		let min_liquidity_offset_msat = self.decayed_offset_msat(*self.min_liquidity_offset_msat);
 		self.min_liquidity_offset_history.track_datapoint(
-			min_liquidity_offset_msat, self.capacity_msat
+			min_liquidity_offset_msat, self.capacity_msat, self.decay_params.historical_datapoint_decay_divisor
 		);
 		let max_liquidity_offset_msat = self.decayed_offset_msat(*self.max_liquidity_offset_msat);
 		self.max_liquidity_offset_history.track_datapoint(
-			max_liquidity_offset_msat, self.capacity_msat
+			max_liquidity_offset_msat, self.capacity_msat, self.decay_params.historical_datapoint_decay_divisor
 		);
 	}
 
@@ -1282,19 +1459,19 @@ impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, T: Time> Score for Probabilis
 			// Only score announced channels.
 			if let Some((channel, source)) = channel_directed_from_source {
 				let capacity_msat = channel.effective_capacity().as_msat();
+				let liquidity = self.channel_liquidities
+					.entry(hop.short_channel_id)
+					.or_insert_with(ChannelLiquidity::new);
 				if at_failed_channel {
-					self.channel_liquidities
-						.entry(hop.short_channel_id)
-						.or_insert_with(ChannelLiquidity::new)
+					liquidity
 						.as_directed_mut(source, &target, 0, capacity_msat, self.decay_params)
 						.failed_at_channel(amount_msat, format_args!("SCID {}, towards {:?}", hop.short_channel_id, target), &self.logger);
 				} else {
-					self.channel_liquidities
-						.entry(hop.short_channel_id)
-						.or_insert_with(ChannelLiquidity::new)
+					liquidity
 						.as_directed_mut(source, &target, 0, capacity_msat, self.decay_params)
 						.failed_downstream(amount_msat, format_args!("SCID {}, towards {:?}", hop.short_channel_id, target), &self.logger);
 				}
+				liquidity.update_seasonal_history(capacity_msat, self.decay_params);
 			} else {
 				log_debug!(self.logger, "Not able to penalize channel with SCID {} as we do not have graph info for it (likely a route-hint last-hop).",
 					hop.short_channel_id);
@@ -1317,11 +1494,13 @@ impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, T: Time> Score for Probabilis
 			// Only score announced channels.
 			if let Some((channel, source)) = channel_directed_from_source {
 				let capacity_msat = channel.effective_capacity().as_msat();
-				self.channel_liquidities
+				let liquidity = self.channel_liquidities
 					.entry(hop.short_channel_id)
-					.or_insert_with(ChannelLiquidity::new)
+					.or_insert_with(ChannelLiquidity::new);
+				liquidity
 					.as_directed_mut(source, &target, 0, capacity_msat, self.decay_params)
 					.successful(amount_msat, format_args!("SCID {}, towards {:?}", hop.short_channel_id, target), &self.logger);
+				liquidity.update_seasonal_history(capacity_msat, self.decay_params);
 			} else {
 				log_debug!(self.logger, "Not able to learn for channel with SCID {} as we do not have graph info for it (likely a route-hint last-hop).",
 					hop.short_channel_id);
@@ -1653,11 +1832,14 @@ impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, T: Time> Writeable for Probab
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
 		write_tlv_fields!(w, {
 			(0, self.channel_liquidities, required),
+			(1, self.decay_params, required),
 		});
 		Ok(())
 	}
 }
 
+/// Reads a scorer using the provided [`ProbabilisticScoringDecayParameters`], overriding any
+/// decay parameters which were persisted alongside it.
 impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, T: Time>
 ReadableArgs<(ProbabilisticScoringDecayParameters, G, L)> for ProbabilisticScorerUsingTime<G, L, T> where L::Target: Logger {
 	#[inline]
@@ -1666,8 +1848,10 @@ ReadableArgs<(ProbabilisticScoringDecayParameters, G, L)> for ProbabilisticScore
 	) -> Result<Self, DecodeError> {
 		let (decay_params, network_graph, logger) = args;
 		let mut channel_liquidities = HashMap::new();
+		let mut _persisted_decay_params: Option<ProbabilisticScoringDecayParameters> = None;
 		read_tlv_fields!(r, {
 			(0, channel_liquidities, required),
+			(1, _persisted_decay_params, option),
 		});
 		Ok(Self {
 			decay_params,
@@ -1678,6 +1862,31 @@ ReadableArgs<(ProbabilisticScoringDecayParameters, G, L)> for ProbabilisticScore
 	}
 }
 
+/// Reads a scorer using the [`ProbabilisticScoringDecayParameters`] which were persisted alongside
+/// it, such that the restored scorer behaves identically to the one which was written.
+///
+/// Scorers written by versions which did not persist their decay parameters are restored using
+/// [`ProbabilisticScoringDecayParameters::default`].
+impl<G: Deref<Target = NetworkGraph<L>>, L: Deref, T: Time>
+ReadableArgs<(G, L)> for ProbabilisticScorerUsingTime<G, L, T> where L::Target: Logger {
+	#[inline]
+	fn read<R: Read>(r: &mut R, args: (G, L)) -> Result<Self, DecodeError> {
+		let (network_graph, logger) = args;
+		let mut channel_liquidities = HashMap::new();
+		let mut decay_params: Option<ProbabilisticScoringDecayParameters> = None;
+		read_tlv_fields!(r, {
+			(0, channel_liquidities, required),
+			(1, decay_params, option),
+		});
+		Ok(Self {
+			decay_params: decay_params.unwrap_or_default(),
+			network_graph,
+			logger,
+			channel_liquidities,
+		})
+	}
+}
+
 impl<T: Time> Writeable for ChannelLiquidity<T> {
 	#[inline]
 	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
@@ -1688,6 +1897,7 @@ impl<T: Time> Writeable for ChannelLiquidity<T> {
 			(2, self.max_liquidity_offset_msat, required),
 			(3, Some(self.max_liquidity_offset_history), option),
 			(4, duration_since_epoch, required),
+			(5, self.seasonal_history, option),
 		});
 		Ok(())
 	}
@@ -1701,12 +1911,14 @@ impl<T: Time> Readable for ChannelLiquidity<T> {
 		let mut min_liquidity_offset_history = Some(HistoricalBucketRangeTracker::new());
 		let mut max_liquidity_offset_history = Some(HistoricalBucketRangeTracker::new());
 		let mut duration_since_epoch = Duration::from_secs(0);
+		let mut seasonal_history = None;
 		read_tlv_fields!(r, {
 			(0, min_liquidity_offset_msat, required),
 			(1, min_liquidity_offset_history, option),
 			(2, max_liquidity_offset_msat, required),
 			(3, max_liquidity_offset_history, option),
 			(4, duration_since_epoch, required),
+			(5, seasonal_history, option),
 		});
 		// On rust prior to 1.60 `Instant::duration_since` will panic if time goes backwards.
 		// We write `last_updated` as wallclock time even though its ultimately an `Instant` (which
@@ -1726,6 +1938,7 @@ impl<T: Time> Readable for ChannelLiquidity<T> {
 			min_liquidity_offset_history: min_liquidity_offset_history.unwrap(),
 			max_liquidity_offset_history: max_liquidity_offset_history.unwrap(),
 			last_updated,
+			seasonal_history,
 		})
 	}
 }
@@ -1912,12 +2125,14 @@ mod tests {
 					min_liquidity_offset_msat: 700, max_liquidity_offset_msat: 100, last_updated,
 					min_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
 					max_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
+					seasonal_history: None,
 				})
 			.with_channel(43,
 				ChannelLiquidity {
 					min_liquidity_offset_msat: 700, max_liquidity_offset_msat: 100, last_updated,
 					min_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
 					max_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
+					seasonal_history: None,
 				});
 		let source = source_node_id();
 		let target = target_node_id();
@@ -1990,6 +2205,7 @@ mod tests {
 					min_liquidity_offset_msat: 200, max_liquidity_offset_msat: 400, last_updated,
 					min_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
 					max_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
+					seasonal_history: None,
 				});
 		let source = source_node_id();
 		let target = target_node_id();
@@ -2049,6 +2265,7 @@ mod tests {
 					min_liquidity_offset_msat: 200, max_liquidity_offset_msat: 400, last_updated,
 					min_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
 					max_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
+					seasonal_history: None,
 				});
 		let source = source_node_id();
 		let target = target_node_id();
@@ -2161,6 +2378,7 @@ mod tests {
 					min_liquidity_offset_msat: 40, max_liquidity_offset_msat: 40, last_updated,
 					min_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
 					max_liquidity_offset_history: HistoricalBucketRangeTracker::new(),
+					seasonal_history: None,
 				});
 		let source = source_node_id();
 		let target = target_node_id();
@@ -2609,6 +2827,61 @@ mod tests {
 		assert_eq!(deserialized_scorer.channel_penalty_msat(42, &source, &target, usage, &params), 365);
 	}
 
+	#[test]
+	fn restores_persisted_decay_params() {
+		let logger = TestLogger::new();
+		let network_graph = network_graph(&logger);
+		let params = ProbabilisticScoringFeeParameters {
+			liquidity_penalty_multiplier_msat: 1_000,
+			considered_impossible_penalty_msat: u64::max_value(),
+			..ProbabilisticScoringFeeParameters::zero_penalty()
+		};
+		let decay_params = ProbabilisticScoringDecayParameters {
+			liquidity_offset_half_life: Duration::from_secs(10),
+			..ProbabilisticScoringDecayParameters::zero_penalty()
+		};
+		let mut scorer = ProbabilisticScorer::new(decay_params, &network_graph, &logger);
+		let source = source_node_id();
+		let target = target_node_id();
+		let usage = ChannelUsage {
+			amount_msat: 500,
+			inflight_htlc_msat: 0,
+			effective_capacity: EffectiveCapacity::Total { capacity_msat: 1_000, htlc_maximum_msat: 1_000 },
+		};
+
+		scorer.payment_path_failed(&payment_path_for_amount(500), 42);
+		assert_eq!(scorer.channel_penalty_msat(42, &source, &target, usage, &params), u64::max_value());
+
+		let mut serialized_scorer = Vec::new();
+		scorer.write(&mut serialized_scorer).unwrap();
+
+		SinceEpoch::advance(Duration::from_secs(10));
+
+		// Reading without explicit decay parameters uses the persisted ones.
+		let deserialized_scorer = <ProbabilisticScorer>::read(
+			&mut io::Cursor::new(&serialized_scorer), (&network_graph, &logger)).unwrap();
+		assert_eq!(deserialized_scorer.decay_params().liquidity_offset_half_life, Duration::from_secs(10));
+		assert_eq!(deserialized_scorer.channel_penalty_msat(42, &source, &target, usage, &params), 473);
+
+		// Re-tuning at runtime keeps the learned bounds but applies the new half-life.
+		let mut retuned_scorer = deserialized_scorer;
+		retuned_scorer.set_decay_params(ProbabilisticScoringDecayParameters {
+			liquidity_offset_half_life: Duration::from_secs(20),
+			..decay_params
+		});
+		assert!(retuned_scorer.channel_penalty_msat(42, &source, &target, usage, &params) > 473);
+		retuned_scorer.set_decay_params(decay_params);
+		assert_eq!(retuned_scorer.channel_penalty_msat(42, &source, &target, usage, &params), 473);
+
+		// Fee parameters round-trip as well.
+		let mut fee_params = params.clone();
+		fee_params.add_banned(&source);
+		let read_fee_params: ProbabilisticScoringFeeParameters =
+			crate::util::ser::Readable::read(&mut io::Cursor::new(&fee_params.encode())).unwrap();
+		assert_eq!(read_fee_params.liquidity_penalty_multiplier_msat, 1_000);
+		assert_eq!(read_fee_params.manual_node_penalties.get(&source), Some(&u64::max_value()));
+	}
+
 	#[test]
 	fn scores_realistic_payments() {
 		// Shows the scores of "realistic" sends of 100k sats over channels of 1-10m sats (with a
@@ -2801,6 +3074,74 @@ mod tests {
 		assert_eq!(scorer.channel_penalty_msat(42, &source, &target, usage, &params), u64::max_value());
 	}
 
+	#[test]
+	fn weights_recent_history_by_decay_divisor() {
+		let logger = TestLogger::new();
+		let network_graph = network_graph(&logger);
+		let decay_params = ProbabilisticScoringDecayParameters {
+			liquidity_offset_half_life: Duration::from_secs(60 * 60),
+			historical_datapoint_decay_divisor: 4,
+			..ProbabilisticScoringDecayParameters::default()
+		};
+		let mut scorer = ProbabilisticScorer::new(decay_params, &network_graph, &logger);
+		let target = target_node_id();
+
+		scorer.payment_path_failed(&payment_path_for_amount(1), 42);
+		assert_eq!(scorer.historical_estimated_channel_liquidity_probabilities(42, &target),
+			Some(([32, 0, 0, 0, 0, 0, 0, 0], [32, 0, 0, 0, 0, 0, 0, 0])));
+
+		// With a divisor of 4 the older datapoint loses a quarter of its weight, rather than the
+		// 1/2048th lost by default.
+		scorer.payment_path_failed(&payment_path_for_amount(1000), 43);
+		assert_eq!(scorer.historical_estimated_channel_liquidity_probabilities(42, &target),
+			Some(([24, 0, 0, 0, 0, 0, 0, 32], [24, 0, 0, 0, 0, 0, 0, 32])));
+	}
+
+	#[test]
+	fn tracks_weekly_seasonality() {
+		let logger = TestLogger::new();
+		let network_graph = network_graph(&logger);
+		let decay_params = ProbabilisticScoringDecayParameters {
+			liquidity_offset_half_life: Duration::from_secs(60 * 60),
+			weekly_seasonality: true,
+			..ProbabilisticScoringDecayParameters::default()
+		};
+		let mut scorer = ProbabilisticScorer::new(decay_params, &network_graph, &logger);
+		let target = target_node_id();
+
+		scorer.payment_path_failed(&payment_path_for_amount(1), 42);
+		let first_window_history = Some(([32, 0, 0, 0, 0, 0, 0, 0], [32, 0, 0, 0, 0, 0, 0, 0]));
+		assert_eq!(scorer.historical_estimated_channel_liquidity_probabilities(42, &target),
+			first_window_history);
+
+		// In a window without any datapoints we fall back to the channel-wide history.
+		SinceEpoch::advance(Duration::from_secs(6 * 60 * 60));
+		assert_eq!(scorer.historical_estimated_channel_liquidity_probabilities(42, &target),
+			first_window_history);
+		scorer.payment_path_failed(&payment_path_for_amount(1000), 43);
+		let second_window_history = scorer.historical_estimated_channel_liquidity_probabilities(42, &target);
+		assert_ne!(second_window_history, first_window_history);
+
+		// A week after the first datapoint we're back in its window, which only saw that datapoint.
+		SinceEpoch::advance(Duration::from_secs(7 * 24 * 60 * 60 - 6 * 60 * 60));
+		assert_eq!(scorer.historical_estimated_channel_liquidity_probabilities(42, &target),
+			first_window_history);
+
+		// Disabling seasonality at runtime reverts to the channel-wide history, which saw both.
+		scorer.set_decay_params(ProbabilisticScoringDecayParameters { weekly_seasonality: false, ..decay_params });
+		let channel_wide_history = scorer.historical_estimated_channel_liquidity_probabilities(42, &target);
+		assert_ne!(channel_wide_history, first_window_history);
+
+		// The seasonal history survives a serialization round-trip.
+		scorer.set_decay_params(decay_params);
+		let mut serialized_scorer = Vec::new();
+		scorer.write(&mut serialized_scorer).unwrap();
+		let deserialized_scorer = <ProbabilisticScorer>::read(
+			&mut io::Cursor::new(&serialized_scorer), (&network_graph, &logger)).unwrap();
+		assert_eq!(deserialized_scorer.historical_estimated_channel_liquidity_probabilities(42, &target),
+			first_window_history);
+	}
+
 	#[test]
 	fn remembers_historical_failures() {
 		let logger = TestLogger::new();
@@ -2813,6 +3154,7 @@ mod tests {
 		let decay_params = ProbabilisticScoringDecayParameters {
 			liquidity_offset_half_life: Duration::from_secs(60 * 60),
 			historical_no_updates_half_life: Duration::from_secs(10),
+			..ProbabilisticScoringDecayParameters::default()
 		};
 		let mut scorer = ProbabilisticScorer::new(decay_params, &network_graph, &logger);
 		let source = source_node_id();
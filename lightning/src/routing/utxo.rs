@@ -14,6 +14,8 @@
 //! order to announce a channel. This module handles that checking.
 
 use bitcoin::{BlockHash, TxOut};
+#[cfg(taproot)]
+use bitcoin::Script;
 use bitcoin::hashes::hex::ToHex;
 
 use crate::events::MessageSendEvent;
@@ -70,12 +72,32 @@ pub trait UtxoLookup {
 enum ChannelAnnouncement {
 	Full(msgs::ChannelAnnouncement),
 	Unsigned(msgs::UnsignedChannelAnnouncement),
+	#[cfg(taproot)]
+	Taproot(msgs::ChannelAnnouncement2),
 }
 impl ChannelAnnouncement {
+	fn short_channel_id(&self) -> u64 {
+		match self {
+			ChannelAnnouncement::Full(msg) => msg.contents.short_channel_id,
+			ChannelAnnouncement::Unsigned(msg) => msg.short_channel_id,
+			#[cfg(taproot)]
+			ChannelAnnouncement::Taproot(msg) => msg.contents.short_channel_id,
+		}
+	}
 	fn node_id_1(&self) -> &NodeId {
 		match self {
 			ChannelAnnouncement::Full(msg) => &msg.contents.node_id_1,
 			ChannelAnnouncement::Unsigned(msg) => &msg.node_id_1,
+			#[cfg(taproot)]
+			ChannelAnnouncement::Taproot(msg) => &msg.contents.node_id_1,
+		}
+	}
+	fn node_id_2(&self) -> &NodeId {
+		match self {
+			ChannelAnnouncement::Full(msg) => &msg.contents.node_id_2,
+			ChannelAnnouncement::Unsigned(msg) => &msg.node_id_2,
+			#[cfg(taproot)]
+			ChannelAnnouncement::Taproot(msg) => &msg.contents.node_id_2,
 		}
 	}
 }
@@ -198,12 +220,8 @@ impl UtxoFuture {
 				return [None, None, None, None, None];
 			}
 
-			let announcement_msg = match async_messages.channel_announce.as_ref().unwrap() {
-				ChannelAnnouncement::Full(signed_msg) => &signed_msg.contents,
-				ChannelAnnouncement::Unsigned(msg) => &msg,
-			};
-
-			pending_checks.lookup_completed(announcement_msg, &Arc::downgrade(&self.state));
+			pending_checks.lookup_completed(async_messages.channel_announce.as_ref().unwrap(),
+				&Arc::downgrade(&self.state));
 
 			(async_messages.channel_announce.take().unwrap(),
 				async_messages.latest_node_announce_a.take(),
@@ -232,6 +250,11 @@ impl UtxoFuture {
 			ChannelAnnouncement::Unsigned(msg) => {
 				let _ = graph.update_channel_from_unsigned_announcement(&msg, &Some(&resolver));
 			},
+			#[cfg(taproot)]
+			ChannelAnnouncement::Taproot(msg) => {
+				// `channel_announcement_2`s cannot yet be relayed.
+				let _ = graph.update_channel_from_announcement_2(&msg, &Some(&resolver));
+			},
 		}
 
 		for announce in core::iter::once(node_a).chain(core::iter::once(node_b)) {
@@ -279,19 +302,19 @@ struct PendingChecksContext {
 
 impl PendingChecksContext {
 	fn lookup_completed(&mut self,
-		msg: &msgs::UnsignedChannelAnnouncement, completed_state: &Weak<Mutex<UtxoMessages>>
+		msg: &ChannelAnnouncement, completed_state: &Weak<Mutex<UtxoMessages>>
 	) {
-		if let hash_map::Entry::Occupied(e) = self.channels.entry(msg.short_channel_id) {
+		if let hash_map::Entry::Occupied(e) = self.channels.entry(msg.short_channel_id()) {
 			if Weak::ptr_eq(e.get(), &completed_state) {
 				e.remove();
 			}
 		}
 
-		if let hash_map::Entry::Occupied(mut e) = self.nodes.entry(msg.node_id_1) {
+		if let hash_map::Entry::Occupied(mut e) = self.nodes.entry(*msg.node_id_1()) {
 			e.get_mut().retain(|elem| !Weak::ptr_eq(&elem, &completed_state));
 			if e.get().is_empty() { e.remove(); }
 		}
-		if let hash_map::Entry::Occupied(mut e) = self.nodes.entry(msg.node_id_2) {
+		if let hash_map::Entry::Occupied(mut e) = self.nodes.entry(*msg.node_id_2()) {
 			e.get_mut().retain(|elem| !Weak::ptr_eq(&elem, &completed_state));
 			if e.get().is_empty() { e.remove(); }
 		}
@@ -393,11 +416,13 @@ impl PendingChecks {
 		Ok(())
 	}
 
-	fn check_replace_previous_entry(msg: &msgs::UnsignedChannelAnnouncement,
-		full_msg: Option<&msgs::ChannelAnnouncement>, replacement: Option<Weak<Mutex<UtxoMessages>>>,
+	/// Checks whether there is a pending lookup for the given SCID, returning an `Err` if the
+	/// announcement it is for is one `announcement_matches` identifies as the one being checked.
+	fn check_replace_previous_entry<F: Fn(&ChannelAnnouncement) -> bool>(short_channel_id: u64,
+		announcement_matches: F, replacement: Option<Weak<Mutex<UtxoMessages>>>,
 		pending_channels: &mut HashMap<u64, Weak<Mutex<UtxoMessages>>>
 	) -> Result<(), msgs::LightningError> {
-		match pending_channels.entry(msg.short_channel_id) {
+		match pending_channels.entry(short_channel_id) {
 			hash_map::Entry::Occupied(mut e) => {
 				// There's already a pending lookup for the given SCID. Check if the messages
 				// are the same and, if so, return immediately (don't bother spawning another
@@ -408,8 +433,7 @@ impl PendingChecks {
 						// struct, however in that case we have a global lockorder of new messages
 						// -> old messages, which makes this safe.
 						let pending_matches = match &pending_msgs.unsafe_well_ordered_double_lock_self().channel_announce {
-							Some(ChannelAnnouncement::Full(pending_msg)) => Some(pending_msg) == full_msg,
-							Some(ChannelAnnouncement::Unsigned(pending_msg)) => pending_msg == msg,
+							Some(pending_msg) => announcement_matches(pending_msg),
 							None => {
 								// This shouldn't actually be reachable. We set the
 								// `channel_announce` field under the same lock as setting the
@@ -485,7 +509,13 @@ impl PendingChecks {
 			}
 		};
 
-		Self::check_replace_previous_entry(msg, full_msg, None,
+		let pending_matches = |pending_msg: &ChannelAnnouncement| match pending_msg {
+			ChannelAnnouncement::Full(pending_msg) => Some(pending_msg) == full_msg,
+			ChannelAnnouncement::Unsigned(pending_msg) => pending_msg == msg,
+			#[cfg(taproot)]
+			ChannelAnnouncement::Taproot(_) => false,
+		};
+		Self::check_replace_previous_entry(msg.short_channel_id, pending_matches, None,
 			&mut self.internal.lock().unwrap().channels)?;
 
 		match utxo_lookup {
@@ -504,7 +534,7 @@ impl PendingChecks {
 							// handle the result in-line.
 							handle_result(res)
 						} else {
-							Self::check_replace_previous_entry(msg, full_msg,
+							Self::check_replace_previous_entry(msg.short_channel_id, pending_matches,
 								Some(Arc::downgrade(&future.state)), &mut pending_checks.channels)?;
 							async_messages.channel_announce = Some(
 								if let Some(msg) = full_msg { ChannelAnnouncement::Full(msg.clone()) }
@@ -524,6 +554,92 @@ impl PendingChecks {
 		}
 	}
 
+	/// Checks an experimental `channel_announcement_2` against the chain, returning the funding
+	/// output's value if it could be looked up.
+	///
+	/// As with [`Self::check_channel_announcement`], asynchronous lookups return an `Err`, with
+	/// the announcement being handled again once the lookup completes.
+	#[cfg(taproot)]
+	pub(super) fn check_channel_announcement_2<U: Deref>(&self,
+		utxo_lookup: &Option<U>, full_msg: &msgs::ChannelAnnouncement2, expected_script: &Script
+	) -> Result<Option<u64>, msgs::LightningError> where U::Target: UtxoLookup {
+		let msg = &full_msg.contents;
+		let handle_result = |res| {
+			match res {
+				Ok(TxOut { value, script_pubkey }) => {
+					if script_pubkey != *expected_script {
+						return Err(LightningError{
+							err: format!("Channel announcement key ({}) didn't match on-chain script ({})",
+								expected_script.to_hex(), script_pubkey.to_hex()),
+							action: ErrorAction::IgnoreError
+						});
+					}
+					if value != msg.capacity_satoshis {
+						return Err(LightningError{
+							err: format!("Channel announcement capacity ({}) didn't match on-chain value ({})",
+								msg.capacity_satoshis, value),
+							action: ErrorAction::IgnoreError
+						});
+					}
+					Ok(Some(value))
+				},
+				Err(UtxoLookupError::UnknownChain) => {
+					Err(LightningError {
+						err: format!("Channel announced on an unknown chain ({})",
+							msg.chain_hash.encode().to_hex()),
+						action: ErrorAction::IgnoreError
+					})
+				},
+				Err(UtxoLookupError::UnknownTx) => {
+					Err(LightningError {
+						err: "Channel announced without corresponding UTXO entry".to_owned(),
+						action: ErrorAction::IgnoreError
+					})
+				},
+			}
+		};
+
+		let pending_matches = |pending_msg: &ChannelAnnouncement| match pending_msg {
+			ChannelAnnouncement::Taproot(pending_msg) => pending_msg == full_msg,
+			_ => false,
+		};
+		Self::check_replace_previous_entry(msg.short_channel_id, pending_matches, None,
+			&mut self.internal.lock().unwrap().channels)?;
+
+		match utxo_lookup {
+			&None => {
+				// Tentatively accept, potentially exposing us to DoS attacks
+				Ok(None)
+			},
+			&Some(ref utxo_lookup) => {
+				match utxo_lookup.get_utxo(&msg.chain_hash, msg.short_channel_id) {
+					UtxoResult::Sync(res) => handle_result(res),
+					UtxoResult::Async(future) => {
+						let mut pending_checks = self.internal.lock().unwrap();
+						let mut async_messages = future.state.lock().unwrap();
+						if let Some(res) = async_messages.complete.take() {
+							// In the unlikely event the future resolved before we managed to get it,
+							// handle the result in-line.
+							handle_result(res)
+						} else {
+							Self::check_replace_previous_entry(msg.short_channel_id, pending_matches,
+								Some(Arc::downgrade(&future.state)), &mut pending_checks.channels)?;
+							async_messages.channel_announce = Some(ChannelAnnouncement::Taproot(full_msg.clone()));
+							pending_checks.nodes.entry(msg.node_id_1)
+								.or_insert(Vec::new()).push(Arc::downgrade(&future.state));
+							pending_checks.nodes.entry(msg.node_id_2)
+								.or_insert(Vec::new()).push(Arc::downgrade(&future.state));
+							Err(LightningError {
+								err: "Channel being checked async".to_owned(),
+								action: ErrorAction::IgnoreAndLog(Level::Gossip),
+							})
+						}
+					},
+				}
+			}
+		}
+	}
+
 	/// The maximum number of pending gossip checks before [`Self::too_many_checks_pending`]
 	/// returns `true`. Note that this isn't a strict upper-bound on the number of checks pending -
 	/// each peer may, at a minimum, read one more socket buffer worth of `channel_announcement`s
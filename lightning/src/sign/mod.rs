@@ -34,18 +34,23 @@ use bitcoin::{PackedLockTime, secp256k1, Sequence, Witness};
 
 use crate::util::transaction_utils;
 use crate::util::crypto::{hkdf_extract_expand_twice, sign, sign_with_aux_rand};
+use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
 use crate::util::ser::{Writeable, Writer, Readable, ReadableArgs};
 use crate::chain::transaction::OutPoint;
 use crate::events::bump_transaction::HTLCDescriptor;
 use crate::ln::channel::ANCHOR_OUTPUT_VALUE_SATOSHI;
 use crate::ln::{chan_utils, PaymentPreimage};
 use crate::ln::chan_utils::{HTLCOutputInCommitment, make_funding_redeemscript, ChannelPublicKeys, HolderCommitmentTransaction, ChannelTransactionParameters, CommitmentTransaction, ClosingTransaction};
+use crate::ln::contracts::{SettlementBundle, contract_message_digest};
 use crate::ln::msgs::{UnsignedChannelAnnouncement, UnsignedGossipMessage};
 use crate::ln::script::ShutdownScript;
+use crate::blinded_path::BlindedPath;
+use crate::onion_message::{key_delegation_hash, onion_message_receipt_hash, signed_custom_message_hash};
 
 use crate::prelude::*;
-use core::convert::TryInto;
+use core::convert::{TryFrom, TryInto};
 use core::ops::Deref;
+use core::time::Duration;
 use core::sync::atomic::{AtomicUsize, Ordering};
 use crate::io::{self, Error};
 use crate::ln::features::ChannelTypeFeatures;
@@ -385,12 +390,22 @@ pub trait ChannelSigner {
 	/// Set the counterparty static channel data, including basepoints,
 	/// `counterparty_selected`/`holder_selected_contest_delay` and funding outpoint.
 	///
-	/// This data is static, and will never change for a channel once set. For a given [`ChannelSigner`]
-	/// instance, LDK will call this method exactly once - either immediately after construction
-	/// (not including if done via [`SignerProvider::read_chan_signer`]) or when the funding
-	/// information has been generated.
+	/// This data is static, and will never change for a channel once set, with the exception of
+	/// [`ChannelTransactionParameters::channel_type_features`] which may change if the channel
+	/// type is renegotiated. For a given [`ChannelSigner`] instance, LDK will call this method
+	/// once - either immediately after construction (not including if done via
+	/// [`SignerProvider::read_chan_signer`]) or when the funding information has been generated -
+	/// and again each time the channel type changes.
+	///
+	/// The channel type can only change once both we and our counterparty enable
+	/// [`ChannelHandshakeConfig::negotiate_channel_type_upgrade`]. Implementations must then sign
+	/// all subsequent transactions according to the most recently provided parameters, including
+	/// commitment transactions for a state number they already signed under the previous type.
+	/// Signers which cannot support this must not be used with that setting enabled.
 	///
 	/// channel_parameters.is_populated() MUST be true.
+	///
+	/// [`ChannelHandshakeConfig::negotiate_channel_type_upgrade`]: crate::util::config::ChannelHandshakeConfig::negotiate_channel_type_upgrade
 	fn provide_channel_parameters(&mut self, channel_parameters: &ChannelTransactionParameters);
 }
 
@@ -541,6 +556,61 @@ pub trait EcdsaChannelSigner: ChannelSigner {
 	fn sign_channel_announcement_with_funding_key(
 		&self, msg: &UnsignedChannelAnnouncement, secp_ctx: &Secp256k1<secp256k1::All>
 	) -> Result<Signature, ()>;
+	/// Computes the signature for the settlement transaction of the branch at `branch_idx` of
+	/// `bundle` with our funding key.
+	///
+	/// The bundle's [`CollateralOutput::holder_funding_pubkey`] must be our funding pubkey as
+	/// returned by [`ChannelSigner::pubkeys`], otherwise an `Err` should be returned.
+	///
+	/// As the collateral output is locked to the same keys as the channel's funding output, an
+	/// `Err` must also be returned if the bundle's [`CollateralOutput::outpoint`] is the channel's
+	/// funding outpoint, as otherwise our counterparty could use the signature to spend the
+	/// channel's funds.
+	///
+	/// The default implementation always returns an `Err`, i.e. contracts are not supported by
+	/// signers which don't override it.
+	///
+	/// [`CollateralOutput::holder_funding_pubkey`]: crate::ln::contracts::CollateralOutput::holder_funding_pubkey
+	/// [`CollateralOutput::outpoint`]: crate::ln::contracts::CollateralOutput::outpoint
+	fn sign_settlement_transaction(
+		&self, bundle: &SettlementBundle, branch_idx: usize, secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Signature, ()> {
+		let _ = (bundle, branch_idx, secp_ctx);
+		Err(())
+	}
+	/// Computes adaptor signatures for the settlement transactions of all branches of `bundle` in
+	/// a single call, each encrypted to the entry of `adaptor_points` at the same index.
+	///
+	/// Contracts over numeric outcomes may have thousands of branches, all spending the same
+	/// collateral output, which this allows signing without a round-trip per branch.
+	///
+	/// As with [`Self::sign_settlement_transaction`], the bundle's collateral output must use our
+	/// funding pubkey and must not be the channel's funding output, and `adaptor_points` must
+	/// contain exactly one point per branch, otherwise an `Err` should be returned.
+	///
+	/// The default implementation always returns an `Err`.
+	fn sign_settlement_transactions_with_adaptor_points(
+		&self, bundle: &SettlementBundle, adaptor_points: &[PublicKey],
+		secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Vec<EcdsaAdaptorSignature>, ()> {
+		let _ = (bundle, adaptor_points, secp_ctx);
+		Err(())
+	}
+	/// Signs a message about a contract whose collateral output is locked to our funding key,
+	/// allowing our counterparty to authenticate it with our funding pubkey.
+	///
+	/// The signature must be over [`contract_message_digest`] of `msg`, which can never be a
+	/// transaction sighash.
+	///
+	/// The default implementation always returns an `Err`.
+	///
+	/// [`contract_message_digest`]: crate::ln::contracts::contract_message_digest
+	fn sign_contract_message(
+		&self, msg: &[u8], secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Signature, ()> {
+		let _ = (msg, secp_ctx);
+		Err(())
+	}
 }
 
 /// A writeable signer.
@@ -627,6 +697,101 @@ pub trait NodeSigner {
 	/// message to be broadcast, as otherwise it may prevent one from receiving funds over the
 	/// corresponding channel.
 	fn sign_gossip_message(&self, msg: UnsignedGossipMessage) -> Result<Signature, ()>;
+
+	/// Sign an arbitrary message with our node secret, returning the zbase32-encoded recoverable
+	/// signature produced by [`message_signing::sign`].
+	///
+	/// This is used to attest to data handed to third parties, such as [`BalanceStatement`]s.
+	///
+	/// The default implementation always fails, for signers which do not support signing
+	/// arbitrary messages.
+	///
+	/// [`message_signing::sign`]: crate::util::message_signing::sign
+	/// [`BalanceStatement`]: crate::chain::channelmonitor::BalanceStatement
+	fn sign_message(&self, msg: &[u8]) -> Result<String, ()> {
+		let _ = msg;
+		Err(())
+	}
+
+	/// Gets the node id we rotated away from, if any, after a node key rotation.
+	///
+	/// While this returns `Some`, inbound connections addressed to the previous node id are
+	/// accepted in addition to those addressed to the one returned by [`Self::get_node_id`],
+	/// giving peers which have not yet learned of the rotation time to catch up. It should return
+	/// `None` again once that window is over, or immediately if the previous key was exposed and
+	/// is actively being abused.
+	///
+	/// The default implementation always returns `None`. See
+	/// [`PeerManager::set_node_key_rotation`] for how rotations are announced.
+	///
+	/// [`PeerManager::set_node_key_rotation`]: crate::ln::peer_handler::PeerManager::set_node_key_rotation
+	fn get_previous_node_id(&self) -> Option<PublicKey> {
+		None
+	}
+
+	/// Gets the ECDH shared secret of the node secret behind [`Self::get_previous_node_id`] and
+	/// `other_key`.
+	///
+	/// The default implementation always fails, for signers which do not support node key
+	/// rotations.
+	fn previous_node_ecdh(&self, other_key: &PublicKey) -> Result<SharedSecret, ()> {
+		let _ = other_key;
+		Err(())
+	}
+
+	/// Sign a receipt acknowledging an onion message which requested one with the given `nonce`,
+	/// with our node secret.
+	///
+	/// The signature must be over the SHA-256 hash of the ASCII string `"LDK onion message receipt"`
+	/// followed by `nonce`. If this fails, no receipt is sent for the message.
+	///
+	/// This is only used for messages sent to our node id directly, as receipts for messages
+	/// received along blinded paths we created are signed with a key derived for the path. The
+	/// default implementation fails, so that only the latter are acknowledged.
+	///
+	/// See [`OnionMessenger::send_onion_message_with_receipt`] for more information.
+	///
+	/// [`OnionMessenger::send_onion_message_with_receipt`]: crate::onion_message::OnionMessenger::send_onion_message_with_receipt
+	fn sign_onion_message_receipt(&self, _nonce: &[u8; 32]) -> Result<Signature, ()> {
+		Err(())
+	}
+
+	/// Sign the encoded `payload` of a custom onion message of type `tlv_type`, to be sent to
+	/// `recipient` with `reply_path`, with our node secret, authenticating us as its sender.
+	///
+	/// The signature must be over the SHA-256 hash of the ASCII string
+	/// `"LDK signed custom onion message"`, followed by `tlv_type` encoded as a BigSize, the
+	/// serialized `recipient`, a `0` byte if there is no `reply_path` or a `1` byte followed by the
+	/// encoded `reply_path` otherwise, and finally `payload`.
+	///
+	/// The default implementation always fails, for signers which do not support signing custom
+	/// onion messages.
+	///
+	/// See [`SignedCustomMessage`] for more information.
+	///
+	/// [`SignedCustomMessage`]: crate::onion_message::SignedCustomMessage
+	fn sign_custom_onion_message(
+		&self, _tlv_type: u64, _recipient: &PublicKey, _reply_path: Option<&BlindedPath>,
+		_payload: &[u8]
+	) -> Result<Signature, ()> {
+		Err(())
+	}
+
+	/// Sign a [`KeyDelegation`] authorizing `delegate_pubkey` to sign custom onion messages on our
+	/// behalf until `expires_at`, given as seconds since the Unix epoch, with our node secret.
+	///
+	/// The signature must be over the SHA-256 hash of the ASCII string
+	/// `"LDK onion message key delegation"`, followed by our serialized node id, the serialized
+	/// `delegate_pubkey` and `expires_at` in seconds as a big-endian `u64`.
+	///
+	/// The default implementation always fails, for signers which do not support delegation.
+	///
+	/// [`KeyDelegation`]: crate::onion_message::KeyDelegation
+	fn sign_onion_message_key_delegation(
+		&self, _delegate_pubkey: &PublicKey, _expires_at: Duration
+	) -> Result<Signature, ()> {
+		Err(())
+	}
 }
 
 /// A trait that can return signer instances for individual channels.
@@ -684,6 +849,77 @@ pub trait SignerProvider {
 	fn get_shutdown_scriptpubkey(&self) -> Result<ShutdownScript, ()>;
 }
 
+/// A source of fresh on-chain scripts, usually backed by an on-chain wallet, which is consulted
+/// whenever we need to send funds on-chain to ourselves.
+///
+/// Use a [`DestinationRotatingSignerProvider`] to have channel close and claim outputs sent to
+/// scripts from this source, and [`KeysManager::spend_spendable_outputs_to_fresh_destination`] to
+/// do the same for change when sweeping [`SpendableOutputDescriptor`]s.
+pub trait ChangeDestinationSource {
+	/// Returns a script pubkey which funds can be sent to.
+	///
+	/// This method should return a different value each time it is called, to avoid linking
+	/// on-chain funds as controlled to the same user. Scripts used as shutdown scripts must be
+	/// segwit scripts as described in [`ShutdownScript`].
+	fn get_change_destination_script(&self) -> Result<Script, ()>;
+}
+
+/// A [`SignerProvider`] which wraps another, but fetches the scripts returned from
+/// [`SignerProvider::get_destination_script`] and [`SignerProvider::get_shutdown_scriptpubkey`]
+/// from a [`ChangeDestinationSource`] rather than from the wrapped [`SignerProvider`], which for a
+/// [`KeysManager`] always returns the same scripts.
+///
+/// Note that funds sent to these scripts are not spendable by the wrapped [`SignerProvider`].
+/// They are instead controlled by whichever wallet backs the [`ChangeDestinationSource`].
+pub struct DestinationRotatingSignerProvider<SP: Deref, D: Deref>
+where
+	SP::Target: SignerProvider,
+	D::Target: ChangeDestinationSource,
+{
+	inner: SP,
+	destination_source: D,
+}
+
+impl<SP: Deref, D: Deref> DestinationRotatingSignerProvider<SP, D>
+where
+	SP::Target: SignerProvider,
+	D::Target: ChangeDestinationSource,
+{
+	/// Constructs a new [`DestinationRotatingSignerProvider`] wrapping `inner`.
+	pub fn new(inner: SP, destination_source: D) -> Self {
+		Self { inner, destination_source }
+	}
+}
+
+impl<SP: Deref, D: Deref> SignerProvider for DestinationRotatingSignerProvider<SP, D>
+where
+	SP::Target: SignerProvider,
+	D::Target: ChangeDestinationSource,
+{
+	type Signer = <SP::Target as SignerProvider>::Signer;
+
+	fn generate_channel_keys_id(&self, inbound: bool, channel_value_satoshis: u64, user_channel_id: u128) -> [u8; 32] {
+		self.inner.generate_channel_keys_id(inbound, channel_value_satoshis, user_channel_id)
+	}
+
+	fn derive_channel_signer(&self, channel_value_satoshis: u64, channel_keys_id: [u8; 32]) -> Self::Signer {
+		self.inner.derive_channel_signer(channel_value_satoshis, channel_keys_id)
+	}
+
+	fn read_chan_signer(&self, reader: &[u8]) -> Result<Self::Signer, DecodeError> {
+		self.inner.read_chan_signer(reader)
+	}
+
+	fn get_destination_script(&self) -> Result<Script, ()> {
+		self.destination_source.get_change_destination_script()
+	}
+
+	fn get_shutdown_scriptpubkey(&self) -> Result<ShutdownScript, ()> {
+		let script = self.destination_source.get_change_destination_script()?;
+		ShutdownScript::try_from(script).map_err(|_| ())
+	}
+}
+
 /// A simple implementation of [`WriteableEcdsaChannelSigner`] that just keeps the private keys in memory.
 ///
 /// This implementation performs no policy checks and is insufficient by itself as
@@ -840,6 +1076,14 @@ impl InMemorySigner {
 	pub fn channel_type_features(&self) -> &ChannelTypeFeatures {
 		&self.get_channel_parameters().channel_type_features
 	}
+	/// Fails if the collateral output of `bundle` may be the channel's funding output, which is
+	/// locked to the same keys, including if we don't know the funding outpoint yet.
+	fn check_collateral_is_not_funding(&self, bundle: &SettlementBundle) -> Result<(), ()> {
+		match self.channel_parameters.as_ref().and_then(|params| params.funding_outpoint.as_ref()) {
+			Some(funding_outpoint) if *funding_outpoint != bundle.collateral().outpoint => Ok(()),
+			_ => Err(()),
+		}
+	}
 	/// Sign the single input of `spend_tx` at index `input_idx`, which spends the output described
 	/// by `descriptor`, returning the witness stack for the input.
 	///
@@ -938,9 +1182,12 @@ impl ChannelSigner for InMemorySigner {
 	fn channel_keys_id(&self) -> [u8; 32] { self.channel_keys_id }
 
 	fn provide_channel_parameters(&mut self, channel_parameters: &ChannelTransactionParameters) {
-		assert!(self.channel_parameters.is_none() || self.channel_parameters.as_ref().unwrap() == channel_parameters);
-		if self.channel_parameters.is_some() {
-			// The channel parameters were already set and they match, return early.
+		if let Some(existing_parameters) = self.channel_parameters.as_mut() {
+			// The channel parameters were already set, only the channel type may change.
+			let mut expected_parameters = existing_parameters.clone();
+			expected_parameters.channel_type_features = channel_parameters.channel_type_features.clone();
+			assert!(expected_parameters == *channel_parameters);
+			*existing_parameters = expected_parameters;
 			return;
 		}
 		assert!(channel_parameters.is_populated(), "Channel parameters must be fully populated");
@@ -1073,6 +1320,27 @@ impl EcdsaChannelSigner for InMemorySigner {
 		let msghash = hash_to_message!(&Sha256dHash::hash(&msg.encode()[..])[..]);
 		Ok(secp_ctx.sign_ecdsa(&msghash, &self.funding_key))
 	}
+
+	fn sign_settlement_transaction(
+		&self, bundle: &SettlementBundle, branch_idx: usize, secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Signature, ()> {
+		self.check_collateral_is_not_funding(bundle)?;
+		bundle.sign_branch(branch_idx, &self.funding_key, secp_ctx)
+	}
+
+	fn sign_settlement_transactions_with_adaptor_points(
+		&self, bundle: &SettlementBundle, adaptor_points: &[PublicKey],
+		secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Vec<EcdsaAdaptorSignature>, ()> {
+		self.check_collateral_is_not_funding(bundle)?;
+		bundle.adaptor_sign_branches(adaptor_points, &self.funding_key, secp_ctx)
+	}
+
+	fn sign_contract_message(
+		&self, msg: &[u8], secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Signature, ()> {
+		Ok(sign(secp_ctx, &contract_message_digest(msg), &self.funding_key))
+	}
 }
 
 const SERIALIZATION_VERSION: u8 = 1;
@@ -1160,6 +1428,8 @@ pub struct KeysManager {
 	shutdown_pubkey: PublicKey,
 	channel_master_key: ExtendedPrivKey,
 	channel_child_index: AtomicUsize,
+	/// The root of the seeds given to [`KeysManager::derive_child`].
+	child_master_key: SecretKey,
 
 	rand_bytes_unique_start: [u8; 32],
 	rand_bytes_index: AtomicCounter,
@@ -1209,6 +1479,7 @@ impl KeysManager {
 				};
 				let channel_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(3).unwrap()).expect("Your RNG is busted");
 				let inbound_payment_key: SecretKey = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(5).unwrap()).expect("Your RNG is busted").private_key;
+				let child_master_key = master_key.ckd_priv(&secp_ctx, ChildNumber::from_hardened_idx(6).unwrap()).expect("Your RNG is busted").private_key;
 				let mut inbound_pmt_key_bytes = [0; 32];
 				inbound_pmt_key_bytes.copy_from_slice(&inbound_payment_key[..]);
 
@@ -1230,6 +1501,7 @@ impl KeysManager {
 
 					channel_master_key,
 					channel_child_index: AtomicUsize::new(0),
+					child_master_key,
 
 					rand_bytes_unique_start,
 					rand_bytes_index: AtomicCounter::new(),
@@ -1251,6 +1523,32 @@ impl KeysManager {
 		self.node_secret
 	}
 
+	/// Derives the seed of the child [`KeysManager`] for the subsystem identified by `namespace`,
+	/// see [`Self::derive_child`].
+	///
+	/// The same `namespace` always results in the same seed, which may be backed up in place of
+	/// ours if only the subsystem's keys need to be recovered.
+	pub fn derive_child_seed(&self, namespace: &str) -> [u8; 32] {
+		let mut ikm = self.child_master_key[..].to_vec();
+		ikm.extend_from_slice(namespace.as_bytes());
+		let (child_seed, _) = hkdf_extract_expand_twice(b"LDK KeysManager Child Derivation", &ikm);
+		child_seed
+	}
+
+	/// Derives a child [`KeysManager`] for the subsystem identified by `namespace`, e.g. `"dlc"`,
+	/// `"lsp"` or `"messaging"`, which may be used as that subsystem's [`EntropySource`],
+	/// [`NodeSigner`] or [`SignerProvider`].
+	///
+	/// Children are derived from a hardened branch of our seed which is otherwise unused, so their
+	/// keys are independent of ours and of those of children in other namespaces, and a child's
+	/// keys don't reveal ours. A child's node id thus differs from ours as well.
+	///
+	/// Children use our `starting_time_secs` and `starting_time_nanos`, so ephemeral data is unique
+	/// across runs as long as it is for us.
+	pub fn derive_child(&self, namespace: &str) -> KeysManager {
+		KeysManager::new(&self.derive_child_seed(namespace), self.starting_time_secs, self.starting_time_nanos)
+	}
+
 	/// Derive an old [`WriteableEcdsaChannelSigner`] containing per-channel secrets based on a key derivation parameters.
 	pub fn derive_channel_keys(&self, channel_value_satoshis: u64, params: &[u8; 32]) -> InMemorySigner {
 		let chan_id = u64::from_be_bytes(params[0..8].try_into().unwrap());
@@ -1407,6 +1705,17 @@ impl KeysManager {
 
 		Ok(spend_tx)
 	}
+
+	/// Creates a [`Transaction`] which spends the given descriptors to the given outputs, plus an
+	/// output to a fresh script from the given [`ChangeDestinationSource`] (if sufficient change
+	/// value remains).
+	///
+	/// See [`Self::spend_spendable_outputs`] for more details.
+	pub fn spend_spendable_outputs_to_fresh_destination<C: Signing, D: Deref>(&self, descriptors: &[&SpendableOutputDescriptor], outputs: Vec<TxOut>, change_destination_source: D, feerate_sat_per_1000_weight: u32, locktime: Option<PackedLockTime>, secp_ctx: &Secp256k1<C>) -> Result<Transaction, ()>
+	where D::Target: ChangeDestinationSource {
+		let change_destination_script = change_destination_source.get_change_destination_script()?;
+		self.spend_spendable_outputs(descriptors, outputs, change_destination_script, feerate_sat_per_1000_weight, locktime, secp_ctx)
+	}
 }
 
 impl EntropySource for KeysManager {
@@ -1454,6 +1763,30 @@ impl NodeSigner for KeysManager {
 		let msg_hash = hash_to_message!(&Sha256dHash::hash(&msg.encode()[..])[..]);
 		Ok(self.secp_ctx.sign_ecdsa(&msg_hash, &self.node_secret))
 	}
+
+	fn sign_message(&self, msg: &[u8]) -> Result<String, ()> {
+		crate::util::message_signing::sign(msg, &self.node_secret).map_err(|_| ())
+	}
+
+	fn sign_onion_message_receipt(&self, nonce: &[u8; 32]) -> Result<Signature, ()> {
+		let msg_hash = onion_message_receipt_hash(nonce);
+		Ok(self.secp_ctx.sign_ecdsa(&msg_hash, &self.node_secret))
+	}
+
+	fn sign_custom_onion_message(
+		&self, tlv_type: u64, recipient: &PublicKey, reply_path: Option<&BlindedPath>,
+		payload: &[u8]
+	) -> Result<Signature, ()> {
+		let msg_hash = signed_custom_message_hash(tlv_type, recipient, reply_path, payload);
+		Ok(self.secp_ctx.sign_ecdsa(&msg_hash, &self.node_secret))
+	}
+
+	fn sign_onion_message_key_delegation(
+		&self, delegate_pubkey: &PublicKey, expires_at: Duration
+	) -> Result<Signature, ()> {
+		let msg_hash = key_delegation_hash(&self.node_id, delegate_pubkey, expires_at);
+		Ok(self.secp_ctx.sign_ecdsa(&msg_hash, &self.node_secret))
+	}
 }
 
 impl SignerProvider for KeysManager {
@@ -1561,6 +1894,27 @@ impl NodeSigner for PhantomKeysManager {
 	fn sign_gossip_message(&self, msg: UnsignedGossipMessage) -> Result<Signature, ()> {
 		self.inner.sign_gossip_message(msg)
 	}
+
+	fn sign_message(&self, msg: &[u8]) -> Result<String, ()> {
+		self.inner.sign_message(msg)
+	}
+
+	fn sign_onion_message_receipt(&self, nonce: &[u8; 32]) -> Result<Signature, ()> {
+		self.inner.sign_onion_message_receipt(nonce)
+	}
+
+	fn sign_custom_onion_message(
+		&self, tlv_type: u64, recipient: &PublicKey, reply_path: Option<&BlindedPath>,
+		payload: &[u8]
+	) -> Result<Signature, ()> {
+		self.inner.sign_custom_onion_message(tlv_type, recipient, reply_path, payload)
+	}
+
+	fn sign_onion_message_key_delegation(
+		&self, delegate_pubkey: &PublicKey, expires_at: Duration
+	) -> Result<Signature, ()> {
+		self.inner.sign_onion_message_key_delegation(delegate_pubkey, expires_at)
+	}
 }
 
 impl SignerProvider for PhantomKeysManager {
@@ -1617,11 +1971,23 @@ impl PhantomKeysManager {
 		self.inner.spend_spendable_outputs(descriptors, outputs, change_destination_script, feerate_sat_per_1000_weight, locktime, secp_ctx)
 	}
 
+	/// See [`KeysManager::spend_spendable_outputs_to_fresh_destination`] for documentation on this
+	/// method.
+	pub fn spend_spendable_outputs_to_fresh_destination<C: Signing, D: Deref>(&self, descriptors: &[&SpendableOutputDescriptor], outputs: Vec<TxOut>, change_destination_source: D, feerate_sat_per_1000_weight: u32, locktime: Option<PackedLockTime>, secp_ctx: &Secp256k1<C>) -> Result<Transaction, ()>
+	where D::Target: ChangeDestinationSource {
+		self.inner.spend_spendable_outputs_to_fresh_destination(descriptors, outputs, change_destination_source, feerate_sat_per_1000_weight, locktime, secp_ctx)
+	}
+
 	/// See [`KeysManager::derive_channel_keys`] for documentation on this method.
 	pub fn derive_channel_keys(&self, channel_value_satoshis: u64, params: &[u8; 32]) -> InMemorySigner {
 		self.inner.derive_channel_keys(channel_value_satoshis, params)
 	}
 
+	/// See [`KeysManager::derive_child`] for documentation on this method.
+	pub fn derive_child(&self, namespace: &str) -> KeysManager {
+		self.inner.derive_child(namespace)
+	}
+
 	/// Gets the "node_id" secret key used to sign gossip announcements, decode onion data, etc.
 	pub fn get_node_secret_key(&self) -> SecretKey {
 		self.inner.get_node_secret_key()
@@ -1640,6 +2006,103 @@ pub fn dyn_sign() {
 	let _signer: Box<dyn EcdsaChannelSigner>;
 }
 
+#[cfg(test)]
+mod tests {
+	use super::{ChangeDestinationSource, ChannelSigner, DestinationRotatingSignerProvider, EcdsaChannelSigner, KeysManager, SignerProvider};
+	use crate::chain::transaction::OutPoint;
+	use crate::ln::chan_utils::{ChannelTransactionParameters, CounterpartyChannelTransactionParameters};
+	use crate::ln::contracts::{CollateralOutput, SettlementBranch, SettlementBundle};
+	use crate::ln::features::ChannelTypeFeatures;
+	use bitcoin::blockdata::script::Script;
+	use bitcoin::hash_types::{Txid, WPubkeyHash};
+	use bitcoin::hashes::Hash;
+	use bitcoin::secp256k1::{PublicKey, Secp256k1, SecretKey};
+	use core::sync::atomic::{AtomicU8, Ordering};
+
+	struct CountingDestinationSource(AtomicU8);
+	impl ChangeDestinationSource for CountingDestinationSource {
+		fn get_change_destination_script(&self) -> Result<Script, ()> {
+			let idx = self.0.fetch_add(1, Ordering::AcqRel);
+			Ok(Script::new_v0_p2wpkh(&WPubkeyHash::from_inner([idx; 20])))
+		}
+	}
+
+	#[test]
+	fn rotates_destination_scripts() {
+		let keys_manager = KeysManager::new(&[42; 32], 42, 42);
+		let destination_source = CountingDestinationSource(AtomicU8::new(0));
+		let signer_provider = DestinationRotatingSignerProvider::new(&keys_manager, &destination_source);
+
+		let destination_script = signer_provider.get_destination_script().unwrap();
+		let shutdown_script = signer_provider.get_shutdown_scriptpubkey().unwrap().into_inner();
+		assert_eq!(destination_script, Script::new_v0_p2wpkh(&WPubkeyHash::from_inner([0; 20])));
+		assert_eq!(shutdown_script, Script::new_v0_p2wpkh(&WPubkeyHash::from_inner([1; 20])));
+		assert_ne!(destination_script, keys_manager.get_destination_script().unwrap());
+		assert_ne!(signer_provider.get_destination_script().unwrap(), destination_script);
+	}
+
+	#[test]
+	fn refuses_to_sign_settlements_spending_funding_output() {
+		let secp_ctx = Secp256k1::new();
+		let keys_manager = KeysManager::new(&[42; 32], 42, 42);
+		let mut signer = keys_manager.derive_channel_signer(100_000, [1; 32]);
+		let counterparty_signer = keys_manager.derive_channel_signer(100_000, [2; 32]);
+		let funding_outpoint = OutPoint { txid: Txid::from_slice(&[42; 32]).unwrap(), index: 0 };
+
+		let bundle_spending = |outpoint: OutPoint| {
+			let collateral = CollateralOutput {
+				outpoint, value_satoshis: 100_000,
+				holder_funding_pubkey: signer.pubkeys().funding_pubkey,
+				counterparty_funding_pubkey: counterparty_signer.pubkeys().funding_pubkey,
+			};
+			let branches = vec![SettlementBranch {
+				outcome: b"win".to_vec(), holder_payout_satoshis: 99_000, counterparty_payout_satoshis: 0,
+			}];
+			SettlementBundle::new(collateral, Script::new(), Script::new(), 0, 354, branches).unwrap()
+		};
+		let funding_bundle = bundle_spending(funding_outpoint);
+		let collateral_bundle = bundle_spending(OutPoint { txid: Txid::from_slice(&[43; 32]).unwrap(), index: 0 });
+
+		// Without knowing the funding outpoint, we can't sign anything.
+		assert!(signer.sign_settlement_transaction(&collateral_bundle, 0, &secp_ctx).is_err());
+
+		signer.provide_channel_parameters(&ChannelTransactionParameters {
+			holder_pubkeys: signer.pubkeys().clone(),
+			holder_selected_contest_delay: 144,
+			is_outbound_from_holder: true,
+			counterparty_parameters: Some(CounterpartyChannelTransactionParameters {
+				pubkeys: counterparty_signer.pubkeys().clone(),
+				selected_contest_delay: 144,
+			}),
+			funding_outpoint: Some(funding_outpoint),
+			channel_type_features: ChannelTypeFeatures::only_static_remote_key(),
+		});
+		assert!(signer.sign_settlement_transaction(&funding_bundle, 0, &secp_ctx).is_err());
+		let adaptor_point = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[3; 32]).unwrap());
+		assert!(signer.sign_settlement_transactions_with_adaptor_points(&funding_bundle, &[adaptor_point], &secp_ctx).is_err());
+		assert!(signer.sign_settlement_transaction(&collateral_bundle, 0, &secp_ctx).is_ok());
+		assert!(signer.sign_settlement_transactions_with_adaptor_points(&collateral_bundle, &[adaptor_point], &secp_ctx).is_ok());
+	}
+
+	#[test]
+	fn derives_independent_children() {
+		let keys_manager = KeysManager::new(&[42; 32], 42, 42);
+		let dlc = keys_manager.derive_child("dlc");
+		let messaging = keys_manager.derive_child("messaging");
+		assert_ne!(dlc.get_node_secret_key(), keys_manager.get_node_secret_key());
+		assert_ne!(dlc.get_node_secret_key(), messaging.get_node_secret_key());
+		assert_ne!(dlc.get_destination_script().unwrap(), keys_manager.get_destination_script().unwrap());
+		assert_ne!(keys_manager.derive_child_seed("dlc"), [42; 32]);
+
+		// Children are deterministic, given the same seed and namespace.
+		let restarted = KeysManager::new(&[42; 32], 43, 43);
+		assert_eq!(restarted.derive_child("dlc").get_node_secret_key(), dlc.get_node_secret_key());
+		assert_eq!(restarted.derive_child_seed("dlc"), keys_manager.derive_child_seed("dlc"));
+		let from_seed = KeysManager::new(&keys_manager.derive_child_seed("dlc"), 44, 44);
+		assert_eq!(from_seed.get_node_secret_key(), dlc.get_node_secret_key());
+	}
+}
+
 #[cfg(ldk_bench)]
 pub mod benches {
 	use std::sync::{Arc, mpsc};
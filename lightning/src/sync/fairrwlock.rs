@@ -54,7 +54,7 @@ impl<T> FairRwLock<T> {
 impl<'a, T: 'a> LockTestExt<'a> for FairRwLock<T> {
 	#[inline]
 	fn held_by_thread(&self) -> LockHeldState {
-		// fairrwlock is only built in non-test modes, so we should never support tests.
+		// fairrwlock is only used in non-test modes, so we should never support tests.
 		LockHeldState::Unsupported
 	}
 	type ExclLock = RwLockWriteGuard<'a, T>;
@@ -3,7 +3,7 @@
 pub(crate) enum LockHeldState {
 	HeldByThread,
 	NotHeldByThread,
-	#[cfg(any(ldk_bench, not(test)))]
+	#[cfg(any(ldk_bench, not(test), feature = "lock_watchdog"))]
 	Unsupported,
 }
 
@@ -28,12 +28,20 @@ pub use debug_sync::*;
 // Note that to make debug_sync's regex work this must not contain `debug_string` in the module name
 mod test_lockorder_checks;
 
-#[cfg(all(feature = "std", any(ldk_bench, not(test))))]
+#[cfg(all(feature = "std", any(ldk_bench, not(test), feature = "lock_watchdog")))]
 pub(crate) mod fairrwlock;
-#[cfg(all(feature = "std", any(ldk_bench, not(test))))]
+#[cfg(all(feature = "std", not(feature = "lock_watchdog"), any(ldk_bench, not(test))))]
 pub use {std::sync::{Arc, Mutex, Condvar, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard}, fairrwlock::FairRwLock};
 
-#[cfg(all(feature = "std", any(ldk_bench, not(test))))]
+// The watchdog wrappers are always built when the feature is set so that they can be tested, but
+// only replace the std locks outside of tests, where debug_sync's lockorder checking is used.
+#[cfg(all(feature = "std", feature = "lock_watchdog"))]
+#[cfg_attr(all(test, not(ldk_bench)), allow(dead_code))]
+mod watchdog_sync;
+#[cfg(all(feature = "std", feature = "lock_watchdog", any(ldk_bench, not(test))))]
+pub use watchdog_sync::*;
+
+#[cfg(all(feature = "std", not(feature = "lock_watchdog"), any(ldk_bench, not(test))))]
 mod ext_impl {
 	use super::*;
 	impl<'a, T: 'a> LockTestExt<'a> for Mutex<T> {
@@ -0,0 +1,339 @@
+//! Lock wrappers which time how long each lock is waited on and held, reporting slow sites via
+//! [`crate::util::lock_watchdog`].
+
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::time::Duration;
+
+use std::sync::Condvar as StdCondvar;
+use std::sync::Mutex as StdMutex;
+use std::sync::MutexGuard as StdMutexGuard;
+use std::sync::RwLock as StdRwLock;
+use std::sync::RwLockReadGuard as StdRwLockReadGuard;
+use std::sync::RwLockWriteGuard as StdRwLockWriteGuard;
+use std::time::Instant;
+
+pub use std::sync::{Arc, WaitTimeoutResult};
+
+use crate::util::lock_watchdog::{start_timer, lock_acquired, lock_released, report_long_hold, report_long_wait, LockKind};
+
+use super::{LockTestExt, LockHeldState};
+use super::fairrwlock::FairRwLock as InnerFairRwLock;
+
+pub type LockResult<Guard> = Result<Guard, ()>;
+
+pub struct Condvar {
+	inner: StdCondvar,
+}
+
+impl Condvar {
+	pub fn new() -> Condvar {
+		Condvar { inner: StdCondvar::new() }
+	}
+
+	pub fn wait_while<'a, T, F: FnMut(&mut T) -> bool>(&'a self, guard: MutexGuard<'a, T>, condition: F)
+	-> LockResult<MutexGuard<'a, T>> {
+		let site = guard.site;
+		// The lock is released while we wait, so only time the period after we get it back.
+		let (lock, pending) = guard.into_inner();
+		self.inner.wait_while(lock, condition)
+			.map(|lock| MutexGuard::new(lock, (start_timer(), None), site, pending))
+			.map_err(|_| ())
+	}
+
+	#[allow(unused)]
+	pub fn wait_timeout_while<'a, T, F: FnMut(&mut T) -> bool>(&'a self, guard: MutexGuard<'a, T>, dur: Duration, condition: F)
+	-> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+		let site = guard.site;
+		let (lock, pending) = guard.into_inner();
+		self.inner.wait_timeout_while(lock, dur, condition).map_err(|_| ())
+			.map(|(lock, e)| (MutexGuard::new(lock, (start_timer(), None), site, pending), e))
+	}
+
+	pub fn notify_all(&self) { self.inner.notify_all(); }
+}
+
+pub struct Mutex<T: Sized> {
+	inner: StdMutex<T>,
+}
+
+impl<T: Sized> Mutex<T> {
+	#[allow(unused)]
+	pub(crate) fn into_inner(self) -> LockResult<T> {
+		self.inner.into_inner().map_err(|_| ())
+	}
+}
+
+/// Slow waits and holds which are reported once the lock they relate to has been released.
+#[derive(Clone, Copy, Default)]
+struct PendingReports {
+	waited: Option<Duration>,
+	held: Option<Duration>,
+}
+
+impl PendingReports {
+	fn report(&self, kind: LockKind, site: &'static Location<'static>) {
+		report_long_wait(self.waited, kind, site);
+		report_long_hold(self.held, kind, site);
+	}
+}
+
+#[must_use = "if unused the Mutex will immediately unlock"]
+pub struct MutexGuard<'a, T: Sized + 'a> {
+	lock: ManuallyDrop<StdMutexGuard<'a, T>>,
+	acquired_at: Option<Instant>,
+	site: &'static Location<'static>,
+	pending: PendingReports,
+}
+
+impl<'a, T: Sized> MutexGuard<'a, T> {
+	fn new(
+		lock: StdMutexGuard<'a, T>, (acquired_at, waited): (Option<Instant>, Option<Duration>),
+		site: &'static Location<'static>, previous: PendingReports,
+	) -> Self {
+		// A guard handed back by a `Condvar` may still have reports from before the wait pending.
+		let pending = PendingReports { waited: previous.waited.or(waited), held: previous.held };
+		MutexGuard { lock: ManuallyDrop::new(lock), acquired_at, site, pending }
+	}
+
+	/// Unwraps the inner guard without releasing the lock, returning the reports which are still
+	/// pending as the lock remains held.
+	fn into_inner(self) -> (StdMutexGuard<'a, T>, PendingReports) {
+		let mut this = ManuallyDrop::new(self);
+		let held = lock_released(this.acquired_at);
+		let pending = PendingReports { waited: this.pending.waited, held: this.pending.held.or(held) };
+		(unsafe { ManuallyDrop::take(&mut this.lock) }, pending)
+	}
+}
+
+impl<T: Sized> Drop for MutexGuard<'_, T> {
+	fn drop(&mut self) {
+		self.pending.held = self.pending.held.or(lock_released(self.acquired_at));
+		// Release the lock before logging so that a slow logger never extends the time it is held.
+		unsafe { ManuallyDrop::drop(&mut self.lock); }
+		self.pending.report(LockKind::Mutex, self.site);
+	}
+}
+
+impl<T: Sized> Deref for MutexGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&**self.lock
+	}
+}
+
+impl<T: Sized> DerefMut for MutexGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut **self.lock
+	}
+}
+
+impl<T> Mutex<T> {
+	pub fn new(inner: T) -> Mutex<T> {
+		Mutex { inner: StdMutex::new(inner) }
+	}
+
+	#[track_caller]
+	pub fn lock<'a>(&'a self) -> LockResult<MutexGuard<'a, T>> {
+		let site = Location::caller();
+		let wait_start = start_timer();
+		let lock = self.inner.lock().map_err(|_| ())?;
+		Ok(MutexGuard::new(lock, lock_acquired(wait_start), site, PendingReports::default()))
+	}
+
+	#[track_caller]
+	pub fn try_lock<'a>(&'a self) -> LockResult<MutexGuard<'a, T>> {
+		let site = Location::caller();
+		self.inner.try_lock().map(|lock| MutexGuard::new(lock, (start_timer(), None), site, PendingReports::default()))
+			.map_err(|_| ())
+	}
+}
+
+impl<'a, T: 'a> LockTestExt<'a> for Mutex<T> {
+	#[inline]
+	fn held_by_thread(&self) -> LockHeldState { LockHeldState::Unsupported }
+	type ExclLock = MutexGuard<'a, T>;
+	#[inline]
+	#[track_caller]
+	fn unsafe_well_ordered_double_lock_self(&'a self) -> MutexGuard<T> { self.lock().unwrap() }
+}
+
+pub struct RwLockReadGuard<'a, T: Sized + 'a> {
+	lock: ManuallyDrop<StdRwLockReadGuard<'a, T>>,
+	acquired_at: Option<Instant>,
+	waited: Option<Duration>,
+	site: &'static Location<'static>,
+}
+
+pub struct RwLockWriteGuard<'a, T: Sized + 'a> {
+	lock: ManuallyDrop<StdRwLockWriteGuard<'a, T>>,
+	acquired_at: Option<Instant>,
+	waited: Option<Duration>,
+	site: &'static Location<'static>,
+}
+
+impl<T: Sized> Deref for RwLockReadGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&**self.lock
+	}
+}
+
+impl<T: Sized> Drop for RwLockReadGuard<'_, T> {
+	fn drop(&mut self) {
+		let held = lock_released(self.acquired_at);
+		unsafe { ManuallyDrop::drop(&mut self.lock); }
+		PendingReports { waited: self.waited, held }.report(LockKind::Read, self.site);
+	}
+}
+
+impl<T: Sized> Deref for RwLockWriteGuard<'_, T> {
+	type Target = T;
+
+	fn deref(&self) -> &T {
+		&**self.lock
+	}
+}
+
+impl<T: Sized> Drop for RwLockWriteGuard<'_, T> {
+	fn drop(&mut self) {
+		let held = lock_released(self.acquired_at);
+		unsafe { ManuallyDrop::drop(&mut self.lock); }
+		PendingReports { waited: self.waited, held }.report(LockKind::Write, self.site);
+	}
+}
+
+impl<T: Sized> DerefMut for RwLockWriteGuard<'_, T> {
+	fn deref_mut(&mut self) -> &mut T {
+		&mut **self.lock
+	}
+}
+
+/// Implements the instrumented `RwLock` API around an inner lock type which hands out std guards,
+/// allowing us to share the guard types between [`RwLock`] and [`FairRwLock`].
+macro_rules! impl_instrumented_rwlock {
+	($name: ident, $inner: ident) => {
+		pub struct $name<T: Sized> {
+			inner: $inner<T>,
+		}
+
+		impl<T> $name<T> {
+			pub fn new(inner: T) -> $name<T> {
+				$name { inner: $inner::new(inner) }
+			}
+
+			#[track_caller]
+			pub fn read<'a>(&'a self) -> LockResult<RwLockReadGuard<'a, T>> {
+				let site = Location::caller();
+				let wait_start = start_timer();
+				let lock = self.inner.read().map_err(|_| ())?;
+				let (acquired_at, waited) = lock_acquired(wait_start);
+				Ok(RwLockReadGuard { lock: ManuallyDrop::new(lock), acquired_at, waited, site })
+			}
+
+			#[track_caller]
+			pub fn write<'a>(&'a self) -> LockResult<RwLockWriteGuard<'a, T>> {
+				let site = Location::caller();
+				let wait_start = start_timer();
+				let lock = self.inner.write().map_err(|_| ())?;
+				let (acquired_at, waited) = lock_acquired(wait_start);
+				Ok(RwLockWriteGuard { lock: ManuallyDrop::new(lock), acquired_at, waited, site })
+			}
+
+			#[allow(dead_code)]
+			#[track_caller]
+			pub fn try_write<'a>(&'a self) -> LockResult<RwLockWriteGuard<'a, T>> {
+				let site = Location::caller();
+				self.inner.try_write()
+					.map(|lock| RwLockWriteGuard { lock: ManuallyDrop::new(lock), acquired_at: start_timer(), waited: None, site })
+					.map_err(|_| ())
+			}
+		}
+
+		impl<'a, T: 'a> LockTestExt<'a> for $name<T> {
+			#[inline]
+			fn held_by_thread(&self) -> LockHeldState { LockHeldState::Unsupported }
+			type ExclLock = RwLockWriteGuard<'a, T>;
+			#[inline]
+			#[track_caller]
+			fn unsafe_well_ordered_double_lock_self(&'a self) -> RwLockWriteGuard<'a, T> { self.write().unwrap() }
+		}
+	}
+}
+
+impl_instrumented_rwlock!(RwLock, StdRwLock);
+impl_instrumented_rwlock!(FairRwLock, InnerFairRwLock);
+
+#[cfg(test)]
+mod tests {
+	use super::{Mutex, RwLock, FairRwLock};
+	use crate::util::lock_watchdog::{enable_lock_watchdog, disable_lock_watchdog, LockWatchdogConfig};
+	use crate::util::logger::{Logger, Record};
+	use crate::util::test_utils::TestLogger;
+
+	use core::time::Duration;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	#[test]
+	fn reports_long_held_locks() {
+		let logger = Arc::new(TestLogger::new());
+		enable_lock_watchdog(Arc::clone(&logger), LockWatchdogConfig {
+			max_wait: Duration::from_secs(3600),
+			max_hold: Duration::from_millis(1),
+		});
+
+		let mutex = Mutex::new(0);
+		let rwlock = RwLock::new(0);
+		let fair_rwlock = FairRwLock::new(0);
+		{
+			let _mutex_lock = mutex.lock().unwrap();
+			let _read_lock = rwlock.read().unwrap();
+			let _write_lock = fair_rwlock.write().unwrap();
+			std::thread::sleep(Duration::from_millis(5));
+		}
+		// Locks released quickly are not reported.
+		*mutex.lock().unwrap() += 1;
+		disable_lock_watchdog();
+
+		let module = "lightning::util::lock_watchdog";
+		logger.assert_log_regex(module, regex::Regex::new(r"Held mutex acquired at .*watchdog_sync.rs").unwrap(), 1);
+		logger.assert_log_regex(module, regex::Regex::new(r"Held read lock acquired at ").unwrap(), 1);
+		logger.assert_log_regex(module, regex::Regex::new(r"Held write lock acquired at ").unwrap(), 1);
+
+		// The watchdog is process-global, so we check reports happen after release in the same test.
+		reports_after_release();
+	}
+
+	struct LockCheckingLogger {
+		mutex: Arc<Mutex<()>>,
+		records: AtomicUsize,
+	}
+	impl Logger for LockCheckingLogger {
+		fn log(&self, _record: &Record) {
+			// The report must only be logged once the reported lock has been released.
+			assert!(self.mutex.try_lock().is_ok());
+			self.records.fetch_add(1, Ordering::SeqCst);
+		}
+	}
+
+	fn reports_after_release() {
+		let mutex = Arc::new(Mutex::new(()));
+		let logger = Arc::new(LockCheckingLogger { mutex: Arc::clone(&mutex), records: AtomicUsize::new(0) });
+		enable_lock_watchdog(Arc::clone(&logger), LockWatchdogConfig {
+			max_wait: Duration::from_secs(3600),
+			max_hold: Duration::from_millis(1),
+		});
+		{
+			let _lock = mutex.lock().unwrap();
+			std::thread::sleep(Duration::from_millis(5));
+		}
+		disable_lock_watchdog();
+		assert_eq!(logger.records.load(Ordering::SeqCst), 1);
+		// Disabling the watchdog drops its reference to the logger.
+		assert_eq!(Arc::strong_count(&logger), 1);
+	}
+}
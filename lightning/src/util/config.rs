@@ -179,6 +179,43 @@ pub struct ChannelHandshakeConfig {
 	/// [`SIGHASH_SINGLE + update_fee Considered Harmful`]: https://lists.linuxfoundation.org/pipermail/lightning-dev/2020-September/002796.html
 	pub negotiate_anchors_zero_fee_htlc_tx: bool,
 
+	/// If set, we attempt to negotiate the `simplified_update` option for all future channels.
+	///
+	/// With `simplified_update`, only one side of the channel may propose updates (HTLC additions
+	/// and removals as well as fee updates) at any given time. The side holding the turn hands it
+	/// over to its counterparty with a `yield` message once all of its updates have been
+	/// irrevocably committed, making concurrent updates, and the desyncs they can cause,
+	/// impossible. This comes at the cost of some additional latency when both sides wish to
+	/// update the channel at the same time.
+	///
+	/// The option is only used for channels opened while both we and our counterparty signal
+	/// support for it, existing channels are unaffected.
+	///
+	/// Default value: false.
+	pub negotiate_simplified_update: bool,
+
+	/// If set, we signal support for renegotiating the type of existing channels upon
+	/// reconnection, allowing channels opened before a newer channel type was available to adopt
+	/// it without being closed.
+	///
+	/// Features are only ever added to the type of existing channels, which currently only affects
+	/// [`ChannelHandshakeConfig::negotiate_anchors_zero_fee_htlc_tx`]. That is, if both we and our
+	/// counterparty support anchor outputs, channels without them are upgraded. Channels with
+	/// anchor outputs are never downgraded automatically, even if either of us no longer supports
+	/// them, but may be moved off them via [`ChannelManager::request_channel_type_downgrade`]. The
+	/// change only takes place if both sides agree on the new type and the channel has no HTLCs
+	/// pending at the time of reconnection.
+	///
+	/// Note that the new type is passed to the channel's signer via a further call to
+	/// [`ChannelSigner::provide_channel_parameters`], which external signers must support before
+	/// this is enabled.
+	///
+	/// [`ChannelManager::request_channel_type_downgrade`]: crate::ln::channelmanager::ChannelManager::request_channel_type_downgrade
+	/// [`ChannelSigner::provide_channel_parameters`]: crate::sign::ChannelSigner::provide_channel_parameters
+	///
+	/// Default value: false.
+	pub negotiate_channel_type_upgrade: bool,
+
 	/// The maximum number of HTLCs in-flight from our counterparty towards us at the same time.
 	///
 	/// Increasing the value can help improve liquidity and stability in
@@ -205,6 +242,8 @@ impl Default for ChannelHandshakeConfig {
 			commit_upfront_shutdown_pubkey: true,
 			their_channel_reserve_proportional_millionths: 10_000,
 			negotiate_anchors_zero_fee_htlc_tx: false,
+			negotiate_simplified_update: false,
+			negotiate_channel_type_upgrade: false,
 			our_max_accepted_htlcs: 50,
 		}
 	}
@@ -696,6 +735,369 @@ impl crate::util::ser::Readable for LegacyChannelConfig {
 	}
 }
 
+/// Limits on the resources which automatic retries of outbound payments may consume, shared
+/// across all pending payments.
+///
+/// Once a retry would exceed either limit the payment is abandoned rather than retried, resulting
+/// in an [`Event::PaymentFailed`] with [`PaymentFailureReason::RetriesExhausted`] once all of its
+/// HTLCs have resolved.
+///
+/// Default value: no limits.
+///
+/// [`Event::PaymentFailed`]: crate::events::Event::PaymentFailed
+/// [`PaymentFailureReason::RetriesExhausted`]: crate::events::PaymentFailureReason::RetriesExhausted
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct PaymentRetryBudget {
+	/// The maximum total value, in millisatoshis, which may be pending across all outbound
+	/// payments which have been retried at least once, including the value of the retry being
+	/// made.
+	pub max_retry_value_in_flight_msat: Option<u64>,
+	/// The maximum total routing fees, in millisatoshis, which retries may commit to within any
+	/// one-hour window.
+	///
+	/// Windows are measured in calls to [`ChannelManager::timer_tick_occurred`], which should be
+	/// called roughly once per minute.
+	///
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	pub max_retry_fees_msat_per_hour: Option<u64>,
+}
+
+/// Node-wide limits on accepting new inbound HTLCs, protecting slow persistence or chain backends
+/// from being overwhelmed by a flood of HTLCs.
+///
+/// HTLCs received beyond either limit are failed back with `temporary_channel_failure`. How the
+/// limits were applied is available via [`ChannelManager::htlc_acceptance_stats`].
+///
+/// Default value: no limits.
+///
+/// [`ChannelManager::htlc_acceptance_stats`]: crate::ln::channelmanager::ChannelManager::htlc_acceptance_stats
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct HTLCAcceptanceLimits {
+	/// The sustained number of new inbound HTLCs per second we accept across all channels,
+	/// enforced as a token bucket which allows bursts of up to one second's worth of HTLCs.
+	///
+	/// Note that without the `std` feature time does not advance, so only this many HTLCs are
+	/// ever accepted until the [`ChannelManager`] is restarted.
+	///
+	/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+	pub max_htlcs_per_second: Option<u32>,
+	/// The maximum number of inbound HTLCs which may be pending across all channels, beyond which
+	/// new inbound HTLCs are failed back until some resolve.
+	pub max_pending_htlcs: Option<u32>,
+}
+
+/// What to do once a channel has been idle for [`IdleChannelConfig::idle_timer_ticks_threshold`]
+/// timer ticks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IdleChannelAction {
+	/// Only generate an [`Event::ChannelIdle`], leaving it up to the user whether to close the
+	/// channel.
+	///
+	/// [`Event::ChannelIdle`]: crate::events::Event::ChannelIdle
+	Alert,
+	/// Generate an [`Event::ChannelIdle`] and, once the [`ConfirmationTarget::Background`] feerate
+	/// is at or below [`IdleChannelConfig::max_close_feerate_sat_per_1000_weight`], initiate a
+	/// cooperative close of the channel.
+	///
+	/// Channels with peers passed to [`ChannelManager::set_idle_close_allowlisted`] are never
+	/// closed automatically.
+	///
+	/// [`Event::ChannelIdle`]: crate::events::Event::ChannelIdle
+	/// [`ConfirmationTarget::Background`]: crate::chain::chaininterface::ConfirmationTarget::Background
+	/// [`ChannelManager::set_idle_close_allowlisted`]: crate::ln::channelmanager::ChannelManager::set_idle_close_allowlisted
+	CooperativeClose,
+}
+
+/// Configuration for detecting channels which have seen no activity for an extended period.
+///
+/// Idle channels still occupy a UTXO and a [`ChannelMonitor`], so nodes with many channels may
+/// wish to be notified of, or automatically close, channels which are no longer used.
+///
+/// A channel is considered idle for a timer tick if no HTLCs were added to it (in either
+/// direction) since the previous call to [`ChannelManager::timer_tick_occurred`] and it has no
+/// HTLCs pending.
+///
+/// Default value: disabled.
+///
+/// [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct IdleChannelConfig {
+	/// The number of consecutive idle calls to [`ChannelManager::timer_tick_occurred`] after
+	/// which a channel is considered idle, or `None` to disable idle channel detection.
+	///
+	/// As [`ChannelManager::timer_tick_occurred`] should be called roughly once per minute, a
+	/// value of `60 * 24 * 30` roughly corresponds to a month.
+	///
+	/// Default value: None.
+	///
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	pub idle_timer_ticks_threshold: Option<u64>,
+	/// What to do once a channel has been found to be idle.
+	///
+	/// Default value: [`IdleChannelAction::Alert`].
+	pub action: IdleChannelAction,
+	/// The maximum [`ConfirmationTarget::Background`] feerate at which an idle channel will be
+	/// cooperatively closed when [`Self::action`] is [`IdleChannelAction::CooperativeClose`]. This
+	/// is also used as the target feerate of the closing transaction.
+	///
+	/// Default value: 1,000 sat per 1000 weight (i.e. 4 sat/vB).
+	///
+	/// [`ConfirmationTarget::Background`]: crate::chain::chaininterface::ConfirmationTarget::Background
+	pub max_close_feerate_sat_per_1000_weight: u32,
+}
+
+impl Default for IdleChannelConfig {
+	fn default() -> Self {
+		IdleChannelConfig {
+			idle_timer_ticks_threshold: None,
+			action: IdleChannelAction::Alert,
+			max_close_feerate_sat_per_1000_weight: 1000,
+		}
+	}
+}
+
+/// Automatic force-closure of channels with HTLCs pending while our counterparty is unresponsive.
+///
+/// A channel's counterparty is considered unresponsive for a timer tick if the channel has HTLCs
+/// pending while the counterparty is disconnected or has not responded to a message we're
+/// awaiting a response to. Once this has been the case for
+/// [`Self::unresponsive_timer_ticks_threshold`] consecutive calls to
+/// [`ChannelManager::timer_tick_occurred`], the channel is force-closed as soon as the chain
+/// reaches [`Self::force_close_margin_blocks`] before the earliest expiry of the HTLCs we'd have
+/// to resolve on-chain, i.e. outbound HTLCs our counterparty hasn't begun removing and inbound
+/// HTLCs we know the preimage for, giving our transactions ample time to confirm. An
+/// [`Event::ChannelUnresponsive`] is generated [`Self::warning_margin_blocks`] before then,
+/// allowing the user to intervene, or right before the channel is force-closed if the threshold
+/// was only reached within the warning window.
+///
+/// Without this, channels are only force-closed once one of their HTLCs is about to expire.
+///
+/// Default value: disabled.
+///
+/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+/// [`Event::ChannelUnresponsive`]: crate::events::Event::ChannelUnresponsive
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UnresponsivePeerConfig {
+	/// The number of consecutive calls to [`ChannelManager::timer_tick_occurred`] during which a
+	/// channel's counterparty must have been unresponsive before we consider force-closing the
+	/// channel, or `None` to disable automatic force-closes.
+	///
+	/// Default value: None.
+	///
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	pub unresponsive_timer_ticks_threshold: Option<u64>,
+	/// The number of blocks before the earliest expiry of a channel's pending HTLCs at which the
+	/// channel is force-closed if its counterparty is unresponsive.
+	///
+	/// Default value: 72 (roughly half a day).
+	pub force_close_margin_blocks: u32,
+	/// The number of blocks before a channel would be force-closed at which an
+	/// [`Event::ChannelUnresponsive`] is generated.
+	///
+	/// Default value: 72.
+	///
+	/// [`Event::ChannelUnresponsive`]: crate::events::Event::ChannelUnresponsive
+	pub warning_margin_blocks: u32,
+}
+
+impl Default for UnresponsivePeerConfig {
+	fn default() -> Self {
+		UnresponsivePeerConfig {
+			unresponsive_timer_ticks_threshold: None,
+			force_close_margin_blocks: 72,
+			warning_margin_blocks: 72,
+		}
+	}
+}
+
+/// Detection and correction of pathological asymmetries between the forwarding parameters we
+/// advertise for a channel and those our counterparty advertises for the same channel.
+///
+/// When we forward an HTLC over a channel, the [`ChannelConfig::cltv_expiry_delta`] we advertise
+/// determines how much time we have to claim the incoming HTLC should our counterparty go on-chain
+/// with the outgoing one. A delta far below the one our counterparty advertises for the other
+/// direction usually indicates a misconfiguration on our end, as does charging a proportional fee
+/// far above theirs, leading to forwarding failures and unused liquidity respectively.
+///
+/// Asymmetries can be listed with [`ChannelManager::list_forwarding_asymmetries`]. If
+/// [`Self::auto_harmonize`] is set, they are also corrected on each call to
+/// [`ChannelManager::timer_tick_occurred`].
+///
+/// Default value: disabled.
+///
+/// [`ChannelManager::list_forwarding_asymmetries`]: crate::ln::channelmanager::ChannelManager::list_forwarding_asymmetries
+/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ForwardingHarmonizationConfig {
+	/// The minimum [`ChannelConfig::cltv_expiry_delta`] we advertise for a channel, as a percentage
+	/// of the `cltv_expiry_delta` our counterparty advertises for it, or `None` to not check CLTV
+	/// expiry deltas.
+	///
+	/// For example, a value of 50 flags channels where our delta is less than half of theirs.
+	///
+	/// Default value: None.
+	pub min_cltv_expiry_delta_percent: Option<u16>,
+	/// The maximum [`ChannelConfig::forwarding_fee_proportional_millionths`] we advertise for a
+	/// channel, as a multiple of the proportional fee our counterparty advertises for it, or `None`
+	/// to not check proportional fees.
+	///
+	/// Channels where our counterparty charges no proportional fee are never flagged.
+	///
+	/// Default value: None.
+	pub max_fee_proportional_millionths_multiple: Option<u32>,
+	/// The highest [`ChannelConfig::cltv_expiry_delta`] we will suggest or apply to resolve an
+	/// asymmetry, regardless of the `cltv_expiry_delta` our counterparty advertises.
+	///
+	/// This prevents a counterparty advertising an excessive delta from inflating ours. Channels
+	/// where our delta is already at least this value are never flagged.
+	///
+	/// Default value: 288 (two days).
+	pub max_cltv_expiry_delta: u16,
+	/// The lowest [`ChannelConfig::forwarding_fee_proportional_millionths`] we will suggest or apply
+	/// to resolve an asymmetry, regardless of the proportional fee our counterparty advertises.
+	///
+	/// This prevents a counterparty advertising a negligible fee from driving ours down. Channels
+	/// where our fee is already at most this value are never flagged.
+	///
+	/// Default value: 100.
+	pub min_fee_proportional_millionths: u32,
+	/// Whether asymmetries should be corrected automatically by raising our CLTV expiry delta or
+	/// lowering our proportional fee to the bound configured above.
+	///
+	/// Default value: false.
+	pub auto_harmonize: bool,
+}
+
+impl Default for ForwardingHarmonizationConfig {
+	fn default() -> Self {
+		ForwardingHarmonizationConfig {
+			min_cltv_expiry_delta_percent: None,
+			max_fee_proportional_millionths_multiple: None,
+			max_cltv_expiry_delta: 288,
+			min_fee_proportional_millionths: 100,
+			auto_harmonize: false,
+		}
+	}
+}
+
+/// Holding of HTLCs paying us until the application decides whether to accept them, e.g. after
+/// running external fraud or risk checks on the payment.
+///
+/// If [`Self::hold_inbound_htlcs`] is set, each HTLC paying us is decoded and surfaced in an
+/// [`Event::HTLCHeldForValidation`], including any custom TLVs the sender included in the onion,
+/// before it may become part of an [`Event::PaymentClaimable`]. The application must then call
+/// [`ChannelManager::accept_held_htlc`] or [`ChannelManager::fail_held_htlc`] for it, otherwise the
+/// HTLC is failed back after [`Self::decision_timeout_ticks`].
+///
+/// HTLCs received for phantom nodes are not held.
+///
+/// Default value: disabled.
+///
+/// [`Event::HTLCHeldForValidation`]: crate::events::Event::HTLCHeldForValidation
+/// [`Event::PaymentClaimable`]: crate::events::Event::PaymentClaimable
+/// [`ChannelManager::accept_held_htlc`]: crate::ln::channelmanager::ChannelManager::accept_held_htlc
+/// [`ChannelManager::fail_held_htlc`]: crate::ln::channelmanager::ChannelManager::fail_held_htlc
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct InboundHTLCValidationConfig {
+	/// Whether HTLCs paying us are held until the application accepts them.
+	///
+	/// Default value: false.
+	pub hold_inbound_htlcs: bool,
+	/// The number of calls to [`ChannelManager::timer_tick_occurred`] after which a held HTLC no
+	/// decision was made for is failed back. Held HTLCs are also failed back once they get close
+	/// to expiring, regardless of this value.
+	///
+	/// Default value: 2, i.e. one to two minutes if the timer is called once per minute.
+	///
+	/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+	pub decision_timeout_ticks: u16,
+}
+
+impl Default for InboundHTLCValidationConfig {
+	fn default() -> Self {
+		InboundHTLCValidationConfig {
+			hold_inbound_htlcs: false,
+			decision_timeout_ticks: 2,
+		}
+	}
+}
+
+/// Options for a circuit breaker protecting us while on-chain feerates spike.
+///
+/// On each [`ChannelManager::timer_tick_occurred`], our [`ConfirmationTarget::Normal`] feerate
+/// estimate is compared against [`Self::engage_feerate_sat_per_1000_weight`]. While the breaker
+/// is engaged:
+///  * new HTLCs may only add a fraction of each channel's maximum dust exposure, see
+///    [`Self::max_dust_htlc_exposure_percent`],
+///  * new inbound HTLCs below [`Self::min_inbound_htlc_msat`] are failed back,
+///  * cooperative closes are deferred if [`Self::defer_cooperative_closes`] is set, and idle
+///    channels are not closed, and
+///  * [`ChannelManager::fee_spike_circuit_breaker_engaged`] returns true, which applications
+///    should check before broadcasting non-urgent sweeps.
+///
+/// An [`Event::FeeSpikeCircuitBreakerEngaged`] or [`Event::FeeSpikeCircuitBreakerReleased`] is
+/// generated whenever the breaker changes state.
+///
+/// Default value: disabled.
+///
+/// [`ChannelManager::timer_tick_occurred`]: crate::ln::channelmanager::ChannelManager::timer_tick_occurred
+/// [`ChannelManager::fee_spike_circuit_breaker_engaged`]: crate::ln::channelmanager::ChannelManager::fee_spike_circuit_breaker_engaged
+/// [`ConfirmationTarget::Normal`]: crate::chain::chaininterface::ConfirmationTarget::Normal
+/// [`Event::FeeSpikeCircuitBreakerEngaged`]: crate::events::Event::FeeSpikeCircuitBreakerEngaged
+/// [`Event::FeeSpikeCircuitBreakerReleased`]: crate::events::Event::FeeSpikeCircuitBreakerReleased
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FeeSpikeCircuitBreakerConfig {
+	/// The feerate, in satoshis per 1000 weight units, at or above which the breaker is engaged.
+	/// If `None`, the breaker is never engaged.
+	///
+	/// Default value: None.
+	pub engage_feerate_sat_per_1000_weight: Option<u32>,
+	/// The feerate, in satoshis per 1000 weight units, below which an engaged breaker is released.
+	/// Setting this below [`Self::engage_feerate_sat_per_1000_weight`] avoids the breaker flapping
+	/// while feerates hover around the threshold. If `None`, the breaker is released once the
+	/// feerate drops below [`Self::engage_feerate_sat_per_1000_weight`].
+	///
+	/// Default value: None.
+	pub release_feerate_sat_per_1000_weight: Option<u32>,
+	/// The percentage of a channel's [`ChannelConfig::max_dust_htlc_exposure`] which new HTLCs may
+	/// use while the breaker is engaged. As the exposure limit is often a multiple of the current
+	/// feerate, it otherwise grows just as dust HTLCs become the most costly to resolve on chain.
+	///
+	/// HTLCs which were already pending and feerate updates are still checked against the full
+	/// limit, as failing those checks may require closing the channel.
+	///
+	/// Default value: 50.
+	pub max_dust_htlc_exposure_percent: u8,
+	/// The amount, in millisatoshis, below which new inbound HTLCs are failed back while the
+	/// breaker is engaged, as they may not be worth claiming on chain.
+	///
+	/// Default value: 1_000_000 (1,000 satoshis).
+	pub min_inbound_htlc_msat: u64,
+	/// Whether cooperative closes initiated via [`ChannelManager::close_channel`] (or
+	/// [`ChannelManager::close_channel_with_feerate_and_script`]) while the breaker is engaged are
+	/// deferred until it is released. Deferred closes are not persisted and have to be initiated
+	/// again after a restart.
+	///
+	/// Default value: true.
+	///
+	/// [`ChannelManager::close_channel`]: crate::ln::channelmanager::ChannelManager::close_channel
+	/// [`ChannelManager::close_channel_with_feerate_and_script`]: crate::ln::channelmanager::ChannelManager::close_channel_with_feerate_and_script
+	pub defer_cooperative_closes: bool,
+}
+
+impl Default for FeeSpikeCircuitBreakerConfig {
+	fn default() -> Self {
+		FeeSpikeCircuitBreakerConfig {
+			engage_feerate_sat_per_1000_weight: None,
+			release_feerate_sat_per_1000_weight: None,
+			max_dust_htlc_exposure_percent: 50,
+			min_inbound_htlc_msat: 1_000_000,
+			defer_cooperative_closes: true,
+		}
+	}
+}
+
 /// Top-level config which holds ChannelHandshakeLimits and ChannelConfig.
 ///
 /// Default::default() provides sane defaults for most configurations
@@ -764,6 +1166,57 @@ pub struct UserConfig {
 	///
 	/// [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
 	pub accept_mpp_keysend: bool,
+	/// Limits on the value in flight and routing fees spent by automatic retries of outbound
+	/// payments, shared across all payments. See [`PaymentRetryBudget`] for more info.
+	///
+	/// Default value: no limits.
+	pub payment_retry_budget: PaymentRetryBudget,
+	/// Detection and optional automatic closure of channels which have seen no activity for an
+	/// extended period. See [`IdleChannelConfig`] for more info.
+	///
+	/// Default value: disabled.
+	pub idle_channel_config: IdleChannelConfig,
+	/// The maximum total value of dust HTLCs forwarded to any single counterparty which we'll have
+	/// lost or put at risk. Dust HTLCs are trimmed from commitment transactions and thus their
+	/// value is lost to fees if the channel is force-closed while they're pending. Dust HTLCs
+	/// which were lost this way are counted along with those currently pending across all
+	/// channels with the counterparty, and further HTLCs which would be dust and exceed the limit
+	/// are failed back rather than forwarded. Dust HTLCs which are claimed or failed normally no
+	/// longer count towards the limit.
+	///
+	/// The value lost so far is available via [`ChannelManager::dust_htlcs_written_off_msat`] and
+	/// the value at risk on each channel via [`ChannelDetails::pending_dust_forwards_msat`].
+	///
+	/// Default value: `None`, i.e. no limit.
+	///
+	/// [`ChannelManager::dust_htlcs_written_off_msat`]: crate::ln::channelmanager::ChannelManager::dust_htlcs_written_off_msat
+	/// [`ChannelDetails::pending_dust_forwards_msat`]: crate::ln::channelmanager::ChannelDetails::pending_dust_forwards_msat
+	pub max_dust_htlc_write_off_msat: Option<u64>,
+	/// Node-wide limits on the rate of new inbound HTLCs and the number pending at once. See
+	/// [`HTLCAcceptanceLimits`] for more info.
+	///
+	/// Default value: no limits.
+	pub htlc_acceptance_limits: HTLCAcceptanceLimits,
+	/// Automatic force-closure of channels with HTLCs pending while our counterparty is
+	/// unresponsive. See [`UnresponsivePeerConfig`] for more info.
+	///
+	/// Default value: disabled.
+	pub unresponsive_peer_config: UnresponsivePeerConfig,
+	/// Detection and correction of asymmetries between the forwarding parameters we and our
+	/// counterparties advertise. See [`ForwardingHarmonizationConfig`] for more info.
+	///
+	/// Default value: disabled.
+	pub forwarding_harmonization_config: ForwardingHarmonizationConfig,
+	/// Holding of HTLCs paying us until the application accepts them. See
+	/// [`InboundHTLCValidationConfig`] for more info.
+	///
+	/// Default value: disabled.
+	pub inbound_htlc_validation_config: InboundHTLCValidationConfig,
+	/// Protective measures taken while on-chain feerates spike. See
+	/// [`FeeSpikeCircuitBreakerConfig`] for more info.
+	///
+	/// Default value: disabled.
+	pub fee_spike_circuit_breaker_config: FeeSpikeCircuitBreakerConfig,
 }
 
 impl Default for UserConfig {
@@ -777,6 +1230,14 @@ impl Default for UserConfig {
 			manually_accept_inbound_channels: false,
 			accept_intercept_htlcs: false,
 			accept_mpp_keysend: false,
+			payment_retry_budget: PaymentRetryBudget::default(),
+			idle_channel_config: IdleChannelConfig::default(),
+			max_dust_htlc_write_off_msat: None,
+			htlc_acceptance_limits: HTLCAcceptanceLimits::default(),
+			unresponsive_peer_config: UnresponsivePeerConfig::default(),
+			forwarding_harmonization_config: ForwardingHarmonizationConfig::default(),
+			inbound_htlc_validation_config: InboundHTLCValidationConfig::default(),
+			fee_spike_circuit_breaker_config: FeeSpikeCircuitBreakerConfig::default(),
 		}
 	}
 }
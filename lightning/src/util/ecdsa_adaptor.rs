@@ -0,0 +1,148 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! ECDSA adaptor signatures.
+//!
+//! An adaptor signature is an ECDSA signature "encrypted" to an adaptor point `Y`. Anyone can
+//! check that it decrypts to a valid signature for a given message and public key, but it can
+//! only be decrypted with the discrete log `y` of `Y`, e.g. an oracle's attestation to an
+//! outcome, see [`crate::ln::contracts`].
+//!
+//! This wraps the ECDSA adaptor signature module of `libsecp256k1-zkp`, which implements the
+//! scheme specified by the [DLC specification], and thus interoperates with other DLC
+//! implementations.
+//!
+//! [DLC specification]: https://github.com/discreetlogcontracts/dlcspecs/blob/master/ECDSA-adaptor.md
+
+use bitcoin::secp256k1::{self, Message, PublicKey, Secp256k1, SecretKey};
+use bitcoin::secp256k1::ecdsa::Signature;
+
+use crate::io;
+use crate::ln::msgs::DecodeError;
+use crate::util::ser::{Readable, Writeable, Writer};
+
+/// The length of a serialized [`EcdsaAdaptorSignature`], in bytes.
+pub const ECDSA_ADAPTOR_SIGNATURE_LENGTH: usize = 162;
+
+/// An ECDSA signature encrypted to an adaptor point, along with a proof that it was constructed
+/// correctly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EcdsaAdaptorSignature(secp256k1_zkp::EcdsaAdaptorSignature);
+
+impl EcdsaAdaptorSignature {
+	/// Signs `msg` with `secret_key`, encrypting the signature to `adaptor_point`.
+	///
+	/// Signing is deterministic, so signing the same message for the same adaptor point twice
+	/// results in the same adaptor signature.
+	pub fn encrypt<C: secp256k1::Signing>(
+		secp_ctx: &Secp256k1<C>, msg: &Message, secret_key: &SecretKey, adaptor_point: &PublicKey,
+	) -> Self {
+		EcdsaAdaptorSignature(secp256k1_zkp::EcdsaAdaptorSignature::encrypt_no_aux_rand(
+			secp_ctx, msg, secret_key, adaptor_point))
+	}
+
+	/// Checks that this adaptor signature decrypts to a valid signature of `msg` under `pubkey`
+	/// given the discrete log of `adaptor_point`.
+	pub fn verify<C: secp256k1::Verification>(
+		&self, secp_ctx: &Secp256k1<C>, msg: &Message, pubkey: &PublicKey, adaptor_point: &PublicKey,
+	) -> Result<(), ()> {
+		self.0.verify(secp_ctx, msg, pubkey, adaptor_point).map_err(|_| ())
+	}
+
+	/// Decrypts this adaptor signature into a regular ECDSA signature using the discrete log of
+	/// the adaptor point it was encrypted to.
+	///
+	/// If `adaptor_secret` is not the discrete log of the adaptor point the result will not be a
+	/// valid signature, which callers should check before relying on it.
+	pub fn decrypt(&self, adaptor_secret: &SecretKey) -> Result<Signature, ()> {
+		let mut sig = self.0.decrypt(adaptor_secret).map_err(|_| ())?;
+		sig.normalize_s();
+		Ok(sig)
+	}
+}
+
+impl Writeable for EcdsaAdaptorSignature {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		w.write_all(self.0.as_ref())
+	}
+}
+
+impl Readable for EcdsaAdaptorSignature {
+	fn read<R: io::Read>(r: &mut R) -> Result<Self, DecodeError> {
+		let mut buf = [0; ECDSA_ADAPTOR_SIGNATURE_LENGTH];
+		r.read_exact(&mut buf)?;
+		secp256k1_zkp::EcdsaAdaptorSignature::from_slice(&buf)
+			.map(EcdsaAdaptorSignature)
+			.map_err(|_| DecodeError::InvalidValue)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::EcdsaAdaptorSignature;
+	use crate::util::ser::{Readable, Writeable};
+
+	use bitcoin::secp256k1::{Message, PublicKey, Secp256k1, SecretKey};
+	use bitcoin::secp256k1::ecdsa::Signature;
+
+
+	#[test]
+	fn encrypt_verify_decrypt() {
+		let secp_ctx = Secp256k1::new();
+		let secret_key = SecretKey::from_slice(&[1; 32]).unwrap();
+		let pubkey = PublicKey::from_secret_key(&secp_ctx, &secret_key);
+		let adaptor_secret = SecretKey::from_slice(&[2; 32]).unwrap();
+		let adaptor_point = PublicKey::from_secret_key(&secp_ctx, &adaptor_secret);
+		let other_point = PublicKey::from_secret_key(&secp_ctx, &SecretKey::from_slice(&[3; 32]).unwrap());
+		let msg = Message::from_slice(&[0xab; 32]).unwrap();
+		let other_msg = Message::from_slice(&[0xcd; 32]).unwrap();
+
+		let adaptor_sig = EcdsaAdaptorSignature::encrypt(&secp_ctx, &msg, &secret_key, &adaptor_point);
+		assert_eq!(adaptor_sig, EcdsaAdaptorSignature::encrypt(&secp_ctx, &msg, &secret_key, &adaptor_point));
+		adaptor_sig.verify(&secp_ctx, &msg, &pubkey, &adaptor_point).unwrap();
+		assert!(adaptor_sig.verify(&secp_ctx, &other_msg, &pubkey, &adaptor_point).is_err());
+		assert!(adaptor_sig.verify(&secp_ctx, &msg, &adaptor_point, &adaptor_point).is_err());
+		assert!(adaptor_sig.verify(&secp_ctx, &msg, &pubkey, &other_point).is_err());
+
+		let sig = adaptor_sig.decrypt(&adaptor_secret).unwrap();
+		secp_ctx.verify_ecdsa(&msg, &sig, &pubkey).unwrap();
+		let wrong_sig = adaptor_sig.decrypt(&SecretKey::from_slice(&[3; 32]).unwrap()).unwrap();
+		assert!(secp_ctx.verify_ecdsa(&msg, &wrong_sig, &pubkey).is_err());
+
+		let read_sig: EcdsaAdaptorSignature = Readable::read(&mut &adaptor_sig.encode()[..]).unwrap();
+		assert_eq!(read_sig, adaptor_sig);
+	}
+
+	#[test]
+	fn dlc_spec_test_vector() {
+		// The verification and decryption test vector of the DLC specification's ECDSA adaptor
+		// signature test vectors.
+		let secp_ctx = Secp256k1::new();
+		let adaptor_sig_bytes = hex::decode("03424d14a5471c048ab87b3b83f6085d125d5864249ae4297a57c84e74710bb6730223f325042fce535d040fee52ec13231bf709ccd84233c6944b90317e62528b2527dff9d659a96db4c99f9750168308633c1867b70f3a18fb0f4539a1aecedcd1fc0148fc22f36b6303083ece3f872b18e35d368b3958efe5fb081f7716736ccb598d269aa3084d57e1855e1ea9a45efc10463bbf32ae378029f5763ceb40173f").unwrap();
+		let msg = Message::from_slice(&hex::decode("8131e6f4b45754f2c90bd06688ceeabc0c45055460729928b4eecf11026a9e2d").unwrap()).unwrap();
+		let pubkey = PublicKey::from_slice(&hex::decode("035be5e9478209674a96e60f1f037f6176540fd001fa1d64694770c56a7709c42c").unwrap()).unwrap();
+		let adaptor_point = PublicKey::from_slice(&hex::decode("02c2662c97488b07b6e819124b8989849206334a4c2fbdf691f7b34d2b16e9c293").unwrap()).unwrap();
+		let adaptor_secret = SecretKey::from_slice(&hex::decode("0b2aba63b885a0f0e96fa0f303920c7fb7431ddfa94376ad94d969fbf4109dc8").unwrap()).unwrap();
+		let expected_sig = Signature::from_compact(&hex::decode("424d14a5471c048ab87b3b83f6085d125d5864249ae4297a57c84e74710bb67329e80e0ee60e57af3e625bbae1672b1ecaa58effe613426b024fa1621d903394").unwrap()).unwrap();
+
+		let adaptor_sig: EcdsaAdaptorSignature = Readable::read(&mut &adaptor_sig_bytes[..]).unwrap();
+		assert_eq!(adaptor_sig.encode(), adaptor_sig_bytes);
+		adaptor_sig.verify(&secp_ctx, &msg, &pubkey, &adaptor_point).unwrap();
+		let sig = adaptor_sig.decrypt(&adaptor_secret).unwrap();
+		assert_eq!(sig, expected_sig);
+		secp_ctx.verify_ecdsa(&msg, &sig, &pubkey).unwrap();
+
+		// Flipping a bit of the proof invalidates it.
+		let mut tampered_bytes = adaptor_sig_bytes.clone();
+		tampered_bytes[100] ^= 1;
+		if let Ok(tampered_sig) = <EcdsaAdaptorSignature as Readable>::read(&mut &tampered_bytes[..]) {
+			assert!(tampered_sig.verify(&secp_ctx, &msg, &pubkey, &adaptor_point).is_err());
+		}
+	}
+}
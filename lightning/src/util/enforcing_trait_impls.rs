@@ -10,6 +10,8 @@
 use crate::ln::channel::{ANCHOR_OUTPUT_VALUE_SATOSHI, MIN_CHAN_DUST_LIMIT_SATOSHIS};
 use crate::ln::chan_utils::{HTLCOutputInCommitment, ChannelPublicKeys, HolderCommitmentTransaction, CommitmentTransaction, ChannelTransactionParameters, TrustedCommitmentTransaction, ClosingTransaction};
 use crate::ln::{chan_utils, msgs, PaymentPreimage};
+use crate::ln::contracts::SettlementBundle;
+use crate::util::ecdsa_adaptor::EcdsaAdaptorSignature;
 use crate::sign::{WriteableEcdsaChannelSigner, InMemorySigner, ChannelSigner, EcdsaChannelSigner};
 
 use crate::prelude::*;
@@ -239,6 +241,25 @@ impl EcdsaChannelSigner for EnforcingSigner {
 	) -> Result<Signature, ()> {
 		self.inner.sign_channel_announcement_with_funding_key(msg, secp_ctx)
 	}
+
+	fn sign_settlement_transaction(
+		&self, bundle: &SettlementBundle, branch_idx: usize, secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Signature, ()> {
+		self.inner.sign_settlement_transaction(bundle, branch_idx, secp_ctx)
+	}
+
+	fn sign_settlement_transactions_with_adaptor_points(
+		&self, bundle: &SettlementBundle, adaptor_points: &[PublicKey],
+		secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Vec<EcdsaAdaptorSignature>, ()> {
+		self.inner.sign_settlement_transactions_with_adaptor_points(bundle, adaptor_points, secp_ctx)
+	}
+
+	fn sign_contract_message(
+		&self, msg: &[u8], secp_ctx: &Secp256k1<secp256k1::All>
+	) -> Result<Signature, ()> {
+		self.inner.sign_contract_message(msg, secp_ctx)
+	}
 }
 
 impl WriteableEcdsaChannelSigner for EnforcingSigner {}
@@ -0,0 +1,197 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Instrumentation which reports internal locks that are waited on or held for too long.
+//!
+//! When built with the `lock_watchdog` feature, every lock LDK takes internally (e.g. the
+//! [`ChannelManager`] per-peer state, the [`NetworkGraph`] and the [`ChainMonitor`] monitor set)
+//! records how long the caller waited to acquire it and how long it was held. Once enabled via
+//! [`enable_lock_watchdog`], any acquisition exceeding the configured thresholds is logged at
+//! [`Level::Warn`] with the [`Record`]'s file and line set to the site which took the lock, which
+//! makes it possible to diagnose latency spikes under high message rates. Reports are only logged
+//! once the lock in question has been released, so a slow logger never extends a lock's hold time.
+//!
+//! Until [`enable_lock_watchdog`] is called the instrumentation costs a single relaxed atomic load
+//! per lock acquisition.
+//!
+//! [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+//! [`NetworkGraph`]: crate::routing::gossip::NetworkGraph
+//! [`ChainMonitor`]: crate::chain::chainmonitor::ChainMonitor
+
+use crate::util::logger::{Level, Logger, Record};
+
+use core::fmt;
+use core::ops::Deref;
+use core::panic::Location;
+use core::time::Duration;
+
+use std::sync::{Arc, Once, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+/// Thresholds above which the lock watchdog reports a lock acquisition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LockWatchdogConfig {
+	/// The maximum amount of time a thread may block waiting to acquire a lock before the
+	/// acquisition is reported as contended.
+	///
+	/// Default value: 10 milliseconds.
+	pub max_wait: Duration,
+	/// The maximum amount of time a lock may be held before it is reported as long-held when it
+	/// is released.
+	///
+	/// Default value: 50 milliseconds.
+	pub max_hold: Duration,
+}
+
+impl Default for LockWatchdogConfig {
+	fn default() -> Self {
+		LockWatchdogConfig {
+			max_wait: Duration::from_millis(10),
+			max_hold: Duration::from_millis(50),
+		}
+	}
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static MAX_WAIT_NANOS: AtomicU64 = AtomicU64::new(u64::max_value());
+static MAX_HOLD_NANOS: AtomicU64 = AtomicU64::new(u64::max_value());
+
+type Reporter = Arc<dyn Logger + Send + Sync>;
+
+/// The registered [`Reporter`], if any.
+///
+/// This is a plain [`std::sync::RwLock`] rather than one of our instrumented wrappers, as the
+/// watchdog is itself called from within those. It is only held to clone or replace the
+/// [`Reporter`], never while logging. As `RwLock::new` isn't `const` on our MSRV, it is created on
+/// first use.
+static mut REPORTER: Option<RwLock<Option<Reporter>>> = None;
+static REPORTER_INIT: Once = Once::new();
+
+fn reporter_lock() -> &'static RwLock<Option<Reporter>> {
+	REPORTER_INIT.call_once(|| { unsafe { REPORTER = Some(RwLock::new(None)); } });
+	unsafe { REPORTER.as_ref() }.unwrap()
+}
+
+fn set_reporter(reporter: Option<Reporter>) {
+	let old_reporter = core::mem::replace(&mut *reporter_lock().write().unwrap(), reporter);
+	// Drop the previous reporter only once the lock has been released, as dropping a logger may run
+	// arbitrary code.
+	drop(old_reporter);
+}
+
+fn get_reporter() -> Option<Reporter> {
+	reporter_lock().read().unwrap().clone()
+}
+
+struct DerefLogger<L: Deref>(L) where L::Target: Logger;
+impl<L: Deref> Logger for DerefLogger<L> where L::Target: Logger {
+	fn log(&self, record: &Record) { self.0.log(record) }
+}
+
+/// Starts reporting lock acquisitions which exceed the thresholds in `config` to `logger`.
+///
+/// The watchdog is process-global, so calling this again replaces any previously-registered
+/// logger and thresholds.
+///
+/// Note that the `logger` must not itself take any LDK-internal locks, or it may deadlock.
+pub fn enable_lock_watchdog<L: Deref + Send + Sync + 'static>(logger: L, config: LockWatchdogConfig)
+where L::Target: Logger {
+	set_reporter(Some(Arc::new(DerefLogger(logger))));
+	MAX_WAIT_NANOS.store(duration_to_nanos(config.max_wait), Ordering::Relaxed);
+	MAX_HOLD_NANOS.store(duration_to_nanos(config.max_hold), Ordering::Relaxed);
+	ENABLED.store(true, Ordering::Release);
+}
+
+/// Stops reporting lock acquisitions and drops the logger passed to [`enable_lock_watchdog`].
+pub fn disable_lock_watchdog() {
+	ENABLED.store(false, Ordering::Release);
+	set_reporter(None);
+}
+
+fn duration_to_nanos(duration: Duration) -> u64 {
+	let nanos = duration.as_nanos();
+	if nanos > u64::max_value() as u128 { u64::max_value() } else { nanos as u64 }
+}
+
+/// Returns the current time if the watchdog is enabled, to be used as the start of a wait.
+#[inline]
+pub(crate) fn start_timer() -> Option<Instant> {
+	if ENABLED.load(Ordering::Acquire) { Some(Instant::now()) } else { None }
+}
+
+/// Called once a lock has been acquired after waiting since `wait_start`. Returns the time at
+/// which the lock was acquired, which should later be passed to [`lock_released`], and how long
+/// we waited for the lock if it should be reported via [`report_long_wait`].
+///
+/// As with [`report_long_hold`], the wait should only be reported once the lock is released, so
+/// that a slow logger never extends the time the lock is held.
+#[inline]
+pub(crate) fn lock_acquired(wait_start: Option<Instant>) -> (Option<Instant>, Option<Duration>) {
+	let wait_start = match wait_start { Some(start) => start, None => return (None, None) };
+	let acquired_at = Instant::now();
+	let waited = acquired_at.duration_since(wait_start);
+	if duration_to_nanos(waited) > MAX_WAIT_NANOS.load(Ordering::Relaxed) {
+		(Some(acquired_at), Some(waited))
+	} else {
+		(Some(acquired_at), None)
+	}
+}
+
+/// Reports an acquisition of a lock at `site` which waited for `waited`, as returned by
+/// [`lock_acquired`].
+#[inline]
+pub(crate) fn report_long_wait(waited: Option<Duration>, kind: LockKind, site: &'static Location<'static>) {
+	if let Some(waited) = waited {
+		report(site, format_args!("Waited {}us to acquire {} at {}:{}:{}",
+			waited.as_micros(), kind, site.file(), site.line(), site.column()));
+	}
+}
+
+/// Called when a lock acquired at `acquired_at` (as returned by [`lock_acquired`]) is released.
+/// Returns how long the lock was held if it should be reported via [`report_long_hold`], which
+/// callers must only do once the lock has actually been released.
+#[inline]
+pub(crate) fn lock_released(acquired_at: Option<Instant>) -> Option<Duration> {
+	let held = acquired_at?.elapsed();
+	if duration_to_nanos(held) > MAX_HOLD_NANOS.load(Ordering::Relaxed) { Some(held) } else { None }
+}
+
+/// Reports a lock acquired at `site` which was held for `held`, as returned by [`lock_released`].
+#[inline]
+pub(crate) fn report_long_hold(held: Option<Duration>, kind: LockKind, site: &'static Location<'static>) {
+	if let Some(held) = held {
+		report(site, format_args!("Held {} acquired at {}:{}:{} for {}us",
+			kind, site.file(), site.line(), site.column(), held.as_micros()));
+	}
+}
+
+/// The kind of lock being reported, for inclusion in log messages.
+#[derive(Clone, Copy)]
+pub(crate) enum LockKind {
+	Mutex,
+	Read,
+	Write,
+}
+
+impl fmt::Display for LockKind {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			LockKind::Mutex => f.write_str("mutex"),
+			LockKind::Read => f.write_str("read lock"),
+			LockKind::Write => f.write_str("write lock"),
+		}
+	}
+}
+
+fn report(site: &'static Location<'static>, args: fmt::Arguments) {
+	if let Some(logger) = get_reporter() {
+		logger.log(&Record::new(Level::Warn, args, module_path!(), site.file(), site.line()));
+	}
+}
@@ -18,10 +18,14 @@ pub mod ser_macros;
 pub mod errors;
 pub mod ser;
 pub mod message_signing;
+pub mod ecdsa_adaptor;
 pub mod invoice;
 pub mod persist;
+pub mod replication;
 pub mod string;
 pub mod wakers;
+#[cfg(all(feature = "std", feature = "lock_watchdog"))]
+pub mod lock_watchdog;
 
 pub(crate) mod atomic_counter;
 pub(crate) mod byte_utils;
@@ -35,7 +39,7 @@ pub(crate) mod poly1305;
 pub(crate) mod chacha20poly1305rfc;
 pub(crate) mod transaction_utils;
 pub(crate) mod scid_utils;
-pub(crate) mod time;
+pub mod time;
 
 pub mod indexed_map;
 
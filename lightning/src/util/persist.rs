@@ -8,9 +8,12 @@
 //! allows one to implement the persistence for [`ChannelManager`], [`NetworkGraph`],
 //! and [`ChannelMonitor`] all in one place.
 
+use core::cmp;
 use core::ops::Deref;
+use core::time::Duration;
 use bitcoin::hashes::hex::ToHex;
 use crate::io;
+use crate::prelude::*;
 use crate::routing::scoring::WriteableScore;
 
 use crate::chain;
@@ -31,6 +34,114 @@ use super::{logger::Logger, ser::Writeable};
 pub trait KVStorePersister {
 	/// Persist the given writeable using the provided key
 	fn persist<W: Writeable>(&self, key: &str, object: &W) -> io::Result<()>;
+
+	/// Returns a snapshot of the health of the underlying store, if the implementation is able to
+	/// measure it. This is forwarded via [`Persist::persistence_health`].
+	fn health(&self) -> Option<PersistenceHealthStatus> { None }
+}
+
+/// The number of recent writes a [`WriteLatencyTracker`] considers.
+pub const WRITE_LATENCY_SAMPLES: usize = 1000;
+
+/// A snapshot of the health of a persistence backend, as returned by
+/// [`KVStorePersister::health`] or [`Persist::persistence_health`].
+///
+/// All fields are optional as not every backend is able to measure every metric.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PersistenceHealthStatus {
+	/// The number of bytes still available to the store, if known.
+	pub free_space_bytes: Option<u64>,
+	/// The median latency of recent writes, or `None` if nothing was written yet.
+	pub write_latency_p50: Option<Duration>,
+	/// The 99th percentile latency of recent writes, or `None` if nothing was written yet.
+	pub write_latency_p99: Option<Duration>,
+	/// A description of the error returned by the most recent failed write, if any.
+	///
+	/// This is sticky, i.e. it is still reported after subsequent writes succeeded, such that a
+	/// transient failure isn't missed in between two health checks. Implementations should offer
+	/// a way to clear it once the user dealt with the failure, e.g.
+	/// `FilesystemPersister::clear_last_error`.
+	pub last_error: Option<String>,
+}
+
+impl PersistenceHealthStatus {
+	/// Returns whether this status is within the given thresholds. Metrics which are unknown are
+	/// assumed to be fine, while a failed last write is always considered unhealthy.
+	pub fn is_healthy(&self, thresholds: &PersistenceHealthThresholds) -> bool {
+		if self.last_error.is_some() { return false; }
+		if let Some(free_space_bytes) = self.free_space_bytes {
+			if free_space_bytes < thresholds.min_free_space_bytes { return false; }
+		}
+		if let Some(write_latency_p99) = self.write_latency_p99 {
+			if write_latency_p99 > thresholds.max_write_latency_p99 { return false; }
+		}
+		true
+	}
+}
+
+impl_writeable_tlv_based!(PersistenceHealthStatus, {
+	(0, free_space_bytes, option),
+	(2, write_latency_p50, option),
+	(4, write_latency_p99, option),
+	(6, last_error, option),
+});
+
+/// Thresholds past which a [`PersistenceHealthStatus`] is considered unhealthy, see
+/// [`ChainMonitor::set_persistence_health_thresholds`].
+///
+/// [`ChainMonitor::set_persistence_health_thresholds`]: crate::chain::chainmonitor::ChainMonitor::set_persistence_health_thresholds
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PersistenceHealthThresholds {
+	/// Persistence is considered unhealthy once fewer bytes than this are available.
+	///
+	/// Default value: 100 MiB.
+	pub min_free_space_bytes: u64,
+	/// Persistence is considered unhealthy once the 99th percentile of recent write latencies
+	/// exceeds this.
+	///
+	/// Default value: 1 second.
+	pub max_write_latency_p99: Duration,
+}
+
+impl Default for PersistenceHealthThresholds {
+	fn default() -> Self {
+		Self {
+			min_free_space_bytes: 100 * 1024 * 1024,
+			max_write_latency_p99: Duration::from_secs(1),
+		}
+	}
+}
+
+/// Keeps the latencies of the last [`WRITE_LATENCY_SAMPLES`] writes, allowing
+/// [`KVStorePersister`] implementations to report percentiles in their
+/// [`PersistenceHealthStatus`].
+pub struct WriteLatencyTracker {
+	samples: VecDeque<Duration>,
+}
+
+impl WriteLatencyTracker {
+	/// Constructs a new, empty, tracker.
+	pub fn new() -> Self {
+		Self { samples: VecDeque::new() }
+	}
+
+	/// Records the latency of a single write, evicting the oldest sample if needed.
+	pub fn record(&mut self, latency: Duration) {
+		if self.samples.len() >= WRITE_LATENCY_SAMPLES {
+			self.samples.pop_front();
+		}
+		self.samples.push_back(latency);
+	}
+
+	/// Returns the given percentile (0-100) of the recorded latencies, using the nearest-rank
+	/// method, or `None` if nothing was recorded yet.
+	pub fn percentile(&self, percentile: u8) -> Option<Duration> {
+		if self.samples.is_empty() { return None; }
+		let mut sorted: Vec<Duration> = self.samples.iter().cloned().collect();
+		sorted.sort_unstable();
+		let rank = (cmp::min(percentile, 100) as usize * sorted.len() + 99) / 100;
+		Some(sorted[rank.saturating_sub(1)])
+	}
 }
 
 /// Trait that handles persisting a [`ChannelManager`], [`NetworkGraph`], and [`WriteableScore`] to disk.
@@ -101,4 +212,57 @@ impl<ChannelSigner: WriteableEcdsaChannelSigner, K: KVStorePersister> Persist<Ch
 			Err(_) => chain::ChannelMonitorUpdateStatus::PermanentFailure,
 		}
 	}
+
+	fn persistence_health(&self) -> Option<PersistenceHealthStatus> {
+		self.health()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{PersistenceHealthStatus, PersistenceHealthThresholds, WriteLatencyTracker, WRITE_LATENCY_SAMPLES};
+	use core::time::Duration;
+
+	#[test]
+	fn test_write_latency_percentiles() {
+		let mut tracker = WriteLatencyTracker::new();
+		assert_eq!(tracker.percentile(50), None);
+		for ms in 1..=100 {
+			tracker.record(Duration::from_millis(ms));
+		}
+		assert_eq!(tracker.percentile(50), Some(Duration::from_millis(50)));
+		assert_eq!(tracker.percentile(99), Some(Duration::from_millis(99)));
+		assert_eq!(tracker.percentile(100), Some(Duration::from_millis(100)));
+		assert_eq!(tracker.percentile(0), Some(Duration::from_millis(1)));
+
+		// Once full, the oldest samples are evicted.
+		for _ in 0..WRITE_LATENCY_SAMPLES {
+			tracker.record(Duration::from_secs(2));
+		}
+		assert_eq!(tracker.percentile(0), Some(Duration::from_secs(2)));
+	}
+
+	#[test]
+	fn test_persistence_health_thresholds() {
+		let thresholds = PersistenceHealthThresholds::default();
+		let mut health = PersistenceHealthStatus {
+			free_space_bytes: None, write_latency_p50: None, write_latency_p99: None, last_error: None,
+		};
+		assert!(health.is_healthy(&thresholds));
+
+		health.free_space_bytes = Some(thresholds.min_free_space_bytes);
+		health.write_latency_p99 = Some(thresholds.max_write_latency_p99);
+		assert!(health.is_healthy(&thresholds));
+
+		health.free_space_bytes = Some(thresholds.min_free_space_bytes - 1);
+		assert!(!health.is_healthy(&thresholds));
+		health.free_space_bytes = None;
+
+		health.write_latency_p99 = Some(thresholds.max_write_latency_p99 + Duration::from_millis(1));
+		assert!(!health.is_healthy(&thresholds));
+		health.write_latency_p99 = None;
+
+		health.last_error = Some("No space left on device".to_owned());
+		assert!(!health.is_healthy(&thresholds));
+	}
 }
@@ -0,0 +1,679 @@
+// This file is Copyright its original authors, visible in version control
+// history.
+//
+// This file is licensed under the Apache License, Version 2.0 <LICENSE-APACHE
+// or http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// You may not use this file except in accordance with one or both of these
+// licenses.
+
+//! Hot-standby replication of a node's persisted state.
+//!
+//! A primary node persists via a [`ReplicatingPersister`], which wraps its [`KVStorePersister`]
+//! and streams every write, i.e. every [`ChannelMonitor`] persisted or updated and every
+//! [`ChannelManager`] persisted, to a standby process over a [`ReplicationTransport`]. The standby
+//! applies them to its own store via a [`ReplicaStore`], such that it holds the same state as the
+//! primary and can take over if the primary fails.
+//!
+//! Frames are authenticated with a key shared by the primary and standby, and numbered such that
+//! the standby notices any write it missed. Every write is replicated before it's committed to
+//! the primary's own store, such that the standby never falls behind the primary. Note that the
+//! [`ChannelManager`] is replicated in full each time it's persisted.
+//!
+//! Two nodes running off the same state at once will lose funds, so the primary only writes while
+//! it holds a lease granted by the standby. It requests one via
+//! [`ReplicatingPersister::timer_tick_occurred`], and the standby grants it in
+//! [`ReplicaStore::handle_frame`] unless it's taking over. A lease lasts a fixed number of timer
+//! ticks, counted by the primary from when it requested the lease and by the standby from when it
+//! granted it, so the primary's lease always expires first. To take over, the standby calls
+//! [`ReplicaStore::begin_takeover`] to stop granting leases, and is promoted by
+//! [`ReplicaStore::try_promote`] once the last lease it granted has expired, whether or not the
+//! primary is still reachable. It may then load its [`ChannelManager`] and [`ChannelMonitor`]s
+//! from its store and start up as the new primary, using the epoch returned by
+//! [`ReplicaStore::epoch`].
+//!
+//! Without a lease, or if a write fails to replicate, [`ChannelMonitor`] writes return
+//! [`ChannelMonitorUpdateStatus::InProgress`], pausing the affected channels rather than closing
+//! them. They're retried before any later write, and must be reported to the [`ChainMonitor`]
+//! once completed, see [`ReplicatingPersister::get_and_clear_completed_updates`]. Other writes
+//! fail until all pending [`ChannelMonitor`] writes have been replicated.
+//!
+//! [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+//! [`ChannelManager`]: crate::ln::channelmanager::ChannelManager
+//! [`ChainMonitor`]: crate::chain::chainmonitor::ChainMonitor
+//! [`ChannelMonitorUpdateStatus::InProgress`]: crate::chain::ChannelMonitorUpdateStatus::InProgress
+
+use bitcoin::hashes::{Hash, HashEngine};
+use bitcoin::hashes::cmp::fixed_time_eq;
+use bitcoin::hashes::hex::ToHex;
+use bitcoin::hashes::hmac::{Hmac, HmacEngine};
+use bitcoin::hashes::sha256::Hash as Sha256;
+
+use core::ops::Deref;
+use crate::chain;
+use crate::chain::chaininterface::{BroadcasterInterface, FeeEstimator};
+use crate::chain::chainmonitor::{MonitorUpdateId, Persist};
+use crate::chain::channelmonitor::{ChannelMonitor, ChannelMonitorUpdate};
+use crate::chain::transaction::OutPoint;
+use crate::io;
+use crate::ln::channelmanager::ChannelManager;
+use crate::ln::msgs::DecodeError;
+use crate::prelude::*;
+use crate::routing::gossip::NetworkGraph;
+use crate::routing::router::Router;
+use crate::routing::scoring::WriteableScore;
+use crate::sign::{EntropySource, NodeSigner, SignerProvider, WriteableEcdsaChannelSigner};
+use crate::sync::Mutex;
+use crate::util::logger::Logger;
+use crate::util::persist::{KVStorePersister, PersistenceHealthStatus, Persister};
+use crate::util::ser::{Readable, Writeable, Writer};
+
+/// Delivers frames between a primary node and its standby, see the [module-level
+/// documentation](self).
+///
+/// Implementations must deliver frames in the order they're sent, and are expected to do so over
+/// a channel which is both encrypted and authenticates the other side, though frames are
+/// additionally authenticated with the key shared by the primary and standby.
+pub trait ReplicationTransport {
+	/// Sends the given frame to the other side, returning an error if it was not delivered.
+	///
+	/// The primary only considers a write replicated once the frame replicating it is delivered,
+	/// so this should block until the standby has handled it, returning an error if
+	/// [`ReplicaStore::handle_frame`] failed.
+	fn send_frame(&self, frame: Vec<u8>) -> Result<(), ()>;
+}
+
+/// An error handling a frame received via a [`ReplicationTransport`], or promoting a standby.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ReplicationError {
+	/// The frame was not authenticated with our shared key.
+	InvalidMac,
+	/// The frame failed to decode.
+	InvalidFrame,
+	/// The frame was sent for an epoch other than the one we expect, i.e. by a replaced primary.
+	StaleEpoch,
+	/// The frame is not the next one we expect, i.e. the standby missed a write, and must be
+	/// reinitialized from a copy of the primary's store.
+	OutOfOrder,
+	/// The frame was not expected by the side handling it.
+	UnexpectedMessage,
+	/// Applying a replicated write to the standby's store failed.
+	PersistenceFailed,
+	/// The standby can't be promoted yet, as it hasn't begun taking over or the last lease it
+	/// granted the primary hasn't expired.
+	LeaseNotExpired,
+	/// The standby may be missing writes, so must not be promoted.
+	MissingWrites,
+}
+
+/// A message sent between a primary node and its standby.
+enum ReplicationMessage {
+	/// A write made by the primary, sent to the standby.
+	Write { key: String, value: Vec<u8> },
+	/// A request for a lease by the primary, made at the given tick of the primary.
+	LeaseRequest { request_tick: u64 },
+	/// A lease granted by the standby in response to the request made at the given tick.
+	LeaseGrant { request_tick: u64 },
+}
+
+impl_writeable_tlv_based_enum!(ReplicationMessage,
+	(0, Write) => {
+		(0, key, required),
+		(2, value, required),
+	},
+	(6, LeaseRequest) => {
+		(0, request_tick, required),
+	},
+	(8, LeaseGrant) => {
+		(0, request_tick, required),
+	};
+);
+
+/// A frame as sent over a [`ReplicationTransport`], followed by its HMAC.
+struct ReplicationFrame {
+	epoch: u64,
+	/// The number of writes the sender made, or applied, before this frame.
+	sequence: u64,
+	message: ReplicationMessage,
+}
+
+impl_writeable_tlv_based!(ReplicationFrame, {
+	(0, epoch, required),
+	(2, sequence, required),
+	(4, message, required),
+});
+
+fn frame_mac(key: &[u8; 32], body: &[u8]) -> [u8; 32] {
+	let mut hmac = HmacEngine::<Sha256>::new(key);
+	hmac.input(body);
+	Hmac::from_engine(hmac).into_inner()
+}
+
+fn encode_frame(key: &[u8; 32], frame: &ReplicationFrame) -> Vec<u8> {
+	let mut bytes = frame.encode();
+	let mac = frame_mac(key, &bytes);
+	bytes.extend_from_slice(&mac);
+	bytes
+}
+
+fn decode_frame(key: &[u8; 32], bytes: &[u8]) -> Result<ReplicationFrame, ReplicationError> {
+	if bytes.len() < 32 { return Err(ReplicationError::InvalidFrame) }
+	let (body, mac) = bytes.split_at(bytes.len() - 32);
+	if !fixed_time_eq(&frame_mac(key, body), mac) { return Err(ReplicationError::InvalidMac) }
+	let mut reader = io::Cursor::new(body);
+	let frame: ReplicationFrame = Readable::read(&mut reader)
+		.map_err(|_: DecodeError| ReplicationError::InvalidFrame)?;
+	if reader.position() != body.len() as u64 { return Err(ReplicationError::InvalidFrame) }
+	Ok(frame)
+}
+
+/// Writes already serialized bytes verbatim.
+struct RawBytes<'a>(&'a [u8]);
+
+impl<'a> Writeable for RawBytes<'a> {
+	fn write<W: Writer>(&self, w: &mut W) -> Result<(), io::Error> {
+		w.write_all(self.0)
+	}
+}
+
+/// A [`ChannelMonitor`] write which we returned [`ChannelMonitorUpdateStatus::InProgress`] for,
+/// along with the updates it completes.
+///
+/// [`ChannelMonitorUpdateStatus::InProgress`]: chain::ChannelMonitorUpdateStatus::InProgress
+struct PendingMonitorWrite {
+	key: String,
+	value: Vec<u8>,
+	updates: Vec<(OutPoint, MonitorUpdateId)>,
+}
+
+struct PrimaryState {
+	epoch: u64,
+	writes: u64,
+	/// The number of times [`ReplicatingPersister::timer_tick_occurred`] was called.
+	ticks: u64,
+	/// The tick at which our lease expires, or zero if we were never granted one.
+	lease_expiry_tick: u64,
+	pending_monitor_writes: VecDeque<PendingMonitorWrite>,
+	completed_updates: Vec<(OutPoint, MonitorUpdateId)>,
+}
+
+/// A [`Persist`] and [`Persister`] implementation which replicates every write to a standby
+/// before committing it to the wrapped [`KVStorePersister`], see the [module-level
+/// documentation](self).
+///
+/// [`ChannelMonitor`] writes which can't be replicated, e.g. as we don't hold a lease, return
+/// [`ChannelMonitorUpdateStatus::InProgress`] and are retried on the next write or timer tick,
+/// after which they're returned by [`Self::get_and_clear_completed_updates`]. Other writes fail
+/// while any [`ChannelMonitor`] writes are pending, such that the [`ChannelManager`] is never
+/// persisted ahead of its [`ChannelMonitor`]s.
+///
+/// [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+/// [`ChannelMonitorUpdateStatus::InProgress`]: chain::ChannelMonitorUpdateStatus::InProgress
+pub struct ReplicatingPersister<K: KVStorePersister, T: Deref> where T::Target: ReplicationTransport {
+	store: K,
+	transport: T,
+	key: [u8; 32],
+	lease_ticks: u16,
+	state: Mutex<PrimaryState>,
+}
+
+impl<K: KVStorePersister, T: Deref> ReplicatingPersister<K, T> where T::Target: ReplicationTransport {
+	/// Creates a new `ReplicatingPersister` persisting to `store` and replicating to a standby
+	/// sharing the given `key` over `transport`.
+	///
+	/// `epoch` must be greater than that of any primary the standby replicated before, e.g. the
+	/// [`ReplicaStore::epoch`] of a promoted standby. The standby must start out with a copy of
+	/// `store`, as only writes made from here on are replicated. `lease_ticks` must match that of
+	/// the standby.
+	///
+	/// No writes are made until the standby grants us a lease, so [`Self::timer_tick_occurred`]
+	/// should be called, and the standby's response handled, before loading the [`ChannelManager`].
+	pub fn new(store: K, transport: T, key: [u8; 32], epoch: u64, lease_ticks: u16) -> Self {
+		ReplicatingPersister {
+			store, transport, key, lease_ticks,
+			state: Mutex::new(PrimaryState {
+				epoch, writes: 0, ticks: 0, lease_expiry_tick: 0,
+				pending_monitor_writes: VecDeque::new(), completed_updates: Vec::new(),
+			}),
+		}
+	}
+
+	/// Handles a frame received from the standby, extending our lease if it grants one.
+	pub fn handle_frame(&self, frame: &[u8]) -> Result<(), ReplicationError> {
+		let frame = decode_frame(&self.key, frame)?;
+		let mut state = self.state.lock().unwrap();
+		if frame.epoch != state.epoch { return Err(ReplicationError::StaleEpoch) }
+		match frame.message {
+			ReplicationMessage::LeaseGrant { request_tick } => {
+				// The lease runs from when we requested it, which is no later than when the standby
+				// granted it, so it expires here first.
+				let expiry_tick = request_tick.saturating_add(self.lease_ticks as u64);
+				state.lease_expiry_tick = core::cmp::max(state.lease_expiry_tick, expiry_tick);
+				Ok(())
+			},
+			_ => Err(ReplicationError::UnexpectedMessage),
+		}
+	}
+
+	/// Requests a new lease from the standby and retries any pending [`ChannelMonitor`] writes.
+	///
+	/// Must be called at the same rate as [`ReplicaStore::timer_tick_occurred`] is on the
+	/// standby, and often enough that our lease is renewed before it expires.
+	///
+	/// [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+	pub fn timer_tick_occurred(&self) {
+		let mut state = self.state.lock().unwrap();
+		state.ticks += 1;
+		let frame = ReplicationFrame {
+			epoch: state.epoch,
+			sequence: state.writes,
+			message: ReplicationMessage::LeaseRequest { request_tick: state.ticks },
+		};
+		// A failure to deliver the request is handled once our lease expires.
+		let _ = self.transport.send_frame(encode_frame(&self.key, &frame));
+		let _ = self.flush_pending_monitor_writes(&mut state);
+	}
+
+	/// Returns whether we currently hold a lease, without which all writes fail.
+	pub fn holds_lease(&self) -> bool {
+		let state = self.state.lock().unwrap();
+		state.ticks < state.lease_expiry_tick
+	}
+
+	/// Returns the number of writes replicated to the standby so far.
+	pub fn writes_replicated(&self) -> u64 {
+		self.state.lock().unwrap().writes
+	}
+
+	/// Returns the [`ChannelMonitor`] updates which we returned
+	/// [`ChannelMonitorUpdateStatus::InProgress`] for and have since been replicated and
+	/// persisted. Each must be passed to [`ChainMonitor::channel_monitor_updated`].
+	///
+	/// [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+	/// [`ChannelMonitorUpdateStatus::InProgress`]: chain::ChannelMonitorUpdateStatus::InProgress
+	/// [`ChainMonitor::channel_monitor_updated`]: crate::chain::chainmonitor::ChainMonitor::channel_monitor_updated
+	pub fn get_and_clear_completed_updates(&self) -> Vec<(OutPoint, MonitorUpdateId)> {
+		core::mem::take(&mut self.state.lock().unwrap().completed_updates)
+	}
+
+	/// Replicates a write to the standby and then commits it to our own store.
+	fn write(&self, state: &mut PrimaryState, key: &str, value: &[u8]) -> io::Result<()> {
+		if state.ticks >= state.lease_expiry_tick {
+			return Err(io::Error::new(io::ErrorKind::Other, "No lease held from standby"));
+		}
+		let frame = ReplicationFrame {
+			epoch: state.epoch,
+			sequence: state.writes,
+			message: ReplicationMessage::Write { key: key.to_owned(), value: value.to_vec() },
+		};
+		self.transport.send_frame(encode_frame(&self.key, &frame))
+			.map_err(|()| io::Error::new(io::ErrorKind::Other, "Failed to replicate write to standby"))?;
+		state.writes += 1;
+		self.store.persist(key, &RawBytes(value))
+	}
+
+	/// Writes any pending [`ChannelMonitor`] writes in order, stopping at the first failure.
+	///
+	/// [`ChannelMonitor`]: crate::chain::channelmonitor::ChannelMonitor
+	fn flush_pending_monitor_writes(&self, state: &mut PrimaryState) -> io::Result<()> {
+		while let Some(pending) = state.pending_monitor_writes.pop_front() {
+			if let Err(e) = self.write(state, &pending.key, &pending.value) {
+				state.pending_monitor_writes.push_front(pending);
+				return Err(e);
+			}
+			state.completed_updates.extend(pending.updates);
+		}
+		Ok(())
+	}
+
+	fn persist_monitor(&self, key: String, value: Vec<u8>, update: (OutPoint, MonitorUpdateId)) -> chain::ChannelMonitorUpdateStatus {
+		// Hold the lock across the write such that writes are replicated in the order they're made.
+		let mut state = self.state.lock().unwrap();
+		if self.flush_pending_monitor_writes(&mut state).is_ok() && self.write(&mut state, &key, &value).is_ok() {
+			return chain::ChannelMonitorUpdateStatus::Completed;
+		}
+		// A later write of a monitor supersedes any earlier one still pending.
+		match state.pending_monitor_writes.iter_mut().find(|pending| pending.key == key) {
+			Some(pending) => {
+				pending.value = value;
+				pending.updates.push(update);
+			},
+			None => state.pending_monitor_writes.push_back(PendingMonitorWrite { key, value, updates: vec![update] }),
+		}
+		chain::ChannelMonitorUpdateStatus::InProgress
+	}
+
+	fn persist_object<W: Writeable>(&self, key: &str, object: &W) -> io::Result<()> {
+		let mut state = self.state.lock().unwrap();
+		self.flush_pending_monitor_writes(&mut state)?;
+		self.write(&mut state, key, &object.encode())
+	}
+}
+
+impl<ChannelSigner: WriteableEcdsaChannelSigner, K: KVStorePersister, T: Deref> Persist<ChannelSigner> for ReplicatingPersister<K, T>
+where T::Target: ReplicationTransport {
+	fn persist_new_channel(&self, funding_txo: OutPoint, monitor: &ChannelMonitor<ChannelSigner>, update_id: MonitorUpdateId) -> chain::ChannelMonitorUpdateStatus {
+		let key = format!("monitors/{}_{}", funding_txo.txid.to_hex(), funding_txo.index);
+		self.persist_monitor(key, monitor.encode(), (funding_txo, update_id))
+	}
+
+	fn update_persisted_channel(&self, funding_txo: OutPoint, _update: Option<&ChannelMonitorUpdate>, monitor: &ChannelMonitor<ChannelSigner>, update_id: MonitorUpdateId) -> chain::ChannelMonitorUpdateStatus {
+		let key = format!("monitors/{}_{}", funding_txo.txid.to_hex(), funding_txo.index);
+		self.persist_monitor(key, monitor.encode(), (funding_txo, update_id))
+	}
+
+	fn persistence_health(&self) -> Option<PersistenceHealthStatus> {
+		self.store.health()
+	}
+}
+
+impl<'a, K: KVStorePersister, TR: Deref, M: Deref, T: Deref, ES: Deref, NS: Deref, SP: Deref, F: Deref, R: Deref, L: Deref, S: WriteableScore<'a>> Persister<'a, M, T, ES, NS, SP, F, R, L, S> for ReplicatingPersister<K, TR>
+	where TR::Target: ReplicationTransport,
+		M::Target: 'static + chain::Watch<<SP::Target as SignerProvider>::Signer>,
+		T::Target: 'static + BroadcasterInterface,
+		ES::Target: 'static + EntropySource,
+		NS::Target: 'static + NodeSigner,
+		SP::Target: 'static + SignerProvider,
+		F::Target: 'static + FeeEstimator,
+		R::Target: 'static + Router,
+		L::Target: 'static + Logger,
+{
+	fn persist_manager(&self, channel_manager: &ChannelManager<M, T, ES, NS, SP, F, R, L>) -> Result<(), io::Error> {
+		self.persist_object("manager", channel_manager)
+	}
+
+	fn persist_graph(&self, network_graph: &NetworkGraph<L>) -> Result<(), io::Error> {
+		self.persist_object("network_graph", network_graph)
+	}
+
+	fn persist_scorer(&self, scorer: &S) -> Result<(), io::Error> {
+		self.persist_object("scorer", &scorer)
+	}
+}
+
+struct StandbyState {
+	epoch: u64,
+	writes: u64,
+	/// The number of times [`ReplicaStore::timer_tick_occurred`] was called.
+	ticks: u64,
+	/// The tick at which we last granted the primary a lease, if ever.
+	last_lease_grant_tick: Option<u64>,
+	taking_over: bool,
+	/// Whether we failed to apply a write, such that we may be missing it.
+	missing_writes: bool,
+	promoted: bool,
+}
+
+/// Applies writes replicated from a primary's [`ReplicatingPersister`] to a standby's store and
+/// grants it leases, see the [module-level documentation](self).
+pub struct ReplicaStore<K: KVStorePersister> {
+	store: K,
+	key: [u8; 32],
+	lease_ticks: u16,
+	state: Mutex<StandbyState>,
+}
+
+impl<K: KVStorePersister> ReplicaStore<K> {
+	/// Creates a new `ReplicaStore` applying writes to `store`, which must start out with a copy
+	/// of the primary's store, from a primary with the given `epoch` sharing the given `key`.
+	///
+	/// `lease_ticks` is the number of timer ticks each lease we grant the primary lasts.
+	pub fn new(store: K, key: [u8; 32], epoch: u64, lease_ticks: u16) -> Self {
+		ReplicaStore {
+			store, key, lease_ticks,
+			state: Mutex::new(StandbyState {
+				epoch, writes: 0, ticks: 0, last_lease_grant_tick: None, taking_over: false,
+				missing_writes: false, promoted: false,
+			}),
+		}
+	}
+
+	/// Handles a frame received from the primary, applying the write it replicates to our store or
+	/// returning a frame granting the lease it requests, to send back to it.
+	///
+	/// Once [`ReplicationError::OutOfOrder`] or [`ReplicationError::PersistenceFailed`] is returned
+	/// our store may be missing writes, and will not be promoted.
+	pub fn handle_frame(&self, frame: &[u8]) -> Result<Option<Vec<u8>>, ReplicationError> {
+		let frame = decode_frame(&self.key, frame)?;
+		let mut state = self.state.lock().unwrap();
+		if state.promoted || frame.epoch != state.epoch { return Err(ReplicationError::StaleEpoch) }
+		match frame.message {
+			ReplicationMessage::Write { key, value } => {
+				if frame.sequence != state.writes {
+					state.missing_writes = true;
+					return Err(ReplicationError::OutOfOrder);
+				}
+				if self.store.persist(&key, &RawBytes(&value)).is_err() {
+					state.missing_writes = true;
+					return Err(ReplicationError::PersistenceFailed);
+				}
+				state.writes += 1;
+				Ok(None)
+			},
+			ReplicationMessage::LeaseRequest { request_tick } => {
+				if state.taking_over { return Ok(None) }
+				state.last_lease_grant_tick = Some(state.ticks);
+				let grant = ReplicationFrame {
+					epoch: state.epoch,
+					sequence: state.writes,
+					message: ReplicationMessage::LeaseGrant { request_tick },
+				};
+				Ok(Some(encode_frame(&self.key, &grant)))
+			},
+			ReplicationMessage::LeaseGrant { .. } => Err(ReplicationError::UnexpectedMessage),
+		}
+	}
+
+	/// Counts the passage of time for the leases we grant. Must be called at the same rate as
+	/// [`ReplicatingPersister::timer_tick_occurred`] is on the primary.
+	pub fn timer_tick_occurred(&self) {
+		self.state.lock().unwrap().ticks += 1;
+	}
+
+	/// Stops granting the primary leases, such that we can take over via [`Self::try_promote`]
+	/// once its current lease has expired.
+	pub fn begin_takeover(&self) {
+		self.state.lock().unwrap().taking_over = true;
+	}
+
+	/// Promotes us to take over from the primary, returning the epoch to take over with.
+	///
+	/// Fails until [`Self::begin_takeover`] was called and the last lease we granted the primary
+	/// expired, which doesn't require the primary to be reachable, or if our store may be missing
+	/// writes.
+	pub fn try_promote(&self) -> Result<u64, ReplicationError> {
+		let mut state = self.state.lock().unwrap();
+		if state.promoted { return Ok(state.epoch) }
+		if state.missing_writes { return Err(ReplicationError::MissingWrites) }
+		if !state.taking_over { return Err(ReplicationError::LeaseNotExpired) }
+		// Wait an extra tick to account for the primary and us ticking at slightly different times.
+		if let Some(grant_tick) = state.last_lease_grant_tick {
+			if state.ticks <= grant_tick.saturating_add(self.lease_ticks as u64) {
+				return Err(ReplicationError::LeaseNotExpired);
+			}
+		}
+		state.epoch += 1;
+		state.promoted = true;
+		Ok(state.epoch)
+	}
+
+	/// Returns whether we've been promoted via [`Self::try_promote`].
+	pub fn is_promoted(&self) -> bool {
+		self.state.lock().unwrap().promoted
+	}
+
+	/// Returns the epoch of the primary we replicate, or, once promoted, the epoch to take over
+	/// with.
+	pub fn epoch(&self) -> u64 {
+		self.state.lock().unwrap().epoch
+	}
+
+	/// Returns the number of writes applied to our store so far.
+	pub fn writes_applied(&self) -> u64 {
+		self.state.lock().unwrap().writes
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{ReplicaStore, ReplicatingPersister, ReplicationError, ReplicationTransport};
+	use bitcoin::hash_types::Txid;
+	use bitcoin::hashes::Hash;
+	use crate::chain::ChannelMonitorUpdateStatus;
+	use crate::chain::chainmonitor::MonitorUpdateId;
+	use crate::chain::transaction::OutPoint;
+	use crate::io;
+	use crate::prelude::*;
+	use crate::sync::Mutex;
+	use crate::util::persist::KVStorePersister;
+	use crate::util::ser::Writeable;
+
+	#[derive(Default)]
+	struct TestStore {
+		entries: Mutex<HashMap<String, Vec<u8>>>,
+	}
+
+	impl KVStorePersister for TestStore {
+		fn persist<W: Writeable>(&self, key: &str, object: &W) -> io::Result<()> {
+			self.entries.lock().unwrap().insert(key.to_owned(), object.encode());
+			Ok(())
+		}
+	}
+
+	#[derive(Default)]
+	struct TestTransport {
+		frames: Mutex<Vec<Vec<u8>>>,
+		fail: Mutex<bool>,
+	}
+
+	impl ReplicationTransport for TestTransport {
+		fn send_frame(&self, frame: Vec<u8>) -> Result<(), ()> {
+			if *self.fail.lock().unwrap() { return Err(()) }
+			self.frames.lock().unwrap().push(frame);
+			Ok(())
+		}
+	}
+
+	fn take_frames(transport: &TestTransport) -> Vec<Vec<u8>> {
+		core::mem::take(&mut *transport.frames.lock().unwrap())
+	}
+
+	/// Has the primary request a lease, delivering the request and the standby's grant.
+	fn renew_lease<T: core::ops::Deref>(
+		primary: &ReplicatingPersister<TestStore, T>, standby: &ReplicaStore<TestStore>, transport: &TestTransport
+	) where T::Target: ReplicationTransport {
+		primary.timer_tick_occurred();
+		standby.timer_tick_occurred();
+		for frame in take_frames(transport) {
+			if let Some(grant) = standby.handle_frame(&frame).unwrap() {
+				primary.handle_frame(&grant).unwrap();
+			}
+		}
+	}
+
+	#[test]
+	fn test_replication_and_takeover() {
+		let transport = TestTransport::default();
+		let primary = ReplicatingPersister::new(TestStore::default(), &transport, [42; 32], 1, 3);
+		let standby = ReplicaStore::new(TestStore::default(), [42; 32], 1, 3);
+
+		// Nothing is written until the standby grants a lease.
+		assert!(primary.persist_object("manager", &vec![0u8]).is_err());
+		assert!(transport.frames.lock().unwrap().is_empty());
+		renew_lease(&primary, &standby, &transport);
+		assert!(primary.holds_lease());
+
+		primary.persist_object("manager", &vec![1u8, 2, 3]).unwrap();
+		primary.persist_object("network_graph", &vec![4u8]).unwrap();
+		let frames = take_frames(&transport);
+		assert_eq!(frames.len(), 2);
+
+		// Frames must be authenticated with our key and applied in order.
+		let mut tampered = frames[0].clone();
+		tampered[0] ^= 1;
+		assert_eq!(standby.handle_frame(&tampered), Err(ReplicationError::InvalidMac));
+		let other_standby = ReplicaStore::new(TestStore::default(), [43; 32], 1, 3);
+		assert_eq!(other_standby.handle_frame(&frames[0]), Err(ReplicationError::InvalidMac));
+		let skipping_standby = ReplicaStore::new(TestStore::default(), [42; 32], 1, 3);
+		assert_eq!(skipping_standby.handle_frame(&frames[1]), Err(ReplicationError::OutOfOrder));
+		skipping_standby.begin_takeover();
+		assert_eq!(skipping_standby.try_promote(), Err(ReplicationError::MissingWrites));
+
+		for frame in frames.iter() {
+			assert_eq!(standby.handle_frame(frame), Ok(None));
+		}
+		assert_eq!(standby.writes_applied(), 2);
+		assert_eq!(*standby.store.entries.lock().unwrap(), *primary.store.entries.lock().unwrap());
+
+		// A write which fails to replicate isn't committed locally either.
+		*transport.fail.lock().unwrap() = true;
+		assert!(primary.persist_object("manager", &vec![5u8]).is_err());
+		assert_eq!(primary.store.entries.lock().unwrap().get("manager"), Some(&vec![1u8, 2, 3].encode()));
+		*transport.fail.lock().unwrap() = false;
+		primary.persist_object("manager", &vec![6u8]).unwrap();
+		standby.handle_frame(&take_frames(&transport)[0]).unwrap();
+		assert_eq!(primary.writes_replicated(), 3);
+
+		// Once the standby begins taking over it no longer grants leases, and is promoted once the
+		// last one expired, without hearing from the primary.
+		standby.begin_takeover();
+		primary.timer_tick_occurred();
+		assert_eq!(standby.handle_frame(&take_frames(&transport)[0]), Ok(None));
+		for _ in 0..3 {
+			standby.timer_tick_occurred();
+			assert_eq!(standby.try_promote(), Err(ReplicationError::LeaseNotExpired));
+		}
+		// The primary's lease expired first, so it no longer writes.
+		primary.timer_tick_occurred();
+		primary.timer_tick_occurred();
+		assert!(!primary.holds_lease());
+		assert!(primary.persist_object("manager", &vec![7u8]).is_err());
+		take_frames(&transport);
+
+		standby.timer_tick_occurred();
+		assert_eq!(standby.try_promote(), Ok(2));
+		assert!(standby.is_promoted());
+		assert_eq!(standby.epoch(), 2);
+
+		// Frames from the replaced primary are rejected.
+		primary.timer_tick_occurred();
+		assert_eq!(standby.handle_frame(&take_frames(&transport)[0]), Err(ReplicationError::StaleEpoch));
+	}
+
+	#[test]
+	fn test_monitor_writes_retried_without_closing() {
+		let transport = TestTransport::default();
+		let primary = ReplicatingPersister::new(TestStore::default(), &transport, [42; 32], 1, 3);
+		let standby = ReplicaStore::new(TestStore::default(), [42; 32], 1, 3);
+		let funding_txo = OutPoint { txid: Txid::all_zeros(), index: 0 };
+		let key = "monitors/abc_0".to_owned();
+
+		// Without a lease, monitor writes are paused rather than failed.
+		assert_eq!(primary.persist_monitor(key.clone(), vec![1], (funding_txo, MonitorUpdateId::from_update_id(1))),
+			ChannelMonitorUpdateStatus::InProgress);
+		assert_eq!(primary.persist_monitor(key.clone(), vec![2], (funding_txo, MonitorUpdateId::from_update_id(2))),
+			ChannelMonitorUpdateStatus::InProgress);
+		assert!(primary.get_and_clear_completed_updates().is_empty());
+
+		// Other writes fail while monitor writes are pending, so that the manager never gets ahead.
+		renew_lease(&primary, &standby, &transport);
+		*transport.fail.lock().unwrap() = true;
+		assert!(primary.persist_object("manager", &vec![0u8]).is_err());
+		*transport.fail.lock().unwrap() = false;
+
+		// Once retried, only the latest pending write is replicated, completing both updates.
+		primary.persist_object("manager", &vec![0u8]).unwrap();
+		for frame in take_frames(&transport) {
+			standby.handle_frame(&frame).unwrap();
+		}
+		assert_eq!(standby.writes_applied(), 2);
+		assert_eq!(standby.store.entries.lock().unwrap().get(&key), Some(&vec![2u8]));
+		assert!(primary.get_and_clear_completed_updates() == vec![
+			(funding_txo, MonitorUpdateId::from_update_id(1)), (funding_txo, MonitorUpdateId::from_update_id(2)),
+		]);
+
+		assert_eq!(primary.persist_monitor(key.clone(), vec![3], (funding_txo, MonitorUpdateId::from_update_id(3))),
+			ChannelMonitorUpdateStatus::Completed);
+		assert!(primary.get_and_clear_completed_updates().is_empty());
+	}
+}
@@ -49,6 +49,137 @@ use crate::util::string::UntrustedString;
 /// serialization buffer size
 pub const MAX_BUF_SIZE: usize = 64 * 1024;
 
+/// Limits applied when reading via [`Readable`].
+///
+/// Reads via [`read_with_limits`] apply the given limits, while all other reads apply the
+/// [`Default`] limits, except for [`DeserializationLimits::max_total_bytes`] which is only
+/// enforced by [`read_with_limits`]. Without the `std` feature, only
+/// [`DeserializationLimits::max_total_bytes`] may be customized, with the [`Default`] values of
+/// the remaining limits applying to all reads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DeserializationLimits {
+	/// The maximum number of bytes read from the underlying reader, beyond which the read fails
+	/// with [`DecodeError::ShortRead`].
+	///
+	/// Default value: 64 KiB, the maximum size of a Lightning wire message.
+	pub max_total_bytes: u64,
+	/// The maximum number of elements accepted in a length-prefixed collection, e.g. a map or a
+	/// list of route paths.
+	///
+	/// Default value: 2^20, which comfortably fits the largest collections LDK writes, such as the
+	/// channels of a mainnet [`NetworkGraph`].
+	///
+	/// [`NetworkGraph`]: crate::routing::gossip::NetworkGraph
+	pub max_collection_len: u64,
+	/// The maximum length, in bytes, of a length-prefixed byte vector.
+	///
+	/// Default value: 16 MiB.
+	pub max_bytes_len: u64,
+	/// The maximum length, in bytes, of the value of a single TLV record, or of a whole TLV stream
+	/// written by [`write_tlv_fields`].
+	///
+	/// Default value: 64 MiB.
+	///
+	/// [`write_tlv_fields`]: crate::write_tlv_fields
+	pub max_tlv_record_len: u64,
+}
+
+impl DeserializationLimits {
+	const DEFAULT: DeserializationLimits = DeserializationLimits {
+		max_total_bytes: 65535,
+		max_collection_len: 1 << 20,
+		max_bytes_len: 16 * 1024 * 1024,
+		max_tlv_record_len: 64 * 1024 * 1024,
+	};
+}
+
+impl Default for DeserializationLimits {
+	fn default() -> Self {
+		DeserializationLimits::DEFAULT
+	}
+}
+
+#[cfg(feature = "std")]
+thread_local! {
+	/// The limits of the [`read_with_limits`] call in progress on this thread, if any.
+	static READ_LIMITS: core::cell::Cell<DeserializationLimits> =
+		core::cell::Cell::new(DeserializationLimits::DEFAULT);
+}
+
+/// Restores the limits which applied before a [`read_with_limits`] call once it returns or
+/// unwinds, allowing such calls to nest.
+#[cfg(feature = "std")]
+struct ReadLimitsGuard(DeserializationLimits);
+
+#[cfg(feature = "std")]
+impl Drop for ReadLimitsGuard {
+	fn drop(&mut self) {
+		READ_LIMITS.with(|limits| limits.set(self.0));
+	}
+}
+
+#[cfg(feature = "std")]
+#[inline]
+fn read_limits() -> DeserializationLimits {
+	READ_LIMITS.with(|limits| limits.get())
+}
+
+#[cfg(not(feature = "std"))]
+#[inline]
+fn read_limits() -> DeserializationLimits {
+	DeserializationLimits::DEFAULT
+}
+
+/// Reads a `T` from `reader`, applying the given `limits` to it and to everything it reads in
+/// turn.
+pub fn read_with_limits<T: Readable, R: Read>(
+	reader: &mut R, limits: &DeserializationLimits
+) -> Result<T, DecodeError> {
+	#[cfg(feature = "std")]
+	let _guard = ReadLimitsGuard(READ_LIMITS.with(|current| current.replace(*limits)));
+	let mut limited_reader = FixedLengthReader::new(reader, limits.max_total_bytes);
+	T::read(&mut limited_reader)
+}
+
+#[inline]
+fn check_len(len: u64, limit: u64) -> Result<(), DecodeError> {
+	if len > limit {
+		Err(DecodeError::BadLengthDescriptor)
+	} else {
+		Ok(())
+	}
+}
+
+/// Fails if a collection of `len` elements exceeds `limit`, which callers reading collections
+/// with a tighter natural bound than [`DeserializationLimits::max_collection_len`], e.g. the
+/// HTLCs of a channel, use.
+#[inline]
+pub(crate) fn check_collection_len_within(len: u64, limit: u64) -> Result<(), DecodeError> {
+	check_len(len, cmp::min(limit, read_limits().max_collection_len))
+}
+
+/// Fails if a collection of `len` elements exceeds [`DeserializationLimits::max_collection_len`].
+#[inline]
+pub(crate) fn check_collection_len(len: u64) -> Result<(), DecodeError> {
+	check_len(len, read_limits().max_collection_len)
+}
+
+/// Fails if a byte vector of `len` bytes exceeds [`DeserializationLimits::max_bytes_len`].
+#[inline]
+fn check_bytes_len(len: u64) -> Result<(), DecodeError> {
+	check_len(len, read_limits().max_bytes_len)
+}
+
+/// Fails if a TLV record value of `len` bytes exceeds
+/// [`DeserializationLimits::max_tlv_record_len`].
+///
+/// This is exported for use by the TLV stream macros, do not use directly.
+#[doc(hidden)]
+#[inline]
+pub fn check_tlv_record_len(len: u64) -> Result<(), DecodeError> {
+	check_len(len, read_limits().max_tlv_record_len)
+}
+
 /// A simplified version of [`std::io::Write`] that exists largely for backwards compatibility.
 /// An impl is provided for any type that also impls [`std::io::Write`].
 ///
@@ -664,7 +795,10 @@ impl<T: MaybeReadable> Readable for WithoutLength<Vec<T>> {
 		loop {
 			let mut track_read = ReadTrackingReader::new(&mut reader);
 			match MaybeReadable::read(&mut track_read) {
-				Ok(Some(v)) => { values.push(v); },
+				Ok(Some(v)) => {
+					values.push(v);
+					check_collection_len(values.len() as u64)?;
+				},
 				Ok(None) => { },
 				// If we failed to read any bytes at all, we reached the end of our TLV
 				// stream and have simply exhausted all entries.
@@ -736,6 +870,7 @@ macro_rules! impl_for_map {
 			#[inline]
 			fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
 				let len: CollectionLength = Readable::read(r)?;
+				check_collection_len(len.0)?;
 				let mut ret = $constr(len.0 as usize);
 				for _ in 0..len.0 {
 					let k = K::read(r)?;
@@ -775,6 +910,7 @@ where T: Readable + Eq + Hash
 	#[inline]
 	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
 		let len: CollectionLength = Readable::read(r)?;
+		check_collection_len(len.0)?;
 		let mut ret = HashSet::with_capacity(cmp::min(len.0 as usize, MAX_BUF_SIZE / core::mem::size_of::<T>()));
 		for _ in 0..len.0 {
 			if !ret.insert(T::read(r)?) {
@@ -806,6 +942,7 @@ macro_rules! impl_readable_for_vec {
 			#[inline]
 			fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
 				let len: CollectionLength = Readable::read(r)?;
+				check_collection_len(len.0)?;
 				let mut ret = Vec::with_capacity(cmp::min(len.0 as usize, MAX_BUF_SIZE / core::mem::size_of::<$ty>()));
 				for _ in 0..len.0 {
 					if let Some(val) = MaybeReadable::read(r)? {
@@ -836,6 +973,7 @@ impl Readable for Vec<u8> {
 	#[inline]
 	fn read<R: Read>(r: &mut R) -> Result<Self, DecodeError> {
 		let mut len: CollectionLength = Readable::read(r)?;
+		check_bytes_len(len.0)?;
 		let mut ret = Vec::new();
 		while len.0 > 0 {
 			let readamt = cmp::min(len.0 as usize, MAX_BUF_SIZE);
@@ -851,6 +989,9 @@ impl Readable for Vec<u8> {
 impl_for_vec!(ecdsa::Signature);
 impl_for_vec!(crate::chain::channelmonitor::ChannelMonitorUpdate);
 impl_for_vec!(crate::ln::channelmanager::MonitorUpdateCompletionAction);
+impl_for_vec!(crate::ln::channelmanager::ChannelDetails);
+impl_for_vec!(crate::ln::msgs::OnionMessage);
+impl_for_vec!(crate::onion_message::OnionMessagePriority);
 impl_for_vec!((A, B), A, B);
 impl_writeable_for_vec!(&crate::routing::router::BlindedTail);
 impl_readable_for_vec!(crate::routing::router::BlindedTail);
@@ -1430,7 +1571,9 @@ impl Readable for ClaimId {
 mod tests {
 	use core::convert::TryFrom;
 	use bitcoin::secp256k1::ecdsa;
-	use crate::util::ser::{Readable, Hostname, Writeable};
+	use crate::util::ser::{Readable, Hostname, Writeable, DeserializationLimits, CollectionLength, read_with_limits};
+	use crate::ln::msgs::DecodeError;
+	use crate::prelude::*;
 
 	#[test]
 	fn hostname_conversion() {
@@ -1463,6 +1606,78 @@ mod tests {
 		assert_eq!(buffer, serialization.to_vec())
 	}
 
+	#[test]
+	fn deserialization_limits() {
+		let defaults = DeserializationLimits::default();
+
+		// Lengths beyond the limits are rejected without attempting to read the elements they claim
+		// to describe.
+		let oversized_collection = CollectionLength(defaults.max_collection_len + 1).encode();
+		assert_eq!(<Vec<ecdsa::Signature>>::read(&mut &oversized_collection[..]), Err(DecodeError::BadLengthDescriptor));
+		assert_eq!(<HashMap<u64, u64>>::read(&mut &oversized_collection[..]), Err(DecodeError::BadLengthDescriptor));
+
+		let oversized_bytes = CollectionLength(defaults.max_bytes_len + 1).encode();
+		assert_eq!(<Vec<u8>>::read(&mut &oversized_bytes[..]), Err(DecodeError::BadLengthDescriptor));
+
+		// Lengths within the limits are read as usual, failing once the data runs out.
+		let truncated_collection = CollectionLength(defaults.max_collection_len).encode();
+		assert_eq!(<Vec<ecdsa::Signature>>::read(&mut &truncated_collection[..]), Err(DecodeError::ShortRead));
+
+		let mut oversized_tlv_stream = Vec::new();
+		super::BigSize(1).write(&mut oversized_tlv_stream).unwrap();
+		super::BigSize(defaults.max_tlv_record_len + 1).write(&mut oversized_tlv_stream).unwrap();
+		let decode_oversized_tlv = |mut reader: &[u8]| -> Result<(), DecodeError> {
+			let mut _field: Option<u64> = None;
+			decode_tlv_stream!(&mut reader, {
+				(1, _field, option),
+			});
+			Ok(())
+		};
+		assert_eq!(decode_oversized_tlv(&oversized_tlv_stream), Err(DecodeError::BadLengthDescriptor));
+
+		// Each read may be bounded in the total number of bytes it consumes.
+		let bytes = vec![42u8; 100].encode();
+		let limits = DeserializationLimits { max_total_bytes: 50, ..defaults };
+		assert_eq!(read_with_limits::<Vec<u8>, _>(&mut &bytes[..], &limits), Err(DecodeError::ShortRead));
+		let limits = DeserializationLimits { max_total_bytes: bytes.len() as u64, ..defaults };
+		assert_eq!(read_with_limits::<Vec<u8>, _>(&mut &bytes[..], &limits), Ok(vec![42u8; 100]));
+	}
+
+	#[test]
+	#[cfg(feature = "std")]
+	fn custom_deserialization_limits() {
+		let defaults = DeserializationLimits::default();
+
+		// Custom limits apply to everything read by a single read, and only to that read.
+		let bytes = vec![42u8; 100].encode();
+		let limits = DeserializationLimits { max_bytes_len: 99, ..defaults };
+		assert_eq!(read_with_limits::<Vec<u8>, _>(&mut &bytes[..], &limits), Err(DecodeError::BadLengthDescriptor));
+		assert_eq!(<Vec<u8>>::read(&mut &bytes[..]), Ok(vec![42u8; 100]));
+
+		let collection = CollectionLength(2).encode();
+		let limits = DeserializationLimits { max_collection_len: 1, ..defaults };
+		assert_eq!(read_with_limits::<HashMap<u64, u64>, _>(&mut &collection[..], &limits), Err(DecodeError::BadLengthDescriptor));
+		assert_eq!(<HashMap<u64, u64>>::read(&mut &collection[..]), Err(DecodeError::ShortRead));
+
+		let mut tlv_stream = Vec::new();
+		super::BigSize(1).write(&mut tlv_stream).unwrap();
+		super::BigSize(8).write(&mut tlv_stream).unwrap();
+		42u64.write(&mut tlv_stream).unwrap();
+		struct TlvField(Option<u64>);
+		impl Readable for TlvField {
+			fn read<R: crate::io::Read>(reader: &mut R) -> Result<Self, DecodeError> {
+				let mut field = None;
+				decode_tlv_stream!(reader, {
+					(1, field, option),
+				});
+				Ok(TlvField(field))
+			}
+		}
+		let limits = DeserializationLimits { max_tlv_record_len: 7, ..defaults };
+		assert_eq!(read_with_limits::<TlvField, _>(&mut &tlv_stream[..], &limits).err(), Some(DecodeError::BadLengthDescriptor));
+		assert_eq!(TlvField::read(&mut &tlv_stream[..]).unwrap().0, Some(42));
+	}
+
 	#[test]
 	fn bigsize_encoding_decoding() {
 		let values = vec![0, 252, 253, 65535, 65536, 4294967295, 4294967296, 18446744073709551615];
@@ -506,6 +506,7 @@ macro_rules! _decode_tlv_stream_range {
 
 			// Finally, read the length and value itself:
 			let length: ser::BigSize = $crate::util::ser::Readable::read(&mut stream_ref)?;
+			ser::check_tlv_record_len(length.0)?;
 			let mut s = ser::FixedLengthReader::new(&mut stream_ref, length.0);
 			match typ.0 {
 				$(_t if $crate::_decode_tlv_stream_match_check!(_t, $type, $fieldty) => {
@@ -591,6 +592,50 @@ macro_rules! impl_writeable_msg {
 	}
 }
 
+/// Like [`impl_writeable_msg`], but additionally reads and writes the `Vec<(u64, Vec<u8>)>` field
+/// `$custom_tlvs`, holding any odd-typed TLVs with types of at least
+/// [`MIN_CUSTOM_CHANNEL_TLV_TYPE`] as opaque bytes.
+///
+/// `$custom_tlvs` must be sorted by type when written, as it always is when read.
+///
+/// [`MIN_CUSTOM_CHANNEL_TLV_TYPE`]: crate::ln::msgs::MIN_CUSTOM_CHANNEL_TLV_TYPE
+macro_rules! impl_writeable_msg_with_custom_tlvs {
+	($st:ident, {$($field:ident),* $(,)*}, {$(($type: expr, $tlvfield: ident, $fieldty: tt)),* $(,)*}, $custom_tlvs: ident) => {
+		impl $crate::util::ser::Writeable for $st {
+			fn write<W: $crate::util::ser::Writer>(&self, w: &mut W) -> Result<(), $crate::io::Error> {
+				$( self.$field.write(w)?; )*
+				$crate::encode_tlv_stream!(w, {$(($type, self.$tlvfield.as_ref(), $fieldty)),*});
+				for (typ, value) in self.$custom_tlvs.iter() {
+					debug_assert!(*typ >= $crate::ln::msgs::MIN_CUSTOM_CHANNEL_TLV_TYPE && *typ % 2 == 1);
+					$crate::util::ser::BigSize(*typ).write(w)?;
+					$crate::util::ser::BigSize(value.len() as u64).write(w)?;
+					w.write_all(value)?;
+				}
+				Ok(())
+			}
+		}
+		impl $crate::util::ser::Readable for $st {
+			fn read<R: $crate::io::Read>(r: &mut R) -> Result<Self, $crate::ln::msgs::DecodeError> {
+				$(let $field = $crate::util::ser::Readable::read(r)?;)*
+				$($crate::_init_tlv_field_var!($tlvfield, $fieldty);)*
+				let mut $custom_tlvs = Vec::new();
+				decode_tlv_stream_with_custom_tlv_decode!(r, {$(($type, $tlvfield, $fieldty)),*},
+					|typ: u64, tlv_reader: &mut $crate::util::ser::FixedLengthReader<_>| -> Result<bool, $crate::ln::msgs::DecodeError> {
+						// Leave anything else to the caller, which rejects unknown even types.
+						if typ < $crate::ln::msgs::MIN_CUSTOM_CHANNEL_TLV_TYPE || typ % 2 == 0 { return Ok(false) }
+						$custom_tlvs.push((typ, $crate::io_extras::read_to_end(tlv_reader)?));
+						Ok(true)
+					});
+				Ok(Self {
+					$($field),*,
+					$($tlvfield),*,
+					$custom_tlvs
+				})
+			}
+		}
+	}
+}
+
 macro_rules! impl_writeable {
 	($st:ident, {$($field:ident),*}) => {
 		impl $crate::util::ser::Writeable for $st {
@@ -677,6 +722,7 @@ macro_rules! read_ver_prefix {
 macro_rules! read_tlv_fields {
 	($stream: expr, {$(($type: expr, $field: ident, $fieldty: tt)),* $(,)*}) => { {
 		let tlv_len: $crate::util::ser::BigSize = $crate::util::ser::Readable::read($stream)?;
+		$crate::util::ser::check_tlv_record_len(tlv_len.0)?;
 		let mut rd = $crate::util::ser::FixedLengthReader::new($stream, tlv_len.0);
 		$crate::decode_tlv_stream!(&mut rd, {$(($type, $field, $fieldty)),*});
 		rd.eat_remaining().map_err(|_| $crate::ln::msgs::DecodeError::ShortRead)?;
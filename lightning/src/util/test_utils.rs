@@ -7,6 +7,7 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
+use crate::blinded_path::BlindedPath;
 use crate::chain;
 use crate::chain::WatchedOutput;
 use crate::chain::chaininterface;
@@ -31,7 +32,9 @@ use crate::routing::scoring::{ChannelUsage, Score};
 use crate::util::config::UserConfig;
 use crate::util::enforcing_trait_impls::{EnforcingSigner, EnforcementState};
 use crate::util::logger::{Logger, Level, Record};
+use crate::util::persist::PersistenceHealthStatus;
 use crate::util::ser::{Readable, ReadableArgs, Writer, Writeable};
+use crate::util::time::TimeSource;
 
 use bitcoin::EcdsaSighashType;
 use bitcoin::blockdata::constants::ChainHash;
@@ -267,6 +270,10 @@ impl<'a> chain::Watch<EnforcingSigner> for TestChainMonitor<'a> {
 	fn release_pending_monitor_events(&self) -> Vec<(OutPoint, Vec<MonitorEvent>, Option<PublicKey>)> {
 		return self.chain_monitor.release_pending_monitor_events();
 	}
+
+	fn watch_onchain_fallback(&self, script_pubkey: &Script) {
+		self.chain_monitor.watch_onchain_fallback(script_pubkey)
+	}
 }
 
 pub struct TestPersister {
@@ -279,6 +286,8 @@ pub struct TestPersister {
 	/// When we get an update_persisted_channel call *with* a ChannelMonitorUpdate, we insert the
 	/// MonitorUpdateId here.
 	pub offchain_monitor_updates: Mutex<HashMap<OutPoint, HashSet<MonitorUpdateId>>>,
+	/// The health we'll report via `persistence_health`.
+	pub health: Mutex<Option<PersistenceHealthStatus>>,
 }
 impl TestPersister {
 	pub fn new() -> Self {
@@ -286,6 +295,7 @@ impl TestPersister {
 			update_rets: Mutex::new(VecDeque::new()),
 			chain_sync_monitor_persistences: Mutex::new(HashMap::new()),
 			offchain_monitor_updates: Mutex::new(HashMap::new()),
+			health: Mutex::new(None),
 		}
 	}
 
@@ -314,10 +324,15 @@ impl<Signer: sign::WriteableEcdsaChannelSigner> chainmonitor::Persist<Signer> fo
 		}
 		ret
 	}
+
+	fn persistence_health(&self) -> Option<PersistenceHealthStatus> {
+		self.health.lock().unwrap().clone()
+	}
 }
 
 pub struct TestBroadcaster {
 	pub txn_broadcasted: Mutex<Vec<Transaction>>,
+	pub txn_broadcasted_metadata: Mutex<Vec<(Txid, chaininterface::TransactionMetadata)>>,
 	pub blocks: Arc<Mutex<Vec<(Block, u32)>>>,
 }
 
@@ -325,12 +340,13 @@ impl TestBroadcaster {
 	pub fn new(network: Network) -> Self {
 		Self {
 			txn_broadcasted: Mutex::new(Vec::new()),
+			txn_broadcasted_metadata: Mutex::new(Vec::new()),
 			blocks: Arc::new(Mutex::new(vec![(genesis_block(network), 0)])),
 		}
 	}
 
 	pub fn with_blocks(blocks: Arc<Mutex<Vec<(Block, u32)>>>) -> Self {
-		Self { txn_broadcasted: Mutex::new(Vec::new()), blocks }
+		Self { txn_broadcasted: Mutex::new(Vec::new()), txn_broadcasted_metadata: Mutex::new(Vec::new()), blocks }
 	}
 
 	pub fn txn_broadcast(&self) -> Vec<Transaction> {
@@ -361,6 +377,13 @@ impl chaininterface::BroadcasterInterface for TestBroadcaster {
 		let owned_txs: Vec<Transaction> = txs.iter().map(|tx| (*tx).clone()).collect();
 		self.txn_broadcasted.lock().unwrap().extend(owned_txs);
 	}
+
+	fn broadcast_transactions_with_meta(&self, txs: &[(&Transaction, chaininterface::TransactionMetadata)]) {
+		self.txn_broadcasted_metadata.lock().unwrap()
+			.extend(txs.iter().map(|(tx, metadata)| (tx.txid(), *metadata)));
+		let txs = txs.iter().map(|(tx, _)| *tx).collect::<Vec<_>>();
+		self.broadcast_transactions(&txs);
+	}
 }
 
 pub struct TestChannelMessageHandler {
@@ -454,6 +477,9 @@ impl msgs::ChannelMessageHandler for TestChannelMessageHandler {
 	fn handle_update_fee(&self, _their_node_id: &PublicKey, msg: &msgs::UpdateFee) {
 		self.received_msg(wire::Message::UpdateFee(msg.clone()));
 	}
+	fn handle_yield(&self, _their_node_id: &PublicKey, msg: &msgs::Yield) {
+		self.received_msg(wire::Message::Yield(msg.clone()));
+	}
 	fn handle_channel_update(&self, _their_node_id: &PublicKey, _msg: &msgs::ChannelUpdate) {
 		// Don't call `received_msg` here as `TestRoutingMessageHandler` generates these sometimes
 	}
@@ -764,11 +790,16 @@ impl Logger for TestLogger {
 
 pub struct TestNodeSigner {
 	node_secret: SecretKey,
+	previous_node_secret: Option<SecretKey>,
 }
 
 impl TestNodeSigner {
 	pub fn new(node_secret: SecretKey) -> Self {
-		Self { node_secret }
+		Self { node_secret, previous_node_secret: None }
+	}
+
+	pub fn with_previous_node_secret(node_secret: SecretKey, previous_node_secret: SecretKey) -> Self {
+		Self { node_secret, previous_node_secret: Some(previous_node_secret) }
 	}
 }
 
@@ -803,6 +834,18 @@ impl NodeSigner for TestNodeSigner {
 	fn sign_gossip_message(&self, _msg: msgs::UnsignedGossipMessage) -> Result<Signature, ()> {
 		unreachable!()
 	}
+
+	fn get_previous_node_id(&self) -> Option<PublicKey> {
+		self.previous_node_secret.map(|secret| PublicKey::from_secret_key(&Secp256k1::signing_only(), &secret))
+	}
+
+	fn previous_node_ecdh(&self, other_key: &PublicKey) -> Result<SharedSecret, ()> {
+		self.previous_node_secret.map(|secret| SharedSecret::new(other_key, &secret)).ok_or(())
+	}
+
+	fn sign_onion_message_receipt(&self, _nonce: &[u8; 32]) -> Result<Signature, ()> {
+		unreachable!()
+	}
 }
 
 pub struct TestKeysInterface {
@@ -843,6 +886,27 @@ impl NodeSigner for TestKeysInterface {
 	fn sign_gossip_message(&self, msg: msgs::UnsignedGossipMessage) -> Result<Signature, ()> {
 		self.backing.sign_gossip_message(msg)
 	}
+
+	fn sign_message(&self, msg: &[u8]) -> Result<String, ()> {
+		self.backing.sign_message(msg)
+	}
+
+	fn sign_onion_message_receipt(&self, nonce: &[u8; 32]) -> Result<Signature, ()> {
+		self.backing.sign_onion_message_receipt(nonce)
+	}
+
+	fn sign_custom_onion_message(
+		&self, tlv_type: u64, recipient: &PublicKey, reply_path: Option<&BlindedPath>,
+		payload: &[u8]
+	) -> Result<Signature, ()> {
+		self.backing.sign_custom_onion_message(tlv_type, recipient, reply_path, payload)
+	}
+
+	fn sign_onion_message_key_delegation(
+		&self, delegate_pubkey: &PublicKey, expires_at: Duration
+	) -> Result<Signature, ()> {
+		self.backing.sign_onion_message_key_delegation(delegate_pubkey, expires_at)
+	}
 }
 
 impl SignerProvider for TestKeysInterface {
@@ -1007,6 +1071,28 @@ impl Drop for TestChainSource {
 	}
 }
 
+/// A [`TimeSource`] which only advances when told to, sharing its time with all its clones.
+#[derive(Clone)]
+pub struct TestTimeSource {
+	now: Arc<Mutex<Duration>>,
+}
+
+impl TestTimeSource {
+	pub fn new() -> Self {
+		Self { now: Arc::new(Mutex::new(Duration::from_secs(0))) }
+	}
+
+	pub fn advance(&self, duration: Duration) {
+		*self.now.lock().unwrap() += duration;
+	}
+}
+
+impl TimeSource for TestTimeSource {
+	fn now(&self) -> Duration {
+		*self.now.lock().unwrap()
+	}
+}
+
 pub struct TestScorer {
 	/// Stores a tuple of (scid, ChannelUsage)
 	scorer_expectations: RefCell<Option<VecDeque<(u64, ChannelUsage)>>>,
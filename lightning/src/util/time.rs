@@ -4,14 +4,40 @@
 // You may not use this file except in accordance with one or both of these
 // licenses.
 
-//! [`Time`] trait and different implementations. Currently, it's mainly used in tests so we can
-//! manually advance time.
-//! Other crates may symlink this file to use it while [`Time`] trait is sealed here.
+//! [`Time`] trait and different implementations.
+//!
+//! All time-dependent logic in LDK, e.g. the decay of the [`ProbabilisticScorer`]'s knowledge,
+//! payment retry timeouts and onion message rate limits, is generic over or configured with a
+//! [`Time`] implementation. Builds with the `std` feature use [`MonotonicTime`], while `no-std`
+//! builds use [`Eternity`], in which time never passes. Users may provide their own
+//! implementation, e.g. one backed by a hardware clock on `no-std` platforms, wherever a [`Time`]
+//! type parameter is exposed, such as [`ProbabilisticScorerUsingTime`] or
+//! `Bolt11Invoice::is_expired_using`. Where no type parameter is exposed, e.g. for the
+//! [`OnionMessenger`]'s rate limits, a [`TimeSource`] may be set at runtime instead. Tests use
+//! `tests::SinceEpoch`, which only advances when told to, allowing time to be fast-forwarded.
+//!
+//! See [`Time`] for the requirements an implementation must meet.
+//!
+//! [`ProbabilisticScorer`]: crate::routing::scoring::ProbabilisticScorer
+//! [`ProbabilisticScorerUsingTime`]: crate::routing::scoring::ProbabilisticScorerUsingTime
+//! [`OnionMessenger`]: crate::onion_message::OnionMessenger
 
 use core::ops::Sub;
 use core::time::Duration;
 
 /// A measurement of time.
+///
+/// Implementations must be monotonic: [`Time::now`] must never return an instant earlier than one
+/// it returned before, and [`Time::elapsed`] and [`Time::duration_since`] should return zero
+/// rather than panic if the underlying clock goes backwards. Time may stand still, as it does for
+/// [`Eternity`], in which case nothing which decays or expires over time ever does.
+///
+/// [`Time::duration_since_epoch`] is compared against absolute timestamps, e.g. an invoice's
+/// creation time, and is used to persist instants, e.g. when serializing a
+/// [`ProbabilisticScorer`], which are restored by subtracting from [`Time::now`]. It should thus
+/// return the time since the UNIX epoch, consistently with [`Time::now`].
+///
+/// [`ProbabilisticScorer`]: crate::routing::scoring::ProbabilisticScorer
 pub trait Time: Copy + Sub<Duration, Output = Self> where Self: Sized {
 	/// Returns an instance corresponding to the current moment.
 	fn now() -> Self;
@@ -58,6 +84,8 @@ impl Sub<Duration> for Eternity {
 	}
 }
 
+/// The [`Time`] used by builds with the `std` feature, backed by [`std::time::Instant`] and
+/// [`std::time::SystemTime`].
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 #[cfg(not(feature = "no-std"))]
 pub struct MonotonicTime(std::time::Instant);
@@ -103,9 +131,59 @@ impl Sub<Duration> for MonotonicTime {
 	}
 }
 
-#[cfg(test)]
+/// The [`Time`] used internally wherever time is not a type parameter.
+#[cfg(not(any(feature = "no-std", test)))]
+pub(crate) type ConfiguredTime = MonotonicTime;
+/// The [`Time`] used internally wherever time is not a type parameter.
+#[cfg(feature = "no-std")]
+pub(crate) type ConfiguredTime = Eternity;
+/// The [`Time`] used internally wherever time is not a type parameter.
+#[cfg(all(not(feature = "no-std"), test))]
+pub(crate) type ConfiguredTime = tests::SinceEpoch;
+
+/// A source of the current time which may be injected at runtime, e.g. via
+/// [`OnionMessenger::set_time_source`], where a [`Time`] type parameter is not available.
+///
+/// This allows `no-std` users to provide a clock, in which case time would otherwise never pass,
+/// and tests to fast-forward time.
+///
+/// [`OnionMessenger::set_time_source`]: crate::onion_message::OnionMessenger::set_time_source
+pub trait TimeSource {
+	/// Returns the current time as a [`Duration`] since some fixed point in the past.
+	///
+	/// The returned value must never decrease, but the point it is relative to is arbitrary.
+	fn now(&self) -> Duration;
+}
+
+/// The [`TimeSource`] used unless another is configured, backed by the [`Time`] implementation of
+/// the current build, i.e. [`MonotonicTime`] with the `std` feature and [`Eternity`] without.
+pub struct DefaultTimeSource {
+	start: ConfiguredTime,
+}
+
+impl DefaultTimeSource {
+	/// Creates a new [`DefaultTimeSource`], measuring time from now.
+	pub fn new() -> Self {
+		Self { start: ConfiguredTime::now() }
+	}
+}
+
+impl Default for DefaultTimeSource {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl TimeSource for DefaultTimeSource {
+	fn now(&self) -> Duration {
+		self.start.elapsed()
+	}
+}
+
+/// [`Time`] implementations for testing.
+#[cfg(any(test, all(feature = "_test_utils", not(feature = "no-std"))))]
 pub mod tests {
-	use super::{Time, Eternity};
+	use super::Time;
 
 	use core::time::Duration;
 	use core::ops::Sub;
@@ -120,6 +198,7 @@ pub mod tests {
 			static ELAPSED: Cell<Duration> = core::cell::Cell::new(Duration::from_secs(0));
 		}
 
+		/// Advances the time of the current thread by the given duration.
 		pub fn advance(duration: Duration) {
 			Self::ELAPSED.with(|elapsed| elapsed.set(elapsed.get() + duration))
 		}
@@ -168,6 +247,7 @@ pub mod tests {
 
 	#[test]
 	fn time_never_passes_in_an_eternity() {
+		use super::Eternity;
 		let now = Eternity::now();
 		let elapsed = now.elapsed();
 		let later = Eternity::now();